@@ -0,0 +1,50 @@
+//! Hash throughput of each algorithm, in memory and from a file.
+
+use criterion::{Criterion, Throughput, black_box, criterion_group, criterion_main};
+use p2p_core::transfer::{HashAlgorithm, compute_file_hash_with_progress};
+
+const SIZE: usize = 64 * 1024 * 1024;
+
+const ALGORITHMS: [HashAlgorithm; 3] = [
+    HashAlgorithm::Blake3,
+    HashAlgorithm::Xxh3,
+    HashAlgorithm::Sha256,
+];
+
+fn in_memory(c: &mut Criterion) {
+    let data: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+    let mut group = c.benchmark_group("hash in memory");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.sample_size(10);
+    for algorithm in ALGORITHMS {
+        group.bench_function(algorithm.label(), |b| {
+            b.iter(|| {
+                let mut hasher = algorithm.hasher().unwrap();
+                hasher.update(black_box(&data));
+                hasher.finalize()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn from_file(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("hash_bench_{}", std::process::id()));
+    let path = p2p_core::testing::write_test_file(&dir, "hash.bin", SIZE).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("hash file");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.sample_size(10);
+    for algorithm in ALGORITHMS {
+        group.bench_function(algorithm.label(), |b| {
+            b.to_async(&runtime)
+                .iter(|| compute_file_hash_with_progress(&path, algorithm, |_, _| {}))
+        });
+    }
+    group.finish();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, in_memory, from_file);
+criterion_main!(benches);
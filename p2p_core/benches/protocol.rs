@@ -0,0 +1,50 @@
+//! Encoding and decoding protocol messages, and cleaning file names.
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use p2p_core::FileInfo;
+use p2p_core::transfer::protocol::decode_msg;
+use p2p_core::transfer::{HashAlgorithm, TransferMsg, sanitize_file_name};
+use std::path::PathBuf;
+
+fn file_metadata() -> TransferMsg {
+    TransferMsg::FileMetadata {
+        info: FileInfo {
+            file_name: "holiday photos/IMG_2041 (edited).jpeg".to_string(),
+            file_size: Some(4_812_345),
+            file_path: PathBuf::new(),
+            file_hash: Some("9f".repeat(32)),
+            hash_algorithm: HashAlgorithm::Blake3,
+            modified: Some(1_760_000_000_000),
+            mode: Some(0o644),
+            note: Some("For the album".to_string()),
+            xattrs: Vec::new(),
+        },
+    }
+}
+
+fn messages(c: &mut Criterion) {
+    let msg = file_metadata();
+    let encoded = serde_json::to_vec(&msg).unwrap();
+
+    c.bench_function("encode FileMetadata", |b| {
+        b.iter(|| serde_json::to_vec(black_box(&msg)).unwrap())
+    });
+    c.bench_function("decode FileMetadata", |b| {
+        b.iter(|| decode_msg(black_box(&encoded)).unwrap())
+    });
+}
+
+fn file_names(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sanitize_file_name");
+    for (case, name) in [
+        ("plain", "report-2026.pdf".to_string()),
+        ("unsafe", "../..\\con:<report>?*|.pdf".to_string()),
+        ("long", "ü".repeat(600)),
+    ] {
+        group.bench_function(case, |b| b.iter(|| sanitize_file_name(black_box(&name))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, messages, file_names);
+criterion_main!(benches);
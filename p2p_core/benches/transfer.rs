@@ -0,0 +1,70 @@
+//! A LAN transfer over loopback QUIC, end to end: hashing, sending,
+//! writing and verifying. 1 GB by default; set `P2P_BENCH_TRANSFER_MB` for
+//! another size.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use p2p_core::testing::{TestPair, write_test_file};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const DEFAULT_MB: u64 = 1024;
+
+/// Write `size` bytes of the test pattern without holding them in memory
+fn write_large_file(dir: &Path, name: &str, size: u64) -> PathBuf {
+    let path = dir.join(name);
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&path).unwrap());
+    let block: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let mut left = size;
+    while left > 0 {
+        let n = left.min(block.len() as u64) as usize;
+        file.write_all(&block[..n]).unwrap();
+        left -= n as u64;
+    }
+    file.flush().unwrap();
+    path
+}
+
+fn loopback(c: &mut Criterion) {
+    let mb = std::env::var("P2P_BENCH_TRANSFER_MB")
+        .ok()
+        .and_then(|mb| mb.parse().ok())
+        .unwrap_or(DEFAULT_MB);
+    let size = mb * 1024 * 1024;
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut pair = runtime.block_on(TestPair::new()).unwrap();
+
+    let outgoing = pair.sender.root().join("outgoing");
+    let warm_up = write_test_file(&outgoing, "pairing.bin", 1024).unwrap();
+    runtime
+        .block_on(pair.send_with_pairing(vec![warm_up]))
+        .unwrap();
+    let path = write_large_file(&outgoing, "large.bin", size);
+
+    let mut group = c.benchmark_group("loopback transfer");
+    group.throughput(Throughput::Bytes(size));
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(60));
+    group.bench_function(format!("{} MB", mb), |b| {
+        b.iter_custom(|iters| {
+            runtime.block_on(async {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    pair.send_paired(vec![path.clone()]).await.unwrap();
+                    total += start.elapsed();
+                    // Received again under the same name, not "large (1).bin"
+                    let downloads = pair.receiver.download_dir().to_path_buf();
+                    let _ = std::fs::remove_dir_all(&downloads);
+                    std::fs::create_dir_all(&downloads).unwrap();
+                }
+                total
+            })
+        })
+    });
+    group.finish();
+    runtime.block_on(pair.shutdown());
+}
+
+criterion_group!(benches, loopback);
+criterion_main!(benches);
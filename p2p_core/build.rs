@@ -0,0 +1,35 @@
+//! Build metadata shown on the HTTP share's about page (see
+//! `src/http_share/about.rs`): the git commit, target and profile.
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=P2P_BUILD_COMMIT={}", commit);
+    println!(
+        "cargo:rustc-env=P2P_BUILD_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+    println!(
+        "cargo:rustc-env=P2P_BUILD_PROFILE={}",
+        std::env::var("PROFILE").unwrap_or_default()
+    );
+
+    // A new commit moves HEAD or the branch it points to; a source tarball
+    // has neither, and a missing path would rerun this on every build
+    println!("cargo:rerun-if-changed=build.rs");
+    for path in ["../.git/HEAD", "../.git/refs", "../.git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
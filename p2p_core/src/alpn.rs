@@ -0,0 +1,135 @@
+//! Protocol identifiers (ALPN) of the QUIC connections.
+//!
+//! Every ALPN is derived here from an application ID and
+//! [`PROTOCOL_VERSION`], so a fork or an incompatible build fails the TLS
+//! handshake instead of talking to this one by accident. The handshake
+//! error is explained by [`crate::transfer::close`]. A private deployment
+//! sets its own `app_id` in `config.json`; only devices with the same ID
+//! connect, and a room key (see [`crate::discovery`]) also hides the
+//! others from the device list.
+
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+/// Version of the wire protocol, bumped when old and new builds can no
+/// longer talk. Part of every ALPN and announced in discovery.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Application ID of the public app
+pub const DEFAULT_APP_ID: &str = "p2p-transfer";
+
+/// Longest application ID; an ALPN may have 255 bytes
+pub const MAX_APP_ID_LEN: usize = 64;
+
+/// Application ID the ALPNs are derived from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AppId(String);
+
+impl Default for AppId {
+    fn default() -> Self {
+        Self(DEFAULT_APP_ID.to_string())
+    }
+}
+
+impl fmt::Display for AppId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AppId {
+    /// `id` checked: ASCII letters, digits, `-`, `_` and `.`, at most
+    /// [`MAX_APP_ID_LEN`] of them
+    pub fn new(id: &str) -> Result<Self> {
+        let id = id.trim();
+        if id.is_empty() || id.len() > MAX_APP_ID_LEN {
+            return Err(anyhow!(
+                "App ID must have 1 to {} characters",
+                MAX_APP_ID_LEN
+            ));
+        }
+        if !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            return Err(anyhow!(
+                "Invalid app ID {}: use letters, digits, '-', '_' and '.'",
+                id
+            ));
+        }
+        Ok(Self(id.to_string()))
+    }
+
+    /// The configured ID, or the default when it is unset or invalid
+    pub fn from_config(id: Option<&str>) -> Self {
+        match id.map(Self::new) {
+            Some(Ok(app_id)) => app_id,
+            Some(Err(e)) => {
+                tracing::warn!("{}; using {}", e, DEFAULT_APP_ID);
+                Self::default()
+            }
+            None => Self::default(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether this is the public app's ID
+    pub fn is_default(&self) -> bool {
+        self.0 == DEFAULT_APP_ID
+    }
+
+    /// ALPN of LAN transfers
+    pub fn lan_alpn(&self) -> Vec<u8> {
+        self.alpn("lan")
+    }
+
+    /// ALPN of WAN transfers
+    pub fn wan_alpn(&self) -> Vec<u8> {
+        self.alpn("wan")
+    }
+
+    /// ALPN of WAN connections linking two devices with a phrase
+    pub fn link_alpn(&self) -> Vec<u8> {
+        self.alpn("link")
+    }
+
+    fn alpn(&self, kind: &str) -> Vec<u8> {
+        format!("{}/{}/{}", self.0, kind, PROTOCOL_VERSION).into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alpns_carry_app_and_version() {
+        let app = AppId::default();
+        assert!(app.is_default());
+        assert_eq!(
+            app.lan_alpn(),
+            format!("p2p-transfer/lan/{}", PROTOCOL_VERSION).into_bytes()
+        );
+        assert_ne!(app.lan_alpn(), app.wan_alpn());
+        assert_ne!(app.wan_alpn(), app.link_alpn());
+
+        let private = AppId::new(" acme-files ").unwrap();
+        assert_eq!(private.as_str(), "acme-files");
+        assert!(!private.is_default());
+        assert_ne!(private.wan_alpn(), app.wan_alpn());
+    }
+
+    #[test]
+    fn test_invalid_app_ids_fall_back() {
+        assert!(AppId::new("").is_err());
+        assert!(AppId::new("with space").is_err());
+        assert!(AppId::new("a/b").is_err());
+        assert!(AppId::new(&"x".repeat(MAX_APP_ID_LEN + 1)).is_err());
+        assert_eq!(AppId::from_config(Some("a/b")), AppId::default());
+        assert_eq!(AppId::from_config(None), AppId::default());
+        assert_eq!(AppId::from_config(Some("corp")).as_str(), "corp");
+    }
+}
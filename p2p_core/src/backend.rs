@@ -0,0 +1,3031 @@
+//! Backend command loop.
+//!
+//! [`run_backend_with_config`] binds the discovery socket and QUIC endpoints,
+//! then feeds every [`AppCommand`] into [`Backend::handle_command`]. Commands
+//! wrapped in [`AppCommand::Tracked`] additionally produce an
+//! [`AppEvent::CommandResult`] carrying the caller's request id. Services
+//! inside the backend reach the same loop through a [`RemoteControl`].
+
+use crate::alpn::{AppId, PROTOCOL_VERSION};
+use crate::config::{AppConfig, ProfileManager, get_config_dir};
+use crate::discovery::room::RoomKey;
+use crate::discovery::{DISCOVERY_INTERVAL_SECS, DiscoveryService};
+use crate::events::EventCategory;
+use crate::folder_watch::{self, FolderWatcher};
+use crate::groups::{self, GroupSend, GroupTarget};
+use crate::health::{self, HEALTH_INTERVAL};
+use crate::history::export::write_export;
+use crate::history::transfers::HistoryQuery;
+use crate::history::usage::{BandwidthCap, CapLevel};
+use crate::history::{HISTORY_FILE, HistoryStore, MAX_QUERY_RESULTS};
+use crate::journal::{JOURNAL_FILE, SendJournal};
+use crate::json_events;
+use crate::metrics::{METRICS_INTERVAL, MetricsSampler};
+use crate::network_info::{
+    self, METERED_CONFIRM_BYTES, METERED_SEND_RATE, MeteredMode, NetworkCost,
+};
+use crate::network_watch::{self, NETWORK_CHECK_INTERVAL, NetworkChange, NetworkWatcher};
+use crate::node::NodeConfig;
+use crate::pairing::guest::GuestMode;
+use crate::pairing::invite::{INVITE_EXPIRY_SECS, InviteRegistry, PairingInvite};
+use crate::pairing::queue;
+use crate::pairing::{FilePairingStore, PairingStore, now_timestamp};
+use crate::policy::Policy;
+use crate::post_receive::{self, PostReceiveHook};
+use crate::power::{SLEEP_CHECK_INTERVAL, SleepDetector};
+use crate::proxy::ProxySettings;
+use crate::quarantine;
+use crate::remote::{RemoteControl, RemoteRequest};
+use crate::rendezvous::{self, RendezvousClient, RendezvousSettings};
+use crate::retention::{RETENTION_CHECK_INTERVAL, RetentionPolicy, run_cleanup};
+use crate::schedule::{
+    SCHEDULE_CHECK_INTERVAL, SCHEDULE_FILE, ScheduleStore, ScheduledSend, probe_reachable,
+};
+use crate::state::{BackendState, StateTracker};
+use crate::swarm::availability::{AVAILABILITY_INTERVAL, SwarmAvailability};
+use crate::swarm::{self, MAX_SWARM_PEERS, SwarmFile, SwarmRegistry, SwarmTarget};
+use crate::telemetry::{self, TELEMETRY_CHECK_INTERVAL, TELEMETRY_FILE, Telemetry, TelemetryMode};
+use crate::transfer::constants::MAX_VERIFICATION_ATTEMPTS;
+use crate::transfer::filename;
+use crate::transfer::hash::{self, HashAlgorithm};
+use crate::transfer::metadata;
+use crate::transfer::moves::{MOVE_UNDO_WINDOW, PendingMoves};
+use crate::transfer::netem::NetworkEmulation;
+use crate::transfer::orphans::{self, ORPHAN_CHECK_INTERVAL};
+use crate::transfer::rate_limit::RateLimiter;
+use crate::transfer::{
+    ConnectionPool, RelayService, ServerOptions, SocketBuffers, SocketReport, TRANSFER_PORT,
+    TransferCancel, make_client_endpoint_with, make_server_endpoint_with,
+};
+use crate::units::{self, UnitPreference};
+use crate::webhook::{self, Webhook};
+use crate::{
+    AppCommand, AppEvent, LogLevel, PeerCapabilities, http_share, identity, state, transfer,
+};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+
+/// How long a profile switch waits for the old endpoints to close
+const ENDPOINT_CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub async fn run_backend(cmd_rx: mpsc::Receiver<AppCommand>, event_tx: mpsc::Sender<AppEvent>) {
+    run_profile_backend(NodeConfig::default(), cmd_rx, event_tx).await;
+}
+
+/// [`run_backend`] starting from `config` instead of the defaults, for what
+/// the profile does not set, such as [`NodeConfig::wan_endpoint`]
+pub async fn run_profile_backend(
+    config: NodeConfig,
+    cmd_rx: mpsc::Receiver<AppCommand>,
+    event_tx: mpsc::Sender<AppEvent>,
+) {
+    run_backend_with_config(profile_config(config), cmd_rx, event_tx).await;
+}
+
+/// `config` with the active profile's settings and the installed policy
+/// applied, as [`run_profile_backend`] starts with
+pub fn profile_config(config: NodeConfig) -> NodeConfig {
+    NodeConfig {
+        policy: Policy::load(),
+        ..with_profile_settings(config, AppConfig::load())
+    }
+}
+
+/// Apply the active profile's `config.json` on top of `config`
+fn with_profile_settings(config: NodeConfig, app_config: AppConfig) -> NodeConfig {
+    let storage = match app_config
+        .storage
+        .open(&app_config.download_path, &app_config.proxy)
+    {
+        Ok(Some(remote)) => {
+            tracing::info!("Received files are written to {:?}", app_config.storage);
+            remote
+        }
+        Ok(None) => config.storage.clone(),
+        Err(e) => {
+            tracing::warn!(
+                "Storage settings unusable, using the download folder: {}",
+                e
+            );
+            config.storage.clone()
+        }
+    };
+    NodeConfig {
+        download_dir: app_config.download_path,
+        preserve_metadata: app_config.preserve_metadata,
+        preserve_executable: app_config.preserve_executable,
+        send_extended_attributes: app_config.send_extended_attributes,
+        request_receipts: app_config.request_receipts,
+        verify_read_back: app_config.verify_read_back,
+        transliterate_names: app_config.transliterate_names,
+        max_concurrent_pairings: app_config.max_concurrent_pairings,
+        room_key: app_config.room_key,
+        receive_only: app_config.receive_only,
+        retention: app_config.retention,
+        orphan_age_days: app_config.orphan_age_days.unwrap_or(config.orphan_age_days),
+        units: app_config.units,
+        per_peer_folders: app_config.per_peer_folders,
+        owner_mode: app_config.owner_mode,
+        upload_approval: app_config.upload_approval,
+        webhook_url: app_config.webhook_url,
+        post_receive_command: app_config.post_receive_command,
+        hash_threads: app_config.hash_threads,
+        hash_algorithm: app_config.hash_algorithm,
+        relay: app_config.relay,
+        receive_limits: app_config.receive_limits,
+        bandwidth_cap: app_config.bandwidth_cap,
+        metered_mode: app_config.metered_mode,
+        socket_buffers: app_config.socket_buffers.unwrap_or_default(),
+        udp_offload: app_config.udp_offload.unwrap_or(true),
+        network_emulation: NetworkEmulation::current(app_config.network_emulation.as_deref()),
+        app_id: AppId::from_config(app_config.app_id.as_deref()),
+        proxy: app_config.proxy,
+        rendezvous: app_config.rendezvous,
+        telemetry: app_config.telemetry,
+        storage,
+        event_output: config.event_output.clone().or(app_config.event_output),
+        ..config
+    }
+}
+
+/// Tell what UDP buffers the transfer socket got, visibly when the OS
+/// granted less than asked for, and whether offloads are in use
+async fn report_socket(
+    event_tx: &mpsc::Sender<AppEvent>,
+    asked: SocketBuffers,
+    report: SocketReport,
+) {
+    let effective = report.buffers;
+    let mut message = format!(
+        "UDP buffers: {} receive, {} send",
+        units::format_size(effective.recv as u64),
+        units::format_size(effective.send as u64)
+    );
+    let level = if asked.capped_in(&effective) {
+        message.push_str(&format!(
+            " of {} asked for; the OS limit is lower, fast transfers may lose packets",
+            units::format_size(asked.recv.max(asked.send) as u64)
+        ));
+        LogLevel::Info
+    } else {
+        LogLevel::Debug
+    };
+    let _ = event_tx
+        .send(AppEvent::log(level, EventCategory::Status, message))
+        .await;
+
+    let offload = if report.offload_active() {
+        format!(
+            "UDP offload active: GSO {} segments, GRO {} segments; set \"udp_offload\": false if transfers stall or fail checks",
+            report.gso_segments, report.gro_segments
+        )
+    } else {
+        "UDP offload inactive (GSO and GRO off or unsupported)".to_string()
+    };
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Debug,
+            EventCategory::Status,
+            offload,
+        ))
+        .await;
+}
+
+/// Keep this device registered with the rendezvous server, if one is set
+fn spawn_rendezvous(
+    settings: &RendezvousSettings,
+    proxy: &ProxySettings,
+    secret_key: Option<&iroh::SecretKey>,
+    name: &str,
+    event_tx: &mpsc::Sender<AppEvent>,
+) -> Result<Option<JoinHandle<()>>, String> {
+    if !settings.is_enabled() {
+        return Ok(None);
+    }
+    let Some(key) = secret_key else {
+        return Err("Rendezvous registration needs an Iroh identity".to_string());
+    };
+    let client = RendezvousClient::new(settings, proxy).map_err(|e| e.to_string())?;
+    Ok(Some(rendezvous::spawn_registration(
+        client,
+        key.clone(),
+        name.to_string(),
+        event_tx.clone(),
+    )))
+}
+
+/// Usage statistics of the profile, next to its history
+fn telemetry_path(config: &NodeConfig) -> Option<PathBuf> {
+    config
+        .history_file
+        .as_deref()
+        .map(|path| path.with_file_name(TELEMETRY_FILE))
+}
+
+/// Activate profile `name` and build the config to restart with: the
+/// profile's identity, pairings and settings, with the same ports
+fn switch_profile(config: &NodeConfig, name: &str) -> Result<NodeConfig, String> {
+    let profiles = ProfileManager::platform()
+        .ok_or_else(|| "No config directory available for profiles".to_string())?;
+    profiles.switch(name).map_err(|e| e.to_string())?;
+
+    let restarted = NodeConfig {
+        endpoint_id: None,
+        secret_key: None,
+        pairing_store: Arc::new(FilePairingStore::default()),
+        schedule_file: get_config_dir().map(|dir| dir.join(SCHEDULE_FILE)),
+        history_file: get_config_dir().map(|dir| dir.join(HISTORY_FILE)),
+        journal_file: get_config_dir().map(|dir| dir.join(JOURNAL_FILE)),
+        ..config.clone()
+    };
+    Ok(with_profile_settings(restarted, AppConfig::load()))
+}
+
+/// Run the backend loop with explicit ports and directories.
+///
+/// `run_backend` is this function with [`NodeConfig::default`], plus the
+/// download directory and other settings from the active profile's
+/// `config.json`.
+///
+/// [`AppCommand::SwitchProfile`] stops every endpoint and starts the backend
+/// again with the other profile. Between commands the loop starts scheduled
+/// sends that are due.
+pub async fn run_backend_with_config(
+    mut config: NodeConfig,
+    mut cmd_rx: mpsc::Receiver<AppCommand>,
+    event_tx: mpsc::Sender<AppEvent>,
+) {
+    // Results of reachability probes for due jobs: (job id, reachable)
+    let (probe_tx, mut probe_rx) = mpsc::channel(16);
+    // Journal IDs of sends that failed because the computer slept
+    let (interrupted_tx, mut interrupted_rx) = mpsc::channel(16);
+
+    // Events pass the usage statistics counter, the state tracker that
+    // completes `GetState` snapshots, then the JSON mirror if one is
+    // configured
+    let event_tx = match config.event_output.clone() {
+        Some(output) => json_events::spawn_mirror(output, event_tx),
+        None => event_tx,
+    };
+    let tracker = Arc::new(Mutex::new(StateTracker::default()));
+    let (tracked_tx, tracked_rx) = mpsc::channel(event_tx.max_capacity());
+    tokio::spawn(state::track_events(tracked_rx, event_tx, tracker.clone()));
+    let event_tx = tracked_tx;
+
+    // Errors are only counted once the user opted in
+    let telemetry = Arc::new(Telemetry::load(
+        config.telemetry.clone(),
+        telemetry_path(&config),
+    ));
+    let (counted_tx, counted_rx) = mpsc::channel(event_tx.max_capacity());
+    tokio::spawn(telemetry::count_events(
+        telemetry.clone(),
+        counted_rx,
+        event_tx,
+    ));
+    let event_tx = counted_tx;
+
+    let (remote, mut remote_rx) = RemoteControl::channel(16);
+
+    let Some(mut backend) = Backend::start(
+        config.clone(),
+        event_tx.clone(),
+        probe_tx.clone(),
+        interrupted_tx.clone(),
+        remote.clone(),
+        telemetry.clone(),
+    )
+    .await
+    else {
+        return;
+    };
+
+    let mut schedule_tick = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+    let mut health_tick = tokio::time::interval(HEALTH_INTERVAL);
+    let mut metrics_tick = tokio::time::interval(METRICS_INTERVAL);
+    let mut metrics = MetricsSampler::new(config.sample_cpu);
+    let mut telemetry_tick = tokio::time::interval(TELEMETRY_CHECK_INTERVAL);
+    let mut sleep_tick = tokio::time::interval(SLEEP_CHECK_INTERVAL);
+    sleep_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut sleep_detector = SleepDetector::default();
+    let mut network_tick = tokio::time::interval(NETWORK_CHECK_INTERVAL);
+    network_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut network_watcher = NetworkWatcher::default();
+
+    // Main loop: Wait for commands from UI
+    loop {
+        let cmd = tokio::select! {
+            cmd = cmd_rx.recv() => match cmd {
+                Some(cmd) => cmd,
+                None => break,
+            },
+            _ = schedule_tick.tick() => {
+                backend.check_network().await;
+                backend.check_usage().await;
+                backend.check_schedule();
+                continue;
+            }
+            _ = health_tick.tick() => {
+                let _ = event_tx.send(backend.health()).await;
+                continue;
+            }
+            _ = metrics_tick.tick() => {
+                let _ = event_tx.send(metrics.sample()).await;
+                continue;
+            }
+            _ = telemetry_tick.tick() => {
+                backend.check_telemetry().await;
+                continue;
+            }
+            _ = sleep_tick.tick() => {
+                if let Some(slept) = sleep_detector.check() {
+                    backend.woke_up(slept).await;
+                }
+                continue;
+            }
+            _ = network_tick.tick() => {
+                if let Some(change) = network_watcher.check(network_watch::interface_addrs()) {
+                    backend.network_moved(change).await;
+                }
+                continue;
+            }
+            Some((job_id, reachable)) = probe_rx.recv() => {
+                backend.probe_finished(&job_id, reachable).await;
+                continue;
+            }
+            Some(id) = interrupted_rx.recv() => {
+                backend.resume_after_sleep(&id).await;
+                continue;
+            }
+            Some(request) = remote_rx.recv() => {
+                match request {
+                    RemoteRequest::State(reply) => {
+                        let mut state = backend.state();
+                        if let Ok(tracker) = tracker.lock() {
+                            tracker.fill(&mut state);
+                        }
+                        let _ = reply.send(state);
+                    }
+                    RemoteRequest::Command(cmd, reply) => {
+                        let _ = reply.send(backend.handle_command(cmd).await);
+                    }
+                }
+                continue;
+            }
+        };
+
+        let (request_id, cmd) = match cmd {
+            AppCommand::Tracked {
+                request_id,
+                command,
+            } => (Some(request_id), *command),
+            cmd => (None, cmd),
+        };
+
+        let result = match cmd {
+            AppCommand::SwitchProfile { name } => match switch_profile(&config, &name) {
+                Ok(next) => {
+                    backend.stop().await;
+                    config = next;
+                    let Some(restarted) = Backend::start(
+                        config.clone(),
+                        event_tx.clone(),
+                        probe_tx.clone(),
+                        interrupted_tx.clone(),
+                        remote.clone(),
+                        telemetry.clone(),
+                    )
+                    .await
+                    else {
+                        return;
+                    };
+                    backend = restarted;
+                    let _ = event_tx.send(AppEvent::ProfileSwitched { name }).await;
+                    Ok(())
+                }
+                Err(e) => {
+                    let _ = event_tx
+                        .send(AppEvent::Error(format!("Cannot switch profile: {}", e)))
+                        .await;
+                    Err(e)
+                }
+            },
+            cmd => backend.handle_command(cmd).await,
+        };
+
+        // Through the backend's own sender, so the result comes after the
+        // events the command caused
+        if let Some(request_id) = request_id {
+            let _ = backend
+                .event_tx
+                .send(AppEvent::CommandResult { request_id, result })
+                .await;
+        }
+    }
+}
+
+/// Resolve a `SendFile` target: either a bare IP (default transfer port) or
+/// an explicit `ip:port` socket address
+fn parse_target_addr(target: &str) -> Result<SocketAddr, std::net::AddrParseError> {
+    target
+        .parse::<SocketAddr>()
+        .or_else(|_| format!("{}:{}", target, TRANSFER_PORT).parse())
+}
+
+/// Pick the best local IPv4 address for share URLs, see
+/// [`network_watch::lan_ip`]
+fn detect_lan_ip() -> String {
+    network_watch::lan_ip(&network_watch::interface_addrs())
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "127.0.0.1".to_string())
+}
+
+/// Periodically announce this node on the LAN, starting immediately
+fn spawn_discovery_broadcast(
+    ds: Arc<DiscoveryService>,
+    endpoint_id: String,
+    name: String,
+    transfer_port: u16,
+) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(DISCOVERY_INTERVAL_SECS));
+        // The first tick completes immediately
+        while !ds.is_shut_down() {
+            interval.tick().await;
+            ds.send_discovery_request(endpoint_id.clone(), name.clone(), transfer_port)
+                .await;
+        }
+    });
+}
+
+/// Apply the retention policy now and every [`RETENTION_CHECK_INTERVAL`]
+fn spawn_retention(
+    download_dir: PathBuf,
+    policy: RetentionPolicy,
+    event_tx: mpsc::Sender<AppEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            cleanup_download_dir(download_dir.clone(), policy.clone(), &event_tx).await;
+        }
+    })
+}
+
+/// Look for orphaned partial files now and every [`ORPHAN_CHECK_INTERVAL`]
+fn spawn_orphan_scan(
+    download_dir: PathBuf,
+    max_age: Duration,
+    event_tx: mpsc::Sender<AppEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ORPHAN_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let dir = download_dir.clone();
+            let files = tokio::task::spawn_blocking(move || {
+                orphans::find_orphans(&dir, max_age, SystemTime::now())
+            })
+            .await;
+            if let Ok(files) = files
+                && !files.is_empty()
+            {
+                let _ = event_tx.send(AppEvent::OrphanedPartials { files }).await;
+            }
+        }
+    })
+}
+
+/// Report where the pieces of every swarm are whenever that changes
+fn spawn_swarm_reports(
+    swarms: Arc<SwarmRegistry>,
+    event_tx: mpsc::Sender<AppEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(AVAILABILITY_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut reported: Vec<SwarmAvailability> = Vec::new();
+        loop {
+            interval.tick().await;
+            let current = swarms.all();
+            let mut changed = current.len() != reported.len();
+            let mut report = Vec::with_capacity(current.len());
+            for swarm in current {
+                if let Some(update) = swarm.availability_update() {
+                    changed = true;
+                    report.push(update);
+                } else if let Some(previous) =
+                    reported.iter().find(|r| r.swarm_id == swarm.swarm_id())
+                {
+                    report.push(previous.clone());
+                }
+            }
+            if changed {
+                report.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+                reported = report;
+                let _ = event_tx
+                    .send(AppEvent::SwarmAvailability {
+                        swarms: reported.clone(),
+                    })
+                    .await;
+            }
+        }
+    })
+}
+
+/// Run one cleanup pass off the async threads; report it if anything matched
+async fn cleanup_download_dir(
+    download_dir: PathBuf,
+    policy: RetentionPolicy,
+    event_tx: &mpsc::Sender<AppEvent>,
+) {
+    let report =
+        tokio::task::spawn_blocking(move || run_cleanup(&download_dir, &policy, SystemTime::now()))
+            .await;
+
+    if let Ok(report) = report
+        && (!report.files.is_empty() || report.dry_run)
+    {
+        let _ = event_tx
+            .send(AppEvent::CleanupReport {
+                dry_run: report.dry_run,
+                files: report.files,
+                freed_bytes: report.freed_bytes,
+            })
+            .await;
+    }
+}
+
+/// What happens to the files of a send once it is over
+enum SendSource {
+    /// The user's files, left alone
+    Keep,
+    /// Files written for this send; the folder is removed afterwards
+    Temporary(PathBuf),
+    /// The user's files, deleted once the receiver verified them
+    Move,
+}
+
+/// Files of one send and what goes with them
+struct OutgoingBatch {
+    files: Vec<PathBuf>,
+    /// Shown to the receiver and kept in its history
+    note: Option<String>,
+    source: SendSource,
+    /// Endpoint ID of the target when known, to carry a broken send on
+    /// over the WAN
+    endpoint_id: Option<String>,
+    /// Told how the send ended; dropped unanswered if it never started
+    done: Option<oneshot::Sender<Result<(), String>>>,
+}
+
+/// A send waiting for [`AppCommand::RespondMeteredSend`]
+struct HeldSend {
+    target_ip: String,
+    target_peer_name: String,
+    batch: OutgoingBatch,
+}
+
+/// State owned by the backend command loop
+pub(crate) struct Backend {
+    event_tx: mpsc::Sender<AppEvent>,
+    my_endpoint_id: String,
+    /// Iroh key behind `my_endpoint_id`, signs identity challenges
+    secret_key: Option<iroh::SecretKey>,
+    my_name: String,
+    transfer_port: u16,
+    /// `None` when discovery is disabled in the [`NodeConfig`]
+    discovery_service: Option<Arc<DiscoveryService>>,
+    server_endpoint: quinn::Endpoint,
+    server_task: JoinHandle<()>,
+    client_endpoint: Arc<quinn::Endpoint>,
+
+    /// Pending verification channels (session id -> Sender). A session
+    /// accepts several codes until the receiver's attempt limit is hit.
+    verification_pending: HashMap<String, mpsc::Sender<String>>,
+    verification_timeout: Duration,
+    /// Kiosk mode: outgoing commands are refused
+    receive_only: bool,
+    /// Commands the administrator forbids are refused
+    policy: Policy,
+    units: UnitPreference,
+
+    /// Sends waiting for their start time
+    schedule: ScheduleStore,
+
+    download_dir: PathBuf,
+    /// Received files go to `download_dir/<sender>/`
+    per_peer_folders: bool,
+    /// Handed to the HTTP owner page when owner mode is on
+    owner_remote: Option<RemoteControl>,
+    /// Browser uploads accepted without asking
+    upload_approval: http_share::UploadApprovalPolicy,
+    retention: RetentionPolicy,
+    /// Periodic cleanup, `None` when the policy has no rules
+    retention_task: Option<JoinHandle<()>>,
+    /// Periodic search for orphaned partial files, `None` when turned off
+    orphan_task: Option<JoinHandle<()>>,
+    /// Reports changes to the download folder; `None` when it can't be watched
+    folder_watcher: Option<FolderWatcher>,
+    bandwidth_cap: BandwidthCap,
+    /// Month ("YYYY-MM") and cap level last warned about
+    cap_warned: Option<(String, CapLevel)>,
+    metered_mode: MeteredMode,
+    /// Cost found by the last check, `None` before the first
+    network_cost: Option<NetworkCost>,
+    /// Shared by the sends started while metered
+    metered_limiter: Arc<RateLimiter>,
+    /// Files are sent with their extended attributes
+    send_extended_attributes: bool,
+    /// Receivers are asked for signed receipts
+    request_receipts: bool,
+    /// Large sends waiting for confirmation, by session ID
+    held_sends: HashMap<String, HeldSend>,
+    /// Due jobs whose peer is being probed right now
+    probing: HashSet<String>,
+    probe_tx: mpsc::Sender<(String, bool)>,
+    /// Bumped on every wake from sleep; a send failing after a bump was
+    /// broken by the sleep
+    wakes: watch::Sender<u64>,
+    interrupted_tx: mpsc::Sender<String>,
+    /// Secrets of the QR-code invites this node has shown
+    invites: Arc<InviteRegistry>,
+    /// New pairings are guest pairings while this is on
+    guests: Arc<GuestMode>,
+    /// Devices we trust and receivers that trust us
+    pairing_store: Arc<dyn PairingStore>,
+    /// Usage statistics, counted only when the user opted in
+    telemetry: Arc<Telemetry>,
+    /// Report shown to the user and waiting for an answer
+    telemetry_offer: Option<String>,
+    /// Stops LAN transfers in flight, both directions
+    transfer_cancel: Arc<TransferCancel>,
+    /// Verified outgoing connections, reused by consecutive sends
+    connection_pool: Arc<ConnectionPool>,
+    /// Moved sources waiting out their undo window
+    moves: Arc<PendingMoves>,
+    /// Received files by hash, with duplicates awaiting a decision
+    history: Arc<HistoryStore>,
+    /// Outgoing sends that have not finished, kept across crashes
+    journal: Arc<SendJournal>,
+    /// Swarm files this device sends or serves pieces of
+    swarms: Arc<SwarmRegistry>,
+    /// Reports where the pieces of `swarms` are
+    swarm_report_task: JoinHandle<()>,
+
+    /// HTTP Server state
+    http_cancel_token: Option<CancellationToken>,
+    upload_state: Arc<http_share::UploadState>,
+
+    /// WAN Share (ngrok tunnel) state
+    ngrok_tunnel: Option<http_share::NgrokTunnel>,
+    /// Proxy the tunnel and rendezvous requests connect through
+    proxy: ProxySettings,
+    rendezvous: RendezvousSettings,
+    /// Periodic rendezvous registration, `None` when no server is set
+    rendezvous_task: Option<JoinHandle<()>>,
+    /// Reported in health events, owned by the WAN listener
+    wan_endpoint: Option<watch::Receiver<iroh::Endpoint>>,
+    /// Protocol spoken on the LAN, shown on the share's about route
+    app_id: AppId,
+    current_session_token: Option<String>,
+}
+
+impl Backend {
+    /// Bind sockets and spawn the long-running services. Returns `None` (after
+    /// reporting the error) if a required socket cannot be bound.
+    async fn start(
+        config: NodeConfig,
+        event_tx: mpsc::Sender<AppEvent>,
+        probe_tx: mpsc::Sender<(String, bool)>,
+        interrupted_tx: mpsc::Sender<String>,
+        remote: RemoteControl,
+        telemetry: Arc<Telemetry>,
+    ) -> Option<Self> {
+        // Load environment variables from .env file (for NGROK_AUTHTOKEN etc.)
+        let _ = dotenvy::dotenv();
+
+        // Install rustls crypto provider (required for rustls 0.23+)
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
+        // The machine's policy wins over the profile, also after a switch
+        let config = config.policy.clone().apply(config);
+
+        // Received files are tagged, then announced to the webhook and the
+        // post-receive command on their way out
+        let event_tx = match config.webhook_url.as_deref().map(Webhook::new) {
+            Some(Ok(hook)) => {
+                let (hooked_tx, hooked_rx) = mpsc::channel(event_tx.max_capacity());
+                tokio::spawn(webhook::forward_events(hook, hooked_rx, event_tx));
+                hooked_tx
+            }
+            Some(Err(e)) => {
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Warning,
+                        EventCategory::Transfer,
+                        format!("Webhook disabled: {}", e),
+                    ))
+                    .await;
+                event_tx
+            }
+            None => event_tx,
+        };
+        let event_tx = match config
+            .post_receive_command
+            .as_deref()
+            .map(PostReceiveHook::new)
+        {
+            Some(Ok(hook)) => {
+                let (hooked_tx, hooked_rx) = mpsc::channel(event_tx.max_capacity());
+                tokio::spawn(post_receive::forward_events(hook, hooked_rx, event_tx));
+                hooked_tx
+            }
+            Some(Err(e)) => {
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Warning,
+                        EventCategory::Transfer,
+                        format!("Post-receive command disabled: {}", e),
+                    ))
+                    .await;
+                event_tx
+            }
+            None => event_tx,
+        };
+        // Programs are tagged before anything else sees them
+        let event_tx = {
+            let (tagged_tx, tagged_rx) = mpsc::channel(event_tx.max_capacity());
+            tokio::spawn(quarantine::forward_events(tagged_rx, event_tx));
+            tagged_tx
+        };
+        telemetry.reload(config.telemetry.clone(), telemetry_path(&config));
+
+        // 1. Get Endpoint ID and Hostname (using Iroh NodeId for unified identity)
+        units::set_unit_preference(config.units);
+        hash::set_hash_threads(config.hash_threads);
+        hash::set_hash_algorithm(config.hash_algorithm);
+        filename::set_transliterate_names(config.transliterate_names);
+        metadata::set_preserve_executable(config.preserve_executable);
+        queue::set_max_concurrent_pairings(config.max_concurrent_pairings);
+
+        let secret_key = config
+            .secret_key
+            .clone()
+            .or_else(identity::get_iroh_secret_key);
+        let my_endpoint_id = config
+            .endpoint_id
+            .clone()
+            .or_else(|| secret_key.as_ref().map(|key| key.public().to_string()))
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let my_name = config
+            .device_name
+            .clone()
+            .unwrap_or_else(identity::default_device_name);
+
+        // Send message to GUI
+        let _ = event_tx
+            .send(AppEvent::log(
+                LogLevel::Info,
+                EventCategory::Status,
+                format!("Endpoint ID: {}, Name: {}", my_endpoint_id, my_name),
+            ))
+            .await;
+
+        let discovery_service = if !config.enable_discovery {
+            None
+        } else {
+            match DiscoveryService::new(config.discovery_port).await {
+                Ok(ds) => {
+                    let room = config.room_key.as_deref().and_then(RoomKey::new);
+                    if room.is_some() {
+                        tracing::info!("Discovery limited to peers in the configured room");
+                    }
+                    let capabilities = PeerCapabilities {
+                        receive_only: config.receive_only,
+                        protocol: PROTOCOL_VERSION,
+                    };
+                    Some(Arc::new(
+                        ds.with_room_key(room).with_capabilities(capabilities),
+                    ))
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to bind discovery port {}: {}",
+                        config.discovery_port,
+                        e
+                    );
+                    let _ = event_tx
+                        .send(AppEvent::Error(format!(
+                            "Cant bind port {}: {}",
+                            config.discovery_port, e
+                        )))
+                        .await;
+                    return None;
+                }
+            }
+        };
+
+        if let Some(emulation) = &config.network_emulation {
+            let _ = event_tx
+                .send(AppEvent::log(
+                    LogLevel::Warning,
+                    EventCategory::Status,
+                    format!("Network emulation on: {}", emulation),
+                ))
+                .await;
+        }
+        let server_addr = SocketAddr::from(([0, 0, 0, 0], config.transfer_port));
+        let server_endpoint = match make_server_endpoint_with(
+            server_addr,
+            config.socket_buffers,
+            config.udp_offload,
+            &config.app_id,
+            config.network_emulation.as_ref(),
+        ) {
+            Ok((ep, report)) => {
+                report_socket(&event_tx, config.socket_buffers, report).await;
+                ep
+            }
+            Err(e) => {
+                let _ = event_tx
+                    .send(AppEvent::Error(format!("Cant init QUIC server: {}", e)))
+                    .await;
+                return None;
+            }
+        };
+        // Report the bound port so an ephemeral (0) configuration is visible
+        let transfer_port = server_endpoint
+            .local_addr()
+            .map(|addr| addr.port())
+            .unwrap_or(config.transfer_port);
+        let _ = event_tx
+            .send(AppEvent::log(
+                LogLevel::Debug,
+                EventCategory::Status,
+                format!("QUIC Server listening at port {}", transfer_port),
+            ))
+            .await;
+
+        let client_endpoint = match make_client_endpoint_with(
+            config.socket_buffers,
+            config.udp_offload,
+            &config.app_id,
+            config.network_emulation.as_ref(),
+        ) {
+            Ok((ep, _)) => Arc::new(ep),
+            Err(e) => {
+                let _ = event_tx
+                    .send(AppEvent::Error(format!("Cant init QUIC client: {}", e)))
+                    .await;
+                return None;
+            }
+        };
+
+        let download_dir = config.download_dir.clone();
+        let server_event_tx = event_tx.clone();
+        let pairing_store = config.pairing_store.clone();
+        let preserve_metadata = config.preserve_metadata;
+        let verify_read_back = config.verify_read_back;
+        let per_peer_folders = config.per_peer_folders;
+        let invites = Arc::new(InviteRegistry::default());
+        let server_invites = invites.clone();
+        let guests = Arc::new(GuestMode::default());
+        let server_guests = guests.clone();
+        let history = Arc::new(HistoryStore::load(config.history_file.clone()));
+        let server_history = history.clone();
+        let transfer_cancel = Arc::new(TransferCancel::default());
+        let server_cancel = transfer_cancel.clone();
+        let swarms = Arc::new(SwarmRegistry::default());
+        let server_swarms = swarms.clone();
+        let relay = Arc::new(
+            RelayService::new(config.relay.clone()).with_discovery(discovery_service.clone()),
+        );
+        let receive_limits = config.receive_limits.clone();
+        let storage = config.storage.clone();
+        let server_secret_key = secret_key.clone();
+        let server = server_endpoint.clone();
+        let server_task = tokio::spawn(async move {
+            let options = ServerOptions {
+                download_dir,
+                pairing_store,
+                invites: server_invites,
+                guests: server_guests,
+                preserve_metadata,
+                verify_read_back,
+                per_peer_folders,
+                history: server_history,
+                cancel: server_cancel,
+                swarms: server_swarms,
+                relay,
+                limits: receive_limits,
+                storage,
+                receipt_key: server_secret_key,
+            };
+            transfer::run_server(server, server_event_tx, options).await;
+        });
+
+        let retention_task = config.retention.is_enabled().then(|| {
+            spawn_retention(
+                config.download_dir.clone(),
+                config.retention.clone(),
+                event_tx.clone(),
+            )
+        });
+
+        let swarm_report_task = spawn_swarm_reports(swarms.clone(), event_tx.clone());
+
+        let orphan_task = orphans::max_age(config.orphan_age_days).map(|max_age| {
+            spawn_orphan_scan(config.download_dir.clone(), max_age, event_tx.clone())
+        });
+
+        let folder_watcher = match folder_watch::watch(&config.download_dir, event_tx.clone()) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                tracing::warn!(
+                    "Not watching {} for changes: {}",
+                    config.download_dir.display(),
+                    e
+                );
+                None
+            }
+        };
+
+        let rendezvous_task = match spawn_rendezvous(
+            &config.rendezvous,
+            &config.proxy,
+            secret_key.as_ref(),
+            &my_name,
+            &event_tx,
+        ) {
+            Ok(task) => task,
+            Err(e) => {
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Warning,
+                        EventCategory::Wan,
+                        format!("Rendezvous disabled: {}", e),
+                    ))
+                    .await;
+                None
+            }
+        };
+
+        if let Some(ds) = &discovery_service {
+            ds.start_listening(
+                event_tx.clone(),
+                my_endpoint_id.clone(),
+                my_name.clone(),
+                transfer_port,
+            );
+            spawn_discovery_broadcast(
+                ds.clone(),
+                my_endpoint_id.clone(),
+                my_name.clone(),
+                transfer_port,
+            );
+        }
+
+        let _ = event_tx
+            .send(AppEvent::BackendReady {
+                endpoint_id: my_endpoint_id.clone(),
+                device_name: my_name.clone(),
+                transfer_port,
+                receive_only: config.receive_only,
+            })
+            .await;
+
+        let journal = Arc::new(SendJournal::load(config.journal_file.clone()));
+        let pending = journal.pending();
+        if !pending.is_empty() {
+            let _ = event_tx
+                .send(AppEvent::PendingSends { sends: pending })
+                .await;
+        }
+
+        Some(Self {
+            moves: PendingMoves::new(event_tx.clone(), MOVE_UNDO_WINDOW),
+            event_tx,
+            my_endpoint_id,
+            secret_key,
+            my_name,
+            transfer_port,
+            discovery_service,
+            server_endpoint,
+            server_task,
+            client_endpoint,
+            verification_pending: HashMap::new(),
+            verification_timeout: config.verification_timeout,
+            receive_only: config.receive_only,
+            policy: config.policy.clone(),
+            units: config.units,
+            schedule: ScheduleStore::load(config.schedule_file.clone()),
+            probing: HashSet::new(),
+            download_dir: config.download_dir.clone(),
+            per_peer_folders,
+            owner_remote: config.owner_mode.then_some(remote),
+            upload_approval: config.upload_approval.clone(),
+            retention: config.retention.clone(),
+            retention_task,
+            orphan_task,
+            folder_watcher,
+            bandwidth_cap: config.bandwidth_cap.clone(),
+            cap_warned: None,
+            metered_mode: config.metered_mode,
+            network_cost: None,
+            metered_limiter: Arc::new(RateLimiter::new(METERED_SEND_RATE)),
+            send_extended_attributes: config.send_extended_attributes,
+            request_receipts: config.request_receipts,
+            held_sends: HashMap::new(),
+            probe_tx,
+            wakes: watch::Sender::new(0),
+            interrupted_tx,
+            invites,
+            guests,
+            pairing_store: config.pairing_store.clone(),
+            telemetry,
+            telemetry_offer: None,
+            transfer_cancel,
+            connection_pool: Arc::new(ConnectionPool::default()),
+            history,
+            journal,
+            swarms,
+            swarm_report_task,
+            http_cancel_token: None,
+            upload_state: Arc::new(http_share::UploadState::with_storage(
+                config.storage.clone(),
+            )),
+            ngrok_tunnel: None,
+            proxy: config.proxy.clone(),
+            rendezvous: config.rendezvous.clone(),
+            rendezvous_task,
+            wan_endpoint: config.wan_endpoint.clone(),
+            app_id: config.app_id.clone(),
+            current_session_token: None,
+        })
+    }
+
+    /// Execute one command. Failures are reported as events as before and
+    /// also returned so tracked commands can be acknowledged precisely.
+    pub(crate) async fn handle_command(&mut self, cmd: AppCommand) -> Result<(), String> {
+        let event_tx = self.event_tx.clone();
+        // Sessions whose transfer already ended are dropped here
+        self.verification_pending.retain(|_, tx| !tx.is_closed());
+
+        if self.receive_only && cmd.is_outgoing() {
+            let msg = "This device is in receive-only mode and cannot send files".to_string();
+            let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+            return Err(msg);
+        }
+        if let Err(rule) = self.policy.check(&cmd) {
+            let message = rule.reason().to_string();
+            let _ = event_tx
+                .send(AppEvent::PolicyViolation {
+                    rule,
+                    message: message.clone(),
+                })
+                .await;
+            return Err(message);
+        }
+        self.telemetry.count_command(&cmd);
+        match cmd {
+            AppCommand::StartDiscovery => {
+                // Trigger manual discovery immediately
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Debug,
+                        EventCategory::Discovery,
+                        "Manual scanning...",
+                    ))
+                    .await;
+                if let Some(ds) = &self.discovery_service {
+                    ds.send_discovery_request(
+                        self.my_endpoint_id.clone(),
+                        self.my_name.clone(),
+                        self.transfer_port,
+                    )
+                    .await;
+                }
+                Ok(())
+            }
+            AppCommand::SendFile {
+                session_id,
+                target_ip,
+                target_endpoint_id,
+                target_peer_name,
+                files,
+                note,
+            } => {
+                self.send_or_hold(
+                    session_id,
+                    target_ip,
+                    target_peer_name,
+                    OutgoingBatch {
+                        files,
+                        note,
+                        source: SendSource::Keep,
+                        endpoint_id: Some(target_endpoint_id).filter(|id| !id.is_empty()),
+                        done: None,
+                    },
+                )
+                .await
+            }
+            AppCommand::MoveFiles {
+                session_id,
+                target_ip,
+                target_endpoint_id,
+                target_peer_name,
+                files,
+                note,
+            } => {
+                self.send_or_hold(
+                    session_id,
+                    target_ip,
+                    target_peer_name,
+                    OutgoingBatch {
+                        files,
+                        note,
+                        source: SendSource::Move,
+                        endpoint_id: Some(target_endpoint_id).filter(|id| !id.is_empty()),
+                        done: None,
+                    },
+                )
+                .await
+            }
+            AppCommand::UndoMove { move_id } => {
+                if self.moves.undo(&move_id).await {
+                    Ok(())
+                } else {
+                    Err("The file was already deleted or kept".to_string())
+                }
+            }
+            AppCommand::SendText {
+                session_id,
+                target_ip,
+                target_peer_name,
+                text,
+            } => {
+                let dir = std::env::temp_dir().join(format!("p2p_text_{}", uuid::Uuid::new_v4()));
+                let path = dir.join(format!("text_{}.txt", now_timestamp()));
+                if let Err(e) =
+                    std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, text))
+                {
+                    let msg = format!("Could not prepare text: {}", e);
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                }
+                self.start_send(
+                    session_id,
+                    &target_ip,
+                    target_peer_name,
+                    OutgoingBatch {
+                        files: vec![path],
+                        note: None,
+                        source: SendSource::Temporary(dir),
+                        endpoint_id: None,
+                        done: None,
+                    },
+                    None,
+                )
+                .await
+            }
+            AppCommand::SendStream {
+                session_id,
+                target_ip,
+                target_peer_name,
+                file_name,
+                source,
+                note,
+            } => {
+                self.start_stream(
+                    session_id,
+                    &target_ip,
+                    target_peer_name,
+                    file_name,
+                    source,
+                    note,
+                )
+                .await
+            }
+            AppCommand::SwarmSend { file, targets } => self.start_swarm(file, targets).await,
+            AppCommand::SendToGroup {
+                group,
+                members,
+                files,
+                note,
+            } => self.start_group_send(group, members, files, note).await,
+            AppCommand::SendViaRelay {
+                session_id,
+                relay_ip,
+                target_ip,
+                target_peer_name,
+                files,
+            } => {
+                let relay = match parse_target_addr(&relay_ip) {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        let msg = format!("Invalid relay address: {}", e);
+                        let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                        return Err(msg);
+                    }
+                };
+                self.start_send(
+                    session_id,
+                    &target_ip,
+                    target_peer_name,
+                    OutgoingBatch {
+                        files,
+                        note: None,
+                        source: SendSource::Keep,
+                        endpoint_id: None,
+                        done: None,
+                    },
+                    Some(relay),
+                )
+                .await
+            }
+            AppCommand::EstimateSend {
+                session_id,
+                target_ip,
+                target_peer_name,
+                files,
+                note,
+            } => {
+                self.start_estimate(session_id, &target_ip, target_peer_name, files, note)
+                    .await
+            }
+            AppCommand::PairWithPeer {
+                session_id,
+                target_ip,
+                target_peer_name,
+            } => {
+                // A send without files stops after the handshake
+                self.start_send(
+                    session_id,
+                    &target_ip,
+                    target_peer_name,
+                    OutgoingBatch {
+                        files: Vec::new(),
+                        note: None,
+                        source: SendSource::Keep,
+                        endpoint_id: None,
+                        done: None,
+                    },
+                    None,
+                )
+                .await
+            }
+            AppCommand::ForgetPeer { endpoint_id } => {
+                self.pairing_store.remove_pairing(&endpoint_id);
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Info,
+                        EventCategory::Pairing,
+                        format!("Forgot pairing with {}", endpoint_id),
+                    ))
+                    .await;
+                self.report_known_peers().await;
+                Ok(())
+            }
+            AppCommand::SetGuestPairing { enabled } => {
+                self.guests.set(enabled);
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Info,
+                        EventCategory::Pairing,
+                        if enabled {
+                            "New pairings are guest pairings for one transfer"
+                        } else {
+                            "New pairings are trusted for 24 hours"
+                        },
+                    ))
+                    .await;
+                self.report_known_peers().await;
+                Ok(())
+            }
+            AppCommand::SetPeerPinned {
+                endpoint_id,
+                pinned,
+            } => {
+                let mut app_config = AppConfig::load();
+                app_config.pinned_peers.retain(|id| *id != endpoint_id);
+                if pinned {
+                    app_config.pinned_peers.push(endpoint_id);
+                }
+                app_config.save();
+                self.report_known_peers().await;
+                Ok(())
+            }
+            AppCommand::SetPeerGroup { name, members } => {
+                let mut app_config = AppConfig::load();
+                if let Err(msg) = groups::set_group(&mut app_config.peer_groups, &name, members) {
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                }
+                app_config.save();
+                self.report_known_peers().await;
+                Ok(())
+            }
+            AppCommand::ListKnownPeers => {
+                self.report_known_peers().await;
+                Ok(())
+            }
+            AppCommand::CancelTransfer => {
+                self.transfer_cancel.cancel_all();
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Info,
+                        EventCategory::Transfer,
+                        "Task cancelled.",
+                    ))
+                    .await;
+                Ok(())
+            }
+            AppCommand::SubmitVerificationCode { session_id, code } => {
+                if let Some(tx) = self.verification_pending.get(&session_id) {
+                    if tx.try_send(code).is_err() {
+                        let msg = "Cannot send verification code (task closed)".to_string();
+                        let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                        Err(msg)
+                    } else {
+                        let _ = event_tx
+                            .send(AppEvent::log(
+                                LogLevel::Info,
+                                EventCategory::Pairing,
+                                "Verification code submitted",
+                            ))
+                            .await;
+                        Ok(())
+                    }
+                } else {
+                    let msg = format!("No pending verification session {}", session_id);
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    Err(msg)
+                }
+            }
+            AppCommand::CancelVerification { session_id } => {
+                // Dropping the sender ends the session's wait for a code
+                self.verification_pending.remove(&session_id);
+                Ok(())
+            }
+            AppCommand::CreatePairingInvite => {
+                let invite = PairingInvite {
+                    endpoint_id: self.my_endpoint_id.clone(),
+                    peer_name: self.my_name.clone(),
+                    addr: SocketAddr::new(
+                        detect_lan_ip()
+                            .parse()
+                            .unwrap_or(std::net::Ipv4Addr::LOCALHOST.into()),
+                        self.transfer_port,
+                    ),
+                    secret: self.invites.issue(),
+                };
+                let _ = event_tx
+                    .send(AppEvent::PairingInviteCreated {
+                        uri: invite.to_uri(),
+                        expires_in_secs: INVITE_EXPIRY_SECS,
+                    })
+                    .await;
+                Ok(())
+            }
+            AppCommand::RedeemPairingInvite { session_id, invite } => {
+                let invite = match PairingInvite::parse(&invite) {
+                    Ok(invite) => invite,
+                    Err(e) => {
+                        let msg = format!("Invalid pairing invite: {}", e);
+                        let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                        return Err(msg);
+                    }
+                };
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Info,
+                        EventCategory::Pairing,
+                        format!("Pairing with {} ({})", invite.peer_name, invite.addr),
+                    ))
+                    .await;
+
+                let client_endpoint = self.client_endpoint.clone();
+                let context = transfer::TransferContext {
+                    session_id,
+                    my_endpoint_id: self.my_endpoint_id.clone(),
+                    my_name: self.my_name.clone(),
+                    target_peer_name: invite.peer_name.clone(),
+                    code_timeout: self.verification_timeout,
+                    pairings: self.pairing_store.clone(),
+                    secret_key: self.secret_key.clone(),
+                    cancel: self.transfer_cancel.clone(),
+                    pool: self.connection_pool.clone(),
+                    journal: None,
+                    moves: None,
+                    note: None,
+                    history: None,
+                    rate_limit: None,
+                    extended_attributes: false,
+                    receipts: false,
+                    receiver_endpoint_id: None,
+                };
+                tokio::spawn(async move {
+                    if let Err(e) =
+                        transfer::redeem_invite(&client_endpoint, &invite, &event_tx, &context)
+                            .await
+                    {
+                        let _ = event_tx
+                            .send(AppEvent::Error(format!("Pairing failed: {}", e)))
+                            .await;
+                    }
+                });
+                Ok(())
+            }
+            AppCommand::ScheduleSend {
+                target,
+                target_peer_name,
+                files,
+                at,
+            } => {
+                if let Err(e) = parse_target_addr(&target) {
+                    let msg = format!("Invalid address: {}", e);
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                }
+                if files.is_empty() {
+                    return Err("No files to schedule".to_string());
+                }
+                self.schedule.add(ScheduledSend {
+                    id: uuid::Uuid::new_v4().simple().to_string(),
+                    target,
+                    target_peer_name,
+                    files,
+                    at,
+                });
+                self.report_schedule().await;
+                // A job for "now" should not wait for the next tick
+                self.check_schedule();
+                Ok(())
+            }
+            AppCommand::CancelScheduledSend { job_id } => {
+                if self.schedule.remove(&job_id).is_none() {
+                    return Err(format!("No scheduled send {}", job_id));
+                }
+                self.report_schedule().await;
+                Ok(())
+            }
+            AppCommand::ListScheduledSends => {
+                self.report_schedule().await;
+                Ok(())
+            }
+            AppCommand::ResumePendingSend { id } => self.resume_pending(&id).await.map(|_| ()),
+            AppCommand::DiscardPendingSend { id } => {
+                if self.journal.remove(&id).is_none() {
+                    return Err(format!("No interrupted send {}", id));
+                }
+                self.report_pending_sends().await;
+                Ok(())
+            }
+            AppCommand::GetState => {
+                let _ = event_tx
+                    .send(AppEvent::StateSnapshot(Box::new(self.state())))
+                    .await;
+                Ok(())
+            }
+            AppCommand::SetUnitPreference(preference) => {
+                units::set_unit_preference(preference);
+                self.units = preference;
+                let mut app_config = AppConfig::load();
+                app_config.units = preference;
+                app_config.save();
+                Ok(())
+            }
+            AppCommand::SetHashAlgorithm(algorithm) => {
+                if algorithm == HashAlgorithm::Unknown {
+                    return Err("Unknown hash algorithm".to_string());
+                }
+                hash::set_hash_algorithm(algorithm);
+                let mut app_config = AppConfig::load();
+                app_config.hash_algorithm = algorithm;
+                app_config.save();
+                Ok(())
+            }
+            AppCommand::SetBandwidthCap(cap) => {
+                let mut app_config = AppConfig::load();
+                app_config.bandwidth_cap = cap.clone();
+                app_config.save();
+                self.bandwidth_cap = cap;
+                self.cap_warned = None;
+                self.check_usage().await;
+                let summary = self.history.usage.summary(&self.bandwidth_cap);
+                let _ = event_tx.send(AppEvent::BandwidthUsage(summary)).await;
+                Ok(())
+            }
+            AppCommand::GetBandwidthUsage => {
+                self.check_usage().await;
+                let summary = self.history.usage.summary(&self.bandwidth_cap);
+                let _ = event_tx.send(AppEvent::BandwidthUsage(summary)).await;
+                Ok(())
+            }
+            AppCommand::SetMeteredMode(mode) => {
+                let mut app_config = AppConfig::load();
+                app_config.metered_mode = mode;
+                app_config.save();
+                let was_metered = self.is_metered();
+                self.metered_mode = mode;
+                self.network_changed(was_metered).await;
+                Ok(())
+            }
+            AppCommand::SetTelemetryMode(mode) => {
+                let mut app_config = AppConfig::load();
+                app_config.telemetry.mode = mode;
+                app_config.save();
+                self.telemetry.set_mode(mode);
+                if mode == TelemetryMode::Off {
+                    self.telemetry_offer = None;
+                }
+                Ok(())
+            }
+            AppCommand::PreviewTelemetry => {
+                let payload = telemetry::payload(&self.telemetry.report(now_timestamp()));
+                let _ = event_tx
+                    .send(AppEvent::TelemetryPreview {
+                        payload,
+                        awaiting_consent: false,
+                    })
+                    .await;
+                Ok(())
+            }
+            AppCommand::RespondTelemetry { send } => {
+                let Some(payload) = self.telemetry_offer.take() else {
+                    return Err("No usage report is waiting for an answer".to_string());
+                };
+                self.telemetry.start_period(now_timestamp());
+                self.telemetry.save();
+                if send {
+                    let telemetry = self.telemetry.clone();
+                    let proxy = self.proxy.clone();
+                    tokio::spawn(async move {
+                        let (level, message) = match telemetry.send(payload, &proxy).await {
+                            Ok(()) => (LogLevel::Info, "Usage report sent, thank you".to_string()),
+                            Err(e) => (LogLevel::Warning, format!("Usage report not sent: {}", e)),
+                        };
+                        let _ = event_tx
+                            .send(AppEvent::log(level, EventCategory::Status, message))
+                            .await;
+                    });
+                }
+                Ok(())
+            }
+            AppCommand::RespondMeteredSend {
+                session_id,
+                accepted,
+            } => {
+                let Some(held) = self.held_sends.remove(&session_id) else {
+                    return Err("No send is waiting for that session".to_string());
+                };
+                if !accepted {
+                    let _ = event_tx
+                        .send(AppEvent::log(
+                            LogLevel::Info,
+                            EventCategory::Transfer,
+                            format!("Send to {} dropped", held.target_peer_name),
+                        ))
+                        .await;
+                    return Ok(());
+                }
+                self.start_send(
+                    session_id,
+                    &held.target_ip,
+                    held.target_peer_name,
+                    held.batch,
+                    None,
+                )
+                .await
+            }
+            AppCommand::SetProxy(settings) => {
+                if let Err(e) = settings.proxy_url() {
+                    let msg = e.to_string();
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                }
+                let mut app_config = AppConfig::load();
+                app_config.proxy = settings.clone();
+                app_config.save();
+                self.proxy = settings;
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Info,
+                        EventCategory::Wan,
+                        "Proxy settings saved; WAN relays use them after a restart",
+                    ))
+                    .await;
+                Ok(())
+            }
+            AppCommand::SetRendezvous(settings) => {
+                let task = match spawn_rendezvous(
+                    &settings,
+                    &self.proxy,
+                    self.secret_key.as_ref(),
+                    &self.my_name,
+                    &event_tx,
+                ) {
+                    Ok(task) => task,
+                    Err(msg) => {
+                        let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                        return Err(msg);
+                    }
+                };
+                if let Some(old) = std::mem::replace(&mut self.rendezvous_task, task) {
+                    old.abort();
+                }
+                let mut app_config = AppConfig::load();
+                app_config.rendezvous = settings.clone();
+                app_config.save();
+                self.rendezvous = settings;
+                Ok(())
+            }
+            AppCommand::ListMyDevices => {
+                let client =
+                    RendezvousClient::new(&self.rendezvous, &self.proxy).map_err(|e| e.to_string());
+                let client = match client {
+                    Ok(client) => client,
+                    Err(msg) => {
+                        let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                        return Err(msg);
+                    }
+                };
+                tokio::spawn(async move {
+                    let event = match client.devices().await {
+                        Ok(devices) => AppEvent::MyDevices { devices },
+                        Err(e) => AppEvent::Error(format!("Could not list your devices: {}", e)),
+                    };
+                    let _ = event_tx.send(event).await;
+                });
+                Ok(())
+            }
+            AppCommand::RunCleanup { dry_run } => {
+                if !self.retention.is_enabled() {
+                    return Err("No retention rules are configured".to_string());
+                }
+                let policy = RetentionPolicy {
+                    dry_run: dry_run || self.retention.dry_run,
+                    ..self.retention.clone()
+                };
+                cleanup_download_dir(self.download_dir.clone(), policy, &event_tx).await;
+                Ok(())
+            }
+            AppCommand::QueryHistory { query } => {
+                let mut records = self.history.transfers.query(&query);
+                records.truncate(MAX_QUERY_RESULTS);
+                let _ = event_tx
+                    .send(AppEvent::HistoryResults { query, records })
+                    .await;
+                Ok(())
+            }
+            AppCommand::ExportHistory { format, path } => {
+                let mut records = self.history.transfers.query(&HistoryQuery::default());
+                records.reverse();
+                if let Err(e) = write_export(&records, format, &path) {
+                    let msg = format!("Could not export history: {}", e);
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                }
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Success,
+                        EventCategory::Status,
+                        format!(
+                            "Exported {} transfer(s) to {}",
+                            records.len(),
+                            path.display()
+                        ),
+                    ))
+                    .await;
+                Ok(())
+            }
+            AppCommand::ResolveDuplicate { path, action } => {
+                if let Err(e) = self.history.resolve(&path, action) {
+                    let msg = format!("Could not resolve duplicate: {}", e);
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                }
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Info,
+                        EventCategory::Transfer,
+                        format!("Duplicate {}: {:?}", path.display(), action),
+                    ))
+                    .await;
+                Ok(())
+            }
+            AppCommand::ResolveOrphan { path, action } => {
+                if let Err(e) = orphans::resolve(&self.download_dir, &path, action) {
+                    let msg = format!("Could not resolve partial file: {}", e);
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                }
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Info,
+                        EventCategory::Transfer,
+                        format!("Partial file {}: {:?}", path.display(), action),
+                    ))
+                    .await;
+                Ok(())
+            }
+            AppCommand::RespondUploadRequest {
+                request_id,
+                accepted,
+            } => {
+                http_share::respond_to_upload(&self.upload_state, &request_id, accepted).await;
+                Ok(())
+            }
+            AppCommand::PushTextToWeb { text } => {
+                if let Err(e) = self
+                    .upload_state
+                    .shared_text
+                    .set(text, http_share::text::FROM_HOST)
+                {
+                    let _ = event_tx.send(AppEvent::Error(e.clone())).await;
+                    return Err(e);
+                }
+                Ok(())
+            }
+            AppCommand::ShareFileLink {
+                path,
+                expires_in_secs,
+                single_use,
+            } => {
+                let Some(file_name) = path
+                    .is_file()
+                    .then(|| path.file_name())
+                    .flatten()
+                    .map(|name| name.to_string_lossy().into_owned())
+                else {
+                    let msg = format!("Not a file: {}", path.display());
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                };
+                if !self.http_running() {
+                    self.start_http_server().await?;
+                }
+                let link_id = self.upload_state.file_links.issue(
+                    path,
+                    expires_in_secs.map(Duration::from_secs),
+                    single_use,
+                );
+                let link_path = format!("{}/{}", http_share::FILE_LINK_PREFIX, link_id);
+                let _ = event_tx
+                    .send(AppEvent::FileLinkCreated {
+                        url: format!(
+                            "http://{}:{}/{}",
+                            detect_lan_ip(),
+                            http_share::HTTP_PORT,
+                            link_path
+                        ),
+                        wan_url: self
+                            .ngrok_tunnel
+                            .as_ref()
+                            .map(|tunnel| format!("{}/{}", tunnel.base_url(), link_path)),
+                        link_id,
+                        file_name,
+                        expires_in_secs,
+                        single_use,
+                    })
+                    .await;
+                Ok(())
+            }
+            AppCommand::RevokeFileLink { link_id } => {
+                if self.upload_state.file_links.revoke(&link_id) {
+                    Ok(())
+                } else {
+                    Err("File link is no longer active".to_string())
+                }
+            }
+            AppCommand::ShareFiles {
+                paths,
+                expires_in_secs,
+            } => {
+                if paths.is_empty() {
+                    return Err("No files to share".to_string());
+                }
+                if let Some(path) = paths.iter().find(|path| !path.is_file()) {
+                    let msg = format!("Not a file: {}", path.display());
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                }
+                if !self.http_running() {
+                    self.start_http_server().await?;
+                }
+                let expires_in = expires_in_secs
+                    .map(Duration::from_secs)
+                    .unwrap_or(http_share::DEFAULT_FILE_SHARE_EXPIRY);
+                let share_id = self.upload_state.file_shares.create(paths, expires_in);
+                let file_names = self
+                    .upload_state
+                    .file_shares
+                    .files(&share_id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|file| file.name)
+                    .collect();
+                let share_path = format!("{}/{}", http_share::FILE_SHARE_PREFIX, share_id);
+                let _ = event_tx
+                    .send(AppEvent::FileShareCreated {
+                        url: format!(
+                            "http://{}:{}/{}",
+                            detect_lan_ip(),
+                            http_share::HTTP_PORT,
+                            share_path
+                        ),
+                        wan_url: self
+                            .ngrok_tunnel
+                            .as_ref()
+                            .map(|tunnel| format!("{}/{}", tunnel.base_url(), share_path)),
+                        share_id,
+                        file_names,
+                        expires_in_secs: expires_in.as_secs(),
+                    })
+                    .await;
+                Ok(())
+            }
+            AppCommand::RevokeFileShare { share_id } => {
+                if self.upload_state.file_shares.revoke(&share_id) {
+                    Ok(())
+                } else {
+                    Err("File share is no longer active".to_string())
+                }
+            }
+            AppCommand::StartHttpServer => {
+                // Stop existing server if running
+                if let Some(ct) = self.http_cancel_token.take() {
+                    ct.cancel();
+                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                }
+                let share_url = self.start_http_server().await?;
+                tracing::info!("HTTP server started: {}", share_url);
+                Ok(())
+            }
+            AppCommand::StopHttpServer => {
+                if let Some(ct) = self.http_cancel_token.take() {
+                    ct.cancel();
+                    let _ = event_tx.send(AppEvent::HttpServerStopped).await;
+                    tracing::info!("HTTP server stopped");
+                    Ok(())
+                } else {
+                    let msg = "HTTP server is not running".to_string();
+                    let _ = event_tx
+                        .send(AppEvent::log(
+                            LogLevel::Warning,
+                            EventCategory::Http,
+                            msg.clone(),
+                        ))
+                        .await;
+                    Err(msg)
+                }
+            }
+            AppCommand::WanConnect { target_endpoint_id } => {
+                tracing::info!("=== WAN Connect Command Received ===");
+                tracing::info!("Target Endpoint ID: {}", target_endpoint_id);
+
+                // Note: Actual WAN connection is handled in p2p_gui layer
+                // which has access to p2p_wan crate
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Debug,
+                        EventCategory::Wan,
+                        format!("WAN Connect request: {}", target_endpoint_id),
+                    ))
+                    .await;
+                Ok(())
+            }
+            AppCommand::StartWanShare => {
+                // First ensure HTTP server is running
+                if !self.http_running() {
+                    self.start_http_server().await?;
+                }
+
+                if self.is_metered() {
+                    let _ = event_tx
+                        .send(AppEvent::log(
+                            LogLevel::Warning,
+                            EventCategory::Wan,
+                            "The connection is metered; downloads from the WAN share use its data",
+                        ))
+                        .await;
+                }
+
+                // Now start ngrok tunnel
+                let session_token = self.current_session_token.clone().unwrap_or_default();
+
+                let proxy = self.proxy.url_for(http_share::tunnel::NGROK_HOST);
+                match http_share::NgrokTunnel::start(http_share::HTTP_PORT, &session_token, proxy)
+                    .await
+                {
+                    Ok(tunnel) => {
+                        let public_url = tunnel.public_url().to_string();
+                        self.ngrok_tunnel = Some(tunnel);
+                        let _ = event_tx
+                            .send(AppEvent::WanShareReady { url: public_url })
+                            .await;
+                        Ok(())
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to start ngrok tunnel: {}", e);
+                        let msg = format!("Failed to start tunnel: {}", e);
+                        let _ = event_tx.send(AppEvent::WanShareError(msg.clone())).await;
+                        Err(msg)
+                    }
+                }
+            }
+            AppCommand::StopWanShare => {
+                if let Some(tunnel) = self.ngrok_tunnel.take() {
+                    tunnel.stop();
+                    let _ = event_tx.send(AppEvent::WanShareStopped).await;
+                    tracing::info!("WAN share tunnel stopped");
+                    Ok(())
+                } else {
+                    let msg = "WAN share is not running".to_string();
+                    let _ = event_tx
+                        .send(AppEvent::log(
+                            LogLevel::Warning,
+                            EventCategory::Wan,
+                            msg.clone(),
+                        ))
+                        .await;
+                    Err(msg)
+                }
+            }
+            AppCommand::Tracked { .. } => Err("Tracked commands cannot be nested".to_string()),
+            AppCommand::SwitchProfile { .. } => {
+                Err("Profiles are switched by the command loop".to_string())
+            }
+        }
+    }
+
+    /// Generate a new session token, spawn the HTTP share server and notify
+    /// the GUI. Returns the share URL.
+    /// Start the HTTP share server, or say why its port cannot be bound
+    async fn start_http_server(&mut self) -> Result<String, String> {
+        let listener = match tokio::net::TcpListener::bind(("0.0.0.0", http_share::HTTP_PORT)).await
+        {
+            Ok(listener) => listener,
+            Err(e) => {
+                let msg = format!("HTTP server failed: {}", e);
+                tracing::error!("{}", msg);
+                let _ = self.event_tx.send(AppEvent::Error(msg.clone())).await;
+                return Err(msg);
+            }
+        };
+        let session_token = http_share::generate_session_token();
+        let base_url = format!("http://{}:{}", detect_lan_ip(), http_share::HTTP_PORT);
+        let share_url = format!("{}/{}", base_url, session_token);
+
+        // The owner page gets its own token; the share link never unlocks it
+        let owner = self
+            .owner_remote
+            .clone()
+            .map(|remote| http_share::OwnerAccess {
+                token: http_share::generate_session_token(),
+                remote,
+            });
+        let owner_url = owner
+            .as_ref()
+            .map(|owner| format!("{}/{}", base_url, owner.token));
+
+        let cancel_token = CancellationToken::new();
+        self.http_cancel_token = Some(cancel_token.clone());
+        self.current_session_token = Some(session_token.clone());
+
+        let http_event_tx = self.event_tx.clone();
+        let failed = cancel_token.clone();
+        let upload_state = self.upload_state.clone();
+        let per_peer_folders = self.per_peer_folders;
+        let approval = self.upload_approval.clone();
+        let about = http_share::AboutInfo::new(self.my_endpoint_id.clone(), &self.app_id);
+
+        tokio::spawn(async move {
+            if let Err(e) = http_share::serve_http_with_websocket(
+                listener,
+                &session_token,
+                http_event_tx.clone(),
+                upload_state,
+                per_peer_folders,
+                approval,
+                about,
+                owner,
+                Some(cancel_token),
+            )
+            .await
+            {
+                tracing::error!("HTTP server error: {}", e);
+                failed.cancel();
+                let _ = http_event_tx
+                    .send(AppEvent::Error(format!("HTTP server failed: {}", e)))
+                    .await;
+            }
+        });
+
+        // Notify GUI that server started
+        let _ = self
+            .event_tx
+            .send(AppEvent::HttpServerStarted {
+                url: share_url.clone(),
+                owner_url,
+            })
+            .await;
+
+        Ok(share_url)
+    }
+}
+
+impl Backend {
+    /// Save the usage statistics, and offer a report once one is due
+    async fn check_telemetry(&mut self) {
+        self.telemetry.save();
+        let now = now_timestamp();
+        if self.telemetry_offer.is_some() || !self.telemetry.is_due(now) {
+            return;
+        }
+        let payload = telemetry::payload(&self.telemetry.report(now));
+        self.telemetry_offer = Some(payload.clone());
+        let _ = self
+            .event_tx
+            .send(AppEvent::TelemetryPreview {
+                payload,
+                awaiting_consent: true,
+            })
+            .await;
+    }
+
+    /// Save the usage counters, and warn once per month and level when the
+    /// cap comes near or is reached
+    async fn check_usage(&mut self) {
+        self.history.usage.save();
+        let Some(cap) = self.bandwidth_cap.monthly_bytes else {
+            return;
+        };
+        let used = self
+            .history
+            .usage
+            .summary(&self.bandwidth_cap)
+            .month
+            .total();
+        let level = self.bandwidth_cap.level(used);
+        let month = chrono::Local::now().format("%Y-%m").to_string();
+        if level == CapLevel::Below
+            || self
+                .cap_warned
+                .as_ref()
+                .is_some_and(|(warned, warned_level)| *warned == month && *warned_level == level)
+        {
+            return;
+        }
+        self.cap_warned = Some((month, level));
+        let reached = level == CapLevel::Reached;
+        let _ = self
+            .event_tx
+            .send(AppEvent::BandwidthCapWarning {
+                used,
+                cap,
+                reached,
+                holding_scheduled: reached && self.bandwidth_cap.hold_scheduled,
+            })
+            .await;
+    }
+
+    /// This month's usage is at the cap
+    fn is_metered(&self) -> bool {
+        self.metered_mode
+            .is_metered(self.network_cost.unwrap_or_default())
+    }
+
+    /// Ask the OS what the connection costs and report it when that changed
+    async fn check_network(&mut self) {
+        let cost = tokio::task::spawn_blocking(network_info::detect)
+            .await
+            .unwrap_or_default();
+        if self.network_cost == Some(cost) {
+            return;
+        }
+        let was_metered = self.is_metered();
+        self.network_cost = Some(cost);
+        self.network_changed(was_metered).await;
+    }
+
+    /// Report the connection's cost, and stop the WAN share once the
+    /// connection became metered. Sends already running keep their rate.
+    async fn network_changed(&mut self, was_metered: bool) {
+        let metered = self.is_metered();
+        let _ = self
+            .event_tx
+            .send(AppEvent::NetworkCost {
+                detected: self.network_cost.unwrap_or_default(),
+                mode: self.metered_mode,
+                metered,
+            })
+            .await;
+        if metered
+            && !was_metered
+            && let Some(tunnel) = self.ngrok_tunnel.take()
+        {
+            tunnel.stop();
+            let _ = self.event_tx.send(AppEvent::WanShareStopped).await;
+            let _ = self
+                .event_tx
+                .send(AppEvent::log(
+                    LogLevel::Warning,
+                    EventCategory::Wan,
+                    "Stopped the WAN share: the connection is metered",
+                ))
+                .await;
+        }
+    }
+
+    /// Start a send the user asked for, or hold it for confirmation when
+    /// the connection is metered and the files are large
+    async fn send_or_hold(
+        &mut self,
+        session_id: String,
+        target_ip: String,
+        target_peer_name: String,
+        batch: OutgoingBatch,
+    ) -> Result<(), String> {
+        if self.is_metered() {
+            let total_size: u64 = batch
+                .files
+                .iter()
+                .filter_map(|path| std::fs::metadata(path).ok())
+                .map(|metadata| metadata.len())
+                .sum();
+            if total_size > METERED_CONFIRM_BYTES {
+                let _ = self
+                    .event_tx
+                    .send(AppEvent::MeteredSendHeld {
+                        session_id: session_id.clone(),
+                        target_peer_name: target_peer_name.clone(),
+                        files: batch.files.len(),
+                        total_size,
+                    })
+                    .await;
+                self.held_sends.insert(
+                    session_id,
+                    HeldSend {
+                        target_ip,
+                        target_peer_name,
+                        batch,
+                    },
+                );
+                return Ok(());
+            }
+        }
+        self.start_send(session_id, &target_ip, target_peer_name, batch, None)
+            .await
+    }
+
+    fn cap_reached(&self) -> bool {
+        let used = self
+            .history
+            .usage
+            .summary(&self.bandwidth_cap)
+            .month
+            .total();
+        self.bandwidth_cap.level(used) == CapLevel::Reached
+    }
+
+    /// Probe the peers of due jobs; reachable ones come back through
+    /// [`probe_finished`](Self::probe_finished). Nothing starts while the
+    /// data cap holds scheduled sends.
+    fn check_schedule(&mut self) {
+        if self.bandwidth_cap.hold_scheduled && self.cap_reached() {
+            return;
+        }
+        let now = now_timestamp();
+        for job in self.schedule.due(now) {
+            if self.probing.contains(&job.id) {
+                continue;
+            }
+            let Ok(addr) = parse_target_addr(&job.target) else {
+                continue;
+            };
+            self.probing.insert(job.id.clone());
+
+            let endpoint = self.client_endpoint.clone();
+            let probe_tx = self.probe_tx.clone();
+            let job_id = job.id.clone();
+            tokio::spawn(async move {
+                let reachable = probe_reachable(&endpoint, addr).await;
+                let _ = probe_tx.send((job_id, reachable)).await;
+            });
+        }
+    }
+
+    /// Start a due job whose peer answered; unreachable ones are retried on
+    /// the next check
+    async fn probe_finished(&mut self, job_id: &str, reachable: bool) {
+        self.probing.remove(job_id);
+        if !reachable {
+            return;
+        }
+        // Cancelled (or started by an earlier backend) while probing
+        let Some(job) = self.schedule.remove(job_id) else {
+            return;
+        };
+
+        let session_id = crate::new_session_id();
+        tracing::info!(
+            "Starting scheduled send {} to {}",
+            job.id,
+            job.target_peer_name
+        );
+        let _ = self
+            .event_tx
+            .send(AppEvent::ScheduledSendStarted {
+                job_id: job.id,
+                session_id: session_id.clone(),
+            })
+            .await;
+        let _ = self
+            .handle_command(AppCommand::SendFile {
+                session_id,
+                target_ip: job.target,
+                target_endpoint_id: String::new(),
+                target_peer_name: job.target_peer_name,
+                files: job.files,
+                note: None,
+            })
+            .await;
+        self.report_schedule().await;
+    }
+
+    /// What the backend itself knows; the event stream adds the rest
+    fn http_running(&self) -> bool {
+        self.http_cancel_token
+            .as_ref()
+            .is_some_and(|token| !token.is_cancelled())
+    }
+
+    /// The heartbeat: which services are up right now
+    fn health(&self) -> AppEvent {
+        let (wan_online, relay_latency_ms) = self
+            .wan_endpoint
+            .as_ref()
+            .map_or((false, None), |endpoint| {
+                health::relay_status(&endpoint.borrow())
+            });
+        AppEvent::BackendHealth {
+            discovery_ok: self
+                .discovery_service
+                .as_ref()
+                .is_some_and(|ds| !ds.is_shut_down()),
+            quic_listening: !self.server_task.is_finished(),
+            http_running: self.http_running(),
+            wan_online,
+            relay_latency_ms,
+        }
+    }
+
+    fn state(&self) -> BackendState {
+        BackendState {
+            endpoint_id: self.my_endpoint_id.clone(),
+            device_name: self.my_name.clone(),
+            transfer_port: self.transfer_port,
+            receive_only: self.receive_only,
+            units: self.units,
+            hash_algorithm: hash::hash_algorithm(),
+            metered_mode: self.metered_mode,
+            metered: self.is_metered(),
+            download_dir: self.download_dir.clone(),
+            paired_devices: self.pairing_store.get_all_pairings().len(),
+            scheduled_sends: self.schedule.jobs().len(),
+            ..Default::default()
+        }
+    }
+
+    /// Connect to `target_ip`, through `relay` if given, pair if needed and
+    /// send the batch's files, then deal with them as its `source` says
+    async fn start_send(
+        &mut self,
+        session_id: String,
+        target_ip: &str,
+        target_peer_name: String,
+        batch: OutgoingBatch,
+        relay: Option<SocketAddr>,
+    ) -> Result<(), String> {
+        let OutgoingBatch {
+            files,
+            note,
+            source,
+            endpoint_id,
+            done,
+        } = batch;
+        tracing::info!(
+            "Initiating transfer to {} ({}) with {} files",
+            target_peer_name,
+            target_ip,
+            files.len()
+        );
+        let event_tx = self.event_tx.clone();
+        let target_addr = match parse_target_addr(target_ip) {
+            Ok(addr) => addr,
+            Err(e) => {
+                let msg = format!("Invalid address: {}", e);
+                let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                return Err(msg);
+            }
+        };
+        if self.cap_reached() {
+            let _ = event_tx
+                .send(AppEvent::log(
+                    LogLevel::Warning,
+                    EventCategory::Transfer,
+                    "This month's data cap is reached; sending anyway",
+                ))
+                .await;
+        }
+        let receiver_endpoint_id = endpoint_id.clone().or_else(|| {
+            self.discovery_service
+                .as_ref()
+                .and_then(|ds| ds.endpoint_at(target_addr.ip()))
+        });
+        if self.request_receipts && receiver_endpoint_id.is_none() {
+            let _ = event_tx
+                .send(AppEvent::log(
+                    LogLevel::Info,
+                    EventCategory::Transfer,
+                    format!(
+                        "No receipts from {}: its endpoint ID is unknown",
+                        target_peer_name
+                    ),
+                ))
+                .await;
+        }
+
+        // Create channel for verification code
+        let (code_tx, code_rx) = mpsc::channel(MAX_VERIFICATION_ATTEMPTS as usize);
+
+        // Keyed by session so parallel sends to one host don't collide
+        self.verification_pending
+            .insert(session_id.clone(), code_tx);
+
+        let client_endpoint = self.client_endpoint.clone();
+
+        // Direct sends of the user's own files can be resumed after a crash
+        let journal =
+            (relay.is_none() && !matches!(source, SendSource::Temporary(_)) && !files.is_empty())
+                .then(|| {
+                    self.journal.begin(
+                        &session_id,
+                        target_ip,
+                        &target_peer_name,
+                        &files,
+                        now_timestamp(),
+                    )
+                });
+
+        // Create transfer context
+        let context = transfer::TransferContext {
+            session_id,
+            my_endpoint_id: self.my_endpoint_id.clone(),
+            my_name: self.my_name.clone(),
+            target_peer_name,
+            code_timeout: self.verification_timeout,
+            pairings: self.pairing_store.clone(),
+            secret_key: self.secret_key.clone(),
+            cancel: self.transfer_cancel.clone(),
+            pool: self.connection_pool.clone(),
+            journal,
+            moves: matches!(source, SendSource::Move).then(|| self.moves.clone()),
+            note: note.as_deref().and_then(transfer::metadata::clean_note),
+            history: Some(self.history.clone()),
+            rate_limit: self.is_metered().then(|| self.metered_limiter.clone()),
+            extended_attributes: self.send_extended_attributes,
+            receipts: self.request_receipts,
+            receiver_endpoint_id,
+        };
+
+        let journal_id = context
+            .journal
+            .is_some()
+            .then(|| context.session_id.clone());
+        let file_names: Vec<String> = files
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        let target_peer_name = context.target_peer_name.clone();
+        let file_count = files.len();
+        let wakes = self.wakes.subscribe();
+        let interrupted_tx = self.interrupted_tx.clone();
+        let journal = self.journal.clone();
+
+        tokio::spawn(async move {
+            let result = async {
+                let mut code_rx = Some(code_rx);
+                let target_addr = match relay {
+                    Some(relay) => {
+                        transfer::request_relay(
+                            &client_endpoint,
+                            relay,
+                            target_addr,
+                            &event_tx,
+                            &context,
+                            &mut code_rx,
+                        )
+                        .await?
+                    }
+                    None => target_addr,
+                };
+                transfer::send_files(
+                    &client_endpoint,
+                    target_addr,
+                    files,
+                    event_tx.clone(),
+                    context,
+                    code_rx,
+                )
+                .await
+            }
+            .await;
+            let outcome = match &result {
+                Ok(()) => Ok(()),
+                Err(e) => Err(transfer::explain(e)),
+            };
+            if let Err(e) = result {
+                // A wake since the start means the connection died in sleep
+                if wakes.has_changed().unwrap_or(false)
+                    && let Some(id) = journal_id
+                {
+                    tracing::info!("Send {} interrupted by sleep: {}", id, e);
+                    let _ = event_tx
+                        .send(AppEvent::SendInterrupted {
+                            target_peer_name,
+                            files: file_names,
+                        })
+                        .await;
+                    let _ = interrupted_tx.send(id).await;
+                } else {
+                    let reason = transfer::explain(&e);
+                    let _ = event_tx
+                        .send(AppEvent::Error(format!("File transfer failed: {}", reason)))
+                        .await;
+                    // Part way through: the rest may still get there over the WAN
+                    let broken = journal_id
+                        .zip(endpoint_id)
+                        .filter(|_| transfer::is_connection_lost(&e))
+                        .and_then(|(id, endpoint_id)| {
+                            let pending = journal.get(&id)?;
+                            let started = pending.files.len() < file_count
+                                || pending.files.iter().any(|file| file.sent > 0);
+                            started.then_some((pending, endpoint_id))
+                        });
+                    if let Some((pending, endpoint_id)) = broken {
+                        let _ = event_tx
+                            .send(AppEvent::SendBroken {
+                                session_id: pending.id,
+                                endpoint_id,
+                                target_peer_name: pending.target_peer_name,
+                                files: pending.files,
+                                reason,
+                            })
+                            .await;
+                    }
+                }
+            }
+            if let SendSource::Temporary(dir) = source {
+                let _ = tokio::fs::remove_dir_all(dir).await;
+            }
+            if let Some(done) = done {
+                let _ = done.send(outcome);
+            }
+        });
+        Ok(())
+    }
+
+    /// Send `files` to each member of `group` and report the members'
+    /// outcomes together as they come in
+    async fn start_group_send(
+        &mut self,
+        group: String,
+        members: Vec<GroupTarget>,
+        files: Vec<PathBuf>,
+        note: Option<String>,
+    ) -> Result<(), String> {
+        let event_tx = self.event_tx.clone();
+        if members.is_empty() || files.is_empty() {
+            let msg = format!("Nothing to send to {}: no members or no files", group);
+            let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+            return Err(msg);
+        }
+        tracing::info!(
+            "Sending {} files to group {} ({} members)",
+            files.len(),
+            group,
+            members.len()
+        );
+
+        let mut send = GroupSend::new(crate::new_session_id(), group, members.len());
+        let mut running = tokio::task::JoinSet::new();
+        for member in members {
+            let Some(target_ip) = member.target_ip.clone() else {
+                send.member_done(&member, Err("not on the LAN".to_string()));
+                continue;
+            };
+            let (done_tx, done_rx) = oneshot::channel();
+            let started = self
+                .send_or_hold(
+                    member.session_id.clone(),
+                    target_ip,
+                    member.target_peer_name.clone(),
+                    OutgoingBatch {
+                        files: files.clone(),
+                        note: note.clone(),
+                        source: SendSource::Keep,
+                        endpoint_id: Some(member.endpoint_id.clone()),
+                        done: Some(done_tx),
+                    },
+                )
+                .await;
+            match started {
+                Ok(()) => {
+                    running.spawn(async move {
+                        let result = done_rx
+                            .await
+                            .unwrap_or_else(|_| Err("the send did not start".to_string()));
+                        (member, result)
+                    });
+                }
+                Err(e) => send.member_done(&member, Err(e)),
+            }
+        }
+
+        tokio::spawn(async move {
+            if !running.is_empty() {
+                let _ = event_tx.send(send.progress()).await;
+            }
+            while let Some(finished) = running.join_next().await {
+                if let Ok((member, result)) = finished {
+                    send.member_done(&member, result);
+                    let _ = event_tx.send(send.progress()).await;
+                }
+            }
+            let _ = event_tx.send(send.finish()).await;
+        });
+        Ok(())
+    }
+
+    /// Connect to `target_ip`, pair if needed and send what `source` reads
+    /// as `file_name`
+    async fn start_stream(
+        &mut self,
+        session_id: String,
+        target_ip: &str,
+        target_peer_name: String,
+        file_name: String,
+        source: transfer::StreamSource,
+        note: Option<String>,
+    ) -> Result<(), String> {
+        let event_tx = self.event_tx.clone();
+        let target_addr = match parse_target_addr(target_ip) {
+            Ok(addr) => addr,
+            Err(e) => {
+                let msg = format!("Invalid address: {}", e);
+                let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                return Err(msg);
+            }
+        };
+        let Some(reader) = source.take() else {
+            return Err("The stream was already sent".to_string());
+        };
+        tracing::info!(
+            "Initiating stream {} to {} ({})",
+            file_name,
+            target_peer_name,
+            target_ip
+        );
+        let (code_tx, code_rx) = mpsc::channel(MAX_VERIFICATION_ATTEMPTS as usize);
+        self.verification_pending
+            .insert(session_id.clone(), code_tx);
+        let context = transfer::TransferContext {
+            session_id,
+            my_endpoint_id: self.my_endpoint_id.clone(),
+            my_name: self.my_name.clone(),
+            target_peer_name,
+            code_timeout: self.verification_timeout,
+            pairings: self.pairing_store.clone(),
+            secret_key: self.secret_key.clone(),
+            cancel: self.transfer_cancel.clone(),
+            pool: self.connection_pool.clone(),
+            journal: None,
+            moves: None,
+            note: note.as_deref().and_then(transfer::metadata::clean_note),
+            history: Some(self.history.clone()),
+            rate_limit: self.is_metered().then(|| self.metered_limiter.clone()),
+            extended_attributes: false,
+            receipts: false,
+            receiver_endpoint_id: None,
+        };
+        let client_endpoint = self.client_endpoint.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = transfer::send_stream(
+                &client_endpoint,
+                target_addr,
+                &file_name,
+                reader,
+                event_tx.clone(),
+                context,
+                Some(code_rx),
+            )
+            .await
+            {
+                let _ = event_tx
+                    .send(AppEvent::Error(format!(
+                        "File transfer failed: {}",
+                        transfer::explain(&e)
+                    )))
+                    .await;
+            }
+        });
+        Ok(())
+    }
+
+    /// Ask the receiver what sending `files` would move, pairing first if
+    /// needed
+    async fn start_estimate(
+        &mut self,
+        session_id: String,
+        target_ip: &str,
+        target_peer_name: String,
+        files: Vec<PathBuf>,
+        note: Option<String>,
+    ) -> Result<(), String> {
+        let event_tx = self.event_tx.clone();
+        let target_addr = match parse_target_addr(target_ip) {
+            Ok(addr) => addr,
+            Err(e) => {
+                let msg = format!("Invalid address: {}", e);
+                let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                return Err(msg);
+            }
+        };
+        let (code_tx, code_rx) = mpsc::channel(MAX_VERIFICATION_ATTEMPTS as usize);
+        self.verification_pending
+            .insert(session_id.clone(), code_tx);
+        let context = transfer::TransferContext {
+            session_id,
+            my_endpoint_id: self.my_endpoint_id.clone(),
+            my_name: self.my_name.clone(),
+            target_peer_name,
+            code_timeout: self.verification_timeout,
+            pairings: self.pairing_store.clone(),
+            secret_key: self.secret_key.clone(),
+            cancel: self.transfer_cancel.clone(),
+            pool: self.connection_pool.clone(),
+            journal: None,
+            moves: None,
+            note: note.as_deref().and_then(transfer::metadata::clean_note),
+            history: None,
+            rate_limit: self.is_metered().then(|| self.metered_limiter.clone()),
+            extended_attributes: false,
+            receipts: false,
+            receiver_endpoint_id: None,
+        };
+        let client_endpoint = self.client_endpoint.clone();
+
+        tokio::spawn(async move {
+            let mut code_rx = Some(code_rx);
+            match transfer::estimate_send(
+                &client_endpoint,
+                target_addr,
+                &files,
+                &event_tx,
+                &context,
+                &mut code_rx,
+            )
+            .await
+            {
+                Ok(estimate) => {
+                    let _ = event_tx
+                        .send(AppEvent::SendEstimated {
+                            session_id: context.session_id,
+                            target_peer_name: context.target_peer_name,
+                            estimate,
+                        })
+                        .await;
+                }
+                Err(e) => {
+                    let _ = event_tx
+                        .send(AppEvent::Error(format!(
+                            "Estimate failed: {}",
+                            transfer::explain(&e)
+                        )))
+                        .await;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Offer `file` to all `targets` as one swarm, pairing with each as
+    /// needed
+    async fn start_swarm(
+        &mut self,
+        file: PathBuf,
+        targets: Vec<SwarmTarget>,
+    ) -> Result<(), String> {
+        let event_tx = self.event_tx.clone();
+        if targets.is_empty() || targets.len() > MAX_SWARM_PEERS {
+            let msg = format!("A swarm needs 1 to {} receivers", MAX_SWARM_PEERS);
+            let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+            return Err(msg);
+        }
+        let mut receivers = Vec::with_capacity(targets.len());
+        for target in targets {
+            match parse_target_addr(&target.target_ip) {
+                Ok(addr) => receivers.push((addr, target)),
+                Err(e) => {
+                    let msg = format!("Invalid address {}: {}", target.target_ip, e);
+                    let _ = event_tx.send(AppEvent::Error(msg.clone())).await;
+                    return Err(msg);
+                }
+            }
+        }
+        tracing::info!(
+            "Starting swarm of {} to {} receivers",
+            file.display(),
+            receivers.len()
+        );
+
+        let mut offers = Vec::with_capacity(receivers.len());
+        for (addr, target) in receivers {
+            let (code_tx, code_rx) = mpsc::channel(MAX_VERIFICATION_ATTEMPTS as usize);
+            self.verification_pending
+                .insert(target.session_id.clone(), code_tx);
+            let context = transfer::TransferContext {
+                session_id: target.session_id,
+                my_endpoint_id: self.my_endpoint_id.clone(),
+                my_name: self.my_name.clone(),
+                target_peer_name: target.target_peer_name,
+                code_timeout: self.verification_timeout,
+                pairings: self.pairing_store.clone(),
+                secret_key: self.secret_key.clone(),
+                cancel: self.transfer_cancel.clone(),
+                pool: self.connection_pool.clone(),
+                journal: None,
+                moves: None,
+                note: None,
+                history: None,
+                rate_limit: None,
+                extended_attributes: false,
+                receipts: false,
+                receiver_endpoint_id: None,
+            };
+            offers.push((addr, context, code_rx));
+        }
+
+        let client_endpoint = self.client_endpoint.clone();
+        let swarms = self.swarms.clone();
+        let seeder_port = self.transfer_port;
+        tokio::spawn(async move {
+            let peers = offers.iter().map(|(addr, _, _)| *addr).collect();
+            let seeded = async {
+                let manifest = swarm::build_manifest(&file, seeder_port, peers).await?;
+                swarms.insert(Arc::new(SwarmFile::seeding(manifest.clone(), file).await?));
+                anyhow::Ok(manifest)
+            }
+            .await;
+            let manifest = match seeded {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    let _ = event_tx
+                        .send(AppEvent::Error(format!("Could not start swarm: {}", e)))
+                        .await;
+                    return;
+                }
+            };
+            for (addr, context, code_rx) in offers {
+                let client_endpoint = client_endpoint.clone();
+                let manifest = manifest.for_target(addr);
+                let event_tx = event_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = transfer::offer_swarm(
+                        &client_endpoint,
+                        addr,
+                        manifest,
+                        event_tx.clone(),
+                        context,
+                        Some(code_rx),
+                    )
+                    .await
+                    {
+                        let _ = event_tx
+                            .send(AppEvent::Error(format!(
+                                "Swarm offer to {} failed: {}",
+                                addr, e
+                            )))
+                            .await;
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    async fn report_known_peers(&self) {
+        let app_config = AppConfig::load();
+        let paired: Vec<String> = self
+            .pairing_store
+            .get_all_pairings()
+            .into_iter()
+            .map(|(endpoint_id, _)| endpoint_id)
+            .collect();
+        let guests = paired
+            .iter()
+            .filter(|endpoint_id| self.pairing_store.is_guest(endpoint_id))
+            .cloned()
+            .collect();
+        let _ = self
+            .event_tx
+            .send(AppEvent::KnownPeersChanged {
+                paired,
+                guests,
+                guest_mode: self.guests.is_enabled(),
+                pinned: app_config.pinned_peers,
+                groups: app_config.peer_groups,
+            })
+            .await;
+    }
+
+    async fn report_pending_sends(&self) {
+        let _ = self
+            .event_tx
+            .send(AppEvent::PendingSends {
+                sends: self.journal.pending(),
+            })
+            .await;
+    }
+
+    /// Queue the rest of journaled send `id` to start once its peer
+    /// answers; returns the peer's name
+    async fn resume_pending(&mut self, id: &str) -> Result<String, String> {
+        let Some(send) = self.journal.remove(id) else {
+            return Err(format!("No interrupted send {}", id));
+        };
+        self.report_pending_sends().await;
+        self.schedule.add(ScheduledSend {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            target: send.target,
+            target_peer_name: send.target_peer_name.clone(),
+            files: send.files.into_iter().map(|file| file.path).collect(),
+            at: now_timestamp(),
+        });
+        self.report_schedule().await;
+        self.check_schedule();
+        Ok(send.target_peer_name)
+    }
+
+    /// The computer slept for `slept`. Close the connections that died
+    /// meanwhile, so the sends on them fail now and come back through
+    /// [`Self::resume_after_sleep`]
+    async fn woke_up(&mut self, slept: Duration) {
+        tracing::info!("Woke up after sleeping {:?}", slept);
+        // Bump first: the sends fail as soon as their connection closes
+        self.wakes.send_modify(|wakes| *wakes += 1);
+        self.connection_pool.close_all(b"sleep");
+        let _ = self
+            .event_tx
+            .send(AppEvent::SystemResumed {
+                slept_secs: slept.as_secs(),
+            })
+            .await;
+    }
+
+    /// The interface addresses changed, e.g. on a switch to another Wi-Fi
+    /// network. Look for peers on the new network and announce the share
+    /// URL under the new address. Peers left behind stop answering
+    /// heartbeats and are reported lost.
+    async fn network_moved(&mut self, change: NetworkChange) {
+        let lan_ip = detect_lan_ip();
+        tracing::info!(
+            "Network changed (added {:?}, removed {:?}), now at {}",
+            change.added,
+            change.removed,
+            lan_ip
+        );
+        let _ = self
+            .event_tx
+            .send(AppEvent::NetworkChanged {
+                lan_ip: lan_ip.clone(),
+                added: change.added.iter().map(|ip| ip.to_string()).collect(),
+                removed: change.removed.iter().map(|ip| ip.to_string()).collect(),
+            })
+            .await;
+
+        if let Some(endpoint) = &self.wan_endpoint {
+            let endpoint = endpoint.borrow().clone();
+            endpoint.network_change().await;
+        }
+        if let Some(ds) = &self.discovery_service {
+            ds.send_discovery_request(
+                self.my_endpoint_id.clone(),
+                self.my_name.clone(),
+                self.transfer_port,
+            )
+            .await;
+        }
+
+        // The listener is bound to every interface and follows the change;
+        // one that died with the old network is started again
+        match (&self.http_cancel_token, &self.current_session_token) {
+            (Some(token), _) if token.is_cancelled() => {
+                let _ = self.start_http_server().await;
+            }
+            (Some(_), Some(session_token)) => {
+                let url = format!(
+                    "http://{}:{}/{}",
+                    lan_ip,
+                    http_share::HTTP_PORT,
+                    session_token
+                );
+                let _ = self.event_tx.send(AppEvent::ShareUrlReady { url }).await;
+            }
+            _ => {}
+        }
+    }
+
+    /// Continue a send the sleep broke, once its peer answers
+    async fn resume_after_sleep(&mut self, id: &str) {
+        if let Ok(peer) = self.resume_pending(id).await {
+            let _ = self
+                .event_tx
+                .send(AppEvent::log(
+                    LogLevel::Info,
+                    EventCategory::Transfer,
+                    format!("Resuming the send to {} once it answers", peer),
+                ))
+                .await;
+        }
+    }
+
+    async fn report_schedule(&self) {
+        let _ = self
+            .event_tx
+            .send(AppEvent::ScheduledSendsChanged {
+                jobs: self.schedule.jobs().to_vec(),
+            })
+            .await;
+    }
+
+    /// Close every endpoint and wait until the sockets are released, so a
+    /// new backend can bind the same ports
+    async fn stop(mut self) {
+        self.shutdown_services();
+        self.history.usage.save();
+        self.telemetry.save();
+        let _ = tokio::time::timeout(ENDPOINT_CLOSE_TIMEOUT, async {
+            self.server_endpoint.wait_idle().await;
+            self.client_endpoint.wait_idle().await;
+        })
+        .await;
+    }
+
+    fn shutdown_services(&mut self) {
+        // Discovery tasks are detached; stop them with the backend so peers
+        // see this node go away
+        if let Some(ds) = &self.discovery_service {
+            ds.shutdown();
+        }
+        self.server_task.abort();
+        if let Some(task) = self.retention_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.orphan_task.take() {
+            task.abort();
+        }
+        self.swarm_report_task.abort();
+        if let Some(task) = self.rendezvous_task.take() {
+            task.abort();
+        }
+        self.folder_watcher = None;
+        let reason = transfer::close::APP_STOPPED_REASON.as_bytes();
+        self.server_endpoint.close(0u32.into(), reason);
+        self.client_endpoint.close(0u32.into(), reason);
+        if let Some(token) = self.http_cancel_token.take() {
+            token.cancel();
+        }
+        self.ngrok_tunnel = None;
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        self.shutdown_services();
+    }
+}
@@ -1,9 +1,24 @@
+use crate::groups::PeerGroup;
+use crate::history::usage::BandwidthCap;
+use crate::http_share::UploadApprovalPolicy;
+use crate::json_events::EventOutput;
+use crate::network_info::MeteredMode;
+use crate::proxy::ProxySettings;
+use crate::rendezvous::RendezvousSettings;
+use crate::retention::RetentionPolicy;
+use crate::storage::StorageSettings;
+use crate::telemetry::TelemetrySettings;
+use crate::transfer::{HashAlgorithm, ReceiveLimits, RelayPolicy, SocketBuffers};
+use crate::units::UnitPreference;
+use anyhow::{Context, Result, anyhow};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use uuid::Uuid;
+use zeroize::{Zeroize, Zeroizing};
 
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
@@ -13,6 +28,29 @@ const APP_ORGANIZATION: &str = "p2p";
 const APP_NAME: &str = "p2p_transfer";
 const ENDPOINT_ID_FILE: &str = "endpoint_id.txt";
 const CONFIG_FILE: &str = "config.json";
+const PROFILES_DIR: &str = "profiles";
+const ACTIVE_PROFILE_FILE: &str = "active_profile.txt";
+
+/// Profile stored directly in the config directory (pre-profile layout)
+pub const DEFAULT_PROFILE: &str = "Default";
+
+/// File in the base config directory where the GUI keeps its layout
+pub const GUI_STATE_FILE: &str = "gui_state.ron";
+
+/// A file of this name next to the executable turns on portable mode: the
+/// config and downloads live beside the executable, e.g. on a USB stick
+pub const PORTABLE_FILE: &str = "portable";
+
+/// Set to anything but `0` or empty for portable mode without the file
+pub const PORTABLE_ENV: &str = "P2P_PORTABLE";
+
+/// Config directory of portable mode, inside its directory
+const PORTABLE_CONFIG_DIR: &str = "config";
+
+/// Download directory of portable mode, inside its directory
+const PORTABLE_DOWNLOAD_DIR: &str = "downloads";
+
+const MAX_PROFILE_NAME_LENGTH: usize = 32;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PairedDevice {
@@ -20,24 +58,260 @@ pub struct PairedDevice {
     pub peer_name: String,
     /// Unix timestamp when pairing was established
     pub paired_at: u64,
+    /// Key agreed during pairing (see [`crate::pairing::key`]); empty for
+    /// pairings made before keys existed, which need a new code
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub key: String,
+    /// Guest pairing: ends after an hour or one transfer (see
+    /// [`crate::pairing::guest`])
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub guest: bool,
+}
+
+impl Drop for PairedDevice {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// Key a receiver agreed with us when we paired with it, by key ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiverKey {
+    pub key: String,
+    pub peer_name: String,
+    pub paired_at: u64,
+}
+
+impl Drop for ReceiverKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// How files travel over a WAN connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WanStrategy {
+    /// One stream per file, resumed from the receiver's offset
+    #[default]
+    Stream,
+    /// Content-addressed chunk collections; see `p2p_wan::blobs`
+    Blobs,
+}
+
+impl WanStrategy {
+    pub const ALL: [WanStrategy; 2] = [Self::Stream, Self::Blobs];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Stream => "Stream",
+            Self::Blobs => "Content-addressed",
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::Stream
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     pub pairing: HashMap<String, PairedDevice>,
+    /// Receivers that trust this device, keyed by key ID
+    #[serde(default)]
+    pub receiver_keys: HashMap<String, ReceiverKey>,
     pub download_path: PathBuf,
+    /// Restore the sender's modification time and permissions on received files
+    #[serde(default = "default_preserve_metadata")]
+    pub preserve_metadata: bool,
+    /// Let senders mark received files executable (Unix); off, received
+    /// files are only readable and writable by their owner
+    #[serde(default)]
+    pub preserve_executable: bool,
+    /// Send each file's extended attributes (Finder tags, resource forks)
+    #[serde(default)]
+    pub send_extended_attributes: bool,
+    /// Ask receivers for a signed receipt of each file sent, kept in the
+    /// history as proof of delivery
+    #[serde(default)]
+    pub request_receipts: bool,
+    /// Check received files by reading them back from the disk, for flaky
+    /// USB sticks and network shares; costs throughput
+    #[serde(default)]
+    pub verify_read_back: bool,
+    /// Save files whose names the download folder's file system rejects
+    /// (FAT32 sticks) under an ASCII transliteration
+    #[serde(default = "default_transliterate_names")]
+    pub transliterate_names: bool,
+    /// Verification codes shown at once; further senders wait in line
+    #[serde(default = "default_max_concurrent_pairings")]
+    pub max_concurrent_pairings: usize,
+    /// Discovery room; only instances with the same key see each other
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_key: Option<String>,
+    /// Kiosk mode: accept files but refuse to send any
+    #[serde(default)]
+    pub receive_only: bool,
+    /// Automatic cleanup of the download folder
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Units for sizes and speeds
+    #[serde(default)]
+    pub units: UnitPreference,
+    /// Save received files in a subfolder per sender
+    #[serde(default)]
+    pub per_peer_folders: bool,
+    /// URL notified of every received file (see [`crate::webhook`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    /// Command run for every received file (see [`crate::post_receive`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_receive_command: Option<String>,
+    /// Serve the owner page for remote control next to the share page
+    #[serde(default)]
+    pub owner_mode: bool,
+    /// Browser uploads accepted without asking
+    #[serde(default)]
+    pub upload_approval: UploadApprovalPolicy,
+    /// Endpoint IDs kept at the top of the device list
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pinned_peers: Vec<String>,
+    /// Named groups of peers to send to together
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peer_groups: Vec<PeerGroup>,
+    /// Most threads used to hash a file (unset = one per core)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_threads: Option<usize>,
+    /// Checksum for files sent from here
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_default")]
+    pub hash_algorithm: HashAlgorithm,
+    /// Forward transfers between paired peers that cannot reach each other
+    #[serde(default)]
+    pub relay: RelayPolicy,
+    /// Streams, files and bytes accepted from one peer
+    #[serde(default)]
+    pub receive_limits: ReceiveLimits,
+    /// Monthly data cap for metered connections
+    #[serde(default)]
+    pub bandwidth_cap: BandwidthCap,
+    /// Override of the detected connection cost
+    #[serde(default)]
+    pub metered_mode: MeteredMode,
+    /// Seconds between heartbeats on WAN connections (0 = off)
+    #[serde(default = "default_wan_heartbeat_secs")]
+    pub wan_heartbeat_secs: u64,
+    /// How the WAN window sends files
+    #[serde(default, skip_serializing_if = "WanStrategy::is_default")]
+    pub wan_strategy: WanStrategy,
+    /// Offer WAN files without their names until the receiver accepts
+    #[serde(default)]
+    pub wan_hide_names: bool,
+    /// Proxy for WAN relays and the tunnel
+    #[serde(default)]
+    pub proxy: ProxySettings,
+    /// Rendezvous server listing this user's devices
+    #[serde(default)]
+    pub rendezvous: RendezvousSettings,
+    /// Mirror backend events as JSON lines (see [`crate::json_events`]);
+    /// `P2P_EVENT_OUTPUT` takes precedence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_output: Option<EventOutput>,
+    /// UDP buffer sizes of the LAN sockets; unset is the platform default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_buffers: Option<SocketBuffers>,
+    /// UDP segmentation offloads (GSO/GRO) of the LAN sockets; set to
+    /// `false` when a NIC driver corrupts transfers. Unset is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp_offload: Option<bool>,
+    /// Latency, jitter and rate cap the LAN sockets emulate, in the form
+    /// of [`crate::transfer::netem::NETEM_ENV`], which wins over it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_emulation: Option<String>,
+    /// Application ID of a private deployment (see [`crate::alpn`]); only
+    /// devices with the same ID connect. Unset is the public app.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    /// Days before an unfinished partial file is reported, 0 = never.
+    /// Unset is [`DEFAULT_ORPHAN_AGE_DAYS`](crate::transfer::orphans::DEFAULT_ORPHAN_AGE_DAYS).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orphan_age_days: Option<u32>,
+    /// Anonymous usage statistics (see [`crate::telemetry`]); off unless
+    /// the user opts in
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    /// Where received files are written: the download folder, or an S3
+    /// bucket or WebDAV share (see [`crate::storage`])
+    #[serde(default, skip_serializing_if = "StorageSettings::is_local")]
+    pub storage: StorageSettings,
+}
+
+fn default_preserve_metadata() -> bool {
+    true
+}
+
+fn default_transliterate_names() -> bool {
+    true
+}
+
+fn default_max_concurrent_pairings() -> usize {
+    crate::pairing::queue::DEFAULT_MAX_CONCURRENT_PAIRINGS
+}
+
+fn default_wan_heartbeat_secs() -> u64 {
+    15
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             pairing: HashMap::new(),
+            receiver_keys: HashMap::new(),
             download_path: get_download_dir(),
+            preserve_metadata: default_preserve_metadata(),
+            preserve_executable: false,
+            send_extended_attributes: false,
+            request_receipts: false,
+            verify_read_back: false,
+            transliterate_names: default_transliterate_names(),
+            max_concurrent_pairings: default_max_concurrent_pairings(),
+            room_key: None,
+            receive_only: false,
+            retention: RetentionPolicy::default(),
+            units: UnitPreference::default(),
+            per_peer_folders: false,
+            webhook_url: None,
+            post_receive_command: None,
+            owner_mode: false,
+            upload_approval: UploadApprovalPolicy::default(),
+            pinned_peers: Vec::new(),
+            peer_groups: Vec::new(),
+            hash_threads: None,
+            hash_algorithm: HashAlgorithm::default(),
+            relay: RelayPolicy::default(),
+            receive_limits: ReceiveLimits::default(),
+            bandwidth_cap: BandwidthCap::default(),
+            metered_mode: MeteredMode::default(),
+            wan_heartbeat_secs: default_wan_heartbeat_secs(),
+            wan_strategy: WanStrategy::default(),
+            wan_hide_names: false,
+            proxy: ProxySettings::default(),
+            rendezvous: RendezvousSettings::default(),
+            event_output: None,
+            socket_buffers: None,
+            udp_offload: None,
+            network_emulation: None,
+            app_id: None,
+            orphan_age_days: None,
+            telemetry: TelemetrySettings::default(),
+            storage: StorageSettings::default(),
         }
     }
 }
 
 pub fn get_download_dir() -> PathBuf {
+    if let Some(dir) = portable_dir() {
+        return dir.join(PORTABLE_DOWNLOAD_DIR);
+    }
     directories::UserDirs::new()
         .map(|dirs| dirs.home_dir().to_path_buf())
         .unwrap_or_else(|| PathBuf::from("."))
@@ -45,39 +319,53 @@ pub fn get_download_dir() -> PathBuf {
 }
 
 impl AppConfig {
-    fn get_config_path() -> Option<PathBuf> {
-        if let Ok(test_path) = std::env::var("P2P_TEST_CONFIG_DIR") {
-            return Some(PathBuf::from(test_path).join(CONFIG_FILE));
-        }
-
-        ProjectDirs::from(APP_QUALIFIER, APP_ORGANIZATION, APP_NAME)
-            .map(|dirs| dirs.config_dir().join(CONFIG_FILE))
+    /// Location of the active profile's `config.json`
+    pub fn default_path() -> Option<PathBuf> {
+        get_config_dir().map(|dir| dir.join(CONFIG_FILE))
     }
 
     pub fn load() -> Self {
-        let path = match Self::get_config_path() {
-            Some(p) => p,
-            None => return Self::default(),
+        let mut config = match Self::default_path() {
+            Some(path) => Self::load_from(&path),
+            None => Self::default(),
         };
+        if let Some(dir) = portable_dir() {
+            config.download_path = dir.join(&config.download_path);
+        }
+        config
+    }
 
-        match fs::read_to_string(&path) {
+    /// Load from an explicit file, falling back to defaults if missing or invalid
+    pub fn load_from(path: &Path) -> Self {
+        // The file holds pairing keys
+        match fs::read_to_string(path).map(Zeroizing::new) {
             Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
             Err(_) => Self::default(),
         }
     }
 
     pub fn save(&self) {
-        let path = match Self::get_config_path() {
-            Some(p) => p,
-            None => return,
+        let Some(path) = Self::default_path() else {
+            return;
         };
+        match portable_dir() {
+            Some(dir) => Self {
+                download_path: relative_to(&self.download_path, dir),
+                ..self.clone()
+            }
+            .save_to(&path),
+            None => self.save_to(&path),
+        }
+    }
 
+    /// Save to an explicit file with owner-only permissions
+    pub fn save_to(&self, path: &Path) {
         if let Some(parent) = path.parent() {
             let _ = create_secure_dir_all(parent);
         }
 
-        if let Ok(json) = serde_json::to_string_pretty(self) {
-            let _ = write_secure_file(&path, &json);
+        if let Ok(json) = serde_json::to_string_pretty(self).map(Zeroizing::new) {
+            let _ = write_secure_file(path, &json);
         }
     }
 }
@@ -124,11 +412,154 @@ pub fn write_secure_file(path: &Path, content: &str) -> std::io::Result<()> {
     file.write_all(content.as_bytes())
 }
 
-pub fn get_config_dir() -> Option<PathBuf> {
+/// Directory of the executable in portable mode (see [`PORTABLE_FILE`]),
+/// `None` otherwise. Decided once per run.
+pub fn portable_dir() -> Option<&'static Path> {
+    static PORTABLE: OnceLock<Option<PathBuf>> = OnceLock::new();
+    PORTABLE
+        .get_or_init(|| {
+            let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+            let forced = std::env::var(PORTABLE_ENV).is_ok_and(|value| {
+                let value = value.trim();
+                !value.is_empty() && value != "0"
+            });
+            portable_in(&exe_dir, forced)
+        })
+        .as_deref()
+}
+
+/// `path` relative to `dir` when inside it, so a portable config still
+/// works after the drive gets another letter or mount point
+fn relative_to(path: &Path, dir: &Path) -> PathBuf {
+    path.strip_prefix(dir).unwrap_or(path).to_path_buf()
+}
+
+/// `exe_dir` if it holds [`PORTABLE_FILE`] or `forced`
+fn portable_in(exe_dir: &Path, forced: bool) -> Option<PathBuf> {
+    (forced || exe_dir.join(PORTABLE_FILE).is_file()).then(|| exe_dir.to_path_buf())
+}
+
+/// Config directory shared by all profiles: the platform's, or the one
+/// beside the executable in portable mode
+pub fn get_base_config_dir() -> Option<PathBuf> {
+    if let Some(dir) = portable_dir() {
+        return Some(dir.join(PORTABLE_CONFIG_DIR));
+    }
     ProjectDirs::from(APP_QUALIFIER, APP_ORGANIZATION, APP_NAME)
         .map(|dirs| dirs.config_dir().to_path_buf())
 }
 
+/// Window layout and open panels of the GUI; shared by all profiles
+pub fn get_gui_state_path() -> Option<PathBuf> {
+    get_base_config_dir().map(|dir| dir.join(GUI_STATE_FILE))
+}
+
+/// Config directory of the active profile: secret key, pairings and settings
+pub fn get_config_dir() -> Option<PathBuf> {
+    ProfileManager::platform().map(|profiles| profiles.profile_dir(&profiles.active()))
+}
+
+/// Profile names become directory names, so keep them simple
+pub fn validate_profile_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_PROFILE_NAME_LENGTH {
+        return Err(anyhow!(
+            "Profile name must be 1 to {} characters",
+            MAX_PROFILE_NAME_LENGTH
+        ));
+    }
+    if name.trim() != name
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+    {
+        return Err(anyhow!(
+            "Profile name may only contain letters, digits, spaces, '-' and '_'"
+        ));
+    }
+    Ok(())
+}
+
+/// Separate identities ("Work", "Home") under one config directory.
+///
+/// [`DEFAULT_PROFILE`] lives in the config directory itself, so existing
+/// installs keep their key and pairings; other profiles get their own
+/// `profiles/<name>/` directory with a separate key, `config.json` and
+/// download folder.
+#[derive(Debug, Clone)]
+pub struct ProfileManager {
+    base_dir: PathBuf,
+}
+
+impl ProfileManager {
+    pub fn new(base_dir: PathBuf) -> Self {
+        Self { base_dir }
+    }
+
+    /// Profiles in the platform config directory
+    pub fn platform() -> Option<Self> {
+        get_base_config_dir().map(Self::new)
+    }
+
+    pub fn profile_dir(&self, name: &str) -> PathBuf {
+        if name == DEFAULT_PROFILE {
+            self.base_dir.clone()
+        } else {
+            self.base_dir.join(PROFILES_DIR).join(name)
+        }
+    }
+
+    /// Name of the profile the backend starts with
+    pub fn active(&self) -> String {
+        fs::read_to_string(self.base_dir.join(ACTIVE_PROFILE_FILE))
+            .ok()
+            .map(|name| name.trim().to_string())
+            .filter(|name| validate_profile_name(name).is_ok())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+    }
+
+    /// All profiles, default first
+    pub fn list(&self) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(self.base_dir.join(PROFILES_DIR))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name != DEFAULT_PROFILE && validate_profile_name(name).is_ok())
+            .collect();
+        names.sort();
+        names.insert(0, DEFAULT_PROFILE.to_string());
+        names
+    }
+
+    /// Make `name` the active profile, creating it on first use.
+    ///
+    /// A new profile downloads into its own subfolder of the default
+    /// download directory.
+    pub fn switch(&self, name: &str) -> Result<PathBuf> {
+        validate_profile_name(name)?;
+        let dir = self.profile_dir(name);
+        create_secure_dir_all(&dir).context("Failed to create profile directory")?;
+
+        let config_path = dir.join(CONFIG_FILE);
+        if name != DEFAULT_PROFILE && !config_path.exists() {
+            let download_path = get_download_dir().join(name);
+            let config = AppConfig {
+                download_path: match portable_dir() {
+                    Some(portable) => relative_to(&download_path, portable),
+                    None => download_path,
+                },
+                ..AppConfig::default()
+            };
+            config.save_to(&config_path);
+        }
+
+        write_secure_file(&self.base_dir.join(ACTIVE_PROFILE_FILE), name)
+            .context("Failed to save active profile")?;
+        Ok(dir)
+    }
+}
+
 pub fn get_or_create_endpoint_id() -> String {
     let config_dir = match get_config_dir() {
         Some(dir) => dir,
@@ -164,6 +595,56 @@ pub fn get_or_create_endpoint_id() -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_portable_mode_keeps_paths_beside_the_executable() {
+        let exe_dir = std::env::temp_dir().join(format!("portable_test_{}", Uuid::new_v4()));
+        fs::create_dir_all(&exe_dir).unwrap();
+        assert_eq!(portable_in(&exe_dir, false), None);
+        assert_eq!(portable_in(&exe_dir, true), Some(exe_dir.clone()));
+        fs::write(exe_dir.join(PORTABLE_FILE), "").unwrap();
+        assert_eq!(portable_in(&exe_dir, false), Some(exe_dir.clone()));
+
+        let downloads = exe_dir.join(PORTABLE_DOWNLOAD_DIR);
+        assert_eq!(
+            relative_to(&downloads, &exe_dir),
+            PathBuf::from(PORTABLE_DOWNLOAD_DIR)
+        );
+        // Chosen outside the portable directory: kept as it is
+        let elsewhere = std::env::temp_dir().join("elsewhere");
+        assert_eq!(relative_to(&elsewhere, &exe_dir), elsewhere);
+        // Loading joins back whichever was saved
+        assert_eq!(exe_dir.join(relative_to(&downloads, &exe_dir)), downloads);
+        assert_eq!(exe_dir.join(&elsewhere), elsewhere);
+
+        fs::remove_dir_all(&exe_dir).unwrap();
+    }
+
+    #[test]
+    fn test_profiles_have_separate_directories() {
+        let base = std::env::temp_dir().join(format!("profiles_test_{}", Uuid::new_v4()));
+        let profiles = ProfileManager::new(base.clone());
+        assert_eq!(profiles.active(), DEFAULT_PROFILE);
+        assert_eq!(profiles.list(), vec![DEFAULT_PROFILE.to_string()]);
+
+        let work = profiles.switch("Work").unwrap();
+        assert_eq!(profiles.active(), "Work");
+        assert_eq!(work, base.join(PROFILES_DIR).join("Work"));
+        let config = AppConfig::load_from(&work.join(CONFIG_FILE));
+        assert_eq!(config.download_path, get_download_dir().join("Work"));
+
+        profiles.switch("Home").unwrap();
+        assert_eq!(profiles.list(), vec!["Default", "Home", "Work"]);
+
+        assert_eq!(profiles.switch(DEFAULT_PROFILE).unwrap(), base);
+        assert_eq!(profiles.active(), DEFAULT_PROFILE);
+
+        for bad in ["", "../etc", "a/b", " padded", &"x".repeat(40)] {
+            assert!(profiles.switch(bad).is_err(), "{:?} accepted", bad);
+        }
+
+        let _ = fs::remove_dir_all(&base);
+    }
+
     #[test]
     fn test_endpoint_id_consistency() {
         let _id1 = get_or_create_endpoint_id();
@@ -1,8 +1,19 @@
-use crate::{AppEvent, DiscoveryMsg, MAGIC_BYTES};
-use std::net::SocketAddr;
-use std::sync::Arc;
+pub mod batch;
+pub mod names;
+pub mod presence;
+pub mod room;
+
+use crate::{APP_VERSION, AppEvent, DiscoveryMsg, MAGIC_BYTES, PeerCapabilities};
+use batch::{PEER_DELTA_INTERVAL, PeerBatch};
+use names::{PeerNames, Sighting};
+use presence::{HEARTBEAT_INTERVAL, PresenceTracker};
+use room::{RoomKey, decode_packet, encode_packet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 /// Default UDP port for peer discovery
 pub const DISCOVERY_PORT: u16 = 8888;
@@ -13,11 +24,12 @@ const DISCOVERY_BUFFER_SIZE: usize = 2048;
 /// Broadcast address for LAN discovery
 const BROADCAST_ADDR: &str = "255.255.255.255";
 
-/// Interval between automatic discovery broadcasts (seconds)
-pub const DISCOVERY_INTERVAL_SECS: u64 = 5;
+/// Interval between automatic discovery broadcasts (seconds). Known peers
+/// are tracked by heartbeats, so broadcasts only need to find new ones.
+pub const DISCOVERY_INTERVAL_SECS: u64 = 30;
 
 /// Build a discovery packet with magic bytes prefix
-fn build_packet(msg: &DiscoveryMsg) -> Option<Vec<u8>> {
+pub fn build_packet(msg: &DiscoveryMsg) -> Option<Vec<u8>> {
     serde_json::to_vec(msg).ok().map(|json_bytes| {
         let mut packet = MAGIC_BYTES.to_vec();
         packet.extend_from_slice(&json_bytes);
@@ -25,8 +37,23 @@ fn build_packet(msg: &DiscoveryMsg) -> Option<Vec<u8>> {
     })
 }
 
+/// Parse a received datagram; `None` for foreign or malformed packets
+pub fn parse_packet(packet: &[u8]) -> Option<DiscoveryMsg> {
+    // Check identify packet, then decode the JSON after it
+    let data = packet.strip_prefix(MAGIC_BYTES)?;
+    serde_json::from_slice(data).ok()
+}
+
 pub struct DiscoveryService {
     socket: Arc<UdpSocket>,
+    presence: Arc<Mutex<PresenceTracker>>,
+    cancel_token: CancellationToken,
+    /// Only peers sharing this key are seen, see [`room`]
+    room: Option<RoomKey>,
+    /// Announced to peers in every packet
+    capabilities: PeerCapabilities,
+    /// Start of the clock stamped on requests, see [`now_ms`]
+    started: Instant,
 }
 
 impl DiscoveryService {
@@ -40,16 +67,85 @@ impl DiscoveryService {
 
         Ok(Self {
             socket: Arc::new(socket),
+            presence: Arc::new(Mutex::new(PresenceTracker::default())),
+            cancel_token: CancellationToken::new(),
+            room: None,
+            capabilities: PeerCapabilities::default(),
+            started: Instant::now(),
         })
     }
 
-    pub async fn send_discovery_request(&self, endpoint_id: String, my_name: String, port: u16) {
-        let msg = DiscoveryMsg::DiscoveryRequest {
+    /// Restrict discovery to peers configured with the same room key
+    pub fn with_room_key(mut self, room: Option<RoomKey>) -> Self {
+        self.room = room;
+        self
+    }
+
+    /// Capabilities to announce, e.g. receive-only mode
+    pub fn with_capabilities(mut self, capabilities: PeerCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Number of peers currently tracked by heartbeat
+    pub fn known_peers(&self) -> usize {
+        lock_presence(&self.presence).len()
+    }
+
+    /// Whether a peer was discovered at `target`'s IP with `target`'s port
+    /// as its transfer port
+    pub fn has_peer_at(&self, target: SocketAddr) -> bool {
+        lock_presence(&self.presence).has_peer_at(target)
+    }
+
+    /// Endpoint ID of the peer discovered at `ip`, if exactly one is
+    pub fn endpoint_at(&self, ip: IpAddr) -> Option<String> {
+        lock_presence(&self.presence)
+            .endpoint_at(ip)
+            .map(str::to_string)
+    }
+
+    /// Stop the listener and heartbeat tasks; peers will see us as lost
+    pub fn shutdown(&self) {
+        self.cancel_token.cancel();
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.cancel_token.is_cancelled()
+    }
+
+    fn request(&self, endpoint_id: String, my_name: String, port: u16) -> DiscoveryMsg {
+        DiscoveryMsg::DiscoveryRequest {
             endpoint_id,
             my_name,
             port,
-        };
-        if let Some(packet) = build_packet(&msg) {
+            capabilities: self.capabilities,
+            version: Some(APP_VERSION.to_string()),
+            sent_ms: Some(now_ms(self.started)),
+        }
+    }
+
+    /// Send a discovery request straight to `target` instead of broadcasting
+    pub async fn send_discovery_request_to(
+        &self,
+        target: SocketAddr,
+        endpoint_id: String,
+        my_name: String,
+        port: u16,
+    ) {
+        let msg = self.request(endpoint_id, my_name, port);
+        if let Some(packet) = encode_packet(&msg, self.room.as_ref()) {
+            let _ = self.socket.send_to(&packet, target).await;
+        }
+    }
+
+    pub async fn send_discovery_request(&self, endpoint_id: String, my_name: String, port: u16) {
+        let msg = self.request(endpoint_id, my_name, port);
+        if let Some(packet) = encode_packet(&msg, self.room.as_ref()) {
             let broadcast_addr = format!("{}:{}", BROADCAST_ADDR, DISCOVERY_PORT);
             let _ = self.socket.send_to(&packet, broadcast_addr).await;
         }
@@ -66,12 +162,17 @@ impl DiscoveryService {
             endpoint_id,
             my_name,
             port,
+            capabilities: self.capabilities,
+            version: Some(APP_VERSION.to_string()),
+            echo_ms: None,
         };
-        if let Some(packet) = build_packet(&msg) {
+        if let Some(packet) = encode_packet(&msg, self.room.as_ref()) {
             let _ = self.socket.send_to(&packet, target).await;
         }
     }
 
+    /// Answer discovery packets, heartbeat known peers and report what
+    /// changed in [`AppEvent::PeerListDelta`]s
     pub fn start_listening(
         &self,
         event_tx: mpsc::Sender<AppEvent>,
@@ -79,64 +180,222 @@ impl DiscoveryService {
         my_name: String,
         my_port: u16,
     ) {
+        let names = Arc::new(Mutex::new(PeerNames::new(my_name.clone())));
+        let batch = Arc::new(Mutex::new(PeerBatch::default()));
+        self.spawn_delta_sender(event_tx, batch.clone());
+        self.spawn_heartbeat(
+            batch.clone(),
+            names.clone(),
+            my_endpoint_id.clone(),
+            my_name.clone(),
+            my_port,
+        );
+
         let socket = self.socket.clone();
+        let presence = self.presence.clone();
+        let cancel_token = self.cancel_token.clone();
+        let room = self.room.clone();
+        let my_capabilities = self.capabilities;
+        let started = self.started;
 
         tokio::spawn(async move {
             let mut buf = [0u8; DISCOVERY_BUFFER_SIZE];
-            while let Ok((len, addr)) = socket.recv_from(&mut buf).await {
-                // Check identify packet
-                if len < MAGIC_BYTES.len() || &buf[..MAGIC_BYTES.len()] != MAGIC_BYTES {
+            loop {
+                let (len, addr) = tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    received = socket.recv_from(&mut buf) => match received {
+                        Ok(received) => received,
+                        Err(_) => break,
+                    },
+                };
+                let Some(msg) = decode_packet(&buf[..len], room.as_ref()) else {
                     continue;
-                }
+                };
 
-                // Extract JSON data after identify bytes
-                let data = &buf[MAGIC_BYTES.len()..len];
-
-                if let Ok(msg) = serde_json::from_slice::<DiscoveryMsg>(data) {
-                    match msg {
-                        DiscoveryMsg::DiscoveryRequest {
-                            endpoint_id: remote_endpoint_id,
-                            my_name: remote_name,
-                            port: _remote_port,
-                        } => {
-                            if remote_endpoint_id != my_endpoint_id {
-                                let response_msg = DiscoveryMsg::DiscoveryResponse {
-                                    endpoint_id: my_endpoint_id.clone(),
-                                    my_name: my_name.clone(),
-                                    port: my_port,
-                                };
-                                if let Some(packet) = build_packet(&response_msg) {
-                                    let _ = socket.send_to(&packet, addr).await;
-                                }
-
-                                //treat this as "Peer found" immediately
-                                let _ = event_tx
-                                    .send(AppEvent::PeerFound {
-                                        endpoint_id: remote_endpoint_id,
-                                        ip: addr.ip().to_string(),
-                                        hostname: remote_name,
-                                    })
-                                    .await;
-                            }
-                        }
-                        DiscoveryMsg::DiscoveryResponse {
-                            endpoint_id: remote_endpoint_id,
-                            my_name: remote_name,
-                            ..
-                        } => {
-                            if remote_endpoint_id != my_endpoint_id {
-                                let _ = event_tx
-                                    .send(AppEvent::PeerFound {
-                                        endpoint_id: remote_endpoint_id,
-                                        ip: addr.ip().to_string(),
-                                        hostname: remote_name,
-                                    })
-                                    .await;
+                let sighting = |port, hostname, capabilities, version, rtt_ms| Sighting {
+                    ip: addr.ip().to_string(),
+                    port,
+                    hostname,
+                    capabilities,
+                    version,
+                    rtt_ms,
+                };
+                let (remote_endpoint_id, sighting, is_heartbeat) = match msg {
+                    DiscoveryMsg::DiscoveryRequest {
+                        endpoint_id,
+                        my_name: remote_name,
+                        port,
+                        capabilities,
+                        version,
+                        sent_ms,
+                    } => {
+                        if endpoint_id != my_endpoint_id {
+                            let response_msg = DiscoveryMsg::DiscoveryResponse {
+                                endpoint_id: my_endpoint_id.clone(),
+                                my_name: my_name.clone(),
+                                port: my_port,
+                                capabilities: my_capabilities,
+                                version: Some(APP_VERSION.to_string()),
+                                echo_ms: sent_ms,
+                            };
+                            if let Some(packet) = encode_packet(&response_msg, room.as_ref()) {
+                                let _ = socket.send_to(&packet, addr).await;
                             }
                         }
+                        let found = sighting(port, remote_name, capabilities, version, None);
+                        (endpoint_id, found, false)
+                    }
+                    DiscoveryMsg::DiscoveryResponse {
+                        endpoint_id,
+                        my_name: remote_name,
+                        port,
+                        capabilities,
+                        version,
+                        echo_ms,
+                    } => {
+                        let rtt_ms = echo_ms.and_then(|sent| round_trip_ms(sent, now_ms(started)));
+                        let found = sighting(port, remote_name, capabilities, version, rtt_ms);
+                        (endpoint_id, found, false)
+                    }
+                    DiscoveryMsg::Heartbeat {
+                        endpoint_id,
+                        my_name: remote_name,
+                        port,
+                        capabilities,
+                        version,
+                    } => {
+                        let found = sighting(port, remote_name, capabilities, version, None);
+                        (endpoint_id, found, true)
+                    }
+                };
+
+                if remote_endpoint_id == my_endpoint_id {
+                    continue;
+                }
+
+                let is_new = lock_presence(&presence).seen(
+                    &remote_endpoint_id,
+                    addr,
+                    sighting.port,
+                    is_heartbeat,
+                    Instant::now(),
+                );
+
+                // Requests and responses always refresh the peer; heartbeats
+                // only announce a peer we had not heard of (e.g. after it
+                // was reported lost)
+                if !is_heartbeat || is_new {
+                    let found = lock_names(&names).found(&remote_endpoint_id, sighting);
+                    let mut batch = lock_batch(&batch);
+                    for event in found {
+                        batch.push(event);
                     }
                 }
             }
         });
     }
+
+    /// Send the collected peer updates every [`PEER_DELTA_INTERVAL`]
+    fn spawn_delta_sender(&self, event_tx: mpsc::Sender<AppEvent>, batch: Arc<Mutex<PeerBatch>>) {
+        let cancel_token = self.cancel_token.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PEER_DELTA_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let delta = lock_batch(&batch).take();
+                if let Some(delta) = delta
+                    && event_tx.send(AppEvent::PeerListDelta(delta)).await.is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    fn spawn_heartbeat(
+        &self,
+        batch: Arc<Mutex<PeerBatch>>,
+        names: Arc<Mutex<PeerNames>>,
+        my_endpoint_id: String,
+        my_name: String,
+        my_port: u16,
+    ) {
+        let socket = self.socket.clone();
+        let presence = self.presence.clone();
+        let cancel_token = self.cancel_token.clone();
+
+        let heartbeat = DiscoveryMsg::Heartbeat {
+            endpoint_id: my_endpoint_id,
+            my_name,
+            port: my_port,
+            capabilities: self.capabilities,
+            version: Some(APP_VERSION.to_string()),
+        };
+        let Some(packet) = encode_packet(&heartbeat, self.room.as_ref()) else {
+            return;
+        };
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+
+                let (targets, lost, renamed) = {
+                    let mut presence = lock_presence(&presence);
+                    let lost = presence.expire(Instant::now());
+                    let renamed = lock_names(&names).retain(|id| presence.contains(id));
+                    (presence.targets(), lost, renamed)
+                };
+
+                {
+                    let mut batch = lock_batch(&batch);
+                    for (endpoint_id, addr) in lost {
+                        tracing::info!("Peer {} ({}) stopped responding", endpoint_id, addr);
+                        batch.push(AppEvent::PeerLost {
+                            endpoint_id,
+                            ip: addr.ip().to_string(),
+                        });
+                    }
+                    for event in renamed {
+                        batch.push(event);
+                    }
+                }
+                for target in targets {
+                    let _ = socket.send_to(&packet, target).await;
+                }
+            }
+        });
+    }
+}
+
+/// Milliseconds on the clock requests are stamped with; answers echo the
+/// stamp, which gives the round-trip time without tracking requests
+fn now_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
+
+/// Round-trip time of an answer to a request stamped `sent_ms`; `None` for
+/// stamps from the future, e.g. forged or from before a restart
+fn round_trip_ms(sent_ms: u64, now_ms: u64) -> Option<u32> {
+    now_ms
+        .checked_sub(sent_ms)
+        .map(|rtt| u32::try_from(rtt).unwrap_or(u32::MAX))
+}
+
+fn lock_presence(presence: &Mutex<PresenceTracker>) -> std::sync::MutexGuard<'_, PresenceTracker> {
+    presence.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn lock_names(names: &Mutex<PeerNames>) -> std::sync::MutexGuard<'_, PeerNames> {
+    names.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn lock_batch(batch: &Mutex<PeerBatch>) -> std::sync::MutexGuard<'_, PeerBatch> {
+    batch.lock().unwrap_or_else(|e| e.into_inner())
 }
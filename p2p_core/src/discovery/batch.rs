@@ -0,0 +1,273 @@
+//! Batching of peer updates for networks with hundreds of instances.
+//!
+//! Every discovery answer used to become a [`AppEvent::PeerFound`], so a
+//! busy LAN flooded the event channel with updates that mostly repeated
+//! what frontends already knew. [`PeerBatch`] collects what discovery
+//! learns and hands it out as one [`AppEvent::PeerListDelta`] every
+//! [`PEER_DELTA_INTERVAL`]: peers reported again unchanged are dropped
+//! unless their last report is [`PEER_REFRESH_AFTER`] old, a peer found and
+//! lost within one interval is never reported, and a delta carries at most
+//! [`MAX_PEERS_PER_DELTA`] peers, the rest following in later deltas.
+
+use crate::AppEvent;
+use crate::state::PeerSnapshot;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// How often collected peer updates are sent
+pub const PEER_DELTA_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Found peers per delta; more wait for the next one
+pub const MAX_PEERS_PER_DELTA: usize = 64;
+
+/// An unchanged peer is reported again after this long, so frontends that
+/// expire silent peers keep it; every broadcast round refreshes it
+pub const PEER_REFRESH_AFTER: Duration = Duration::from_secs(super::DISCOVERY_INTERVAL_SECS / 2);
+
+/// Round-trip times within this many milliseconds (or a quarter) of the
+/// reported one are not worth an update
+const RTT_CHURN_MS: u32 = 2;
+
+/// A peer that stopped announcing itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LostPeer {
+    pub endpoint_id: String,
+    pub ip: String,
+}
+
+/// Peers found or changed, and peers lost, since the last delta
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PeerDelta {
+    /// Applied after `lost`, so a peer that came back shows up
+    pub found: Vec<PeerSnapshot>,
+    pub lost: Vec<LostPeer>,
+}
+
+impl PeerDelta {
+    pub fn is_empty(&self) -> bool {
+        self.found.is_empty() && self.lost.is_empty()
+    }
+
+    /// The delta as single [`AppEvent::PeerLost`] and
+    /// [`AppEvent::PeerFound`] events, in the order to apply them
+    pub fn events(&self) -> Vec<AppEvent> {
+        let lost = self.lost.iter().map(|peer| AppEvent::PeerLost {
+            endpoint_id: peer.endpoint_id.clone(),
+            ip: peer.ip.clone(),
+        });
+        let found = self.found.iter().cloned().map(|peer| AppEvent::PeerFound {
+            endpoint_id: peer.endpoint_id,
+            ip: peer.ip,
+            port: peer.port,
+            hostname: peer.hostname,
+            display_name: peer.display_name,
+            capabilities: peer.capabilities,
+            version: peer.version,
+            rtt_ms: peer.rtt_ms,
+        });
+        lost.chain(found).collect()
+    }
+}
+
+/// Peer updates waiting for the next delta
+#[derive(Debug, Default)]
+pub struct PeerBatch {
+    /// Latest sighting of each peer not sent yet, by endpoint ID
+    found: BTreeMap<String, PeerSnapshot>,
+    lost: BTreeMap<String, String>,
+    /// What the last deltas said about each peer still up, and when
+    reported: HashMap<String, (PeerSnapshot, Instant)>,
+}
+
+impl PeerBatch {
+    /// Take in a [`AppEvent::PeerFound`] or [`AppEvent::PeerLost`];
+    /// other events are given back to be sent as they are
+    pub fn push(&mut self, event: AppEvent) -> Option<AppEvent> {
+        self.push_at(event, Instant::now())
+    }
+
+    fn push_at(&mut self, event: AppEvent, now: Instant) -> Option<AppEvent> {
+        match event {
+            AppEvent::PeerFound {
+                endpoint_id,
+                ip,
+                port,
+                hostname,
+                display_name,
+                capabilities,
+                version,
+                rtt_ms,
+            } => {
+                let peer = PeerSnapshot {
+                    endpoint_id,
+                    ip,
+                    port,
+                    hostname,
+                    display_name,
+                    capabilities,
+                    version,
+                    rtt_ms,
+                };
+                self.found(peer, now);
+                None
+            }
+            AppEvent::PeerLost { endpoint_id, ip } => {
+                self.lost(endpoint_id, ip);
+                None
+            }
+            other => Some(other),
+        }
+    }
+
+    fn found(&mut self, peer: PeerSnapshot, now: Instant) {
+        if self
+            .reported
+            .get(&peer.endpoint_id)
+            .is_some_and(|(reported, at)| {
+                same_peer(reported, &peer) && now.duration_since(*at) < PEER_REFRESH_AFTER
+            })
+        {
+            // Rebroadcast churn: nothing new since the last delta
+            self.found.remove(&peer.endpoint_id);
+            return;
+        }
+        self.found.insert(peer.endpoint_id.clone(), peer);
+    }
+
+    fn lost(&mut self, endpoint_id: String, ip: String) {
+        self.found.remove(&endpoint_id);
+        // Frontends never heard of a peer lost before its first delta
+        if self.reported.remove(&endpoint_id).is_some() {
+            self.lost.insert(endpoint_id, ip);
+        }
+    }
+
+    /// The next delta, `None` when nothing changed
+    pub fn take(&mut self) -> Option<PeerDelta> {
+        self.take_at(Instant::now())
+    }
+
+    fn take_at(&mut self, now: Instant) -> Option<PeerDelta> {
+        let lost = std::mem::take(&mut self.lost)
+            .into_iter()
+            .map(|(endpoint_id, ip)| LostPeer { endpoint_id, ip })
+            .collect();
+        let ids: Vec<String> = self
+            .found
+            .keys()
+            .take(MAX_PEERS_PER_DELTA)
+            .cloned()
+            .collect();
+        let found = ids
+            .iter()
+            .filter_map(|id| self.found.remove(id))
+            .inspect(|peer| {
+                self.reported
+                    .insert(peer.endpoint_id.clone(), (peer.clone(), now));
+            })
+            .collect();
+        let delta = PeerDelta { found, lost };
+        (!delta.is_empty()).then_some(delta)
+    }
+}
+
+/// Whether `new` only repeats `reported`, up to round-trip jitter
+fn same_peer(reported: &PeerSnapshot, new: &PeerSnapshot) -> bool {
+    let rtt_close = match (reported.rtt_ms, new.rtt_ms) {
+        (Some(old), Some(new)) => old.abs_diff(new) <= RTT_CHURN_MS.max(old / 4),
+        (old, new) => old == new,
+    };
+    rtt_close
+        && PeerSnapshot {
+            rtt_ms: reported.rtt_ms,
+            ..new.clone()
+        } == *reported
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(endpoint_id: &str, rtt_ms: Option<u32>) -> AppEvent {
+        AppEvent::PeerFound {
+            endpoint_id: endpoint_id.to_string(),
+            ip: format!("10.0.0.{}", endpoint_id.len()),
+            port: 9000,
+            hostname: endpoint_id.to_string(),
+            display_name: endpoint_id.to_string(),
+            capabilities: Default::default(),
+            version: None,
+            rtt_ms,
+        }
+    }
+
+    fn lost(endpoint_id: &str) -> AppEvent {
+        AppEvent::PeerLost {
+            endpoint_id: endpoint_id.to_string(),
+            ip: format!("10.0.0.{}", endpoint_id.len()),
+        }
+    }
+
+    fn ids(delta: &PeerDelta) -> Vec<&str> {
+        delta
+            .found
+            .iter()
+            .map(|peer| peer.endpoint_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_repeated_sightings_are_sent_once() {
+        let mut batch = PeerBatch::default();
+        assert!(batch.push(found("a", Some(10))).is_none());
+        assert!(batch.push(found("a", Some(12))).is_none());
+        assert!(batch.push(AppEvent::Status("x".into())).is_some());
+        let delta = batch.take().unwrap();
+        assert_eq!(ids(&delta), ["a"]);
+        assert_eq!(delta.found[0].rtt_ms, Some(12));
+        assert_eq!(batch.take(), None);
+
+        // Jitter is churn, a real change is not
+        batch.push(found("a", Some(11)));
+        assert_eq!(batch.take(), None);
+        batch.push(found("a", Some(40)));
+        assert_eq!(ids(&batch.take().unwrap()), ["a"]);
+
+        // Nor is a peer unreported for long
+        let later = Instant::now() + PEER_REFRESH_AFTER;
+        batch.push_at(found("a", Some(40)), later);
+        assert_eq!(ids(&batch.take_at(later).unwrap()), ["a"]);
+    }
+
+    #[test]
+    fn test_lost_peers_are_reported_only_once_known() {
+        let mut batch = PeerBatch::default();
+        batch.push(found("a", None));
+        batch.push(lost("a"));
+        assert_eq!(batch.take(), None);
+
+        batch.push(found("b", None));
+        batch.take();
+        batch.push(lost("b"));
+        batch.push(found("b", None));
+        let delta = batch.take().unwrap();
+        assert_eq!(delta.lost.len(), 1);
+        assert_eq!(ids(&delta), ["b"]);
+        assert!(matches!(
+            &delta.events()[..],
+            [AppEvent::PeerLost { .. }, AppEvent::PeerFound { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_large_lists_are_split_into_pages() {
+        let mut batch = PeerBatch::default();
+        for i in 0..MAX_PEERS_PER_DELTA + 10 {
+            batch.push(found(&format!("peer-{:03}", i), None));
+        }
+        assert_eq!(batch.take().unwrap().found.len(), MAX_PEERS_PER_DELTA);
+        assert_eq!(batch.take().unwrap().found.len(), 10);
+        assert_eq!(batch.take(), None);
+    }
+}
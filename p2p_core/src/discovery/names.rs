@@ -0,0 +1,228 @@
+//! Telling apart peers with the same hostname.
+//!
+//! Freshly installed machines often share a default name
+//! (`DESKTOP-ABC123`), which makes them indistinguishable in the devices
+//! list. Like Bonjour, a peer whose name is taken gets a suffix: here the
+//! start of its endpoint ID, so the display name stays the same across
+//! restarts. When a second peer with the same name appears, the first one
+//! is renamed as well and announced again.
+
+use crate::{AppEvent, PeerCapabilities};
+use std::collections::HashMap;
+
+/// Endpoint ID characters appended to a colliding name
+pub const ENDPOINT_SUFFIX_LEN: usize = 6;
+
+/// `hostname` with the start of `endpoint_id`, e.g. `DESKTOP-ABC123 (3f9a0c)`
+pub fn disambiguated_name(hostname: &str, endpoint_id: &str) -> String {
+    let suffix: String = endpoint_id.chars().take(ENDPOINT_SUFFIX_LEN).collect();
+    format!("{} ({})", hostname, suffix)
+}
+
+/// What one discovery packet told about a peer
+#[derive(Debug, Clone)]
+pub struct Sighting {
+    pub ip: String,
+    pub port: u16,
+    pub hostname: String,
+    pub capabilities: PeerCapabilities,
+    pub version: Option<String>,
+    /// Set when the packet answered one of our requests
+    pub rtt_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+struct NamedPeer {
+    sighting: Sighting,
+    display_name: String,
+}
+
+/// Hostnames of known peers keyed by endpoint ID
+#[derive(Debug, Default)]
+pub struct PeerNames {
+    /// Our own name; a peer using it is disambiguated too
+    own_name: String,
+    peers: HashMap<String, NamedPeer>,
+}
+
+impl PeerNames {
+    pub fn new(own_name: impl Into<String>) -> Self {
+        Self {
+            own_name: own_name.into(),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Record a peer and return the [`AppEvent::PeerFound`] events to send:
+    /// one for the peer itself and one for every peer renamed by it
+    pub fn found(&mut self, endpoint_id: &str, mut sighting: Sighting) -> Vec<AppEvent> {
+        let previous = self.peers.remove(endpoint_id);
+        // Only answers carry a round-trip time; keep the last one meanwhile
+        if sighting.rtt_ms.is_none() {
+            sighting.rtt_ms = previous.as_ref().and_then(|peer| peer.sighting.rtt_ms);
+        }
+        self.peers.insert(
+            endpoint_id.to_string(),
+            NamedPeer {
+                sighting,
+                display_name: previous.map(|peer| peer.display_name).unwrap_or_default(),
+            },
+        );
+        let mut renamed = self.rename();
+        if !renamed.iter().any(|id| id == endpoint_id) {
+            renamed.push(endpoint_id.to_string());
+        }
+        self.announce(renamed)
+    }
+
+    /// Forget peers for which `keep` is false; returns the
+    /// [`AppEvent::PeerFound`] events for peers that got their plain name
+    /// back
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) -> Vec<AppEvent> {
+        let before = self.peers.len();
+        self.peers.retain(|endpoint_id, _| keep(endpoint_id));
+        if self.peers.len() == before {
+            return Vec::new();
+        }
+        let renamed = self.rename();
+        self.announce(renamed)
+    }
+
+    /// Recompute display names; returns the endpoint IDs whose name changed
+    fn rename(&mut self) -> Vec<String> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for peer in self.peers.values() {
+            *counts.entry(peer.sighting.hostname.clone()).or_default() += 1;
+        }
+        let mut renamed = Vec::new();
+        for (endpoint_id, peer) in &mut self.peers {
+            let hostname = &peer.sighting.hostname;
+            let taken = counts[hostname] > 1 || *hostname == self.own_name;
+            let display_name = if taken {
+                disambiguated_name(hostname, endpoint_id)
+            } else {
+                hostname.clone()
+            };
+            if display_name != peer.display_name {
+                peer.display_name = display_name;
+                renamed.push(endpoint_id.clone());
+            }
+        }
+        renamed
+    }
+
+    fn announce(&self, endpoint_ids: Vec<String>) -> Vec<AppEvent> {
+        endpoint_ids
+            .into_iter()
+            .filter_map(|endpoint_id| {
+                let peer = self.peers.get(&endpoint_id)?;
+                let sighting = peer.sighting.clone();
+                Some(AppEvent::PeerFound {
+                    ip: sighting.ip,
+                    port: sighting.port,
+                    hostname: sighting.hostname,
+                    display_name: peer.display_name.clone(),
+                    capabilities: sighting.capabilities,
+                    version: sighting.version,
+                    rtt_ms: sighting.rtt_ms,
+                    endpoint_id,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display_names(events: &[AppEvent]) -> Vec<(String, String)> {
+        let mut names: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                AppEvent::PeerFound {
+                    endpoint_id,
+                    display_name,
+                    ..
+                } => (endpoint_id.clone(), display_name.clone()),
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn sighting(ip: &str, hostname: &str, rtt_ms: Option<u32>) -> Sighting {
+        Sighting {
+            ip: ip.to_string(),
+            port: 9000,
+            hostname: hostname.to_string(),
+            capabilities: PeerCapabilities::default(),
+            version: None,
+            rtt_ms,
+        }
+    }
+
+    #[test]
+    fn test_round_trip_time_is_kept_until_measured_again() {
+        let mut names = PeerNames::new("Me");
+        let rtt = |events: Vec<AppEvent>| match &events[..] {
+            [AppEvent::PeerFound { rtt_ms, .. }] => *rtt_ms,
+            other => panic!("unexpected events: {:?}", other),
+        };
+        assert_eq!(
+            rtt(names.found("a", sighting("10.0.0.2", "Desk", None))),
+            None
+        );
+        assert_eq!(
+            rtt(names.found("a", sighting("10.0.0.2", "Desk", Some(4)))),
+            Some(4)
+        );
+        assert_eq!(
+            rtt(names.found("a", sighting("10.0.0.2", "Desk", None))),
+            Some(4)
+        );
+        assert_eq!(
+            rtt(names.found("a", sighting("10.0.0.2", "Desk", Some(9)))),
+            Some(9)
+        );
+    }
+
+    #[test]
+    fn test_colliding_names_get_endpoint_suffixes() {
+        let mut names = PeerNames::new("Me");
+        let found = |names: &mut PeerNames, id: &str, ip: &str, hostname: &str| {
+            names.found(id, sighting(ip, hostname, None))
+        };
+
+        assert_eq!(
+            display_names(&found(&mut names, "aaaaaaaa11", "10.0.0.2", "DESKTOP")),
+            vec![("aaaaaaaa11".to_string(), "DESKTOP".to_string())]
+        );
+        // The second DESKTOP renames the first one as well
+        assert_eq!(
+            display_names(&found(&mut names, "bbbbbbbb22", "10.0.0.3", "DESKTOP")),
+            vec![
+                ("aaaaaaaa11".to_string(), "DESKTOP (aaaaaa)".to_string()),
+                ("bbbbbbbb22".to_string(), "DESKTOP (bbbbbb)".to_string()),
+            ]
+        );
+        // Refreshing a peer only announces that peer
+        assert_eq!(
+            display_names(&found(&mut names, "aaaaaaaa11", "10.0.0.2", "DESKTOP")),
+            vec![("aaaaaaaa11".to_string(), "DESKTOP (aaaaaa)".to_string())]
+        );
+        // Our own name is taken, too
+        assert_eq!(
+            display_names(&found(&mut names, "cccccccc33", "10.0.0.4", "Me")),
+            vec![("cccccccc33".to_string(), "Me (cccccc)".to_string())]
+        );
+
+        // When one DESKTOP leaves, the other gets its plain name back
+        assert_eq!(
+            display_names(&names.retain(|id| id != "bbbbbbbb22")),
+            vec![("aaaaaaaa11".to_string(), "DESKTOP".to_string())]
+        );
+        assert!(names.retain(|_| true).is_empty());
+    }
+}
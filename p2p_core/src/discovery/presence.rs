@@ -0,0 +1,177 @@
+//! Liveness of discovered peers.
+//!
+//! Once two peers have found each other they exchange a small unicast
+//! heartbeat every [`HEARTBEAT_INTERVAL`]. A peer that goes quiet for
+//! [`PEER_LOST_TIMEOUT`] is reported lost, so broadcasts are only needed to
+//! find new peers and can be rare.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use super::DISCOVERY_INTERVAL_SECS;
+
+/// How often each known peer is sent a heartbeat
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Silence after which a heartbeating peer is reported lost
+pub const PEER_LOST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Peers that never sent a heartbeat (older versions) are forgotten quietly
+/// after missing a few broadcast rounds
+const LEGACY_PEER_TIMEOUT: Duration = Duration::from_secs(3 * DISCOVERY_INTERVAL_SECS);
+
+#[derive(Debug, Clone)]
+struct PeerPresence {
+    addr: SocketAddr,
+    /// Transfer port the peer advertised
+    port: u16,
+    last_seen: Instant,
+    /// Whether the peer speaks the heartbeat protocol
+    heartbeats: bool,
+}
+
+/// Known peers keyed by endpoint ID
+#[derive(Debug, Default)]
+pub struct PresenceTracker {
+    peers: HashMap<String, PeerPresence>,
+}
+
+impl PresenceTracker {
+    /// Record a packet from `endpoint_id` advertising transfer `port`.
+    /// Returns true for a peer that was not known before.
+    pub fn seen(
+        &mut self,
+        endpoint_id: &str,
+        addr: SocketAddr,
+        port: u16,
+        heartbeat: bool,
+        now: Instant,
+    ) -> bool {
+        match self.peers.get_mut(endpoint_id) {
+            Some(peer) => {
+                peer.addr = addr;
+                peer.port = port;
+                peer.last_seen = now;
+                peer.heartbeats |= heartbeat;
+                false
+            }
+            None => {
+                self.peers.insert(
+                    endpoint_id.to_string(),
+                    PeerPresence {
+                        addr,
+                        port,
+                        last_seen: now,
+                        heartbeats: heartbeat,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Addresses to send the next heartbeat to
+    pub fn targets(&self) -> Vec<SocketAddr> {
+        self.peers.values().map(|peer| peer.addr).collect()
+    }
+
+    /// Drop peers that went quiet; returns the heartbeating ones as lost
+    pub fn expire(&mut self, now: Instant) -> Vec<(String, SocketAddr)> {
+        let mut lost = Vec::new();
+        self.peers.retain(|endpoint_id, peer| {
+            let silence = now.saturating_duration_since(peer.last_seen);
+            if peer.heartbeats && silence >= PEER_LOST_TIMEOUT {
+                lost.push((endpoint_id.clone(), peer.addr));
+                return false;
+            }
+            silence < LEGACY_PEER_TIMEOUT
+        });
+        lost
+    }
+
+    /// Whether a peer at `target`'s IP advertised `target`'s port for
+    /// transfers
+    pub fn has_peer_at(&self, target: SocketAddr) -> bool {
+        self.peers
+            .values()
+            .any(|peer| peer.addr.ip() == target.ip() && peer.port == target.port())
+    }
+
+    /// The peer seen at `ip`, unless several share that address
+    pub fn endpoint_at(&self, ip: IpAddr) -> Option<&str> {
+        let mut at_ip = self.peers.iter().filter(|(_, peer)| peer.addr.ip() == ip);
+        match (at_ip.next(), at_ip.next()) {
+            (Some((endpoint_id, _)), None) => Some(endpoint_id),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, endpoint_id: &str) -> bool {
+        self.peers.contains_key(endpoint_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silent_peers_are_lost() {
+        let mut tracker = PresenceTracker::default();
+        let start = Instant::now();
+        let a: SocketAddr = "192.168.1.2:8888".parse().unwrap();
+        let b: SocketAddr = "192.168.1.3:8888".parse().unwrap();
+
+        assert!(tracker.seen("a", a, 4433, false, start));
+        assert!(!tracker.seen("a", a, 4433, true, start));
+        assert!(tracker.seen("legacy", b, 4433, false, start));
+        assert_eq!(tracker.targets().len(), 2);
+
+        // Heartbeats keep a peer alive
+        let later = start + PEER_LOST_TIMEOUT;
+        tracker.seen("a", a, 4433, true, later - HEARTBEAT_INTERVAL);
+        assert!(tracker.expire(later).is_empty());
+
+        let lost = tracker.expire(later + PEER_LOST_TIMEOUT);
+        assert_eq!(lost, vec![("a".to_string(), a)]);
+
+        // Peers without heartbeats are never reported, only forgotten
+        assert!(tracker.expire(start + LEGACY_PEER_TIMEOUT).is_empty());
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn test_endpoint_at_needs_a_single_peer_on_the_address() {
+        let mut tracker = PresenceTracker::default();
+        let now = Instant::now();
+        let a: SocketAddr = "192.168.1.2:8888".parse().unwrap();
+        tracker.seen("a", a, 4433, true, now);
+        assert_eq!(tracker.endpoint_at(a.ip()), Some("a"));
+        assert_eq!(tracker.endpoint_at("192.168.1.3".parse().unwrap()), None);
+
+        // Two instances on one host cannot be told apart by address
+        tracker.seen("b", "192.168.1.2:8889".parse().unwrap(), 4434, true, now);
+        assert_eq!(tracker.endpoint_at(a.ip()), None);
+    }
+
+    #[test]
+    fn test_peers_are_only_reachable_on_their_transfer_port() {
+        let mut tracker = PresenceTracker::default();
+        let a: SocketAddr = "192.168.1.2:8888".parse().unwrap();
+        tracker.seen("a", a, 4433, true, Instant::now());
+
+        assert!(tracker.has_peer_at("192.168.1.2:4433".parse().unwrap()));
+        assert!(!tracker.has_peer_at("192.168.1.2:22".parse().unwrap()));
+        assert!(!tracker.has_peer_at(a));
+        assert!(!tracker.has_peer_at("192.168.1.3:4433".parse().unwrap()));
+    }
+}
@@ -0,0 +1,109 @@
+//! Optional discovery rooms.
+//!
+//! Instances configured with the same room key only see each other: every
+//! discovery packet carries a BLAKE3 MAC over its payload, and packets with
+//! a missing or wrong tag are dropped. Nodes without a room key keep using
+//! plain packets, which room members ignore (and vice versa, since the
+//! trailing tag makes the JSON unparsable).
+
+use crate::{DiscoveryMsg, MAGIC_BYTES};
+
+/// Context string for deriving the MAC key from the user's passphrase
+const ROOM_KEY_CONTEXT: &str = "p2p_transfer 2024 discovery room key";
+
+/// Length of the tag appended to room packets
+pub const ROOM_TAG_LEN: usize = blake3::OUT_LEN;
+
+/// MAC key derived from a room passphrase
+#[derive(Clone)]
+pub struct RoomKey {
+    key: [u8; 32],
+}
+
+impl std::fmt::Debug for RoomKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RoomKey(..)")
+    }
+}
+
+impl RoomKey {
+    /// Derive the key for `passphrase`; blank passphrases mean "no room"
+    pub fn new(passphrase: &str) -> Option<Self> {
+        let passphrase = passphrase.trim();
+        if passphrase.is_empty() {
+            return None;
+        }
+        Some(Self {
+            key: blake3::derive_key(ROOM_KEY_CONTEXT, passphrase.as_bytes()),
+        })
+    }
+
+    fn tag(&self, payload: &[u8]) -> blake3::Hash {
+        blake3::keyed_hash(&self.key, payload)
+    }
+}
+
+/// Build a discovery packet, tagged when a room key is set
+pub fn encode_packet(msg: &DiscoveryMsg, room: Option<&RoomKey>) -> Option<Vec<u8>> {
+    let mut packet = super::build_packet(msg)?;
+    if let Some(room) = room {
+        let tag = room.tag(&packet);
+        packet.extend_from_slice(tag.as_bytes());
+    }
+    Some(packet)
+}
+
+/// Parse a received datagram, dropping packets from outside our room
+pub fn decode_packet(packet: &[u8], room: Option<&RoomKey>) -> Option<DiscoveryMsg> {
+    let Some(room) = room else {
+        return super::parse_packet(packet);
+    };
+
+    if !packet.starts_with(MAGIC_BYTES) || packet.len() < MAGIC_BYTES.len() + ROOM_TAG_LEN {
+        return None;
+    }
+    let (payload, tag) = packet.split_at(packet.len() - ROOM_TAG_LEN);
+    let tag: [u8; ROOM_TAG_LEN] = tag.try_into().ok()?;
+    // `Hash` equality is constant time
+    if room.tag(payload) != blake3::Hash::from(tag) {
+        return None;
+    }
+    super::parse_packet(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> DiscoveryMsg {
+        DiscoveryMsg::DiscoveryRequest {
+            endpoint_id: "abc".to_string(),
+            my_name: "Desk".to_string(),
+            port: 9000,
+            capabilities: Default::default(),
+            version: None,
+            sent_ms: None,
+        }
+    }
+
+    #[test]
+    fn test_rooms_isolate_peers() {
+        let team = RoomKey::new("team-a").unwrap();
+        let other = RoomKey::new("team-b").unwrap();
+        assert!(RoomKey::new("  ").is_none());
+
+        let tagged = encode_packet(&request(), Some(&team)).unwrap();
+        assert!(decode_packet(&tagged, Some(&team)).is_some());
+        assert!(decode_packet(&tagged, Some(&other)).is_none());
+        assert!(decode_packet(&tagged, None).is_none());
+
+        let plain = encode_packet(&request(), None).unwrap();
+        assert!(decode_packet(&plain, None).is_some());
+        assert!(decode_packet(&plain, Some(&team)).is_none());
+
+        // Tampering with the payload breaks the tag
+        let mut forged = tagged.clone();
+        forged[MAGIC_BYTES.len() + 2] ^= 1;
+        assert!(decode_packet(&forged, Some(&team)).is_none());
+    }
+}
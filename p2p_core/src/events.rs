@@ -0,0 +1,477 @@
+//! Event fan-out for multiple independent consumers.
+//!
+//! The backend still reports through a single `mpsc::Sender<AppEvent>`;
+//! [`EventBus::forward`] republishes that stream on a broadcast channel so the
+//! GUI, loggers and other observers can each subscribe to the categories they
+//! care about.
+//!
+//! Progress, discovery and log chatter travel on a separate broadcast channel
+//! from everything else (see [`EventPriority`]). A subscriber that falls
+//! behind during a burst only loses telemetry; errors, prompts and
+//! completions have their own buffer. Subscriptions drain both channels and
+//! hand events out in the order they were published.
+
+use crate::{AppEvent, LogLevel};
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{broadcast, mpsc};
+
+/// Default number of events buffered per subscriber before it starts lagging
+pub const DEFAULT_EVENT_CAPACITY: usize = 1000;
+
+/// Coarse grouping of [`AppEvent`] variants used for subscriptions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventCategory {
+    /// Status messages, generic errors and command acknowledgments
+    Status,
+    /// LAN peer discovery
+    Discovery,
+    /// Verification codes and pairing results
+    Pairing,
+    /// File transfers and integrity checks
+    Transfer,
+    /// HTTP share server and browser uploads
+    Http,
+    /// WAN connections and tunnels
+    Wan,
+}
+
+impl EventCategory {
+    /// Every category, for subscribers that want the full stream
+    pub const ALL: &'static [EventCategory] = &[
+        EventCategory::Status,
+        EventCategory::Discovery,
+        EventCategory::Pairing,
+        EventCategory::Transfer,
+        EventCategory::Http,
+        EventCategory::Wan,
+    ];
+}
+
+impl AppEvent {
+    /// Category this event belongs to
+    pub fn category(&self) -> EventCategory {
+        match self {
+            AppEvent::Status(_)
+            | AppEvent::Error(_)
+            | AppEvent::BackendReady { .. }
+            | AppEvent::BackendHealth { .. }
+            | AppEvent::MetricsSample { .. }
+            | AppEvent::StateSnapshot(_)
+            | AppEvent::ProfileSwitched { .. }
+            | AppEvent::PolicyViolation { .. }
+            | AppEvent::TelemetryPreview { .. }
+            | AppEvent::NetworkCost { .. }
+            | AppEvent::SystemResumed { .. }
+            | AppEvent::CommandResult { .. } => EventCategory::Status,
+            AppEvent::Log { category, .. } => *category,
+            AppEvent::PeerFound { .. }
+            | AppEvent::PeerLost { .. }
+            | AppEvent::PeerListDelta(_)
+            | AppEvent::NetworkChanged { .. } => EventCategory::Discovery,
+            AppEvent::ShowVerificationCode { .. }
+            | AppEvent::RequestVerificationCode { .. }
+            | AppEvent::PairingQueued { .. }
+            | AppEvent::VerificationCancelled { .. }
+            | AppEvent::PairingInviteCreated { .. }
+            | AppEvent::KnownPeersChanged { .. }
+            | AppEvent::GuestPairingEnded { .. }
+            | AppEvent::PairingResult { .. }
+            | AppEvent::PairingBlocked { .. }
+            | AppEvent::LinkPhraseReady { .. }
+            | AppEvent::DeviceLinked { .. }
+            | AppEvent::LinkFailed { .. } => EventCategory::Pairing,
+            AppEvent::TransferProgress { .. }
+            | AppEvent::ScheduledSendsChanged { .. }
+            | AppEvent::ScheduledSendStarted { .. }
+            | AppEvent::PendingSends { .. }
+            | AppEvent::CleanupReport { .. }
+            | AppEvent::OrphanedPartials { .. }
+            | AppEvent::SwarmAvailability { .. }
+            | AppEvent::LocalFilesChanged
+            | AppEvent::SendEstimated { .. }
+            | AppEvent::BandwidthUsage(_)
+            | AppEvent::BandwidthCapWarning { .. }
+            | AppEvent::MeteredSendHeld { .. }
+            | AppEvent::SendInterrupted { .. }
+            | AppEvent::SendBroken { .. }
+            | AppEvent::PathSwitched { .. }
+            | AppEvent::GroupSendProgress { .. }
+            | AppEvent::GroupSendFinished { .. }
+            | AppEvent::DuplicateReceived { .. }
+            | AppEvent::ResendOffered { .. }
+            | AppEvent::MovePending { .. }
+            | AppEvent::MoveFinished { .. }
+            | AppEvent::HistoryResults { .. }
+            | AppEvent::SecurityInfo { .. }
+            | AppEvent::TransferCompleted { .. }
+            | AppEvent::TransferCancelled { .. }
+            | AppEvent::VerificationStarted { .. }
+            | AppEvent::VerificationProgress { .. }
+            | AppEvent::VerificationCompleted { .. } => EventCategory::Transfer,
+            AppEvent::ShareUrlReady { .. }
+            | AppEvent::HttpServerStarted { .. }
+            | AppEvent::HttpServerStopped
+            | AppEvent::UploadRequest { .. }
+            | AppEvent::UploadAutoApproved { .. }
+            | AppEvent::TextReceived { .. }
+            | AppEvent::FileLinkCreated { .. }
+            | AppEvent::FileShareCreated { .. }
+            | AppEvent::FileShareDownloaded { .. }
+            | AppEvent::UploadRequestCancelled { .. }
+            | AppEvent::UploadProgress { .. }
+            | AppEvent::UploadCompleted { .. } => EventCategory::Http,
+            AppEvent::WanConnected(_)
+            | AppEvent::WanConnectionInfo { .. }
+            | AppEvent::WanConnectionLost { .. }
+            | AppEvent::WanShareReady { .. }
+            | AppEvent::WanShareStopped
+            | AppEvent::MyDevices { .. }
+            | AppEvent::WanOffer { .. }
+            | AppEvent::WanShareError(_) => EventCategory::Wan,
+        }
+    }
+}
+
+/// Whether an event may be dropped when a subscriber falls behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPriority {
+    /// Errors, prompts, completions, lost peers: never dropped for telemetry
+    Critical,
+    /// Progress, discovery and informational messages; the next one
+    /// supersedes a dropped one
+    Telemetry,
+}
+
+impl AppEvent {
+    /// Channel this event travels on
+    pub fn priority(&self) -> EventPriority {
+        match self {
+            AppEvent::Status(_)
+            | AppEvent::PeerFound { .. }
+            | AppEvent::TransferProgress { .. }
+            | AppEvent::UploadProgress { .. }
+            | AppEvent::VerificationStarted { .. }
+            | AppEvent::VerificationProgress { .. }
+            | AppEvent::ScheduledSendsChanged { .. }
+            | AppEvent::SecurityInfo { .. }
+            | AppEvent::LocalFilesChanged
+            | AppEvent::MetricsSample { .. }
+            | AppEvent::SwarmAvailability { .. }
+            | AppEvent::WanConnectionInfo { .. } => EventPriority::Telemetry,
+            AppEvent::Log { level, .. } if *level != LogLevel::Error => EventPriority::Telemetry,
+            _ => EventPriority::Critical,
+        }
+    }
+}
+
+/// Event tagged with its publication order
+type Sequenced = (u64, AppEvent);
+
+/// Broadcast hub for backend events
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    critical: broadcast::Sender<Sequenced>,
+    telemetry: broadcast::Sender<Sequenced>,
+    next_seq: Arc<AtomicU64>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_CAPACITY)
+    }
+}
+
+impl EventBus {
+    /// Bus buffering up to `capacity` events of each priority per subscriber
+    pub fn new(capacity: usize) -> Self {
+        let (critical, _) = broadcast::channel(capacity.max(1));
+        let (telemetry, _) = broadcast::channel(capacity.max(1));
+        Self {
+            critical,
+            telemetry,
+            next_seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Publish an event to all current subscribers
+    pub fn publish(&self, event: AppEvent) {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let tx = match event.priority() {
+            EventPriority::Critical => &self.critical,
+            EventPriority::Telemetry => &self.telemetry,
+        };
+        // No subscribers is not an error: events are simply dropped
+        let _ = tx.send((seq, event));
+    }
+
+    /// Republish everything received on `rx` until the channel closes;
+    /// subscribe first, as a subscription misses what was published before it
+    pub fn forward(&self, mut rx: mpsc::Receiver<AppEvent>) -> tokio::task::JoinHandle<()> {
+        let bus = self.clone();
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                bus.publish(event);
+            }
+        })
+    }
+
+    /// Subscribe to events from the given categories
+    pub fn subscribe(&self, categories: &[EventCategory]) -> EventSubscription {
+        EventSubscription {
+            critical: PriorityReceiver::new(self.critical.subscribe()),
+            telemetry: PriorityReceiver::new(self.telemetry.subscribe()),
+            categories: categories.to_vec(),
+        }
+    }
+
+    /// Subscribe to every event
+    pub fn subscribe_all(&self) -> EventSubscription {
+        self.subscribe(EventCategory::ALL)
+    }
+
+    /// Number of active subscriptions
+    pub fn subscriber_count(&self) -> usize {
+        self.critical.receiver_count()
+    }
+}
+
+/// One priority channel of a subscription, with a one-event lookahead
+#[derive(Debug)]
+struct PriorityReceiver {
+    rx: broadcast::Receiver<Sequenced>,
+    next: Option<Sequenced>,
+    lagged: u64,
+}
+
+impl PriorityReceiver {
+    fn new(rx: broadcast::Receiver<Sequenced>) -> Self {
+        Self {
+            rx,
+            next: None,
+            lagged: 0,
+        }
+    }
+
+    fn note_lag(&mut self, n: u64) {
+        tracing::warn!("Event subscriber lagged, {} events dropped", n);
+        self.lagged += n;
+    }
+
+    /// Fill the lookahead from already queued events
+    fn poll(&mut self) {
+        while self.next.is_none() {
+            match self.rx.try_recv() {
+                Ok(event) => self.next = Some(event),
+                Err(broadcast::error::TryRecvError::Lagged(n)) => self.note_lag(n),
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Wait until the lookahead is filled; `false` once the bus is gone
+    async fn fill(&mut self) -> bool {
+        while self.next.is_none() {
+            match self.rx.recv().await {
+                Ok(event) => self.next = Some(event),
+                Err(broadcast::error::RecvError::Lagged(n)) => self.note_lag(n),
+                Err(broadcast::error::RecvError::Closed) => return false,
+            }
+        }
+        true
+    }
+
+    fn seq(&self) -> Option<u64> {
+        self.next.as_ref().map(|(seq, _)| *seq)
+    }
+}
+
+/// Receiving side of an [`EventBus`] filtered by category
+#[derive(Debug)]
+pub struct EventSubscription {
+    critical: PriorityReceiver,
+    telemetry: PriorityReceiver,
+    categories: Vec<EventCategory>,
+}
+
+impl EventSubscription {
+    fn wants(&self, event: &AppEvent) -> bool {
+        self.categories.contains(&event.category())
+    }
+
+    /// Take the earliest queued event of either channel
+    fn pop_earliest(&mut self) -> Option<AppEvent> {
+        self.critical.poll();
+        self.telemetry.poll();
+        let channel = match (self.critical.seq(), self.telemetry.seq()) {
+            (Some(critical), Some(telemetry)) if telemetry < critical => &mut self.telemetry,
+            (Some(_), _) => &mut self.critical,
+            (None, Some(_)) => &mut self.telemetry,
+            (None, None) => return None,
+        };
+        channel.next.take().map(|(_, event)| event)
+    }
+
+    /// Wait for the next matching event; `None` once the bus is gone
+    pub async fn recv(&mut self) -> Option<AppEvent> {
+        loop {
+            if let Some(event) = self.pop_earliest() {
+                if self.wants(&event) {
+                    return Some(event);
+                }
+                continue;
+            }
+            let open = tokio::select! {
+                open = self.critical.fill() => open,
+                open = self.telemetry.fill() => open,
+            };
+            if !open {
+                return self.try_recv();
+            }
+        }
+    }
+
+    /// Return a queued matching event without waiting
+    pub fn try_recv(&mut self) -> Option<AppEvent> {
+        while let Some(event) = self.pop_earliest() {
+            if self.wants(&event) {
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    /// Total number of events this subscriber missed by falling behind
+    pub fn lagged(&self) -> u64 {
+        self.critical.lagged + self.telemetry.lagged
+    }
+
+    /// Critical events missed; only a flood of critical events causes this
+    pub fn lagged_critical(&self) -> u64 {
+        self.critical.lagged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscription_filters_by_category() {
+        let bus = EventBus::new(16);
+        let mut transfers = bus.subscribe(&[EventCategory::Transfer]);
+        let mut everything = bus.subscribe_all();
+
+        bus.publish(AppEvent::Status("hello".to_string()));
+        bus.publish(AppEvent::TransferCompleted {
+            file_name: "a.txt".to_string(),
+            saved_path: None,
+            peer: None,
+        });
+
+        match transfers.recv().await {
+            Some(AppEvent::TransferCompleted { file_name, .. }) => assert_eq!(file_name, "a.txt"),
+            other => panic!("Unexpected event: {:?}", other),
+        }
+        assert!(transfers.try_recv().is_none());
+
+        assert!(matches!(everything.try_recv(), Some(AppEvent::Status(_))));
+        assert!(matches!(
+            everything.try_recv(),
+            Some(AppEvent::TransferCompleted { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_forward_from_mpsc() {
+        let bus = EventBus::new(16);
+        let mut sub = bus.subscribe(&[EventCategory::Http]);
+        let (tx, rx) = mpsc::channel(4);
+        let handle = bus.forward(rx);
+
+        tx.send(AppEvent::HttpServerStopped).await.unwrap();
+        drop(tx);
+        handle.await.unwrap();
+
+        assert!(matches!(
+            sub.recv().await,
+            Some(AppEvent::HttpServerStopped)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscriber_recovers() {
+        let bus = EventBus::new(2);
+        let mut sub = bus.subscribe_all();
+
+        for i in 0..5 {
+            bus.publish(AppEvent::Status(format!("msg {}", i)));
+        }
+
+        assert!(matches!(sub.recv().await, Some(AppEvent::Status(_))));
+        assert_eq!(sub.lagged(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_progress_burst_does_not_drop_errors() {
+        let bus = EventBus::new(4);
+        let mut sub = bus.subscribe_all();
+        let progress = |i: u32| AppEvent::TransferProgress {
+            file_name: "a.bin".to_string(),
+            progress: i as f32,
+            speed: String::new(),
+            speed_bps: 0.0,
+            is_sending: true,
+            bytes: None,
+        };
+
+        bus.publish(AppEvent::Error("disk full".to_string()));
+        for i in 0..50 {
+            bus.publish(progress(i));
+        }
+        bus.publish(AppEvent::TransferCompleted {
+            file_name: "a.bin".to_string(),
+            saved_path: None,
+            peer: None,
+        });
+        bus.publish(progress(99));
+
+        let mut received = Vec::new();
+        while let Some(event) = sub.try_recv() {
+            received.push(event);
+        }
+        assert!(matches!(received[0], AppEvent::Error(_)));
+        assert_eq!(sub.lagged_critical(), 0);
+        assert!(sub.lagged() > 0);
+        // Publication order holds across the two channels
+        let completed = received
+            .iter()
+            .position(|e| matches!(e, AppEvent::TransferCompleted { .. }))
+            .unwrap();
+        assert!(matches!(
+            received[completed + 1],
+            AppEvent::TransferProgress { progress, .. } if progress == 99.0
+        ));
+        assert_eq!(received.len(), completed + 2);
+    }
+
+    #[test]
+    fn test_peer_list_changes_are_never_dropped() {
+        // No later event brings a dropped one back
+        let lost = AppEvent::PeerLost {
+            endpoint_id: "id".to_string(),
+            ip: "10.0.0.2".to_string(),
+        };
+        let known = AppEvent::KnownPeersChanged {
+            paired: Vec::new(),
+            guests: Vec::new(),
+            guest_mode: false,
+            pinned: Vec::new(),
+            groups: Vec::new(),
+        };
+        assert_eq!(lost.priority(), EventPriority::Critical);
+        assert_eq!(known.priority(), EventPriority::Critical);
+    }
+}
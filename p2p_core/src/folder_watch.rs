@@ -0,0 +1,97 @@
+//! Notice when files appear in or leave the download folder.
+//!
+//! The GUI lists the download folder, and files moved or deleted there by
+//! other programs would otherwise only show after a manual refresh. A
+//! [`FolderWatcher`] reports such changes as one
+//! [`AppEvent::LocalFilesChanged`] per burst. Writes into existing files are
+//! ignored, so a running receive does not make the list reload all the time.
+
+use crate::AppEvent;
+use notify::event::{EventKind, ModifyKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Changes this close together are reported once
+pub const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches a folder until dropped
+pub struct FolderWatcher {
+    _watcher: RecommendedWatcher,
+    task: JoinHandle<()>,
+}
+
+impl Drop for FolderWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Whether `kind` adds, removes or renames a file
+fn lists_differently(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+    )
+}
+
+/// Watch `dir` and its subfolders, creating it if needed
+pub fn watch(dir: &Path, event_tx: mpsc::Sender<AppEvent>) -> notify::Result<FolderWatcher> {
+    crate::config::create_secure_dir_all(dir)?;
+    let (changed_tx, mut changed_rx) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if lists_differently(&event.kind) => {
+                let _ = changed_tx.send(());
+            }
+            Ok(_) => {}
+            Err(e) => tracing::debug!("Download folder watch error: {}", e),
+        })?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+
+    let task = tokio::spawn(async move {
+        while changed_rx.recv().await.is_some() {
+            tokio::time::sleep(WATCH_DEBOUNCE).await;
+            while changed_rx.try_recv().is_ok() {}
+            if event_tx.send(AppEvent::LocalFilesChanged).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(FolderWatcher {
+        _watcher: watcher,
+        task,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_new_and_deleted_files_are_reported_once() {
+        let dir = std::env::temp_dir().join(format!("watch_{}", uuid::Uuid::new_v4()));
+        let (event_tx, mut event_rx) = mpsc::channel(16);
+        let _watcher = watch(&dir, event_tx).unwrap();
+
+        for i in 0..5 {
+            std::fs::write(dir.join(format!("{}.txt", i)), b"new").unwrap();
+        }
+        let event = tokio::time::timeout(Duration::from_secs(5), event_rx.recv())
+            .await
+            .unwrap();
+        assert!(matches!(event, Some(AppEvent::LocalFilesChanged)));
+        tokio::time::sleep(WATCH_DEBOUNCE * 2).await;
+        assert!(event_rx.try_recv().is_err());
+
+        std::fs::remove_file(dir.join("0.txt")).unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(5), event_rx.recv())
+            .await
+            .unwrap();
+        assert!(matches!(event, Some(AppEvent::LocalFilesChanged)));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,204 @@
+//! Named groups of peers, and sends to a whole group at once.
+//!
+//! A group ("Team A") lists endpoint IDs and is saved to the profile next
+//! to the pinned peers. [`crate::AppCommand::SendToGroup`] sends one batch
+//! to every member: each member gets a send of its own, paired and
+//! journaled like any other, and [`GroupSend`] folds their outcomes into
+//! one progress count and a report of which members failed and why.
+
+use crate::AppEvent;
+use serde::{Deserialize, Serialize};
+
+/// Longest group name kept
+pub const MAX_GROUP_NAME_CHARS: usize = 64;
+
+/// Peers sent to together, by endpoint ID
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerGroup {
+    pub name: String,
+    pub members: Vec<String>,
+}
+
+/// One member of a group send
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupTarget {
+    /// Verification session, see [`crate::AppCommand::SendFile`]
+    pub session_id: String,
+    pub endpoint_id: String,
+    pub target_peer_name: String,
+    /// `ip` or `ip:port`; `None` when the member is not on the LAN, which
+    /// counts as a failure of that member
+    pub target_ip: Option<String>,
+}
+
+/// A member the batch did not reach
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GroupFailure {
+    pub endpoint_id: String,
+    pub name: String,
+    pub reason: String,
+}
+
+/// Create, change or, with no members, delete the group `name` in
+/// `groups`. Members are kept once each, in the order given.
+pub fn set_group(
+    groups: &mut Vec<PeerGroup>,
+    name: &str,
+    members: Vec<String>,
+) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("A group needs a name".to_string());
+    }
+    if name.chars().count() > MAX_GROUP_NAME_CHARS {
+        return Err(format!(
+            "Group names are at most {} characters",
+            MAX_GROUP_NAME_CHARS
+        ));
+    }
+    let mut unique = Vec::with_capacity(members.len());
+    for member in members {
+        if !member.is_empty() && !unique.contains(&member) {
+            unique.push(member);
+        }
+    }
+
+    let existing = groups.iter().position(|group| group.name == name);
+    match (existing, unique.is_empty()) {
+        (Some(index), true) => {
+            groups.remove(index);
+        }
+        (Some(index), false) => groups[index].members = unique,
+        (None, true) => {}
+        (None, false) => {
+            groups.push(PeerGroup {
+                name: name.to_string(),
+                members: unique,
+            });
+            groups.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of the members of one group send so far
+#[derive(Debug)]
+pub struct GroupSend {
+    group_id: String,
+    group: String,
+    total: usize,
+    /// Names of the members that got the whole batch
+    sent: Vec<String>,
+    failed: Vec<GroupFailure>,
+}
+
+impl GroupSend {
+    pub fn new(group_id: String, group: String, total: usize) -> Self {
+        Self {
+            group_id,
+            group,
+            total,
+            sent: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+
+    /// Record how the send to `target` ended
+    pub fn member_done(&mut self, target: &GroupTarget, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.sent.push(target.target_peer_name.clone()),
+            Err(reason) => self.failed.push(GroupFailure {
+                endpoint_id: target.endpoint_id.clone(),
+                name: target.target_peer_name.clone(),
+                reason,
+            }),
+        }
+    }
+
+    /// Every member has finished, one way or the other
+    pub fn is_done(&self) -> bool {
+        self.sent.len() + self.failed.len() >= self.total
+    }
+
+    /// [`AppEvent::GroupSendProgress`] for the members so far
+    pub fn progress(&self) -> AppEvent {
+        AppEvent::GroupSendProgress {
+            group_id: self.group_id.clone(),
+            group: self.group.clone(),
+            sent: self.sent.len(),
+            failed: self.failed.len(),
+            total: self.total,
+        }
+    }
+
+    /// [`AppEvent::GroupSendFinished`] with every member's outcome
+    pub fn finish(self) -> AppEvent {
+        AppEvent::GroupSendFinished {
+            group_id: self.group_id,
+            group: self.group,
+            sent: self.sent,
+            failed: self.failed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(endpoint_id: &str, name: &str) -> GroupTarget {
+        GroupTarget {
+            session_id: format!("session-{}", endpoint_id),
+            endpoint_id: endpoint_id.to_string(),
+            target_peer_name: name.to_string(),
+            target_ip: Some("10.0.0.2".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_groups_are_created_changed_and_deleted() {
+        let mut groups = Vec::new();
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        set_group(&mut groups, " Team B ", ids(&["c"])).unwrap();
+        set_group(&mut groups, "Team A", ids(&["a", "b", "a", ""])).unwrap();
+        assert_eq!(groups[0].name, "Team A");
+        assert_eq!(groups[0].members, ["a", "b"]);
+        assert_eq!(groups[1].name, "Team B");
+
+        set_group(&mut groups, "Team A", ids(&["b"])).unwrap();
+        assert_eq!(groups[0].members, ["b"]);
+        set_group(&mut groups, "Team B", Vec::new()).unwrap();
+        assert_eq!(groups.len(), 1);
+
+        assert!(set_group(&mut groups, "  ", ids(&["a"])).is_err());
+        let long = "x".repeat(MAX_GROUP_NAME_CHARS + 1);
+        assert!(set_group(&mut groups, &long, ids(&["a"])).is_err());
+    }
+
+    #[test]
+    fn test_group_send_reports_each_failed_member() {
+        let mut send = GroupSend::new("g1".to_string(), "Team A".to_string(), 3);
+        send.member_done(&target("a", "Alpha"), Ok(()));
+        send.member_done(&target("b", "Beta"), Err("not on the LAN".to_string()));
+        assert!(!send.is_done());
+        assert!(matches!(
+            send.progress(),
+            AppEvent::GroupSendProgress {
+                sent: 1,
+                failed: 1,
+                total: 3,
+                ..
+            }
+        ));
+
+        send.member_done(&target("c", "Gamma"), Ok(()));
+        assert!(send.is_done());
+        let AppEvent::GroupSendFinished { sent, failed, .. } = send.finish() else {
+            panic!("expected GroupSendFinished");
+        };
+        assert_eq!(sent, ["Alpha", "Gamma"]);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].endpoint_id, "b");
+        assert_eq!(failed[0].reason, "not on the LAN");
+    }
+}
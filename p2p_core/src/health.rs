@@ -0,0 +1,39 @@
+//! Heartbeat of the backend's services.
+//!
+//! Every [`HEALTH_INTERVAL`] the backend reports
+//! [`AppEvent::BackendHealth`](crate::AppEvent::BackendHealth) with what is
+//! actually up, so frontends need not guess from status lines. One that
+//! hears nothing for [`HEALTH_STALE_AFTER`] should assume the backend is
+//! stuck. The WAN fields come from the Iroh endpoint handed in with
+//! [`P2pNodeBuilder::wan_endpoint`](crate::P2pNodeBuilder::wan_endpoint)
+//! or [`wan_endpoints`](crate::P2pNodeBuilder::wan_endpoints).
+
+use iroh::{Endpoint, Watcher};
+use std::time::Duration;
+
+/// How often the backend reports its health
+pub const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// No report for this long means the backend stopped answering
+pub const HEALTH_STALE_AFTER: Duration = Duration::from_secs(15);
+
+/// Whether `endpoint` is connected to its home relay, and the round trip to
+/// that relay in the last network report, in milliseconds
+pub fn relay_status(endpoint: &Endpoint) -> (bool, Option<u64>) {
+    if endpoint.is_closed() {
+        return (false, None);
+    }
+    let addr = endpoint.addr();
+    let Some(home) = addr.relay_urls().next() else {
+        return (false, None);
+    };
+    let latency = endpoint.net_report().get().and_then(|report| {
+        report
+            .relay_latency
+            .iter()
+            .filter(|(_, url, _)| *url == home)
+            .map(|(_, _, latency)| latency)
+            .min()
+    });
+    (true, latency.map(|latency| latency.as_millis() as u64))
+}
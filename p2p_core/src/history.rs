@@ -0,0 +1,213 @@
+//! History of transferred files.
+//!
+//! Every verified file is recorded in `received_history.json` in the
+//! profile's config directory. When a new file has the same hash and size
+//! as an earlier one that is still on disk, the receiver reports an
+//! [`AppEvent::DuplicateReceived`](crate::AppEvent::DuplicateReceived) and
+//! the user decides with [`DuplicateAction`] what happens to the copy.
+//! Every file sent or received also goes into the [`transfers`] log, which
+//! [`AppCommand::QueryHistory`](crate::AppCommand::QueryHistory) searches
+//! and [`AppCommand::ExportHistory`](crate::AppCommand::ExportHistory)
+//! writes out, and the bytes moved per day are counted in [`usage`].
+
+pub mod export;
+pub mod transfers;
+pub mod usage;
+
+use crate::config::{create_secure_dir_all, write_secure_file};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use transfers::{TRANSFER_LOG_FILE, TransferLog};
+use usage::{USAGE_FILE, UsageMeter};
+
+/// File name of the hash index inside the config directory
+pub const HISTORY_FILE: &str = "received_history.json";
+
+/// Most records returned by one [`AppCommand::QueryHistory`](crate::AppCommand::QueryHistory)
+pub const MAX_QUERY_RESULTS: usize = 500;
+
+/// What to do with a received file that duplicates an earlier one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateAction {
+    /// Replace the new file with a hard link to the earlier copy
+    HardLink,
+    /// Delete the new file
+    Skip,
+    /// Leave both files as they are
+    KeepBoth,
+}
+
+/// One received file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceivedEntry {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl ReceivedEntry {
+    /// Whether the file is still on disk with the recorded size
+    fn is_present(&self) -> bool {
+        fs::metadata(&self.path).is_ok_and(|meta| meta.is_file() && meta.len() == self.size)
+    }
+}
+
+#[derive(Debug, Default)]
+struct HistoryState {
+    /// BLAKE3 hash -> files received with that content
+    by_hash: HashMap<String, Vec<ReceivedEntry>>,
+    /// Duplicates waiting for a decision (new file -> earlier copy)
+    pending: HashMap<PathBuf, PathBuf>,
+}
+
+/// Hash index of received files, shared by the server and the backend
+#[derive(Debug, Default)]
+pub struct HistoryStore {
+    /// `None` keeps the index in memory only
+    path: Option<PathBuf>,
+    state: Mutex<HistoryState>,
+    /// Every file sent or received, kept next to the index
+    pub transfers: TransferLog,
+    /// Bytes sent and received per day, kept next to the index
+    pub usage: Arc<UsageMeter>,
+}
+
+impl HistoryStore {
+    /// Load the index saved at `path` and the transfer log beside it; a
+    /// missing or invalid file is empty
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let transfers = TransferLog::load(
+            path.as_deref()
+                .map(|path| path.with_file_name(TRANSFER_LOG_FILE)),
+        );
+        let usage = UsageMeter::load(path.as_deref().map(|path| path.with_file_name(USAGE_FILE)));
+        let by_hash = path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            state: Mutex::new(HistoryState {
+                by_hash,
+                pending: HashMap::new(),
+            }),
+            transfers,
+            usage: Arc::new(usage),
+        }
+    }
+
+    /// Record a verified file. Returns an earlier copy with the same content
+    /// if one is still on disk; the pair then waits for [`resolve`](Self::resolve).
+    pub fn record(&self, hash: &str, path: &Path, size: u64) -> Option<PathBuf> {
+        let mut state = self.state.lock().unwrap();
+        let entries = state.by_hash.entry(hash.to_string()).or_default();
+        entries.retain(|entry| entry.path != path && entry.is_present());
+        let existing = entries.first().map(|entry| entry.path.clone());
+        entries.push(ReceivedEntry {
+            path: path.to_path_buf(),
+            size,
+        });
+        state.by_hash.retain(|_, entries| !entries.is_empty());
+
+        if let Some(existing) = &existing {
+            state.pending.insert(path.to_path_buf(), existing.clone());
+        }
+        self.save(&state.by_hash);
+        existing
+    }
+
+    /// Apply the user's decision for the duplicate at `path`
+    pub fn resolve(&self, path: &Path, action: DuplicateAction) -> Result<()> {
+        let existing = self
+            .state
+            .lock()
+            .unwrap()
+            .pending
+            .remove(path)
+            .ok_or_else(|| anyhow!("No pending duplicate for {}", path.display()))?;
+
+        match action {
+            DuplicateAction::KeepBoth => return Ok(()),
+            DuplicateAction::Skip => fs::remove_file(path)?,
+            DuplicateAction::HardLink => {
+                // Link under a temporary name first so a failure (e.g. another
+                // file system) leaves the received copy untouched
+                let mut link_name = path.as_os_str().to_owned();
+                link_name.push(".p2p-link");
+                let link = PathBuf::from(link_name);
+                fs::hard_link(&existing, &link)?;
+                if let Err(e) = fs::rename(&link, path) {
+                    let _ = fs::remove_file(&link);
+                    return Err(e.into());
+                }
+                return Ok(());
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        for entries in state.by_hash.values_mut() {
+            entries.retain(|entry| entry.path != path);
+        }
+        state.by_hash.retain(|_, entries| !entries.is_empty());
+        self.save(&state.by_hash);
+        Ok(())
+    }
+
+    fn save(&self, by_hash: &HashMap<String, Vec<ReceivedEntry>>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = create_secure_dir_all(parent);
+        }
+        match serde_json::to_string_pretty(by_hash) {
+            Ok(json) => {
+                if let Err(e) = write_secure_file(path, &json) {
+                    tracing::warn!("Failed to save received file history: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to serialize received file history: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_are_found_and_resolved() {
+        let dir = std::env::temp_dir().join(format!("history_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let index = dir.join(HISTORY_FILE);
+        let first = dir.join("a.txt");
+        let second = dir.join("b.txt");
+        let third = dir.join("c.txt");
+        for file in [&first, &second, &third] {
+            fs::write(file, b"same").unwrap();
+        }
+
+        let store = HistoryStore::load(Some(index.clone()));
+        assert_eq!(store.record("h", &first, 4), None);
+        assert_eq!(store.record("h", &second, 4), Some(first.clone()));
+
+        // The index survives a restart
+        let store = HistoryStore::load(Some(index));
+        assert_eq!(store.record("h", &third, 4), Some(first.clone()));
+        store.resolve(&third, DuplicateAction::Skip).unwrap();
+        assert!(!third.exists());
+        assert!(store.resolve(&third, DuplicateAction::Skip).is_err());
+
+        // A copy that changed on disk no longer counts
+        fs::write(&first, b"edited").unwrap();
+        let fourth = dir.join("d.txt");
+        fs::write(&fourth, b"same").unwrap();
+        assert_eq!(store.record("h", &fourth, 4), Some(second));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
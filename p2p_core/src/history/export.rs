@@ -0,0 +1,161 @@
+//! Writing the transfer log out as CSV or JSON for reports.
+
+use super::transfers::{Direction, TransferRecord, TransferStatus};
+use crate::config::write_secure_file;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    /// A pretty-printed array of [`TransferRecord`]s
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+const CSV_HEADER: &str =
+    "time,direction,status,file_name,size,path,peer,peer_id,hash,hash_algorithm,note";
+
+/// Write `records` to `path`, readable only by the user since it names
+/// peers and files
+pub fn write_export(records: &[TransferRecord], format: ExportFormat, path: &Path) -> Result<()> {
+    let content = match format {
+        ExportFormat::Csv => to_csv(records),
+        ExportFormat::Json => serde_json::to_string_pretty(records)?,
+    };
+    write_secure_file(path, &content)?;
+    Ok(())
+}
+
+fn to_csv(records: &[TransferRecord]) -> String {
+    let mut out = String::from(CSV_HEADER);
+    out.push_str("\r\n");
+    for record in records {
+        let fields = [
+            format_time(record.at),
+            direction_name(record.direction).to_string(),
+            status_name(record.status).to_string(),
+            record.file_name.clone(),
+            record.size.to_string(),
+            record
+                .path
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            record.peer.clone(),
+            record.peer_id.clone().unwrap_or_default(),
+            record.hash.clone().unwrap_or_default(),
+            serde_json::to_value(record.hash_algorithm)
+                .ok()
+                .and_then(|value| value.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            record.note.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&row.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Quote a field per RFC 4180, and defuse values a spreadsheet would run as
+/// a formula
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// UTC, RFC 3339
+fn format_time(at: u64) -> String {
+    i64::try_from(at)
+        .ok()
+        .and_then(|at| chrono::DateTime::from_timestamp(at, 0))
+        .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Sent => "sent",
+        Direction::Received => "received",
+    }
+}
+
+fn status_name(status: TransferStatus) -> &'static str {
+    match status {
+        TransferStatus::Completed => "completed",
+        TransferStatus::Verified => "verified",
+        TransferStatus::Unverified => "unverified",
+        TransferStatus::Failed => "failed",
+        TransferStatus::Cancelled => "cancelled",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_quotes_and_defuses_fields() {
+        let record = TransferRecord {
+            at: 1_700_000_000,
+            hash: Some("ab12".to_string()),
+            note: Some("=SUM(A1), \"Q3\"".to_string()),
+            ..TransferRecord::new(
+                Direction::Received,
+                TransferStatus::Verified,
+                "a,b.txt",
+                7,
+                "Laptop",
+            )
+        };
+        let csv = to_csv(&[record]);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER));
+        assert_eq!(
+            lines.next(),
+            Some(
+                "2023-11-14T22:13:20Z,received,verified,\"a,b.txt\",7,,Laptop,,ab12,blake3,\"'=SUM(A1), \"\"Q3\"\"\""
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_export_reads_back() {
+        let records = vec![TransferRecord {
+            peer_id: Some("a1b2".to_string()),
+            ..TransferRecord::new(
+                Direction::Sent,
+                TransferStatus::Completed,
+                "a.txt",
+                3,
+                "Phone",
+            )
+        }];
+        let path =
+            std::env::temp_dir().join(format!("history_export_{}.json", uuid::Uuid::new_v4()));
+        write_export(&records, ExportFormat::Json, &path).unwrap();
+        let read: Vec<TransferRecord> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(read, records);
+        let _ = std::fs::remove_file(&path);
+    }
+}
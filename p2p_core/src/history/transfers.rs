@@ -0,0 +1,356 @@
+//! Log of every file sent or received, for searching and reports.
+//!
+//! One JSON record per line in `transfer_history.jsonl`, next to the hash
+//! index. New records are appended; the oldest are dropped on load once
+//! there are more than [`MAX_TRANSFER_RECORDS`].
+
+use crate::config::{create_secure_dir_all, write_secure_file};
+use crate::pairing::now_timestamp;
+use crate::transfer::hash::HashAlgorithm;
+use crate::transfer::receipt::SignedReceipt;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// File name of the log inside the config directory
+pub const TRANSFER_LOG_FILE: &str = "transfer_history.jsonl";
+
+/// Records kept; older ones are dropped
+pub const MAX_TRANSFER_RECORDS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// How a transfer ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStatus {
+    /// Sent and acknowledged by the receiver
+    Completed,
+    /// Received and its hash matched
+    Verified,
+    /// Received without a hash this version can check
+    Unverified,
+    /// Hash mismatch or an error
+    Failed,
+    Cancelled,
+}
+
+/// One file sent or received
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferRecord {
+    /// Unix seconds when the transfer ended
+    pub at: u64,
+    pub direction: Direction,
+    pub status: TransferStatus,
+    pub file_name: String,
+    pub size: u64,
+    /// Where a received file was saved, or the source of a sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Name of the other device
+    pub peer: String,
+    /// The sender's endpoint ID, or the fingerprint of the receiver's
+    /// certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// The receiver's signed proof of delivery, for a sent file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<SignedReceipt>,
+}
+
+impl TransferRecord {
+    /// A record of `file_name` ending now, to be filled in with the
+    /// struct update syntax
+    pub fn new(
+        direction: Direction,
+        status: TransferStatus,
+        file_name: impl Into<String>,
+        size: u64,
+        peer: impl Into<String>,
+    ) -> Self {
+        Self {
+            at: now_timestamp(),
+            direction,
+            status,
+            file_name: file_name.into(),
+            size,
+            path: None,
+            peer: peer.into(),
+            peer_id: None,
+            hash: None,
+            hash_algorithm: HashAlgorithm::default(),
+            note: None,
+            receipt: None,
+        }
+    }
+}
+
+/// Filter for [`TransferLog::query`]; empty fields match everything
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    /// Part of the file name or note, ignoring case
+    pub text: String,
+    /// Part of the peer's name or ID, ignoring case
+    pub peer: String,
+    /// Unix seconds, inclusive
+    pub since: Option<u64>,
+    /// Unix seconds, inclusive
+    pub until: Option<u64>,
+    pub direction: Option<Direction>,
+    pub status: Option<TransferStatus>,
+}
+
+fn contains_ignoring_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(needle)
+}
+
+impl HistoryQuery {
+    pub fn matches(&self, record: &TransferRecord) -> bool {
+        let text = self.text.trim().to_lowercase();
+        let peer = self.peer.trim().to_lowercase();
+        (text.is_empty()
+            || contains_ignoring_case(&record.file_name, &text)
+            || record
+                .note
+                .as_deref()
+                .is_some_and(|note| contains_ignoring_case(note, &text)))
+            && (peer.is_empty()
+                || contains_ignoring_case(&record.peer, &peer)
+                || record
+                    .peer_id
+                    .as_deref()
+                    .is_some_and(|id| contains_ignoring_case(id, &peer)))
+            && self.since.is_none_or(|since| record.at >= since)
+            && self.until.is_none_or(|until| record.at <= until)
+            && self.direction.is_none_or(|d| record.direction == d)
+            && self.status.is_none_or(|s| record.status == s)
+    }
+}
+
+/// The records, in the order they were written
+#[derive(Debug, Default)]
+pub struct TransferLog {
+    /// `None` keeps the log in memory only
+    path: Option<PathBuf>,
+    records: Mutex<Vec<TransferRecord>>,
+}
+
+impl TransferLog {
+    /// Load the log at `path`; unreadable lines are skipped
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut records: Vec<TransferRecord> = path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if records.len() > MAX_TRANSFER_RECORDS {
+            records.drain(..records.len() - MAX_TRANSFER_RECORDS);
+            if let Some(path) = &path {
+                rewrite(path, &records);
+            }
+        }
+        Self {
+            path,
+            records: Mutex::new(records),
+        }
+    }
+
+    pub fn add(&self, record: TransferRecord) {
+        if let Some(path) = &self.path
+            && let Err(e) = append(path, &record)
+        {
+            tracing::warn!("Failed to save transfer history: {}", e);
+        }
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.push(record);
+        if records.len() > MAX_TRANSFER_RECORDS {
+            records.remove(0);
+        }
+    }
+
+    /// Records matching `query`, newest first
+    pub fn query(&self, query: &HistoryQuery) -> Vec<TransferRecord> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records
+            .iter()
+            .rev()
+            .filter(|record| query.matches(record))
+            .cloned()
+            .collect()
+    }
+}
+
+fn append(path: &Path, record: &TransferRecord) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        create_secure_dir_all(parent)?;
+    }
+    if !path.exists() {
+        write_secure_file(path, "")?;
+    }
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    fs::OpenOptions::new()
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+fn rewrite(path: &Path, records: &[TransferRecord]) {
+    let content: String = records
+        .iter()
+        .filter_map(|record| serde_json::to_string(record).ok())
+        .map(|line| line + "\n")
+        .collect();
+    if let Err(e) = write_secure_file(path, &content) {
+        tracing::warn!("Failed to trim transfer history: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, direction: Direction, status: TransferStatus, at: u64) -> TransferRecord {
+        TransferRecord {
+            at,
+            note: Some("Invoices Q3".to_string()),
+            peer_id: Some("a1b2c3".to_string()),
+            ..TransferRecord::new(direction, status, name, 10, "Laptop")
+        }
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let log = TransferLog::load(None);
+        log.add(record(
+            "a.pdf",
+            Direction::Sent,
+            TransferStatus::Completed,
+            100,
+        ));
+        log.add(record(
+            "b.jpg",
+            Direction::Received,
+            TransferStatus::Verified,
+            200,
+        ));
+        log.add(TransferRecord {
+            at: 300,
+            ..TransferRecord::new(
+                Direction::Received,
+                TransferStatus::Cancelled,
+                "c.pdf",
+                5,
+                "Phone",
+            )
+        });
+
+        let names = |query: HistoryQuery| -> Vec<String> {
+            log.query(&query)
+                .into_iter()
+                .map(|record| record.file_name)
+                .collect()
+        };
+        assert_eq!(names(HistoryQuery::default()), ["c.pdf", "b.jpg", "a.pdf"]);
+        let text = |text: &str| HistoryQuery {
+            text: text.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(names(text(".PDF")), ["c.pdf", "a.pdf"]);
+        assert_eq!(names(text("invoices")), ["b.jpg", "a.pdf"]);
+        assert_eq!(
+            names(HistoryQuery {
+                peer: "A1B2".to_string(),
+                ..Default::default()
+            }),
+            ["b.jpg", "a.pdf"]
+        );
+        assert_eq!(
+            names(HistoryQuery {
+                since: Some(150),
+                until: Some(300),
+                direction: Some(Direction::Received),
+                ..Default::default()
+            }),
+            ["c.pdf", "b.jpg"]
+        );
+        assert_eq!(
+            names(HistoryQuery {
+                status: Some(TransferStatus::Cancelled),
+                ..Default::default()
+            }),
+            ["c.pdf"]
+        );
+    }
+
+    #[test]
+    fn test_log_is_appended_and_trimmed() {
+        let path = std::env::temp_dir()
+            .join(format!("history_{}", uuid::Uuid::new_v4()))
+            .join(TRANSFER_LOG_FILE);
+        let log = TransferLog::load(Some(path.clone()));
+        for at in 0..3 {
+            log.add(record(
+                "a.pdf",
+                Direction::Sent,
+                TransferStatus::Completed,
+                at,
+            ));
+        }
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"not json\n")
+            .unwrap();
+
+        let reloaded = TransferLog::load(Some(path.clone()));
+        assert_eq!(
+            reloaded.query(&HistoryQuery::default()),
+            log.query(&HistoryQuery::default())
+        );
+
+        let many = std::iter::repeat_n(
+            serde_json::to_string(&record(
+                "a.pdf",
+                Direction::Sent,
+                TransferStatus::Completed,
+                0,
+            ))
+            .unwrap()
+                + "\n",
+            MAX_TRANSFER_RECORDS + 5,
+        )
+        .collect::<String>();
+        std::fs::write(&path, many).unwrap();
+        let trimmed = TransferLog::load(Some(path.clone()));
+        assert_eq!(
+            trimmed.query(&HistoryQuery::default()).len(),
+            MAX_TRANSFER_RECORDS
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap().lines().count(),
+            MAX_TRANSFER_RECORDS
+        );
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,251 @@
+//! Bytes sent and received per day, for metered connections.
+//!
+//! Transfers add to today's counters as data moves; the backend saves them
+//! to `bandwidth_usage.json` on its schedule tick and compares the calendar
+//! month against the user's [`BandwidthCap`]. Days are local dates, and
+//! only the last [`DAYS_KEPT`] are kept.
+
+use super::transfers::Direction;
+use crate::config::{create_secure_dir_all, write_secure_file};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// File name of the counters inside the config directory
+pub const USAGE_FILE: &str = "bandwidth_usage.json";
+
+/// Days of counters kept
+pub const DAYS_KEPT: usize = 400;
+
+/// Share of the cap, in percent, at which the user is warned
+pub const CAP_WARNING_PERCENT: u64 = 80;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DayUsage {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl DayUsage {
+    pub fn total(&self) -> u64 {
+        self.sent.saturating_add(self.received)
+    }
+
+    fn add(&mut self, other: DayUsage) {
+        self.sent = self.sent.saturating_add(other.sent);
+        self.received = self.received.saturating_add(other.received);
+    }
+}
+
+/// Monthly data cap, stored in `config.json`; off by default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandwidthCap {
+    /// Bytes sent and received per calendar month
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_bytes: Option<u64>,
+    /// Hold scheduled sends while the cap is reached; sends the user starts
+    /// go ahead with a warning
+    #[serde(default)]
+    pub hold_scheduled: bool,
+}
+
+/// How far this month's usage is along the cap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CapLevel {
+    Below,
+    /// Past [`CAP_WARNING_PERCENT`]
+    Near,
+    Reached,
+}
+
+impl BandwidthCap {
+    pub fn level(&self, used: u64) -> CapLevel {
+        match self.monthly_bytes {
+            Some(cap) if used >= cap => CapLevel::Reached,
+            Some(cap) if used.saturating_mul(100) >= cap.saturating_mul(CAP_WARNING_PERCENT) => {
+                CapLevel::Near
+            }
+            _ => CapLevel::Below,
+        }
+    }
+}
+
+/// Totals for [`AppEvent::BandwidthUsage`](crate::AppEvent::BandwidthUsage)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageSummary {
+    pub today: DayUsage,
+    /// Today and the six days before
+    pub week: DayUsage,
+    /// The current calendar month
+    pub month: DayUsage,
+    pub cap: BandwidthCap,
+}
+
+#[derive(Debug, Default)]
+struct UsageState {
+    /// "YYYY-MM-DD" -> bytes; the format sorts by date
+    days: BTreeMap<String, DayUsage>,
+    /// Changed since the last save
+    dirty: bool,
+}
+
+/// Per-day counters, shared by every transfer of a profile
+#[derive(Debug, Default)]
+pub struct UsageMeter {
+    /// `None` keeps the counters in memory only
+    path: Option<PathBuf>,
+    state: Mutex<UsageState>,
+}
+
+fn today() -> chrono::NaiveDate {
+    chrono::Local::now().date_naive()
+}
+
+fn day_key(date: chrono::NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+impl UsageMeter {
+    /// Load the counters saved at `path`; a missing or invalid file is empty
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let days = path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            state: Mutex::new(UsageState { days, dirty: false }),
+        }
+    }
+
+    /// Count `bytes` moved today
+    pub fn add(&self, direction: Direction, bytes: u64) {
+        self.add_on(today(), direction, bytes);
+    }
+
+    fn add_on(&self, date: chrono::NaiveDate, direction: Direction, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let day = state.days.entry(day_key(date)).or_default();
+        let counter = match direction {
+            Direction::Sent => &mut day.sent,
+            Direction::Received => &mut day.received,
+        };
+        *counter = counter.saturating_add(bytes);
+        state.dirty = true;
+    }
+
+    /// Totals as of today
+    pub fn summary(&self, cap: &BandwidthCap) -> UsageSummary {
+        self.summary_on(today(), cap)
+    }
+
+    fn summary_on(&self, date: chrono::NaiveDate, cap: &BandwidthCap) -> UsageSummary {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let today = day_key(date);
+        let week_start = day_key(date - chrono::Days::new(6));
+        let month = date.format("%Y-%m-").to_string();
+        let mut summary = UsageSummary {
+            cap: cap.clone(),
+            ..UsageSummary::default()
+        };
+        for (day, usage) in state.days.range(week_start.clone().min(month.clone())..) {
+            if *day == today {
+                summary.today.add(*usage);
+            }
+            if *day >= week_start && *day <= today {
+                summary.week.add(*usage);
+            }
+            if day.starts_with(&month) {
+                summary.month.add(*usage);
+            }
+        }
+        summary
+    }
+
+    /// Write the counters if they changed, dropping the oldest days
+    pub fn save(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if !state.dirty {
+            return;
+        }
+        while state.days.len() > DAYS_KEPT {
+            state.days.pop_first();
+        }
+        state.dirty = false;
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = create_secure_dir_all(parent);
+        }
+        match serde_json::to_string_pretty(&state.days) {
+            Ok(json) => {
+                if let Err(e) = write_secure_file(path, &json) {
+                    tracing::warn!("Failed to save bandwidth usage: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to serialize bandwidth usage: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> chrono::NaiveDate {
+        chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_summary_by_day_week_and_month() {
+        let meter = UsageMeter::load(None);
+        meter.add_on(date("2026-09-28"), Direction::Sent, 1);
+        meter.add_on(date("2026-09-30"), Direction::Received, 10);
+        meter.add_on(date("2026-10-01"), Direction::Sent, 100);
+        meter.add_on(date("2026-10-03"), Direction::Sent, 1000);
+        meter.add_on(date("2026-10-03"), Direction::Received, 2000);
+
+        let summary = meter.summary_on(date("2026-10-03"), &BandwidthCap::default());
+        assert_eq!(
+            summary.today,
+            DayUsage {
+                sent: 1000,
+                received: 2000
+            }
+        );
+        assert_eq!(summary.week.total(), 3111);
+        assert_eq!(summary.month.total(), 3100);
+    }
+
+    #[test]
+    fn test_cap_levels() {
+        let cap = BandwidthCap {
+            monthly_bytes: Some(1000),
+            hold_scheduled: true,
+        };
+        assert_eq!(cap.level(799), CapLevel::Below);
+        assert_eq!(cap.level(800), CapLevel::Near);
+        assert_eq!(cap.level(1000), CapLevel::Reached);
+        assert_eq!(BandwidthCap::default().level(u64::MAX), CapLevel::Below);
+    }
+
+    #[test]
+    fn test_counters_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("usage_{}.json", uuid::Uuid::new_v4()));
+        let meter = UsageMeter::load(Some(path.clone()));
+        meter.add(Direction::Sent, 42);
+        meter.save();
+
+        let reloaded = UsageMeter::load(Some(path.clone()));
+        assert_eq!(reloaded.summary(&BandwidthCap::default()).today.sent, 42);
+        let _ = std::fs::remove_file(&path);
+    }
+}
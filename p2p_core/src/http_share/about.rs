@@ -0,0 +1,110 @@
+//! About route: which host and app a share link leads to.
+//!
+//! `GET /<token>/about` answers with the app and protocol version, the
+//! application ID and the endpoint ID of the sharing device, plus the build
+//! it runs. A remote user can compare the endpoint ID with the one the
+//! owner told them before uploading anything. Only holders of the share
+//! token can see it, like the share page itself.
+
+use crate::APP_VERSION;
+use crate::alpn::{AppId, PROTOCOL_VERSION};
+use axum::{Json, Router, extract::State, middleware, routing::get};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::server::add_security_headers;
+
+/// How this binary was built, from `build.rs`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// Short git commit, "unknown" outside a checkout
+    pub commit: String,
+    /// Target triple, e.g. `x86_64-pc-windows-msvc`
+    pub target: String,
+    /// `debug` or `release`
+    pub profile: String,
+}
+
+impl BuildInfo {
+    /// The build of this binary
+    pub fn current() -> Self {
+        Self {
+            commit: env!("P2P_BUILD_COMMIT").to_string(),
+            target: env!("P2P_BUILD_TARGET").to_string(),
+            profile: env!("P2P_BUILD_PROFILE").to_string(),
+        }
+    }
+}
+
+/// Body of `GET /<token>/about`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AboutInfo {
+    pub app_version: String,
+    pub protocol_version: u32,
+    pub app_id: String,
+    pub endpoint_id: String,
+    pub build: BuildInfo,
+}
+
+impl AboutInfo {
+    /// This device, as the node with `endpoint_id` speaking `app_id`
+    pub fn new(endpoint_id: impl Into<String>, app_id: &AppId) -> Self {
+        Self {
+            app_version: APP_VERSION.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            app_id: app_id.to_string(),
+            endpoint_id: endpoint_id.into(),
+            build: BuildInfo::current(),
+        }
+    }
+}
+
+async fn about_handler(State(about): State<Arc<AboutInfo>>) -> Json<AboutInfo> {
+    Json(about.as_ref().clone())
+}
+
+/// Route of the about answer under the share `token`, to be merged into
+/// the share server's router
+pub fn create_about_router(token: &str, about: AboutInfo) -> Router {
+    Router::new()
+        .route(&format!("/{}/about", token), get(about_handler))
+        .layer(middleware::from_fn(add_security_headers))
+        .with_state(Arc::new(about))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    async fn fetch(router: &Router, uri: &str) -> axum::response::Response {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_about_needs_the_share_token() {
+        let router =
+            create_about_router("share_token", AboutInfo::new("node-id", &AppId::default()));
+
+        let response = fetch(&router, "/share_token/about").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let about: AboutInfo = serde_json::from_slice(&body).unwrap();
+        assert_eq!(about.endpoint_id, "node-id");
+        assert_eq!(about.app_version, APP_VERSION);
+        assert_eq!(about.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(about.app_id, "p2p-transfer");
+        assert!(!about.build.commit.is_empty());
+        assert!(!about.build.target.is_empty());
+
+        let response = fetch(&router, "/other_token/about").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = fetch(&router, "/about").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
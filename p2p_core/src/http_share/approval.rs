@@ -0,0 +1,206 @@
+//! Automatic approval of browser uploads.
+//!
+//! Every upload from the share page normally waits for the host to click
+//! accept. An [`UploadApprovalPolicy`] lets some through on its own: files
+//! up to a size threshold, clients in trusted networks, and clients the
+//! host already approved once while the server is running. Auto-approved
+//! uploads are announced with [`AppEvent::UploadAutoApproved`] instead of
+//! an [`AppEvent::UploadRequest`].
+//!
+//! Loopback clients are never trusted or remembered: every visitor of the
+//! internet tunnel reaches the server from localhost, so approving one of
+//! them must not approve them all.
+//!
+//! [`AppEvent::UploadAutoApproved`]: crate::AppEvent::UploadAutoApproved
+//! [`AppEvent::UploadRequest`]: crate::AppEvent::UploadRequest
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Approval rules, stored in `config.json`; all rules are off by default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadApprovalPolicy {
+    /// Accept files up to this many bytes without asking, except from
+    /// loopback clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_auto_accept_bytes: Option<u64>,
+    /// Client IPs or subnets (`192.168.1.0/24`, `fd00::/8`) accepted
+    /// without asking; loopback clients never are
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_networks: Vec<String>,
+    /// After the host accepts one upload from a client, accept its further
+    /// uploads until the server stops
+    #[serde(default)]
+    pub remember_approved_clients: bool,
+}
+
+/// Why an upload was accepted without asking
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutoApproval {
+    SmallFile,
+    TrustedNetwork(String),
+    ApprovedBefore,
+}
+
+impl fmt::Display for AutoApproval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoApproval::SmallFile => write!(f, "below the size threshold"),
+            AutoApproval::TrustedNetwork(network) => write!(f, "trusted network {}", network),
+            AutoApproval::ApprovedBefore => write!(f, "client approved earlier"),
+        }
+    }
+}
+
+impl UploadApprovalPolicy {
+    /// Whether an upload of `file_size` bytes from `client_ip` is accepted
+    /// without asking; `approved` holds the clients the host accepted
+    /// before in this server session
+    pub fn auto_approval(
+        &self,
+        client_ip: &str,
+        file_size: u64,
+        approved: &HashSet<String>,
+    ) -> Option<AutoApproval> {
+        let Ok(ip) = client_ip.parse::<IpAddr>().map(unmap) else {
+            return None;
+        };
+        if ip.is_loopback() {
+            return None;
+        }
+        if let Some(max) = self.max_auto_accept_bytes
+            && file_size <= max
+        {
+            return Some(AutoApproval::SmallFile);
+        }
+        if let Some(network) = self
+            .trusted_networks
+            .iter()
+            .find(|network| network_contains(network, ip))
+        {
+            return Some(AutoApproval::TrustedNetwork(network.clone()));
+        }
+        if self.remember_approved_clients && approved.contains(client_ip) {
+            return Some(AutoApproval::ApprovedBefore);
+        }
+        None
+    }
+
+    /// Whether to accept further uploads from `client_ip` without asking
+    /// once the host accepted one
+    pub fn remembers(&self, client_ip: &str) -> bool {
+        self.remember_approved_clients
+            && client_ip
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| !unmap(ip).is_loopback())
+    }
+}
+
+/// IPv4 clients of a dual-stack socket show up as ::ffff:a.b.c.d
+fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+/// Whether `network`, an address or `address/prefix`, contains `ip`.
+/// Malformed entries contain nothing.
+fn network_contains(network: &str, ip: IpAddr) -> bool {
+    let (address, prefix) = match network.trim().split_once('/') {
+        Some((address, prefix)) => match prefix.parse::<u32>() {
+            Ok(prefix) => (address, Some(prefix)),
+            Err(_) => return false,
+        },
+        None => (network.trim(), None),
+    };
+    let Ok(address) = address.parse::<IpAddr>() else {
+        return false;
+    };
+    match (address, unmap(ip)) {
+        (IpAddr::V4(net), IpAddr::V4(ip)) => {
+            let prefix = prefix.unwrap_or(32);
+            prefix <= 32 && mask_u32(u32::from(net), prefix) == mask_u32(u32::from(ip), prefix)
+        }
+        (IpAddr::V6(net), IpAddr::V6(ip)) => {
+            let prefix = prefix.unwrap_or(128);
+            prefix <= 128 && mask_u128(u128::from(net), prefix) == mask_u128(u128::from(ip), prefix)
+        }
+        _ => false,
+    }
+}
+
+fn mask_u32(value: u32, prefix: u32) -> u32 {
+    value.checked_shr(32 - prefix).unwrap_or(0)
+}
+
+fn mask_u128(value: u128, prefix: u32) -> u128 {
+    value.checked_shr(128 - prefix).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_approve_small_files_trusted_networks_and_known_clients() {
+        let policy = UploadApprovalPolicy {
+            max_auto_accept_bytes: Some(1024),
+            trusted_networks: vec![
+                "192.168.1.0/24".to_string(),
+                "10.0.0.7".to_string(),
+                "fd00::/8".to_string(),
+                "not a network".to_string(),
+            ],
+            remember_approved_clients: true,
+        };
+        let none = HashSet::new();
+
+        assert_eq!(
+            policy.auto_approval("172.16.0.1", 1024, &none),
+            Some(AutoApproval::SmallFile)
+        );
+        assert_eq!(
+            policy.auto_approval("192.168.1.200", 1 << 30, &none),
+            Some(AutoApproval::TrustedNetwork("192.168.1.0/24".to_string()))
+        );
+        assert!(
+            policy
+                .auto_approval("::ffff:10.0.0.7", 1 << 30, &none)
+                .is_some()
+        );
+        assert!(policy.auto_approval("fd12::1", 1 << 30, &none).is_some());
+        assert_eq!(policy.auto_approval("192.168.2.1", 1 << 30, &none), None);
+        assert_eq!(policy.auto_approval("10.0.0.8", 1 << 30, &none), None);
+
+        let approved = HashSet::from(["172.16.0.1".to_string()]);
+        assert_eq!(
+            policy.auto_approval("172.16.0.1", 1 << 30, &approved),
+            Some(AutoApproval::ApprovedBefore)
+        );
+        assert_eq!(
+            UploadApprovalPolicy::default().auto_approval("172.16.0.1", 0, &approved),
+            None
+        );
+        assert!(network_contains("0.0.0.0/0", "8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_loopback_clients_are_never_auto_approved_or_remembered() {
+        let policy = UploadApprovalPolicy {
+            max_auto_accept_bytes: Some(1024),
+            trusted_networks: vec!["127.0.0.0/8".to_string(), "::1".to_string()],
+            remember_approved_clients: true,
+        };
+        let approved = HashSet::from(["127.0.0.1".to_string(), "::1".to_string()]);
+        for client in ["127.0.0.1", "::1", "::ffff:127.0.0.1"] {
+            assert_eq!(policy.auto_approval(client, 1 << 30, &approved), None);
+            assert!(!policy.remembers(client));
+        }
+        assert_eq!(policy.auto_approval("127.0.0.1", 10, &approved), None);
+        assert!(policy.remembers("192.168.1.5"));
+        assert!(!UploadApprovalPolicy::default().remembers("192.168.1.5"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Download links for single files.
+//!
+//! The host can hand out one file without exposing anything else: each
+//! link has its own random token and is served at `/f/<token>` on the share
+//! server, next to (not under) the session token. Links can be single-use
+//! and expire; either way they are forgotten when the app exits.
+
+use super::server::generate_session_token;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Path prefix of file links on the share server
+pub const FILE_LINK_PREFIX: &str = "f";
+
+#[derive(Debug)]
+struct FileLink {
+    path: PathBuf,
+    expires_at: Option<Instant>,
+    single_use: bool,
+}
+
+impl FileLink {
+    fn expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| now >= at)
+    }
+}
+
+/// Issued file links by token
+#[derive(Debug, Default)]
+pub struct FileLinks {
+    links: Mutex<HashMap<String, FileLink>>,
+}
+
+impl FileLinks {
+    /// Issue a link to `path`; returns its token
+    pub fn issue(&self, path: PathBuf, expires_in: Option<Duration>, single_use: bool) -> String {
+        let token = generate_session_token();
+        let now = Instant::now();
+        let mut links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+        links.retain(|_, link| !link.expired(now));
+        links.insert(
+            token.clone(),
+            FileLink {
+                path,
+                expires_at: expires_in.map(|expiry| now + expiry),
+                single_use,
+            },
+        );
+        token
+    }
+
+    /// The file behind `token`, if the link is still valid. Using a
+    /// single-use link revokes it.
+    pub fn redeem(&self, token: &str) -> Option<PathBuf> {
+        let mut links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+        let link = links.get(token)?;
+        if link.expired(Instant::now()) {
+            links.remove(token);
+            return None;
+        }
+        if link.single_use {
+            return links.remove(token).map(|link| link.path);
+        }
+        Some(link.path.clone())
+    }
+
+    /// Revoke a link; returns whether it existed
+    pub fn revoke(&self, token: &str) -> bool {
+        let mut links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+        links.remove(token).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_links_are_single_use_or_expire() {
+        let links = FileLinks::default();
+        let reusable = links.issue(PathBuf::from("/tmp/a.txt"), None, false);
+        let once = links.issue(PathBuf::from("/tmp/b.txt"), None, true);
+        let expired = links.issue(PathBuf::from("/tmp/c.txt"), Some(Duration::ZERO), false);
+        assert_ne!(reusable, once);
+
+        assert_eq!(links.redeem(&reusable), Some(PathBuf::from("/tmp/a.txt")));
+        assert_eq!(links.redeem(&reusable), Some(PathBuf::from("/tmp/a.txt")));
+        assert_eq!(links.redeem(&once), Some(PathBuf::from("/tmp/b.txt")));
+        assert_eq!(links.redeem(&once), None);
+        assert_eq!(links.redeem(&expired), None);
+        assert_eq!(links.redeem("unknown"), None);
+
+        assert!(links.revoke(&reusable));
+        assert_eq!(links.redeem(&reusable), None);
+        assert!(!links.revoke(&reusable));
+    }
+}
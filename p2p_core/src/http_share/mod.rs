@@ -1,14 +1,28 @@
 //! HTTP file sharing module
 //!
-//! Browser sharing interface with WebSocket upload support.
+//! Browser sharing interface with WebSocket upload support, download links
+//! for single files, temporary shares of chosen files and an about route,
+//! plus an optional owner page for remote control.
 
+pub mod about;
+pub mod approval;
+pub mod links;
+pub mod owner;
 pub mod server;
+pub mod shares;
+pub mod text;
 pub mod tunnel;
 pub mod websocket;
 
+pub use about::{AboutInfo, BuildInfo};
+pub use approval::UploadApprovalPolicy;
+pub use links::{FILE_LINK_PREFIX, FileLinks};
+pub use owner::OwnerAccess;
 pub use server::{
-    HTTP_PORT, generate_session_token, start_default_http_server_with_websocket,
-    start_http_server_with_websocket,
+    HTTP_PORT, generate_session_token, serve_http_with_websocket,
+    start_default_http_server_with_websocket, start_http_server_with_websocket,
 };
+pub use shares::{DEFAULT_FILE_SHARE_EXPIRY, FILE_SHARE_PREFIX, FileShares};
+pub use text::{MAX_SHARED_TEXT_LEN, SharedText};
 pub use tunnel::NgrokTunnel;
 pub use websocket::{UploadState, respond_to_upload};
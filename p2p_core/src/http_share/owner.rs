@@ -0,0 +1,321 @@
+//! Owner page: a minimal web remote for headless installs.
+//!
+//! With owner mode on, the HTTP share server also serves a page under a
+//! second token that only the device owner gets. Unlike the share page,
+//! which lets visitors upload to this device, the owner page drives the
+//! node itself through [`RemoteControl`]:
+//!
+//! - `GET  /<owner token>/api/state` lists the device, LAN peers and transfers
+//! - `POST /<owner token>/api/discover` scans the LAN ([`AppCommand::StartDiscovery`])
+//! - `POST /<owner token>/api/send` sends files from this device to a peer
+//!   ([`AppCommand::SendFile`]); unpaired peers still need their code entered
+//!   on this device
+//!
+//! Files are named by their path on this device. Whoever holds the owner
+//! token can send any readable file, so it must not be shared like the
+//! upload link; a WAN share tunnel exposes it as well.
+
+use crate::remote::RemoteControl;
+use crate::state::BackendState;
+use crate::{AppCommand, new_session_id};
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{StatusCode, header},
+    middleware,
+    response::Html,
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use super::server::add_security_headers;
+
+/// Static HTML content for the owner page
+const OWNER_HTML: &str = include_str!("static/owner.html");
+
+/// Static JS content for the owner page
+const OWNER_JS: &str = include_str!("static/owner.js");
+
+/// Token and backend handle for the owner routes
+#[derive(Debug, Clone)]
+pub struct OwnerAccess {
+    pub token: String,
+    pub remote: RemoteControl,
+}
+
+#[derive(Debug, Serialize)]
+struct OwnerPeer {
+    endpoint_id: String,
+    ip: String,
+    hostname: String,
+    display_name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct OwnerTransfer {
+    file_name: String,
+    is_sending: bool,
+    progress: f32,
+}
+
+/// Response of `GET api/state`
+#[derive(Debug, Serialize)]
+struct OwnerState {
+    device_name: String,
+    receive_only: bool,
+    peers: Vec<OwnerPeer>,
+    transfers: Vec<OwnerTransfer>,
+}
+
+impl From<BackendState> for OwnerState {
+    fn from(state: BackendState) -> Self {
+        Self {
+            device_name: state.device_name,
+            receive_only: state.receive_only,
+            peers: state
+                .peers
+                .into_iter()
+                .map(|peer| OwnerPeer {
+                    endpoint_id: peer.endpoint_id,
+                    ip: peer.ip,
+                    hostname: peer.hostname,
+                    display_name: peer.display_name,
+                })
+                .collect(),
+            transfers: state
+                .transfers
+                .into_iter()
+                .map(|transfer| OwnerTransfer {
+                    file_name: transfer.file_name,
+                    is_sending: transfer.is_sending,
+                    progress: transfer.progress,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Body of `POST api/send`
+#[derive(Debug, Deserialize)]
+struct SendRequest {
+    /// IP of a discovered peer
+    ip: String,
+    /// Paths on this device
+    files: Vec<PathBuf>,
+    /// Note or tag for the receiver
+    #[serde(default)]
+    note: Option<String>,
+}
+
+type ApiError = (StatusCode, String);
+
+fn unavailable(e: anyhow::Error) -> ApiError {
+    (StatusCode::SERVICE_UNAVAILABLE, e.to_string())
+}
+
+async fn owner_handler() -> Html<&'static str> {
+    Html(OWNER_HTML)
+}
+
+async fn owner_js_handler() -> impl axum::response::IntoResponse {
+    ([(header::CONTENT_TYPE, "application/javascript")], OWNER_JS)
+}
+
+async fn state_handler(State(remote): State<RemoteControl>) -> Result<Json<OwnerState>, ApiError> {
+    let state = remote.state().await.map_err(unavailable)?;
+    Ok(Json(state.into()))
+}
+
+async fn discover_handler(State(remote): State<RemoteControl>) -> Result<StatusCode, ApiError> {
+    remote
+        .command(AppCommand::StartDiscovery)
+        .await
+        .map_err(unavailable)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn send_handler(
+    State(remote): State<RemoteControl>,
+    Json(request): Json<SendRequest>,
+) -> Result<StatusCode, ApiError> {
+    if request.files.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No files given".to_string()));
+    }
+    if let Some(missing) = request.files.iter().find(|path| !path.is_file()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Not a file: {}", missing.display()),
+        ));
+    }
+
+    let state = remote.state().await.map_err(unavailable)?;
+    let Some(peer) = state.peers.into_iter().find(|peer| peer.ip == request.ip) else {
+        return Err((StatusCode::NOT_FOUND, format!("No peer at {}", request.ip)));
+    };
+
+    remote
+        .command(AppCommand::SendFile {
+            session_id: new_session_id(),
+            target_ip: peer.ip,
+            target_endpoint_id: peer.endpoint_id,
+            target_peer_name: peer.hostname,
+            files: request.files,
+            note: request.note,
+        })
+        .await
+        .map_err(|e| (StatusCode::CONFLICT, e.to_string()))?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Routes of the owner page, to be merged into the share server's router
+pub fn create_owner_router(access: OwnerAccess) -> Router {
+    let page_path = format!("/{}", access.token);
+    let api_path = |route: &str| format!("/{}/api/{}", access.token, route);
+
+    Router::new()
+        .route(&page_path, get(owner_handler))
+        .route(&api_path("state"), get(state_handler))
+        .route(&api_path("discover"), post(discover_handler))
+        .route(&api_path("send"), post(send_handler))
+        .route("/owner.js", get(owner_js_handler))
+        .layer(middleware::from_fn(add_security_headers))
+        .with_state(access.remote)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::RemoteRequest;
+    use crate::state::PeerSnapshot;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    /// Answer remote requests like a backend with one peer, reporting the
+    /// commands it receives
+    fn fake_backend() -> (RemoteControl, mpsc::UnboundedReceiver<AppCommand>) {
+        let (remote, mut requests) = RemoteControl::channel(4);
+        let (commands_tx, commands) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                match request {
+                    RemoteRequest::State(reply) => {
+                        let _ = reply.send(BackendState {
+                            device_name: "nas".to_string(),
+                            peers: vec![PeerSnapshot {
+                                endpoint_id: "peer-id".to_string(),
+                                ip: "10.0.0.2".to_string(),
+                                port: 9000,
+                                hostname: "Laptop".to_string(),
+                                display_name: "Laptop".to_string(),
+                                capabilities: Default::default(),
+                                version: None,
+                                rtt_ms: None,
+                            }],
+                            ..Default::default()
+                        });
+                    }
+                    RemoteRequest::Command(command, reply) => {
+                        let _ = commands_tx.send(command);
+                        let _ = reply.send(Ok(()));
+                    }
+                }
+            }
+        });
+        (remote, commands)
+    }
+
+    fn send_request(token: &str, body: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/{}/api/send", token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_owner_api_bridges_to_backend_commands() {
+        let (remote, mut commands) = fake_backend();
+        let router = create_owner_router(OwnerAccess {
+            token: "owner_token".to_string(),
+            remote,
+        });
+        let file = std::env::temp_dir().join(format!("owner_{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&file, b"remote").unwrap();
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/owner_token/api/state")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let state: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(state["device_name"], "nas");
+        assert_eq!(state["peers"][0]["hostname"], "Laptop");
+
+        let response = router
+            .clone()
+            .oneshot(send_request(
+                "owner_token",
+                serde_json::json!({ "ip": "10.0.0.2", "files": [file] }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        match commands.recv().await {
+            Some(AppCommand::SendFile {
+                target_endpoint_id,
+                target_peer_name,
+                files,
+                ..
+            }) => {
+                assert_eq!(target_endpoint_id, "peer-id");
+                assert_eq!(target_peer_name, "Laptop");
+                assert_eq!(files, vec![file.clone()]);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        // Unknown peers, missing files and other tokens are refused
+        let response = router
+            .clone()
+            .oneshot(send_request(
+                "owner_token",
+                serde_json::json!({ "ip": "10.0.0.9", "files": [file] }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = router
+            .clone()
+            .oneshot(send_request(
+                "owner_token",
+                serde_json::json!({ "ip": "10.0.0.2", "files": ["/no/such/file"] }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = router
+            .oneshot(send_request(
+                "share_token",
+                serde_json::json!({ "ip": "10.0.0.2", "files": [file] }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(commands.try_recv().is_err());
+
+        let _ = std::fs::remove_file(&file);
+    }
+}
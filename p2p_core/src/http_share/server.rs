@@ -2,25 +2,35 @@
 //!
 //! LAN HTTP server with session tokens and WebSocket uploads.
 
-use crate::AppEvent;
 use crate::config;
+use crate::{AppEvent, EventCategory, LogLevel};
 use anyhow::Result;
 use axum::{
-    Router,
-    extract::{Request, ws::WebSocketUpgrade},
-    http::{HeaderValue, header},
+    Json, Router,
+    body::Body,
+    extract::{Path, Request, ws::WebSocketUpgrade},
+    http::{HeaderValue, Method, StatusCode, header},
     middleware::{self, Next},
-    response::{Html, Response},
+    response::{Html, IntoResponse, Response},
     routing::get,
 };
+use serde::Deserialize;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, atomic::AtomicUsize};
 use tokio::net::TcpListener;
 use tokio::sync::mpsc;
+use tokio_util::io::ReaderStream;
 use tokio_util::sync::CancellationToken;
+use tower_http::services::ServeFile;
 use uuid::Uuid;
 
+use super::about::{self, AboutInfo};
+use super::approval::UploadApprovalPolicy;
+use super::links::FILE_LINK_PREFIX;
+use super::owner::{self, OwnerAccess};
+use super::shares::FILE_SHARE_PREFIX;
+use super::text::SharedTextEntry;
 use super::websocket::{self, UploadState, WebSocketState};
 
 /// Default HTTP port for file sharing
@@ -73,7 +83,7 @@ fn sanitize_host(host: &str) -> String {
 }
 
 /// Middleware to add security headers
-async fn add_security_headers(req: Request, next: Next) -> Response {
+pub(super) async fn add_security_headers(req: Request, next: Next) -> Response {
     // Extract and sanitize Host header for dynamic CSP
     let host = req
         .headers()
@@ -133,6 +143,214 @@ pub fn generate_session_token() -> String {
     Uuid::new_v4().simple().to_string()
 }
 
+/// Body of `POST /<token>/text`
+#[derive(Debug, Deserialize)]
+struct TextMessage {
+    text: String,
+}
+
+/// Handler for reading the shared text box
+async fn get_text_handler(
+    axum::extract::State(state): axum::extract::State<Arc<WebSocketState>>,
+) -> Json<Option<SharedTextEntry>> {
+    Json(state.upload_state.shared_text.get())
+}
+
+/// Handler for text pasted into the page; shown on the host
+async fn post_text_handler(
+    axum::extract::State(state): axum::extract::State<Arc<WebSocketState>>,
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<SocketAddr>,
+    Json(message): Json<TextMessage>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let from_ip = addr.ip().to_string();
+    state
+        .upload_state
+        .shared_text
+        .set(message.text.clone(), &from_ip)
+        .map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e))?;
+    let _ = state
+        .event_tx
+        .send(AppEvent::TextReceived {
+            text: message.text,
+            from_ip,
+        })
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Percent-encode a file name for `Content-Disposition: filename*`
+fn encode_file_name(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Handler for single-file download links
+async fn file_link_handler(
+    axum::extract::State(state): axum::extract::State<Arc<WebSocketState>>,
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<SocketAddr>,
+    Path(link): Path<String>,
+) -> Response {
+    let Some(path) = state.upload_state.file_links.redeem(&link) else {
+        return not_found_handler().await.into_response();
+    };
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            tracing::warn!("File link for {} is broken: {}", path.display(), e);
+            return not_found_handler().await.into_response();
+        }
+    };
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "download".to_string());
+
+    let _ = state
+        .event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Http,
+            format!("{} downloaded {} from a file link", addr.ip(), file_name),
+        ))
+        .await;
+
+    let size = file.metadata().await.map(|meta| meta.len()).ok();
+    let mut response = Body::from_stream(ReaderStream::new(file)).into_response();
+    let headers = response.headers_mut();
+    if let Some(size) = size {
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+    }
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    set_attachment(headers, &file_name);
+    response
+}
+
+/// Have the browser save the response as `file_name`
+fn set_attachment(headers: &mut header::HeaderMap, file_name: &str) {
+    if let Ok(value) = HeaderValue::from_str(&format!(
+        "attachment; filename*=UTF-8''{}",
+        encode_file_name(file_name)
+    )) {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+}
+
+/// Escape text for an HTML page
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Handler listing the files of a share
+async fn file_share_handler(
+    axum::extract::State(state): axum::extract::State<Arc<WebSocketState>>,
+    Path(share): Path<String>,
+) -> Response {
+    let Some(files) = state.upload_state.file_shares.files(&share) else {
+        return not_found_handler().await.into_response();
+    };
+    let mut items = String::new();
+    for (index, file) in files.iter().enumerate() {
+        let size = std::fs::metadata(&file.path)
+            .map(|meta| crate::units::format_size(meta.len()))
+            .unwrap_or_default();
+        items.push_str(&format!(
+            "<li><a href=\"/{}/{}/{}\" download>{}</a> {}</li>\n",
+            FILE_SHARE_PREFIX,
+            share,
+            index,
+            escape_html(&file.name),
+            size
+        ));
+    }
+    Html(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>Shared files</title><link rel=\"stylesheet\" href=\"/style.css\"></head>\
+         <body><h1>Shared files</h1><ul>\n{}</ul></body></html>",
+        items
+    ))
+    .into_response()
+}
+
+/// Whether `response` carries the whole file: a plain 200, or a range from
+/// its first byte to its last. Probes and resumed downloads carry less.
+fn is_whole_file(response: &Response) -> bool {
+    match response.status() {
+        StatusCode::OK => true,
+        StatusCode::PARTIAL_CONTENT => response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .and_then(|range| range.strip_prefix("bytes 0-"))
+            .and_then(|range| range.split_once('/'))
+            .and_then(|(last, len)| Some((last.parse::<u64>().ok()?, len.parse::<u64>().ok()?)))
+            .is_some_and(|(last, len)| last + 1 == len),
+        _ => false,
+    }
+}
+
+/// Handler for one file of a share; answers range requests
+async fn file_share_download_handler(
+    axum::extract::State(state): axum::extract::State<Arc<WebSocketState>>,
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<SocketAddr>,
+    Path((share, index)): Path<(String, usize)>,
+    request: Request,
+) -> Response {
+    let Some(file) = state.upload_state.file_shares.file(&share, index) else {
+        return not_found_handler().await.into_response();
+    };
+    let head = request.method() == Method::HEAD;
+    let mut response = match ServeFile::new(&file.path).try_call(request).await {
+        Ok(response) => response.map(Body::new),
+        Err(e) => {
+            tracing::warn!("Shared file {} is broken: {}", file.path.display(), e);
+            return not_found_handler().await.into_response();
+        }
+    };
+    if response.status() == StatusCode::NOT_FOUND {
+        return not_found_handler().await.into_response();
+    }
+    if !head
+        && is_whole_file(&response)
+        && let Some(downloads) = state
+            .upload_state
+            .file_shares
+            .record_download(&share, index)
+    {
+        let _ = state
+            .event_tx
+            .send(AppEvent::FileShareDownloaded {
+                share_id: share,
+                file_name: file.name.clone(),
+                downloads,
+                from_ip: addr.ip().to_string(),
+            })
+            .await;
+    }
+    set_attachment(response.headers_mut(), &file.name);
+    response
+}
+
 /// WebSocket upgrade handler
 async fn ws_upgrade_handler(
     ws: WebSocketUpgrade,
@@ -151,23 +369,65 @@ pub fn create_router_with_websocket(
     event_tx: mpsc::Sender<AppEvent>,
     upload_state: Arc<UploadState>,
     download_dir: PathBuf,
+) -> Router {
+    create_upload_router(
+        token,
+        event_tx,
+        upload_state,
+        download_dir,
+        false,
+        UploadApprovalPolicy::default(),
+        CancellationToken::new(),
+    )
+}
+
+/// [`create_router_with_websocket`], optionally saving each client's
+/// uploads in its own subfolder of `download_dir` and accepting uploads
+/// that match `approval` without asking. Connected clients are notified
+/// when `shutdown` is cancelled.
+pub fn create_upload_router(
+    token: &str,
+    event_tx: mpsc::Sender<AppEvent>,
+    upload_state: Arc<UploadState>,
+    download_dir: PathBuf,
+    per_peer_folders: bool,
+    approval: UploadApprovalPolicy,
+    shutdown: CancellationToken,
 ) -> Router {
     // Create shared WebSocket state
     let ws_state = Arc::new(WebSocketState {
         event_tx,
         upload_state,
         download_dir,
+        per_peer_folders,
         connection_count: AtomicUsize::new(0),
         ip_counts: std::sync::Mutex::new(std::collections::HashMap::new()),
+        shutdown,
+        approval,
+        approved_clients: std::sync::Mutex::new(std::collections::HashSet::new()),
     });
 
     // Routes
     let index_path = format!("/{}", token);
     let ws_path = format!("/{}/ws", token);
+    let text_path = format!("/{}/text", token);
 
     Router::new()
         .route(&index_path, get(index_handler))
         .route(&ws_path, get(ws_upgrade_handler))
+        .route(&text_path, get(get_text_handler).post(post_text_handler))
+        .route(
+            &format!("/{}/{{link}}", FILE_LINK_PREFIX),
+            get(file_link_handler),
+        )
+        .route(
+            &format!("/{}/{{share}}", FILE_SHARE_PREFIX),
+            get(file_share_handler),
+        )
+        .route(
+            &format!("/{}/{{share}}/{{index}}", FILE_SHARE_PREFIX),
+            get(file_share_download_handler),
+        )
         .route("/app.js", get(js_handler))
         .route("/style.css", get(css_handler))
         .fallback(not_found_handler)
@@ -175,19 +435,71 @@ pub fn create_router_with_websocket(
         .with_state(ws_state)
 }
 
-/// Start the HTTP server with WebSocket support
+/// Start the HTTP server with WebSocket support and `about` this device,
+/// and the owner page when `owner` is given
+#[allow(clippy::too_many_arguments)]
 pub async fn start_http_server_with_websocket(
     addr: SocketAddr,
     token: &str,
     event_tx: mpsc::Sender<AppEvent>,
     upload_state: Arc<UploadState>,
+    per_peer_folders: bool,
+    approval: UploadApprovalPolicy,
+    about: AboutInfo,
+    owner: Option<OwnerAccess>,
     cancel_token: Option<CancellationToken>,
 ) -> Result<()> {
-    let download_dir = config::get_download_dir();
-    let router = create_router_with_websocket(token, event_tx, upload_state, download_dir);
     let listener = TcpListener::bind(addr).await?;
+    serve_http_with_websocket(
+        listener,
+        token,
+        event_tx,
+        upload_state,
+        per_peer_folders,
+        approval,
+        about,
+        owner,
+        cancel_token,
+    )
+    .await
+}
 
-    tracing::info!("HTTP server starting on http://{}/{}", addr, token);
+/// Serve the HTTP server of [`start_http_server_with_websocket`] on a
+/// listener already bound, so that a caller learns of a taken port before
+/// the server runs in the background
+#[allow(clippy::too_many_arguments)]
+pub async fn serve_http_with_websocket(
+    listener: TcpListener,
+    token: &str,
+    event_tx: mpsc::Sender<AppEvent>,
+    upload_state: Arc<UploadState>,
+    per_peer_folders: bool,
+    approval: UploadApprovalPolicy,
+    about: AboutInfo,
+    owner: Option<OwnerAccess>,
+    cancel_token: Option<CancellationToken>,
+) -> Result<()> {
+    let download_dir = config::get_download_dir();
+    let router = create_upload_router(
+        token,
+        event_tx,
+        upload_state,
+        download_dir,
+        per_peer_folders,
+        approval,
+        cancel_token.clone().unwrap_or_default(),
+    )
+    .merge(about::create_about_router(token, about));
+    let router = match owner {
+        Some(access) => router.merge(owner::create_owner_router(access)),
+        None => router,
+    };
+
+    tracing::info!(
+        "HTTP server starting on http://{}/{}",
+        listener.local_addr()?,
+        token
+    );
 
     if let Some(ct) = cancel_token {
         axum::serve(
@@ -211,14 +523,30 @@ pub async fn start_http_server_with_websocket(
 }
 
 /// Start the HTTP server on the default port with WebSocket support
+#[allow(clippy::too_many_arguments)]
 pub async fn start_default_http_server_with_websocket(
     token: &str,
     event_tx: mpsc::Sender<AppEvent>,
     upload_state: Arc<UploadState>,
+    per_peer_folders: bool,
+    approval: UploadApprovalPolicy,
+    about: AboutInfo,
+    owner: Option<OwnerAccess>,
     cancel_token: Option<CancellationToken>,
 ) -> Result<()> {
     let addr: SocketAddr = format!("0.0.0.0:{}", HTTP_PORT).parse()?;
-    start_http_server_with_websocket(addr, token, event_tx, upload_state, cancel_token).await
+    start_http_server_with_websocket(
+        addr,
+        token,
+        event_tx,
+        upload_state,
+        per_peer_folders,
+        approval,
+        about,
+        owner,
+        cancel_token,
+    )
+    .await
 }
 
 // Keep old function for backward compatibility (deprecated)
@@ -392,12 +720,10 @@ mod tests {
             // We use a timeout because accepted clients might not receive anything until user action
             if let Ok(Some(Ok(msg))) =
                 tokio::time::timeout(tokio::time::Duration::from_millis(100), read.next()).await
+                && let tokio_tungstenite::tungstenite::Message::Text(text) = msg
+                && text.contains("Too many pending uploads")
             {
-                if let tokio_tungstenite::tungstenite::Message::Text(text) = msg {
-                    if text.contains("Too many pending uploads") {
-                        rejected_count += 1;
-                    }
-                }
+                rejected_count += 1;
             }
         }
 
@@ -694,6 +1020,384 @@ mod tests {
             csp
         );
     }
+
+    #[tokio::test]
+    async fn test_client_hears_about_approval_verification_and_shutdown() {
+        use crate::http_share::websocket::{ClientMessage, ServerMessage, respond_to_upload};
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::connect_async;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let token = "test_token_push";
+        let (tx, mut rx) = mpsc::channel(100);
+        let upload_state = Arc::new(UploadState::default());
+        let download_dir = std::env::temp_dir().join(format!("p2p_push_{}", Uuid::new_v4()));
+        let shutdown = CancellationToken::new();
+        let router = create_upload_router(
+            token,
+            tx,
+            upload_state.clone(),
+            download_dir.clone(),
+            false,
+            UploadApprovalPolicy::default(),
+            shutdown.clone(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+        let ws_url = format!("ws://127.0.0.1:{}/{}/ws", port, token);
+
+        async fn next_message(
+            read: &mut (
+                     impl StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+                     + Unpin
+                 ),
+        ) -> ServerMessage {
+            loop {
+                let msg = tokio::time::timeout(tokio::time::Duration::from_secs(5), read.next())
+                    .await
+                    .expect("Timeout waiting for server message")
+                    .expect("Socket closed")
+                    .unwrap();
+                if let Message::Text(text) = msg {
+                    return serde_json::from_str(&text).unwrap();
+                }
+            }
+        }
+        let file_info = |file_name: &str, file_size: u64| {
+            Message::Text(
+                serde_json::to_string(&ClientMessage::FileInfo {
+                    file_name: file_name.to_string(),
+                    file_size,
+                })
+                .unwrap()
+                .into(),
+            )
+        };
+
+        // An accepted upload is verified before it completes
+        let (ws_stream, _) = connect_async(&ws_url).await.unwrap();
+        let (mut write, mut read) = ws_stream.split();
+        write.send(file_info("pushed.txt", 5)).await.unwrap();
+        assert!(matches!(
+            next_message(&mut read).await,
+            ServerMessage::AwaitingApproval { seconds_left } if seconds_left > 0
+        ));
+        let request_id = loop {
+            if let Some(AppEvent::UploadRequest { request_id, .. }) = rx.recv().await {
+                break request_id;
+            }
+        };
+        respond_to_upload(&upload_state, &request_id, true).await;
+        assert!(matches!(
+            next_message(&mut read).await,
+            ServerMessage::Accepted { .. }
+        ));
+        write
+            .send(Message::Binary(b"hello".to_vec().into()))
+            .await
+            .unwrap();
+        let verification = loop {
+            match next_message(&mut read).await {
+                ServerMessage::Progress { .. } => continue,
+                other => break other,
+            }
+        };
+        match verification {
+            ServerMessage::Verification {
+                verified,
+                received_bytes,
+                hash,
+            } => {
+                assert!(verified);
+                assert_eq!(received_bytes, 5);
+                assert_eq!(hash, blake3::hash(b"hello").to_hex().to_string());
+            }
+            other => panic!("Expected verification, got {:?}", other),
+        }
+        assert!(matches!(
+            next_message(&mut read).await,
+            ServerMessage::Complete
+        ));
+
+        // A client waiting for approval is told when the server stops
+        let (ws_stream, _) = connect_async(&ws_url).await.unwrap();
+        let (mut write, mut read) = ws_stream.split();
+        write.send(file_info("waiting.txt", 5)).await.unwrap();
+        assert!(matches!(
+            next_message(&mut read).await,
+            ServerMessage::AwaitingApproval { .. }
+        ));
+        shutdown.cancel();
+        assert!(matches!(
+            next_message(&mut read).await,
+            ServerMessage::ShuttingDown
+        ));
+
+        let _ = std::fs::remove_dir_all(&download_dir);
+    }
+
+    #[tokio::test]
+    async fn test_small_upload_from_loopback_is_still_asked() {
+        use crate::http_share::websocket::ClientMessage;
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::connect_async;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let token = "test_token_auto";
+        let (tx, mut rx) = mpsc::channel(100);
+        let download_dir = std::env::temp_dir().join(format!("p2p_auto_{}", Uuid::new_v4()));
+        let router = create_upload_router(
+            token,
+            tx,
+            Arc::new(UploadState::default()),
+            download_dir.clone(),
+            false,
+            UploadApprovalPolicy {
+                max_auto_accept_bytes: Some(10),
+                ..Default::default()
+            },
+            CancellationToken::new(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+
+        let ws_url = format!("ws://127.0.0.1:{}/{}/ws", port, token);
+        let (ws_stream, _) = connect_async(&ws_url).await.unwrap();
+        let (mut write, _read) = ws_stream.split();
+        let msg = ClientMessage::FileInfo {
+            file_name: "note.txt".to_string(),
+            file_size: 5,
+        };
+        write
+            .send(Message::Text(serde_json::to_string(&msg).unwrap().into()))
+            .await
+            .unwrap();
+
+        // The size threshold does not apply to clients on this device
+        match tokio::time::timeout(tokio::time::Duration::from_secs(2), rx.recv())
+            .await
+            .unwrap()
+        {
+            Some(AppEvent::UploadRequest {
+                file_name, from_ip, ..
+            }) => {
+                assert_eq!(file_name, "note.txt");
+                assert_eq!(from_ip, "127.0.0.1");
+            }
+            other => panic!("Expected UploadRequest, got {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(&download_dir);
+    }
+
+    #[tokio::test]
+    async fn test_text_box_is_shared_with_the_host() {
+        use crate::http_share::MAX_SHARED_TEXT_LEN;
+        use axum::extract::ConnectInfo;
+
+        let token = "test_token_text";
+        let (tx, mut rx) = mpsc::channel(100);
+        let router =
+            create_router_with_websocket(token, tx, Arc::new(UploadState::default()), ".".into());
+        let post = |text: String| {
+            Request::builder()
+                .method("POST")
+                .uri(format!("/{}/text", token))
+                .header("content-type", "application/json")
+                .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 2], 5000))))
+                .body(Body::from(serde_json::json!({ "text": text }).to_string()))
+                .unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(post("https://example.com".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        match rx.recv().await {
+            Some(AppEvent::TextReceived { text, from_ip }) => {
+                assert_eq!(text, "https://example.com");
+                assert_eq!(from_ip, "10.0.0.2");
+            }
+            other => panic!("Expected TextReceived, got {:?}", other),
+        }
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/{}/text", token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let entry: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(entry["text"], "https://example.com");
+        assert_eq!(entry["from"], "10.0.0.2");
+
+        let response = router
+            .oneshot(post("x".repeat(MAX_SHARED_TEXT_LEN + 1)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_single_use_file_link_serves_the_file_once() {
+        use axum::extract::ConnectInfo;
+
+        let file = std::env::temp_dir().join(format!("link {}.txt", Uuid::new_v4()));
+        std::fs::write(&file, b"just this file").unwrap();
+        let upload_state = Arc::new(UploadState::default());
+        let link = upload_state.file_links.issue(file.clone(), None, true);
+        let (tx, mut rx) = mpsc::channel(100);
+        let router = create_router_with_websocket("test_token_link", tx, upload_state, ".".into());
+        let get = || {
+            Request::builder()
+                .uri(format!("/{}/{}", FILE_LINK_PREFIX, link))
+                .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 2], 5000))))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router.clone().oneshot(get()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let disposition = response.headers()[header::CONTENT_DISPOSITION]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(disposition.starts_with("attachment; filename*=UTF-8''link%20"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"just this file");
+        assert!(matches!(rx.recv().await, Some(AppEvent::Log { .. })));
+
+        let response = router.oneshot(get()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn test_file_share_serves_ranges_and_counts_downloads() {
+        use axum::extract::ConnectInfo;
+
+        let file = std::env::temp_dir().join(format!("share<{}>.txt", Uuid::new_v4()));
+        std::fs::write(&file, b"0123456789").unwrap();
+        let upload_state = Arc::new(UploadState::default());
+        let share = upload_state
+            .file_shares
+            .create(vec![file.clone()], std::time::Duration::from_secs(60));
+        let (tx, mut rx) = mpsc::channel(100);
+        let router =
+            create_router_with_websocket("test_token_share", tx, upload_state.clone(), ".".into());
+        let get = |path: &str, range: Option<&str>| {
+            let mut request = Request::builder()
+                .uri(format!("/{}/{}{}", FILE_SHARE_PREFIX, share, path))
+                .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 2], 5000))));
+            if let Some(range) = range {
+                request = request.header(header::RANGE, range);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        // The listing escapes the name
+        let response = router.clone().oneshot(get("", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains("share&lt;"));
+        assert!(page.contains(&format!("/{}/{}/0", FILE_SHARE_PREFIX, share)));
+
+        let response = router.clone().oneshot(get("/0", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            response.headers()[header::CONTENT_DISPOSITION]
+                .to_str()
+                .unwrap()
+                .starts_with("attachment; filename*=UTF-8''share%3C")
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"0123456789");
+        assert!(matches!(
+            rx.recv().await,
+            Some(AppEvent::FileShareDownloaded { downloads: 1, .. })
+        ));
+
+        // Resuming is not another download
+        let response = router
+            .clone()
+            .oneshot(get("/0", Some("bytes=4-")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"456789");
+        assert!(rx.try_recv().is_err());
+        assert_eq!(
+            upload_state.file_shares.file(&share, 0).unwrap().downloads,
+            1
+        );
+
+        // Neither is a probe for the first byte
+        let response = router
+            .clone()
+            .oneshot(get("/0", Some("bytes=0-0")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(rx.try_recv().is_err());
+
+        // A range over the whole file is
+        let response = router
+            .clone()
+            .oneshot(get("/0", Some("bytes=0-")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(matches!(
+            rx.recv().await,
+            Some(AppEvent::FileShareDownloaded { downloads: 2, .. })
+        ));
+
+        let response = router.clone().oneshot(get("/1", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        assert!(upload_state.file_shares.revoke(&share));
+        let response = router.clone().oneshot(get("/0", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = router.oneshot(get("", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let _ = std::fs::remove_file(&file);
+    }
 }
 
 #[cfg(test)]
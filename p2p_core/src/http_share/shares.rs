@@ -0,0 +1,136 @@
+//! Temporary shares of a few chosen files.
+//!
+//! Rather than the whole share page, the host can hand out a handful of
+//! files under one random token: `/s/<token>` lists them and
+//! `/s/<token>/<index>` downloads one, with range requests so browsers and
+//! download managers can resume. Every share expires, counts the downloads
+//! of each file and can be revoked early; all are forgotten when the app
+//! exits.
+
+use super::server::generate_session_token;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Path prefix of file shares on the share server
+pub const FILE_SHARE_PREFIX: &str = "s";
+
+/// Lifetime of a share when none is given
+pub const DEFAULT_FILE_SHARE_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// One file of a share, as listed to the downloader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedFile {
+    pub path: PathBuf,
+    pub name: String,
+    /// Downloads started so far; resumed ranges do not count again
+    pub downloads: u32,
+}
+
+#[derive(Debug)]
+struct FileShare {
+    files: Vec<SharedFile>,
+    expires_at: Instant,
+}
+
+/// Issued file shares by token
+#[derive(Debug, Default)]
+pub struct FileShares {
+    shares: Mutex<HashMap<String, FileShare>>,
+}
+
+impl FileShares {
+    /// Share `paths` until `expires_in` has passed; returns the token
+    pub fn create(&self, paths: Vec<PathBuf>, expires_in: Duration) -> String {
+        let token = generate_session_token();
+        let now = Instant::now();
+        let files = paths
+            .into_iter()
+            .map(|path| SharedFile {
+                name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "download".to_string()),
+                path,
+                downloads: 0,
+            })
+            .collect();
+        let mut shares = self.shares.lock().unwrap_or_else(|e| e.into_inner());
+        shares.retain(|_, share| now < share.expires_at);
+        shares.insert(
+            token.clone(),
+            FileShare {
+                files,
+                expires_at: now + expires_in,
+            },
+        );
+        token
+    }
+
+    /// Files of the share `token`, if it is still valid
+    pub fn files(&self, token: &str) -> Option<Vec<SharedFile>> {
+        let mut shares = self.shares.lock().unwrap_or_else(|e| e.into_inner());
+        let share = shares.get(token)?;
+        if Instant::now() >= share.expires_at {
+            shares.remove(token);
+            return None;
+        }
+        Some(share.files.clone())
+    }
+
+    /// File `index` of the share `token`, if the share is still valid
+    pub fn file(&self, token: &str, index: usize) -> Option<SharedFile> {
+        self.files(token)?.into_iter().nth(index)
+    }
+
+    /// Count a download of file `index`; returns the new count
+    pub fn record_download(&self, token: &str, index: usize) -> Option<u32> {
+        let mut shares = self.shares.lock().unwrap_or_else(|e| e.into_inner());
+        let file = shares.get_mut(token)?.files.get_mut(index)?;
+        file.downloads += 1;
+        Some(file.downloads)
+    }
+
+    /// Revoke a share; returns whether it existed
+    pub fn revoke(&self, token: &str) -> bool {
+        let mut shares = self.shares.lock().unwrap_or_else(|e| e.into_inner());
+        shares.remove(token).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shares_count_expire_and_revoke() {
+        let shares = FileShares::default();
+        let token = shares.create(
+            vec![PathBuf::from("/tmp/a.txt"), PathBuf::from("/tmp/b.txt")],
+            Duration::from_secs(60),
+        );
+        let expired = shares.create(vec![PathBuf::from("/tmp/c.txt")], Duration::ZERO);
+
+        let files = shares.files(&token).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].name, "b.txt");
+        assert_eq!(
+            shares.file(&token, 0).unwrap().path,
+            PathBuf::from("/tmp/a.txt")
+        );
+        assert!(shares.file(&token, 2).is_none());
+        assert!(shares.files(&expired).is_none());
+        assert!(shares.files("unknown").is_none());
+
+        assert_eq!(shares.record_download(&token, 1), Some(1));
+        assert_eq!(shares.record_download(&token, 1), Some(2));
+        assert_eq!(shares.file(&token, 1).unwrap().downloads, 2);
+        assert_eq!(shares.file(&token, 0).unwrap().downloads, 0);
+
+        assert!(shares.revoke(&token));
+        assert!(shares.files(&token).is_none());
+        assert_eq!(shares.record_download(&token, 1), None);
+        assert!(!shares.revoke(&token));
+    }
+}
@@ -12,7 +12,11 @@ const els = {
     progressText: document.getElementById('progressText'),
     statusText: document.getElementById('statusText'),
     logContainer: document.getElementById('logContainer'),
-    toggleLogBtn: document.getElementById('toggleLogBtn')
+    toggleLogBtn: document.getElementById('toggleLogBtn'),
+    sharedText: document.getElementById('sharedText'),
+    shareTextBtn: document.getElementById('shareTextBtn'),
+    copyTextBtn: document.getElementById('copyTextBtn'),
+    sharedTextInfo: document.getElementById('sharedTextInfo')
 };
 
 let selectedFile = null;
@@ -207,6 +211,9 @@ function cleanupAfterTransfer() {
 
 function handleServerMessage(msg) {
     switch (msg.type) {
+        case 'awaiting_approval':
+            updateStatus(`Please approve on PC... (${msg.seconds_left}s)`, "--text-primary");
+            break;
         case 'accepted':
             updateStatus("Uploading...", "--accent");
             uploadFileChunks();
@@ -221,12 +228,23 @@ function handleServerMessage(msg) {
             els.progressBar.style.width = pStr;
             els.progressText.textContent = pStr;
             break;
+        case 'verification':
+            if (msg.verified) {
+                log(`Verified ${formatSize(msg.received_bytes)}, BLAKE3 ${msg.hash}`);
+            } else {
+                log(`Only ${formatSize(msg.received_bytes)} of ${formatSize(selectedFile.size)} arrived`, 'error');
+            }
+            break;
         case 'complete':
             els.progressBar.style.width = '100%';
             els.progressText.textContent = '100%';
             updateStatus("Completed", "--success");
             ws.close();
             break;
+        case 'shutting_down':
+            updateStatus("Server stopped by host", "--error");
+            ws.close();
+            break;
         case 'error':
             updateStatus(`Error: ${msg.message}`, "--error");
             ws.close();
@@ -249,6 +267,62 @@ async function uploadFileChunks() {
     readNextChunk();
 }
 
+// --- Shared text ---
+
+const TEXT_POLL_MS = 3000;
+const textUrl = window.location.pathname.replace(/\/$/, '') + '/text';
+let lastSharedText = null;
+
+els.shareTextBtn.addEventListener('click', async () => {
+    const text = els.sharedText.value;
+    if (!text.trim()) return;
+    try {
+        const response = await fetch(textUrl, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ text })
+        });
+        if (response.ok) {
+            lastSharedText = text;
+            els.sharedTextInfo.textContent = 'Shared with the PC';
+        } else {
+            els.sharedTextInfo.textContent = await response.text();
+        }
+    } catch (e) {
+        els.sharedTextInfo.textContent = `Could not share text: ${e}`;
+    }
+});
+
+els.copyTextBtn.addEventListener('click', async () => {
+    try {
+        await navigator.clipboard.writeText(els.sharedText.value);
+    } catch (e) {
+        // The clipboard API needs HTTPS; fall back to the selection
+        els.sharedText.select();
+        document.execCommand('copy');
+    }
+    els.sharedTextInfo.textContent = 'Copied';
+});
+
+async function pollSharedText() {
+    try {
+        const response = await fetch(textUrl);
+        if (!response.ok) return;
+        const entry = await response.json();
+        // Leave the box alone while the user is typing
+        if (entry && entry.text !== lastSharedText && document.activeElement !== els.sharedText) {
+            lastSharedText = entry.text;
+            els.sharedText.value = entry.text;
+            els.sharedTextInfo.textContent = entry.from === 'host' ? 'From the PC' : `From ${entry.from}`;
+        }
+    } catch (e) {
+        // Server stopped; try again on the next tick
+    }
+}
+
+pollSharedText();
+setInterval(pollSharedText, TEXT_POLL_MS);
+
 function formatSize(bytes) {
     if (bytes === 0) return '0 B';
     const k = 1024;
@@ -0,0 +1,76 @@
+const els = {
+    deviceName: document.getElementById('deviceName'),
+    peerSelect: document.getElementById('peerSelect'),
+    discoverBtn: document.getElementById('discoverBtn'),
+    filePaths: document.getElementById('filePaths'),
+    sendBtn: document.getElementById('sendBtn'),
+    transfers: document.getElementById('transfers'),
+    statusText: document.getElementById('statusText')
+};
+
+// API routes live below the owner token in the page URL
+const api = window.location.pathname.replace(/\/$/, '') + '/api';
+const REFRESH_MS = 2000;
+
+els.discoverBtn.addEventListener('click', () => post('/discover', null, 'Scanning...'));
+els.sendBtn.addEventListener('click', send);
+
+async function post(route, body, pending) {
+    setStatus(pending);
+    try {
+        const response = await fetch(api + route, {
+            method: 'POST',
+            headers: body ? { 'Content-Type': 'application/json' } : {},
+            body: body ? JSON.stringify(body) : null
+        });
+        setStatus(response.ok ? 'Done' : await response.text());
+        refresh();
+    } catch (e) {
+        setStatus('Request failed: ' + e);
+    }
+}
+
+function send() {
+    const ip = els.peerSelect.value;
+    const files = els.filePaths.value.split('\n').map(p => p.trim()).filter(p => p);
+    if (!ip || !files.length) {
+        setStatus('Choose a peer and at least one file');
+        return;
+    }
+    post('/send', { ip, files }, 'Starting transfer...');
+}
+
+async function refresh() {
+    try {
+        const response = await fetch(api + '/state');
+        if (!response.ok) return;
+        render(await response.json());
+    } catch (e) {
+        setStatus('Device unreachable');
+    }
+}
+
+function render(state) {
+    els.deviceName.textContent = state.device_name + (state.receive_only ? ' (receive-only)' : '');
+    els.sendBtn.disabled = state.receive_only;
+
+    const selected = els.peerSelect.value;
+    els.peerSelect.replaceChildren(...state.peers.map(peer => {
+        const option = document.createElement('option');
+        option.value = peer.ip;
+        option.textContent = `${peer.display_name} (${peer.ip})`;
+        return option;
+    }));
+    if (state.peers.some(peer => peer.ip === selected)) els.peerSelect.value = selected;
+
+    els.transfers.textContent = state.transfers.length
+        ? state.transfers.map(t => `${t.is_sending ? '↑' : '↓'} ${t.file_name} ${t.progress.toFixed(0)}%`).join('\n')
+        : 'None';
+}
+
+function setStatus(text) {
+    els.statusText.textContent = text;
+}
+
+refresh();
+setInterval(refresh, REFRESH_MS);
@@ -0,0 +1,111 @@
+//! Text box shared between the host and the share page.
+//!
+//! A phone can paste a link or note into the page and it shows up on the
+//! host as [`AppEvent::TextReceived`]; the host puts text on the page with
+//! [`AppCommand::PushTextToWeb`]. Only the latest text is kept, in memory,
+//! and it is forgotten after [`SHARED_TEXT_EXPIRY`].
+//!
+//! [`AppEvent::TextReceived`]: crate::AppEvent::TextReceived
+//! [`AppCommand::PushTextToWeb`]: crate::AppCommand::PushTextToWeb
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Longest text accepted from either side, in bytes
+pub const MAX_SHARED_TEXT_LEN: usize = 64 * 1024;
+
+/// How long shared text stays on the page
+pub const SHARED_TEXT_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+/// Who shared the text on the board
+pub const FROM_HOST: &str = "host";
+
+/// The latest shared text, as the page receives it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SharedTextEntry {
+    pub text: String,
+    /// [`FROM_HOST`] or the IP address of the browser
+    pub from: String,
+    pub age_secs: u64,
+}
+
+#[derive(Debug)]
+pub struct SharedText {
+    current: Mutex<Option<(String, String, Instant)>>,
+    expiry: Duration,
+}
+
+impl Default for SharedText {
+    fn default() -> Self {
+        Self::with_expiry(SHARED_TEXT_EXPIRY)
+    }
+}
+
+impl SharedText {
+    pub fn with_expiry(expiry: Duration) -> Self {
+        Self {
+            current: Mutex::new(None),
+            expiry,
+        }
+    }
+
+    /// Replace the shared text; fails if it is longer than
+    /// [`MAX_SHARED_TEXT_LEN`]
+    pub fn set(&self, text: String, from: &str) -> Result<(), String> {
+        if text.len() > MAX_SHARED_TEXT_LEN {
+            return Err(format!(
+                "Text is too long ({} bytes, at most {})",
+                text.len(),
+                MAX_SHARED_TEXT_LEN
+            ));
+        }
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        *current = Some((text, from.to_string(), Instant::now()));
+        Ok(())
+    }
+
+    /// The shared text, unless it has expired
+    pub fn get(&self) -> Option<SharedTextEntry> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        let (text, from, shared_at) = current.as_ref()?;
+        let age = shared_at.elapsed();
+        if age >= self.expiry {
+            *current = None;
+            return None;
+        }
+        Some(SharedTextEntry {
+            text: text.clone(),
+            from: from.clone(),
+            age_secs: age.as_secs(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latest_text_is_kept_until_it_expires() {
+        let shared = SharedText::default();
+        assert_eq!(shared.get(), None);
+        shared.set("first".to_string(), FROM_HOST).unwrap();
+        shared
+            .set("https://example.com".to_string(), "10.0.0.2")
+            .unwrap();
+        let entry = shared.get().unwrap();
+        assert_eq!(entry.text, "https://example.com");
+        assert_eq!(entry.from, "10.0.0.2");
+
+        assert!(
+            shared
+                .set("x".repeat(MAX_SHARED_TEXT_LEN + 1), FROM_HOST)
+                .is_err()
+        );
+
+        let expired = SharedText::with_expiry(Duration::ZERO);
+        expired.set("gone".to_string(), FROM_HOST).unwrap();
+        assert_eq!(expired.get(), None);
+    }
+}
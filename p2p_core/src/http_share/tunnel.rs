@@ -12,11 +12,15 @@ use tokio::sync::RwLock;
 use tokio_util::sync::CancellationToken;
 use url::Url;
 
+/// Host the ngrok agent connects to, for proxy bypass rules
+pub const NGROK_HOST: &str = "connect.ngrok-agent.com";
+
 /// Shared tunnel state for async access
 pub type SharedTunnel = Arc<RwLock<Option<NgrokTunnel>>>;
 
 /// Ngrok tunnel state
 pub struct NgrokTunnel {
+    base_url: String,
     public_url: String,
     cancel_token: CancellationToken,
 }
@@ -27,19 +31,23 @@ impl NgrokTunnel {
     /// Returns the tunnel instance with the public HTTPS URL.
     /// The tunnel runs in a background task until cancelled.
     ///
-    /// Requires NGROK_AUTHTOKEN environment variable to be set.
-    pub async fn start(local_port: u16, session_token: &str) -> Result<Self> {
+    /// Requires NGROK_AUTHTOKEN environment variable to be set. With
+    /// `proxy`, the session goes through that HTTP or SOCKS5 proxy.
+    pub async fn start(local_port: u16, session_token: &str, proxy: Option<Url>) -> Result<Self> {
         // Build ngrok session (will read NGROK_AUTHTOKEN from env)
-        let session = ngrok::Session::builder()
-            .authtoken_from_env()
-            .connect()
-            .await
-            .map_err(|e| {
-                anyhow::anyhow!(
-                    "Failed to connect to ngrok: {}. Make sure NGROK_AUTHTOKEN is set.",
-                    e
-                )
-            })?;
+        let mut builder = ngrok::Session::builder();
+        builder.authtoken_from_env();
+        if let Some(proxy) = proxy {
+            builder
+                .proxy_url(proxy)
+                .map_err(|e| anyhow::anyhow!("Unsupported proxy for ngrok: {}", e))?;
+        }
+        let session = builder.connect().await.map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to connect to ngrok: {}. Make sure NGROK_AUTHTOKEN is set.",
+                e
+            )
+        })?;
 
         // Create HTTP tunnel forwarding to local server
         // Forward to the root port, as the server handles the /token logic
@@ -51,7 +59,8 @@ impl NgrokTunnel {
             .await
             .map_err(|e| anyhow::anyhow!("Failed to start ngrok tunnel: {}", e))?;
 
-        let public_url = format!("{}/{}", forwarder.url(), session_token);
+        let base_url = forwarder.url().to_string();
+        let public_url = format!("{}/{}", base_url, session_token);
         let cancel_token = CancellationToken::new();
         let cancel_clone = cancel_token.clone();
 
@@ -75,6 +84,7 @@ impl NgrokTunnel {
         });
 
         Ok(Self {
+            base_url,
             public_url,
             cancel_token,
         })
@@ -85,6 +95,11 @@ impl NgrokTunnel {
         &self.public_url
     }
 
+    /// Public URL of the server root, without the session token
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
     /// Stop the tunnel
     pub fn stop(&self) {
         self.cancel_token.cancel();
@@ -1,17 +1,22 @@
 //! WebSocket connection handler
 
 use super::messages::{
-    MAX_CONNECTIONS, MAX_CONNECTIONS_PER_IP, ServerMessage, USER_RESPONSE_TIMEOUT_SECS,
+    APPROVAL_UPDATE_INTERVAL_SECS, MAX_CONNECTIONS, MAX_CONNECTIONS_PER_IP, ServerMessage,
+    USER_RESPONSE_TIMEOUT_SECS,
 };
 use super::state::{ActiveUploadGuard, WebSocketState};
-use super::utils::{cleanup_pending, create_secure_file, validate_file_info, wait_for_file_info};
-use crate::AppEvent;
-use crate::transfer::utils::sanitize_file_name;
+use super::utils::{cleanup_pending, validate_file_info, wait_for_file_info};
+use crate::storage::{ensure_space, preallocate};
+use crate::transfer::filename::normalize_file_name;
+use crate::transfer::{HashAlgorithm, compute_stored_hash};
+use crate::units::format_size;
+use crate::{AppEvent, EventCategory, LogLevel};
 use axum::extract::ws::{Message, WebSocket};
+use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
-use tokio::{io::AsyncWriteExt, sync::oneshot};
+use tokio::sync::oneshot;
 use uuid::Uuid;
 
 /// RAII guard to decrement connection count on drop
@@ -23,7 +28,11 @@ struct ConnectionGuard {
 impl Drop for ConnectionGuard {
     fn drop(&mut self) {
         self.state.connection_count.fetch_sub(1, Ordering::SeqCst);
-        let mut counts = self.state.ip_counts.lock().unwrap_or_else(|e| e.into_inner());
+        let mut counts = self
+            .state
+            .ip_counts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
         if let Some(count) = counts.get_mut(&self.client_ip) {
             if *count > 0 {
                 *count -= 1;
@@ -42,6 +51,14 @@ const PING_INTERVAL_SECS: u64 = 5;
 /// Timeout for the initial handshake to prevent DoS (10 seconds)
 const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
 
+/// Send one message to the client; false if the socket is gone
+async fn send_message(sender: &mut SplitSink<WebSocket, Message>, message: &ServerMessage) -> bool {
+    let Ok(text) = serde_json::to_string(message) else {
+        return false;
+    };
+    sender.send(Message::Text(text.into())).await.is_ok()
+}
+
 /// Handle WebSocket connection
 pub async fn handle_socket(socket: WebSocket, state: Arc<WebSocketState>, client_ip: String) {
     let (mut sender, mut receiver) = socket.split();
@@ -186,121 +203,199 @@ pub async fn handle_socket(socket: WebSocket, state: Arc<WebSocketState>, client
         return;
     }
 
-    // Sanitize filename to prevent directory traversal
-    let file_name = sanitize_file_name(&raw_file_name);
+    // Normalize filename to prevent directory traversal and names the
+    // download directory's file system cannot store
+    let normalized = normalize_file_name(&raw_file_name, &state.download_dir);
+    for notice in normalized.notices() {
+        let _ = state
+            .event_tx
+            .send(AppEvent::log(
+                LogLevel::Warning,
+                EventCategory::Transfer,
+                notice,
+            ))
+            .await;
+    }
+    let warning = normalized.warning_notice();
+    let file_name = normalized.name;
 
     // Use full UUID entropy (128 bits) instead of 8 chars (32 bits)
     // to prevent brute-force attacks on request tokens.
     let request_id = Uuid::new_v4().simple().to_string();
 
-    // Create response channel
-    let (response_tx, response_rx) = oneshot::channel();
-    // Pin response_rx so we can poll it in a loop
-    tokio::pin!(response_rx);
+    // Rules may accept the upload without asking the host, unless its name
+    // could mislead them
+    let auto_approval = if warning.is_some() {
+        None
+    } else {
+        let approved = state
+            .approved_clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        state
+            .approval
+            .auto_approval(&client_ip, file_size, &approved)
+    };
 
-    // Store pending upload
-    if !state
-        .upload_state
-        .try_add_request(request_id.clone(), response_tx)
-        .await
-    {
-        tracing::warn!(
-            "Rejecting upload from {}: Too many pending uploads",
-            client_ip
+    let accepted = if let Some(reason) = auto_approval {
+        tracing::info!(
+            "Auto-approving upload of {} from {}: {}",
+            file_name,
+            client_ip,
+            reason
         );
-        let _ = sender
-            .send(Message::Text(
-                serde_json::to_string(&ServerMessage::Error {
-                    message: "Too many pending uploads".to_string(),
-                })
-                .unwrap_or_else(|_| {
-                    "{\"type\":\"error\",\"message\":\"Internal serialization error\"}".to_string()
-                })
-                .into(),
-            ))
+        let _ = state
+            .event_tx
+            .send(AppEvent::UploadAutoApproved {
+                request_id: request_id.clone(),
+                file_name: file_name.clone(),
+                file_size,
+                from_ip: client_ip.clone(),
+                reason: reason.to_string(),
+            })
             .await;
-        return;
-    }
+        true
+    } else {
+        // Create response channel
+        let (response_tx, response_rx) = oneshot::channel();
+        // Pin response_rx so we can poll it in a loop
+        tokio::pin!(response_rx);
+
+        // Store pending upload
+        if !state
+            .upload_state
+            .try_add_request(request_id.clone(), response_tx)
+            .await
+        {
+            tracing::warn!(
+                "Rejecting upload from {}: Too many pending uploads",
+                client_ip
+            );
+            let _ = sender
+                .send(Message::Text(
+                    serde_json::to_string(&ServerMessage::Error {
+                        message: "Too many pending uploads".to_string(),
+                    })
+                    .unwrap_or_else(|_| {
+                        "{\"type\":\"error\",\"message\":\"Internal serialization error\"}"
+                            .to_string()
+                    })
+                    .into(),
+                ))
+                .await;
+            return;
+        }
 
-    // Send upload request event to GUI
-    let _ = state
-        .event_tx
-        .send(AppEvent::UploadRequest {
-            request_id: request_id.clone(),
-            file_name: file_name.clone(),
-            file_size,
-            from_ip: client_ip.clone(),
-        })
-        .await;
+        // Send upload request event to GUI
+        let _ = state
+            .event_tx
+            .send(AppEvent::UploadRequest {
+                request_id: request_id.clone(),
+                file_name: file_name.clone(),
+                file_size,
+                from_ip: client_ip.clone(),
+                warning,
+            })
+            .await;
 
-    // Wait for user response with timeout or client disconnect
-    let accepted = loop {
-        tokio::select! {
-            // 1. User response from GUI
-            res = &mut response_rx => {
-                match res {
-                    Ok(val) => break val,
-                    Err(_) => {
-                        // Channel closed (internal error)
-                        let _ = sender
-                            .send(Message::Text(
-                                serde_json::to_string(&ServerMessage::Error {
-                                    message: "Internal error".to_string(),
-                                })
-                                .unwrap_or_else(|_| "{\"type\":\"error\",\"message\":\"Internal serialization error\"}".to_string())
-                                .into(),
-                            ))
-                            .await;
-                        cleanup_pending(&state.upload_state, &request_id).await;
-                        // Notify GUI to close popup
-                        let _ = state.event_tx.send(AppEvent::UploadRequestCancelled { request_id: request_id.clone() }).await;
-                        return;
-                    }
+        // Wait for user response with timeout or client disconnect, keeping the
+        // client informed of the time left
+        let deadline = tokio::time::Instant::now()
+            + tokio::time::Duration::from_secs(USER_RESPONSE_TIMEOUT_SECS);
+        let mut approval_updates = tokio::time::interval(tokio::time::Duration::from_secs(
+            APPROVAL_UPDATE_INTERVAL_SECS,
+        ));
+        let accepted = loop {
+            tokio::select! {
+                _ = approval_updates.tick() => {
+                    let seconds_left = deadline
+                        .saturating_duration_since(tokio::time::Instant::now())
+                        .as_secs();
+                    send_message(&mut sender, &ServerMessage::AwaitingApproval { seconds_left }).await;
                 }
-            }
-            // 2. Client disconnected (Socket closed) or sent unexpected message
-            msg = receiver.next() => {
-                 match msg {
-                    Some(Ok(Message::Close(_))) | None => {
-                        // Client disconnected
-                         cleanup_pending(&state.upload_state, &request_id).await;
-                         let _ = state.event_tx.send(AppEvent::UploadRequestCancelled { request_id: request_id.clone() }).await;
-                         return;
-                    }
-                    Some(Err(e)) => {
-                        tracing::error!("WebSocket error: {}", e);
-                        cleanup_pending(&state.upload_state, &request_id).await;
-                        let _ = state.event_tx.send(AppEvent::UploadRequestCancelled { request_id: request_id.clone() }).await;
-                        return;
-                    }
-                    _ => {
-                        // Ignore other messages (e.g. Ping/Pong) or unexpected data
-                        continue;
+                // 0. Server stopped while the host was deciding
+                _ = state.shutdown.cancelled() => {
+                    send_message(&mut sender, &ServerMessage::ShuttingDown).await;
+                    cleanup_pending(&state.upload_state, &request_id).await;
+                    let _ = state.event_tx.send(AppEvent::UploadRequestCancelled { request_id: request_id.clone() }).await;
+                    let _ = sender.send(Message::Close(None)).await;
+                    return;
+                }
+                // 1. User response from GUI
+                res = &mut response_rx => {
+                    match res {
+                        Ok(val) => break val,
+                        Err(_) => {
+                            // Channel closed (internal error)
+                            let _ = sender
+                                .send(Message::Text(
+                                    serde_json::to_string(&ServerMessage::Error {
+                                        message: "Internal error".to_string(),
+                                    })
+                                    .unwrap_or_else(|_| "{\"type\":\"error\",\"message\":\"Internal serialization error\"}".to_string())
+                                    .into(),
+                                ))
+                                .await;
+                            cleanup_pending(&state.upload_state, &request_id).await;
+                            // Notify GUI to close popup
+                            let _ = state.event_tx.send(AppEvent::UploadRequestCancelled { request_id: request_id.clone() }).await;
+                            return;
+                        }
                     }
-                 }
-            }
-            // 3. Timeout
-            _ = tokio::time::sleep(tokio::time::Duration::from_secs(USER_RESPONSE_TIMEOUT_SECS)) => {
-                let _ = sender
-                    .send(Message::Text(
-                        serde_json::to_string(&ServerMessage::Rejected {
-                            reason: "Request timed out".to_string(),
-                        })
-                        .unwrap_or_else(|_| "{\"type\":\"error\",\"message\":\"Internal serialization error\"}".to_string())
-                        .into(),
-                    ))
-                    .await;
-                cleanup_pending(&state.upload_state, &request_id).await;
-                // Notify GUI to close popup
-                let _ = state.event_tx.send(AppEvent::UploadRequestCancelled { request_id: request_id.clone() }).await;
-                return;
+                }
+                // 2. Client disconnected (Socket closed) or sent unexpected message
+                msg = receiver.next() => {
+                     match msg {
+                        Some(Ok(Message::Close(_))) | None => {
+                            // Client disconnected
+                             cleanup_pending(&state.upload_state, &request_id).await;
+                             let _ = state.event_tx.send(AppEvent::UploadRequestCancelled { request_id: request_id.clone() }).await;
+                             return;
+                        }
+                        Some(Err(e)) => {
+                            tracing::error!("WebSocket error: {}", e);
+                            cleanup_pending(&state.upload_state, &request_id).await;
+                            let _ = state.event_tx.send(AppEvent::UploadRequestCancelled { request_id: request_id.clone() }).await;
+                            return;
+                        }
+                        _ => {
+                            // Ignore other messages (e.g. Ping/Pong) or unexpected data
+                            continue;
+                        }
+                     }
+                }
+                // 3. Timeout
+                _ = tokio::time::sleep_until(deadline) => {
+                    let _ = sender
+                        .send(Message::Text(
+                            serde_json::to_string(&ServerMessage::Rejected {
+                                reason: "Request timed out".to_string(),
+                            })
+                            .unwrap_or_else(|_| "{\"type\":\"error\",\"message\":\"Internal serialization error\"}".to_string())
+                            .into(),
+                        ))
+                        .await;
+                    cleanup_pending(&state.upload_state, &request_id).await;
+                    // Notify GUI to close popup
+                    let _ = state.event_tx.send(AppEvent::UploadRequestCancelled { request_id: request_id.clone() }).await;
+                    return;
+                }
             }
+        };
+
+        // Clean up pending
+        cleanup_pending(&state.upload_state, &request_id).await;
+
+        if accepted && state.approval.remembers(&client_ip) {
+            state
+                .approved_clients
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(client_ip.clone());
         }
+        accepted
     };
 
-    // Clean up pending
-    cleanup_pending(&state.upload_state, &request_id).await;
-
     if !accepted {
         let _ = sender
             .send(Message::Text(
@@ -350,7 +445,11 @@ pub async fn handle_socket(socket: WebSocket, state: Arc<WebSocketState>, client
         .await;
 
     // Prepare download path
-    let download_dir = state.download_dir.clone();
+    let download_dir = if state.per_peer_folders {
+        crate::transfer::peer_folder(&state.download_dir, &format!("web {}", client_ip), "")
+    } else {
+        state.download_dir.clone()
+    };
     if let Err(e) = crate::config::create_secure_dir_all_async(&download_dir).await {
         tracing::error!("Failed to create download dir: {}", e);
         let _ = sender
@@ -367,8 +466,21 @@ pub async fn handle_socket(socket: WebSocket, state: Arc<WebSocketState>, client
         return;
     }
 
+    let storage = state.upload_state.storage.as_ref();
+    if let Err(e) = ensure_space(storage, &download_dir, file_size).await {
+        tracing::warn!("Refused upload of {}: {}", file_name, e);
+        send_message(
+            &mut sender,
+            &ServerMessage::Error {
+                message: e.to_string(),
+            },
+        )
+        .await;
+        return;
+    }
+
     let file_path = download_dir.join(&file_name);
-    let mut file = match create_secure_file(&file_path).await {
+    let mut file = match storage.create(&file_path).await {
         Ok(f) => f,
         Err(e) => {
             tracing::error!("Failed to create secure file: {}", e);
@@ -387,6 +499,19 @@ pub async fn handle_socket(socket: WebSocket, state: Arc<WebSocketState>, client
             return;
         }
     };
+    if let Err(e) = preallocate(file.as_mut(), file_size).await {
+        tracing::warn!("Refused upload of {}: {}", file_name, e);
+        drop(file);
+        let _ = storage.remove(&file_path).await;
+        send_message(
+            &mut sender,
+            &ServerMessage::Error {
+                message: "Not enough free space".to_string(),
+            },
+        )
+        .await;
+        return;
+    }
 
     // Receive binary chunks with periodic ping to keep connection alive
     let mut received_bytes: u64 = 0;
@@ -399,6 +524,22 @@ pub async fn handle_socket(socket: WebSocket, state: Arc<WebSocketState>, client
 
     loop {
         tokio::select! {
+            // Server stopped mid-upload: the partial file is discarded
+            _ = state.shutdown.cancelled() => {
+                send_message(&mut sender, &ServerMessage::ShuttingDown).await;
+                let _ = sender.send(Message::Close(None)).await;
+                drop(file);
+                let _ = storage.remove(&file_path).await;
+                let _ = state
+                    .event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Warning,
+                        EventCategory::Http,
+                        format!("Upload of {} stopped: server shutting down", file_name),
+                    ))
+                    .await;
+                return;
+            }
             // Send periodic ping to keep connection alive
             _ = ping_interval.tick() => {
                 tracing::info!("Sending WebSocket ping to keep connection alive");
@@ -532,6 +673,38 @@ pub async fn handle_socket(socket: WebSocket, state: Arc<WebSocketState>, client
 
     let saved_path = file_path.to_string_lossy().to_string();
 
+    // Let the client compare what arrived with what it sent
+    let verified = received_bytes == file_size;
+    match compute_stored_hash(storage, &file_path, HashAlgorithm::Blake3, |_, _| {}).await {
+        Ok(hash) => {
+            send_message(
+                &mut sender,
+                &ServerMessage::Verification {
+                    verified,
+                    received_bytes,
+                    hash,
+                },
+            )
+            .await;
+        }
+        Err(e) => tracing::error!("Failed to hash upload {}: {}", saved_path, e),
+    }
+    if !verified {
+        let _ = state
+            .event_tx
+            .send(AppEvent::log(
+                LogLevel::Warning,
+                EventCategory::Http,
+                format!(
+                    "Upload of {} is incomplete: {} of {}",
+                    file_name,
+                    format_size(received_bytes),
+                    format_size(file_size)
+                ),
+            ))
+            .await;
+    }
+
     // Send complete message
     let _ = sender
         .send(Message::Text(
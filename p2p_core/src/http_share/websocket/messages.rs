@@ -1,5 +1,6 @@
 //! WebSocket message types and constants
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Chunk size for binary transfer (256KB - optimized for LAN)
@@ -8,6 +9,10 @@ pub const CHUNK_SIZE: usize = 256 * 1024;
 /// Timeout for user response (60 seconds)
 pub const USER_RESPONSE_TIMEOUT_SECS: u64 = 60;
 
+/// Interval of [`ServerMessage::AwaitingApproval`] updates while the host
+/// decides
+pub const APPROVAL_UPDATE_INTERVAL_SECS: u64 = 5;
+
 /// Timeout for WebSocket handshake (10 seconds)
 pub const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
 
@@ -29,25 +34,39 @@ pub const MAX_CONNECTIONS_PER_IP: usize = 20;
 pub const MAX_CONNECTIONS_PER_IP: usize = 15;
 
 /// Messages from client to server
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ClientMessage {
     /// Initial file info before upload
     FileInfo { file_name: String, file_size: u64 },
 }
 
-/// Messages from server to client
-#[derive(Debug, Serialize, Deserialize)]
+/// Messages from server to client. Besides answers to the upload, the
+/// server pushes what happens on the host: the approval countdown, the
+/// upload's verification and a notice when the server shuts down.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ServerMessage {
+    /// Request shown on the host; rejected after `seconds_left`
+    AwaitingApproval { seconds_left: u64 },
     /// Upload request accepted
     Accepted { request_id: String },
     /// Upload request rejected
     Rejected { reason: String },
     /// Progress update
     Progress { received_bytes: u64 },
+    /// Check of the saved file, sent right before [`ServerMessage::Complete`]
+    Verification {
+        /// All declared bytes arrived
+        verified: bool,
+        received_bytes: u64,
+        /// BLAKE3 hash of the saved file
+        hash: String,
+    },
     /// Upload complete
     Complete,
+    /// The host stopped the server; a running upload is discarded
+    ShuttingDown,
     /// Error occurred
     Error { message: String },
 }
@@ -60,7 +79,7 @@ mod tests {
     fn test_constants() {
         use crate::transfer::constants::{MAX_FILE_SIZE, MAX_FILENAME_LENGTH};
         assert_eq!(MAX_FILENAME_LENGTH, 255);
-        assert!(MAX_FILE_SIZE > 0);
+        const { assert!(MAX_FILE_SIZE > 0) };
         assert_eq!(MAX_PENDING_UPLOADS, 10);
         assert_eq!(MAX_ACTIVE_UPLOADS, 5);
         assert_eq!(MAX_CONNECTIONS, 30); // In test mode
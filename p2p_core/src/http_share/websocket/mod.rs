@@ -9,8 +9,8 @@ mod utils;
 
 pub use handler::handle_socket;
 pub use messages::{
-    CHUNK_SIZE, ClientMessage, MAX_ACTIVE_UPLOADS, MAX_CONNECTIONS, MAX_CONNECTIONS_PER_IP,
-    MAX_PENDING_UPLOADS, ServerMessage, USER_RESPONSE_TIMEOUT_SECS,
+    APPROVAL_UPDATE_INTERVAL_SECS, CHUNK_SIZE, ClientMessage, MAX_ACTIVE_UPLOADS, MAX_CONNECTIONS,
+    MAX_CONNECTIONS_PER_IP, MAX_PENDING_UPLOADS, ServerMessage, USER_RESPONSE_TIMEOUT_SECS,
 };
 pub use state::{PendingUpload, UploadState, WebSocketState, respond_to_upload};
 
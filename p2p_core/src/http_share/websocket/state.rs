@@ -2,9 +2,19 @@
 
 use super::messages::{MAX_ACTIVE_UPLOADS, MAX_PENDING_UPLOADS};
 use crate::AppEvent;
+use crate::http_share::approval::UploadApprovalPolicy;
+use crate::http_share::links::FileLinks;
+use crate::http_share::shares::FileShares;
+use crate::http_share::text::SharedText;
+use crate::storage::{LocalStorage, Storage};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+};
 use tokio::sync::{RwLock, mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
 
 /// Pending upload waiting for user response
 pub struct PendingUpload {
@@ -12,19 +22,41 @@ pub struct PendingUpload {
 }
 
 /// Shared state for upload handling
-#[derive(Default)]
 pub struct UploadState {
     /// Pending uploads waiting for user approval
     pub pending: RwLock<HashMap<String, PendingUpload>>,
     /// Number of active concurrent uploads
     pub active_count: AtomicUsize,
+    /// Text box shared with the page
+    pub shared_text: SharedText,
+    /// Download links for single files
+    pub file_links: FileLinks,
+    /// Temporary shares of chosen files
+    pub file_shares: FileShares,
+    /// Where uploads are written
+    pub storage: Arc<dyn Storage>,
+}
+
+impl Default for UploadState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl UploadState {
     pub fn new() -> Self {
+        Self::with_storage(Arc::new(LocalStorage))
+    }
+
+    /// Write uploads through `storage` instead of the local file system
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
         Self {
             pending: RwLock::new(HashMap::new()),
             active_count: AtomicUsize::new(0),
+            shared_text: SharedText::default(),
+            file_links: FileLinks::default(),
+            file_shares: FileShares::default(),
+            storage,
         }
     }
 
@@ -62,8 +94,16 @@ pub struct WebSocketState {
     pub event_tx: mpsc::Sender<AppEvent>,
     pub upload_state: Arc<UploadState>,
     pub download_dir: PathBuf,
+    /// Save uploads in a subfolder per client IP
+    pub per_peer_folders: bool,
     pub connection_count: AtomicUsize,
     pub ip_counts: std::sync::Mutex<HashMap<String, usize>>,
+    /// Cancelled when the server stops; clients are told before it closes
+    pub shutdown: CancellationToken,
+    /// Uploads accepted without asking the host
+    pub approval: UploadApprovalPolicy,
+    /// Client IPs the host accepted an upload from in this session
+    pub approved_clients: std::sync::Mutex<HashSet<String>>,
 }
 
 /// Guard for active upload count
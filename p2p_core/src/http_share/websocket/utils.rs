@@ -2,30 +2,12 @@
 
 use super::messages::{ClientMessage, HANDSHAKE_TIMEOUT_SECS};
 use super::state::UploadState;
-use crate::transfer::constants::{MAX_FILE_SIZE, MAX_FILENAME_LENGTH};
+use crate::transfer::constants::{MAX_FILE_SIZE, MAX_WIRE_FILENAME_LENGTH};
 use axum::extract::ws::{Message, WebSocket};
 use futures_util::StreamExt;
-use std::path::Path;
 use std::time::Duration;
-use tokio::fs::{File, OpenOptions};
 use tokio::time::timeout;
 
-/// Create a file with secure permissions (0o600 on Unix)
-pub async fn create_secure_file(path: &Path) -> std::io::Result<File> {
-    // Remove existing file to prevent TOCTOU
-    let _ = tokio::fs::remove_file(path).await;
-
-    let mut options = OpenOptions::new();
-    options.write(true).create_new(true);
-
-    #[cfg(unix)]
-    options.mode(0o600);
-
-    let file = options.open(path).await?;
-
-    Ok(file)
-}
-
 /// Wait for file_info message
 pub async fn wait_for_file_info(
     receiver: &mut futures_util::stream::SplitStream<WebSocket>,
@@ -61,10 +43,10 @@ pub async fn wait_for_file_info(
 
 /// Validate file info against security constraints
 pub fn validate_file_info(file_name: &str, file_size: u64) -> Result<(), String> {
-    if file_name.len() > MAX_FILENAME_LENGTH {
+    if file_name.len() > MAX_WIRE_FILENAME_LENGTH {
         return Err(format!(
             "Filename too long (max {} characters)",
-            MAX_FILENAME_LENGTH
+            MAX_WIRE_FILENAME_LENGTH
         ));
     }
 
@@ -87,6 +69,7 @@ pub async fn cleanup_pending(state: &UploadState, request_id: &str) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transfer::utils::create_secure_file;
 
     #[test]
     fn test_validate_file_info() {
@@ -94,7 +77,7 @@ mod tests {
         assert!(validate_file_info("test.txt", 1024).is_ok());
 
         // Invalid name length
-        let long_name = "a".repeat(MAX_FILENAME_LENGTH + 1);
+        let long_name = "a".repeat(MAX_WIRE_FILENAME_LENGTH + 1);
         assert!(validate_file_info(&long_name, 1024).is_err());
 
         // Invalid file size
@@ -147,6 +130,7 @@ mod tests {
             let file = tokio::fs::OpenOptions::new()
                 .write(true)
                 .create(true)
+                .truncate(true)
                 .open(&file_path)
                 .await
                 .expect("Failed to create initial file");
@@ -2,6 +2,7 @@ use anyhow::{Context, Result};
 use iroh::SecretKey;
 use std::path::PathBuf;
 use tokio::{fs, io::AsyncWriteExt};
+use zeroize::Zeroizing;
 
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
@@ -37,13 +38,15 @@ impl IdentityManager {
                     }
                 }
 
-                let mut key_bytes = Vec::new();
+                // Wiped on drop, like every copy of the key made here
+                let mut key_bytes = Zeroizing::new(Vec::new());
                 use tokio::io::AsyncReadExt;
                 file.read_to_end(&mut key_bytes).await.context("Failed to read secret key file")?;
 
-                let bytes: [u8; 32] = key_bytes
-                    .try_into()
-                    .map_err(|_| anyhow::anyhow!("Invalid secret key length in file"))?;
+                let bytes = Zeroizing::new(
+                    <[u8; 32]>::try_from(key_bytes.as_slice())
+                        .map_err(|_| anyhow::anyhow!("Invalid secret key length in file"))?,
+                );
 
                 Ok(SecretKey::from_bytes(&bytes))
             }
@@ -80,7 +83,7 @@ impl IdentityManager {
                     }
                 }
 
-                file.write_all(&secret_key.to_bytes())
+                file.write_all(Zeroizing::new(secret_key.to_bytes()).as_slice())
                     .await
                     .context("Failed to write secret key")?;
 
@@ -109,13 +112,14 @@ impl IdentityManager {
                     }
                 }
 
-                let mut key_bytes = Vec::new();
+                let mut key_bytes = Zeroizing::new(Vec::new());
                 use std::io::Read;
                 file.read_to_end(&mut key_bytes).context("Failed to read secret key file")?;
 
-                let bytes: [u8; 32] = key_bytes
-                    .try_into()
-                    .map_err(|_| anyhow::anyhow!("Invalid secret key length in file"))?;
+                let bytes = Zeroizing::new(
+                    <[u8; 32]>::try_from(key_bytes.as_slice())
+                        .map_err(|_| anyhow::anyhow!("Invalid secret key length in file"))?,
+                );
 
                 Ok(SecretKey::from_bytes(&bytes))
             }
@@ -150,7 +154,7 @@ impl IdentityManager {
                     }
                 }
 
-                file.write_all(&secret_key.to_bytes())
+                file.write_all(Zeroizing::new(secret_key.to_bytes()).as_slice())
                     .context("Failed to write secret key")?;
 
                 Ok(secret_key)
@@ -164,23 +168,36 @@ impl IdentityManager {
     }
 }
 
-/// Get the Iroh NodeId as the endpoint ID string
-/// This provides a unified identity across LAN and WAN transfers
-pub fn get_iroh_endpoint_id() -> String {
+/// Load the stored Iroh secret key, generating it on first use
+pub fn get_iroh_secret_key() -> Option<SecretKey> {
     use crate::config::get_config_dir;
 
     let config_dir = get_config_dir().unwrap_or_else(|| PathBuf::from("."));
     let manager = IdentityManager::new(config_dir);
 
     match manager.load_or_generate_sync() {
-        Ok(secret_key) => {
-            let public_key = secret_key.public();
-            public_key.to_string()
-        }
+        Ok(secret_key) => Some(secret_key),
         Err(e) => {
             tracing::error!("Failed to get Iroh identity: {}", e);
-            // Fallback to UUID if Iroh fails
-            uuid::Uuid::new_v4().to_string()
+            None
         }
     }
 }
+
+/// Get the Iroh NodeId as the endpoint ID string
+/// This provides a unified identity across LAN and WAN transfers
+pub fn get_iroh_endpoint_id() -> String {
+    match get_iroh_secret_key() {
+        Some(secret_key) => secret_key.public().to_string(),
+        // Fallback to UUID if Iroh fails
+        None => uuid::Uuid::new_v4().to_string(),
+    }
+}
+
+/// Name this device goes by when none is configured: its host name
+pub fn default_device_name() -> String {
+    hostname::get()
+        .ok()
+        .and_then(|s| s.into_string().ok())
+        .unwrap_or_else(|| "Unknown-PC".to_string())
+}
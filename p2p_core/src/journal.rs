@@ -0,0 +1,230 @@
+//! Journal of outgoing sends, for recovery after a crash.
+//!
+//! Every LAN send is written to `outgoing_journal.json` in the profile's
+//! config directory when it starts, with its target and files. The sender
+//! notes how far each file got and drops files as they finish; a send that
+//! ends normally leaves nothing behind. Whatever is still listed when the
+//! backend starts was interrupted, so it is offered back to the user with
+//! [`AppEvent::PendingSends`](crate::AppEvent::PendingSends). A resumed
+//! send goes through the [`schedule`](crate::schedule) queue and starts once
+//! the peer answers; the receiver's resume records then continue each
+//! partial file.
+
+use crate::config::{create_secure_dir_all, write_secure_file};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// File name of the journal inside the config directory
+pub const JOURNAL_FILE: &str = "outgoing_journal.json";
+
+/// Offsets are written at most this often while data flows
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// One file of a journaled send
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournaledFile {
+    pub path: PathBuf,
+    /// Bytes the receiver had when last noted
+    #[serde(default)]
+    pub sent: u64,
+}
+
+/// A send that had not finished when last written
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingSend {
+    /// Session the send ran under
+    pub id: String,
+    /// Peer IP, or `ip:port` for a non-default transfer port
+    pub target: String,
+    pub target_peer_name: String,
+    /// Files not yet sent in full
+    pub files: Vec<JournaledFile>,
+    /// Unix timestamp (seconds)
+    pub started: u64,
+}
+
+#[derive(Debug, Default)]
+struct JournalState {
+    sends: Vec<PendingSend>,
+    last_save: Option<Instant>,
+}
+
+/// The journal, shared by the backend and its running sends
+#[derive(Debug, Default)]
+pub struct SendJournal {
+    /// `None` keeps the journal in memory only
+    path: Option<PathBuf>,
+    state: Mutex<JournalState>,
+}
+
+impl SendJournal {
+    /// Load the journal saved at `path`; a missing or invalid file is empty
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let sends = path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            state: Mutex::new(JournalState {
+                sends,
+                last_save: None,
+            }),
+        }
+    }
+
+    /// Sends in the journal, oldest first
+    pub fn pending(&self) -> Vec<PendingSend> {
+        self.state.lock().unwrap().sends.clone()
+    }
+
+    /// Note a send starting now; the handle records its progress
+    pub fn begin(
+        self: &Arc<Self>,
+        id: &str,
+        target: &str,
+        target_peer_name: &str,
+        files: &[PathBuf],
+        started: u64,
+    ) -> JournalHandle {
+        let mut state = self.state.lock().unwrap();
+        state.sends.retain(|send| send.id != id);
+        state.sends.push(PendingSend {
+            id: id.to_string(),
+            target: target.to_string(),
+            target_peer_name: target_peer_name.to_string(),
+            files: files
+                .iter()
+                .map(|path| JournaledFile {
+                    path: path.clone(),
+                    sent: 0,
+                })
+                .collect(),
+            started,
+        });
+        self.save(&mut state);
+        JournalHandle {
+            journal: self.clone(),
+            id: id.to_string(),
+        }
+    }
+
+    /// The send `id`, if it is still unfinished
+    pub fn get(&self, id: &str) -> Option<PendingSend> {
+        let state = self.state.lock().unwrap();
+        state.sends.iter().find(|send| send.id == id).cloned()
+    }
+
+    /// Take the send `id` out of the journal
+    pub fn remove(&self, id: &str) -> Option<PendingSend> {
+        let mut state = self.state.lock().unwrap();
+        let index = state.sends.iter().position(|send| send.id == id)?;
+        let send = state.sends.remove(index);
+        self.save(&mut state);
+        Some(send)
+    }
+
+    fn update(&self, id: &str, force_save: bool, change: impl FnOnce(&mut PendingSend)) {
+        let mut state = self.state.lock().unwrap();
+        let Some(index) = state.sends.iter().position(|send| send.id == id) else {
+            return;
+        };
+        change(&mut state.sends[index]);
+        if state.sends[index].files.is_empty() {
+            state.sends.remove(index);
+        } else if !force_save
+            && state
+                .last_save
+                .is_some_and(|last| last.elapsed() < SAVE_INTERVAL)
+        {
+            return;
+        }
+        self.save(&mut state);
+    }
+
+    fn save(&self, state: &mut JournalState) {
+        state.last_save = Some(Instant::now());
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = create_secure_dir_all(parent);
+        }
+        match serde_json::to_string_pretty(&state.sends) {
+            Ok(json) => {
+                if let Err(e) = write_secure_file(path, &json) {
+                    tracing::warn!("Failed to save the outgoing journal: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to serialize the outgoing journal: {}", e),
+        }
+    }
+}
+
+/// Progress recorder for one journaled send
+#[derive(Debug, Clone)]
+pub struct JournalHandle {
+    journal: Arc<SendJournal>,
+    id: String,
+}
+
+impl JournalHandle {
+    /// The receiver has the first `sent` bytes of `path`
+    pub fn sent(&self, path: &Path, sent: u64) {
+        self.journal.update(&self.id, false, |send| {
+            if let Some(file) = send.files.iter_mut().find(|file| file.path == path) {
+                file.sent = sent;
+            }
+        });
+    }
+
+    /// `path` needs no resuming: it arrived, or the user cancelled it
+    pub fn file_done(&self, path: &Path) {
+        self.journal.update(&self.id, true, |send| {
+            send.files.retain(|file| file.path != path);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unfinished_sends_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("journal_{}.json", uuid::Uuid::new_v4()));
+        let journal = Arc::new(SendJournal::load(Some(path.clone())));
+        let files = [PathBuf::from("a.bin"), PathBuf::from("b.bin")];
+
+        let done = journal.begin("done", "192.168.1.20", "Office-PC", &files[..1], 100);
+        let crashed = journal.begin("crashed", "192.168.1.21:9000", "Laptop", &files, 200);
+        done.file_done(&files[0]);
+        crashed.file_done(&files[0]);
+        crashed.sent(&files[1], 4096);
+        // Offsets are written when a file finishes or now and then
+        crashed.file_done(Path::new("not-in-this-send.bin"));
+
+        let reloaded = SendJournal::load(Some(path.clone()));
+        assert_eq!(
+            reloaded.pending(),
+            vec![PendingSend {
+                id: "crashed".to_string(),
+                target: "192.168.1.21:9000".to_string(),
+                target_peer_name: "Laptop".to_string(),
+                files: vec![JournaledFile {
+                    path: files[1].clone(),
+                    sent: 4096,
+                }],
+                started: 200,
+            }]
+        );
+
+        assert!(reloaded.remove("crashed").is_some());
+        assert!(SendJournal::load(Some(path.clone())).pending().is_empty());
+        let _ = fs::remove_file(&path);
+    }
+}
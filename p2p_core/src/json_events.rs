@@ -0,0 +1,262 @@
+//! Backend events as JSON lines, for scripts and dashboards.
+//!
+//! With [`NodeConfig::event_output`](crate::NodeConfig::event_output) set,
+//! from the `P2P_EVENT_OUTPUT` environment variable or `event_output` in
+//! `config.json`, every [`AppEvent`] leaving the backend is also written as
+//! one line of JSON:
+//!
+//! ```text
+//! {"ts":1760784000000,"category":"transfer","event":"transfer_completed","data":{"file_name":"a.txt",...}}
+//! ```
+//!
+//! `P2P_EVENT_OUTPUT=stdout` (or `-`) prints the lines; any other value is a
+//! path, usually a named pipe made with `mkfifo`, and a plain file is
+//! appended to. Writing happens on its own thread: while no one reads the
+//! pipe, lines beyond a small buffer are dropped rather than slowing the
+//! backend down, and a reader that goes away is waited for again.
+//! Verification codes, invite links and link phrases are redacted.
+
+use crate::{AppEvent, EventCategory};
+use serde::{Deserialize, Serialize, Serializer};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{SyncSender, TrySendError, sync_channel};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+/// Environment variable that turns the output on, overriding `config.json`
+pub const EVENT_OUTPUT_ENV: &str = "P2P_EVENT_OUTPUT";
+
+/// Lines buffered for a slow or absent reader before events are dropped
+const OUTPUT_BUFFER: usize = 1024;
+
+const REDACTED: &str = "<redacted>";
+
+/// Where the JSON lines go
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventOutput {
+    Stdout,
+    /// Named pipe or file
+    Pipe(PathBuf),
+}
+
+impl EventOutput {
+    /// `stdout` or `-` for standard output, anything else a path; empty
+    /// means off
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "" => None,
+            "stdout" | "-" => Some(EventOutput::Stdout),
+            path => Some(EventOutput::Pipe(PathBuf::from(path))),
+        }
+    }
+
+    /// Output set by [`EVENT_OUTPUT_ENV`]
+    pub fn from_env() -> Option<Self> {
+        std::env::var(EVENT_OUTPUT_ENV)
+            .ok()
+            .and_then(|value| Self::parse(&value))
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Write + Send>> {
+        match self {
+            EventOutput::Stdout => Ok(Box::new(io::stdout())),
+            // Opening a pipe waits for a reader
+            EventOutput::Pipe(path) => Ok(Box::new(
+                std::fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(path)?,
+            )),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonEvent<'a> {
+    /// Milliseconds since the Unix epoch
+    ts: u64,
+    category: EventCategory,
+    #[serde(flatten)]
+    event: &'a AppEvent,
+}
+
+/// `event` as one line of JSON, without the newline
+pub fn to_json_line(event: &AppEvent) -> serde_json::Result<String> {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    serde_json::to_string(&JsonEvent {
+        ts,
+        category: event.category(),
+        event,
+    })
+}
+
+/// Write events passing through the returned sender to `output`, then pass
+/// them on to `event_tx`
+pub fn spawn_mirror(
+    output: EventOutput,
+    event_tx: mpsc::Sender<AppEvent>,
+) -> mpsc::Sender<AppEvent> {
+    let (tx, mut rx) = mpsc::channel::<AppEvent>(event_tx.max_capacity());
+    let lines = spawn_writer(output);
+    tokio::spawn(async move {
+        let mut dropping = false;
+        while let Some(event) = rx.recv().await {
+            match to_json_line(&event) {
+                Ok(line) => match lines.try_send(line) {
+                    Ok(()) => dropping = false,
+                    Err(TrySendError::Full(_)) if !dropping => {
+                        tracing::warn!("JSON event output is not being read, dropping events");
+                        dropping = true;
+                    }
+                    Err(_) => {}
+                },
+                Err(e) => tracing::warn!("Could not write an event as JSON: {}", e),
+            }
+            if event_tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+    tx
+}
+
+fn spawn_writer(output: EventOutput) -> SyncSender<String> {
+    let (tx, rx) = sync_channel::<String>(OUTPUT_BUFFER);
+    std::thread::spawn(move || {
+        let mut sink = None;
+        for line in rx {
+            if sink.is_none() {
+                match output.open() {
+                    Ok(opened) => sink = Some(opened),
+                    Err(e) => {
+                        tracing::warn!("Could not open {}: {}", describe(&output), e);
+                        continue;
+                    }
+                }
+            }
+            if let Some(writer) = sink.as_mut()
+                && let Err(e) = writeln!(writer, "{}", line).and_then(|()| writer.flush())
+            {
+                // The reader went away; wait for the next one
+                tracing::debug!("JSON event output closed: {}", e);
+                sink = None;
+            }
+        }
+    });
+    tx
+}
+
+fn describe(output: &EventOutput) -> String {
+    match output {
+        EventOutput::Stdout => "standard output".to_string(),
+        EventOutput::Pipe(path) => path.display().to_string(),
+    }
+}
+
+/// Serialize a secret as a placeholder
+pub(crate) fn redacted<S: Serializer>(_: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(REDACTED)
+}
+
+/// Serialize a WAN connection as the peer's endpoint ID
+pub(crate) fn connection_peer<S: Serializer>(
+    connection: &iroh::endpoint::Connection,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&connection.remote_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_event_json() {
+        let line = to_json_line(&AppEvent::TransferCompleted {
+            file_name: "a.txt".to_string(),
+            saved_path: Some(PathBuf::from("/tmp/a.txt")),
+            peer: Some("Laptop".to_string()),
+        })
+        .unwrap();
+        let json: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["category"], "transfer");
+        assert_eq!(json["event"], "transfer_completed");
+        assert_eq!(json["data"]["file_name"], "a.txt");
+        assert_eq!(json["data"]["peer"], "Laptop");
+        assert!(json["ts"].as_u64().unwrap() > 0);
+
+        let json: Value =
+            serde_json::from_str(&to_json_line(&AppEvent::HttpServerStopped).unwrap()).unwrap();
+        assert_eq!(json["event"], "http_server_stopped");
+        let json: Value =
+            serde_json::from_str(&to_json_line(&AppEvent::Error("boom".to_string())).unwrap())
+                .unwrap();
+        assert_eq!(json["data"], "boom");
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let line = to_json_line(&AppEvent::ShowVerificationCode {
+            session_id: "s1".to_string(),
+            code: "123456".to_string(),
+            from_ip: "10.0.0.2".to_string(),
+            from_name: "Laptop".to_string(),
+            note: None,
+        })
+        .unwrap();
+        assert!(!line.contains("123456"));
+        assert!(line.contains(REDACTED));
+    }
+
+    #[test]
+    fn test_parse_output() {
+        assert_eq!(EventOutput::parse("stdout"), Some(EventOutput::Stdout));
+        assert_eq!(EventOutput::parse("-"), Some(EventOutput::Stdout));
+        assert_eq!(
+            EventOutput::parse("/tmp/p2p-events"),
+            Some(EventOutput::Pipe(PathBuf::from("/tmp/p2p-events")))
+        );
+        assert_eq!(EventOutput::parse(" "), None);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_writes_and_forwards() {
+        let path = std::env::temp_dir().join(format!("events_{}.jsonl", uuid::Uuid::new_v4()));
+        let (event_tx, mut event_rx) = mpsc::channel(8);
+        let mirror = spawn_mirror(EventOutput::Pipe(path.clone()), event_tx);
+
+        mirror
+            .send(AppEvent::Status("one".to_string()))
+            .await
+            .unwrap();
+        mirror
+            .send(AppEvent::Status("two".to_string()))
+            .await
+            .unwrap();
+        assert!(matches!(event_rx.recv().await, Some(AppEvent::Status(s)) if s == "one"));
+        assert!(matches!(event_rx.recv().await, Some(AppEvent::Status(s)) if s == "two"));
+
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            lines = std::fs::read_to_string(&path)
+                .unwrap_or_default()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            if lines.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(lines.len(), 2);
+        let first: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(first["data"], "one");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,49 +1,142 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::sync::mpsc;
-use tokio::sync::oneshot;
-use tokio_util::sync::CancellationToken;
+use transfer::hash::HashAlgorithm;
+use transfer::xattrs::ExtendedAttribute;
 
+pub mod alpn;
+mod backend;
 pub mod config;
 pub mod discovery;
+pub mod events;
+pub mod folder_watch;
+pub mod groups;
+pub mod health;
+pub mod history;
 pub mod http_share;
 pub mod identity;
+pub mod journal;
+pub mod json_events;
+pub mod link;
+pub mod metrics;
+pub mod network_info;
+pub mod network_watch;
+pub mod node;
 pub mod pairing;
+pub mod path;
+pub mod peers;
+pub mod policy;
+pub mod post_receive;
+pub mod power;
+pub mod profiling;
+pub mod protocol_doc;
+pub mod proxy;
+pub mod quarantine;
+pub mod received;
+pub mod remote;
+pub mod rendezvous;
+pub mod retention;
+pub mod schedule;
+#[cfg(feature = "simulation")]
+pub mod simulation;
+pub mod state;
+pub mod storage;
+pub mod swarm;
+pub mod telemetry;
+pub mod testing;
 pub mod transfer;
+pub mod units;
+pub mod webhook;
 
-use discovery::{DISCOVERY_INTERVAL_SECS, DISCOVERY_PORT, DiscoveryService};
-use transfer::{TRANSFER_PORT, make_client_endpoint, make_server_endpoint};
+pub use backend::{profile_config, run_backend, run_backend_with_config, run_profile_backend};
+pub use events::{EventBus, EventCategory, EventPriority, EventSubscription};
+pub use node::{NodeConfig, P2pNode, P2pNodeBuilder, TransferHandle};
 
 /// Magic bytes to identify our app's packets (6 bytes: "P2PLT\0")
 pub const MAGIC_BYTES: &[u8] = b"P2PLT\x00";
 
+/// Version announced in discovery packets
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What a peer is willing to do, announced in its discovery packets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerCapabilities {
+    /// Drop-box node: accepts files but never sends any
+    #[serde(default)]
+    pub receive_only: bool,
+    /// [`PROTOCOL_VERSION`](alpn::PROTOCOL_VERSION) the peer speaks; 0 for
+    /// versions that did not announce it
+    #[serde(default)]
+    pub protocol: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum DiscoveryMsg {
     DiscoveryRequest {
         endpoint_id: String,
         my_name: String,
         port: u16,
+        #[serde(default)]
+        capabilities: PeerCapabilities,
+        /// [`APP_VERSION`] of the sender; absent from older versions
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+        /// Sender's clock in milliseconds, echoed in the response
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sent_ms: Option<u64>,
     },
     DiscoveryResponse {
         endpoint_id: String,
         my_name: String,
         port: u16,
+        #[serde(default)]
+        capabilities: PeerCapabilities,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+        /// `sent_ms` of the request answered, for the round-trip time
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        echo_ms: Option<u64>,
+    },
+    /// Unicast keep-alive between peers that already discovered each other
+    Heartbeat {
+        endpoint_id: String,
+        my_name: String,
+        port: u16,
+        #[serde(default)]
+        capabilities: PeerCapabilities,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
     },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct FileInfo {
     pub file_name: String,
-    pub file_size: u64,
+    /// `None` for a stream, such as a pipe or a camera, that ends when it ends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size: Option<u64>,
     ///Skip file path when serializing
     #[serde(skip)]
     pub file_path: PathBuf,
-    /// BLAKE3 hash for integrity verification (64-character hex string)
+    /// Hex digest for integrity verification
     #[serde(skip_serializing_if = "Option::is_none")]
     pub file_hash: Option<String>,
+    /// Algorithm of `file_hash`; absent means BLAKE3
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_default")]
+    pub hash_algorithm: HashAlgorithm,
+    /// Source modification time, milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    /// Source Unix permission bits (`0o777` mask)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Note or tag of the batch this file was sent in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Source extended attributes, when the sender opted in (see
+    /// [`transfer::xattrs`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub xattrs: Vec<ExtendedAttribute>,
 }
 
 #[derive(Debug, Clone)]
@@ -51,74 +144,453 @@ pub enum AppCommand {
     ///Broadcast LAN
     StartDiscovery,
     ///Send file to specific IP and list of files
+    /// (`target_ip` may also be `ip:port` for a non-default transfer port).
+    /// `session_id` identifies this transfer's verification prompt; create one
+    /// with [`new_session_id`].
     SendFile {
+        session_id: String,
         target_ip: String,
         target_endpoint_id: String,
         target_peer_name: String,
         files: Vec<PathBuf>,
+        /// Note or tag shown to the receiver and kept in its history
+        note: Option<String>,
+    },
+    /// Send `text` to a peer as a small `.txt` file, like
+    /// [`AppCommand::SendFile`]
+    SendText {
+        session_id: String,
+        target_ip: String,
+        target_peer_name: String,
+        text: String,
+    },
+    /// Send what `source` reads, such as standard input, as one file named
+    /// `file_name` whose size is only known at the end (see
+    /// [`transfer::stream`])
+    SendStream {
+        session_id: String,
+        target_ip: String,
+        target_peer_name: String,
+        file_name: String,
+        source: transfer::StreamSource,
+        note: Option<String>,
     },
     ///Cancel transfer
     CancelTransfer,
     /// User submitted verification code (sender side)
-    SubmitVerificationCode { target_ip: String, code: String },
+    SubmitVerificationCode { session_id: String, code: String },
+    /// User dismissed a verification prompt (sender side)
+    CancelVerification { session_id: String },
+    /// Issue a one-time QR-code invite (receiver side)
+    CreatePairingInvite,
+    /// Pair using a scanned `p2p-pair://` invite link (sender side)
+    RedeemPairingInvite { session_id: String, invite: String },
+    /// Send one file to several peers at once; the receivers also pass
+    /// pieces to each other (see [`swarm`])
+    SwarmSend {
+        file: PathBuf,
+        targets: Vec<swarm::SwarmTarget>,
+    },
+    /// Send `files` to every member of `group`, one send per member;
+    /// reported with [`AppEvent::GroupSendProgress`] and
+    /// [`AppEvent::GroupSendFinished`]
+    SendToGroup {
+        group: String,
+        members: Vec<groups::GroupTarget>,
+        files: Vec<PathBuf>,
+        note: Option<String>,
+    },
+    /// Like [`AppCommand::SendFile`], through the peer at `relay_ip` for a
+    /// target this device cannot reach itself
+    SendViaRelay {
+        session_id: String,
+        relay_ip: String,
+        target_ip: String,
+        target_peer_name: String,
+        files: Vec<PathBuf>,
+    },
+    /// Like [`AppCommand::SendFile`], but each file is deleted here once the
+    /// receiver has verified its copy (see [`transfer::moves`])
+    MoveFiles {
+        session_id: String,
+        target_ip: String,
+        target_endpoint_id: String,
+        target_peer_name: String,
+        files: Vec<PathBuf>,
+        note: Option<String>,
+    },
+    /// Keep a moved file announced by [`AppEvent::MovePending`]
+    UndoMove { move_id: String },
+    /// Ask the receiver what sending `files` would move, without sending
+    /// them; answered with [`AppEvent::SendEstimated`]
+    EstimateSend {
+        session_id: String,
+        target_ip: String,
+        target_peer_name: String,
+        files: Vec<PathBuf>,
+        note: Option<String>,
+    },
+    /// Pair with a discovered peer without sending files (sender side)
+    PairWithPeer {
+        session_id: String,
+        target_ip: String,
+        target_peer_name: String,
+    },
+    /// Stop trusting `endpoint_id`; it needs a code again to send here
+    ForgetPeer { endpoint_id: String },
+    /// Make new pairings guest pairings, which end after one transfer or
+    /// an hour (see [`pairing::guest`]); off at start
+    SetGuestPairing { enabled: bool },
+    /// Keep a peer at the top of the device list; saved to the profile
+    SetPeerPinned { endpoint_id: String, pinned: bool },
+    /// Create or change the peer group `name`, or delete it with no
+    /// `members`; saved to the profile
+    SetPeerGroup { name: String, members: Vec<String> },
+    /// Report paired and pinned peers and the peer groups with [`AppEvent::KnownPeersChanged`]
+    ListKnownPeers,
     /// Start the HTTP server for file sharing
     StartHttpServer,
     /// Stop the HTTP server
     StopHttpServer,
     /// Respond to upload request from web
     RespondUploadRequest { request_id: String, accepted: bool },
+    /// Show `text` in the share page's text box
+    PushTextToWeb { text: String },
+    /// Hand out a download link for one file, starting the HTTP server if
+    /// needed; answered with [`AppEvent::FileLinkCreated`]
+    ShareFileLink {
+        path: PathBuf,
+        /// `None` keeps the link until the app exits
+        expires_in_secs: Option<u64>,
+        /// Revoke the link after the first download
+        single_use: bool,
+    },
+    /// Revoke a link from [`AppEvent::FileLinkCreated`]
+    RevokeFileLink { link_id: String },
+    /// Share `paths` under one temporary link (see [`http_share::shares`]),
+    /// starting the HTTP server if needed; answered with
+    /// [`AppEvent::FileShareCreated`]
+    ShareFiles {
+        paths: Vec<PathBuf>,
+        /// `None` for [`http_share::DEFAULT_FILE_SHARE_EXPIRY`]
+        expires_in_secs: Option<u64>,
+    },
+    /// Revoke a share from [`AppEvent::FileShareCreated`]
+    RevokeFileShare { share_id: String },
     /// Connect to a remote peer over WAN using Iroh
     WanConnect { target_endpoint_id: String },
+    /// Ask the rendezvous server for this room's devices, answered with
+    /// [`AppEvent::MyDevices`]
+    ListMyDevices,
+    /// Register with another rendezvous server or room from now on; saved
+    /// to the profile
+    SetRendezvous(rendezvous::RendezvousSettings),
     /// Start bore tunnel for WAN HTTP share
     StartWanShare,
     /// Stop bore tunnel
     StopWanShare,
+    /// Send `files` to `target` (IP or `ip:port`) once the Unix time `at`
+    /// has passed and the peer is reachable; the job survives restarts
+    ScheduleSend {
+        target: String,
+        target_peer_name: String,
+        files: Vec<PathBuf>,
+        at: u64,
+    },
+    /// Drop a scheduled send before it starts
+    CancelScheduledSend { job_id: String },
+    /// Report the pending jobs with [`AppEvent::ScheduledSendsChanged`]
+    ListScheduledSends,
+    /// Queue the rest of an interrupted send (see [`journal`]) to start
+    /// once its peer answers
+    ResumePendingSend { id: String },
+    /// Forget an interrupted send
+    DiscardPendingSend { id: String },
+    /// Report everything a frontend needs with [`AppEvent::StateSnapshot`]
+    GetState,
+    /// Display sizes and speeds in other units; saved to the profile
+    SetUnitPreference(units::UnitPreference),
+    /// Checksum for files sent from now on; saved to the profile
+    SetHashAlgorithm(HashAlgorithm),
+    /// Proxy for the WAN tunnel from now on; saved to the profile, and used
+    /// for WAN relays after a restart
+    SetProxy(proxy::ProxySettings),
+    /// Monthly data cap from now on; saved to the profile
+    SetBandwidthCap(history::usage::BandwidthCap),
+    /// Answered with [`AppEvent::BandwidthUsage`], after any due
+    /// [`AppEvent::BandwidthCapWarning`]
+    GetBandwidthUsage,
+    /// Treat the connection as metered or not regardless of what the OS
+    /// says, or follow it again; saved to the profile
+    SetMeteredMode(network_info::MeteredMode),
+    /// Start or drop a send held by [`AppEvent::MeteredSendHeld`]
+    RespondMeteredSend { session_id: String, accepted: bool },
+    /// Apply the download folder retention policy now; with `dry_run`
+    /// only report what would be deleted
+    RunCleanup { dry_run: bool },
+    /// Decide what happens to a file reported by [`AppEvent::DuplicateReceived`]
+    ResolveDuplicate {
+        path: PathBuf,
+        action: history::DuplicateAction,
+    },
+    /// Decide what happens to a file reported by [`AppEvent::OrphanedPartials`]
+    ResolveOrphan {
+        path: PathBuf,
+        action: transfer::orphans::OrphanAction,
+    },
+    /// Find sent and received files in the history; answered with
+    /// [`AppEvent::HistoryResults`]
+    QueryHistory {
+        query: history::transfers::HistoryQuery,
+    },
+    /// Write every transfer record, oldest first, to `path`
+    ExportHistory {
+        format: history::export::ExportFormat,
+        path: PathBuf,
+    },
+    /// Count anonymous usage statistics, and how; saved to the profile
+    SetTelemetryMode(telemetry::TelemetryMode),
+    /// Show what would be reported with [`AppEvent::TelemetryPreview`]
+    PreviewTelemetry,
+    /// Answer a report offered with [`AppEvent::TelemetryPreview`]; either
+    /// way counting starts over
+    RespondTelemetry { send: bool },
+    /// Restart the backend under another profile (created on first use),
+    /// with that profile's key, pairings, download dir and settings
+    SwitchProfile { name: String },
+    /// Run `command` and answer with [`AppEvent::CommandResult`] carrying `request_id`
+    Tracked {
+        request_id: String,
+        command: Box<AppCommand>,
+    },
 }
-#[derive(Debug, Clone)]
+
+impl AppCommand {
+    /// Wrap this command so the backend acknowledges it.
+    ///
+    /// Returns the generated request id and the wrapped command.
+    pub fn tracked(self) -> (String, AppCommand) {
+        let request_id = uuid::Uuid::new_v4().simple().to_string();
+        let cmd = AppCommand::Tracked {
+            request_id: request_id.clone(),
+            command: Box::new(self),
+        };
+        (request_id, cmd)
+    }
+
+    /// Commands that start sending from this device, refused in
+    /// receive-only mode
+    pub fn is_outgoing(&self) -> bool {
+        matches!(
+            self,
+            AppCommand::SendFile { .. }
+                | AppCommand::MoveFiles { .. }
+                | AppCommand::SendText { .. }
+                | AppCommand::ScheduleSend { .. }
+                | AppCommand::RedeemPairingInvite { .. }
+                | AppCommand::SwarmSend { .. }
+                | AppCommand::SendToGroup { .. }
+                | AppCommand::SendViaRelay { .. }
+                | AppCommand::ResumePendingSend { .. }
+                | AppCommand::PairWithPeer { .. }
+                | AppCommand::EstimateSend { .. }
+                | AppCommand::WanConnect { .. }
+        )
+    }
+}
+
+/// Generate an id for a verification session (see [`AppCommand::SendFile`])
+pub fn new_session_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Severity of an [`AppEvent::Log`] line, from least to most important
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LogLevel {
+    /// Diagnostic detail hidden by default
+    Debug,
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+/// Serialized only for [`json_events`], which redacts codes and secrets
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
 pub enum AppEvent {
     Status(String),
 
+    /// A peer was discovered, or its display name changed
     PeerFound {
         endpoint_id: String,
         ip: String,
+        /// Transfer port the peer announced
+        port: u16,
+        /// Name the peer announced
         hostname: String,
+        /// `hostname`, with an endpoint ID suffix if another peer uses the
+        /// same name (see [`discovery::names`])
+        display_name: String,
+        capabilities: PeerCapabilities,
+        /// [`APP_VERSION`] the peer announced; `None` for older versions
+        version: Option<String>,
+        /// Round-trip time of the last discovery request answered by the
+        /// peer, once one was
+        rtt_ms: Option<u32>,
+    },
+
+    /// A discovered peer stopped sending heartbeats
+    PeerLost {
+        endpoint_id: String,
+        ip: String,
     },
 
+    /// Peers found, changed or lost since the last delta; LAN discovery
+    /// reports peers only this way (see [`discovery::batch`])
+    PeerListDelta(discovery::batch::PeerDelta),
+
     TransferProgress {
         file_name: String,
         progress: f32,
         speed: String,
         speed_bps: f64,
         is_sending: bool,
+        /// Bytes through so far, in place of `progress`, for a stream of
+        /// unknown size
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bytes: Option<u64>,
+    },
+    /// A file was sent or received in full
+    TransferCompleted {
+        file_name: String,
+        /// Where a received file was saved; `None` on the sending side
+        saved_path: Option<PathBuf>,
+        /// Who sent a received file: display name or endpoint ID
+        peer: Option<String>,
+    },
+    /// Answer to [`AppCommand::GetState`], current as of every event before it
+    StateSnapshot(Box<state::BackendState>),
+
+    /// A transfer was stopped by a user, on this side or the peer's
+    TransferCancelled {
+        file_name: String,
+        is_sending: bool,
+        /// Whether the other device cancelled
+        by_peer: bool,
+        reason: String,
     },
-    TransferCompleted(String),
     Error(String),
 
     /// Receiver: Show this code to user for verification
     ShowVerificationCode {
+        session_id: String,
+        #[serde(serialize_with = "json_events::redacted")]
         code: String,
         from_ip: String,
         from_name: String,
+        /// Note the sender attached to the files it is about to send
+        note: Option<String>,
     },
 
     /// Sender: Ask user to input verification code
     RequestVerificationCode {
+        session_id: String,
         target_ip: String,
+        target_name: String,
+    },
+
+    /// Sender: the receiver is showing codes to other senders; this many
+    /// wait ahead of us
+    PairingQueued {
+        session_id: String,
+        peer_name: String,
+        ahead: u32,
+    },
+
+    /// Sender: a verification prompt ended without success (timeout,
+    /// cancellation or too many wrong codes) and can be closed
+    VerificationCancelled {
+        session_id: String,
+        reason: String,
     },
 
-    /// Verification/Pairing result
+    /// Endpoint IDs of paired and pinned peers and the peer groups, after
+    /// [`AppCommand::ListKnownPeers`] or a change
+    KnownPeersChanged {
+        /// Peers that may send here without a code
+        paired: Vec<String>,
+        /// The ones among `paired` that are guests, for one transfer
+        guests: Vec<String>,
+        /// New pairings are guest pairings, see [`AppCommand::SetGuestPairing`]
+        guest_mode: bool,
+        pinned: Vec<String>,
+        groups: Vec<groups::PeerGroup>,
+    },
+
+    /// A guest's pairing ended after its transfer; it needs a code to send
+    /// here again
+    GuestPairingEnded {
+        endpoint_id: String,
+        peer_name: String,
+    },
+
+    /// A pairing invite was issued; show `uri` as a QR code
+    PairingInviteCreated {
+        #[serde(serialize_with = "json_events::redacted")]
+        uri: String,
+        expires_in_secs: u64,
+    },
+
+    /// A link phrase is being shown; another device can type it within
+    /// `expires_in_secs` to pair with this one
+    LinkPhraseReady {
+        #[serde(serialize_with = "json_events::redacted")]
+        phrase: String,
+        expires_in_secs: u64,
+    },
+    /// A device was linked with a phrase and is now paired both ways
+    DeviceLinked {
+        endpoint_id: String,
+        peer_name: String,
+    },
+    /// Showing or typing a link phrase failed
+    LinkFailed {
+        message: String,
+    },
+    /// Verification/Pairing result for the session of the same id
     PairingResult {
+        session_id: String,
         success: bool,
         peer_name: String,
         message: String,
     },
 
+    /// A locked-out sender tried to pair again. Sent for the first refused
+    /// attempt and then every
+    /// [`REPORT_BLOCKED_EVERY`](pairing::lockout::REPORT_BLOCKED_EVERY) attempts.
+    PairingBlocked {
+        ip: String,
+        endpoint_id: String,
+        /// Attempts refused during the current lockout
+        attempts: u32,
+        retry_after_secs: u64,
+    },
+
     /// File verification started
     VerificationStarted {
         file_name: String,
         is_sending: bool,
     },
 
+    /// Share of the file hashed so far
+    VerificationProgress {
+        file_name: String,
+        is_sending: bool,
+        /// Percent done
+        progress: f32,
+    },
+
     /// File verification completed
     VerificationCompleted {
         file_name: String,
@@ -126,7 +598,7 @@ pub enum AppEvent {
         verified: bool,
     },
 
-    /// HTTP share URL is ready (sent once at startup)
+    /// HTTP share URL is ready, or changed with the LAN address
     ShareUrlReady {
         url: String,
     },
@@ -134,6 +606,9 @@ pub enum AppEvent {
     /// HTTP server has been started
     HttpServerStarted {
         url: String,
+        /// Owner page for remote control, if enabled (see
+        /// [`http_share::owner`])
+        owner_url: Option<String>,
     },
 
     /// HTTP server has been stopped
@@ -145,6 +620,57 @@ pub enum AppEvent {
         file_name: String,
         file_size: u64,
         from_ip: String,
+        /// Why the name may not be what it looks like (see
+        /// [`transfer::NameWarning`])
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        warning: Option<String>,
+    },
+
+    /// Upload from web client accepted by the approval policy (see
+    /// [`http_share::approval`]); no response is expected
+    UploadAutoApproved {
+        request_id: String,
+        file_name: String,
+        file_size: u64,
+        from_ip: String,
+        /// Rule that matched
+        reason: String,
+    },
+
+    /// Text pasted into the share page (see [`http_share::text`])
+    TextReceived {
+        text: String,
+        from_ip: String,
+    },
+
+    /// A download link for one file was issued; show `url` as a QR code
+    FileLinkCreated {
+        link_id: String,
+        file_name: String,
+        url: String,
+        /// The same link through the WAN share tunnel, if it is running
+        wan_url: Option<String>,
+        expires_in_secs: Option<u64>,
+        single_use: bool,
+    },
+
+    /// Chosen files were shared under one temporary link; show `url` as a
+    /// QR code
+    FileShareCreated {
+        share_id: String,
+        file_names: Vec<String>,
+        url: String,
+        /// The same share through the WAN share tunnel, if it is running
+        wan_url: Option<String>,
+        expires_in_secs: u64,
+    },
+    /// A file of a share was downloaded; resumed downloads are not counted
+    FileShareDownloaded {
+        share_id: String,
+        file_name: String,
+        /// Downloads of this file so far
+        downloads: u32,
+        from_ip: String,
     },
 
     /// Upload request cancelled (timeout or client disconnected)
@@ -166,12 +692,20 @@ pub enum AppEvent {
     },
 
     /// WAN Connection established
-    WanConnected(iroh::endpoint::Connection),
+    WanConnected(
+        #[serde(serialize_with = "json_events::connection_peer")] iroh::endpoint::Connection,
+    ),
 
     /// WAN Connection info update (type changed or periodic update)
     WanConnectionInfo {
         connection_type: String,
         rtt_ms: Option<u64>,
+        /// Round trip of the last answered heartbeat
+        latency_ms: Option<u64>,
+    },
+    /// The WAN peer stopped answering heartbeats; the connection is closed
+    WanConnectionLost {
+        reason: String,
     },
 
     WanShareReady {
@@ -179,424 +713,277 @@ pub enum AppEvent {
     },
     WanShareStopped,
     WanShareError(String),
+    /// A WAN peer offers files with hidden names; answer through the WAN
+    /// listener. Only `labels`, which say nothing about the names, are shown.
+    WanOffer {
+        offer_id: String,
+        peer: String,
+        labels: Vec<String>,
+        total_size: u64,
+    },
+    /// Devices registered in this node's rendezvous room, this one included
+    MyDevices {
+        devices: Vec<rendezvous::RegisteredDevice>,
+    },
+
+    /// Backend finished binding its sockets and is accepting commands
+    BackendReady {
+        endpoint_id: String,
+        device_name: String,
+        transfer_port: u16,
+        /// Kiosk mode: outgoing commands will be refused
+        receive_only: bool,
+    },
+    /// Heartbeat, every [`health::HEALTH_INTERVAL`]
+    BackendHealth {
+        /// The discovery socket is bound and broadcasting
+        discovery_ok: bool,
+        /// The QUIC transfer server accepts connections
+        quic_listening: bool,
+        /// The HTTP share server is running
+        http_running: bool,
+        /// The Iroh endpoint is connected to its home relay
+        wan_online: bool,
+        /// Round trip to the home relay, when measured
+        relay_latency_ms: Option<u64>,
+    },
+
+    /// Which device has which pieces of every swarm file here, sent when
+    /// any of it changes (at most every
+    /// [`swarm::availability::AVAILABILITY_INTERVAL`])
+    SwarmAvailability {
+        swarms: Vec<swarm::availability::SwarmAvailability>,
+    },
+
+    /// Transfer rates and totals, every [`metrics::METRICS_INTERVAL`]
+    MetricsSample {
+        /// Bytes per second over the last interval, all transfers together
+        upload_bps: f64,
+        download_bps: f64,
+        /// Bytes since the process started
+        bytes_sent: u64,
+        bytes_received: u64,
+        /// Files being sent or received
+        active_transfers: usize,
+        /// Set when the node samples CPU and memory
+        system: Option<metrics::SystemUsage>,
+    },
+
+    /// Pending scheduled sends, after any change or on request
+    ScheduledSendsChanged {
+        jobs: Vec<schedule::ScheduledSend>,
+    },
+    /// A scheduled job's time came and its peer answered; the transfer runs
+    /// under `session_id` like a normal [`AppCommand::SendFile`]
+    ScheduledSendStarted {
+        job_id: String,
+        session_id: String,
+    },
+    /// Sends interrupted by a crash or a lost connection, on startup and
+    /// after each resume or discard
+    PendingSends {
+        sends: Vec<journal::PendingSend>,
+    },
+
+    /// Answer to [`AppCommand::EstimateSend`]
+    SendEstimated {
+        session_id: String,
+        target_peer_name: String,
+        estimate: transfer::SendEstimate,
+    },
+
+    /// Files were added to, removed from or renamed in the download folder,
+    /// by this app or another program
+    LocalFilesChanged,
+
+    /// Result of a download folder cleanup pass
+    CleanupReport {
+        dry_run: bool,
+        /// Paths relative to the download folder
+        files: Vec<String>,
+        freed_bytes: u64,
+    },
+
+    /// Partial files in the download folder that no transfer continued for
+    /// longer than the configured age; answer each with
+    /// [`AppCommand::ResolveOrphan`]
+    OrphanedPartials {
+        files: Vec<transfer::orphans::OrphanedPart>,
+    },
+
+    /// Bytes sent and received today, this week and this month
+    BandwidthUsage(history::usage::UsageSummary),
+    /// This month's usage came near the cap or reached it; sent once per
+    /// month and level
+    BandwidthCapWarning {
+        used: u64,
+        cap: u64,
+        reached: bool,
+        /// Scheduled sends wait until the cap is raised or the month ends
+        holding_scheduled: bool,
+    },
+    /// The connection's cost was detected or the override changed; sent
+    /// once at start and on every change
+    NetworkCost {
+        detected: network_info::NetworkCost,
+        mode: network_info::MeteredMode,
+        /// Sends are slowed and large ones wait for confirmation
+        metered: bool,
+    },
+    /// The computer woke up; transfers running before it slept were
+    /// interrupted
+    SystemResumed {
+        slept_secs: u64,
+    },
+    /// This device's interface addresses changed, e.g. on a switch to
+    /// another Wi-Fi network; peers are being discovered again
+    NetworkChanged {
+        /// Address share URLs use from now on
+        lan_ip: String,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// A send broke because the computer slept; the rest starts once the
+    /// peer answers
+    SendInterrupted {
+        target_peer_name: String,
+        files: Vec<String>,
+    },
+    /// A LAN send to a device with a known endpoint ID lost its connection
+    /// part way; `files` are what is left, each with the bytes the receiver
+    /// has. The GUI carries them on over the WAN when it can reach the
+    /// device there, then drops `session_id` with
+    /// [`AppCommand::DiscardPendingSend`].
+    SendBroken {
+        session_id: String,
+        endpoint_id: String,
+        target_peer_name: String,
+        files: Vec<journal::JournaledFile>,
+        reason: String,
+    },
+    /// A broken LAN send to `target_peer_name` continues over the WAN,
+    /// `file_name` from byte `offset` on
+    PathSwitched {
+        target_peer_name: String,
+        file_name: String,
+        offset: u64,
+    },
+    /// Another member of a [`AppCommand::SendToGroup`] finished
+    GroupSendProgress {
+        group_id: String,
+        group: String,
+        /// Members that got the whole batch
+        sent: usize,
+        failed: usize,
+        total: usize,
+    },
+    /// Every member of a group send finished
+    GroupSendFinished {
+        group_id: String,
+        group: String,
+        /// Names of the members that got the whole batch
+        sent: Vec<String>,
+        failed: Vec<groups::GroupFailure>,
+    },
+    /// A send above [`network_info::METERED_CONFIRM_BYTES`] on a metered
+    /// connection waits for [`AppCommand::RespondMeteredSend`]
+    MeteredSendHeld {
+        session_id: String,
+        target_peer_name: String,
+        files: usize,
+        total_size: u64,
+    },
+
+    /// Encryption, certificate pinning and path of the connection carrying
+    /// `file_name`, sent when the file starts
+    SecurityInfo {
+        file_name: String,
+        is_sending: bool,
+        security: transfer::SecurityInfo,
+    },
+
+    /// A received file has the same content as an earlier one still on
+    /// disk; answer with [`AppCommand::ResolveDuplicate`]
+    DuplicateReceived {
+        file_name: String,
+        /// The new file
+        path: PathBuf,
+        /// The earlier copy
+        existing: PathBuf,
+        size: u64,
+    },
+
+    /// Transfers matching an [`AppCommand::QueryHistory`] query, newest
+    /// first and at most [`history::MAX_QUERY_RESULTS`]
+    HistoryResults {
+        query: history::transfers::HistoryQuery,
+        records: Vec<history::transfers::TransferRecord>,
+    },
+
+    /// The receiver's hash check of a sent file failed; send `path` again
+    /// with [`AppCommand::SendFile`] to `target_ip` if the user agrees
+    ResendOffered {
+        file_name: String,
+        path: PathBuf,
+        target_ip: String,
+        target_peer_name: String,
+    },
+
+    /// A moved file was verified by the receiver and is deleted after
+    /// `undo_secs` unless [`AppCommand::UndoMove`] arrives first
+    MovePending {
+        move_id: String,
+        file_name: String,
+        path: PathBuf,
+        undo_secs: u64,
+    },
+
+    /// A pending move ended; `deleted` is false when the source was kept
+    MoveFinished {
+        move_id: String,
+        deleted: bool,
+    },
+
+    /// The backend restarted under another profile; a new
+    /// [`BackendReady`](AppEvent::BackendReady) follows
+    ProfileSwitched {
+        name: String,
+    },
+    /// The exact JSON of a usage report (see [`telemetry`]); with
+    /// `awaiting_consent` it is only sent once answered with
+    /// [`AppCommand::RespondTelemetry`]
+    TelemetryPreview {
+        payload: String,
+        awaiting_consent: bool,
+    },
+    /// A command was refused because the machine's policy forbids it (see
+    /// [`policy`])
+    PolicyViolation {
+        rule: policy::PolicyRule,
+        message: String,
+    },
+
+    /// Structured log line with a severity and the area it concerns
+    Log {
+        level: LogLevel,
+        category: EventCategory,
+        message: String,
+    },
+
+    /// Outcome of an [`AppCommand::Tracked`] command
+    CommandResult {
+        request_id: String,
+        result: Result<(), String>,
+    },
 }
 
-pub async fn run_backend(mut cmd_rx: mpsc::Receiver<AppCommand>, event_tx: mpsc::Sender<AppEvent>) {
-    // Load environment variables from .env file (for NGROK_AUTHTOKEN etc.)
-    let _ = dotenvy::dotenv();
-
-    // Install rustls crypto provider (required for rustls 0.23+)
-    let _ = rustls::crypto::ring::default_provider().install_default();
-
-    // 1. Get Endpoint ID and Hostname (using Iroh NodeId for unified identity)
-    let my_endpoint_id = identity::get_iroh_endpoint_id();
-    let my_name = hostname::get()
-        .ok()
-        .and_then(|s| s.into_string().ok())
-        .unwrap_or_else(|| "Unknown-PC".to_string());
-
-    // Store pending verification channels (IP -> Sender)
-    let mut verification_pending: HashMap<String, oneshot::Sender<String>> = HashMap::new();
-
-    // 2. Setup Ports - use constants from discovery module
-
-    // Send message to GUI
-    let _ = event_tx
-        .send(AppEvent::Status(format!(
-            "Endpoint ID: {}, Name: {}",
-            my_endpoint_id, my_name
-        )))
-        .await;
-
-    let discovery_service = match DiscoveryService::new(DISCOVERY_PORT).await {
-        Ok(ds) => Arc::new(ds),
-        Err(e) => {
-            tracing::error!("Failed to bind discovery port {}: {}", DISCOVERY_PORT, e);
-            let _ = event_tx
-                .send(AppEvent::Error(format!(
-                    "Cant bind port {}: {}",
-                    DISCOVERY_PORT, e
-                )))
-                .await;
-            return;
-        }
-    };
-
-    let server_addr: SocketAddr = format!("0.0.0.0:{}", TRANSFER_PORT).parse().unwrap();
-    let server_endpoint = match make_server_endpoint(server_addr) {
-        Ok(ep) => ep,
-        Err(e) => {
-            let _ = event_tx
-                .send(AppEvent::Error(format!("Cant init QUIC server: {}", e)))
-                .await;
-            return;
-        }
-    };
-    let _ = event_tx
-        .send(AppEvent::Status(format!(
-            "QUIC Server listening at port {}",
-            TRANSFER_PORT
-        )))
-        .await;
-
-    let client_endpoint = match make_client_endpoint() {
-        Ok(ep) => Arc::new(ep),
-        Err(e) => {
-            let _ = event_tx
-                .send(AppEvent::Error(format!("Cant init QUIC client: {}", e)))
-                .await;
-            return;
-        }
-    };
-
-    let download_dir = config::get_download_dir();
-    let server_event_tx = event_tx.clone();
-    tokio::spawn(async move {
-        transfer::run_server(server_endpoint, server_event_tx, download_dir).await;
-    });
-
-    discovery_service.start_listening(
-        event_tx.clone(),
-        my_endpoint_id.clone(),
-        my_name.clone(),
-        TRANSFER_PORT,
-    );
-
-    let ds_clone = discovery_service.clone();
-    let endpoint_id_clone = my_endpoint_id.clone();
-    let name_clone = my_name.clone();
-    tokio::spawn(async move {
-        // Broadcast immediately on start
-        ds_clone
-            .send_discovery_request(endpoint_id_clone.clone(), name_clone.clone(), TRANSFER_PORT)
-            .await;
-
-        let mut interval =
-            tokio::time::interval(tokio::time::Duration::from_secs(DISCOVERY_INTERVAL_SECS));
-        loop {
-            interval.tick().await;
-            ds_clone
-                .send_discovery_request(
-                    endpoint_id_clone.clone(),
-                    name_clone.clone(),
-                    TRANSFER_PORT,
-                )
-                .await;
-        }
-    });
-
-    // 9. HTTP Server state
-    let mut http_cancel_token: Option<CancellationToken> = None;
-    let upload_state = Arc::new(http_share::UploadState::new());
-
-    // 10. WAN Share (ngrok tunnel) state
-    let mut ngrok_tunnel: Option<http_share::NgrokTunnel> = None;
-    let mut current_session_token: Option<String> = None;
-
-    // Main loop: Wait for commands from UI
-    while let Some(cmd) = cmd_rx.recv().await {
-        match cmd {
-            AppCommand::StartDiscovery => {
-                // Trigger manual discovery immediately
-                let _ = event_tx
-                    .send(AppEvent::Status("Manual scanning...".to_string()))
-                    .await;
-                discovery_service
-                    .send_discovery_request(my_endpoint_id.clone(), my_name.clone(), TRANSFER_PORT)
-                    .await;
-            }
-            AppCommand::SendFile {
-                target_ip,
-                target_endpoint_id: _target_endpoint_id,
-                target_peer_name,
-                files,
-            } => {
-                tracing::info!(
-                    "Initiating transfer to {} ({}) with {} files",
-                    target_peer_name,
-                    target_ip,
-                    files.len()
-                );
-                let target_addr: SocketAddr =
-                    match format!("{}:{}", target_ip, TRANSFER_PORT).parse() {
-                        Ok(addr) => addr,
-                        Err(e) => {
-                            let _ = event_tx
-                                .send(AppEvent::Error(format!("Invalid address: {}", e)))
-                                .await;
-                            continue;
-                        }
-                    };
-
-                // Create channel for verification code
-                let (code_tx, code_rx) = oneshot::channel();
-
-                // Store tx in map, keyed by IP
-                verification_pending.insert(target_ip.clone(), code_tx);
-
-                let client_endpoint = client_endpoint.clone();
-                let evt = event_tx.clone();
-
-                // Create transfer context
-                let context = transfer::TransferContext {
-                    my_endpoint_id: my_endpoint_id.clone(),
-                    my_name: my_name.clone(),
-                    target_peer_name,
-                };
-
-                tokio::spawn(async move {
-                    if let Err(e) = transfer::sender::send_files(
-                        &client_endpoint,
-                        target_addr,
-                        files,
-                        evt.clone(),
-                        context,
-                        Some(code_rx),
-                    )
-                    .await
-                    {
-                        let _ = evt
-                            .send(AppEvent::Error(format!("File transfer failed: {}", e)))
-                            .await;
-                    }
-                });
-            }
-            AppCommand::CancelTransfer => {
-                let _ = event_tx
-                    .send(AppEvent::Status("Task cancelled.".to_string()))
-                    .await;
-            }
-            AppCommand::SubmitVerificationCode { target_ip, code } => {
-                if let Some(tx) = verification_pending.remove(&target_ip) {
-                    if tx.send(code.clone()).is_err() {
-                        let _ = event_tx
-                            .send(AppEvent::Error(
-                                "Cannot send verification code (task closed)".to_string(),
-                            ))
-                            .await;
-                    } else {
-                        let _ = event_tx
-                            .send(AppEvent::Status(format!(
-                                "Verification code sent to {}",
-                                target_ip
-                            )))
-                            .await;
-                    }
-                } else {
-                    let _ = event_tx
-                        .send(AppEvent::Error(format!(
-                            "No pending verification session found for {}",
-                            target_ip
-                        )))
-                        .await;
-                }
-            }
-            AppCommand::RespondUploadRequest {
-                request_id,
-                accepted,
-            } => {
-                http_share::respond_to_upload(&upload_state, &request_id, accepted).await;
-            }
-            AppCommand::StartHttpServer => {
-                // Stop existing server if running
-                if let Some(ct) = http_cancel_token.take() {
-                    ct.cancel();
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                }
-
-                // Generate new session token and start server
-                let session_token = http_share::generate_session_token();
-                // Get local IP, preferring non-loopback IPv4
-                // Get local IP, prioritizing LAN ranges (192.168.x.x, 10.x.x.x, 172.16.x.x)
-                let local_ip = local_ip_address::list_afinet_netifas()
-                    .ok()
-                    .and_then(|ips| {
-                        let mut best_ip = None;
-                        for (_name, ip) in ips {
-                            if ip.is_loopback() || !ip.is_ipv4() {
-                                continue;
-                            }
-                            let ip_str = ip.to_string();
-                            if ip_str.starts_with("192.168.") {
-                                return Some(ip_str); // Best match
-                            }
-                            if ip_str.starts_with("10.") {
-                                best_ip = Some(ip_str);
-                                continue;
-                            }
-                            if ip_str.starts_with("172.") && best_ip.is_none() {
-                                best_ip = Some(ip_str);
-                                continue;
-                            }
-                            if best_ip.is_none() {
-                                best_ip = Some(ip_str);
-                            }
-                        }
-                        best_ip
-                    })
-                    .unwrap_or_else(|| "127.0.0.1".to_string());
-                let share_url = format!(
-                    "http://{}:{}/{}",
-                    local_ip,
-                    http_share::HTTP_PORT,
-                    session_token
-                );
-
-                let cancel_token = CancellationToken::new();
-                http_cancel_token = Some(cancel_token.clone());
-                current_session_token = Some(session_token.clone());
-
-                let http_event_tx = event_tx.clone();
-                let token_clone = session_token.clone();
-                let url_clone = share_url.clone();
-                let upload_state_clone = upload_state.clone();
-
-                tokio::spawn(async move {
-                    if let Err(e) = http_share::start_default_http_server_with_websocket(
-                        &token_clone,
-                        http_event_tx.clone(),
-                        upload_state_clone,
-                        Some(cancel_token),
-                    )
-                    .await
-                    {
-                        tracing::error!("HTTP server error: {}", e);
-                        let _ = http_event_tx
-                            .send(AppEvent::Error(format!("HTTP server failed: {}", e)))
-                            .await;
-                    }
-                });
-
-                // Notify GUI that server started
-                let _ = event_tx
-                    .send(AppEvent::HttpServerStarted { url: share_url })
-                    .await;
-                tracing::info!("HTTP server started: {}", url_clone);
-            }
-            AppCommand::StopHttpServer => {
-                if let Some(ct) = http_cancel_token.take() {
-                    ct.cancel();
-                    let _ = event_tx.send(AppEvent::HttpServerStopped).await;
-                    tracing::info!("HTTP server stopped");
-                } else {
-                    let _ = event_tx
-                        .send(AppEvent::Status("HTTP server is not running".to_string()))
-                        .await;
-                }
-            }
-            AppCommand::WanConnect { target_endpoint_id } => {
-                tracing::info!("=== WAN Connect Command Received ===");
-                tracing::info!("Target Endpoint ID: {}", target_endpoint_id);
-
-                // Note: Actual WAN connection is handled in p2p_gui layer
-                // which has access to p2p_wan crate
-                let _ = event_tx
-                    .send(AppEvent::Status(format!(
-                        "WAN Connect request: {}",
-                        target_endpoint_id
-                    )))
-                    .await;
-            }
-            AppCommand::StartWanShare => {
-                // First ensure HTTP server is running
-                if http_cancel_token.is_none() {
-                    // Start HTTP server first
-                    let session_token = http_share::generate_session_token();
-                    let local_ip = local_ip_address::list_afinet_netifas()
-                        .ok()
-                        .and_then(|ips| {
-                            let mut best_ip = None;
-                            for (_name, ip) in ips {
-                                if ip.is_loopback() || !ip.is_ipv4() {
-                                    continue;
-                                }
-                                let ip_str = ip.to_string();
-                                if ip_str.starts_with("192.168.") {
-                                    return Some(ip_str);
-                                }
-                                if ip_str.starts_with("10.") {
-                                    best_ip = Some(ip_str);
-                                    continue;
-                                }
-                                if ip_str.starts_with("172.") && best_ip.is_none() {
-                                    best_ip = Some(ip_str);
-                                    continue;
-                                }
-                                if best_ip.is_none() {
-                                    best_ip = Some(ip_str);
-                                }
-                            }
-                            best_ip
-                        })
-                        .unwrap_or_else(|| "127.0.0.1".to_string());
-                    let share_url = format!(
-                        "http://{}:{}/{}",
-                        local_ip,
-                        http_share::HTTP_PORT,
-                        session_token
-                    );
-
-                    let cancel_token = CancellationToken::new();
-                    http_cancel_token = Some(cancel_token.clone());
-                    current_session_token = Some(session_token.clone());
-
-                    let http_event_tx = event_tx.clone();
-                    let token_clone = session_token.clone();
-                    let upload_state_clone = upload_state.clone();
-
-                    tokio::spawn(async move {
-                        if let Err(e) = http_share::start_default_http_server_with_websocket(
-                            &token_clone,
-                            http_event_tx.clone(),
-                            upload_state_clone,
-                            Some(cancel_token),
-                        )
-                        .await
-                        {
-                            tracing::error!("HTTP server error: {}", e);
-                            let _ = http_event_tx
-                                .send(AppEvent::Error(format!("HTTP server failed: {}", e)))
-                                .await;
-                        }
-                    });
-
-                    let _ = event_tx
-                        .send(AppEvent::HttpServerStarted { url: share_url })
-                        .await;
-                }
-
-                // Now start ngrok tunnel
-                let session_token = current_session_token.clone().unwrap_or_default();
-                let evt = event_tx.clone();
-
-                match http_share::NgrokTunnel::start(http_share::HTTP_PORT, &session_token).await {
-                    Ok(tunnel) => {
-                        let public_url = tunnel.public_url().to_string();
-                        ngrok_tunnel = Some(tunnel);
-                        let _ = evt.send(AppEvent::WanShareReady { url: public_url }).await;
-                    }
-                    Err(e) => {
-                        tracing::error!("Failed to start ngrok tunnel: {}", e);
-                        let _ = evt
-                            .send(AppEvent::WanShareError(format!(
-                                "Failed to start tunnel: {}",
-                                e
-                            )))
-                            .await;
-                    }
-                }
-            }
-            AppCommand::StopWanShare => {
-                if let Some(tunnel) = ngrok_tunnel.take() {
-                    tunnel.stop();
-                    let _ = event_tx.send(AppEvent::WanShareStopped).await;
-                    tracing::info!("WAN share tunnel stopped");
-                } else {
-                    let _ = event_tx
-                        .send(AppEvent::Status("WAN share is not running".to_string()))
-                        .await;
-                }
-            }
+impl AppEvent {
+    /// Build an [`AppEvent::Log`]
+    pub fn log(level: LogLevel, category: EventCategory, message: impl Into<String>) -> Self {
+        AppEvent::Log {
+            level,
+            category,
+            message: message.into(),
         }
     }
 }
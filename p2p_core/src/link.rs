@@ -0,0 +1,177 @@
+//! `p2p://` links that open the app from a chat message or a web page.
+//!
+//! `p2p://connect/<endpoint-id>` names a device to dial over the WAN, and
+//! `p2p://share/<token>?at=<origin>` the share page another device serves
+//! at `<origin>/<token>`, the token being that share's session token. The
+//! OS passes a clicked link to the app as its only argument; frontends
+//! parse it with [`AppLink::parse`] and decide what to open.
+
+use anyhow::{Context, Result, anyhow};
+use iroh::PublicKey;
+use std::fmt;
+use std::str::FromStr;
+use url::Url;
+
+/// URI scheme of app links
+pub const LINK_SCHEME: &str = "p2p";
+
+/// What a `p2p://` link asks the app to open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppLink {
+    /// Fill in the WAN connect window with this device
+    Connect { endpoint_id: String },
+    /// Show the share page at `at`, under `token`
+    Share { token: String, at: Url },
+}
+
+impl AppLink {
+    /// Parse a link produced by [`to_uri`](Self::to_uri)
+    pub fn parse(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri.trim()).context("Not a valid link")?;
+        if url.scheme() != LINK_SCHEME {
+            return Err(anyhow!("Not a {}:// link", LINK_SCHEME));
+        }
+        let value = url
+            .path()
+            .strip_prefix('/')
+            .filter(|value| !value.is_empty() && !value.contains('/'))
+            .ok_or_else(|| anyhow!("The link names nothing to open"))?;
+        match url.host_str() {
+            Some("connect") => {
+                let key = PublicKey::from_str(value).context("Not an endpoint ID")?;
+                Ok(Self::Connect {
+                    endpoint_id: key.to_string(),
+                })
+            }
+            Some("share") => {
+                if !value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    return Err(anyhow!("Not a share token"));
+                }
+                let at = url
+                    .query_pairs()
+                    .find(|(key, _)| key == "at")
+                    .ok_or_else(|| anyhow!("The share link does not say where the share is"))?
+                    .1;
+                Ok(Self::Share {
+                    token: value.to_string(),
+                    at: share_origin(&at)?,
+                })
+            }
+            _ => Err(anyhow!("Unknown {}:// link", LINK_SCHEME)),
+        }
+    }
+
+    /// Link to the share page at `share_url`, as the share announces it:
+    /// its origin followed by the session token
+    pub fn for_share_page(share_url: &str) -> Result<Self> {
+        let url = Url::parse(share_url.trim()).context("Not a valid share URL")?;
+        let token = url
+            .path_segments()
+            .and_then(|mut segments| segments.find(|segment| !segment.is_empty()))
+            .ok_or_else(|| anyhow!("The share URL has no token"))?
+            .to_string();
+        Self::parse(&Self::Share { token, at: url }.to_uri())
+    }
+
+    /// Encode as `p2p://connect/<id>` or `p2p://share/<token>?at=<origin>`
+    pub fn to_uri(&self) -> String {
+        match self {
+            Self::Connect { endpoint_id } => {
+                format!("{}://connect/{}", LINK_SCHEME, endpoint_id)
+            }
+            Self::Share { token, at } => {
+                let mut url = Url::parse(&format!("{}://share/{}", LINK_SCHEME, token))
+                    .expect("share tokens form a valid path");
+                url.query_pairs_mut()
+                    .append_pair("at", at.origin().ascii_serialization().as_str());
+                url.to_string()
+            }
+        }
+    }
+
+    /// Address of the share page a [`Share`](Self::Share) link names
+    pub fn share_page(&self) -> Option<String> {
+        match self {
+            Self::Share { token, at } => {
+                Some(format!("{}/{}/", at.origin().ascii_serialization(), token))
+            }
+            Self::Connect { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for AppLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_uri())
+    }
+}
+
+/// Web origin of a share, without path or query
+fn share_origin(at: &str) -> Result<Url> {
+    let url = Url::parse(at).context("The share's address is not a URL")?;
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        return Err(anyhow!("Share pages are served over http or https"));
+    }
+    Url::parse(&url.origin().ascii_serialization()).context("The share has no web origin")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint_id() -> String {
+        iroh::SecretKey::generate(&mut rand::rng())
+            .public()
+            .to_string()
+    }
+
+    #[test]
+    fn test_links_round_trip() {
+        let connect = AppLink::Connect {
+            endpoint_id: endpoint_id(),
+        };
+        assert_eq!(AppLink::parse(&connect.to_uri()).unwrap(), connect);
+
+        let share =
+            AppLink::for_share_page("https://abcd.ngrok.app/0123456789abcdef0123456789abcdef")
+                .unwrap();
+        assert_eq!(
+            share.to_uri(),
+            "p2p://share/0123456789abcdef0123456789abcdef?at=https%3A%2F%2Fabcd.ngrok.app"
+        );
+        assert_eq!(AppLink::parse(&share.to_uri()).unwrap(), share);
+        assert_eq!(
+            share.share_page().unwrap(),
+            "https://abcd.ngrok.app/0123456789abcdef0123456789abcdef/"
+        );
+
+        let lan = AppLink::for_share_page("http://192.168.1.20:8080/token/").unwrap();
+        assert_eq!(lan.share_page().unwrap(), "http://192.168.1.20:8080/token/");
+    }
+
+    #[test]
+    fn test_bad_links_are_rejected() {
+        for bad in [
+            "https://connect/abc",
+            "p2p://connect/",
+            "p2p://connect/not-a-key",
+            "p2p://pair/abc",
+            "p2p://share/token",
+            "p2p://share/to%20ken?at=https://example.com",
+            "p2p://share/token?at=file:///etc",
+            "p2p://share/token/extra?at=https://example.com",
+        ] {
+            assert!(AppLink::parse(bad).is_err(), "{}", bad);
+        }
+        // Only the origin of `at` is kept
+        let AppLink::Share { at, .. } =
+            AppLink::parse("p2p://share/token?at=https://example.com/elsewhere?x=1").unwrap()
+        else {
+            panic!("not a share link");
+        };
+        assert_eq!(at.as_str(), "https://example.com/");
+    }
+}
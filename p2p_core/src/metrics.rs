@@ -0,0 +1,152 @@
+//! Process-wide transfer counters and the periodic metrics sample.
+//!
+//! Every [`ProgressReporter`](crate::transfer::ProgressReporter), LAN or
+//! WAN, adds the bytes it sees here. Every [`METRICS_INTERVAL`] the backend
+//! turns the counters into [`AppEvent::MetricsSample`] with current rates,
+//! so frontends and headless deployments show the same numbers instead of
+//! summing progress events themselves. CPU and memory are sampled only when
+//! [`NodeConfig::sample_cpu`](crate::NodeConfig::sample_cpu) is set.
+
+use crate::AppEvent;
+use crate::history::transfers::Direction;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use sysinfo::System;
+
+/// How often the backend reports [`AppEvent::MetricsSample`]
+pub const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_TRANSFERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Count `bytes` that went through a transfer
+pub fn add(direction: Direction, bytes: u64) {
+    let counter = match direction {
+        Direction::Sent => &BYTES_SENT,
+        Direction::Received => &BYTES_RECEIVED,
+    };
+    counter.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Bytes sent and received by this process so far
+pub fn totals() -> (u64, u64) {
+    (
+        BYTES_SENT.load(Ordering::Relaxed),
+        BYTES_RECEIVED.load(Ordering::Relaxed),
+    )
+}
+
+/// Counts one file in flight while alive
+#[derive(Debug)]
+pub(crate) struct ActiveTransfer(());
+
+impl ActiveTransfer {
+    pub(crate) fn start() -> Self {
+        ACTIVE_TRANSFERS.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for ActiveTransfer {
+    fn drop(&mut self) {
+        ACTIVE_TRANSFERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Turns the counters into rates between two samples
+pub struct MetricsSampler {
+    last: Instant,
+    sent: u64,
+    received: u64,
+    system: Option<System>,
+}
+
+impl MetricsSampler {
+    /// Sampler starting from the current counters; `cpu` adds CPU and
+    /// memory usage to every sample
+    pub fn new(cpu: bool) -> Self {
+        let (sent, received) = totals();
+        Self {
+            last: Instant::now(),
+            sent,
+            received,
+            system: cpu.then(System::new),
+        }
+    }
+
+    /// Sample now
+    pub fn sample(&mut self) -> AppEvent {
+        let now = Instant::now();
+        let (sent, received) = totals();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        let rate = |now: u64, before: u64| {
+            if elapsed > 0.0 {
+                now.saturating_sub(before) as f64 / elapsed
+            } else {
+                0.0
+            }
+        };
+        let event = AppEvent::MetricsSample {
+            upload_bps: rate(sent, self.sent),
+            download_bps: rate(received, self.received),
+            bytes_sent: sent,
+            bytes_received: received,
+            active_transfers: ACTIVE_TRANSFERS.load(Ordering::Relaxed),
+            system: self.system.as_mut().map(|system| {
+                system.refresh_cpu_usage();
+                system.refresh_memory();
+                SystemUsage {
+                    cpu_percent: system.global_cpu_usage(),
+                    memory_used: system.used_memory(),
+                    memory_total: system.total_memory(),
+                }
+            }),
+        };
+        self.last = now;
+        self.sent = sent;
+        self.received = received;
+        event
+    }
+}
+
+/// CPU and memory of the machine, in a [`AppEvent::MetricsSample`]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct SystemUsage {
+    /// Average over all cores since the previous sample
+    pub cpu_percent: f32,
+    /// Bytes
+    pub memory_used: u64,
+    pub memory_total: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_reports_bytes_since_the_last_one() {
+        let mut sampler = MetricsSampler::new(false);
+        add(Direction::Sent, 1000);
+        add(Direction::Received, 10);
+        let _transfer = ActiveTransfer::start();
+        std::thread::sleep(Duration::from_millis(10));
+
+        let AppEvent::MetricsSample {
+            upload_bps,
+            download_bps,
+            bytes_sent,
+            active_transfers,
+            system,
+            ..
+        } = sampler.sample()
+        else {
+            panic!("not a metrics sample");
+        };
+        // Other tests may count bytes and transfers at the same time
+        assert!(upload_bps > 0.0 && download_bps > 0.0);
+        assert!(bytes_sent >= 1000);
+        assert!(active_transfers >= 1);
+        assert!(system.is_none());
+    }
+}
@@ -0,0 +1,206 @@
+//! Whether the connection to the internet is metered.
+//!
+//! Windows knows the cost of the active connection profile. macOS has no such
+//! setting for Wi-Fi, so a phone's hotspot is recognized instead: an iPhone
+//! hands out addresses from `172.20.10.0/28`, and Android marks its DHCP
+//! offers `ANDROID_METERED`. Elsewhere the cost is unknown.
+//!
+//! While the connection counts as metered (see [`MeteredMode`]) the backend
+//! sends at most [`METERED_SEND_RATE`], stops the WAN share tunnel, and holds
+//! sends above [`METERED_CONFIRM_BYTES`] until the user confirms them.
+
+use serde::{Deserialize, Serialize};
+
+/// Upload rate of LAN sends started on a metered connection, bytes per second
+pub const METERED_SEND_RATE: u64 = 1_000_000;
+
+/// Sends larger than this wait for confirmation on a metered connection
+pub const METERED_CONFIRM_BYTES: u64 = 100_000_000;
+
+/// What the OS says about the active connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkCost {
+    Unmetered,
+    /// Billed by the byte, roaming, or a phone's hotspot
+    Metered,
+    #[default]
+    Unknown,
+}
+
+/// The user's override of the detected [`NetworkCost`], stored in
+/// `config.json`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeteredMode {
+    /// Follow the OS
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl MeteredMode {
+    pub const ALL: [MeteredMode; 3] = [MeteredMode::Auto, MeteredMode::Always, MeteredMode::Never];
+
+    pub fn is_metered(self, detected: NetworkCost) -> bool {
+        match self {
+            MeteredMode::Auto => detected == NetworkCost::Metered,
+            MeteredMode::Always => true,
+            MeteredMode::Never => false,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MeteredMode::Auto => "Detect",
+            MeteredMode::Always => "Always metered",
+            MeteredMode::Never => "Never metered",
+        }
+    }
+}
+
+/// Ask the OS about the active connection; blocks for a moment on macOS,
+/// which runs `route` and `ipconfig`
+pub fn detect() -> NetworkCost {
+    platform::detect()
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::NetworkCost;
+    use windows::Networking::Connectivity::{NetworkCostType, NetworkInformation};
+
+    pub fn detect() -> NetworkCost {
+        let Ok(cost) = NetworkInformation::GetInternetConnectionProfile()
+            .and_then(|profile| profile.GetConnectionCost())
+        else {
+            // No internet connection at all
+            return NetworkCost::Unknown;
+        };
+        let cost_type = cost.NetworkCostType().unwrap_or(NetworkCostType::Unknown);
+        let limited = cost_type == NetworkCostType::Fixed
+            || cost_type == NetworkCostType::Variable
+            || cost.Roaming().unwrap_or(false)
+            || cost.OverDataLimit().unwrap_or(false);
+        if limited {
+            NetworkCost::Metered
+        } else if cost_type == NetworkCostType::Unrestricted {
+            NetworkCost::Unmetered
+        } else {
+            NetworkCost::Unknown
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::NetworkCost;
+    use super::hotspot::{is_hotspot, parse_default_route};
+    use std::process::Command;
+
+    pub fn detect() -> NetworkCost {
+        let Some(route) = run("route", &["-n", "get", "default"]) else {
+            return NetworkCost::Unknown;
+        };
+        let Some((gateway, interface)) = parse_default_route(&route) else {
+            // No default route, so no internet connection
+            return NetworkCost::Unknown;
+        };
+        let packet = interface.and_then(|interface| run("ipconfig", &["getpacket", &interface]));
+        if is_hotspot(gateway, packet.as_deref()) {
+            NetworkCost::Metered
+        } else {
+            NetworkCost::Unmetered
+        }
+    }
+
+    fn run(program: &str, args: &[&str]) -> Option<String> {
+        let output = Command::new(program).args(args).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    use super::NetworkCost;
+
+    pub fn detect() -> NetworkCost {
+        NetworkCost::Unknown
+    }
+}
+
+/// Recognizing a phone's hotspot from `route` and `ipconfig` output
+#[cfg(any(target_os = "macos", test))]
+mod hotspot {
+    use std::net::Ipv4Addr;
+
+    /// Gateway and interface of `route -n get default`
+    pub fn parse_default_route(output: &str) -> Option<(Option<Ipv4Addr>, Option<String>)> {
+        let mut gateway = None;
+        let mut interface = None;
+        let mut found = false;
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            match key.trim() {
+                "gateway" => {
+                    found = true;
+                    gateway = value.trim().parse().ok();
+                }
+                "interface" => {
+                    found = true;
+                    interface = Some(value.trim().to_string());
+                }
+                _ => {}
+            }
+        }
+        found.then_some((gateway, interface))
+    }
+
+    /// iPhone Personal Hotspot gateway, or an Android DHCP offer marked
+    /// metered in `ipconfig getpacket`
+    pub fn is_hotspot(gateway: Option<Ipv4Addr>, dhcp_packet: Option<&str>) -> bool {
+        let iphone = gateway.is_some_and(|ip| {
+            let [a, b, c, d] = ip.octets();
+            (a, b, c) == (172, 20, 10) && d < 16
+        });
+        iphone || dhcp_packet.is_some_and(|packet| packet.contains("ANDROID_METERED"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hotspot::*;
+    use super::*;
+
+    #[test]
+    fn test_mode_overrides_detection() {
+        assert!(MeteredMode::Auto.is_metered(NetworkCost::Metered));
+        assert!(!MeteredMode::Auto.is_metered(NetworkCost::Unknown));
+        assert!(MeteredMode::Always.is_metered(NetworkCost::Unmetered));
+        assert!(!MeteredMode::Never.is_metered(NetworkCost::Metered));
+    }
+
+    #[test]
+    fn test_hotspot_recognized() {
+        let route = "   route to: default\ndestination: default\n       mask: default\n    gateway: 172.20.10.1\n  interface: en0\n      flags: <UP,GATEWAY,DONE,STATIC,PRCLONING>\n";
+        let (gateway, interface) = parse_default_route(route).unwrap();
+        assert_eq!(gateway, Some("172.20.10.1".parse().unwrap()));
+        assert_eq!(interface.as_deref(), Some("en0"));
+        assert!(is_hotspot(gateway, None));
+
+        assert!(!is_hotspot(Some("192.168.1.1".parse().unwrap()), None));
+        let android =
+            "op = BOOTREPLY\noptions:\nOption 43 (vendor_specific): opaque ANDROID_METERED\n";
+        assert!(is_hotspot(
+            Some("192.168.43.1".parse().unwrap()),
+            Some(android)
+        ));
+        assert!(parse_default_route("route: writing to routing socket: not in table").is_none());
+    }
+}
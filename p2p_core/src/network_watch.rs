@@ -0,0 +1,123 @@
+//! Noticing that the machine moved to another network.
+//!
+//! Switching Wi-Fi networks changes this device's LAN address: the share
+//! URL points nowhere and the peers found on the old network are gone. No
+//! OS event is portable, so the backend lists the interface addresses every
+//! [`NETWORK_CHECK_INTERVAL`] and compares them with the last list. On a
+//! change it rediscovers peers and announces the share URL again.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// How often the interface addresses are listed
+pub const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Interface name and address
+pub type InterfaceAddr = (String, IpAddr);
+
+/// Non-loopback IPv4 addresses of the interfaces that are up, sorted
+pub fn interface_addrs() -> Vec<InterfaceAddr> {
+    let mut addrs: Vec<_> = local_ip_address::list_afinet_netifas()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, ip)| ip.is_ipv4() && !ip.is_loopback())
+        .collect();
+    addrs.sort();
+    addrs
+}
+
+/// The best address for share URLs, preferring LAN ranges (192.168.x.x,
+/// then 10.x.x.x, then 172.x.x.x)
+pub fn lan_ip(addrs: &[InterfaceAddr]) -> Option<IpAddr> {
+    let rank = |ip: &IpAddr| match ip {
+        IpAddr::V4(v4) => match v4.octets() {
+            [192, 168, ..] => 0,
+            [10, ..] => 1,
+            [172, ..] => 2,
+            _ => 3,
+        },
+        IpAddr::V6(_) => 4,
+    };
+    addrs
+        .iter()
+        .map(|(_, ip)| *ip)
+        .filter(|ip| !ip.is_loopback())
+        .min_by_key(rank)
+}
+
+/// Addresses that came and went between two checks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkChange {
+    pub added: Vec<IpAddr>,
+    pub removed: Vec<IpAddr>,
+}
+
+/// Interface addresses of the last check
+#[derive(Debug, Default)]
+pub struct NetworkWatcher {
+    last: Option<Vec<InterfaceAddr>>,
+}
+
+impl NetworkWatcher {
+    /// Compare `addrs` with the last check. The first check only records
+    /// them.
+    pub fn check(&mut self, addrs: Vec<InterfaceAddr>) -> Option<NetworkChange> {
+        let last = self.last.replace(addrs.clone())?;
+        let ips = |list: &[InterfaceAddr]| list.iter().map(|(_, ip)| *ip).collect::<Vec<_>>();
+        let (before, after) = (ips(&last), ips(&addrs));
+        let added: Vec<_> = after
+            .iter()
+            .filter(|ip| !before.contains(ip))
+            .copied()
+            .collect();
+        let removed: Vec<_> = before
+            .iter()
+            .filter(|ip| !after.contains(ip))
+            .copied()
+            .collect();
+        (!added.is_empty() || !removed.is_empty()).then_some(NetworkChange { added, removed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(name: &str, ip: &str) -> InterfaceAddr {
+        (name.to_string(), ip.parse().unwrap())
+    }
+
+    #[test]
+    fn test_lan_ranges_are_preferred() {
+        let addrs = [
+            addr("tun0", "100.64.0.2"),
+            addr("docker0", "172.17.0.1"),
+            addr("eth0", "10.0.0.5"),
+        ];
+        assert_eq!(lan_ip(&addrs), Some("10.0.0.5".parse().unwrap()));
+        let addrs = [addr("eth0", "10.0.0.5"), addr("wlan0", "192.168.1.20")];
+        assert_eq!(lan_ip(&addrs), Some("192.168.1.20".parse().unwrap()));
+        assert_eq!(lan_ip(&[]), None);
+    }
+
+    #[test]
+    fn test_switching_networks_is_a_change() {
+        let mut watcher = NetworkWatcher::default();
+        let home = vec![addr("wlan0", "192.168.1.20")];
+        assert_eq!(watcher.check(home.clone()), None);
+        assert_eq!(watcher.check(home), None);
+
+        let office = vec![addr("wlan0", "10.1.2.3")];
+        assert_eq!(
+            watcher.check(office.clone()),
+            Some(NetworkChange {
+                added: vec!["10.1.2.3".parse().unwrap()],
+                removed: vec!["192.168.1.20".parse().unwrap()],
+            })
+        );
+
+        // Same address on another interface is the same network
+        assert_eq!(watcher.check(vec![addr("eth0", "10.1.2.3")]), None);
+        assert!(watcher.check(Vec::new()).is_some());
+    }
+}
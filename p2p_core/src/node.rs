@@ -0,0 +1,852 @@
+//! Embeddable node facade.
+//!
+//! [`P2pNode`] wraps [`run_backend_with_config`](crate::run_backend_with_config)
+//! behind a small, stable surface so applications other than the bundled GUI
+//! can drive the transfer engine without wiring channels by hand.
+//!
+//! ```no_run
+//! use p2p_core::{AppEvent, P2pNode};
+//!
+//! # async fn demo() -> anyhow::Result<()> {
+//! let mut node = P2pNode::builder()
+//!     .download_dir("/tmp/p2p_downloads")
+//!     .spawn();
+//!
+//! let transfer = node
+//!     .send_files("192.168.1.20", "Office-PC", vec!["report.pdf".into()])
+//!     .await?;
+//!
+//! while let Some(event) = node.next_event().await {
+//!     match event {
+//!         AppEvent::RequestVerificationCode { .. } => {
+//!             transfer.submit_verification_code("1234").await?;
+//!         }
+//!         AppEvent::TransferCompleted { file_name, .. } => {
+//!             println!("sent {}", file_name);
+//!             break;
+//!         }
+//!         _ => {}
+//!     }
+//! }
+//!
+//! node.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::alpn::AppId;
+use crate::discovery::DISCOVERY_PORT;
+use crate::events::{EventBus, EventCategory, EventSubscription};
+use crate::history::HISTORY_FILE;
+use crate::history::usage::BandwidthCap;
+use crate::http_share::UploadApprovalPolicy;
+use crate::journal::JOURNAL_FILE;
+use crate::json_events::EventOutput;
+use crate::network_info::MeteredMode;
+use crate::pairing::{FilePairingStore, PairingStore};
+use crate::policy::Policy;
+use crate::proxy::ProxySettings;
+use crate::rendezvous::RendezvousSettings;
+use crate::retention::RetentionPolicy;
+use crate::schedule::SCHEDULE_FILE;
+use crate::storage::{LocalStorage, Storage};
+use crate::telemetry::TelemetrySettings;
+use crate::transfer::netem::NetworkEmulation;
+use crate::transfer::orphans::DEFAULT_ORPHAN_AGE_DAYS;
+use crate::transfer::{
+    HashAlgorithm, ReceiveLimits, RelayPolicy, SocketBuffers, StreamSource, TRANSFER_PORT,
+};
+use crate::units::UnitPreference;
+use crate::{AppCommand, AppEvent, config};
+use anyhow::{Result, anyhow};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// Default capacity of the command and event channels
+const DEFAULT_CHANNEL_CAPACITY: usize = 1000;
+
+/// Ports and directories used by a running backend
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// UDP port for LAN discovery
+    pub discovery_port: u16,
+    /// UDP port for the QUIC transfer server (0 = ephemeral)
+    pub transfer_port: u16,
+    /// Directory where received files are written
+    pub download_dir: PathBuf,
+    /// Endpoint ID announced to peers (defaults to the Iroh identity)
+    pub endpoint_id: Option<String>,
+    /// Iroh secret key proving ownership of the endpoint ID (defaults to
+    /// the stored identity)
+    pub secret_key: Option<iroh::SecretKey>,
+    /// Device name announced to peers (defaults to the hostname)
+    pub device_name: Option<String>,
+    /// Whether to bind the discovery port and broadcast on the LAN
+    pub enable_discovery: bool,
+    /// Trusted devices that may send without a verification code
+    pub pairing_store: Arc<dyn PairingStore>,
+    /// Restore the sender's modification time and permissions on received files
+    pub preserve_metadata: bool,
+    /// Let senders mark received files executable (see
+    /// [`crate::transfer::metadata::received_mode`])
+    pub preserve_executable: bool,
+    /// Send each file's extended attributes (see [`crate::transfer::xattrs`])
+    pub send_extended_attributes: bool,
+    /// Ask receivers for signed receipts (see [`crate::transfer::receipt`])
+    pub request_receipts: bool,
+    /// Hash received files as the disk returns them rather than from the
+    /// page cache (see [`crate::transfer::verify`])
+    pub verify_read_back: bool,
+    /// Transliterate names the file system rejects (see
+    /// [`crate::transfer::filename`])
+    pub transliterate_names: bool,
+    /// Verification codes shown at once (see [`crate::pairing::queue`])
+    pub max_concurrent_pairings: usize,
+    /// How long a sender waits for the user to enter the receiver's code
+    pub verification_timeout: Duration,
+    /// Discovery room key; `None` sees every instance on the LAN
+    pub room_key: Option<String>,
+    /// Drop-box mode: receive only, refuse every outgoing command
+    pub receive_only: bool,
+    /// Machine-wide restrictions over everything else here (see
+    /// [`crate::policy`]); [`run_profile_backend`](crate::run_profile_backend)
+    /// loads the installed one
+    pub policy: Policy,
+    /// Where scheduled sends are persisted (`None` = memory only)
+    pub schedule_file: Option<PathBuf>,
+    /// Cleanup rules for `download_dir` (off by default)
+    pub retention: RetentionPolicy,
+    /// Days before an unfinished partial file in `download_dir` is
+    /// reported (see [`crate::transfer::orphans`]), 0 = never
+    pub orphan_age_days: u32,
+    /// Hash index of received files (`None` = memory only)
+    pub history_file: Option<PathBuf>,
+    /// Journal of unfinished outgoing sends (`None` = memory only)
+    pub journal_file: Option<PathBuf>,
+    /// Units for sizes and speeds in events
+    pub units: UnitPreference,
+    /// Save received files in `download_dir/<sender>/`
+    pub per_peer_folders: bool,
+    /// POST a notification here for every received file
+    pub webhook_url: Option<String>,
+    /// Command line run for every received file
+    pub post_receive_command: Option<String>,
+    /// Serve the owner page (see [`crate::http_share::owner`]) with the
+    /// HTTP share server
+    pub owner_mode: bool,
+    /// Browser uploads accepted without asking the host
+    pub upload_approval: UploadApprovalPolicy,
+    /// Most threads used to hash a file (`None` = one per core)
+    pub hash_threads: Option<usize>,
+    /// Checksum for files sent from this node
+    pub hash_algorithm: HashAlgorithm,
+    /// Relaying for peers that cannot reach each other (off by default)
+    pub relay: RelayPolicy,
+    /// How much one peer may send at once and per day
+    pub receive_limits: ReceiveLimits,
+    /// Monthly data cap (off by default)
+    pub bandwidth_cap: BandwidthCap,
+    /// Override of the detected connection cost
+    pub metered_mode: MeteredMode,
+    /// UDP buffer sizes asked for on the LAN sockets
+    pub socket_buffers: SocketBuffers,
+    /// UDP segmentation offloads on the LAN sockets (on by default)
+    pub udp_offload: bool,
+    /// Slow link the LAN sockets send through, for testing (see
+    /// [`crate::transfer::netem`])
+    pub network_emulation: Option<NetworkEmulation>,
+    /// Application ID the LAN protocol is derived from (see [`crate::alpn`]);
+    /// only nodes with the same one connect
+    pub app_id: AppId,
+    /// Where the LAN and HTTP receivers write files
+    pub storage: Arc<dyn Storage>,
+    /// Proxy for the WAN tunnel (see [`crate::proxy`])
+    pub proxy: ProxySettings,
+    /// Rendezvous server to register with (see [`crate::rendezvous`])
+    pub rendezvous: RendezvousSettings,
+    /// Anonymous usage statistics (see [`crate::telemetry`]), off by default
+    pub telemetry: TelemetrySettings,
+    /// Iroh endpoint whose relay connection health events report (see
+    /// [`crate::health`]); the latest one sent when the WAN listener is
+    /// rebuilt for another profile
+    pub wan_endpoint: Option<watch::Receiver<iroh::Endpoint>>,
+    /// Also write every event as a JSON line here (see
+    /// [`crate::json_events`]); defaults to `P2P_EVENT_OUTPUT`
+    pub event_output: Option<EventOutput>,
+    /// Add CPU and memory usage to [`AppEvent::MetricsSample`](crate::AppEvent::MetricsSample)
+    pub sample_cpu: bool,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            discovery_port: DISCOVERY_PORT,
+            transfer_port: TRANSFER_PORT,
+            download_dir: config::get_download_dir(),
+            endpoint_id: None,
+            secret_key: None,
+            device_name: None,
+            enable_discovery: true,
+            pairing_store: Arc::new(FilePairingStore::default()),
+            preserve_metadata: true,
+            preserve_executable: false,
+            send_extended_attributes: false,
+            request_receipts: false,
+            verify_read_back: false,
+            transliterate_names: true,
+            max_concurrent_pairings: crate::pairing::queue::DEFAULT_MAX_CONCURRENT_PAIRINGS,
+            verification_timeout: crate::transfer::constants::get_pairing_timeout(),
+            room_key: None,
+            receive_only: false,
+            policy: Policy::default(),
+            schedule_file: config::get_config_dir().map(|dir| dir.join(SCHEDULE_FILE)),
+            retention: RetentionPolicy::default(),
+            orphan_age_days: DEFAULT_ORPHAN_AGE_DAYS,
+            history_file: config::get_config_dir().map(|dir| dir.join(HISTORY_FILE)),
+            journal_file: config::get_config_dir().map(|dir| dir.join(JOURNAL_FILE)),
+            units: UnitPreference::default(),
+            per_peer_folders: false,
+            webhook_url: None,
+            post_receive_command: None,
+            owner_mode: false,
+            upload_approval: UploadApprovalPolicy::default(),
+            hash_threads: None,
+            hash_algorithm: HashAlgorithm::default(),
+            relay: RelayPolicy::default(),
+            receive_limits: ReceiveLimits::default(),
+            bandwidth_cap: BandwidthCap::default(),
+            metered_mode: MeteredMode::default(),
+            socket_buffers: SocketBuffers::default(),
+            udp_offload: true,
+            network_emulation: None,
+            app_id: AppId::default(),
+            storage: Arc::new(LocalStorage),
+            proxy: ProxySettings::default(),
+            rendezvous: RendezvousSettings::default(),
+            telemetry: TelemetrySettings::default(),
+            wan_endpoint: None,
+            sample_cpu: false,
+            event_output: EventOutput::from_env(),
+        }
+    }
+}
+
+/// Builder for [`P2pNode`]
+#[derive(Debug, Clone)]
+pub struct P2pNodeBuilder {
+    config: NodeConfig,
+    channel_capacity: usize,
+}
+
+impl Default for P2pNodeBuilder {
+    fn default() -> Self {
+        Self {
+            config: NodeConfig::default(),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+        }
+    }
+}
+
+impl P2pNodeBuilder {
+    /// Start from `config` instead of the defaults
+    pub fn from_config(config: NodeConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Override the UDP discovery port
+    pub fn discovery_port(mut self, port: u16) -> Self {
+        self.config.discovery_port = port;
+        self
+    }
+
+    /// Override the QUIC transfer port (0 picks an ephemeral port)
+    pub fn transfer_port(mut self, port: u16) -> Self {
+        self.config.transfer_port = port;
+        self
+    }
+
+    /// Override the directory received files are written to
+    pub fn download_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.download_dir = dir.into();
+        self
+    }
+
+    /// Announce a fixed endpoint ID instead of the stored identity
+    pub fn endpoint_id(mut self, id: impl Into<String>) -> Self {
+        self.config.endpoint_id = Some(id.into());
+        self
+    }
+
+    /// Use this identity instead of the stored one. Unless overridden with
+    /// [`endpoint_id`](Self::endpoint_id), the key's public half is announced.
+    pub fn secret_key(mut self, key: iroh::SecretKey) -> Self {
+        self.config.secret_key = Some(key);
+        self
+    }
+
+    /// Announce a fixed device name instead of the hostname
+    pub fn device_name(mut self, name: impl Into<String>) -> Self {
+        self.config.device_name = Some(name.into());
+        self
+    }
+
+    /// Turn off LAN discovery entirely (no UDP socket, no broadcasts)
+    pub fn disable_discovery(mut self) -> Self {
+        self.config.enable_discovery = false;
+        self
+    }
+
+    /// Use a custom pairing store (e.g. [`MemoryPairingStore`](crate::pairing::MemoryPairingStore))
+    pub fn pairing_store(mut self, store: Arc<dyn PairingStore>) -> Self {
+        self.config.pairing_store = store;
+        self
+    }
+
+    /// Restore (or with `false`, drop) the sender's modification time and
+    /// permissions on received files
+    pub fn preserve_metadata(mut self, enabled: bool) -> Self {
+        self.config.preserve_metadata = enabled;
+        self
+    }
+
+    /// Let senders mark received files executable (or with `false`, never)
+    pub fn preserve_executable(mut self, enabled: bool) -> Self {
+        self.config.preserve_executable = enabled;
+        self
+    }
+
+    /// Send (or with `false`, leave out) each file's extended attributes
+    pub fn send_extended_attributes(mut self, enabled: bool) -> Self {
+        self.config.send_extended_attributes = enabled;
+        self
+    }
+
+    /// Ask (or with `false`, do not ask) receivers for a signed receipt of
+    /// each file
+    pub fn request_receipts(mut self, enabled: bool) -> Self {
+        self.config.request_receipts = enabled;
+        self
+    }
+
+    /// Read received files back from the disk to check them; slower, but
+    /// catches storage that loses writes
+    pub fn verify_read_back(mut self, enabled: bool) -> Self {
+        self.config.verify_read_back = enabled;
+        self
+    }
+
+    /// Save files with names the file system rejects under an ASCII
+    /// transliteration (or with `false`, fail them)
+    pub fn transliterate_names(mut self, enabled: bool) -> Self {
+        self.config.transliterate_names = enabled;
+        self
+    }
+
+    /// Show at most `limit` verification codes at once and queue the
+    /// senders beyond it
+    pub fn max_concurrent_pairings(mut self, limit: usize) -> Self {
+        self.config.max_concurrent_pairings = limit;
+        self
+    }
+
+    /// Give up on an unanswered verification prompt after `timeout`
+    pub fn verification_timeout(mut self, timeout: Duration) -> Self {
+        self.config.verification_timeout = timeout;
+        self
+    }
+
+    /// Only discover (and be discovered by) peers using the same room key
+    pub fn room_key(mut self, key: impl Into<String>) -> Self {
+        self.config.room_key = Some(key.into());
+        self
+    }
+
+    /// Only receive files; sends are rejected and peers are told so
+    pub fn receive_only(mut self, enabled: bool) -> Self {
+        self.config.receive_only = enabled;
+        self
+    }
+
+    /// Enforce `policy`, as an administrator's policy file would
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.config.policy = policy;
+        self
+    }
+
+    /// Persist scheduled sends in `path` instead of the config directory
+    pub fn schedule_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.schedule_file = Some(path.into());
+        self
+    }
+
+    /// Clean up the download folder according to `policy`
+    pub fn retention(mut self, policy: RetentionPolicy) -> Self {
+        self.config.retention = policy;
+        self
+    }
+
+    /// Warn at `cap`, and hold scheduled sends once it is reached if it says so
+    pub fn bandwidth_cap(mut self, cap: BandwidthCap) -> Self {
+        self.config.bandwidth_cap = cap;
+        self
+    }
+
+    /// Treat the connection as metered or not instead of asking the OS
+    pub fn metered_mode(mut self, mode: MeteredMode) -> Self {
+        self.config.metered_mode = mode;
+        self
+    }
+
+    /// Ask for `buffers` on the LAN sockets instead of the platform default
+    pub fn socket_buffers(mut self, buffers: SocketBuffers) -> Self {
+        self.config.socket_buffers = buffers;
+        self
+    }
+
+    /// Turn the UDP segmentation offloads (GSO/GRO) of the LAN sockets on
+    /// or off
+    pub fn udp_offload(mut self, enabled: bool) -> Self {
+        self.config.udp_offload = enabled;
+        self
+    }
+
+    /// Send over the LAN through an emulated slow link
+    pub fn network_emulation(mut self, emulation: NetworkEmulation) -> Self {
+        self.config.network_emulation = Some(emulation);
+        self
+    }
+
+    /// Speak the protocol of a private deployment instead of the public app's
+    pub fn app_id(mut self, app_id: AppId) -> Self {
+        self.config.app_id = app_id;
+        self
+    }
+
+    /// Add CPU and memory usage to every metrics sample
+    pub fn sample_cpu(mut self, enabled: bool) -> Self {
+        self.config.sample_cpu = enabled;
+        self
+    }
+
+    /// Keep the received file hash index in `path` instead of the config directory
+    pub fn history_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.history_file = Some(path.into());
+        self
+    }
+
+    /// Journal unfinished sends in `path` instead of the config directory
+    pub fn journal_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.journal_file = Some(path.into());
+        self
+    }
+
+    /// Display sizes and speeds in `units`
+    pub fn units(mut self, units: UnitPreference) -> Self {
+        self.config.units = units;
+        self
+    }
+
+    /// Sort received files into a subfolder per sender
+    pub fn per_peer_folders(mut self, enabled: bool) -> Self {
+        self.config.per_peer_folders = enabled;
+        self
+    }
+
+    /// Hash files with at most `threads` threads
+    pub fn hash_threads(mut self, threads: usize) -> Self {
+        self.config.hash_threads = Some(threads);
+        self
+    }
+
+    /// Checksum files sent from this node with `algorithm`
+    pub fn hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.config.hash_algorithm = algorithm;
+        self
+    }
+
+    /// Let paired peers relay transfers through this node (see
+    /// [`crate::transfer::relay`])
+    pub fn relay(mut self, policy: RelayPolicy) -> Self {
+        self.config.relay = policy;
+        self
+    }
+
+    /// Cap what one peer may send (see [`crate::transfer::limits`])
+    pub fn receive_limits(mut self, limits: ReceiveLimits) -> Self {
+        self.config.receive_limits = limits;
+        self
+    }
+
+    /// Write received files through `storage` instead of the local file
+    /// system, e.g. a [`FailingStorage`](crate::storage::FailingStorage)
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.config.storage = storage;
+        self
+    }
+
+    /// Mirror every event as a JSON line to `output` (see
+    /// [`crate::json_events`])
+    pub fn event_output(mut self, output: EventOutput) -> Self {
+        self.config.event_output = Some(output);
+        self
+    }
+
+    /// Reach the WAN tunnel service through a proxy (see [`crate::proxy`])
+    pub fn proxy(mut self, settings: ProxySettings) -> Self {
+        self.config.proxy = settings;
+        self
+    }
+
+    /// Register with a rendezvous server (see [`crate::rendezvous`])
+    pub fn rendezvous(mut self, settings: RendezvousSettings) -> Self {
+        self.config.rendezvous = settings;
+        self
+    }
+
+    /// Report the relay connection of `endpoint` in health events
+    pub fn wan_endpoint(mut self, endpoint: iroh::Endpoint) -> Self {
+        self.config.wan_endpoint = Some(watch::channel(endpoint).1);
+        self
+    }
+
+    /// Report the relay connection of the endpoint `endpoints` last saw,
+    /// for a WAN listener that gets rebuilt
+    pub fn wan_endpoints(mut self, endpoints: watch::Receiver<iroh::Endpoint>) -> Self {
+        self.config.wan_endpoint = Some(endpoints);
+        self
+    }
+
+    /// Announce received files to `url` (see [`crate::webhook`])
+    pub fn webhook_url(mut self, url: impl Into<String>) -> Self {
+        self.config.webhook_url = Some(url.into());
+        self
+    }
+
+    /// Run `command` for every received file (see [`crate::post_receive`])
+    pub fn post_receive_command(mut self, command: impl Into<String>) -> Self {
+        self.config.post_receive_command = Some(command.into());
+        self
+    }
+
+    /// Let the owner page of the HTTP share server control this node
+    pub fn owner_mode(mut self, enabled: bool) -> Self {
+        self.config.owner_mode = enabled;
+        self
+    }
+
+    /// Accept browser uploads matching `policy` without asking
+    pub fn upload_approval(mut self, policy: UploadApprovalPolicy) -> Self {
+        self.config.upload_approval = policy;
+        self
+    }
+
+    /// Set the capacity of the command and event channels
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.channel_capacity = capacity.max(1);
+        self
+    }
+
+    /// Start the backend on the current tokio runtime
+    pub fn spawn(self) -> P2pNode {
+        let (cmd_tx, cmd_rx) = mpsc::channel(self.channel_capacity);
+        let (event_tx, event_rx) = mpsc::channel(self.channel_capacity);
+
+        let bus = EventBus::new(self.channel_capacity);
+        let events = bus.subscribe_all();
+        let forwarder = bus.forward(event_rx);
+
+        let backend_event_tx = event_tx.clone();
+        let config = self.config.clone();
+        let task = tokio::spawn(async move {
+            crate::run_backend_with_config(config, cmd_rx, backend_event_tx).await;
+        });
+
+        P2pNode {
+            config: self.config,
+            cmd_tx,
+            event_tx,
+            bus,
+            events,
+            tasks: vec![task, forwarder],
+        }
+    }
+}
+
+/// A running transfer engine
+pub struct P2pNode {
+    config: NodeConfig,
+    cmd_tx: mpsc::Sender<AppCommand>,
+    event_tx: mpsc::Sender<AppEvent>,
+    bus: EventBus,
+    events: EventSubscription,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl P2pNode {
+    /// Create a builder with default ports and directories
+    pub fn builder() -> P2pNodeBuilder {
+        P2pNodeBuilder::default()
+    }
+
+    /// Configuration the node was started with
+    pub fn config(&self) -> &NodeConfig {
+        &self.config
+    }
+
+    /// Send a raw command to the backend
+    pub async fn command(&self, cmd: AppCommand) -> Result<()> {
+        self.cmd_tx
+            .send(cmd)
+            .await
+            .map_err(|_| anyhow!("Backend is not running"))
+    }
+
+    /// Send a command and wait for the backend to acknowledge it.
+    ///
+    /// The command is wrapped in [`AppCommand::Tracked`]; the returned error
+    /// carries the backend's failure message, or a timeout if no
+    /// [`AppEvent::CommandResult`] arrives in time.
+    pub async fn execute(&self, cmd: AppCommand, timeout: Duration) -> Result<()> {
+        let (request_id, cmd) = cmd.tracked();
+        // Subscribe before sending so the acknowledgment cannot be missed
+        let mut results = self.bus.subscribe(&[EventCategory::Status]);
+        self.command(cmd).await?;
+
+        let wait = async {
+            while let Some(event) = results.recv().await {
+                if let AppEvent::CommandResult {
+                    request_id: id,
+                    result,
+                } = event
+                    && id == request_id
+                {
+                    return result.map_err(|e| anyhow!(e));
+                }
+            }
+            Err(anyhow!("Backend is not running"))
+        };
+
+        tokio::time::timeout(timeout, wait)
+            .await
+            .map_err(|_| anyhow!("Command {} timed out", request_id))?
+    }
+
+    /// Sender for raw commands, for callers that need to share it
+    pub fn command_sender(&self) -> mpsc::Sender<AppCommand> {
+        self.cmd_tx.clone()
+    }
+
+    /// Sender that injects events into this node's stream (e.g. from a WAN listener)
+    pub fn event_sender(&self) -> mpsc::Sender<AppEvent> {
+        self.event_tx.clone()
+    }
+
+    /// Wait for the next backend event
+    pub async fn next_event(&mut self) -> Option<AppEvent> {
+        self.events.recv().await
+    }
+
+    /// Return an already queued event without waiting
+    pub fn try_next_event(&mut self) -> Option<AppEvent> {
+        self.events.try_recv()
+    }
+
+    /// Independent subscription to a subset of the event stream
+    pub fn subscribe(&self, categories: &[EventCategory]) -> EventSubscription {
+        self.bus.subscribe(categories)
+    }
+
+    /// Event bus shared by all subscribers of this node
+    pub fn event_bus(&self) -> &EventBus {
+        &self.bus
+    }
+
+    /// Start sending files to a LAN peer
+    pub async fn send_files(
+        &self,
+        target_ip: &str,
+        target_peer_name: &str,
+        files: Vec<PathBuf>,
+    ) -> Result<TransferHandle> {
+        self.send_files_to_endpoint(target_ip, "", target_peer_name, files)
+            .await
+    }
+
+    /// Like [`send_files`](Self::send_files) to a peer whose endpoint ID is
+    /// known, which delivery receipts are then checked against
+    pub async fn send_files_to_endpoint(
+        &self,
+        target_ip: &str,
+        target_endpoint_id: &str,
+        target_peer_name: &str,
+        files: Vec<PathBuf>,
+    ) -> Result<TransferHandle> {
+        let session_id = crate::new_session_id();
+        self.command(AppCommand::SendFile {
+            session_id: session_id.clone(),
+            target_ip: target_ip.to_string(),
+            target_endpoint_id: target_endpoint_id.to_string(),
+            target_peer_name: target_peer_name.to_string(),
+            files: files.clone(),
+            note: None,
+        })
+        .await?;
+
+        Ok(TransferHandle {
+            session_id,
+            target_ip: target_ip.to_string(),
+            files,
+            cmd_tx: self.cmd_tx.clone(),
+        })
+    }
+
+    /// Start sending what `reader` yields to a LAN peer as one file named
+    /// `file_name`, without knowing its size (see [`crate::transfer::stream`])
+    pub async fn send_stream(
+        &self,
+        target_ip: &str,
+        target_peer_name: &str,
+        file_name: &str,
+        reader: impl AsyncRead + Send + Unpin + 'static,
+    ) -> Result<TransferHandle> {
+        let session_id = crate::new_session_id();
+        self.command(AppCommand::SendStream {
+            session_id: session_id.clone(),
+            target_ip: target_ip.to_string(),
+            target_peer_name: target_peer_name.to_string(),
+            file_name: file_name.to_string(),
+            source: StreamSource::new(reader),
+            note: None,
+        })
+        .await?;
+
+        Ok(TransferHandle {
+            session_id,
+            target_ip: target_ip.to_string(),
+            files: Vec::new(),
+            cmd_tx: self.cmd_tx.clone(),
+        })
+    }
+
+    /// Stop the backend and wait for it to exit
+    pub async fn shutdown(self) {
+        drop(self.cmd_tx);
+        for task in self.tasks {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+}
+
+/// Handle to an outgoing transfer started through [`P2pNode::send_files`]
+#[derive(Debug, Clone)]
+pub struct TransferHandle {
+    session_id: String,
+    target_ip: String,
+    files: Vec<PathBuf>,
+    cmd_tx: mpsc::Sender<AppCommand>,
+}
+
+impl TransferHandle {
+    /// Verification session of this transfer, as carried by
+    /// [`AppEvent::RequestVerificationCode`]
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// IP address of the receiving peer
+    pub fn target_ip(&self) -> &str {
+        &self.target_ip
+    }
+
+    /// Files included in this transfer
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Answer the receiver's verification prompt
+    pub async fn submit_verification_code(&self, code: &str) -> Result<()> {
+        self.cmd_tx
+            .send(AppCommand::SubmitVerificationCode {
+                session_id: self.session_id.clone(),
+                code: code.to_string(),
+            })
+            .await
+            .map_err(|_| anyhow!("Backend is not running"))
+    }
+
+    /// Request cancellation of the transfer
+    pub async fn cancel(&self) -> Result<()> {
+        self.cmd_tx
+            .send(AppCommand::CancelTransfer)
+            .await
+            .map_err(|_| anyhow!("Backend is not running"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_overrides() {
+        let builder = P2pNode::builder()
+            .discovery_port(0)
+            .transfer_port(0)
+            .download_dir("/tmp/p2p_node_test")
+            .channel_capacity(0);
+
+        assert_eq!(builder.config.discovery_port, 0);
+        assert_eq!(builder.config.transfer_port, 0);
+        assert_eq!(
+            builder.config.download_dir,
+            PathBuf::from("/tmp/p2p_node_test")
+        );
+        assert_eq!(builder.channel_capacity, 1);
+    }
+
+    #[test]
+    fn test_tracked_wraps_command() {
+        let (id, cmd) = AppCommand::StartDiscovery.tracked();
+        assert_eq!(id.len(), 32);
+        match cmd {
+            AppCommand::Tracked {
+                request_id,
+                command,
+            } => {
+                assert_eq!(request_id, id);
+                assert!(matches!(*command, AppCommand::StartDiscovery));
+            }
+            other => panic!("Unexpected command: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_reports_failure() {
+        let dir = std::env::temp_dir().join(format!("p2p_node_exec_{}", uuid::Uuid::new_v4()));
+        let node = P2pNode::builder()
+            .discovery_port(0)
+            .transfer_port(0)
+            .download_dir(&dir)
+            .spawn();
+
+        node.execute(AppCommand::StartDiscovery, Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        let err = node
+            .execute(AppCommand::StopHttpServer, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not running"));
+
+        node.shutdown().await;
+    }
+}
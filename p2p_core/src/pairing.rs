@@ -1,113 +1,327 @@
 //! Pairing management for trusted devices.
 //!
-//! Stores paired endpoint IDs with 24-hour expiry.
-
-use crate::config::{AppConfig, PairedDevice};
-use std::sync::atomic::{AtomicUsize, Ordering};
+//! Stores paired endpoint IDs with 24-hour expiry behind the [`PairingStore`]
+//! trait: [`FilePairingStore`] persists to `config.json`, while
+//! [`MemoryPairingStore`] keeps everything in memory for tests.
+//! QR-code invites that pair without a code live in [`invite`],
+//! [`lockout`] refuses senders that keep entering wrong codes, and [`key`]
+//! makes a pairing usable only by the device that made it. Devices that
+//! are not on the same network link with a one-time [`phrase`], and
+//! [`guest`] pairings trust a device for one transfer only. [`queue`] caps
+//! how many code prompts run at once and lines up the senders waiting.
+
+use crate::config::{AppConfig, PairedDevice, ReceiverKey};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
+use zeroize::Zeroizing;
+
+pub mod guest;
+pub mod invite;
+pub mod key;
+pub mod lockout;
+pub mod phrase;
+pub mod queue;
+
+pub use queue::PairingGuard;
 
 /// Pairing expires after 24 hours
 const PAIRING_EXPIRY_SECS: u64 = 24 * 60 * 60;
 
-/// Maximum concurrent pairing attempts
-const MAX_PAIRING_ATTEMPTS: usize = 3;
-
-/// Active pairing attempts counter
-static ACTIVE_PAIRING_ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
-
-/// Guard to track an active pairing attempt
-pub struct PairingGuard;
-
-impl PairingGuard {
-    /// Try to acquire a pairing slot. Returns None if limit reached.
-    pub fn try_acquire() -> Option<Self> {
-        let mut count = ACTIVE_PAIRING_ATTEMPTS.load(Ordering::Relaxed);
-        loop {
-            if count >= MAX_PAIRING_ATTEMPTS {
-                return None;
-            }
-            match ACTIVE_PAIRING_ATTEMPTS.compare_exchange_weak(
-                count,
-                count + 1,
-                Ordering::Relaxed,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => return Some(Self),
-                Err(x) => count = x,
-            }
-        }
-    }
+pub fn now_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+fn is_fresh(device: &PairedDevice, now: u64) -> bool {
+    let expiry = if device.guest {
+        guest::GUEST_PAIRING_EXPIRY_SECS
+    } else {
+        PAIRING_EXPIRY_SECS
+    };
+    now.saturating_sub(device.paired_at) < expiry
+}
+
+/// When the guest pairing `device` ends, if it is one and unexpired
+fn guest_expiry(device: Option<&PairedDevice>, now: u64) -> Option<u64> {
+    device
+        .filter(|device| device.guest && is_fresh(device, now))
+        .map(|device| device.paired_at + guest::GUEST_PAIRING_EXPIRY_SECS)
 }
 
-impl Drop for PairingGuard {
-    fn drop(&mut self) {
-        ACTIVE_PAIRING_ATTEMPTS.fetch_sub(1, Ordering::Relaxed);
+fn is_fresh_key(receiver: &ReceiverKey, now: u64) -> bool {
+    now.saturating_sub(receiver.paired_at) < PAIRING_EXPIRY_SECS
+}
+
+/// Key of an unexpired pairing made with a key
+fn fresh_key(device: Option<&PairedDevice>, now: u64) -> Option<Zeroizing<String>> {
+    device
+        .filter(|device| is_fresh(device, now) && !device.key.is_empty())
+        .map(|device| Zeroizing::new(device.key.clone()))
+}
+
+/// Storage for trusted devices, passed explicitly to the transfer server
+pub trait PairingStore: Send + Sync + std::fmt::Debug {
+    /// Whether `endpoint_id` has a pairing that has not expired
+    fn is_paired(&self, endpoint_id: &str) -> bool;
+
+    /// Key agreed with `endpoint_id`, if its pairing is unexpired and has one
+    fn pair_key(&self, endpoint_id: &str) -> Option<Zeroizing<String>>;
+
+    /// Record (or refresh) a pairing with its key and drop expired ones
+    fn add_pairing(&self, endpoint_id: &str, peer_name: &str, key: &str);
+
+    /// Like [`PairingStore::add_pairing`], for a guest that is trusted for
+    /// one transfer and at most [`guest::GUEST_PAIRING_EXPIRY_SECS`]
+    fn add_guest_pairing(&self, endpoint_id: &str, peer_name: &str, key: &str);
+
+    /// Unix time the unexpired guest pairing of `endpoint_id` ends
+    fn guest_expiry(&self, endpoint_id: &str) -> Option<u64>;
+
+    /// Whether `endpoint_id` has an unexpired guest pairing
+    fn is_guest(&self, endpoint_id: &str) -> bool {
+        self.guest_expiry(endpoint_id).is_some()
     }
+
+    fn remove_pairing(&self, endpoint_id: &str);
+
+    /// `(endpoint_id, peer_name)` of every unexpired pairing
+    fn get_all_pairings(&self) -> Vec<(String, String)>;
+
+    /// Sender side: remember the key a receiver agreed with us
+    fn add_receiver_key(&self, peer_name: &str, key: &str);
+
+    /// Sender side: the unexpired key named `key_id` by a receiver's challenge
+    fn receiver_key(&self, key_id: &str) -> Option<Zeroizing<String>>;
 }
 
-fn now_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or(Duration::ZERO)
-        .as_secs()
+/// Pairings persisted in `config.json`
+#[derive(Debug, Clone)]
+pub struct FilePairingStore {
+    path: Option<PathBuf>,
 }
 
-pub fn is_paired(endpoint_id: &str) -> bool {
-    let config = AppConfig::load();
+impl Default for FilePairingStore {
+    /// Store in the platform config directory
+    fn default() -> Self {
+        Self {
+            path: AppConfig::default_path(),
+        }
+    }
+}
 
-    if let Some(device) = config.pairing.get(endpoint_id) {
-        let now = now_timestamp();
-        let elapsed = now.saturating_sub(device.paired_at);
-        return elapsed < PAIRING_EXPIRY_SECS;
+impl FilePairingStore {
+    /// Store in an explicit config file
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+        }
     }
 
-    false
+    fn load(&self) -> AppConfig {
+        self.path
+            .as_deref()
+            .map(AppConfig::load_from)
+            .unwrap_or_default()
+    }
+
+    fn save(&self, config: &AppConfig) {
+        if let Some(path) = &self.path {
+            config.save_to(path);
+        }
+    }
+
+    fn insert(&self, endpoint_id: &str, peer_name: &str, key: &str, guest: bool) {
+        let mut config = self.load();
+        let now = now_timestamp();
+
+        config.pairing.insert(
+            endpoint_id.to_string(),
+            PairedDevice {
+                endpoint_id: endpoint_id.to_string(),
+                peer_name: peer_name.to_string(),
+                paired_at: now,
+                key: key.to_string(),
+                guest,
+            },
+        );
+        config.pairing.retain(|_, device| is_fresh(device, now));
+
+        self.save(&config);
+    }
 }
 
-pub fn add_pairing(endpoint_id: &str, peer_name: &str) {
-    let mut config = AppConfig::load();
+impl PairingStore for FilePairingStore {
+    fn is_paired(&self, endpoint_id: &str) -> bool {
+        self.load()
+            .pairing
+            .get(endpoint_id)
+            .is_some_and(|device| is_fresh(device, now_timestamp()))
+    }
+
+    fn pair_key(&self, endpoint_id: &str) -> Option<Zeroizing<String>> {
+        fresh_key(self.load().pairing.get(endpoint_id), now_timestamp())
+    }
+
+    fn add_pairing(&self, endpoint_id: &str, peer_name: &str, key: &str) {
+        self.insert(endpoint_id, peer_name, key, false);
+    }
+
+    fn add_guest_pairing(&self, endpoint_id: &str, peer_name: &str, key: &str) {
+        self.insert(endpoint_id, peer_name, key, true);
+    }
+
+    fn guest_expiry(&self, endpoint_id: &str) -> Option<u64> {
+        guest_expiry(self.load().pairing.get(endpoint_id), now_timestamp())
+    }
 
-    config.pairing.insert(
-        endpoint_id.to_string(),
-        PairedDevice {
-            endpoint_id: endpoint_id.to_string(),
-            peer_name: peer_name.to_string(),
-            paired_at: now_timestamp(),
-        },
-    );
+    fn remove_pairing(&self, endpoint_id: &str) {
+        let mut config = self.load();
+        config.pairing.remove(endpoint_id);
+        self.save(&config);
+    }
 
-    remove_expired(&mut config);
+    fn get_all_pairings(&self) -> Vec<(String, String)> {
+        let now = now_timestamp();
+        self.load()
+            .pairing
+            .values()
+            .filter(|device| is_fresh(device, now))
+            .map(|d| (d.endpoint_id.clone(), d.peer_name.clone()))
+            .collect()
+    }
 
-    config.save();
+    fn add_receiver_key(&self, peer_name: &str, key: &str) {
+        let mut config = self.load();
+        let now = now_timestamp();
+        config.receiver_keys.insert(
+            key::key_id(key),
+            ReceiverKey {
+                key: key.to_string(),
+                peer_name: peer_name.to_string(),
+                paired_at: now,
+            },
+        );
+        config
+            .receiver_keys
+            .retain(|_, receiver| is_fresh_key(receiver, now));
+        self.save(&config);
+    }
+
+    fn receiver_key(&self, key_id: &str) -> Option<Zeroizing<String>> {
+        self.load()
+            .receiver_keys
+            .get(key_id)
+            .filter(|receiver| is_fresh_key(receiver, now_timestamp()))
+            .map(|receiver| Zeroizing::new(receiver.key.clone()))
+    }
 }
 
-pub fn remove_pairing(endpoint_id: &str) {
-    let mut config = AppConfig::load();
-    config.pairing.remove(endpoint_id);
-    config.save();
+/// Pairings kept in memory only, for tests and ephemeral nodes
+#[derive(Debug, Default)]
+pub struct MemoryPairingStore {
+    devices: Mutex<HashMap<String, PairedDevice>>,
+    receiver_keys: Mutex<HashMap<String, ReceiverKey>>,
 }
 
-fn remove_expired(config: &mut AppConfig) {
-    let now = now_timestamp();
-    config.pairing.retain(|_, device| {
-        let elapsed = now.saturating_sub(device.paired_at);
-        elapsed < PAIRING_EXPIRY_SECS
-    });
+impl MemoryPairingStore {
+    /// Insert a pairing with an explicit timestamp (e.g. to test expiry)
+    pub fn insert_at(&self, endpoint_id: &str, peer_name: &str, key: &str, paired_at: u64) {
+        self.insert(endpoint_id, peer_name, key, paired_at, false);
+    }
+
+    /// Insert a guest pairing with an explicit timestamp
+    pub fn insert_guest_at(&self, endpoint_id: &str, peer_name: &str, key: &str, paired_at: u64) {
+        self.insert(endpoint_id, peer_name, key, paired_at, true);
+    }
+
+    fn insert(&self, endpoint_id: &str, peer_name: &str, key: &str, paired_at: u64, guest: bool) {
+        self.devices().insert(
+            endpoint_id.to_string(),
+            PairedDevice {
+                endpoint_id: endpoint_id.to_string(),
+                peer_name: peer_name.to_string(),
+                paired_at,
+                key: key.to_string(),
+                guest,
+            },
+        );
+    }
+
+    fn devices(&self) -> std::sync::MutexGuard<'_, HashMap<String, PairedDevice>> {
+        self.devices.lock().unwrap_or_else(|e| e.into_inner())
+    }
 }
 
-pub fn get_all_pairings() -> Vec<(String, String)> {
-    let mut config = AppConfig::load();
-    remove_expired(&mut config);
+impl PairingStore for MemoryPairingStore {
+    fn is_paired(&self, endpoint_id: &str) -> bool {
+        self.devices()
+            .get(endpoint_id)
+            .is_some_and(|device| is_fresh(device, now_timestamp()))
+    }
+
+    fn pair_key(&self, endpoint_id: &str) -> Option<Zeroizing<String>> {
+        fresh_key(self.devices().get(endpoint_id), now_timestamp())
+    }
+
+    fn add_pairing(&self, endpoint_id: &str, peer_name: &str, key: &str) {
+        let now = now_timestamp();
+        self.insert_at(endpoint_id, peer_name, key, now);
+        self.devices().retain(|_, device| is_fresh(device, now));
+    }
+
+    fn add_guest_pairing(&self, endpoint_id: &str, peer_name: &str, key: &str) {
+        let now = now_timestamp();
+        self.insert_guest_at(endpoint_id, peer_name, key, now);
+        self.devices().retain(|_, device| is_fresh(device, now));
+    }
+
+    fn guest_expiry(&self, endpoint_id: &str) -> Option<u64> {
+        guest_expiry(self.devices().get(endpoint_id), now_timestamp())
+    }
+
+    fn remove_pairing(&self, endpoint_id: &str) {
+        self.devices().remove(endpoint_id);
+    }
+
+    fn get_all_pairings(&self) -> Vec<(String, String)> {
+        let now = now_timestamp();
+        self.devices()
+            .values()
+            .filter(|device| is_fresh(device, now))
+            .map(|d| (d.endpoint_id.clone(), d.peer_name.clone()))
+            .collect()
+    }
 
-    config
-        .pairing
-        .values()
-        .map(|d| (d.endpoint_id.clone(), d.peer_name.clone()))
-        .collect()
+    fn add_receiver_key(&self, peer_name: &str, key: &str) {
+        let now = now_timestamp();
+        let mut receivers = self.receiver_keys.lock().unwrap_or_else(|e| e.into_inner());
+        receivers.insert(
+            key::key_id(key),
+            ReceiverKey {
+                key: key.to_string(),
+                peer_name: peer_name.to_string(),
+                paired_at: now,
+            },
+        );
+        receivers.retain(|_, receiver| is_fresh_key(receiver, now));
+    }
+
+    fn receiver_key(&self, key_id: &str) -> Option<Zeroizing<String>> {
+        self.receiver_keys
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key_id)
+            .filter(|receiver| is_fresh_key(receiver, now_timestamp()))
+            .map(|receiver| Zeroizing::new(receiver.key.clone()))
+    }
 }
 
-pub fn generate_verification_code() -> String {
+pub fn generate_verification_code() -> Zeroizing<String> {
     // Securely generate a random number for the verification code
     // We use Uuid::new_v4() which relies on a CSPRNG (getrandom)
     let uuid = Uuid::new_v4();
@@ -116,7 +330,13 @@ pub fn generate_verification_code() -> String {
     // Use from_ne_bytes for random number; endianness indifferent
     let val = u32::from_ne_bytes(bytes[0..4].try_into().unwrap_or([0; 4]));
     let code = val % 10000;
-    format!("{:04}", code)
+    Zeroizing::new(format!("{:04}", code))
+}
+
+/// Compare a typed code with the shown one in constant time
+pub fn codes_match(typed: &str, shown: &str) -> bool {
+    // `blake3::Hash` compares in constant time
+    blake3::hash(typed.as_bytes()) == blake3::hash(shown.as_bytes())
 }
 
 #[cfg(test)]
@@ -128,43 +348,101 @@ mod tests {
         let code = generate_verification_code();
         assert_eq!(code.len(), 4);
         assert!(code.chars().all(|c| c.is_ascii_digit()));
+        assert!(codes_match(&code, &code.clone()));
+        assert!(!codes_match("", &code));
     }
 
     #[test]
-    fn test_concurrency_limit() {
-        // Clear state just in case
-        ACTIVE_PAIRING_ATTEMPTS.store(0, Ordering::Relaxed);
-
-        // Acquire max guards
-        let g1 = PairingGuard::try_acquire();
-        assert!(g1.is_some());
-
-        let g2 = PairingGuard::try_acquire();
-        assert!(g2.is_some());
-
-        let g3 = PairingGuard::try_acquire();
-        assert!(g3.is_some());
-
-        // Should fail now
-        let g4 = PairingGuard::try_acquire();
-        assert!(g4.is_none());
-
-        // Drop one
-        drop(g1);
-
-        // Should succeed now
-        let g5 = PairingGuard::try_acquire();
-        assert!(g5.is_some());
-
-        // Should fail again
-        let g6 = PairingGuard::try_acquire();
-        assert!(g6.is_none());
+    fn test_memory_store_expiry() {
+        let store = MemoryPairingStore::default();
+        store.add_pairing("fresh", "Fresh PC", "k1");
+        store.insert_at(
+            "stale",
+            "Stale PC",
+            "k2",
+            now_timestamp() - PAIRING_EXPIRY_SECS - 1,
+        );
+        store.insert_at("legacy", "Old PC", "", now_timestamp());
+
+        assert!(store.is_paired("fresh"));
+        assert_eq!(
+            store.pair_key("fresh").as_deref().map(String::as_str),
+            Some("k1")
+        );
+        assert_eq!(store.pair_key("stale"), None);
+        // Pairings from before keys existed need a new code
+        assert!(store.is_paired("legacy"));
+        assert_eq!(store.pair_key("legacy"), None);
+        store.remove_pairing("legacy");
+        assert!(!store.is_paired("stale"));
+        assert!(!store.is_paired("unknown"));
+        assert_eq!(
+            store.get_all_pairings(),
+            vec![("fresh".to_string(), "Fresh PC".to_string())]
+        );
+
+        store.remove_pairing("fresh");
+        assert!(!store.is_paired("fresh"));
+    }
 
-        // Cleanup
-        drop(g2);
-        drop(g3);
-        drop(g5);
+    #[test]
+    fn test_guest_pairings_expire_after_an_hour() {
+        let store = MemoryPairingStore::default();
+        let now = now_timestamp();
+        store.insert_guest_at("guest", "Colleague", "k1", now - 60);
+        store.insert_guest_at(
+            "old-guest",
+            "Visitor",
+            "k2",
+            now - guest::GUEST_PAIRING_EXPIRY_SECS,
+        );
+        store.insert_at(
+            "trusted",
+            "Laptop",
+            "k3",
+            now - guest::GUEST_PAIRING_EXPIRY_SECS,
+        );
+
+        assert!(store.is_paired("guest"));
+        assert!(store.is_guest("guest"));
+        assert!(!store.is_paired("old-guest"));
+        assert!(!store.is_guest("old-guest"));
+        assert_eq!(store.pair_key("old-guest"), None);
+        assert!(store.is_paired("trusted"));
+        assert!(!store.is_guest("trusted"));
+
+        // Pairing with a code again makes a guest a trusted device
+        store.add_pairing("guest", "Colleague", "k4");
+        assert!(!store.is_guest("guest"));
+    }
 
-        assert_eq!(ACTIVE_PAIRING_ATTEMPTS.load(Ordering::Relaxed), 0);
+    #[test]
+    fn test_file_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("p2p_pairing_{}", Uuid::new_v4()));
+        let path = dir.join("config.json");
+
+        FilePairingStore::new(&path).add_pairing("peer-1", "Laptop", "k1");
+        FilePairingStore::new(&path).add_receiver_key("Desktop", "k2");
+        FilePairingStore::new(&path).add_guest_pairing("peer-2", "Visitor", "k3");
+        let reopened = FilePairingStore::new(&path);
+        assert!(reopened.is_guest("peer-2"));
+        assert!(!reopened.is_guest("peer-1"));
+        assert!(reopened.is_paired("peer-1"));
+        assert_eq!(
+            reopened.pair_key("peer-1").as_deref().map(String::as_str),
+            Some("k1")
+        );
+        assert_eq!(
+            reopened
+                .receiver_key(&key::key_id("k2"))
+                .as_deref()
+                .map(String::as_str),
+            Some("k2")
+        );
+
+        reopened.remove_pairing("peer-1");
+        assert!(!FilePairingStore::new(&path).is_paired("peer-1"));
+
+        let _ = std::fs::remove_dir_all(dir);
     }
 }
@@ -0,0 +1,136 @@
+//! Guest pairings, for receiving from a device once without trusting it.
+//!
+//! While [`GuestMode`] is on, devices that pair with this one are recorded
+//! as guests. A guest pairing lets its connection send without a code like
+//! any other, but it ends after the first file received from the guest or
+//! after [`GUEST_PAIRING_EXPIRY_SECS`], whichever comes first. The
+//! connection the guest paired on holds a [`GuestPass`]: the files of that
+//! batch already under way still arrive, but nothing new starts on it once
+//! a file arrived or the pairing expired. The next send needs a code again.
+
+use super::now_timestamp;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Guest pairings expire after an hour
+pub const GUEST_PAIRING_EXPIRY_SECS: u64 = 60 * 60;
+
+/// Whether new pairings are made as guests; shared by the backend and the
+/// transfer server, off at start
+#[derive(Debug, Default)]
+pub struct GuestMode {
+    enabled: AtomicBool,
+}
+
+impl GuestMode {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// What a guest may still do on its connection: send one batch before its
+/// pairing expires
+#[derive(Debug)]
+pub struct GuestPass {
+    /// Unix time the guest pairing expires
+    expires_at: u64,
+    /// A file arrived; only the files of its batch already under way finish
+    used: AtomicBool,
+    /// Files from the guest being received
+    in_flight: AtomicU32,
+}
+
+impl GuestPass {
+    /// Pass of a guest pairing that expires at Unix time `expires_at`
+    pub fn new(expires_at: u64) -> Self {
+        Self {
+            expires_at,
+            used: AtomicBool::new(false),
+            in_flight: AtomicU32::new(0),
+        }
+    }
+
+    /// Why nothing new may start on the connection, if so
+    pub fn ended(&self) -> Option<&'static str> {
+        self.ended_at(now_timestamp(), self.in_flight.load(Ordering::SeqCst))
+    }
+
+    fn ended_at(&self, now: u64, in_flight: u32) -> Option<&'static str> {
+        if now >= self.expires_at {
+            Some("The guest pairing expired; pair again with a code")
+        } else if self.used.load(Ordering::SeqCst) && in_flight == 0 {
+            Some("The guest pairing was used up by a transfer; pair again with a code")
+        } else {
+            None
+        }
+    }
+
+    /// Admit a file, unless the pass ended
+    pub fn begin_file(&self) -> Result<GuestFile<'_>, &'static str> {
+        // Counted first, so a file of the batch finishing meanwhile does
+        // not end the pass under it
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let file = GuestFile { pass: self };
+        match self.ended_at(now_timestamp(), in_flight) {
+            Some(reason) => Err(reason),
+            None => Ok(file),
+        }
+    }
+}
+
+/// A guest's file being received; dropping it without
+/// [`complete`](Self::complete) leaves the pass unused
+#[derive(Debug)]
+pub struct GuestFile<'a> {
+    pass: &'a GuestPass,
+}
+
+impl GuestFile<'_> {
+    /// The file arrived: the pass now only covers the rest of its batch
+    pub fn complete(self) {
+        self.pass.used.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for GuestFile<'_> {
+    fn drop(&mut self) {
+        self.pass.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pass_covers_one_batch() {
+        let pass = GuestPass::new(now_timestamp() + GUEST_PAIRING_EXPIRY_SECS);
+        let first = pass.begin_file().unwrap();
+        let second = pass.begin_file().unwrap();
+        first.complete();
+        // The rest of the batch may still start and finish
+        let third = pass.begin_file().unwrap();
+        drop(second);
+        third.complete();
+        assert!(pass.ended().is_some());
+        assert!(pass.begin_file().is_err());
+    }
+
+    #[test]
+    fn test_failed_files_leave_the_pass_unused() {
+        let pass = GuestPass::new(now_timestamp() + GUEST_PAIRING_EXPIRY_SECS);
+        drop(pass.begin_file().unwrap());
+        assert!(pass.ended().is_none());
+        assert!(pass.begin_file().is_ok());
+    }
+
+    #[test]
+    fn test_pass_expires_with_the_pairing() {
+        let pass = GuestPass::new(now_timestamp());
+        assert!(pass.ended().is_some());
+        assert!(pass.begin_file().is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! One-time pairing invites, shown by the receiver as a QR code.
+//!
+//! The receiver issues a secret that stays valid for a few minutes and puts
+//! it, together with its address and identity, into a `p2p-pair://` link.
+//! A sender presenting the secret is paired without typing a verification
+//! code. Each secret works once.
+
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use url::Url;
+use uuid::Uuid;
+use zeroize::Zeroize;
+
+use super::now_timestamp;
+
+/// URI scheme of invite links
+pub const INVITE_SCHEME: &str = "p2p-pair";
+
+/// Unredeemed invites expire after 5 minutes
+pub const INVITE_EXPIRY_SECS: u64 = 5 * 60;
+
+/// Contents of an invite QR code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingInvite {
+    /// Endpoint ID of the receiver that issued the invite
+    pub endpoint_id: String,
+    /// Device name of the receiver
+    pub peer_name: String,
+    /// Address of the receiver's transfer server
+    pub addr: SocketAddr,
+    /// One-time secret from [`InviteRegistry::issue`], wiped on drop
+    pub secret: String,
+}
+
+impl Drop for PairingInvite {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl PairingInvite {
+    /// Encode as `p2p-pair://<addr>?id=..&name=..&secret=..`
+    pub fn to_uri(&self) -> String {
+        let mut url = Url::parse(&format!("{}://{}", INVITE_SCHEME, self.addr))
+            .expect("socket address forms a valid URL authority");
+        url.query_pairs_mut()
+            .append_pair("id", &self.endpoint_id)
+            .append_pair("name", &self.peer_name)
+            .append_pair("secret", &self.secret);
+        url.to_string()
+    }
+
+    /// Parse a link produced by [`to_uri`](Self::to_uri)
+    pub fn parse(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri.trim()).context("Not a valid invite link")?;
+        if url.scheme() != INVITE_SCHEME {
+            return Err(anyhow!("Not a pairing invite ({}:// link)", url.scheme()));
+        }
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("Invite has no address"))?;
+        let port = url.port().ok_or_else(|| anyhow!("Invite has no port"))?;
+        let addr = format!("{}:{}", host, port)
+            .parse()
+            .context("Invite address is not an IP address")?;
+
+        let mut endpoint_id = None;
+        let mut peer_name = None;
+        let mut secret = None;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "id" => endpoint_id = Some(value.into_owned()),
+                "name" => peer_name = Some(value.into_owned()),
+                "secret" => secret = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            endpoint_id: endpoint_id.ok_or_else(|| anyhow!("Invite has no endpoint id"))?,
+            peer_name: peer_name.unwrap_or_default(),
+            addr,
+            secret: secret
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| anyhow!("Invite has no secret"))?,
+        })
+    }
+}
+
+/// Outstanding invite secrets of this node, shared with the transfer server
+#[derive(Debug, Default)]
+pub struct InviteRegistry {
+    /// BLAKE3 hash of the secret -> issue timestamp (seconds); the secrets
+    /// themselves are not kept
+    secrets: Mutex<HashMap<blake3::Hash, u64>>,
+}
+
+impl InviteRegistry {
+    /// Create a new one-time secret
+    pub fn issue(&self) -> String {
+        // Uuid::new_v4() draws from a CSPRNG (getrandom)
+        let secret = Uuid::new_v4().simple().to_string();
+        self.issue_at(&secret, now_timestamp());
+        secret
+    }
+
+    /// Register `secret` as issued at `issued_at` (e.g. to test expiry)
+    pub fn issue_at(&self, secret: &str, issued_at: u64) {
+        self.secrets()
+            .insert(blake3::hash(secret.as_bytes()), issued_at);
+    }
+
+    /// Consume `secret`; true only for a known, unexpired secret
+    pub fn redeem(&self, secret: &str) -> bool {
+        let now = now_timestamp();
+        let mut secrets = self.secrets();
+        secrets.retain(|_, issued_at| now.saturating_sub(*issued_at) < INVITE_EXPIRY_SECS);
+        secrets.remove(&blake3::hash(secret.as_bytes())).is_some()
+    }
+
+    fn secrets(&self) -> std::sync::MutexGuard<'_, HashMap<blake3::Hash, u64>> {
+        self.secrets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invite_uri_round_trip() {
+        let invite = PairingInvite {
+            endpoint_id: "abc123".to_string(),
+            peer_name: "Living room PC & co".to_string(),
+            addr: "192.168.1.20:9000".parse().unwrap(),
+            secret: "0123456789abcdef".to_string(),
+        };
+        let uri = invite.to_uri();
+        assert!(uri.starts_with("p2p-pair://192.168.1.20:9000?"));
+        assert_eq!(PairingInvite::parse(&uri).unwrap(), invite);
+
+        let mut v6 = invite.clone();
+        v6.addr = "[fe80::1]:9000".parse().unwrap();
+        assert_eq!(PairingInvite::parse(&v6.to_uri()).unwrap(), v6);
+
+        assert!(PairingInvite::parse("https://example.com/?secret=x").is_err());
+        assert!(PairingInvite::parse("p2p-pair://10.0.0.1:9000?id=a").is_err());
+    }
+
+    #[test]
+    fn test_secrets_are_single_use_and_expire() {
+        let registry = InviteRegistry::default();
+        let secret = registry.issue();
+        assert!(!registry.redeem("not-issued"));
+        assert!(registry.redeem(&secret));
+        assert!(!registry.redeem(&secret));
+
+        let stale = now_timestamp() - INVITE_EXPIRY_SECS;
+        registry.issue_at("old", stale);
+        assert!(!registry.redeem("old"));
+    }
+}
@@ -0,0 +1,162 @@
+//! Per-pair keys that prove a paired sender is the device that paired.
+//!
+//! When a pairing succeeds, both ends export the same 32-byte key from that
+//! connection's TLS session; a man in the middle would hold two different
+//! sessions and therefore two different keys. The receiver stores the key
+//! with the pairing, the sender under its [`key_id`].
+//!
+//! On later connections the receiver answers a `PairingRequest` from a
+//! paired endpoint ID with a challenge naming the key, and the sender replies
+//! with a keyed hash of the new session's exported secret. Without the key,
+//! claiming a paired endpoint ID only leads to the verification code prompt.
+//!
+//! The challenge also carries a fresh nonce. The sender signs it, bound to
+//! the session, with its Iroh secret key, and the receiver checks the
+//! signature against the claimed endpoint ID, which is that key's public
+//! half. A leaked pairing key alone therefore does not let another device
+//! pose as the paired one.
+
+use anyhow::{Result, anyhow};
+use iroh::{PublicKey, SecretKey, Signature};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+const PAIR_KEY_LABEL: &[u8] = b"p2p-transfer pairing key";
+const PROOF_LABEL: &[u8] = b"p2p-transfer pairing proof";
+const KEY_ID_CONTEXT: &str = "p2p-transfer 2025 pairing key id";
+const IDENTITY_LABEL: &[u8] = b"p2p-transfer identity proof";
+
+/// Export the key of a pairing that just succeeded on `connection`
+pub fn derive_pair_key(
+    connection: &quinn::Connection,
+    sender_id: &str,
+) -> Result<Zeroizing<String>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    connection
+        .export_keying_material(key.as_mut(), PAIR_KEY_LABEL, sender_id.as_bytes())
+        .map_err(|_| anyhow!("Could not derive the pairing key"))?;
+    Ok(Zeroizing::new(hex_encode(key.as_ref())))
+}
+
+/// Public name of a key, sent in the challenge so the sender can find it
+pub fn key_id(key: &str) -> String {
+    let hash = blake3::derive_key(KEY_ID_CONTEXT, key.as_bytes());
+    blake3::Hash::from_bytes(hash).to_hex()[..32].to_string()
+}
+
+/// Proof of holding `key`, bound to this connection's TLS session
+pub fn session_proof(connection: &quinn::Connection, key: &str) -> Result<String> {
+    let key = blake3::Hash::from_hex(key).map_err(|_| anyhow!("Malformed pairing key"))?;
+    let mut secret = Zeroizing::new([0u8; 32]);
+    connection
+        .export_keying_material(secret.as_mut(), PROOF_LABEL, &[])
+        .map_err(|_| anyhow!("Could not derive the session secret"))?;
+    Ok(blake3::keyed_hash(key.as_bytes(), secret.as_ref())
+        .to_hex()
+        .to_string())
+}
+
+/// Check a sender's proof in constant time
+pub fn verify_proof(connection: &quinn::Connection, key: &str, proof: &str) -> bool {
+    let (Ok(expected), Ok(proof)) = (
+        session_proof(connection, key).and_then(|p| Ok(blake3::Hash::from_hex(p)?)),
+        blake3::Hash::from_hex(proof),
+    ) else {
+        return false;
+    };
+    // `blake3::Hash` compares in constant time
+    expected == proof
+}
+
+/// Fresh random nonce for an identity challenge
+pub fn new_nonce() -> String {
+    blake3::Hash::from_bytes(rand::random::<[u8; 32]>())
+        .to_hex()
+        .to_string()
+}
+
+/// What the sender signs: `nonce` mixed into this connection's TLS session
+fn identity_message(connection: &quinn::Connection, nonce: &str) -> Result<[u8; 32]> {
+    let mut message = [0u8; 32];
+    connection
+        .export_keying_material(&mut message, IDENTITY_LABEL, nonce.as_bytes())
+        .map_err(|_| anyhow!("Could not derive the identity challenge"))?;
+    Ok(message)
+}
+
+/// Sign the receiver's nonce with our Iroh secret key
+pub fn sign_nonce(
+    connection: &quinn::Connection,
+    secret: &SecretKey,
+    nonce: &str,
+) -> Result<String> {
+    let message = identity_message(connection, nonce)?;
+    Ok(hex_encode(&secret.sign(&message).to_bytes()))
+}
+
+/// Check that `signature` over `nonce` was made by the key `endpoint_id` names
+pub fn verify_signature(
+    connection: &quinn::Connection,
+    endpoint_id: &str,
+    nonce: &str,
+    signature: &str,
+) -> bool {
+    let Ok(public) = PublicKey::from_str(endpoint_id) else {
+        return false;
+    };
+    let Some(bytes) = hex_decode(signature).and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+    else {
+        return false;
+    };
+    let Ok(message) = identity_message(connection, nonce) else {
+        return false;
+    };
+    public
+        .verify(&message, &Signature::from_bytes(&bytes))
+        .is_ok()
+}
+
+pub(crate) fn hex_encode(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    // Sized up front so that encoding a key leaves no partial copies behind
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        hex.push(DIGITS[(byte >> 4) as usize] as char);
+        hex.push(DIGITS[(byte & 0x0f) as usize] as char);
+    }
+    hex
+}
+
+pub(crate) fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_id_is_stable_and_short() {
+        let key = blake3::hash(b"pair").to_hex().to_string();
+        assert_eq!(key_id(&key), key_id(&key));
+        assert_eq!(key_id(&key).len(), 32);
+        assert_ne!(key_id(&key), key[..32]);
+    }
+
+    #[test]
+    fn test_signature_hex_round_trips() {
+        let secret = SecretKey::generate(&mut rand::rng());
+        let signature = secret.sign(b"nonce").to_bytes();
+        let hex = hex_encode(&signature);
+        assert_eq!(hex.len(), 128);
+        assert_eq!(hex_decode(&hex).unwrap(), signature.to_vec());
+        assert_eq!(hex_decode("abc"), None);
+        assert_eq!(hex_decode("zz"), None);
+    }
+}
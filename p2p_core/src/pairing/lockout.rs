@@ -0,0 +1,173 @@
+//! Lockout for peers that keep entering wrong verification codes.
+//!
+//! [`PairingGuard`](super::PairingGuard) only caps how many prompts run at
+//! once. This tracks wrong codes per sender, under both its endpoint ID and
+//! its IP so that neither a fresh ID nor a fresh address starts over. After
+//! [`FAILURES_BEFORE_LOCKOUT`] wrong codes the peer is refused for
+//! [`LOCKOUT_BASE`], doubling with every further lockout up to
+//! [`LOCKOUT_MAX`]. A correct code clears the record.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Wrong codes that trigger a lockout
+pub const FAILURES_BEFORE_LOCKOUT: u32 = 3;
+
+/// Length of the first lockout
+pub const LOCKOUT_BASE: Duration = Duration::from_secs(30);
+
+/// Longest lockout
+pub const LOCKOUT_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// Refused attempts are reported on the first one and then every this many
+pub const REPORT_BLOCKED_EVERY: u32 = 10;
+
+/// Records untouched for this long are dropped, lockout history included
+const FORGET_AFTER: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// A refused attempt from a locked-out peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Blocked {
+    /// Attempts refused during the current lockout, this one included
+    pub attempts: u32,
+    pub retry_after: Duration,
+}
+
+#[derive(Debug)]
+struct PeerRecord {
+    failures: u32,
+    lockouts: u32,
+    locked_until: Option<Instant>,
+    blocked: u32,
+    last_seen: Instant,
+}
+
+impl PeerRecord {
+    fn new(now: Instant) -> Self {
+        Self {
+            failures: 0,
+            lockouts: 0,
+            locked_until: None,
+            blocked: 0,
+            last_seen: now,
+        }
+    }
+}
+
+/// Failed pairing attempts per endpoint ID and IP, owned by one transfer server
+#[derive(Debug, Default)]
+pub struct PairingLockout {
+    peers: Mutex<HashMap<String, PeerRecord>>,
+}
+
+impl PairingLockout {
+    /// Whether a peer known by `keys` is locked out. A refused attempt is
+    /// counted towards the returned [`Blocked`].
+    pub fn check(&self, keys: &[String], now: Instant) -> Option<Blocked> {
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|_, record| {
+            now.duration_since(record.last_seen) < FORGET_AFTER
+                || record.locked_until.is_some_and(|until| until > now)
+        });
+
+        let mut blocked: Option<Blocked> = None;
+        for key in keys {
+            let Some(record) = peers.get_mut(key) else {
+                continue;
+            };
+            let Some(until) = record.locked_until.filter(|until| *until > now) else {
+                continue;
+            };
+            record.blocked += 1;
+            record.last_seen = now;
+            let current = Blocked {
+                attempts: record.blocked,
+                retry_after: until - now,
+            };
+            blocked = Some(match blocked {
+                Some(other) => Blocked {
+                    attempts: other.attempts.max(current.attempts),
+                    retry_after: other.retry_after.max(current.retry_after),
+                },
+                None => current,
+            });
+        }
+        blocked
+    }
+
+    /// Count a wrong code. Returns the lockout length if this one started it.
+    pub fn record_failure(&self, keys: &[String], now: Instant) -> Option<Duration> {
+        let mut peers = self.peers.lock().unwrap();
+        let mut started = None;
+        for key in keys {
+            let record = peers
+                .entry(key.clone())
+                .or_insert_with(|| PeerRecord::new(now));
+            record.last_seen = now;
+            record.failures += 1;
+            if record.failures < FAILURES_BEFORE_LOCKOUT {
+                continue;
+            }
+
+            let duration = LOCKOUT_BASE
+                .saturating_mul(1u32 << record.lockouts.min(16))
+                .min(LOCKOUT_MAX);
+            record.failures = 0;
+            record.lockouts += 1;
+            record.blocked = 0;
+            record.locked_until = Some(now + duration);
+            started = Some(started.map_or(duration, |other: Duration| other.max(duration)));
+        }
+        started
+    }
+
+    /// Forget the failures of a peer that entered the right code
+    pub fn record_success(&self, keys: &[String]) {
+        let mut peers = self.peers.lock().unwrap();
+        for key in keys {
+            peers.remove(key);
+        }
+    }
+}
+
+/// Lockout keys of a sender: its claimed endpoint ID and its IP
+pub fn peer_keys(endpoint_id: &str, ip: std::net::IpAddr) -> Vec<String> {
+    vec![format!("id:{}", endpoint_id), format!("ip:{}", ip)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout_doubles_and_clears() {
+        let lockout = PairingLockout::default();
+        let start = Instant::now();
+        let ip: std::net::IpAddr = "192.168.1.50".parse().unwrap();
+        let keys = peer_keys("peer-a", ip);
+
+        assert_eq!(lockout.record_failure(&keys, start), None);
+        assert_eq!(lockout.record_failure(&keys, start), None);
+        assert_eq!(lockout.record_failure(&keys, start), Some(LOCKOUT_BASE));
+
+        let blocked = lockout.check(&keys, start).unwrap();
+        assert_eq!(blocked.attempts, 1);
+        assert_eq!(blocked.retry_after, LOCKOUT_BASE);
+        assert_eq!(lockout.check(&keys, start).unwrap().attempts, 2);
+
+        // Another endpoint ID from the same address is still refused
+        assert!(lockout.check(&peer_keys("peer-b", ip), start).is_some());
+
+        // The next lockout lasts twice as long
+        let later = start + LOCKOUT_BASE;
+        assert!(lockout.check(&keys, later).is_none());
+        for _ in 0..2 {
+            assert_eq!(lockout.record_failure(&keys, later), None);
+        }
+        assert_eq!(lockout.record_failure(&keys, later), Some(LOCKOUT_BASE * 2));
+
+        lockout.record_success(&keys);
+        assert!(lockout.check(&keys, later).is_none());
+    }
+}
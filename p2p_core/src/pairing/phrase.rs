@@ -0,0 +1,254 @@
+//! One-time phrases that link two devices without an account.
+//!
+//! The host shows [`PHRASE_WORDS`] words from a list of 256 (48 random
+//! bits). The phrase doubles as a rendezvous room secret: the host registers
+//! there, and the device the phrase is typed on finds the host's endpoint ID
+//! in that room, connects over Iroh and proves it knows the phrase. The host
+//! proves the same back, and both store a keyed pairing with each other.
+//! Proofs are bound to the connection's TLS session, so they cannot be
+//! replayed on another one.
+
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::sync::Mutex;
+use zeroize::{Zeroize, Zeroizing};
+
+use super::now_timestamp;
+
+/// Words in a phrase
+pub const PHRASE_WORDS: usize = 6;
+
+/// Unused phrases expire after 10 minutes
+pub const LINK_PHRASE_EXPIRY_SECS: u64 = 10 * 60;
+
+/// Wrong proofs before a phrase is withdrawn
+pub const MAX_LINK_ATTEMPTS: u32 = 3;
+
+const PROOF_CONTEXT: &str = "p2p-transfer 2025 link phrase proof";
+
+/// Sorted, so lookups can binary search
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adult", "agent", "alarm", "album", "alley", "amber", "angle",
+    "ankle", "apple", "april", "arena", "argue", "armor", "arrow", "atlas", "attic", "audio",
+    "autumn", "axis", "bacon", "badge", "baker", "bamboo", "banana", "banjo", "barn", "basil",
+    "basket", "beach", "beard", "beetle", "bench", "berry", "bicycle", "bishop", "blade",
+    "blanket", "blossom", "board", "bonus", "border", "bottle", "branch", "bread", "brick",
+    "bridge", "bronze", "brush", "bubble", "bucket", "buffalo", "butter", "cabin", "cactus",
+    "camel", "camera", "canal", "candle", "canvas", "canyon", "carbon", "carpet", "carrot",
+    "castle", "cattle", "cedar", "cement", "chalk", "cherry", "chess", "cider", "circle", "citrus",
+    "clay", "cliff", "clock", "cloud", "clover", "coach", "cobalt", "coconut", "coffee", "comet",
+    "copper", "coral", "cotton", "cousin", "coyote", "crane", "crater", "cricket", "crystal",
+    "cube", "curtain", "cycle", "dagger", "daisy", "dance", "delta", "denim", "desert", "diamond",
+    "dinner", "dolphin", "donkey", "dragon", "drum", "eagle", "echo", "eclipse", "elbow", "ember",
+    "engine", "falcon", "feather", "fence", "ferry", "fiber", "fiddle", "finger", "flame", "flute",
+    "forest", "fossil", "fox", "frost", "galaxy", "garden", "garlic", "gecko", "ginger", "giraffe",
+    "glacier", "globe", "goose", "gravel", "guitar", "hammer", "harbor", "hazel", "helmet",
+    "heron", "honey", "horizon", "hotel", "island", "ivory", "jacket", "jaguar", "jelly", "jungle",
+    "kettle", "kitten", "ladder", "lagoon", "lantern", "lemon", "lizard", "lobster", "magnet",
+    "mango", "maple", "marble", "meadow", "melon", "mirror", "monkey", "mosaic", "mountain",
+    "museum", "needle", "nest", "noodle", "nutmeg", "oasis", "ocean", "olive", "onion", "orange",
+    "orbit", "orchid", "otter", "oyster", "paddle", "palace", "panda", "paper", "parrot", "pebble",
+    "pencil", "pepper", "piano", "pigeon", "pillow", "planet", "plaza", "pocket", "pony", "potato",
+    "pumpkin", "puzzle", "quartz", "rabbit", "radar", "radio", "raven", "ribbon", "river", "robot",
+    "rocket", "saddle", "salmon", "sandal", "scarf", "shadow", "shell", "silver", "sketch",
+    "sleeve", "spider", "sponge", "stable", "statue", "summit", "sunset", "swan", "tablet",
+    "tango", "teapot", "temple", "thunder", "tiger", "timber", "tomato", "torch", "tower",
+    "tractor", "trumpet", "tulip", "tunnel", "turtle", "valley", "velvet", "violin", "volcano",
+    "wagon", "walnut", "whale", "willow", "window", "winter", "wizard", "zebra",
+];
+
+/// Which end of a link a proof comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkRole {
+    /// Showed the phrase
+    Host,
+    /// Typed the phrase in
+    Joiner,
+}
+
+/// A normalized phrase: lowercase words separated by single spaces, wiped
+/// on drop
+#[derive(Clone, PartialEq, Eq)]
+pub struct LinkPhrase(String);
+
+impl LinkPhrase {
+    /// Fresh random phrase
+    pub fn generate() -> Self {
+        let words: Vec<&str> = Zeroizing::new(rand::random::<[u8; PHRASE_WORDS]>())
+            .iter()
+            .map(|&i| WORDS[i as usize])
+            .collect();
+        Self(words.join(" "))
+    }
+
+    /// Accept a typed phrase in any case, separated by spaces, commas or dashes
+    pub fn parse(input: &str) -> Result<Self> {
+        let words: Zeroizing<Vec<String>> = Zeroizing::new(
+            input
+                .split(|c: char| c.is_whitespace() || c == ',' || c == '-')
+                .filter(|word| !word.is_empty())
+                .map(str::to_lowercase)
+                .collect(),
+        );
+        if words.len() != PHRASE_WORDS {
+            return Err(anyhow!(
+                "A link phrase has {} words, not {}",
+                PHRASE_WORDS,
+                words.len()
+            ));
+        }
+        if let Some(unknown) = words
+            .iter()
+            .find(|word| WORDS.binary_search(&word.as_str()).is_err())
+        {
+            return Err(anyhow!("\"{}\" is not a link phrase word", unknown));
+        }
+        Ok(Self(words.join(" ")))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Rendezvous room secret the host registers under
+    pub fn room_secret(&self) -> String {
+        format!("link {}", self.0)
+    }
+
+    /// Proof that `role` knows the phrase, bound to `session_secret`
+    pub fn proof(&self, role: LinkRole, session_secret: &[u8; 32]) -> String {
+        let key = Zeroizing::new(blake3::derive_key(PROOF_CONTEXT, self.0.as_bytes()));
+        let mut hasher = blake3::Hasher::new_keyed(&key);
+        hasher.update(match role {
+            LinkRole::Host => b"host",
+            LinkRole::Joiner => b"join",
+        });
+        hasher.update(session_secret);
+        hasher.finalize().to_hex().to_string()
+    }
+
+    /// Check a proof in constant time
+    pub fn verify(&self, role: LinkRole, session_secret: &[u8; 32], proof: &str) -> bool {
+        let Ok(proof) = blake3::Hash::from_hex(proof) else {
+            return false;
+        };
+        // `blake3::Hash` compares in constant time
+        blake3::Hash::from_hex(self.proof(role, session_secret)).is_ok_and(|p| p == proof)
+    }
+}
+
+impl Drop for LinkPhrase {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Display for LinkPhrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for LinkPhrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LinkPhrase(..)")
+    }
+}
+
+#[derive(Debug)]
+struct Offer {
+    phrase: LinkPhrase,
+    issued_at: u64,
+    failures: u32,
+}
+
+/// The phrase this node is showing, if any; a new one replaces it
+#[derive(Debug, Default)]
+pub struct LinkOffer {
+    offer: Mutex<Option<Offer>>,
+}
+
+impl LinkOffer {
+    /// Show `phrase` from `issued_at` on
+    pub fn offer_at(&self, phrase: LinkPhrase, issued_at: u64) {
+        *self.current() = Some(Offer {
+            phrase,
+            issued_at,
+            failures: 0,
+        });
+    }
+
+    /// Show a fresh phrase from now on
+    pub fn offer(&self, phrase: LinkPhrase) {
+        self.offer_at(phrase, now_timestamp());
+    }
+
+    /// Check a joiner's proof. A match withdraws the phrase and returns it;
+    /// [`MAX_LINK_ATTEMPTS`] misses withdraw it as well.
+    pub fn redeem(&self, session_secret: &[u8; 32], proof: &str) -> Option<LinkPhrase> {
+        let now = now_timestamp();
+        let mut offer = self.current();
+        let current = offer
+            .as_mut()
+            .filter(|o| now.saturating_sub(o.issued_at) < LINK_PHRASE_EXPIRY_SECS)?;
+        if current
+            .phrase
+            .verify(LinkRole::Joiner, session_secret, proof)
+        {
+            return offer.take().map(|o| o.phrase);
+        }
+        current.failures += 1;
+        if current.failures >= MAX_LINK_ATTEMPTS {
+            *offer = None;
+        }
+        None
+    }
+
+    fn current(&self) -> std::sync::MutexGuard<'_, Option<Offer>> {
+        self.offer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_words_are_sorted_and_unique() {
+        assert!(WORDS.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_phrase_parsing_is_forgiving() {
+        let phrase = LinkPhrase::generate();
+        assert_eq!(phrase.as_str().split(' ').count(), PHRASE_WORDS);
+        let shouted = phrase.as_str().to_uppercase().replace(' ', " - ");
+        assert_eq!(LinkPhrase::parse(&shouted).unwrap(), phrase);
+
+        assert!(LinkPhrase::parse("acid acorn actor").is_err());
+        assert!(LinkPhrase::parse("acid acorn actor adult agent zzz").is_err());
+    }
+
+    #[test]
+    fn test_offer_is_single_use_and_limits_guesses() {
+        let secret = [7u8; 32];
+        let offer = LinkOffer::default();
+        let phrase = LinkPhrase::parse("acid acorn actor adult agent alarm").unwrap();
+        let proof = phrase.proof(LinkRole::Joiner, &secret);
+        assert!(!phrase.verify(LinkRole::Host, &secret, &proof));
+        assert!(!phrase.verify(LinkRole::Joiner, &[8u8; 32], &proof));
+
+        offer.offer(phrase.clone());
+        assert_eq!(offer.redeem(&secret, &proof), Some(phrase.clone()));
+        assert_eq!(offer.redeem(&secret, &proof), None);
+
+        offer.offer(phrase.clone());
+        for _ in 0..MAX_LINK_ATTEMPTS {
+            assert_eq!(offer.redeem(&secret, "0badc0de"), None);
+        }
+        assert_eq!(offer.redeem(&secret, &proof), None);
+
+        offer.offer_at(phrase, now_timestamp() - LINK_PHRASE_EXPIRY_SECS);
+        assert_eq!(offer.redeem(&secret, &proof), None);
+    }
+}
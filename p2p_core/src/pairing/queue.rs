@@ -0,0 +1,234 @@
+//! How many verification code prompts run at once, and who waits for one.
+//!
+//! Each prompt holds a [`PairingGuard`]; at most
+//! [`max_concurrent_pairings`] exist at a time, which bounds how many
+//! codes a brute-forcing sender can try in parallel. A sender arriving
+//! while all slots are taken joins a queue of at most [`MAX_PAIRING_QUEUE`]
+//! and is told, with `PairingQueued`, how many senders are ahead of it
+//! until a slot frees up. Only a sender finding the queue full is refused.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Prompts shown at once unless configured otherwise
+pub const DEFAULT_MAX_CONCURRENT_PAIRINGS: usize = 3;
+
+/// Senders that may wait for a prompt; more are refused
+pub const MAX_PAIRING_QUEUE: usize = 8;
+
+/// How long a sender may wait in the queue
+pub const PAIRING_QUEUE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+static MAX_CONCURRENT: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_CONCURRENT_PAIRINGS);
+
+static SLOTS: LazyLock<PairingSlots> = LazyLock::new(PairingSlots::default);
+
+/// Prompts allowed at once
+pub fn max_concurrent_pairings() -> usize {
+    MAX_CONCURRENT.load(Ordering::Relaxed)
+}
+
+/// Allow `limit` prompts at once, at least one
+pub fn set_max_concurrent_pairings(limit: usize) {
+    MAX_CONCURRENT.store(limit.max(1), Ordering::Relaxed);
+    SLOTS.freed.notify_waiters();
+}
+
+#[derive(Default)]
+struct Slots {
+    active: usize,
+    /// Tickets of the waiting senders, first in line first
+    queue: VecDeque<u64>,
+    next_ticket: u64,
+}
+
+/// Prompt slots and their queue
+#[derive(Default)]
+struct PairingSlots {
+    slots: Mutex<Slots>,
+    freed: Notify,
+    /// Overrides [`max_concurrent_pairings`], for tests
+    limit: Option<usize>,
+}
+
+impl PairingSlots {
+    fn lock(&self) -> MutexGuard<'_, Slots> {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or_else(max_concurrent_pairings)
+    }
+
+    fn try_acquire(&'static self) -> Option<PairingGuard> {
+        let mut slots = self.lock();
+        if slots.active >= self.limit() || !slots.queue.is_empty() {
+            return None;
+        }
+        slots.active += 1;
+        Some(PairingGuard { slots: self })
+    }
+
+    fn enqueue(&'static self) -> Option<PairingTicket> {
+        let mut slots = self.lock();
+        if slots.queue.len() >= MAX_PAIRING_QUEUE {
+            return None;
+        }
+        let id = slots.next_ticket;
+        slots.next_ticket += 1;
+        slots.queue.push_back(id);
+        Some(PairingTicket { slots: self, id })
+    }
+
+    fn release(&self) {
+        self.lock().active -= 1;
+        self.freed.notify_waiters();
+    }
+}
+
+/// A running verification code prompt; frees its slot when dropped
+pub struct PairingGuard {
+    slots: &'static PairingSlots,
+}
+
+impl PairingGuard {
+    /// Take a slot if one is free and nobody waits for it
+    pub fn try_acquire() -> Option<Self> {
+        SLOTS.try_acquire()
+    }
+
+    /// Wait in line for a slot; `None` if the queue is full
+    pub fn enqueue() -> Option<PairingTicket> {
+        SLOTS.enqueue()
+    }
+}
+
+impl Drop for PairingGuard {
+    fn drop(&mut self) {
+        self.slots.release();
+    }
+}
+
+/// Where [`PairingTicket::next`] left a waiting sender
+pub enum Admission {
+    /// A slot was taken
+    Admitted(PairingGuard),
+    /// Still waiting, with this many senders ahead
+    Waiting(usize),
+}
+
+/// A place in the queue; leaves it when dropped
+pub struct PairingTicket {
+    slots: &'static PairingSlots,
+    id: u64,
+}
+
+impl PairingTicket {
+    /// Wait until a slot is taken or the number of senders ahead differs
+    /// from `ahead`
+    pub async fn next(&mut self, ahead: Option<usize>) -> Admission {
+        loop {
+            let freed = self.slots.freed.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+            {
+                let mut slots = self.slots.lock();
+                let position = slots
+                    .queue
+                    .iter()
+                    .position(|&id| id == self.id)
+                    .unwrap_or(0);
+                if position == 0 && slots.active < self.slots.limit() {
+                    slots.queue.retain(|&id| id != self.id);
+                    slots.active += 1;
+                    return Admission::Admitted(PairingGuard { slots: self.slots });
+                }
+                if Some(position) != ahead {
+                    return Admission::Waiting(position);
+                }
+            }
+            freed.await;
+        }
+    }
+}
+
+impl Drop for PairingTicket {
+    fn drop(&mut self) {
+        self.slots.lock().queue.retain(|&id| id != self.id);
+        // The sender behind may have moved up
+        self.slots.freed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slots(limit: usize) -> &'static PairingSlots {
+        Box::leak(Box::new(PairingSlots {
+            limit: Some(limit),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_concurrency_limit() {
+        let slots = slots(3);
+        let g1 = slots.try_acquire();
+        assert!(g1.is_some());
+        let g2 = slots.try_acquire();
+        let g3 = slots.try_acquire();
+        assert!(g2.is_some() && g3.is_some());
+
+        // Should fail now
+        assert!(slots.try_acquire().is_none());
+
+        // Dropping one frees its slot
+        drop(g1);
+        let g4 = slots.try_acquire();
+        assert!(g4.is_some());
+        assert!(slots.try_acquire().is_none());
+
+        drop((g2, g3, g4));
+        assert_eq!(slots.lock().active, 0);
+    }
+
+    #[tokio::test]
+    async fn test_waiting_senders_move_up_in_order() {
+        let slots = slots(1);
+        let running = slots.try_acquire().unwrap();
+        let mut first = slots.enqueue().unwrap();
+        let mut second = slots.enqueue().unwrap();
+        // Nobody jumps the queue
+        assert!(slots.try_acquire().is_none());
+
+        assert!(matches!(first.next(None).await, Admission::Waiting(0)));
+        assert!(matches!(second.next(None).await, Admission::Waiting(1)));
+
+        drop(running);
+        let Admission::Admitted(guard) = first.next(Some(0)).await else {
+            panic!("the first in line was not admitted");
+        };
+        drop(first);
+        assert!(matches!(second.next(Some(1)).await, Admission::Waiting(0)));
+
+        // The running prompt ending lets the next one in
+        drop(guard);
+        assert!(matches!(second.next(Some(0)).await, Admission::Admitted(_)));
+    }
+
+    #[test]
+    fn test_full_queue_refuses() {
+        let slots = slots(1);
+        let _running = slots.try_acquire().unwrap();
+        let tickets: Vec<_> = (0..MAX_PAIRING_QUEUE)
+            .map(|_| slots.enqueue().unwrap())
+            .collect();
+        assert!(slots.enqueue().is_none());
+        drop(tickets);
+        assert!(slots.enqueue().is_some());
+    }
+}
@@ -0,0 +1,152 @@
+//! Choosing between the LAN and the WAN for a send to a known device.
+//!
+//! Both routes are probed at once: the LAN with a QUIC handshake against
+//! the device's transfer server, the WAN by dialing its endpoint ID over
+//! iroh. A LAN that answers always wins, being faster and never relayed;
+//! the WAN carries the send when the LAN probe fails or the device is not
+//! on the LAN (see [`crate::peers`]). The WAN dial is passed in, as iroh
+//! connections belong to `p2p_wan`, and its connection is handed back to
+//! carry the send.
+
+use anyhow::{Result, anyhow};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How long a LAN handshake may take before the route counts as down
+pub const LAN_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long dialing over the WAN may take, relays included
+pub const WAN_PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Route picked for a send, with the WAN connection to send over
+#[derive(Debug)]
+pub enum ChosenPath<C> {
+    Lan { addr: SocketAddr, rtt: Duration },
+    Wan { connection: C, rtt: Duration },
+}
+
+impl<C> ChosenPath<C> {
+    /// "LAN" or "WAN"
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChosenPath::Lan { .. } => "LAN",
+            ChosenPath::Wan { .. } => "WAN",
+        }
+    }
+
+    /// Time the probe of the chosen route took
+    pub fn rtt(&self) -> Duration {
+        match self {
+            ChosenPath::Lan { rtt, .. } | ChosenPath::Wan { rtt, .. } => *rtt,
+        }
+    }
+}
+
+/// Time a QUIC handshake with the transfer server at `addr` takes
+pub async fn probe_lan(endpoint: &quinn::Endpoint, addr: SocketAddr) -> Result<Duration> {
+    let started = Instant::now();
+    let connecting = endpoint.connect(addr, "localhost")?;
+    let connection = tokio::time::timeout(LAN_PROBE_TIMEOUT, connecting)
+        .await
+        .map_err(|_| anyhow!("no answer from {}", addr))??;
+    let rtt = started.elapsed();
+    connection.close(0u32.into(), b"probe");
+    Ok(rtt)
+}
+
+/// Probe `lan` and `wan` together and pick the route: the LAN if it
+/// answers, else the WAN. Either may be `None` where the device can't be
+/// reached that way. Fails with both reasons when neither answers.
+pub async fn choose_path<C>(
+    lan: Option<(&quinn::Endpoint, SocketAddr)>,
+    wan: Option<impl Future<Output = Result<C>>>,
+) -> Result<ChosenPath<C>> {
+    let lan_probe = async {
+        let (endpoint, addr) = lan.ok_or_else(|| anyhow!("not on the LAN"))?;
+        probe_lan(endpoint, addr).await.map(|rtt| (addr, rtt))
+    };
+    let wan_probe = async {
+        let dial = wan.ok_or_else(|| anyhow!("not known over the WAN"))?;
+        let started = Instant::now();
+        let connection = tokio::time::timeout(WAN_PROBE_TIMEOUT, dial)
+            .await
+            .map_err(|_| anyhow!("timed out"))??;
+        Ok::<_, anyhow::Error>((connection, started.elapsed()))
+    };
+    tokio::pin!(lan_probe, wan_probe);
+
+    // The WAN dials meanwhile, but only the LAN's answer decides
+    let mut wan_result = None;
+    let lan_result = loop {
+        tokio::select! {
+            biased;
+            lan = &mut lan_probe => break lan,
+            wan = &mut wan_probe, if wan_result.is_none() => wan_result = Some(wan),
+        }
+    };
+    let lan_error = match lan_result {
+        Ok((addr, rtt)) => return Ok(ChosenPath::Lan { addr, rtt }),
+        Err(e) => e,
+    };
+
+    let wan_result = match wan_result {
+        Some(result) => result,
+        None => wan_probe.await,
+    };
+    match wan_result {
+        Ok((connection, rtt)) => Ok(ChosenPath::Wan { connection, rtt }),
+        Err(wan_error) => Err(anyhow!(
+            "No route to the device: LAN {}; WAN {}",
+            lan_error,
+            wan_error
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::{make_client_endpoint, make_server_endpoint};
+
+    #[tokio::test]
+    async fn test_lan_wins_when_it_answers() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let server = make_server_endpoint("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let connection = server.accept().await.unwrap().await.unwrap();
+            connection.closed().await;
+        });
+        let client = make_client_endpoint().unwrap();
+
+        let chosen = choose_path(Some((&client, addr)), Some(async { Ok("wan") }))
+            .await
+            .unwrap();
+        assert!(matches!(chosen, ChosenPath::Lan { addr: a, .. } if a == addr));
+        assert_eq!(chosen.label(), "LAN");
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wan_carries_what_the_lan_cannot() {
+        let chosen = choose_path(None, Some(async { Ok("wan") })).await.unwrap();
+        assert!(matches!(
+            chosen,
+            ChosenPath::Wan {
+                connection: "wan",
+                ..
+            }
+        ));
+
+        let error = choose_path::<()>(None, Some(async { Err(anyhow!("relay down")) }))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("LAN not on the LAN"));
+        assert!(error.contains("WAN relay down"));
+
+        let none = choose_path::<()>(None, None::<std::future::Ready<Result<()>>>).await;
+        assert!(none.is_err());
+    }
+}
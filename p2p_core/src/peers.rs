@@ -0,0 +1,281 @@
+//! One entry per device, however it can be reached.
+//!
+//! LAN discovery, pairing and the rendezvous server each know some devices
+//! by endpoint ID, which is also the device's iroh identity. [`PeerRegistry`]
+//! folds their events into one [`KnownDevice`] per ID: the LAN address while
+//! the device announces itself, and whether it is known well enough to be
+//! dialed over the WAN. [`crate::path`] picks the route of a send from it.
+
+use crate::AppEvent;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Where a device answers on the LAN
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LanRoute {
+    /// Transfer server of the device
+    pub addr: SocketAddr,
+    /// Discovery round-trip time, once measured
+    pub rtt_ms: Option<u32>,
+}
+
+/// A device seen on the LAN, paired, or listed by the rendezvous server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KnownDevice {
+    pub endpoint_id: String,
+    /// Last name the device went by; its short ID until one is known
+    pub name: String,
+    /// Set while the device announces itself on the LAN
+    pub lan: Option<LanRoute>,
+    /// Paired with this device
+    pub paired: bool,
+    /// Listed in this device's rendezvous room
+    pub listed: bool,
+}
+
+impl KnownDevice {
+    fn new(endpoint_id: &str) -> Self {
+        Self {
+            endpoint_id: endpoint_id.to_string(),
+            name: endpoint_id.chars().take(12).collect(),
+            lan: None,
+            paired: false,
+            listed: false,
+        }
+    }
+
+    /// Worth dialing over the WAN: a device the user knows, not just any
+    /// instance on the network
+    pub fn wan_candidate(&self) -> bool {
+        self.paired || self.listed
+    }
+}
+
+/// Devices by endpoint ID, kept up to date from the event stream
+#[derive(Debug, Default)]
+pub struct PeerRegistry {
+    devices: HashMap<String, KnownDevice>,
+    /// This device, left out of every list
+    own_id: Option<String>,
+}
+
+impl PeerRegistry {
+    /// Take in what `event` says about devices
+    pub fn apply(&mut self, event: &AppEvent) {
+        match event {
+            AppEvent::BackendReady { endpoint_id, .. } => {
+                self.devices.remove(endpoint_id);
+                self.own_id = Some(endpoint_id.clone());
+            }
+            AppEvent::StateSnapshot(state) => {
+                self.own_id = Some(state.endpoint_id.clone());
+                self.devices.remove(&state.endpoint_id);
+                for device in self.devices.values_mut() {
+                    device.lan = None;
+                }
+                for peer in &state.peers {
+                    self.found(
+                        &peer.endpoint_id,
+                        &peer.ip,
+                        peer.port,
+                        &peer.display_name,
+                        peer.rtt_ms,
+                    );
+                }
+                self.forget_unreachable();
+            }
+            AppEvent::PeerFound {
+                endpoint_id,
+                ip,
+                port,
+                display_name,
+                rtt_ms,
+                ..
+            } => self.found(endpoint_id, ip, *port, display_name, *rtt_ms),
+            AppEvent::PeerListDelta(delta) => {
+                for event in delta.events() {
+                    self.apply(&event);
+                }
+            }
+            AppEvent::PeerLost { endpoint_id, .. } => {
+                let Some(device) = self.devices.get_mut(endpoint_id) else {
+                    return;
+                };
+                device.lan = None;
+                if !device.wan_candidate() {
+                    self.devices.remove(endpoint_id);
+                }
+            }
+            AppEvent::KnownPeersChanged { paired, .. } => {
+                for device in self.devices.values_mut() {
+                    device.paired = paired.contains(&device.endpoint_id);
+                }
+                for endpoint_id in paired {
+                    if let Some(device) = self.entry(endpoint_id) {
+                        device.paired = true;
+                    }
+                }
+                self.forget_unreachable();
+            }
+            AppEvent::MyDevices { devices } => {
+                for device in self.devices.values_mut() {
+                    device.listed = devices.iter().any(|d| d.endpoint_id == device.endpoint_id);
+                }
+                for listed in devices {
+                    if let Some(device) = self.entry(&listed.endpoint_id) {
+                        device.listed = true;
+                        if device.lan.is_none() {
+                            device.name = listed.name.clone();
+                        }
+                    }
+                }
+                self.forget_unreachable();
+            }
+            _ => {}
+        }
+    }
+
+    /// `endpoint_id` announced itself on the LAN
+    fn found(&mut self, endpoint_id: &str, ip: &str, port: u16, name: &str, rtt_ms: Option<u32>) {
+        let Ok(ip) = ip.parse::<IpAddr>() else {
+            return;
+        };
+        if let Some(device) = self.entry(endpoint_id) {
+            device.name = name.to_string();
+            device.lan = Some(LanRoute {
+                addr: SocketAddr::new(ip, port),
+                rtt_ms,
+            });
+        }
+    }
+
+    /// Entry of `endpoint_id`, created if new; `None` for this device
+    fn entry(&mut self, endpoint_id: &str) -> Option<&mut KnownDevice> {
+        if self.own_id.as_deref() == Some(endpoint_id) {
+            return None;
+        }
+        Some(
+            self.devices
+                .entry(endpoint_id.to_string())
+                .or_insert_with(|| KnownDevice::new(endpoint_id)),
+        )
+    }
+
+    /// Drop devices neither on the LAN nor known from elsewhere
+    fn forget_unreachable(&mut self) {
+        self.devices
+            .retain(|_, device| device.lan.is_some() || device.wan_candidate());
+    }
+
+    pub fn get(&self, endpoint_id: &str) -> Option<&KnownDevice> {
+        self.devices.get(endpoint_id)
+    }
+
+    /// Every known device, by name
+    pub fn devices(&self) -> Vec<&KnownDevice> {
+        let mut devices: Vec<_> = self.devices.values().collect();
+        devices.sort_by(|a, b| (&a.name, &a.endpoint_id).cmp(&(&b.name, &b.endpoint_id)));
+        devices
+    }
+
+    /// Known devices that are not on the LAN right now, by name
+    pub fn away(&self) -> Vec<&KnownDevice> {
+        self.devices()
+            .into_iter()
+            .filter(|device| device.lan.is_none() && device.wan_candidate())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PeerCapabilities;
+    use crate::rendezvous::RegisteredDevice;
+
+    fn found(endpoint_id: &str, ip: &str, name: &str) -> AppEvent {
+        AppEvent::PeerFound {
+            endpoint_id: endpoint_id.to_string(),
+            ip: ip.to_string(),
+            port: 9000,
+            hostname: name.to_string(),
+            display_name: name.to_string(),
+            capabilities: PeerCapabilities::default(),
+            version: None,
+            rtt_ms: Some(3),
+        }
+    }
+
+    fn lost(endpoint_id: &str) -> AppEvent {
+        AppEvent::PeerLost {
+            endpoint_id: endpoint_id.to_string(),
+            ip: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_lan_pairing_and_rendezvous_merge_by_endpoint_id() {
+        let mut registry = PeerRegistry::default();
+        registry.apply(&AppEvent::BackendReady {
+            endpoint_id: "me".to_string(),
+            device_name: "Me".to_string(),
+            transfer_port: 9000,
+            receive_only: false,
+        });
+        registry.apply(&found("laptop", "192.168.1.20", "Laptop"));
+        registry.apply(&found("guest", "192.168.1.30", "Guest"));
+        registry.apply(&AppEvent::KnownPeersChanged {
+            paired: vec!["laptop".to_string(), "office-pc-0000000".to_string()],
+            guests: Vec::new(),
+            guest_mode: false,
+            pinned: Vec::new(),
+            groups: Vec::new(),
+        });
+        registry.apply(&AppEvent::MyDevices {
+            devices: vec![
+                RegisteredDevice {
+                    endpoint_id: "phone".to_string(),
+                    name: "Phone".to_string(),
+                    last_seen: 0,
+                },
+                RegisteredDevice {
+                    endpoint_id: "me".to_string(),
+                    name: "Me".to_string(),
+                    last_seen: 0,
+                },
+            ],
+        });
+
+        let laptop = registry.get("laptop").unwrap();
+        assert_eq!(laptop.name, "Laptop");
+        assert!(laptop.paired && laptop.wan_candidate());
+        assert_eq!(
+            laptop.lan.unwrap().addr,
+            "192.168.1.20:9000".parse().unwrap()
+        );
+        assert!(!registry.get("guest").unwrap().wan_candidate());
+        assert!(registry.get("me").is_none());
+        let away: Vec<&str> = registry.away().iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(away, ["Phone", "office-pc-00"]);
+
+        // A paired device leaving the LAN stays, reachable over the WAN only
+        registry.apply(&lost("laptop"));
+        registry.apply(&lost("guest"));
+        let laptop = registry.get("laptop").unwrap();
+        assert!(laptop.lan.is_none());
+        assert_eq!(laptop.name, "Laptop");
+        assert!(registry.get("guest").is_none());
+
+        // Unpaired and unlisted: forgotten
+        registry.apply(&AppEvent::KnownPeersChanged {
+            paired: Vec::new(),
+            guests: Vec::new(),
+            guest_mode: false,
+            pinned: Vec::new(),
+            groups: Vec::new(),
+        });
+        assert!(registry.get("laptop").is_none());
+        assert_eq!(registry.devices().len(), 1);
+    }
+}
@@ -0,0 +1,428 @@
+//! Machine-wide policy for managed deployments.
+//!
+//! An administrator can drop a read-only `policy.json` in a system location
+//! (see [`policy_path`]) that overrides what users and profiles set:
+//!
+//! ```json
+//! {
+//!   "disable_wan_share": true,
+//!   "disable_http_share": true,
+//!   "download_dir": "/srv/incoming",
+//!   "require_verification": true
+//! }
+//! ```
+//!
+//! The backend refuses commands the policy forbids with
+//! [`AppEvent::PolicyViolation`](crate::AppEvent::PolicyViolation), saves
+//! received files only in the forced download folder whatever the profile
+//! says, on this device's disk rather than a remote storage, and with
+//! `require_verification` never trusts or keeps a pairing, so every new
+//! connection needs a code; only a send already verified reuses its
+//! connection, for at most
+//! [`CONNECTION_IDLE_EXPIRY`](crate::transfer::pool::CONNECTION_IDLE_EXPIRY).
+//! Without a policy file nothing is restricted. A file that exists but
+//! cannot be read or parsed restricts everything rather than nothing.
+//!
+//! A file named by [`POLICY_ENV`] only adds restrictions on top of the
+//! machine-wide one, so a user cannot lift the administrator's policy.
+
+use crate::AppCommand;
+use crate::node::NodeConfig;
+use crate::pairing::PairingStore;
+use crate::storage::LocalStorage;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+/// Environment variable pointing at another policy file, for testing a
+/// policy before deploying it; it restricts on top of the machine-wide one
+pub const POLICY_ENV: &str = "P2P_POLICY_FILE";
+
+const POLICY_FILE: &str = "policy.json";
+
+/// Settings forced by the administrator
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Refuse the WAN tunnel share and WAN connections
+    pub disable_wan_share: bool,
+    /// Refuse the HTTP share server and file links
+    pub disable_http_share: bool,
+    /// Save received files here, whatever the profile says
+    pub download_dir: Option<PathBuf>,
+    /// Ask for a verification code on every send; pairings are not kept
+    pub require_verification: bool,
+}
+
+/// What a refused command ran into, see
+/// [`AppEvent::PolicyViolation`](crate::AppEvent::PolicyViolation)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    WanShareDisabled,
+    HttpShareDisabled,
+    VerificationRequired,
+}
+
+impl PolicyRule {
+    /// Why the command was refused, for the user
+    pub fn reason(self) -> &'static str {
+        match self {
+            PolicyRule::WanShareDisabled => {
+                "Sharing over the internet is turned off on this device"
+            }
+            PolicyRule::HttpShareDisabled => {
+                "Sharing through the browser is turned off on this device"
+            }
+            PolicyRule::VerificationRequired => {
+                "Every send needs a verification code on this device; pairing is turned off"
+            }
+        }
+    }
+}
+
+/// The machine-wide policy file: `%ProgramData%\p2p_transfer` on Windows,
+/// `/Library/Application Support/p2p_transfer` on macOS and
+/// `/etc/p2p_transfer` elsewhere
+pub fn policy_path() -> PathBuf {
+    system_config_dir().join("p2p_transfer").join(POLICY_FILE)
+}
+
+/// The ProgramData folder from the shell, not from the user's environment
+#[cfg(windows)]
+fn system_config_dir() -> PathBuf {
+    use windows::Win32::System::Com::CoTaskMemFree;
+    use windows::Win32::UI::Shell::{FOLDERID_ProgramData, KF_FLAG_DEFAULT, SHGetKnownFolderPath};
+
+    // SAFETY: the shell allocates the returned string, which is copied and
+    // then freed exactly once
+    let dir = unsafe {
+        SHGetKnownFolderPath(&FOLDERID_ProgramData, KF_FLAG_DEFAULT, None).map(|path| {
+            let dir = path.to_string();
+            CoTaskMemFree(Some(path.0 as *const _));
+            dir
+        })
+    };
+    match dir {
+        Ok(Ok(dir)) => PathBuf::from(dir),
+        _ => PathBuf::from(r"C:\ProgramData"),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn system_config_dir() -> PathBuf {
+    PathBuf::from("/Library/Application Support")
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn system_config_dir() -> PathBuf {
+    PathBuf::from("/etc")
+}
+
+impl Policy {
+    /// Everything restricted, for a policy file that cannot be understood
+    pub fn strict() -> Self {
+        Self {
+            disable_wan_share: true,
+            disable_http_share: true,
+            download_dir: None,
+            require_verification: true,
+        }
+    }
+
+    /// The machine-wide policy, unrestricted if there is none, with the
+    /// restrictions of a [`POLICY_ENV`] file added
+    pub fn load() -> Self {
+        let policy = Self::load_from(&policy_path());
+        match std::env::var_os(POLICY_ENV) {
+            Some(path) => policy.restrict(Self::load_from(Path::new(&path))),
+            None => policy,
+        }
+    }
+
+    /// Everything either policy restricts; a forced download folder of
+    /// `self` wins over the one of `other`
+    pub fn restrict(self, other: Self) -> Self {
+        Self {
+            disable_wan_share: self.disable_wan_share || other.disable_wan_share,
+            disable_http_share: self.disable_http_share || other.disable_http_share,
+            download_dir: self.download_dir.or(other.download_dir),
+            require_verification: self.require_verification || other.require_verification,
+        }
+    }
+
+    /// Load from an explicit file: unrestricted if it is missing, strict if
+    /// it cannot be read or parsed
+    pub fn load_from(path: &Path) -> Self {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                tracing::warn!(
+                    "Cannot read policy {:?}, restricting everything: {}",
+                    path,
+                    e
+                );
+                return Self::strict();
+            }
+        };
+        match serde_json::from_str(&content) {
+            Ok(policy) => {
+                tracing::info!("Policy {:?} applies: {:?}", path, policy);
+                policy
+            }
+            Err(e) => {
+                tracing::warn!("Invalid policy {:?}, restricting everything: {}", path, e);
+                Self::strict()
+            }
+        }
+    }
+
+    /// Whether anything is restricted
+    pub fn is_managed(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// The rule `cmd` breaks, if any
+    pub fn check(&self, cmd: &AppCommand) -> Result<(), PolicyRule> {
+        match cmd {
+            AppCommand::Tracked { command, .. } => self.check(command),
+            AppCommand::StartWanShare | AppCommand::WanConnect { .. } if self.disable_wan_share => {
+                Err(PolicyRule::WanShareDisabled)
+            }
+            AppCommand::StartHttpServer
+            | AppCommand::ShareFileLink { .. }
+            | AppCommand::ShareFiles { .. }
+            | AppCommand::PushTextToWeb { .. }
+                if self.disable_http_share =>
+            {
+                Err(PolicyRule::HttpShareDisabled)
+            }
+            // The WAN share serves through the HTTP server
+            AppCommand::StartWanShare if self.disable_http_share => {
+                Err(PolicyRule::HttpShareDisabled)
+            }
+            AppCommand::CreatePairingInvite
+            | AppCommand::RedeemPairingInvite { .. }
+            | AppCommand::PairWithPeer { .. }
+                if self.require_verification =>
+            {
+                Err(PolicyRule::VerificationRequired)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `config` with the forced settings in place of the profile's
+    pub fn apply(&self, config: NodeConfig) -> NodeConfig {
+        let storage = match &self.download_dir {
+            Some(_) if !config.storage.is_local() => {
+                tracing::info!("Policy saves received files on this device, not remotely");
+                Arc::new(LocalStorage)
+            }
+            _ => config.storage,
+        };
+        let download_dir = match &self.download_dir {
+            Some(forced) if *forced != config.download_dir => {
+                tracing::info!(
+                    "Policy saves received files in {:?} instead of {:?}",
+                    forced,
+                    config.download_dir
+                );
+                forced.clone()
+            }
+            _ => config.download_dir,
+        };
+        NodeConfig {
+            download_dir,
+            pairing_store: self.pairings(config.pairing_store),
+            storage,
+            ..config
+        }
+    }
+
+    /// The pairings to use under this policy, for services started outside
+    /// the backend such as the WAN listener
+    pub fn pairings(&self, store: Arc<dyn PairingStore>) -> Arc<dyn PairingStore> {
+        if self.require_verification {
+            Arc::new(NoPairings(store))
+        } else {
+            store
+        }
+    }
+}
+
+/// Pairings under `require_verification`: nothing is trusted or remembered.
+/// Forgetting still reaches the wrapped store, so old pairings can be cleaned up.
+#[derive(Debug)]
+struct NoPairings(Arc<dyn PairingStore>);
+
+impl PairingStore for NoPairings {
+    fn is_paired(&self, _endpoint_id: &str) -> bool {
+        false
+    }
+
+    fn pair_key(&self, _endpoint_id: &str) -> Option<Zeroizing<String>> {
+        None
+    }
+
+    fn add_pairing(&self, _endpoint_id: &str, _peer_name: &str, _key: &str) {}
+
+    fn add_guest_pairing(&self, _endpoint_id: &str, _peer_name: &str, _key: &str) {}
+
+    fn guest_expiry(&self, _endpoint_id: &str) -> Option<u64> {
+        None
+    }
+
+    fn remove_pairing(&self, endpoint_id: &str) {
+        self.0.remove_pairing(endpoint_id);
+    }
+
+    fn get_all_pairings(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    fn add_receiver_key(&self, _peer_name: &str, _key: &str) {}
+
+    fn receiver_key(&self, _key_id: &str) -> Option<Zeroizing<String>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pairing::MemoryPairingStore;
+
+    #[test]
+    fn test_missing_file_restricts_nothing_and_broken_file_everything() {
+        let dir = std::env::temp_dir().join(format!("policy_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(POLICY_FILE);
+        assert_eq!(Policy::load_from(&path), Policy::default());
+        assert!(!Policy::load_from(&path).is_managed());
+
+        std::fs::write(
+            &path,
+            r#"{"disable_http_share": true, "download_dir": "/srv/in"}"#,
+        )
+        .unwrap();
+        let policy = Policy::load_from(&path);
+        assert!(policy.disable_http_share && !policy.disable_wan_share);
+        assert_eq!(policy.download_dir, Some(PathBuf::from("/srv/in")));
+
+        // A typo must not quietly lift the restrictions
+        std::fs::write(&path, r#"{"disable_http_shares": true}"#).unwrap();
+        assert_eq!(Policy::load_from(&path), Policy::strict());
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(Policy::load_from(&path), Policy::strict());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_restrict_never_lifts_a_restriction() {
+        let system = Policy {
+            disable_wan_share: true,
+            download_dir: Some(PathBuf::from("/srv/in")),
+            ..Default::default()
+        };
+        assert_eq!(system.clone().restrict(Policy::default()), system);
+
+        let extra = Policy {
+            disable_http_share: true,
+            download_dir: Some(PathBuf::from("/tmp/in")),
+            ..Default::default()
+        };
+        let merged = system.restrict(extra.clone());
+        assert!(merged.disable_wan_share && merged.disable_http_share);
+        assert!(!merged.require_verification);
+        assert_eq!(merged.download_dir, Some(PathBuf::from("/srv/in")));
+        assert_eq!(
+            Policy::default().restrict(extra).download_dir,
+            Some(PathBuf::from("/tmp/in"))
+        );
+    }
+
+    #[test]
+    fn test_check_refuses_only_what_the_policy_forbids() {
+        let link = AppCommand::ShareFileLink {
+            path: PathBuf::from("a.txt"),
+            expires_in_secs: None,
+            single_use: false,
+        };
+        assert_eq!(Policy::default().check(&link), Ok(()));
+        assert_eq!(Policy::default().check(&AppCommand::StartWanShare), Ok(()));
+
+        let policy = Policy {
+            disable_http_share: true,
+            ..Default::default()
+        };
+        assert_eq!(policy.check(&link), Err(PolicyRule::HttpShareDisabled));
+        let (_, tracked) = AppCommand::StartHttpServer.tracked();
+        assert_eq!(policy.check(&tracked), Err(PolicyRule::HttpShareDisabled));
+        assert_eq!(
+            policy.check(&AppCommand::StartWanShare),
+            Err(PolicyRule::HttpShareDisabled)
+        );
+        assert_eq!(policy.check(&AppCommand::StopHttpServer), Ok(()));
+
+        let strict = Policy::strict();
+        assert_eq!(
+            strict.check(&AppCommand::WanConnect {
+                target_endpoint_id: "peer".to_string()
+            }),
+            Err(PolicyRule::WanShareDisabled)
+        );
+        assert_eq!(
+            strict.check(&AppCommand::CreatePairingInvite),
+            Err(PolicyRule::VerificationRequired)
+        );
+        assert_eq!(strict.check(&AppCommand::ListKnownPeers), Ok(()));
+    }
+
+    #[test]
+    fn test_apply_forces_the_download_dir_and_forgets_pairings() {
+        let store = Arc::new(MemoryPairingStore::default());
+        store.add_pairing("peer", "Peer", "key");
+        let config = NodeConfig {
+            download_dir: PathBuf::from("/home/user/p2p_transfer"),
+            pairing_store: store.clone(),
+            ..Default::default()
+        };
+        let policy = Policy {
+            download_dir: Some(PathBuf::from("/srv/in")),
+            require_verification: true,
+            ..Default::default()
+        };
+        let config = policy.apply(config);
+        assert_eq!(config.download_dir, PathBuf::from("/srv/in"));
+        assert!(!config.pairing_store.is_paired("peer"));
+        config.pairing_store.add_pairing("other", "Other", "key");
+        assert!(!store.is_paired("other"));
+        config.pairing_store.remove_pairing("peer");
+        assert!(!store.is_paired("peer"));
+
+        let dav = crate::storage::StorageSettings::WebDav(crate::storage::webdav::WebDavSettings {
+            url: "https://nas.local/dav".to_string(),
+            ..Default::default()
+        });
+        let remote = NodeConfig {
+            storage: dav
+                .open(Path::new("/home/user/p2p_transfer"), &Default::default())
+                .unwrap()
+                .unwrap(),
+            ..Default::default()
+        };
+        assert!(!remote.storage.is_local());
+        assert!(policy.apply(remote).storage.is_local());
+
+        let unmanaged = Policy::default().apply(NodeConfig {
+            pairing_store: store.clone(),
+            ..Default::default()
+        });
+        unmanaged.pairing_store.add_pairing("other", "Other", "key");
+        assert!(store.is_paired("other"));
+    }
+}
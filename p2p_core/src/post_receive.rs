@@ -0,0 +1,333 @@
+//! Post-receive command for custom processing of received files.
+//!
+//! The configured command line, e.g. `ingest.sh {path} {peer}`, runs once
+//! per received file (see [`crate::received`]). It is split into arguments
+//! like a shell would split it, but no shell is involved: placeholders are
+//! replaced inside each argument, so a file or peer name cannot inject
+//! further commands. They are replaced in one pass, so a name that contains
+//! a placeholder is passed on as it is.
+//!
+//! | Placeholder | Value |
+//! |-------------|-------|
+//! | `{path}`    | absolute path of the saved file |
+//! | `{name}`    | file name as sent |
+//! | `{dir}`     | folder the file was saved in |
+//! | `{peer}`    | sender's name or endpoint ID, browser IP for uploads, empty if unknown |
+//!
+//! Each run starts in a fresh, empty working directory that is removed
+//! afterwards, with only `PATH` taken from the environment. Runs are queued
+//! one after another, up to [`MAX_QUEUED_RUNS`], stopped after
+//! [`POST_RECEIVE_TIMEOUT`], and their output is copied into the log.
+
+use crate::received::{self, ReceivedFile};
+use crate::{AppEvent, EventCategory, LogLevel};
+use anyhow::{Result, anyhow};
+use std::borrow::Cow;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+
+/// Time a run may take before it is killed
+pub const POST_RECEIVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Runs waiting at most; files received while the queue is full are skipped
+pub const MAX_QUEUED_RUNS: usize = 64;
+
+/// Output kept per stream; the rest is cut off in the log
+const MAX_LOGGED_OUTPUT: usize = 4096;
+
+/// Result of one finished run
+#[derive(Debug)]
+pub struct HookOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Command line run for every received file
+#[derive(Debug, Clone)]
+pub struct PostReceiveHook {
+    template: Vec<String>,
+    timeout: Duration,
+}
+
+impl PostReceiveHook {
+    /// Hook for a command line such as `ingest.sh {path} {peer}`
+    pub fn new(command: &str) -> Result<Self> {
+        let template = split_command(command)?;
+        if template.is_empty() {
+            return Err(anyhow!("Post-receive command is empty"));
+        }
+        Ok(Self {
+            template,
+            timeout: POST_RECEIVE_TIMEOUT,
+        })
+    }
+
+    /// Stop runs after `timeout` instead of [`POST_RECEIVE_TIMEOUT`]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Program and arguments for `file`, placeholders filled in
+    pub fn command_for(&self, file: &ReceivedFile) -> Vec<String> {
+        let path = std::path::absolute(&file.path).unwrap_or_else(|_| file.path.clone());
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let values = [
+            ("{path}", path.to_string_lossy()),
+            ("{name}", file.file_name.as_str().into()),
+            ("{dir}", dir.to_string_lossy()),
+            ("{peer}", file.peer.as_deref().unwrap_or("").into()),
+        ];
+        self.template
+            .iter()
+            .map(|arg| fill_placeholders(arg, &values))
+            .collect()
+    }
+
+    /// Run the command for `file` in a scratch directory and wait for it
+    pub async fn run(&self, file: &ReceivedFile) -> Result<HookOutput> {
+        let argv = self.command_for(file);
+        let work_dir = std::env::temp_dir().join(format!("p2p_hook_{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&work_dir).await?;
+        let result = self.run_in(&argv, &work_dir).await;
+        let _ = tokio::fs::remove_dir_all(&work_dir).await;
+        result
+    }
+
+    async fn run_in(&self, argv: &[String], work_dir: &Path) -> Result<HookOutput> {
+        let mut command = Command::new(&argv[0]);
+        command
+            .args(&argv[1..])
+            .current_dir(work_dir)
+            .env_clear()
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+        if let Some(path) = std::env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+        #[cfg(windows)]
+        if let Some(root) = std::env::var_os("SystemRoot") {
+            command.env("SystemRoot", root);
+        }
+
+        let output = tokio::time::timeout(self.timeout, command.output())
+            .await
+            .map_err(|_| anyhow!("timed out after {:?} and was stopped", self.timeout))?
+            .map_err(|e| anyhow!("could not start {}: {}", argv[0], e))?;
+        Ok(HookOutput {
+            status: output.status,
+            stdout: captured(&output.stdout),
+            stderr: captured(&output.stderr),
+        })
+    }
+}
+
+/// `arg` with each placeholder replaced by its value, left to right; values
+/// are not searched for placeholders again
+fn fill_placeholders(arg: &str, values: &[(&str, Cow<'_, str>)]) -> String {
+    let mut filled = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match values.iter().find(|(key, _)| rest.starts_with(key)) {
+            Some((key, value)) => {
+                filled.push_str(value);
+                rest = &rest[key.len()..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Split a command line at whitespace; single or double quotes group words
+fn split_command(command: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    for c in command.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            None if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(anyhow!("Unterminated quote in post-receive command"));
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+fn captured(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim();
+    if text.len() <= MAX_LOGGED_OUTPUT {
+        return text.to_string();
+    }
+    let mut end = MAX_LOGGED_OUTPUT;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+/// Forward `events` to `event_tx`, running `hook` for received files on
+/// the way. Ends when either side closes.
+pub async fn forward_events(
+    hook: PostReceiveHook,
+    events: mpsc::Receiver<AppEvent>,
+    event_tx: mpsc::Sender<AppEvent>,
+) {
+    let (queue_tx, mut queue_rx) = mpsc::channel::<ReceivedFile>(MAX_QUEUED_RUNS);
+    let log_tx = event_tx.clone();
+    tokio::spawn(async move {
+        while let Some(file) = queue_rx.recv().await {
+            for (level, message) in log_lines(&file.file_name, hook.run(&file).await) {
+                let _ = log_tx
+                    .send(AppEvent::log(level, EventCategory::Transfer, message))
+                    .await;
+            }
+        }
+    });
+
+    received::forward_events(events, event_tx, move |file| {
+        if let Err(mpsc::error::TrySendError::Full(file)) = queue_tx.try_send(file) {
+            tracing::warn!(
+                "Post-receive queue is full, not running the command for {}",
+                file.file_name
+            );
+        }
+    })
+    .await
+}
+
+fn log_lines(file_name: &str, result: Result<HookOutput>) -> Vec<(LogLevel, String)> {
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            return vec![(
+                LogLevel::Warning,
+                format!("Post-receive command for {}: {}", file_name, e),
+            )];
+        }
+    };
+    let mut lines = vec![if output.status.success() {
+        (
+            LogLevel::Info,
+            format!("Post-receive command for {} finished", file_name),
+        )
+    } else {
+        (
+            LogLevel::Warning,
+            format!(
+                "Post-receive command for {} failed ({})",
+                file_name, output.status
+            ),
+        )
+    }];
+    if !output.stdout.is_empty() {
+        lines.push((LogLevel::Info, format!("[{}] {}", file_name, output.stdout)));
+    }
+    if !output.stderr.is_empty() {
+        lines.push((
+            LogLevel::Warning,
+            format!("[{}] {}", file_name, output.stderr),
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn received(file_name: &str, path: &str) -> ReceivedFile {
+        ReceivedFile {
+            event: "transfer_completed",
+            file_name: file_name.to_string(),
+            path: PathBuf::from(path),
+            peer: Some("Laptop".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_placeholders_stay_within_their_argument() {
+        let hook = PostReceiveHook::new(r#"ingest.sh --from "{peer} via lan" {name}"#).unwrap();
+        let file = received("a b; rm -rf ~", "/downloads/a b; rm -rf ~");
+        assert_eq!(
+            hook.command_for(&file),
+            vec!["ingest.sh", "--from", "Laptop via lan", "a b; rm -rf ~"]
+        );
+
+        assert!(PostReceiveHook::new("  ").is_err());
+        assert!(PostReceiveHook::new("ingest.sh 'open").is_err());
+    }
+
+    #[test]
+    fn test_placeholders_in_values_are_not_expanded() {
+        let hook = PostReceiveHook::new("ingest.sh {name} {peer} {dir}").unwrap();
+        let file = ReceivedFile {
+            peer: Some("{name}".to_string()),
+            ..received("{peer}{path}.txt", "/downloads/{dir}/x.txt")
+        };
+        assert_eq!(
+            hook.command_for(&file),
+            vec![
+                "ingest.sh",
+                "{peer}{path}.txt",
+                "{name}",
+                "/downloads/{dir}"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_runs_in_scratch_dir_with_timeout() {
+        let hook = PostReceiveHook::new(r#"sh -c 'pwd; echo "$1" >&2' hook {path}"#).unwrap();
+        let output = hook
+            .run(&received("a.txt", "/downloads/a.txt"))
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout.contains("p2p_hook_"));
+        assert!(!Path::new(&output.stdout).exists());
+        assert_eq!(output.stderr, "/downloads/a.txt");
+
+        let hook = PostReceiveHook::new("sleep 5")
+            .unwrap()
+            .with_timeout(Duration::from_millis(100));
+        let error = hook
+            .run(&received("a.txt", "/downloads/a.txt"))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+    }
+}
@@ -0,0 +1,82 @@
+//! Noticing that the computer slept.
+//!
+//! Connections die while the computer sleeps, so the backend compares its
+//! clocks every [`SLEEP_CHECK_INTERVAL`] instead of waiting for an OS event.
+//! On Linux and macOS `Instant` stands still during sleep while the wall
+//! clock goes on; on Windows `Instant` goes on too, and the tick arrives
+//! late. Either way the gap shows: more than [`SLEEP_THRESHOLD`] past the
+//! interval counts as a sleep. A large change of the wall clock looks the
+//! same, which only costs a needless reconnect.
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the clocks are compared
+pub const SLEEP_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A gap this much longer than the interval was a sleep, not a busy moment
+pub const SLEEP_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Clock readings of the last check
+#[derive(Debug, Clone)]
+pub struct SleepDetector {
+    last: (Instant, SystemTime),
+}
+
+impl Default for SleepDetector {
+    fn default() -> Self {
+        Self {
+            last: (Instant::now(), SystemTime::now()),
+        }
+    }
+}
+
+impl SleepDetector {
+    /// How long the computer slept since the last check, if it did
+    pub fn check(&mut self) -> Option<Duration> {
+        self.check_at(Instant::now(), SystemTime::now())
+    }
+
+    fn check_at(&mut self, now: Instant, wall: SystemTime) -> Option<Duration> {
+        let (last, last_wall) = std::mem::replace(&mut self.last, (now, wall));
+        let elapsed = now
+            .saturating_duration_since(last)
+            // A clock set back counts as no time passing
+            .max(wall.duration_since(last_wall).unwrap_or_default());
+        (elapsed > SLEEP_CHECK_INTERVAL + SLEEP_THRESHOLD)
+            .then(|| elapsed.saturating_sub(SLEEP_CHECK_INTERVAL))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sleep_shows_on_either_clock() {
+        let start = Instant::now();
+        let wall = SystemTime::now();
+        let mut detector = SleepDetector {
+            last: (start, wall),
+        };
+        let hour = Duration::from_secs(3600);
+
+        // A regular tick
+        let tick = start + SLEEP_CHECK_INTERVAL;
+        let wall = wall + SLEEP_CHECK_INTERVAL;
+        assert_eq!(detector.check_at(tick, wall), None);
+
+        // Instant stood still (Linux, macOS)
+        let tick = tick + SLEEP_CHECK_INTERVAL;
+        let wall = wall + SLEEP_CHECK_INTERVAL + hour;
+        assert_eq!(detector.check_at(tick, wall), Some(hour));
+
+        // Instant went on (Windows)
+        let tick = tick + SLEEP_CHECK_INTERVAL + hour;
+        let wall = wall + SLEEP_CHECK_INTERVAL + hour;
+        assert_eq!(detector.check_at(tick, wall), Some(hour));
+
+        // The wall clock was set back
+        let tick = tick + SLEEP_CHECK_INTERVAL;
+        assert_eq!(detector.check_at(tick, wall - hour), None);
+    }
+}
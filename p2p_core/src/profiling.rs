@@ -0,0 +1,82 @@
+//! Profiling hooks around the hot paths, built with the `profiling` feature.
+//!
+//! Two recorders cover different work. [`profile_scope!`](crate::profile_scope)
+//! times CPU work (hashing, encoding messages) in puffin, which the GUI
+//! shows in its profiler window; puffin keeps a stack per thread, so a scope
+//! must not span an `.await`. [`profile_future!`](crate::profile_future)
+//! wraps reads and writes in a tracing span instead, which follows a future
+//! across threads. With [`FLAME_GRAPH_ENV`] set, [`flame_layer`] writes
+//! those spans as folded stacks for `inferno-flamegraph`.
+//!
+//! Without the feature the macros expand to nothing and cost nothing.
+
+#[cfg(feature = "profiling")]
+pub use puffin;
+#[cfg(feature = "profiling")]
+pub use tracing::{self, Instrument};
+
+/// Folded stacks of the read and write spans go to the file this names
+pub const FLAME_GRAPH_ENV: &str = "P2P_FLAME_GRAPH";
+
+/// Time the rest of the enclosing block in puffin
+#[cfg(feature = "profiling")]
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        $crate::profiling::puffin::profile_scope!($name);
+    };
+}
+
+/// Time the rest of the enclosing block in puffin
+#[cfg(not(feature = "profiling"))]
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {};
+}
+
+/// Record the polls of `future` as a span named `name`
+#[cfg(feature = "profiling")]
+#[macro_export]
+macro_rules! profile_future {
+    ($name:literal, $future:expr) => {
+        $crate::profiling::Instrument::instrument(
+            $future,
+            $crate::profiling::tracing::trace_span!($name),
+        )
+    };
+}
+
+/// Record the polls of `future` as a span named `name`
+#[cfg(not(feature = "profiling"))]
+#[macro_export]
+macro_rules! profile_future {
+    ($name:literal, $future:expr) => {
+        $future
+    };
+}
+
+#[cfg(feature = "profiling")]
+type FlameFile = std::io::BufWriter<std::fs::File>;
+
+/// Flame graph layer writing to the file of [`FLAME_GRAPH_ENV`], if set.
+/// Keep the guard until the end; it flushes the file when dropped.
+#[cfg(feature = "profiling")]
+pub fn flame_layer<S>() -> Option<(
+    tracing_flame::FlameLayer<S, FlameFile>,
+    tracing_flame::FlushGuard<FlameFile>,
+)>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    let path = std::env::var_os(FLAME_GRAPH_ENV)?;
+    match tracing_flame::FlameLayer::with_file(&path) {
+        Ok((layer, guard)) => Some((
+            layer.with_threads_collapsed(true).with_empty_samples(false),
+            guard,
+        )),
+        Err(e) => {
+            eprintln!("Could not write the flame graph: {}", e);
+            None
+        }
+    }
+}
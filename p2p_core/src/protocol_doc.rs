@@ -0,0 +1,418 @@
+//! Protocol description generated from the message types.
+//!
+//! The messages of the LAN transfer stream ([`TransferMsg`]) and of the
+//! share page's WebSocket ([`ClientMessage`], [`ServerMessage`]) derive
+//! [`JsonSchema`], as do the WAN messages of `p2p_wan`, which adds them
+//! with [`ProtocolDoc::channel`]. [`ProtocolDoc`] renders the schemas as one
+//! JSON document and as markdown for authors of other clients, stamped with
+//! [`PROTOCOL_VERSION`] and illustrated by the golden
+//! [`vectors`](crate::transfer::vectors). The copies under `docs/` are
+//! compared with freshly generated ones by a test in `p2p_wan`, so they
+//! change with every message.
+
+use crate::alpn::PROTOCOL_VERSION;
+use crate::http_share::websocket::{CHUNK_SIZE, ClientMessage, ServerMessage};
+use crate::transfer::vectors::{self, TestVector};
+use crate::transfer::{MAX_MSG_SIZE, TransferMsg};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Write;
+
+/// Messages sent over one kind of connection
+#[derive(Debug, Clone, Serialize)]
+pub struct Channel {
+    pub name: String,
+    /// How messages are framed on the wire
+    pub framing: String,
+    /// JSON Schema of a message, descriptions taken from the doc comments
+    pub schema: Value,
+    /// Golden bodies shown as examples
+    #[serde(skip)]
+    pub examples: &'static [TestVector],
+}
+
+/// The protocol of one version, channel by channel
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtocolDoc {
+    pub protocol_version: u32,
+    pub channels: Vec<Channel>,
+}
+
+/// The channels this crate speaks: LAN transfers and the share page
+pub fn describe() -> ProtocolDoc {
+    ProtocolDoc {
+        protocol_version: PROTOCOL_VERSION,
+        channels: Vec::new(),
+    }
+    .channel::<TransferMsg>(
+        "LAN transfer",
+        &length_prefixed("a bidirectional QUIC stream"),
+        vectors::VECTORS,
+    )
+    .channel::<ClientMessage>(
+        "Share page upload, browser to host",
+        "JSON text frames on the share page's WebSocket; the file's data \
+         follows the accepted upload in binary frames",
+        &[],
+    )
+    .channel::<ServerMessage>(
+        "Share page upload, host to browser",
+        &format!(
+            "JSON text frames on the share page's WebSocket; binary frames of \
+             at most {} bytes carry the file's data the other way",
+            CHUNK_SIZE
+        ),
+        &[],
+    )
+}
+
+/// Framing of the QUIC and iroh streams carried by `transport`
+pub fn length_prefixed(transport: &str) -> String {
+    format!(
+        "Over {}, each message is a 4-byte big-endian length followed by \
+         that many bytes of JSON, at most {} bytes",
+        transport, MAX_MSG_SIZE
+    )
+}
+
+impl ProtocolDoc {
+    /// Add the messages of type `T`
+    pub fn channel<T: JsonSchema>(
+        mut self,
+        name: &str,
+        framing: &str,
+        examples: &'static [TestVector],
+    ) -> Self {
+        let mut schema = schemars::schema_for!(T).to_value();
+        plain_descriptions(&mut schema);
+        self.channels.push(Channel {
+            name: name.to_string(),
+            framing: framing.to_string(),
+            schema,
+            examples,
+        });
+        self
+    }
+
+    /// Everything as one JSON document
+    pub fn to_json(&self) -> String {
+        let mut json =
+            serde_json::to_string_pretty(self).expect("schemas serialize to JSON") + "\n";
+        // Stable across platforms, like the markdown
+        json.retain(|c| c != '\r');
+        json
+    }
+
+    /// Everything as a markdown page
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(
+            md,
+            "# P2P Transfer protocol, version {}\n",
+            self.protocol_version
+        );
+        md.push_str(
+            "Generated from the message types by `p2p_core::protocol_doc`; do not edit. \
+             Builds of another protocol version refuse to connect.\n",
+        );
+        for channel in &self.channels {
+            channel.write_markdown(&mut md);
+        }
+        md
+    }
+}
+
+impl Channel {
+    fn write_markdown(&self, md: &mut String) {
+        let _ = writeln!(md, "\n## {}\n\n{}.", self.name, self.framing);
+        if let Some(description) = self.schema["description"].as_str() {
+            let _ = writeln!(md, "\n{}", one_line(description));
+        }
+        for message in messages(&self.schema) {
+            let _ = writeln!(md, "\n### `{}`\n", message.name);
+            if let Some(description) = message.description {
+                let _ = writeln!(md, "{}\n", one_line(description));
+            }
+            let _ = writeln!(md, "Sent as `{}`.", message.wire);
+            if let Some(fields) = message.fields {
+                write_fields(md, fields, message.tag);
+            }
+            if let Some(example) = self
+                .examples
+                .iter()
+                .find(|vector| vector.canonical && vector.name == message.name)
+            {
+                let _ = writeln!(
+                    md,
+                    "\nExample:\n\n```json\n{}\n```",
+                    String::from_utf8_lossy(example.body)
+                );
+            }
+        }
+        let Some(defs) = self.schema["$defs"]
+            .as_object()
+            .filter(|defs| !defs.is_empty())
+        else {
+            return;
+        };
+        let _ = writeln!(md, "\n### Types of {}", self.name.to_lowercase());
+        for (name, def) in defs {
+            let _ = writeln!(md, "\n#### `{}`", name);
+            if let Some(description) = def["description"].as_str() {
+                let _ = writeln!(md, "\n{}", one_line(description));
+            }
+            if def.get("properties").is_some() {
+                write_fields(md, def, None);
+            } else {
+                let _ = writeln!(md, "\nOne of:\n");
+                for (value, description) in variants(def) {
+                    let _ = match description {
+                        Some(description) => {
+                            writeln!(md, "- `{}`: {}", value, one_line(description))
+                        }
+                        None => writeln!(md, "- `{}`", value),
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// One message of a channel's `oneOf`
+struct Message<'a> {
+    name: String,
+    /// Shape of the encoded message
+    wire: String,
+    description: Option<&'a str>,
+    /// Object schema of the message's fields
+    fields: Option<&'a Value>,
+    /// Field naming the message in internally tagged enums
+    tag: Option<&'a str>,
+}
+
+fn messages(schema: &Value) -> Vec<Message<'_>> {
+    let mut messages = Vec::new();
+    for variant in schema["oneOf"].as_array().into_iter().flatten() {
+        let description = variant["description"].as_str();
+        // Unit variants: a bare string
+        let names = variant["enum"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .chain(variant.get("const"));
+        for name in names.filter_map(Value::as_str) {
+            messages.push(Message {
+                name: name.to_string(),
+                wire: format!("\"{}\"", name),
+                description,
+                fields: None,
+                tag: None,
+            });
+        }
+        let Some(properties) = variant["properties"].as_object() else {
+            continue;
+        };
+        // Internally tagged: `{"type": "name", ...}`
+        if let Some((tag, name)) = properties
+            .iter()
+            .find_map(|(tag, field)| Some((tag, field["const"].as_str()?)))
+        {
+            messages.push(Message {
+                name: name.to_string(),
+                wire: if properties.len() > 1 {
+                    format!("{{\"{}\": \"{}\", ...}}", tag, name)
+                } else {
+                    format!("{{\"{}\": \"{}\"}}", tag, name)
+                },
+                description,
+                fields: Some(variant),
+                tag: Some(tag),
+            });
+            continue;
+        }
+        // Externally tagged: `{"Name": {...}}`
+        for (name, fields) in properties {
+            messages.push(Message {
+                name: name.clone(),
+                wire: format!("{{\"{}\": {{...}}}}", name),
+                description,
+                fields: Some(fields),
+                tag: None,
+            });
+        }
+    }
+    messages
+}
+
+/// Table of an object schema's fields, without the tag
+fn write_fields(md: &mut String, object: &Value, tag: Option<&str>) {
+    let fields: Vec<_> = object["properties"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(name, _)| Some(name.as_str()) != tag)
+        .collect();
+    if fields.is_empty() {
+        return;
+    }
+    let required = |name: &str| {
+        object["required"]
+            .as_array()
+            .is_some_and(|required| required.iter().any(|field| field == name))
+    };
+    md.push_str("\n| Field | Type | Required | Description |\n|---|---|---|---|\n");
+    for (name, field) in fields {
+        let _ = writeln!(
+            md,
+            "| `{}` | {} | {} | {} |",
+            name,
+            type_name(field),
+            if required(name) { "yes" } else { "no" },
+            field["description"]
+                .as_str()
+                .map(one_line)
+                .unwrap_or_default()
+        );
+    }
+}
+
+/// Values of an enum schema with their descriptions
+fn variants(schema: &Value) -> Vec<(String, Option<&str>)> {
+    let mut values = Vec::new();
+    for variant in schema["oneOf"]
+        .as_array()
+        .map(|variants| variants.iter().collect())
+        .unwrap_or_else(|| vec![schema])
+    {
+        let description = variant["description"].as_str();
+        for value in variant["enum"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .chain(variant.get("const"))
+        {
+            values.push((value.to_string(), description));
+        }
+    }
+    values
+}
+
+/// Short type of a field schema, such as `uint64 or null`
+fn type_name(schema: &Value) -> String {
+    if let Some(reference) = schema["$ref"].as_str() {
+        return format!("`{}`", reference.rsplit('/').next().unwrap_or(reference));
+    }
+    if let Some(value) = schema.get("const") {
+        return format!("`{}`", value);
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        let values: Vec<_> = values.iter().map(|value| format!("`{}`", value)).collect();
+        return values.join(" or ");
+    }
+    if let Some(variants) = schema["anyOf"].as_array().or(schema["oneOf"].as_array()) {
+        let names: Vec<_> = variants.iter().map(type_name).collect();
+        return names.join(" or ");
+    }
+    let name = |kind: &str| match kind {
+        "integer" => schema["format"].as_str().unwrap_or(kind).to_string(),
+        "array" => format!("array of {}", type_name(&schema["items"])),
+        other => other.to_string(),
+    };
+    match &schema["type"] {
+        Value::String(kind) => name(kind),
+        Value::Array(kinds) => {
+            let names: Vec<_> = kinds.iter().filter_map(Value::as_str).map(name).collect();
+            names.join(" or ")
+        }
+        _ => "any".to_string(),
+    }
+}
+
+/// A doc comment on one line, for paragraphs and table cells
+fn one_line(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('|', "\\|")
+}
+
+/// Replace rustdoc links, which mean nothing outside the crate, by their
+/// text in every description
+fn plain_descriptions(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    Value::String(text) if key == "description" => *text = strip_links(text),
+                    _ => plain_descriptions(value),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(plain_descriptions),
+        _ => {}
+    }
+}
+
+/// `[text](target)` and `[`path`]` to their text
+fn strip_links(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        plain.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let Some((label, after)) = rest
+            .split_once(']')
+            .filter(|(label, _)| !label.contains('['))
+        else {
+            plain.push('[');
+            continue;
+        };
+        if let Some(end) = after.strip_prefix('(').and_then(|target| target.find(')')) {
+            plain.push_str(label);
+            rest = &after[end + 2..];
+        } else if label.len() > 1 && label.starts_with('`') && label.ends_with('`') {
+            plain.push_str(label);
+            rest = after;
+        } else {
+            plain.push('[');
+        }
+    }
+    plain.push_str(rest);
+    plain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rustdoc_links_are_stripped() {
+        assert_eq!(
+            strip_links("Answer to [`Ping`](Self::Ping); see [`ack`](super::ack)."),
+            "Answer to `Ping`; see `ack`."
+        );
+        assert_eq!(
+            strip_links("Bytes as `[u8]` and [x], see [`transfer::xattrs`]"),
+            "Bytes as `[u8]` and [x], see `transfer::xattrs`"
+        );
+    }
+
+    #[test]
+    fn test_every_message_is_described() {
+        let doc = describe();
+        let md = doc.to_markdown();
+        for vector in vectors::VECTORS {
+            assert!(
+                md.contains(&format!("### `{}`", vector.name)),
+                "{}",
+                vector.name
+            );
+        }
+        assert!(md.contains("### `file_info`"));
+        assert!(md.contains("Sent as `{\"type\": \"awaiting_approval\", ...}`."));
+        assert!(md.contains("| `endpoint_id` | string | yes |"));
+        assert!(!doc.to_json().contains("](Self::"));
+        assert!(md.contains(&format!("version {}", PROTOCOL_VERSION)));
+    }
+}
@@ -0,0 +1,193 @@
+//! Mark of the web for received programs.
+//!
+//! A program that arrives from another device is tagged the way a browser
+//! tags downloads, so the system asks before running it: on Windows with a
+//! `Zone.Identifier` stream naming the Internet zone, on macOS with the
+//! `com.apple.quarantine` attribute Gatekeeper checks. Other systems have no
+//! such mark. Either way the receiver is told that a program arrived.
+//!
+//! Programs are recognized by their extension
+//! ([`crate::transfer::EXECUTABLE_EXTENSIONS`]). Like [`crate::webhook`]
+//! and [`crate::post_receive`], tagging sits in the event stream (see
+//! [`crate::received`]); it comes first, so the others see tagged files.
+
+use crate::received::{self, ReceivedFile};
+use crate::transfer::is_executable;
+use crate::{AppEvent, EventCategory, LogLevel};
+use std::io;
+use std::path::Path;
+use tokio::sync::mpsc;
+
+/// Tag `path` as downloaded. Returns false where the system has no mark.
+pub fn mark(path: &Path) -> io::Result<bool> {
+    // Not a file of ours when it went to remote storage; on Windows writing
+    // the stream would create it
+    std::fs::metadata(path)?;
+    platform::mark(path)
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    /// Zone 3 is the Internet, the zone SmartScreen checks
+    const ZONE_IDENTIFIER: &str = "[ZoneTransfer]\r\nZoneId=3\r\n";
+
+    pub fn mark(path: &Path) -> io::Result<bool> {
+        let mut stream = path.as_os_str().to_owned();
+        stream.push(":Zone.Identifier");
+        std::fs::write(stream, ZONE_IDENTIFIER)?;
+        Ok(true)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    /// Downloaded, and to be checked by Gatekeeper on first open
+    const QUARANTINE_FLAGS: &str = "0081";
+
+    /// Name the tagged files are said to come from
+    const AGENT_NAME: &str = "P2P Transfer";
+
+    pub fn mark(path: &Path) -> io::Result<bool> {
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        let value = format!(
+            "{};{:08x};{};{}",
+            QUARANTINE_FLAGS,
+            seconds,
+            AGENT_NAME,
+            uuid::Uuid::new_v4().to_string().to_uppercase()
+        );
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let name = c"com.apple.quarantine";
+        // SAFETY: both strings are NUL-terminated and outlive the call
+        let result = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+                0,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    pub fn mark(_path: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Log lines for a received `file`, tagging it if it is a program
+fn tag(file: &ReceivedFile) -> Vec<(LogLevel, String)> {
+    if !is_executable(&file.file_name) {
+        return Vec::new();
+    }
+    let from = file
+        .peer
+        .as_deref()
+        .map(|peer| format!(" from {}", peer))
+        .unwrap_or_default();
+    match mark(&file.path) {
+        Ok(true) => vec![(
+            LogLevel::Warning,
+            format!(
+                "{} is a program{}; it is marked as downloaded, so the system asks before running it",
+                file.file_name, from
+            ),
+        )],
+        Ok(false) => vec![(
+            LogLevel::Warning,
+            format!(
+                "{} is a program{}; only run it if you trust the sender",
+                file.file_name, from
+            ),
+        )],
+        // Saved to remote storage, where it runs nowhere
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => vec![(
+            LogLevel::Warning,
+            format!(
+                "{} is a program{} and could not be marked as downloaded ({}); only run it if you trust the sender",
+                file.file_name, from, e
+            ),
+        )],
+    }
+}
+
+/// Forward `events` to `event_tx`, tagging received programs on the way.
+/// Ends when either side closes.
+pub async fn forward_events(events: mpsc::Receiver<AppEvent>, event_tx: mpsc::Sender<AppEvent>) {
+    let log_tx = event_tx.clone();
+    received::forward_events(events, event_tx, move |file| {
+        let lines = tag(&file);
+        if lines.is_empty() {
+            return;
+        }
+        let log_tx = log_tx.clone();
+        tokio::spawn(async move {
+            for (level, message) in lines {
+                let _ = log_tx
+                    .send(AppEvent::log(level, EventCategory::Transfer, message))
+                    .await;
+            }
+        });
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(path: &Path, name: &str) -> ReceivedFile {
+        ReceivedFile {
+            event: "transfer_completed",
+            file_name: name.to_string(),
+            path: path.to_path_buf(),
+            peer: Some("Laptop".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_only_programs_are_tagged() {
+        let dir = std::env::temp_dir().join(format!("quarantine_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let document = dir.join("notes.txt");
+        let program = dir.join("setup.exe");
+        std::fs::write(&document, b"notes").unwrap();
+        std::fs::write(&program, b"MZ").unwrap();
+
+        assert!(tag(&received(&document, "notes.txt")).is_empty());
+        let lines = tag(&received(&program, "setup.exe"));
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].0, LogLevel::Warning);
+        assert!(lines[0].1.starts_with("setup.exe is a program from Laptop"));
+        // The file itself is untouched
+        assert_eq!(std::fs::read(&program).unwrap(), b"MZ");
+
+        // Remote storage leaves nothing here to tag
+        assert!(tag(&received(&dir.join("gone.exe"), "gone.exe")).is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,86 @@
+//! Files that arrived, as seen in the backend's event stream.
+//!
+//! Notifications that act on received files ([`crate::webhook`],
+//! [`crate::post_receive`]) sit between the backend and its consumers via
+//! [`forward_events`]. A received file is an
+//! [`AppEvent::TransferCompleted`] with a saved path (LAN and WAN) or an
+//! [`AppEvent::UploadCompleted`] (browser uploads); sent files are not
+//! reported.
+
+use crate::AppEvent;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+
+/// One file that was saved to disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFile {
+    /// `transfer_completed` or `upload_completed`
+    pub event: &'static str,
+    pub file_name: String,
+    pub path: PathBuf,
+    /// Sender's display name or endpoint ID, or the browser's IP address
+    pub peer: Option<String>,
+}
+
+/// Picks received files out of the event stream
+#[derive(Debug, Default)]
+pub struct ReceivedFiles {
+    /// Browser uploads name their client only in the request
+    upload_clients: HashMap<String, String>,
+}
+
+impl ReceivedFiles {
+    pub fn observe(&mut self, event: &AppEvent) -> Option<ReceivedFile> {
+        match event {
+            AppEvent::UploadRequest {
+                file_name, from_ip, ..
+            }
+            | AppEvent::UploadAutoApproved {
+                file_name, from_ip, ..
+            } => {
+                self.upload_clients
+                    .insert(file_name.clone(), from_ip.clone());
+                None
+            }
+            AppEvent::TransferCompleted {
+                file_name,
+                saved_path: Some(path),
+                peer,
+            } => Some(ReceivedFile {
+                event: "transfer_completed",
+                file_name: file_name.clone(),
+                path: path.clone(),
+                peer: peer.clone(),
+            }),
+            AppEvent::UploadCompleted {
+                file_name,
+                saved_path,
+            } => Some(ReceivedFile {
+                event: "upload_completed",
+                file_name: file_name.clone(),
+                path: PathBuf::from(saved_path),
+                peer: self.upload_clients.remove(file_name),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Forward `events` to `event_tx`, calling `on_received` for every received
+/// file on the way. Ends when either side closes.
+pub async fn forward_events(
+    mut events: mpsc::Receiver<AppEvent>,
+    event_tx: mpsc::Sender<AppEvent>,
+    mut on_received: impl FnMut(ReceivedFile) + Send,
+) {
+    let mut received = ReceivedFiles::default();
+    while let Some(event) = events.recv().await {
+        if let Some(file) = received.observe(&event) {
+            on_received(file);
+        }
+        if event_tx.send(event).await.is_err() {
+            break;
+        }
+    }
+}
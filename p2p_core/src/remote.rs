@@ -0,0 +1,55 @@
+//! Control of a running backend beside its frontend.
+//!
+//! The backend loop serves one command channel, owned by the frontend.
+//! [`RemoteControl`] is a second, cloneable way in for services inside the
+//! backend, such as the HTTP owner page ([`crate::http_share::owner`]):
+//! requests are answered directly instead of through the event stream, so
+//! the frontend does not see replies it never asked for.
+
+use crate::AppCommand;
+use crate::state::BackendState;
+use anyhow::{Result, anyhow};
+use tokio::sync::{mpsc, oneshot};
+
+/// One request to the backend loop
+#[derive(Debug)]
+pub enum RemoteRequest {
+    /// Answer with the current [`BackendState`], peers and transfers included
+    State(oneshot::Sender<BackendState>),
+    /// Run a command and answer with its result
+    Command(AppCommand, oneshot::Sender<Result<(), String>>),
+}
+
+/// Handle for sending [`RemoteRequest`]s to the backend loop
+#[derive(Debug, Clone)]
+pub struct RemoteControl {
+    tx: mpsc::Sender<RemoteRequest>,
+}
+
+impl RemoteControl {
+    /// Handle and the receiver the backend loop serves
+    pub fn channel(capacity: usize) -> (Self, mpsc::Receiver<RemoteRequest>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (Self { tx }, rx)
+    }
+
+    pub async fn state(&self) -> Result<BackendState> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(RemoteRequest::State(reply))
+            .await
+            .map_err(|_| anyhow!("Backend is not running"))?;
+        rx.await.map_err(|_| anyhow!("Backend stopped"))
+    }
+
+    pub async fn command(&self, command: AppCommand) -> Result<()> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(RemoteRequest::Command(command, reply))
+            .await
+            .map_err(|_| anyhow!("Backend is not running"))?;
+        rx.await
+            .map_err(|_| anyhow!("Backend stopped"))?
+            .map_err(|e| anyhow!(e))
+    }
+}
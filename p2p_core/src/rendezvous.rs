@@ -0,0 +1,287 @@
+//! Client of a self-hosted rendezvous server (the `p2p_rendezvous` binary).
+//!
+//! Devices configured with the same room secret register their endpoint ID
+//! and name with the server every [`REFRESH_INTERVAL`], and any of them can
+//! list the others to connect over WAN without copying endpoint IDs around.
+//! The server only learns the [`room_id`], a hash of the secret. Each
+//! registration is signed with the device's Iroh secret key, so nobody can
+//! register an endpoint ID they do not own; devices that stop refreshing
+//! drop off the list after [`REGISTRATION_TTL_SECS`].
+
+use crate::pairing::key::{hex_decode, hex_encode};
+use crate::pairing::now_timestamp;
+use crate::proxy::ProxySettings;
+use crate::{AppEvent, EventCategory, LogLevel};
+use anyhow::{Result, anyhow};
+use iroh::{PublicKey, SecretKey, Signature};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// A registration not refreshed for this long is dropped
+pub const REGISTRATION_TTL_SECS: u64 = 600;
+
+/// How often a device renews its registration
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(240);
+
+/// Oldest (or furthest in the future) registration timestamp accepted
+pub const MAX_CLOCK_SKEW_SECS: u64 = 300;
+
+/// Longest device name a registration may carry
+pub const MAX_DEVICE_NAME_LEN: usize = 64;
+
+const ROOM_CONTEXT: &str = "p2p-transfer 2025 rendezvous room";
+const SIGNATURE_LABEL: &str = "p2p-transfer rendezvous registration";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Rendezvous server and room, stored in `config.json`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RendezvousSettings {
+    /// Base URL of the server, e.g. `https://rendezvous.example.com`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Secret shared by the user's devices; never sent to the server
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub room_secret: String,
+}
+
+impl RendezvousSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.url
+            .as_deref()
+            .is_some_and(|url| !url.trim().is_empty())
+            && !self.room_secret.is_empty()
+    }
+}
+
+/// Public name of the room a secret opens
+pub fn room_id(room_secret: &str) -> String {
+    blake3::Hash::from_bytes(blake3::derive_key(ROOM_CONTEXT, room_secret.as_bytes()))
+        .to_hex()
+        .to_string()
+}
+
+/// Whether `room` has the shape of a [`room_id`]
+pub fn is_room_id(room: &str) -> bool {
+    room.len() == 64 && room.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// A device announcing itself in a room
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registration {
+    pub endpoint_id: String,
+    pub name: String,
+    /// Unix time of signing
+    pub timestamp: u64,
+    /// Hex Ed25519 signature by the endpoint's key, see [`Registration::signed`]
+    pub signature: String,
+}
+
+impl Registration {
+    /// Registration of the device holding `key` in `room_id`, signed now
+    pub fn signed(room_id: &str, key: &SecretKey, name: &str, timestamp: u64) -> Self {
+        let endpoint_id = key.public().to_string();
+        let message = signed_message(room_id, &endpoint_id, name, timestamp);
+        Self {
+            signature: hex_encode(&key.sign(&message).to_bytes()),
+            endpoint_id,
+            name: name.to_string(),
+            timestamp,
+        }
+    }
+
+    /// Check the signature, the name and that the timestamp is near `now`
+    pub fn verify(&self, room_id: &str, now: u64) -> Result<()> {
+        if self.name.trim().is_empty() || self.name.chars().count() > MAX_DEVICE_NAME_LEN {
+            return Err(anyhow!(
+                "Device name must be 1 to {} characters",
+                MAX_DEVICE_NAME_LEN
+            ));
+        }
+        if self.timestamp.abs_diff(now) > MAX_CLOCK_SKEW_SECS {
+            return Err(anyhow!("Registration is too old or from the future"));
+        }
+        let public =
+            PublicKey::from_str(&self.endpoint_id).map_err(|_| anyhow!("Invalid endpoint ID"))?;
+        let signature = hex_decode(&self.signature)
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+            .ok_or_else(|| anyhow!("Malformed signature"))?;
+        let message = signed_message(room_id, &self.endpoint_id, &self.name, self.timestamp);
+        public
+            .verify(&message, &Signature::from_bytes(&signature))
+            .map_err(|_| anyhow!("Signature does not match the endpoint ID"))
+    }
+}
+
+fn signed_message(room_id: &str, endpoint_id: &str, name: &str, timestamp: u64) -> Vec<u8> {
+    let mut hasher = blake3::Hasher::new();
+    for part in [SIGNATURE_LABEL, room_id, endpoint_id, name] {
+        hasher.update(&(part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher.update(&timestamp.to_be_bytes());
+    hasher.finalize().as_bytes().to_vec()
+}
+
+/// A device listed in a room
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisteredDevice {
+    pub endpoint_id: String,
+    pub name: String,
+    /// Unix time of its last registration
+    pub last_seen: u64,
+}
+
+/// Talks to one room of a rendezvous server
+#[derive(Debug, Clone)]
+pub struct RendezvousClient {
+    devices_url: reqwest::Url,
+    room_id: String,
+    client: reqwest::Client,
+}
+
+impl RendezvousClient {
+    /// Client for the configured server, through `proxy` unless bypassed
+    pub fn new(settings: &RendezvousSettings, proxy: &ProxySettings) -> Result<Self> {
+        if !settings.is_enabled() {
+            return Err(anyhow!(
+                "Rendezvous server and room secret must both be set"
+            ));
+        }
+        let raw = settings.url.as_deref().unwrap_or_default().trim();
+        let mut base =
+            reqwest::Url::parse(raw).map_err(|e| anyhow!("Invalid rendezvous URL: {}", e))?;
+        if !matches!(base.scheme(), "http" | "https") {
+            return Err(anyhow!("Rendezvous URL must use http or https"));
+        }
+        // Keep a path prefix when joining
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        let room_id = room_id(&settings.room_secret);
+        let devices_url = base
+            .join(&format!("v1/rooms/{}/devices", room_id))
+            .map_err(|e| anyhow!("Invalid rendezvous URL: {}", e))?;
+
+        let mut builder = reqwest::Client::builder().timeout(REQUEST_TIMEOUT);
+        if let Some(proxy_url) = base.host_str().and_then(|host| proxy.url_for(host)) {
+            match reqwest::Proxy::all(proxy_url) {
+                Ok(proxy) => builder = builder.proxy(proxy),
+                Err(e) => tracing::warn!("Rendezvous server reached directly: {}", e),
+            }
+        }
+        Ok(Self {
+            devices_url,
+            room_id,
+            client: builder.build()?,
+        })
+    }
+
+    /// Announce the device holding `key` under `name`
+    pub async fn register(&self, key: &SecretKey, name: &str) -> Result<()> {
+        let name: String = name.chars().take(MAX_DEVICE_NAME_LEN).collect();
+        let registration = Registration::signed(&self.room_id, key, &name, now_timestamp());
+        self.client
+            .put(self.devices_url.clone())
+            .json(&registration)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Devices currently registered in the room
+    pub async fn devices(&self) -> Result<Vec<RegisteredDevice>> {
+        Ok(self
+            .client
+            .get(self.devices_url.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+/// Register now and every [`REFRESH_INTERVAL`] until aborted. The first
+/// failure in a row is logged as a warning, later ones only at debug level.
+pub fn spawn_registration(
+    client: RendezvousClient,
+    key: SecretKey,
+    name: String,
+    event_tx: mpsc::Sender<AppEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        let mut failing = false;
+        loop {
+            interval.tick().await;
+            match client.register(&key, &name).await {
+                Ok(()) => failing = false,
+                Err(e) if failing => tracing::debug!("Rendezvous registration failed: {}", e),
+                Err(e) => {
+                    failing = true;
+                    let _ = event_tx
+                        .send(AppEvent::log(
+                            LogLevel::Warning,
+                            EventCategory::Wan,
+                            format!("Could not register with the rendezvous server: {}", e),
+                        ))
+                        .await;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registration_signature() {
+        let key = SecretKey::generate(&mut rand::rng());
+        let room = room_id("family photos");
+        assert!(is_room_id(&room));
+        let registration = Registration::signed(&room, &key, "Laptop", 1_000);
+        assert!(registration.verify(&room, 1_100).is_ok());
+
+        // Another room, a late replay, a renamed device or a borrowed ID
+        assert!(registration.verify(&room_id("work"), 1_100).is_err());
+        assert!(
+            registration
+                .verify(&room, 1_000 + MAX_CLOCK_SKEW_SECS + 1)
+                .is_err()
+        );
+        let renamed = Registration {
+            name: "Desktop".to_string(),
+            ..registration.clone()
+        };
+        assert!(renamed.verify(&room, 1_100).is_err());
+        let other = SecretKey::generate(&mut rand::rng());
+        let borrowed = Registration {
+            endpoint_id: other.public().to_string(),
+            ..registration
+        };
+        assert!(borrowed.verify(&room, 1_100).is_err());
+    }
+
+    #[test]
+    fn test_client_needs_url_and_secret() {
+        let settings = |url: &str, secret: &str| RendezvousSettings {
+            url: Some(url.to_string()),
+            room_secret: secret.to_string(),
+        };
+        let proxy = ProxySettings::default();
+        assert!(RendezvousClient::new(&settings("http://localhost:7780", ""), &proxy).is_err());
+        assert!(RendezvousClient::new(&settings("ftp://host", "s"), &proxy).is_err());
+        let client =
+            RendezvousClient::new(&settings("http://localhost:7780/", "s"), &proxy).unwrap();
+        assert_eq!(
+            client.devices_url.as_str(),
+            format!("http://localhost:7780/v1/rooms/{}/devices", room_id("s"))
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Automatic cleanup of the download folder.
+//!
+//! A [`RetentionPolicy`] deletes received files older than a number of
+//! days and/or trims the folder to a size cap, oldest files first. Files
+//! listed in `exclusions` are never touched, and a dry run only reports what
+//! would be deleted.
+//!
+//! Received files may carry the sender's modification time, so a file's age
+//! is taken from the later of its creation and modification times, which is
+//! when it arrived here.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the background task applies the policy
+pub const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Files touched this recently may still be receiving and are left alone
+const IN_PROGRESS_GRACE: Duration = Duration::from_secs(10 * 60);
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Cleanup rules, stored in `config.json`; all rules are off by default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Delete files received more than this many days ago
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u32>,
+    /// Keep the folder under this many bytes, deleting the oldest files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_bytes: Option<u64>,
+    /// Paths relative to the download folder (or bare file names) to keep
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclusions: Vec<String>,
+    /// Only report what would be deleted
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days.is_some() || self.max_total_bytes.is_some()
+    }
+
+    fn is_excluded(&self, relative: &Path) -> bool {
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let file_name = relative.rsplit('/').next().unwrap_or(&relative);
+        self.exclusions
+            .iter()
+            .any(|pattern| pattern == &relative || pattern == file_name)
+    }
+}
+
+/// Files deleted (or, in a dry run, to be deleted) by one cleanup pass
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    pub dry_run: bool,
+    /// Paths relative to the download folder
+    pub files: Vec<String>,
+    pub freed_bytes: u64,
+}
+
+struct Candidate {
+    path: PathBuf,
+    relative: PathBuf,
+    size: u64,
+    received: SystemTime,
+}
+
+/// Apply `policy` to `dir` as of `now`. Files that fail to delete are left
+/// out of the report.
+pub fn run_cleanup(dir: &Path, policy: &RetentionPolicy, now: SystemTime) -> CleanupReport {
+    let mut report = CleanupReport {
+        dry_run: policy.dry_run,
+        ..CleanupReport::default()
+    };
+    if !policy.is_enabled() {
+        return report;
+    }
+
+    let mut files = Vec::new();
+    collect_files(dir, dir, &mut files);
+    // Oldest first, so the size cap removes the oldest files
+    files.sort_by_key(|file| file.received);
+
+    let mut total: u64 = files.iter().map(|file| file.size).sum();
+    let max_age = policy
+        .max_age_days
+        .map(|days| Duration::from_secs(u64::from(days) * SECS_PER_DAY));
+
+    for file in files {
+        let age = now.duration_since(file.received).unwrap_or_default();
+        if age < IN_PROGRESS_GRACE || policy.is_excluded(&file.relative) {
+            continue;
+        }
+        let too_old = max_age.is_some_and(|max_age| age > max_age);
+        let over_cap = policy.max_total_bytes.is_some_and(|cap| total > cap);
+        if !too_old && !over_cap {
+            continue;
+        }
+
+        if !policy.dry_run
+            && let Err(e) = fs::remove_file(&file.path)
+        {
+            tracing::warn!("Cleanup could not delete {:?}: {}", file.path, e);
+            continue;
+        }
+        total = total.saturating_sub(file.size);
+        report.freed_bytes += file.size;
+        report
+            .files
+            .push(file.relative.to_string_lossy().into_owned());
+    }
+    report
+}
+
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<Candidate>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Do not follow symlinks out of the download folder
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            collect_files(root, &path, out);
+        } else if metadata.is_file() {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            let received = metadata.created().map_or(modified, |c| c.max(modified));
+            out.push(Candidate {
+                relative: path.strip_prefix(root).unwrap_or(&path).to_path_buf(),
+                path,
+                size: metadata.len(),
+                received,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("retention_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("album")).unwrap();
+        fs::write(dir.join("a.bin"), vec![0u8; 1000]).unwrap();
+        fs::write(dir.join("keep.txt"), vec![0u8; 1000]).unwrap();
+        fs::write(dir.join("album/b.bin"), vec![0u8; 1000]).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_age_rule_with_exclusions_and_dry_run() {
+        let dir = scratch_dir();
+        let in_ten_days = SystemTime::now() + Duration::from_secs(10 * SECS_PER_DAY);
+        let mut policy = RetentionPolicy {
+            max_age_days: Some(7),
+            exclusions: vec!["keep.txt".to_string()],
+            dry_run: true,
+            ..RetentionPolicy::default()
+        };
+
+        // Fresh files are kept
+        assert!(
+            run_cleanup(&dir, &policy, SystemTime::now())
+                .files
+                .is_empty()
+        );
+
+        let mut report = run_cleanup(&dir, &policy, in_ten_days);
+        report.files.sort();
+        assert_eq!(report.files, vec!["a.bin", "album/b.bin"]);
+        assert_eq!(report.freed_bytes, 2000);
+        assert!(dir.join("a.bin").exists(), "dry run deletes nothing");
+
+        policy.dry_run = false;
+        assert_eq!(run_cleanup(&dir, &policy, in_ten_days).files.len(), 2);
+        assert!(!dir.join("a.bin").exists());
+        assert!(dir.join("keep.txt").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_size_cap_removes_only_what_is_needed() {
+        let dir = scratch_dir();
+        let later = SystemTime::now() + Duration::from_secs(SECS_PER_DAY);
+        let policy = RetentionPolicy {
+            max_total_bytes: Some(2000),
+            ..RetentionPolicy::default()
+        };
+
+        let report = run_cleanup(&dir, &policy, later);
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.freed_bytes, 1000);
+        assert!(run_cleanup(&dir, &policy, later).files.is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
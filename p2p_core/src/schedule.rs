@@ -0,0 +1,134 @@
+//! Transfers scheduled for later (e.g. off-peak hours).
+//!
+//! Jobs are stored in `scheduled_sends.json` in the profile's config
+//! directory so they survive restarts. The backend checks them every
+//! [`SCHEDULE_CHECK_INTERVAL`]; a due job starts once its peer answers a
+//! connection probe and stays queued while the peer is offline.
+
+use crate::config::{create_secure_dir_all, write_secure_file};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// File name of the job list inside the config directory
+pub const SCHEDULE_FILE: &str = "scheduled_sends.json";
+
+/// How often due jobs are looked for
+pub const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// One send waiting for its start time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledSend {
+    pub id: String,
+    /// Peer IP, or `ip:port` for a non-default transfer port
+    pub target: String,
+    pub target_peer_name: String,
+    pub files: Vec<PathBuf>,
+    /// Earliest start, Unix timestamp (seconds)
+    pub at: u64,
+}
+
+/// Pending jobs, ordered by start time
+#[derive(Debug, Default)]
+pub struct ScheduleStore {
+    /// `None` keeps the jobs in memory only
+    path: Option<PathBuf>,
+    jobs: Vec<ScheduledSend>,
+}
+
+impl ScheduleStore {
+    /// Load the jobs saved at `path`; a missing or invalid file is empty
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut jobs: Vec<ScheduledSend> = path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        jobs.sort_by_key(|job| job.at);
+        Self { path, jobs }
+    }
+
+    pub fn jobs(&self) -> &[ScheduledSend] {
+        &self.jobs
+    }
+
+    pub fn add(&mut self, job: ScheduledSend) {
+        let index = self.jobs.partition_point(|other| other.at <= job.at);
+        self.jobs.insert(index, job);
+        self.save();
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<ScheduledSend> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        let job = self.jobs.remove(index);
+        self.save();
+        Some(job)
+    }
+
+    /// Jobs whose start time has passed
+    pub fn due(&self, now: u64) -> impl Iterator<Item = &ScheduledSend> {
+        self.jobs.iter().take_while(move |job| job.at <= now)
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = create_secure_dir_all(parent);
+        }
+        match serde_json::to_string_pretty(&self.jobs) {
+            Ok(json) => {
+                if let Err(e) = write_secure_file(path, &json) {
+                    tracing::warn!("Failed to save scheduled sends: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to serialize scheduled sends: {}", e),
+        }
+    }
+}
+
+/// Whether the transfer server at `addr` accepts a QUIC connection within
+/// [`LAN_PROBE_TIMEOUT`](crate::path::LAN_PROBE_TIMEOUT)
+pub async fn probe_reachable(endpoint: &quinn::Endpoint, addr: SocketAddr) -> bool {
+    crate::path::probe_lan(endpoint, addr).await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: &str, at: u64) -> ScheduledSend {
+        ScheduledSend {
+            id: id.to_string(),
+            target: "192.168.1.20".to_string(),
+            target_peer_name: "Office-PC".to_string(),
+            files: vec![PathBuf::from("report.pdf")],
+            at,
+        }
+    }
+
+    #[test]
+    fn test_jobs_persist_in_start_order() {
+        let path = std::env::temp_dir().join(format!("schedule_{}.json", uuid::Uuid::new_v4()));
+
+        let mut store = ScheduleStore::load(Some(path.clone()));
+        store.add(job("late", 300));
+        store.add(job("early", 100));
+        store.add(job("middle", 200));
+
+        let due: Vec<_> = store.due(200).map(|job| job.id.as_str()).collect();
+        assert_eq!(due, vec!["early", "middle"]);
+
+        assert!(store.remove("early").is_some());
+        assert!(store.remove("early").is_none());
+
+        let reloaded = ScheduleStore::load(Some(path.clone()));
+        let ids: Vec<_> = reloaded.jobs().iter().map(|job| job.id.as_str()).collect();
+        assert_eq!(ids, vec!["middle", "late"]);
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,576 @@
+//! Fake backend for working on the GUI without a second machine or network.
+//!
+//! [`run_simulated_backend`] takes the place of [`run_backend`](crate::run_backend)
+//! and speaks the same [`AppCommand`]/[`AppEvent`] protocol: a few made-up
+//! peers come and go, sends ask for a verification code (logged, since no
+//! real peer shows it) and then report progress and a hash check, and
+//! incoming transfers and browser uploads arrive every so often. Nothing is
+//! written or sent. Enabled with the `simulation` feature.
+
+use crate::history::transfers::Direction;
+use crate::metrics::{self, ActiveTransfer, MetricsSampler};
+use crate::units::format_speed;
+use crate::{AppCommand, AppEvent, EventCategory, LogLevel, PeerCapabilities, new_session_id};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// (name, ip) of the made-up peers
+const PEERS: [(&str, &str); 3] = [
+    ("Laptop (simulated)", "10.0.0.21"),
+    ("Desktop (simulated)", "10.0.0.22"),
+    ("Phone (simulated)", "10.0.0.23"),
+];
+
+/// Progress updates per transfer
+const TRANSFER_STEPS: u32 = 40;
+/// Pace of the simulation; transfers report progress once per tick
+const DEFAULT_TICK: Duration = Duration::from_millis(100);
+/// How often something arrives unasked
+const INCOMING_EVERY_TICKS: u64 = 300;
+
+/// Run the fake backend until `cmd_rx` closes
+pub async fn run_simulated_backend(
+    cmd_rx: mpsc::Receiver<AppCommand>,
+    event_tx: mpsc::Sender<AppEvent>,
+) {
+    Simulator::new(event_tx, DEFAULT_TICK).run(cmd_rx).await;
+}
+
+struct PendingSend {
+    peer: String,
+    code: String,
+    files: Vec<PathBuf>,
+}
+
+struct Simulator {
+    event_tx: mpsc::Sender<AppEvent>,
+    tick: Duration,
+    /// Bumped by `CancelTransfer`; running transfers stop when it changes
+    cancel_generation: Arc<AtomicU64>,
+    /// Sends waiting for their verification code, by session
+    pending_sends: HashMap<String, PendingSend>,
+    /// Uploads waiting for approval: request id → (file name, size)
+    pending_uploads: HashMap<String, (String, u64)>,
+    http_running: bool,
+    metrics: MetricsSampler,
+}
+
+impl Simulator {
+    fn new(event_tx: mpsc::Sender<AppEvent>, tick: Duration) -> Self {
+        Self {
+            event_tx,
+            tick,
+            cancel_generation: Arc::new(AtomicU64::new(0)),
+            pending_sends: HashMap::new(),
+            pending_uploads: HashMap::new(),
+            http_running: false,
+            metrics: MetricsSampler::new(true),
+        }
+    }
+
+    async fn emit(&self, event: AppEvent) {
+        let _ = self.event_tx.send(event).await;
+    }
+
+    async fn log(&self, level: LogLevel, category: EventCategory, message: String) {
+        self.emit(AppEvent::log(level, category, message)).await;
+    }
+
+    async fn run(mut self, mut cmd_rx: mpsc::Receiver<AppCommand>) {
+        self.emit(AppEvent::BackendReady {
+            endpoint_id: "simulated-endpoint".to_string(),
+            device_name: "This device (simulated)".to_string(),
+            transfer_port: 0,
+            receive_only: false,
+        })
+        .await;
+        self.log(
+            LogLevel::Warning,
+            EventCategory::Status,
+            "Simulation mode: peers and transfers are made up".to_string(),
+        )
+        .await;
+
+        let mut ticks = tokio::time::interval(self.tick);
+        let mut tick_count: u64 = 0;
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(cmd) => self.handle(cmd).await,
+                    None => break,
+                },
+                _ = ticks.tick() => {
+                    self.on_tick(tick_count).await;
+                    tick_count += 1;
+                }
+            }
+        }
+    }
+
+    async fn on_tick(&mut self, tick_count: u64) {
+        let ticks_per_sec = (1000 / self.tick.as_millis().max(1)) as u64;
+        let announce_every = (ticks_per_sec * crate::discovery::DISCOVERY_INTERVAL_SECS).max(1);
+        let heartbeat_every = (ticks_per_sec * crate::health::HEALTH_INTERVAL.as_secs()).max(1);
+        let metrics_every = (ticks_per_sec * crate::metrics::METRICS_INTERVAL.as_secs()).max(1);
+        if tick_count.is_multiple_of(announce_every) {
+            self.announce_peers(tick_count / announce_every).await;
+        }
+        if tick_count.is_multiple_of(heartbeat_every) {
+            self.emit(AppEvent::BackendHealth {
+                discovery_ok: true,
+                quic_listening: true,
+                http_running: self.http_running,
+                wan_online: true,
+                relay_latency_ms: Some(rand::random_range(20..80)),
+            })
+            .await;
+        }
+        if tick_count.is_multiple_of(metrics_every) {
+            let sample = self.metrics.sample();
+            self.emit(sample).await;
+        }
+        if tick_count > 0 && tick_count.is_multiple_of(INCOMING_EVERY_TICKS) {
+            let (peer, ip) = PEERS[(tick_count / INCOMING_EVERY_TICKS) as usize % PEERS.len()];
+            if self.http_running && tick_count.is_multiple_of(2 * INCOMING_EVERY_TICKS) {
+                self.request_upload(ip).await;
+            } else {
+                self.receive(peer, ip).await;
+            }
+        }
+    }
+
+    /// All peers but the last, which is away every other round
+    async fn announce_peers(&self, round: u64) {
+        for (i, (name, ip)) in PEERS.iter().enumerate() {
+            let endpoint_id = format!("simulated-peer-{}", i);
+            if i == PEERS.len() - 1 && round % 2 == 1 {
+                self.emit(AppEvent::PeerLost {
+                    endpoint_id,
+                    ip: ip.to_string(),
+                })
+                .await;
+                continue;
+            }
+            self.emit(AppEvent::PeerFound {
+                endpoint_id,
+                ip: ip.to_string(),
+                port: 4433,
+                hostname: name.to_string(),
+                display_name: name.to_string(),
+                capabilities: PeerCapabilities {
+                    receive_only: i == 2,
+                    protocol: crate::alpn::PROTOCOL_VERSION,
+                },
+                // The first peer runs an older version
+                version: (i > 0).then(|| crate::APP_VERSION.to_string()),
+                rtt_ms: Some(2 + 7 * i as u32),
+            })
+            .await;
+        }
+    }
+
+    async fn handle(&mut self, cmd: AppCommand) {
+        match cmd {
+            AppCommand::SendFile {
+                session_id,
+                target_ip,
+                target_peer_name,
+                files,
+                ..
+            }
+            // Simulated moves never touch the real files
+            | AppCommand::MoveFiles {
+                session_id,
+                target_ip,
+                target_peer_name,
+                files,
+                ..
+            }
+            | AppCommand::SendViaRelay {
+                session_id,
+                target_ip,
+                target_peer_name,
+                files,
+                ..
+            } => {
+                let code = format!("{:06}", rand::random_range(0..1_000_000));
+                self.log(
+                    LogLevel::Info,
+                    EventCategory::Pairing,
+                    format!("{} shows code {}", target_peer_name, code),
+                )
+                .await;
+                self.pending_sends.insert(
+                    session_id.clone(),
+                    PendingSend {
+                        peer: target_peer_name.clone(),
+                        code,
+                        files,
+                    },
+                );
+                self.emit(AppEvent::RequestVerificationCode {
+                    session_id,
+                    target_ip,
+                    target_name: target_peer_name,
+                })
+                .await;
+            }
+            AppCommand::SubmitVerificationCode { session_id, code } => {
+                let Some(send) = self.pending_sends.remove(&session_id) else {
+                    return;
+                };
+                let success = code.trim() == send.code;
+                self.emit(AppEvent::PairingResult {
+                    session_id: session_id.clone(),
+                    success,
+                    peer_name: send.peer.clone(),
+                    message: if success {
+                        "Paired".to_string()
+                    } else {
+                        "Wrong code".to_string()
+                    },
+                })
+                .await;
+                if !success {
+                    self.emit(AppEvent::VerificationCancelled {
+                        session_id,
+                        reason: "Wrong code".to_string(),
+                    })
+                    .await;
+                    return;
+                }
+                for file in send.files {
+                    let file_name = file
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| file.display().to_string());
+                    let size = std::fs::metadata(&file).map(|m| m.len()).unwrap_or(0);
+                    self.spawn_transfer(file_name, size.max(1 << 20), true, send.peer.clone());
+                }
+            }
+            AppCommand::CancelVerification { session_id } => {
+                if self.pending_sends.remove(&session_id).is_some() {
+                    self.emit(AppEvent::VerificationCancelled {
+                        session_id,
+                        reason: "Cancelled".to_string(),
+                    })
+                    .await;
+                }
+            }
+            AppCommand::CancelTransfer => {
+                self.cancel_generation.fetch_add(1, Ordering::SeqCst);
+            }
+            AppCommand::SendText {
+                target_peer_name, ..
+            } => {
+                self.log(
+                    LogLevel::Success,
+                    EventCategory::Transfer,
+                    format!("Text sent to {}", target_peer_name),
+                )
+                .await;
+            }
+            AppCommand::StartHttpServer => {
+                self.http_running = true;
+                let url = "http://10.0.0.10:8080".to_string();
+                self.emit(AppEvent::HttpServerStarted {
+                    url: url.clone(),
+                    owner_url: None,
+                })
+                .await;
+                self.emit(AppEvent::ShareUrlReady { url }).await;
+            }
+            AppCommand::StopHttpServer => {
+                self.http_running = false;
+                self.emit(AppEvent::HttpServerStopped).await;
+            }
+            AppCommand::RespondUploadRequest {
+                request_id,
+                accepted,
+            } => {
+                let Some((file_name, size)) = self.pending_uploads.remove(&request_id) else {
+                    return;
+                };
+                if accepted {
+                    self.spawn_upload(request_id, file_name, size);
+                } else {
+                    self.emit(AppEvent::UploadRequestCancelled { request_id })
+                        .await;
+                }
+            }
+            AppCommand::Tracked {
+                request_id,
+                command,
+            } => {
+                Box::pin(self.handle(*command)).await;
+                self.emit(AppEvent::CommandResult {
+                    request_id,
+                    result: Ok(()),
+                })
+                .await;
+            }
+            other => {
+                self.log(
+                    LogLevel::Debug,
+                    EventCategory::Status,
+                    format!("Simulation ignores {:?}", other),
+                )
+                .await;
+            }
+        }
+    }
+
+    /// An unasked transfer from `peer`, preceded by its verification code
+    async fn receive(&self, peer: &str, ip: &str) {
+        self.emit(AppEvent::ShowVerificationCode {
+            session_id: new_session_id(),
+            code: format!("{:06}", rand::random_range(0..1_000_000)),
+            from_ip: ip.to_string(),
+            from_name: peer.to_string(),
+            note: Some("holiday photos".to_string()),
+        })
+        .await;
+        let size = rand::random_range(1..200) << 20;
+        self.spawn_transfer(
+            format!("holiday_{}.jpg", rand::random_range(100..999)),
+            size,
+            false,
+            peer.to_string(),
+        );
+    }
+
+    async fn request_upload(&mut self, ip: &str) {
+        let request_id = new_session_id();
+        let file_name = format!("scan_{}.pdf", rand::random_range(100..999));
+        let size = rand::random_range(1..50) << 20;
+        self.pending_uploads
+            .insert(request_id.clone(), (file_name.clone(), size));
+        self.emit(AppEvent::UploadRequest {
+            request_id,
+            file_name,
+            file_size: size,
+            from_ip: ip.to_string(),
+            warning: None,
+        })
+        .await;
+    }
+
+    fn spawn_transfer(&self, file_name: String, size: u64, is_sending: bool, peer: String) {
+        let event_tx = self.event_tx.clone();
+        let tick = self.tick;
+        let cancel = self.cancel_generation.clone();
+        let generation = cancel.load(Ordering::SeqCst);
+        tokio::spawn(async move {
+            let _active = ActiveTransfer::start();
+            let direction = if is_sending {
+                Direction::Sent
+            } else {
+                Direction::Received
+            };
+            let step_secs = tick.as_secs_f64();
+            for step in 1..=TRANSFER_STEPS {
+                tokio::time::sleep(tick).await;
+                if cancel.load(Ordering::SeqCst) != generation {
+                    let _ = event_tx
+                        .send(AppEvent::TransferCancelled {
+                            file_name,
+                            is_sending,
+                            by_peer: false,
+                            reason: "Cancelled".to_string(),
+                        })
+                        .await;
+                    return;
+                }
+                // Jitter, so the speed readout moves
+                let speed_bps =
+                    size as f64 / TRANSFER_STEPS as f64 / step_secs * rand::random_range(0.7..1.3);
+                metrics::add(direction, (speed_bps * step_secs) as u64);
+                let _ = event_tx
+                    .send(AppEvent::TransferProgress {
+                        file_name: file_name.clone(),
+                        progress: step as f32 * 100.0 / TRANSFER_STEPS as f32,
+                        speed: format_speed(speed_bps),
+                        speed_bps,
+                        is_sending,
+                        bytes: None,
+                    })
+                    .await;
+            }
+            let _ = event_tx
+                .send(AppEvent::VerificationStarted {
+                    file_name: file_name.clone(),
+                    is_sending,
+                })
+                .await;
+            for step in 1..=4 {
+                tokio::time::sleep(tick).await;
+                let _ = event_tx
+                    .send(AppEvent::VerificationProgress {
+                        file_name: file_name.clone(),
+                        is_sending,
+                        progress: step as f32 * 25.0,
+                    })
+                    .await;
+            }
+            let _ = event_tx
+                .send(AppEvent::VerificationCompleted {
+                    file_name: file_name.clone(),
+                    is_sending,
+                    verified: true,
+                })
+                .await;
+            let saved_path =
+                (!is_sending).then(|| crate::config::get_download_dir().join(&file_name));
+            let _ = event_tx
+                .send(AppEvent::TransferCompleted {
+                    file_name,
+                    saved_path,
+                    peer: Some(peer),
+                })
+                .await;
+        });
+    }
+
+    fn spawn_upload(&self, request_id: String, file_name: String, size: u64) {
+        let event_tx = self.event_tx.clone();
+        let tick = self.tick;
+        tokio::spawn(async move {
+            for step in 1..=TRANSFER_STEPS as u64 {
+                tokio::time::sleep(tick).await;
+                let _ = event_tx
+                    .send(AppEvent::UploadProgress {
+                        request_id: request_id.clone(),
+                        received_bytes: size * step / TRANSFER_STEPS as u64,
+                        total_bytes: size,
+                    })
+                    .await;
+            }
+            let saved_path = crate::config::get_download_dir().join(&file_name);
+            let _ = event_tx
+                .send(AppEvent::UploadCompleted {
+                    file_name,
+                    saved_path: saved_path.display().to_string(),
+                })
+                .await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn next_matching(
+        rx: &mut mpsc::Receiver<AppEvent>,
+        mut pred: impl FnMut(&AppEvent) -> bool,
+    ) -> AppEvent {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let event = rx.recv().await.expect("simulator stopped");
+                if pred(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("event did not arrive")
+    }
+
+    fn spawn() -> (mpsc::Sender<AppCommand>, mpsc::Receiver<AppEvent>) {
+        let (cmd_tx, cmd_rx) = mpsc::channel(16);
+        let (event_tx, event_rx) = mpsc::channel(1000);
+        tokio::spawn(Simulator::new(event_tx, Duration::from_millis(1)).run(cmd_rx));
+        (cmd_tx, event_rx)
+    }
+
+    #[tokio::test]
+    async fn test_simulated_send_with_verification() {
+        let (cmd_tx, mut events) = spawn();
+        next_matching(&mut events, |e| matches!(e, AppEvent::PeerFound { .. })).await;
+
+        cmd_tx
+            .send(AppCommand::SendFile {
+                session_id: "s1".to_string(),
+                target_ip: PEERS[0].1.to_string(),
+                target_endpoint_id: "simulated-peer-0".to_string(),
+                target_peer_name: PEERS[0].0.to_string(),
+                files: vec![PathBuf::from("/nonexistent/report.pdf")],
+                note: None,
+            })
+            .await
+            .unwrap();
+        let AppEvent::Log { message, .. } = next_matching(
+            &mut events,
+            |e| matches!(e, AppEvent::Log { message, .. } if message.contains("shows code")),
+        )
+        .await
+        else {
+            unreachable!()
+        };
+        let code = message.rsplit(' ').next().unwrap().to_string();
+        next_matching(&mut events, |e| {
+            matches!(e, AppEvent::RequestVerificationCode { .. })
+        })
+        .await;
+
+        cmd_tx
+            .send(AppCommand::SubmitVerificationCode {
+                session_id: "s1".to_string(),
+                code,
+            })
+            .await
+            .unwrap();
+        let result =
+            next_matching(&mut events, |e| matches!(e, AppEvent::PairingResult { .. })).await;
+        assert!(matches!(
+            result,
+            AppEvent::PairingResult { success: true, .. }
+        ));
+        let done = next_matching(&mut events, |e| {
+            matches!(e, AppEvent::TransferCompleted { file_name, .. } if file_name == "report.pdf")
+        })
+        .await;
+        assert!(matches!(
+            done,
+            AppEvent::TransferCompleted {
+                saved_path: None,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_code_cancels() {
+        let (cmd_tx, mut events) = spawn();
+        cmd_tx
+            .send(AppCommand::SendFile {
+                session_id: "s2".to_string(),
+                target_ip: PEERS[1].1.to_string(),
+                target_endpoint_id: "simulated-peer-1".to_string(),
+                target_peer_name: PEERS[1].0.to_string(),
+                files: vec![PathBuf::from("a.txt")],
+                note: None,
+            })
+            .await
+            .unwrap();
+        next_matching(&mut events, |e| {
+            matches!(e, AppEvent::RequestVerificationCode { .. })
+        })
+        .await;
+        cmd_tx
+            .send(AppCommand::SubmitVerificationCode {
+                session_id: "s2".to_string(),
+                code: "not a code".to_string(),
+            })
+            .await
+            .unwrap();
+        next_matching(&mut events, |e| {
+            matches!(e, AppEvent::VerificationCancelled { session_id, .. } if session_id == "s2")
+        })
+        .await;
+    }
+}
@@ -0,0 +1,263 @@
+//! Snapshot of a running backend for frontends that attach late.
+//!
+//! The backend loop knows its own identity and stores, but peers, transfers
+//! and share URLs only show up as events. [`StateTracker`] watches the event
+//! stream on its way out of the backend and fills those parts into the
+//! [`AppEvent::StateSnapshot`] answering [`AppCommand::GetState`]. Because
+//! the snapshot travels the same stream, it already includes every event
+//! sent before it.
+//!
+//! [`AppCommand::GetState`]: crate::AppCommand::GetState
+
+use crate::network_info::MeteredMode;
+use crate::transfer::HashAlgorithm;
+use crate::units::UnitPreference;
+use crate::{AppEvent, PeerCapabilities};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// A LAN peer currently announcing itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerSnapshot {
+    pub endpoint_id: String,
+    pub ip: String,
+    pub port: u16,
+    pub hostname: String,
+    /// See [`AppEvent::PeerFound`]
+    pub display_name: String,
+    pub capabilities: PeerCapabilities,
+    pub version: Option<String>,
+    pub rtt_ms: Option<u32>,
+}
+
+/// A file being sent or received
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransferSnapshot {
+    pub file_name: String,
+    pub is_sending: bool,
+    /// Percent done
+    pub progress: f32,
+    pub speed_bps: f64,
+    /// Bytes through, for a stream of unknown size
+    pub bytes: Option<u64>,
+}
+
+/// Everything a frontend needs to draw its first frame
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BackendState {
+    pub endpoint_id: String,
+    pub device_name: String,
+    pub transfer_port: u16,
+    pub receive_only: bool,
+    pub units: UnitPreference,
+    /// Checksum for files sent from here
+    pub hash_algorithm: HashAlgorithm,
+    pub metered_mode: MeteredMode,
+    /// The connection counts as metered right now
+    pub metered: bool,
+    pub download_dir: std::path::PathBuf,
+    pub peers: Vec<PeerSnapshot>,
+    pub transfers: Vec<TransferSnapshot>,
+    /// URL of the running HTTP share server
+    pub http_share_url: Option<String>,
+    /// Public URL of the running WAN share tunnel
+    pub wan_share_url: Option<String>,
+    /// Devices that may send here without a code
+    pub paired_devices: usize,
+    pub scheduled_sends: usize,
+}
+
+/// Event-derived part of [`BackendState`]
+#[derive(Debug, Default)]
+pub struct StateTracker {
+    /// Keyed by IP, like the GUI's peer list
+    peers: HashMap<String, PeerSnapshot>,
+    transfers: HashMap<String, TransferSnapshot>,
+    http_share_url: Option<String>,
+    wan_share_url: Option<String>,
+}
+
+impl StateTracker {
+    /// Update from an event leaving the backend. A snapshot passing through
+    /// gets the tracked peers, transfers and share URLs filled in.
+    pub fn observe(&mut self, event: &mut AppEvent) {
+        match event {
+            AppEvent::PeerFound {
+                endpoint_id,
+                ip,
+                port,
+                hostname,
+                display_name,
+                capabilities,
+                version,
+                rtt_ms,
+            } => {
+                self.peers.insert(
+                    ip.clone(),
+                    PeerSnapshot {
+                        endpoint_id: endpoint_id.clone(),
+                        ip: ip.clone(),
+                        port: *port,
+                        hostname: hostname.clone(),
+                        display_name: display_name.clone(),
+                        capabilities: *capabilities,
+                        version: version.clone(),
+                        rtt_ms: *rtt_ms,
+                    },
+                );
+            }
+            AppEvent::PeerLost { ip, .. } => {
+                self.peers.remove(ip);
+            }
+            AppEvent::PeerListDelta(delta) => {
+                for mut event in delta.events() {
+                    self.observe(&mut event);
+                }
+            }
+            AppEvent::TransferProgress {
+                file_name,
+                progress,
+                speed_bps,
+                is_sending,
+                bytes,
+                ..
+            } => {
+                self.transfers.insert(
+                    file_name.clone(),
+                    TransferSnapshot {
+                        file_name: file_name.clone(),
+                        is_sending: *is_sending,
+                        progress: *progress,
+                        speed_bps: *speed_bps,
+                        bytes: *bytes,
+                    },
+                );
+            }
+            AppEvent::TransferCompleted { file_name, .. }
+            | AppEvent::TransferCancelled { file_name, .. } => {
+                self.transfers.remove(file_name);
+            }
+            AppEvent::HttpServerStarted { url, .. } => self.http_share_url = Some(url.clone()),
+            AppEvent::HttpServerStopped => self.http_share_url = None,
+            AppEvent::WanShareReady { url } => self.wan_share_url = Some(url.clone()),
+            AppEvent::WanShareStopped | AppEvent::WanShareError(_) => self.wan_share_url = None,
+            // The restarted backend rediscovers everything
+            AppEvent::ProfileSwitched { .. } => *self = Self::default(),
+            AppEvent::StateSnapshot(state) => self.fill(state),
+            _ => {}
+        }
+    }
+
+    /// Fill the tracked peers, transfers and share URLs into `state`
+    pub fn fill(&self, state: &mut BackendState) {
+        let mut peers: Vec<_> = self.peers.values().cloned().collect();
+        peers.sort_by(|a, b| a.ip.cmp(&b.ip));
+        let mut transfers: Vec<_> = self.transfers.values().cloned().collect();
+        transfers.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        state.peers = peers;
+        state.transfers = transfers;
+        state.http_share_url = self.http_share_url.clone();
+        state.wan_share_url = self.wan_share_url.clone();
+    }
+}
+
+/// Forward `events` to `event_tx`, tracking state in `tracker` on the way.
+/// Ends when either side closes.
+pub async fn track_events(
+    mut events: mpsc::Receiver<AppEvent>,
+    event_tx: mpsc::Sender<AppEvent>,
+    tracker: Arc<Mutex<StateTracker>>,
+) {
+    while let Some(mut event) = events.recv().await {
+        if let Ok(mut tracker) = tracker.lock() {
+            tracker.observe(&mut event);
+        }
+        if event_tx.send(event).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_reflects_earlier_events() {
+        let mut tracker = StateTracker::default();
+        for mut event in [
+            AppEvent::PeerFound {
+                endpoint_id: "a".to_string(),
+                ip: "10.0.0.2".to_string(),
+                port: 9000,
+                hostname: "Laptop".to_string(),
+                display_name: "Laptop".to_string(),
+                capabilities: PeerCapabilities::default(),
+                version: None,
+                rtt_ms: Some(3),
+            },
+            AppEvent::PeerFound {
+                endpoint_id: "b".to_string(),
+                ip: "10.0.0.3".to_string(),
+                port: 9000,
+                hostname: "Desktop".to_string(),
+                display_name: "Desktop".to_string(),
+                capabilities: PeerCapabilities::default(),
+                version: None,
+                rtt_ms: None,
+            },
+            AppEvent::PeerLost {
+                endpoint_id: "b".to_string(),
+                ip: "10.0.0.3".to_string(),
+            },
+            AppEvent::TransferProgress {
+                file_name: "a.bin".to_string(),
+                progress: 40.0,
+                speed: String::new(),
+                speed_bps: 1.0,
+                is_sending: true,
+                bytes: None,
+            },
+            AppEvent::TransferProgress {
+                file_name: "b.bin".to_string(),
+                progress: 10.0,
+                speed: String::new(),
+                speed_bps: 1.0,
+                is_sending: false,
+                bytes: None,
+            },
+            AppEvent::TransferCompleted {
+                file_name: "b.bin".to_string(),
+                saved_path: None,
+                peer: None,
+            },
+            AppEvent::HttpServerStarted {
+                url: "http://10.0.0.1:8080".to_string(),
+                owner_url: None,
+            },
+        ] {
+            tracker.observe(&mut event);
+        }
+
+        let mut event = AppEvent::StateSnapshot(Box::new(BackendState {
+            paired_devices: 2,
+            ..Default::default()
+        }));
+        tracker.observe(&mut event);
+        let AppEvent::StateSnapshot(state) = event else {
+            unreachable!();
+        };
+        assert_eq!(state.paired_devices, 2);
+        assert_eq!(state.peers.len(), 1);
+        assert_eq!(state.peers[0].hostname, "Laptop");
+        assert_eq!(state.transfers.len(), 1);
+        assert_eq!(state.transfers[0].file_name, "a.bin");
+        assert_eq!(
+            state.http_share_url.as_deref(),
+            Some("http://10.0.0.1:8080")
+        );
+        assert_eq!(state.wan_share_url, None);
+    }
+}
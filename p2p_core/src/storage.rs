@@ -0,0 +1,592 @@
+//! File system access of the receivers.
+//!
+//! The LAN, WAN and HTTP receivers write through a [`Storage`] instead of
+//! calling `tokio::fs` themselves, so tests can make the disk fill up or
+//! refuse a file half way through with [`FailingStorage`]. The backend uses
+//! [`LocalStorage`] unless the profile's [`StorageSettings`] name a remote
+//! one ([`s3`], [`webdav`]) or
+//! [`P2pNodeBuilder::storage`](crate::P2pNodeBuilder::storage) says
+//! otherwise, such as a [`pipe`] for `receive --stdout`.
+//!
+//! A remote storage maps each path below the download folder to an object
+//! or file of the same relative name. Received files are checked by reading
+//! them back through the storage, so a file is only reported verified when
+//! what the storage holds matches the sender's hash. Remote files cannot be
+//! appended to, so their transfers always start from the beginning.
+
+pub mod pipe;
+pub mod remote;
+pub mod s3;
+pub mod webdav;
+
+use crate::transfer::utils::{append_secure_file, create_secure_file};
+use crate::units::format_size;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::io::{self, ErrorKind, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt};
+
+pub use remote::StorageSettings;
+
+/// A file read back from a [`Storage`]
+pub type StorageReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// What [`Storage::stat`] reports about a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    pub len: u64,
+    pub is_file: bool,
+}
+
+/// Where received files are written
+#[async_trait]
+pub trait Storage: Send + Sync + Debug {
+    /// Create `path` for writing, readable by this user only, replacing
+    /// any file already there
+    async fn create(&self, path: &Path) -> io::Result<Box<dyn StorageFile>>;
+
+    /// Open `path`, created by [`create`](Self::create), to write at its end
+    async fn append(&self, path: &Path) -> io::Result<Box<dyn StorageFile>>;
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    async fn remove(&self, path: &Path) -> io::Result<()>;
+
+    async fn stat(&self, path: &Path) -> io::Result<FileStat>;
+
+    /// Read `path` from the start, to check what was written
+    async fn open(&self, path: &Path) -> io::Result<StorageReader>;
+
+    /// Bytes that can still be written below `dir`
+    async fn available_space(&self, dir: &Path) -> io::Result<u64>;
+
+    /// Whether paths are files on this device, which can be hashed, resumed
+    /// and given their metadata in place
+    fn is_local(&self) -> bool;
+}
+
+/// A file opened by a [`Storage`]
+#[async_trait]
+pub trait StorageFile: Send {
+    async fn write_all(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Grow or shrink the file to `len` bytes and continue writing there
+    async fn set_len(&mut self, len: u64) -> io::Result<()>;
+
+    /// Reserve disk space for `len` bytes without changing the file's
+    /// length, so a partial file still tells how much arrived
+    async fn preallocate(&mut self, len: u64) -> io::Result<()>;
+
+    async fn flush(&mut self) -> io::Result<()>;
+
+    /// Whether growing the file with [`set_len`](Self::set_len) leaves an
+    /// unallocated hole rather than written zeros
+    fn supports_holes(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl StorageFile for tokio::fs::File {
+    async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        AsyncWriteExt::write_all(self, data).await
+    }
+
+    async fn set_len(&mut self, len: u64) -> io::Result<()> {
+        tokio::fs::File::set_len(self, len).await?;
+        // Append-mode files already write at the end; others must seek
+        self.seek(SeekFrom::Start(len)).await?;
+        Ok(())
+    }
+
+    async fn preallocate(&mut self, len: u64) -> io::Result<()> {
+        let file = self.try_clone().await?.into_std().await;
+        tokio::task::spawn_blocking(move || platform::preallocate(&file, len)).await?
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        AsyncWriteExt::flush(self).await
+    }
+
+    /// Unix file systems keep holes; NTFS only does for files marked sparse
+    fn supports_holes(&self) -> bool {
+        cfg!(unix)
+    }
+}
+
+/// The local file system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalStorage;
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn create(&self, path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        Ok(Box::new(create_secure_file(path).await?))
+    }
+
+    async fn append(&self, path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        Ok(Box::new(append_secure_file(path).await?))
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        tokio::fs::rename(from, to).await
+    }
+
+    async fn remove(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::remove_file(path).await
+    }
+
+    async fn stat(&self, path: &Path) -> io::Result<FileStat> {
+        let metadata = tokio::fs::metadata(path).await?;
+        Ok(FileStat {
+            len: metadata.len(),
+            is_file: metadata.is_file(),
+        })
+    }
+
+    async fn open(&self, path: &Path) -> io::Result<StorageReader> {
+        Ok(Box::new(tokio::fs::File::open(path).await?))
+    }
+
+    async fn available_space(&self, dir: &Path) -> io::Result<u64> {
+        let dir = tokio::fs::canonicalize(dir).await?;
+        tokio::task::spawn_blocking(move || {
+            // The disk mounted closest to `dir` holds it
+            let disks = sysinfo::Disks::new_with_refreshed_list();
+            disks
+                .list()
+                .iter()
+                .filter(|disk| dir.starts_with(disk.mount_point()))
+                .max_by_key(|disk| disk.mount_point().as_os_str().len())
+                .map(|disk| disk.available_space())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::Unsupported,
+                        format!("No disk found for {}", dir.display()),
+                    )
+                })
+        })
+        .await?
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+/// Open `path` to write from `offset`: a new file at 0, else its end
+pub async fn open_at(
+    storage: &dyn Storage,
+    path: &Path,
+    offset: u64,
+) -> io::Result<Box<dyn StorageFile>> {
+    if offset > 0 {
+        storage.append(path).await
+    } else {
+        storage.create(path).await
+    }
+}
+
+/// Reserve the disk space of a file received to `len` bytes. Running out of
+/// space fails now rather than half way through; a file system that cannot
+/// preallocate gets the file written as it arrives.
+pub async fn preallocate(file: &mut dyn StorageFile, len: u64) -> io::Result<()> {
+    match file.preallocate(len).await {
+        Err(e) if e.kind() == ErrorKind::StorageFull => Err(e),
+        Err(e) => {
+            tracing::debug!("Could not preallocate {}: {}", format_size(len), e);
+            Ok(())
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Refuse to receive `needed` bytes into `dir` when it has less free space.
+/// A file system that cannot tell lets the transfer go ahead.
+pub async fn ensure_space(storage: &dyn Storage, dir: &Path, needed: u64) -> Result<()> {
+    match storage.available_space(dir).await {
+        Ok(available) if available < needed => Err(anyhow!(
+            "Not enough free space: {} needed, {} available",
+            format_size(needed),
+            format_size(available)
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::debug!("Free space of {} unknown: {}", dir.display(), e);
+            Ok(())
+        }
+    }
+}
+
+/// A [`Storage`] that fails on cue, for tests
+#[derive(Debug)]
+pub struct FailingStorage {
+    inner: Arc<dyn Storage>,
+    fail_open: Option<ErrorKind>,
+    fail_rename: Option<ErrorKind>,
+    fail_preallocate: Option<ErrorKind>,
+    /// Writes over all files fail once this many bytes were written
+    write_limit: Option<(u64, ErrorKind)>,
+    written: Arc<AtomicU64>,
+    available_space: Option<u64>,
+}
+
+impl FailingStorage {
+    /// Pass everything through to `inner` until told otherwise
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        Self {
+            inner,
+            fail_open: None,
+            fail_rename: None,
+            fail_preallocate: None,
+            write_limit: None,
+            written: Arc::default(),
+            available_space: None,
+        }
+    }
+
+    /// Over [`LocalStorage`]
+    pub fn local() -> Self {
+        Self::new(Arc::new(LocalStorage))
+    }
+
+    /// Fail every [`create`](Storage::create) and [`append`](Storage::append)
+    pub fn fail_open(mut self, kind: ErrorKind) -> Self {
+        self.fail_open = Some(kind);
+        self
+    }
+
+    pub fn fail_rename(mut self, kind: ErrorKind) -> Self {
+        self.fail_rename = Some(kind);
+        self
+    }
+
+    /// Fail preallocations with `kind`; files then keep no holes, so a
+    /// receiver always tries to preallocate them
+    pub fn fail_preallocate(mut self, kind: ErrorKind) -> Self {
+        self.fail_preallocate = Some(kind);
+        self
+    }
+
+    /// Fail writes with `kind` once `bytes` were written, the bytes up to
+    /// the limit still landing like on a real disk
+    pub fn fail_writes_after(mut self, bytes: u64, kind: ErrorKind) -> Self {
+        self.write_limit = Some((bytes, kind));
+        self
+    }
+
+    /// A disk that fills up after `bytes`
+    pub fn disk_full_after(self, bytes: u64) -> Self {
+        self.fail_writes_after(bytes, ErrorKind::StorageFull)
+    }
+
+    /// Report `bytes` of free space wherever asked
+    pub fn report_space(mut self, bytes: u64) -> Self {
+        self.available_space = Some(bytes);
+        self
+    }
+
+    /// Bytes written through this storage so far
+    pub fn bytes_written(&self) -> u64 {
+        self.written.load(Ordering::SeqCst)
+    }
+
+    fn check_open(&self) -> io::Result<()> {
+        match self.fail_open {
+            Some(kind) => Err(io::Error::new(kind, "injected open failure")),
+            None => Ok(()),
+        }
+    }
+
+    fn wrap(&self, file: Box<dyn StorageFile>) -> Box<dyn StorageFile> {
+        Box::new(FailingFile {
+            inner: file,
+            limit: self.write_limit,
+            fail_preallocate: self.fail_preallocate,
+            written: self.written.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for FailingStorage {
+    async fn create(&self, path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        self.check_open()?;
+        Ok(self.wrap(self.inner.create(path).await?))
+    }
+
+    async fn append(&self, path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        self.check_open()?;
+        Ok(self.wrap(self.inner.append(path).await?))
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if let Some(kind) = self.fail_rename {
+            return Err(io::Error::new(kind, "injected rename failure"));
+        }
+        self.inner.rename(from, to).await
+    }
+
+    async fn remove(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove(path).await
+    }
+
+    async fn stat(&self, path: &Path) -> io::Result<FileStat> {
+        self.inner.stat(path).await
+    }
+
+    async fn open(&self, path: &Path) -> io::Result<StorageReader> {
+        self.inner.open(path).await
+    }
+
+    async fn available_space(&self, dir: &Path) -> io::Result<u64> {
+        match self.available_space {
+            Some(bytes) => Ok(bytes),
+            None => self.inner.available_space(dir).await,
+        }
+    }
+
+    fn is_local(&self) -> bool {
+        self.inner.is_local()
+    }
+}
+
+struct FailingFile {
+    inner: Box<dyn StorageFile>,
+    limit: Option<(u64, ErrorKind)>,
+    fail_preallocate: Option<ErrorKind>,
+    written: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl StorageFile for FailingFile {
+    async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        let Some((limit, kind)) = self.limit else {
+            self.written.fetch_add(data.len() as u64, Ordering::SeqCst);
+            return self.inner.write_all(data).await;
+        };
+        let room = limit.saturating_sub(self.written.load(Ordering::SeqCst));
+        let fits = data.len().min(room as usize);
+        self.inner.write_all(&data[..fits]).await?;
+        self.written.fetch_add(fits as u64, Ordering::SeqCst);
+        if fits < data.len() {
+            return Err(io::Error::new(kind, "injected write failure"));
+        }
+        Ok(())
+    }
+
+    async fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.inner.set_len(len).await
+    }
+
+    async fn preallocate(&mut self, len: u64) -> io::Result<()> {
+        if let Some(kind) = self.fail_preallocate {
+            return Err(io::Error::new(kind, "injected preallocation failure"));
+        }
+        self.inner.preallocate(len).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    fn supports_holes(&self) -> bool {
+        self.fail_preallocate.is_none() && self.inner.supports_holes()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    pub fn preallocate(file: &File, len: u64) -> io::Result<()> {
+        let len = i64::try_from(len).map_err(io::Error::other)?;
+        // SAFETY: the descriptor belongs to `file`, open for the whole call
+        let result =
+            unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len) };
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    pub fn preallocate(file: &File, len: u64) -> io::Result<()> {
+        // F_PEOFPOSMODE counts from the end of what is allocated already
+        let current = file.metadata()?.len();
+        let Some(more) = len.checked_sub(current).filter(|&more| more > 0) else {
+            return Ok(());
+        };
+        let mut store = libc::fstore_t {
+            fst_flags: libc::F_ALLOCATEALL,
+            fst_posmode: libc::F_PEOFPOSMODE,
+            fst_offset: 0,
+            fst_length: i64::try_from(more).map_err(io::Error::other)?,
+            fst_bytesalloc: 0,
+        };
+        // SAFETY: the descriptor belongs to `file`, and `store` outlives the call
+        let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_PREALLOCATE, &mut store) };
+        match result {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Storage::FileSystem::{
+        FILE_ALLOCATION_INFO, FileAllocationInfo, SetFileInformationByHandle,
+    };
+
+    pub fn preallocate(file: &File, len: u64) -> io::Result<()> {
+        let info = FILE_ALLOCATION_INFO {
+            AllocationSize: i64::try_from(len).map_err(io::Error::other)?,
+        };
+        // SAFETY: the handle belongs to `file`, and `info` outlives the call
+        unsafe {
+            SetFileInformationByHandle(
+                HANDLE(file.as_raw_handle()),
+                FileAllocationInfo,
+                (&info as *const FILE_ALLOCATION_INFO).cast(),
+                size_of::<FILE_ALLOCATION_INFO>() as u32,
+            )
+        }
+        .map_err(io::Error::from)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use std::fs::File;
+    use std::io;
+
+    pub fn preallocate(_file: &File, _len: u64) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("storage_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_round_trip() {
+        let dir = temp_dir();
+        let path = dir.join("a.bin");
+        let storage = LocalStorage;
+
+        let mut file = open_at(&storage, &path, 0).await.unwrap();
+        file.write_all(b"abc").await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        let mut file = open_at(&storage, &path, 3).await.unwrap();
+        file.set_len(5).await.unwrap();
+        file.write_all(b"de").await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        assert_eq!(std::fs::read(&path).unwrap(), b"abc\0\0de");
+
+        let moved = dir.join("b.bin");
+        storage.rename(&path, &moved).await.unwrap();
+        assert_eq!(
+            storage.stat(&moved).await.unwrap(),
+            FileStat {
+                len: 7,
+                is_file: true
+            }
+        );
+        assert_eq!(
+            storage.stat(&path).await.unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_disk_full_mid_write() {
+        let dir = temp_dir();
+        let path = dir.join("full.bin");
+        let storage = FailingStorage::local().disk_full_after(4);
+
+        let mut file = storage.create(&path).await.unwrap();
+        file.write_all(b"abc").await.unwrap();
+        let err = file.write_all(b"def").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StorageFull);
+        file.flush().await.unwrap();
+        drop(file);
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcd");
+        assert_eq!(storage.bytes_written(), 4);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_injected_failures() {
+        let dir = temp_dir();
+        let path = dir.join("denied.bin");
+        let storage = FailingStorage::local()
+            .fail_open(ErrorKind::PermissionDenied)
+            .fail_rename(ErrorKind::PermissionDenied);
+        let err = storage.create(&path).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(!path.exists());
+        assert!(storage.rename(&path, &dir.join("b")).await.is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_preallocate_keeps_length() {
+        let dir = temp_dir();
+        let path = dir.join("reserved.bin");
+        let mut file = LocalStorage.create(&path).await.unwrap();
+        preallocate(file.as_mut(), 1024 * 1024).await.unwrap();
+        file.write_all(b"abc").await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        assert_eq!(std::fs::read(&path).unwrap(), b"abc");
+
+        // Unsupported is no reason to stop, a full disk is
+        let storage = FailingStorage::local().fail_preallocate(ErrorKind::Unsupported);
+        let mut file = storage.create(&path).await.unwrap();
+        assert!(preallocate(file.as_mut(), 1024).await.is_ok());
+        let storage = FailingStorage::local().fail_preallocate(ErrorKind::StorageFull);
+        let mut file = storage.create(&path).await.unwrap();
+        let err = preallocate(file.as_mut(), 1024).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StorageFull);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_space() {
+        let dir = temp_dir();
+        let storage = FailingStorage::local().report_space(1000);
+        assert!(ensure_space(&storage, &dir, 1000).await.is_ok());
+        let err = ensure_space(&storage, &dir, 1001).await.unwrap_err();
+        assert!(err.to_string().contains("Not enough free space"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,135 @@
+//! A pipe, such as standard output, in place of the download folder.
+//!
+//! `p2p_gui receive --stdout` writes the file it receives to its standard
+//! output, typically a stream from `send --stdin` (see
+//! [`crate::transfer::stream`]). What is written cannot be taken back: when
+//! a stream fails its check the bytes are already out, and only the exit
+//! status tells. Renames and removals do nothing, and a second file is
+//! refused.
+
+use super::remote;
+use super::{FileStat, Storage, StorageFile, StorageReader};
+use async_trait::async_trait;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+type PipeWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Writes the first file received to one pipe
+#[derive(Clone)]
+pub struct PipeStorage {
+    writer: Arc<Mutex<Option<PipeWriter>>>,
+}
+
+impl PipeStorage {
+    pub fn new(writer: impl AsyncWrite + Send + Unpin + 'static) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(Some(Box::new(writer)))),
+        }
+    }
+}
+
+impl fmt::Debug for PipeStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PipeStorage")
+    }
+}
+
+#[async_trait]
+impl Storage for PipeStorage {
+    async fn create(&self, _path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        let writer = self.writer.lock().unwrap().take().ok_or_else(|| {
+            io::Error::new(ErrorKind::AlreadyExists, "The pipe already took a file")
+        })?;
+        Ok(Box::new(PipeFile { writer, written: 0 }))
+    }
+
+    async fn append(&self, _path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        Err(remote::unsupported("Resuming"))
+    }
+
+    async fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn remove(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn stat(&self, path: &Path) -> io::Result<FileStat> {
+        Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} went to a pipe", path.display()),
+        ))
+    }
+
+    async fn open(&self, _path: &Path) -> io::Result<StorageReader> {
+        Err(remote::unsupported("Reading back"))
+    }
+
+    async fn available_space(&self, _dir: &Path) -> io::Result<u64> {
+        Ok(u64::MAX)
+    }
+
+    fn is_local(&self) -> bool {
+        false
+    }
+}
+
+struct PipeFile {
+    writer: PipeWriter,
+    written: u64,
+}
+
+#[async_trait]
+impl StorageFile for PipeFile {
+    async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data).await?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    async fn set_len(&mut self, len: u64) -> io::Result<()> {
+        let written = self.written;
+        remote::extend(self, written, len).await
+    }
+
+    async fn preallocate(&mut self, _len: u64) -> io::Result<()> {
+        Err(remote::unsupported("Preallocating"))
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_first_file_goes_to_the_pipe() {
+        let (writer, mut reader) = tokio::io::duplex(1024);
+        let storage = PipeStorage::new(writer);
+        let path = Path::new("/in/a.bin");
+
+        let mut file = storage.create(path).await.unwrap();
+        file.write_all(b"abc").await.unwrap();
+        // Holes are written out as zeros
+        file.set_len(5).await.unwrap();
+        file.flush().await.unwrap();
+        assert!(file.set_len(2).await.is_err());
+        drop(file);
+        storage.rename(path, Path::new("/in/b.bin")).await.unwrap();
+
+        let mut piped = Vec::new();
+        reader.read_to_end(&mut piped).await.unwrap();
+        assert_eq!(piped, b"abc\0\0");
+        assert!(storage.create(path).await.is_err());
+        assert!(storage.open(path).await.is_err());
+    }
+}
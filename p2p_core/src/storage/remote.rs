@@ -0,0 +1,265 @@
+//! What remote storages share: the per-profile settings, how paths become
+//! remote names, and the HTTP plumbing.
+
+use super::s3::{S3Settings, S3Storage};
+use super::webdav::{WebDavSettings, WebDavStorage};
+use super::{Storage, StorageFile, StorageReader};
+use crate::proxy::ProxySettings;
+use anyhow::{Result, anyhow};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::io::StreamReader;
+use url::Url;
+
+/// How long to wait for a remote storage to accept a connection
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Zeros written at once when a file is extended over a hole
+const ZERO_CHUNK: usize = 64 * 1024;
+
+/// Where a profile keeps received files, stored in `config.json`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StorageSettings {
+    /// The download folder on this device
+    #[default]
+    Local,
+    /// An S3 bucket, written with multipart uploads
+    S3(S3Settings),
+    /// A WebDAV share, such as a NAS or Nextcloud folder
+    #[serde(rename = "webdav")]
+    WebDav(WebDavSettings),
+}
+
+impl StorageSettings {
+    pub fn is_local(&self) -> bool {
+        matches!(self, Self::Local)
+    }
+
+    /// The storage for files received below `root`, reached through `proxy`
+    /// unless bypassed; `None` for the local disk
+    pub fn open(&self, root: &Path, proxy: &ProxySettings) -> Result<Option<Arc<dyn Storage>>> {
+        let paths = RemotePaths::new(root);
+        Ok(match self {
+            Self::Local => None,
+            Self::S3(settings) => Some(Arc::new(S3Storage::new(settings, paths, proxy)?)),
+            Self::WebDav(settings) => Some(Arc::new(WebDavStorage::new(settings, paths, proxy)?)),
+        })
+    }
+}
+
+/// Names on a remote storage for paths below the download folder
+#[derive(Debug, Clone)]
+pub struct RemotePaths {
+    root: PathBuf,
+}
+
+impl RemotePaths {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// `path` relative to the download folder, `/`-separated
+    pub fn key(&self, path: &Path) -> io::Result<String> {
+        let relative = path.strip_prefix(&self.root).map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is outside the download folder", path.display()),
+            )
+        })?;
+        let mut segments = Vec::new();
+        for component in relative.components() {
+            match component {
+                Component::Normal(segment) => segments.push(segment.to_str().ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{} is not valid UTF-8", path.display()),
+                    )
+                })?),
+                _ => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{} is not a plain path", path.display()),
+                    ));
+                }
+            }
+        }
+        if segments.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "The download folder itself is not a file",
+            ));
+        }
+        Ok(segments.join("/"))
+    }
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986,
+/// keeping `/` when `keep_slash`
+pub fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// `raw` checked as an http(s) URL
+pub fn parse_url(raw: &str, what: &str) -> Result<Url> {
+    let url = Url::parse(raw.trim()).map_err(|e| anyhow!("Invalid {} URL: {}", what, e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("{} URL must use http or https", what));
+    }
+    if url.host_str().is_none() {
+        return Err(anyhow!("{} URL has no host", what));
+    }
+    Ok(url)
+}
+
+/// Client for `url`, through `proxy` unless bypassed. There is no overall
+/// timeout: uploading a large file takes as long as it takes.
+pub fn client(url: &Url, proxy: &ProxySettings) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().connect_timeout(CONNECT_TIMEOUT);
+    if let Some(proxy_url) = url.host_str().and_then(|host| proxy.url_for(host)) {
+        match reqwest::Proxy::all(proxy_url) {
+            Ok(proxy) => builder = builder.proxy(proxy),
+            Err(e) => tracing::warn!("Storage reached directly: {}", e),
+        }
+    }
+    Ok(builder.build()?)
+}
+
+pub fn request_error(e: reqwest::Error) -> io::Error {
+    let kind = if e.is_timeout() {
+        ErrorKind::TimedOut
+    } else {
+        ErrorKind::Other
+    };
+    io::Error::new(kind, e)
+}
+
+/// Fail unless `response` succeeded, mapping the status to an error kind
+pub async fn check(response: reqwest::Response, what: &str) -> io::Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let kind = match status.as_u16() {
+        404 => ErrorKind::NotFound,
+        401 | 403 => ErrorKind::PermissionDenied,
+        // WebDAV's Insufficient Storage
+        507 => ErrorKind::StorageFull,
+        _ => ErrorKind::Other,
+    };
+    let body = response.text().await.unwrap_or_default();
+    let detail: String = body.trim().chars().take(200).collect();
+    Err(io::Error::new(
+        kind,
+        format!("{} failed with {}: {}", what, status, detail),
+    ))
+}
+
+/// The `Content-Length` a response announced; `HEAD` responses have no body
+/// to measure
+pub fn content_length(response: &reqwest::Response) -> io::Result<u64> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| io::Error::other("Storage did not report the file size"))
+}
+
+/// The body of `response` as it arrives
+pub fn body_reader(response: reqwest::Response) -> StorageReader {
+    Box::new(StreamReader::new(
+        response.bytes_stream().map_err(io::Error::other),
+    ))
+}
+
+/// Extend a file of `written` bytes to `len` with zeros. Remote files are
+/// written in order, so they can grow but never shrink.
+pub async fn extend(file: &mut dyn StorageFile, written: u64, len: u64) -> io::Result<()> {
+    if len < written {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "Remote files cannot be truncated",
+        ));
+    }
+    let zeros = [0u8; ZERO_CHUNK];
+    let mut missing = len - written;
+    while missing > 0 {
+        let n = missing.min(ZERO_CHUNK as u64) as usize;
+        file.write_all(&zeros[..n]).await?;
+        missing -= n as u64;
+    }
+    Ok(())
+}
+
+/// What remote files refuse
+pub fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::Unsupported,
+        format!("{} is not possible on remote storage", what),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_are_relative_to_the_download_folder() {
+        let root = PathBuf::from("/srv/in");
+        let paths = RemotePaths::new(&root);
+        assert_eq!(paths.key(&root.join("a.bin")).unwrap(), "a.bin");
+        assert_eq!(
+            paths.key(&root.join("Alice").join("b c.txt")).unwrap(),
+            "Alice/b c.txt"
+        );
+        assert!(paths.key(&root).is_err());
+        assert!(paths.key(Path::new("/elsewhere/a.bin")).is_err());
+        assert!(paths.key(&root.join("..").join("a.bin")).is_err());
+
+        assert_eq!(
+            uri_encode("Alice/b c~(1).txt", true),
+            "Alice/b%20c~%281%29.txt"
+        );
+        assert_eq!(uri_encode("a/b", false), "a%2Fb");
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let settings: StorageSettings = serde_json::from_str(
+            r#"{"kind": "webdav", "url": "https://nas.local/dav/incoming", "username": "me"}"#,
+        )
+        .unwrap();
+        assert!(
+            matches!(&settings, StorageSettings::WebDav(dav) if dav.username.as_deref() == Some("me"))
+        );
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(
+            serde_json::from_str::<StorageSettings>(&json).unwrap(),
+            settings
+        );
+        assert!(StorageSettings::default().is_local());
+        assert!(
+            StorageSettings::default()
+                .open(Path::new("/srv/in"), &ProxySettings::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+}
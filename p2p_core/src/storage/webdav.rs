@@ -0,0 +1,409 @@
+//! Received files streamed onto a WebDAV share.
+//!
+//! Each file is sent with one `PUT` whose body is fed as the bytes arrive,
+//! so nothing is buffered beyond a few chunks; folders such as per-sender
+//! subfolders are made with `MKCOL` first. A file dropped before it is
+//! complete has its request aborted, which servers discard. Renames are a
+//! `MOVE` on the server.
+
+use super::remote::{self, RemotePaths};
+use super::{FileStat, Storage, StorageFile, StorageReader};
+use crate::proxy::ProxySettings;
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use url::Url;
+use zeroize::Zeroizing;
+
+/// Chunks queued for the request body before writes wait
+const BODY_QUEUE_LEN: usize = 8;
+
+/// A WebDAV share, stored in `config.json`
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebDavSettings {
+    /// Folder received files go to, e.g. `https://nas.local/dav/incoming`
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl fmt::Debug for WebDavSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebDavSettings")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Received files on a WebDAV share
+#[derive(Debug)]
+pub struct WebDavStorage {
+    share: Arc<Share>,
+    paths: RemotePaths,
+}
+
+impl WebDavStorage {
+    /// Files below `paths`' download folder go to `settings`' folder
+    pub fn new(
+        settings: &WebDavSettings,
+        paths: RemotePaths,
+        proxy: &ProxySettings,
+    ) -> Result<Self> {
+        let mut base = remote::parse_url(&settings.url, "WebDAV")?;
+        // Keep the folder when joining
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Ok(Self {
+            share: Arc::new(Share {
+                client: remote::client(&base, proxy)?,
+                base,
+                username: settings.username.clone().filter(|name| !name.is_empty()),
+                password: settings.password.clone().map(Zeroizing::new),
+            }),
+            paths,
+        })
+    }
+
+    fn url(&self, path: &Path) -> io::Result<Url> {
+        self.share.url(&self.paths.key(path)?)
+    }
+}
+
+#[async_trait]
+impl Storage for WebDavStorage {
+    async fn create(&self, path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        let key = self.paths.key(path)?;
+        self.share.make_folders(&key).await?;
+        let url = self.share.url(&key)?;
+        let (tx, mut rx) = mpsc::channel::<io::Result<Bytes>>(BODY_QUEUE_LEN);
+        let body = futures_util::stream::poll_fn(move |cx| rx.poll_recv(cx));
+        let request = self
+            .share
+            .request(Method::PUT, url)
+            .body(reqwest::Body::wrap_stream(body));
+        let what = format!("WebDAV upload of {}", key);
+        let upload = tokio::spawn(async move {
+            let response = request.send().await.map_err(remote::request_error)?;
+            remote::check(response, &what).await?;
+            Ok(())
+        });
+        Ok(Box::new(WebDavFile {
+            body: Some(tx),
+            upload: Some(upload),
+            written: 0,
+        }))
+    }
+
+    async fn append(&self, _path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        Err(remote::unsupported("Appending"))
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let key = self.paths.key(to)?;
+        self.share.make_folders(&key).await?;
+        let destination = self.share.url(&key)?;
+        let response = self
+            .share
+            .request(
+                Method::from_bytes(b"MOVE").map_err(io::Error::other)?,
+                self.url(from)?,
+            )
+            .header("Destination", destination.as_str())
+            .header("Overwrite", "T")
+            .send()
+            .await
+            .map_err(remote::request_error)?;
+        remote::check(response, "WebDAV move").await?;
+        Ok(())
+    }
+
+    async fn remove(&self, path: &Path) -> io::Result<()> {
+        let response = self
+            .share
+            .request(Method::DELETE, self.url(path)?)
+            .send()
+            .await
+            .map_err(remote::request_error)?;
+        remote::check(response, "WebDAV delete").await?;
+        Ok(())
+    }
+
+    async fn stat(&self, path: &Path) -> io::Result<FileStat> {
+        let response = self
+            .share
+            .request(Method::HEAD, self.url(path)?)
+            .send()
+            .await
+            .map_err(remote::request_error)?;
+        let response = remote::check(response, "WebDAV stat").await?;
+        Ok(FileStat {
+            len: remote::content_length(&response)?,
+            is_file: true,
+        })
+    }
+
+    async fn open(&self, path: &Path) -> io::Result<StorageReader> {
+        let response = self
+            .share
+            .request(Method::GET, self.url(path)?)
+            .send()
+            .await
+            .map_err(remote::request_error)?;
+        Ok(remote::body_reader(
+            remote::check(response, "WebDAV download").await?,
+        ))
+    }
+
+    async fn available_space(&self, _dir: &Path) -> io::Result<u64> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "Free space of WebDAV shares is not asked for",
+        ))
+    }
+
+    fn is_local(&self) -> bool {
+        false
+    }
+}
+
+/// A file being uploaded
+struct WebDavFile {
+    /// Feeds the request body; dropped to end it
+    body: Option<mpsc::Sender<io::Result<Bytes>>>,
+    upload: Option<JoinHandle<io::Result<()>>>,
+    written: u64,
+}
+
+impl WebDavFile {
+    /// Wait for the request to end and report how it went
+    async fn finish_upload(&mut self) -> io::Result<()> {
+        self.body = None;
+        match self.upload.take() {
+            Some(upload) => upload.await.map_err(io::Error::other)?,
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageFile for WebDavFile {
+    async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        let Some(body) = &self.body else {
+            return Err(io::Error::other("The file is already complete"));
+        };
+        if body.send(Ok(Bytes::copy_from_slice(data))).await.is_err() {
+            // The server ended the request early; tell why
+            self.finish_upload().await?;
+            return Err(io::Error::other("WebDAV server stopped the upload"));
+        }
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    async fn set_len(&mut self, len: u64) -> io::Result<()> {
+        let written = self.written;
+        remote::extend(self, written, len).await
+    }
+
+    async fn preallocate(&mut self, _len: u64) -> io::Result<()> {
+        Err(remote::unsupported("Preallocating"))
+    }
+
+    /// End the body and wait until the server stored the file
+    async fn flush(&mut self) -> io::Result<()> {
+        self.finish_upload().await
+    }
+}
+
+impl Drop for WebDavFile {
+    fn drop(&mut self) {
+        if let Some(upload) = self.upload.take() {
+            upload.abort();
+        }
+    }
+}
+
+/// The share and how to reach it
+struct Share {
+    client: reqwest::Client,
+    /// Ends with `/`
+    base: Url,
+    username: Option<String>,
+    password: Option<Zeroizing<String>>,
+}
+
+impl fmt::Debug for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Share")
+            .field("base", &self.base.as_str())
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Share {
+    fn url(&self, key: &str) -> io::Result<Url> {
+        self.base
+            .join(&remote::uri_encode(key, true))
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))
+    }
+
+    fn request(&self, method: Method, url: Url) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.username {
+            Some(username) => {
+                request.basic_auth(username, self.password.as_ref().map(|p| p.as_str()))
+            }
+            None => request,
+        }
+    }
+
+    /// Make the folders `key` lies in; ones that exist are fine
+    async fn make_folders(&self, key: &str) -> io::Result<()> {
+        let Some((folders, _)) = key.rsplit_once('/') else {
+            return Ok(());
+        };
+        let mut folder = String::new();
+        for segment in folders.split('/') {
+            folder.push_str(segment);
+            folder.push('/');
+            let response = self
+                .request(
+                    Method::from_bytes(b"MKCOL").map_err(io::Error::other)?,
+                    self.url(&folder)?,
+                )
+                .send()
+                .await
+                .map_err(remote::request_error)?;
+            // 405: the folder exists
+            if response.status().as_u16() != 405 {
+                remote::check(response, "WebDAV folder creation").await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::extract::{Request, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use tokio::io::AsyncReadExt;
+
+    type Files = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Just enough of a WebDAV server: files by path, folders always exist
+    async fn serve(State(files): State<Files>, headers: HeaderMap, request: Request) -> Response {
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        match method.as_str() {
+            "PUT" => {
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                files.lock().unwrap().insert(path, body.to_vec());
+                StatusCode::CREATED.into_response()
+            }
+            "MKCOL" => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+            "MOVE" => {
+                let destination = headers["destination"].to_str().unwrap();
+                let target = Url::parse(destination).unwrap().path().to_string();
+                let mut files = files.lock().unwrap();
+                match files.remove(&path) {
+                    Some(data) => {
+                        files.insert(target, data);
+                        StatusCode::CREATED.into_response()
+                    }
+                    None => StatusCode::NOT_FOUND.into_response(),
+                }
+            }
+            "DELETE" => match files.lock().unwrap().remove(&path) {
+                Some(_) => StatusCode::NO_CONTENT.into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            },
+            "GET" | "HEAD" => match files.lock().unwrap().get(&path) {
+                Some(data) => Response::builder()
+                    .header("content-length", data.len())
+                    .body(Body::from(data.clone()))
+                    .unwrap(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            },
+            _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_through_a_share() {
+        let files = Files::default();
+        let app = Router::new().fallback(serve).with_state(files.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let root = PathBuf::from("/srv/in");
+        let settings = WebDavSettings {
+            url: format!("http://{}/dav/incoming", addr),
+            ..Default::default()
+        };
+        let storage = WebDavStorage::new(
+            &settings,
+            RemotePaths::new(&root),
+            &ProxySettings::default(),
+        )
+        .unwrap();
+        let part = root.join("Alice").join("a b.bin.1234.p2p-part");
+        let target = root.join("Alice").join("a b.bin");
+
+        let mut file = storage.create(&part).await.unwrap();
+        file.write_all(b"hello ").await.unwrap();
+        file.set_len(8).await.unwrap();
+        file.write_all(b"world").await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+        storage.rename(&part, &target).await.unwrap();
+
+        assert_eq!(
+            storage.stat(&part).await.unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(storage.stat(&target).await.unwrap().len, 13);
+        let mut read = Vec::new();
+        storage
+            .open(&target)
+            .await
+            .unwrap()
+            .read_to_end(&mut read)
+            .await
+            .unwrap();
+        assert_eq!(read, b"hello \0\0world");
+        assert!(
+            files
+                .lock()
+                .unwrap()
+                .contains_key("/dav/incoming/Alice/a%20b.bin")
+        );
+
+        storage.remove(&target).await.unwrap();
+        assert!(files.lock().unwrap().is_empty());
+        assert!(storage.append(&target).await.is_err());
+    }
+}
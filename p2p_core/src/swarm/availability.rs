@@ -0,0 +1,161 @@
+//! Who has which pieces of a swarm file.
+//!
+//! A member learns what the members it fetches from have from their
+//! bitfields. Members fetching from it tell it with
+//! [`SwarmMsg::Have`](super::wire::SwarmMsg::Have) once it announced that
+//! it takes them, so the original sender, which fetches from nobody, still
+//! sees every receiver's progress. Pieces are never lost, so reports are
+//! merged by adding them up and arrive in any order.
+//!
+//! The backend reports every swarm's [`SwarmAvailability`] while they
+//! change, so the user sees where a file can still be fetched from.
+
+use super::pieces::PieceBitmap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// How often the backend checks swarms for changes to report
+pub const AVAILABILITY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pieces of the other members of one swarm
+#[derive(Debug)]
+pub struct Availability {
+    members: BTreeMap<SocketAddr, PieceBitmap>,
+    /// Something changed since the last [`Availability::take_changed`]
+    changed: bool,
+}
+
+impl Availability {
+    /// Nothing known yet about `members` of a file with `pieces` pieces
+    pub fn new(members: impl IntoIterator<Item = SocketAddr>, pieces: usize) -> Self {
+        Self {
+            members: members
+                .into_iter()
+                .map(|addr| (canonical(addr), PieceBitmap::new(pieces)))
+                .collect(),
+            changed: true,
+        }
+    }
+
+    /// Add what `addr` has; ignored unless it is a member and `pieces`
+    /// fits the file
+    pub fn record(&mut self, addr: SocketAddr, pieces: &PieceBitmap) {
+        let Some(known) = self.members.get_mut(&canonical(addr)) else {
+            return;
+        };
+        let before = known.count();
+        known.union(pieces);
+        self.changed |= known.count() != before;
+    }
+
+    /// Note a change not in the table, such as a piece arriving here
+    pub fn touch(&mut self) {
+        self.changed = true;
+    }
+
+    /// Whether anything changed since the last call
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn members(&self) -> Vec<MemberAvailability> {
+        self.members
+            .iter()
+            .map(|(addr, pieces)| MemberAvailability {
+                addr: *addr,
+                pieces: pieces.clone(),
+            })
+            .collect()
+    }
+}
+
+/// A dual-stack socket reports IPv4 peers as mapped IPv6 addresses
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// What one other member has
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemberAvailability {
+    /// Its transfer address
+    pub addr: SocketAddr,
+    pub pieces: PieceBitmap,
+}
+
+/// Where the pieces of one swarm file are, see
+/// [`AppEvent::SwarmAvailability`](crate::AppEvent::SwarmAvailability)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SwarmAvailability {
+    pub swarm_id: String,
+    pub file_name: String,
+    pub file_size: u64,
+    /// Pieces on this device
+    pub local: PieceBitmap,
+    pub members: Vec<MemberAvailability>,
+}
+
+impl SwarmAvailability {
+    pub fn piece_count(&self) -> usize {
+        self.local.len()
+    }
+
+    /// Devices with piece `index`, this one included
+    pub fn copies(&self, index: usize) -> usize {
+        usize::from(self.local.has(index))
+            + self
+                .members
+                .iter()
+                .filter(|member| member.pieces.has(index))
+                .count()
+    }
+
+    /// Whether every piece is somewhere, so the file can still be completed
+    pub fn is_available(&self) -> bool {
+        (0..self.piece_count()).all(|index| self.copies(index) > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv6Addr};
+
+    #[test]
+    fn test_reports_add_up_for_members_only() {
+        let seeder: SocketAddr = "10.0.0.1:9000".parse().unwrap();
+        let peer: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        let mut availability = Availability::new([seeder, peer], 3);
+        assert!(availability.take_changed());
+
+        let mut first = PieceBitmap::new(3);
+        first.set(0);
+        let mapped = SocketAddr::new(
+            IpAddr::V6(Ipv6Addr::from([0, 0, 0, 0, 0, 0xffff, 0x0a00, 0x0002])),
+            9000,
+        );
+        availability.record(mapped, &first);
+        assert!(availability.take_changed());
+        // An older report adds nothing
+        availability.record(peer, &PieceBitmap::new(3));
+        assert!(!availability.take_changed());
+        // Strangers and bitmaps of another size are ignored
+        availability.record("10.0.0.9:9000".parse().unwrap(), &first);
+        availability.record(seeder, &PieceBitmap::full(4));
+        assert!(!availability.take_changed());
+        availability.record(seeder, &PieceBitmap::full(3));
+
+        let report = SwarmAvailability {
+            swarm_id: "s".to_string(),
+            file_name: "a.bin".to_string(),
+            file_size: 3,
+            local: first,
+            members: availability.members(),
+        };
+        assert_eq!(report.members.len(), 2);
+        assert_eq!(report.copies(0), 3);
+        assert_eq!(report.copies(2), 1);
+        assert!(report.is_available());
+    }
+}
@@ -0,0 +1,298 @@
+//! Receiving a swarm file from every member at once.
+
+use super::pieces::PieceManager;
+use super::wire::{SwarmMsg, recv_swarm_msg, send_swarm_msg};
+use super::{SwarmFile, SwarmManifest, SwarmRegistry};
+use crate::transfer::cancel::report_cancelled;
+use crate::transfer::progress::ProgressReporter;
+use crate::transfer::protocol::{TransferMsg, send_msg};
+use crate::transfer::utils::open_secure_file;
+use crate::{AppEvent, EventCategory, LogLevel};
+use anyhow::{Result, anyhow};
+use quinn::Endpoint;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// Tries to reach a member that may still be pairing with the sender
+const CONNECT_ATTEMPTS: u32 = 10;
+
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How often a member with nothing new to offer is asked again
+const BITFIELD_POLL: Duration = Duration::from_millis(250);
+
+/// How long fetchers get to finish once the file is complete
+const FINISH_GRACE: Duration = Duration::from_secs(1);
+
+/// Fetch the pieces of `manifest` from `seeder` and the other members into
+/// `file_path`, serving the verified ones through `registry` meanwhile.
+/// `peer` names the sender in the completion event.
+#[allow(clippy::too_many_arguments)]
+pub async fn download(
+    endpoint: &Endpoint,
+    registry: &SwarmRegistry,
+    manifest: SwarmManifest,
+    seeder: SocketAddr,
+    file_path: PathBuf,
+    peer: &str,
+    event_tx: &mpsc::Sender<AppEvent>,
+    cancel: CancellationToken,
+) -> Result<()> {
+    let file_name = manifest.file_name.clone();
+    let file_size = manifest.file_size;
+
+    open_secure_file(&file_path, 0)
+        .await?
+        .set_len(file_size)
+        .await?;
+    let file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&file_path)
+        .await?;
+    let swarm = Arc::new(SwarmFile::receiving(
+        manifest.clone(),
+        file_path.clone(),
+        seeder,
+        file,
+    ));
+    registry.insert(swarm.clone());
+
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!(
+                "Joining swarm for {} ({} pieces, {} other receivers)",
+                file_name,
+                manifest.piece_count(),
+                manifest.peers.len()
+            ),
+        ))
+        .await;
+
+    let manager = Arc::new(Mutex::new(PieceManager::new(manifest.piece_count())));
+    let (done_tx, mut done_rx) = mpsc::channel::<(SocketAddr, u64)>(manifest.piece_count().max(1));
+    let mut fetchers = JoinSet::new();
+    for source in std::iter::once(seeder).chain(manifest.peers.iter().copied()) {
+        let endpoint = endpoint.clone();
+        let swarm = swarm.clone();
+        let manager = manager.clone();
+        let done_tx = done_tx.clone();
+        fetchers.spawn(async move {
+            let result = fetch_from(&endpoint, source, &swarm, &manager, &done_tx).await;
+            manager.lock().unwrap().remove_peer(source);
+            if let Err(e) = result {
+                tracing::debug!("Swarm member {} left: {}", source, e);
+            }
+        });
+    }
+    drop(done_tx);
+
+    let mut progress = ProgressReporter::new(event_tx, &file_name, file_size, 0, false);
+    progress.update(0).await;
+    let mut received = 0u64;
+    let mut from_seeder = 0usize;
+    let mut pieces = 0usize;
+    while pieces < manifest.piece_count() {
+        let piece = tokio::select! {
+            piece = done_rx.recv() => piece,
+            _ = cancel.cancelled() => {
+                fetchers.abort_all();
+                registry.remove(swarm.swarm_id());
+                let _ = tokio::fs::remove_file(&file_path).await;
+                report_cancelled(event_tx, &file_name, false, false, "Cancelled by the receiver").await;
+                return Ok(());
+            }
+        };
+        let Some((source, len)) = piece else {
+            registry.remove(swarm.swarm_id());
+            return Err(anyhow!(
+                "No swarm member has the rest of {} ({}/{} pieces)",
+                file_name,
+                pieces,
+                manifest.piece_count()
+            ));
+        };
+        pieces += 1;
+        received += len;
+        if source == seeder {
+            from_seeder += 1;
+        }
+        progress.update(received).await;
+    }
+    progress.update(file_size).await;
+    // Let the fetchers tell their members the file is complete
+    let _ = tokio::time::timeout(FINISH_GRACE, async {
+        while fetchers.join_next().await.is_some() {}
+    })
+    .await;
+    fetchers.abort_all();
+
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!(
+                "Swarm download of {} done: {} of {} pieces from the sender, the rest from other receivers",
+                file_name, from_seeder, pieces
+            ),
+        ))
+        .await;
+    let _ = event_tx
+        .send(AppEvent::TransferCompleted {
+            file_name,
+            saved_path: Some(file_path),
+            peer: Some(peer.to_string()),
+        })
+        .await;
+    Ok(())
+}
+
+/// Fetch pieces from one member until the file is complete
+async fn fetch_from(
+    endpoint: &Endpoint,
+    source: SocketAddr,
+    swarm: &SwarmFile,
+    manager: &Mutex<PieceManager>,
+    done_tx: &mpsc::Sender<(SocketAddr, u64)>,
+) -> Result<()> {
+    let (mut send, mut recv) = join(endpoint, source, swarm.swarm_id()).await?;
+
+    let pieces = swarm.manifest.piece_count();
+    // Pieces last told to `source`, if it takes `Have`
+    let mut told = None;
+    loop {
+        if manager.lock().unwrap().is_complete() {
+            tell_pieces(&mut send, swarm, &mut told).await?;
+            let _ = send.finish();
+            // Closing before the other side finished may cut off the `Have`
+            let _ = tokio::time::timeout(FINISH_GRACE, recv_swarm_msg(&mut recv)).await;
+            return Ok(());
+        }
+
+        send_swarm_msg(&mut send, &SwarmMsg::GetBitfield).await?;
+        match recv_swarm_msg(&mut recv).await? {
+            Some(SwarmMsg::Bitfield {
+                pieces: offered,
+                gossip,
+            }) if offered.is_valid_for(pieces) => {
+                swarm.record_member(source, &offered);
+                manager.lock().unwrap().update_peer(source, offered);
+                if gossip && told.is_none() {
+                    told = Some(usize::MAX);
+                }
+            }
+            other => return Err(anyhow!("Expected a bitfield, got {:?}", other)),
+        }
+        tell_pieces(&mut send, swarm, &mut told).await?;
+
+        let mut fetched = false;
+        loop {
+            let Some(index) = manager.lock().unwrap().next_for(source) else {
+                break;
+            };
+            match fetch_piece(&mut send, &mut recv, swarm, index).await {
+                Ok(len) => {
+                    manager.lock().unwrap().completed(index);
+                    fetched = true;
+                    let _ = done_tx.send((source, len)).await;
+                }
+                Err(e) => {
+                    manager.lock().unwrap().failed(index);
+                    return Err(e);
+                }
+            }
+        }
+        if !fetched {
+            tokio::time::sleep(BITFIELD_POLL).await;
+        }
+    }
+}
+
+/// Send our pieces to a member that takes `Have` if they changed since
+/// `told`
+async fn tell_pieces(
+    send: &mut quinn::SendStream,
+    swarm: &SwarmFile,
+    told: &mut Option<usize>,
+) -> Result<()> {
+    let Some(last) = told else {
+        return Ok(());
+    };
+    let pieces = swarm.bitmap();
+    if pieces.count() != *last {
+        *last = pieces.count();
+        send_swarm_msg(send, &SwarmMsg::Have { pieces }).await?;
+    }
+    Ok(())
+}
+
+async fn fetch_piece(
+    send: &mut quinn::SendStream,
+    recv: &mut quinn::RecvStream,
+    swarm: &SwarmFile,
+    index: usize,
+) -> Result<u64> {
+    send_swarm_msg(send, &SwarmMsg::Request { index }).await?;
+    let len = match recv_swarm_msg(recv).await? {
+        Some(SwarmMsg::Piece { index: got, len }) if got == index => len,
+        Some(SwarmMsg::Reject { reason }) => return Err(anyhow!(reason)),
+        other => return Err(anyhow!("Expected piece {}, got {:?}", index, other)),
+    };
+    let (_, expected_len) = swarm.manifest.piece_range(index);
+    if len != expected_len {
+        return Err(anyhow!(
+            "Piece {} has {} bytes, expected {}",
+            index,
+            len,
+            expected_len
+        ));
+    }
+    let mut data = vec![0u8; len as usize];
+    recv.read_exact(&mut data).await?;
+    swarm.write_piece(index, &data).await?;
+    Ok(len)
+}
+
+/// Open a swarm stream to `addr`. Other receivers only know the swarm once
+/// they accepted the offer, so refusals are retried for a while.
+async fn join(
+    endpoint: &Endpoint,
+    addr: SocketAddr,
+    swarm_id: &str,
+) -> Result<(quinn::SendStream, quinn::RecvStream)> {
+    let mut attempt = 1;
+    loop {
+        let result = async {
+            let connection = endpoint.connect(addr, "localhost")?.await?;
+            let (mut send, mut recv) = connection.open_bi().await?;
+            send_msg(
+                &mut send,
+                &TransferMsg::SwarmJoin {
+                    swarm_id: swarm_id.to_string(),
+                },
+            )
+            .await?;
+            match recv_swarm_msg(&mut recv).await? {
+                Some(SwarmMsg::Bitfield { .. }) => Ok((send, recv)),
+                Some(SwarmMsg::Reject { reason }) => Err(anyhow!(reason)),
+                other => Err(anyhow!("Expected a bitfield, got {:?}", other)),
+            }
+        }
+        .await;
+        match result {
+            Ok(streams) => return Ok(streams),
+            Err(e) if attempt >= CONNECT_ATTEMPTS => return Err(e),
+            Err(_) => {
+                attempt += 1;
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Swarm transfer of one file to several LAN peers.
+//!
+//! Sending a large file to many receivers one by one costs the sender the
+//! whole file per receiver. In a swarm the sender splits the file into
+//! pieces and offers a [`SwarmManifest`] to every receiver over its paired
+//! connection (see [`crate::transfer::offer_swarm`]). Each receiver then
+//! fetches pieces from the sender and from the other receivers at the same
+//! time ([`download`]), always asking for the piece the fewest peers have,
+//! and serves the pieces it already verified to the others ([`wire`]).
+//!
+//! Piece exchange needs no pairing between the receivers: the random swarm
+//! ID is only ever sent over paired connections and is required to join.
+//! Every piece is checked against its BLAKE3 hash from the manifest before
+//! it is written or passed on. Members also tell each other which pieces
+//! they have ([`availability`]), for showing where a file can be fetched
+//! from.
+
+pub mod availability;
+pub mod download;
+pub mod pieces;
+pub mod wire;
+
+use anyhow::{Result, anyhow};
+use availability::{Availability, SwarmAvailability};
+use pieces::PieceBitmap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// Smallest piece; smaller files get fewer pieces
+pub const MIN_PIECE_SIZE: u64 = 1024 * 1024;
+
+/// Most pieces per file, so the manifest fits in one protocol message
+pub const MAX_PIECES: usize = 512;
+
+/// Most receivers in one swarm
+pub const MAX_SWARM_PEERS: usize = 32;
+
+/// A swarm nobody fetched from for this long stops being served
+pub const SWARM_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// One receiver of a swarm send
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwarmTarget {
+    /// Verification session, see [`crate::AppCommand::SendFile`]
+    pub session_id: String,
+    /// `ip` or `ip:port`
+    pub target_ip: String,
+    pub target_peer_name: String,
+}
+
+/// What a receiver needs to join a swarm
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SwarmManifest {
+    pub swarm_id: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub piece_size: u64,
+    /// BLAKE3 of every piece, in order
+    pub piece_hashes: Vec<String>,
+    /// Transfer port of the original sender; its IP is where the offer
+    /// came from
+    pub seeder_port: u16,
+    /// Other receivers, which serve the pieces they already have
+    pub peers: Vec<SocketAddr>,
+}
+
+impl SwarmManifest {
+    pub fn piece_count(&self) -> usize {
+        self.piece_hashes.len()
+    }
+
+    /// Byte range of piece `index` as `(offset, len)`
+    pub fn piece_range(&self, index: usize) -> (u64, u64) {
+        let offset = index as u64 * self.piece_size;
+        (
+            offset,
+            self.piece_size.min(self.file_size.saturating_sub(offset)),
+        )
+    }
+
+    /// Reject manifests that do not describe `file_size` consistently
+    pub fn validate(&self) -> Result<()> {
+        if self.piece_size == 0
+            || self.piece_count() > MAX_PIECES
+            || self.peers.len() > MAX_SWARM_PEERS
+        {
+            return Err(anyhow!("Invalid swarm piece layout"));
+        }
+        if self.file_size.div_ceil(self.piece_size) != self.piece_count() as u64 {
+            return Err(anyhow!(
+                "Swarm manifest has {} pieces for {} bytes",
+                self.piece_count(),
+                self.file_size
+            ));
+        }
+        let valid_hash =
+            |hash: &String| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit());
+        if !self.piece_hashes.iter().all(valid_hash) {
+            return Err(anyhow!("Invalid swarm piece hash"));
+        }
+        Ok(())
+    }
+
+    /// The manifest as offered to `target`, which does not fetch from itself
+    pub fn for_target(&self, target: SocketAddr) -> Self {
+        Self {
+            peers: self
+                .peers
+                .iter()
+                .copied()
+                .filter(|peer| *peer != target)
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Piece size for a file of `file_size` bytes: whole MiB, at most
+/// [`MAX_PIECES`] pieces
+pub fn piece_size_for(file_size: u64) -> u64 {
+    file_size
+        .div_ceil(MAX_PIECES as u64)
+        .div_ceil(MIN_PIECE_SIZE)
+        .max(1)
+        * MIN_PIECE_SIZE
+}
+
+/// Split the file at `path` into pieces and hash them
+pub async fn build_manifest(
+    path: &Path,
+    seeder_port: u16,
+    peers: Vec<SocketAddr>,
+) -> Result<SwarmManifest> {
+    let path = path.to_path_buf();
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("Invalid file name"))?
+        .to_string();
+
+    let (file_size, piece_size, piece_hashes) = tokio::task::spawn_blocking(move || {
+        use std::io::Read;
+        let mut file = std::fs::File::open(&path)?;
+        let file_size = file.metadata()?.len();
+        let piece_size = piece_size_for(file_size);
+        let mut piece_hashes = Vec::new();
+        let mut buffer = vec![0u8; piece_size.min(file_size.max(1)) as usize];
+        let mut remaining = file_size;
+        while remaining > 0 {
+            let len = piece_size.min(remaining) as usize;
+            file.read_exact(&mut buffer[..len])?;
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_rayon(&buffer[..len]);
+            piece_hashes.push(hasher.finalize().to_hex().to_string());
+            remaining -= len as u64;
+        }
+        anyhow::Ok((file_size, piece_size, piece_hashes))
+    })
+    .await??;
+
+    Ok(SwarmManifest {
+        swarm_id: uuid::Uuid::new_v4().simple().to_string(),
+        file_name,
+        file_size,
+        piece_size,
+        piece_hashes,
+        seeder_port,
+        peers,
+    })
+}
+
+/// One file taking part in a swarm on this device
+#[derive(Debug)]
+pub struct SwarmFile {
+    pub manifest: SwarmManifest,
+    pub path: PathBuf,
+    have: Mutex<PieceBitmap>,
+    /// Pieces of the other members
+    availability: Mutex<Availability>,
+    file: tokio::sync::Mutex<tokio::fs::File>,
+    last_activity: Mutex<Instant>,
+}
+
+impl SwarmFile {
+    /// The complete original at `path`
+    pub async fn seeding(manifest: SwarmManifest, path: PathBuf) -> Result<Self> {
+        let file = tokio::fs::File::open(&path).await?;
+        let have = PieceBitmap::full(manifest.piece_count());
+        let members = manifest.peers.clone();
+        Ok(Self::new(manifest, path, have, members, file))
+    }
+
+    /// An empty copy being received into `path`, opened for reading and
+    /// writing, of the swarm `seeder` sends
+    pub fn receiving(
+        manifest: SwarmManifest,
+        path: PathBuf,
+        seeder: SocketAddr,
+        file: tokio::fs::File,
+    ) -> Self {
+        let have = PieceBitmap::new(manifest.piece_count());
+        let members = std::iter::once(seeder)
+            .chain(manifest.peers.iter().copied())
+            .collect();
+        Self::new(manifest, path, have, members, file)
+    }
+
+    fn new(
+        manifest: SwarmManifest,
+        path: PathBuf,
+        have: PieceBitmap,
+        members: Vec<SocketAddr>,
+        file: tokio::fs::File,
+    ) -> Self {
+        let availability = Availability::new(members, manifest.piece_count());
+        Self {
+            manifest,
+            path,
+            have: Mutex::new(have),
+            availability: Mutex::new(availability),
+            file: tokio::sync::Mutex::new(file),
+            last_activity: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn swarm_id(&self) -> &str {
+        &self.manifest.swarm_id
+    }
+
+    /// Verified pieces
+    pub fn bitmap(&self) -> PieceBitmap {
+        self.have.lock().unwrap().clone()
+    }
+
+    pub fn has(&self, index: usize) -> bool {
+        self.have.lock().unwrap().has(index)
+    }
+
+    /// Read piece `index`, which must be present
+    pub async fn read_piece(&self, index: usize) -> Result<Vec<u8>> {
+        if !self.has(index) {
+            return Err(anyhow!("Piece {} is not here", index));
+        }
+        self.touch();
+        let (offset, len) = self.manifest.piece_range(index);
+        let mut data = vec![0u8; len as usize];
+        let mut file = self.file.lock().await;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.read_exact(&mut data).await?;
+        Ok(data)
+    }
+
+    /// Check piece `index` against the manifest, write it and offer it to
+    /// other peers
+    pub async fn write_piece(&self, index: usize, data: &[u8]) -> Result<()> {
+        let expected = self
+            .manifest
+            .piece_hashes
+            .get(index)
+            .ok_or_else(|| anyhow!("No piece {}", index))?;
+        let (offset, len) = self.manifest.piece_range(index);
+        if data.len() as u64 != len || blake3::hash(data).to_hex().as_str() != expected {
+            return Err(anyhow!("Piece {} failed verification", index));
+        }
+        {
+            let mut file = self.file.lock().await;
+            file.seek(SeekFrom::Start(offset)).await?;
+            file.write_all(data).await?;
+            file.flush().await?;
+        }
+        self.have.lock().unwrap().set(index);
+        self.availability.lock().unwrap().touch();
+        self.touch();
+        Ok(())
+    }
+
+    /// Note the pieces member `addr` reported
+    pub fn record_member(&self, addr: SocketAddr, pieces: &PieceBitmap) {
+        self.availability.lock().unwrap().record(addr, pieces);
+    }
+
+    /// Where the pieces are, if that changed since the last call
+    pub fn availability_update(&self) -> Option<SwarmAvailability> {
+        let members = {
+            let mut availability = self.availability.lock().unwrap();
+            if !availability.take_changed() {
+                return None;
+            }
+            availability.members()
+        };
+        Some(SwarmAvailability {
+            swarm_id: self.manifest.swarm_id.clone(),
+            file_name: self.manifest.file_name.clone(),
+            file_size: self.manifest.file_size,
+            local: self.bitmap(),
+            members,
+        })
+    }
+
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        now.saturating_duration_since(*self.last_activity.lock().unwrap()) >= SWARM_IDLE_TIMEOUT
+    }
+}
+
+/// Swarms this device serves pieces for, by swarm ID
+#[derive(Debug, Default)]
+pub struct SwarmRegistry {
+    swarms: Mutex<HashMap<String, Arc<SwarmFile>>>,
+}
+
+impl SwarmRegistry {
+    pub fn insert(&self, swarm: Arc<SwarmFile>) {
+        self.swarms
+            .lock()
+            .unwrap()
+            .insert(swarm.swarm_id().to_string(), swarm);
+    }
+
+    /// The swarm `swarm_id`; idle swarms are dropped first
+    pub fn get(&self, swarm_id: &str) -> Option<Arc<SwarmFile>> {
+        let mut swarms = self.swarms.lock().unwrap();
+        let now = Instant::now();
+        swarms.retain(|_, swarm| !swarm.is_idle(now));
+        swarms.get(swarm_id).cloned()
+    }
+
+    /// Every swarm still served; idle swarms are dropped first
+    pub fn all(&self) -> Vec<Arc<SwarmFile>> {
+        let mut swarms = self.swarms.lock().unwrap();
+        let now = Instant::now();
+        swarms.retain(|_, swarm| !swarm.is_idle(now));
+        swarms.values().cloned().collect()
+    }
+
+    pub fn remove(&self, swarm_id: &str) {
+        self.swarms.lock().unwrap().remove(swarm_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.swarms.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_piece_layout_fits_one_message() {
+        assert_eq!(piece_size_for(0), MIN_PIECE_SIZE);
+        assert_eq!(piece_size_for(3 * MIN_PIECE_SIZE), MIN_PIECE_SIZE);
+        let large = crate::transfer::constants::MAX_FILE_SIZE;
+        assert!(large.div_ceil(piece_size_for(large)) <= MAX_PIECES as u64);
+
+        let manifest = SwarmManifest {
+            swarm_id: "s".to_string(),
+            file_name: "a.bin".to_string(),
+            file_size: 2 * MIN_PIECE_SIZE + 1,
+            piece_size: MIN_PIECE_SIZE,
+            piece_hashes: vec!["0".repeat(64); 3],
+            seeder_port: 9000,
+            peers: vec!["10.0.0.2:9000".parse().unwrap()],
+        };
+        manifest.validate().unwrap();
+        assert_eq!(manifest.piece_range(2), (2 * MIN_PIECE_SIZE, 1));
+        assert!(
+            manifest
+                .for_target("10.0.0.2:9000".parse().unwrap())
+                .peers
+                .is_empty()
+        );
+
+        let json = serde_json::to_vec(&crate::transfer::protocol::TransferMsg::SwarmOffer {
+            manifest: SwarmManifest {
+                file_name: "x".repeat(crate::transfer::constants::MAX_WIRE_FILENAME_LENGTH),
+                piece_hashes: vec!["0".repeat(64); MAX_PIECES],
+                peers: vec!["255.255.255.255:65535".parse().unwrap(); MAX_SWARM_PEERS],
+                ..manifest.clone()
+            },
+        })
+        .unwrap();
+        assert!(json.len() <= crate::transfer::constants::MAX_MSG_SIZE);
+
+        let short = SwarmManifest {
+            piece_hashes: vec!["0".repeat(64); 2],
+            ..manifest
+        };
+        assert!(short.validate().is_err());
+    }
+}
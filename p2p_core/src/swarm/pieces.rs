@@ -0,0 +1,173 @@
+//! Piece bookkeeping: who has which piece and what to fetch next.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+
+/// One bit per piece, set when the piece is on disk and verified
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceBitmap {
+    len: usize,
+    bits: Vec<u8>,
+}
+
+impl PieceBitmap {
+    /// No pieces yet
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            bits: vec![0; len.div_ceil(8)],
+        }
+    }
+
+    /// Every piece
+    pub fn full(len: usize) -> Self {
+        let mut bitmap = Self::new(len);
+        for index in 0..len {
+            bitmap.set(index);
+        }
+        bitmap
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether a bitmap from the wire is consistent and covers `pieces`
+    pub fn is_valid_for(&self, pieces: usize) -> bool {
+        self.len == pieces && self.bits.len() == pieces.div_ceil(8)
+    }
+
+    pub fn set(&mut self, index: usize) {
+        if index < self.len {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    pub fn has(&self, index: usize) -> bool {
+        index < self.len
+            && self
+                .bits
+                .get(index / 8)
+                .is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+    }
+
+    /// Add the pieces of `other`, which covers as many pieces
+    pub fn union(&mut self, other: &PieceBitmap) {
+        if other.is_valid_for(self.len) {
+            for (mine, theirs) in self.bits.iter_mut().zip(&other.bits) {
+                *mine |= theirs;
+            }
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        (0..self.len).filter(|&index| self.has(index)).count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.count() == self.len
+    }
+}
+
+/// Download plan of one receiver
+#[derive(Debug)]
+pub struct PieceManager {
+    have: PieceBitmap,
+    in_flight: HashSet<usize>,
+    peers: HashMap<SocketAddr, PieceBitmap>,
+}
+
+impl PieceManager {
+    pub fn new(pieces: usize) -> Self {
+        Self {
+            have: PieceBitmap::new(pieces),
+            in_flight: HashSet::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Record what `peer` announced
+    pub fn update_peer(&mut self, peer: SocketAddr, pieces: PieceBitmap) {
+        self.peers.insert(peer, pieces);
+    }
+
+    pub fn remove_peer(&mut self, peer: SocketAddr) {
+        self.peers.remove(&peer);
+    }
+
+    /// Claim the rarest missing piece `peer` has that nobody is fetching
+    pub fn next_for(&mut self, peer: SocketAddr) -> Option<usize> {
+        let offered = self.peers.get(&peer)?;
+        let index = (0..self.have.len())
+            .filter(|&index| {
+                offered.has(index) && !self.have.has(index) && !self.in_flight.contains(&index)
+            })
+            .min_by_key(|&index| {
+                self.peers
+                    .values()
+                    .filter(|pieces| pieces.has(index))
+                    .count()
+            })?;
+        self.in_flight.insert(index);
+        Some(index)
+    }
+
+    /// A claimed piece arrived intact
+    pub fn completed(&mut self, index: usize) {
+        self.in_flight.remove(&index);
+        self.have.set(index);
+    }
+
+    /// A claimed piece did not arrive; someone else may fetch it
+    pub fn failed(&mut self, index: usize) {
+        self.in_flight.remove(&index);
+    }
+
+    pub fn have(&self) -> &PieceBitmap {
+        &self.have
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.have.is_complete()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rarest_piece_is_fetched_first_and_only_once() {
+        let seeder: SocketAddr = "10.0.0.1:9000".parse().unwrap();
+        let peer: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        let mut manager = PieceManager::new(3);
+        manager.update_peer(seeder, PieceBitmap::full(3));
+        let mut partial = PieceBitmap::new(3);
+        partial.set(0);
+        partial.set(1);
+        manager.update_peer(peer, partial);
+
+        // Piece 2 is only at the seeder
+        assert_eq!(manager.next_for(seeder), Some(2));
+        assert_eq!(manager.next_for(peer), Some(0));
+        assert_eq!(manager.next_for(peer), Some(1));
+        assert_eq!(manager.next_for(peer), None);
+
+        manager.failed(1);
+        assert_eq!(manager.next_for(seeder), Some(1));
+        for index in 0..3 {
+            manager.completed(index);
+        }
+        assert!(manager.is_complete());
+        assert_eq!(manager.next_for(seeder), None);
+
+        let bitmap: PieceBitmap = serde_json::from_str(r#"{"len":9,"bits":[255]}"#).unwrap();
+        assert!(!bitmap.is_valid_for(9));
+        assert!(!bitmap.has(8));
+    }
+}
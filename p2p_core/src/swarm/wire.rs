@@ -0,0 +1,124 @@
+//! Piece exchange between swarm members.
+//!
+//! A member opens a stream to another one with
+//! [`TransferMsg::SwarmJoin`](crate::transfer::protocol::TransferMsg::SwarmJoin)
+//! and gets the other side's bitmap, or a [`SwarmMsg::Reject`] while that
+//! side has not joined the swarm yet. It then asks for fresh bitmaps and for
+//! pieces with [`SwarmMsg`]s. A
+//! [`SwarmMsg::Piece`] header is followed by the piece's raw bytes.
+//!
+//! A bitmap with `gossip` set invites [`SwarmMsg::Have`]: the member then
+//! also tells the other side its own pieces, whenever it got new ones.
+//! Versions before gossip neither set the flag nor send `Have`.
+
+use super::SwarmRegistry;
+use super::pieces::PieceBitmap;
+use crate::transfer::constants::MAX_MSG_SIZE;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+/// Messages on a swarm stream after the join
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SwarmMsg {
+    /// Ask which pieces the other side has
+    GetBitfield,
+    Bitfield {
+        pieces: PieceBitmap,
+        /// The sender takes [`SwarmMsg::Have`]
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        gossip: bool,
+    },
+    /// The pieces the sender of the message has; unanswered
+    Have {
+        pieces: PieceBitmap,
+    },
+    Request {
+        index: usize,
+    },
+    /// Followed by `len` bytes of piece data
+    Piece {
+        index: usize,
+        len: u64,
+    },
+    /// The swarm or piece is not available here
+    Reject {
+        reason: String,
+    },
+}
+
+pub async fn send_swarm_msg(send: &mut quinn::SendStream, msg: &SwarmMsg) -> Result<()> {
+    let json = serde_json::to_vec(msg)?;
+    send.write_all(&(json.len() as u32).to_be_bytes()).await?;
+    send.write_all(&json).await?;
+    Ok(())
+}
+
+/// Next message, or `None` once the other side finished the stream
+pub async fn recv_swarm_msg(recv: &mut quinn::RecvStream) -> Result<Option<SwarmMsg>> {
+    let mut len_buf = [0u8; 4];
+    match recv.read_exact(&mut len_buf).await {
+        Ok(()) => {}
+        Err(quinn::ReadExactError::FinishedEarly(0)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_MSG_SIZE {
+        return Err(anyhow!(
+            "Message too large: {} bytes (max {})",
+            len,
+            MAX_MSG_SIZE
+        ));
+    }
+    let mut buf = vec![0u8; len];
+    recv.read_exact(&mut buf).await?;
+    Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+/// Answer the member at `remote` that sent `SwarmJoin { swarm_id }`: its
+/// bitmap first, then its requests until it finishes the stream
+pub async fn serve(
+    registry: &SwarmRegistry,
+    swarm_id: &str,
+    remote: SocketAddr,
+    send: &mut quinn::SendStream,
+    recv: &mut quinn::RecvStream,
+) -> Result<()> {
+    let Some(swarm) = registry.get(swarm_id) else {
+        let reason = "Unknown swarm".to_string();
+        send_swarm_msg(send, &SwarmMsg::Reject { reason }).await?;
+        let _ = send.finish();
+        return Ok(());
+    };
+    let bitfield = |pieces| SwarmMsg::Bitfield {
+        pieces,
+        gossip: true,
+    };
+    send_swarm_msg(send, &bitfield(swarm.bitmap())).await?;
+
+    while let Some(msg) = recv_swarm_msg(recv).await? {
+        match msg {
+            SwarmMsg::GetBitfield => {
+                send_swarm_msg(send, &bitfield(swarm.bitmap())).await?;
+            }
+            SwarmMsg::Have { pieces } => swarm.record_member(remote, &pieces),
+            SwarmMsg::Request { index } => match swarm.read_piece(index).await {
+                Ok(data) => {
+                    let header = SwarmMsg::Piece {
+                        index,
+                        len: data.len() as u64,
+                    };
+                    send_swarm_msg(send, &header).await?;
+                    send.write_all(&data).await?;
+                }
+                Err(e) => {
+                    let reason = e.to_string();
+                    send_swarm_msg(send, &SwarmMsg::Reject { reason }).await?;
+                }
+            },
+            other => return Err(anyhow!("Unexpected swarm message: {:?}", other)),
+        }
+    }
+    let _ = send.finish();
+    Ok(())
+}
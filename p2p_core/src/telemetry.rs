@@ -0,0 +1,472 @@
+//! Opt-in anonymous usage statistics.
+//!
+//! Off by default; nothing is counted until the user picks a
+//! [`TelemetryMode`]. Then the backend counts which features are used (see
+//! [`feature`]) and which kinds of errors happen (see [`error_kind`]). Only
+//! these totals are kept: no names, paths, addresses or endpoint IDs, and
+//! no install ID to tie one report to the next.
+//!
+//! [`TelemetryMode::Offline`] only writes the totals to `telemetry.json`
+//! next to the history, for the user to read or hand over themselves.
+//! [`TelemetryMode::Online`] with an endpoint set additionally offers a
+//! report every [`REPORT_PERIOD`]: the exact JSON is shown with
+//! [`AppEvent::TelemetryPreview`](crate::AppEvent::TelemetryPreview) and
+//! only posted once the user agrees. Either answer starts a new period.
+//! Turning telemetry off deletes what was counted.
+
+use crate::config::{create_secure_dir_all, write_secure_file};
+use crate::proxy::ProxySettings;
+use crate::{AppCommand, AppEvent, EventCategory, LogLevel};
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// File name of the totals, next to the history
+pub const TELEMETRY_FILE: &str = "telemetry.json";
+
+/// How long totals are collected before a report is offered
+pub const REPORT_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How often the backend saves the totals and checks whether a report is due
+pub const TELEMETRY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What happens to the counts, stored in `config.json`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TelemetryMode {
+    /// Nothing is counted
+    #[default]
+    Off,
+    /// Counted and written locally, never sent
+    Offline,
+    /// Counted, and reports are offered for sending
+    Online,
+}
+
+impl TelemetryMode {
+    pub const ALL: [TelemetryMode; 3] = [
+        TelemetryMode::Off,
+        TelemetryMode::Offline,
+        TelemetryMode::Online,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TelemetryMode::Off => "Off",
+            TelemetryMode::Offline => "Count locally only",
+            TelemetryMode::Online => "Count and offer to send",
+        }
+    }
+}
+
+/// Telemetry section of `config.json`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelemetrySettings {
+    #[serde(default)]
+    pub mode: TelemetryMode,
+    /// Where reports are posted; without one, online mode counts only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+impl TelemetrySettings {
+    /// The endpoint to post reports to, if the mode allows sending
+    fn send_url(&self) -> Option<&str> {
+        (self.mode == TelemetryMode::Online)
+            .then_some(self.endpoint.as_deref())
+            .flatten()
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+    }
+}
+
+/// Totals kept between reports
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Counts {
+    /// Unix time counting started
+    since: u64,
+    features: BTreeMap<String, u64>,
+    errors: BTreeMap<String, u64>,
+}
+
+/// Everything a report contains; shown to the user as JSON before sending
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TelemetryReport {
+    pub app_version: String,
+    /// `linux`, `macos` or `windows`
+    pub os: String,
+    /// Whole days the counts cover
+    pub days: u64,
+    /// Uses per feature
+    pub features: BTreeMap<String, u64>,
+    /// Errors per area
+    pub errors: BTreeMap<String, u64>,
+}
+
+/// Feature name counted for `cmd`, `None` for commands that are not one
+pub fn feature(cmd: &AppCommand) -> Option<&'static str> {
+    Some(match cmd {
+        AppCommand::SendFile { .. } => "send_files",
+        AppCommand::MoveFiles { .. } => "move_files",
+        AppCommand::SendText { .. } => "send_text",
+        AppCommand::SendStream { .. } => "send_stream",
+        AppCommand::SwarmSend { .. } => "swarm_send",
+        AppCommand::SendViaRelay { .. } => "relay_send",
+        AppCommand::ScheduleSend { .. } => "schedule_send",
+        AppCommand::EstimateSend { .. } => "estimate_send",
+        AppCommand::ResumePendingSend { .. } => "resume_send",
+        AppCommand::UndoMove { .. } => "undo_move",
+        AppCommand::CreatePairingInvite => "pairing_invite",
+        AppCommand::RedeemPairingInvite { .. } => "pairing_invite_redeem",
+        AppCommand::PairWithPeer { .. } => "pair_with_peer",
+        AppCommand::StartHttpServer => "http_share",
+        AppCommand::ShareFileLink { .. } => "file_link",
+        AppCommand::ShareFiles { .. } => "file_share",
+        AppCommand::PushTextToWeb { .. } => "web_text",
+        AppCommand::WanConnect { .. } => "wan_connect",
+        AppCommand::StartWanShare => "wan_share",
+        AppCommand::ListMyDevices => "my_devices",
+        AppCommand::RunCleanup { .. } => "cleanup",
+        AppCommand::ResolveDuplicate { .. } => "resolve_duplicate",
+        AppCommand::ResolveOrphan { .. } => "resolve_orphan",
+        AppCommand::QueryHistory { .. } => "history_search",
+        AppCommand::ExportHistory { .. } => "history_export",
+        AppCommand::SetBandwidthCap(_) => "bandwidth_cap",
+        AppCommand::SetProxy(_) => "proxy",
+        AppCommand::SetRendezvous(_) => "rendezvous",
+        AppCommand::Tracked { command, .. } => return feature(command),
+        _ => return None,
+    })
+}
+
+/// Area counted for an error `event`, `None` for other events
+pub fn error_kind(event: &AppEvent) -> Option<&'static str> {
+    match event {
+        AppEvent::Error(_) => Some("general"),
+        AppEvent::Log {
+            level: LogLevel::Error,
+            category,
+            ..
+        } => Some(match category {
+            EventCategory::Status => "general",
+            EventCategory::Discovery => "discovery",
+            EventCategory::Pairing => "pairing",
+            EventCategory::Transfer => "transfer",
+            EventCategory::Http => "http",
+            EventCategory::Wan => "wan",
+        }),
+        AppEvent::VerificationCompleted {
+            verified: false, ..
+        } => Some("verification"),
+        AppEvent::PairingResult { success: false, .. } | AppEvent::LinkFailed { .. } => {
+            Some("pairing")
+        }
+        AppEvent::WanShareError(_) => Some("wan"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default)]
+struct TelemetryState {
+    settings: TelemetrySettings,
+    /// `None` keeps the totals in memory only
+    path: Option<PathBuf>,
+    counts: Counts,
+    /// Changed since the last save
+    dirty: bool,
+}
+
+impl TelemetryState {
+    fn load(settings: TelemetrySettings, path: Option<PathBuf>) -> Self {
+        let counts = path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_else(|| Counts {
+                since: now_secs(),
+                ..Counts::default()
+            });
+        Self {
+            settings,
+            path,
+            counts,
+            dirty: false,
+        }
+    }
+
+    fn save(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = create_secure_dir_all(parent);
+        }
+        match serde_json::to_string_pretty(&self.counts) {
+            Ok(json) => {
+                if let Err(e) = write_secure_file(path, &json) {
+                    tracing::warn!("Failed to save usage statistics: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to serialize usage statistics: {}", e),
+        }
+    }
+}
+
+/// Usage totals of the active profile. The backend keeps one across profile
+/// switches, so events are counted in the order they are delivered.
+#[derive(Debug, Default)]
+pub struct Telemetry {
+    state: Mutex<TelemetryState>,
+}
+
+fn now_secs() -> u64 {
+    crate::pairing::now_timestamp()
+}
+
+impl Telemetry {
+    /// Load the totals saved at `path`; a missing or invalid file starts
+    /// counting now
+    pub fn load(settings: TelemetrySettings, path: Option<PathBuf>) -> Self {
+        Self {
+            state: Mutex::new(TelemetryState::load(settings, path)),
+        }
+    }
+
+    /// Save the totals, then continue with another profile's
+    pub fn reload(&self, settings: TelemetrySettings, path: Option<PathBuf>) {
+        let mut state = self.state();
+        state.save();
+        *state = TelemetryState::load(settings, path);
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, TelemetryState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn settings(&self) -> TelemetrySettings {
+        self.state().settings.clone()
+    }
+
+    /// Switch mode; turning telemetry off deletes the totals, turning it on
+    /// starts counting now
+    pub fn set_mode(&self, mode: TelemetryMode) {
+        let mut state = self.state();
+        let was_off = state.settings.mode == TelemetryMode::Off;
+        state.settings.mode = mode;
+        if was_off || mode == TelemetryMode::Off {
+            state.counts = Counts {
+                since: now_secs(),
+                ..Counts::default()
+            };
+            state.dirty = false;
+            if let Some(path) = &state.path {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Count `cmd` if it is a feature and telemetry is on
+    pub fn count_command(&self, cmd: &AppCommand) {
+        if let Some(name) = feature(cmd) {
+            self.count(|counts| &mut counts.features, name);
+        }
+    }
+
+    /// Count `event` if it is an error and telemetry is on
+    pub fn count_event(&self, event: &AppEvent) {
+        if let Some(kind) = error_kind(event) {
+            self.count(|counts| &mut counts.errors, kind);
+        }
+    }
+
+    fn count(&self, table: impl FnOnce(&mut Counts) -> &mut BTreeMap<String, u64>, key: &str) {
+        let mut state = self.state();
+        if state.settings.mode == TelemetryMode::Off {
+            return;
+        }
+        let counter = table(&mut state.counts).entry(key.to_string()).or_default();
+        *counter = counter.saturating_add(1);
+        state.dirty = true;
+    }
+
+    /// The report for the totals so far, as of Unix time `now`
+    pub fn report(&self, now: u64) -> TelemetryReport {
+        let state = self.state();
+        TelemetryReport {
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+            days: now.saturating_sub(state.counts.since) / (24 * 60 * 60),
+            features: state.counts.features.clone(),
+            errors: state.counts.errors.clone(),
+        }
+    }
+
+    /// Whether a report should be offered for sending at Unix time `now`
+    pub fn is_due(&self, now: u64) -> bool {
+        let state = self.state();
+        state.settings.send_url().is_some()
+            && now.saturating_sub(state.counts.since) >= REPORT_PERIOD.as_secs()
+            && !(state.counts.features.is_empty() && state.counts.errors.is_empty())
+    }
+
+    /// Drop the totals and count from Unix time `now`
+    pub fn start_period(&self, now: u64) {
+        let mut state = self.state();
+        state.counts = Counts {
+            since: now,
+            ..Counts::default()
+        };
+        state.dirty = true;
+    }
+
+    /// Write the totals if they changed
+    pub fn save(&self) {
+        self.state().save();
+    }
+
+    /// Post `payload`, exactly as shown to the user, to the endpoint
+    pub async fn send(&self, payload: String, proxy: &ProxySettings) -> Result<()> {
+        let Some(endpoint) = self.settings().send_url().map(str::to_string) else {
+            bail!("No telemetry endpoint is set");
+        };
+        let url = reqwest::Url::parse(&endpoint)
+            .map_err(|e| anyhow!("Invalid telemetry endpoint: {}", e))?;
+        if url.scheme() != "https" {
+            bail!("The telemetry endpoint must use https");
+        }
+        let mut builder = reqwest::Client::builder().timeout(REQUEST_TIMEOUT);
+        if let Some(proxy_url) = url.host_str().and_then(|host| proxy.url_for(host)) {
+            builder = builder.proxy(reqwest::Proxy::all(proxy_url)?);
+        }
+        builder
+            .build()?
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(payload)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// The JSON posted for `report`, shown to the user unchanged
+pub fn payload(report: &TelemetryReport) -> String {
+    serde_json::to_string_pretty(report).unwrap_or_default()
+}
+
+/// Count errors among the events passing from `rx` to `event_tx`
+pub async fn count_events(
+    telemetry: std::sync::Arc<Telemetry>,
+    mut rx: mpsc::Receiver<AppEvent>,
+    event_tx: mpsc::Sender<AppEvent>,
+) {
+    while let Some(event) = rx.recv().await {
+        telemetry.count_event(&event);
+        if event_tx.send(event).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(mode: TelemetryMode) -> TelemetrySettings {
+        TelemetrySettings {
+            mode,
+            endpoint: Some("https://telemetry.example/report".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_nothing_is_counted_until_opted_in() {
+        let dir = std::env::temp_dir().join(format!("telemetry_{}", uuid::Uuid::new_v4()));
+        let path = dir.join(TELEMETRY_FILE);
+        let telemetry = Telemetry::load(TelemetrySettings::default(), Some(path.clone()));
+        telemetry.count_command(&AppCommand::StartHttpServer);
+        telemetry.count_event(&AppEvent::Error("boom".to_string()));
+        telemetry.save();
+        assert!(!path.exists());
+        assert!(telemetry.report(now_secs()).features.is_empty());
+
+        telemetry.set_mode(TelemetryMode::Offline);
+        let (_, tracked) = AppCommand::StartHttpServer.tracked();
+        telemetry.count_command(&tracked);
+        telemetry.count_command(&AppCommand::GetState);
+        telemetry.count_event(&AppEvent::Error("boom".to_string()));
+        telemetry.count_event(&AppEvent::log(
+            LogLevel::Error,
+            EventCategory::Transfer,
+            "disk full",
+        ));
+        telemetry.count_event(&AppEvent::log(
+            LogLevel::Warning,
+            EventCategory::Transfer,
+            "slow",
+        ));
+        telemetry.save();
+
+        let reloaded = Telemetry::load(settings(TelemetryMode::Offline), Some(path.clone()));
+        let report = reloaded.report(now_secs());
+        assert_eq!(
+            report.features,
+            BTreeMap::from([("http_share".to_string(), 1)])
+        );
+        assert_eq!(
+            report.errors,
+            BTreeMap::from([("general".to_string(), 1), ("transfer".to_string(), 1)])
+        );
+        // Nothing that says who or where
+        let json = payload(&report);
+        assert!(!json.contains("boom") && !json.contains("disk full"));
+
+        reloaded.set_mode(TelemetryMode::Off);
+        assert!(!path.exists());
+        assert!(reloaded.report(now_secs()).errors.is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_reports_are_due_weekly_and_only_online_with_an_endpoint() {
+        let now = now_secs();
+        let later = now + REPORT_PERIOD.as_secs();
+        let offline = Telemetry::load(settings(TelemetryMode::Offline), None);
+        offline.count_command(&AppCommand::StartWanShare);
+        assert!(!offline.is_due(later));
+
+        let online = Telemetry::load(settings(TelemetryMode::Online), None);
+        assert!(!online.is_due(later), "nothing counted");
+        online.count_command(&AppCommand::StartWanShare);
+        assert!(!online.is_due(now));
+        assert!(online.is_due(later));
+        assert_eq!(online.report(later).days, 7);
+
+        online.start_period(later);
+        assert!(!online.is_due(later + 1));
+        assert!(online.report(later).features.is_empty());
+
+        let no_endpoint = Telemetry::load(
+            TelemetrySettings {
+                mode: TelemetryMode::Online,
+                endpoint: None,
+            },
+            None,
+        );
+        no_endpoint.count_command(&AppCommand::StartWanShare);
+        assert!(!no_endpoint.is_due(later));
+    }
+}
@@ -0,0 +1,275 @@
+//! In-process test harness.
+//!
+//! Spins up complete backends on loopback with ephemeral ports, discovery
+//! turned off and an in-memory pairing store, so integration tests can
+//! drive the full pair → send → verify → resume flow through
+//! [`AppCommand`]/[`AppEvent`] without touching the user's real setup.
+//!
+//! ```no_run
+//! use p2p_core::testing::{TestPair, write_test_file};
+//!
+//! # async fn demo() -> anyhow::Result<()> {
+//! let mut pair = TestPair::new().await?;
+//! let file = write_test_file(pair.sender.root(), "hello.bin", 4096)?;
+//! pair.send_with_pairing(vec![file]).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::node::{P2pNode, P2pNodeBuilder, TransferHandle};
+use crate::pairing::MemoryPairingStore;
+use crate::{AppCommand, AppEvent};
+use anyhow::{Context, Result, anyhow, bail};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default time to wait for an expected event
+pub const DEFAULT_EVENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Write a file of `size` bytes with a deterministic, non-repeating pattern
+pub fn write_test_file(dir: &Path, name: &str, size: usize) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(name);
+    let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, data)?;
+    Ok(path)
+}
+
+/// One backend running on loopback
+pub struct TestNode {
+    node: P2pNode,
+    root: PathBuf,
+    endpoint_id: String,
+    name: String,
+    transfer_addr: SocketAddr,
+    pairings: Arc<MemoryPairingStore>,
+}
+
+impl TestNode {
+    /// Start a node named `name` with its own download directory
+    pub async fn spawn(name: &str) -> Result<Self> {
+        Self::spawn_with(name, |builder| builder).await
+    }
+
+    /// Like [`spawn`](Self::spawn), with extra builder settings applied last
+    pub async fn spawn_with(
+        name: &str,
+        configure: impl FnOnce(P2pNodeBuilder) -> P2pNodeBuilder,
+    ) -> Result<Self> {
+        let secret_key = iroh::SecretKey::generate(&mut rand::rng());
+        let endpoint_id = secret_key.public().to_string();
+        let root = std::env::temp_dir().join(format!("p2p_test_{}_{}", name, endpoint_id));
+        let download_dir = root.join("downloads");
+        let pairings = Arc::new(MemoryPairingStore::default());
+
+        let builder = P2pNode::builder()
+            .transfer_port(0)
+            .disable_discovery()
+            .secret_key(secret_key)
+            .device_name(name)
+            .download_dir(&download_dir)
+            .pairing_store(pairings.clone())
+            .schedule_file(root.join("scheduled_sends.json"))
+            .history_file(root.join("received_history.json"))
+            .journal_file(root.join("outgoing_journal.json"));
+        let mut node = configure(builder).spawn();
+
+        let ready = wait_for_event(&mut node, DEFAULT_EVENT_TIMEOUT, |event| {
+            matches!(event, AppEvent::BackendReady { .. } | AppEvent::Error(_))
+        })
+        .await
+        .context("Backend did not start")?;
+
+        let transfer_port = match ready {
+            AppEvent::BackendReady { transfer_port, .. } => transfer_port,
+            other => bail!("Backend failed to start: {:?}", other),
+        };
+
+        Ok(Self {
+            node,
+            root,
+            endpoint_id,
+            name: name.to_string(),
+            transfer_addr: SocketAddr::from(([127, 0, 0, 1], transfer_port)),
+            pairings,
+        })
+    }
+
+    /// Scratch directory owned by this node (downloads live in `downloads/`)
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn download_dir(&self) -> &Path {
+        &self.node.config().download_dir
+    }
+
+    pub fn endpoint_id(&self) -> &str {
+        &self.endpoint_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Loopback address of this node's QUIC transfer server
+    pub fn transfer_addr(&self) -> SocketAddr {
+        self.transfer_addr
+    }
+
+    /// Devices this node trusts as a receiver
+    pub fn pairings(&self) -> &MemoryPairingStore {
+        &self.pairings
+    }
+
+    pub fn node(&mut self) -> &mut P2pNode {
+        &mut self.node
+    }
+
+    pub async fn command(&self, cmd: AppCommand) -> Result<()> {
+        self.node.command(cmd).await
+    }
+
+    /// Start sending files to `peer`
+    pub async fn send_files_to(
+        &self,
+        peer: &TestNode,
+        files: Vec<PathBuf>,
+    ) -> Result<TransferHandle> {
+        self.node
+            .send_files_to_endpoint(
+                &peer.transfer_addr.to_string(),
+                peer.endpoint_id(),
+                &peer.name,
+                files,
+            )
+            .await
+    }
+
+    /// Wait until an event matching `predicate` arrives, skipping others
+    pub async fn wait_for(
+        &mut self,
+        timeout: Duration,
+        predicate: impl FnMut(&AppEvent) -> bool,
+    ) -> Result<AppEvent> {
+        wait_for_event(&mut self.node, timeout, predicate)
+            .await
+            .with_context(|| format!("Node '{}' timed out waiting for event", self.name))
+    }
+
+    /// Wait for `TransferCompleted` of the given file name
+    pub async fn wait_for_completion(&mut self, file_name: &str) -> Result<()> {
+        self.wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |event| matches!(event, AppEvent::TransferCompleted { file_name: name, .. } if name == file_name),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Stop the backend and remove the scratch directory
+    pub async fn shutdown(self) {
+        self.node.shutdown().await;
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+async fn wait_for_event(
+    node: &mut P2pNode,
+    timeout: Duration,
+    mut predicate: impl FnMut(&AppEvent) -> bool,
+) -> Result<AppEvent> {
+    let wait = async {
+        while let Some(event) = node.next_event().await {
+            if predicate(&event) {
+                return Ok(event);
+            }
+        }
+        Err(anyhow!("Event stream closed"))
+    };
+    tokio::time::timeout(timeout, wait)
+        .await
+        .map_err(|_| anyhow!("Timed out after {:?}", timeout))?
+}
+
+/// A sender and a receiver wired for LAN transfers over loopback
+pub struct TestPair {
+    pub sender: TestNode,
+    pub receiver: TestNode,
+}
+
+impl TestPair {
+    pub async fn new() -> Result<Self> {
+        Ok(Self {
+            sender: TestNode::spawn("sender").await?,
+            receiver: TestNode::spawn("receiver").await?,
+        })
+    }
+
+    /// Send files, answering the verification prompt with the code the
+    /// receiver displays, and wait for every file to complete on both sides
+    pub async fn send_with_pairing(&mut self, files: Vec<PathBuf>) -> Result<()> {
+        let names = file_names(&files)?;
+        let transfer = self.sender.send_files_to(&self.receiver, files).await?;
+
+        let code = match self
+            .receiver
+            .wait_for(DEFAULT_EVENT_TIMEOUT, |event| {
+                matches!(event, AppEvent::ShowVerificationCode { .. })
+            })
+            .await?
+        {
+            AppEvent::ShowVerificationCode { code, .. } => code,
+            _ => unreachable!(),
+        };
+
+        self.sender
+            .wait_for(DEFAULT_EVENT_TIMEOUT, |event| {
+                matches!(
+                    event,
+                    AppEvent::RequestVerificationCode { session_id, .. }
+                        if session_id == transfer.session_id()
+                )
+            })
+            .await?;
+        transfer.submit_verification_code(&code).await?;
+
+        self.wait_for_files(&names).await
+    }
+
+    /// Send files to an already paired receiver and wait for completion
+    pub async fn send_paired(&mut self, files: Vec<PathBuf>) -> Result<()> {
+        let names = file_names(&files)?;
+        self.sender.send_files_to(&self.receiver, files).await?;
+        self.wait_for_files(&names).await
+    }
+
+    async fn wait_for_files(&mut self, names: &[String]) -> Result<()> {
+        for name in names {
+            self.receiver.wait_for_completion(name).await?;
+        }
+        for name in names {
+            self.sender.wait_for_completion(name).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn shutdown(self) {
+        self.sender.shutdown().await;
+        self.receiver.shutdown().await;
+    }
+}
+
+fn file_names(files: &[PathBuf]) -> Result<Vec<String>> {
+    files
+        .iter()
+        .map(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Invalid file name: {}", path.display()))
+        })
+        .collect()
+}
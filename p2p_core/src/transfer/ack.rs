@@ -0,0 +1,227 @@
+//! Completion handshake at the end of a LAN file transfer.
+//!
+//! Both ends announce the handshake they speak, the receiver in `ResumeInfo`
+//! and the sender in `ResumeStart`; an absent field is version 0, this one
+//! is [`COMPLETION_ACK_VERSION`]. From version 1 on the sender keeps its
+//! half of the stream open after the data, the receiver always answers
+//! `TransferComplete` once the file is written and the result of its hash
+//! check after that, and the sender reports the file as done only after
+//! both. While it waits the sender asks again with `AckRequest` every
+//! [`COMPLETION_ACK_WAIT`]; a receiver still checking answers
+//! `TransferComplete`. Silence for [`COMPLETION_ACK_RETRIES`] more rounds
+//! fails the transfer if `TransferComplete` is missing, and leaves the file
+//! unconfirmed if only the hash check's result is. The result is
+//! `VerificationResult` from version 2 on, `HashVerified` before.
+//!
+//! Against a version 0 receiver the sender finishes its half as before,
+//! waits without asking again, and expects `HashVerified` only for a move.
+
+use super::moves::HASH_CONFIRM_TIMEOUT;
+use super::protocol::{TransferMsg, recv_msg, send_msg};
+use anyhow::{Result, bail};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Completion handshake this version speaks
+pub const COMPLETION_ACK_VERSION: u32 = 2;
+
+/// How long the sender waits for an answer before asking again
+pub const COMPLETION_ACK_WAIT: Duration = Duration::from_secs(10);
+
+/// Unanswered `AckRequest`s before the sender gives up
+pub const COMPLETION_ACK_RETRIES: u32 = 3;
+
+/// A peer that did not announce a version speaks version 0
+pub(crate) fn is_legacy(version: &u32) -> bool {
+    *version == 0
+}
+
+/// Whether the sender waits for `TransferComplete` a bounded time only, or
+/// keeps the stream open and asks again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckMode {
+    /// The receiver answers `AckRequest`
+    Versioned,
+    /// The receiver never answers `AckRequest`; wait once
+    Legacy,
+}
+
+impl AckMode {
+    /// Mode for a receiver that announced `version`
+    pub fn for_receiver(version: u32) -> Self {
+        if is_legacy(&version) {
+            Self::Legacy
+        } else {
+            Self::Versioned
+        }
+    }
+}
+
+/// Wait for the hash check's result after `TransferComplete`, `None` if
+/// it does not come
+pub async fn await_hash_check(
+    send: &mut quinn::SendStream,
+    recv: &mut quinn::RecvStream,
+    mode: AckMode,
+) -> Option<bool> {
+    // A legacy receiver checks without answering; it may take a while
+    let wait = match mode {
+        AckMode::Versioned => COMPLETION_ACK_WAIT,
+        AckMode::Legacy => HASH_CONFIRM_TIMEOUT / (COMPLETION_ACK_RETRIES + 1),
+    };
+    loop {
+        let next = std::pin::pin!(recv_msg(recv));
+        match await_answer(send, next, mode, wait).await {
+            // Still checking
+            Ok(TransferMsg::TransferComplete) => continue,
+            Ok(
+                TransferMsg::VerificationResult { verified }
+                | TransferMsg::HashVerified { verified },
+            ) => return Some(verified),
+            Ok(other) => {
+                tracing::warn!("Expected the hash check's result, got {:?}", other);
+                return None;
+            }
+            Err(e) => {
+                tracing::warn!("No hash check result from the receiver: {}", e);
+                return None;
+            }
+        }
+    }
+}
+
+/// Read one answer, asking again after each `wait` without one
+pub async fn await_answer<F>(
+    send: &mut quinn::SendStream,
+    mut answer: Pin<&mut F>,
+    mode: AckMode,
+    wait: Duration,
+) -> Result<TransferMsg>
+where
+    F: Future<Output = Result<TransferMsg>>,
+{
+    for attempt in 0..=COMPLETION_ACK_RETRIES {
+        if let Ok(msg) = tokio::time::timeout(wait, answer.as_mut()).await {
+            return msg;
+        }
+        if mode == AckMode::Versioned && attempt < COMPLETION_ACK_RETRIES {
+            send_msg(send, &TransferMsg::AckRequest).await?;
+        }
+    }
+    bail!(
+        "The receiver did not acknowledge the transfer within {:?}",
+        wait * (COMPLETION_ACK_RETRIES + 1)
+    )
+}
+
+/// Wait for the hash check `result`, answering the sender's `AckRequest`s
+/// meanwhile; a check that never reports counts as failed
+pub async fn await_verification(
+    send: &mut quinn::SendStream,
+    recv: &mut quinn::RecvStream,
+    mut result: oneshot::Receiver<bool>,
+) -> Result<bool> {
+    let mut listening = true;
+    loop {
+        tokio::select! {
+            verified = &mut result => return Ok(verified.unwrap_or(false)),
+            msg = recv_msg(recv), if listening => match msg {
+                Ok(TransferMsg::AckRequest) => {
+                    send_msg(send, &TransferMsg::TransferComplete).await?;
+                }
+                Ok(other) => bail!("Unexpected message while verifying: {:?}", other),
+                // The sender stopped asking; the answer may still reach it
+                Err(_) => listening = false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::{make_client_endpoint, make_server_endpoint};
+
+    /// Both ends of one stream over loopback: (sender, receiver)
+    async fn stream_pair() -> (
+        (quinn::SendStream, quinn::RecvStream),
+        (quinn::SendStream, quinn::RecvStream),
+        quinn::Connection,
+    ) {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let server = make_server_endpoint("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let client = make_client_endpoint().unwrap();
+        let accept = tokio::spawn(async move {
+            let incoming = server.accept().await.unwrap().await.unwrap();
+            let (send, mut recv) = incoming.accept_bi().await.unwrap();
+            recv_msg(&mut recv).await.unwrap();
+            (send, recv, incoming)
+        });
+        let connection = client.connect(addr, "localhost").unwrap().await.unwrap();
+        let (mut send, recv) = connection.open_bi().await.unwrap();
+        // The stream only reaches the server with its first bytes
+        send_msg(&mut send, &TransferMsg::ReadyForData)
+            .await
+            .unwrap();
+        let (their_send, their_recv, _incoming) = accept.await.unwrap();
+        ((send, recv), (their_send, their_recv), connection)
+    }
+
+    #[tokio::test]
+    async fn test_sender_asks_again_until_the_hash_check_ends() {
+        let ((mut send, mut recv), (mut their_send, mut their_recv), _connection) =
+            stream_pair().await;
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let receiver = tokio::spawn(async move {
+            let verified = await_verification(&mut their_send, &mut their_recv, result_rx)
+                .await
+                .unwrap();
+            send_msg(
+                &mut their_send,
+                &TransferMsg::VerificationResult { verified },
+            )
+            .await
+            .unwrap();
+            (their_send, their_recv)
+        });
+
+        // Unanswered until asked, then answered while checking
+        let answer = {
+            let first = std::pin::pin!(recv_msg(&mut recv));
+            await_answer(
+                &mut send,
+                first,
+                AckMode::Versioned,
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap()
+        };
+        assert!(matches!(answer, TransferMsg::TransferComplete));
+
+        result_tx.send(true).unwrap();
+        assert_eq!(
+            await_hash_check(&mut send, &mut recv, AckMode::Versioned).await,
+            Some(true)
+        );
+        receiver.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_silent_receiver_fails_after_the_retries() {
+        let ((mut send, mut recv), _receiver, _connection) = stream_pair().await;
+        let answer = std::pin::pin!(recv_msg(&mut recv));
+        let result = await_answer(
+            &mut send,
+            answer,
+            AckMode::Versioned,
+            Duration::from_millis(10),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Read buffers shared by all send and receive loops.
+//!
+//! Every file in flight reads through a [`BUFFER_SIZE`] buffer, so ten
+//! concurrent files used to hold 160 MB. Loops now borrow their buffer from
+//! [`transfer_buffers`], which keeps returned buffers for reuse and never
+//! allocates past [`MEMORY_BUDGET`]. Once the budget is spent, a loop gets a
+//! small buffer of [`SMALL_BUFFER_SIZE`] instead of waiting: the transfer
+//! goes on more slowly, and a sender and receiver in one process cannot
+//! starve each other.
+
+use super::constants::BUFFER_SIZE;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, OnceLock};
+
+/// Large buffers allocated at most, in use or kept for reuse
+pub const MEMORY_BUDGET: usize = 8 * BUFFER_SIZE;
+
+/// Buffer of files this small, or of loops over budget, outside the pool
+pub const SMALL_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Buffers of [`BUFFER_SIZE`] within a memory budget
+#[derive(Debug)]
+pub struct BufferPool {
+    budget: usize,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    /// Bytes of all pooled buffers, lent out or free
+    allocated: usize,
+    free: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            state: Mutex::default(),
+        }
+    }
+
+    /// A buffer to move `remaining` bytes through; returned when dropped
+    pub fn borrow(&self, remaining: u64) -> PooledBuffer<'_> {
+        if remaining <= SMALL_BUFFER_SIZE as u64 {
+            return PooledBuffer::unpooled(remaining.max(1) as usize);
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(data) = state.free.pop() {
+            return PooledBuffer {
+                data,
+                pool: Some(self),
+            };
+        }
+        if state.allocated + BUFFER_SIZE > self.budget {
+            return PooledBuffer::unpooled(SMALL_BUFFER_SIZE);
+        }
+        state.allocated += BUFFER_SIZE;
+        PooledBuffer {
+            data: vec![0u8; BUFFER_SIZE],
+            pool: Some(self),
+        }
+    }
+
+    /// Bytes of pooled buffers currently lent out
+    pub fn in_use(&self) -> usize {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.allocated - state.free.len() * BUFFER_SIZE
+    }
+
+    fn give_back(&self, data: Vec<u8>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.free.push(data);
+    }
+}
+
+/// The pool of this process
+pub fn transfer_buffers() -> &'static BufferPool {
+    static POOL: OnceLock<BufferPool> = OnceLock::new();
+    POOL.get_or_init(|| BufferPool::new(MEMORY_BUDGET))
+}
+
+/// A buffer lent by a [`BufferPool`]
+pub struct PooledBuffer<'a> {
+    data: Vec<u8>,
+    /// Where the buffer goes back to; `None` when it was allocated outside
+    pool: Option<&'a BufferPool>,
+}
+
+impl PooledBuffer<'_> {
+    fn unpooled(len: usize) -> Self {
+        Self {
+            data: vec![0u8; len],
+            pool: None,
+        }
+    }
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool {
+            pool.give_back(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_caps_large_buffers() {
+        let pool = BufferPool::new(2 * BUFFER_SIZE);
+        let big = BUFFER_SIZE as u64 * 4;
+
+        let small = pool.borrow(1000);
+        assert_eq!(small.len(), 1000);
+        assert_eq!(pool.in_use(), 0);
+
+        let first = pool.borrow(big);
+        let second = pool.borrow(big);
+        assert_eq!(first.len(), BUFFER_SIZE);
+        assert_eq!(second.len(), BUFFER_SIZE);
+        assert_eq!(pool.in_use(), 2 * BUFFER_SIZE);
+
+        // Over budget: a small buffer, right away
+        let third = pool.borrow(big);
+        assert_eq!(third.len(), SMALL_BUFFER_SIZE);
+
+        // Returned buffers are lent again
+        drop(first);
+        assert_eq!(pool.in_use(), BUFFER_SIZE);
+        let again = pool.borrow(big);
+        assert_eq!(again.len(), BUFFER_SIZE);
+        assert_eq!(pool.in_use(), 2 * BUFFER_SIZE);
+    }
+}
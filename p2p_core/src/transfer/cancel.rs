@@ -0,0 +1,78 @@
+//! Cancelling LAN transfers in flight, from either side.
+//!
+//! While file data flows the receiver's half of the stream is quiet, so it
+//! cancels with a framed [`TransferMsg::Cancel`](super::TransferMsg::Cancel)
+//! and stops reading. The sender's half carries raw file bytes, where no
+//! message can be framed; before the data starts it sends `Cancel` in place
+//! of `ResumeStart`, afterwards it resets the stream with [`CANCEL_CODE`].
+//! Both ends report [`AppEvent::TransferCancelled`](crate::AppEvent::TransferCancelled)
+//! instead of an error.
+//!
+//! A partial file the receiving user cancelled is deleted. One the sender
+//! cancelled is kept with its resume record, so sending it again continues
+//! where it stopped.
+
+use crate::AppEvent;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// QUIC stream error code meaning "cancelled by the user"
+pub const CANCEL_CODE: u32 = 0x4341;
+
+/// Cancels every transfer started before the call; later ones are unaffected
+#[derive(Debug, Default)]
+pub struct TransferCancel {
+    current: Mutex<CancellationToken>,
+}
+
+impl TransferCancel {
+    /// Token for a transfer starting now
+    pub fn token(&self) -> CancellationToken {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Cancel all transfers in flight
+    pub fn cancel_all(&self) {
+        let mut current = self.current.lock().unwrap();
+        current.cancel();
+        *current = CancellationToken::new();
+    }
+}
+
+/// Whether a stream was reset or stopped by the peer cancelling
+pub fn is_cancel_code(code: quinn::VarInt) -> bool {
+    code == quinn::VarInt::from_u32(CANCEL_CODE)
+}
+
+/// Tell the GUI a transfer was cancelled
+pub async fn report_cancelled(
+    event_tx: &mpsc::Sender<AppEvent>,
+    file_name: &str,
+    is_sending: bool,
+    by_peer: bool,
+    reason: &str,
+) {
+    let _ = event_tx
+        .send(AppEvent::TransferCancelled {
+            file_name: file_name.to_string(),
+            is_sending,
+            by_peer,
+            reason: reason.to_string(),
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_all_spares_later_transfers() {
+        let cancel = TransferCancel::default();
+        let running = cancel.token();
+        cancel.cancel_all();
+        assert!(running.is_cancelled());
+        assert!(!cancel.token().is_cancelled());
+    }
+}
@@ -0,0 +1,197 @@
+//! Failure injection in the send path, built with the `chaos` feature.
+//!
+//! With [`CHAOS_ENV`] set, each chunk the sender writes may be delayed,
+//! corrupted or end the stream with a reset, so integration tests can show
+//! that hash verification and resume recover from a bad network. The
+//! variable holds comma-separated `key=value` pairs:
+//!
+//! - `delay_ms`: pause up to this long before each chunk
+//! - `drop`: chance per chunk, `0.0..=1.0`, that the stream is reset
+//! - `corrupt`: chance per chunk that bytes are flipped
+//! - `bytes`: how many bytes a corrupted chunk gets wrong (default 1)
+//! - `after`: leave the first this many bytes of each file alone
+//! - `chunk`: largest chunk in bytes (default 64 KiB), so a file sees many
+//!
+//! `P2P_CHAOS=delay_ms=20,corrupt=0.05,bytes=4` slows a send down and
+//! breaks about one chunk in twenty. Tests that run in one process use
+//! [`set_override`] instead of the environment.
+
+use anyhow::{Result, anyhow, bail};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Failure injection settings are read from this variable
+pub const CHAOS_ENV: &str = "P2P_CHAOS";
+
+/// Chunk size while failures are injected; the normal buffers would send a
+/// test file in one piece
+pub const DEFAULT_CHAOS_CHUNK: usize = 64 * 1024;
+
+/// Stream reset code of a dropped stream; the receiver keeps its partial file
+pub const CHAOS_DROP_CODE: u32 = 0xC4A0;
+
+/// What goes wrong while sending
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosConfig {
+    /// Longest random pause before a chunk
+    pub max_delay: Duration,
+    /// Chance per chunk that the stream is reset
+    pub drop_chance: f64,
+    /// Chance per chunk that bytes are flipped
+    pub corrupt_chance: f64,
+    /// Bytes flipped in a corrupted chunk
+    pub corrupt_bytes: usize,
+    /// Bytes of each file sent untouched
+    pub after: u64,
+    /// Largest chunk read and written at once
+    pub chunk_size: usize,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            max_delay: Duration::ZERO,
+            drop_chance: 0.0,
+            corrupt_chance: 0.0,
+            corrupt_bytes: 1,
+            after: 0,
+            chunk_size: DEFAULT_CHAOS_CHUNK,
+        }
+    }
+}
+
+/// Set by tests; takes precedence over [`CHAOS_ENV`]
+static OVERRIDE: RwLock<Option<ChaosConfig>> = RwLock::new(None);
+
+/// Use `config` instead of [`CHAOS_ENV`] until cleared with `None`
+pub fn set_override(config: Option<ChaosConfig>) {
+    *OVERRIDE.write().unwrap_or_else(|e| e.into_inner()) = config;
+}
+
+impl ChaosConfig {
+    /// Settings in effect: the override, else [`CHAOS_ENV`] if set and valid
+    pub fn current() -> Option<Self> {
+        if let Some(config) = OVERRIDE.read().unwrap_or_else(|e| e.into_inner()).clone() {
+            return Some(config);
+        }
+        let spec = std::env::var(CHAOS_ENV).ok()?;
+        match Self::parse(&spec) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                tracing::warn!("Ignoring {}: {}", CHAOS_ENV, e);
+                None
+            }
+        }
+    }
+
+    /// Parse the `key=value,...` form of [`CHAOS_ENV`]
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut config = ChaosConfig::default();
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected key=value, got '{}'", pair))?;
+            let value = value.trim();
+            match key.trim() {
+                "delay_ms" => config.max_delay = Duration::from_millis(value.parse()?),
+                "drop" => config.drop_chance = chance(value)?,
+                "corrupt" => config.corrupt_chance = chance(value)?,
+                "bytes" => config.corrupt_bytes = value.parse()?,
+                "after" => config.after = value.parse()?,
+                "chunk" => config.chunk_size = value.parse::<usize>()?.max(1),
+                other => bail!("Unknown key '{}'", other),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn chance(value: &str) -> Result<f64> {
+    let chance: f64 = value.parse()?;
+    if !(0.0..=1.0).contains(&chance) {
+        bail!("Chance {} is not between 0 and 1", chance);
+    }
+    Ok(chance)
+}
+
+/// What happens to one chunk
+#[derive(Debug, PartialEq)]
+pub enum ChunkFate {
+    /// Written, possibly late or with flipped bytes
+    Send,
+    /// The stream is reset before the chunk goes out
+    Drop,
+}
+
+/// Pick the fate of the chunk starting at file offset `offset`, flipping
+/// bytes of `chunk` in place if it is corrupted, after the pause to take
+pub fn strike(config: &ChaosConfig, offset: u64, chunk: &mut [u8]) -> (Duration, ChunkFate) {
+    if offset < config.after || chunk.is_empty() {
+        return (Duration::ZERO, ChunkFate::Send);
+    }
+    let delay = if config.max_delay.is_zero() {
+        Duration::ZERO
+    } else {
+        config.max_delay.mul_f64(rand::random())
+    };
+    if rand::random_bool(config.drop_chance) {
+        return (delay, ChunkFate::Drop);
+    }
+    if rand::random_bool(config.corrupt_chance) {
+        for _ in 0..config.corrupt_bytes {
+            let i = rand::random_range(0..chunk.len());
+            chunk[i] ^= rand::random_range(1..=u8::MAX);
+        }
+    }
+    (delay, ChunkFate::Send)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reads_every_key() {
+        let config =
+            ChaosConfig::parse("delay_ms=20, drop=0.1,corrupt=0.5,bytes=4,after=1024,chunk=512")
+                .unwrap();
+        assert_eq!(
+            config,
+            ChaosConfig {
+                max_delay: Duration::from_millis(20),
+                drop_chance: 0.1,
+                corrupt_chance: 0.5,
+                corrupt_bytes: 4,
+                after: 1024,
+                chunk_size: 512,
+            }
+        );
+        assert_eq!(ChaosConfig::parse("").unwrap().corrupt_bytes, 1);
+        assert!(ChaosConfig::parse("drop=2").is_err());
+        assert!(ChaosConfig::parse("explode=1").is_err());
+        assert!(ChaosConfig::parse("corrupt").is_err());
+    }
+
+    #[test]
+    fn test_strike_corrupts_or_drops_only_after_the_offset() {
+        let config = ChaosConfig {
+            corrupt_chance: 1.0,
+            corrupt_bytes: 3,
+            after: 100,
+            ..Default::default()
+        };
+        let mut chunk = vec![0u8; 64];
+        assert_eq!(strike(&config, 0, &mut chunk).1, ChunkFate::Send);
+        assert!(chunk.iter().all(|&b| b == 0));
+
+        assert_eq!(strike(&config, 100, &mut chunk).1, ChunkFate::Send);
+        let flipped = chunk.iter().filter(|&&b| b != 0).count();
+        assert!((1..=3).contains(&flipped));
+
+        let config = ChaosConfig {
+            drop_chance: 1.0,
+            ..config
+        };
+        assert_eq!(strike(&config, 200, &mut chunk).1, ChunkFate::Drop);
+    }
+}
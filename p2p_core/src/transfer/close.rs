@@ -0,0 +1,207 @@
+//! Why a QUIC connection or stream ended, in words a user can act on.
+//!
+//! quinn reports "closed by peer: 0x178" or "timed out", which says little
+//! to someone whose transfer just failed. [`explain`] finds the QUIC error
+//! behind a transfer error and describes it: a peer that is not this app
+//! (ALPN mismatch), one that stopped answering, one that rejected us. The
+//! WAN crate maps iroh's errors, which come from its own QUIC fork, to
+//! [`CloseReason`] and shares the descriptions.
+
+use super::cancel::CANCEL_CODE;
+use super::constants::VERIFICATION_FAILED_CLOSE_CODE;
+use std::fmt;
+
+/// TLS alert `no_application_protocol` as a QUIC transport error code
+pub const NO_APPLICATION_PROTOCOL: u64 = 0x100 + 120;
+
+/// Reason the backend closes its endpoints with when it stops
+pub const APP_STOPPED_REASON: &str = "backend stopped";
+
+/// How a connection ended, whichever QUIC stack reported it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseReason {
+    /// No QUIC version in common
+    VersionMismatch,
+    /// A QUIC or TLS error from either side; `code` is the transport error code
+    Transport { code: u64, reason: String },
+    /// The peer's application closed the connection
+    Application { code: u64, reason: String },
+    /// The peer lost the connection state, usually after a restart
+    Reset,
+    /// Nothing arrived for the idle timeout
+    TimedOut,
+    /// This side closed the connection
+    LocallyClosed,
+    /// The endpoint ran out of connection IDs
+    CidsExhausted,
+}
+
+impl CloseReason {
+    pub fn from_quinn(error: &quinn::ConnectionError) -> Self {
+        use quinn::ConnectionError as E;
+        match error {
+            E::VersionMismatch => Self::VersionMismatch,
+            E::TransportError(e) => Self::Transport {
+                code: e.code.into(),
+                reason: e.reason.clone(),
+            },
+            E::ConnectionClosed(close) => Self::Transport {
+                code: close.error_code.into(),
+                reason: String::from_utf8_lossy(&close.reason).into_owned(),
+            },
+            E::ApplicationClosed(close) => Self::Application {
+                code: close.error_code.into_inner(),
+                reason: String::from_utf8_lossy(&close.reason).into_owned(),
+            },
+            E::Reset => Self::Reset,
+            E::TimedOut => Self::TimedOut,
+            E::LocallyClosed => Self::LocallyClosed,
+            E::CidsExhausted => Self::CidsExhausted,
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::VersionMismatch => write!(f, "The peer runs an incompatible QUIC version"),
+            Self::Transport { code, .. } if *code == NO_APPLICATION_PROTOCOL => write!(
+                f,
+                "The peer is not this app or runs an incompatible version (protocol mismatch)"
+            ),
+            Self::Transport { code, reason } if (0x100..0x200).contains(code) => {
+                write!(f, "The secure handshake with the peer failed")?;
+                with_detail(f, reason)
+            }
+            Self::Transport { code, reason } => {
+                write!(f, "Connection error (QUIC code {:#x})", code)?;
+                with_detail(f, reason)
+            }
+            Self::Application { code: 0, reason } if reason == APP_STOPPED_REASON => {
+                write!(f, "The peer closed the app")
+            }
+            Self::Application { code: 0, reason } => {
+                write!(f, "The peer closed the connection")?;
+                with_detail(f, reason)
+            }
+            Self::Application { code, reason }
+                if *code == u64::from(VERIFICATION_FAILED_CLOSE_CODE) =>
+            {
+                write!(f, "The peer rejected this device")?;
+                with_detail(f, reason)
+            }
+            Self::Application { code, reason } => {
+                write!(f, "The peer closed the connection (code {})", code)?;
+                with_detail(f, reason)
+            }
+            Self::Reset => write!(f, "The peer dropped the connection, perhaps by restarting"),
+            Self::TimedOut => write!(
+                f,
+                "The peer stopped answering; it may be offline or blocked by a firewall"
+            ),
+            Self::LocallyClosed => write!(f, "The connection was closed on this side"),
+            Self::CidsExhausted => write!(f, "Too many connections at once; try again later"),
+        }
+    }
+}
+
+fn with_detail(f: &mut fmt::Formatter<'_>, detail: &str) -> fmt::Result {
+    if detail.is_empty() {
+        Ok(())
+    } else {
+        write!(f, ": {}", detail)
+    }
+}
+
+/// What a stream error code from the peer means
+pub fn stream_code_reason(code: u64) -> String {
+    if code == u64::from(CANCEL_CODE) {
+        "Cancelled by the peer".to_string()
+    } else {
+        format!("The peer abandoned the transfer (code {})", code)
+    }
+}
+
+/// Describe `error` by the QUIC error behind it, or as it is when there is
+/// none
+pub fn explain(error: &anyhow::Error) -> String {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<quinn::ConnectionError>() {
+            return CloseReason::from_quinn(e).to_string();
+        }
+    }
+    for cause in error.chain() {
+        if let Some(quinn::ReadError::Reset(code)) = cause.downcast_ref() {
+            return stream_code_reason(code.into_inner());
+        }
+        if let Some(quinn::ReadExactError::ReadError(quinn::ReadError::Reset(code))) =
+            cause.downcast_ref()
+        {
+            return stream_code_reason(code.into_inner());
+        }
+        if let Some(quinn::WriteError::Stopped(code)) = cause.downcast_ref() {
+            return stream_code_reason(code.into_inner());
+        }
+        if let Some(quinn::ReadExactError::FinishedEarly(_)) = cause.downcast_ref() {
+            return "The peer ended the transfer early".to_string();
+        }
+        if let Some(quinn::ConnectError::EndpointStopping) = cause.downcast_ref() {
+            return "The transfer service is shutting down".to_string();
+        }
+    }
+    error.to_string()
+}
+
+/// Whether `error` is the connection dying under a transfer: the peer went
+/// silent or lost its state, rather than closing or refusing on purpose
+pub fn is_connection_lost(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<quinn::ConnectionError>(),
+            Some(quinn::ConnectionError::TimedOut | quinn::ConnectionError::Reset)
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close_reasons_are_explained() {
+        let alpn = CloseReason::Transport {
+            code: NO_APPLICATION_PROTOCOL,
+            reason: "peer doesn't support any known protocol".to_string(),
+        };
+        assert!(alpn.to_string().contains("not this app"));
+
+        let rejected = CloseReason::Application {
+            code: VERIFICATION_FAILED_CLOSE_CODE.into(),
+            reason: "too many wrong verification codes".to_string(),
+        };
+        assert_eq!(
+            rejected.to_string(),
+            "The peer rejected this device: too many wrong verification codes"
+        );
+
+        let stopped = CloseReason::Application {
+            code: 0,
+            reason: APP_STOPPED_REASON.to_string(),
+        };
+        assert_eq!(stopped.to_string(), "The peer closed the app");
+
+        let error = anyhow::Error::from(quinn::ReadExactError::ReadError(
+            quinn::ReadError::ConnectionLost(quinn::ConnectionError::TimedOut),
+        ))
+        .context("Failed to receive completion ack");
+        assert_eq!(explain(&error), CloseReason::TimedOut.to_string());
+        assert!(is_connection_lost(&error));
+
+        let cancelled = anyhow::Error::from(quinn::WriteError::Stopped(CANCEL_CODE.into()));
+        assert_eq!(explain(&cancelled), "Cancelled by the peer");
+        assert!(!is_connection_lost(&cancelled));
+
+        let plain = anyhow::anyhow!("File not found");
+        assert_eq!(explain(&plain), "File not found");
+    }
+}
@@ -5,15 +5,26 @@ pub const BUFFER_SIZE: usize = 16 * 1024 * 1024;
 /// Maximum file size (10 GB)
 pub const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024 * 1024;
 
-/// Maximum filename length (255 bytes)
+/// Maximum filename length on disk (255 bytes)
 pub const MAX_FILENAME_LENGTH: usize = 255;
 
+/// Maximum filename length accepted from a sender (1 KB); longer names are
+/// rejected, shorter ones are shortened to [`MAX_FILENAME_LENGTH`] on receipt
+pub const MAX_WIRE_FILENAME_LENGTH: usize = 1024;
+
 /// Maximum protocol message size (64KB) to prevent DoS via allocation
 pub const MAX_MSG_SIZE: usize = 64 * 1024;
 
 /// Timeout for pairing verification code input
 pub const DEFAULT_PAIRING_TIMEOUT_SECS: u64 = 60;
 
+/// Wrong codes a sender may submit in one session before the receiver
+/// closes the connection
+pub const MAX_VERIFICATION_ATTEMPTS: u32 = 3;
+
+/// QUIC application close code used after too many wrong codes
+pub const VERIFICATION_FAILED_CLOSE_CODE: u32 = 1;
+
 pub fn get_pairing_timeout() -> std::time::Duration {
     // Check environment variable for override (useful for tests)
     let secs = std::env::var("P2P_PAIRING_TIMEOUT")
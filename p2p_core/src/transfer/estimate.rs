@@ -0,0 +1,348 @@
+//! Dry runs of a send: what it would move, without moving it.
+//!
+//! [`estimate_send`] connects and pairs like a send, then shows the
+//! receiver the names, sizes and hashes of the files in
+//! [`TransferMsg::EstimateRequest`]s. The receiver answers with the resume
+//! offer each file would get, the size of any file already saved under its
+//! name, and its free space, and writes nothing. The estimate counts the
+//! bytes that would cross the wire and, once a send to that peer was timed
+//! or a rate cap applies, how long they would take; its
+//! [`SendEstimate::preview`] says per file what the send would do there.
+
+use super::hash::{compute_file_hash_with_progress, hash_algorithm};
+use super::protocol::{TransferMsg, recv_msg, send_msg};
+use super::rate_limit::RateLimiter;
+use super::sender::{TransferContext, connect_verified};
+use super::{normalize_file_name, resume, validate_transfer_info};
+use crate::storage::Storage;
+use crate::{AppEvent, FileInfo};
+use anyhow::{Result, anyhow};
+use quinn::Endpoint;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// Files per request, keeping a request under the message size limit
+pub const ESTIMATE_BATCH: usize = 32;
+
+/// What the receiver would do with one file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EstimateOffer {
+    /// Where the receiver would resume
+    pub offset: u64,
+    /// Hash of the bytes before `offset`, as in a resume offer
+    pub prefix_hash: Option<String>,
+    /// Why the receiver would refuse the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refused: Option<String>,
+    /// Size of the file already saved under this name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub existing_size: Option<u64>,
+}
+
+/// What a send would do with one file on the receiver
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FileAction {
+    /// Nothing of that name there yet
+    New,
+    /// Continue a partial copy from `offset`
+    Resume {
+        offset: u64,
+    },
+    /// The receiver has this exact file; nothing is sent
+    Skip,
+    /// Replace the different file of `existing_size` bytes saved there
+    Overwrite {
+        existing_size: u64,
+    },
+    Refused {
+        reason: String,
+    },
+}
+
+impl FileAction {
+    /// Action for a file of `size` the sender may resume at `offset`
+    fn of(size: u64, offset: u64, existing_size: Option<u64>) -> Self {
+        match existing_size {
+            _ if size > 0 && offset == size => Self::Skip,
+            _ if offset > 0 => Self::Resume { offset },
+            Some(existing_size) => Self::Overwrite { existing_size },
+            None => Self::New,
+        }
+    }
+}
+
+/// One file of a send and what it would do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FilePreview {
+    pub file_name: String,
+    pub size: u64,
+    #[serde(flatten)]
+    pub action: FileAction,
+}
+
+/// A file the receiver would refuse
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RefusedFile {
+    pub file_name: String,
+    pub reason: String,
+}
+
+/// What a send would move
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SendEstimate {
+    pub files: usize,
+    pub total_bytes: u64,
+    /// Files that are streams, such as named pipes, and not counted in the
+    /// bytes: their size is known only once they end
+    pub streams: usize,
+    /// Bytes left after resuming what the receiver already has
+    pub bytes_to_send: u64,
+    /// Files the receiver already has in full
+    pub complete_files: usize,
+    pub refused: Vec<RefusedFile>,
+    /// Free space of the receiver's download folder, when it knows
+    pub free_space: Option<u64>,
+    /// Expected duration, when a send to the peer was timed or a rate cap
+    /// applies
+    pub expected_secs: Option<u64>,
+    /// Each file that is not a stream, in send order
+    pub preview: Vec<FilePreview>,
+}
+
+impl SendEstimate {
+    /// Whether the bytes to send fit the receiver's free space
+    pub fn fits(&self) -> bool {
+        self.free_space
+            .is_none_or(|free| free >= self.bytes_to_send)
+    }
+
+    /// Files the send would replace on the receiver
+    pub fn overwrites(&self) -> usize {
+        self.preview
+            .iter()
+            .filter(|file| matches!(file.action, FileAction::Overwrite { .. }))
+            .count()
+    }
+}
+
+/// Estimate sending `files` to `target_addr`, pairing first if needed
+pub async fn estimate_send(
+    endpoint: &Endpoint,
+    target_addr: SocketAddr,
+    files: &[PathBuf],
+    event_tx: &mpsc::Sender<AppEvent>,
+    context: &TransferContext,
+    input_code_rx: &mut Option<mpsc::Receiver<String>>,
+) -> Result<SendEstimate> {
+    let connection =
+        connect_verified(endpoint, target_addr, event_tx, context, input_code_rx).await?;
+    let result = estimate_on(&connection, files, context.note.as_deref()).await;
+    context.pool.release(target_addr);
+    context.pool.schedule_expiry();
+
+    let mut estimate = result?;
+    estimate.expected_secs = expected_secs(
+        estimate.bytes_to_send,
+        context.pool.rate(target_addr),
+        context
+            .rate_limit
+            .as_deref()
+            .map(RateLimiter::bytes_per_sec),
+    );
+    Ok(estimate)
+}
+
+async fn estimate_on(
+    connection: &quinn::Connection,
+    files: &[PathBuf],
+    note: Option<&str>,
+) -> Result<SendEstimate> {
+    let algorithm = hash_algorithm();
+    let mut estimate = SendEstimate::default();
+    for batch in files.chunks(ESTIMATE_BATCH) {
+        let mut paths = Vec::with_capacity(batch.len());
+        let mut infos = Vec::with_capacity(batch.len());
+        for path in batch {
+            let file_name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| anyhow!("Invalid file name: {}", path.display()))?
+                .to_string();
+            let metadata = tokio::fs::metadata(path).await?;
+            // Hashing a stream would consume it
+            if !metadata.is_file() {
+                estimate.files += 1;
+                estimate.streams += 1;
+                continue;
+            }
+            paths.push(path);
+            infos.push(FileInfo {
+                file_name,
+                file_size: Some(metadata.len()),
+                file_path: PathBuf::new(),
+                file_hash: Some(compute_file_hash_with_progress(path, algorithm, |_, _| {}).await?),
+                hash_algorithm: algorithm,
+                modified: None,
+                mode: None,
+                note: note.map(str::to_string),
+                xattrs: Vec::new(),
+            });
+        }
+
+        if infos.is_empty() {
+            continue;
+        }
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send_msg(
+            &mut send,
+            &TransferMsg::EstimateRequest {
+                files: infos.clone(),
+            },
+        )
+        .await?;
+        let _ = send.finish();
+        let (offers, free_space) = match recv_msg(&mut recv).await? {
+            TransferMsg::EstimateReply { files, free_space } if files.len() == infos.len() => {
+                (files, free_space)
+            }
+            TransferMsg::VerificationFailed { message } => {
+                return Err(anyhow!("Receiver refused the estimate: {}", message));
+            }
+            other => return Err(anyhow!("Expected EstimateReply, got {:?}", other)),
+        };
+        estimate.free_space = free_space;
+
+        for ((path, info), offer) in paths.into_iter().zip(infos).zip(offers) {
+            let file_size = info.file_size.unwrap_or(0);
+            estimate.files += 1;
+            estimate.total_bytes += file_size;
+            if let Some(reason) = offer.refused {
+                estimate.preview.push(FilePreview {
+                    file_name: info.file_name.clone(),
+                    size: file_size,
+                    action: FileAction::Refused {
+                        reason: reason.clone(),
+                    },
+                });
+                estimate.refused.push(RefusedFile {
+                    file_name: info.file_name,
+                    reason,
+                });
+                continue;
+            }
+            let offset =
+                resume::start_offset(path, file_size, offer.offset, offer.prefix_hash.as_deref())
+                    .await?;
+            let action = FileAction::of(file_size, offset, offer.existing_size);
+            if action == FileAction::Skip {
+                estimate.complete_files += 1;
+            }
+            estimate.bytes_to_send += file_size - offset;
+            estimate.preview.push(FilePreview {
+                file_name: info.file_name,
+                size: file_size,
+                action,
+            });
+        }
+    }
+    Ok(estimate)
+}
+
+/// Seconds to move `bytes` at the slower of the measured and capped rates
+fn expected_secs(bytes: u64, measured: Option<u64>, cap: Option<u64>) -> Option<u64> {
+    let rate = match (measured, cap) {
+        (Some(measured), Some(cap)) => measured.min(cap),
+        (rate, None) | (None, rate) => rate?,
+    };
+    (rate > 0).then(|| bytes.div_ceil(rate))
+}
+
+/// Receiver side: answer an [`TransferMsg::EstimateRequest`] for files
+/// that would land in `download_dir`
+pub(super) async fn answer_estimate(
+    send: &mut quinn::SendStream,
+    files: Vec<FileInfo>,
+    download_dir: &Path,
+    storage: &dyn Storage,
+) -> Result<()> {
+    if files.len() > ESTIMATE_BATCH {
+        let message = format!("At most {} files per estimate", ESTIMATE_BATCH);
+        return send_msg(send, &TransferMsg::VerificationFailed { message }).await;
+    }
+    let mut offers = Vec::with_capacity(files.len());
+    for mut info in files {
+        if let Err(e) = validate_transfer_info(&info.file_name, info.file_size.unwrap_or(0)) {
+            offers.push(EstimateOffer {
+                offset: 0,
+                prefix_hash: None,
+                refused: Some(e.to_string()),
+                existing_size: None,
+            });
+            continue;
+        }
+        info.file_name = normalize_file_name(&info.file_name, download_dir).name;
+        let target = download_dir.join(&info.file_name);
+        let offer = resume::offer(storage, &target, &info).await?;
+        let existing_size = match storage.stat(&target).await {
+            Ok(stat) if stat.is_file => Some(stat.len),
+            _ => None,
+        };
+        offers.push(EstimateOffer {
+            offset: offer.offset,
+            prefix_hash: offer.prefix_hash,
+            refused: None,
+            existing_size,
+        });
+    }
+    let free_space = storage.available_space(download_dir).await.ok();
+    send_msg(
+        send,
+        &TransferMsg::EstimateReply {
+            files: offers,
+            free_space,
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_secs_uses_the_slower_rate() {
+        assert_eq!(expected_secs(1000, None, None), None);
+        assert_eq!(expected_secs(1000, Some(100), None), Some(10));
+        assert_eq!(expected_secs(1000, Some(100), Some(10)), Some(100));
+        assert_eq!(expected_secs(1001, None, Some(100)), Some(11));
+        assert_eq!(expected_secs(1000, Some(0), None), None);
+    }
+
+    #[test]
+    fn test_file_actions_follow_the_receivers_copy() {
+        assert_eq!(FileAction::of(100, 0, None), FileAction::New);
+        assert_eq!(FileAction::of(100, 100, Some(100)), FileAction::Skip);
+        assert_eq!(
+            FileAction::of(100, 40, Some(40)),
+            FileAction::Resume { offset: 40 }
+        );
+        // A partial copy kept beside the name resumes without replacing
+        assert_eq!(
+            FileAction::of(100, 40, None),
+            FileAction::Resume { offset: 40 }
+        );
+        assert_eq!(
+            FileAction::of(100, 0, Some(7)),
+            FileAction::Overwrite { existing_size: 7 }
+        );
+        // An empty file is always sent again
+        assert_eq!(
+            FileAction::of(0, 0, Some(0)),
+            FileAction::Overwrite { existing_size: 0 }
+        );
+    }
+}
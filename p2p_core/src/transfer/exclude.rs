@@ -0,0 +1,348 @@
+//! Sender-side exclude patterns for sending folders.
+//!
+//! Transfers carry single files today; this is the filter a folder walker
+//! applies before anything is offered, so it can be tested and configured
+//! ahead of directory transfer itself. Patterns follow `.gitignore`:
+//!
+//! - `node_modules` matches a file or folder of that name at any depth
+//! - `build/` matches folders only
+//! - `/target` is anchored to the folder being sent
+//! - `*.tmp`, `photo?.jpg`: `*` and `?` stay within one path component
+//! - `docs/**/*.pdf`: `**` spans any number of folders
+//! - `!keep.tmp` re-includes what an earlier pattern excluded
+//!
+//! Hidden files and folders (names starting with `.`) are skipped unless
+//! [`ExcludeFilter::include_hidden`] is set.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Patterns offered for new setups
+pub const DEFAULT_EXCLUDES: [&str; 4] = ["node_modules/", "*.tmp", ".DS_Store", "Thumbs.db"];
+
+/// Saved exclude settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExcludeSettings {
+    pub patterns: Vec<String>,
+    pub include_hidden: bool,
+}
+
+impl Default for ExcludeSettings {
+    fn default() -> Self {
+        Self {
+            patterns: DEFAULT_EXCLUDES.iter().map(|p| p.to_string()).collect(),
+            include_hidden: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    /// Path components; `**` is kept as its own component
+    parts: Vec<String>,
+    /// Contains a `/` other than a trailing one: matched from the root
+    anchored: bool,
+    dir_only: bool,
+    negated: bool,
+    source: String,
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        let source = line.trim();
+        if source.is_empty() || source.starts_with('#') {
+            return None;
+        }
+        let (negated, rest) = match source.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, source),
+        };
+        let dir_only = rest.ends_with('/');
+        let rest = rest.trim_end_matches('/');
+        let anchored = rest.contains('/');
+        let parts: Vec<String> = rest
+            .trim_start_matches('/')
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .map(str::to_string)
+            .collect();
+        if parts.is_empty() {
+            return None;
+        }
+        Some(Self {
+            parts,
+            anchored,
+            dir_only,
+            negated,
+            source: source.to_string(),
+        })
+    }
+
+    fn matches(&self, components: &[&str], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            match_parts(&self.parts, components)
+        } else {
+            // A bare name matches the last component at any depth
+            components
+                .last()
+                .is_some_and(|name| glob_match(&self.parts[0], name))
+        }
+    }
+}
+
+fn match_parts(parts: &[String], components: &[&str]) -> bool {
+    match parts.split_first() {
+        None => components.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=components.len()).any(|skip| match_parts(rest, &components[skip..]))
+        }
+        Some((first, rest)) => components
+            .split_first()
+            .is_some_and(|(name, tail)| glob_match(first, name) && match_parts(rest, tail)),
+    }
+}
+
+/// `*` and `?` wildcards within one path component
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p + 1, n));
+            p += 1;
+        } else if let Some((after_star, tried)) = backtrack {
+            p = after_star;
+            n = tried + 1;
+            backtrack = Some((after_star, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// What a walk left out, for the confirmation before sending
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExcludeSummary {
+    pub files_skipped: usize,
+    pub folders_skipped: usize,
+    /// Patterns that excluded at least one entry, in the order given
+    pub patterns_used: Vec<String>,
+    pub hidden_skipped: usize,
+}
+
+impl ExcludeSummary {
+    pub fn is_empty(&self) -> bool {
+        self.files_skipped == 0 && self.folders_skipped == 0
+    }
+
+    /// One line such as "Skipped 12 files and 1 folder (node_modules/, *.tmp, 3 hidden)"
+    pub fn describe(&self) -> String {
+        if self.is_empty() {
+            return "Nothing excluded".to_string();
+        }
+        let plural = |count: usize, word: &str| {
+            format!("{} {}{}", count, word, if count == 1 { "" } else { "s" })
+        };
+        let mut counts = Vec::new();
+        if self.files_skipped > 0 {
+            counts.push(plural(self.files_skipped, "file"));
+        }
+        if self.folders_skipped > 0 {
+            counts.push(plural(self.folders_skipped, "folder"));
+        }
+        let mut reasons = self.patterns_used.clone();
+        if self.hidden_skipped > 0 {
+            reasons.push(format!("{} hidden", self.hidden_skipped));
+        }
+        format!("Skipped {} ({})", counts.join(" and "), reasons.join(", "))
+    }
+}
+
+/// Compiled [`ExcludeSettings`]
+#[derive(Debug, Clone)]
+pub struct ExcludeFilter {
+    patterns: Vec<Pattern>,
+    pub include_hidden: bool,
+}
+
+impl ExcludeFilter {
+    pub fn new<S: AsRef<str>>(patterns: &[S], include_hidden: bool) -> Self {
+        Self {
+            patterns: patterns
+                .iter()
+                .filter_map(|line| Pattern::parse(line.as_ref()))
+                .collect(),
+            include_hidden,
+        }
+    }
+
+    pub fn from_settings(settings: &ExcludeSettings) -> Self {
+        Self::new(&settings.patterns, settings.include_hidden)
+    }
+
+    /// Pattern excluding `relative` (`/`-separated, below the folder being
+    /// sent), or `None` if it is sent; the last matching pattern wins
+    fn excluded_by(&self, relative: &str, is_dir: bool) -> Option<&Pattern> {
+        let components: Vec<&str> = relative.split('/').filter(|c| !c.is_empty()).collect();
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(&components, is_dir))
+            .filter(|pattern| !pattern.negated)
+    }
+
+    fn is_hidden(&self, name: &str) -> bool {
+        !self.include_hidden && name.starts_with('.')
+    }
+
+    pub fn is_excluded(&self, relative: &str, is_dir: bool) -> bool {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        self.is_hidden(name) || self.excluded_by(relative, is_dir).is_some()
+    }
+
+    /// Files below `root` that pass the filter, sorted, and what was left out.
+    /// Excluded folders are not descended into; symlinks are not followed.
+    pub fn walk(&self, root: &Path) -> Result<(Vec<PathBuf>, ExcludeSummary)> {
+        let mut files = Vec::new();
+        let mut summary = ExcludeSummary::default();
+        let mut stack = vec![(root.to_path_buf(), String::new())];
+        while let Some((dir, prefix)) = stack.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_symlink() {
+                    continue;
+                }
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let relative = format!("{}{}", prefix, name);
+                let is_dir = file_type.is_dir();
+
+                let reason = if self.is_hidden(&name) {
+                    summary.hidden_skipped += 1;
+                    Some(None)
+                } else {
+                    self.excluded_by(&relative, is_dir)
+                        .map(|pattern| Some(pattern.source.clone()))
+                };
+                if let Some(pattern) = reason {
+                    if let Some(pattern) = pattern
+                        && !summary.patterns_used.contains(&pattern)
+                    {
+                        summary.patterns_used.push(pattern);
+                    }
+                    if is_dir {
+                        summary.folders_skipped += 1;
+                    } else {
+                        summary.files_skipped += 1;
+                    }
+                    continue;
+                }
+
+                if is_dir {
+                    stack.push((entry.path(), format!("{}/", relative)));
+                } else if file_type.is_file() {
+                    files.push(entry.path());
+                }
+            }
+        }
+        files.sort();
+        // Report patterns in the order they were written
+        let order: Vec<&str> = self.patterns.iter().map(|p| p.source.as_str()).collect();
+        summary
+            .patterns_used
+            .sort_by_key(|used| order.iter().position(|p| p == used));
+        Ok((files, summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.tmp", "a.tmp"));
+        assert!(glob_match("*.tmp", ".tmp"));
+        assert!(!glob_match("*.tmp", "a.tmp.txt"));
+        assert!(glob_match("photo?.jpg", "photo1.jpg"));
+        assert!(!glob_match("photo?.jpg", "photo12.jpg"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(glob_match("node_modules", "node_modules"));
+    }
+
+    #[test]
+    fn test_gitignore_rules() {
+        let filter = ExcludeFilter::new(
+            &[
+                "node_modules",
+                "build/",
+                "/target",
+                "*.tmp",
+                "!keep.tmp",
+                "docs/**/*.pdf",
+                "# comment",
+            ],
+            false,
+        );
+        assert!(filter.is_excluded("node_modules", true));
+        assert!(filter.is_excluded("web/node_modules", true));
+        assert!(filter.is_excluded("build", true));
+        assert!(!filter.is_excluded("build", false));
+        assert!(filter.is_excluded("target", true));
+        assert!(!filter.is_excluded("crate/target", true));
+        assert!(filter.is_excluded("a/b/c.tmp", false));
+        assert!(!filter.is_excluded("keep.tmp", false));
+        assert!(filter.is_excluded("docs/a.pdf", false));
+        assert!(filter.is_excluded("docs/x/y/a.pdf", false));
+        assert!(!filter.is_excluded("other/a.pdf", false));
+        assert!(filter.is_excluded(".git", true));
+        assert!(!filter.is_excluded("src/main.rs", false));
+
+        let with_hidden = ExcludeFilter::new(&["*.tmp"], true);
+        assert!(!with_hidden.is_excluded(".env", false));
+    }
+
+    #[test]
+    fn test_walk_skips_and_summarizes() {
+        let dir = std::env::temp_dir().join(format!("exclude_{}", uuid::Uuid::new_v4()));
+        let root = dir.as_path();
+        for file in [
+            "a.txt",
+            "b.tmp",
+            "src/lib.rs",
+            "node_modules/x/index.js",
+            ".hidden/secret",
+            ".env",
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"x").unwrap();
+        }
+
+        let filter = ExcludeFilter::from_settings(&ExcludeSettings::default());
+        let (files, summary) = filter.walk(root).unwrap();
+        assert_eq!(files, vec![root.join("a.txt"), root.join("src/lib.rs")]);
+        assert_eq!(summary.files_skipped, 2);
+        assert_eq!(summary.folders_skipped, 2);
+        assert_eq!(summary.hidden_skipped, 2);
+        assert_eq!(summary.patterns_used, vec!["node_modules/", "*.tmp"]);
+        assert_eq!(
+            summary.describe(),
+            "Skipped 2 files and 2 folders (node_modules/, *.tmp, 2 hidden)"
+        );
+        assert_eq!(ExcludeSummary::default().describe(), "Nothing excluded");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,766 @@
+//! Receiver-side file name normalization shared by the LAN, WAN and HTTP
+//! receivers.
+//!
+//! Names arrive from whatever OS the sender runs, so they are reduced to one
+//! path component that can be created on every platform we ship for. The
+//! rules, applied in order:
+//!
+//! 1. Only the last path component is kept (`/` and `\` both separate).
+//!    The name is composed to Unicode NFC first, so `é` sent by macOS as `e`
+//!    plus an accent is the same file as everywhere else; this alone is not
+//!    reported as a rename.
+//! 2. Control characters, text direction controls (which can make
+//!    `invoice\u{202E}fdp.exe` display as `invoiceexe.pdf`) and `<>:"/\|?*`
+//!    are removed.
+//! 3. Leading whitespace and trailing dots/spaces are stripped; Windows drops
+//!    them silently, which would otherwise alias another file.
+//! 4. Reserved device names (`CON`, `NUL`, `COM1`, ...) get a `_` prefix,
+//!    also when followed by extensions (`CON.tar.gz` becomes `_CON.tar.gz`).
+//! 5. Names longer than [`MAX_FILENAME_LENGTH`] bytes are shortened from the
+//!    end of the stem, keeping the extension.
+//! 6. On Windows the stem is shortened further so the full download path fits
+//!    in `MAX_PATH`.
+//! 7. Names left empty become `unknown_file.bin`.
+//! 8. A name with non-ASCII characters that the download directory's file
+//!    system rejects, such as a FAT32 stick mounted with a legacy code page,
+//!    is transliterated to ASCII (`Müller` becomes `Muller`, `東京` becomes
+//!    `Dong Jing`), and characters without a transliteration are escaped
+//!    as `_uXXXX`. The rules above then run again on the result. With
+//!    [`set_transliterate_names`] off the name is kept and a notice says
+//!    why saving it will fail.
+//!
+//! Every rule that changed the name is recorded so receivers can tell the user
+//! why the saved file differs from what the sender offered, with the
+//! characters transliterated.
+//!
+//! Names that pass can still look like something else: `pаypal.exe` with a
+//! Cyrillic `а`, or invisible characters hiding what a name really is. These
+//! are kept, since they are legitimate in some languages, but reported as
+//! [`NameWarning`]s for the receiver to show before the file is accepted.
+
+use super::constants::MAX_FILENAME_LENGTH;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use unicode_normalization::UnicodeNormalization;
+use unicode_script::{Script, UnicodeScript};
+
+/// Name used when nothing usable is left after normalization
+pub const FALLBACK_FILE_NAME: &str = "unknown_file.bin";
+
+/// Prefix of the empty file that tests whether a directory takes a name
+const PROBE_PREFIX: &str = ".p2p-name-probe-";
+
+/// Whether names the file system rejects are transliterated (rule 8)
+static TRANSLITERATE_NAMES: AtomicBool = AtomicBool::new(true);
+
+/// Transliterate (or with `false`, keep) names the file system rejects
+pub fn set_transliterate_names(enabled: bool) {
+    TRANSLITERATE_NAMES.store(enabled, Ordering::Relaxed);
+}
+
+/// Characters of the sender's ID in its folder name (see [`peer_folder`])
+const PEER_ID_CHARS: usize = 8;
+
+/// Longest full path (in UTF-16 units, excluding the terminator) Windows
+/// accepts without long path support enabled
+pub const WINDOWS_MAX_PATH: usize = 259;
+
+/// Extensions longer than this are treated as part of the stem when shortening
+const MAX_EXTENSION_CHARS: usize = 16;
+
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const INVALID_CHARS: &str = "<>:\"/\\|?*";
+
+/// Characters that reorder the text around them when displayed
+const BIDI_CONTROLS: [char; 12] = [
+    '\u{061C}', '\u{200E}', '\u{200F}', '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}', '\u{202E}',
+    '\u{2066}', '\u{2067}', '\u{2068}', '\u{2069}',
+];
+
+/// Characters that take no space or look like nothing when displayed
+const INVISIBLE_CHARS: [char; 23] = [
+    '\u{00AD}', '\u{034F}', '\u{115F}', '\u{1160}', '\u{17B4}', '\u{17B5}', '\u{180E}', '\u{200B}',
+    '\u{200C}', '\u{200D}', '\u{2060}', '\u{2061}', '\u{2062}', '\u{2063}', '\u{2064}', '\u{206A}',
+    '\u{206B}', '\u{206C}', '\u{206D}', '\u{206E}', '\u{206F}', '\u{3164}', '\u{FEFF}',
+];
+
+/// Scripts written together in one word by Japanese, Chinese and Korean;
+/// other mixes within a word are treated as lookalikes
+const SCRIPT_MIXES: [&[Script]; 3] = [
+    &[
+        Script::Latin,
+        Script::Han,
+        Script::Hiragana,
+        Script::Katakana,
+    ],
+    &[Script::Latin, Script::Han, Script::Bopomofo],
+    &[Script::Latin, Script::Han, Script::Hangul],
+];
+
+/// Extensions of files the receiving system runs when opened
+pub const EXECUTABLE_EXTENSIONS: [&str; 22] = [
+    "exe", "com", "scr", "bat", "cmd", "pif", "msi", "msix", "appx", "ps1", "vbs", "vbe", "js",
+    "jse", "wsf", "hta", "cpl", "lnk", "jar", "app", "command", "sh",
+];
+
+/// Why a received file name was changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameReason {
+    /// Directory components were removed
+    DirectoriesRemoved,
+    /// Control or Windows-invalid characters were removed
+    InvalidCharacters,
+    /// Characters changing the direction of the text were removed
+    DirectionControls,
+    /// Leading whitespace or trailing dots/spaces were removed
+    TrailingDotsOrSpaces,
+    /// The name is a reserved Windows device name
+    ReservedName,
+    /// The name exceeded the file system's name length limit
+    NameTooLong,
+    /// The full path exceeded the platform's path length limit
+    PathTooLong,
+    /// The file system cannot store some characters, which were
+    /// transliterated to ASCII
+    Transliterated,
+    /// Nothing usable was left, so a placeholder name was used
+    Empty,
+}
+
+impl fmt::Display for RenameReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            RenameReason::DirectoriesRemoved => "directory components removed",
+            RenameReason::InvalidCharacters => "invalid characters removed",
+            RenameReason::DirectionControls => "text direction controls removed",
+            RenameReason::TrailingDotsOrSpaces => "surrounding dots/spaces removed",
+            RenameReason::ReservedName => "reserved Windows device name",
+            RenameReason::NameTooLong => "name too long",
+            RenameReason::PathTooLong => "path too long",
+            RenameReason::Transliterated => "characters the file system cannot store replaced",
+            RenameReason::Empty => "no usable characters",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Why a received file name may not be what it looks like
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameWarning {
+    /// A word mixes letters of scripts that are not written together, like
+    /// Latin and Cyrillic
+    MixedScripts,
+    /// The name has characters that are not displayed
+    InvisibleCharacters,
+}
+
+impl fmt::Display for NameWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            NameWarning::MixedScripts => "mixes lookalike letters of different alphabets",
+            NameWarning::InvisibleCharacters => "contains invisible characters",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Result of normalizing a received file name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizedName {
+    /// Name the file is saved under
+    pub name: String,
+    /// Name as offered by the sender
+    pub original: String,
+    /// Rules that changed the name, in the order they were applied
+    pub reasons: Vec<RenameReason>,
+    /// What about the saved name could mislead the user
+    pub warnings: Vec<NameWarning>,
+    /// Characters transliterated by rule 8, with their replacement
+    pub transliterated: Vec<(char, String)>,
+    /// The file system rejects the name and transliteration is off, so
+    /// saving it will fail
+    pub unstorable: bool,
+}
+
+impl NormalizedName {
+    /// Whether the name changed beyond its Unicode composition
+    pub fn was_renamed(&self) -> bool {
+        self.name.chars().ne(self.original.nfc())
+    }
+
+    /// Whether the saved name runs a program when opened
+    pub fn is_executable(&self) -> bool {
+        is_executable(&self.name)
+    }
+
+    /// User-facing warning to show before the file is accepted, or `None`
+    /// if the name looks like what it is
+    pub fn warning_notice(&self) -> Option<String> {
+        if self.warnings.is_empty() {
+            return None;
+        }
+        let warnings: Vec<String> = self.warnings.iter().map(ToString::to_string).collect();
+        let kind = if self.is_executable() {
+            "program"
+        } else {
+            "file"
+        };
+        Some(format!(
+            "The name of the {} '{}' {}; it may not be what it looks like",
+            kind,
+            self.name,
+            warnings.join(" and ")
+        ))
+    }
+
+    /// The rename and warning notices, for receivers to log
+    pub fn notices(&self) -> impl Iterator<Item = String> {
+        let unstorable = self.unstorable.then(|| {
+            format!(
+                "The download folder's file system cannot store the name '{}'; \
+                 turn on transliterate_names to save such files under an ASCII name",
+                self.name
+            )
+        });
+        self.rename_notice()
+            .into_iter()
+            .chain(self.warning_notice())
+            .chain(unstorable)
+    }
+
+    /// User-facing explanation, or `None` if the name was kept as is
+    pub fn rename_notice(&self) -> Option<String> {
+        if !self.was_renamed() {
+            return None;
+        }
+        let reasons: Vec<String> = self.reasons.iter().map(ToString::to_string).collect();
+        let mut notice = format!(
+            "Saving '{}' as '{}' ({}",
+            self.original,
+            self.name,
+            reasons.join(", ")
+        );
+        if !self.transliterated.is_empty() {
+            let mapping: Vec<String> = self
+                .transliterated
+                .iter()
+                .map(|(from, to)| format!("{} → {}", from, to))
+                .collect();
+            notice.push_str(": ");
+            notice.push_str(&mapping.join(", "));
+        }
+        notice.push(')');
+        Some(notice)
+    }
+}
+
+/// Normalize a received name for saving into `download_dir`.
+///
+/// Applies every rule above, including the Windows path length limit when
+/// running on Windows. Rule 8 creates and deletes an empty file in
+/// `download_dir` for names that are not plain ASCII.
+pub fn normalize_file_name(raw: &str, download_dir: &Path) -> NormalizedName {
+    let max_path = cfg!(windows).then_some(WINDOWS_MAX_PATH);
+    let normalized = normalize_with_limit(raw, download_dir, max_path);
+    if normalized.name.is_ascii() || can_store_name(download_dir, &normalized.name) {
+        return normalized;
+    }
+    fit_legacy_file_system(
+        normalized,
+        download_dir,
+        max_path,
+        TRANSLITERATE_NAMES.load(Ordering::Relaxed),
+    )
+}
+
+/// Whether the file system of `dir` takes `name`. Only a rejection of the
+/// name itself counts; a missing or read-only directory fails later with
+/// its own error.
+fn can_store_name(dir: &Path, name: &str) -> bool {
+    let probe = dir.join(format!("{}{}", PROBE_PREFIX, name));
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            true
+        }
+        Err(e) => !is_invalid_name(&e),
+    }
+}
+
+/// Whether `error` says a file name cannot be stored: `EINVAL` or `EILSEQ`
+/// from FAT and exFAT drivers, `ERROR_INVALID_NAME` on Windows
+fn is_invalid_name(error: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(libc::EILSEQ) {
+        return true;
+    }
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidFilename
+    )
+}
+
+/// Rule 8: transliterate `normalized` to ASCII, or mark it unstorable when
+/// `transliterate` is off
+fn fit_legacy_file_system(
+    mut normalized: NormalizedName,
+    download_dir: &Path,
+    max_path: Option<usize>,
+    transliterate: bool,
+) -> NormalizedName {
+    if !transliterate {
+        normalized.unstorable = true;
+        return normalized;
+    }
+    let (ascii, transliterated) = transliterate_name(&normalized.name);
+    let again = normalize_with_limit(&ascii, download_dir, max_path);
+    normalized.reasons.push(RenameReason::Transliterated);
+    for reason in again.reasons {
+        if !normalized.reasons.contains(&reason) {
+            normalized.reasons.push(reason);
+        }
+    }
+    NormalizedName {
+        name: again.name,
+        warnings: again.warnings,
+        transliterated,
+        ..normalized
+    }
+}
+
+/// `name` in ASCII, with each distinct character replaced and its
+/// replacement. Characters without a transliteration become `_uXXXX`.
+pub fn transliterate_name(name: &str) -> (String, Vec<(char, String)>) {
+    let mut ascii = String::with_capacity(name.len());
+    let mut replaced: Vec<(char, String)> = Vec::new();
+    // Syllables of one word come with a trailing space: 東京 is "Dong Jing"
+    let mut word_break = false;
+    for c in name.chars() {
+        if c.is_ascii() {
+            if word_break && c.is_ascii_alphanumeric() {
+                ascii.push(' ');
+            }
+            ascii.push(c);
+            word_break = false;
+            continue;
+        }
+        let replacement = match deunicode::deunicode_char(c) {
+            Some(text) if !text.trim().is_empty() => text.to_string(),
+            _ => format!("_u{:04X}", c as u32),
+        };
+        if !replaced.iter().any(|(from, _)| *from == c) {
+            replaced.push((c, replacement.trim().to_string()));
+        }
+        let trimmed = replacement.trim_end();
+        if word_break && trimmed.starts_with(|first: char| first.is_ascii_alphanumeric()) {
+            ascii.push(' ');
+        }
+        ascii.push_str(trimmed);
+        word_break = trimmed.len() < replacement.len();
+    }
+    (ascii, replaced)
+}
+
+/// Normalize a name without considering the destination directory
+pub fn sanitize_file_name(raw: &str) -> String {
+    normalize_with_limit(raw, Path::new(""), None).name
+}
+
+/// Subfolder of `download_dir` for files from one sender, when received
+/// files are sorted by peer.
+///
+/// The folder is named after the sender's display name plus the start of
+/// its ID, so two devices called "Laptop" stay apart and a peer cannot
+/// write into another's folder by copying its name. The name follows the
+/// file name rules; separators in the display name become `_`.
+pub fn peer_folder(download_dir: &Path, peer_name: &str, peer_id: &str) -> PathBuf {
+    let peer_name = peer_name.trim().replace(['/', '\\'], "_");
+    let short_id: String = peer_id.chars().take(PEER_ID_CHARS).collect();
+    let label = match (peer_name.is_empty(), short_id.is_empty()) {
+        (false, false) => format!("{}-{}", peer_name, short_id),
+        (false, true) => peer_name,
+        (true, false) => short_id,
+        (true, true) => "unknown".to_string(),
+    };
+    download_dir.join(sanitize_file_name(&label))
+}
+
+/// Whether `name` has an extension the receiving system runs when opened
+pub fn is_executable(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(_, extension)| {
+        EXECUTABLE_EXTENSIONS
+            .iter()
+            .any(|executable| extension.eq_ignore_ascii_case(executable))
+    })
+}
+
+fn normalize_with_limit(raw: &str, download_dir: &Path, max_path: Option<usize>) -> NormalizedName {
+    let mut reasons = Vec::new();
+
+    // 1. Last component only, whichever separator the sender used
+    let composed: String = raw.nfc().collect();
+    let last = composed.rsplit(['/', '\\']).next().unwrap_or(&composed);
+    if last.len() != composed.len() {
+        reasons.push(RenameReason::DirectoriesRemoved);
+    }
+
+    // 2. Drop control characters, direction controls and characters Windows
+    // cannot store
+    let visible: String = last
+        .chars()
+        .filter(|c| !BIDI_CONTROLS.contains(c))
+        .collect();
+    if visible.len() != last.len() {
+        reasons.push(RenameReason::DirectionControls);
+    }
+    let filtered: String = visible
+        .chars()
+        .filter(|c| !c.is_control() && !INVALID_CHARS.contains(*c))
+        .collect();
+    if filtered.len() != visible.len() {
+        reasons.push(RenameReason::InvalidCharacters);
+    }
+
+    // 3. Windows strips trailing dots and spaces on create
+    let trimmed = trim_name(&filtered);
+    if trimmed.len() != filtered.len() {
+        reasons.push(RenameReason::TrailingDotsOrSpaces);
+    }
+
+    if trimmed.is_empty() {
+        reasons.push(RenameReason::Empty);
+        return NormalizedName {
+            name: FALLBACK_FILE_NAME.to_string(),
+            original: raw.to_string(),
+            reasons,
+            warnings: Vec::new(),
+            transliterated: Vec::new(),
+            unstorable: false,
+        };
+    }
+
+    // 4. Reserved device names
+    let mut name = trimmed.to_string();
+    if is_reserved(&name) {
+        reasons.push(RenameReason::ReservedName);
+        name.insert(0, '_');
+    }
+
+    // 5. Per-component limit (bytes; never more than UTF-16 units)
+    if name.len() > MAX_FILENAME_LENGTH {
+        reasons.push(RenameReason::NameTooLong);
+        name = shorten(&name, |candidate| candidate.len() <= MAX_FILENAME_LENGTH);
+    }
+
+    // 6. Whole-path limit, counted in UTF-16 units like Windows does
+    if let Some(max_path) = max_path {
+        let dir_units = download_dir.to_string_lossy().encode_utf16().count();
+        let budget = max_path.saturating_sub(dir_units + 1);
+        if name.encode_utf16().count() > budget {
+            reasons.push(RenameReason::PathTooLong);
+            name = shorten(&name, |candidate| {
+                candidate.encode_utf16().count() <= budget
+            });
+        }
+    }
+
+    NormalizedName {
+        warnings: name_warnings(&name),
+        name,
+        original: raw.to_string(),
+        reasons,
+        transliterated: Vec::new(),
+        unstorable: false,
+    }
+}
+
+/// What about `name` could make it look like a different name
+fn name_warnings(name: &str) -> Vec<NameWarning> {
+    let mut warnings = Vec::new();
+    let mixed = name
+        .split(|c: char| !c.is_alphanumeric() && !INVISIBLE_CHARS.contains(&c))
+        .any(|word| {
+            let scripts: HashSet<Script> = word
+                .chars()
+                .map(|c| c.script())
+                .filter(|script| {
+                    !matches!(script, Script::Common | Script::Inherited | Script::Unknown)
+                })
+                .collect();
+            scripts.len() > 1
+                && !SCRIPT_MIXES
+                    .iter()
+                    .any(|mix| scripts.iter().all(|script| mix.contains(script)))
+        });
+    if mixed {
+        warnings.push(NameWarning::MixedScripts);
+    }
+    if name.chars().any(|c| INVISIBLE_CHARS.contains(&c)) {
+        warnings.push(NameWarning::InvisibleCharacters);
+    }
+    warnings
+}
+
+fn trim_name(name: &str) -> &str {
+    name.trim_start()
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+}
+
+/// Windows reserves the device name for everything before the first dot
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or("").trim_end();
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// Drop characters from the end of the stem until `fits` accepts the name.
+///
+/// The extension is kept when it is short enough to be meaningful. The result
+/// is never empty, reserved, or ending in a dot or space.
+fn shorten(name: &str, fits: impl Fn(&str) -> bool) -> String {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, ext))
+            if !stem.is_empty()
+                && !ext.is_empty()
+                && ext.chars().count() <= MAX_EXTENSION_CHARS =>
+        {
+            (stem, format!(".{}", ext))
+        }
+        _ => (name, String::new()),
+    };
+
+    let acceptable = |candidate: &str| {
+        !candidate.is_empty()
+            && trim_name(candidate) == candidate
+            && !is_reserved(candidate)
+            && fits(candidate)
+    };
+
+    let mut stem_chars: Vec<char> = stem.chars().collect();
+    while !stem_chars.is_empty() {
+        let candidate = format!("{}{}", stem_chars.iter().collect::<String>(), extension);
+        if acceptable(&candidate) {
+            return candidate;
+        }
+        stem_chars.pop();
+    }
+
+    // Not even the extension fits: cut the whole name instead
+    let mut chars: Vec<char> = name.chars().collect();
+    while !chars.is_empty() {
+        let candidate: String = chars.iter().collect();
+        if acceptable(&candidate) {
+            return candidate;
+        }
+        chars.pop();
+    }
+    FALLBACK_FILE_NAME.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_folder() {
+        let dir = Path::new("downloads");
+        assert_eq!(
+            peer_folder(dir, "Laptop", "1a2b3c4d5e6f"),
+            dir.join("Laptop-1a2b3c4d")
+        );
+        assert_eq!(
+            peer_folder(dir, "../../etc", "abc"),
+            dir.join(".._.._etc-abc")
+        );
+        assert_eq!(peer_folder(dir, "CON", ""), dir.join("_CON"));
+        assert_eq!(peer_folder(dir, " ", "abc"), dir.join("abc"));
+        assert_eq!(peer_folder(dir, "", ""), dir.join("unknown"));
+    }
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("normal_file.txt"), "normal_file.txt");
+        assert_eq!(sanitize_file_name("path/to/file.txt"), "file.txt");
+        assert_eq!(sanitize_file_name("/absolute/path/to/file.txt"), "file.txt");
+        // Cross-platform separators
+        assert_eq!(
+            sanitize_file_name("path\\to\\windows\\file.txt"),
+            "file.txt"
+        );
+        assert_eq!(sanitize_file_name("mixed/path\\to/file.txt"), "file.txt");
+
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name(".."), "unknown_file.bin");
+        assert_eq!(sanitize_file_name("."), "unknown_file.bin");
+        assert_eq!(sanitize_file_name(""), "unknown_file.bin");
+        assert_eq!(sanitize_file_name("   "), "unknown_file.bin");
+        assert_eq!(sanitize_file_name("foo/../bar.txt"), "bar.txt");
+
+        // Control characters
+        assert_eq!(sanitize_file_name("file\nname.txt"), "filename.txt");
+        assert_eq!(sanitize_file_name("file\0name.txt"), "filename.txt");
+
+        // Reserved names
+        assert_eq!(sanitize_file_name("CON"), "_CON");
+        assert_eq!(sanitize_file_name("con.txt"), "_con.txt");
+        assert_eq!(sanitize_file_name("LPT1"), "_LPT1");
+        assert_eq!(sanitize_file_name("aux"), "_aux");
+
+        // Unicode
+        assert_eq!(sanitize_file_name("文件.txt"), "文件.txt");
+    }
+
+    #[test]
+    fn test_sanitize_file_name_windows_chars() {
+        // These characters are invalid in Windows filenames
+        let invalid_chars = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+        for c in invalid_chars {
+            let name = format!("file{}name.txt", c);
+            let sanitized = sanitize_file_name(&name);
+
+            // The sanitized name should NOT contain the invalid character
+            // (except / and \ which are path separators and already handled by rsplit,
+            // but sanitize_file_name handles them again in the filter just in case)
+            assert!(
+                !sanitized.contains(c),
+                "Character '{}' was not filtered out from '{}'",
+                c,
+                sanitized
+            );
+        }
+    }
+
+    #[test]
+    fn test_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_file_name("report.txt. . "), "report.txt");
+        assert_eq!(sanitize_file_name("CON .txt"), "_CON .txt");
+        assert_eq!(sanitize_file_name("..."), "unknown_file.bin");
+        assert_eq!(sanitize_file_name(".bashrc"), ".bashrc");
+    }
+
+    #[test]
+    fn test_long_names_keep_extension() {
+        let long = format!("{}.tar.gz", "a".repeat(300));
+        let normalized = normalize_with_limit(&long, Path::new(""), None);
+        assert_eq!(normalized.name.len(), MAX_FILENAME_LENGTH);
+        assert!(normalized.name.ends_with(".gz"));
+        assert_eq!(normalized.reasons, [RenameReason::NameTooLong]);
+
+        // Multi-byte characters are never split
+        let wide = "文".repeat(120);
+        let name = sanitize_file_name(&wide);
+        assert!(name.len() <= MAX_FILENAME_LENGTH);
+        assert!(name.chars().all(|c| c == '文'));
+    }
+
+    #[test]
+    fn test_path_limit() {
+        let dir = Path::new("C:\\Users\\someone\\Downloads");
+        let name = format!("{}.txt", "b".repeat(250));
+        let normalized = normalize_with_limit(&name, dir, Some(WINDOWS_MAX_PATH));
+
+        assert!(normalized.reasons.contains(&RenameReason::PathTooLong));
+        assert!(normalized.name.ends_with(".txt"));
+        assert_eq!(
+            dir.to_string_lossy().len() + 1 + normalized.name.len(),
+            WINDOWS_MAX_PATH
+        );
+    }
+
+    #[test]
+    fn test_rename_notice() {
+        let dir = Path::new("downloads");
+        assert_eq!(normalize_file_name("plain.txt", dir).rename_notice(), None);
+
+        let notice = normalize_file_name("CON.txt", dir).rename_notice().unwrap();
+        assert_eq!(
+            notice,
+            "Saving 'CON.txt' as '_CON.txt' (reserved Windows device name)"
+        );
+    }
+
+    #[test]
+    fn test_unicode_composition_and_direction_controls() {
+        let dir = Path::new("downloads");
+        // Decomposed as macOS sends it
+        let decomposed = normalize_file_name("cafe\u{301}.txt", dir);
+        assert_eq!(decomposed.name, "caf\u{e9}.txt");
+        assert_eq!(decomposed.rename_notice(), None);
+
+        let reversed = normalize_file_name("invoice\u{202E}fdp.exe", dir);
+        assert_eq!(reversed.name, "invoicefdp.exe");
+        assert_eq!(reversed.reasons, [RenameReason::DirectionControls]);
+        assert!(reversed.is_executable());
+    }
+
+    #[test]
+    fn test_lookalike_names_are_warned_about() {
+        let warnings = |name: &str| normalize_file_name(name, Path::new("")).warnings;
+        // Cyrillic а in a Latin word
+        assert_eq!(warnings("p\u{430}ypal.exe"), [NameWarning::MixedScripts]);
+        assert_eq!(
+            warnings("report\u{200B}.pdf.exe"),
+            [NameWarning::InvisibleCharacters]
+        );
+        assert!(warnings("東京タワー photo.jpg").is_empty());
+        assert!(warnings("한국어 文書.hwp").is_empty());
+        assert!(warnings("Отчёт report 2024.pdf").is_empty());
+        assert!(warnings("plain.txt").is_empty());
+
+        let notice = normalize_file_name("p\u{430}ypal.exe", Path::new(""))
+            .warning_notice()
+            .unwrap();
+        assert!(notice.starts_with("The name of the program"));
+        assert_eq!(
+            normalize_file_name("plain.txt", Path::new("")).warning_notice(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_names_a_legacy_file_system_rejects_are_transliterated() {
+        let (ascii, mapping) = transliterate_name("Müller Ärger.txt");
+        assert_eq!(ascii, "Muller Arger.txt");
+        assert_eq!(mapping, [('ü', "u".to_string()), ('Ä', "A".to_string())]);
+        assert_eq!(transliterate_name("東京.jpg").0, "Dong Jing.jpg");
+        assert_eq!(transliterate_name("Отчёт 2024.pdf").0, "Otchiot 2024.pdf");
+        assert_eq!(transliterate_name("\u{E000}.bin").0, "_uE000.bin");
+
+        let dir = Path::new("E:\\");
+        let rejected = || normalize_with_limit("Straße.txt", dir, None);
+        let fitted = fit_legacy_file_system(rejected(), dir, None, true);
+        assert_eq!(fitted.name, "Strasse.txt");
+        assert_eq!(fitted.reasons, [RenameReason::Transliterated]);
+        assert_eq!(
+            fitted.rename_notice().unwrap(),
+            "Saving 'Straße.txt' as 'Strasse.txt' \
+             (characters the file system cannot store replaced: ß → ss)"
+        );
+
+        let kept = fit_legacy_file_system(rejected(), dir, None, false);
+        assert_eq!(kept.name, "Straße.txt");
+        assert!(
+            kept.notices()
+                .any(|notice| notice.contains("transliterate_names"))
+        );
+
+        // Names the directory takes are left alone
+        let downloads = std::env::temp_dir();
+        assert_eq!(
+            normalize_file_name("Straße.txt", &downloads).name,
+            "Straße.txt"
+        );
+    }
+}
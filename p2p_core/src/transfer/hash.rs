@@ -1,31 +1,389 @@
-use anyhow::Result;
+//! Whole-file checksums for integrity checks.
+//!
+//! The sender picks a [`HashAlgorithm`] (the process-wide default, see
+//! [`set_hash_algorithm`]) and names it in the file's metadata next to the
+//! hash; the receiver checks with the same one. BLAKE3 is the default and
+//! hashes on several threads, XXH3 is faster but only catches accidental
+//! corruption, and SHA-256 is there where a standard algorithm is required.
+//! A receiver that does not know the named algorithm keeps the file
+//! unchecked. Builds from before the choice existed only know BLAKE3 and
+//! report other hashes as failed.
+
+use crate::AppEvent;
+use crate::storage::Storage;
+use anyhow::{Result, anyhow};
 use blake3::Hasher;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::io::Read;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+/// Checksum used to verify a transferred file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    /// XXH3, 128 bits; not cryptographic
+    Xxh3,
+    Sha256,
+    /// Named by a newer peer; cannot be checked here
+    #[serde(other)]
+    Unknown,
+}
+
+impl HashAlgorithm {
+    /// Algorithms that can be picked
+    pub const ALL: [HashAlgorithm; 3] = [Self::Blake3, Self::Xxh3, Self::Sha256];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Blake3 => "BLAKE3",
+            Self::Xxh3 => "xxHash (XXH3)",
+            Self::Sha256 => "SHA-256",
+            Self::Unknown => "Unknown",
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::Blake3
+    }
+
+    /// Start hashing with this algorithm
+    pub fn hasher(self) -> Result<Box<dyn FileHasher>> {
+        Ok(match self {
+            Self::Blake3 => Box::new(Blake3Hasher {
+                hasher: Hasher::new(),
+                pool: hash_pool()?,
+            }),
+            Self::Xxh3 => Box::new(Xxh3Hasher(xxhash_rust::xxh3::Xxh3::new())),
+            Self::Sha256 => Box::new(Sha256Hasher(sha2::Sha256::new())),
+            Self::Unknown => return Err(anyhow!("Unsupported hash algorithm")),
+        })
+    }
+}
+
+/// Incremental hash of one file
+pub trait FileHasher: Send {
+    fn update(&mut self, data: &[u8]);
+    /// Lowercase hex digest
+    fn finalize(self: Box<Self>) -> String;
+}
+
+struct Blake3Hasher {
+    hasher: Hasher,
+    pool: Arc<rayon::ThreadPool>,
+}
+
+impl FileHasher for Blake3Hasher {
+    fn update(&mut self, data: &[u8]) {
+        let hasher = &mut self.hasher;
+        self.pool.install(|| hasher.update_rayon(data));
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        self.hasher.finalize().to_hex().to_string()
+    }
+}
+
+struct Xxh3Hasher(xxhash_rust::xxh3::Xxh3);
+
+impl FileHasher for Xxh3Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        format!("{:032x}", self.0.digest128())
+    }
+}
+
+struct Sha256Hasher(sha2::Sha256);
+
+impl FileHasher for Sha256Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Algorithm used for files sent from now on
+static DEFAULT_ALGORITHM: AtomicU8 = AtomicU8::new(0);
+
+pub fn hash_algorithm() -> HashAlgorithm {
+    match DEFAULT_ALGORITHM.load(Ordering::Relaxed) {
+        1 => HashAlgorithm::Xxh3,
+        2 => HashAlgorithm::Sha256,
+        _ => HashAlgorithm::Blake3,
+    }
+}
+
+/// Hash files sent from now on with `algorithm`; [`HashAlgorithm::Unknown`]
+/// falls back to BLAKE3
+pub fn set_hash_algorithm(algorithm: HashAlgorithm) {
+    let value = match algorithm {
+        HashAlgorithm::Xxh3 => 1,
+        HashAlgorithm::Sha256 => 2,
+        HashAlgorithm::Blake3 | HashAlgorithm::Unknown => 0,
+    };
+    DEFAULT_ALGORITHM.store(value, Ordering::Relaxed);
+}
+
+/// Bytes read per step when hashing a whole file. Large enough for blake3 to
+/// spread each step over all hashing threads.
+const HASH_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Most threads used to hash one file (0 = one per core)
+static HASH_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Pool built for the current thread cap
+static HASH_POOL: Mutex<Option<(usize, Arc<rayon::ThreadPool>)>> = Mutex::new(None);
+
+/// Cap the threads used to hash a file (`None` = one per core)
+pub fn set_hash_threads(threads: Option<usize>) {
+    HASH_THREADS.store(threads.unwrap_or(0), Ordering::Relaxed);
+}
+
+fn hash_pool() -> Result<Arc<rayon::ThreadPool>> {
+    let threads = HASH_THREADS.load(Ordering::Relaxed);
+    let mut pool = HASH_POOL.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((built_for, pool)) = pool.as_ref()
+        && *built_for == threads
+    {
+        return Ok(pool.clone());
+    }
+    let built = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("blake3-{}", i))
+            .build()?,
+    );
+    *pool = Some((threads, built.clone()));
+    Ok(built)
+}
 
 /// Compute BLAKE3 hash of a file using parallelism
 pub async fn compute_file_hash(file_path: &std::path::Path) -> Result<String> {
+    compute_file_hash_with_progress(file_path, HashAlgorithm::Blake3, |_, _| {}).await
+}
+
+/// Hash a file with `algorithm`, calling `on_progress(hashed, total)` after
+/// every chunk
+pub async fn compute_file_hash_with_progress(
+    file_path: &std::path::Path,
+    algorithm: HashAlgorithm,
+    mut on_progress: impl FnMut(u64, u64) + Send + 'static,
+) -> Result<String> {
     let path = file_path.to_path_buf();
 
     tokio::task::spawn_blocking(move || {
-        let file = std::fs::File::open(&path)?;
-        let metadata = file.metadata()?;
-        let len = metadata.len();
+        let mut file = std::fs::File::open(&path)?;
+        let len = file.metadata()?.len();
+        let mut hasher = algorithm.hasher()?;
+        let mut buffer = vec![0u8; HASH_CHUNK_SIZE.min(len.max(1) as usize)];
+        let mut hashed = 0u64;
+        loop {
+            let n = read_full(&mut file, &mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            {
+                crate::profile_scope!("hash");
+                hasher.update(&buffer[..n]);
+            }
+            hashed += n as u64;
+            on_progress(hashed, len);
+        }
 
-        let mut hasher = Hasher::new();
+        Ok(hasher.finalize())
+    })
+    .await?
+}
 
-        if len > 0 {
-            use std::io::Read;
-            let mut buffer = vec![0u8; 65536]; // 64KB buffer
-            let mut reader = std::io::BufReader::new(file);
-            loop {
-                let n = reader.read(&mut buffer)?;
-                if n == 0 {
-                    break;
-                }
-                hasher.update(&buffer[..n]);
+/// [`compute_file_hash_with_progress`] of what `storage` holds at
+/// `file_path`, read back through the storage unless it is the local disk
+pub async fn compute_stored_hash(
+    storage: &dyn Storage,
+    file_path: &std::path::Path,
+    algorithm: HashAlgorithm,
+    mut on_progress: impl FnMut(u64, u64) + Send + 'static,
+) -> Result<String> {
+    if storage.is_local() {
+        return compute_file_hash_with_progress(file_path, algorithm, on_progress).await;
+    }
+    let len = storage.stat(file_path).await?.len;
+    let mut reader = storage.open(file_path).await?;
+    let mut hasher = algorithm.hasher()?;
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE.min(len.max(1) as usize)];
+    let mut hashed = 0u64;
+    loop {
+        let n = reader.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        hashed += n as u64;
+        on_progress(hashed, len);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Fill `buffer` unless the file ends first; returns the bytes read
+fn read_full(file: &mut std::fs::File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Progress callback for [`compute_file_hash_with_progress`] that reports
+/// [`AppEvent::VerificationProgress`], once per whole percent
+pub fn verification_progress(
+    event_tx: mpsc::Sender<AppEvent>,
+    file_name: String,
+    is_sending: bool,
+) -> impl FnMut(u64, u64) + Send + 'static {
+    let mut last_percent = None;
+    move |hashed, total| {
+        let percent = (hashed * 100).checked_div(total).unwrap_or(100);
+        if last_percent == Some(percent) {
+            return;
+        }
+        last_percent = Some(percent);
+        // Progress is telemetry; a full channel only costs a step
+        let _ = event_tx.try_send(AppEvent::VerificationProgress {
+            file_name: file_name.clone(),
+            is_sending,
+            progress: percent as f32,
+        });
+    }
+}
+
+/// Compute the BLAKE3 hash of the first `len` bytes of a file
+pub async fn compute_prefix_hash(file_path: &std::path::Path, len: u64) -> Result<String> {
+    let path = file_path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&path)?;
+        let mut reader = std::io::BufReader::new(file).take(len);
+        let mut hasher = Hasher::new();
+        let mut buffer = vec![0u8; 65536];
+        let mut read = 0u64;
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
             }
+            hasher.update(&buffer[..n]);
+            read += n as u64;
+        }
+        if read < len {
+            return Err(anyhow::anyhow!(
+                "File is shorter than {} bytes ({} read)",
+                len,
+                read
+            ));
         }
 
         Ok(hasher.finalize().to_hex().to_string())
     })
     .await?
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parallel_hash_matches_sequential() {
+        let dir = std::env::temp_dir().join(format!("p2p_hash_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.bin");
+        let data: Vec<u8> = (0..HASH_CHUNK_SIZE * 2 + 12345)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let hash =
+            compute_file_hash_with_progress(&path, HashAlgorithm::Blake3, move |hashed, total| {
+                seen.lock().unwrap().push((hashed, total));
+            })
+            .await
+            .unwrap();
+        assert_eq!(hash, blake3::hash(&data).to_hex().to_string());
+
+        let reports = reports.lock().unwrap().clone();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(
+            reports.last(),
+            Some(&(data.len() as u64, data.len() as u64))
+        );
+
+        let empty = dir.join("empty.bin");
+        std::fs::write(&empty, b"").unwrap();
+        assert_eq!(
+            compute_file_hash(&empty).await.unwrap(),
+            blake3::hash(b"").to_hex().to_string()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_each_algorithm_matches_its_reference() {
+        let dir = std::env::temp_dir().join(format!("p2p_hash_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("abc.txt");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let hash = |algorithm| {
+            let path = path.clone();
+            async move {
+                compute_file_hash_with_progress(&path, algorithm, |_, _| {})
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(
+            hash(HashAlgorithm::Sha256).await,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash(HashAlgorithm::Xxh3).await,
+            format!("{:032x}", xxhash_rust::xxh3::xxh3_128(b"abc"))
+        );
+        assert_eq!(
+            hash(HashAlgorithm::Blake3).await,
+            blake3::hash(b"abc").to_hex().to_string()
+        );
+        assert!(
+            compute_file_hash_with_progress(&path, HashAlgorithm::Unknown, |_, _| {})
+                .await
+                .is_err()
+        );
+
+        let parsed: HashAlgorithm = serde_json::from_str("\"md5\"").unwrap();
+        assert_eq!(parsed, HashAlgorithm::Unknown);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+//! Limits on what one peer may send to this device.
+//!
+//! Without them a peer could open stream after stream, or send file after
+//! file, until the receiver's disk is full. The transfer server refuses,
+//! with a `VerificationFailed` message the sender shows:
+//! - streams beyond [`ReceiveLimits::max_streams_per_connection`] open at
+//!   once on one connection,
+//! - files beyond [`ReceiveLimits::max_files_per_peer`] received at once
+//!   from one peer, over all its connections,
+//! - files that would take a peer past [`ReceiveLimits::daily_quota_bytes`]
+//!   in the current UTC day. Streams, whose size is unknown up front, are
+//!   charged as they arrive and cancelled once they go over.
+//!
+//! Peers are told apart by endpoint ID. Like the pairing lockout, the counts
+//! last as long as the server.
+
+use crate::units::format_size;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_MAX_STREAMS_PER_CONNECTION: u32 = 16;
+pub const DEFAULT_MAX_FILES_PER_PEER: u32 = 8;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How much peers may send at once and per day, stored in `config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiveLimits {
+    /// Streams open at once on one connection (0 = no limit)
+    #[serde(default = "default_max_streams_per_connection")]
+    pub max_streams_per_connection: u32,
+    /// Files received at once from one peer (0 = no limit)
+    #[serde(default = "default_max_files_per_peer")]
+    pub max_files_per_peer: u32,
+    /// Bytes accepted from one peer per UTC day (`None` = no quota)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_quota_bytes: Option<u64>,
+}
+
+fn default_max_streams_per_connection() -> u32 {
+    DEFAULT_MAX_STREAMS_PER_CONNECTION
+}
+
+fn default_max_files_per_peer() -> u32 {
+    DEFAULT_MAX_FILES_PER_PEER
+}
+
+impl Default for ReceiveLimits {
+    fn default() -> Self {
+        Self {
+            max_streams_per_connection: DEFAULT_MAX_STREAMS_PER_CONNECTION,
+            max_files_per_peer: DEFAULT_MAX_FILES_PER_PEER,
+            daily_quota_bytes: None,
+        }
+    }
+}
+
+/// Days since the Unix epoch, the period of the daily quota
+pub fn utc_day(now: SystemTime) -> u64 {
+    now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / DAY.as_secs()
+}
+
+#[derive(Debug, Default)]
+struct PeerUsage {
+    receiving: u32,
+    day: u64,
+    /// Bytes of completed and running files on `day`
+    bytes: u64,
+}
+
+/// Enforces [`ReceiveLimits`] for one transfer server
+#[derive(Debug, Default)]
+pub struct ReceiveGuard {
+    limits: ReceiveLimits,
+    peers: Arc<Mutex<HashMap<String, PeerUsage>>>,
+}
+
+impl ReceiveGuard {
+    pub fn new(limits: ReceiveLimits) -> Self {
+        Self {
+            limits,
+            peers: Arc::default(),
+        }
+    }
+
+    pub fn limits(&self) -> &ReceiveLimits {
+        &self.limits
+    }
+
+    /// Count one more stream on a connection with `open` streams
+    pub fn open_stream(&self, open: &Arc<AtomicU32>) -> Result<StreamSlot> {
+        let max = self.limits.max_streams_per_connection;
+        open.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            (max == 0 || n < max).then_some(n + 1)
+        })
+        .map_err(|_| anyhow!("Too many streams at once; the receiver allows {}", max))?;
+        Ok(StreamSlot(open.clone()))
+    }
+
+    /// Count a file of `size` bytes from `peer` on `day`. Its bytes are
+    /// given back unless [`FileSlot::complete`] is called.
+    pub fn begin_file(&self, peer: &str, size: u64, day: u64) -> Result<FileSlot> {
+        let mut peers = self.peers.lock().unwrap();
+        let usage = peers.entry(peer.to_string()).or_default();
+        if usage.day != day {
+            usage.day = day;
+            usage.bytes = 0;
+        }
+
+        let max = self.limits.max_files_per_peer;
+        if max != 0 && usage.receiving >= max {
+            return Err(anyhow!(
+                "The receiver takes at most {} files at once from one device; try again when one finishes",
+                max
+            ));
+        }
+        if let Some(quota) = self.limits.daily_quota_bytes
+            && usage.bytes.saturating_add(size) > quota
+        {
+            return Err(quota_reached(quota, usage.bytes));
+        }
+
+        usage.receiving += 1;
+        usage.bytes += size;
+        Ok(FileSlot {
+            peers: self.peers.clone(),
+            peer: peer.to_string(),
+            day,
+            size,
+            quota: self.limits.daily_quota_bytes,
+            completed: false,
+        })
+    }
+}
+
+fn quota_reached(quota: u64, used: u64) -> anyhow::Error {
+    anyhow!(
+        "Daily quota of {} from this device reached ({} used today); try again after midnight UTC",
+        format_size(quota),
+        format_size(used)
+    )
+}
+
+/// One open stream of a connection, until dropped
+pub struct StreamSlot(Arc<AtomicU32>);
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// One file being received from a peer, until dropped
+pub struct FileSlot {
+    peers: Arc<Mutex<HashMap<String, PeerUsage>>>,
+    peer: String,
+    day: u64,
+    size: u64,
+    quota: Option<u64>,
+    completed: bool,
+}
+
+impl FileSlot {
+    /// Count `bytes` more of a file of unknown size, failing once they take
+    /// the peer past the daily quota
+    pub fn charge(&mut self, bytes: u64) -> Result<()> {
+        let mut peers = self.peers.lock().unwrap();
+        let usage = peers.entry(self.peer.clone()).or_default();
+        if usage.day != self.day {
+            return Ok(());
+        }
+        if let Some(quota) = self.quota
+            && usage.bytes.saturating_add(bytes) > quota
+        {
+            return Err(quota_reached(quota, usage.bytes));
+        }
+        usage.bytes += bytes;
+        self.size += bytes;
+        Ok(())
+    }
+
+    /// Keep the file's bytes counted against the quota
+    pub fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for FileSlot {
+    fn drop(&mut self) {
+        let mut peers = self.peers.lock().unwrap();
+        let Some(usage) = peers.get_mut(&self.peer) else {
+            return;
+        };
+        usage.receiving = usage.receiving.saturating_sub(1);
+        if !self.completed && usage.day == self.day {
+            usage.bytes = usage.bytes.saturating_sub(self.size);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streams_per_connection() {
+        let guard = ReceiveGuard::new(ReceiveLimits {
+            max_streams_per_connection: 2,
+            ..ReceiveLimits::default()
+        });
+        let open = Arc::new(AtomicU32::new(0));
+        let first = guard.open_stream(&open).unwrap();
+        let _second = guard.open_stream(&open).unwrap();
+        assert!(guard.open_stream(&open).is_err());
+        drop(first);
+        assert!(guard.open_stream(&open).is_ok());
+    }
+
+    #[test]
+    fn test_files_per_peer() {
+        let guard = ReceiveGuard::new(ReceiveLimits {
+            max_files_per_peer: 1,
+            ..ReceiveLimits::default()
+        });
+        let slot = guard.begin_file("a", 10, 1).unwrap();
+        assert!(guard.begin_file("a", 10, 1).is_err());
+        assert!(guard.begin_file("b", 10, 1).is_ok());
+        slot.complete();
+        assert!(guard.begin_file("a", 10, 1).is_ok());
+    }
+
+    #[test]
+    fn test_daily_quota() {
+        let guard = ReceiveGuard::new(ReceiveLimits {
+            daily_quota_bytes: Some(100),
+            ..ReceiveLimits::default()
+        });
+        guard.begin_file("a", 60, 1).unwrap().complete();
+        let err = guard.begin_file("a", 60, 1).err().unwrap();
+        assert!(err.to_string().contains("Daily quota"));
+
+        // A failed file gives its bytes back
+        drop(guard.begin_file("a", 40, 1).unwrap());
+        guard.begin_file("a", 40, 1).unwrap().complete();
+        assert!(guard.begin_file("a", 1, 1).is_err());
+
+        // Other peers and the next day start over
+        assert!(guard.begin_file("b", 100, 1).is_ok());
+        assert!(guard.begin_file("a", 100, 2).is_ok());
+    }
+
+    #[test]
+    fn test_streams_are_charged_as_they_arrive() {
+        let guard = ReceiveGuard::new(ReceiveLimits {
+            daily_quota_bytes: Some(100),
+            ..ReceiveLimits::default()
+        });
+        let mut stream = guard.begin_file("a", 0, 1).unwrap();
+        stream.charge(60).unwrap();
+        assert!(guard.begin_file("a", 60, 1).is_err());
+        assert!(stream.charge(60).is_err());
+
+        // A cancelled stream gives back what it was charged
+        drop(stream);
+        guard.begin_file("a", 100, 1).unwrap().complete();
+    }
+}
@@ -0,0 +1,180 @@
+//! File timestamps, permission bits and batch notes carried alongside
+//! [`FileInfo`].
+//!
+//! The sender fills them in from the source file; the receiver restores them
+//! (and any [extended attributes](super::xattrs)) only once the content has
+//! been verified, and only when metadata preservation is enabled. A note ("invoices Q3") labels a whole batch; it
+//! is shown before the receiver accepts and kept in its history.
+
+use super::xattrs::write_extended_attributes;
+use crate::FileInfo;
+use std::fs::Metadata;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Whether senders may mark received files executable
+static PRESERVE_EXECUTABLE: AtomicBool = AtomicBool::new(false);
+
+/// Let senders mark received files executable (or with `false`, never)
+pub fn set_preserve_executable(enabled: bool) {
+    PRESERVE_EXECUTABLE.store(enabled, Ordering::Relaxed);
+}
+
+/// Longest note kept, in characters
+pub const MAX_NOTE_CHARS: usize = 120;
+
+/// `note` on one line, without control characters and at most
+/// [`MAX_NOTE_CHARS`] long; `None` when nothing is left
+pub fn clean_note(note: &str) -> Option<String> {
+    let words: Vec<&str> = note.split_whitespace().collect();
+    let cleaned: String = words
+        .join(" ")
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NOTE_CHARS)
+        .collect();
+    let cleaned = cleaned.trim_end();
+    (!cleaned.is_empty()).then(|| cleaned.to_string())
+}
+
+impl FileInfo {
+    /// Attach the modification time and (on Unix) permission bits of the
+    /// source file
+    pub fn with_source_metadata(mut self, metadata: &Metadata) -> Self {
+        self.modified = modified_millis(metadata);
+        self.mode = permission_mode(metadata);
+        self
+    }
+}
+
+/// Modification time in milliseconds since the Unix epoch
+pub fn modified_millis(metadata: &Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(since_epoch.as_millis()).ok()
+}
+
+/// Permission bits (`0o777` mask), or `None` where they don't apply
+#[cfg(unix)]
+pub fn permission_mode(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o777)
+}
+
+/// Permission bits (`0o777` mask), or `None` where they don't apply
+#[cfg(not(unix))]
+pub fn permission_mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+/// Permission bits a received file gets for the sender's `mode`.
+///
+/// Received files are only readable and writable by the owner, whatever the
+/// sender says: no access for other users, no setuid/setgid/sticky. Only
+/// with `executable` does the owner's execute bit come from the wire.
+pub fn received_mode(mode: u32, executable: bool) -> u32 {
+    let exec = if executable { mode & 0o100 } else { 0 };
+    0o600 | exec
+}
+
+/// Restore the metadata in `info` onto a received file, with permission
+/// bits limited by [`received_mode`] and the execute bit only taken when
+/// [`set_preserve_executable`] allows it
+pub async fn apply_file_metadata(path: &Path, info: &FileInfo) -> std::io::Result<()> {
+    let path = path.to_path_buf();
+    let modified = info.modified;
+    let mode = info.mode;
+    let xattrs = info.xattrs.clone();
+
+    tokio::task::spawn_blocking(move || {
+        if let Some(millis) = modified {
+            let file = std::fs::File::options().write(true).open(&path)?;
+            file.set_modified(UNIX_EPOCH + Duration::from_millis(millis))?;
+        }
+
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = received_mode(mode, PRESERVE_EXECUTABLE.load(Ordering::Relaxed));
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        // Last, so a file system without attributes still gets the rest
+        if !xattrs.is_empty() {
+            write_extended_attributes(&path, &xattrs)?;
+        }
+
+        Ok(())
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_clean_note() {
+        assert_eq!(
+            clean_note("  invoices\n Q3\t\u{7}").as_deref(),
+            Some("invoices Q3")
+        );
+        assert_eq!(clean_note(" \n "), None);
+        assert_eq!(
+            clean_note(&"é".repeat(500)).unwrap().chars().count(),
+            MAX_NOTE_CHARS
+        );
+    }
+
+    #[test]
+    fn test_received_mode_is_owner_only() {
+        assert_eq!(received_mode(0o777, false), 0o600);
+        assert_eq!(received_mode(0o4755, false), 0o600);
+        assert_eq!(received_mode(0o666, false), 0o600);
+        assert_eq!(received_mode(0o000, false), 0o600);
+
+        // Opted in, only the owner's execute bit is taken
+        assert_eq!(received_mode(0o777, true), 0o700);
+        assert_eq!(received_mode(0o4755, true), 0o700);
+        assert_eq!(received_mode(0o644, true), 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_apply_round_trip() {
+        let path = std::env::temp_dir().join(format!("metadata_test_{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"hello").unwrap();
+
+        let info = FileInfo {
+            file_name: "metadata_test.txt".to_string(),
+            file_size: Some(5),
+            file_path: PathBuf::new(),
+            file_hash: None,
+            hash_algorithm: Default::default(),
+            modified: Some(1_600_000_000_123),
+            mode: Some(0o4755),
+            note: None,
+            xattrs: Vec::new(),
+        };
+        apply_file_metadata(&path, &info).await.unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(modified_millis(&metadata), Some(1_600_000_000_123));
+        #[cfg(unix)]
+        assert_eq!(permission_mode(&metadata), Some(0o600));
+
+        let captured = FileInfo {
+            modified: None,
+            mode: None,
+            ..info
+        }
+        .with_source_metadata(&metadata);
+        assert_eq!(captured.modified, Some(1_600_000_000_123));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
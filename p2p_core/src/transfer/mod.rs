@@ -5,18 +5,101 @@
 //! - QUIC server endpoint (to receive files)
 //! - QUIC client endpoint (to send files)
 //! - Verification handshake with 4-digit code
+//!
+//! The legacy single-file `transfer.rs` implementation (hash-less `FileInfo`,
+//! unverified handshake) has been fully replaced by the submodules below.
+//! Everything callers need is re-exported from here; the old flat entry
+//! points are gone and must not come back:
+//!
+//! ```compile_fail
+//! // The old monolithic receive entry point no longer exists.
+//! use p2p_core::transfer::handle_incoming_connection;
+//! ```
+//!
+//! ```compile_fail
+//! // The old sender took no verification context.
+//! use p2p_core::transfer::send_file;
+//! ```
+//!
+//! ```compile_fail
+//! // `FileInfo` always carries the optional integrity hash now.
+//! let _info = p2p_core::FileInfo {
+//!     file_name: String::new(),
+//!     file_size: Some(0),
+//!     file_path: std::path::PathBuf::new(),
+//! };
+//! ```
 
+pub mod ack;
+pub mod buffers;
+pub mod cancel;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod close;
 pub mod constants;
+pub mod estimate;
+pub mod exclude;
+pub mod filename;
 pub mod hash;
+pub mod limits;
+pub mod metadata;
+pub mod moves;
+pub mod netem;
+pub mod orphans;
+pub mod pool;
+pub mod progress;
 pub mod protocol;
 pub mod quic;
+pub mod rate_limit;
+pub mod receipt;
 pub mod receiver;
+pub mod relay;
+pub mod resume;
+pub mod security;
 pub mod sender;
 pub mod server;
+pub mod sparse;
+pub mod staging;
+pub mod stream;
 pub mod utils;
+pub mod vectors;
+pub mod verify;
+pub mod writer;
+pub mod xattrs;
 
 // Re-export public API
-pub use constants::TRANSFER_PORT;
-pub use quic::{make_client_endpoint, make_server_endpoint};
-pub use sender::{TransferContext, send_files};
-pub use server::run_server;
+pub use buffers::{PooledBuffer, transfer_buffers};
+pub use cancel::TransferCancel;
+pub use close::{CloseReason, explain, is_connection_lost};
+pub use constants::{BUFFER_SIZE, MAX_MSG_SIZE, TRANSFER_PORT};
+pub use estimate::{FileAction, FilePreview, SendEstimate, estimate_send};
+pub use filename::{
+    EXECUTABLE_EXTENSIONS, NameWarning, NormalizedName, RenameReason, is_executable,
+    normalize_file_name, peer_folder, sanitize_file_name, set_transliterate_names,
+};
+pub use hash::{
+    FileHasher, HashAlgorithm, compute_file_hash, compute_file_hash_with_progress,
+    compute_prefix_hash, compute_stored_hash, hash_algorithm, set_hash_algorithm, set_hash_threads,
+    verification_progress,
+};
+pub use limits::{ReceiveGuard, ReceiveLimits};
+pub use metadata::apply_file_metadata;
+pub use pool::ConnectionPool;
+pub use progress::ProgressReporter;
+pub use protocol::{TransferMsg, recv_msg, send_msg};
+pub use quic::{
+    SocketBuffers, SocketReport, make_client_endpoint, make_client_endpoint_with,
+    make_server_endpoint, make_server_endpoint_with,
+};
+pub use receiver::receive_file;
+pub use relay::{RelayPolicy, RelayService, request_relay};
+pub use resume::ResumeOffer;
+pub use security::{CertPin, ConnectionPath, SecurityInfo};
+pub use sender::{TransferContext, offer_swarm, redeem_invite, send_files};
+pub use server::{ServerOptions, run_server};
+pub use sparse::SparseWriter;
+pub use stream::{StreamSource, send_stream};
+pub use utils::{
+    format_transfer_speed, open_secure_file, progress_percent, validate_transfer_info,
+};
+pub use writer::WritePipeline;
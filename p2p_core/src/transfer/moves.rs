@@ -0,0 +1,231 @@
+//! "Move" sends: the source is deleted once the receiver has verified its copy.
+//!
+//! The sender asks for a hash confirmation in
+//! [`ResumeStart`](super::protocol::TransferMsg::ResumeStart). The receiver
+//! answers [`HashVerified`](super::protocol::TransferMsg::HashVerified) after
+//! its background check; anything else, including a receiver too old to
+//! answer, keeps the source. A confirmed file is deleted after an undo
+//! window announced with [`AppEvent::MovePending`], and only if it has not
+//! changed since it was sent.
+
+use crate::{AppEvent, EventCategory, LogLevel};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Time to undo a move before the source is deleted
+pub const MOVE_UNDO_WINDOW: Duration = Duration::from_secs(30);
+
+/// How long the sender waits for the receiver's hash check
+pub const HASH_CONFIRM_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Size and modification time of a source when it was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceStamp {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl SourceStamp {
+    pub fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+
+    async fn read(path: &Path) -> Option<Self> {
+        tokio::fs::metadata(path).await.ok().map(|m| Self::of(&m))
+    }
+}
+
+#[derive(Debug)]
+struct PendingMove {
+    file_name: String,
+    cancel: CancellationToken,
+}
+
+/// Confirmed sources waiting out their undo window
+#[derive(Debug)]
+pub struct PendingMoves {
+    pending: Mutex<HashMap<String, PendingMove>>,
+    undo_window: Duration,
+    event_tx: mpsc::Sender<AppEvent>,
+}
+
+impl PendingMoves {
+    pub fn new(event_tx: mpsc::Sender<AppEvent>, undo_window: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            pending: Mutex::new(HashMap::new()),
+            undo_window,
+            event_tx,
+        })
+    }
+
+    /// Delete `path` after the undo window unless undone or changed since
+    /// it was sent as `stamp`
+    pub async fn schedule(self: &Arc<Self>, path: PathBuf, file_name: String, stamp: SourceStamp) {
+        let move_id = crate::new_session_id();
+        let cancel = CancellationToken::new();
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(
+                move_id.clone(),
+                PendingMove {
+                    file_name: file_name.clone(),
+                    cancel: cancel.clone(),
+                },
+            );
+        let _ = self
+            .event_tx
+            .send(AppEvent::MovePending {
+                move_id: move_id.clone(),
+                file_name: file_name.clone(),
+                path: path.clone(),
+                undo_secs: self.undo_window.as_secs(),
+            })
+            .await;
+
+        let moves = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = tokio::time::sleep(moves.undo_window) => {}
+            }
+            if moves.take(&move_id).is_none() {
+                return;
+            }
+            let (level, message, deleted) = if SourceStamp::read(&path).await != Some(stamp) {
+                (
+                    LogLevel::Warning,
+                    format!(
+                        "{} changed after it was sent; it was not deleted",
+                        file_name
+                    ),
+                    false,
+                )
+            } else {
+                match tokio::fs::remove_file(&path).await {
+                    Ok(()) => (
+                        LogLevel::Success,
+                        format!("Moved {}: the receiver verified it", file_name),
+                        true,
+                    ),
+                    Err(e) => (
+                        LogLevel::Error,
+                        format!("Could not delete {} after moving it: {}", file_name, e),
+                        false,
+                    ),
+                }
+            };
+            moves.finish(move_id, level, message, deleted).await;
+        });
+    }
+
+    /// Keep the source of `move_id`; false when it is already gone
+    pub async fn undo(&self, move_id: &str) -> bool {
+        let Some(pending) = self.take(move_id) else {
+            return false;
+        };
+        pending.cancel.cancel();
+        self.finish(
+            move_id.to_string(),
+            LogLevel::Info,
+            format!("Kept {}", pending.file_name),
+            false,
+        )
+        .await;
+        true
+    }
+
+    fn take(&self, move_id: &str) -> Option<PendingMove> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(move_id)
+    }
+
+    async fn finish(&self, move_id: String, level: LogLevel, message: String, deleted: bool) {
+        let _ = self
+            .event_tx
+            .send(AppEvent::log(level, EventCategory::Transfer, message))
+            .await;
+        let _ = self
+            .event_tx
+            .send(AppEvent::MoveFinished { move_id, deleted })
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn finished(events: &mut mpsc::Receiver<AppEvent>) -> (String, bool) {
+        loop {
+            if let AppEvent::MoveFinished { move_id, deleted } = events.recv().await.unwrap() {
+                return (move_id, deleted);
+            }
+        }
+    }
+
+    async fn pending_id(events: &mut mpsc::Receiver<AppEvent>) -> String {
+        loop {
+            if let AppEvent::MovePending { move_id, .. } = events.recv().await.unwrap() {
+                return move_id;
+            }
+        }
+    }
+
+    fn source(content: &[u8]) -> (PathBuf, SourceStamp) {
+        let path = std::env::temp_dir().join(format!("move_{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, content).unwrap();
+        let stamp = SourceStamp::of(&std::fs::metadata(&path).unwrap());
+        (path, stamp)
+    }
+
+    #[tokio::test]
+    async fn test_source_is_deleted_after_the_window() {
+        let (event_tx, mut events) = mpsc::channel(16);
+        let moves = PendingMoves::new(event_tx, Duration::from_millis(20));
+        let (path, stamp) = source(b"data");
+
+        moves.schedule(path.clone(), "a.bin".into(), stamp).await;
+        let id = pending_id(&mut events).await;
+        assert_eq!(finished(&mut events).await, (id, true));
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_undo_keeps_the_source() {
+        let (event_tx, mut events) = mpsc::channel(16);
+        let moves = PendingMoves::new(event_tx, Duration::from_secs(60));
+        let (path, stamp) = source(b"data");
+
+        moves.schedule(path.clone(), "a.bin".into(), stamp).await;
+        let id = pending_id(&mut events).await;
+        assert!(moves.undo(&id).await);
+        assert_eq!(finished(&mut events).await, (id.clone(), false));
+        assert!(!moves.undo(&id).await);
+        assert!(path.exists());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_changed_source_is_kept() {
+        let (event_tx, mut events) = mpsc::channel(16);
+        let moves = PendingMoves::new(event_tx, Duration::from_millis(20));
+        let (path, stamp) = source(b"data");
+        std::fs::write(&path, b"edited meanwhile").unwrap();
+
+        moves.schedule(path.clone(), "a.bin".into(), stamp).await;
+        pending_id(&mut events).await;
+        assert!(!finished(&mut events).await.1);
+        assert!(path.exists());
+        let _ = std::fs::remove_file(&path);
+    }
+}
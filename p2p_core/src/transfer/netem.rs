@@ -0,0 +1,241 @@
+//! Network emulation for reproducing slow links.
+//!
+//! With [`NETEM_ENV`] or the `network_emulation` setting, the LAN QUIC
+//! sockets hold back every datagram they send: for the time it takes at
+//! the rate cap, plus a latency with random jitter. A "slow Wi-Fi" bug
+//! report can then be reproduced on a fast network. Only what this device
+//! sends is delayed, so emulating on one side adds the latency to the round
+//! trip once. The setting holds comma-separated `key=value` pairs:
+//!
+//! - `latency_ms`: fixed delay of every datagram
+//! - `jitter_ms`: random extra delay of up to this long; datagrams may be
+//!   reordered, as on a real network
+//! - `rate_kbps`: rate cap in kilobits per second
+//!
+//! `P2P_NETEM=latency_ms=80,jitter_ms=40,rate_kbps=2000` behaves like a
+//! busy 2 Mbit/s Wi-Fi. The environment variable wins over the setting.
+//! Datagrams that would wait behind more than [`MAX_BACKLOG`] at the rate
+//! cap are dropped, as a router with a full queue does.
+
+use anyhow::{Result, anyhow, bail};
+use quinn::udp::{RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, UdpPoller};
+use std::fmt;
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Network emulation settings are read from this variable
+pub const NETEM_ENV: &str = "P2P_NETEM";
+
+/// Longest queue at the rate cap before datagrams are dropped
+pub const MAX_BACKLOG: Duration = Duration::from_secs(1);
+
+/// How the emulated link behaves
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkEmulation {
+    /// Delay of every datagram
+    pub latency: Duration,
+    /// Longest random delay on top of `latency`
+    pub jitter: Duration,
+    /// Rate cap in bits per second
+    pub rate_bps: Option<u64>,
+}
+
+impl fmt::Display for NetworkEmulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "latency {} ms, jitter {} ms",
+            self.latency.as_millis(),
+            self.jitter.as_millis()
+        )?;
+        match self.rate_bps {
+            Some(rate) => write!(f, ", rate {} kbit/s", rate / 1000),
+            None => write!(f, ", no rate cap"),
+        }
+    }
+}
+
+impl NetworkEmulation {
+    /// Settings in effect: [`NETEM_ENV`] if set, else `setting`; `None`
+    /// when neither is set or valid
+    pub fn current(setting: Option<&str>) -> Option<Self> {
+        let (source, spec) = match std::env::var(NETEM_ENV) {
+            Ok(spec) => (NETEM_ENV, spec),
+            Err(_) => ("network_emulation", setting?.to_string()),
+        };
+        if spec.trim().is_empty() {
+            return None;
+        }
+        match Self::parse(&spec) {
+            Ok(emulation) => Some(emulation),
+            Err(e) => {
+                tracing::warn!("Ignoring {}: {}", source, e);
+                None
+            }
+        }
+    }
+
+    /// Parse the `key=value,...` form of [`NETEM_ENV`]
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut emulation = NetworkEmulation::default();
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected key=value, got '{}'", pair))?;
+            let value = value.trim();
+            match key.trim() {
+                "latency_ms" => emulation.latency = Duration::from_millis(value.parse()?),
+                "jitter_ms" => emulation.jitter = Duration::from_millis(value.parse()?),
+                "rate_kbps" => match value.parse::<u64>()? {
+                    0 => bail!("Rate cap must be above 0"),
+                    kbps => emulation.rate_bps = Some(kbps * 1000),
+                },
+                other => bail!("Unknown key '{}'", other),
+            }
+        }
+        Ok(emulation)
+    }
+
+    /// Time `bytes` take through the rate cap
+    pub fn transmit_time(&self, bytes: usize) -> Duration {
+        match self.rate_bps {
+            Some(rate) => Duration::from_secs_f64(bytes as f64 * 8.0 / rate as f64),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Delay of one datagram after it left the queue
+    fn delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            self.latency
+        } else {
+            self.latency + self.jitter.mul_f64(rand::random())
+        }
+    }
+
+    /// `socket`, sending through the emulated link. Needs a Tokio runtime.
+    pub fn wrap(&self, socket: Arc<dyn AsyncUdpSocket>) -> Result<Arc<dyn AsyncUdpSocket>> {
+        Ok(Arc::new(EmulatedSocket {
+            inner: socket,
+            emulation: self.clone(),
+            runtime: tokio::runtime::Handle::try_current()?,
+            link_free_at: Mutex::new(Instant::now()),
+        }))
+    }
+}
+
+/// UDP socket whose sends are held back by a [`NetworkEmulation`]
+#[derive(Debug)]
+struct EmulatedSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    emulation: NetworkEmulation,
+    runtime: tokio::runtime::Handle,
+    /// When the rate cap has let through everything queued so far
+    link_free_at: Mutex<Instant>,
+}
+
+impl AsyncUdpSocket for EmulatedSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let now = Instant::now();
+        let sent_at = {
+            let mut free_at = self.link_free_at.lock().unwrap_or_else(|e| e.into_inner());
+            let start = (*free_at).max(now);
+            if start - now > MAX_BACKLOG {
+                // Lost like in a full router queue; QUIC sends it again
+                return Ok(());
+            }
+            *free_at = start + self.emulation.transmit_time(transmit.contents.len());
+            *free_at
+        };
+        let deliver_at = sent_at + self.emulation.delay();
+
+        let inner = self.inner.clone();
+        let destination = transmit.destination;
+        let ecn = transmit.ecn;
+        let contents = transmit.contents.to_vec();
+        let segment_size = transmit.segment_size;
+        let src_ip = transmit.src_ip;
+        self.runtime.spawn(async move {
+            tokio::time::sleep_until(deliver_at.into()).await;
+            let transmit = Transmit {
+                destination,
+                ecn,
+                contents: &contents,
+                segment_size,
+                src_ip,
+            };
+            if let Err(e) = inner.try_send(&transmit) {
+                tracing::trace!("Emulated datagram to {} lost: {}", destination, e);
+            }
+        });
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        self.inner.max_transmit_segments()
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_settings() {
+        let emulation =
+            NetworkEmulation::parse("latency_ms=80, jitter_ms=40,rate_kbps=2000").unwrap();
+        assert_eq!(emulation.latency, Duration::from_millis(80));
+        assert_eq!(emulation.jitter, Duration::from_millis(40));
+        assert_eq!(emulation.rate_bps, Some(2_000_000));
+        // 250 KB at 2 Mbit/s
+        assert_eq!(emulation.transmit_time(250_000), Duration::from_secs(1));
+        assert_eq!(
+            NetworkEmulation::default().transmit_time(250_000),
+            Duration::ZERO
+        );
+        for _ in 0..100 {
+            let delay = emulation.delay();
+            assert!(delay >= emulation.latency);
+            assert!(delay <= emulation.latency + emulation.jitter);
+        }
+
+        assert!(NetworkEmulation::parse("latency=80").is_err());
+        assert!(NetworkEmulation::parse("latency_ms").is_err());
+        assert!(NetworkEmulation::parse("rate_kbps=0").is_err());
+        assert!(NetworkEmulation::parse("jitter_ms=-1").is_err());
+        assert_eq!(
+            NetworkEmulation::parse("").unwrap(),
+            NetworkEmulation::default()
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Partial files that no transfer came back for.
+//!
+//! A transfer that breaks off leaves its part file (see [`super::staging`])
+//! and resume record (see [`super::resume`]) behind, so the next send of
+//! the same file continues where it stopped. When that send never comes
+//! they only take space. [`find_orphans`] looks for partial files in the
+//! download folder that have not changed for a while, so the user can ask
+//! the sender to send them again, delete them or keep them. Records whose
+//! partial file is gone are deleted on the way.
+//!
+//! A kept file counts as fresh again and is only reported once it has been
+//! left alone for another full period.
+
+use super::resume::{self, PartialSender, RESUME_SUFFIX};
+use super::staging::{self, PART_SUFFIX};
+use anyhow::{Result, bail};
+use serde::Serialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the background task looks for orphaned partial files
+pub const ORPHAN_CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Days a partial file may sit unchanged before it is reported
+pub const DEFAULT_ORPHAN_AGE_DAYS: u32 = 7;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A partial file left alone for longer than the configured age
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrphanedPart {
+    /// The partial file
+    pub path: PathBuf,
+    /// Name the file gets once complete
+    pub file_name: String,
+    pub size: u64,
+    /// Size of the complete file, if the resume record says
+    pub expected_size: Option<u64>,
+    /// Whole days since the file last changed
+    pub age_days: u64,
+    /// Who was sending it, if the resume record says
+    pub sender: Option<PartialSender>,
+}
+
+/// What to do with an [`OrphanedPart`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanAction {
+    /// Delete the partial file and its resume record
+    Delete,
+    /// Leave it for the next send, reporting it again after another period
+    Keep,
+}
+
+/// Age after which partial files are reported, `None` for `days == 0`
+pub fn max_age(days: u32) -> Option<Duration> {
+    (days > 0).then(|| Duration::from_secs(u64::from(days) * SECS_PER_DAY))
+}
+
+/// Partial files under `dir` unchanged for longer than `max_age` as of
+/// `now`, oldest first. Part files of running transfers are skipped.
+pub fn find_orphans(dir: &Path, max_age: Duration, now: SystemTime) -> Vec<OrphanedPart> {
+    let mut found = Vec::new();
+    scan(dir, max_age, now, &mut found);
+    found.sort_by_key(|orphan| std::cmp::Reverse(orphan.age_days));
+    found
+}
+
+fn scan(dir: &Path, max_age: Duration, now: SystemTime, out: &mut Vec<OrphanedPart>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Do not follow symlinks out of the download folder
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            scan(&path, max_age, now, out);
+            continue;
+        }
+        if !metadata.is_file() {
+            continue;
+        }
+        let age = now
+            .duration_since(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH))
+            .unwrap_or_default();
+
+        if let Some(partial) = partial_of_record(&path) {
+            if !partial.exists() && age > max_age {
+                tracing::info!("Deleting the resume record of a missing file: {:?}", path);
+                let _ = fs::remove_file(&path);
+            }
+            continue;
+        }
+        if !is_partial(&path) || staging::is_claimed(&path) {
+            continue;
+        }
+        let record_path = resume::record_path(&path);
+        // Writing to the file or starting over both count as activity
+        let record_age = fs::metadata(&record_path)
+            .and_then(|m| m.modified())
+            .map(|modified| now.duration_since(modified).unwrap_or_default())
+            .unwrap_or(age);
+        let age = age.min(record_age);
+        if age <= max_age {
+            continue;
+        }
+        let record = resume::read_record_blocking(&record_path);
+        out.push(OrphanedPart {
+            file_name: target_name(&path),
+            size: metadata.len(),
+            expected_size: record.as_ref().map(|r| r.file_size),
+            age_days: age.as_secs() / SECS_PER_DAY,
+            sender: record.and_then(|r| r.sender),
+            path,
+        });
+    }
+}
+
+/// The partial file a resume record belongs to, `None` for other files
+fn partial_of_record(path: &Path) -> Option<PathBuf> {
+    path.to_str()
+        .and_then(|name| name.strip_suffix(RESUME_SUFFIX))
+        .map(PathBuf::from)
+}
+
+/// A part file, or a file of an older version written in place with a
+/// resume record next to it
+fn is_partial(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(PART_SUFFIX) || resume::record_path(path).exists()
+}
+
+/// Name of the complete file: `report.pdf` for `report.pdf.<token>.p2p-part`
+fn target_name(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match name.strip_suffix(PART_SUFFIX) {
+        Some(stem) => stem
+            .rsplit_once('.')
+            .map_or(stem, |(target, _token)| target)
+            .to_string(),
+        None => name,
+    }
+}
+
+/// Apply `action` to the partial file at `path` under `dir`
+pub fn resolve(dir: &Path, path: &Path, action: OrphanAction) -> Result<()> {
+    let inside =
+        path.starts_with(dir) && !path.components().any(|c| matches!(c, Component::ParentDir));
+    if !inside || !is_partial(path) {
+        bail!(
+            "{} is not a partial file in the download folder",
+            path.display()
+        );
+    }
+    if staging::is_claimed(path) {
+        bail!("{} is being received", path.display());
+    }
+    match action {
+        OrphanAction::Delete => {
+            fs::remove_file(path)?;
+            let _ = fs::remove_file(resume::record_path(path));
+        }
+        OrphanAction::Keep => {
+            fs::File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(SystemTime::now())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileInfo;
+
+    fn info(size: u64) -> FileInfo {
+        FileInfo {
+            file_name: "movie.mkv".to_string(),
+            file_size: Some(size),
+            file_path: PathBuf::new(),
+            file_hash: Some("ab".repeat(32)),
+            hash_algorithm: Default::default(),
+            modified: None,
+            mode: None,
+            note: None,
+            xattrs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_stale_partials_are_reported_with_their_sender() {
+        let dir = std::env::temp_dir().join(format!("orphans_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("alice")).unwrap();
+        let part = staging::part_path(&dir.join("alice/movie.mkv"), "0123456789abcdef");
+        fs::write(&part, vec![0u8; 1000]).unwrap();
+        let sender = PartialSender {
+            name: "Alice".to_string(),
+            endpoint_id: "alice-id".to_string(),
+        };
+        resume::begin(&part, &info(4000), "0123456789abcdef", Some(sender.clone())).unwrap();
+        // A record left behind by a file that is gone
+        let dangling = resume::record_path(&dir.join("gone.bin"));
+        fs::write(&dangling, "{}").unwrap();
+        fs::write(dir.join("done.txt"), "complete").unwrap();
+
+        let week = max_age(DEFAULT_ORPHAN_AGE_DAYS).unwrap();
+        assert!(find_orphans(&dir, week, SystemTime::now()).is_empty());
+        assert!(dangling.exists(), "fresh records are kept");
+
+        let later = SystemTime::now() + Duration::from_secs(10 * SECS_PER_DAY);
+        let orphans = find_orphans(&dir, week, later);
+        assert_eq!(
+            orphans,
+            vec![OrphanedPart {
+                path: part.clone(),
+                file_name: "movie.mkv".to_string(),
+                size: 1000,
+                expected_size: Some(4000),
+                age_days: 10,
+                sender: Some(sender),
+            }]
+        );
+        assert!(!dangling.exists());
+
+        // Running transfers are not orphans
+        let claim = staging::Staged::claim(part.clone(), dir.join("alice/movie.mkv")).unwrap();
+        assert!(find_orphans(&dir, week, later).is_empty());
+        assert!(resolve(&dir, &part, OrphanAction::Delete).is_err());
+        drop(claim);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_resolve_keeps_or_deletes_only_partials() {
+        let dir = std::env::temp_dir().join(format!("orphans_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let part = staging::part_path(&dir.join("a.bin"), "feedfacecafe");
+        fs::write(&part, vec![0u8; 10]).unwrap();
+        resume::begin(&part, &info(20), "feedfacecafe", None).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(30 * SECS_PER_DAY);
+        for path in [&part, &resume::record_path(&part)] {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+        let week = max_age(7).unwrap();
+        assert_eq!(find_orphans(&dir, week, SystemTime::now()).len(), 1);
+
+        resolve(&dir, &part, OrphanAction::Keep).unwrap();
+        assert!(find_orphans(&dir, week, SystemTime::now()).is_empty());
+
+        let complete = dir.join("done.txt");
+        fs::write(&complete, "complete").unwrap();
+        assert!(resolve(&dir, &complete, OrphanAction::Delete).is_err());
+        assert!(resolve(Path::new("/elsewhere"), &part, OrphanAction::Delete).is_err());
+
+        resolve(&dir, &part, OrphanAction::Delete).unwrap();
+        assert!(!part.exists());
+        assert!(!resume::record_path(&part).exists());
+        assert!(complete.exists());
+        assert_eq!(max_age(0), None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,194 @@
+//! Verified connections kept open for the next send to the same peer.
+//!
+//! The receiver authenticates a connection once, by pairing or by code, and
+//! then accepts any number of file streams on it. Keeping the connection
+//! saves the QUIC and TLS handshakes and the pairing exchange on every
+//! further send. QUIC keep-alives hold a pooled connection open; the pool
+//! closes it after [`CONNECTION_IDLE_EXPIRY`] without a send.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long an unused connection stays in the pool
+pub const CONNECTION_IDLE_EXPIRY: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Pooled {
+    connection: quinn::Connection,
+    /// Sends currently using the connection
+    in_use: usize,
+    last_used: Instant,
+}
+
+/// Open, verified connections by peer address
+#[derive(Debug)]
+pub struct ConnectionPool {
+    idle_expiry: Duration,
+    entries: Mutex<HashMap<SocketAddr, Pooled>>,
+    /// Throughput of the last timed send to each peer, in bytes per second
+    rates: Mutex<HashMap<SocketAddr, u64>>,
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        Self::new(CONNECTION_IDLE_EXPIRY)
+    }
+}
+
+impl ConnectionPool {
+    pub fn new(idle_expiry: Duration) -> Self {
+        Self {
+            idle_expiry,
+            entries: Mutex::new(HashMap::new()),
+            rates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take the pooled connection to `addr` for a send, if it is still open.
+    /// Hand it back with [`release`](Self::release).
+    pub fn acquire(&self, addr: SocketAddr) -> Option<quinn::Connection> {
+        let mut entries = self.entries.lock().unwrap();
+        let pooled = entries.get_mut(&addr)?;
+        if pooled.connection.close_reason().is_some() {
+            entries.remove(&addr);
+            return None;
+        }
+        pooled.in_use += 1;
+        pooled.last_used = Instant::now();
+        Some(pooled.connection.clone())
+    }
+
+    /// Add a connection that just passed the pairing handshake, in use by
+    /// the send that opened it
+    pub fn insert(&self, addr: SocketAddr, connection: quinn::Connection) {
+        let previous = self.entries.lock().unwrap().insert(
+            addr,
+            Pooled {
+                connection,
+                in_use: 1,
+                last_used: Instant::now(),
+            },
+        );
+        if let Some(previous) = previous
+            && previous.in_use == 0
+        {
+            previous.connection.close(0u32.into(), b"replaced");
+        }
+    }
+
+    /// A send on the connection to `addr` finished
+    pub fn release(&self, addr: SocketAddr) {
+        if let Some(pooled) = self.entries.lock().unwrap().get_mut(&addr) {
+            pooled.in_use = pooled.in_use.saturating_sub(1);
+            pooled.last_used = Instant::now();
+        }
+    }
+
+    /// Drop the connection to `addr`, e.g. after it failed
+    pub fn remove(&self, addr: SocketAddr) {
+        self.entries.lock().unwrap().remove(&addr);
+    }
+
+    /// Close connections that have been idle for too long
+    pub fn expire(&self, now: Instant) {
+        self.entries.lock().unwrap().retain(|_, pooled| {
+            let expired = pooled.in_use == 0
+                && now.saturating_duration_since(pooled.last_used) >= self.idle_expiry;
+            if expired || pooled.connection.close_reason().is_some() {
+                pooled.connection.close(0u32.into(), b"idle");
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Check for idle connections once the expiry has passed
+    pub fn schedule_expiry(self: &Arc<Self>) {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(pool.idle_expiry).await;
+            pool.expire(Instant::now());
+        });
+    }
+
+    /// Close every connection, e.g. after they died during sleep; sends
+    /// still using one fail right away
+    pub fn close_all(&self, reason: &[u8]) {
+        for (_, pooled) in self.entries.lock().unwrap().drain() {
+            pooled.connection.close(0u32.into(), reason);
+        }
+    }
+
+    /// Remember the throughput a send to `addr` reached
+    pub fn record_rate(&self, addr: SocketAddr, bytes_per_sec: u64) {
+        self.rates.lock().unwrap().insert(addr, bytes_per_sec);
+    }
+
+    /// Throughput of the last timed send to `addr`
+    pub fn rate(&self, addr: SocketAddr) -> Option<u64> {
+        self.rates.lock().unwrap().get(&addr).copied()
+    }
+
+    /// Number of pooled connections
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::{make_client_endpoint, make_server_endpoint};
+
+    #[tokio::test]
+    async fn test_idle_connections_expire() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let server = make_server_endpoint("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+        let client = make_client_endpoint().unwrap();
+        let connection = client.connect(addr, "localhost").unwrap().await.unwrap();
+        let _server_side = accept.await.unwrap().unwrap();
+
+        let pool = ConnectionPool::new(Duration::from_secs(60));
+        pool.insert(addr, connection.clone());
+        assert!(pool.acquire(addr).is_some());
+
+        // Busy connections never expire
+        let later = Instant::now() + Duration::from_secs(120);
+        pool.expire(later);
+        assert_eq!(pool.len(), 1);
+
+        pool.release(addr);
+        pool.release(addr);
+        pool.expire(Instant::now());
+        assert_eq!(pool.len(), 1);
+        pool.expire(later);
+        assert!(pool.is_empty());
+        assert!(connection.close_reason().is_some());
+        assert!(pool.acquire(addr).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_close_all_closes_busy_connections() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let server = make_server_endpoint("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+        let client = make_client_endpoint().unwrap();
+        let connection = client.connect(addr, "localhost").unwrap().await.unwrap();
+        let _server_side = accept.await.unwrap().unwrap();
+
+        let pool = ConnectionPool::default();
+        pool.insert(addr, connection.clone());
+        pool.close_all(b"sleep");
+        assert!(pool.is_empty());
+        assert!(connection.close_reason().is_some());
+    }
+}
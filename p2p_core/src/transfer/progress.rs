@@ -0,0 +1,237 @@
+//! Throttled progress events for one transfer.
+//!
+//! Transfer loops call [`ProgressReporter::update`] after every chunk. On a
+//! fast link that is thousands of calls per second, far more than a progress
+//! bar needs and enough to fill the bounded event channel ahead of events
+//! that matter. The reporter passes at most [`MAX_PROGRESS_EVENTS_PER_SEC`]
+//! of them on, and none while the channel is more than half full; skipped
+//! updates are covered by the next one. Completion (100%) is always
+//! delivered. A stream of unknown size reports the bytes through instead of
+//! a percentage, until [`ProgressReporter::complete`]. Every byte is still counted in [`crate::metrics`] and in the
+//! bandwidth usage, if the reporter has a [`UsageMeter`].
+
+use super::utils::{format_transfer_speed, progress_percent};
+use crate::AppEvent;
+use crate::history::transfers::Direction;
+use crate::history::usage::UsageMeter;
+use crate::metrics::{self, ActiveTransfer};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Upper bound on progress events per transfer and second
+pub const MAX_PROGRESS_EVENTS_PER_SEC: u32 = 10;
+
+/// Sends `TransferProgress` for one file
+#[derive(Debug)]
+pub struct ProgressReporter {
+    event_tx: mpsc::Sender<AppEvent>,
+    file_name: String,
+    /// `None` for a stream: bytes only, no percentage
+    total_bytes: Option<u64>,
+    /// Bytes already present when the transfer started (resume)
+    offset: u64,
+    is_sending: bool,
+    start_time: Instant,
+    interval: Duration,
+    last_report: Option<Instant>,
+    completed: bool,
+    usage: Option<Arc<UsageMeter>>,
+    /// Bytes already counted, including the offset
+    counted: u64,
+    /// Counts the file as in flight until it completes
+    active: Option<ActiveTransfer>,
+}
+
+impl ProgressReporter {
+    pub fn new(
+        event_tx: &mpsc::Sender<AppEvent>,
+        file_name: &str,
+        total_bytes: u64,
+        offset: u64,
+        is_sending: bool,
+    ) -> Self {
+        Self {
+            event_tx: event_tx.clone(),
+            file_name: file_name.to_string(),
+            total_bytes: Some(total_bytes),
+            offset,
+            is_sending,
+            start_time: Instant::now(),
+            interval: Duration::from_secs(1) / MAX_PROGRESS_EVENTS_PER_SEC,
+            last_report: None,
+            completed: false,
+            usage: None,
+            counted: offset,
+            active: Some(ActiveTransfer::start()),
+        }
+    }
+
+    /// Reporter for a stream, whose size is known only once it ends
+    pub fn for_stream(
+        event_tx: &mpsc::Sender<AppEvent>,
+        file_name: &str,
+        is_sending: bool,
+    ) -> Self {
+        Self {
+            total_bytes: None,
+            ..Self::new(event_tx, file_name, 0, 0, is_sending)
+        }
+    }
+
+    /// Count the bytes that go through in `usage`
+    pub fn with_usage(mut self, usage: Arc<UsageMeter>) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    /// Minimum time between two progress events
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Report that `bytes_done` of the file are through
+    pub async fn update(&mut self, bytes_done: u64) {
+        if bytes_done > self.counted {
+            let direction = if self.is_sending {
+                Direction::Sent
+            } else {
+                Direction::Received
+            };
+            metrics::add(direction, bytes_done - self.counted);
+            if let Some(usage) = &self.usage {
+                usage.add(direction, bytes_done - self.counted);
+            }
+            self.counted = bytes_done;
+        }
+        if self.total_bytes.is_some_and(|total| bytes_done >= total) {
+            self.complete(bytes_done).await;
+            return;
+        }
+        if self.completed {
+            return;
+        }
+
+        let now = Instant::now();
+        if let Some(last) = self.last_report
+            && now.duration_since(last) < self.interval
+        {
+            return;
+        }
+        // Leave room for the events that cannot be skipped
+        if self.event_tx.capacity() * 2 < self.event_tx.max_capacity() {
+            return;
+        }
+        if self.event_tx.try_send(self.event(bytes_done)).is_ok() {
+            self.last_report = Some(now);
+        }
+    }
+
+    /// Report the end of the transfer at `bytes_done`, for a stream the
+    /// moment its size is known
+    pub async fn complete(&mut self, bytes_done: u64) {
+        if !self.completed {
+            self.completed = true;
+            self.active = None;
+            let _ = self.event_tx.send(self.event(bytes_done)).await;
+        }
+    }
+
+    fn event(&self, bytes_done: u64) -> AppEvent {
+        let elapsed = self.start_time.elapsed().as_secs_f64();
+        let transferred = bytes_done.saturating_sub(self.offset);
+        let speed_bps = if elapsed > 0.0 {
+            transferred as f64 / elapsed
+        } else {
+            0.0
+        };
+        AppEvent::TransferProgress {
+            file_name: self.file_name.clone(),
+            progress: match self.total_bytes {
+                Some(total) => progress_percent(bytes_done, total),
+                None if self.completed => 100.0,
+                None => 0.0,
+            },
+            speed: format_transfer_speed(transferred, elapsed),
+            speed_bps,
+            is_sending: self.is_sending,
+            bytes: self.total_bytes.is_none().then_some(bytes_done),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress_events(rx: &mut mpsc::Receiver<AppEvent>) -> Vec<f32> {
+        let mut progress = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            if let AppEvent::TransferProgress { progress: p, .. } = event {
+                progress.push(p);
+            }
+        }
+        progress
+    }
+
+    #[tokio::test]
+    async fn test_updates_are_coalesced_but_completion_is_not() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let mut reporter = ProgressReporter::new(&tx, "a.bin", 1000, 0, true)
+            .with_interval(Duration::from_secs(60));
+        for done in (0..=1000).step_by(10) {
+            reporter.update(done).await;
+        }
+        reporter.update(1000).await;
+        assert_eq!(progress_events(&mut rx), vec![0.0, 100.0]);
+    }
+
+    #[tokio::test]
+    async fn test_busy_channel_only_gets_completion() {
+        let (tx, mut rx) = mpsc::channel(4);
+        for _ in 0..3 {
+            tx.send(AppEvent::Status("busy".to_string())).await.unwrap();
+        }
+        let mut reporter =
+            ProgressReporter::new(&tx, "a.bin", 1000, 0, false).with_interval(Duration::ZERO);
+        reporter.update(500).await;
+        reporter.update(1000).await;
+        assert_eq!(progress_events(&mut rx), vec![100.0]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_reports_bytes_until_complete() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let mut reporter =
+            ProgressReporter::for_stream(&tx, "live.log", false).with_interval(Duration::ZERO);
+        reporter.update(500).await;
+        reporter.update(1500).await;
+        reporter.complete(1500).await;
+        reporter.update(2000).await;
+        let mut reported = Vec::new();
+        while let Ok(AppEvent::TransferProgress {
+            progress, bytes, ..
+        }) = rx.try_recv()
+        {
+            reported.push((progress, bytes));
+        }
+        assert_eq!(
+            reported,
+            vec![(0.0, Some(500)), (0.0, Some(1500)), (100.0, Some(1500))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_usage_counts_every_byte_past_the_offset() {
+        let (tx, _rx) = mpsc::channel(100);
+        let usage = Arc::new(UsageMeter::load(None));
+        let mut reporter =
+            ProgressReporter::new(&tx, "a.bin", 1000, 200, false).with_usage(usage.clone());
+        for done in [300, 300, 700, 1000] {
+            reporter.update(done).await;
+        }
+        let today = usage.summary(&Default::default()).today;
+        assert_eq!((today.sent, today.received), (0, 800));
+    }
+}
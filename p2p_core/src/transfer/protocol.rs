@@ -1,37 +1,175 @@
 use crate::FileInfo;
+use crate::swarm::SwarmManifest;
 use crate::transfer::constants::MAX_MSG_SIZE;
+use crate::transfer::estimate::EstimateOffer;
+use crate::transfer::receipt::SignedReceipt;
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use zeroize::{Zeroize, Zeroizing};
 
 /// Protocol messages for transfer handshake
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum TransferMsg {
     PairingRequest {
         endpoint_id: String,
         peer_name: String,
+        /// Note of the batch that follows, for the receiver's prompt
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+    },
+    /// Pair by presenting a secret from a QR-code invite instead of a code
+    InviteRedeem {
+        endpoint_id: String,
+        peer_name: String,
+        secret: String,
+    },
+    /// The sender's endpoint ID is paired; prove it holds the key `key_id`
+    /// and sign `nonce` with the endpoint's secret key
+    PairingChallenge {
+        key_id: String,
+        nonce: String,
+    },
+    /// Answer to [`PairingChallenge`](Self::PairingChallenge); `None` when
+    /// the sender has no such key or no secret key to sign with
+    PairingProof {
+        proof: Option<String>,
+        signature: Option<String>,
     },
     PairingAccepted,
     VerificationRequired,
+    /// The receiver shows other codes; this many senders wait ahead
+    PairingQueued {
+        ahead: u32,
+    },
     VerificationCode {
         code: String,
     },
     VerificationSuccess,
+    /// Wrong code, but the sender may try again
+    VerificationRetry {
+        attempts_left: u32,
+    },
     VerificationFailed {
         message: String,
     },
+    /// A file to send; without `file_size` its data comes as a
+    /// [`stream`](super::stream)
     FileMetadata {
         info: FileInfo,
     },
+    /// The receiver takes a file of unknown size; the data frames follow,
+    /// see [`stream`](super::stream)
     ReadyForData,
+    /// What the sender read, after the last frame of a stream; answered
+    /// with `VerificationResult`
+    StreamEnd {
+        size: u64,
+        file_hash: String,
+    },
+    /// Where the receiver can continue; see [`resume`](super::resume)
     ResumeInfo {
         offset: u64,
+        /// Hash of the receiver's first `offset` bytes
+        prefix_hash: Option<String>,
+        token: String,
+        /// Completion handshake the receiver speaks; see [`ack`](super::ack)
+        #[serde(default, skip_serializing_if = "super::ack::is_legacy")]
+        ack_version: u32,
+        /// The receiver can sign a receipt; see [`receipt`](super::receipt)
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        receipts: bool,
+    },
+    /// The sender's answer to `ResumeInfo`: the offered offset, or 0 when
+    /// the receiver's bytes differ from its file
+    ResumeStart {
+        offset: u64,
+        token: String,
+        /// Answer `HashVerified` after the hash check; see
+        /// [`moves`](super::moves)
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        confirm_hash: bool,
+        /// Completion handshake the sender speaks; see [`ack`](super::ack)
+        #[serde(default, skip_serializing_if = "super::ack::is_legacy")]
+        ack_version: u32,
+        /// Follow a passed hash check with `Receipt`
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        want_receipt: bool,
     },
     TransferComplete,
+    /// The sender is still waiting for `TransferComplete` or `HashVerified`;
+    /// answered with `TransferComplete` while the hash check runs
+    AckRequest,
+    /// Result of the receiver's hash check, after `TransferComplete`, for
+    /// a sender asking with `confirm_hash` before
+    /// [`ack`](super::ack) version 2; later ones get `VerificationResult`
+    HashVerified {
+        verified: bool,
+    },
+    /// Result of the receiver's hash check, after `TransferComplete`
+    VerificationResult {
+        verified: bool,
+    },
+    /// Proof of delivery after a passed hash check, for a sender asking
+    /// with `want_receipt`
+    Receipt {
+        receipt: SignedReceipt,
+    },
+    /// The transfer of this stream's file was cancelled by a user; see
+    /// [`cancel`](super::cancel)
+    Cancel {
+        reason: String,
+    },
+    /// Receive a file from a swarm (see [`crate::swarm`]); paired senders only
+    SwarmOffer {
+        manifest: SwarmManifest,
+    },
+    SwarmAccepted,
+    /// Exchange pieces of swarm `swarm_id`; continues with
+    /// [`SwarmMsg`](crate::swarm::wire::SwarmMsg)s
+    SwarmJoin {
+        swarm_id: String,
+    },
+    /// Ask a paired receiver to forward to `target`; see
+    /// [`relay`](super::relay)
+    RelayRequest {
+        target: SocketAddr,
+    },
+    /// The relay forwards packets sent to `port` on its address
+    RelayReady {
+        port: u16,
+    },
+    /// What the receiver would do with these files, without sending them;
+    /// see [`estimate`](super::estimate)
+    EstimateRequest {
+        files: Vec<FileInfo>,
+    },
+    EstimateReply {
+        files: Vec<EstimateOffer>,
+        /// Free space of the download folder
+        free_space: Option<u64>,
+    },
+}
+
+impl Zeroize for TransferMsg {
+    /// Wipe the secrets a message carries, for use in [`Zeroizing`]
+    fn zeroize(&mut self) {
+        match self {
+            TransferMsg::VerificationCode { code } => code.zeroize(),
+            TransferMsg::InviteRedeem { secret, .. } => secret.zeroize(),
+            _ => {}
+        }
+    }
 }
 
 /// Send a protocol message over a bidirectional stream
 pub async fn send_msg(send: &mut quinn::SendStream, msg: &TransferMsg) -> Result<()> {
-    let json = serde_json::to_vec(msg)?;
+    // Encoded messages may hold a code or secret
+    let json = {
+        crate::profile_scope!("serialize");
+        Zeroizing::new(serde_json::to_vec(msg)?)
+    };
     let len = (json.len() as u32).to_be_bytes();
     send.write_all(&len).await?;
     send.write_all(&json).await?;
@@ -52,9 +190,20 @@ pub async fn recv_msg(recv: &mut quinn::RecvStream) -> Result<TransferMsg> {
         ));
     }
 
-    let mut buf = vec![0u8; len];
+    let mut buf = Zeroizing::new(vec![0u8; len]);
     recv.read_exact(&mut buf).await?;
 
-    let msg: TransferMsg = serde_json::from_slice(&buf)?;
-    Ok(msg)
+    decode_msg(&buf)
+}
+
+/// Decode a message body (without the length prefix)
+pub fn decode_msg(buf: &[u8]) -> Result<TransferMsg> {
+    if buf.len() > MAX_MSG_SIZE {
+        return Err(anyhow::anyhow!(
+            "Message too large: {} bytes (max {})",
+            buf.len(),
+            MAX_MSG_SIZE
+        ));
+    }
+    Ok(serde_json::from_slice(buf)?)
 }
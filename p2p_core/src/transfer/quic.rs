@@ -1,61 +1,260 @@
-use anyhow::Result;
-use quinn::{ClientConfig, Endpoint, ServerConfig, TransportConfig};
+use anyhow::{Result, anyhow};
+use quinn::{ClientConfig, Endpoint, EndpointConfig, ServerConfig, TransportConfig};
 use rustls::pki_types::CertificateDer;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::alpn::AppId;
+use crate::transfer::netem::NetworkEmulation;
 use crate::transfer::utils::generate_self_signed_cert;
 
-fn create_optimized_transport_config() -> Result<Arc<TransportConfig>> {
+/// UDP buffer size asked for by default. Windows starts sockets at 64 KB,
+/// which drops packets of a fast transfer while the receive loop is busy;
+/// macOS refuses much more than 4 MB (`kern.ipc.maxsockbuf`). Linux caps the
+/// request at `net.core.rmem_max` and `wmem_max` without an error.
+pub const DEFAULT_SOCKET_BUFFER: usize = if cfg!(windows) {
+    16 * 1024 * 1024
+} else if cfg!(target_os = "macos") {
+    4 * 1024 * 1024
+} else {
+    8 * 1024 * 1024
+};
+
+/// Receive and send buffer sizes of the LAN sockets, in bytes. The WAN
+/// endpoint's sockets belong to iroh, which sizes them itself (7 MB).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocketBuffers {
+    pub recv: usize,
+    pub send: usize,
+}
+
+impl Default for SocketBuffers {
+    fn default() -> Self {
+        Self {
+            recv: DEFAULT_SOCKET_BUFFER,
+            send: DEFAULT_SOCKET_BUFFER,
+        }
+    }
+}
+
+impl SocketBuffers {
+    /// Whether `effective`, as the OS reports it, falls short of these.
+    /// Linux reports twice what it grants, so only a real cap shows.
+    pub fn capped_in(&self, effective: &SocketBuffers) -> bool {
+        effective.recv < self.recv || effective.send < self.send
+    }
+}
+
+/// What a LAN socket got from the OS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketReport {
+    pub buffers: SocketBuffers,
+    /// Datagrams sent per system call with segmentation offload (GSO);
+    /// 1 when it is off or unsupported
+    pub gso_segments: usize,
+    /// Datagrams the kernel may coalesce into one read (GRO); 1 when off
+    pub gro_segments: usize,
+}
+
+impl SocketReport {
+    /// Whether any UDP offload is in use
+    pub fn offload_active(&self) -> bool {
+        self.gso_segments > 1 || self.gro_segments > 1
+    }
+}
+
+/// Bind a UDP socket at `addr` asking for `buffers`; returns the socket
+/// and the buffers it got. A refused size leaves the OS default.
+fn bind_udp(
+    addr: SocketAddr,
+    buffers: SocketBuffers,
+) -> Result<(std::net::UdpSocket, SocketBuffers)> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if let Err(e) = socket.set_recv_buffer_size(buffers.recv) {
+        tracing::debug!("UDP receive buffer of {} refused: {}", buffers.recv, e);
+    }
+    if let Err(e) = socket.set_send_buffer_size(buffers.send) {
+        tracing::debug!("UDP send buffer of {} refused: {}", buffers.send, e);
+    }
+    socket.bind(&addr.into())?;
+    let effective = SocketBuffers {
+        recv: socket.recv_buffer_size()?,
+        send: socket.send_buffer_size()?,
+    };
+    Ok((socket.into(), effective))
+}
+
+/// Turn receive coalescing off again after quinn turned it on. Only Linux
+/// lets a socket opt out; elsewhere GRO stays as the driver has it.
+#[cfg(target_os = "linux")]
+fn disable_gro(socket: &std::net::UdpSocket) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let off: libc::c_int = 0;
+    // SAFETY: the option value is a live c_int of the size passed
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            libc::UDP_GRO,
+            &off as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+fn endpoint_on(
+    addr: SocketAddr,
+    buffers: SocketBuffers,
+    offload: bool,
+    app_id: &AppId,
+    emulation: Option<&NetworkEmulation>,
+    server_config: Option<ServerConfig>,
+) -> Result<(Endpoint, SocketReport)> {
+    let (socket, effective) = bind_udp(addr, buffers)?;
+    let state = quinn::udp::UdpSocketState::new((&socket).into())?;
+    let mut report = SocketReport {
+        buffers: effective,
+        gso_segments: if offload { state.max_gso_segments() } else { 1 },
+        gro_segments: state.gro_segments(),
+    };
+    #[cfg(target_os = "linux")]
+    let gro_handle = if offload {
+        None
+    } else {
+        Some(socket.try_clone()?)
+    };
+    let runtime = quinn::default_runtime().ok_or_else(|| anyhow!("No async runtime found"))?;
+    let mut endpoint = match emulation {
+        None => Endpoint::new(EndpointConfig::default(), server_config, socket, runtime)?,
+        Some(emulation) => {
+            let socket = emulation.wrap(runtime.wrap_udp_socket(socket)?)?;
+            Endpoint::new_with_abstract_socket(
+                EndpointConfig::default(),
+                server_config,
+                socket,
+                runtime,
+            )?
+        }
+    };
+    #[cfg(target_os = "linux")]
+    if let Some(handle) = gro_handle {
+        match disable_gro(&handle) {
+            Ok(()) => report.gro_segments = 1,
+            Err(e) => tracing::debug!("UDP GRO could not be turned off: {}", e),
+        }
+    }
+    endpoint.set_default_client_config(make_client_config(offload, app_id)?);
+    Ok((endpoint, report))
+}
+
+fn create_optimized_transport_config(offload: bool) -> Result<Arc<TransportConfig>> {
     let mut transport_config = TransportConfig::default();
+    // Some NIC drivers corrupt or drop segmented sends; off is the escape hatch
+    transport_config.enable_segmentation_offload(offload);
     transport_config.max_idle_timeout(Some(Duration::from_secs(30).try_into()?));
     transport_config.keep_alive_interval(Some(Duration::from_secs(2)));
     transport_config.stream_receive_window((64 * 1024 * 1024_u32).into());
     transport_config.receive_window((128 * 1024 * 1024_u32).into());
     transport_config.send_window(128 * 1024 * 1024);
     transport_config.datagram_receive_buffer_size(Some(64 * 1024 * 1024));
+    // The protocol only uses bidirectional streams
+    transport_config.max_concurrent_uni_streams(0u32.into());
     Ok(Arc::new(transport_config))
 }
 
-/// Create a QUIC server endpoint
+/// Create a QUIC server endpoint. It can also connect out, so swarm
+/// members reach each other from their transfer port.
 pub fn make_server_endpoint(bind_addr: SocketAddr) -> Result<Endpoint> {
+    make_server_endpoint_with(
+        bind_addr,
+        SocketBuffers::default(),
+        true,
+        &AppId::default(),
+        None,
+    )
+    .map(|(endpoint, _)| endpoint)
+}
+
+/// [`make_server_endpoint`] asking for `buffers`, with UDP offloads on or
+/// off, speaking the protocol of `app_id` and sending through `emulation`
+/// if given; also returns what the socket got
+pub fn make_server_endpoint_with(
+    bind_addr: SocketAddr,
+    buffers: SocketBuffers,
+    offload: bool,
+    app_id: &AppId,
+    emulation: Option<&NetworkEmulation>,
+) -> Result<(Endpoint, SocketReport)> {
     let (certs, key) = generate_self_signed_cert()?;
 
     let mut server_crypto = rustls::ServerConfig::builder()
         .with_no_client_auth()
         .with_single_cert(certs, key.into())?;
 
-    server_crypto.alpn_protocols = vec![b"p2p-transfer".to_vec()];
+    server_crypto.alpn_protocols = vec![app_id.lan_alpn()];
 
     let mut server_config = ServerConfig::with_crypto(Arc::new(
         quinn::crypto::rustls::QuicServerConfig::try_from(server_crypto)?,
     ));
 
-    server_config.transport_config(create_optimized_transport_config()?);
+    server_config.transport_config(create_optimized_transport_config(offload)?);
 
-    let endpoint = Endpoint::server(server_config, bind_addr)?;
-    Ok(endpoint)
+    endpoint_on(
+        bind_addr,
+        buffers,
+        offload,
+        app_id,
+        emulation,
+        Some(server_config),
+    )
 }
 
 pub fn make_client_endpoint() -> Result<Endpoint> {
+    make_client_endpoint_with(SocketBuffers::default(), true, &AppId::default(), None)
+        .map(|(endpoint, _)| endpoint)
+}
+
+/// [`make_client_endpoint`] asking for `buffers`, with UDP offloads on or
+/// off, speaking the protocol of `app_id` and sending through `emulation`
+/// if given; also returns what the socket got
+pub fn make_client_endpoint_with(
+    buffers: SocketBuffers,
+    offload: bool,
+    app_id: &AppId,
+    emulation: Option<&NetworkEmulation>,
+) -> Result<(Endpoint, SocketReport)> {
+    endpoint_on(
+        "0.0.0.0:0".parse()?,
+        buffers,
+        offload,
+        app_id,
+        emulation,
+        None,
+    )
+}
+
+fn make_client_config(offload: bool, app_id: &AppId) -> Result<ClientConfig> {
     let mut crypto = rustls::ClientConfig::builder()
         .dangerous()
         .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
         .with_no_client_auth();
 
-    crypto.alpn_protocols = vec![b"p2p-transfer".to_vec()];
+    crypto.alpn_protocols = vec![app_id.lan_alpn()];
 
     let mut client_config = ClientConfig::new(Arc::new(
         quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?,
     ));
 
-    client_config.transport_config(create_optimized_transport_config()?);
-
-    let mut endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
-    endpoint.set_default_client_config(client_config);
-
-    Ok(endpoint)
+    client_config.transport_config(create_optimized_transport_config(offload)?);
+    Ok(client_config)
 }
 
 /// No-op certificate verifier for P2P self-signed certs
@@ -107,3 +306,104 @@ impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socket_buffers_are_applied() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let asked = SocketBuffers {
+            recv: 64 * 1024,
+            send: 64 * 1024,
+        };
+        let (endpoint, report) = make_server_endpoint_with(
+            "127.0.0.1:0".parse().unwrap(),
+            asked,
+            true,
+            &AppId::default(),
+            None,
+        )
+        .unwrap();
+        assert!(endpoint.local_addr().unwrap().port() > 0);
+        let effective = report.buffers;
+        assert!(!asked.capped_in(&effective), "{:?}", effective);
+
+        let huge = SocketBuffers {
+            recv: usize::MAX / 4,
+            send: usize::MAX / 4,
+        };
+        assert!(huge.capped_in(&effective));
+    }
+
+    #[tokio::test]
+    async fn test_offload_can_be_turned_off() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let (_endpoint, report) = make_server_endpoint_with(
+            "127.0.0.1:0".parse().unwrap(),
+            SocketBuffers::default(),
+            false,
+            &AppId::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.gso_segments, 1);
+        if cfg!(target_os = "linux") {
+            assert_eq!(report.gro_segments, 1);
+            assert!(!report.offload_active());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_other_app_ids_cannot_connect() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let private = AppId::new("acme").unwrap();
+        let (server, _) = make_server_endpoint_with(
+            "127.0.0.1:0".parse().unwrap(),
+            SocketBuffers::default(),
+            true,
+            &private,
+            None,
+        )
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Some(incoming) = server.accept().await {
+                let _ = incoming.await;
+            }
+        });
+
+        let public = make_client_endpoint().unwrap();
+        assert!(public.connect(addr, "localhost").unwrap().await.is_err());
+        let (same, _) =
+            make_client_endpoint_with(SocketBuffers::default(), true, &private, None).unwrap();
+        assert!(same.connect(addr, "localhost").unwrap().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_emulated_latency_adds_to_the_round_trip() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let slow = NetworkEmulation::parse("latency_ms=100,rate_kbps=100000").unwrap();
+        let (server, _) = make_server_endpoint_with(
+            "127.0.0.1:0".parse().unwrap(),
+            SocketBuffers::default(),
+            true,
+            &AppId::default(),
+            Some(&slow),
+        )
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Some(incoming) = server.accept().await {
+                let _ = incoming.await;
+            }
+        });
+
+        let client = make_client_endpoint().unwrap();
+        let started = std::time::Instant::now();
+        let connection = client.connect(addr, "localhost").unwrap().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(connection.rtt() >= Duration::from_millis(100));
+    }
+}
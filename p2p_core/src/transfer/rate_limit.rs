@@ -0,0 +1,69 @@
+//! Token bucket keeping traffic under a rate, used by the relays and by
+//! sends on a metered connection.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A burst of up to one second of traffic passes at once, after that
+/// callers wait
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    /// Available bytes (negative while in debt) and when they were counted
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            state: Mutex::new((bytes_per_sec as f64, Instant::now())),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Take `bytes` from the bucket and return how long to wait before
+    /// sending them
+    fn delay_for(&self, bytes: usize, now: Instant) -> Duration {
+        let rate = self.bytes_per_sec as f64;
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = *state;
+        let elapsed = now.saturating_duration_since(last).as_secs_f64();
+        let tokens = (tokens + elapsed * rate).min(rate) - bytes as f64;
+        *state = (tokens, now);
+        if tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-tokens / rate)
+        }
+    }
+
+    /// Wait until `bytes` may go out
+    pub async fn acquire(&self, bytes: usize) {
+        let delay = self.delay_for(bytes, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_delays_past_the_burst() {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        assert_eq!(limiter.delay_for(600, start), Duration::ZERO);
+        assert_eq!(limiter.delay_for(400, start), Duration::ZERO);
+        assert_eq!(limiter.delay_for(500, start), Duration::from_millis(500));
+        // Half a second later the debt is paid off, nothing saved up
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.delay_for(0, later), Duration::ZERO);
+        assert_eq!(limiter.delay_for(100, later), Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,165 @@
+//! Signed delivery receipts, as proof that a file arrived intact.
+//!
+//! A sender that wants a receipt sets `want_receipt` in `ResumeStart`, and
+//! only to a receiver that announced `receipts` in `ResumeInfo`. Once its
+//! hash check passes, that receiver answers `VerificationResult` with a
+//! `Receipt` message carrying a [`SignedReceipt`]: the file's name, size and
+//! hash, when it was received, and both endpoint IDs, signed with the
+//! receiver's Iroh secret key. The sender only asks a receiver whose
+//! endpoint ID it knows, checks that the receipt names that receiver and
+//! that its key, the endpoint ID's public half, signed it, and keeps the
+//! receipt in its history; anyone holding the receipt can check it again
+//! later without either device.
+
+use super::ack::COMPLETION_ACK_WAIT;
+use super::hash::HashAlgorithm;
+use super::protocol::{TransferMsg, recv_msg};
+use crate::pairing::key::{hex_decode, hex_encode};
+use anyhow::{Context, Result, anyhow};
+use iroh::{PublicKey, SecretKey, Signature};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+const RECEIPT_LABEL: &[u8] = b"p2p-transfer delivery receipt v1\n";
+
+/// What the receiver vouches for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Receipt {
+    pub file_name: String,
+    pub size: u64,
+    pub file_hash: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    /// Unix seconds when the hash check passed
+    pub received_at: u64,
+    /// Endpoint ID of the receiver, whose key signed the receipt
+    pub receiver: String,
+    /// Endpoint ID of the sender
+    pub sender: String,
+}
+
+impl Receipt {
+    /// The bytes that are signed; field order is fixed by the struct
+    fn message(&self) -> Result<Vec<u8>> {
+        let mut message = RECEIPT_LABEL.to_vec();
+        message.extend(serde_json::to_vec(self)?);
+        Ok(message)
+    }
+
+    /// Sign with the receiver's key, which must be the one `receiver` names
+    pub fn sign(self, secret: &SecretKey) -> Result<SignedReceipt> {
+        if secret.public().to_string() != self.receiver {
+            return Err(anyhow!("The receipt names another receiver"));
+        }
+        let signature = hex_encode(&secret.sign(&self.message()?).to_bytes());
+        Ok(SignedReceipt {
+            receipt: self,
+            signature,
+        })
+    }
+}
+
+/// A receipt with the receiver's signature over it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SignedReceipt {
+    #[serde(flatten)]
+    pub receipt: Receipt,
+    /// Ed25519 signature, hex
+    pub signature: String,
+}
+
+impl SignedReceipt {
+    /// Check that the key `receipt.receiver` names signed this receipt
+    pub fn verify(&self) -> Result<()> {
+        let public = PublicKey::from_str(&self.receipt.receiver)
+            .context("The receipt's receiver is not an endpoint ID")?;
+        let bytes = hex_decode(&self.signature)
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+            .ok_or_else(|| anyhow!("Malformed receipt signature"))?;
+        public
+            .verify(&self.receipt.message()?, &Signature::from_bytes(&bytes))
+            .map_err(|_| anyhow!("The receipt's signature does not match"))
+    }
+
+    /// Whether this receipt is for the file `sender` sent as described
+    pub fn covers(&self, file_hash: &str, size: u64, sender: &str) -> bool {
+        self.receipt.file_hash == file_hash
+            && self.receipt.size == size
+            && self.receipt.sender == sender
+    }
+}
+
+/// Read the receipt that follows a passed hash check and check that
+/// `receiver` signed it for the file `sender` sent, `file_hash` of `size`
+/// bytes
+pub async fn await_receipt(
+    recv: &mut quinn::RecvStream,
+    file_hash: &str,
+    size: u64,
+    sender: &str,
+    receiver: &str,
+) -> Result<SignedReceipt> {
+    let msg = tokio::time::timeout(COMPLETION_ACK_WAIT, recv_msg(recv))
+        .await
+        .map_err(|_| anyhow!("The receiver sent no receipt"))??;
+    let TransferMsg::Receipt { receipt } = msg else {
+        return Err(anyhow!("Expected Receipt, got {:?}", msg));
+    };
+    if receipt.receipt.receiver != receiver {
+        return Err(anyhow!("The receipt is signed by another device"));
+    }
+    receipt.verify()?;
+    if !receipt.covers(file_hash, size, sender) {
+        return Err(anyhow!("The receipt is for another file"));
+    }
+    Ok(receipt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(receiver: &SecretKey) -> Receipt {
+        Receipt {
+            file_name: "invoice.pdf".to_string(),
+            size: 4096,
+            file_hash: "ab".repeat(32),
+            hash_algorithm: HashAlgorithm::Blake3,
+            received_at: 1_700_000_000,
+            receiver: receiver.public().to_string(),
+            sender: SecretKey::generate(&mut rand::rng()).public().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_signed_receipt_verifies_and_survives_json() {
+        let key = SecretKey::generate(&mut rand::rng());
+        let signed = receipt(&key).sign(&key).unwrap();
+        signed.verify().unwrap();
+
+        let json = serde_json::to_string(&signed).unwrap();
+        let parsed: SignedReceipt = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, signed);
+        parsed.verify().unwrap();
+        assert!(parsed.covers(&"ab".repeat(32), 4096, &signed.receipt.sender));
+        assert!(!parsed.covers(&"ab".repeat(32), 4095, &signed.receipt.sender));
+    }
+
+    #[test]
+    fn test_altered_or_foreign_receipts_are_rejected() {
+        let key = SecretKey::generate(&mut rand::rng());
+        let mut signed = receipt(&key).sign(&key).unwrap();
+        signed.receipt.size += 1;
+        assert!(signed.verify().is_err());
+
+        // Only the named receiver's key can sign
+        let other = SecretKey::generate(&mut rand::rng());
+        assert!(receipt(&key).sign(&other).is_err());
+
+        // A receipt re-addressed to another receiver no longer verifies
+        let mut signed = receipt(&key).sign(&key).unwrap();
+        signed.receipt.receiver = other.public().to_string();
+        assert!(signed.verify().is_err());
+    }
+}
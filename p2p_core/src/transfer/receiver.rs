@@ -1,133 +1,398 @@
-use crate::{AppEvent, FileInfo};
+use crate::history::transfers::{Direction, TransferRecord, TransferStatus};
+use crate::pairing::now_timestamp;
+use crate::storage::{self, Storage};
+use crate::units::format_size;
+use crate::{AppEvent, EventCategory, FileInfo, LogLevel};
 use anyhow::Result;
-use std::path::PathBuf;
-use tokio::io::AsyncWriteExt;
+use std::path::{Path, PathBuf};
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
-use super::constants::BUFFER_SIZE;
-use super::hash::compute_file_hash;
-use super::utils::{open_secure_file, report_progress, sanitize_file_name, validate_transfer_info};
+use super::cancel::{CANCEL_CODE, is_cancel_code, report_cancelled};
+
+use super::ack::{COMPLETION_ACK_VERSION, await_verification};
+use super::buffers::transfer_buffers;
+use super::filename::normalize_file_name;
+use super::hash::HashAlgorithm;
+use super::limits::FileSlot;
+use super::metadata::{apply_file_metadata, clean_note};
+use super::progress::ProgressReporter;
+use super::protocol::{TransferMsg, recv_msg, send_msg};
+use super::receipt::Receipt;
+use super::resume::{self, PartialSender};
+use super::sparse::SparseWriter;
+use super::stream;
+use super::utils::validate_transfer_info;
+use super::verify::VerifyQueue;
+use super::writer::WritePipeline;
 
 /// Receive a single file from the stream
+///
+/// The file is acknowledged once all bytes are written; a file with a hash
+/// is then checked in the background by `verifier`.
+/// Cancelling `cancel` stops the transfer and deletes the partial file.
+/// `peer` names the sender in the completion event and, with its endpoint
+/// ID `peer_id`, in the history. The file is written through `storage`; one
+/// that does not fit is refused up front. With `receipt_key`, a sender
+/// that asks gets a [`receipt`](super::receipt) signed with it. A stream is
+/// charged to `file_slot` as it arrives.
+#[allow(clippy::too_many_arguments)]
 pub async fn receive_file(
     send: &mut quinn::SendStream,
     recv: &mut quinn::RecvStream,
     download_dir: &PathBuf,
     event_tx: &mpsc::Sender<AppEvent>,
     mut file_info: FileInfo,
+    preserve_metadata: bool,
+    verifier: &VerifyQueue,
+    cancel: CancellationToken,
+    peer: &str,
+    peer_id: &str,
+    storage: &dyn Storage,
+    receipt_key: Option<&iroh::SecretKey>,
+    file_slot: &mut FileSlot,
 ) -> Result<()> {
     // Enforce strict file size and name limits to prevent DoS
-    if let Err(e) = validate_transfer_info(&file_info.file_name, file_info.file_size) {
+    if let Err(e) = validate_transfer_info(&file_info.file_name, file_info.file_size.unwrap_or(0)) {
         let _ = event_tx.send(AppEvent::Error(e.to_string())).await;
         return Err(e);
     }
 
-    file_info.file_name = sanitize_file_name(&file_info.file_name);
+    let normalized = normalize_file_name(&file_info.file_name, download_dir);
+    for notice in normalized.notices() {
+        let _ = event_tx
+            .send(AppEvent::log(
+                LogLevel::Warning,
+                EventCategory::Transfer,
+                notice,
+            ))
+            .await;
+    }
+    file_info.file_name = normalized.name;
+    file_info.note = file_info.note.as_deref().and_then(clean_note);
 
+    let note = file_info
+        .note
+        .as_ref()
+        .map(|note| format!(" [{}]", note))
+        .unwrap_or_default();
     let _ = event_tx
-        .send(AppEvent::Status(format!(
-            "Receiving: {} ({} bytes)",
-            file_info.file_name, file_info.file_size
-        )))
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!(
+                "Receiving: {} ({}){}",
+                file_info.file_name,
+                file_info
+                    .file_size
+                    .map_or_else(|| "stream".to_string(), format_size),
+                note
+            ),
+        ))
+        .await;
+
+    let Some(total) = file_info.file_size else {
+        return stream::receive_stream(
+            send,
+            recv,
+            download_dir,
+            event_tx,
+            file_info,
+            verifier,
+            cancel,
+            peer,
+            peer_id,
+            storage,
+            file_slot,
+        )
         .await;
+    };
 
     crate::config::create_secure_dir_all_async(download_dir).await?;
-    let file_path = download_dir.join(&file_info.file_name);
-
-    let mut offset = 0;
-    if file_path.exists() {
-        let metadata = tokio::fs::metadata(&file_path).await?;
-        let current_size = metadata.len();
-        if current_size < file_info.file_size {
-            offset = current_size;
-        } else {
-            offset = 0;
+    let mut file_path = download_dir.join(&file_info.file_name);
+
+    let offer = resume::offer(storage, &file_path, &file_info).await?;
+    let part = offer.staged.part().to_path_buf();
+    let needed = total.saturating_sub(offer.offset);
+    if let Err(e) = storage::ensure_space(storage, download_dir, needed).await {
+        let message = e.to_string();
+        let _ = send_msg(send, &TransferMsg::VerificationFailed { message }).await;
+        return Err(e);
+    }
+    send_msg(
+        send,
+        &TransferMsg::ResumeInfo {
+            offset: offer.offset,
+            prefix_hash: offer.prefix_hash.clone(),
+            token: offer.token.clone(),
+            ack_version: COMPLETION_ACK_VERSION,
+            receipts: receipt_key.is_some(),
+        },
+    )
+    .await?;
+    let (offset, confirm_hash, ack_version, want_receipt) = match recv_msg(recv).await? {
+        TransferMsg::ResumeStart {
+            offset,
+            token,
+            confirm_hash,
+            ack_version,
+            want_receipt,
+        } => (
+            resume::accept_start(&offer, offset, &token)?,
+            confirm_hash,
+            ack_version,
+            want_receipt,
+        ),
+        TransferMsg::Cancel { reason } => {
+            verifier.log(received_record(
+                &file_info,
+                &file_path,
+                peer,
+                peer_id,
+                TransferStatus::Cancelled,
+            ));
+            report_cancelled(event_tx, &file_info.file_name, false, true, &reason).await;
+            return Ok(());
+        }
+        other => return Err(anyhow::anyhow!("Expected ResumeStart, got {:?}", other)),
+    };
+    if offset == 0 {
+        if offer.offset > 0 {
+            tracing::warn!(
+                "Partial copy of {} differs from the sender's file, receiving it again",
+                file_info.file_name
+            );
+        }
+        let sender = (!peer_id.is_empty()).then(|| PartialSender {
+            name: peer.to_string(),
+            endpoint_id: peer_id.to_string(),
+        });
+        // Remote files are never resumed, so they need no record
+        if storage.is_local() {
+            resume::begin(&part, &file_info, &offer.token, sender)?;
         }
     }
 
-    use super::protocol::{TransferMsg, send_msg};
-    send_msg(send, &TransferMsg::ResumeInfo { offset }).await?;
-
-    // Use open_secure_file to ensure secure permissions (0o600) on creation
-    let mut file = open_secure_file(&file_path, offset).await?;
+    let file = storage::open_at(storage, &part, offset).await?;
+    let mut file = SparseWriter::new(file, offset);
+    file.preallocate(total).await?;
+    let mut file = WritePipeline::spawn(file);
 
     let mut received: u64 = offset;
-    let mut buffer = vec![0u8; BUFFER_SIZE];
-    let total = file_info.file_size;
-    let start_time = std::time::Instant::now();
-    let mut last_progress_update = 0u64;
-
-    report_progress(
-        event_tx,
-        &file_info.file_name,
-        received,
-        total,
-        start_time,
-        offset,
-        false,
-    )
-    .await;
+    let mut buffer = transfer_buffers().borrow(total.saturating_sub(offset));
+    let mut progress = ProgressReporter::new(event_tx, &file_info.file_name, total, offset, false)
+        .with_usage(verifier.history().usage.clone());
+    progress.update(received).await;
 
     while received < total {
-        let to_read = std::cmp::min(BUFFER_SIZE as u64, total - received) as usize;
-        let n = recv.read(&mut buffer[..to_read]).await?.unwrap_or(0);
+        let to_read = std::cmp::min(buffer.len() as u64, total - received) as usize;
+        let read = tokio::select! {
+            read = recv.read(&mut buffer[..to_read]) => read,
+            _ = cancel.cancelled() => {
+                let reason = "Cancelled by the receiver";
+                let _ = send_msg(send, &TransferMsg::Cancel { reason: reason.to_string() }).await;
+                let _ = recv.stop(CANCEL_CODE.into());
+                // Let queued writes land before the partial file goes
+                let _ = file.finish().await;
+                resume::discard(storage, &part).await;
+                verifier.log(received_record(&file_info, &file_path, peer, peer_id, TransferStatus::Cancelled));
+                report_cancelled(event_tx, &file_info.file_name, false, false, reason).await;
+                return Ok(());
+            }
+        };
+        let n = match read {
+            Ok(n) => n.unwrap_or(0),
+            // Keep what arrived so a later send resumes from it
+            Err(quinn::ReadError::Reset(code)) if is_cancel_code(code) => {
+                file.finish().await?;
+                if !storage.is_local() {
+                    resume::discard(storage, &part).await;
+                }
+                verifier.log(received_record(
+                    &file_info,
+                    &file_path,
+                    peer,
+                    peer_id,
+                    TransferStatus::Cancelled,
+                ));
+                report_cancelled(
+                    event_tx,
+                    &file_info.file_name,
+                    false,
+                    true,
+                    "Cancelled by the sender",
+                )
+                .await;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
         if n == 0 {
             break;
         }
-        file.write_all(&buffer[..n]).await?;
+        file.write(&buffer[..n]).await?;
         received += n as u64;
 
-        if received == total || received - last_progress_update >= BUFFER_SIZE as u64 {
-            last_progress_update = received;
-            report_progress(
-                event_tx,
-                &file_info.file_name,
-                received,
-                total,
-                start_time,
-                offset,
-                false,
-            )
-            .await;
-        }
+        progress.update(received).await;
     }
 
-    file.flush().await?;
-
-    if let Some(expected_hash) = file_info.file_hash {
-        let _ = event_tx
-            .send(AppEvent::VerificationStarted {
-                file_name: file_info.file_name.clone(),
-                is_sending: false,
-            })
-            .await;
-
-        let computed_hash = compute_file_hash(&file_path).await?;
-        let verified = computed_hash == expected_hash;
+    file.finish().await?;
 
-        if !verified {
-            let _ = event_tx
-                .send(AppEvent::Error(format!(
-                    "Hash verification FAILED for {}!",
-                    file_info.file_name
-                )))
-                .await;
-        }
+    if received == total {
+        resume::finish(&part).await;
+        file_path = offer.staged.commit(storage).await?;
+    } else {
+        file_path = part;
+    }
 
+    let file_name = file_info.file_name.clone();
+    if file_info.file_hash.is_some() && file_info.hash_algorithm == HashAlgorithm::Unknown {
         let _ = event_tx
-            .send(AppEvent::VerificationCompleted {
-                file_name: file_info.file_name.clone(),
-                is_sending: false,
-                verified,
-            })
+            .send(AppEvent::log(
+                LogLevel::Warning,
+                EventCategory::Transfer,
+                format!(
+                    "{} was hashed with an algorithm this version does not know; it is not verified",
+                    file_name
+                ),
+            ))
             .await;
+        file_info.file_hash = None;
     }
+    let record = received_record(
+        &file_info,
+        &file_path,
+        peer,
+        peer_id,
+        TransferStatus::Unverified,
+    );
+    // What a receipt vouches for, before the verifier takes `file_info`
+    let receipt_hash = file_info.file_hash.clone();
+    let hash_algorithm = file_info.hash_algorithm;
+    let verified = match file_info.file_hash.clone() {
+        Some(expected_hash) if confirm_hash => Some(
+            verifier
+                .enqueue_confirmed(
+                    file_path.clone(),
+                    file_info,
+                    expected_hash,
+                    preserve_metadata,
+                    record,
+                )
+                .await,
+        ),
+        Some(expected_hash) => {
+            verifier
+                .enqueue(
+                    file_path.clone(),
+                    file_info,
+                    expected_hash,
+                    preserve_metadata,
+                    record,
+                )
+                .await;
+            None
+        }
+        None => {
+            verifier.log(record);
+            if preserve_metadata && storage.is_local() {
+                restore_metadata(&file_path, &file_info, event_tx).await;
+            }
+            None
+        }
+    };
 
     send_msg(send, &TransferMsg::TransferComplete).await?;
 
     let _ = event_tx
-        .send(AppEvent::TransferCompleted(file_info.file_name.clone()))
+        .send(AppEvent::TransferCompleted {
+            file_name: file_name.clone(),
+            saved_path: Some(file_path),
+            peer: Some(peer.to_string()),
+        })
         .await;
 
+    // A moving sender deletes its file on this answer; without a hash
+    // there is nothing to vouch for
+    if confirm_hash {
+        let verified = match verified {
+            Some(result) if ack_version >= 1 => await_verification(send, recv, result).await?,
+            Some(result) => result.await.unwrap_or(false),
+            None => false,
+        };
+        let result = if ack_version >= 2 {
+            TransferMsg::VerificationResult { verified }
+        } else {
+            TransferMsg::HashVerified { verified }
+        };
+        send_msg(send, &result).await?;
+
+        if let (true, true, Some(key), Some(file_hash)) =
+            (verified, want_receipt, receipt_key, receipt_hash)
+        {
+            let receipt = Receipt {
+                file_name: file_name.clone(),
+                size: total,
+                file_hash,
+                hash_algorithm,
+                received_at: now_timestamp(),
+                receiver: key.public().to_string(),
+                sender: peer_id.to_string(),
+            };
+            send_msg(
+                send,
+                &TransferMsg::Receipt {
+                    receipt: receipt.sign(key)?,
+                },
+            )
+            .await?;
+        }
+    }
+
     Ok(())
 }
+
+/// History entry for `file_info` received from `peer`
+fn received_record(
+    file_info: &FileInfo,
+    file_path: &Path,
+    peer: &str,
+    peer_id: &str,
+    status: TransferStatus,
+) -> TransferRecord {
+    TransferRecord {
+        path: Some(file_path.to_path_buf()),
+        peer_id: Some(peer_id.to_string()),
+        hash: file_info.file_hash.clone(),
+        hash_algorithm: file_info.hash_algorithm,
+        note: file_info.note.clone(),
+        ..TransferRecord::new(
+            Direction::Received,
+            status,
+            &file_info.file_name,
+            file_info.file_size.unwrap_or(0),
+            peer,
+        )
+    }
+}
+
+/// Apply the sender's timestamps, permissions and extended attributes,
+/// warning if that fails
+pub async fn restore_metadata(
+    file_path: &Path,
+    file_info: &FileInfo,
+    event_tx: &mpsc::Sender<AppEvent>,
+) {
+    if let Err(e) = apply_file_metadata(file_path, file_info).await {
+        let _ = event_tx
+            .send(AppEvent::log(
+                LogLevel::Warning,
+                EventCategory::Transfer,
+                format!(
+                    "Could not restore all metadata of {}: {}",
+                    file_info.file_name, e
+                ),
+            ))
+            .await;
+    }
+}
@@ -0,0 +1,370 @@
+//! Relaying LAN transfers between peers that cannot reach each other.
+//!
+//! When B cannot reach C but both reach A, B asks A for a relay with
+//! [`request_relay`]. A must allow relaying (see [`RelayPolicy`]) and B must
+//! have passed the pairing handshake with A on that connection. A then binds
+//! a fresh UDP port and forwards QUIC packets between B and C on it; B sends
+//! to that port exactly as it would to C. The QUIC connection, its TLS keys
+//! and the pairing exchange stay end to end between B and C, so A only ever
+//! sees encrypted packets and cannot pose as either side.
+//!
+//! A relay only forwards to a peer discovery found at a private LAN address,
+//! never to this device's own services or out to the internet. It only
+//! accepts packets from the requester's IP address and ends after
+//! [`RELAY_IDLE_TIMEOUT`] without traffic. At most [`MAX_RELAYS`] run
+//! at once, and all of them together stay under the policy's bandwidth cap.
+
+use crate::discovery::DiscoveryService;
+use crate::units::format_size;
+use crate::{AppEvent, EventCategory, LogLevel};
+use anyhow::{Result, anyhow};
+use quinn::Endpoint;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+use super::protocol::{TransferMsg, recv_msg, send_msg};
+use super::rate_limit::RateLimiter;
+use super::sender::{TransferContext, connect_verified};
+
+/// Most relays this device runs at the same time
+pub const MAX_RELAYS: usize = 4;
+
+/// A relay nobody sent a packet through for this long is closed
+pub const RELAY_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest UDP datagram
+const MAX_DATAGRAM: usize = 65_535;
+
+/// Whether this device relays for others, stored in `config.json`; off by
+/// default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayPolicy {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Most bytes per second forwarded for all relays together
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+/// The relays this device runs for paired peers
+#[derive(Default)]
+pub struct RelayService {
+    policy: RelayPolicy,
+    limiter: Option<Arc<RateLimiter>>,
+    active: Arc<AtomicUsize>,
+    /// Where the peers a relay may forward to were found
+    discovery: Option<Arc<DiscoveryService>>,
+}
+
+impl RelayService {
+    pub fn new(policy: RelayPolicy) -> Self {
+        let limiter = policy
+            .max_bytes_per_sec
+            .filter(|rate| *rate > 0)
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
+        Self {
+            policy,
+            limiter,
+            active: Arc::new(AtomicUsize::new(0)),
+            discovery: None,
+        }
+    }
+
+    /// Forward only to peers `discovery` found; without it nothing is relayed
+    pub fn with_discovery(mut self, discovery: Option<Arc<DiscoveryService>>) -> Self {
+        self.discovery = discovery;
+        self
+    }
+
+    /// Whether a relay may forward to `target`: a discovered LAN peer's
+    /// transfer port
+    fn allows(&self, target: SocketAddr) -> bool {
+        is_lan_address(target.ip())
+            && self
+                .discovery
+                .as_ref()
+                .is_some_and(|ds| ds.has_peer_at(target))
+    }
+
+    pub fn policy(&self) -> &RelayPolicy {
+        &self.policy
+    }
+
+    /// Relays currently forwarding
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    fn try_reserve(&self) -> Option<RelaySlot> {
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < MAX_RELAYS).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| RelaySlot(self.active.clone()))
+    }
+}
+
+/// Whether `ip` is a private or link-local LAN address: not this device's
+/// loopback, not unspecified and not on the internet
+fn is_lan_address(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    };
+    match ip {
+        IpAddr::V4(v4) => v4.is_private() || v4.is_link_local(),
+        IpAddr::V6(v6) => v6.is_unique_local() || v6.is_unicast_link_local(),
+    }
+}
+
+/// Counts one running relay until dropped
+struct RelaySlot(Arc<AtomicUsize>);
+
+impl Drop for RelaySlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Answer a paired peer's [`TransferMsg::RelayRequest`]: open a relay from
+/// `client_ip` to `target` and tell the peer its port
+pub(super) async fn handle_relay_request(
+    service: &RelayService,
+    send: &mut quinn::SendStream,
+    event_tx: &mpsc::Sender<AppEvent>,
+    client_ip: IpAddr,
+    peer_name: &str,
+    target: SocketAddr,
+) -> Result<()> {
+    let refuse = |message: &str| TransferMsg::VerificationFailed {
+        message: message.to_string(),
+    };
+    if !service.policy.enabled {
+        send_msg(send, &refuse("This device does not relay transfers")).await?;
+        return Ok(());
+    }
+    if !service.allows(target) {
+        tracing::warn!("Refused to relay for {} to {}", peer_name, target);
+        send_msg(
+            send,
+            &refuse("This device only relays to devices it found on its network"),
+        )
+        .await?;
+        return Ok(());
+    }
+    let Some(slot) = service.try_reserve() else {
+        send_msg(send, &refuse("Too many relays running")).await?;
+        return Ok(());
+    };
+
+    let unspecified = match target {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+    let port = socket.local_addr()?.port();
+    send_msg(send, &TransferMsg::RelayReady { port }).await?;
+    let _ = send.finish();
+
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!("Relaying for {} to {} on port {}", peer_name, target, port),
+        ))
+        .await;
+
+    let limiter = service.limiter.clone();
+    let event_tx = event_tx.clone();
+    let peer_name = peer_name.to_string();
+    tokio::spawn(async move {
+        let forwarded = forward(socket, client_ip, target, limiter).await;
+        drop(slot);
+        let _ = event_tx
+            .send(AppEvent::log(
+                LogLevel::Info,
+                EventCategory::Transfer,
+                format!(
+                    "Relay for {} to {} closed after {}",
+                    peer_name,
+                    target,
+                    format_size(forwarded)
+                ),
+            ))
+            .await;
+    });
+    Ok(())
+}
+
+/// Pass packets between the client and `target` until the relay goes idle;
+/// returns the bytes forwarded
+async fn forward(
+    socket: UdpSocket,
+    client_ip: IpAddr,
+    target: SocketAddr,
+    limiter: Option<Arc<RateLimiter>>,
+) -> u64 {
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    // The client's port is only known from its first packet
+    let mut client: Option<SocketAddr> = None;
+    let mut forwarded = 0u64;
+    loop {
+        let (len, from) =
+            match tokio::time::timeout(RELAY_IDLE_TIMEOUT, socket.recv_from(&mut buf)).await {
+                Ok(Ok(received)) => received,
+                // Windows reports ICMP port unreachable on the next receive
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionReset => continue,
+                Ok(Err(e)) => {
+                    tracing::debug!("Relay to {} failed: {}", target, e);
+                    break;
+                }
+                Err(_) => break,
+            };
+        let to = if from == target {
+            match client {
+                Some(client) => client,
+                None => continue,
+            }
+        } else if from.ip() == client_ip && client.is_none_or(|client| client == from) {
+            client = Some(from);
+            target
+        } else {
+            continue;
+        };
+        if let Some(limiter) = &limiter {
+            limiter.acquire(len).await;
+        }
+        if socket.send_to(&buf[..len], to).await.is_ok() {
+            forwarded += len as u64;
+        }
+    }
+    forwarded
+}
+
+/// Ask the peer at `relay` to forward to `target`, pairing with the relay
+/// first if needed. Returns the address to send to instead of `target`.
+pub async fn request_relay(
+    endpoint: &Endpoint,
+    relay: SocketAddr,
+    target: SocketAddr,
+    event_tx: &mpsc::Sender<AppEvent>,
+    context: &TransferContext,
+    input_code_rx: &mut Option<mpsc::Receiver<String>>,
+) -> Result<SocketAddr> {
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!(
+                "Asking {} to relay to {} ({})",
+                relay, context.target_peer_name, target
+            ),
+        ))
+        .await;
+
+    let relay_context = TransferContext {
+        target_peer_name: format!("relay {}", relay.ip()),
+        ..context.clone()
+    };
+    let connection =
+        connect_verified(endpoint, relay, event_tx, &relay_context, input_code_rx).await?;
+    let result = async {
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send_msg(&mut send, &TransferMsg::RelayRequest { target }).await?;
+        match recv_msg(&mut recv).await? {
+            TransferMsg::RelayReady { port } => Ok(SocketAddr::new(relay.ip(), port)),
+            TransferMsg::VerificationFailed { message } => {
+                Err(anyhow!("Relay refused: {}", message))
+            }
+            other => Err(anyhow!("Expected RelayReady, got {:?}", other)),
+        }
+    }
+    .await;
+    context.pool.release(relay);
+    context.pool.schedule_expiry();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_slots_are_capped() {
+        let service = RelayService::new(RelayPolicy {
+            enabled: true,
+            max_bytes_per_sec: None,
+        });
+        let slots: Vec<_> = (0..MAX_RELAYS)
+            .map(|_| service.try_reserve().unwrap())
+            .collect();
+        assert!(service.try_reserve().is_none());
+        drop(slots);
+        assert_eq!(service.active(), 0);
+        assert!(service.try_reserve().is_some());
+    }
+
+    #[test]
+    fn test_only_lan_addresses_can_be_relayed_to() {
+        for lan in [
+            "192.168.1.20",
+            "10.0.0.7",
+            "172.16.5.4",
+            "169.254.1.1",
+            "fd12::1",
+            "fe80::1",
+            "::ffff:192.168.1.20",
+        ] {
+            assert!(is_lan_address(lan.parse().unwrap()), "{}", lan);
+        }
+        for other in [
+            "127.0.0.1",
+            "::1",
+            "0.0.0.0",
+            "::",
+            "8.8.8.8",
+            "2001:db8::1",
+            "::ffff:127.0.0.1",
+            "255.255.255.255",
+        ] {
+            assert!(!is_lan_address(other.parse().unwrap()), "{}", other);
+        }
+
+        // Without discovery no peer is known, so nothing is relayed
+        let service = RelayService::new(RelayPolicy {
+            enabled: true,
+            max_bytes_per_sec: None,
+        });
+        assert!(!service.allows("192.168.1.20:4433".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_forward_passes_packets_both_ways() {
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        tokio::spawn(forward(
+            relay,
+            client.local_addr().unwrap().ip(),
+            target_addr,
+            None,
+        ));
+
+        let mut buf = [0u8; 16];
+        client.send_to(b"hello", relay_addr).await.unwrap();
+        let (len, from) = target.recv_from(&mut buf).await.unwrap();
+        assert_eq!((&buf[..len], from), (&b"hello"[..], relay_addr));
+
+        target.send_to(b"back", relay_addr).await.unwrap();
+        let (len, from) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!((&buf[..len], from), (&b"back"[..], relay_addr));
+    }
+}
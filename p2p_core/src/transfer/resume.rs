@@ -0,0 +1,440 @@
+//! Checks that make resuming a partial file safe.
+//!
+//! The on-disk size alone says nothing about which transfer wrote a file.
+//! When a transfer starts from scratch the receiver keeps a small record
+//! next to the file (`<name>.p2p-resume`) with the expected hash and size
+//! and a token for that transfer. A partial file is only offered for resume
+//! when the record matches the incoming [`FileInfo`]; anything else starts
+//! over.
+//!
+//! The offer also carries the BLAKE3 hash of the bytes the receiver already
+//! has. The sender compares it with the start of its own file and sends from
+//! the beginning if they differ, echoing the token so the receiver knows the
+//! answer belongs to its offer. The final hash check still covers the whole
+//! file.
+//!
+//! Records are matched on content, not name: a partial file in the same
+//! folder whose record has the same hash and size is resumed, whatever it
+//! is called. Moving or renaming the file on the sender therefore does not
+//! lose the bytes already received. Partial files are the part files of
+//! [`super::staging`]; one that a running transfer writes is left alone.
+//!
+//! Files on remote storage (see [`crate::storage`]) are always received from
+//! the start: they cannot be appended to.
+
+use super::hash::{HashAlgorithm, compute_file_hash_with_progress, compute_prefix_hash};
+use super::staging::{self, Staged};
+use crate::FileInfo;
+use crate::config::write_secure_file;
+use crate::storage::Storage;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Suffix of the record kept next to a partial file
+pub const RESUME_SUFFIX: &str = ".p2p-resume";
+
+/// What the receiver knows about the transfer that wrote a partial file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct ResumeRecord {
+    token: String,
+    file_hash: String,
+    pub(super) file_size: u64,
+    /// Absent in records written before senders were kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) sender: Option<PartialSender>,
+}
+
+/// The peer that sent a partial file, who can be asked to send it again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialSender {
+    pub name: String,
+    pub endpoint_id: String,
+}
+
+/// Where the receiver offers to continue one incoming file
+#[derive(Debug)]
+pub struct ResumeOffer {
+    pub offset: u64,
+    /// Hash of the first `offset` bytes on disk (`None` when starting over)
+    pub prefix_hash: Option<String>,
+    /// Token of the transfer the partial file belongs to
+    pub token: String,
+    /// The file this transfer writes to, claimed until the offer is dropped
+    pub staged: Staged,
+}
+
+/// Prefix of a file on disk that can be continued
+struct Existing {
+    offset: u64,
+    prefix_hash: String,
+    /// Token of the record, `None` for a complete file
+    token: Option<String>,
+}
+
+/// Path of the resume record for `file_path`. Part files leave room in
+/// their names for the suffix (see [`staging::part_path`]).
+pub fn record_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.as_os_str().to_owned();
+    name.push(RESUME_SUFFIX);
+    PathBuf::from(name)
+}
+
+fn new_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Offer to write `target` from scratch, into a new part file
+pub(super) fn start_over(target: &Path) -> Result<ResumeOffer> {
+    let token = new_token();
+    let staged = Staged::claim(staging::part_path(target, &token), target.to_path_buf())
+        .ok_or_else(|| anyhow!("Part file of {} is in use", target.display()))?;
+    Ok(ResumeOffer {
+        offset: 0,
+        prefix_hash: None,
+        token,
+        staged,
+    })
+}
+
+/// Offer to continue `existing` at `path`, if no other transfer claims it
+fn continue_at(path: &Path, target: &Path, existing: Existing) -> Option<ResumeOffer> {
+    let staged = Staged::claim(path.to_path_buf(), target.to_path_buf())?;
+    Some(ResumeOffer {
+        offset: existing.offset,
+        prefix_hash: Some(existing.prefix_hash),
+        token: existing.token.unwrap_or_else(new_token),
+        staged,
+    })
+}
+
+async fn read_record(path: &Path) -> Option<ResumeRecord> {
+    let content = tokio::fs::read_to_string(path).await.ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// [`read_record`] for scans that run off the async threads
+pub(super) fn read_record_blocking(path: &Path) -> Option<ResumeRecord> {
+    let content = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Decide where to continue a file received into `target` for the
+/// incoming `info`, looking at the files through `storage`.
+///
+/// A complete file at `target` whose hash already matches is offered at its
+/// full size, so nothing is sent again.
+pub async fn offer(storage: &dyn Storage, target: &Path, info: &FileInfo) -> Result<ResumeOffer> {
+    // Streams have neither
+    let (Some(expected_hash), Some(_)) = (&info.file_hash, info.file_size) else {
+        return start_over(target);
+    };
+    if !storage.is_local() {
+        return start_over(target);
+    }
+    if let Some(existing) = offer_existing(storage, target, info, expected_hash).await?
+        && existing.token.is_none()
+        && let Some(offer) = continue_at(target, target, existing)
+    {
+        return Ok(offer);
+    }
+
+    for partial in find_partials(storage, target, info, expected_hash).await {
+        if staging::is_claimed(&partial) {
+            continue;
+        }
+        if let Some(existing) = offer_existing(storage, &partial, info, expected_hash).await?
+            && let Some(offer) = continue_at(&partial, target, existing)
+        {
+            if partial != target {
+                tracing::info!(
+                    "Resuming {} from the partial copy at {}",
+                    target.display(),
+                    partial.display()
+                );
+            }
+            return Ok(offer);
+        }
+    }
+    start_over(target)
+}
+
+/// What can be continued at `file_path`, `None` to start over
+async fn offer_existing(
+    storage: &dyn Storage,
+    file_path: &Path,
+    info: &FileInfo,
+    expected_hash: &str,
+) -> Result<Option<Existing>> {
+    let Ok(stat) = storage.stat(file_path).await else {
+        return Ok(None);
+    };
+    let size = stat.len;
+    if !stat.is_file || size == 0 || info.file_size.is_none_or(|total| size > total) {
+        return Ok(None);
+    }
+
+    if Some(size) == info.file_size {
+        let hash =
+            compute_file_hash_with_progress(file_path, info.hash_algorithm, |_, _| {}).await?;
+        if hash != expected_hash {
+            return Ok(None);
+        }
+        // The sender checks the prefix with BLAKE3
+        let prefix_hash = match info.hash_algorithm {
+            HashAlgorithm::Blake3 => hash,
+            _ => compute_prefix_hash(file_path, size).await?,
+        };
+        return Ok(Some(Existing {
+            offset: size,
+            prefix_hash,
+            token: None,
+        }));
+    }
+
+    let Some(record) = read_record(&record_path(file_path)).await.filter(|record| {
+        record.file_hash == expected_hash && Some(record.file_size) == info.file_size
+    }) else {
+        return Ok(None);
+    };
+
+    Ok(Some(Existing {
+        offset: size,
+        prefix_hash: compute_prefix_hash(file_path, size).await?,
+        token: Some(record.token),
+    }))
+}
+
+/// Partial files next to `file_path` that a transfer of the same content
+/// left behind
+async fn find_partials(
+    storage: &dyn Storage,
+    file_path: &Path,
+    info: &FileInfo,
+    expected_hash: &str,
+) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let Some(dir) = file_path.parent() else {
+        return found;
+    };
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return found;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let Some(partial) = path
+            .to_str()
+            .and_then(|name| name.strip_suffix(RESUME_SUFFIX))
+            .map(PathBuf::from)
+        else {
+            continue;
+        };
+        let matches = read_record(&path).await.is_some_and(|record| {
+            record.file_hash == expected_hash && Some(record.file_size) == info.file_size
+        });
+        if !matches {
+            continue;
+        }
+        if let Ok(stat) = storage.stat(&partial).await
+            && stat.is_file
+            && stat.len > 0
+            && info.file_size.is_some_and(|total| stat.len < total)
+        {
+            found.push(partial);
+        }
+    }
+    found
+}
+
+/// Check the sender's answer to `offer` and return the offset to write from
+pub fn accept_start(offer: &ResumeOffer, offset: u64, token: &str) -> Result<u64> {
+    if token != offer.token {
+        return Err(anyhow!("Resume answer does not belong to this transfer"));
+    }
+    if offset != 0 && offset != offer.offset {
+        return Err(anyhow!(
+            "Sender asked to resume at {} but {} was offered",
+            offset,
+            offer.offset
+        ));
+    }
+    Ok(offset)
+}
+
+/// Sender side: where to start given the receiver's offer for `file_path`.
+/// Returns 0 unless the receiver's bytes match the start of our file.
+pub async fn start_offset(
+    file_path: &Path,
+    file_size: u64,
+    offset: u64,
+    prefix_hash: Option<&str>,
+) -> Result<u64> {
+    if offset == 0 || offset > file_size {
+        return Ok(0);
+    }
+    let Some(prefix_hash) = prefix_hash else {
+        return Ok(0);
+    };
+    if compute_prefix_hash(file_path, offset).await? == prefix_hash {
+        Ok(offset)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Remember which transfer, from `sender` if known, is writing `file_path`
+/// from scratch
+pub fn begin(
+    file_path: &Path,
+    info: &FileInfo,
+    token: &str,
+    sender: Option<PartialSender>,
+) -> Result<()> {
+    let (Some(file_hash), Some(file_size)) = (&info.file_hash, info.file_size) else {
+        return Ok(());
+    };
+    let record = ResumeRecord {
+        token: token.to_string(),
+        file_hash: file_hash.clone(),
+        file_size,
+        sender,
+    };
+    write_secure_file(&record_path(file_path), &serde_json::to_string(&record)?)?;
+    Ok(())
+}
+
+/// Drop the record once `file_path` is complete
+pub async fn finish(file_path: &Path) {
+    let _ = tokio::fs::remove_file(record_path(file_path)).await;
+}
+
+/// Delete a partial file that will not be resumed, with its record
+pub async fn discard(storage: &dyn Storage, file_path: &Path) {
+    let _ = storage.remove(file_path).await;
+    finish(file_path).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    fn info_for(data: &[u8]) -> FileInfo {
+        FileInfo {
+            file_name: "data.bin".to_string(),
+            file_size: Some(data.len() as u64),
+            file_path: PathBuf::new(),
+            file_hash: Some(blake3::hash(data).to_hex().to_string()),
+            hash_algorithm: Default::default(),
+            modified: None,
+            mode: None,
+            note: None,
+            xattrs: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_partial_file_needs_a_matching_record() {
+        let dir = std::env::temp_dir().join(format!("resume_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let partial = dir.join("data.bin");
+        let source = dir.join("source.bin");
+        let data: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let info = info_for(&data);
+        std::fs::write(&source, &data).unwrap();
+        std::fs::write(&partial, &data[..1000]).unwrap();
+
+        // No record: the size alone is not trusted
+        assert_eq!(
+            offer(&LocalStorage, &partial, &info).await.unwrap().offset,
+            0
+        );
+
+        begin(&partial, &info, "t1", None).unwrap();
+        let resumed = offer(&LocalStorage, &partial, &info).await.unwrap();
+        assert_eq!(resumed.offset, 1000);
+        assert_eq!(resumed.token, "t1");
+        assert_eq!(
+            start_offset(&source, 4096, 1000, resumed.prefix_hash.as_deref())
+                .await
+                .unwrap(),
+            1000
+        );
+        assert_eq!(accept_start(&resumed, 1000, "t1").unwrap(), 1000);
+        assert!(accept_start(&resumed, 1000, "other").is_err());
+        assert!(accept_start(&resumed, 500, "t1").is_err());
+
+        // Only one transfer writes the partial file
+        let concurrent = offer(&LocalStorage, &partial, &info).await.unwrap();
+        assert_eq!(concurrent.offset, 0);
+        assert_ne!(concurrent.staged.part(), partial.as_path());
+        drop((resumed, concurrent));
+
+        // Another file with the same name and size does not match the record
+        let mut other = data.clone();
+        other[0] ^= 0xff;
+        assert_eq!(
+            offer(&LocalStorage, &partial, &info_for(&other))
+                .await
+                .unwrap()
+                .offset,
+            0
+        );
+
+        // Bytes that differ from the sender's make it start over
+        std::fs::write(&partial, &other[..1000]).unwrap();
+        let mismatched = offer(&LocalStorage, &partial, &info).await.unwrap();
+        assert_eq!(mismatched.offset, 1000);
+        assert_eq!(mismatched.staged.part(), partial.as_path());
+        assert_eq!(
+            start_offset(&source, 4096, 1000, mismatched.prefix_hash.as_deref())
+                .await
+                .unwrap(),
+            0
+        );
+
+        finish(&partial).await;
+        assert!(!record_path(&partial).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_partial_file_is_found_under_a_new_name() {
+        let dir = std::env::temp_dir().join(format!("resume_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let info = info_for(&data);
+        let old_name = dir.join("old.bin");
+        std::fs::write(&old_name, &data[..1000]).unwrap();
+        begin(&old_name, &info, "t1", None).unwrap();
+
+        // Different content under the new name is never adopted
+        let other = info_for(&data[..2048]);
+        let new_name = dir.join("new.bin");
+        assert_eq!(
+            offer(&LocalStorage, &new_name, &other)
+                .await
+                .unwrap()
+                .offset,
+            0
+        );
+        assert!(old_name.exists());
+
+        let resumed = offer(&LocalStorage, &new_name, &info).await.unwrap();
+        assert_eq!(resumed.offset, 1000);
+        assert_eq!(resumed.token, "t1");
+        assert_eq!(resumed.staged.part(), old_name.as_path());
+
+        // The partial copy gets the new name once complete
+        std::fs::write(&old_name, &data).unwrap();
+        finish(&old_name).await;
+        assert_eq!(
+            resumed.staged.commit(&LocalStorage).await.unwrap(),
+            new_name
+        );
+        assert!(!old_name.exists());
+        assert_eq!(std::fs::read(&new_name).unwrap(), data);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
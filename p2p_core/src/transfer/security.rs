@@ -0,0 +1,142 @@
+//! What protects a transfer connection, shown as the lock on transfer cards.
+//!
+//! Both transports run QUIC, which always uses TLS 1.3, with rustls' ring
+//! provider. QUIC does not report the negotiated cipher suite, but every
+//! build of this app offers the same list in the same order, so the first
+//! TLS 1.3 suite of that list is the one the handshake agrees on.
+
+use rustls::pki_types::CertificateDer;
+use serde::Serialize;
+
+/// How the bytes travel between the two devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionPath {
+    /// Straight to the peer on the local network
+    Lan,
+    /// Straight to the peer over the internet (hole punched)
+    Direct,
+    /// Through an Iroh relay server (still end-to-end encrypted)
+    Relay,
+    /// Direct and relayed paths at once
+    Mixed,
+    /// No path information yet
+    Unknown,
+}
+
+impl ConnectionPath {
+    pub fn label(self) -> &'static str {
+        match self {
+            ConnectionPath::Lan => "Direct (LAN)",
+            ConnectionPath::Direct => "Direct (WAN)",
+            ConnectionPath::Relay => "Relay",
+            ConnectionPath::Mixed => "Direct + relay",
+            ConnectionPath::Unknown => "Unknown path",
+        }
+    }
+}
+
+/// Whether the peer's certificate is tied to an identity we expect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertPin {
+    /// Self-signed LAN certificate accepted as is; the verification code or
+    /// pairing is what authenticates the peer
+    Unpinned,
+    /// The certificate key is the peer's endpoint ID, checked by the handshake
+    EndpointId,
+}
+
+/// Security properties of one connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SecurityInfo {
+    pub cipher_suite: String,
+    pub cert_pin: CertPin,
+    pub path: ConnectionPath,
+    /// Short BLAKE3 fingerprint of the certificate the peer presented
+    /// (only the connecting side sees one)
+    pub fingerprint: Option<String>,
+}
+
+impl SecurityInfo {
+    /// A LAN connection of the QUIC transfer server or client
+    pub fn lan(connection: &quinn::Connection) -> Self {
+        let fingerprint = connection
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+            .and_then(|certs| certs.first().map(|cert| certificate_fingerprint(cert)));
+        Self {
+            cipher_suite: negotiated_cipher_suite(),
+            cert_pin: CertPin::Unpinned,
+            path: ConnectionPath::Lan,
+            fingerprint,
+        }
+    }
+
+    /// An Iroh connection, which authenticates the peer by its endpoint ID
+    pub fn wan(path: ConnectionPath) -> Self {
+        Self {
+            cipher_suite: negotiated_cipher_suite(),
+            cert_pin: CertPin::EndpointId,
+            path,
+            fingerprint: None,
+        }
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.cert_pin == CertPin::EndpointId
+    }
+
+    /// Multi-line description for a hover tooltip
+    pub fn details(&self) -> String {
+        let pin = match self.cert_pin {
+            CertPin::Unpinned => {
+                "Certificate: self-signed, not pinned (peer checked by code/pairing)"
+            }
+            CertPin::EndpointId => "Certificate: pinned to the peer's endpoint ID",
+        };
+        let mut details = format!(
+            "Encryption: QUIC / TLS 1.3, {}\n{}\nPath: {}",
+            self.cipher_suite,
+            pin,
+            self.path.label()
+        );
+        if let Some(fingerprint) = &self.fingerprint {
+            details.push_str(&format!("\nFingerprint: {}", fingerprint));
+        }
+        details
+    }
+}
+
+/// The TLS 1.3 suite two instances of this app agree on
+pub fn negotiated_cipher_suite() -> String {
+    rustls::crypto::ring::default_provider()
+        .cipher_suites
+        .iter()
+        .find(|suite| suite.tls13().is_some())
+        .and_then(|suite| suite.suite().as_str())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+fn certificate_fingerprint(cert: &CertificateDer<'_>) -> String {
+    let hash = blake3::hash(cert.as_ref()).to_hex();
+    hash[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wan_info_is_pinned_and_described() {
+        let info = SecurityInfo::wan(ConnectionPath::Relay);
+        assert!(info.is_pinned());
+        assert!(info.cipher_suite.starts_with("TLS13_"));
+
+        let details = info.details();
+        assert!(details.contains("TLS 1.3"));
+        assert!(details.contains("Relay"));
+        assert!(!details.contains("Fingerprint"));
+    }
+}
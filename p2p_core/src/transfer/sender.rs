@@ -1,24 +1,130 @@
-use crate::{AppEvent, FileInfo};
+use crate::history::HistoryStore;
+use crate::history::transfers::{Direction, TransferRecord, TransferStatus};
+use crate::journal::JournalHandle;
+use crate::pairing::PairingStore;
+use crate::pairing::invite::PairingInvite;
+use crate::pairing::key::{derive_pair_key, session_proof, sign_nonce};
+use crate::swarm::SwarmManifest;
+use crate::units::format_size;
+use crate::{AppEvent, EventCategory, FileInfo, LogLevel};
 use anyhow::{Result, anyhow};
 use quinn::Endpoint;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::mpsc;
-
-use super::constants::BUFFER_SIZE;
-use super::hash::compute_file_hash;
+use tokio_util::sync::CancellationToken;
+use zeroize::Zeroizing;
+
+use super::ack::{self, AckMode, COMPLETION_ACK_VERSION, COMPLETION_ACK_WAIT};
+use super::buffers::transfer_buffers;
+use super::cancel::{CANCEL_CODE, TransferCancel, is_cancel_code, report_cancelled};
+use super::close::explain;
+use super::hash::{compute_file_hash_with_progress, hash_algorithm};
+use super::moves::{PendingMoves, SourceStamp};
+use super::pool::ConnectionPool;
+use super::progress::ProgressReporter;
 use super::protocol::{TransferMsg, recv_msg, send_msg};
-use super::utils::report_progress;
+use super::rate_limit::RateLimiter;
+use super::receipt;
+use super::resume;
+use super::security::SecurityInfo;
+use super::stream;
+use super::xattrs::{ExtendedAttribute, read_extended_attributes};
+
+/// Sends smaller than this are too short to time
+const RATE_SAMPLE_MIN_BYTES: u64 = 8 * 1024 * 1024;
 
 /// Context for file transfers containing peer information
 #[derive(Debug, Clone)]
 pub struct TransferContext {
+    /// Verification session, echoed in `RequestVerificationCode`
+    pub session_id: String,
     pub my_endpoint_id: String,
     pub my_name: String,
     pub target_peer_name: String,
+    /// How long the user has to enter the receiver's code
+    pub code_timeout: Duration,
+    /// Where the keys of receivers that trust us are kept
+    pub pairings: Arc<dyn PairingStore>,
+    /// Key behind `my_endpoint_id`, used to prove we own that ID
+    pub secret_key: Option<iroh::SecretKey>,
+    /// Stops the files of this send when the user cancels
+    pub cancel: Arc<TransferCancel>,
+    /// Verified connections reused by later sends to the same peer
+    pub pool: Arc<ConnectionPool>,
+    /// Where this send's progress is journaled, if it is
+    pub journal: Option<JournalHandle>,
+    /// Set for a move: sources are deleted once the receiver verified them
+    pub moves: Option<Arc<PendingMoves>>,
+    /// Note or tag sent with every file of this batch
+    pub note: Option<String>,
+    /// Where each file's outcome is logged, if anywhere
+    pub history: Option<Arc<HistoryStore>>,
+    /// Shared by the files of this send, set on a metered connection
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    /// Send each file's extended attributes (see [`super::xattrs`])
+    pub extended_attributes: bool,
+    /// Ask receivers for a signed receipt of each file (see
+    /// [`super::receipt`])
+    pub receipts: bool,
+    /// Endpoint ID of the receiver when known, from the command or
+    /// discovery; receipts are only asked of a known receiver and must name it
+    pub receiver_endpoint_id: Option<String>,
+}
+
+/// What the files of one send share, cloned into each file's task
+#[derive(Clone)]
+pub(super) struct FileOptions {
+    journal: Option<JournalHandle>,
+    moves: Option<Arc<PendingMoves>>,
+    pub(super) note: Option<String>,
+    pub(super) history: Option<Arc<HistoryStore>>,
+    pub(super) rate_limit: Option<Arc<RateLimiter>>,
+    extended_attributes: bool,
+    /// Our endpoint ID and the receiver's, set when the receiver is asked
+    /// for receipts
+    receipt_for: Option<(String, String)>,
+    /// Receiver's name, for the history
+    pub(super) peer: String,
+    /// Receiver's address, for a re-send offer
+    target: SocketAddr,
+}
+
+impl FileOptions {
+    pub(super) fn new(context: &TransferContext, target: SocketAddr) -> Self {
+        Self {
+            journal: context.journal.clone(),
+            moves: context.moves.clone(),
+            note: context.note.clone(),
+            history: context.history.clone(),
+            rate_limit: context.rate_limit.clone(),
+            extended_attributes: context.extended_attributes,
+            receipt_for: context
+                .receipts
+                .then(|| context.my_endpoint_id.clone())
+                .zip(context.receiver_endpoint_id.clone()),
+            peer: context.target_peer_name.clone(),
+            target,
+        }
+    }
+
+    pub(super) fn log(&self, record: TransferRecord) {
+        if let Some(history) = &self.history {
+            history.transfers.add(record);
+        }
+    }
+}
+
+/// What ended one pass of the sender's data loop
+pub(super) enum SendStep {
+    Sent(Result<usize>),
+    Reply(Result<TransferMsg>),
+    Cancelled,
 }
 
 /// Send files to a remote peer
@@ -28,53 +134,85 @@ pub async fn send_files(
     files: Vec<PathBuf>,
     event_tx: mpsc::Sender<AppEvent>,
     context: TransferContext,
-    input_code_rx: Option<tokio::sync::oneshot::Receiver<String>>,
+    mut input_code_rx: Option<mpsc::Receiver<String>>,
 ) -> Result<()> {
     let _ = event_tx
-        .send(AppEvent::Status(format!(
-            "Connecting to: {} ({})",
-            context.target_peer_name, target_addr
-        )))
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!(
+                "Connecting to: {} ({})",
+                context.target_peer_name, target_addr
+            ),
+        ))
         .await;
 
-    // Connect to peer
-    let connection = endpoint.connect(target_addr, "localhost")?.await?;
-
-    // Perform verification handshake
-    let (mut send_stream, mut recv_stream) = connection.open_bi().await?;
-    if let Err(e) = perform_verification_handshake(
-        &mut send_stream,
-        &mut recv_stream,
-        &event_tx,
-        context.clone(),
+    let connection = connect_verified(
+        endpoint,
         target_addr,
-        input_code_rx,
+        &event_tx,
+        &context,
+        &mut input_code_rx,
     )
-    .await
-    {
-        return Err(anyhow!("Handshake failed: {}", e));
+    .await?;
+
+    // Nothing to send: the peer was only paired
+    if files.is_empty() {
+        context.pool.release(target_addr);
+        context.pool.schedule_expiry();
+        return Ok(());
     }
 
     let _ = event_tx
-        .send(AppEvent::Status(
-            "Connected and verified. Starting file transfer...".to_string(),
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            "Connected and verified. Starting file transfer...",
         ))
         .await;
 
+    let started = (Instant::now(), connection.stats().udp_tx.bytes);
     let mut handles = Vec::new();
+    let cancel = context.cancel.token();
+    let options = FileOptions::new(&context, target_addr);
 
     for file_path in files.iter() {
         let connection = connection.clone();
         let file_path = file_path.clone();
         let event_tx = event_tx.clone();
+        let cancel = cancel.clone();
+        let options = options.clone();
 
         let handle = tokio::spawn(async move {
-            if let Err(e) = send_single_file(&connection, &file_path, &event_tx).await {
+            let result =
+                send_single_file(&connection, &file_path, &event_tx, cancel, &options).await;
+            if let Some(journal) = &options.journal
+                && result.is_ok()
+            {
+                journal.file_done(&file_path);
+            }
+            if let Err(e) = result {
+                let file_name = file_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let size = std::fs::metadata(&file_path).map_or(0, |meta| meta.len());
+                options.log(TransferRecord {
+                    path: Some(file_path.clone()),
+                    note: options.note.clone(),
+                    ..TransferRecord::new(
+                        Direction::Sent,
+                        TransferStatus::Failed,
+                        file_name,
+                        size,
+                        &options.peer,
+                    )
+                });
                 let _ = event_tx
                     .send(AppEvent::Error(format!(
                         "Error sending {}: {}",
                         file_path.display(),
-                        e
+                        explain(&e)
                     )))
                     .await;
             }
@@ -91,32 +229,210 @@ pub async fn send_files(
         }
     }
 
+    // Timed for later estimates, once enough went through to mean something
+    let sent = connection.stats().udp_tx.bytes.saturating_sub(started.1);
+    let elapsed = started.0.elapsed().as_secs_f64();
+    if sent >= RATE_SAMPLE_MIN_BYTES && elapsed > 0.0 {
+        context
+            .pool
+            .record_rate(target_addr, (sent as f64 / elapsed) as u64);
+    }
+
+    context.pool.release(target_addr);
+    context.pool.schedule_expiry();
     Ok(())
 }
 
+/// Reuse the pooled connection to `target_addr` or connect and pair
+pub(super) async fn connect_verified(
+    endpoint: &Endpoint,
+    target_addr: SocketAddr,
+    event_tx: &mpsc::Sender<AppEvent>,
+    context: &TransferContext,
+    input_code_rx: &mut Option<mpsc::Receiver<String>>,
+) -> Result<quinn::Connection> {
+    Ok(match context.pool.acquire(target_addr) {
+        // Already verified by an earlier send
+        Some(connection) => {
+            let _ = event_tx
+                .send(AppEvent::log(
+                    LogLevel::Info,
+                    EventCategory::Transfer,
+                    format!("Reusing the open connection to {}", target_addr),
+                ))
+                .await;
+            connection
+        }
+        None => {
+            // Connect to peer
+            let connection = endpoint.connect(target_addr, "localhost")?.await?;
+
+            // Perform verification handshake
+            let (mut send_stream, mut recv_stream) = connection.open_bi().await?;
+            if let Err(e) = perform_verification_handshake(
+                &connection,
+                &mut send_stream,
+                &mut recv_stream,
+                event_tx,
+                context.clone(),
+                target_addr,
+                input_code_rx,
+            )
+            .await
+            {
+                return Err(anyhow!("Handshake failed: {}", e));
+            }
+            context.pool.insert(target_addr, connection.clone());
+            connection
+        }
+    })
+}
+
+/// Offer the swarm described by `manifest` to a receiver, pairing first if
+/// needed. The receiver then fetches the pieces on its own (see
+/// [`crate::swarm`]).
+pub async fn offer_swarm(
+    endpoint: &Endpoint,
+    target_addr: SocketAddr,
+    manifest: SwarmManifest,
+    event_tx: mpsc::Sender<AppEvent>,
+    context: TransferContext,
+    mut input_code_rx: Option<mpsc::Receiver<String>>,
+) -> Result<()> {
+    let connection = connect_verified(
+        endpoint,
+        target_addr,
+        &event_tx,
+        &context,
+        &mut input_code_rx,
+    )
+    .await?;
+    let result = async {
+        let (mut send_stream, mut recv_stream) = connection.open_bi().await?;
+        send_msg(&mut send_stream, &TransferMsg::SwarmOffer { manifest }).await?;
+        match recv_msg(&mut recv_stream).await? {
+            TransferMsg::SwarmAccepted => Ok(()),
+            TransferMsg::VerificationFailed { message } => Err(anyhow!(message)),
+            other => Err(anyhow!("Expected SwarmAccepted, got {:?}", other)),
+        }
+    }
+    .await;
+    context.pool.release(target_addr);
+    context.pool.schedule_expiry();
+    result?;
+
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!("{} joined the swarm", context.target_peer_name),
+        ))
+        .await;
+    Ok(())
+}
+
+/// Pair with the receiver of a scanned invite, without sending files.
+/// Later transfers to it skip the verification code.
+pub async fn redeem_invite(
+    endpoint: &Endpoint,
+    invite: &PairingInvite,
+    event_tx: &mpsc::Sender<AppEvent>,
+    context: &TransferContext,
+) -> Result<()> {
+    let connection = endpoint.connect(invite.addr, "localhost")?.await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+
+    send_msg(
+        &mut send,
+        &Zeroizing::new(TransferMsg::InviteRedeem {
+            endpoint_id: context.my_endpoint_id.clone(),
+            peer_name: context.my_name.clone(),
+            secret: invite.secret.clone(),
+        }),
+    )
+    .await?;
+
+    let response = recv_msg(&mut recv).await;
+    // Export before closing; only kept if the receiver accepted
+    let key = derive_pair_key(&connection, &context.my_endpoint_id);
+    let _ = send.finish();
+    connection.close(0u32.into(), b"paired");
+
+    let (success, message) = match response? {
+        TransferMsg::PairingAccepted => {
+            context.pairings.add_receiver_key(&invite.peer_name, &key?);
+            (true, "Paired via QR code".to_string())
+        }
+        TransferMsg::VerificationFailed { message } => (false, message),
+        other => return Err(anyhow!("Unexpected response: {:?}", other)),
+    };
+    let _ = event_tx
+        .send(AppEvent::PairingResult {
+            session_id: context.session_id.clone(),
+            success,
+            peer_name: context.target_peer_name.clone(),
+            message: message.clone(),
+        })
+        .await;
+
+    if success {
+        Ok(())
+    } else {
+        Err(anyhow!("Invite rejected: {}", message))
+    }
+}
+
 /// Perform verification handshake on sender side
 async fn perform_verification_handshake(
+    connection: &quinn::Connection,
     send: &mut quinn::SendStream,
     recv: &mut quinn::RecvStream,
     event_tx: &mpsc::Sender<AppEvent>,
     context: TransferContext,
     target_addr: SocketAddr,
-    input_code_rx: Option<tokio::sync::oneshot::Receiver<String>>,
+    input_code_rx: &mut Option<mpsc::Receiver<String>>,
 ) -> Result<()> {
     send_msg(
         send,
         &TransferMsg::PairingRequest {
             endpoint_id: context.my_endpoint_id.clone(),
             peer_name: context.my_name.clone(),
+            note: context.note.clone(),
         },
     )
     .await?;
 
-    let msg = recv_msg(recv).await?;
+    let mut msg = recv_msg(recv).await?;
+    if let TransferMsg::PairingChallenge { key_id, nonce } = &msg {
+        // Prove we are the device that paired; without the key or the
+        // endpoint's secret key the receiver falls back to asking for a code
+        let proof = context
+            .pairings
+            .receiver_key(key_id)
+            .and_then(|key| session_proof(connection, &key).ok());
+        let signature = context
+            .secret_key
+            .as_ref()
+            .and_then(|secret| sign_nonce(connection, secret, nonce).ok());
+        send_msg(send, &TransferMsg::PairingProof { proof, signature }).await?;
+        msg = recv_msg(recv).await?;
+    }
+    // The receiver is busy with other codes; wait our turn
+    while let TransferMsg::PairingQueued { ahead } = msg {
+        let _ = event_tx
+            .send(AppEvent::PairingQueued {
+                session_id: context.session_id.clone(),
+                peer_name: context.target_peer_name.clone(),
+                ahead,
+            })
+            .await;
+        msg = recv_msg(recv).await?;
+    }
     match msg {
         TransferMsg::PairingAccepted => {
             let _ = event_tx
                 .send(AppEvent::PairingResult {
+                    session_id: context.session_id.clone(),
                     success: true,
                     peer_name: context.target_peer_name.clone(),
                     message: "Already paired.".to_string(),
@@ -127,90 +443,242 @@ async fn perform_verification_handshake(
         TransferMsg::VerificationRequired => {
             let _ = event_tx
                 .send(AppEvent::RequestVerificationCode {
+                    session_id: context.session_id.clone(),
                     target_ip: target_addr.ip().to_string(),
+                    target_name: context.target_peer_name.clone(),
                 })
                 .await;
 
             let _ = event_tx
-                .send(AppEvent::Status(
-                    "Please enter the verification code shown on the other device...".to_string(),
+                .send(AppEvent::log(
+                    LogLevel::Info,
+                    EventCategory::Pairing,
+                    "Please enter the verification code shown on the other device...",
                 ))
                 .await;
 
-            let code = if let Some(rx) = input_code_rx {
-                match rx.await {
-                    Ok(c) => c,
-                    Err(_) => return Err(anyhow!("User cancelled verification input")),
-                }
-            } else {
+            let Some(code_rx) = input_code_rx.as_mut() else {
                 return Err(anyhow!("No input channel provided for verification code"));
             };
 
-            send_msg(send, &TransferMsg::VerificationCode { code }).await?;
-
-            let result_msg = recv_msg(recv).await?;
-            match result_msg {
-                TransferMsg::VerificationSuccess => {
-                    let _ = event_tx
-                        .send(AppEvent::PairingResult {
-                            success: true,
-                            peer_name: context.target_peer_name,
-                            message: "Verification successful".to_string(),
-                        })
+            // The whole session, retries included, must finish in time
+            let deadline = tokio::time::Instant::now() + context.code_timeout;
+
+            loop {
+                let code = match tokio::time::timeout_at(deadline, code_rx.recv()).await {
+                    Ok(Some(code)) => code,
+                    Ok(None) => {
+                        cancel_session(event_tx, &context, "Verification cancelled").await;
+                        return Err(anyhow!("User cancelled verification input"));
+                    }
+                    Err(_) => {
+                        cancel_session(
+                            event_tx,
+                            &context,
+                            "Timed out waiting for the verification code",
+                        )
                         .await;
-                    Ok(())
+                        return Err(anyhow!("Verification code timed out"));
+                    }
+                };
+
+                send_msg(
+                    send,
+                    &Zeroizing::new(TransferMsg::VerificationCode { code }),
+                )
+                .await?;
+
+                let result_msg = recv_msg(recv).await?;
+                match result_msg {
+                    TransferMsg::VerificationSuccess => {
+                        let key = derive_pair_key(connection, &context.my_endpoint_id)?;
+                        context
+                            .pairings
+                            .add_receiver_key(&context.target_peer_name, &key);
+                        let _ = event_tx
+                            .send(AppEvent::PairingResult {
+                                session_id: context.session_id.clone(),
+                                success: true,
+                                peer_name: context.target_peer_name.clone(),
+                                message: "Verification successful".to_string(),
+                            })
+                            .await;
+                        return Ok(());
+                    }
+                    TransferMsg::VerificationRetry { attempts_left } => {
+                        let _ = event_tx
+                            .send(AppEvent::PairingResult {
+                                session_id: context.session_id.clone(),
+                                success: false,
+                                peer_name: context.target_peer_name.clone(),
+                                message: format!("Invalid code ({} attempts left)", attempts_left),
+                            })
+                            .await;
+                    }
+                    TransferMsg::VerificationFailed { message } => {
+                        let _ = event_tx
+                            .send(AppEvent::PairingResult {
+                                session_id: context.session_id.clone(),
+                                success: false,
+                                peer_name: context.target_peer_name.clone(),
+                                message: message.clone(),
+                            })
+                            .await;
+                        cancel_session(event_tx, &context, &message).await;
+                        return Err(anyhow!("Verification failed: {}", message));
+                    }
+                    _ => return Err(anyhow!("Unexpected response: {:?}", result_msg)),
                 }
-                TransferMsg::VerificationFailed { message } => {
-                    let _ = event_tx
-                        .send(AppEvent::PairingResult {
-                            success: false,
-                            peer_name: context.target_peer_name,
-                            message: message.clone(),
-                        })
-                        .await;
-                    Err(anyhow!("Verification failed: {}", message))
-                }
-                _ => Err(anyhow!("Unexpected response: {:?}", result_msg)),
             }
         }
+        // Refused before a code was shown (busy or locked out)
+        TransferMsg::VerificationFailed { message } => {
+            let _ = event_tx
+                .send(AppEvent::PairingResult {
+                    session_id: context.session_id.clone(),
+                    success: false,
+                    peer_name: context.target_peer_name.clone(),
+                    message: message.clone(),
+                })
+                .await;
+            Err(anyhow!("Pairing refused: {}", message))
+        }
         _ => Err(anyhow!("Unexpected handshake response: {:?}", msg)),
     }
 }
 
-/// Send a single file through the connection
+/// Tell the GUI a verification prompt is no longer answerable
+async fn cancel_session(
+    event_tx: &mpsc::Sender<AppEvent>,
+    context: &TransferContext,
+    reason: &str,
+) {
+    let _ = event_tx
+        .send(AppEvent::VerificationCancelled {
+            session_id: context.session_id.clone(),
+            reason: reason.to_string(),
+        })
+        .await;
+}
+
+/// Portable extended attributes of `path`; none, with a warning, when they
+/// can't be read
+async fn source_attributes(
+    path: &Path,
+    file_name: &str,
+    event_tx: &mpsc::Sender<AppEvent>,
+) -> Vec<ExtendedAttribute> {
+    let path = path.to_path_buf();
+    let read = tokio::task::spawn_blocking(move || read_extended_attributes(&path))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|read| read);
+    match read {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            let _ = event_tx
+                .send(AppEvent::log(
+                    LogLevel::Warning,
+                    EventCategory::Transfer,
+                    format!(
+                        "Sending {} without its extended attributes: {}",
+                        file_name, e
+                    ),
+                ))
+                .await;
+            Vec::new()
+        }
+    }
+}
+
+/// Send a single file through the connection, logging how it ended unless
+/// it fails
 async fn send_single_file(
     connection: &quinn::Connection,
     file_path: &PathBuf,
     event_tx: &mpsc::Sender<AppEvent>,
+    cancel: CancellationToken,
+    options: &FileOptions,
 ) -> Result<()> {
+    let journal = options.journal.as_ref();
+    let moves = options.moves.as_ref();
     // Open file
     let mut file = File::open(file_path).await?;
     let metadata = file.metadata().await?;
-    let file_size = metadata.len();
     let file_name = file_path
         .file_name()
         .and_then(|n| n.to_str())
         .ok_or_else(|| anyhow!("Invalid file name"))?
         .to_string();
+    // A named pipe or a device has no size to announce
+    if !metadata.is_file() {
+        return stream::stream_to(
+            connection,
+            &file_name,
+            Some(file_path),
+            file,
+            event_tx,
+            cancel,
+            options,
+        )
+        .await;
+    }
+    let file_size = metadata.len();
+    let stamp = SourceStamp::of(&metadata);
 
+    let security = SecurityInfo::lan(connection);
+    let peer_id = security.fingerprint.clone();
     let _ = event_tx
-        .send(AppEvent::Status(format!(
-            "Sending: {} ({} bytes)",
-            file_name, file_size
-        )))
+        .send(AppEvent::SecurityInfo {
+            file_name: file_name.clone(),
+            is_sending: true,
+            security,
+        })
+        .await;
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!("Sending: {} ({})", file_name, format_size(file_size)),
+        ))
         .await;
 
     // Compute hash before sending
-    let file_hash = compute_file_hash(file_path).await?;
+    let algorithm = hash_algorithm();
+    let file_hash = compute_file_hash_with_progress(file_path, algorithm, |_, _| {}).await?;
+    let record = |status| TransferRecord {
+        path: Some(file_path.clone()),
+        peer_id: peer_id.clone(),
+        hash: Some(file_hash.clone()),
+        hash_algorithm: algorithm,
+        note: options.note.clone(),
+        ..TransferRecord::new(
+            Direction::Sent,
+            status,
+            &file_name,
+            file_size,
+            &options.peer,
+        )
+    };
+    let log = |status| options.log(record(status));
 
     let (mut send_stream, mut recv_stream) = connection.open_bi().await?;
 
-    let file_info = FileInfo {
+    let mut file_info = FileInfo {
         file_name: file_name.clone(),
-        file_size,
+        file_size: Some(file_size),
         file_path: PathBuf::new(),
         file_hash: Some(file_hash.clone()),
-    };
+        hash_algorithm: algorithm,
+        modified: None,
+        mode: None,
+        note: options.note.clone(),
+        xattrs: Vec::new(),
+    }
+    .with_source_metadata(&metadata);
+    if options.extended_attributes {
+        file_info.xattrs = source_attributes(file_path, &file_name, event_tx).await;
+    }
 
     send_msg(
         &mut send_stream,
@@ -219,10 +687,66 @@ async fn send_single_file(
     .await?;
 
     let msg = recv_msg(&mut recv_stream).await?;
-    let offset = match msg {
-        TransferMsg::ResumeInfo { offset } => offset,
+    let (offered, prefix_hash, token, ack_mode, receipts) = match msg {
+        TransferMsg::ResumeInfo {
+            offset,
+            prefix_hash,
+            token,
+            ack_version,
+            receipts,
+        } => (
+            offset,
+            prefix_hash,
+            token,
+            AckMode::for_receiver(ack_version),
+            receipts,
+        ),
+        TransferMsg::VerificationFailed { message } => {
+            return Err(anyhow!("Receiver refused {}: {}", file_name, message));
+        }
         _ => return Err(anyhow!("Expected ResumeInfo, got {:?}", msg)),
     };
+    let offset =
+        resume::start_offset(file_path, file_size, offered, prefix_hash.as_deref()).await?;
+    if offset < offered {
+        let _ = event_tx
+            .send(AppEvent::log(
+                LogLevel::Warning,
+                EventCategory::Transfer,
+                format!(
+                    "Receiver's partial copy of {} does not match, sending it again",
+                    file_name
+                ),
+            ))
+            .await;
+    }
+    if cancel.is_cancelled() {
+        let reason = "Cancelled by the sender";
+        send_msg(
+            &mut send_stream,
+            &TransferMsg::Cancel {
+                reason: reason.to_string(),
+            },
+        )
+        .await?;
+        let _ = send_stream.finish();
+        log(TransferStatus::Cancelled);
+        report_cancelled(event_tx, &file_name, true, false, reason).await;
+        return Ok(());
+    }
+    let confirm_hash = moves.is_some() || ack_mode == AckMode::Versioned;
+    let want_receipt = confirm_hash && receipts && options.receipt_for.is_some();
+    send_msg(
+        &mut send_stream,
+        &TransferMsg::ResumeStart {
+            offset,
+            token,
+            confirm_hash,
+            ack_version: COMPLETION_ACK_VERSION,
+            want_receipt,
+        },
+    )
+    .await?;
 
     if offset > 0 {
         use std::io::SeekFrom;
@@ -230,73 +754,235 @@ async fn send_single_file(
     }
 
     let mut sent: u64 = offset;
-    let mut buffer = vec![0u8; BUFFER_SIZE];
-    let start_time = std::time::Instant::now();
-    let mut last_progress_update = 0u64;
-
-    report_progress(
-        event_tx, &file_name, sent, file_size, start_time, offset, true,
-    )
-    .await;
-
-    loop {
-        //Read file to buffer
-        let n = file.read(&mut buffer).await?;
+    let mut buffer = transfer_buffers().borrow(file_size.saturating_sub(offset));
+    let mut progress = ProgressReporter::new(event_tx, &file_name, file_size, offset, true);
+    if let Some(history) = &options.history {
+        progress = progress.with_usage(history.usage.clone());
+    }
+    progress.update(sent).await;
+
+    #[cfg(feature = "chaos")]
+    let chaos = super::chaos::ChaosConfig::current();
+
+    // The receiver only answers once all data is in, unless it cancels
+    // Boxed so the stream is free again for a move's hash confirmation
+    let mut reply = Box::pin(recv_msg(&mut recv_stream));
+
+    // Send exactly the announced size, even if the file changes meanwhile
+    while sent < file_size {
+        let to_read = std::cmp::min(buffer.len() as u64, file_size - sent) as usize;
+        #[cfg(feature = "chaos")]
+        let to_read = chaos
+            .as_ref()
+            .map_or(to_read, |c| to_read.min(c.chunk_size));
+        let step = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => SendStep::Cancelled,
+            msg = &mut reply => SendStep::Reply(msg),
+            chunk = async {
+                //Read file to buffer, then send it to the remote peer
+                let n = crate::profile_future!("read", file.read(&mut buffer[..to_read])).await?;
+                if let Some(limiter) = &options.rate_limit {
+                    limiter.acquire(n).await;
+                }
+                if n > 0 {
+                    #[cfg(feature = "chaos")]
+                    if let Some(config) = &chaos {
+                        let (delay, fate) = super::chaos::strike(config, sent, &mut buffer[..n]);
+                        tokio::time::sleep(delay).await;
+                        if fate == super::chaos::ChunkFate::Drop {
+                            let _ = send_stream.reset(super::chaos::CHAOS_DROP_CODE.into());
+                            return Err(anyhow!("Chaos: dropped {} at {} bytes", file_name, sent));
+                        }
+                    }
+                    send_stream.write_all(&buffer[..n]).await?;
+                }
+                Ok(n)
+            } => SendStep::Sent(chunk),
+        };
+
+        let n = match step {
+            SendStep::Sent(Ok(n)) => n,
+            SendStep::Sent(Err(e)) => {
+                let stopped = matches!(
+                    e.downcast_ref::<quinn::WriteError>(),
+                    Some(quinn::WriteError::Stopped(code)) if is_cancel_code(*code)
+                );
+                if !stopped {
+                    return Err(e);
+                }
+                // The receiver's Cancel message says why
+                let reason = match tokio::time::timeout(Duration::from_secs(1), &mut reply).await {
+                    Ok(Ok(TransferMsg::Cancel { reason })) => reason,
+                    _ => "Cancelled by the receiver".to_string(),
+                };
+                log(TransferStatus::Cancelled);
+                report_cancelled(event_tx, &file_name, true, true, &reason).await;
+                return Ok(());
+            }
+            SendStep::Reply(Ok(TransferMsg::Cancel { reason })) => {
+                log(TransferStatus::Cancelled);
+                report_cancelled(event_tx, &file_name, true, true, &reason).await;
+                return Ok(());
+            }
+            SendStep::Reply(Ok(msg)) => {
+                return Err(anyhow!("Unexpected message during transfer: {:?}", msg));
+            }
+            SendStep::Reply(Err(e)) => return Err(e),
+            SendStep::Cancelled => {
+                let _ = send_stream.reset(CANCEL_CODE.into());
+                log(TransferStatus::Cancelled);
+                report_cancelled(event_tx, &file_name, true, false, "Cancelled by the sender")
+                    .await;
+                return Ok(());
+            }
+        };
         if n == 0 {
-            break;
+            return Err(anyhow!(
+                "{} shrank during transfer ({}/{} bytes)",
+                file_name,
+                sent,
+                file_size
+            ));
         }
-        //Send buffer to remote peer
-        send_stream.write_all(&buffer[..n]).await?;
         sent += n as u64;
 
-        // Report progress more frequently (every BUFFER_SIZE = 1MB or when complete)
-        if sent == file_size || sent - last_progress_update >= BUFFER_SIZE as u64 {
-            last_progress_update = sent;
-            report_progress(
-                event_tx, &file_name, sent, file_size, start_time, offset, true,
-            )
-            .await;
+        progress.update(sent).await;
+        if let Some(journal) = journal {
+            journal.sent(file_path, sent);
         }
     }
 
-    // Finish stream
-    send_stream.finish()?;
+    // A versioned receiver may be asked again, so the stream stays open
+    if ack_mode == AckMode::Legacy {
+        send_stream.finish()?;
+    }
 
     // Wait for receiver confirmation (sent after data flush/verify)
     // Wait for TransferComplete to avoid early connection loss.
-    match recv_msg(&mut recv_stream).await {
-        Ok(TransferMsg::TransferComplete) => {
-            // Transfer confirmed by receiver
-        }
-        Ok(msg) => {
-            let _ = event_tx
-                .send(AppEvent::Error(format!(
-                    "Unexpected completion message: {:?}",
-                    msg
-                )))
-                .await;
+    let ack = ack::await_answer(
+        &mut send_stream,
+        reply.as_mut(),
+        ack_mode,
+        COMPLETION_ACK_WAIT,
+    )
+    .await
+    .map_err(|e| anyhow!("No completion ack for {}: {}", file_name, explain(&e)))?;
+    drop(reply);
+    match ack {
+        TransferMsg::TransferComplete => {}
+        TransferMsg::Cancel { reason } => {
+            log(TransferStatus::Cancelled);
+            report_cancelled(event_tx, &file_name, true, true, &reason).await;
+            return Ok(());
         }
-        Err(e) => {
-            // Handle potential connection drop or peer closure
-            let _ = event_tx
-                .send(AppEvent::Error(format!(
-                    "Failed to receive completion ack: {}",
-                    e
-                )))
-                .await;
+        msg => return Err(anyhow!("Unexpected completion message: {:?}", msg)),
+    }
+
+    let verified = if confirm_hash {
+        ack::await_hash_check(&mut send_stream, &mut recv_stream, ack_mode).await
+    } else {
+        None
+    };
+    let receipt = match (&options.receipt_for, verified) {
+        (Some((me, receiver)), Some(true)) if want_receipt => {
+            match receipt::await_receipt(&mut recv_stream, &file_hash, file_size, me, receiver)
+                .await
+            {
+                Ok(receipt) => {
+                    let _ = event_tx
+                        .send(AppEvent::log(
+                            LogLevel::Info,
+                            EventCategory::Transfer,
+                            format!("{} signed a receipt for {}", options.peer, file_name),
+                        ))
+                        .await;
+                    Some(receipt)
+                }
+                Err(e) => {
+                    let _ = event_tx
+                        .send(AppEvent::log(
+                            LogLevel::Warning,
+                            EventCategory::Transfer,
+                            format!("No valid receipt for {}: {}", file_name, e),
+                        ))
+                        .await;
+                    None
+                }
+            }
         }
+        _ => None,
+    };
+    let _ = send_stream.finish();
+
+    options.log(TransferRecord {
+        receipt,
+        ..record(TransferStatus::Completed)
+    });
+
+    // Only the receiver's own check counts; a receiver too old to report
+    // one leaves the file without a verdict
+    if let Some(verified) = verified {
+        let _ = event_tx
+            .send(AppEvent::VerificationCompleted {
+                file_name: file_name.clone(),
+                is_sending: true,
+                verified,
+            })
+            .await;
+    }
+    if verified == Some(false) {
+        let _ = event_tx
+            .send(AppEvent::ResendOffered {
+                file_name: file_name.clone(),
+                path: file_path.clone(),
+                target_ip: options.target.to_string(),
+                target_peer_name: options.peer.clone(),
+            })
+            .await;
     }
 
-    // Notify sender that transfer is complete
     let _ = event_tx
-        .send(AppEvent::VerificationCompleted {
+        .send(AppEvent::TransferCompleted {
             file_name: file_name.clone(),
-            is_sending: true,
-            verified: true, // Sender side always true (we computed the hash)
+            saved_path: None,
+            peer: None,
         })
         .await;
 
-    let _ = event_tx.send(AppEvent::TransferCompleted(file_name)).await;
+    if let Some(moves) = moves {
+        match verified {
+            Some(true) => {
+                moves
+                    .schedule(file_path.clone(), file_name.clone(), stamp)
+                    .await;
+            }
+            Some(false) => {
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Warning,
+                        EventCategory::Transfer,
+                        format!(
+                            "The receiver could not verify {}; the original is kept",
+                            file_name
+                        ),
+                    ))
+                    .await;
+            }
+            None => {
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Warning,
+                        EventCategory::Transfer,
+                        format!(
+                            "The receiver did not confirm {}; the original is kept",
+                            file_name
+                        ),
+                    ))
+                    .await;
+            }
+        }
+    }
 
     Ok(())
 }
@@ -1,38 +1,181 @@
-use crate::{AppEvent, pairing};
+use crate::history::HistoryStore;
+use crate::pairing::guest::{GUEST_PAIRING_EXPIRY_SECS, GuestMode, GuestPass};
+use crate::pairing::invite::InviteRegistry;
+use crate::pairing::lockout::{PairingLockout, REPORT_BLOCKED_EVERY, peer_keys};
+use crate::pairing::{self, MemoryPairingStore, PairingStore};
+use crate::storage::{LocalStorage, Storage};
+use crate::swarm::download::download as download_swarm;
+use crate::swarm::{SwarmManifest, SwarmRegistry, wire as swarm_wire};
+use crate::{AppEvent, EventCategory, LogLevel, config};
 use anyhow::{Result, anyhow};
-use quinn::Endpoint;
+use quinn::{Endpoint, VarInt};
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU32;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use zeroize::Zeroizing;
 
+use super::cancel::TransferCancel;
+use super::close::{CloseReason, explain};
+use super::constants::{
+    MAX_VERIFICATION_ATTEMPTS, VERIFICATION_FAILED_CLOSE_CODE, get_pairing_timeout,
+};
+use super::estimate::answer_estimate;
+use super::filename::{normalize_file_name, peer_folder};
+use super::limits::{ReceiveGuard, ReceiveLimits, utc_day};
+use super::metadata::clean_note;
 use super::protocol::{TransferMsg, recv_msg, send_msg};
 use super::receiver::receive_file;
+use super::relay::{RelayService, handle_relay_request};
+use super::security::SecurityInfo;
+use super::utils::validate_transfer_info;
+use super::verify::VerifyQueue;
 
-/// Run the QUIC server to accept incoming file transfers
+/// How [`run_server`] receives files
+#[derive(Clone)]
+pub struct ServerOptions {
+    pub download_dir: PathBuf,
+    /// Senders that skip the verification code
+    pub pairing_store: Arc<dyn PairingStore>,
+    /// Secrets of QR-code invites that pair without one
+    pub invites: Arc<InviteRegistry>,
+    /// While on, new pairings are guest pairings (see [`pairing::guest`])
+    pub guests: Arc<GuestMode>,
+    /// Restore the sender's timestamps and permissions
+    pub preserve_metadata: bool,
+    /// Check received files by reading them back from the disk
+    pub verify_read_back: bool,
+    /// Save each sender's files in its own subfolder of `download_dir`
+    pub per_peer_folders: bool,
+    /// Index of received files, to spot duplicates as they are verified in
+    /// the background (see [`super::verify`])
+    pub history: Arc<HistoryStore>,
+    /// [`TransferCancel::cancel_all`] stops every file being received
+    pub cancel: Arc<TransferCancel>,
+    /// Swarms whose pieces are served to other members
+    pub swarms: Arc<SwarmRegistry>,
+    /// Whether paired peers may relay through this device
+    pub relay: Arc<RelayService>,
+    /// How much one peer may send (see [`super::limits`])
+    pub limits: ReceiveLimits,
+    /// Where received files are written
+    pub storage: Arc<dyn Storage>,
+    /// Signs delivery receipts for senders that ask (see [`super::receipt`])
+    pub receipt_key: Option<iroh::SecretKey>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            download_dir: config::get_download_dir(),
+            pairing_store: Arc::new(MemoryPairingStore::default()),
+            invites: Arc::default(),
+            guests: Arc::default(),
+            preserve_metadata: true,
+            verify_read_back: false,
+            per_peer_folders: false,
+            history: Arc::default(),
+            cancel: Arc::default(),
+            swarms: Arc::default(),
+            relay: Arc::default(),
+            limits: ReceiveLimits::default(),
+            storage: Arc::new(LocalStorage),
+            receipt_key: None,
+        }
+    }
+}
+
+/// Run the QUIC server to accept incoming file transfers as `options` say.
+///
+/// Senders that keep entering wrong codes are locked out for the lifetime
+/// of the server.
 pub async fn run_server(
     endpoint: Endpoint,
     event_tx: mpsc::Sender<AppEvent>,
-    download_dir: PathBuf,
+    options: ServerOptions,
 ) {
+    let ServerOptions {
+        download_dir,
+        pairing_store,
+        invites,
+        guests,
+        preserve_metadata,
+        verify_read_back,
+        per_peer_folders,
+        history,
+        cancel,
+        swarms,
+        relay,
+        limits,
+        storage,
+        receipt_key,
+    } = options;
+    let lockout = Arc::new(PairingLockout::default());
+    let limits = Arc::new(ReceiveGuard::new(limits));
+    let verifier = VerifyQueue::spawn(history, event_tx.clone(), storage.clone(), verify_read_back);
     while let Some(incoming) = endpoint.accept().await {
+        let lockout = lockout.clone();
         let event_tx = event_tx.clone();
         let download_dir = download_dir.clone();
+        let pairing_store = pairing_store.clone();
+        let invites = invites.clone();
+        let guests = guests.clone();
+        let verifier = verifier.clone();
+        let cancel = cancel.clone();
+        let swarms = swarms.clone();
+        let relay = relay.clone();
+        let limits = limits.clone();
+        let storage = storage.clone();
+        let receipt_key = receipt_key.clone();
+        let endpoint = endpoint.clone();
 
         tokio::spawn(async move {
             match incoming.await {
                 Ok(connection) => {
                     let remote_addr = connection.remote_address();
-                    let is_authenticated = Arc::new(AtomicBool::new(false));
+                    let authenticated = Arc::new(OnceLock::new());
+                    let open_streams = Arc::new(AtomicU32::new(0));
 
                     while let Ok((mut send_stream, mut recv_stream)) = connection.accept_bi().await
                     {
+                        let stream_slot = match limits.open_stream(&open_streams) {
+                            Ok(slot) => slot,
+                            Err(e) => {
+                                tracing::warn!("Refused a stream from {}: {}", remote_addr, e);
+                                tokio::spawn(async move {
+                                    let message = e.to_string();
+                                    let _ = send_msg(
+                                        &mut send_stream,
+                                        &TransferMsg::VerificationFailed { message },
+                                    )
+                                    .await;
+                                    let _ = send_stream.finish();
+                                });
+                                continue;
+                            }
+                        };
                         let event_tx = event_tx.clone();
                         let download_dir = download_dir.clone();
-                        let is_authenticated = is_authenticated.clone();
+                        let authenticated = authenticated.clone();
+                        let pairing_store = pairing_store.clone();
+                        let invites = invites.clone();
+                        let guests = guests.clone();
+                        let verifier = verifier.clone();
+                        let swarms = swarms.clone();
+                        let relay = relay.clone();
+                        let endpoint = endpoint.clone();
+                        let cancel = cancel.clone();
+                        let lockout = lockout.clone();
+                        let limits = limits.clone();
+                        let storage = storage.clone();
+                        let receipt_key = receipt_key.clone();
+                        let connection = connection.clone();
 
                         tokio::spawn(async move {
+                            let _stream_slot = stream_slot;
                             // Read first message to determine type
                             // Use a 5s timeout for the initial message to prevent Slowloris attacks
                             let msg_result = tokio::time::timeout(
@@ -60,16 +203,24 @@ pub async fn run_server(
                                         TransferMsg::PairingRequest {
                                             endpoint_id,
                                             peer_name,
+                                            note,
                                         } => {
                                             // Handle Handshake
                                             if let Err(e) = handle_verification_handshake(
+                                                &connection,
                                                 &mut send_stream,
                                                 &mut recv_stream,
                                                 &event_tx,
-                                                remote_addr,
-                                                endpoint_id,
-                                                peer_name,
-                                                &is_authenticated,
+                                                PairingPeer {
+                                                    remote_addr,
+                                                    endpoint_id,
+                                                    peer_name,
+                                                    note,
+                                                },
+                                                &authenticated,
+                                                pairing_store.as_ref(),
+                                                &guests,
+                                                &lockout,
                                             )
                                             .await
                                             {
@@ -81,9 +232,32 @@ pub async fn run_server(
                                                     .await;
                                             }
                                         }
+                                        TransferMsg::InviteRedeem {
+                                            endpoint_id,
+                                            peer_name,
+                                            secret,
+                                        } => {
+                                            handle_invite(
+                                                &connection,
+                                                &mut send_stream,
+                                                &event_tx,
+                                                PairingPeer {
+                                                    remote_addr,
+                                                    endpoint_id,
+                                                    peer_name,
+                                                    note: None,
+                                                },
+                                                &Zeroizing::new(secret),
+                                                &authenticated,
+                                                pairing_store.as_ref(),
+                                                &guests,
+                                                &invites,
+                                            )
+                                            .await;
+                                        }
                                         TransferMsg::FileMetadata { info } => {
                                             // Check authentication
-                                            if !is_authenticated.load(Ordering::SeqCst) {
+                                            let Some(sender) = authenticated.get() else {
                                                 tracing::warn!(
                                                     "Rejected unauthenticated upload from {}",
                                                     remote_addr
@@ -98,27 +272,226 @@ pub async fn run_server(
                                                 )
                                                 .await;
                                                 return;
-                                            }
+                                            };
+                                            let guest_file = match sender
+                                                .guest
+                                                .as_ref()
+                                                .map(GuestPass::begin_file)
+                                                .transpose()
+                                            {
+                                                Ok(file) => file,
+                                                Err(reason) => {
+                                                    end_guest_connection(
+                                                        &connection,
+                                                        &mut send_stream,
+                                                        reason,
+                                                    )
+                                                    .await;
+                                                    return;
+                                                }
+                                            };
+                                            let download_dir = if per_peer_folders {
+                                                peer_folder(
+                                                    &download_dir,
+                                                    &sender.peer_name,
+                                                    &sender.endpoint_id,
+                                                )
+                                            } else {
+                                                download_dir
+                                            };
+                                            // A stream's size is unknown; it is
+                                            // charged as it arrives
+                                            let mut file_slot = match limits.begin_file(
+                                                &sender.endpoint_id,
+                                                info.file_size.unwrap_or(0),
+                                                utc_day(SystemTime::now()),
+                                            ) {
+                                                Ok(slot) => slot,
+                                                Err(e) => {
+                                                    refuse_file(
+                                                        &mut send_stream,
+                                                        &event_tx,
+                                                        &info.file_name,
+                                                        &sender.peer_name,
+                                                        e,
+                                                    )
+                                                    .await;
+                                                    return;
+                                                }
+                                            };
 
                                             // Handle File Transfer
-
-                                            if let Err(e) = receive_file(
+                                            let _ = event_tx
+                                                .send(AppEvent::SecurityInfo {
+                                                    file_name: normalize_file_name(
+                                                        &info.file_name,
+                                                        &download_dir,
+                                                    )
+                                                    .name,
+                                                    is_sending: false,
+                                                    security: SecurityInfo::lan(&connection),
+                                                })
+                                                .await;
+                                            match receive_file(
                                                 &mut send_stream,
                                                 &mut recv_stream,
                                                 &download_dir,
                                                 &event_tx,
                                                 info,
+                                                preserve_metadata,
+                                                &verifier,
+                                                cancel.token(),
+                                                &sender.peer_name,
+                                                &sender.endpoint_id,
+                                                storage.as_ref(),
+                                                receipt_key.as_ref(),
+                                                &mut file_slot,
+                                            )
+                                            .await
+                                            {
+                                                Ok(()) => {
+                                                    file_slot.complete();
+                                                    if let Some(guest_file) = guest_file {
+                                                        guest_file.complete();
+                                                        end_guest_pairing(
+                                                            pairing_store.as_ref(),
+                                                            &event_tx,
+                                                            sender,
+                                                        )
+                                                        .await;
+                                                    }
+                                                }
+                                                Err(e) => {
+                                                    let _ = event_tx
+                                                        .send(AppEvent::Error(format!(
+                                                            "Receive file error: {}",
+                                                            explain(&e)
+                                                        )))
+                                                        .await;
+                                                }
+                                            }
+                                        }
+                                        TransferMsg::SwarmOffer { manifest } => {
+                                            handle_swarm_offer(
+                                                &endpoint,
+                                                &connection,
+                                                &mut send_stream,
+                                                &event_tx,
+                                                &swarms,
+                                                &limits,
+                                                manifest,
+                                                authenticated.get(),
+                                                &download_dir,
+                                                per_peer_folders,
+                                                cancel.token(),
+                                            )
+                                            .await;
+                                        }
+                                        TransferMsg::SwarmJoin { swarm_id } => {
+                                            if let Err(e) = swarm_wire::serve(
+                                                &swarms,
+                                                &swarm_id,
+                                                remote_addr,
+                                                &mut send_stream,
+                                                &mut recv_stream,
+                                            )
+                                            .await
+                                            {
+                                                tracing::debug!(
+                                                    "Swarm stream from {} ended: {}",
+                                                    remote_addr,
+                                                    e
+                                                );
+                                            }
+                                        }
+                                        TransferMsg::RelayRequest { target } => {
+                                            let Some(sender) = authenticated.get() else {
+                                                tracing::warn!(
+                                                    "Rejected unauthenticated relay request from {}",
+                                                    remote_addr
+                                                );
+                                                let _ = send_msg(
+                                                    &mut send_stream,
+                                                    &TransferMsg::VerificationFailed {
+                                                        message: "Unauthenticated relay rejected"
+                                                            .to_string(),
+                                                    },
+                                                )
+                                                .await;
+                                                return;
+                                            };
+                                            if sender.guest.is_some() {
+                                                refuse_guest(&mut send_stream, "relay for others")
+                                                    .await;
+                                                return;
+                                            }
+                                            if let Err(e) = handle_relay_request(
+                                                &relay,
+                                                &mut send_stream,
+                                                &event_tx,
+                                                remote_addr.ip(),
+                                                &sender.peer_name,
+                                                target,
                                             )
                                             .await
                                             {
                                                 let _ = event_tx
                                                     .send(AppEvent::Error(format!(
-                                                        "Receive file error: {}",
-                                                        e
+                                                        "Relay error ({}): {}",
+                                                        remote_addr, e
                                                     )))
                                                     .await;
                                             }
                                         }
+                                        TransferMsg::EstimateRequest { files } => {
+                                            let Some(sender) = authenticated.get() else {
+                                                let _ = send_msg(
+                                                    &mut send_stream,
+                                                    &TransferMsg::VerificationFailed {
+                                                        message:
+                                                            "Unauthenticated estimate rejected"
+                                                                .to_string(),
+                                                    },
+                                                )
+                                                .await;
+                                                return;
+                                            };
+                                            if let Some(reason) =
+                                                sender.guest.as_ref().and_then(GuestPass::ended)
+                                            {
+                                                end_guest_connection(
+                                                    &connection,
+                                                    &mut send_stream,
+                                                    reason,
+                                                )
+                                                .await;
+                                                return;
+                                            }
+                                            let download_dir = if per_peer_folders {
+                                                peer_folder(
+                                                    &download_dir,
+                                                    &sender.peer_name,
+                                                    &sender.endpoint_id,
+                                                )
+                                            } else {
+                                                download_dir
+                                            };
+                                            if let Err(e) = answer_estimate(
+                                                &mut send_stream,
+                                                files,
+                                                &download_dir,
+                                                storage.as_ref(),
+                                            )
+                                            .await
+                                            {
+                                                tracing::warn!(
+                                                    "Estimate for {} failed: {}",
+                                                    remote_addr,
+                                                    explain(&e)
+                                                );
+                                            }
+                                            let _ = send_stream.finish();
+                                        }
                                         _ => {
                                             let _ = event_tx
                                                 .send(AppEvent::Error(format!(
@@ -133,7 +506,8 @@ pub async fn run_server(
                                     let _ = event_tx
                                         .send(AppEvent::Error(format!(
                                             "Error reading first message from {}: {}",
-                                            remote_addr, e
+                                            remote_addr,
+                                            explain(&e)
                                         )))
                                         .await;
                                 }
@@ -143,7 +517,10 @@ pub async fn run_server(
                 }
                 Err(e) => {
                     let _ = event_tx
-                        .send(AppEvent::Error(format!("QUIC connection error: {}", e)))
+                        .send(AppEvent::Error(format!(
+                            "QUIC connection error: {}",
+                            CloseReason::from_quinn(&e)
+                        )))
                         .await;
                 }
             }
@@ -151,25 +528,427 @@ pub async fn run_server(
     }
 }
 
+/// Identity a remote peer claims in its `PairingRequest`
+struct PairingPeer {
+    remote_addr: SocketAddr,
+    endpoint_id: String,
+    peer_name: String,
+    /// Note of the batch the sender is about to offer
+    note: Option<String>,
+}
+
+/// Sender a connection authenticated as, by pairing or by code
+#[derive(Debug)]
+struct AuthenticatedPeer {
+    endpoint_id: String,
+    peer_name: String,
+    /// Paired as a guest: one batch of files, see [`GuestPass`]
+    guest: Option<GuestPass>,
+}
+
+/// Set once the connection's sender is trusted; file streams need it
+type Authenticated = Arc<OnceLock<AuthenticatedPeer>>;
+
+/// Accept a swarm offered by a paired sender and download its file
+#[allow(clippy::too_many_arguments)]
+async fn handle_swarm_offer(
+    endpoint: &Endpoint,
+    connection: &quinn::Connection,
+    send: &mut quinn::SendStream,
+    event_tx: &mpsc::Sender<AppEvent>,
+    swarms: &SwarmRegistry,
+    limits: &ReceiveGuard,
+    manifest: SwarmManifest,
+    sender: Option<&AuthenticatedPeer>,
+    download_dir: &Path,
+    per_peer_folders: bool,
+    cancel: CancellationToken,
+) {
+    let Some(sender) = sender else {
+        tracing::warn!(
+            "Rejected unauthenticated swarm offer from {}",
+            connection.remote_address()
+        );
+        let message = "Unauthenticated transfer rejected".to_string();
+        let _ = send_msg(send, &TransferMsg::VerificationFailed { message }).await;
+        return;
+    };
+    if sender.guest.is_some() {
+        refuse_guest(send, "offer a swarm").await;
+        return;
+    }
+    if let Err(e) = manifest
+        .validate()
+        .and_then(|_| validate_transfer_info(&manifest.file_name, manifest.file_size))
+    {
+        let message = e.to_string();
+        let _ = event_tx.send(AppEvent::Error(message.clone())).await;
+        let _ = send_msg(send, &TransferMsg::VerificationFailed { message }).await;
+        return;
+    }
+    let file_slot = match limits.begin_file(
+        &sender.endpoint_id,
+        manifest.file_size,
+        utc_day(SystemTime::now()),
+    ) {
+        Ok(slot) => slot,
+        Err(e) => {
+            refuse_file(send, event_tx, &manifest.file_name, &sender.peer_name, e).await;
+            return;
+        }
+    };
+
+    let download_dir = if per_peer_folders {
+        peer_folder(download_dir, &sender.peer_name, &sender.endpoint_id)
+    } else {
+        download_dir.to_path_buf()
+    };
+    let normalized = normalize_file_name(&manifest.file_name, &download_dir);
+    for notice in normalized.notices() {
+        let _ = event_tx
+            .send(AppEvent::log(
+                LogLevel::Warning,
+                EventCategory::Transfer,
+                notice,
+            ))
+            .await;
+    }
+    if let Err(e) = crate::config::create_secure_dir_all_async(&download_dir).await {
+        let message = format!("Cannot create {}: {}", download_dir.display(), e);
+        let _ = event_tx.send(AppEvent::Error(message.clone())).await;
+        let _ = send_msg(send, &TransferMsg::VerificationFailed { message }).await;
+        return;
+    }
+    let manifest = SwarmManifest {
+        file_name: normalized.name,
+        ..manifest
+    };
+    let file_path = download_dir.join(&manifest.file_name);
+
+    let _ = event_tx
+        .send(AppEvent::SecurityInfo {
+            file_name: manifest.file_name.clone(),
+            is_sending: false,
+            security: SecurityInfo::lan(connection),
+        })
+        .await;
+    if send_msg(send, &TransferMsg::SwarmAccepted).await.is_err() {
+        return;
+    }
+    let _ = send.finish();
+
+    let seeder = SocketAddr::new(connection.remote_address().ip(), manifest.seeder_port);
+    match download_swarm(
+        endpoint,
+        swarms,
+        manifest,
+        seeder,
+        file_path,
+        &sender.peer_name,
+        event_tx,
+        cancel,
+    )
+    .await
+    {
+        Ok(()) => file_slot.complete(),
+        Err(e) => {
+            let _ = event_tx
+                .send(AppEvent::Error(format!("Swarm download error: {}", e)))
+                .await;
+        }
+    }
+}
+
+/// Tell the sender and the user why a file over the receive limits is refused
+async fn refuse_file(
+    send: &mut quinn::SendStream,
+    event_tx: &mpsc::Sender<AppEvent>,
+    file_name: &str,
+    peer_name: &str,
+    reason: anyhow::Error,
+) {
+    let message = reason.to_string();
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Warning,
+            EventCategory::Transfer,
+            format!("Refused {} from {}: {}", file_name, peer_name, message),
+        ))
+        .await;
+    let _ = send_msg(send, &TransferMsg::VerificationFailed { message }).await;
+    let _ = send.finish();
+}
+
+/// Record a pairing made by code or invite, as a guest while guest mode
+/// is on. Returns whether it is a guest pairing.
+fn add_pairing(
+    pairing_store: &dyn PairingStore,
+    guests: &GuestMode,
+    endpoint_id: &str,
+    peer_name: &str,
+    key: &str,
+) -> bool {
+    let guest = guests.is_enabled();
+    if guest {
+        pairing_store.add_guest_pairing(endpoint_id, peer_name, key);
+    } else {
+        pairing_store.add_pairing(endpoint_id, peer_name, key);
+    }
+    guest
+}
+
+/// Pass of a connection that just paired as a guest
+fn guest_pass(pairing_store: &dyn PairingStore, endpoint_id: &str) -> GuestPass {
+    // A store that keeps no pairings still ends the guest after an hour
+    GuestPass::new(
+        pairing_store
+            .guest_expiry(endpoint_id)
+            .unwrap_or_else(|| pairing::now_timestamp() + GUEST_PAIRING_EXPIRY_SECS),
+    )
+}
+
+/// Refuse what a guest pairing does not cover
+async fn refuse_guest(send: &mut quinn::SendStream, what: &str) {
+    let message = format!("A guest pairing cannot {}; pair with a code", what);
+    let _ = send_msg(send, &TransferMsg::VerificationFailed { message }).await;
+    let _ = send.finish();
+}
+
+/// Refuse a stream from a guest whose pass ended and drop its connection,
+/// so its next send pairs again
+async fn end_guest_connection(
+    connection: &quinn::Connection,
+    send: &mut quinn::SendStream,
+    reason: &str,
+) {
+    let message = reason.to_string();
+    let _ = send_msg(send, &TransferMsg::VerificationFailed { message }).await;
+    let _ = send.finish();
+    let _ = tokio::time::timeout(Duration::from_secs(2), send.stopped()).await;
+    connection.close(
+        VarInt::from_u32(VERIFICATION_FAILED_CLOSE_CODE),
+        reason.as_bytes(),
+    );
+}
+
+/// A file from a guest arrived: its pairing has served its transfer. The
+/// connection's [`GuestPass`] lets the rest of the batch finish.
+async fn end_guest_pairing(
+    pairing_store: &dyn PairingStore,
+    event_tx: &mpsc::Sender<AppEvent>,
+    sender: &AuthenticatedPeer,
+) {
+    // Paired again in full meanwhile, or already ended by another file
+    if !pairing_store.is_guest(&sender.endpoint_id) {
+        return;
+    }
+    pairing_store.remove_pairing(&sender.endpoint_id);
+    let _ = event_tx
+        .send(AppEvent::GuestPairingEnded {
+            endpoint_id: sender.endpoint_id.clone(),
+            peer_name: sender.peer_name.clone(),
+        })
+        .await;
+}
+
+/// Pair a sender that scanned one of our invite QR codes
+#[allow(clippy::too_many_arguments)]
+async fn handle_invite(
+    connection: &quinn::Connection,
+    send: &mut quinn::SendStream,
+    event_tx: &mpsc::Sender<AppEvent>,
+    peer: PairingPeer,
+    secret: &str,
+    authenticated: &Authenticated,
+    pairing_store: &dyn PairingStore,
+    guests: &GuestMode,
+    invites: &InviteRegistry,
+) {
+    if !invites.redeem(secret) {
+        tracing::warn!("Rejected invalid pairing invite from {}", peer.remote_addr);
+        let _ = send_msg(
+            send,
+            &TransferMsg::VerificationFailed {
+                message: "Invalid or expired pairing invite".to_string(),
+            },
+        )
+        .await;
+        let _ = send.finish();
+        return;
+    }
+
+    let key = match pairing::key::derive_pair_key(connection, &peer.endpoint_id) {
+        Ok(key) => key,
+        Err(e) => {
+            tracing::warn!("Pairing with {} failed: {}", peer.remote_addr, e);
+            return;
+        }
+    };
+    let guest = add_pairing(
+        pairing_store,
+        guests,
+        &peer.endpoint_id,
+        &peer.peer_name,
+        &key,
+    );
+    let _ = authenticated.set(AuthenticatedPeer {
+        endpoint_id: peer.endpoint_id.clone(),
+        peer_name: peer.peer_name.clone(),
+        guest: guest.then(|| guest_pass(pairing_store, &peer.endpoint_id)),
+    });
+    let _ = send_msg(send, &TransferMsg::PairingAccepted).await;
+    let _ = send.finish();
+    let _ = event_tx
+        .send(AppEvent::PairingResult {
+            session_id: crate::new_session_id(),
+            success: true,
+            peer_name: peer.peer_name,
+            message: "Paired via QR code".to_string(),
+        })
+        .await;
+}
+
+/// Hold a queued sender until a code prompt frees up, telling it how many
+/// senders are ahead whenever that changes
+async fn wait_for_pairing_slot(
+    send: &mut quinn::SendStream,
+    mut ticket: pairing::queue::PairingTicket,
+    remote_addr: SocketAddr,
+) -> Result<pairing::PairingGuard> {
+    let deadline = tokio::time::Instant::now() + pairing::queue::PAIRING_QUEUE_TIMEOUT;
+    let mut ahead = None;
+    loop {
+        match tokio::time::timeout_at(deadline, ticket.next(ahead)).await {
+            Ok(pairing::queue::Admission::Admitted(guard)) => return Ok(guard),
+            Ok(pairing::queue::Admission::Waiting(n)) => {
+                ahead = Some(n);
+                send_msg(
+                    send,
+                    &TransferMsg::PairingQueued {
+                        ahead: u32::try_from(n).unwrap_or(u32::MAX),
+                    },
+                )
+                .await?;
+            }
+            Err(_) => {
+                send_msg(
+                    send,
+                    &TransferMsg::VerificationFailed {
+                        message: "Timed out waiting for the host".to_string(),
+                    },
+                )
+                .await?;
+                tracing::warn!("Pairing from {} timed out in the queue", remote_addr);
+                return Err(anyhow!("Timed out in the pairing queue"));
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_verification_handshake(
+    connection: &quinn::Connection,
     send: &mut quinn::SendStream,
     recv: &mut quinn::RecvStream,
     event_tx: &mpsc::Sender<AppEvent>,
-    remote_addr: SocketAddr,
-    endpoint_id: String,
-    peer_name: String,
-    is_authenticated: &Arc<AtomicBool>,
+    peer: PairingPeer,
+    authenticated: &Authenticated,
+    pairing_store: &dyn PairingStore,
+    guests: &GuestMode,
+    lockout: &PairingLockout,
 ) -> Result<()> {
-    if pairing::is_paired(&endpoint_id) {
-        send_msg(send, &TransferMsg::PairingAccepted).await?;
-        is_authenticated.store(true, Ordering::SeqCst);
-        let _ = event_tx
-            .send(AppEvent::PairingResult {
-                success: true,
+    let PairingPeer {
+        remote_addr,
+        endpoint_id,
+        peer_name,
+        note,
+    } = peer;
+    // Ties the receiver's code prompt to its result
+    let session_id = crate::new_session_id();
+
+    // A paired endpoint ID is only trusted with proof of the pairing key
+    // and a signature by the endpoint's own secret key
+    if let Some(key) = pairing_store.pair_key(&endpoint_id) {
+        let nonce = pairing::key::new_nonce();
+        send_msg(
+            send,
+            &TransferMsg::PairingChallenge {
+                key_id: pairing::key::key_id(&key),
+                nonce: nonce.clone(),
+            },
+        )
+        .await?;
+        let (proof, signature) =
+            match tokio::time::timeout(Duration::from_secs(5), recv_msg(recv)).await {
+                Ok(Ok(TransferMsg::PairingProof { proof, signature })) => (proof, signature),
+                Ok(Ok(other)) => return Err(anyhow!("Expected PairingProof, got {:?}", other)),
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Err(anyhow!("Timed out waiting for the pairing proof")),
+            };
+
+        let has_key =
+            proof.is_some_and(|proof| pairing::key::verify_proof(connection, &key, &proof));
+        let owns_id = signature.is_some_and(|signature| {
+            pairing::key::verify_signature(connection, &endpoint_id, &nonce, &signature)
+        });
+        if has_key && owns_id {
+            send_msg(send, &TransferMsg::PairingAccepted).await?;
+            let _ = authenticated.set(AuthenticatedPeer {
+                endpoint_id: endpoint_id.clone(),
                 peer_name: peer_name.clone(),
-                message: "Previously paired".to_string(),
-            })
-            .await;
+                guest: pairing_store.guest_expiry(&endpoint_id).map(GuestPass::new),
+            });
+            let _ = event_tx
+                .send(AppEvent::PairingResult {
+                    session_id: session_id.clone(),
+                    success: true,
+                    peer_name: peer_name.clone(),
+                    message: "Previously paired".to_string(),
+                })
+                .await;
+            return Ok(());
+        }
+        tracing::warn!(
+            "{} claimed paired endpoint {} without {}",
+            remote_addr,
+            endpoint_id,
+            if has_key {
+                "a valid identity signature"
+            } else {
+                "its pairing key"
+            }
+        );
+    }
+
+    let lockout_keys = peer_keys(&endpoint_id, remote_addr.ip());
+    if let Some(blocked) = lockout.check(&lockout_keys, std::time::Instant::now()) {
+        let retry_after_secs = blocked.retry_after.as_secs().max(1);
+        send_msg(
+            send,
+            &TransferMsg::VerificationFailed {
+                message: format!(
+                    "Too many wrong codes, try again in {} seconds",
+                    retry_after_secs
+                ),
+            },
+        )
+        .await?;
+        let _ = send.finish();
+        tracing::warn!(
+            "Refused pairing from {} ({}): locked out",
+            remote_addr,
+            endpoint_id
+        );
+        if blocked.attempts == 1 || blocked.attempts % REPORT_BLOCKED_EVERY == 0 {
+            let _ = event_tx
+                .send(AppEvent::PairingBlocked {
+                    ip: remote_addr.ip().to_string(),
+                    endpoint_id,
+                    attempts: blocked.attempts,
+                    retry_after_secs,
+                })
+                .await;
+        }
         return Ok(());
     }
 
@@ -177,87 +956,137 @@ async fn handle_verification_handshake(
     // The guard is held until the end of the function scope
     let _guard = match pairing::PairingGuard::try_acquire() {
         Some(g) => g,
-        None => {
-            send_msg(
-                send,
-                &TransferMsg::VerificationFailed {
-                    message: "Too many pending verification attempts".to_string(),
-                },
-            )
-            .await?;
-            // We don't notify the user to avoid spam, but we log it
-            tracing::warn!(
-                "Rejected pairing from {}: Too many pending attempts",
-                remote_addr
-            );
-            return Err(anyhow!("Too many pending attempts"));
-        }
+        None => match pairing::PairingGuard::enqueue() {
+            Some(ticket) => wait_for_pairing_slot(send, ticket, remote_addr).await?,
+            None => {
+                send_msg(
+                    send,
+                    &TransferMsg::VerificationFailed {
+                        message: "Too many pending verification attempts".to_string(),
+                    },
+                )
+                .await?;
+                // We don't notify the user to avoid spam, but we log it
+                tracing::warn!(
+                    "Rejected pairing from {}: Too many pending attempts",
+                    remote_addr
+                );
+                return Err(anyhow!("Too many pending attempts"));
+            }
+        },
     };
 
     let code = pairing::generate_verification_code();
 
     let _ = event_tx
         .send(AppEvent::ShowVerificationCode {
-            code: code.clone(),
+            session_id: session_id.clone(),
+            code: code.to_string(),
             from_ip: remote_addr.ip().to_string(),
             from_name: peer_name.clone(),
+            note: note.as_deref().and_then(clean_note),
         })
         .await;
 
     send_msg(send, &TransferMsg::VerificationRequired).await?;
 
-    // Default 60 seconds timeout, override with P2P_PAIRING_TIMEOUT
-    let timeout_secs = std::env::var("P2P_PAIRING_TIMEOUT")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(60);
+    // Default 60 seconds for the whole session, override with P2P_PAIRING_TIMEOUT
+    let deadline = tokio::time::Instant::now() + get_pairing_timeout();
+    let mut attempts_left = MAX_VERIFICATION_ATTEMPTS;
 
-    let msg =
-        match tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), recv_msg(recv))
-            .await
-        {
+    loop {
+        let msg = match tokio::time::timeout_at(deadline, recv_msg(recv)).await {
             Ok(res) => res?,
             Err(_) => return Err(anyhow!("Verification timed out")),
         };
 
-    match msg {
-        TransferMsg::VerificationCode {
+        let TransferMsg::VerificationCode {
             code: received_code,
-        } => {
-            // Add delay to slow down brute-force attacks
-            // This holds the connection (and the guard) for 2 seconds
-            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-
-            if received_code == code {
-                pairing::add_pairing(&endpoint_id, &peer_name);
-                send_msg(send, &TransferMsg::VerificationSuccess).await?;
-                is_authenticated.store(true, Ordering::SeqCst);
-                let _ = event_tx
-                    .send(AppEvent::PairingResult {
-                        success: true,
-                        peer_name,
-                        message: "Verification successful".to_string(),
-                    })
-                    .await;
-                Ok(())
-            } else {
-                send_msg(
-                    send,
-                    &TransferMsg::VerificationFailed {
-                        message: "Invalid code".to_string(),
-                    },
-                )
-                .await?;
-                let _ = event_tx
-                    .send(AppEvent::PairingResult {
-                        success: false,
+        } = msg
+        else {
+            return Err(anyhow!("Expected VerificationCode, got {:?}", msg));
+        };
+        let received_code = Zeroizing::new(received_code);
+
+        // Add delay to slow down brute-force attacks
+        // This holds the connection (and the guard) for 2 seconds
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+
+        if pairing::codes_match(&received_code, &code) {
+            lockout.record_success(&lockout_keys);
+            let key = pairing::key::derive_pair_key(connection, &endpoint_id)?;
+            let guest = add_pairing(pairing_store, guests, &endpoint_id, &peer_name, &key);
+            send_msg(send, &TransferMsg::VerificationSuccess).await?;
+            let _ = authenticated.set(AuthenticatedPeer {
+                endpoint_id: endpoint_id.clone(),
+                peer_name: peer_name.clone(),
+                guest: guest.then(|| guest_pass(pairing_store, &endpoint_id)),
+            });
+            let _ = event_tx
+                .send(AppEvent::PairingResult {
+                    session_id,
+                    success: true,
+                    peer_name,
+                    message: "Verification successful".to_string(),
+                })
+                .await;
+            return Ok(());
+        }
+
+        attempts_left -= 1;
+        let locked_for = lockout.record_failure(&lockout_keys, std::time::Instant::now());
+        if let Some(duration) = locked_for {
+            let _ = event_tx
+                .send(AppEvent::log(
+                    LogLevel::Warning,
+                    EventCategory::Pairing,
+                    format!(
+                        "Locked out {} ({}) for {} seconds after repeated wrong codes",
                         peer_name,
-                        message: "Invalid verification code".to_string(),
-                    })
-                    .await;
-                Err(anyhow!("Verification failed: Wrong code"))
-            }
+                        remote_addr.ip(),
+                        duration.as_secs()
+                    ),
+                ))
+                .await;
+        }
+        if attempts_left > 0 && locked_for.is_none() {
+            send_msg(send, &TransferMsg::VerificationRetry { attempts_left }).await?;
+            let _ = event_tx
+                .send(AppEvent::log(
+                    LogLevel::Warning,
+                    EventCategory::Pairing,
+                    format!(
+                        "Wrong verification code from {} ({} attempts left)",
+                        peer_name, attempts_left
+                    ),
+                ))
+                .await;
+            continue;
         }
-        _ => Err(anyhow!("Expected VerificationCode, got {:?}", msg)),
+
+        send_msg(
+            send,
+            &TransferMsg::VerificationFailed {
+                message: "Invalid code".to_string(),
+            },
+        )
+        .await?;
+        let _ = event_tx
+            .send(AppEvent::PairingResult {
+                session_id,
+                success: false,
+                peer_name,
+                message: "Invalid verification code".to_string(),
+            })
+            .await;
+
+        // Let the rejection reach the sender, then drop the peer entirely
+        let _ = send.finish();
+        let _ = tokio::time::timeout(std::time::Duration::from_secs(2), send.stopped()).await;
+        connection.close(
+            VarInt::from_u32(VERIFICATION_FAILED_CLOSE_CODE),
+            b"too many wrong verification codes",
+        );
+        return Err(anyhow!("Verification failed: too many wrong codes"));
     }
 }
@@ -0,0 +1,226 @@
+//! Hole-aware writer for received files.
+//!
+//! Sparse source files (VM images, databases) arrive as long runs of zeros.
+//! Instead of writing them out, [`SparseWriter`] extends the file with
+//! `set_len`, which leaves a hole on file systems that support them and
+//! reads back as zeros everywhere else. Extending works for files opened in
+//! append mode as well, so resumed transfers stay sparse too.
+
+use crate::storage::{self, StorageFile};
+
+/// Zero runs are detected at this granularity (a common file system block)
+pub const SPARSE_BLOCK_SIZE: usize = 4096;
+
+pub struct SparseWriter {
+    file: Box<dyn StorageFile>,
+    /// Bytes written or skipped so far, i.e. the logical file length
+    len: u64,
+    /// Zero bytes not yet materialized as a hole
+    pending_zeros: u64,
+    /// Total zero bytes turned into holes
+    skipped: u64,
+}
+
+impl SparseWriter {
+    /// Wrap `file`, whose current length is `offset`
+    pub fn new(file: Box<dyn StorageFile>, offset: u64) -> Self {
+        Self {
+            file,
+            len: offset,
+            pending_zeros: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Reserve the space of a file received to `total` bytes, unless the
+    /// file keeps holes: reserving would allocate them after all
+    pub async fn preallocate(&mut self, total: u64) -> std::io::Result<()> {
+        if self.file.supports_holes() {
+            return Ok(());
+        }
+        storage::preallocate(self.file.as_mut(), total).await
+    }
+
+    /// Write `data`, turning whole zero blocks into holes
+    pub async fn write_chunk(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let mut dense_start = None;
+
+        for (index, block) in data.chunks(SPARSE_BLOCK_SIZE).enumerate() {
+            let start = index * SPARSE_BLOCK_SIZE;
+            let is_hole = block.len() == SPARSE_BLOCK_SIZE && block.iter().all(|&b| b == 0);
+
+            if is_hole {
+                if let Some(dense) = dense_start.take() {
+                    self.write_dense(&data[dense..start]).await?;
+                }
+                self.pending_zeros += block.len() as u64;
+            } else if dense_start.is_none() {
+                dense_start = Some(start);
+            }
+        }
+
+        if let Some(dense) = dense_start {
+            self.write_dense(&data[dense..]).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_dense(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.materialize_holes().await?;
+        self.file.write_all(data).await?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+
+    async fn materialize_holes(&mut self) -> std::io::Result<()> {
+        if self.pending_zeros > 0 {
+            self.len += self.pending_zeros;
+            self.file.set_len(self.len).await?;
+            self.skipped += self.pending_zeros;
+            self.pending_zeros = 0;
+        }
+        Ok(())
+    }
+
+    /// Logical length of the file, including pending holes
+    pub fn len(&self) -> u64 {
+        self.len + self.pending_zeros
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Zero bytes that were skipped instead of written
+    pub fn bytes_skipped(&self) -> u64 {
+        self.skipped + self.pending_zeros
+    }
+
+    /// Extend the file over any trailing hole and flush
+    pub async fn finish(&mut self) -> std::io::Result<()> {
+        self.materialize_holes().await?;
+        self.file.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::utils::open_secure_file;
+    use std::io::{Read, Seek, SeekFrom};
+
+    /// Whether the temp directory's file system stores holes at all
+    fn supports_holes(dir: &std::path::Path) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let probe = dir.join(format!("hole_probe_{}", uuid::Uuid::new_v4()));
+            let file = std::fs::File::create(&probe).unwrap();
+            file.set_len(1024 * 1024).unwrap();
+            let blocks = file.metadata().unwrap().blocks();
+            let _ = std::fs::remove_file(&probe);
+            blocks == 0
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = dir;
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zero_runs_become_holes() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("sparse_test_{}.bin", uuid::Uuid::new_v4()));
+
+        let file = open_secure_file(&path, 0).await.unwrap();
+        let mut writer = SparseWriter::new(Box::new(file), 0);
+
+        let mut chunk = vec![0u8; 1024 * 1024];
+        chunk[..3].copy_from_slice(b"abc");
+        writer.write_chunk(&chunk).await.unwrap();
+        writer
+            .write_chunk(&vec![0u8; 4 * 1024 * 1024])
+            .await
+            .unwrap();
+        // Unaligned tail is always written densely
+        writer.write_chunk(&[0u8; 100]).await.unwrap();
+        writer.finish().await.unwrap();
+
+        let expected_len = 5 * 1024 * 1024 + 100;
+        assert_eq!(writer.len(), expected_len);
+        assert!(writer.bytes_skipped() >= 4 * 1024 * 1024);
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data.len() as u64, expected_len);
+        assert_eq!(&data[..3], b"abc");
+        assert!(data[3..].iter().all(|&b| b == 0));
+
+        #[cfg(unix)]
+        if supports_holes(&dir) {
+            use std::os::unix::fs::MetadataExt;
+            let allocated = std::fs::metadata(&path).unwrap().blocks() * 512;
+            assert!(allocated < 2 * 1024 * 1024, "allocated {} bytes", allocated);
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_preallocation_keeps_holes() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("sparse_prealloc_{}.bin", uuid::Uuid::new_v4()));
+        let total = 16 * 1024 * 1024;
+
+        let file = open_secure_file(&path, 0).await.unwrap();
+        let mut writer = SparseWriter::new(Box::new(file), 0);
+        writer.preallocate(total).await.unwrap();
+        writer.write_chunk(b"head").await.unwrap();
+        writer
+            .write_chunk(&vec![0u8; total as usize - 8])
+            .await
+            .unwrap();
+        writer.write_chunk(b"tail").await.unwrap();
+        writer.finish().await.unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), total);
+
+        #[cfg(unix)]
+        if supports_holes(&dir) {
+            use std::os::unix::fs::MetadataExt;
+            let allocated = std::fs::metadata(&path).unwrap().blocks() * 512;
+            assert!(allocated < 1024 * 1024, "allocated {} bytes", allocated);
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_resume_past_4gb() {
+        let path = std::env::temp_dir().join(format!("sparse_4gb_{}.bin", uuid::Uuid::new_v4()));
+        let offset: u64 = 5 * 1024 * 1024 * 1024 + 7;
+
+        // Partial download of a huge file, created sparse so the test is cheap
+        {
+            let file = open_secure_file(&path, 0).await.unwrap();
+            file.set_len(offset).await.unwrap();
+        }
+
+        let file = open_secure_file(&path, offset).await.unwrap();
+        let mut writer = SparseWriter::new(Box::new(file), offset);
+        writer.write_chunk(&vec![0u8; 64 * 1024]).await.unwrap();
+        writer.write_chunk(b"tail").await.unwrap();
+        writer.finish().await.unwrap();
+
+        let total = offset + 64 * 1024 + 4;
+        assert_eq!(writer.len(), total);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), total);
+
+        let mut file = std::fs::File::open(&path).unwrap();
+        file.seek(SeekFrom::Start(total - 4)).unwrap();
+        let mut tail = [0u8; 4];
+        file.read_exact(&mut tail).unwrap();
+        assert_eq!(&tail, b"tail");
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
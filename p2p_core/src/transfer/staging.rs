@@ -0,0 +1,216 @@
+//! Where an incoming file is written until it is complete.
+//!
+//! Each transfer writes to a part file of its own next to the target,
+//! `<name>.<token>.p2p-part`, and holds a claim on it, so two peers sending
+//! `report.pdf` at once never write into the same file. When the transfer
+//! ends, [`Staged::commit`] renames the part to its final name under one
+//! lock. A name that another transfer took while this one ran gets a
+//! numbered variant (`report (1).pdf`); an older file of that name is
+//! replaced, as a repeated send always did.
+
+use super::constants::MAX_FILENAME_LENGTH;
+use super::resume::RESUME_SUFFIX;
+use crate::storage::Storage;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+/// Suffix of a file still being received
+pub const PART_SUFFIX: &str = ".p2p-part";
+
+/// Part files in use and names committed while transfers run
+#[derive(Default)]
+struct Registry {
+    claimed: HashSet<PathBuf>,
+    /// Final name -> commit number; cleared when no transfer runs
+    committed: HashMap<PathBuf, u64>,
+    commits: u64,
+}
+
+fn registry() -> MutexGuard<'static, Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Serializes renames to final names
+fn commit_lock() -> &'static tokio::sync::Mutex<()> {
+    static LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(Default::default)
+}
+
+/// Part file of the transfer with `token` into `target`. Long names are cut
+/// short so the part name, and the name of its resume record, still fit in
+/// [`MAX_FILENAME_LENGTH`].
+pub fn part_path(target: &Path, token: &str) -> PathBuf {
+    let short: String = token.chars().take(12).collect();
+    let suffix = format!(".{}{}", short, PART_SUFFIX);
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let budget = MAX_FILENAME_LENGTH - suffix.len() - RESUME_SUFFIX.len();
+    let mut end = name.len().min(budget);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    target.with_file_name(format!("{}{}", &name[..end], suffix))
+}
+
+/// Whether a running transfer writes to `part`
+pub fn is_claimed(part: &Path) -> bool {
+    registry().claimed.contains(part)
+}
+
+/// A transfer's claim on the file it writes; released when dropped
+#[derive(Debug)]
+pub struct Staged {
+    part: PathBuf,
+    target: PathBuf,
+    /// Commits made before this transfer started
+    started: u64,
+}
+
+impl Staged {
+    /// Claim `part` for a transfer into `target`; `None` when another
+    /// transfer writes to it
+    pub fn claim(part: PathBuf, target: PathBuf) -> Option<Self> {
+        let mut registry = registry();
+        if !registry.claimed.insert(part.clone()) {
+            return None;
+        }
+        Some(Self {
+            part,
+            target,
+            started: registry.commits,
+        })
+    }
+
+    /// The file to write to
+    pub fn part(&self) -> &Path {
+        &self.part
+    }
+
+    /// The name the file is meant to get
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Give the complete part its final name and return it
+    pub async fn commit(self, storage: &dyn Storage) -> io::Result<PathBuf> {
+        if self.part == self.target {
+            return Ok(self.target.clone());
+        }
+        let _lock = commit_lock().lock().await;
+        let mut name = self.target.clone();
+        let mut n = 0;
+        while self.taken_since_start(&name) || (n > 0 && exists(storage, &name).await) {
+            n += 1;
+            name = numbered(&self.target, n);
+        }
+        storage.rename(&self.part, &name).await?;
+        if n > 0 {
+            tracing::info!(
+                "{} was received meanwhile, saved as {}",
+                self.target.display(),
+                name.display()
+            );
+        }
+        let mut registry = registry();
+        registry.commits += 1;
+        let commit = registry.commits;
+        registry.committed.insert(name.clone(), commit);
+        Ok(name)
+    }
+
+    fn taken_since_start(&self, name: &Path) -> bool {
+        registry()
+            .committed
+            .get(name)
+            .is_some_and(|&commit| commit > self.started)
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let mut registry = registry();
+        registry.claimed.remove(&self.part);
+        if registry.claimed.is_empty() {
+            registry.committed.clear();
+        }
+    }
+}
+
+async fn exists(storage: &dyn Storage, path: &Path) -> bool {
+    match storage.stat(path).await {
+        Ok(_) => true,
+        Err(e) => e.kind() != io::ErrorKind::NotFound,
+    }
+}
+
+/// `report (n).pdf` for `report.pdf`
+fn numbered(target: &Path, n: u32) -> PathBuf {
+    let stem = target
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let name = match target.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    target.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    #[tokio::test]
+    async fn test_concurrent_transfers_keep_both_files() {
+        let dir = std::env::temp_dir().join(format!("staging_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("report.pdf");
+        std::fs::write(&target, b"old").unwrap();
+
+        let first = Staged::claim(part_path(&target, "aaaa"), target.clone()).unwrap();
+        let second = Staged::claim(part_path(&target, "bbbb"), target.clone()).unwrap();
+        assert!(Staged::claim(first.part().to_path_buf(), target.clone()).is_none());
+        assert!(is_claimed(second.part()));
+        std::fs::write(first.part(), b"first").unwrap();
+        std::fs::write(second.part(), b"second").unwrap();
+
+        // The older file is replaced, the name taken meanwhile is not
+        assert_eq!(first.commit(&LocalStorage).await.unwrap(), target);
+        let renamed = second.commit(&LocalStorage).await.unwrap();
+        assert_eq!(renamed, dir.join("report (1).pdf"));
+        assert_eq!(std::fs::read(&target).unwrap(), b"first");
+        assert_eq!(std::fs::read(&renamed).unwrap(), b"second");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_part_and_record_names_of_longest_name_fit() {
+        let dir = std::env::temp_dir().join(format!("staging_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Multi-byte characters, so the cut has to find a char boundary
+        let name = format!("{}.txt", "é".repeat((MAX_FILENAME_LENGTH - 4) / 2));
+        let target = dir.join(&name);
+        std::fs::write(&target, b"complete").unwrap();
+
+        let part = part_path(&target, "0123456789abcdef");
+        let part_name = part.file_name().unwrap().to_str().unwrap();
+        assert!(part_name.len() + RESUME_SUFFIX.len() <= MAX_FILENAME_LENGTH);
+        assert!(part_name.starts_with("éé"));
+        assert!(part_name.ends_with(".0123456789ab.p2p-part"));
+        std::fs::write(&part, b"partial").unwrap();
+        std::fs::write(crate::transfer::resume::record_path(&part), b"{}").unwrap();
+        assert_ne!(part_path(&target, "fedcba9876543210"), part);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
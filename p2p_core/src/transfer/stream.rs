@@ -0,0 +1,503 @@
+//! Files of unknown size: standard input, a named pipe a live log is
+//! written to, a camera device.
+//!
+//! A regular send announces the size up front and the receiver reads
+//! exactly that many bytes. A stream cannot, so its
+//! [`TransferMsg::FileMetadata`] has no `file_size` and no hash, and after
+//! the receiver's `ReadyForData` the data goes in frames: a big-endian
+//! `u32` length and that many bytes, at most [`MAX_FRAME_SIZE`]. An empty
+//! frame ends the data, and [`TransferMsg::StreamEnd`] carries the size and
+//! hash the sender computed while reading. The receiver hashes what arrives
+//! as it writes it and answers with `VerificationResult`; a file that does
+//! not match is deleted. Progress is reported in bytes, and streams are
+//! never resumed.
+
+use crate::history::transfers::{Direction, TransferRecord, TransferStatus};
+use crate::storage::Storage;
+use crate::{AppEvent, EventCategory, FileInfo, LogLevel};
+use anyhow::{Result, anyhow};
+use quinn::Endpoint;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::buffers::transfer_buffers;
+use super::cancel::{CANCEL_CODE, is_cancel_code, report_cancelled};
+use super::hash::hash_algorithm;
+use super::limits::FileSlot;
+use super::progress::ProgressReporter;
+use super::protocol::{TransferMsg, recv_msg, send_msg};
+use super::resume;
+use super::security::SecurityInfo;
+use super::sender::{FileOptions, SendStep, TransferContext, connect_verified};
+use super::sparse::SparseWriter;
+use super::utils::validate_transfer_info;
+use super::verify::VerifyQueue;
+use super::writer::WritePipeline;
+
+/// Largest data frame of a stream
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Reader of a stream to send, carried by
+/// [`AppCommand::SendStream`](crate::AppCommand::SendStream). Commands are
+/// cloned, but the reader can only be taken once.
+#[derive(Clone)]
+pub struct StreamSource(Arc<Mutex<Option<Box<dyn AsyncRead + Send + Unpin>>>>);
+
+impl StreamSource {
+    pub fn new(reader: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        Self(Arc::new(Mutex::new(Some(Box::new(reader)))))
+    }
+
+    /// The reader, unless a send already took it
+    pub fn take(&self) -> Option<Box<dyn AsyncRead + Send + Unpin>> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+impl fmt::Debug for StreamSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StreamSource")
+    }
+}
+
+/// Send everything `source` yields to the peer at `target_addr` as a file
+/// named `file_name`, pairing first if needed
+pub async fn send_stream(
+    endpoint: &Endpoint,
+    target_addr: SocketAddr,
+    file_name: &str,
+    source: impl AsyncRead + Unpin,
+    event_tx: mpsc::Sender<AppEvent>,
+    context: TransferContext,
+    mut input_code_rx: Option<mpsc::Receiver<String>>,
+) -> Result<()> {
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!(
+                "Connecting to: {} ({})",
+                context.target_peer_name, target_addr
+            ),
+        ))
+        .await;
+    let connection = connect_verified(
+        endpoint,
+        target_addr,
+        &event_tx,
+        &context,
+        &mut input_code_rx,
+    )
+    .await?;
+    let options = FileOptions::new(&context, target_addr);
+    let result = stream_to(
+        &connection,
+        file_name,
+        None,
+        source,
+        &event_tx,
+        context.cancel.token(),
+        &options,
+    )
+    .await;
+    context.pool.release(target_addr);
+    context.pool.schedule_expiry();
+    if result.is_err() {
+        options.log(TransferRecord {
+            note: options.note.clone(),
+            ..TransferRecord::new(
+                Direction::Sent,
+                TransferStatus::Failed,
+                file_name,
+                0,
+                &options.peer,
+            )
+        });
+    }
+    result
+}
+
+/// Send `source` through the connection as a stream named `file_name`,
+/// read from `path` if it is a file such as a named pipe, logging how it
+/// ended unless it fails
+pub(super) async fn stream_to(
+    connection: &quinn::Connection,
+    file_name: &str,
+    path: Option<&Path>,
+    mut source: impl AsyncRead + Unpin,
+    event_tx: &mpsc::Sender<AppEvent>,
+    cancel: CancellationToken,
+    options: &FileOptions,
+) -> Result<()> {
+    let algorithm = hash_algorithm();
+    let mut hasher = algorithm.hasher()?;
+    let security = SecurityInfo::lan(connection);
+    let peer_id = security.fingerprint.clone();
+    let _ = event_tx
+        .send(AppEvent::SecurityInfo {
+            file_name: file_name.to_string(),
+            is_sending: true,
+            security,
+        })
+        .await;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_msg(
+        &mut send,
+        &TransferMsg::FileMetadata {
+            info: FileInfo {
+                file_name: file_name.to_string(),
+                file_size: None,
+                file_path: PathBuf::new(),
+                file_hash: None,
+                hash_algorithm: algorithm,
+                modified: None,
+                mode: None,
+                note: options.note.clone(),
+                xattrs: Vec::new(),
+            },
+        },
+    )
+    .await?;
+    match recv_msg(&mut recv).await? {
+        TransferMsg::ReadyForData => {}
+        TransferMsg::VerificationFailed { message } => {
+            return Err(anyhow!("Receiver refused {}: {}", file_name, message));
+        }
+        other => return Err(anyhow!("Expected ReadyForData, got {:?}", other)),
+    }
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!("Streaming: {}", file_name),
+        ))
+        .await;
+
+    let log = |status, size, hash: Option<String>| {
+        options.log(TransferRecord {
+            path: path.map(Path::to_path_buf),
+            peer_id: peer_id.clone(),
+            hash,
+            hash_algorithm: algorithm,
+            note: options.note.clone(),
+            ..TransferRecord::new(Direction::Sent, status, file_name, size, &options.peer)
+        });
+    };
+
+    let mut buffer = transfer_buffers().borrow(u64::MAX);
+    let frame = buffer.len().min(MAX_FRAME_SIZE);
+    let mut sent: u64 = 0;
+    let mut progress = ProgressReporter::for_stream(event_tx, file_name, true);
+    if let Some(history) = &options.history {
+        progress = progress.with_usage(history.usage.clone());
+    }
+    // The receiver only answers after the end, unless it cancels
+    let mut reply = Box::pin(recv_msg(&mut recv));
+
+    loop {
+        let step = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => SendStep::Cancelled,
+            msg = &mut reply => SendStep::Reply(msg),
+            chunk = async {
+                let n = source.read(&mut buffer[..frame]).await?;
+                if n > 0 {
+                    if let Some(limiter) = &options.rate_limit {
+                        limiter.acquire(n).await;
+                    }
+                    send.write_all(&(n as u32).to_be_bytes()).await?;
+                    send.write_all(&buffer[..n]).await?;
+                }
+                Ok(n)
+            } => SendStep::Sent(chunk),
+        };
+        let n = match step {
+            SendStep::Sent(Ok(n)) => n,
+            SendStep::Sent(Err(e)) => {
+                let stopped = matches!(
+                    e.downcast_ref::<quinn::WriteError>(),
+                    Some(quinn::WriteError::Stopped(code)) if is_cancel_code(*code)
+                );
+                if !stopped {
+                    return Err(e);
+                }
+                let reason = match tokio::time::timeout(Duration::from_secs(1), &mut reply).await {
+                    Ok(Ok(TransferMsg::Cancel { reason })) => reason,
+                    _ => "Cancelled by the receiver".to_string(),
+                };
+                log(TransferStatus::Cancelled, sent, None);
+                report_cancelled(event_tx, file_name, true, true, &reason).await;
+                return Ok(());
+            }
+            SendStep::Reply(Ok(TransferMsg::Cancel { reason })) => {
+                log(TransferStatus::Cancelled, sent, None);
+                report_cancelled(event_tx, file_name, true, true, &reason).await;
+                return Ok(());
+            }
+            SendStep::Reply(Ok(msg)) => {
+                return Err(anyhow!("Unexpected message during transfer: {:?}", msg));
+            }
+            SendStep::Reply(Err(e)) => return Err(e),
+            SendStep::Cancelled => {
+                let _ = send.reset(CANCEL_CODE.into());
+                log(TransferStatus::Cancelled, sent, None);
+                report_cancelled(event_tx, file_name, true, false, "Cancelled by the sender").await;
+                return Ok(());
+            }
+        };
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        sent += n as u64;
+        progress.update(sent).await;
+    }
+    drop(buffer);
+    progress.complete(sent).await;
+
+    let file_hash = hasher.finalize();
+    send.write_all(&0u32.to_be_bytes()).await?;
+    send_msg(
+        &mut send,
+        &TransferMsg::StreamEnd {
+            size: sent,
+            file_hash: file_hash.clone(),
+        },
+    )
+    .await?;
+    let verified = match reply.await? {
+        TransferMsg::VerificationResult { verified } => verified,
+        TransferMsg::Cancel { reason } => {
+            log(TransferStatus::Cancelled, sent, None);
+            report_cancelled(event_tx, file_name, true, true, &reason).await;
+            return Ok(());
+        }
+        other => return Err(anyhow!("Expected VerificationResult, got {:?}", other)),
+    };
+    let _ = send.finish();
+
+    log(
+        if verified {
+            TransferStatus::Completed
+        } else {
+            TransferStatus::Failed
+        },
+        sent,
+        Some(file_hash),
+    );
+    let _ = event_tx
+        .send(AppEvent::VerificationCompleted {
+            file_name: file_name.to_string(),
+            is_sending: true,
+            verified,
+        })
+        .await;
+    let _ = event_tx
+        .send(AppEvent::TransferCompleted {
+            file_name: file_name.to_string(),
+            saved_path: None,
+            peer: None,
+        })
+        .await;
+    Ok(())
+}
+
+/// Receive the stream `file_info` announces into `download_dir`, through
+/// `storage`, checking it as it arrives. Called by
+/// [`receive_file`](super::receiver::receive_file), which already checked
+/// and normalized the name.
+///
+/// `peer` names the sender in the completion event and, with its endpoint
+/// ID `peer_id`, in the history. Cancelling `cancel` stops the stream and
+/// deletes what arrived, as does going over the daily quota of `file_slot`.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn receive_stream(
+    send: &mut quinn::SendStream,
+    recv: &mut quinn::RecvStream,
+    download_dir: &Path,
+    event_tx: &mpsc::Sender<AppEvent>,
+    file_info: FileInfo,
+    verifier: &VerifyQueue,
+    cancel: CancellationToken,
+    peer: &str,
+    peer_id: &str,
+    storage: &dyn Storage,
+    file_slot: &mut FileSlot,
+) -> Result<()> {
+    let algorithm = file_info.hash_algorithm;
+    // Nothing to check the stream against afterwards
+    let mut hasher = match algorithm.hasher() {
+        Ok(hasher) => hasher,
+        Err(e) => {
+            let message = e.to_string();
+            let _ = send_msg(send, &TransferMsg::VerificationFailed { message }).await;
+            return Err(e);
+        }
+    };
+    let FileInfo {
+        file_name, note, ..
+    } = file_info;
+
+    crate::config::create_secure_dir_all_async(download_dir).await?;
+    let staged = resume::start_over(&download_dir.join(&file_name))?.staged;
+    let part = staged.part().to_path_buf();
+    let file = storage.create(&part).await?;
+    let mut file = WritePipeline::spawn(SparseWriter::new(file, 0));
+    send_msg(send, &TransferMsg::ReadyForData).await?;
+
+    let record = |status, size, hash: Option<String>, path: &Path| TransferRecord {
+        path: Some(path.to_path_buf()),
+        peer_id: Some(peer_id.to_string()),
+        hash,
+        hash_algorithm: algorithm,
+        note: note.clone(),
+        ..TransferRecord::new(Direction::Received, status, &file_name, size, peer)
+    };
+
+    let mut buffer = vec![0u8; MAX_FRAME_SIZE];
+    let mut received: u64 = 0;
+    let mut progress = ProgressReporter::for_stream(event_tx, &file_name, false)
+        .with_usage(verifier.history().usage.clone());
+    loop {
+        let frame = tokio::select! {
+            frame = read_frame(recv, &mut buffer) => frame,
+            _ = cancel.cancelled() => {
+                let reason = "Cancelled by the receiver";
+                let _ = send_msg(send, &TransferMsg::Cancel { reason: reason.to_string() }).await;
+                let _ = recv.stop(CANCEL_CODE.into());
+                let _ = file.finish().await;
+                resume::discard(storage, &part).await;
+                verifier.log(record(TransferStatus::Cancelled, received, None, &part));
+                report_cancelled(event_tx, &file_name, false, false, reason).await;
+                return Ok(());
+            }
+        };
+        let n = match frame {
+            Ok(n) => n,
+            Err(e) => {
+                let _ = file.finish().await;
+                resume::discard(storage, &part).await;
+                let reset = matches!(
+                    e.downcast_ref::<quinn::ReadExactError>(),
+                    Some(quinn::ReadExactError::ReadError(quinn::ReadError::Reset(code)))
+                        if is_cancel_code(*code)
+                );
+                if !reset {
+                    return Err(e);
+                }
+                verifier.log(record(TransferStatus::Cancelled, received, None, &part));
+                report_cancelled(event_tx, &file_name, false, true, "Cancelled by the sender")
+                    .await;
+                return Ok(());
+            }
+        };
+        if n == 0 {
+            break;
+        }
+        received += n as u64;
+        let allowed =
+            validate_transfer_info(&file_name, received).and_then(|()| file_slot.charge(n as u64));
+        if let Err(e) = allowed {
+            let _ = send_msg(
+                send,
+                &TransferMsg::Cancel {
+                    reason: e.to_string(),
+                },
+            )
+            .await;
+            let _ = recv.stop(CANCEL_CODE.into());
+            let _ = file.finish().await;
+            resume::discard(storage, &part).await;
+            return Err(e);
+        }
+        hasher.update(&buffer[..n]);
+        file.write(&buffer[..n]).await?;
+        progress.update(received).await;
+    }
+    file.finish().await?;
+    progress.complete(received).await;
+
+    let (size, expected_hash) = match recv_msg(recv).await? {
+        TransferMsg::StreamEnd { size, file_hash } => (size, file_hash),
+        other => {
+            resume::discard(storage, &part).await;
+            return Err(anyhow!("Expected StreamEnd, got {:?}", other));
+        }
+    };
+    let computed_hash = hasher.finalize();
+    let verified = size == received && computed_hash == expected_hash;
+
+    let saved_path = if verified {
+        let path = staged.commit(storage).await?;
+        if let Some(existing) = verifier.history().record(&computed_hash, &path, received) {
+            let _ = event_tx
+                .send(AppEvent::DuplicateReceived {
+                    file_name: file_name.clone(),
+                    path: path.clone(),
+                    existing,
+                    size: received,
+                })
+                .await;
+        }
+        verifier.log(record(
+            TransferStatus::Verified,
+            received,
+            Some(computed_hash),
+            &path,
+        ));
+        Some(path)
+    } else {
+        resume::discard(storage, &part).await;
+        verifier.log(record(TransferStatus::Failed, received, None, &part));
+        let _ = event_tx
+            .send(AppEvent::Error(format!(
+                "Hash verification FAILED for {}!",
+                file_name
+            )))
+            .await;
+        None
+    };
+    send_msg(send, &TransferMsg::VerificationResult { verified }).await?;
+    let _ = send.finish();
+
+    let _ = event_tx
+        .send(AppEvent::VerificationCompleted {
+            file_name: file_name.clone(),
+            is_sending: false,
+            verified,
+        })
+        .await;
+    if let Some(path) = saved_path {
+        let _ = event_tx
+            .send(AppEvent::TransferCompleted {
+                file_name,
+                saved_path: Some(path),
+                peer: Some(peer.to_string()),
+            })
+            .await;
+    }
+    Ok(())
+}
+
+/// Read the next frame into `buffer` and return its length, 0 for the end
+/// of the data
+async fn read_frame(recv: &mut quinn::RecvStream, buffer: &mut [u8]) -> Result<usize> {
+    let mut len = [0u8; 4];
+    recv.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > buffer.len() {
+        return Err(anyhow!(
+            "Stream frame too large: {} bytes (max {})",
+            len,
+            buffer.len()
+        ));
+    }
+    recv.read_exact(&mut buffer[..len]).await?;
+    Ok(len)
+}
@@ -1,14 +1,14 @@
-use crate::AppEvent;
-use crate::transfer::constants::{MAX_FILE_SIZE, MAX_FILENAME_LENGTH};
+use crate::transfer::constants::{MAX_FILE_SIZE, MAX_WIRE_FILENAME_LENGTH};
 use anyhow::Result;
 use rcgen::generate_simple_self_signed;
 use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
 use std::path::Path;
-use std::time::Instant;
 use tokio::fs::{File, OpenOptions};
-use tokio::sync::mpsc;
 
-/// Validate file info against security limits (size and name length)
+/// Validate file info against security limits (size and name length).
+///
+/// Names within the wire limit but over the file system limit are shortened
+/// later by [`normalize_file_name`](super::filename::normalize_file_name).
 pub fn validate_transfer_info(file_name: &str, file_size: u64) -> Result<()> {
     if file_size > MAX_FILE_SIZE {
         return Err(anyhow::anyhow!(
@@ -19,11 +19,11 @@ pub fn validate_transfer_info(file_name: &str, file_size: u64) -> Result<()> {
         ));
     }
 
-    if file_name.len() > MAX_FILENAME_LENGTH {
+    if file_name.len() > MAX_WIRE_FILENAME_LENGTH {
         return Err(anyhow::anyhow!(
             "File rejected: Filename too long ({} chars, max {})",
             file_name.len(),
-            MAX_FILENAME_LENGTH
+            MAX_WIRE_FILENAME_LENGTH
         ));
     }
     Ok(())
@@ -31,24 +31,33 @@ pub fn validate_transfer_info(file_name: &str, file_size: u64) -> Result<()> {
 
 /// Open a file with secure permissions (0o600 on Unix) for writing
 pub async fn open_secure_file(path: &Path, offset: u64) -> std::io::Result<File> {
-    let mut options = OpenOptions::new();
-    options.write(true);
-
     if offset > 0 {
-        options.append(true);
+        append_secure_file(path).await
     } else {
-        // Remove existing file to prevent TOCTOU
-        let _ = tokio::fs::remove_file(path).await;
-
-        options.create_new(true);
-        #[cfg(unix)]
-        options.mode(0o600);
+        create_secure_file(path).await
     }
+}
 
-    let file = options.open(path).await?;
+/// Create a file readable by this user only (0o600 on Unix), replacing any
+/// file at `path`
+pub async fn create_secure_file(path: &Path) -> std::io::Result<File> {
+    // Remove existing file to prevent TOCTOU
+    let _ = tokio::fs::remove_file(path).await;
 
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
     #[cfg(unix)]
-    if offset > 0 {
+    options.mode(0o600);
+    options.open(path).await
+}
+
+/// Open an existing file to write at its end, refusing files others can
+/// read or write (Unix)
+pub async fn append_secure_file(path: &Path) -> std::io::Result<File> {
+    let file = OpenOptions::new().append(true).open(path).await?;
+
+    #[cfg(unix)]
+    {
         use std::os::unix::fs::PermissionsExt;
         let metadata = file.metadata().await?;
         if metadata.permissions().mode() & 0o777 != 0o600 {
@@ -62,20 +71,12 @@ pub async fn open_secure_file(path: &Path, offset: u64) -> std::io::Result<File>
     Ok(file)
 }
 
-/// Format transfer speed from bytes and elapsed time
+/// Format transfer speed from bytes and elapsed time, in the user's units
 pub fn format_transfer_speed(bytes_transferred: u64, elapsed_secs: f64) -> String {
     if elapsed_secs <= 0.0 {
         return "Starting...".to_string();
     }
-
-    let speed_bps = bytes_transferred as f64 / elapsed_secs;
-    if speed_bps > 1_000_000.0 {
-        format!("{:.2} MB/s", speed_bps / 1_000_000.0)
-    } else if speed_bps > 1_000.0 {
-        format!("{:.1} KB/s", speed_bps / 1_000.0)
-    } else {
-        format!("{:.0} B/s", speed_bps)
-    }
+    crate::units::format_speed(bytes_transferred as f64 / elapsed_secs)
 }
 
 /// Generate a self-signed certificate for QUIC and HTTPS
@@ -87,78 +88,13 @@ pub fn generate_self_signed_cert()
     Ok((vec![cert_der], key))
 }
 
-pub fn sanitize_file_name(file_name: &str) -> String {
-    // 1. Get the last component using string splitting to be OS-agnostic
-    // Split by / and \ and take the last part
-    let file_name = file_name
-        .rsplit(['/', '\\'])
-        .next()
-        .unwrap_or(file_name);
-
-    // 2. Filter characters
-    // Disallow control characters, path separators, and Windows reserved characters (<>:"/\|?*)
-    // This blocklist approach ensures cross-platform compatibility while allowing most valid filenames.
-    let sanitized: String = file_name
-        .chars()
-        .filter(|c| !c.is_control() && !"<>:\"/\\|?*".contains(*c))
-        .collect();
-
-    // 3. Trim
-    let sanitized = sanitized.trim().to_string();
-
-    // 4. Handle reserved names (Windows) - Optional but good for defense in depth
-    // CON, PRN, AUX, NUL, COM1-9, LPT1-9
-    let upper = sanitized.to_ascii_uppercase();
-    let reserved = [
-        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
-        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
-    ];
-
-    // Check if it exactly matches or matches name with extension (e.g. CON.txt is invalid on Windows too)
-    let stem = std::path::Path::new(&sanitized)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("")
-        .to_ascii_uppercase();
-
-    if reserved.contains(&stem.as_str()) || reserved.contains(&upper.as_str()) {
-        return format!("_{}", sanitized);
+/// Completion percentage, computed in `f64` so multi-gigabyte sizes keep
+/// their precision. 100% is only reported once every byte is done.
+pub fn progress_percent(bytes_done: u64, total_bytes: u64) -> f32 {
+    if bytes_done >= total_bytes {
+        return 100.0;
     }
-
-    if sanitized.is_empty() || sanitized == "." || sanitized == ".." {
-        return "unknown_file.bin".to_string();
-    }
-
-    sanitized
-}
-/// Report transfer progress to the event channel
-pub async fn report_progress(
-    event_tx: &mpsc::Sender<AppEvent>,
-    file_name: &str,
-    bytes_done: u64,
-    total_bytes: u64,
-    start_time: Instant,
-    offset: u64,
-    is_sending: bool,
-) {
-    let progress = (bytes_done as f32 / total_bytes as f32) * 100.0;
-    let elapsed = start_time.elapsed().as_secs_f64();
-    let speed_bps = if elapsed > 0.0 {
-        bytes_done.saturating_sub(offset) as f64 / elapsed
-    } else {
-        0.0
-    };
-    let speed = format_transfer_speed(bytes_done.saturating_sub(offset), elapsed);
-
-    let _ = event_tx
-        .send(AppEvent::TransferProgress {
-            file_name: file_name.to_string(),
-            progress,
-            speed,
-            speed_bps,
-            is_sending,
-        })
-        .await;
+    ((bytes_done as f64 / total_bytes as f64 * 100.0) as f32).min(99.99)
 }
 
 #[cfg(test)]
@@ -174,62 +110,26 @@ mod tests {
         assert!(validate_transfer_info("huge.txt", MAX_FILE_SIZE + 1).is_err());
 
         // Invalid name length
-        let long_name = "a".repeat(MAX_FILENAME_LENGTH + 1);
+        let long_name = "a".repeat(MAX_WIRE_FILENAME_LENGTH + 1);
         assert!(validate_transfer_info(&long_name, 1024).is_err());
-    }
 
-    #[test]
-    fn test_sanitize_file_name() {
-        assert_eq!(sanitize_file_name("normal_file.txt"), "normal_file.txt");
-        assert_eq!(sanitize_file_name("path/to/file.txt"), "file.txt");
-        assert_eq!(sanitize_file_name("/absolute/path/to/file.txt"), "file.txt");
-        // Cross-platform separators
-        assert_eq!(
-            sanitize_file_name("path\\to\\windows\\file.txt"),
-            "file.txt"
-        );
-        assert_eq!(sanitize_file_name("mixed/path\\to/file.txt"), "file.txt");
-
-        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
-        assert_eq!(sanitize_file_name(".."), "unknown_file.bin");
-        assert_eq!(sanitize_file_name("."), "unknown_file.bin");
-        assert_eq!(sanitize_file_name(""), "unknown_file.bin");
-        assert_eq!(sanitize_file_name("   "), "unknown_file.bin");
-        assert_eq!(sanitize_file_name("foo/../bar.txt"), "bar.txt");
-
-        // Control characters
-        assert_eq!(sanitize_file_name("file\nname.txt"), "filename.txt");
-        assert_eq!(sanitize_file_name("file\0name.txt"), "filename.txt");
-
-        // Reserved names
-        assert_eq!(sanitize_file_name("CON"), "_CON");
-        assert_eq!(sanitize_file_name("con.txt"), "_con.txt");
-        assert_eq!(sanitize_file_name("LPT1"), "_LPT1");
-        assert_eq!(sanitize_file_name("aux"), "_aux");
-
-        // Unicode
-        assert_eq!(sanitize_file_name("文件.txt"), "文件.txt");
+        // Over the on-disk limit is fine: the receiver shortens it
+        assert!(validate_transfer_info(&"a".repeat(300), 1024).is_ok());
     }
 
     #[test]
-    fn test_sanitize_file_name_windows_chars() {
-        // These characters are invalid in Windows filenames
-        let invalid_chars = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
-
-        for c in invalid_chars {
-            let name = format!("file{}name.txt", c);
-            let sanitized = sanitize_file_name(&name);
-
-            // The sanitized name should NOT contain the invalid character
-            // (except / and \ which are path separators and already handled by rsplit,
-            // but sanitize_file_name handles them again in the filter just in case)
-            assert!(
-                !sanitized.contains(c),
-                "Character '{}' was not filtered out from '{}'",
-                c,
-                sanitized
-            );
-        }
+    fn test_progress_percent_large_files() {
+        assert_eq!(progress_percent(0, 0), 100.0);
+        assert_eq!(progress_percent(5, 10), 50.0);
+
+        // One byte short of 10 GB must not round up to 100%
+        let total = MAX_FILE_SIZE;
+        assert!(progress_percent(total - 1, total) < 100.0);
+        assert_eq!(progress_percent(total, total), 100.0);
+
+        // Past 4 GB the value keeps moving
+        let four_gb = 4 * 1024 * 1024 * 1024u64;
+        assert!(progress_percent(four_gb + 1024 * 1024, total) > progress_percent(four_gb, total));
     }
 
     #[tokio::test]
@@ -267,16 +167,14 @@ mod tests {
     #[tokio::test]
     async fn test_open_secure_file_append_insecure_fails() {
         let temp_dir = std::env::temp_dir();
-        let file_path = temp_dir.join(format!(
-            "secure_append_test_{}.txt",
-            uuid::Uuid::new_v4()
-        ));
+        let file_path = temp_dir.join(format!("secure_append_test_{}.txt", uuid::Uuid::new_v4()));
 
         // 1. Create file with 0o666 (rw-rw-rw-)
         {
             let file = tokio::fs::OpenOptions::new()
                 .write(true)
                 .create(true)
+                .truncate(true)
                 .open(&file_path)
                 .await
                 .expect("Failed to create initial file");
@@ -294,7 +192,10 @@ mod tests {
         let result = open_secure_file(&file_path, 100).await;
 
         #[cfg(unix)]
-        assert!(result.is_err(), "Appending to a file with insecure permissions should fail");
+        assert!(
+            result.is_err(),
+            "Appending to a file with insecure permissions should fail"
+        );
 
         // Cleanup
         let _ = tokio::fs::remove_file(&file_path).await;
@@ -314,6 +215,7 @@ mod tests {
             let file = tokio::fs::OpenOptions::new()
                 .write(true)
                 .create(true)
+                .truncate(true)
                 .open(&file_path)
                 .await
                 .expect("Failed to create initial file");
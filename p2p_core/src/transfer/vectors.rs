@@ -0,0 +1,196 @@
+//! Golden encodings of [`TransferMsg`](super::TransferMsg).
+//!
+//! Every message travels as a 4-byte big-endian length followed by the
+//! JSON body; [`VECTORS`] holds bodies as this version sends them, plus the
+//! older forms it must still read. A serialization change that breaks one
+//! of them breaks older peers, and other implementations of the protocol
+//! can check their encoder and decoder against the same bytes.
+
+/// One golden message body
+#[derive(Debug, Clone, Copy)]
+pub struct TestVector {
+    /// Variant the body decodes to
+    pub name: &'static str,
+    /// Message body, without the length prefix
+    pub body: &'static [u8],
+    /// Whether this version encodes the message to exactly `body`; false
+    /// for forms only older versions send
+    pub canonical: bool,
+}
+
+/// Golden [`TransferMsg`](super::TransferMsg) bodies
+pub const VECTORS: &[TestVector] = &[
+    TestVector {
+        name: "PairingRequest",
+        body: br#"{"PairingRequest":{"endpoint_id":"a1b2c3","peer_name":"Laptop"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "PairingRequest",
+        body: br#"{"PairingRequest":{"endpoint_id":"a1b2c3","peer_name":"Laptop","note":"holiday"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "VerificationRequired",
+        body: br#""VerificationRequired""#,
+        canonical: true,
+    },
+    TestVector {
+        name: "PairingQueued",
+        body: br#"{"PairingQueued":{"ahead":1}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "VerificationCode",
+        body: br#"{"VerificationCode":{"code":"123456"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "VerificationSuccess",
+        body: br#""VerificationSuccess""#,
+        canonical: true,
+    },
+    TestVector {
+        name: "VerificationRetry",
+        body: br#"{"VerificationRetry":{"attempts_left":2}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "FileMetadata",
+        body: br#"{"FileMetadata":{"info":{"file_name":"photo.jpg","file_size":1048576,"file_hash":"af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262","modified":1700000000000,"mode":420}}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "FileMetadata",
+        body: br#"{"FileMetadata":{"info":{"file_name":"photo.jpg","file_size":1048576,"file_hash":"0123456789abcdef0123456789abcdef","hash_algorithm":"xxh3"}}}"#,
+        canonical: true,
+    },
+    // Before hashes, timestamps and notes
+    TestVector {
+        name: "FileMetadata",
+        body: br#"{"FileMetadata":{"info":{"file_name":"photo.jpg","file_size":1048576}}}"#,
+        canonical: true,
+    },
+    // A stream: the size is only known at the end
+    TestVector {
+        name: "FileMetadata",
+        body: br#"{"FileMetadata":{"info":{"file_name":"backup.tar","note":"nightly"}}}"#,
+        canonical: true,
+    },
+    // A newer peer's algorithm decodes as `Unknown`
+    TestVector {
+        name: "FileMetadata",
+        body: br#"{"FileMetadata":{"info":{"file_name":"photo.jpg","file_size":1,"file_hash":"00","hash_algorithm":"blake7"}}}"#,
+        canonical: false,
+    },
+    TestVector {
+        name: "ReadyForData",
+        body: br#""ReadyForData""#,
+        canonical: true,
+    },
+    TestVector {
+        name: "StreamEnd",
+        body: br#"{"StreamEnd":{"size":10240,"file_hash":"af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "ResumeInfo",
+        body: br#"{"ResumeInfo":{"offset":65536,"prefix_hash":"af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262","token":"5f0c6a1e"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "ResumeStart",
+        body: br#"{"ResumeStart":{"offset":65536,"token":"5f0c6a1e","confirm_hash":true}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "ResumeInfo",
+        body: br#"{"ResumeInfo":{"offset":0,"prefix_hash":null,"token":"5f0c6a1e","ack_version":1}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "ResumeStart",
+        body: br#"{"ResumeStart":{"offset":0,"token":"5f0c6a1e","confirm_hash":true,"ack_version":1}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "ResumeInfo",
+        body: br#"{"ResumeInfo":{"offset":0,"prefix_hash":null,"token":"5f0c6a1e","ack_version":2,"receipts":true}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "ResumeStart",
+        body: br#"{"ResumeStart":{"offset":0,"token":"5f0c6a1e","confirm_hash":true,"ack_version":2,"want_receipt":true}}"#,
+        canonical: true,
+    },
+    // Before moves asked for the hash check
+    TestVector {
+        name: "ResumeStart",
+        body: br#"{"ResumeStart":{"offset":0,"token":"5f0c6a1e"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "TransferComplete",
+        body: br#""TransferComplete""#,
+        canonical: true,
+    },
+    TestVector {
+        name: "AckRequest",
+        body: br#""AckRequest""#,
+        canonical: true,
+    },
+    TestVector {
+        name: "HashVerified",
+        body: br#"{"HashVerified":{"verified":true}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "VerificationResult",
+        body: br#"{"VerificationResult":{"verified":false}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "Receipt",
+        body: br#"{"Receipt":{"receipt":{"file_name":"invoice.pdf","size":4096,"file_hash":"af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262","hash_algorithm":"blake3","received_at":1700000000,"receiver":"b1c2d3","sender":"a1b2c3","signature":"0f1e"}}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "Cancel",
+        body: br#"{"Cancel":{"reason":"Cancelled by the sender"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "RelayReady",
+        body: br#"{"RelayReady":{"port":9001}}"#,
+        canonical: true,
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::protocol::decode_msg;
+
+    #[test]
+    fn test_vectors_decode_and_canonical_ones_encode_identically() {
+        for vector in VECTORS {
+            let body = String::from_utf8_lossy(vector.body);
+            let msg = decode_msg(vector.body)
+                .unwrap_or_else(|e| panic!("{} does not decode: {}", body, e));
+            assert!(
+                format!("{:?}", msg).starts_with(vector.name),
+                "{} decoded to {:?}",
+                body,
+                msg
+            );
+            if vector.canonical {
+                assert_eq!(
+                    String::from_utf8(serde_json::to_vec(&msg).unwrap()).unwrap(),
+                    body,
+                    "{} no longer encodes the same",
+                    vector.name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,422 @@
+//! Hash verification of received files, off the transfer path.
+//!
+//! A LAN receiver acknowledges a file as soon as its last byte is on disk and
+//! hands the hash check to a [`VerifyQueue`]. One background worker checks
+//! the queued files in the order they arrived, so the [`HistoryStore`] sees
+//! them in that order too and a duplicate is always reported against the
+//! copy that finished first. The queue holds at most [`VERIFY_QUEUE_LEN`]
+//! files; beyond that receivers wait before acknowledging, so hashing can
+//! fall behind the network only so far. Files are read back through the
+//! [`Storage`] they were written to, so a file on remote storage is checked
+//! as the storage holds it.
+//!
+//! A local file is normally read back from the page cache, which proves what
+//! arrived but not what reached the disk. With read-back checks on (the
+//! `verify_read_back` setting) each file is first flushed and dropped from
+//! the cache, so the hash covers the bytes the disk returns: worth the lost
+//! throughput on USB sticks or network shares of doubtful health. Senders
+//! count a file as delivered only on this result.
+
+use crate::history::HistoryStore;
+use crate::history::transfers::{TransferRecord, TransferStatus};
+use crate::pairing::now_timestamp;
+use crate::storage::Storage;
+use crate::{AppEvent, FileInfo};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+use super::hash::{compute_stored_hash, verification_progress};
+use super::receiver::restore_metadata;
+
+/// Files that may wait for verification before receivers block
+pub const VERIFY_QUEUE_LEN: usize = 64;
+
+/// A received file waiting for its hash check
+#[derive(Debug)]
+struct VerifyJob {
+    file_path: PathBuf,
+    file_info: FileInfo,
+    expected_hash: String,
+    preserve_metadata: bool,
+    /// Logged with the outcome once the file is checked
+    record: TransferRecord,
+    /// Told the outcome, for a sender waiting on it
+    result: Option<oneshot::Sender<bool>>,
+}
+
+/// Handle for queueing received files; cheap to clone
+#[derive(Debug, Clone)]
+pub struct VerifyQueue {
+    jobs: mpsc::Sender<VerifyJob>,
+    event_tx: mpsc::Sender<AppEvent>,
+    history: Arc<HistoryStore>,
+}
+
+impl VerifyQueue {
+    /// Start the worker checking files in `storage`, reading them back from
+    /// the disk if `read_back`. It stops once every handle is dropped and
+    /// the queue is drained.
+    pub fn spawn(
+        history: Arc<HistoryStore>,
+        event_tx: mpsc::Sender<AppEvent>,
+        storage: Arc<dyn Storage>,
+        read_back: bool,
+    ) -> Self {
+        let (jobs, rx) = mpsc::channel(VERIFY_QUEUE_LEN);
+        tokio::spawn(run_worker(
+            rx,
+            history.clone(),
+            event_tx.clone(),
+            storage,
+            read_back,
+        ));
+        Self {
+            jobs,
+            event_tx,
+            history,
+        }
+    }
+
+    /// Log a received file that is not queued, such as one without a hash
+    pub fn log(&self, record: TransferRecord) {
+        self.history.transfers.add(record);
+    }
+
+    /// Where verified files are recorded
+    pub fn history(&self) -> &Arc<HistoryStore> {
+        &self.history
+    }
+
+    /// Queue `file_path` for checking against `expected_hash`, waiting while
+    /// the queue is full. Reports [`AppEvent::VerificationStarted`] right
+    /// away; the worker reports progress and the result, and logs `record`
+    /// with it. Metadata is only restored once the file is verified.
+    pub async fn enqueue(
+        &self,
+        file_path: PathBuf,
+        file_info: FileInfo,
+        expected_hash: String,
+        preserve_metadata: bool,
+        record: TransferRecord,
+    ) {
+        let job = VerifyJob {
+            file_path,
+            file_info,
+            expected_hash,
+            preserve_metadata,
+            record,
+            result: None,
+        };
+        self.enqueue_job(job).await;
+    }
+
+    /// [`enqueue`](Self::enqueue), resolving to whether the file verified
+    pub async fn enqueue_confirmed(
+        &self,
+        file_path: PathBuf,
+        file_info: FileInfo,
+        expected_hash: String,
+        preserve_metadata: bool,
+        record: TransferRecord,
+    ) -> oneshot::Receiver<bool> {
+        let (tx, rx) = oneshot::channel();
+        let job = VerifyJob {
+            file_path,
+            file_info,
+            expected_hash,
+            preserve_metadata,
+            record,
+            result: Some(tx),
+        };
+        self.enqueue_job(job).await;
+        rx
+    }
+
+    async fn enqueue_job(&self, job: VerifyJob) {
+        let _ = self
+            .event_tx
+            .send(AppEvent::VerificationStarted {
+                file_name: job.file_info.file_name.clone(),
+                is_sending: false,
+            })
+            .await;
+        if self.jobs.send(job).await.is_err() {
+            tracing::warn!("Verification worker stopped; file left unverified");
+        }
+    }
+}
+
+async fn run_worker(
+    mut jobs: mpsc::Receiver<VerifyJob>,
+    history: Arc<HistoryStore>,
+    event_tx: mpsc::Sender<AppEvent>,
+    storage: Arc<dyn Storage>,
+    read_back: bool,
+) {
+    while let Some(job) = jobs.recv().await {
+        verify(job, &history, &event_tx, storage.as_ref(), read_back).await;
+    }
+}
+
+async fn verify(
+    job: VerifyJob,
+    history: &HistoryStore,
+    event_tx: &mpsc::Sender<AppEvent>,
+    storage: &dyn Storage,
+    read_back: bool,
+) {
+    let file_name = job.file_info.file_name.clone();
+    // Remote storage is always read from where it keeps the file
+    let flushed = if read_back && storage.is_local() {
+        let path = job.file_path.clone();
+        match tokio::task::spawn_blocking(move || flush_from_cache(&path)).await {
+            Ok(result) => result,
+            Err(e) => Err(std::io::Error::other(e)),
+        }
+    } else {
+        Ok(())
+    };
+    let hashed = match flushed {
+        Ok(()) => {
+            compute_stored_hash(
+                storage,
+                &job.file_path,
+                job.file_info.hash_algorithm,
+                verification_progress(event_tx.clone(), file_name.clone(), false),
+            )
+            .await
+        }
+        Err(e) => Err(anyhow::anyhow!("Could not flush it to disk: {}", e)),
+    };
+    let verified = match hashed {
+        Ok(computed_hash) if computed_hash == job.expected_hash => {
+            // Only files of known size are checked here; streams as they arrive
+            let size = job.file_info.file_size.unwrap_or(0);
+            if let Some(existing) = history.record(&computed_hash, &job.file_path, size) {
+                let _ = event_tx
+                    .send(AppEvent::DuplicateReceived {
+                        file_name: file_name.clone(),
+                        path: job.file_path.clone(),
+                        existing,
+                        size,
+                    })
+                    .await;
+            }
+            true
+        }
+        Ok(_) if read_back => {
+            let _ = event_tx
+                .send(AppEvent::Error(format!(
+                    "Hash verification FAILED for {}: the disk returned other bytes than \
+                     arrived, so the storage may be failing",
+                    file_name
+                )))
+                .await;
+            false
+        }
+        Ok(_) => {
+            let _ = event_tx
+                .send(AppEvent::Error(format!(
+                    "Hash verification FAILED for {}!",
+                    file_name
+                )))
+                .await;
+            false
+        }
+        Err(e) => {
+            let _ = event_tx
+                .send(AppEvent::Error(format!(
+                    "Could not verify {}: {}",
+                    file_name, e
+                )))
+                .await;
+            false
+        }
+    };
+
+    history.transfers.add(TransferRecord {
+        at: now_timestamp(),
+        status: if verified {
+            TransferStatus::Verified
+        } else {
+            TransferStatus::Failed
+        },
+        ..job.record
+    });
+
+    if verified && job.preserve_metadata && storage.is_local() {
+        restore_metadata(&job.file_path, &job.file_info, event_tx).await;
+    }
+    if let Some(result) = job.result {
+        let _ = result.send(verified);
+    }
+
+    let _ = event_tx
+        .send(AppEvent::VerificationCompleted {
+            file_name,
+            is_sending: false,
+            verified,
+        })
+        .await;
+}
+
+/// Write `path` through to the disk and drop it from the page cache, so
+/// reading it again returns what the disk holds
+fn flush_from_cache(path: &Path) -> std::io::Result<()> {
+    // Windows flushes only handles open for writing
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+    file.sync_all()?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        // Only advice: pages the kernel keeps are read from memory as before
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::transfers::{Direction, HistoryQuery};
+    use crate::storage::LocalStorage;
+
+    fn file_info(name: &str, size: u64) -> FileInfo {
+        FileInfo {
+            file_name: name.to_string(),
+            file_size: Some(size),
+            file_path: PathBuf::new(),
+            file_hash: None,
+            hash_algorithm: Default::default(),
+            modified: None,
+            mode: None,
+            note: None,
+            xattrs: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_files_are_verified_in_arrival_order() {
+        let dir = std::env::temp_dir().join(format!("p2p_verify_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("first.bin");
+        let second = dir.join("second.bin");
+        let corrupt = dir.join("corrupt.bin");
+        for path in [&first, &second, &corrupt] {
+            std::fs::write(path, b"same content").unwrap();
+        }
+        let hash = blake3::hash(b"same content").to_hex().to_string();
+
+        let (event_tx, mut events) = mpsc::channel(64);
+        let history = Arc::new(HistoryStore::load(None));
+        let queue = VerifyQueue::spawn(history.clone(), event_tx, Arc::new(LocalStorage), false);
+        for (path, expected) in [
+            (&first, hash.clone()),
+            (&second, hash.clone()),
+            (&corrupt, "0".repeat(64)),
+        ] {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let record = TransferRecord::new(
+                Direction::Received,
+                TransferStatus::Verified,
+                name,
+                12,
+                "Peer",
+            );
+            queue
+                .enqueue(path.clone(), file_info(name, 12), expected, false, record)
+                .await;
+        }
+        drop(queue);
+
+        let mut completed = Vec::new();
+        let mut duplicate = None;
+        while let Some(event) = events.recv().await {
+            match event {
+                AppEvent::VerificationCompleted {
+                    file_name,
+                    verified,
+                    ..
+                } => completed.push((file_name, verified)),
+                AppEvent::DuplicateReceived { path, existing, .. } => {
+                    duplicate = Some((path, existing))
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(
+            completed,
+            vec![
+                ("first.bin".to_string(), true),
+                ("second.bin".to_string(), true),
+                ("corrupt.bin".to_string(), false),
+            ]
+        );
+        assert_eq!(duplicate, Some((second, first)));
+        let logged: Vec<_> = history
+            .transfers
+            .query(&HistoryQuery::default())
+            .into_iter()
+            .map(|record| (record.file_name, record.status))
+            .collect();
+        assert_eq!(
+            logged,
+            vec![
+                ("corrupt.bin".to_string(), TransferStatus::Failed),
+                ("second.bin".to_string(), TransferStatus::Verified),
+                ("first.bin".to_string(), TransferStatus::Verified),
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_back_checks_the_flushed_file() {
+        let dir = std::env::temp_dir().join(format!("p2p_verify_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stick.bin");
+        std::fs::write(&path, b"on the stick").unwrap();
+        let hash = blake3::hash(b"on the stick").to_hex().to_string();
+
+        let (event_tx, _events) = mpsc::channel(64);
+        let history = Arc::new(HistoryStore::load(None));
+        let queue = VerifyQueue::spawn(history, event_tx, Arc::new(LocalStorage), true);
+        let record = TransferRecord::new(
+            Direction::Received,
+            TransferStatus::Verified,
+            "stick.bin",
+            12,
+            "Peer",
+        );
+        let good = queue
+            .enqueue_confirmed(
+                path.clone(),
+                file_info("stick.bin", 12),
+                hash,
+                false,
+                record.clone(),
+            )
+            .await;
+        assert!(good.await.unwrap());
+
+        let bad = queue
+            .enqueue_confirmed(
+                path,
+                file_info("stick.bin", 12),
+                "0".repeat(64),
+                false,
+                record,
+            )
+            .await;
+        assert!(!bad.await.unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
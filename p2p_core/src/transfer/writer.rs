@@ -0,0 +1,150 @@
+//! Disk writes of a receive, kept off the receive loop.
+//!
+//! Writing inline stops reading from the stream whenever the disk is slow
+//! (SMR drives, network mounts), and QUIC then closes the sender's window.
+//! [`WritePipeline`] hands the data to a task that owns the file instead;
+//! the file's own writes run on tokio's blocking thread pool. Small reads
+//! are coalesced into chunks of [`WRITE_CHUNK_SIZE`], and at most
+//! [`WRITE_QUEUE_DEPTH`] chunks wait, so a disk that cannot keep up still
+//! slows the sender down, only later and smoothly.
+
+use super::sparse::SparseWriter;
+use std::io;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Reads are collected up to this size before they are written
+pub const WRITE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Chunks waiting for the disk before the receive loop waits too
+pub const WRITE_QUEUE_DEPTH: usize = 8;
+
+/// Writes a received file in the background, in order
+pub struct WritePipeline {
+    /// Data not yet queued
+    pending: Vec<u8>,
+    chunks: mpsc::Sender<Vec<u8>>,
+    /// Written chunks, handed back for reuse
+    spent: mpsc::Receiver<Vec<u8>>,
+    task: JoinHandle<io::Result<SparseWriter>>,
+}
+
+impl WritePipeline {
+    /// Start writing to `file`
+    pub fn spawn(file: SparseWriter) -> Self {
+        let (chunks, chunk_rx) = mpsc::channel(WRITE_QUEUE_DEPTH);
+        let (spent_tx, spent) = mpsc::channel(WRITE_QUEUE_DEPTH);
+        Self {
+            pending: Vec::with_capacity(WRITE_CHUNK_SIZE),
+            chunks,
+            spent,
+            task: tokio::spawn(write_chunks(file, chunk_rx, spent_tx)),
+        }
+    }
+
+    /// Queue `data`; waits only while the queue is full. After an error the
+    /// pipeline is done and must be dropped.
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut data = data;
+        while !data.is_empty() {
+            let room = WRITE_CHUNK_SIZE - self.pending.len();
+            let (now, later) = data.split_at(room.min(data.len()));
+            self.pending.extend_from_slice(now);
+            data = later;
+            if self.pending.len() == WRITE_CHUNK_SIZE {
+                self.queue().await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn queue(&mut self) -> io::Result<()> {
+        let next = self
+            .spent
+            .try_recv()
+            .unwrap_or_else(|_| Vec::with_capacity(WRITE_CHUNK_SIZE));
+        let chunk = std::mem::replace(&mut self.pending, next);
+        if self.chunks.send(chunk).await.is_ok() {
+            return Ok(());
+        }
+        // The writer stopped on an error; report that one
+        match (&mut self.task).await {
+            Ok(Err(e)) => Err(e),
+            Ok(Ok(_)) => Err(io::Error::other("The file writer stopped")),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    /// Write what is left and flush; returns the file once it is all on disk
+    pub async fn finish(mut self) -> io::Result<SparseWriter> {
+        if !self.pending.is_empty() {
+            self.queue().await?;
+        }
+        drop(self.chunks);
+        self.task.await.map_err(io::Error::other)?
+    }
+}
+
+async fn write_chunks(
+    mut file: SparseWriter,
+    mut chunks: mpsc::Receiver<Vec<u8>>,
+    spent: mpsc::Sender<Vec<u8>>,
+) -> io::Result<SparseWriter> {
+    while let Some(mut chunk) = chunks.recv().await {
+        crate::profile_future!("write", file.write_chunk(&chunk)).await?;
+        chunk.clear();
+        let _ = spent.try_send(chunk);
+    }
+    file.finish().await?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{FailingStorage, Storage};
+    use crate::transfer::utils::open_secure_file;
+
+    #[tokio::test]
+    async fn test_writes_arrive_in_order() {
+        let path = std::env::temp_dir().join(format!("pipeline_{}.bin", uuid::Uuid::new_v4()));
+        let file = open_secure_file(&path, 0).await.unwrap();
+        let mut pipeline = WritePipeline::spawn(SparseWriter::new(Box::new(file), 0));
+
+        let data: Vec<u8> = (0..3 * WRITE_CHUNK_SIZE + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        for piece in data.chunks(7_777) {
+            pipeline.write(piece).await.unwrap();
+        }
+        let file = pipeline.finish().await.unwrap();
+
+        assert_eq!(file.len(), data.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_write_error_is_reported() {
+        let dir = std::env::temp_dir().join(format!("pipeline_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = FailingStorage::local().disk_full_after(WRITE_CHUNK_SIZE as u64);
+        let file = storage.create(&dir.join("full.bin")).await.unwrap();
+        let mut pipeline = WritePipeline::spawn(SparseWriter::new(file, 0));
+
+        let data = vec![1u8; WRITE_CHUNK_SIZE];
+        let mut result = Ok(());
+        for _ in 0..2 * WRITE_QUEUE_DEPTH + 2 {
+            result = pipeline.write(&data).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        let error = match result {
+            Err(e) => e,
+            Ok(()) => pipeline.finish().await.err().unwrap(),
+        };
+        assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,344 @@
+//! Extended attributes carried alongside [`FileInfo`](crate::FileInfo).
+//!
+//! Finder tags, resource forks and color labels live in extended
+//! attributes, which copying the content alone loses. Sending them is
+//! opt-in (`send_extended_attributes`): each file's attributes then travel
+//! in its `FileInfo`, and the receiver restores them with the rest of the
+//! metadata (see [`super::metadata`]). Receivers that can't store them
+//! skip them and keep the file: Windows, file systems without attribute
+//! support, and builds that predate the field, which ignore it.
+//!
+//! On Linux only the `user` namespace is portable; SELinux labels and ACLs
+//! belong to the machine and are neither sent nor restored. Names from
+//! other systems are stored under `user.` there. `com.apple.quarantine` is
+//! never sent, as the receiver marks programs itself (see
+//! [`crate::quarantine`]). A file's metadata has to fit one protocol
+//! message, so attributes beyond [`MAX_XATTR_BYTES`] are left out.
+
+use crate::pairing::key::{hex_decode, hex_encode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::io;
+use std::path::Path;
+
+/// Most bytes of names and values sent per file
+pub const MAX_XATTR_BYTES: usize = 16 * 1024;
+
+/// Whether this system can read and write extended attributes
+pub const XATTRS_SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+
+/// Namespaces that describe the machine rather than the file
+const LOCAL_NAMESPACES: &[&str] = &["security.", "system.", "trusted."];
+
+/// Attributes the receiving system sets itself
+const LOCAL_ATTRIBUTES: &[&str] = &["com.apple.quarantine", "com.apple.provenance"];
+
+/// One extended attribute of a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ExtendedAttribute {
+    pub name: String,
+    /// Raw value, hex encoded on the wire
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    #[schemars(with = "String")]
+    pub value: Vec<u8>,
+}
+
+fn to_hex<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex_encode(value))
+}
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    hex_decode(&hex).ok_or_else(|| serde::de::Error::custom("invalid hex in attribute value"))
+}
+
+/// Whether `name` describes the file itself and may cross machines
+pub fn is_portable(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains('\0')
+        && !LOCAL_NAMESPACES.iter().any(|ns| name.starts_with(ns))
+        && !LOCAL_ATTRIBUTES.contains(&name)
+}
+
+/// Name `name` is stored under on this system
+fn local_name(name: &str) -> String {
+    if cfg!(target_os = "linux") && !name.starts_with("user.") {
+        format!("user.{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Portable attributes of `path`, smallest first, up to [`MAX_XATTR_BYTES`].
+/// Empty where attributes aren't supported.
+pub fn read_extended_attributes(path: &Path) -> io::Result<Vec<ExtendedAttribute>> {
+    let mut attributes = Vec::new();
+    for name in platform::list(path)? {
+        if !is_portable(&name) {
+            continue;
+        }
+        match platform::get(path, &name) {
+            Ok(value) => attributes.push(ExtendedAttribute { name, value }),
+            // Removed since it was listed
+            Err(e) if e.raw_os_error() == Some(platform::NO_ATTRIBUTE) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(within_budget(attributes, path))
+}
+
+/// The smallest of `attributes` that fit [`MAX_XATTR_BYTES`] together
+fn within_budget(mut attributes: Vec<ExtendedAttribute>, path: &Path) -> Vec<ExtendedAttribute> {
+    attributes.sort_by_key(|attribute| attribute.name.len() + attribute.value.len());
+    let mut budget = MAX_XATTR_BYTES;
+    attributes.retain(|attribute| {
+        let size = attribute.name.len() + attribute.value.len();
+        if size > budget {
+            tracing::warn!(
+                "Extended attribute {} of {:?} ({} bytes) is too large to send",
+                attribute.name,
+                path,
+                attribute.value.len()
+            );
+            return false;
+        }
+        budget -= size;
+        true
+    });
+    attributes
+}
+
+/// Set `attributes` on `path`, skipping names that aren't portable. Every
+/// attribute is tried; the first failure is returned.
+pub fn write_extended_attributes(path: &Path, attributes: &[ExtendedAttribute]) -> io::Result<()> {
+    if !XATTRS_SUPPORTED {
+        tracing::debug!(
+            "Skipping {} extended attribute(s) of {:?}: not supported here",
+            attributes.len(),
+            path
+        );
+        return Ok(());
+    }
+
+    let mut first_error = None;
+    for attribute in attributes.iter().filter(|a| is_portable(&a.name)) {
+        if let Err(e) = platform::set(path, &local_name(&attribute.name), &attribute.value) {
+            tracing::debug!("Extended attribute {} of {:?}: {}", attribute.name, path, e);
+            first_error.get_or_insert(e);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod platform {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    /// `errno` of a missing attribute
+    #[cfg(target_os = "linux")]
+    pub const NO_ATTRIBUTE: i32 = libc::ENODATA;
+    #[cfg(target_os = "macos")]
+    pub const NO_ATTRIBUTE: i32 = libc::ENOATTR;
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    /// Call `read` with a buffer of the size it asks for when passed none,
+    /// again if the value grew in between
+    fn read_sized(read: impl Fn(*mut u8, usize) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let size = read(std::ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buf = vec![0u8; size as usize];
+            let read = read(buf.as_mut_ptr(), buf.len());
+            if read >= 0 {
+                buf.truncate(read as usize);
+                return Ok(buf);
+            }
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ERANGE) {
+                return Err(e);
+            }
+        }
+    }
+
+    pub fn list(path: &Path) -> io::Result<Vec<String>> {
+        let path = c_path(path)?;
+        // SAFETY: `path` is NUL-terminated and the buffer is `size` bytes long
+        let names = read_sized(|buf, size| unsafe {
+            #[cfg(target_os = "linux")]
+            let read = libc::listxattr(path.as_ptr(), buf.cast(), size);
+            #[cfg(target_os = "macos")]
+            let read = libc::listxattr(path.as_ptr(), buf.cast(), size, 0);
+            read
+        });
+        let names = match names {
+            Ok(names) => names,
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(names
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| std::str::from_utf8(name).ok())
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub fn get(path: &Path, name: &str) -> io::Result<Vec<u8>> {
+        let path = c_path(path)?;
+        let name = CString::new(name)?;
+        // SAFETY: both strings are NUL-terminated and the buffer is `size`
+        // bytes long
+        read_sized(|buf, size| unsafe {
+            #[cfg(target_os = "linux")]
+            let read = libc::getxattr(path.as_ptr(), name.as_ptr(), buf.cast(), size);
+            #[cfg(target_os = "macos")]
+            let read = libc::getxattr(path.as_ptr(), name.as_ptr(), buf.cast(), size, 0, 0);
+            read
+        })
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let path = c_path(path)?;
+        let name = CString::new(name)?;
+        // SAFETY: both strings are NUL-terminated and outlive the call
+        let result = unsafe {
+            #[cfg(target_os = "linux")]
+            let result = libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            );
+            #[cfg(target_os = "macos")]
+            let result = libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+                0,
+            );
+            result
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    pub const NO_ATTRIBUTE: i32 = 0;
+
+    pub fn list(_path: &Path) -> io::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    pub fn get(_path: &Path, _name: &str) -> io::Result<Vec<u8>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_portable_names_and_wire_form() {
+        assert!(is_portable("user.xdg.tags"));
+        assert!(is_portable("com.apple.FinderInfo"));
+        assert!(!is_portable("security.selinux"));
+        assert!(!is_portable("system.posix_acl_access"));
+        assert!(!is_portable("com.apple.quarantine"));
+        assert!(!is_portable(""));
+
+        let attribute = ExtendedAttribute {
+            name: "user.tag".to_string(),
+            value: vec![0, 0xff, b'a'],
+        };
+        let json = serde_json::to_string(&attribute).unwrap();
+        assert_eq!(json, r#"{"name":"user.tag","value":"00ff61"}"#);
+        assert_eq!(
+            serde_json::from_str::<ExtendedAttribute>(&json).unwrap(),
+            attribute
+        );
+        assert!(serde_json::from_str::<ExtendedAttribute>(r#"{"name":"a","value":"0"}"#).is_err());
+    }
+
+    #[test]
+    fn test_oversized_attributes_are_left_out() {
+        let attribute = |name: &str, size: usize| ExtendedAttribute {
+            name: name.to_string(),
+            value: vec![1; size],
+        };
+        let kept = within_budget(
+            vec![
+                attribute("user.fork", MAX_XATTR_BYTES),
+                attribute("user.b", 9500),
+                attribute("user.a", 7000),
+                attribute("user.tag", 4),
+            ],
+            Path::new("design.psd"),
+        );
+        let names: Vec<&str> = kept.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["user.tag", "user.a"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_round_trip_skips_local_attributes() {
+        let path = std::env::temp_dir().join(format!("xattrs_test_{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"design").unwrap();
+
+        let attributes = vec![
+            ExtendedAttribute {
+                name: "user.xdg.tags".to_string(),
+                value: b"blue".to_vec(),
+            },
+            ExtendedAttribute {
+                // Stored as user.com.apple.FinderInfo
+                name: "com.apple.FinderInfo".to_string(),
+                value: vec![7; 32],
+            },
+            ExtendedAttribute {
+                name: "security.selinux".to_string(),
+                value: b"ignored".to_vec(),
+            },
+        ];
+        match write_extended_attributes(&path, &attributes) {
+            Ok(()) => {}
+            // The temp directory's file system stores no user attributes
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                let _ = std::fs::remove_file(&path);
+                return;
+            }
+            Err(e) => panic!("{}", e),
+        }
+
+        let read = read_extended_attributes(&path).unwrap();
+        let names: Vec<&str> = read.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["user.xdg.tags", "user.com.apple.FinderInfo"]);
+        assert_eq!(read[0].value, b"blue");
+        assert_eq!(read[1].value, vec![7; 32]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,194 @@
+//! Human-readable sizes and speeds in the user's preferred units.
+//!
+//! Everything shown to the user goes through [`format_size`] and
+//! [`format_speed`], so progress events from the backend and labels drawn
+//! by the GUI agree. The preference is process-wide: the backend applies
+//! the profile's setting on start and on [`AppCommand::SetUnitPreference`].
+//! Decimals use the separator of the locale in `LC_ALL`, `LC_NUMERIC` or
+//! `LANG`, so "1,50 MB" in German and "1.50 MB" in English.
+//!
+//! [`AppCommand::SetUnitPreference`]: crate::AppCommand::SetUnitPreference
+
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How sizes and speeds are displayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitPreference {
+    /// Powers of 1024 (KiB, MiB) instead of 1000 (KB, MB)
+    #[serde(default)]
+    pub binary: bool,
+    /// Speeds in bits per second (Mbit/s) instead of bytes
+    #[serde(default)]
+    pub bits: bool,
+}
+
+const BINARY: u8 = 1;
+const BITS: u8 = 2;
+
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+/// Languages writing "1,5" rather than "1.5"
+const DECIMAL_COMMA_LANGUAGES: &[&str] = &[
+    "af", "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fr", "gl", "hr", "hu", "id",
+    "is", "it", "lt", "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv",
+    "tr", "uk", "vi",
+];
+
+/// Preference used by [`format_size`] and [`format_speed`]
+pub fn unit_preference() -> UnitPreference {
+    let flags = CURRENT.load(Ordering::Relaxed);
+    UnitPreference {
+        binary: flags & BINARY != 0,
+        bits: flags & BITS != 0,
+    }
+}
+
+pub fn set_unit_preference(preference: UnitPreference) {
+    let mut flags = 0;
+    if preference.binary {
+        flags |= BINARY;
+    }
+    if preference.bits {
+        flags |= BITS;
+    }
+    CURRENT.store(flags, Ordering::Relaxed);
+}
+
+/// Decimal separator for a POSIX locale name like `de_DE.UTF-8`
+pub fn decimal_separator_for(locale: &str) -> char {
+    let language = locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if DECIMAL_COMMA_LANGUAGES.contains(&language.as_str()) {
+        ','
+    } else {
+        '.'
+    }
+}
+
+/// Decimal separator of the user's locale, read once
+pub fn decimal_separator() -> char {
+    static SEPARATOR: OnceLock<char> = OnceLock::new();
+    *SEPARATOR.get_or_init(|| {
+        ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .map_or('.', |locale| decimal_separator_for(&locale))
+    })
+}
+
+/// Swap the decimal point of a formatted number for `separator`
+fn localized(formatted: String, separator: char) -> String {
+    if separator == '.' {
+        formatted
+    } else {
+        formatted.replacen('.', &separator.to_string(), 1)
+    }
+}
+
+fn scaled(value: f64, preference: UnitPreference, units: [&str; 5]) -> String {
+    let base = if preference.binary { 1024.0 } else { 1000.0 };
+    let mut value = value.max(0.0);
+    let mut index = 0;
+    while value >= base && index < units.len() - 1 {
+        value /= base;
+        index += 1;
+    }
+    match index {
+        0 => format!("{:.0} {}", value, units[0]),
+        1 => format!("{:.1} {}", value, units[1]),
+        _ => format!("{:.2} {}", value, units[index]),
+    }
+}
+
+/// Format a byte count, e.g. "1.50 MB" or "1.43 MiB"
+pub fn format_size_with(bytes: u64, preference: UnitPreference) -> String {
+    let units = if preference.binary {
+        ["B", "KiB", "MiB", "GiB", "TiB"]
+    } else {
+        ["B", "KB", "MB", "GB", "TB"]
+    };
+    scaled(bytes as f64, preference, units)
+}
+
+/// Format a speed given in bytes per second, e.g. "12.50 MB/s" or "100.00 Mbit/s"
+pub fn format_speed_with(bytes_per_sec: f64, preference: UnitPreference) -> String {
+    if preference.bits {
+        let units = if preference.binary {
+            ["bit/s", "Kibit/s", "Mibit/s", "Gibit/s", "Tibit/s"]
+        } else {
+            ["bit/s", "kbit/s", "Mbit/s", "Gbit/s", "Tbit/s"]
+        };
+        scaled(bytes_per_sec * 8.0, preference, units)
+    } else {
+        let units = if preference.binary {
+            ["B/s", "KiB/s", "MiB/s", "GiB/s", "TiB/s"]
+        } else {
+            ["B/s", "KB/s", "MB/s", "GB/s", "TB/s"]
+        };
+        scaled(bytes_per_sec, preference, units)
+    }
+}
+
+/// [`format_size_with`] the current preference and locale
+pub fn format_size(bytes: u64) -> String {
+    localized(
+        format_size_with(bytes, unit_preference()),
+        decimal_separator(),
+    )
+}
+
+/// [`format_speed_with`] the current preference and locale
+pub fn format_speed(bytes_per_sec: f64) -> String {
+    localized(
+        format_speed_with(bytes_per_sec, unit_preference()),
+        decimal_separator(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units_follow_the_preference() {
+        let decimal = UnitPreference::default();
+        let binary = UnitPreference {
+            binary: true,
+            bits: false,
+        };
+        let bits = UnitPreference {
+            binary: false,
+            bits: true,
+        };
+
+        assert_eq!(format_size_with(999, decimal), "999 B");
+        assert_eq!(format_size_with(1_500_000, decimal), "1.50 MB");
+        assert_eq!(format_size_with(1_500_000, binary), "1.43 MiB");
+        assert_eq!(format_size_with(2048, binary), "2.0 KiB");
+
+        assert_eq!(format_speed_with(12_500_000.0, decimal), "12.50 MB/s");
+        assert_eq!(format_speed_with(12_500_000.0, bits), "100.00 Mbit/s");
+        assert_eq!(format_speed_with(1_048_576.0, binary), "1.00 MiB/s");
+        assert_eq!(format_speed_with(0.0, decimal), "0 B/s");
+    }
+
+    #[test]
+    fn test_decimal_separator_follows_the_locale() {
+        assert_eq!(decimal_separator_for("de_DE.UTF-8"), ',');
+        assert_eq!(decimal_separator_for("vi_VN"), ',');
+        assert_eq!(decimal_separator_for("fr"), ',');
+        assert_eq!(decimal_separator_for("en_US.UTF-8"), '.');
+        assert_eq!(decimal_separator_for("C"), '.');
+        assert_eq!(decimal_separator_for(""), '.');
+
+        let size = format_size_with(1_500_000, UnitPreference::default());
+        assert_eq!(localized(size.clone(), ','), "1,50 MB");
+        assert_eq!(localized(size, '.'), "1.50 MB");
+    }
+}
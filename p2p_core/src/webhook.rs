@@ -0,0 +1,257 @@
+//! Webhook notifications for received files.
+//!
+//! With a webhook URL configured, every file that arrives over LAN, WAN or
+//! the browser upload page is announced with a JSON `POST` (see
+//! [`WebhookPayload`]), e.g. to start a home-automation rule or a NAS import.
+//! Received files are picked out of the backend's event stream by
+//! [`crate::received`]; sent files are not reported.
+//!
+//! Deliveries run in the background and are retried with growing delays
+//! when the request fails or the server answers with an error status. A
+//! webhook that stays unreachable is logged, never surfaced as a transfer
+//! error.
+
+use crate::received::{self, ReceivedFile};
+use crate::transfer::compute_file_hash;
+use crate::{AppEvent, EventCategory, LogLevel};
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Attempts per notification before giving up
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry; doubled for each further one
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Body of a webhook request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WebhookPayload {
+    /// `transfer_completed` or `upload_completed`
+    pub event: String,
+    pub file_name: String,
+    pub size: u64,
+    /// Sender's display name or endpoint ID, or the browser's IP address
+    pub peer: Option<String>,
+    /// BLAKE3 hash of the saved file
+    pub hash: String,
+    pub path: PathBuf,
+}
+
+/// Posts [`WebhookPayload`]s to one URL
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: reqwest::Url,
+    client: reqwest::Client,
+    retry_delay: Duration,
+}
+
+impl Webhook {
+    /// Webhook for an `http` or `https` URL
+    pub fn new(url: &str) -> Result<Self> {
+        let url = reqwest::Url::parse(url).map_err(|e| anyhow!("Invalid webhook URL: {}", e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(anyhow!("Webhook URL must use http or https"));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            url,
+            client,
+            retry_delay: FIRST_RETRY_DELAY,
+        })
+    }
+
+    /// Wait `delay` before the first retry instead of one second
+    pub fn with_retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Post `payload`, retrying up to [`WEBHOOK_MAX_ATTEMPTS`] times
+    pub async fn deliver(&self, payload: &WebhookPayload) -> Result<()> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .client
+                .post(self.url.clone())
+                .json(payload)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt >= WEBHOOK_MAX_ATTEMPTS => {
+                    return Err(anyhow!("Webhook failed after {} attempts: {}", attempt, e));
+                }
+                Err(e) => {
+                    tracing::debug!("Webhook attempt {} failed: {}", attempt, e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Describe the file saved at `path`
+pub async fn payload_for(
+    event: &str,
+    file_name: &str,
+    path: &Path,
+    peer: Option<String>,
+) -> Result<WebhookPayload> {
+    let size = tokio::fs::metadata(path).await?.len();
+    let hash = compute_file_hash(path).await?;
+    Ok(WebhookPayload {
+        event: event.to_string(),
+        file_name: file_name.to_string(),
+        size,
+        peer,
+        hash,
+        path: path.to_path_buf(),
+    })
+}
+
+/// Forward `events` to `event_tx`, notifying `webhook` of received files
+/// on the way. Ends when either side closes.
+pub async fn forward_events(
+    webhook: Webhook,
+    events: mpsc::Receiver<AppEvent>,
+    event_tx: mpsc::Sender<AppEvent>,
+) {
+    let log_tx = event_tx.clone();
+    received::forward_events(events, event_tx, move |file: ReceivedFile| {
+        let webhook = webhook.clone();
+        let event_tx = log_tx.clone();
+        tokio::spawn(async move {
+            let result = match payload_for(file.event, &file.file_name, &file.path, file.peer).await
+            {
+                Ok(payload) => webhook.deliver(&payload).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                let _ = event_tx
+                    .send(AppEvent::log(
+                        LogLevel::Warning,
+                        EventCategory::Transfer,
+                        format!("Webhook for {}: {}", file.file_name, e),
+                    ))
+                    .await;
+            }
+        });
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Clone)]
+    struct Receiver {
+        /// Requests to answer with an error before accepting
+        failures: u32,
+        calls: Arc<AtomicU32>,
+        accepted: mpsc::Sender<serde_json::Value>,
+    }
+
+    async fn hook(
+        State(receiver): State<Receiver>,
+        axum::Json(body): axum::Json<serde_json::Value>,
+    ) -> StatusCode {
+        if receiver.calls.fetch_add(1, Ordering::SeqCst) < receiver.failures {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        let _ = receiver.accepted.send(body).await;
+        StatusCode::OK
+    }
+
+    /// Server that fails the first `failures` requests and reports the
+    /// accepted payloads
+    async fn spawn_receiver(failures: u32) -> (String, mpsc::Receiver<serde_json::Value>) {
+        let (accepted, rx) = mpsc::channel(8);
+        let app = axum::Router::new()
+            .route("/hook", post(hook))
+            .with_state(Receiver {
+                failures,
+                calls: Arc::new(AtomicU32::new(0)),
+                accepted,
+            });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}/hook", addr), rx)
+    }
+
+    #[tokio::test]
+    async fn test_received_file_is_posted_after_retries() {
+        let dir = std::env::temp_dir().join(format!("webhook_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.pdf");
+        std::fs::write(&path, b"hello webhook").unwrap();
+
+        let (url, mut received) = spawn_receiver(2).await;
+        let webhook = Webhook::new(&url)
+            .unwrap()
+            .with_retry_delay(Duration::from_millis(10));
+        let (tx, rx) = mpsc::channel(8);
+        let (out_tx, mut out_rx) = mpsc::channel(8);
+        tokio::spawn(forward_events(webhook, rx, out_tx));
+
+        // Sent files are not reported
+        tx.send(AppEvent::TransferCompleted {
+            file_name: "sent.bin".to_string(),
+            saved_path: None,
+            peer: None,
+        })
+        .await
+        .unwrap();
+        tx.send(AppEvent::TransferCompleted {
+            file_name: "report.pdf".to_string(),
+            saved_path: Some(path.clone()),
+            peer: Some("Laptop".to_string()),
+        })
+        .await
+        .unwrap();
+        assert!(matches!(
+            out_rx.recv().await,
+            Some(AppEvent::TransferCompleted { .. })
+        ));
+
+        let body = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(body["event"], "transfer_completed");
+        assert_eq!(body["file_name"], "report.pdf");
+        assert_eq!(body["size"], 13);
+        assert_eq!(body["peer"], "Laptop");
+        assert_eq!(
+            body["hash"],
+            blake3::hash(b"hello webhook").to_hex().to_string()
+        );
+        assert!(received.try_recv().is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_only_http_urls_are_accepted() {
+        assert!(Webhook::new("https://nas.local/hook").is_ok());
+        assert!(Webhook::new("file:///etc/passwd").is_err());
+        assert!(Webhook::new("not a url").is_err());
+    }
+}
@@ -0,0 +1,31 @@
+use p2p_core::AppEvent;
+use p2p_core::health::HEALTH_INTERVAL;
+use p2p_core::testing::TestNode;
+
+#[tokio::test]
+async fn test_heartbeat_reports_services() {
+    let mut node = TestNode::spawn("health").await.unwrap();
+
+    let health = node
+        .wait_for(HEALTH_INTERVAL * 2, |event| {
+            matches!(event, AppEvent::BackendHealth { .. })
+        })
+        .await
+        .unwrap();
+    let AppEvent::BackendHealth {
+        discovery_ok,
+        quic_listening,
+        http_running,
+        wan_online,
+        relay_latency_ms,
+    } = health
+    else {
+        unreachable!();
+    };
+    // Test nodes run without discovery, HTTP share or an Iroh endpoint
+    assert!(quic_listening);
+    assert!(!discovery_ok && !http_running && !wan_online);
+    assert_eq!(relay_latency_ms, None);
+
+    node.shutdown().await;
+}
@@ -0,0 +1,167 @@
+//! Sends through injected failures recover once the network behaves again.
+//! Needs the `chaos` feature: `cargo test -p p2p_core --features chaos`.
+
+use p2p_core::AppEvent;
+use p2p_core::testing::{DEFAULT_EVENT_TIMEOUT, TestNode, TestPair, write_test_file};
+use p2p_core::transfer::chaos::{self, ChaosConfig};
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+/// The override is process-wide; tests take turns
+static CHAOS: Mutex<()> = Mutex::const_new(());
+
+/// A pair that already trusts each other, so later sends need no code
+async fn paired() -> TestPair {
+    // In case a failed test left its settings behind
+    chaos::set_override(None);
+    let mut pair = TestPair::new().await.unwrap();
+    let warmup = write_test_file(pair.sender.root(), "warmup.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![warmup]).await.unwrap();
+    pair
+}
+
+/// Wait for the receiver's hash check of `file_name`
+async fn verified(receiver: &mut TestNode, file_name: &str) -> bool {
+    match receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::VerificationCompleted { file_name: name, is_sending: false, .. }
+                    if name == file_name
+            )
+        })
+        .await
+        .unwrap()
+    {
+        AppEvent::VerificationCompleted { verified, .. } => verified,
+        _ => unreachable!(),
+    }
+}
+
+/// Wait for the receiver to save `file_name`, returning where
+async fn saved(receiver: &mut TestNode, file_name: &str) -> PathBuf {
+    match receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::TransferCompleted { file_name: name, .. } if name == file_name)
+        })
+        .await
+        .unwrap()
+    {
+        AppEvent::TransferCompleted {
+            saved_path: Some(path),
+            ..
+        } => path,
+        other => panic!("No saved path in {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_corrupted_bytes_fail_verification_and_a_resend_repairs_them() {
+    let _guard = CHAOS.lock().await;
+    let mut pair = paired().await;
+    let file = write_test_file(pair.sender.root(), "corrupt.bin", 512 * 1024).unwrap();
+
+    chaos::set_override(Some(ChaosConfig {
+        corrupt_chance: 1.0,
+        corrupt_bytes: 4,
+        ..Default::default()
+    }));
+    pair.sender
+        .send_files_to(&pair.receiver, vec![file.clone()])
+        .await
+        .unwrap();
+    assert!(!verified(&mut pair.receiver, "corrupt.bin").await);
+    // The sender hears of it and offers to send the file again
+    let offer = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::VerificationCompleted {
+                    is_sending: true,
+                    ..
+                }
+            )
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        offer,
+        AppEvent::VerificationCompleted {
+            verified: false,
+            ..
+        }
+    ));
+    let offer = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::ResendOffered { .. })
+        })
+        .await
+        .unwrap();
+    assert!(matches!(offer, AppEvent::ResendOffered { path, .. } if path == file));
+    chaos::set_override(None);
+
+    pair.sender
+        .send_files_to(&pair.receiver, vec![file.clone()])
+        .await
+        .unwrap();
+    let path = saved(&mut pair.receiver, "corrupt.bin").await;
+    assert!(verified(&mut pair.receiver, "corrupt.bin").await);
+    assert_eq!(std::fs::read(path).unwrap(), std::fs::read(&file).unwrap());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_dropped_stream_is_resumed_by_the_next_send() {
+    let _guard = CHAOS.lock().await;
+    let mut pair = paired().await;
+    let file = write_test_file(pair.sender.root(), "dropped.bin", 2 * 1024 * 1024).unwrap();
+
+    chaos::set_override(Some(ChaosConfig {
+        drop_chance: 1.0,
+        after: 512 * 1024,
+        ..Default::default()
+    }));
+    pair.sender
+        .send_files_to(&pair.receiver, vec![file.clone()])
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| matches!(e, AppEvent::Error(_)))
+        .await
+        .unwrap();
+    chaos::set_override(None);
+
+    pair.sender
+        .send_files_to(&pair.receiver, vec![file.clone()])
+        .await
+        .unwrap();
+    let path = saved(&mut pair.receiver, "dropped.bin").await;
+    assert!(verified(&mut pair.receiver, "dropped.bin").await);
+    assert_eq!(std::fs::read(path).unwrap(), std::fs::read(&file).unwrap());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_delays_only_slow_a_send_down() {
+    let _guard = CHAOS.lock().await;
+    let mut pair = paired().await;
+    let file = write_test_file(pair.sender.root(), "slow.bin", 256 * 1024).unwrap();
+
+    chaos::set_override(Some(ChaosConfig {
+        max_delay: std::time::Duration::from_millis(20),
+        ..Default::default()
+    }));
+    pair.send_paired(vec![file.clone()]).await.unwrap();
+    chaos::set_override(None);
+
+    assert_eq!(
+        std::fs::read(pair.receiver.download_dir().join("slow.bin")).unwrap(),
+        std::fs::read(&file).unwrap()
+    );
+
+    pair.shutdown().await;
+}
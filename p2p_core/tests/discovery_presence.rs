@@ -0,0 +1,110 @@
+use p2p_core::AppEvent;
+use p2p_core::discovery::DiscoveryService;
+use p2p_core::discovery::presence::PEER_LOST_TIMEOUT;
+use p2p_core::discovery::room::RoomKey;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+async fn start(name: &str) -> (DiscoveryService, SocketAddr, mpsc::Receiver<AppEvent>) {
+    start_in_room(name, None).await
+}
+
+async fn start_in_room(
+    name: &str,
+    room: Option<&str>,
+) -> (DiscoveryService, SocketAddr, mpsc::Receiver<AppEvent>) {
+    let ds = DiscoveryService::new(0)
+        .await
+        .unwrap()
+        .with_room_key(room.and_then(RoomKey::new));
+    let port = ds.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel(64);
+    ds.start_listening(tx, format!("{}-id", name), name.to_string(), 9000);
+    (ds, SocketAddr::from(([127, 0, 0, 1], port)), rx)
+}
+
+/// The only peer update in the next delta
+async fn next_event(rx: &mut mpsc::Receiver<AppEvent>, within: Duration) -> AppEvent {
+    let event = timeout(within, rx.recv())
+        .await
+        .expect("timed out waiting for discovery event")
+        .expect("event channel closed");
+    match event {
+        AppEvent::PeerListDelta(delta) => match &delta.events()[..] {
+            [event] => event.clone(),
+            events => panic!("expected one peer update, got {:?}", events),
+        },
+        other => panic!("unexpected event: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_peer_lost_after_heartbeats_stop() {
+    let (a, _a_addr, mut a_rx) = start("alice").await;
+    let (b, b_addr, mut b_rx) = start("bob").await;
+
+    a.send_discovery_request_to(b_addr, "alice-id".into(), "alice".into(), 9000)
+        .await;
+
+    match next_event(&mut b_rx, Duration::from_secs(2)).await {
+        AppEvent::PeerFound { endpoint_id, .. } => assert_eq!(endpoint_id, "alice-id"),
+        other => panic!("unexpected event: {:?}", other),
+    }
+    match next_event(&mut a_rx, Duration::from_secs(2)).await {
+        AppEvent::PeerFound { endpoint_id, .. } => assert_eq!(endpoint_id, "bob-id"),
+        other => panic!("unexpected event: {:?}", other),
+    }
+
+    // Heartbeats keep both sides alive without further broadcasts
+    tokio::time::sleep(PEER_LOST_TIMEOUT + Duration::from_millis(500)).await;
+    assert_eq!(a.known_peers(), 1);
+    assert_eq!(b.known_peers(), 1);
+    assert!(
+        a_rx.try_recv().is_err(),
+        "no events while both peers are up"
+    );
+
+    b.shutdown();
+    match next_event(&mut a_rx, PEER_LOST_TIMEOUT + Duration::from_secs(1)).await {
+        AppEvent::PeerLost { endpoint_id, ip } => {
+            assert_eq!(endpoint_id, "bob-id");
+            assert_eq!(ip, "127.0.0.1");
+        }
+        other => panic!("unexpected event: {:?}", other),
+    }
+    assert_eq!(a.known_peers(), 0);
+}
+
+#[tokio::test]
+async fn test_room_key_hides_other_rooms() {
+    let (alice, _, mut alice_rx) = start_in_room("alice", Some("team-a")).await;
+    let (_bob, bob_addr, mut bob_rx) = start_in_room("bob", Some("team-b")).await;
+    let (_carol, carol_addr, mut carol_rx) = start_in_room("carol", Some("team-a")).await;
+    let (_dave, dave_addr, mut dave_rx) = start("dave").await;
+
+    for target in [bob_addr, carol_addr, dave_addr] {
+        alice
+            .send_discovery_request_to(target, "alice-id".into(), "alice".into(), 9000)
+            .await;
+    }
+
+    match next_event(&mut carol_rx, Duration::from_secs(2)).await {
+        AppEvent::PeerFound { endpoint_id, .. } => assert_eq!(endpoint_id, "alice-id"),
+        other => panic!("unexpected event: {:?}", other),
+    }
+    match next_event(&mut alice_rx, Duration::from_secs(2)).await {
+        AppEvent::PeerFound { endpoint_id, .. } => assert_eq!(endpoint_id, "carol-id"),
+        other => panic!("unexpected event: {:?}", other),
+    }
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(bob_rx.try_recv().is_err(), "other room must not see alice");
+    assert!(
+        dave_rx.try_recv().is_err(),
+        "roomless peer must not see alice"
+    );
+    assert!(alice_rx.try_recv().is_err());
+    assert_eq!(alice.known_peers(), 1);
+}
@@ -0,0 +1,208 @@
+use p2p_core::discovery::{build_packet, parse_packet};
+use p2p_core::transfer::constants::MAX_FILENAME_LENGTH;
+use p2p_core::transfer::protocol::decode_msg;
+use p2p_core::transfer::{MAX_MSG_SIZE, TransferMsg, sanitize_file_name};
+use p2p_core::{DiscoveryMsg, FileInfo, MAGIC_BYTES, PeerCapabilities};
+use proptest::prelude::*;
+use std::path::{Component, Path};
+
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Names built from the pieces most likely to break sanitization
+fn hostile_name() -> impl Strategy<Value = String> {
+    let piece = prop_oneof![
+        Just("..".to_string()),
+        Just(".".to_string()),
+        Just("/".to_string()),
+        Just("\\".to_string()),
+        Just("\0".to_string()),
+        Just(":".to_string()),
+        Just(" ".to_string()),
+        Just("C:".to_string()),
+        Just("\u{202e}".to_string()),
+        Just("ファイル".to_string()),
+        Just("tệp".to_string()),
+        proptest::sample::select(RESERVED.to_vec()).prop_map(str::to_string),
+        "[a-zA-Z0-9_.-]{1,12}",
+        any::<String>(),
+    ];
+    proptest::collection::vec(piece, 0..40).prop_map(|parts| parts.concat())
+}
+
+fn transfer_msg() -> impl Strategy<Value = TransferMsg> {
+    prop_oneof![
+        (
+            any::<String>(),
+            any::<String>(),
+            proptest::option::of(any::<String>())
+        )
+            .prop_map(|(endpoint_id, peer_name, note)| {
+                TransferMsg::PairingRequest {
+                    endpoint_id,
+                    peer_name,
+                    note,
+                }
+            }),
+        (any::<String>(), any::<String>(), any::<String>()).prop_map(
+            |(endpoint_id, peer_name, secret)| TransferMsg::InviteRedeem {
+                endpoint_id,
+                peer_name,
+                secret,
+            }
+        ),
+        Just(TransferMsg::PairingAccepted),
+        Just(TransferMsg::VerificationRequired),
+        any::<String>().prop_map(|code| TransferMsg::VerificationCode { code }),
+        any::<u32>().prop_map(|attempts_left| TransferMsg::VerificationRetry { attempts_left }),
+        any::<u32>().prop_map(|ahead| TransferMsg::PairingQueued { ahead }),
+        any::<String>().prop_map(|message| TransferMsg::VerificationFailed { message }),
+        (
+            any::<String>(),
+            proptest::option::of(any::<u64>()),
+            proptest::option::of("[0-9a-f]{64}"),
+            proptest::option::of(any::<u64>()),
+            proptest::option::of(any::<u32>()),
+            proptest::option::of(any::<String>()),
+        )
+            .prop_map(|(file_name, file_size, file_hash, modified, mode, note)| {
+                TransferMsg::FileMetadata {
+                    info: FileInfo {
+                        file_name,
+                        file_size,
+                        file_path: Default::default(),
+                        file_hash,
+                        hash_algorithm: Default::default(),
+                        modified,
+                        mode,
+                        note,
+                        xattrs: Vec::new(),
+                    },
+                }
+            }),
+        (
+            any::<u64>(),
+            proptest::option::of("[0-9a-f]{64}"),
+            "[0-9a-f]{32}",
+            any::<bool>()
+        )
+            .prop_map(
+                |(offset, prefix_hash, token, receipts)| TransferMsg::ResumeInfo {
+                    offset,
+                    prefix_hash,
+                    token,
+                    ack_version: 1,
+                    receipts,
+                }
+            ),
+        (
+            any::<u64>(),
+            "[0-9a-f]{32}",
+            any::<bool>(),
+            0..3u32,
+            any::<bool>()
+        )
+            .prop_map(|(offset, token, confirm_hash, ack_version, want_receipt)| {
+                TransferMsg::ResumeStart {
+                    offset,
+                    token,
+                    confirm_hash,
+                    ack_version,
+                    want_receipt,
+                }
+            }),
+        Just(TransferMsg::TransferComplete),
+        Just(TransferMsg::AckRequest),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn sanitized_names_stay_inside_download_dir(name in hostile_name()) {
+        let sanitized = sanitize_file_name(&name);
+
+        prop_assert!(!sanitized.is_empty());
+        prop_assert!(sanitized != "." && sanitized != "..");
+        prop_assert!(!sanitized.contains(['/', '\\', ':', '\0']));
+        prop_assert!(!sanitized.chars().any(char::is_control));
+
+        let joined = Path::new("downloads").join(&sanitized);
+        prop_assert_eq!(joined.parent(), Some(Path::new("downloads")));
+        prop_assert!(
+            Path::new(&sanitized)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+        );
+    }
+
+    #[test]
+    fn sanitized_names_avoid_windows_reserved_stems(name in hostile_name()) {
+        let sanitized = sanitize_file_name(&name);
+        let stem = sanitized.split('.').next().unwrap_or("").to_ascii_uppercase();
+        prop_assert!(!RESERVED.contains(&stem.as_str()), "{:?} -> {:?}", name, sanitized);
+    }
+
+    #[test]
+    fn sanitized_names_fit_windows_rules(name in hostile_name()) {
+        let sanitized = sanitize_file_name(&name);
+        prop_assert!(sanitized.len() <= MAX_FILENAME_LENGTH);
+        prop_assert!(!sanitized.ends_with(['.', ' ']), "{:?} -> {:?}", name, sanitized);
+    }
+
+    #[test]
+    fn sanitize_is_idempotent(name in hostile_name()) {
+        let once = sanitize_file_name(&name);
+        prop_assert_eq!(sanitize_file_name(&once), once);
+    }
+
+    #[test]
+    fn decode_arbitrary_bytes_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let _ = decode_msg(&bytes);
+    }
+
+    #[test]
+    fn transfer_msg_round_trips(msg in transfer_msg()) {
+        let json = serde_json::to_vec(&msg).unwrap();
+        prop_assume!(json.len() <= MAX_MSG_SIZE);
+        let decoded = decode_msg(&json).unwrap();
+        prop_assert_eq!(format!("{:?}", decoded), format!("{:?}", msg));
+    }
+
+    #[test]
+    fn discovery_parse_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let _ = parse_packet(&bytes);
+        let mut prefixed = MAGIC_BYTES.to_vec();
+        prefixed.extend_from_slice(&bytes);
+        let _ = parse_packet(&prefixed);
+    }
+
+    #[test]
+    fn discovery_packet_round_trips(
+        endpoint_id in any::<String>(),
+        my_name in any::<String>(),
+        port in any::<u16>(),
+        receive_only in any::<bool>(),
+        protocol in any::<u32>(),
+    ) {
+        let capabilities = PeerCapabilities { receive_only, protocol };
+        let msg = DiscoveryMsg::DiscoveryResponse {
+            endpoint_id,
+            my_name,
+            port,
+            capabilities,
+            version: Some(p2p_core::APP_VERSION.to_string()),
+            echo_ms: Some(u64::from(port)),
+        };
+        let packet = build_packet(&msg).unwrap();
+        let parsed = parse_packet(&packet).unwrap();
+        prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", msg));
+    }
+}
+
+#[test]
+fn test_oversized_message_rejected() {
+    let body = vec![b' '; MAX_MSG_SIZE + 1];
+    assert!(decode_msg(&body).is_err());
+}
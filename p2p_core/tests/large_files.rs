@@ -0,0 +1,97 @@
+use p2p_core::testing::TestPair;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Create a sparse file of `size` bytes with a marker at the start, the
+/// middle and the end, so content checks catch misplaced holes
+fn create_sparse_file(path: &Path, size: u64) -> Vec<(u64, &'static [u8])> {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let markers: Vec<(u64, &'static [u8])> = vec![
+        (0, b"start"),
+        (size / 2 + 13, b"middle"),
+        (size - 3, b"end"),
+    ];
+
+    let mut file = std::fs::File::create(path).unwrap();
+    file.set_len(size).unwrap();
+    for (offset, bytes) in &markers {
+        file.seek(SeekFrom::Start(*offset)).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+    markers
+}
+
+fn assert_markers(path: &Path, size: u64, markers: &[(u64, &[u8])]) {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path).unwrap();
+    assert_eq!(file.metadata().unwrap().len(), size);
+    for (offset, bytes) in markers {
+        let mut buf = vec![0u8; bytes.len()];
+        file.seek(SeekFrom::Start(*offset)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, bytes, "marker at {}", offset);
+    }
+}
+
+/// Check that `received` stayed sparse, when the file system kept the holes
+/// of `source` to begin with
+fn assert_holes_kept(source: &Path, received: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let allocated = |path: &Path| std::fs::metadata(path).unwrap().blocks() * 512;
+        let size = std::fs::metadata(received).unwrap().len();
+        if allocated(source) < size / 8 {
+            let received = allocated(received);
+            assert!(
+                received < size / 8,
+                "allocated {} of {} bytes",
+                received,
+                size
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (source, received);
+}
+
+#[tokio::test]
+async fn test_sparse_file_round_trip() {
+    let mut pair = TestPair::new().await.unwrap();
+    let size = 48 * 1024 * 1024 + 5;
+    let source = pair.sender.root().join("outgoing").join("disk.img");
+    let markers = create_sparse_file(&source, size);
+
+    pair.send_with_pairing(vec![source.clone()]).await.unwrap();
+
+    let received = pair.receiver.download_dir().join("disk.img");
+    assert_markers(&received, size, &markers);
+    assert_eq!(
+        std::fs::read(&received).unwrap(),
+        std::fs::read(&source).unwrap()
+    );
+    assert_holes_kept(&source, &received);
+
+    pair.shutdown().await;
+}
+
+/// Moves more than 4 GB over loopback, which takes minutes in debug builds.
+/// Run with `cargo test --release -p p2p_core --test large_files -- --ignored`.
+#[tokio::test]
+#[ignore = "slow: transfers 5 GB over loopback"]
+async fn test_transfer_larger_than_4gb() {
+    let mut pair = TestPair::new().await.unwrap();
+    let size = 5 * 1024 * 1024 * 1024 + 17;
+    let source = pair.sender.root().join("outgoing").join("huge.img");
+    let markers = create_sparse_file(&source, size);
+
+    pair.send_with_pairing(vec![source.clone()]).await.unwrap();
+
+    let received = pair.receiver.download_dir().join("huge.img");
+    assert_markers(&received, size, &markers);
+    assert_holes_kept(&source, &received);
+
+    pair.shutdown().await;
+}
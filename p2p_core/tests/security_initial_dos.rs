@@ -1,4 +1,4 @@
-use p2p_core::transfer::{make_client_endpoint, make_server_endpoint, run_server};
+use p2p_core::transfer::{ServerOptions, make_client_endpoint, make_server_endpoint, run_server};
 use std::path::PathBuf;
 use tokio::sync::mpsc;
 
@@ -16,7 +16,15 @@ async fn test_initial_connection_hang() {
     // Spawn server
     let endpoint_clone = server_endpoint.clone();
     tokio::spawn(async move {
-        run_server(endpoint_clone, tx, download_dir).await;
+        run_server(
+            endpoint_clone,
+            tx,
+            ServerOptions {
+                download_dir,
+                ..Default::default()
+            },
+        )
+        .await;
     });
 
     // 2. Connect client and HANG
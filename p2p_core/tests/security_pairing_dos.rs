@@ -27,13 +27,21 @@ async fn test_pairing_dos_timeout() {
 
     let server_endpoint_clone = server_endpoint.clone();
     tokio::spawn(async move {
-        p2p_core::transfer::run_server(server_endpoint_clone, tx, download_dir).await;
+        p2p_core::transfer::run_server(
+            server_endpoint_clone,
+            tx,
+            p2p_core::transfer::ServerOptions {
+                download_dir,
+                ..Default::default()
+            },
+        )
+        .await;
     });
 
     // Spawn a task to drain the event channel so the server doesn't block on sending events
-    tokio::spawn(async move { while let Some(_) = rx.recv().await {} });
+    tokio::spawn(async move { while rx.recv().await.is_some() {} });
 
-    // 2. Connect 3 stalling clients (MAX_PAIRING_ATTEMPTS = 3)
+    // 2. Connect 3 stalling clients (DEFAULT_MAX_CONCURRENT_PAIRINGS = 3)
     let client_endpoint = make_client_endpoint().unwrap();
     let mut stalled_conns = Vec::new();
 
@@ -49,6 +57,7 @@ async fn test_pairing_dos_timeout() {
         let msg = TransferMsg::PairingRequest {
             endpoint_id: format!("attacker_{}", i),
             peer_name: "Attacker".to_string(),
+            note: None,
         };
         send_msg(&mut send, &msg).await.unwrap();
 
@@ -62,8 +71,8 @@ async fn test_pairing_dos_timeout() {
         }
     }
 
-    // 3. Try 4th client - should be rejected immediately (slots full)
-    {
+    // 3. Try 4th client - should wait in line (slots full), first in line
+    let (_queued_send, mut queued_recv) = {
         let connection = client_endpoint
             .connect(server_addr, "localhost")
             .unwrap()
@@ -74,20 +83,29 @@ async fn test_pairing_dos_timeout() {
         let msg = TransferMsg::PairingRequest {
             endpoint_id: "victim_1".to_string(),
             peer_name: "Victim 1".to_string(),
+            note: None,
         };
         send_msg(&mut send, &msg).await.unwrap();
 
         let resp = recv_msg(&mut recv).await.unwrap();
         match resp {
-            TransferMsg::VerificationFailed { message } => {
-                assert_eq!(message, "Too many pending verification attempts");
-            }
-            _ => panic!("Expected VerificationFailed immediately, got {:?}", resp),
+            TransferMsg::PairingQueued { ahead } => assert_eq!(ahead, 0),
+            _ => panic!("Expected PairingQueued immediately, got {:?}", resp),
         }
-    }
-
-    // 4. Wait for timeout (1.5s > 1s)
-    tokio::time::sleep(Duration::from_millis(1500)).await;
+        (send, recv)
+    };
+
+    // 4. The stalled prompts time out (1s), which lets the 4th client in
+    let resp = tokio::time::timeout(Duration::from_secs(3), recv_msg(&mut queued_recv))
+        .await
+        .expect("queued client was never admitted")
+        .unwrap();
+    assert!(
+        matches!(resp, TransferMsg::VerificationRequired),
+        "Expected VerificationRequired after the queue, got {:?}",
+        resp
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
 
     // 5. Try 5th client - should SUCCEED if timeout works (slots freed)
     // If vulnerability exists (no timeout), this will fail with "Too many pending verification attempts"
@@ -102,6 +120,7 @@ async fn test_pairing_dos_timeout() {
         let msg = TransferMsg::PairingRequest {
             endpoint_id: "victim_2".to_string(),
             peer_name: "Victim 2".to_string(),
+            note: None,
         };
         send_msg(&mut send, &msg).await.unwrap();
 
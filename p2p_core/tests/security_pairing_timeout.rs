@@ -1,5 +1,5 @@
 use p2p_core::transfer::protocol::{TransferMsg, recv_msg, send_msg};
-use p2p_core::transfer::{make_client_endpoint, make_server_endpoint, run_server};
+use p2p_core::transfer::{ServerOptions, make_client_endpoint, make_server_endpoint, run_server};
 use std::path::PathBuf;
 use tokio::sync::mpsc;
 
@@ -22,7 +22,15 @@ async fn test_pairing_dos_reproduction() {
     // Spawn server
     let endpoint_clone = server_endpoint.clone();
     tokio::spawn(async move {
-        run_server(endpoint_clone, tx, download_dir).await;
+        run_server(
+            endpoint_clone,
+            tx,
+            ServerOptions {
+                download_dir,
+                ..Default::default()
+            },
+        )
+        .await;
     });
 
     // 2. Spawn 3 "Attacker" Clients that hang during handshake
@@ -45,6 +53,7 @@ async fn test_pairing_dos_reproduction() {
                 &TransferMsg::PairingRequest {
                     endpoint_id: format!("attacker-{}", i),
                     peer_name: format!("Attacker {}", i),
+                    note: None,
                 },
             )
             .await
@@ -83,25 +92,19 @@ async fn test_pairing_dos_reproduction() {
         &TransferMsg::PairingRequest {
             endpoint_id: "legitimate-user".to_string(),
             peer_name: "Legitimate User".to_string(),
+            note: None,
         },
     )
     .await
     .unwrap();
 
-    // Expect Rejection (VerificationFailed) immediately
+    // Expect to wait in line immediately
     // Because slots are full (3 attackers holding slots)
     let msg = recv_msg(&mut recv).await.unwrap();
 
     match msg {
-        TransferMsg::VerificationFailed { message } => {
-            assert!(
-                message.contains("Too many pending attempts")
-                    || message.contains("Too many pending verification attempts"),
-                "Expected 'Too many pending attempts', got: {}",
-                message
-            );
-        }
-        _ => panic!("Expected VerificationFailed due to DoS, got {:?}", msg),
+        TransferMsg::PairingQueued { ahead } => assert_eq!(ahead, 0),
+        _ => panic!("Expected PairingQueued due to DoS, got {:?}", msg),
     }
 
     // Cleanup attackers
@@ -111,6 +114,16 @@ async fn test_pairing_dos_reproduction() {
     println!("Waiting for timeout...");
     tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
 
+    // The queued client got the first freed slot
+    let msg = recv_msg(&mut recv).await.unwrap();
+    match msg {
+        TransferMsg::VerificationRequired => {}
+        _ => panic!(
+            "Expected VerificationRequired after queueing, got {:?}",
+            msg
+        ),
+    }
+
     println!("Connecting 5th client...");
     let success_endpoint = client_endpoint.clone();
     let connection = success_endpoint
@@ -125,6 +138,7 @@ async fn test_pairing_dos_reproduction() {
         &TransferMsg::PairingRequest {
             endpoint_id: "success-user".to_string(),
             peer_name: "Success User".to_string(),
+            note: None,
         },
     )
     .await
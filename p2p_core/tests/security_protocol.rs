@@ -54,7 +54,10 @@ async fn test_large_message_rejection() {
 
     // 6. Wait for server result
     // Use timeout to prevent hanging if test fails
-    if let Err(_) = tokio::time::timeout(tokio::time::Duration::from_secs(2), server_handle).await {
+    if tokio::time::timeout(tokio::time::Duration::from_secs(2), server_handle)
+        .await
+        .is_err()
+    {
         panic!("Test timed out");
     }
 }
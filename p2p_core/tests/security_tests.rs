@@ -73,11 +73,11 @@ async fn test_websocket_upload_overflow() {
     // 3. Wait for Accepted message from WebSocket
     let mut accepted = false;
     while let Some(msg) = read.next().await {
-        if let Ok(tokio_tungstenite::tungstenite::Message::Text(text)) = msg {
-            if text.contains("accepted") {
-                accepted = true;
-                break;
-            }
+        if let Ok(tokio_tungstenite::tungstenite::Message::Text(text)) = msg
+            && text.contains("accepted")
+        {
+            accepted = true;
+            break;
         }
     }
     assert!(accepted, "Should have received accepted message");
@@ -96,11 +96,11 @@ async fn test_websocket_upload_overflow() {
     let mut completed = false;
     let _ = tokio::time::timeout(tokio::time::Duration::from_secs(2), async {
         while let Some(msg) = read.next().await {
-            if let Ok(tokio_tungstenite::tungstenite::Message::Text(text)) = msg {
-                if text.contains("complete") {
-                    completed = true;
-                    break;
-                }
+            if let Ok(tokio_tungstenite::tungstenite::Message::Text(text)) = msg
+                && text.contains("complete")
+            {
+                completed = true;
+                break;
             }
         }
     })
@@ -189,13 +189,14 @@ mod tests {
         let mut rejected_count = 0;
 
         for (_write, mut read, _i) in clients {
-            // Read response
+            // Read response, skipping the approval countdown
             // We expect immediate response after acceptance
-            if let Ok(Some(Ok(Message::Text(text)))) =
+            while let Ok(Some(Ok(Message::Text(text)))) =
                 tokio::time::timeout(tokio::time::Duration::from_secs(1), read.next()).await
             {
                 let server_msg: ServerMessage = serde_json::from_str(&text).unwrap();
                 match server_msg {
+                    ServerMessage::AwaitingApproval { .. } => continue,
                     ServerMessage::Accepted { .. } => accepted_count += 1,
                     ServerMessage::Rejected { reason } => {
                         rejected_count += 1;
@@ -203,6 +204,7 @@ mod tests {
                     }
                     _ => println!("Unexpected message: {:?}", server_msg),
                 }
+                break;
             }
         }
 
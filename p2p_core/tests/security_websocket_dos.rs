@@ -1,6 +1,6 @@
 use futures_util::{SinkExt, StreamExt};
 use p2p_core::http_share::server::create_router_with_websocket;
-use p2p_core::http_share::websocket::{ClientMessage, UploadState};
+use p2p_core::http_share::websocket::UploadState;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -65,10 +65,10 @@ async fn test_large_message_dos() {
                         "Should be closed with code 1009 (Message Too Big)"
                     );
                 }
-            } else if let Message::Text(text) = msg {
-                if text.contains("Expected file_info message") {
-                    panic!("Server accepted large message! Vulnerability present.");
-                }
+            } else if let Message::Text(text) = msg
+                && text.contains("Expected file_info message")
+            {
+                panic!("Server accepted large message! Vulnerability present.");
             }
         }
         Ok(Some(Err(e))) => {
@@ -124,14 +124,13 @@ async fn test_normal_message_size() {
 
     match result {
         Ok(Some(Ok(msg))) => {
-            if let Message::Close(frame) = msg {
-                if let Some(f) = frame {
-                    if u16::from(f.code) == 1009 {
-                        panic!(
-                            "Connection closed with Message Too Big for 256KB message! Limit is too low."
-                        );
-                    }
-                }
+            if let Message::Close(frame) = msg
+                && let Some(f) = frame
+                && u16::from(f.code) == 1009
+            {
+                panic!(
+                    "Connection closed with Message Too Big for 256KB message! Limit is too low."
+                );
             }
             // Any other message (Text error, etc.) means it was accepted by WS layer.
         }
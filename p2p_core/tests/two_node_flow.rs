@@ -0,0 +1,2324 @@
+use p2p_core::history::transfers::{Direction, HistoryQuery, TransferStatus};
+use p2p_core::history::usage::BandwidthCap;
+use p2p_core::journal::SendJournal;
+use p2p_core::network_info::{METERED_CONFIRM_BYTES, MeteredMode};
+use p2p_core::pairing::invite::PairingInvite;
+use p2p_core::pairing::{MemoryPairingStore, PairingStore};
+use p2p_core::policy::{Policy, PolicyRule};
+use p2p_core::storage::FailingStorage;
+use p2p_core::storage::pipe::PipeStorage;
+use p2p_core::testing::{DEFAULT_EVENT_TIMEOUT, TestNode, TestPair, write_test_file};
+use p2p_core::transfer::netem::NetworkEmulation;
+use p2p_core::transfer::{FileAction, ReceiveLimits, RelayPolicy, peer_folder, resume, staging};
+use p2p_core::{AppCommand, AppEvent, FileInfo};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_pair_send_verify_resume() {
+    let mut pair = TestPair::new().await.unwrap();
+    let source_dir = pair.sender.root().join("outgoing");
+
+    // 1. First transfer requires the verification code
+    let first = write_test_file(&source_dir, "first.bin", 256 * 1024).unwrap();
+    pair.send_with_pairing(vec![first.clone()]).await.unwrap();
+
+    let received = pair.receiver.download_dir().join("first.bin");
+    assert_eq!(
+        std::fs::read(&received).unwrap(),
+        std::fs::read(&first).unwrap()
+    );
+
+    assert!(
+        pair.receiver
+            .pairings()
+            .is_paired(pair.sender.endpoint_id())
+    );
+
+    // 2. Paired sender skips verification; a partial file is resumed
+    let second = write_test_file(&source_dir, "second.bin", 512 * 1024).unwrap();
+    let data = std::fs::read(&second).unwrap();
+    let partial = pair.receiver.download_dir().join("second.bin");
+    std::fs::write(&partial, &data[..100 * 1024]).unwrap();
+    // Resume only appends to files with private permissions
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    // ...as left behind by an interrupted transfer of the same file
+    let info = FileInfo {
+        file_name: "second.bin".to_string(),
+        file_size: Some(data.len() as u64),
+        file_path: Default::default(),
+        file_hash: Some(blake3::hash(&data).to_hex().to_string()),
+        hash_algorithm: Default::default(),
+        modified: None,
+        mode: None,
+        note: None,
+        xattrs: Vec::new(),
+    };
+    resume::begin(&partial, &info, "interrupted", None).unwrap();
+
+    pair.send_paired(vec![second]).await.unwrap();
+    assert_eq!(std::fs::read(&partial).unwrap(), data);
+    assert!(!resume::record_path(&partial).exists());
+
+    // 3. A partial copy with different bytes is replaced, not appended to
+    let third = write_test_file(&source_dir, "third.bin", 256 * 1024).unwrap();
+    let data = std::fs::read(&third).unwrap();
+    let partial = pair.receiver.download_dir().join("third.bin");
+    std::fs::write(&partial, vec![0xAAu8; 64 * 1024]).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o600)).unwrap();
+    }
+    let info = FileInfo {
+        file_name: "third.bin".to_string(),
+        file_size: Some(data.len() as u64),
+        file_hash: Some(blake3::hash(&data).to_hex().to_string()),
+        hash_algorithm: Default::default(),
+        ..info
+    };
+    resume::begin(&partial, &info, "interrupted", None).unwrap();
+
+    pair.send_paired(vec![third]).await.unwrap();
+    assert_eq!(std::fs::read(&partial).unwrap(), data);
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_consecutive_sends_reuse_the_connection() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let first = write_test_file(&outgoing, "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+
+    let second = write_test_file(&outgoing, "second.bin", 1024).unwrap();
+    pair.sender
+        .send_files_to(&pair.receiver, vec![second])
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::Log { message, .. } if message.starts_with("Reusing the open connection"))
+        })
+        .await
+        .unwrap();
+    pair.receiver
+        .wait_for_completion("second.bin")
+        .await
+        .unwrap();
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_estimate_counts_only_what_the_receiver_lacks() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let sent = write_test_file(&outgoing, "sent.bin", 64 * 1024).unwrap();
+    pair.send_with_pairing(vec![sent.clone()]).await.unwrap();
+
+    let new = write_test_file(&outgoing, "new.bin", 100 * 1024).unwrap();
+    let changed = write_test_file(&outgoing, "changed.bin", 32 * 1024).unwrap();
+    std::fs::write(
+        pair.receiver.download_dir().join("changed.bin"),
+        vec![7u8; 10 * 1024],
+    )
+    .unwrap();
+    let session_id = p2p_core::new_session_id();
+    pair.sender
+        .command(AppCommand::EstimateSend {
+            session_id: session_id.clone(),
+            target_ip: pair.receiver.transfer_addr().to_string(),
+            target_peer_name: pair.receiver.name().to_string(),
+            files: vec![sent, new, changed],
+            note: None,
+        })
+        .await
+        .unwrap();
+    let event = pair
+        .sender
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::SendEstimated { session_id: id, .. } if *id == session_id),
+        )
+        .await
+        .unwrap();
+    let AppEvent::SendEstimated { estimate, .. } = event else {
+        unreachable!()
+    };
+    assert_eq!(estimate.files, 3);
+    assert_eq!(estimate.total_bytes, 196 * 1024);
+    assert_eq!(estimate.bytes_to_send, 132 * 1024);
+    assert_eq!(estimate.complete_files, 1);
+    assert!(estimate.refused.is_empty());
+    assert!(estimate.fits());
+    let actions: Vec<_> = estimate
+        .preview
+        .iter()
+        .map(|file| (file.file_name.as_str(), file.action.clone()))
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            ("sent.bin", FileAction::Skip),
+            ("new.bin", FileAction::New),
+            (
+                "changed.bin",
+                FileAction::Overwrite {
+                    existing_size: 10 * 1024
+                }
+            ),
+        ]
+    );
+    assert_eq!(estimate.overwrites(), 1);
+
+    // Nothing was written on the receiver
+    let mut names: Vec<_> = std::fs::read_dir(pair.receiver.download_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["changed.bin", "sent.bin"]);
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_get_state_reports_a_running_backend() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let file = write_test_file(&outgoing, "state.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![file]).await.unwrap();
+
+    pair.receiver.command(AppCommand::GetState).await.unwrap();
+    let event = pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::StateSnapshot(_))
+        })
+        .await
+        .unwrap();
+    let AppEvent::StateSnapshot(state) = event else {
+        unreachable!();
+    };
+    assert_eq!(state.endpoint_id, pair.receiver.endpoint_id());
+    assert_eq!(state.download_dir, pair.receiver.download_dir());
+    assert_eq!(state.paired_devices, 1);
+    assert!(state.transfers.is_empty());
+    assert!(!state.receive_only);
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_per_peer_folders_sort_received_files() {
+    let mut pair = TestPair {
+        sender: TestNode::spawn("sender").await.unwrap(),
+        receiver: TestNode::spawn_with("receiver", |builder| builder.per_peer_folders(true))
+            .await
+            .unwrap(),
+    };
+    let folder = peer_folder(
+        pair.receiver.download_dir(),
+        "sender",
+        pair.sender.endpoint_id(),
+    );
+    let outgoing = pair.sender.root().join("outgoing");
+    let first = write_test_file(&outgoing, "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+    assert!(folder.join("first.bin").exists());
+    assert!(!pair.receiver.download_dir().join("first.bin").exists());
+
+    let second = write_test_file(&outgoing, "second.bin", 1024).unwrap();
+    pair.sender
+        .send_files_to(&pair.receiver, vec![second])
+        .await
+        .unwrap();
+    let completed = pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::TransferCompleted { .. })
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        completed,
+        AppEvent::TransferCompleted { saved_path: Some(ref path), .. }
+            if *path == folder.join("second.bin")
+    ));
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_transfer_survives_an_emulated_slow_link() {
+    let slow = NetworkEmulation::parse("latency_ms=20,jitter_ms=20,rate_kbps=16000").unwrap();
+    let mut pair = TestPair {
+        sender: TestNode::spawn_with("sender", |builder| builder.network_emulation(slow))
+            .await
+            .unwrap(),
+        receiver: TestNode::spawn("receiver").await.unwrap(),
+    };
+    let file = write_test_file(
+        &pair.sender.root().join("outgoing"),
+        "slow.bin",
+        1024 * 1024,
+    )
+    .unwrap();
+
+    let started = std::time::Instant::now();
+    pair.send_with_pairing(vec![file.clone()]).await.unwrap();
+    // 1 MiB at 16 Mbit/s takes half a second
+    assert!(started.elapsed() >= Duration::from_millis(500));
+    assert_eq!(
+        std::fs::read(pair.receiver.download_dir().join("slow.bin")).unwrap(),
+        std::fs::read(&file).unwrap()
+    );
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_daily_quota_refuses_files() {
+    let limits = ReceiveLimits {
+        daily_quota_bytes: Some(3000),
+        ..ReceiveLimits::default()
+    };
+    let mut pair = TestPair {
+        sender: TestNode::spawn("sender").await.unwrap(),
+        receiver: TestNode::spawn_with("receiver", |builder| builder.receive_limits(limits))
+            .await
+            .unwrap(),
+    };
+    let outgoing = pair.sender.root().join("outgoing");
+    let first = write_test_file(&outgoing, "first.bin", 2000).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+
+    let second = write_test_file(&outgoing, "second.bin", 2000).unwrap();
+    pair.sender
+        .send_files_to(&pair.receiver, vec![second])
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::Error(message) if message.contains("Daily quota")),
+        )
+        .await
+        .unwrap();
+    assert!(!pair.receiver.download_dir().join("second.bin").exists());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_stream_over_daily_quota_is_cancelled() {
+    let limits = ReceiveLimits {
+        daily_quota_bytes: Some(2 * 1024 * 1024),
+        ..ReceiveLimits::default()
+    };
+    let mut pair = TestPair {
+        sender: TestNode::spawn("sender").await.unwrap(),
+        receiver: TestNode::spawn_with("receiver", |builder| builder.receive_limits(limits))
+            .await
+            .unwrap(),
+    };
+    let first = write_test_file(&pair.sender.root().join("outgoing"), "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+
+    let target = pair.receiver.transfer_addr().to_string();
+    let name = pair.receiver.name().to_string();
+    pair.sender
+        .node()
+        .send_stream(
+            &target,
+            &name,
+            "endless.log",
+            std::io::Cursor::new(vec![7u8; 4 * 1024 * 1024]),
+        )
+        .await
+        .unwrap();
+    let cancelled = wait_for_cancel(&mut pair.sender, "endless.log").await;
+    assert!(matches!(
+        cancelled,
+        AppEvent::TransferCancelled { by_peer: true, reason, .. } if reason.contains("Daily quota")
+    ));
+    assert!(!pair.receiver.download_dir().join("endless.log").exists());
+    assert!(part_files(&pair.receiver, "endless.log").is_empty());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_bandwidth_usage_is_counted_and_capped() {
+    let cap = BandwidthCap {
+        monthly_bytes: Some(1000),
+        hold_scheduled: true,
+    };
+    let mut pair = TestPair {
+        sender: TestNode::spawn_with("sender", |builder| builder.bandwidth_cap(cap))
+            .await
+            .unwrap(),
+        receiver: TestNode::spawn("receiver").await.unwrap(),
+    };
+    let outgoing = pair.sender.root().join("outgoing");
+    let file = write_test_file(&outgoing, "metered.bin", 2000).unwrap();
+    pair.send_with_pairing(vec![file]).await.unwrap();
+
+    pair.receiver
+        .command(AppCommand::GetBandwidthUsage)
+        .await
+        .unwrap();
+    let event = pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::BandwidthUsage(_))
+        })
+        .await
+        .unwrap();
+    let AppEvent::BandwidthUsage(usage) = event else {
+        unreachable!()
+    };
+    assert_eq!((usage.month.sent, usage.month.received), (0, 2000));
+
+    pair.sender
+        .command(AppCommand::GetBandwidthUsage)
+        .await
+        .unwrap();
+    let event = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::BandwidthCapWarning { .. })
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        event,
+        AppEvent::BandwidthCapWarning {
+            used: 2000,
+            cap: 1000,
+            reached: true,
+            holding_scheduled: true,
+        }
+    ));
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_large_send_on_metered_connection_asks_first() {
+    let mut pair = TestPair {
+        sender: TestNode::spawn_with("sender", |builder| {
+            builder.metered_mode(MeteredMode::Always)
+        })
+        .await
+        .unwrap(),
+        receiver: TestNode::spawn("receiver").await.unwrap(),
+    };
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::NetworkCost { metered: true, .. })
+        })
+        .await
+        .unwrap();
+
+    // Sparse, so the test does not write 100 MB
+    let outgoing = pair.sender.root().join("outgoing");
+    std::fs::create_dir_all(&outgoing).unwrap();
+    let big = outgoing.join("big.bin");
+    std::fs::File::create(&big)
+        .unwrap()
+        .set_len(METERED_CONFIRM_BYTES + 1)
+        .unwrap();
+    pair.sender
+        .send_files_to(&pair.receiver, vec![big])
+        .await
+        .unwrap();
+    let event = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::MeteredSendHeld { .. })
+        })
+        .await
+        .unwrap();
+    let AppEvent::MeteredSendHeld {
+        session_id,
+        files,
+        total_size,
+        ..
+    } = event
+    else {
+        unreachable!()
+    };
+    assert_eq!((files, total_size), (1, METERED_CONFIRM_BYTES + 1));
+
+    pair.sender
+        .command(AppCommand::RespondMeteredSend {
+            session_id,
+            accepted: false,
+        })
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::Log { message, .. } if message.contains("dropped")),
+        )
+        .await
+        .unwrap();
+
+    // Small sends go ahead, only slowed down
+    let small = write_test_file(&outgoing, "small.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![small]).await.unwrap();
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_disk_full_fails_the_receive() {
+    let storage = Arc::new(FailingStorage::local().disk_full_after(64 * 1024));
+    let receiver_storage = storage.clone();
+    let mut pair = TestPair {
+        sender: TestNode::spawn("sender").await.unwrap(),
+        receiver: TestNode::spawn_with("receiver", |builder| builder.storage(receiver_storage))
+            .await
+            .unwrap(),
+    };
+    let outgoing = pair.sender.root().join("outgoing");
+    let small = write_test_file(&outgoing, "small.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![small]).await.unwrap();
+
+    let big = write_test_file(&outgoing, "big.bin", 256 * 1024).unwrap();
+    pair.sender
+        .send_files_to(&pair.receiver, vec![big])
+        .await
+        .unwrap();
+    pair.receiver
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::Error(message) if message.contains("injected write failure")),
+        )
+        .await
+        .unwrap();
+    assert_eq!(storage.bytes_written(), 64 * 1024);
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_failed_preallocation_fails_before_writing() {
+    let storage = Arc::new(FailingStorage::local().fail_preallocate(ErrorKind::StorageFull));
+    let receiver_storage = storage.clone();
+    let mut pair = TestPair {
+        sender: TestNode::spawn("sender").await.unwrap(),
+        receiver: TestNode::spawn_with("receiver", |builder| builder.storage(receiver_storage))
+            .await
+            .unwrap(),
+    };
+    let outgoing = pair.sender.root().join("outgoing");
+    let big = write_test_file(&outgoing, "big.bin", 256 * 1024).unwrap();
+    let transfer = pair
+        .sender
+        .send_files_to(&pair.receiver, vec![big])
+        .await
+        .unwrap();
+    let code = match pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::ShowVerificationCode { .. })
+        })
+        .await
+        .unwrap()
+    {
+        AppEvent::ShowVerificationCode { code, .. } => code,
+        _ => unreachable!(),
+    };
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::RequestVerificationCode { .. })
+        })
+        .await
+        .unwrap();
+    transfer.submit_verification_code(&code).await.unwrap();
+    pair.receiver
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::Error(message) if message.contains("injected preallocation failure")),
+        )
+        .await
+        .unwrap();
+    assert_eq!(storage.bytes_written(), 0);
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_file_larger_than_free_space_is_refused() {
+    let storage = Arc::new(FailingStorage::local().report_space(4096));
+    let mut pair = TestPair {
+        sender: TestNode::spawn("sender").await.unwrap(),
+        receiver: TestNode::spawn_with("receiver", |builder| builder.storage(storage))
+            .await
+            .unwrap(),
+    };
+    let outgoing = pair.sender.root().join("outgoing");
+    let small = write_test_file(&outgoing, "small.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![small]).await.unwrap();
+
+    let big = write_test_file(&outgoing, "big.bin", 8192).unwrap();
+    pair.sender
+        .send_files_to(&pair.receiver, vec![big])
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::Error(message) if message.contains("Not enough free space")),
+        )
+        .await
+        .unwrap();
+    assert!(!pair.receiver.download_dir().join("big.bin").exists());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_wrong_code_is_rejected() {
+    let mut pair = TestPair::new().await.unwrap();
+    let file = write_test_file(&pair.sender.root().join("outgoing"), "nope.bin", 1024).unwrap();
+
+    let transfer = pair
+        .sender
+        .send_files_to(&pair.receiver, vec![file])
+        .await
+        .unwrap();
+
+    let code = match pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::ShowVerificationCode { .. })
+        })
+        .await
+        .unwrap()
+    {
+        AppEvent::ShowVerificationCode { code, .. } => code,
+        _ => unreachable!(),
+    };
+    let wrong = if code == "0000" { "1111" } else { "0000" };
+    transfer.submit_verification_code(wrong).await.unwrap();
+
+    let result = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::PairingResult { .. })
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        AppEvent::PairingResult { success: false, .. }
+    ));
+    assert!(!pair.receiver.download_dir().join("nope.bin").exists());
+    assert!(
+        !pair
+            .receiver
+            .pairings()
+            .is_paired(pair.sender.endpoint_id())
+    );
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_timestamps_and_permissions_are_preserved() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let mut pair = TestPair::new().await.unwrap();
+    let file = write_test_file(&pair.sender.root().join("outgoing"), "dated.bin", 4096).unwrap();
+    let modified = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    std::fs::File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o750)).unwrap();
+    }
+
+    pair.send_with_pairing(vec![file]).await.unwrap();
+
+    // Metadata is restored once the background hash check passes, which may
+    // finish before or after the transfer is reported complete
+    let path = pair.receiver.download_dir().join("dated.bin");
+    let deadline = tokio::time::Instant::now() + DEFAULT_EVENT_TIMEOUT;
+    while std::fs::metadata(&path).unwrap().modified().unwrap() != modified
+        && tokio::time::Instant::now() < deadline
+    {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let received = std::fs::metadata(&path).unwrap();
+    assert_eq!(received.modified().unwrap(), modified);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // Only the owner may read a received file
+        assert_eq!(received.permissions().mode() & 0o777, 0o600);
+    }
+
+    pair.shutdown().await;
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_extended_attributes_are_sent_when_opted_in() {
+    use p2p_core::transfer::xattrs::{
+        ExtendedAttribute, read_extended_attributes, write_extended_attributes,
+    };
+
+    let mut pair = TestPair {
+        sender: TestNode::spawn_with("sender", |builder| builder.send_extended_attributes(true))
+            .await
+            .unwrap(),
+        receiver: TestNode::spawn("receiver").await.unwrap(),
+    };
+    let file = write_test_file(&pair.sender.root().join("outgoing"), "tagged.psd", 4096).unwrap();
+    let tag = ExtendedAttribute {
+        name: "user.xdg.tags".to_string(),
+        value: b"final,blue".to_vec(),
+    };
+    if write_extended_attributes(&file, std::slice::from_ref(&tag)).is_err() {
+        // No user attributes on this file system
+        pair.shutdown().await;
+        return;
+    }
+
+    pair.send_with_pairing(vec![file]).await.unwrap();
+
+    // Restored with the rest of the metadata once the hash check passes
+    let path = pair.receiver.download_dir().join("tagged.psd");
+    let deadline = tokio::time::Instant::now() + DEFAULT_EVENT_TIMEOUT;
+    while read_extended_attributes(&path).unwrap().is_empty()
+        && tokio::time::Instant::now() < deadline
+    {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(read_extended_attributes(&path).unwrap(), vec![tag]);
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_receiver_signs_a_receipt_when_asked() {
+    let mut pair = TestPair {
+        sender: TestNode::spawn_with("sender", |builder| builder.request_receipts(true))
+            .await
+            .unwrap(),
+        receiver: TestNode::spawn("receiver").await.unwrap(),
+    };
+    let file = write_test_file(&pair.sender.root().join("outgoing"), "contract.pdf", 8192).unwrap();
+    pair.send_with_pairing(vec![file]).await.unwrap();
+
+    pair.sender
+        .command(AppCommand::QueryHistory {
+            query: HistoryQuery {
+                direction: Some(Direction::Sent),
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap();
+    let event = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::HistoryResults { .. })
+        })
+        .await
+        .unwrap();
+    let AppEvent::HistoryResults { records, .. } = event else {
+        unreachable!()
+    };
+    let receipt = records[0]
+        .receipt
+        .as_ref()
+        .expect("no receipt in the history");
+    receipt.verify().unwrap();
+    assert_eq!(receipt.receipt.receiver, pair.receiver.endpoint_id());
+    assert_eq!(receipt.receipt.sender, pair.sender.endpoint_id());
+    assert_eq!(receipt.receipt.size, 8192);
+    assert_eq!(records[0].hash.as_ref(), Some(&receipt.receipt.file_hash));
+
+    // A receipt signed by another device than the one expected is not kept
+    let file = write_test_file(&pair.sender.root().join("outgoing"), "other.pdf", 4096).unwrap();
+    let addr = pair.receiver.transfer_addr().to_string();
+    let impostor = pair.sender.endpoint_id().to_string();
+    pair.sender
+        .node()
+        .send_files_to_endpoint(&addr, &impostor, "receiver", vec![file])
+        .await
+        .unwrap();
+    pair.receiver
+        .wait_for_completion("other.pdf")
+        .await
+        .unwrap();
+    pair.sender.wait_for_completion("other.pdf").await.unwrap();
+    pair.sender
+        .command(AppCommand::QueryHistory {
+            query: HistoryQuery {
+                direction: Some(Direction::Sent),
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap();
+    let AppEvent::HistoryResults { records, .. } = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::HistoryResults { .. })
+        })
+        .await
+        .unwrap()
+    else {
+        unreachable!()
+    };
+    let other = records
+        .iter()
+        .find(|record| record.file_name == "other.pdf")
+        .unwrap();
+    assert!(other.receipt.is_none());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_parallel_sessions_to_same_host() {
+    let mut pair = TestPair::new().await.unwrap();
+    let dir = pair.sender.root().join("outgoing");
+    let a = write_test_file(&dir, "a.bin", 1024).unwrap();
+    let b = write_test_file(&dir, "b.bin", 1024).unwrap();
+
+    let first = pair
+        .sender
+        .send_files_to(&pair.receiver, vec![a])
+        .await
+        .unwrap();
+    let second = pair
+        .sender
+        .send_files_to(&pair.receiver, vec![b])
+        .await
+        .unwrap();
+    assert_ne!(first.session_id(), second.session_id());
+
+    let mut codes = Vec::new();
+    while codes.len() < 2 {
+        if let AppEvent::ShowVerificationCode { code, .. } = pair
+            .receiver
+            .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+                matches!(e, AppEvent::ShowVerificationCode { .. })
+            })
+            .await
+            .unwrap()
+        {
+            codes.push(code);
+        }
+    }
+
+    // Both prompts must stay answerable; neither replaces the other
+    first.submit_verification_code(&codes[0]).await.unwrap();
+    second.submit_verification_code(&codes[1]).await.unwrap();
+
+    let mut finished = Vec::new();
+    while finished.len() < 2 {
+        if let AppEvent::PairingResult { session_id, .. } = pair
+            .sender
+            .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+                matches!(e, AppEvent::PairingResult { .. })
+            })
+            .await
+            .unwrap()
+        {
+            finished.push(session_id);
+        }
+    }
+    finished.sort();
+    let mut expected = vec![
+        first.session_id().to_string(),
+        second.session_id().to_string(),
+    ];
+    expected.sort();
+    assert_eq!(finished, expected);
+
+    pair.shutdown().await;
+}
+
+async fn shown_code(receiver: &mut TestNode) -> String {
+    match receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::ShowVerificationCode { .. })
+        })
+        .await
+        .unwrap()
+    {
+        AppEvent::ShowVerificationCode { code, .. } => code,
+        _ => unreachable!(),
+    }
+}
+
+fn wrong_code(code: &str) -> &'static str {
+    if code == "0000" { "1111" } else { "0000" }
+}
+
+#[tokio::test]
+async fn test_retry_after_wrong_code() {
+    let mut pair = TestPair::new().await.unwrap();
+    let file = write_test_file(&pair.sender.root().join("outgoing"), "retry.bin", 1024).unwrap();
+
+    let transfer = pair
+        .sender
+        .send_files_to(&pair.receiver, vec![file])
+        .await
+        .unwrap();
+    let code = shown_code(&mut pair.receiver).await;
+
+    transfer
+        .submit_verification_code(wrong_code(&code))
+        .await
+        .unwrap();
+    let result = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::PairingResult { .. })
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        AppEvent::PairingResult { success: false, .. }
+    ));
+
+    // The same session accepts the right code afterwards
+    transfer.submit_verification_code(&code).await.unwrap();
+    pair.receiver
+        .wait_for_completion("retry.bin")
+        .await
+        .unwrap();
+    assert!(
+        pair.receiver
+            .pairings()
+            .is_paired(pair.sender.endpoint_id())
+    );
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_too_many_wrong_codes_end_the_session() {
+    let mut pair = TestPair::new().await.unwrap();
+    let file = write_test_file(&pair.sender.root().join("outgoing"), "locked.bin", 1024).unwrap();
+
+    let transfer = pair
+        .sender
+        .send_files_to(&pair.receiver, vec![file])
+        .await
+        .unwrap();
+    let code = shown_code(&mut pair.receiver).await;
+
+    for _ in 0..3 {
+        transfer
+            .submit_verification_code(wrong_code(&code))
+            .await
+            .unwrap();
+        pair.sender
+            .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+                matches!(e, AppEvent::PairingResult { success: false, .. })
+            })
+            .await
+            .unwrap();
+    }
+
+    let cancelled = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::VerificationCancelled { .. })
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        cancelled,
+        AppEvent::VerificationCancelled { session_id, .. } if session_id == transfer.session_id()
+    ));
+    assert!(!pair.receiver.download_dir().join("locked.bin").exists());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_unanswered_prompt_times_out() {
+    let mut sender = TestNode::spawn_with("sender", |builder| {
+        builder.verification_timeout(Duration::from_millis(500))
+    })
+    .await
+    .unwrap();
+    let mut receiver = TestNode::spawn("receiver").await.unwrap();
+    let file = write_test_file(&sender.root().join("outgoing"), "late.bin", 1024).unwrap();
+
+    let transfer = sender.send_files_to(&receiver, vec![file]).await.unwrap();
+    shown_code(&mut receiver).await;
+
+    let cancelled = sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::VerificationCancelled { .. })
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        cancelled,
+        AppEvent::VerificationCancelled { session_id, .. } if session_id == transfer.session_id()
+    ));
+
+    // The prompt is gone: late codes have nowhere to go
+    transfer.submit_verification_code("1234").await.unwrap();
+    sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::Error(msg) if msg.contains("No pending verification session"))
+        })
+        .await
+        .unwrap();
+
+    sender.shutdown().await;
+    receiver.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_invite_pairs_without_code() {
+    let mut pair = TestPair::new().await.unwrap();
+
+    pair.receiver
+        .command(AppCommand::CreatePairingInvite)
+        .await
+        .unwrap();
+    let uri = match pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::PairingInviteCreated { .. })
+        })
+        .await
+        .unwrap()
+    {
+        AppEvent::PairingInviteCreated { uri, .. } => uri,
+        _ => unreachable!(),
+    };
+
+    // The invite advertises the LAN address; tests run on loopback
+    let mut invite = PairingInvite::parse(&uri).unwrap();
+    invite.addr = pair.receiver.transfer_addr();
+    assert_eq!(invite.endpoint_id, pair.receiver.endpoint_id());
+
+    for expect_success in [true, false] {
+        pair.sender
+            .command(AppCommand::RedeemPairingInvite {
+                session_id: p2p_core::new_session_id(),
+                invite: invite.to_uri(),
+            })
+            .await
+            .unwrap();
+        let result = pair
+            .sender
+            .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+                matches!(e, AppEvent::PairingResult { .. })
+            })
+            .await
+            .unwrap();
+        // A secret works only once
+        assert!(matches!(
+            result,
+            AppEvent::PairingResult { success, .. } if success == expect_success
+        ));
+    }
+    assert!(
+        pair.receiver
+            .pairings()
+            .is_paired(pair.sender.endpoint_id())
+    );
+
+    let file = write_test_file(&pair.sender.root().join("outgoing"), "qr.bin", 1024).unwrap();
+    pair.send_paired(vec![file]).await.unwrap();
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_invalid_profile_keeps_backend_running() {
+    let mut node = TestNode::spawn("profiles").await.unwrap();
+
+    let result = node
+        .node()
+        .execute(
+            AppCommand::SwitchProfile {
+                name: "../escape".to_string(),
+            },
+            DEFAULT_EVENT_TIMEOUT,
+        )
+        .await;
+    assert!(result.is_err());
+
+    // The old backend still answers
+    node.node()
+        .execute(AppCommand::CreatePairingInvite, DEFAULT_EVENT_TIMEOUT)
+        .await
+        .unwrap();
+
+    node.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_receive_only_node_refuses_to_send() {
+    let mut kiosk = TestNode::spawn_with("kiosk", |builder| builder.receive_only(true))
+        .await
+        .unwrap();
+    let sender = TestNode::spawn("sender").await.unwrap();
+
+    let file = write_test_file(&kiosk.root().join("outgoing"), "nope.bin", 1024).unwrap();
+    let (request_id, cmd) = AppCommand::SendFile {
+        session_id: p2p_core::new_session_id(),
+        target_ip: sender.transfer_addr().to_string(),
+        target_endpoint_id: sender.endpoint_id().to_string(),
+        target_peer_name: sender.name().to_string(),
+        files: vec![file],
+        note: None,
+    }
+    .tracked();
+    kiosk.command(cmd).await.unwrap();
+    let result = kiosk
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::CommandResult { request_id: id, .. } if *id == request_id),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        AppEvent::CommandResult { result: Err(_), .. }
+    ));
+
+    // Receiving still works
+    let file = write_test_file(&sender.root().join("outgoing"), "drop.bin", 4096).unwrap();
+    sender.send_files_to(&kiosk, vec![file]).await.unwrap();
+    let code = kiosk
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::ShowVerificationCode { .. })
+        })
+        .await
+        .unwrap();
+    assert!(matches!(code, AppEvent::ShowVerificationCode { .. }));
+
+    kiosk.shutdown().await;
+    sender.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_policy_forces_the_download_dir_codes_and_no_http_share() {
+    let managed_dir = std::env::temp_dir().join(format!("p2p_policy_{}", uuid::Uuid::new_v4()));
+    let policy = Policy {
+        disable_http_share: true,
+        download_dir: Some(managed_dir.clone()),
+        require_verification: true,
+        ..Default::default()
+    };
+    let mut pair = TestPair {
+        sender: TestNode::spawn("sender").await.unwrap(),
+        receiver: TestNode::spawn_with("receiver", |builder| builder.policy(policy))
+            .await
+            .unwrap(),
+    };
+    let first = write_test_file(&pair.sender.root().join("outgoing"), "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+    assert!(managed_dir.join("first.bin").exists());
+    assert!(!pair.receiver.download_dir().join("first.bin").exists());
+    assert!(
+        !pair
+            .receiver
+            .pairings()
+            .is_paired(pair.sender.endpoint_id())
+    );
+
+    let (request_id, cmd) = AppCommand::StartHttpServer.tracked();
+    pair.receiver.command(cmd).await.unwrap();
+    let violation = pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::PolicyViolation { .. })
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        violation,
+        AppEvent::PolicyViolation {
+            rule: PolicyRule::HttpShareDisabled,
+            ..
+        }
+    ));
+    let result = pair
+        .receiver
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::CommandResult { request_id: id, .. } if *id == request_id),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        AppEvent::CommandResult { result: Err(_), .. }
+    ));
+
+    pair.shutdown().await;
+    let _ = std::fs::remove_dir_all(&managed_dir);
+}
+
+#[tokio::test]
+async fn test_http_server_on_a_taken_port_fails_its_command() {
+    // Whoever holds the port, the server cannot bind it
+    let _taken = std::net::TcpListener::bind(("0.0.0.0", p2p_core::http_share::HTTP_PORT));
+    let mut node = TestNode::spawn("host").await.unwrap();
+
+    let (request_id, cmd) = AppCommand::StartHttpServer.tracked();
+    node.command(cmd).await.unwrap();
+    let result = node
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::CommandResult { request_id: id, .. } if *id == request_id),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        AppEvent::CommandResult { result: Err(ref message), .. } if message.contains("HTTP server failed")
+    ));
+
+    node.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_scheduled_send_runs_when_due() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let first = write_test_file(&outgoing, "pairing.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let target = pair.receiver.transfer_addr().to_string();
+
+    // A job for later waits and can be cancelled
+    let later = write_test_file(&outgoing, "later.bin", 1024).unwrap();
+    pair.sender
+        .command(AppCommand::ScheduleSend {
+            target: target.clone(),
+            target_peer_name: "receiver".to_string(),
+            files: vec![later],
+            at: now + 3600,
+        })
+        .await
+        .unwrap();
+    let job_id = match pair
+        .sender
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::ScheduledSendsChanged { jobs } if jobs.len() == 1),
+        )
+        .await
+        .unwrap()
+    {
+        AppEvent::ScheduledSendsChanged { jobs } => jobs[0].id.clone(),
+        _ => unreachable!(),
+    };
+    pair.sender
+        .command(AppCommand::CancelScheduledSend { job_id })
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::ScheduledSendsChanged { jobs } if jobs.is_empty()),
+        )
+        .await
+        .unwrap();
+
+    // A due job starts as soon as the receiver answers
+    let due = write_test_file(&outgoing, "due.bin", 64 * 1024).unwrap();
+    pair.sender
+        .command(AppCommand::ScheduleSend {
+            target,
+            target_peer_name: "receiver".to_string(),
+            files: vec![due],
+            at: now,
+        })
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::ScheduledSendStarted { .. })
+        })
+        .await
+        .unwrap();
+    pair.receiver.wait_for_completion("due.bin").await.unwrap();
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_cleanup_command_reports() {
+    let mut plain = TestNode::spawn("no-rules").await.unwrap();
+    let result = plain
+        .node()
+        .execute(
+            AppCommand::RunCleanup { dry_run: true },
+            DEFAULT_EVENT_TIMEOUT,
+        )
+        .await;
+    assert!(result.is_err(), "cleanup needs a policy");
+    plain.shutdown().await;
+
+    let policy = p2p_core::retention::RetentionPolicy {
+        max_age_days: Some(30),
+        ..Default::default()
+    };
+    let mut node = TestNode::spawn_with("rules", |builder| builder.retention(policy))
+        .await
+        .unwrap();
+    write_test_file(node.download_dir(), "fresh.bin", 1024).unwrap();
+
+    node.command(AppCommand::RunCleanup { dry_run: true })
+        .await
+        .unwrap();
+    let report = node
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::CleanupReport { .. })
+        })
+        .await
+        .unwrap();
+    // Fresh files are never candidates
+    assert!(matches!(
+        report,
+        AppEvent::CleanupReport { dry_run: true, ref files, .. } if files.is_empty()
+    ));
+    assert!(node.download_dir().join("fresh.bin").exists());
+
+    node.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_duplicate_received_file_is_hard_linked() {
+    let mut pair = TestPair::new().await.unwrap();
+    let source_dir = pair.sender.root().join("outgoing");
+
+    let original = write_test_file(&source_dir, "original.bin", 64 * 1024).unwrap();
+    pair.send_with_pairing(vec![original.clone()])
+        .await
+        .unwrap();
+
+    let copy = source_dir.join("copy.bin");
+    std::fs::copy(&original, &copy).unwrap();
+    pair.sender
+        .send_files_to(&pair.receiver, vec![copy])
+        .await
+        .unwrap();
+
+    let event = pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::DuplicateReceived { .. })
+        })
+        .await
+        .unwrap();
+    let AppEvent::DuplicateReceived { path, existing, .. } = event else {
+        unreachable!()
+    };
+    assert_eq!(path, pair.receiver.download_dir().join("copy.bin"));
+    assert_eq!(existing, pair.receiver.download_dir().join("original.bin"));
+
+    pair.receiver
+        .node()
+        .execute(
+            AppCommand::ResolveDuplicate {
+                path: path.clone(),
+                action: p2p_core::history::DuplicateAction::HardLink,
+            },
+            DEFAULT_EVENT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(&path).unwrap(),
+        std::fs::read(&original).unwrap()
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().ino(),
+            std::fs::metadata(&existing).unwrap().ino()
+        );
+    }
+
+    // Each duplicate is resolved once
+    let again = pair
+        .receiver
+        .node()
+        .execute(
+            AppCommand::ResolveDuplicate {
+                path,
+                action: p2p_core::history::DuplicateAction::Skip,
+            },
+            DEFAULT_EVENT_TIMEOUT,
+        )
+        .await;
+    assert!(again.is_err());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_move_waits_for_the_receiver_hash_and_can_be_undone() {
+    let mut pair = TestPair::new().await.unwrap();
+    let source_dir = pair.sender.root().join("outgoing");
+    let first = write_test_file(&source_dir, "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+
+    let moved = write_test_file(&source_dir, "moved.bin", 64 * 1024).unwrap();
+    pair.sender
+        .command(AppCommand::MoveFiles {
+            session_id: p2p_core::new_session_id(),
+            target_ip: pair.receiver.transfer_addr().to_string(),
+            target_endpoint_id: pair.receiver.endpoint_id().to_string(),
+            target_peer_name: pair.receiver.name().to_string(),
+            files: vec![moved.clone()],
+            note: None,
+        })
+        .await
+        .unwrap();
+
+    // Announced only after the receiver's background hash check
+    let event = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::MovePending { .. })
+        })
+        .await
+        .unwrap();
+    let AppEvent::MovePending {
+        move_id,
+        path,
+        undo_secs,
+        ..
+    } = event
+    else {
+        unreachable!()
+    };
+    assert_eq!(path, moved);
+    assert!(undo_secs > 0);
+    assert_eq!(
+        std::fs::read(pair.receiver.download_dir().join("moved.bin")).unwrap(),
+        std::fs::read(&moved).unwrap()
+    );
+
+    pair.sender
+        .command(AppCommand::UndoMove {
+            move_id: move_id.clone(),
+        })
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::MoveFinished { move_id: id, deleted: false } if *id == move_id)
+        })
+        .await
+        .unwrap();
+    assert!(moved.exists());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_note_is_shown_before_pairing_and_searchable_in_history() {
+    let mut pair = TestPair::new().await.unwrap();
+    let source_dir = pair.sender.root().join("outgoing");
+    let file = write_test_file(&source_dir, "march.pdf", 4 * 1024).unwrap();
+
+    let session_id = p2p_core::new_session_id();
+    pair.sender
+        .command(AppCommand::SendFile {
+            session_id: session_id.clone(),
+            target_ip: pair.receiver.transfer_addr().to_string(),
+            target_endpoint_id: pair.receiver.endpoint_id().to_string(),
+            target_peer_name: pair.receiver.name().to_string(),
+            files: vec![file],
+            note: Some("  invoices\nQ3 ".to_string()),
+        })
+        .await
+        .unwrap();
+
+    let event = pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::ShowVerificationCode { .. })
+        })
+        .await
+        .unwrap();
+    let AppEvent::ShowVerificationCode { code, note, .. } = event else {
+        unreachable!()
+    };
+    assert_eq!(note.as_deref(), Some("invoices Q3"));
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::RequestVerificationCode { .. })
+        })
+        .await
+        .unwrap();
+    pair.sender
+        .command(AppCommand::SubmitVerificationCode { session_id, code })
+        .await
+        .unwrap();
+    pair.receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::VerificationCompleted {
+                    is_sending: false,
+                    ..
+                }
+            )
+        })
+        .await
+        .unwrap();
+
+    pair.receiver
+        .command(AppCommand::QueryHistory {
+            query: HistoryQuery {
+                text: "INVOICES".to_string(),
+                direction: Some(Direction::Received),
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap();
+    let event = pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::HistoryResults { .. })
+        })
+        .await
+        .unwrap();
+    let AppEvent::HistoryResults { records, .. } = event else {
+        unreachable!()
+    };
+    assert_eq!(records.len(), 1);
+    assert_eq!(
+        records[0].path,
+        Some(pair.receiver.download_dir().join("march.pdf"))
+    );
+    assert_eq!(records[0].note.as_deref(), Some("invoices Q3"));
+    assert_eq!(records[0].status, TransferStatus::Verified);
+    assert!(records[0].hash.is_some() && records[0].peer_id.is_some());
+
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::TransferCompleted { .. })
+        })
+        .await
+        .unwrap();
+    pair.sender
+        .command(AppCommand::QueryHistory {
+            query: HistoryQuery {
+                direction: Some(Direction::Sent),
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap();
+    let event = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::HistoryResults { .. })
+        })
+        .await
+        .unwrap();
+    let AppEvent::HistoryResults { records, .. } = event else {
+        unreachable!()
+    };
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].status, TransferStatus::Completed);
+    assert_eq!(records[0].note.as_deref(), Some("invoices Q3"));
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_lan_transfer_reports_security_info() {
+    let mut pair = TestPair::new().await.unwrap();
+    let source_dir = pair.sender.root().join("outgoing");
+
+    let first = write_test_file(&source_dir, "first.bin", 16 * 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+
+    let second = write_test_file(&source_dir, "second.bin", 16 * 1024).unwrap();
+    pair.sender
+        .send_files_to(&pair.receiver, vec![second])
+        .await
+        .unwrap();
+
+    for (node, sending) in [(&mut pair.receiver, false), (&mut pair.sender, true)] {
+        let event = node
+            .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+                matches!(e, AppEvent::SecurityInfo { file_name, .. } if file_name == "second.bin")
+            })
+            .await
+            .unwrap();
+        let AppEvent::SecurityInfo {
+            is_sending,
+            security,
+            ..
+        } = event
+        else {
+            unreachable!()
+        };
+        assert_eq!(is_sending, sending);
+        assert_eq!(security.path, p2p_core::transfer::ConnectionPath::Lan);
+        assert!(!security.is_pinned());
+        assert!(security.cipher_suite.starts_with("TLS13_"));
+        // Only the connecting side sees the peer's certificate
+        assert_eq!(security.fingerprint.is_some(), sending);
+    }
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_sender_reports_the_receivers_hash_check_before_completion() {
+    let mut pair = TestPair::new().await.unwrap();
+    let file = write_test_file(pair.sender.root(), "checked.bin", 64 * 1024).unwrap();
+    let transfer = pair
+        .sender
+        .send_files_to(&pair.receiver, vec![file])
+        .await
+        .unwrap();
+    let code = shown_code(&mut pair.receiver).await;
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::RequestVerificationCode { .. })
+        })
+        .await
+        .unwrap();
+    transfer.submit_verification_code(&code).await.unwrap();
+
+    let event = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::VerificationCompleted {
+                    is_sending: true,
+                    ..
+                } | AppEvent::TransferCompleted { .. }
+            )
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        event,
+        AppEvent::VerificationCompleted { verified: true, .. }
+    ));
+    pair.sender
+        .wait_for_completion("checked.bin")
+        .await
+        .unwrap();
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_repeated_wrong_codes_lock_the_sender_out() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let file = write_test_file(&outgoing, "guess.bin", 1024).unwrap();
+
+    let transfer = pair
+        .sender
+        .send_files_to(&pair.receiver, vec![file.clone()])
+        .await
+        .unwrap();
+    let code = shown_code(&mut pair.receiver).await;
+    for _ in 0..3 {
+        transfer
+            .submit_verification_code(wrong_code(&code))
+            .await
+            .unwrap();
+    }
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::VerificationCancelled { .. })
+        })
+        .await
+        .unwrap();
+
+    // The next attempt is refused before any code is shown
+    let retry = pair
+        .sender
+        .send_files_to(&pair.receiver, vec![file])
+        .await
+        .unwrap();
+    let blocked = pair
+        .receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::PairingBlocked { .. } | AppEvent::ShowVerificationCode { .. }
+            )
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        blocked,
+        AppEvent::PairingBlocked { attempts: 1, ref ip, .. } if ip == "127.0.0.1"
+    ));
+
+    let result = pair
+        .sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::PairingResult { session_id, .. } if session_id == retry.session_id())
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        AppEvent::PairingResult { success: false, ref message, .. } if message.contains("try again")
+    ));
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_claimed_endpoint_id_without_pairing_key_needs_a_code() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let first = write_test_file(&outgoing, "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+
+    // The real sender proves the pairing and skips the code
+    let second = write_test_file(&outgoing, "second.bin", 1024).unwrap();
+    pair.send_paired(vec![second]).await.unwrap();
+
+    // An impostor announcing the same endpoint ID gets the code prompt
+    let sender_id = pair.sender.endpoint_id().to_string();
+    let mut impostor = TestNode::spawn_with("impostor", |builder| builder.endpoint_id(sender_id))
+        .await
+        .unwrap();
+    let file = write_test_file(&impostor.root().join("outgoing"), "fake.bin", 1024).unwrap();
+    let transfer = impostor
+        .send_files_to(&pair.receiver, vec![file])
+        .await
+        .unwrap();
+    impostor
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::RequestVerificationCode { session_id, .. }
+                    if session_id == transfer.session_id()
+            )
+        })
+        .await
+        .unwrap();
+    assert!(!pair.receiver.download_dir().join("fake.bin").exists());
+
+    impostor.shutdown().await;
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_stolen_pairing_key_without_identity_key_needs_a_code() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let first = write_test_file(&outgoing, "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+
+    // The impostor has the sender's pairing keys but its own Iroh identity,
+    // so it cannot sign for the sender's endpoint ID
+    let sender_id = pair.sender.endpoint_id().to_string();
+    let key = pair.receiver.pairings().pair_key(&sender_id).unwrap();
+    let stolen = Arc::new(MemoryPairingStore::default());
+    stolen.add_receiver_key(pair.receiver.name(), &key);
+    let mut impostor = TestNode::spawn_with("impostor", |builder| {
+        builder.endpoint_id(sender_id).pairing_store(stolen)
+    })
+    .await
+    .unwrap();
+    let file = write_test_file(&impostor.root().join("outgoing"), "fake.bin", 1024).unwrap();
+    let transfer = impostor
+        .send_files_to(&pair.receiver, vec![file])
+        .await
+        .unwrap();
+    impostor
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::RequestVerificationCode { session_id, .. }
+                    if session_id == transfer.session_id()
+            )
+        })
+        .await
+        .unwrap();
+    assert!(!pair.receiver.download_dir().join("fake.bin").exists());
+
+    impostor.shutdown().await;
+    pair.shutdown().await;
+}
+
+/// A sparse file big enough to still be in flight when a cancel arrives
+fn write_large_sparse_file(dir: &std::path::Path, name: &str) -> std::path::PathBuf {
+    std::fs::create_dir_all(dir).unwrap();
+    let path = dir.join(name);
+    std::fs::File::create(&path)
+        .unwrap()
+        .set_len(1024 * 1024 * 1024)
+        .unwrap();
+    path
+}
+
+/// Wait until `node` reports progress on `file_name`
+async fn wait_for_progress(node: &mut TestNode, file_name: &str) {
+    node.wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+        matches!(e, AppEvent::TransferProgress { file_name: name, progress, .. }
+            if name == file_name && *progress > 0.0)
+    })
+    .await
+    .unwrap();
+}
+
+async fn wait_for_cancel(node: &mut TestNode, file_name: &str) -> AppEvent {
+    node.wait_for(
+        DEFAULT_EVENT_TIMEOUT,
+        |e| matches!(e, AppEvent::TransferCancelled { file_name: name, .. } if name == file_name),
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_receiver_cancel_reaches_sender_and_deletes_partial() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let small = write_test_file(&outgoing, "small.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![small]).await.unwrap();
+
+    let big = write_large_sparse_file(&outgoing, "big.bin");
+    pair.sender
+        .send_files_to(&pair.receiver, vec![big])
+        .await
+        .unwrap();
+    wait_for_progress(&mut pair.receiver, "big.bin").await;
+    pair.receiver
+        .command(AppCommand::CancelTransfer)
+        .await
+        .unwrap();
+
+    let here = wait_for_cancel(&mut pair.receiver, "big.bin").await;
+    assert!(matches!(
+        here,
+        AppEvent::TransferCancelled {
+            is_sending: false,
+            by_peer: false,
+            ..
+        }
+    ));
+    let there = wait_for_cancel(&mut pair.sender, "big.bin").await;
+    assert!(matches!(
+        there,
+        AppEvent::TransferCancelled {
+            is_sending: true,
+            by_peer: true,
+            ..
+        }
+    ));
+
+    let partial = pair.receiver.download_dir().join("big.bin");
+    assert!(!partial.exists());
+    assert!(part_files(&pair.receiver, "big.bin").is_empty());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_sender_cancel_keeps_partial_for_resume() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let small = write_test_file(&outgoing, "small.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![small]).await.unwrap();
+
+    let big = write_large_sparse_file(&outgoing, "big.bin");
+    pair.sender
+        .send_files_to(&pair.receiver, vec![big])
+        .await
+        .unwrap();
+    wait_for_progress(&mut pair.receiver, "big.bin").await;
+    pair.sender
+        .command(AppCommand::CancelTransfer)
+        .await
+        .unwrap();
+
+    let there = wait_for_cancel(&mut pair.receiver, "big.bin").await;
+    assert!(matches!(
+        there,
+        AppEvent::TransferCancelled {
+            is_sending: false,
+            by_peer: true,
+            ..
+        }
+    ));
+
+    // The partial file stays resumable, under a part name of its own
+    assert!(!pair.receiver.download_dir().join("big.bin").exists());
+    let parts = part_files(&pair.receiver, "big.bin");
+    assert_eq!(parts.len(), 1);
+    assert!(resume::record_path(&parts[0]).exists());
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_longest_name_is_resumed() {
+    let mut pair = TestPair::new().await.unwrap();
+    let name = format!("{}.bin", "n".repeat(251));
+    let source = write_test_file(&pair.sender.root().join("outgoing"), &name, 256 * 1024).unwrap();
+    let data = std::fs::read(&source).unwrap();
+
+    // A partial copy left behind by an interrupted transfer
+    let target = pair.receiver.download_dir().join(&name);
+    let partial = staging::part_path(&target, "0123456789abcdef");
+    std::fs::create_dir_all(pair.receiver.download_dir()).unwrap();
+    std::fs::write(&partial, &data[..100 * 1024]).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o600)).unwrap();
+    }
+    let info = FileInfo {
+        file_name: name.clone(),
+        file_size: Some(data.len() as u64),
+        file_path: Default::default(),
+        file_hash: Some(blake3::hash(&data).to_hex().to_string()),
+        hash_algorithm: Default::default(),
+        modified: None,
+        mode: None,
+        note: None,
+        xattrs: Vec::new(),
+    };
+    resume::begin(&partial, &info, "0123456789abcdef", None).unwrap();
+
+    pair.send_with_pairing(vec![source]).await.unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), data);
+    assert!(!partial.exists());
+    assert!(!resume::record_path(&partial).exists());
+
+    pair.shutdown().await;
+}
+
+/// Part files of `name` in the download folder of `node`
+fn part_files(node: &TestNode, name: &str) -> Vec<std::path::PathBuf> {
+    std::fs::read_dir(node.download_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|n| n.to_str())
+                        .is_some_and(|n| n.starts_with(name) && n.ends_with(staging::PART_SUFFIX))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn test_swarm_send_reaches_every_receiver() {
+    let mut sender = TestNode::spawn("swarm_sender").await.unwrap();
+    let mut receivers = vec![
+        TestNode::spawn("swarm_a").await.unwrap(),
+        TestNode::spawn("swarm_b").await.unwrap(),
+    ];
+    let file = write_test_file(
+        &sender.root().join("outgoing"),
+        "swarm.bin",
+        5 * 1024 * 1024 + 7,
+    )
+    .unwrap();
+
+    let targets: Vec<_> = receivers
+        .iter()
+        .map(|receiver| p2p_core::swarm::SwarmTarget {
+            session_id: p2p_core::new_session_id(),
+            target_ip: receiver.transfer_addr().to_string(),
+            target_peer_name: receiver.name().to_string(),
+        })
+        .collect();
+    sender
+        .command(AppCommand::SwarmSend {
+            file: file.clone(),
+            targets: targets.clone(),
+        })
+        .await
+        .unwrap();
+
+    for (receiver, target) in receivers.iter_mut().zip(&targets) {
+        let code = shown_code(receiver).await;
+        sender
+            .command(AppCommand::SubmitVerificationCode {
+                session_id: target.session_id.clone(),
+                code,
+            })
+            .await
+            .unwrap();
+    }
+
+    let data = std::fs::read(&file).unwrap();
+    for receiver in &mut receivers {
+        receiver.wait_for_completion("swarm.bin").await.unwrap();
+        let received = receiver.download_dir().join("swarm.bin");
+        assert_eq!(std::fs::read(&received).unwrap(), data);
+    }
+
+    // The receivers told the sender what they have
+    sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| match e {
+            AppEvent::SwarmAvailability { swarms } => swarms.iter().any(|swarm| {
+                swarm.members.len() == 2
+                    && swarm
+                        .members
+                        .iter()
+                        .all(|member| member.pieces.is_complete())
+            }),
+            _ => false,
+        })
+        .await
+        .unwrap();
+
+    sender.shutdown().await;
+    for receiver in receivers {
+        receiver.shutdown().await;
+    }
+}
+
+#[tokio::test]
+async fn test_relay_refuses_targets_it_did_not_discover() {
+    let mut relay = TestNode::spawn_with("relay", |builder| {
+        builder.relay(RelayPolicy {
+            enabled: true,
+            max_bytes_per_sec: Some(64 * 1024 * 1024),
+        })
+    })
+    .await
+    .unwrap();
+    let mut pair = TestPair::new().await.unwrap();
+    let file = write_test_file(
+        &pair.sender.root().join("outgoing"),
+        "relayed.bin",
+        512 * 1024,
+    )
+    .unwrap();
+
+    // The receiver is on the relay's loopback and was never discovered
+    let session_id = p2p_core::new_session_id();
+    pair.sender
+        .command(AppCommand::SendViaRelay {
+            session_id: session_id.clone(),
+            relay_ip: relay.transfer_addr().to_string(),
+            target_ip: pair.receiver.transfer_addr().to_string(),
+            target_peer_name: pair.receiver.name().to_string(),
+            files: vec![file],
+        })
+        .await
+        .unwrap();
+    let code = shown_code(&mut relay).await;
+    pair.sender
+        .command(AppCommand::SubmitVerificationCode { session_id, code })
+        .await
+        .unwrap();
+
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::Error(message) if message.contains("only relays to devices it found"))
+        })
+        .await
+        .unwrap();
+    assert!(!pair.receiver.download_dir().join("relayed.bin").exists());
+
+    pair.shutdown().await;
+    relay.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_relay_is_off_by_default() {
+    let relay = TestNode::spawn("no_relay").await.unwrap();
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let file = write_test_file(&outgoing, "pairing.bin", 1024).unwrap();
+
+    // Pair with the would-be relay first so only the policy refuses
+    let mut relay_pair = TestPair {
+        sender: pair.sender,
+        receiver: relay,
+    };
+    relay_pair
+        .send_with_pairing(vec![file.clone()])
+        .await
+        .unwrap();
+    pair.sender = relay_pair.sender;
+    let relay = relay_pair.receiver;
+
+    pair.sender
+        .command(AppCommand::SendViaRelay {
+            session_id: p2p_core::new_session_id(),
+            relay_ip: relay.transfer_addr().to_string(),
+            target_ip: pair.receiver.transfer_addr().to_string(),
+            target_peer_name: pair.receiver.name().to_string(),
+            files: vec![file],
+        })
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(
+            DEFAULT_EVENT_TIMEOUT,
+            |e| matches!(e, AppEvent::Error(message) if message.contains("does not relay")),
+        )
+        .await
+        .unwrap();
+
+    pair.shutdown().await;
+    relay.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_interrupted_send_is_offered_after_restart() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let first = write_test_file(&outgoing, "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+    // Finished sends leave nothing to resume
+    let journal = SendJournal::load(Some(pair.sender.root().join("outgoing_journal.json")));
+    assert!(journal.pending().is_empty());
+
+    // A sender that crashed halfway through a send
+    let unsent = write_test_file(&outgoing, "unsent.bin", 64 * 1024).unwrap();
+    let journal_path = pair.sender.root().join("crashed_journal.json");
+    let crashed = Arc::new(SendJournal::load(Some(journal_path.clone())));
+    crashed.begin(
+        "crashed",
+        &pair.receiver.transfer_addr().to_string(),
+        pair.receiver.name(),
+        std::slice::from_ref(&unsent),
+        0,
+    );
+    drop(crashed);
+
+    let mut restarted =
+        TestNode::spawn_with("restarted", |builder| builder.journal_file(&journal_path))
+            .await
+            .unwrap();
+    match restarted
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::PendingSends { .. })
+        })
+        .await
+        .unwrap()
+    {
+        AppEvent::PendingSends { sends } => {
+            assert_eq!(sends.len(), 1);
+            assert_eq!(sends[0].files[0].path, unsent);
+        }
+        _ => unreachable!(),
+    }
+
+    restarted
+        .command(AppCommand::ResumePendingSend {
+            id: "crashed".to_string(),
+        })
+        .await
+        .unwrap();
+    let session_id = match restarted
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(e, AppEvent::ScheduledSendStarted { .. })
+        })
+        .await
+        .unwrap()
+    {
+        AppEvent::ScheduledSendStarted { session_id, .. } => session_id,
+        _ => unreachable!(),
+    };
+    // The restarted node has a new identity, so it pairs again
+    let code = shown_code(&mut pair.receiver).await;
+    restarted
+        .command(AppCommand::SubmitVerificationCode { session_id, code })
+        .await
+        .unwrap();
+    pair.receiver
+        .wait_for_completion("unsent.bin")
+        .await
+        .unwrap();
+    restarted.wait_for_completion("unsent.bin").await.unwrap();
+    assert!(SendJournal::load(Some(journal_path)).pending().is_empty());
+
+    restarted.shutdown().await;
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_stream_of_unknown_size_is_verified_at_the_end() {
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let first = write_test_file(&outgoing, "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+
+    // Several frames, the last one short
+    let data: Vec<u8> = (0..3 * 1024 * 1024 + 17).map(|i| (i % 251) as u8).collect();
+    let target = pair.receiver.transfer_addr().to_string();
+    let name = pair.receiver.name().to_string();
+    pair.sender
+        .node()
+        .send_stream(
+            &target,
+            &name,
+            "backup.tar",
+            std::io::Cursor::new(data.clone()),
+        )
+        .await
+        .unwrap();
+    pair.receiver
+        .wait_for_completion("backup.tar")
+        .await
+        .unwrap();
+    pair.sender
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::VerificationCompleted {
+                    is_sending: true,
+                    verified: true,
+                    ..
+                }
+            )
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(pair.receiver.download_dir().join("backup.tar")).unwrap(),
+        data
+    );
+
+    pair.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_stream_received_into_a_pipe() {
+    let (writer, mut reader) = tokio::io::duplex(64 * 1024);
+    let piped = tokio::spawn(async move {
+        use tokio::io::AsyncReadExt;
+        let mut piped = Vec::new();
+        reader.read_to_end(&mut piped).await.map(|_| piped)
+    });
+    let storage = Arc::new(PipeStorage::new(writer));
+    let mut pair = TestPair {
+        sender: TestNode::spawn("sender").await.unwrap(),
+        receiver: TestNode::spawn_with("receiver", |builder| builder.storage(storage))
+            .await
+            .unwrap(),
+    };
+    let target = pair.receiver.transfer_addr().to_string();
+    let name = pair.receiver.name().to_string();
+    let data = b"streamed through a pipe".repeat(1000);
+    let transfer = pair
+        .sender
+        .node()
+        .send_stream(
+            &target,
+            &name,
+            "log.txt",
+            std::io::Cursor::new(data.clone()),
+        )
+        .await
+        .unwrap();
+    let code = shown_code(&mut pair.receiver).await;
+    transfer.submit_verification_code(&code).await.unwrap();
+    pair.receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::VerificationCompleted {
+                    is_sending: false,
+                    verified: true,
+                    ..
+                }
+            )
+        })
+        .await
+        .unwrap();
+    assert_eq!(piped.await.unwrap().unwrap(), data);
+    assert!(!pair.receiver.download_dir().join("log.txt").exists());
+
+    pair.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_named_pipe_is_streamed_with_progress_in_bytes() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut pair = TestPair::new().await.unwrap();
+    let outgoing = pair.sender.root().join("outgoing");
+    let first = write_test_file(&outgoing, "first.bin", 1024).unwrap();
+    pair.send_with_pairing(vec![first]).await.unwrap();
+
+    // A live log: its size is only known once the writer closes it
+    let fifo = outgoing.join("live.log");
+    let path = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+    let data = b"12:00:01 camera online\n".repeat(100_000);
+    let writer = {
+        let (fifo, data) = (fifo.clone(), data.clone());
+        std::thread::spawn(move || std::fs::write(fifo, data))
+    };
+    pair.sender
+        .send_files_to(&pair.receiver, vec![fifo])
+        .await
+        .unwrap();
+
+    let total = data.len() as u64;
+    pair.receiver
+        .wait_for(DEFAULT_EVENT_TIMEOUT, |e| {
+            matches!(
+                e,
+                AppEvent::TransferProgress { file_name, bytes: Some(bytes), progress, .. }
+                    if file_name == "live.log" && *bytes == total && *progress == 100.0
+            )
+        })
+        .await
+        .unwrap();
+    pair.receiver.wait_for_completion("live.log").await.unwrap();
+    pair.sender.wait_for_completion("live.log").await.unwrap();
+    writer.join().unwrap().unwrap();
+    assert_eq!(
+        std::fs::read(pair.receiver.download_dir().join("live.log")).unwrap(),
+        data
+    );
+
+    pair.shutdown().await;
+}
@@ -1,482 +1,715 @@
+use crate::bridge::{CommandBridge, DialogResult, FileDialogTask};
+use crate::diagnostics;
+use crate::os_auth::{self, Sensitive, SensitiveGate};
+use crate::state::{AppState, BrokenSend, Effect, VerificationStatus};
+use crate::status_log::LogFilter;
+use crate::taskbar::{self, TaskbarProgress};
 use crate::ui;
-use crate::ui::windows::qr_code::{QrCodeCache, ShareTab};
-use crate::ui::windows::upload_confirm::{self, UploadConfirmState};
-use crate::ui::windows::verify::{self, VerificationState};
+use crate::ui::windows::duplicates;
+use crate::ui::windows::files::{LocalFile, Trashed};
+use crate::ui::windows::metered;
+use crate::ui::windows::moves;
+use crate::ui::windows::orphans;
+use crate::ui::windows::proxy::ProxyWindowState;
+use crate::ui::windows::qr_code::LinkRequest;
+use crate::ui::windows::resend;
+use crate::ui::windows::send_picker::{self, SendPickerState};
+use crate::ui::windows::send_preview;
+use crate::ui::windows::swarms;
+use crate::ui::windows::telemetry;
+use crate::ui::windows::upload_confirm;
+use crate::ui::windows::verify;
 use crate::ui::windows::wan_connect::{self, WanConnectState};
+use crate::ui::windows::wan_offer;
+use crate::update::{UpdateChecker, UpdateSettings};
+use crate::url_scheme::LinkInbox;
 use eframe::egui;
-use p2p_core::{AppCommand, AppEvent};
-use std::collections::HashMap;
+use p2p_core::network_info::MeteredMode;
+use p2p_core::pairing::phrase::LINK_PHRASE_EXPIRY_SECS;
+use p2p_core::telemetry::TelemetryMode;
+use p2p_core::{AppCommand, AppEvent, EventCategory, EventSubscription, LogLevel};
 use std::time::{Duration, Instant};
-use sysinfo::System;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot, watch};
 
-/// Timeout for peer discovery - peers not seen within this time are pruned
-const PEER_TIMEOUT_SECS: u64 = 12;
+/// Key of [`AppUIState`] in eframe's storage
+const UI_STATE_KEY: &str = "ui_state";
 
-#[derive(Default)]
+/// Open panels and GUI preferences; restored on the next start
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct AppUIState {
     pub show_devices: bool,
     pub show_files: bool,
     pub show_qrcode: bool,
     pub show_wan_connect: bool,
-}
-
-struct PeerInfo {
-    ip: String,
-    hostname: String,
-    last_seen: Instant,
-}
-
-#[derive(Debug, Clone, Copy)]
-enum VerificationStatus {
-    Verifying,
-    Verified,
-    Failed,
-}
-
-struct TransferState {
-    file_name: String,
-    progress: f32,
-    speed: String,
-    speed_bps: f64,
-    is_sending: bool,
-    verification_status: Option<VerificationStatus>,
-}
-
-/// Log entry with type for color coding
-#[derive(Clone)]
-enum LogType {
-    Info,    // Default - gray/white
-    Success, // Green
-    Error,   // Red
-    Warning, // Yellow/Orange
-}
-
-#[derive(Clone)]
-struct LogEntry {
-    message: String,
-    log_type: LogType,
+    pub show_scheduled: bool,
+    pub show_proxy: bool,
+    pub show_history: bool,
+    #[cfg(feature = "profiling")]
+    pub show_profiler: bool,
+    /// Units picked in the toolbar; sent to the backend when changed.
+    /// Saved in the profile instead.
+    #[serde(skip)]
+    pub units: p2p_core::units::UnitPreference,
+    /// Saved in the profile as well.
+    #[serde(skip)]
+    pub hash_algorithm: p2p_core::transfer::HashAlgorithm,
+    /// Picked in the toolbar, saved in the profile as well.
+    #[serde(skip)]
+    pub metered_mode: MeteredMode,
+    /// The backend treats the connection as metered
+    #[serde(skip)]
+    pub metered: bool,
+    pub update_check: UpdateSettings,
+    /// Delete received files for good instead of into the trash
+    pub hard_delete: bool,
+    /// Ask the system to confirm the user before sensitive actions
+    pub confirm_sensitive: bool,
+    /// Set by the administrator, never saved
+    #[serde(skip)]
+    pub policy: p2p_core::policy::Policy,
+    /// Picked in the toolbar, saved in the profile instead
+    #[serde(skip)]
+    pub telemetry_mode: TelemetryMode,
+    /// The toolbar asked to see the usage report
+    #[serde(skip)]
+    pub preview_telemetry: bool,
+    /// Font files tried before the system's for text egui's fonts lack
+    /// (see [`crate::fonts`])
+    pub user_fonts: Vec<std::path::PathBuf>,
+    /// The toolbar asked to add a font
+    #[serde(skip)]
+    pub pick_font: bool,
 }
 
 pub struct MyApp {
-    cmd_sender: mpsc::Sender<AppCommand>,
-    event_receiver: mpsc::Receiver<AppEvent>,
+    cmd_sender: CommandBridge,
+    event_receiver: EventSubscription,
     event_sender: mpsc::Sender<AppEvent>,
 
-    ui_state: AppUIState,
-    verification_state: VerificationState,
-    upload_confirm_state: UploadConfirmState,
-
-    status_log: Vec<LogEntry>,
-    // Key: IP address (unique identifier for now)
-    peers: HashMap<String, PeerInfo>,
-
-    download_path: std::path::PathBuf,
-    local_files: Vec<String>,
-    active_transfers: HashMap<String, TransferState>,
-
-    system: System,
-    last_metrics_update: Instant,
-
-    // QR Code & HTTP Share
-    qrcode_cache: QrCodeCache,
-    share_tab: ShareTab,
-    share_url: String,
-    http_server_running: bool,
-    http_server_pending: bool,
-
-    // WAN Share (bore tunnel)
-    wan_share_url: Option<String>,
-    wan_share_running: bool,
-    wan_share_pending: bool,
+    /// What backend events change, see [`AppState::apply`]
+    state: AppState,
+    send_picker: SendPickerState,
+    /// `p2p://` links waiting to be opened
+    links: LinkInbox,
+    /// Usage statistics mode last sent to the backend
+    telemetry_mode: TelemetryMode,
+
+    log_export_dialog: Option<FileDialogTask>,
+    font_dialog: Option<FileDialogTask>,
+    /// User fonts the current font definitions were built with
+    fonts_applied: Option<Vec<std::path::PathBuf>>,
+    proxy_state: ProxyWindowState,
+
+    local_files: Vec<LocalFile>,
+    /// Files the files window moved to the trash, offered back for a while
+    trashed_files: Vec<Trashed>,
+    /// Sensitive action waiting for the system to confirm the user
+    sensitive: SensitiveGate,
+    #[cfg(feature = "profiling")]
+    profiler: ui::windows::profiler::ProfilerWindow,
+    taskbar_progress: TaskbarProgress,
+    update_checker: UpdateChecker,
 
-    // WAN Connect
-    wan_connect_state: WanConnectState,
     wan_service: std::sync::Arc<p2p_wan::ConnectionListener>,
+    /// Tells the backend which WAN endpoint to report on
+    wan_endpoints: watch::Sender<iroh::Endpoint>,
+    /// The listener of the new profile, once started
+    wan_restart: Option<oneshot::Receiver<std::sync::Arc<p2p_wan::ConnectionListener>>>,
     wan_runtime: tokio::runtime::Handle,
 }
 
 impl MyApp {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        storage: Option<&dyn eframe::Storage>,
         tx: mpsc::Sender<AppCommand>,
-        rx: mpsc::Receiver<AppEvent>,
+        rx: EventSubscription,
         event_tx: mpsc::Sender<AppEvent>,
         wan_service: std::sync::Arc<p2p_wan::ConnectionListener>,
+        wan_endpoints: watch::Sender<iroh::Endpoint>,
         wan_runtime: tokio::runtime::Handle,
+        send_picker: SendPickerState,
+        links: LinkInbox,
     ) -> Self {
+        let telemetry_mode = p2p_core::config::AppConfig::load().telemetry.mode;
+        let ui_state = AppUIState {
+            units: p2p_core::units::unit_preference(),
+            hash_algorithm: p2p_core::transfer::hash_algorithm(),
+            policy: p2p_core::policy::Policy::load(),
+            telemetry_mode,
+            ..storage
+                .and_then(|storage| eframe::get_value(storage, UI_STATE_KEY))
+                .unwrap_or_default()
+        };
         let mut app = Self {
-            cmd_sender: tx,
+            cmd_sender: CommandBridge::new(tx),
             event_receiver: rx,
             event_sender: event_tx,
-            ui_state: AppUIState::default(),
-            verification_state: VerificationState::default(),
-            upload_confirm_state: UploadConfirmState::default(),
-            status_log: Vec::new(),
-            peers: HashMap::new(),
-            download_path: p2p_core::config::get_download_dir(),
+            state: AppState::new(ui_state, WanConnectState::default()),
+            send_picker,
+            links,
+            telemetry_mode,
+            log_export_dialog: None,
+            font_dialog: None,
+            fonts_applied: None,
+            proxy_state: ProxyWindowState::default(),
             local_files: Vec::new(),
-            active_transfers: HashMap::new(),
-            system: System::new_all(),
-            last_metrics_update: Instant::now(),
-            qrcode_cache: QrCodeCache::default(),
-            share_tab: ShareTab::default(),
-            share_url: "Server not started".to_string(),
-            http_server_running: false,
-            http_server_pending: false,
-            wan_share_url: None,
-            wan_share_running: false,
-            wan_share_pending: false,
-            wan_connect_state: WanConnectState::default(),
+            trashed_files: Vec::new(),
+            sensitive: SensitiveGate::default(),
+            #[cfg(feature = "profiling")]
+            profiler: Default::default(),
+            taskbar_progress: TaskbarProgress::default(),
+            update_checker: UpdateChecker::default(),
             wan_service,
+            wan_endpoints,
+            wan_restart: None,
             wan_runtime,
         };
+        app.sensitive.required = app.state.ui_state.confirm_sensitive && os_auth::SUPPORTED;
         app.refresh_local_files();
         app
     }
 
+    /// Carry out what an event asked for beyond the state
+    fn run_effect(&mut self, effect: Effect) {
+        match effect {
+            Effect::Send(command) => self.cmd_sender.send(command),
+            Effect::RefreshLocalFiles => self.refresh_local_files(),
+            Effect::MonitorWan(conn) => {
+                // Connection type and latency come back as events
+                let endpoint = self.wan_service.endpoint().clone();
+                let peer_id = conn.remote_id();
+                let event_tx = self.event_sender.clone();
+                let heartbeat = match p2p_core::config::AppConfig::load().wan_heartbeat_secs {
+                    0 => None,
+                    secs => Some(Duration::from_secs(secs)),
+                };
+                self.wan_runtime.spawn(async move {
+                    p2p_wan::listener::spawn_connection_monitor(
+                        endpoint, peer_id, conn, event_tx, heartbeat,
+                    )
+                    .await;
+                });
+            }
+            Effect::UseUnits(units) => p2p_core::units::set_unit_preference(units),
+            Effect::ResumeOverWan(broken) => self.resume_over_wan(broken),
+            Effect::RestartWan => self.restart_wan(),
+        }
+    }
+
+    /// Close the WAN listener and start the active profile's in its place
+    fn restart_wan(&mut self) {
+        let previous = self.wan_service.clone();
+        let event_tx = self.event_sender.clone();
+        let (restarted_tx, restarted_rx) = oneshot::channel();
+        self.wan_restart = Some(restarted_rx);
+        self.wan_runtime.spawn(async move {
+            previous.endpoint().close().await;
+            match crate::wan::start(crate::wan::WanProfile::active(), event_tx.clone()).await {
+                Ok(listener) => {
+                    let _ = restarted_tx.send(listener);
+                }
+                Err(e) => {
+                    let _ = event_tx
+                        .send(AppEvent::Error(format!(
+                            "Could not start the WAN listener of the profile: {:#}",
+                            e
+                        )))
+                        .await;
+                }
+            }
+        });
+    }
+
+    /// Send what is left of a broken LAN send over the WAN; the receiver's
+    /// resume records continue each partial file where the LAN left it
+    fn resume_over_wan(&self, broken: BrokenSend) {
+        let wan_service = self.wan_service.clone();
+        let cmd_sender = self.cmd_sender.clone();
+        let event_tx = self.event_sender.clone();
+        self.wan_runtime.spawn(async move {
+            let result = async {
+                let id = broken.endpoint_id.parse::<iroh::EndpointId>()?;
+                let connection = tokio::time::timeout(
+                    p2p_core::path::WAN_PROBE_TIMEOUT,
+                    wan_service.connect(id),
+                )
+                .await
+                .map_err(|_| anyhow::anyhow!("the device did not answer"))??;
+                if let Some(first) = broken.files.first() {
+                    let file_name = first
+                        .path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    let _ = event_tx
+                        .send(AppEvent::PathSwitched {
+                            target_peer_name: broken.target_peer_name.clone(),
+                            file_name,
+                            offset: first.sent,
+                        })
+                        .await;
+                }
+                let security = p2p_wan::listener::security_info(
+                    wan_service.endpoint(),
+                    connection.remote_id(),
+                );
+                let files = broken.files.iter().map(|file| file.path.clone()).collect();
+                p2p_wan::sender::send_files(&connection, files, security, event_tx.clone()).await
+            }
+            .await;
+            match result {
+                Ok(()) => cmd_sender.send(AppCommand::DiscardPendingSend {
+                    id: broken.session_id,
+                }),
+                Err(e) => {
+                    let _ = event_tx
+                        .send(AppEvent::Error(format!(
+                            "Could not continue the send to {} over the WAN: {}",
+                            broken.target_peer_name,
+                            p2p_wan::close::explain(&e)
+                        )))
+                        .await;
+                }
+            }
+        });
+    }
+
     pub fn refresh_local_files(&mut self) {
         self.local_files.clear();
-        if let Ok(entries) = std::fs::read_dir(&self.download_path) {
+        if let Ok(entries) = std::fs::read_dir(&self.state.download_path) {
             for entry in entries.flatten() {
-                if let Ok(meta) = entry.metadata()
-                    && meta.is_file()
-                    && let Some(name) = entry.file_name().to_str()
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                if meta.is_file() {
+                    self.local_files.push(LocalFile::new(name, &meta));
+                } else if meta.is_dir()
+                    && let Ok(inner) = std::fs::read_dir(entry.path())
                 {
-                    self.local_files.push(name.to_string());
+                    // Per-sender folders, one level deep
+                    for file in inner.flatten() {
+                        if let Ok(file_meta) = file.metadata()
+                            && file_meta.is_file()
+                            && let Some(file_name) = file.file_name().to_str()
+                        {
+                            self.local_files.push(LocalFile::new(
+                                format!("{}/{}", name, file_name),
+                                &file_meta,
+                            ));
+                        }
+                    }
                 }
             }
         }
-        self.local_files.sort();
+        self.local_files.sort_by(|a, b| a.name.cmp(&b.name));
     }
-}
 
-impl eframe::App for MyApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        while let Ok(event) = self.event_receiver.try_recv() {
-            match event {
-                AppEvent::Status(msg) => {
-                    let log_type = if msg.contains("error")
-                        || msg.contains("Error")
-                        || msg.contains("ERROR")
-                    {
-                        LogType::Error
-                    } else if msg.contains("Complete")
-                        || msg.contains("success")
-                        || msg.contains("Verified")
-                    {
-                        LogType::Success
-                    } else if msg.contains("Connecting") || msg.contains("Starting") {
-                        LogType::Warning
-                    } else {
-                        LogType::Info
-                    };
-                    self.status_log.push(LogEntry {
-                        message: msg,
-                        log_type,
-                    });
-                }
-                AppEvent::PeerFound {
-                    endpoint_id: _,
-                    ip,
-                    hostname,
-                } => {
-                    // Update or insert peer (using IP as key)
-                    self.peers.insert(
-                        ip.clone(),
-                        PeerInfo {
-                            ip,
-                            hostname,
-                            last_seen: Instant::now(),
+    /// Show or redeem a link phrase on the WAN runtime; the outcome comes
+    /// back as a link event
+    fn start_link(&self, request: LinkRequest) {
+        let app_config = p2p_core::config::AppConfig::load();
+        let wan_service = self.wan_service.clone();
+        let event_tx = self.event_sender.clone();
+        self.wan_runtime.spawn(async move {
+            let Some(url) = app_config
+                .rendezvous
+                .url
+                .filter(|url| !url.trim().is_empty())
+            else {
+                let message = "Set a rendezvous server in the WAN window first".to_string();
+                let _ = event_tx.send(AppEvent::LinkFailed { message }).await;
+                return;
+            };
+            let event = match request {
+                LinkRequest::Offer => match wan_service.offer_link(&url, &app_config.proxy).await {
+                    Ok(phrase) => AppEvent::LinkPhraseReady {
+                        phrase: phrase.to_string(),
+                        expires_in_secs: LINK_PHRASE_EXPIRY_SECS,
+                    },
+                    Err(e) => AppEvent::LinkFailed {
+                        message: format!("{:#}", e),
+                    },
+                },
+                LinkRequest::Join(phrase) => {
+                    match wan_service.link(&phrase, &url, &app_config.proxy).await {
+                        Ok(linked) => AppEvent::DeviceLinked {
+                            endpoint_id: linked.endpoint_id.to_string(),
+                            peer_name: linked.name,
                         },
-                    );
-                }
-                AppEvent::ShowVerificationCode {
-                    code,
-                    from_ip,
-                    from_name,
-                } => {
-                    self.verification_state = VerificationState::ShowingCode {
-                        code,
-                        from_ip,
-                        from_name,
-                    };
-                }
-                AppEvent::RequestVerificationCode { target_ip } => {
-                    self.verification_state = VerificationState::InputtingCode {
-                        target_ip,
-                        code_input: String::new(),
-                        error_msg: None,
-                    };
-                }
-                AppEvent::PairingResult {
-                    success,
-                    peer_name,
-                    message,
-                } => {
-                    self.status_log.push(LogEntry {
-                        message: format!("Pairing with {}: {}", peer_name, message),
-                        log_type: if success {
-                            LogType::Success
-                        } else {
-                            LogType::Error
+                        Err(e) => AppEvent::LinkFailed {
+                            message: format!("{:#}", e),
                         },
-                    });
-
-                    if !success
-                        && let VerificationState::InputtingCode { error_msg, .. } =
-                            &mut self.verification_state
-                    {
-                        *error_msg = Some(message);
-                        continue; // Keep window open to show error
                     }
-                    self.verification_state = VerificationState::None;
                 }
+            };
+            let _ = event_tx.send(event).await;
+        });
+    }
 
-                AppEvent::TransferProgress {
-                    file_name,
-                    progress,
-                    speed,
-                    speed_bps,
-                    is_sending,
-                } => {
-                    self.active_transfers
-                        .entry(file_name.clone())
-                        .and_modify(|t| {
-                            t.progress = progress;
-                            t.speed = speed.clone();
-                            t.speed_bps = speed_bps;
-                        })
-                        .or_insert(TransferState {
-                            file_name: file_name.clone(),
-                            progress,
-                            speed: speed.clone(),
-                            speed_bps,
-                            is_sending,
-                            verification_status: None,
-                        });
+    /// Send to a known device over the LAN if it answers there, else over
+    /// the WAN; the route taken shows in the status log
+    fn start_auto_send(&self, send: ui::windows::devices::AutoSend) {
+        let wan_service = self.wan_service.clone();
+        let strategy = self.state.wan_connect_state.strategy;
+        let cmd_sender = self.cmd_sender.clone();
+        let event_tx = self.event_sender.clone();
+        self.wan_runtime.spawn(async move {
+            let probe = match send.lan {
+                Some(_) => p2p_core::transfer::make_client_endpoint_with(
+                    p2p_core::transfer::SocketBuffers::default(),
+                    true,
+                    wan_service.app_id(),
+                    None,
+                )
+                .map(|(endpoint, _)| Some(endpoint)),
+                None => Ok(None),
+            };
+            let probe = match probe {
+                Ok(probe) => probe,
+                Err(e) => {
+                    let _ = event_tx
+                        .send(AppEvent::Error(format!(
+                            "Send to {} failed: {}",
+                            send.name, e
+                        )))
+                        .await;
+                    return;
                 }
-                AppEvent::TransferCompleted(file_name) => {
-                    self.status_log.push(LogEntry {
-                        message: format!("Transfer Complete: {}", file_name),
-                        log_type: LogType::Success,
-                    });
-                    self.active_transfers.remove(&file_name);
-                    self.refresh_local_files();
+            };
+            let lan = probe.as_ref().zip(send.lan);
+            let wan = send
+                .endpoint_id
+                .parse::<iroh::EndpointId>()
+                .ok()
+                .map(|id| wan_service.connect(id));
+            let chosen = match p2p_core::path::choose_path(lan, wan).await {
+                Ok(chosen) => chosen,
+                Err(e) => {
+                    let _ = event_tx
+                        .send(AppEvent::Error(format!(
+                            "Send to {} failed: {}",
+                            send.name, e
+                        )))
+                        .await;
+                    return;
                 }
-                AppEvent::Error(msg) => {
-                    self.status_log.push(LogEntry {
-                        message: format!("[ERROR] {}", msg),
-                        log_type: LogType::Error,
+            };
+            let _ = event_tx
+                .send(AppEvent::log(
+                    LogLevel::Info,
+                    EventCategory::Transfer,
+                    format!(
+                        "Sending to {} over the {} ({} ms)",
+                        send.name,
+                        chosen.label(),
+                        chosen.rtt().as_millis()
+                    ),
+                ))
+                .await;
+            match chosen {
+                p2p_core::path::ChosenPath::Lan { addr, .. } => {
+                    cmd_sender.send(AppCommand::SendFile {
+                        session_id: p2p_core::new_session_id(),
+                        target_ip: addr.to_string(),
+                        target_endpoint_id: send.endpoint_id,
+                        target_peer_name: send.name,
+                        files: send.files,
+                        note: send.note,
                     });
                 }
-                AppEvent::VerificationStarted {
-                    file_name,
-                    is_sending: _,
-                } => {
-                    if let Some(transfer) = self.active_transfers.get_mut(&file_name) {
-                        transfer.verification_status = Some(VerificationStatus::Verifying);
+                p2p_core::path::ChosenPath::Wan { connection, .. } => {
+                    let security = p2p_wan::listener::security_info(
+                        wan_service.endpoint(),
+                        connection.remote_id(),
+                    );
+                    if let Err(e) = p2p_wan::sender::send_with_strategy(
+                        &connection,
+                        send.files,
+                        strategy,
+                        security,
+                        event_tx.clone(),
+                    )
+                    .await
+                    {
+                        let _ = event_tx
+                            .send(AppEvent::Error(format!(
+                                "WAN send error: {}",
+                                p2p_wan::close::explain(&e)
+                            )))
+                            .await;
                     }
                 }
-                AppEvent::VerificationCompleted {
-                    file_name,
-                    is_sending: _,
-                    verified,
-                } => {
-                    if let Some(transfer) = self.active_transfers.get_mut(&file_name) {
-                        transfer.verification_status = Some(if verified {
-                            VerificationStatus::Verified
-                        } else {
-                            VerificationStatus::Failed
-                        });
+            }
+        });
+    }
+
+    /// Zip recent events and the status log for a bug report
+    fn create_diagnostic_bundle(&mut self) {
+        let mut status_log = Vec::new();
+        let _ = self.state.status_log.write_to(&mut status_log);
+        match diagnostics::create_bundle(String::from_utf8_lossy(&status_log).into_owned()) {
+            Ok(path) => self.state.status_log.push(
+                LogLevel::Success,
+                EventCategory::Status,
+                format!("Diagnostic bundle written to {}", path.display()),
+            ),
+            Err(e) => self.state.status_log.push(
+                LogLevel::Error,
+                EventCategory::Status,
+                format!("Failed to create diagnostic bundle: {}", e),
+            ),
+        }
+    }
+
+    /// Load the fallback fonts, again once the user's fonts change
+    pub fn apply_fonts(&mut self, ctx: &egui::Context) {
+        if self.fonts_applied.as_ref() == Some(&self.state.ui_state.user_fonts) {
+            return;
+        }
+        let (fonts, problems) = crate::fonts::definitions(&self.state.ui_state.user_fonts);
+        ctx.set_fonts(fonts);
+        for problem in problems {
+            self.state
+                .status_log
+                .push(LogLevel::Warning, EventCategory::Status, problem);
+        }
+        self.fonts_applied = Some(self.state.ui_state.user_fonts.clone());
+    }
+
+    /// Status log with level filter, search and export
+    fn show_status_log(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        if let Some(dialog) = &self.log_export_dialog {
+            match dialog.poll() {
+                DialogResult::Pending => {}
+                DialogResult::Picked(paths) => {
+                    self.log_export_dialog = None;
+                    if let Some(path) = paths.first() {
+                        match self.state.status_log.export(path) {
+                            Ok(()) => self.state.status_log.push(
+                                LogLevel::Success,
+                                EventCategory::Status,
+                                format!("Log exported to {}", path.display()),
+                            ),
+                            Err(e) => self.state.status_log.push(
+                                LogLevel::Error,
+                                EventCategory::Status,
+                                format!("Failed to export log: {}", e),
+                            ),
+                        }
                     }
-                    let status = if verified {
-                        format!("{} Verified", egui_phosphor::regular::CHECK_CIRCLE)
-                    } else {
-                        format!("{} Corrupted", egui_phosphor::regular::X_CIRCLE)
-                    };
-                    self.status_log.push(LogEntry {
-                        message: format!("Verification: {} - {}", file_name, status),
-                        log_type: if verified {
-                            LogType::Success
-                        } else {
-                            LogType::Error
-                        },
-                    });
-                }
-                AppEvent::ShareUrlReady { url } => {
-                    self.share_url = url;
-                    // Reset QR cache to regenerate with new URL
-                    self.qrcode_cache = QrCodeCache::default();
-                }
-                AppEvent::HttpServerStarted { url } => {
-                    self.share_url = url;
-                    self.http_server_running = true;
-                    self.http_server_pending = false;
-                    self.qrcode_cache = QrCodeCache::default();
-                    self.status_log.push(LogEntry {
-                        message: "HTTP server started".to_string(),
-                        log_type: LogType::Success,
-                    });
-                }
-                AppEvent::HttpServerStopped => {
-                    self.http_server_running = false;
-                    self.http_server_pending = false;
-                    self.share_url = "Server not started".to_string();
-                    self.status_log.push(LogEntry {
-                        message: "HTTP server stopped".to_string(),
-                        log_type: LogType::Info,
-                    });
-                }
-                AppEvent::UploadRequest {
-                    request_id,
-                    file_name,
-                    file_size,
-                    from_ip,
-                } => {
-                    self.upload_confirm_state =
-                        UploadConfirmState::Pending(upload_confirm::PendingUpload {
-                            request_id,
-                            file_name,
-                            file_size,
-                            from_ip,
-                        });
                 }
-                AppEvent::UploadRequestCancelled { request_id } => {
-                    if let UploadConfirmState::Pending(upload) = &self.upload_confirm_state
-                        && upload.request_id == request_id
-                    {
-                        self.upload_confirm_state = UploadConfirmState::None;
-                        self.status_log.push(LogEntry {
-                            message: "Upload request cancelled".to_string(),
-                            log_type: LogType::Info,
-                        });
+                DialogResult::Cancelled => self.log_export_dialog = None,
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.label(format!("Status Logs ({}):", self.state.status_log.len()));
+            egui::ComboBox::from_id_salt("log_filter")
+                .selected_text(self.state.status_log.filter.label())
+                .show_ui(ui, |ui| {
+                    for filter in LogFilter::ALL {
+                        ui.selectable_value(
+                            &mut self.state.status_log.filter,
+                            filter,
+                            filter.label(),
+                        );
                     }
-                }
-                AppEvent::UploadProgress {
-                    request_id: _,
-                    received_bytes,
-                    total_bytes: _,
-                } => {
-                    if received_bytes == 0 {
-                        self.status_log.push(LogEntry {
-                            message: "Incoming upload started...".to_string(),
-                            log_type: LogType::Info,
-                        });
+                });
+            ui.checkbox(&mut self.state.status_log.show_debug, "Debug");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.state.status_log.search)
+                    .desired_width(120.0)
+                    .hint_text("Search..."),
+            );
+            if ui
+                .add_enabled(
+                    self.log_export_dialog.is_none(),
+                    egui::Button::new(egui_phosphor::regular::EXPORT),
+                )
+                .on_hover_text("Export log to file")
+                .clicked()
+            {
+                self.log_export_dialog =
+                    Some(FileDialogTask::save_file(ctx, "p2p_transfer_log.txt"));
+            }
+            if ui
+                .button(egui_phosphor::regular::FIRST_AID_KIT)
+                .on_hover_text("Create diagnostic bundle")
+                .clicked()
+            {
+                self.create_diagnostic_bundle();
+            }
+            if ui
+                .button(egui_phosphor::regular::TRASH)
+                .on_hover_text("Clear log")
+                .clicked()
+            {
+                self.state.status_log.clear();
+            }
+        });
+
+        egui::ScrollArea::vertical()
+            .max_height(200.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for entry in self.state.status_log.visible() {
+                    let color = match entry.level {
+                        LogLevel::Debug => egui::Color32::DARK_GRAY,
+                        LogLevel::Info => egui::Color32::GRAY,
+                        LogLevel::Success => egui::Color32::from_rgb(100, 200, 100),
+                        LogLevel::Error => egui::Color32::from_rgb(255, 100, 100),
+                        LogLevel::Warning => egui::Color32::from_rgb(255, 200, 100),
+                    };
+                    if entry.repeat > 1 {
+                        ui.colored_label(color, format!("{} (x{})", entry.message, entry.repeat));
+                    } else {
+                        ui.colored_label(color, &entry.message);
                     }
                 }
-                AppEvent::UploadCompleted {
-                    file_name,
-                    saved_path: _,
-                } => {
-                    self.status_log.push(LogEntry {
-                        message: format!("Upload received: {}", file_name),
-                        log_type: LogType::Success,
-                    });
-                    self.refresh_local_files();
-                }
-                AppEvent::WanConnected(conn) => {
-                    self.status_log.push(LogEntry {
-                        message: format!("Connected to WAN peer: {}", conn.remote_id()),
-                        log_type: LogType::Success,
-                    });
+            });
+    }
+}
 
-                    // Spawn connection type monitor
-                    let endpoint = self.wan_service.endpoint().clone();
-                    let peer_id = conn.remote_id();
-                    let event_tx = self.event_sender.clone();
-                    let conn_for_rtt = conn.clone();
-
-                    self.wan_runtime.spawn(async move {
-                        p2p_wan::listener::spawn_connection_monitor(
-                            endpoint,
-                            peer_id,
-                            conn_for_rtt,
-                            event_tx,
-                        )
-                        .await;
-                    });
+impl eframe::App for MyApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, UI_STATE_KEY, &self.state.ui_state);
+    }
 
-                    self.wan_connect_state.active_connection = Some(conn);
-                    self.wan_connect_state.connection_status = "Connected".to_string();
-                    self.wan_connect_state.connection_type = "Checking...".to_string();
-                }
-                AppEvent::WanConnectionInfo {
-                    connection_type,
-                    rtt_ms,
-                } => {
-                    let rtt_str = rtt_ms
-                        .map(|ms| format!(" (RTT: {}ms)", ms))
-                        .unwrap_or_default();
-                    self.wan_connect_state.connection_type =
-                        format!("{}{}", connection_type, rtt_str);
-                }
-                AppEvent::WanShareReady { url } => {
-                    self.wan_share_url = Some(url.clone());
-                    self.wan_share_running = true;
-                    self.wan_share_pending = false;
-                    self.qrcode_cache = QrCodeCache::default();
-                    self.status_log.push(LogEntry {
-                        message: format!("WAN share ready: {}", url),
-                        log_type: LogType::Success,
-                    });
-                }
-                AppEvent::WanShareStopped => {
-                    self.wan_share_url = None;
-                    self.wan_share_running = false;
-                    self.wan_share_pending = false;
-                    self.status_log.push(LogEntry {
-                        message: "WAN share stopped".to_string(),
-                        log_type: LogType::Info,
-                    });
-                }
-                AppEvent::WanShareError(msg) => {
-                    self.wan_share_pending = false;
-                    self.status_log.push(LogEntry {
-                        message: format!("[WAN Share Error] {}", msg),
-                        log_type: LogType::Error,
-                    });
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        #[cfg(feature = "profiling")]
+        p2p_core::profiling::puffin::GlobalProfiler::lock().new_frame();
+
+        while let Some(event) = self.event_receiver.try_recv() {
+            for effect in self.state.apply(event) {
+                self.run_effect(effect);
+            }
+        }
+        if let Some(restart) = &mut self.wan_restart {
+            match restart.try_recv() {
+                Ok(listener) => {
+                    self.wan_endpoints.send_replace(listener.endpoint().clone());
+                    self.wan_service = listener;
+                    self.wan_restart = None;
                 }
+                Err(oneshot::error::TryRecvError::Closed) => self.wan_restart = None,
+                Err(oneshot::error::TryRecvError::Empty) => {}
             }
         }
-
-        let now = Instant::now();
-        self.peers.retain(|_, info| {
-            now.duration_since(info.last_seen) < Duration::from_secs(PEER_TIMEOUT_SECS)
-        });
-
-        if now.duration_since(self.last_metrics_update) > Duration::from_secs(1) {
-            self.system.refresh_cpu_all();
-            self.system.refresh_memory();
-            self.last_metrics_update = now;
+        self.state.expire_peers(Instant::now());
+        while let Some(link) = self.links.next() {
+            self.state.open_link(link);
         }
 
-        // Calculate Bandwidth
-        let mut total_upload = 0.0;
-        let mut total_download = 0.0;
+        let progress = taskbar::overall_progress(
+            self.state
+                .active_transfers
+                .values()
+                // A stream has no percentage to add
+                .filter(|transfer| !transfer.done && transfer.bytes.is_none())
+                .map(|transfer| transfer.progress),
+        );
+        self.taskbar_progress.update(ctx, frame, progress);
 
-        for transfer in self.active_transfers.values() {
-            let mbps = transfer.speed_bps / 1_000_000.0;
-            if transfer.is_sending {
-                total_upload += mbps;
+        ui::toolbar::show(ctx, &mut self.state.ui_state);
+        if self.state.ui_state.units != p2p_core::units::unit_preference() {
+            p2p_core::units::set_unit_preference(self.state.ui_state.units);
+            self.cmd_sender
+                .send(AppCommand::SetUnitPreference(self.state.ui_state.units));
+        }
+        if self.state.ui_state.hash_algorithm != p2p_core::transfer::hash_algorithm() {
+            p2p_core::transfer::set_hash_algorithm(self.state.ui_state.hash_algorithm);
+            self.cmd_sender.send(AppCommand::SetHashAlgorithm(
+                self.state.ui_state.hash_algorithm,
+            ));
+        }
+        if self.state.ui_state.metered_mode != self.state.metered_mode {
+            self.state.metered_mode = self.state.ui_state.metered_mode;
+            self.cmd_sender
+                .send(AppCommand::SetMeteredMode(self.state.ui_state.metered_mode));
+        }
+        if self.state.ui_state.telemetry_mode != self.telemetry_mode {
+            self.telemetry_mode = self.state.ui_state.telemetry_mode;
+            self.cmd_sender
+                .send(AppCommand::SetTelemetryMode(self.telemetry_mode));
+        }
+        if std::mem::take(&mut self.state.ui_state.preview_telemetry) {
+            self.cmd_sender.send(AppCommand::PreviewTelemetry);
+        }
+        if std::mem::take(&mut self.state.ui_state.pick_font) && self.font_dialog.is_none() {
+            self.font_dialog = Some(FileDialogTask::pick_font(ctx));
+        }
+        if let Some(dialog) = &self.font_dialog {
+            match dialog.poll() {
+                DialogResult::Pending => {}
+                DialogResult::Picked(paths) => {
+                    self.font_dialog = None;
+                    for path in paths {
+                        if !self.state.ui_state.user_fonts.contains(&path) {
+                            self.state.ui_state.user_fonts.push(path);
+                        }
+                    }
+                }
+                DialogResult::Cancelled => self.font_dialog = None,
+            }
+        }
+        self.apply_fonts(ctx);
+        if self.state.ui_state.confirm_sensitive != self.sensitive.required {
+            if self.state.ui_state.confirm_sensitive {
+                self.sensitive.required = true;
             } else {
-                total_download += mbps;
+                // Turning the prompts off is itself confirmed
+                self.state.ui_state.confirm_sensitive = true;
+                self.sensitive.ask(
+                    ctx,
+                    "Stop confirming sensitive actions",
+                    Sensitive::StopAsking,
+                );
             }
         }
-
-        let mut peer_list: Vec<String> = self
-            .peers
-            .values()
-            .map(|info| format!("{} ({})", info.hostname, info.ip))
-            .collect();
-        peer_list.sort();
-
-        ui::toolbar::show(ctx, &mut self.ui_state);
+        match self.sensitive.poll() {
+            Some(Ok(Sensitive::Send(cmd))) => self.cmd_sender.send(cmd),
+            Some(Ok(Sensitive::StopAsking)) => {
+                self.state.ui_state.confirm_sensitive = false;
+                self.sensitive.required = false;
+            }
+            Some(Err(message)) => {
+                self.state
+                    .status_log
+                    .push(LogLevel::Warning, EventCategory::Status, message);
+            }
+            None => {}
+        }
+        self.update_checker.poll(
+            &mut self.state.ui_state.update_check,
+            &self.wan_runtime,
+            ctx,
+        );
+        ui::update_banner::show(
+            ctx,
+            &mut self.update_checker,
+            &mut self.state.ui_state.update_check,
+        );
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Active Transfers");
-            if self.active_transfers.is_empty() {
+            ui.horizontal(|ui| {
+                ui.heading("Active Transfers");
+                if !self.state.active_transfers.is_empty()
+                    && ui
+                        .button(format!("{} Cancel all", egui_phosphor::regular::X))
+                        .on_hover_text("Stop every transfer in progress, on both devices")
+                        .clicked()
+                {
+                    self.cmd_sender.send(AppCommand::CancelTransfer);
+                }
+            });
+            if self.state.active_transfers.is_empty() {
                 ui.label("No active transfers.");
             } else {
-                for transfer in self.active_transfers.values() {
+                for transfer in self.state.active_transfers.values() {
                     ui.group(|ui| {
                         let direction = if transfer.is_sending {
                             "Sending"
@@ -486,8 +719,12 @@ impl eframe::App for MyApp {
 
                         // Show verification status if available
                         let verification_text = match transfer.verification_status {
-                            Some(VerificationStatus::Verifying) => {
-                                format!(" {} Verifying...", egui_phosphor::regular::TIMER)
+                            Some(VerificationStatus::Verifying(progress)) => {
+                                format!(
+                                    " {} Verifying... {:.0}%",
+                                    egui_phosphor::regular::TIMER,
+                                    progress
+                                )
                             }
                             Some(VerificationStatus::Verified) => {
                                 format!(" {} Verified", egui_phosphor::regular::CHECK_CIRCLE)
@@ -495,6 +732,9 @@ impl eframe::App for MyApp {
                             Some(VerificationStatus::Failed) => {
                                 format!(" {} Corrupted", egui_phosphor::regular::X_CIRCLE)
                             }
+                            None if transfer.interrupted => {
+                                format!(" {} Interrupted by sleep", egui_phosphor::regular::MOON)
+                            }
                             None => "".to_string(),
                         };
 
@@ -503,97 +743,124 @@ impl eframe::App for MyApp {
                             direction, transfer.file_name, transfer.speed, verification_text
                         );
 
-                        // Color code based on verification status
-                        match transfer.verification_status {
-                            Some(VerificationStatus::Verified) => {
-                                ui.colored_label(egui::Color32::GREEN, label_text);
-                            }
-                            Some(VerificationStatus::Failed) => {
-                                ui.colored_label(egui::Color32::RED, label_text);
+                        ui.horizontal(|ui| {
+                            if let Some(security) = &transfer.security {
+                                // Shield: peer key pinned; lock: encrypted, peer checked by code
+                                let icon = if security.is_pinned() {
+                                    egui_phosphor::regular::SHIELD_CHECK
+                                } else {
+                                    egui_phosphor::regular::LOCK
+                                };
+                                ui.label(icon).on_hover_text(security.details());
                             }
-                            _ => {
-                                ui.label(label_text);
+
+                            // Color code based on verification status
+                            match transfer.verification_status {
+                                Some(VerificationStatus::Verified) => {
+                                    ui.colored_label(egui::Color32::GREEN, label_text);
+                                }
+                                Some(VerificationStatus::Failed) => {
+                                    ui.colored_label(egui::Color32::RED, label_text);
+                                }
+                                _ => {
+                                    ui.label(label_text);
+                                }
                             }
-                        }
+                        });
 
-                        ui.add(egui::ProgressBar::new(transfer.progress / 100.0).show_percentage());
+                        let bar = match transfer.bytes {
+                            Some(bytes) => egui::ProgressBar::new(transfer.progress / 100.0)
+                                .text(p2p_core::units::format_size(bytes))
+                                .animate(transfer.progress < 100.0),
+                            None => {
+                                egui::ProgressBar::new(transfer.progress / 100.0).show_percentage()
+                            }
+                        };
+                        ui.add(bar);
                     });
                 }
             }
 
             // Show status logs with color coding
             ui.separator();
-            ui.label("Status Logs:");
-            egui::ScrollArea::vertical()
-                .max_height(200.0)
-                .stick_to_bottom(true)
-                .show(ui, |ui| {
-                    for entry in &self.status_log {
-                        let color = match entry.log_type {
-                            LogType::Info => egui::Color32::GRAY,
-                            LogType::Success => egui::Color32::from_rgb(100, 200, 100),
-                            LogType::Error => egui::Color32::from_rgb(255, 100, 100),
-                            LogType::Warning => egui::Color32::from_rgb(255, 200, 100),
-                        };
-                        ui.colored_label(color, &entry.message);
-                    }
-                });
+            self.show_status_log(ui, ctx);
         });
 
         // 5. Draw Bottom Status Bar (System Metrics)
         egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                let cpu_usage = self.system.global_cpu_usage();
-                ui.label(format!("CPU: {:.1}%", cpu_usage));
-                ui.add(egui::ProgressBar::new(cpu_usage / 100.0).desired_width(100.0));
-
-                ui.separator();
-
-                // RAM
-                let used_mem = self.system.used_memory() as f32 / 1024.0 / 1024.0 / 1024.0; // GB
-                let total_mem = self.system.total_memory() as f32 / 1024.0 / 1024.0 / 1024.0; // GB
-                let mem_ratio = if total_mem > 0.0 {
-                    used_mem / total_mem
-                } else {
-                    0.0
-                };
-                ui.label(format!("RAM: {:.1}/{:.1} GB", used_mem, total_mem));
-                ui.add(egui::ProgressBar::new(mem_ratio).desired_width(100.0));
+                let (total_upload, total_download, system) = self.state.metrics;
+                if let Some(system) = system {
+                    ui.label(format!("CPU: {:.1}%", system.cpu_percent));
+                    ui.add(egui::ProgressBar::new(system.cpu_percent / 100.0).desired_width(100.0));
+
+                    ui.separator();
+
+                    // RAM
+                    let used_mem = system.memory_used as f32 / 1024.0 / 1024.0 / 1024.0; // GB
+                    let total_mem = system.memory_total as f32 / 1024.0 / 1024.0 / 1024.0; // GB
+                    let mem_ratio = if total_mem > 0.0 {
+                        used_mem / total_mem
+                    } else {
+                        0.0
+                    };
+                    ui.label(format!("RAM: {:.1}/{:.1} GB", used_mem, total_mem));
+                    ui.add(egui::ProgressBar::new(mem_ratio).desired_width(100.0));
 
-                ui.separator();
+                    ui.separator();
+                }
 
                 // Bandwidth
                 ui.label(format!(
-                    "{} Upload: {:.2} MB/s",
+                    "{} Upload: {}",
                     egui_phosphor::regular::UPLOAD_SIMPLE,
-                    total_upload
+                    p2p_core::units::format_speed(total_upload)
                 ));
                 ui.label(format!(
-                    "{} Download: {:.2} MB/s",
+                    "{} Download: {}",
                     egui_phosphor::regular::DOWNLOAD_SIMPLE,
-                    total_download
+                    p2p_core::units::format_speed(total_download)
                 ));
+
+                ui.separator();
+                ui::health::show(ui, &self.state.health);
             });
         });
 
         // 6. Draw Floating Windows
-        if self.ui_state.show_devices {
+        if self.state.ui_state.show_devices {
             ui::windows::devices::show(
                 ctx,
-                &mut self.ui_state.show_devices,
-                &peer_list,
+                &mut self.state.ui_state.show_devices,
+                &mut self.state.devices_state,
+                &self.state.peers,
+                (!self.state.ui_state.policy.disable_wan_share).then_some(&self.state.registry),
+                &mut self.sensitive,
                 &self.cmd_sender,
             );
+            for send in self.state.devices_state.take_auto_sends() {
+                self.start_auto_send(send);
+            }
         }
 
-        if self.ui_state.show_files {
+        send_picker::show(
+            ctx,
+            &mut self.send_picker,
+            &self.state.peers,
+            self.state.devices_state.receive_only,
+            &self.cmd_sender,
+        );
+
+        if self.state.ui_state.show_files {
             let mut trigger_refresh = false;
 
             ui::windows::files::show(
                 ctx,
-                &mut self.ui_state.show_files,
-                &self.download_path,
+                &mut self.state.ui_state.show_files,
+                &self.state.download_path,
                 &self.local_files,
+                &mut self.state.ui_state.hard_delete,
+                &mut self.trashed_files,
                 || {
                     trigger_refresh = true;
                 },
@@ -603,42 +870,118 @@ impl eframe::App for MyApp {
                 self.refresh_local_files();
             }
         }
+        if ui::windows::files::show_undo(ctx, &mut self.trashed_files) {
+            self.refresh_local_files();
+        }
+
+        if self.state.ui_state.show_history {
+            ui::windows::history::show(
+                ctx,
+                &mut self.state.ui_state.show_history,
+                &mut self.state.history_window,
+                &mut self.sensitive,
+                &self.cmd_sender,
+            );
+        }
+
+        #[cfg(feature = "profiling")]
+        ui::windows::profiler::show(ctx, &mut self.state.ui_state.show_profiler, &self.profiler);
 
         // QR Code Window
-        if self.ui_state.show_qrcode {
+        if self.state.ui_state.show_qrcode {
             ui::windows::qr_code::show(
                 ctx,
-                &mut self.ui_state.show_qrcode,
-                &mut self.qrcode_cache,
-                &mut self.share_tab,
+                &mut self.state.ui_state.show_qrcode,
+                &mut self.state.qrcode_cache,
+                &mut self.state.share_tab,
                 // LAN
-                &self.share_url,
-                self.http_server_running,
-                &mut self.http_server_pending,
+                &self.state.share_url,
+                self.state.http_server_running,
+                &mut self.state.http_server_pending,
                 // WAN
-                self.wan_share_url.as_deref(),
-                self.wan_share_running,
-                &mut self.wan_share_pending,
+                self.state.wan_share_url.as_deref(),
+                self.state.wan_share_running,
+                &mut self.state.wan_share_pending,
+                &mut self.state.pair_state,
+                &mut self.state.shared_text,
+                &mut self.state.file_links,
                 &self.cmd_sender,
             );
+            if let Some(request) = self.state.pair_state.take_link_request() {
+                self.start_link(request);
+            }
+            if let Some(endpoint_id) = self.state.pair_state.take_scanned_endpoint() {
+                self.state.wan_connect_state.fill_scanned(endpoint_id);
+                self.state.ui_state.show_wan_connect = true;
+            }
         }
 
         // 7. Draw Verification Windows
-        verify::show_verification_windows(ctx, &mut self.verification_state, &self.cmd_sender);
+        verify::show_verification_windows(
+            ctx,
+            &mut self.state.verification_state,
+            &self.cmd_sender,
+        );
 
         // 8. Draw Upload Confirmation Windows
         upload_confirm::show_upload_confirm_window(
             ctx,
-            &mut self.upload_confirm_state,
+            &mut self.state.upload_confirm_state,
             &self.cmd_sender,
         );
+        wan_offer::show(ctx, &mut self.state.wan_offers, &self.wan_service);
+        duplicates::show(ctx, &mut self.state.duplicates, &self.cmd_sender);
+        moves::show(ctx, &mut self.state.pending_moves, &self.cmd_sender);
+        metered::show(ctx, &mut self.state.held_sends, &self.cmd_sender);
+        send_preview::show(
+            ctx,
+            &mut self.state.devices_state.previews,
+            &self.cmd_sender,
+        );
+        resend::show(ctx, &mut self.state.resend_offers, &self.cmd_sender);
+        orphans::show(
+            ctx,
+            &mut self.state.orphaned_partials,
+            &self.state.peers,
+            &self.cmd_sender,
+        );
+        swarms::show(ctx, &self.state.swarm_availability, &self.state.peers);
+        telemetry::show(ctx, &mut self.state.telemetry_preview, &self.cmd_sender);
+
+        // Scheduled Sends Window
+        if self.state.ui_state.show_scheduled {
+            ui::windows::scheduled::show(
+                ctx,
+                &mut self.state.ui_state.show_scheduled,
+                &self.state.scheduled_sends,
+                &self.cmd_sender,
+            );
+        }
+
+        if self.state.ui_state.show_proxy {
+            ui::windows::proxy::show(
+                ctx,
+                &mut self.state.ui_state.show_proxy,
+                &mut self.proxy_state,
+                &self.cmd_sender,
+            );
+        }
+
+        if self.state.show_pending_sends {
+            ui::windows::pending::show(
+                ctx,
+                &mut self.state.show_pending_sends,
+                &self.state.pending_sends,
+                &self.cmd_sender,
+            );
+        }
 
         // 9. Draw WAN Connect Window
-        if self.ui_state.show_wan_connect {
+        if self.state.ui_state.show_wan_connect && !self.state.ui_state.policy.disable_wan_share {
             wan_connect::show(
                 ctx,
-                &mut self.ui_state.show_wan_connect,
-                &mut self.wan_connect_state,
+                &mut self.state.ui_state.show_wan_connect,
+                &mut self.state.wan_connect_state,
                 &self.cmd_sender,
                 &self.event_sender,
                 &self.wan_service,
@@ -0,0 +1,132 @@
+//! Non-blocking glue between the egui thread and the async backend.
+//!
+//! Nothing in here may block the UI thread: commands are queued to a
+//! dedicated forwarding thread and native file dialogs run on their own
+//! thread, with the result polled on later frames.
+
+use eframe::egui;
+use p2p_core::AppCommand;
+use std::path::PathBuf;
+use std::sync::mpsc as std_mpsc;
+use tokio::sync::mpsc;
+
+/// Command sender that never blocks the caller.
+///
+/// Commands go into an unbounded queue drained by a forwarding thread, which
+/// waits on the bounded backend channel instead of the UI. Order is preserved.
+#[derive(Clone)]
+pub struct CommandBridge {
+    queue: std_mpsc::Sender<AppCommand>,
+}
+
+impl CommandBridge {
+    pub fn new(cmd_tx: mpsc::Sender<AppCommand>) -> Self {
+        let (queue, pending) = std_mpsc::channel::<AppCommand>();
+
+        std::thread::Builder::new()
+            .name("gui-command-bridge".to_string())
+            .spawn(move || {
+                while let Ok(cmd) = pending.recv() {
+                    if cmd_tx.blocking_send(cmd).is_err() {
+                        tracing::error!("Backend command channel closed");
+                        break;
+                    }
+                }
+            })
+            .expect("Failed to spawn command bridge thread");
+
+        Self { queue }
+    }
+
+    /// Queue a command for the backend
+    pub fn send(&self, cmd: AppCommand) {
+        if self.queue.send(cmd).is_err() {
+            tracing::error!("Command dropped: backend bridge is not running");
+        }
+    }
+}
+
+/// Outcome of a [`FileDialogTask`] as seen from one frame
+pub enum DialogResult {
+    /// The dialog is still open
+    Pending,
+    /// The user picked one or more files
+    Picked(Vec<PathBuf>),
+    /// The dialog was closed without a selection
+    Cancelled,
+}
+
+/// Native file dialog running off the UI thread
+pub struct FileDialogTask {
+    rx: std_mpsc::Receiver<Option<Vec<PathBuf>>>,
+}
+
+impl FileDialogTask {
+    /// Open the dialog; the UI is repainted as soon as it closes
+    pub fn pick_files(ctx: &egui::Context) -> Self {
+        let (tx, rx) = std_mpsc::channel();
+        let ctx = ctx.clone();
+
+        std::thread::spawn(move || {
+            let _ = tx.send(rfd::FileDialog::new().pick_files());
+            ctx.request_repaint();
+        });
+
+        Self { rx }
+    }
+
+    /// Open a dialog for a single image, e.g. a QR code screenshot or photo
+    pub fn pick_image(ctx: &egui::Context) -> Self {
+        let (tx, rx) = std_mpsc::channel();
+        let ctx = ctx.clone();
+
+        std::thread::spawn(move || {
+            let picked = rfd::FileDialog::new()
+                .add_filter("Image", &["png", "jpg", "jpeg"])
+                .pick_file();
+            let _ = tx.send(picked.map(|path| vec![path]));
+            ctx.request_repaint();
+        });
+
+        Self { rx }
+    }
+
+    /// Open a dialog for font files
+    pub fn pick_font(ctx: &egui::Context) -> Self {
+        let (tx, rx) = std_mpsc::channel();
+        let ctx = ctx.clone();
+
+        std::thread::spawn(move || {
+            let picked = rfd::FileDialog::new()
+                .add_filter("Font", &["ttf", "otf", "ttc"])
+                .pick_files();
+            let _ = tx.send(picked);
+            ctx.request_repaint();
+        });
+
+        Self { rx }
+    }
+
+    /// Open a "save as" dialog; a chosen path is reported as a single pick
+    pub fn save_file(ctx: &egui::Context, default_name: &str) -> Self {
+        let (tx, rx) = std_mpsc::channel();
+        let ctx = ctx.clone();
+        let dialog = rfd::FileDialog::new().set_file_name(default_name);
+
+        std::thread::spawn(move || {
+            let _ = tx.send(dialog.save_file().map(|path| vec![path]));
+            ctx.request_repaint();
+        });
+
+        Self { rx }
+    }
+
+    /// Check whether the dialog has closed
+    pub fn poll(&self) -> DialogResult {
+        match self.rx.try_recv() {
+            Ok(Some(files)) if !files.is_empty() => DialogResult::Picked(files),
+            Ok(_) | Err(std_mpsc::TryRecvError::Disconnected) => DialogResult::Cancelled,
+            Err(std_mpsc::TryRecvError::Empty) => DialogResult::Pending,
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Command-line arguments of the GUI.
+//!
+//! `p2p_gui [--send] FILE... [--to PEER]` opens the app with the files
+//! queued for sending, or hands them to the app that is already running
+//! (see [`crate::instance`]); files dropped on the executable arrive the
+//! same way. `--register-share-target` adds the app to the file
+//! manager's menus, which then runs `--send` on the selection.
+//! `p2p_gui p2p://...` opens a link (see [`crate::url_scheme`]), after
+//! `--register-url-scheme` the way clicked links arrive.
+//! `send --stdin` and `receive --stdout` stream through a pipe without
+//! the window (see [`crate::stdio`]).
+
+use anyhow::{Result, anyhow};
+use p2p_core::link::{AppLink, LINK_SCHEME};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "\
+Usage: p2p_gui [[--send] FILE... [--to PEER]]
+       p2p_gui send --stdin --name NAME [--note NOTE] PEER
+       p2p_gui receive --stdout [--port PORT]
+       p2p_gui p2p://connect/ENDPOINT_ID | p2p://share/TOKEN?at=ORIGIN
+       p2p_gui --register-share-target | --unregister-share-target
+       p2p_gui --register-url-scheme | --unregister-url-scheme
+
+PEER is a device name, IP address or endpoint ID; without --to the app asks
+which device to send to. An app that is already running takes the files.
+A FILE that is a named pipe or a device, such as a camera, is streamed
+until it ends.
+send --stdin streams standard input to a paired device as the file NAME,
+without a temporary file; receive --stdout writes the next file received
+to standard output and fails if it does not match the sender's hash.
+A p2p:// link opens the WAN window for the device, or the share page; an
+app that is already running opens it.";
+
+/// Files to send, from the command line or a later instance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendRequest {
+    /// Absolute paths, so they survive the hand-off to another process
+    pub files: Vec<PathBuf>,
+    /// Device name, IP address or endpoint ID; `None` to ask the user
+    pub to: Option<String>,
+}
+
+/// Standard input to send as one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamSend {
+    /// File name the receiver saves the stream as
+    pub name: String,
+    /// Device name, IP address or endpoint ID
+    pub to: String,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Start the app, with files to send if given
+    Run(Option<SendRequest>),
+    /// Start the app and open a `p2p://` link
+    Open(AppLink),
+    /// Send standard input without starting the app
+    SendStdin(StreamSend),
+    /// Receive one file to standard output, on `port` if given
+    ReceiveStdout {
+        port: Option<u16>,
+    },
+    RegisterShareTarget,
+    UnregisterShareTarget,
+    RegisterUrlScheme,
+    UnregisterUrlScheme,
+    Help,
+}
+
+/// Parse the arguments after the program name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(String::as_str) {
+        Some("send") => return parse_send_stdin(args.skip(1)),
+        Some("receive") => return parse_receive_stdout(args.skip(1)),
+        Some(link) if is_link(link) => {
+            let link = AppLink::parse(link)?;
+            if args.nth(1).is_some() {
+                return Err(anyhow!("A link is opened on its own"));
+            }
+            return Ok(Command::Open(link));
+        }
+        _ => {}
+    }
+    let mut sending = false;
+    let mut files = Vec::new();
+    let mut to = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--send" => sending = true,
+            "--to" => {
+                let peer = args.next().filter(|peer| !peer.trim().is_empty());
+                to = Some(peer.ok_or_else(|| anyhow!("--to needs a device"))?);
+            }
+            "--register-share-target" => return Ok(Command::RegisterShareTarget),
+            "--unregister-share-target" => return Ok(Command::UnregisterShareTarget),
+            "--register-url-scheme" => return Ok(Command::RegisterUrlScheme),
+            "--unregister-url-scheme" => return Ok(Command::UnregisterUrlScheme),
+            "-h" | "--help" => return Ok(Command::Help),
+            file if !file.starts_with('-') => files.push(send_path(file)?),
+            other => return Err(anyhow!("Unknown argument {}", other)),
+        }
+    }
+
+    if files.is_empty() {
+        return match (sending, to) {
+            (false, None) => Ok(Command::Run(None)),
+            (true, _) => Err(anyhow!("--send needs at least one file")),
+            (false, Some(_)) => Err(anyhow!("--to needs files to send")),
+        };
+    }
+    Ok(Command::Run(Some(SendRequest { files, to })))
+}
+
+/// Whether `arg` is a `p2p://` link rather than a file
+fn is_link(arg: &str) -> bool {
+    arg.get(..LINK_SCHEME.len() + 3)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{}://", LINK_SCHEME)))
+}
+
+/// `send --stdin --name NAME [--note NOTE] PEER`
+fn parse_send_stdin(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut stdin = false;
+    let mut name = None;
+    let mut note = None;
+    let mut to = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stdin" => stdin = true,
+            "--name" => {
+                let value = args.next().filter(|name| !name.trim().is_empty());
+                name = Some(value.ok_or_else(|| anyhow!("--name needs a file name"))?);
+            }
+            "--note" => note = Some(args.next().ok_or_else(|| anyhow!("--note needs a text"))?),
+            peer if !peer.starts_with('-') && to.is_none() => to = Some(peer.to_string()),
+            other => return Err(anyhow!("Unknown argument {}", other)),
+        }
+    }
+    if !stdin {
+        return Err(anyhow!("send only reads --stdin; pass files with --send"));
+    }
+    Ok(Command::SendStdin(StreamSend {
+        name: name.ok_or_else(|| anyhow!("--stdin needs --name for the receiver"))?,
+        to: to.ok_or_else(|| anyhow!("send --stdin needs a device"))?,
+        note,
+    }))
+}
+
+/// `receive --stdout [--port PORT]`
+fn parse_receive_stdout(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut stdout = false;
+    let mut port = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stdout" => stdout = true,
+            "--port" => {
+                let value = args.next().and_then(|port| port.parse().ok());
+                port = Some(value.ok_or_else(|| anyhow!("--port needs a port number"))?);
+            }
+            other => return Err(anyhow!("Unknown argument {}", other)),
+        }
+    }
+    if !stdout {
+        return Err(anyhow!("receive only writes to --stdout"));
+    }
+    Ok(Command::ReceiveStdout { port })
+}
+
+fn send_path(arg: &str) -> Result<PathBuf> {
+    let path = std::path::absolute(Path::new(arg))?;
+    // Named pipes and devices are sent as streams
+    if !path.exists() || path.is_dir() {
+        return Err(anyhow!("Not a file: {}", arg));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_send() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        let (a_arg, b_arg) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        assert_eq!(parse_args(&[]).unwrap(), Command::Run(None));
+        assert_eq!(
+            parse_args(&["--send", a_arg, "--to", "Laptop", b_arg]).unwrap(),
+            Command::Run(Some(SendRequest {
+                files: vec![a.clone(), b],
+                to: Some("Laptop".to_string()),
+            }))
+        );
+        assert_eq!(
+            parse_args(&[a_arg]).unwrap(),
+            Command::Run(Some(SendRequest {
+                files: vec![a],
+                to: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_arguments() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.txt");
+        assert!(parse_args(&["--send"]).is_err());
+        assert!(parse_args(&["--send", missing.to_str().unwrap()]).is_err());
+        assert!(parse_args(&["--send", dir.path().to_str().unwrap()]).is_err());
+        assert!(parse_args(&["--to", "Laptop"]).is_err());
+        assert!(parse_args(&["--to"]).is_err());
+        assert!(parse_args(&["--bogus"]).is_err());
+        assert_eq!(parse_args(&["--help"]).unwrap(), Command::Help);
+    }
+
+    #[test]
+    fn test_parse_links() {
+        let id = iroh::SecretKey::generate(&mut rand::rng())
+            .public()
+            .to_string();
+        let link = format!("p2p://connect/{}", id);
+        assert_eq!(
+            parse_args(&[&link]).unwrap(),
+            Command::Open(AppLink::Connect { endpoint_id: id })
+        );
+        assert!(parse_args(&[&link, "--to", "Laptop"]).is_err());
+        assert!(parse_args(&["p2p://connect/nobody"]).is_err());
+        assert_eq!(
+            parse_args(&["--register-url-scheme"]).unwrap(),
+            Command::RegisterUrlScheme
+        );
+    }
+
+    #[test]
+    fn test_parse_streams() {
+        assert_eq!(
+            parse_args(&["send", "--stdin", "--name", "backup.tar", "Laptop"]).unwrap(),
+            Command::SendStdin(StreamSend {
+                name: "backup.tar".to_string(),
+                to: "Laptop".to_string(),
+                note: None,
+            })
+        );
+        assert_eq!(
+            parse_args(&["receive", "--stdout", "--port", "9100"]).unwrap(),
+            Command::ReceiveStdout { port: Some(9100) }
+        );
+        assert_eq!(
+            parse_args(&["receive", "--stdout"]).unwrap(),
+            Command::ReceiveStdout { port: None }
+        );
+        assert!(parse_args(&["send", "--name", "backup.tar", "Laptop"]).is_err());
+        assert!(parse_args(&["send", "--stdin", "Laptop"]).is_err());
+        assert!(parse_args(&["send", "--stdin", "--name", "a", "Laptop", "Desk"]).is_err());
+        assert!(parse_args(&["receive"]).is_err());
+        assert!(parse_args(&["receive", "--stdout", "--port", "x"]).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Crash reports and diagnostic bundles to attach to bug reports.
+//!
+//! Both are zip files in the `diagnostics` folder of the config directory,
+//! holding the app version and platform, the most recent backend events as
+//! JSON lines (with secrets redacted) and, for a crash, the panic message
+//! and backtrace.
+
+use p2p_core::AppEvent;
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+
+/// Events kept for the next report
+const MAX_RECENT_EVENTS: usize = 500;
+
+const DIAGNOSTICS_DIR: &str = "diagnostics";
+
+/// The last backend events, fed from the event bus and read by the panic hook
+pub static RECENT_EVENTS: RecentEvents = RecentEvents::new();
+
+pub struct RecentEvents(Mutex<VecDeque<String>>);
+
+impl RecentEvents {
+    const fn new() -> Self {
+        Self(Mutex::new(VecDeque::new()))
+    }
+
+    pub fn record(&self, event: &AppEvent) {
+        let Ok(line) = p2p_core::json_events::to_json_line(event) else {
+            return;
+        };
+        let mut events = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if events.len() == MAX_RECENT_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(line);
+    }
+
+    /// One JSON line per event, oldest first
+    fn to_jsonl(&self) -> String {
+        // A panic while the lock is held must not deadlock the report
+        let events = match self.0.try_lock() {
+            Ok(events) => events,
+            Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(std::sync::TryLockError::WouldBlock) => return String::new(),
+        };
+        events.iter().map(|line| format!("{}\n", line)).collect()
+    }
+}
+
+/// Folder the bundles are written to
+pub fn diagnostics_dir() -> Option<PathBuf> {
+    p2p_core::config::get_base_config_dir().map(|dir| dir.join(DIAGNOSTICS_DIR))
+}
+
+fn system_info() -> String {
+    format!(
+        "version: {}\nos: {}\narch: {}\ntime: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        now_secs(),
+    )
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Zip `files` (name, contents) into `dir/{prefix}-{time}.zip`
+fn write_bundle(dir: &Path, prefix: &str, files: &[(&str, String)]) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}-{}.zip", prefix, now_secs()));
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&path)?);
+    let options = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .unix_permissions(0o600);
+    for (name, contents) in files {
+        zip.start_file(*name, options)?;
+        zip.write_all(contents.as_bytes())?;
+    }
+    zip.finish()?.sync_all()?;
+    Ok(path)
+}
+
+/// Bundle for the "Create diagnostic bundle" button
+pub fn create_bundle(status_log: String) -> io::Result<PathBuf> {
+    let dir = diagnostics_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config directory"))?;
+    write_bundle(
+        &dir,
+        "diagnostic",
+        &[
+            ("system.txt", system_info()),
+            ("events.jsonl", RECENT_EVENTS.to_jsonl()),
+            ("status_log.txt", status_log),
+        ],
+    )
+}
+
+/// Write a crash bundle on every panic, then run the default hook
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        let panic = format!(
+            "thread '{}' panicked at {}:\n{}\n\n{}\n",
+            thread.name().unwrap_or("<unnamed>"),
+            info.location()
+                .map(|location| location.to_string())
+                .unwrap_or_default(),
+            info.payload_as_str().unwrap_or("Box<dyn Any>"),
+            Backtrace::force_capture(),
+        );
+        if let Some(dir) = diagnostics_dir() {
+            match write_bundle(
+                &dir,
+                "crash",
+                &[
+                    ("system.txt", system_info()),
+                    ("panic.txt", panic),
+                    ("events.jsonl", RECENT_EVENTS.to_jsonl()),
+                ],
+            ) {
+                Ok(path) => eprintln!("Crash report written to {}", path.display()),
+                Err(e) => eprintln!("Could not write a crash report: {}", e),
+            }
+        }
+        previous(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_recent_events_are_bounded() {
+        let events = RecentEvents::new();
+        for i in 0..MAX_RECENT_EVENTS + 10 {
+            events.record(&AppEvent::Status(format!("event {}", i)));
+        }
+        let jsonl = events.to_jsonl();
+        assert_eq!(jsonl.lines().count(), MAX_RECENT_EVENTS);
+        assert!(!jsonl.contains("\"event 9\""));
+        assert!(jsonl.lines().last().unwrap().contains("event 509"));
+    }
+
+    #[test]
+    fn test_bundle_contains_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_bundle(
+            dir.path(),
+            "diagnostic",
+            &[
+                ("system.txt", system_info()),
+                ("events.jsonl", "{}\n".to_string()),
+            ],
+        )
+        .unwrap();
+        assert!(
+            path.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with("diagnostic-")
+        );
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut system = String::new();
+        archive
+            .by_name("system.txt")
+            .unwrap()
+            .read_to_string(&mut system)
+            .unwrap();
+        assert!(system.contains(env!("CARGO_PKG_VERSION")));
+    }
+}
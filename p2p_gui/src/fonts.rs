@@ -0,0 +1,317 @@
+//! Fallback fonts for text egui's own fonts cannot draw.
+//!
+//! egui ships Latin, Greek and Cyrillic glyphs, so CJK peer and file names
+//! and parts of Vietnamese come out as boxes. At startup the fonts of the
+//! system are searched for one per [`Script`] and added after egui's own,
+//! in an order picked from the locale: Han characters look different in
+//! Japanese, Korean and Chinese fonts, so the user's language comes first.
+//! A script an earlier font already covers loads nothing more. Fonts the
+//! user added in the toolbar come before the system's.
+
+use ab_glyph::{Font, FontRef};
+use eframe::egui::{FontData, FontDefinitions, FontFamily};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// How deep font folders are searched
+const MAX_DEPTH: usize = 4;
+
+/// Text that needs a fallback font
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Script {
+    /// Stacked diacritics like ữ and ệ
+    Vietnamese,
+    Japanese,
+    Korean,
+    SimplifiedChinese,
+    TraditionalChinese,
+}
+
+impl Script {
+    /// A character only a font covering the script has
+    fn sample(self) -> char {
+        match self {
+            Self::Vietnamese => 'ữ',
+            Self::Japanese => 'あ',
+            Self::Korean => '한',
+            Self::SimplifiedChinese => '们',
+            Self::TraditionalChinese => '們',
+        }
+    }
+
+    /// Font files with the script, best first, and the face to use in a
+    /// collection
+    fn candidates(self) -> &'static [(&'static str, u32)] {
+        // Noto Sans CJK holds one face per language
+        match self {
+            Self::Vietnamese => &[
+                ("segoeui.ttf", 0),
+                ("Helvetica.ttc", 0),
+                ("NotoSans-Regular.ttf", 0),
+                ("DejaVuSans.ttf", 0),
+                ("Arial Unicode.ttf", 0),
+            ],
+            Self::Japanese => &[
+                ("YuGothM.ttc", 0),
+                ("meiryo.ttc", 0),
+                ("msgothic.ttc", 0),
+                ("ヒラギノ角ゴシック W3.ttc", 0),
+                ("NotoSansCJK-Regular.ttc", 0),
+                ("NotoSansCJKjp-Regular.otf", 0),
+            ],
+            Self::Korean => &[
+                ("malgun.ttf", 0),
+                ("AppleSDGothicNeo.ttc", 0),
+                ("NotoSansCJK-Regular.ttc", 1),
+                ("NotoSansCJKkr-Regular.otf", 0),
+            ],
+            Self::SimplifiedChinese => &[
+                ("msyh.ttc", 0),
+                ("PingFang.ttc", 0),
+                ("Hiragino Sans GB.ttc", 0),
+                ("NotoSansCJK-Regular.ttc", 2),
+                ("NotoSansCJKsc-Regular.otf", 0),
+                ("wqy-microhei.ttc", 0),
+                ("DroidSansFallbackFull.ttf", 0),
+            ],
+            Self::TraditionalChinese => &[
+                ("msjh.ttc", 0),
+                ("PingFang.ttc", 0),
+                ("NotoSansCJK-Regular.ttc", 3),
+                ("NotoSansCJKtc-Regular.otf", 0),
+                ("DroidSansFallbackFull.ttf", 0),
+            ],
+        }
+    }
+}
+
+/// Scripts in the order their fonts are tried for a POSIX locale name like
+/// `ja_JP.UTF-8`, or `None` if unknown
+pub fn script_order(locale: Option<&str>) -> [Script; 5] {
+    use Script::*;
+    let locale = locale.unwrap_or_default().to_ascii_lowercase();
+    let language = locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default();
+    // Vietnamese first: CJK fonts have Latin letters, but not all of these
+    let cjk = match language {
+        "ja" => [Japanese, SimplifiedChinese, TraditionalChinese, Korean],
+        "ko" => [Korean, TraditionalChinese, SimplifiedChinese, Japanese],
+        "zh" if ["_tw", "_hk", "_mo", "hant"]
+            .iter()
+            .any(|tag| locale.contains(tag)) =>
+        {
+            [TraditionalChinese, SimplifiedChinese, Japanese, Korean]
+        }
+        _ => [SimplifiedChinese, Japanese, Korean, TraditionalChinese],
+    };
+    [Vietnamese, cjk[0], cjk[1], cjk[2], cjk[3]]
+}
+
+/// The user's locale, as the C library reads it
+fn locale() -> Option<String> {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Folders the system keeps fonts in
+fn font_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(windows) {
+        let windir = std::env::var_os("WINDIR").unwrap_or_else(|| "C:\\Windows".into());
+        dirs.push(Path::new(&windir).join("Fonts"));
+    } else if cfg!(target_os = "macos") {
+        dirs.extend(
+            ["/System/Library/Fonts", "/Library/Fonts"]
+                .iter()
+                .map(PathBuf::from),
+        );
+    } else {
+        dirs.extend(
+            ["/usr/share/fonts", "/usr/local/share/fonts"]
+                .iter()
+                .map(PathBuf::from),
+        );
+        if let Some(home) = std::env::var_os("HOME") {
+            dirs.push(Path::new(&home).join(".local/share/fonts"));
+            dirs.push(Path::new(&home).join(".fonts"));
+        }
+    }
+    dirs
+}
+
+/// Every file below `dirs`, by file name
+fn index_fonts(dirs: &[PathBuf]) -> HashMap<String, PathBuf> {
+    let mut found = HashMap::new();
+    let mut pending: Vec<(PathBuf, usize)> = dirs.iter().map(|dir| (dir.clone(), 0)).collect();
+    while let Some((dir, depth)) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if depth < MAX_DEPTH {
+                    pending.push((path, depth + 1));
+                }
+            } else if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                found.entry(name.to_string()).or_insert(path);
+            }
+        }
+    }
+    found
+}
+
+/// Font files in use. egui wants `'static` data and keeps fonts for the
+/// life of the app, and they are set again when the user adds one, so each
+/// file used is read once and kept.
+static LOADED: OnceLock<Mutex<HashMap<PathBuf, &'static [u8]>>> = OnceLock::new();
+
+/// The contents of the font at `path`, kept or read now
+fn read_font(path: &Path) -> std::io::Result<Cow<'static, [u8]>> {
+    let loaded = LOADED.get_or_init(Default::default);
+    match loaded.lock().unwrap().get(path) {
+        Some(bytes) => Ok(Cow::Borrowed(*bytes)),
+        None => Ok(Cow::Owned(std::fs::read(path)?)),
+    }
+}
+
+/// Keep `bytes` read from `path` for the rest of the run
+fn keep(path: &Path, bytes: Cow<'static, [u8]>) -> &'static [u8] {
+    let loaded = LOADED.get_or_init(Default::default);
+    let mut loaded = loaded.lock().unwrap();
+    loaded
+        .entry(path.to_path_buf())
+        .or_insert_with(|| match bytes {
+            Cow::Borrowed(bytes) => bytes,
+            Cow::Owned(bytes) => Box::leak(bytes.into_boxed_slice()),
+        })
+}
+
+/// Whether face `index` of `bytes` parses and has a glyph for `sample`
+pub fn covers(bytes: &[u8], index: u32, sample: Option<char>) -> bool {
+    FontRef::try_from_slice_and_index(bytes, index)
+        .is_ok_and(|font| sample.is_none_or(|c| font.glyph_id(c).0 != 0))
+}
+
+/// Fonts added to egui's own
+struct Fallbacks {
+    fonts: FontDefinitions,
+    /// Faces added so far, to skip scripts they already cover
+    added: Vec<(&'static [u8], u32)>,
+}
+
+impl Fallbacks {
+    fn add(&mut self, name: String, path: &Path, bytes: Cow<'static, [u8]>, index: u32) {
+        let bytes = keep(path, bytes);
+        self.fonts.font_data.insert(
+            name.clone(),
+            Arc::new(FontData {
+                index,
+                ..FontData::from_static(bytes)
+            }),
+        );
+        for family in [FontFamily::Proportional, FontFamily::Monospace] {
+            self.fonts
+                .families
+                .entry(family)
+                .or_default()
+                .push(name.clone());
+        }
+        self.added.push((bytes, index));
+    }
+
+    fn covered(&self, sample: char) -> bool {
+        self.added
+            .iter()
+            .any(|(bytes, index)| covers(bytes, *index, Some(sample)))
+    }
+}
+
+/// egui's fonts with the icon font, `user_fonts` and the system fonts that
+/// cover the other scripts. Also returns why user fonts were skipped.
+pub fn definitions(user_fonts: &[PathBuf]) -> (FontDefinitions, Vec<String>) {
+    let mut fonts = FontDefinitions::default();
+    egui_phosphor::add_to_fonts(&mut fonts, egui_phosphor::Variant::Regular);
+    let mut fallbacks = Fallbacks {
+        fonts,
+        added: Vec::new(),
+    };
+
+    let mut problems = Vec::new();
+    for path in user_fonts {
+        match read_font(path) {
+            Ok(bytes) if covers(&bytes, 0, None) => {
+                fallbacks.add(format!("user:{}", path.display()), path, bytes, 0);
+            }
+            Ok(_) => problems.push(format!("{} is not a font egui can read", path.display())),
+            Err(e) => problems.push(format!("Could not read {}: {}", path.display(), e)),
+        }
+    }
+
+    let system = index_fonts(&font_dirs());
+    for script in script_order(locale().as_deref()) {
+        if fallbacks.covered(script.sample()) {
+            continue;
+        }
+        let found = script.candidates().iter().find_map(|(file, index)| {
+            let path = system.get(*file)?;
+            let bytes = read_font(path).ok()?;
+            covers(&bytes, *index, Some(script.sample())).then_some((path, bytes, *index))
+        });
+        match found {
+            Some((path, bytes, index)) => {
+                tracing::debug!("{:?} text falls back to {}", script, path.display());
+                fallbacks.add(
+                    format!("system:{}:{}", path.display(), index),
+                    path,
+                    bytes,
+                    index,
+                );
+            }
+            None => tracing::debug!("No font found for {:?} text", script),
+        }
+    }
+    (fallbacks.fonts, problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Script::*;
+
+    #[test]
+    fn test_user_language_comes_first() {
+        assert_eq!(
+            script_order(Some("ja_JP.UTF-8")),
+            [
+                Vietnamese,
+                Japanese,
+                SimplifiedChinese,
+                TraditionalChinese,
+                Korean
+            ]
+        );
+        assert_eq!(script_order(Some("ko_KR"))[1], Korean);
+        assert_eq!(script_order(Some("zh_TW.UTF-8"))[1], TraditionalChinese);
+        assert_eq!(script_order(Some("zh_CN.UTF-8"))[1], SimplifiedChinese);
+        assert_eq!(script_order(Some("vi_VN"))[0], Vietnamese);
+        assert_eq!(script_order(None), script_order(Some("C")));
+    }
+
+    #[test]
+    fn test_unreadable_user_font_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.ttf");
+        std::fs::write(&path, b"not a font").unwrap();
+        assert!(!covers(b"not a font", 0, None));
+        let (fonts, problems) = definitions(&[path, dir.path().join("missing.ttf")]);
+        assert_eq!(problems.len(), 2);
+        assert!(!fonts.font_data.keys().any(|name| name.starts_with("user:")));
+    }
+}
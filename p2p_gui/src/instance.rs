@@ -0,0 +1,318 @@
+//! One app at a time.
+//!
+//! The first app to start holds an exclusive lock on `instance.lock` next to
+//! the profiles until it exits, so a second one never gets as far as binding
+//! the discovery, transfer and HTTP ports. Instead it hands its files or
+//! `p2p://` link (or, without either, just a request to show the window)
+//! to the first one and exits. The running app listens for that on a
+//! loopback port recorded, with a random token, in `instance.json`; only
+//! processes that can read the file know the token, so other users of the
+//! machine cannot queue sends.
+
+use crate::cli::SendRequest;
+use anyhow::{Context, Result, anyhow};
+use eframe::egui;
+use p2p_core::link::AppLink;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, TryLockError};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, mpsc};
+use std::time::{Duration, Instant};
+
+const LOCK_FILE: &str = "instance.lock";
+const INSTANCE_FILE: &str = "instance.json";
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a second instance waits for a first one that is still starting
+const HAND_OFF_WAIT: Duration = Duration::from_secs(15);
+const HAND_OFF_RETRY: Duration = Duration::from_millis(250);
+/// Longest hand-off accepted, a few thousand paths
+const MAX_HAND_OFF_LEN: u64 = 1024 * 1024;
+const ACCEPTED: &str = "ok";
+
+/// Where the running app can be reached
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InstanceInfo {
+    port: u16,
+    token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HandOff {
+    token: String,
+    /// `None` only brings the window to the front
+    request: Option<SendRequest>,
+    /// `p2p://` link to open
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
+}
+
+/// Held by the running app; the OS drops it when the process exits
+pub struct InstanceLock {
+    _file: Option<File>,
+}
+
+pub enum Claim {
+    /// No other app runs; keep the lock until exit
+    First(InstanceLock),
+    /// The running app took over
+    HandedOff,
+}
+
+fn config_dir() -> Result<PathBuf> {
+    p2p_core::config::get_base_config_dir().ok_or_else(|| anyhow!("No config directory"))
+}
+
+/// Become the running app, or pass `request` and `link` to the one that
+/// already is. Fails when another app holds the lock but does not answer.
+pub fn claim(request: Option<&SendRequest>, link: Option<&AppLink>) -> Result<Claim> {
+    claim_in(&config_dir()?, &hand_off(request, link))
+}
+
+/// What a later start passes on, without the token
+fn hand_off(request: Option<&SendRequest>, link: Option<&AppLink>) -> HandOff {
+    HandOff {
+        token: String::new(),
+        request: request.cloned(),
+        link: link.map(AppLink::to_uri),
+    }
+}
+
+fn claim_in(dir: &Path, hand_off: &HandOff) -> Result<Claim> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(LOCK_FILE);
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Could not open {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Claim::First(InstanceLock { _file: Some(file) })),
+        Err(TryLockError::Error(e)) => {
+            // Rather two apps than none, e.g. on a file system without locks
+            tracing::warn!("Could not lock {}: {}", path.display(), e);
+            Ok(Claim::First(InstanceLock { _file: None }))
+        }
+        Err(TryLockError::WouldBlock) => {
+            let deadline = Instant::now() + HAND_OFF_WAIT;
+            loop {
+                match forward(dir, hand_off) {
+                    Ok(()) => return Ok(Claim::HandedOff),
+                    Err(e) if Instant::now() >= deadline => {
+                        return Err(e.context("The app is already running but did not answer"));
+                    }
+                    Err(_) => std::thread::sleep(HAND_OFF_RETRY),
+                }
+            }
+        }
+    }
+}
+
+fn forward(dir: &Path, hand_off: &HandOff) -> Result<()> {
+    let info: InstanceInfo = serde_json::from_slice(&std::fs::read(dir.join(INSTANCE_FILE))?)?;
+    send_hand_off(&info, hand_off)
+}
+
+fn send_hand_off(info: &InstanceInfo, hand_off: &HandOff) -> Result<()> {
+    let stream = TcpStream::connect_timeout(&(Ipv4Addr::LOCALHOST, info.port).into(), IO_TIMEOUT)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut message = serde_json::to_vec(&HandOff {
+        token: info.token.clone(),
+        request: hand_off.request.clone(),
+        link: hand_off.link.clone(),
+    })?;
+    message.push(b'\n');
+    (&stream).write_all(&message)?;
+
+    let mut answer = String::new();
+    BufReader::new(&stream).read_line(&mut answer)?;
+    if answer.trim() != ACCEPTED {
+        return Err(anyhow!("The running app did not take the request"));
+    }
+    Ok(())
+}
+
+/// Requests from later instances, taken from the start so none waits for
+/// the window to open
+pub struct HandOffs {
+    requests: mpsc::Receiver<SendRequest>,
+    links: mpsc::Receiver<AppLink>,
+    ctx: Arc<OnceLock<egui::Context>>,
+}
+
+impl HandOffs {
+    /// Let hand-offs raise and repaint the window from now on; returns the
+    /// files to send and the links to open
+    pub fn attach(
+        self,
+        ctx: &egui::Context,
+    ) -> (mpsc::Receiver<SendRequest>, mpsc::Receiver<AppLink>) {
+        let _ = self.ctx.set(ctx.clone());
+        (self.requests, self.links)
+    }
+}
+
+/// Take requests from later instances for as long as the app runs
+pub fn listen() -> Result<HandOffs> {
+    listen_in(&config_dir()?)
+}
+
+fn listen_in(dir: &Path) -> Result<HandOffs> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let info = InstanceInfo {
+        port: listener.local_addr()?.port(),
+        token: format!("{:032x}", rand::random::<u128>()),
+    };
+    let path = dir.join(INSTANCE_FILE);
+    write_private(&path, &serde_json::to_vec(&info)?)
+        .with_context(|| format!("Could not write {}", path.display()))?;
+
+    let (tx, rx) = mpsc::channel();
+    let (link_tx, link_rx) = mpsc::channel();
+    let ctx = Arc::new(OnceLock::new());
+    let serve_ctx = ctx.clone();
+    std::thread::spawn(move || serve(listener, &info.token, &tx, &link_tx, &serve_ctx));
+    Ok(HandOffs {
+        requests: rx,
+        links: link_rx,
+        ctx,
+    })
+}
+
+fn serve(
+    listener: TcpListener,
+    token: &str,
+    tx: &mpsc::Sender<SendRequest>,
+    link_tx: &mpsc::Sender<AppLink>,
+    ctx: &OnceLock<egui::Context>,
+) {
+    for stream in listener.incoming().flatten() {
+        let hand_off = match receive(stream, token) {
+            Ok(hand_off) => hand_off,
+            Err(e) => {
+                tracing::warn!("Ignored a request from another instance: {}", e);
+                continue;
+            }
+        };
+        if let Some(request) = hand_off.request
+            && tx.send(request).is_err()
+        {
+            return;
+        }
+        let link = hand_off.link.as_deref().map(AppLink::parse).transpose();
+        let link = link.unwrap_or_else(|e| {
+            tracing::warn!("Ignored a link from another instance: {}", e);
+            None
+        });
+        if let Some(link) = link
+            && link_tx.send(link).is_err()
+        {
+            return;
+        }
+        if let Some(ctx) = ctx.get() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            ctx.request_repaint();
+        }
+    }
+}
+
+fn receive(stream: TcpStream, token: &str) -> Result<HandOff> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new((&stream).take(MAX_HAND_OFF_LEN)).read_line(&mut line)?;
+    let hand_off: HandOff = serde_json::from_str(&line)?;
+    if hand_off.token != token {
+        return Err(anyhow!("wrong token"));
+    }
+    (&stream).write_all(format!("{}\n", ACCEPTED).as_bytes())?;
+    Ok(hand_off)
+}
+
+/// Readable by this user only where the platform allows
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> SendRequest {
+        SendRequest {
+            files: vec![PathBuf::from("/tmp/a.txt")],
+            to: Some("Laptop".to_string()),
+        }
+    }
+
+    fn link() -> AppLink {
+        AppLink::Connect {
+            endpoint_id: iroh::SecretKey::generate(&mut rand::rng())
+                .public()
+                .to_string(),
+        }
+    }
+
+    #[test]
+    fn test_hand_off_needs_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let hand_offs = listen_in(dir.path()).unwrap();
+        let info: InstanceInfo =
+            serde_json::from_slice(&std::fs::read(dir.path().join(INSTANCE_FILE)).unwrap())
+                .unwrap();
+
+        let wrong = InstanceInfo {
+            token: "guess".to_string(),
+            ..info.clone()
+        };
+        let files = hand_off(Some(&request()), None);
+        assert!(send_hand_off(&wrong, &files).is_err());
+        send_hand_off(&info, &hand_off(None, None)).unwrap();
+        send_hand_off(&info, &files).unwrap();
+        let (requests, links) = hand_offs.attach(&egui::Context::default());
+        assert_eq!(requests.recv().unwrap(), request());
+        assert!(requests.try_recv().is_err());
+        assert!(links.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_second_instance_hands_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let Claim::First(_lock) = claim_in(dir.path(), &hand_off(None, None)).unwrap() else {
+            panic!("first claim must win");
+        };
+        let hand_offs = listen_in(dir.path()).unwrap();
+
+        assert!(matches!(
+            claim_in(dir.path(), &hand_off(Some(&request()), None)).unwrap(),
+            Claim::HandedOff
+        ));
+        assert_eq!(hand_offs.requests.recv().unwrap(), request());
+
+        let link = link();
+        claim_in(dir.path(), &hand_off(None, Some(&link))).unwrap();
+        assert_eq!(hand_offs.links.recv().unwrap(), link);
+        assert!(hand_offs.requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_lock_released_with_first_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = claim_in(dir.path(), &hand_off(None, None)).unwrap();
+        assert!(matches!(first, Claim::First(_)));
+        drop(first);
+        assert!(matches!(
+            claim_in(dir.path(), &hand_off(None, None)).unwrap(),
+            Claim::First(_)
+        ));
+    }
+}
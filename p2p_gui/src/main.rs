@@ -1,22 +1,159 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 use eframe::egui;
-use p2p_core::{AppCommand, AppEvent, run_backend};
+use p2p_core::json_events::EventOutput;
+use p2p_core::{AppCommand, AppEvent, EventBus, EventCategory, NodeConfig};
 use std::thread;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 mod app;
+mod bridge;
+mod cli;
+mod diagnostics;
+mod fonts;
+mod instance;
+mod os_auth;
+mod qr_scan;
+mod share_target;
+mod state;
+mod status_log;
+mod stdio;
+mod taskbar;
 mod ui;
+mod update;
+mod url_scheme;
+mod wan;
 
 use app::MyApp;
+use cli::Command;
+use instance::Claim;
+use ui::windows::send_picker::SendPickerState;
+use url_scheme::LinkInbox;
+
+/// Window title, also shown next to the progress where the title carries it
+pub const APP_TITLE: &str = "LAN P2P Transfer";
 
 fn main() -> Result<(), eframe::Error> {
     // 0. Initialize logging
+    use tracing_subscriber::prelude::*;
     use tracing_subscriber::{EnvFilter, fmt};
     let filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new("info"))
         .add_directive("netlink_packet_route=error".parse().unwrap());
 
-    fmt::Subscriber::builder().with_env_filter(filter).init();
+    // Keep standard output to the JSON event lines, or to the received
+    // stream, when they go there
+    let command = cli::parse(std::env::args().skip(1));
+    let events_on_stdout = matches!(
+        EventOutput::from_env().or(p2p_core::config::AppConfig::load().event_output),
+        Some(EventOutput::Stdout)
+    );
+    let headless = matches!(
+        command,
+        Ok(Command::SendStdin(_) | Command::ReceiveStdout { .. })
+    );
+    let log = if events_on_stdout || headless {
+        fmt::layer().with_writer(std::io::stderr).boxed()
+    } else {
+        fmt::layer().boxed()
+    };
+    // The filter is the log's alone, so the flame graph gets its trace spans
+    let subscriber = tracing_subscriber::registry().with(log.with_filter(filter));
+    #[cfg(feature = "profiling")]
+    let (subscriber, _flame_guard) = {
+        let (layer, guard) = p2p_core::profiling::flame_layer().unzip();
+        (subscriber.with(layer), guard)
+    };
+    subscriber.init();
+
+    // 0.1. A panic leaves a crash report with the latest events behind
+    diagnostics::install_panic_hook();
+
+    // 0.2. Command line: maybe just register with the file manager, stream
+    // through a pipe, or hand the files to the app that is already running
+    let (send_request, link) = match command {
+        Ok(Command::Run(request)) => (request, None),
+        Ok(Command::Open(link)) => (None, Some(link)),
+        Ok(Command::SendStdin(request)) => {
+            if let Err(e) = stdio::send_stdin(&request) {
+                eprintln!("Could not send: {:#}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Ok(Command::ReceiveStdout { port }) => {
+            if let Err(e) = stdio::receive_stdout(port) {
+                eprintln!("Could not receive: {:#}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Ok(Command::RegisterShareTarget) => {
+            match share_target::register() {
+                Ok(done) => println!("{}", done),
+                Err(e) => {
+                    eprintln!("Could not register: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        Ok(Command::UnregisterShareTarget) => {
+            if let Err(e) = share_target::unregister() {
+                eprintln!("Could not unregister: {:#}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Ok(Command::RegisterUrlScheme) => {
+            match url_scheme::register() {
+                Ok(done) => println!("{}", done),
+                Err(e) => {
+                    eprintln!("Could not register: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        Ok(Command::UnregisterUrlScheme) => {
+            if let Err(e) = url_scheme::unregister() {
+                eprintln!("Could not unregister: {:#}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(dir) = p2p_core::config::portable_dir() {
+        tracing::info!("Portable mode: settings and downloads in {}", dir.display());
+    }
+
+    // 0.3. One app at a time, before any port is bound: a second start
+    // hands its files or link to the first one and exits
+    let _instance_lock = match instance::claim(send_request.as_ref(), link.as_ref()) {
+        Ok(Claim::First(lock)) => lock,
+        Ok(Claim::HandedOff) => {
+            tracing::info!("Handed over to the app that is already running");
+            return Ok(());
+        }
+        Err(e) => {
+            tracing::error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let hand_offs = instance::listen()
+        .inspect_err(|e| tracing::warn!("Later starts cannot hand over files: {}", e))
+        .ok();
+
+    // 0.5. Show sizes and speeds in the profile's units from the first frame
+    p2p_core::units::set_unit_preference(p2p_core::config::AppConfig::load().units);
 
     // 1. Create channels (bounded with capacity 1000 for backpressure)
     let (tx_cmd, rx_cmd) = mpsc::channel::<AppCommand>(1000);
@@ -32,37 +169,23 @@ fn main() -> Result<(), eframe::Error> {
             .build()
             .unwrap();
 
-        let config_dir =
-            p2p_core::config::get_config_dir().unwrap_or(std::path::PathBuf::from("."));
-        let download_dir = p2p_core::config::get_download_dir();
-        let identity_manager = p2p_core::identity::IdentityManager::new(config_dir);
-
-        let wan_service = wan_runtime.block_on(async {
-            let secret_key = identity_manager
-                .load_or_generate()
-                .await
-                .expect("Failed to load identity");
-            p2p_wan::ConnectionListener::new(secret_key, download_dir, wan_event_tx)
-                .await
-                .expect("Failed to create WAN listener")
-        });
-        let wan_service = std::sync::Arc::new(wan_service);
-
-        // Spawn listener loop
-        let ws_clone = wan_service.clone();
-        wan_runtime.spawn(async move {
-            if let Err(e) = ws_clone.listen().await {
-                tracing::error!("WAN Listener error: {}", e);
-            }
-        });
-
+        let wan_service = wan_runtime
+            .block_on(wan::start(wan::WanProfile::active(), wan_event_tx))
+            .expect("Failed to create WAN listener");
         (wan_runtime, wan_service)
     })
     .join()
     .unwrap();
 
-    // 2. Spawn Backend thread
+    // 2. Spawn Backend thread; its heartbeat also covers the WAN listener,
+    // which is replaced on a profile switch
+    let (wan_endpoints, wan_endpoint) = watch::channel(wan_service.endpoint().clone());
     let backend_tx_event = tx_event.clone();
+    let backend_config = NodeConfig {
+        wan_endpoint: Some(wan_endpoint),
+        sample_cpu: true,
+        ..NodeConfig::default()
+    };
     thread::spawn(move || {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -70,34 +193,78 @@ fn main() -> Result<(), eframe::Error> {
             .unwrap();
 
         rt.block_on(async move {
-            run_backend(rx_cmd, backend_tx_event).await;
+            #[cfg(feature = "simulation")]
+            {
+                let _ = backend_config;
+                p2p_core::simulation::run_simulated_backend(rx_cmd, backend_tx_event).await;
+            }
+            #[cfg(not(feature = "simulation"))]
+            p2p_core::run_profile_backend(backend_config, rx_cmd, backend_tx_event).await;
         });
     });
 
+    // 2.5. Fan backend events out to independent subscribers, all of them
+    // subscribed before forwarding starts so no startup event is lost
+    let gui_events = {
+        let _guard = wan_runtime.enter();
+        let bus = EventBus::default();
+        let gui_events = bus.subscribe_all();
+
+        // Mirror errors into the tracing log regardless of what the GUI shows
+        let mut error_log = bus.subscribe(&[EventCategory::Status]);
+        tokio::spawn(async move {
+            while let Some(event) = error_log.recv().await {
+                if let AppEvent::Error(msg) = event {
+                    tracing::error!("{}", msg);
+                }
+            }
+        });
+
+        // Keep the latest events for crash reports and diagnostic bundles
+        let mut recorder = bus.subscribe_all();
+        tokio::spawn(async move {
+            while let Some(event) = recorder.recv().await {
+                diagnostics::RECENT_EVENTS.record(&event);
+            }
+        });
+
+        bus.forward(rx_event);
+        gui_events
+    };
+
     // 3. Configure window options
+    // Window geometry and egui's window layout are saved next to the
+    // profiles, so they survive restarts and profile switches
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([800.0, 600.0]),
+        persist_window: true,
+        persistence_path: p2p_core::config::get_gui_state_path(),
         ..Default::default()
     };
 
     // 4. Run App
     let wan_rt_handle = wan_runtime.handle().clone();
     eframe::run_native(
-        "LAN P2P Transfer",
+        APP_TITLE,
         options,
         Box::new(move |cc| {
-            // Initialize phosphor icons font
-            let mut fonts = egui::FontDefinitions::default();
-            egui_phosphor::add_to_fonts(&mut fonts, egui_phosphor::Variant::Regular);
-            cc.egui_ctx.set_fonts(fonts);
-
-            Ok(Box::new(MyApp::new(
+            let (requests, links) = hand_offs
+                .map(|hand_offs| hand_offs.attach(&cc.egui_ctx))
+                .unzip();
+            let mut app = MyApp::new(
+                cc.storage,
                 tx_cmd,
-                rx_event,
+                gui_events,
                 tx_event,
                 wan_service,
+                wan_endpoints,
                 wan_rt_handle,
-            )))
+                SendPickerState::new(send_request, requests),
+                LinkInbox::new(link, links),
+            );
+            // Icons, the user's fonts and the system's for CJK and Vietnamese
+            app.apply_fonts(&cc.egui_ctx);
+            Ok(Box::new(app))
         }),
     )
 }
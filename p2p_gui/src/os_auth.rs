@@ -0,0 +1,179 @@
+//! Ask the operating system to confirm the user before sensitive actions.
+//!
+//! With "Confirm sensitive actions" on, forgetting a paired device and
+//! exporting the history (which carries peer IDs and hashes) wait for
+//! Windows Hello or a polkit prompt. The prompt runs on its own thread and
+//! is polled like a file dialog, so the UI keeps drawing meanwhile.
+
+use crate::bridge::CommandBridge;
+use anyhow::{Result, anyhow};
+use eframe::egui;
+use p2p_core::AppCommand;
+use std::sync::mpsc as std_mpsc;
+
+/// Whether this platform can prompt at all
+pub const SUPPORTED: bool = cfg!(any(windows, target_os = "linux"));
+
+/// polkit action checked on Linux: the one `pkexec` uses, which asks for
+/// an administrator password
+#[cfg(target_os = "linux")]
+const POLKIT_ACTION: &str = "org.freedesktop.policykit.exec";
+
+/// Ask the system to confirm the user, blocking until answered. `Ok(false)`
+/// is a refusal or a dismissed prompt; `Err` means no prompt could be shown.
+#[cfg(windows)]
+fn authenticate(reason: &str) -> Result<bool> {
+    use windows::Security::Credentials::UI::{
+        UserConsentVerificationResult, UserConsentVerifier, UserConsentVerifierAvailability,
+    };
+    use windows::core::HSTRING;
+
+    let availability = UserConsentVerifier::CheckAvailabilityAsync()?.get()?;
+    if availability != UserConsentVerifierAvailability::Available {
+        return Err(anyhow!("Windows Hello is not set up"));
+    }
+    let result = UserConsentVerifier::RequestVerificationAsync(&HSTRING::from(reason))?.get()?;
+    Ok(result == UserConsentVerificationResult::Verified)
+}
+
+#[cfg(target_os = "linux")]
+fn authenticate(reason: &str) -> Result<bool> {
+    use anyhow::Context;
+    use std::process::{Command, Stdio};
+
+    // The agent shows the action's own message, not ours
+    tracing::debug!("Asking polkit to confirm: {}", reason);
+    let status = Command::new("pkcheck")
+        .args(["--action-id", POLKIT_ACTION, "--allow-user-interaction"])
+        .arg("--process")
+        .arg(std::process::id().to_string())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .context("pkcheck is not installed")?;
+    match status.code() {
+        Some(0) => Ok(true),
+        // Not authorized, or the prompt was dismissed
+        Some(1 | 2) => Ok(false),
+        _ => Err(anyhow!(
+            "polkit could not ask; is an authentication agent running?"
+        )),
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn authenticate(_reason: &str) -> Result<bool> {
+    Err(anyhow!("System authentication is not supported here"))
+}
+
+/// A system prompt running off the UI thread
+struct AuthTask {
+    rx: std_mpsc::Receiver<Result<bool>>,
+}
+
+impl AuthTask {
+    fn start(ctx: &egui::Context, reason: &str) -> Self {
+        let (tx, rx) = std_mpsc::channel();
+        let ctx = ctx.clone();
+        let reason = reason.to_string();
+
+        std::thread::spawn(move || {
+            let _ = tx.send(authenticate(&reason));
+            ctx.request_repaint();
+        });
+
+        Self { rx }
+    }
+
+    /// The answer, once the prompt closed
+    fn poll(&self) -> Option<Result<bool>> {
+        match self.rx.try_recv() {
+            Ok(answer) => Some(answer),
+            Err(std_mpsc::TryRecvError::Empty) => None,
+            Err(std_mpsc::TryRecvError::Disconnected) => {
+                Some(Err(anyhow!("The prompt closed unexpectedly")))
+            }
+        }
+    }
+}
+
+/// What runs once the user is confirmed
+#[derive(Debug)]
+pub enum Sensitive {
+    Send(AppCommand),
+    /// Turn "Confirm sensitive actions" off
+    StopAsking,
+}
+
+/// Holds a sensitive action until the system confirmed the user
+#[derive(Default)]
+pub struct SensitiveGate {
+    /// Prompt before sensitive actions; mirrors the GUI setting
+    pub required: bool,
+    pending: Option<(Sensitive, AuthTask)>,
+}
+
+impl SensitiveGate {
+    /// Send `cmd` now, or once the user is confirmed when that is required
+    pub fn send(
+        &mut self,
+        ctx: &egui::Context,
+        cmd_tx: &CommandBridge,
+        reason: &str,
+        cmd: AppCommand,
+    ) {
+        if self.required {
+            self.ask(ctx, reason, Sensitive::Send(cmd));
+        } else {
+            cmd_tx.send(cmd);
+        }
+    }
+
+    /// Prompt for `action`; a prompt already open keeps its action
+    pub fn ask(&mut self, ctx: &egui::Context, reason: &str, action: Sensitive) {
+        if self.pending.is_none() {
+            self.pending = Some((action, AuthTask::start(ctx, reason)));
+        }
+    }
+
+    /// A prompt is open
+    pub fn busy(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// The action confirmed since the last call, or why it was not. Turning
+    /// the prompts off needs no confirmation where none can be shown.
+    pub fn poll(&mut self) -> Option<Result<Sensitive, String>> {
+        let answer = self.pending.as_ref()?.1.poll()?;
+        let (action, _) = self.pending.take()?;
+        Some(match (answer, action) {
+            (Ok(true), action) => Ok(action),
+            (Ok(false), _) => Err("Not confirmed; the action was cancelled".to_string()),
+            (Err(_), Sensitive::StopAsking) => Ok(Sensitive::StopAsking),
+            (Err(e), _) => Err(format!("Could not confirm: {}", e)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_commands_go_straight_through_when_not_required() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let cmd_tx = CommandBridge::new(tx);
+        let mut gate = SensitiveGate::default();
+
+        gate.send(
+            &egui::Context::default(),
+            &cmd_tx,
+            "Forget this device",
+            AppCommand::GetState,
+        );
+        assert!(!gate.busy());
+        assert!(gate.poll().is_none());
+        assert!(matches!(rx.blocking_recv(), Some(AppCommand::GetState)));
+    }
+}
@@ -0,0 +1,608 @@
+//! Minimal QR decoder for scanning pairing invites, share URLs and WAN
+//! endpoint IDs from image files.
+//!
+//! Aimed at clean codes such as the ones we render or screenshots of them:
+//! finder patterns are located on a thresholded image and the grid is
+//! sampled with an affine transform, so scaling and rotation are fine but
+//! perspective is not. Format, masking and block layout come from the
+//! `qrcode` crate's own tables. Blocks are checked against their error
+//! correction bytes but not repaired, so a damaged code is rejected rather
+//! than misread.
+
+use anyhow::{Context, Result, anyhow};
+use image::GrayImage;
+use p2p_core::pairing::invite::INVITE_SCHEME;
+use qrcode::bits::Bits;
+use qrcode::canvas::{Canvas, MaskPattern, Module};
+use qrcode::ec::{construct_codewords, create_error_correction_code};
+use qrcode::types::{EcLevel, Mode, Version};
+use std::path::Path;
+
+const EC_LEVELS: [EcLevel; 4] = [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H];
+
+const MASKS: [MaskPattern; 8] = [
+    MaskPattern::Checkerboard,
+    MaskPattern::HorizontalLines,
+    MaskPattern::VerticalLines,
+    MaskPattern::DiagonalLines,
+    MaskPattern::LargeCheckerboard,
+    MaskPattern::Fields,
+    MaskPattern::Diamonds,
+    MaskPattern::Meadow,
+];
+
+const ALPHANUMERIC: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+/// What a scanned QR code is for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScannedCode {
+    /// `p2p-pair://` invite
+    Invite(String),
+    /// Link to another device's LAN or WAN share page
+    ShareUrl(String),
+    /// WAN endpoint ID, bare or taken from a link
+    EndpointId(String),
+}
+
+impl ScannedCode {
+    /// Recognise the text of a QR code
+    pub fn classify(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let scheme = |scheme: &str| {
+            text.get(..scheme.len() + 3)
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{}://", scheme)))
+        };
+        if scheme(INVITE_SCHEME) {
+            return Ok(Self::Invite(text.to_string()));
+        }
+        if let Some(detected) = p2p_wan::detect_endpoint_id(text) {
+            return Ok(Self::EndpointId(detected.endpoint_id.to_string()));
+        }
+        if scheme("http") || scheme("https") {
+            return Ok(Self::ShareUrl(text.to_string()));
+        }
+        Err(anyhow!(
+            "Not a pairing link, share URL or endpoint ID: {}",
+            text
+        ))
+    }
+}
+
+/// Decode and recognise the first QR code found in an image file
+pub fn scan_file(path: &Path) -> Result<ScannedCode> {
+    ScannedCode::classify(&decode_file(path)?)
+}
+
+/// Decode the first QR code found in an image file
+pub fn decode_file(path: &Path) -> Result<String> {
+    let img = image::open(path)
+        .with_context(|| format!("Cannot open {}", path.display()))?
+        .to_luma8();
+    decode(&img)
+}
+
+/// Decode the QR code in a grayscale image
+pub fn decode(img: &GrayImage) -> Result<String> {
+    let bitmap = Bitmap::threshold(img).ok_or_else(|| anyhow!("No QR code found"))?;
+    let [tl, tr, bl] = locate(&bitmap)?;
+
+    let module = (tl.module + tr.module + bl.module) / 3.0;
+    let side = (tl.distance(&tr) + tl.distance(&bl)) / 2.0 / module + 7.0;
+    let estimate = ((side - 17.0) / 4.0).round() as i16;
+
+    let mut best: Option<(usize, Grid, EcLevel, Canvas)> = None;
+    for v in (estimate - 1..=estimate + 1).filter(|v| (1..=40).contains(v)) {
+        let grid = Grid::sample(&bitmap, Version::Normal(v), tl, tr, bl);
+        if let Some((errors, ec_level, mask)) = grid.best_format()
+            && best.as_ref().is_none_or(|(e, ..)| errors < *e)
+        {
+            best = Some((errors, grid, ec_level, mask));
+        }
+    }
+
+    let (_, grid, ec_level, mask) = best.ok_or_else(|| anyhow!("No QR code found"))?;
+    let data = grid.data_codewords(ec_level, &mask)?;
+    parse_segments(&data, grid.version)
+}
+
+/// Thresholded image: `true` is a dark pixel
+struct Bitmap {
+    width: i32,
+    height: i32,
+    dark: Vec<bool>,
+}
+
+impl Bitmap {
+    fn threshold(img: &GrayImage) -> Option<Self> {
+        let (min, max) = img.pixels().fold((u8::MAX, u8::MIN), |(lo, hi), p| {
+            (lo.min(p.0[0]), hi.max(p.0[0]))
+        });
+        if max.saturating_sub(min) < 40 {
+            return None;
+        }
+        let mid = ((min as u16 + max as u16) / 2) as u8;
+        Some(Self {
+            width: img.width() as i32,
+            height: img.height() as i32,
+            dark: img.pixels().map(|p| p.0[0] < mid).collect(),
+        })
+    }
+
+    fn get(&self, x: i32, y: i32) -> Option<bool> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.dark[(y * self.width + x) as usize])
+    }
+}
+
+/// Centre and module size of a finder pattern
+#[derive(Debug, Clone, Copy)]
+struct Finder {
+    x: f32,
+    y: f32,
+    module: f32,
+    hits: u32,
+}
+
+impl Finder {
+    fn distance(&self, other: &Finder) -> f32 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+}
+
+/// Find the three finder patterns, ordered top-left, top-right, bottom-left
+fn locate(bitmap: &Bitmap) -> Result<[Finder; 3]> {
+    let mut finders: Vec<Finder> = Vec::new();
+
+    for y in 0..bitmap.height {
+        let mut x = 0;
+        while x < bitmap.width {
+            // Only test from the first pixel of each dark run
+            if bitmap.get(x, y) != Some(true) || bitmap.get(x - 1, y) == Some(true) {
+                x += 1;
+                continue;
+            }
+            let mut end = x;
+            while bitmap.get(end, y) == Some(true) {
+                end += 1;
+            }
+            if let Some(found) = check_finder(bitmap, end, y) {
+                match finders.iter_mut().find(|f| {
+                    (f.x - found.x).abs() < f.module * 2.0 && (f.y - found.y).abs() < f.module * 2.0
+                }) {
+                    Some(f) => {
+                        let n = f.hits as f32;
+                        f.x = (f.x * n + found.x) / (n + 1.0);
+                        f.y = (f.y * n + found.y) / (n + 1.0);
+                        f.module = (f.module * n + found.module) / (n + 1.0);
+                        f.hits += 1;
+                    }
+                    None => finders.push(found),
+                }
+            }
+            x = end;
+        }
+    }
+
+    finders.sort_by_key(|f| std::cmp::Reverse(f.hits));
+    if finders.len() < 3 {
+        return Err(anyhow!("No QR code found"));
+    }
+    let three = [finders[0], finders[1], finders[2]];
+
+    // The top-left corner is where the other two meet at a right angle
+    let corner = (0..3)
+        .min_by(|&a, &b| corner_cos(&three, a).total_cmp(&corner_cos(&three, b)))
+        .unwrap_or(0);
+    let tl = three[corner];
+    let mut a = three[(corner + 1) % 3];
+    let mut b = three[(corner + 2) % 3];
+    // Image y grows downwards: a positive cross product puts `a` on the right
+    if (a.x - tl.x) * (b.y - tl.y) - (a.y - tl.y) * (b.x - tl.x) < 0.0 {
+        std::mem::swap(&mut a, &mut b);
+    }
+    Ok([tl, a, b])
+}
+
+fn corner_cos(points: &[Finder; 3], corner: usize) -> f32 {
+    let c = points[corner];
+    let a = points[(corner + 1) % 3];
+    let b = points[(corner + 2) % 3];
+    let (ax, ay, bx, by) = (a.x - c.x, a.y - c.y, b.x - c.x, b.y - c.y);
+    ((ax * bx + ay * by) / (ax.hypot(ay) * bx.hypot(by))).abs()
+}
+
+/// Check for a 1:1:3:1:1 pattern whose first dark run ends at `x`
+fn check_finder(bitmap: &Bitmap, x: i32, y: i32) -> Option<Finder> {
+    // Skip the light run to land inside the centre square
+    let mut cx = x;
+    while bitmap.get(cx, y) == Some(false) {
+        cx += 1;
+    }
+    let (offset_h, module_h) = check_line(bitmap, cx, y, (1, 0))?;
+    let cx = (cx as f32 + offset_h).round() as i32;
+    let (offset_v, module_v) = check_line(bitmap, cx, y, (0, 1))?;
+    let cy = (y as f32 + offset_v).round() as i32;
+    // Re-measure horizontally through the true centre
+    let (offset_h, module_h2) = check_line(bitmap, cx, cy, (1, 0))?;
+
+    let module = (module_h + module_v + module_h2) / 3.0;
+    if (module_h - module_v).abs() > module * 0.5 {
+        return None;
+    }
+    Some(Finder {
+        x: cx as f32 + offset_h,
+        y: cy as f32,
+        module,
+        hits: 1,
+    })
+}
+
+/// Measure the 1:1:3:1:1 runs through dark pixel `(x, y)` along `step`.
+/// Returns the centre's offset from `(x, y)` and the module size.
+fn check_line(bitmap: &Bitmap, x: i32, y: i32, step: (i32, i32)) -> Option<(f32, f32)> {
+    if bitmap.get(x, y) != Some(true) {
+        return None;
+    }
+
+    // [centre dark, light, outer dark] walking away from (x, y)
+    let walk = |sign: i32| -> Option<[u32; 3]> {
+        let mut counts = [0u32; 3];
+        let mut state = 0;
+        let mut i = 1;
+        while let Some(dark) = bitmap.get(x + sign * step.0 * i, y + sign * step.1 * i) {
+            if dark != (state != 1) {
+                state += 1;
+                if state == 3 {
+                    break;
+                }
+            }
+            counts[state] += 1;
+            i += 1;
+        }
+        (counts[1] > 0 && counts[2] > 0).then_some(counts)
+    };
+    let back = walk(-1)?;
+    let forward = walk(1)?;
+
+    let runs = [
+        back[2],
+        back[1],
+        back[0] + 1 + forward[0],
+        forward[1],
+        forward[2],
+    ];
+    let total: u32 = runs.iter().sum();
+    let module = total as f32 / 7.0;
+    let tolerance = module / 2.0;
+    let expected = [1.0, 1.0, 3.0, 1.0, 1.0];
+    let fits = runs
+        .iter()
+        .zip(expected)
+        .all(|(&run, modules)| (run as f32 - module * modules).abs() < tolerance * modules);
+
+    fits.then(|| ((forward[0] as f32 - back[0] as f32) / 2.0, module))
+}
+
+/// Modules sampled from the image, `true` for dark
+struct Grid {
+    version: Version,
+    width: i16,
+    modules: Vec<bool>,
+    /// Function patterns of this version; data modules are left empty
+    layout: Canvas,
+}
+
+impl Grid {
+    fn sample(bitmap: &Bitmap, version: Version, tl: Finder, tr: Finder, bl: Finder) -> Self {
+        let width = version.width();
+        // Finder centres sit 3 modules in from the edges
+        let span = (width - 7) as f32;
+        let (rx, ry) = ((tr.x - tl.x) / span, (tr.y - tl.y) / span);
+        let (dx, dy) = ((bl.x - tl.x) / span, (bl.y - tl.y) / span);
+
+        let mut modules = Vec::with_capacity((width as usize).pow(2));
+        for my in 0..width {
+            for mx in 0..width {
+                let (fx, fy) = ((mx - 3) as f32, (my - 3) as f32);
+                let px = tl.x + fx * rx + fy * dx;
+                let py = tl.y + fx * ry + fy * dy;
+                modules.push(
+                    bitmap
+                        .get(px.round() as i32, py.round() as i32)
+                        .unwrap_or(false),
+                );
+            }
+        }
+        // The EC level only affects the format bits, which are not data
+        let mut layout = Canvas::new(version, EcLevel::L);
+        layout.draw_all_functional_patterns();
+        Self {
+            version,
+            width,
+            modules,
+            layout,
+        }
+    }
+
+    fn get(&self, x: i16, y: i16) -> bool {
+        self.modules[(y as usize) * (self.width as usize) + x as usize]
+    }
+
+    fn is_data(&self, x: i16, y: i16) -> bool {
+        self.layout.get(x, y) == Module::Empty
+    }
+
+    /// Compare the function patterns against every EC level / mask pair.
+    /// Returns the mismatch count, level, and a blank canvas holding the
+    /// mask bits of the best match.
+    fn best_format(&self) -> Option<(usize, EcLevel, Canvas)> {
+        let mut best: Option<(usize, EcLevel, Canvas)> = None;
+        let mut functional = 0;
+
+        for ec_level in EC_LEVELS {
+            for mask in MASKS {
+                let mut canvas = Canvas::new(self.version, ec_level);
+                canvas.draw_all_functional_patterns();
+                canvas.apply_mask(mask);
+
+                let mut errors = 0;
+                functional = 0;
+                for y in 0..self.width {
+                    for x in 0..self.width {
+                        if !self.is_data(x, y) {
+                            functional += 1;
+                            if canvas.get(x, y).is_dark() != self.get(x, y) {
+                                errors += 1;
+                            }
+                        }
+                    }
+                }
+                if best.as_ref().is_none_or(|(e, _, _)| errors < *e) {
+                    best = Some((errors, ec_level, canvas));
+                }
+            }
+        }
+
+        // Format bits aside, the patterns must match almost exactly
+        best.filter(|(errors, _, _)| *errors <= functional / 20)
+    }
+
+    /// Read, unmask and de-interleave the data codewords
+    fn data_codewords(&self, ec_level: EcLevel, mask: &Canvas) -> Result<Vec<u8>> {
+        let mut bits = Vec::new();
+        let mut x = self.width - 1;
+        let mut upward = true;
+        while x > 0 {
+            // Column 6 holds the vertical timing pattern
+            if x == 6 {
+                x -= 1;
+            }
+            for i in 0..self.width {
+                let y = if upward { self.width - 1 - i } else { i };
+                for col in [x, x - 1] {
+                    if self.is_data(col, y) {
+                        bits.push(self.get(col, y) != mask.get(col, y).is_dark());
+                    }
+                }
+            }
+            upward = !upward;
+            x -= 2;
+        }
+        let codewords: Vec<u8> = bits
+            .chunks_exact(8)
+            .map(|byte| byte.iter().fold(0u8, |acc, &bit| acc << 1 | bit as u8))
+            .collect();
+
+        let data_len = Bits::new(self.version)
+            .max_len(ec_level)
+            .map_err(|e| anyhow!("Unsupported QR version: {:?}", e))?
+            / 8;
+
+        // Feed index bytes through the encoder to learn the interleaving
+        let low: Vec<u8> = (0..data_len).map(|i| i as u8).collect();
+        let high: Vec<u8> = (0..data_len).map(|i| (i >> 8) as u8).collect();
+        let (low_order, ec_bytes) = construct_codewords(&low, self.version, ec_level)
+            .map_err(|e| anyhow!("Unsupported QR version: {:?}", e))?;
+        let (high_order, _) = construct_codewords(&high, self.version, ec_level)
+            .map_err(|e| anyhow!("Unsupported QR version: {:?}", e))?;
+        let order: Vec<usize> = low_order
+            .iter()
+            .zip(&high_order)
+            .map(|(&lo, &hi)| lo as usize | (hi as usize) << 8)
+            .collect();
+
+        if codewords.len() < data_len + ec_bytes.len() {
+            return Err(anyhow!("QR code is truncated"));
+        }
+
+        let mut data = vec![0u8; data_len];
+        for (position, &index) in order.iter().enumerate() {
+            data[index] = codewords[position];
+        }
+
+        // The first round of the interleaving holds each block's first byte
+        let blocks = order.iter().position(|&i| i == 1).unwrap_or(1);
+        let ec_per_block = ec_bytes.len() / blocks;
+        let mut starts: Vec<usize> = order[..blocks].to_vec();
+        starts.sort_unstable();
+        starts.push(data_len);
+
+        let ec_read = &codewords[data_len..data_len + ec_bytes.len()];
+        for block in 0..blocks {
+            let expected =
+                create_error_correction_code(&data[starts[block]..starts[block + 1]], ec_per_block);
+            let read = (0..ec_per_block).map(|i| ec_read[i * blocks + block]);
+            if !expected.iter().copied().eq(read) {
+                return Err(anyhow!("QR code is damaged or unreadable"));
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Decode numeric, alphanumeric and byte segments
+fn parse_segments(data: &[u8], version: Version) -> Result<String> {
+    let mut reader = BitReader { data, pos: 0 };
+    let mut out = Vec::new();
+    let truncated = || anyhow!("QR data is truncated");
+
+    while reader.remaining() >= 4 {
+        match reader.read(4).ok_or_else(truncated)? {
+            0b0000 => break,
+            0b0100 => {
+                let count = reader
+                    .read(Mode::Byte.length_bits_count(version))
+                    .ok_or_else(truncated)?;
+                for _ in 0..count {
+                    out.push(reader.read(8).ok_or_else(truncated)? as u8);
+                }
+            }
+            0b0010 => {
+                let mut count = reader
+                    .read(Mode::Alphanumeric.length_bits_count(version))
+                    .ok_or_else(truncated)?;
+                while count > 0 {
+                    let (bits, chars) = if count >= 2 { (11, 2) } else { (6, 1) };
+                    let value = reader.read(bits).ok_or_else(truncated)? as usize;
+                    if chars == 2 {
+                        out.push(alphanumeric(value / 45)?);
+                    }
+                    out.push(alphanumeric(value % 45)?);
+                    count -= chars;
+                }
+            }
+            0b0001 => {
+                let mut count = reader
+                    .read(Mode::Numeric.length_bits_count(version))
+                    .ok_or_else(truncated)?;
+                while count > 0 {
+                    let digits = count.min(3);
+                    let value = reader
+                        .read([4, 7, 10][digits as usize - 1])
+                        .ok_or_else(truncated)?;
+                    out.extend(format!("{:0width$}", value, width = digits as usize).bytes());
+                    count -= digits;
+                }
+            }
+            // ECI designator (assignment numbers below 128): the text is UTF-8 anyway
+            0b0111 => {
+                reader.read(8).ok_or_else(truncated)?;
+            }
+            mode => return Err(anyhow!("Unsupported QR data mode {:#06b}", mode)),
+        }
+    }
+
+    String::from_utf8(out).map_err(|_| anyhow!("QR code does not contain text"))
+}
+
+fn alphanumeric(value: usize) -> Result<u8> {
+    ALPHANUMERIC
+        .get(value)
+        .copied()
+        .ok_or_else(|| anyhow!("Invalid alphanumeric QR data"))
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    fn read(&mut self, bits: usize) -> Option<u32> {
+        if bits > self.remaining() {
+            return None;
+        }
+        let mut value = 0u32;
+        for _ in 0..bits {
+            let bit = self.data[self.pos / 8] >> (7 - self.pos % 8) & 1;
+            value = value << 1 | bit as u32;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qrcode::QrCode;
+
+    fn render(code: &QrCode) -> GrayImage {
+        code.render::<image::Luma<u8>>()
+            .min_dimensions(200, 200)
+            .build()
+    }
+
+    #[test]
+    fn test_decodes_rendered_invites() {
+        let short = "p2p-pair://192.168.1.20:9000?id=abc&name=PC&secret=0123456789abcdef";
+        let long = format!(
+            "p2p-pair://10.0.0.7:9000?id={}&name=Living+room&secret={}",
+            "f".repeat(64),
+            "0".repeat(32)
+        );
+        for text in [short, long.as_str(), "HELLO WORLD 12345", "1234567890"] {
+            for ec_level in EC_LEVELS {
+                let code = QrCode::with_error_correction_level(text, ec_level).unwrap();
+                assert_eq!(decode(&render(&code)).unwrap(), text, "{:?}", ec_level);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decodes_rotated_and_large_codes() {
+        let text = "x".repeat(600);
+        let code = QrCode::new(&text).unwrap();
+        assert!(matches!(code.version(), Version::Normal(v) if v >= 7));
+
+        let img = render(&code);
+        assert_eq!(decode(&img).unwrap(), text);
+        assert_eq!(decode(&image::imageops::rotate90(&img)).unwrap(), text);
+        assert_eq!(decode(&image::imageops::rotate180(&img)).unwrap(), text);
+    }
+
+    #[test]
+    fn test_classifies_scanned_text() {
+        let id = iroh::SecretKey::generate(&mut rand::rng())
+            .public()
+            .to_string();
+        let invite = "p2p-pair://192.168.1.20:9000?id=abc&name=PC&secret=0123456789abcdef";
+        let cases = [
+            (invite.to_string(), ScannedCode::Invite(invite.to_string())),
+            (format!(" {}\n", id), ScannedCode::EndpointId(id.clone())),
+            (
+                format!("https://example.com/connect/{}", id),
+                ScannedCode::EndpointId(id.clone()),
+            ),
+            (
+                "HTTP://192.168.1.20:8080/".to_string(),
+                ScannedCode::ShareUrl("HTTP://192.168.1.20:8080/".to_string()),
+            ),
+        ];
+        for (text, expected) in cases {
+            let code = QrCode::new(&text).unwrap();
+            let scanned = ScannedCode::classify(&decode(&render(&code)).unwrap()).unwrap();
+            assert_eq!(scanned, expected);
+        }
+        assert!(ScannedCode::classify("HELLO WORLD").is_err());
+        assert!(ScannedCode::classify("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn test_rejects_images_without_code() {
+        let blank = GrayImage::from_pixel(300, 300, image::Luma([255]));
+        assert!(decode(&blank).is_err());
+
+        let mut noise = GrayImage::new(300, 300);
+        for (x, y, p) in noise.enumerate_pixels_mut() {
+            *p = image::Luma([if (x / 7 + y / 5) % 3 == 0 { 0 } else { 255 }]);
+        }
+        assert!(decode(&noise).is_err());
+    }
+}
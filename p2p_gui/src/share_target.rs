@@ -0,0 +1,170 @@
+//! "Send with P2P Transfer" in the file manager.
+//!
+//! On Windows this is a context-menu entry for every file, under
+//! `HKCU\Software\Classes\*\shell`; on Linux a `.desktop` file that lists
+//! the app under "Open With". Both run `p2p_gui --send` on the selection.
+//! Nothing needs administrator rights.
+
+use anyhow::{Context, Result, anyhow};
+use std::path::Path;
+
+/// Menu entry shown by the file manager
+const LABEL: &str = "Send with P2P Transfer";
+
+/// Add the entry for the running executable; returns where it went
+pub fn register() -> Result<String> {
+    let exe = std::env::current_exe().context("Could not find the executable")?;
+    platform::register(&exe)
+}
+
+/// Remove the entry again
+pub fn unregister() -> Result<()> {
+    platform::unregister()
+}
+
+// Also used to register the `p2p://` scheme (see [`crate::url_scheme`])
+#[cfg(windows)]
+pub(crate) use platform::reg;
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) use platform::{applications_dir, exec_quote};
+
+#[cfg(windows)]
+mod platform {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    const KEY: &str = r"HKCU\Software\Classes\*\shell\P2PTransferSend";
+
+    pub(crate) fn reg(args: &[&str]) -> Result<()> {
+        let status = Command::new("reg")
+            .args(args)
+            .stdout(Stdio::null())
+            .status()
+            .context("Could not run reg.exe")?;
+        if !status.success() {
+            return Err(anyhow!("reg {} {} failed", args[0], args[1]));
+        }
+        Ok(())
+    }
+
+    pub fn register(exe: &Path) -> Result<String> {
+        let exe = exe.display().to_string();
+        reg(&["add", KEY, "/ve", "/d", LABEL, "/f"])?;
+        reg(&["add", KEY, "/v", "Icon", "/d", &exe, "/f"])?;
+        // One process per selected file; the first running app takes them all
+        let command = format!("\"{}\" --send \"%1\"", exe);
+        reg(&[
+            "add",
+            &format!(r"{}\command", KEY),
+            "/ve",
+            "/d",
+            &command,
+            "/f",
+        ])?;
+        Ok(format!("Added \"{}\" to the Explorer context menu", LABEL))
+    }
+
+    pub fn unregister() -> Result<()> {
+        reg(&["delete", KEY, "/f"])
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use super::*;
+    use std::path::PathBuf;
+
+    const DESKTOP_FILE: &str = "p2p-transfer-send.desktop";
+
+    /// Where the user's `.desktop` files go
+    pub(crate) fn applications_dir() -> Result<PathBuf> {
+        let data_dir = std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+            .ok_or_else(|| anyhow!("Neither XDG_DATA_HOME nor HOME is set"))?;
+        Ok(data_dir.join("applications"))
+    }
+
+    fn desktop_file() -> Result<PathBuf> {
+        Ok(applications_dir()?.join(DESKTOP_FILE))
+    }
+
+    pub fn register(exe: &Path) -> Result<String> {
+        let path = desktop_file()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, desktop_entry(exe))
+            .with_context(|| format!("Could not write {}", path.display()))?;
+        Ok(format!("Wrote {}", path.display()))
+    }
+
+    pub fn unregister() -> Result<()> {
+        match std::fs::remove_file(desktop_file()?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hidden from the launcher, offered for any file
+    pub(super) fn desktop_entry(exe: &Path) -> String {
+        format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name={}\n\
+             Exec={} --send %F\n\
+             MimeType=application/octet-stream;all/all;\n\
+             NoDisplay=true\n\
+             Terminal=false\n",
+            LABEL,
+            exec_quote(&exe.to_string_lossy())
+        )
+    }
+
+    /// Quote an `Exec` argument as the Desktop Entry spec asks
+    pub(crate) fn exec_quote(arg: &str) -> String {
+        let mut quoted = String::from("\"");
+        for c in arg.chars() {
+            match c {
+                '"' | '`' | '$' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                '%' => quoted.push_str("%%"),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+}
+
+// macOS takes services from the app bundle's Info.plist, not from a binary
+#[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+mod platform {
+    use super::*;
+
+    pub fn register(_exe: &Path) -> Result<String> {
+        Err(anyhow!(
+            "Not supported on this platform; run `p2p_gui --send FILE...` instead"
+        ))
+    }
+
+    pub fn unregister() -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_desktop_entry_quotes_exec() {
+        let entry = platform::desktop_entry(Path::new("/opt/P2P Transfer/100%/p2p_gui"));
+        assert!(entry.contains("Exec=\"/opt/P2P Transfer/100%%/p2p_gui\" --send %F\n"));
+        let entry = platform::desktop_entry(Path::new("/home/a$b/p2p_gui"));
+        assert!(entry.contains("Exec=\"/home/a\\$b/p2p_gui\" --send %F\n"));
+    }
+}
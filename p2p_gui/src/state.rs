@@ -0,0 +1,2066 @@
+//! GUI state driven by backend events.
+//!
+//! [`AppState::apply`] folds one [`AppEvent`] into the state without
+//! touching egui, the file system or the network; what has to happen
+//! outside comes back as [`Effect`]s, which [`MyApp`](crate::app::MyApp)
+//! carries out. The reducer can thus be tested event by event.
+
+use crate::app::AppUIState;
+use crate::status_log::StatusLog;
+use crate::ui::health::{BackendHealth, HealthState};
+use crate::ui::windows::devices::{DevicesState, GroupProgress, PeerEntry};
+use crate::ui::windows::duplicates::PendingDuplicate;
+use crate::ui::windows::history::HistoryWindow;
+use crate::ui::windows::metered::HeldSend;
+use crate::ui::windows::moves::PendingMove;
+use crate::ui::windows::qr_code::{
+    FileLinkTabState, PairTabState, QrCodeCache, ShareTab, SharedTextState,
+};
+use crate::ui::windows::resend::ResendOffer;
+use crate::ui::windows::telemetry::TelemetryPreview;
+use crate::ui::windows::upload_confirm::{self, UploadConfirmState};
+use crate::ui::windows::verify::VerificationState;
+use crate::ui::windows::wan_connect::WanConnectState;
+use crate::ui::windows::wan_offer::{PendingWanOffer, WanOfferState};
+use p2p_core::journal::{JournaledFile, PendingSend};
+use p2p_core::link::AppLink;
+use p2p_core::metrics::SystemUsage;
+use p2p_core::network_info::MeteredMode;
+use p2p_core::peers::PeerRegistry;
+use p2p_core::schedule::ScheduledSend;
+use p2p_core::swarm::availability::SwarmAvailability;
+use p2p_core::transfer::SecurityInfo;
+use p2p_core::transfer::orphans::OrphanedPart;
+use p2p_core::units::UnitPreference;
+use p2p_core::{AppCommand, AppEvent, EventCategory, LogLevel};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Fallback timeout for peers that never send `PeerLost` (older versions);
+/// they answer every broadcast, so allow a few missed rounds
+const PEER_TIMEOUT_SECS: u64 = 3 * p2p_core::discovery::DISCOVERY_INTERVAL_SECS;
+
+/// Share URL shown while the HTTP server is off
+const SERVER_NOT_STARTED: &str = "Server not started";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerificationStatus {
+    /// Percent hashed
+    Verifying(f32),
+    Verified,
+    Failed,
+}
+
+pub struct TransferState {
+    pub file_name: String,
+    pub progress: f32,
+    /// Bytes through, for a stream of unknown size
+    pub bytes: Option<u64>,
+    pub speed: String,
+    pub is_sending: bool,
+    pub verification_status: Option<VerificationStatus>,
+    pub security: Option<SecurityInfo>,
+    /// All bytes arrived; shown until the background check finishes
+    pub done: bool,
+    /// The computer slept; cleared when data flows again
+    pub interrupted: bool,
+}
+
+/// Work an event asks for beyond the state itself
+#[derive(Debug)]
+pub enum Effect {
+    /// Send a command to the backend
+    Send(AppCommand),
+    /// List the download folder again
+    RefreshLocalFiles,
+    /// Watch a new WAN connection's path and latency
+    MonitorWan(iroh::endpoint::Connection),
+    /// Format sizes and speeds in these units from now on
+    UseUnits(UnitPreference),
+    /// Carry a broken LAN send on over the WAN
+    ResumeOverWan(BrokenSend),
+    /// Start the WAN listener again for the active profile
+    RestartWan,
+}
+
+/// What is left of a LAN send that lost its connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenSend {
+    pub session_id: String,
+    pub endpoint_id: String,
+    pub target_peer_name: String,
+    pub files: Vec<JournaledFile>,
+}
+
+/// Everything the windows show that backend events change
+pub struct AppState {
+    pub ui_state: AppUIState,
+    pub devices_state: DevicesState,
+    pub verification_state: VerificationState,
+    pub upload_confirm_state: UploadConfirmState,
+    pub wan_offers: WanOfferState,
+    pub duplicates: Vec<PendingDuplicate>,
+    /// Moved sources waiting out their undo window
+    pub pending_moves: Vec<PendingMove>,
+    /// Large sends waiting because the connection is metered
+    pub held_sends: Vec<HeldSend>,
+    /// Sends the receiver could not verify, offered again
+    pub resend_offers: Vec<ResendOffer>,
+    /// Partial downloads from the last orphan scan, until resolved
+    pub orphaned_partials: Vec<OrphanedPart>,
+    /// Who has which pieces of the running swarms
+    pub swarm_availability: Vec<SwarmAvailability>,
+    /// Metered mode the backend last reported
+    pub metered_mode: MeteredMode,
+    /// Usage report on screen
+    pub telemetry_preview: Option<TelemetryPreview>,
+
+    pub status_log: StatusLog,
+    // Key: IP address (unique identifier for now)
+    pub peers: HashMap<String, PeerEntry>,
+    /// Devices by endpoint ID, on the LAN or known over the WAN
+    pub registry: PeerRegistry,
+    pub scheduled_sends: Vec<ScheduledSend>,
+    /// Sends interrupted last time, offered for resuming
+    pub pending_sends: Vec<PendingSend>,
+    pub show_pending_sends: bool,
+
+    pub download_path: PathBuf,
+    pub history_window: HistoryWindow,
+    pub active_transfers: HashMap<String, TransferState>,
+    /// Security details that arrived before the transfer's first progress
+    pub pending_security: HashMap<String, SecurityInfo>,
+    pub health: HealthState,
+
+    /// Last [`AppEvent::MetricsSample`]: upload and download rates, CPU
+    /// and memory
+    pub metrics: (f64, f64, Option<SystemUsage>),
+
+    // QR Code & HTTP Share
+    pub qrcode_cache: QrCodeCache,
+    pub share_tab: ShareTab,
+    pub pair_state: PairTabState,
+    pub shared_text: SharedTextState,
+    pub file_links: FileLinkTabState,
+    pub share_url: String,
+    pub http_server_running: bool,
+    pub http_server_pending: bool,
+
+    // WAN Share (bore tunnel)
+    pub wan_share_url: Option<String>,
+    pub wan_share_running: bool,
+    pub wan_share_pending: bool,
+
+    // WAN Connect
+    pub wan_connect_state: WanConnectState,
+}
+
+impl AppState {
+    pub fn new(ui_state: AppUIState, wan_connect_state: WanConnectState) -> Self {
+        Self {
+            ui_state,
+            devices_state: DevicesState::default(),
+            verification_state: VerificationState::default(),
+            upload_confirm_state: UploadConfirmState::default(),
+            wan_offers: WanOfferState::default(),
+            duplicates: Vec::new(),
+            pending_moves: Vec::new(),
+            held_sends: Vec::new(),
+            resend_offers: Vec::new(),
+            orphaned_partials: Vec::new(),
+            swarm_availability: Vec::new(),
+            metered_mode: MeteredMode::default(),
+            telemetry_preview: None,
+            status_log: StatusLog::default(),
+            peers: HashMap::new(),
+            registry: PeerRegistry::default(),
+            scheduled_sends: Vec::new(),
+            pending_sends: Vec::new(),
+            show_pending_sends: false,
+            download_path: p2p_core::config::get_download_dir(),
+            history_window: HistoryWindow::default(),
+            active_transfers: HashMap::new(),
+            pending_security: HashMap::new(),
+            health: HealthState::default(),
+            metrics: (0.0, 0.0, None),
+            qrcode_cache: QrCodeCache::default(),
+            share_tab: ShareTab::default(),
+            pair_state: PairTabState::default(),
+            shared_text: SharedTextState::default(),
+            file_links: FileLinkTabState::default(),
+            share_url: SERVER_NOT_STARTED.to_string(),
+            http_server_running: false,
+            http_server_pending: false,
+            wan_share_url: None,
+            wan_share_running: false,
+            wan_share_pending: false,
+            wan_connect_state,
+        }
+    }
+
+    /// Fold `event` into the state; returns what the caller has to do
+    pub fn apply(&mut self, event: AppEvent) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        self.registry.apply(&event);
+        match event {
+            AppEvent::Status(msg) => {
+                // Unstructured status: guess a level from the wording
+                let level =
+                    if msg.contains("error") || msg.contains("Error") || msg.contains("ERROR") {
+                        LogLevel::Error
+                    } else if msg.contains("Complete")
+                        || msg.contains("success")
+                        || msg.contains("Verified")
+                    {
+                        LogLevel::Success
+                    } else if msg.contains("Connecting") || msg.contains("Starting") {
+                        LogLevel::Warning
+                    } else {
+                        LogLevel::Info
+                    };
+                self.status_log.push(level, EventCategory::Status, msg);
+            }
+            AppEvent::Log {
+                level,
+                category,
+                message,
+            } => {
+                self.status_log.push(level, category, message);
+            }
+            AppEvent::PeerFound {
+                endpoint_id,
+                ip,
+                port,
+                hostname,
+                display_name,
+                capabilities,
+                version,
+                rtt_ms,
+            } => {
+                // Update or insert peer (using IP as key)
+                self.peers.insert(
+                    ip.clone(),
+                    PeerEntry {
+                        endpoint_id,
+                        ip,
+                        port,
+                        hostname,
+                        display_name,
+                        receive_only: capabilities.receive_only,
+                        protocol: capabilities.protocol,
+                        version,
+                        rtt_ms,
+                        last_seen: Instant::now(),
+                    },
+                );
+            }
+            AppEvent::PeerLost { ip, .. } => {
+                self.peers.remove(&ip);
+            }
+            AppEvent::PeerListDelta(delta) => {
+                for event in delta.events() {
+                    effects.extend(self.apply(event));
+                }
+            }
+            AppEvent::ShowVerificationCode {
+                session_id,
+                code,
+                from_ip,
+                from_name,
+                note,
+            } => {
+                self.verification_state
+                    .show_code(session_id, code, from_ip, from_name, note);
+            }
+            AppEvent::RequestVerificationCode {
+                session_id,
+                target_ip,
+                target_name,
+            } => {
+                self.verification_state
+                    .request_code(session_id, target_ip, target_name);
+            }
+            AppEvent::PairingQueued {
+                peer_name, ahead, ..
+            } => {
+                let place = match ahead {
+                    0 => "you are next".to_string(),
+                    n => format!("{} ahead of you", n),
+                };
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Pairing,
+                    format!("Waiting for {} to show a code, {}", peer_name, place),
+                );
+            }
+            AppEvent::PairingResult {
+                session_id,
+                success,
+                peer_name,
+                message,
+            } => {
+                self.status_log.push(
+                    if success {
+                        LogLevel::Success
+                    } else {
+                        LogLevel::Error
+                    },
+                    EventCategory::Pairing,
+                    format!("Pairing with {}: {}", peer_name, message),
+                );
+
+                self.verification_state
+                    .pairing_result(&session_id, success, message);
+                effects.push(Effect::Send(AppCommand::ListKnownPeers));
+            }
+
+            AppEvent::LinkPhraseReady {
+                phrase,
+                expires_in_secs,
+            } => {
+                self.pair_state.show_phrase(phrase, expires_in_secs);
+            }
+
+            AppEvent::DeviceLinked {
+                endpoint_id,
+                peer_name,
+            } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Pairing,
+                    format!("Linked with {} ({})", peer_name, endpoint_id),
+                );
+                self.pair_state.linked(&peer_name);
+                effects.push(Effect::Send(AppCommand::ListKnownPeers));
+            }
+
+            AppEvent::LinkFailed { message } => {
+                self.status_log.push(
+                    LogLevel::Error,
+                    EventCategory::Pairing,
+                    format!("Link failed: {}", message),
+                );
+                self.pair_state.link_failed(message);
+            }
+
+            AppEvent::KnownPeersChanged {
+                paired,
+                guests,
+                guest_mode,
+                pinned,
+                groups,
+            } => {
+                self.devices_state
+                    .set_known_peers(paired, guests, pinned, groups);
+                self.devices_state.guest_mode = guest_mode;
+            }
+            AppEvent::GuestPairingEnded { peer_name, .. } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Pairing,
+                    format!("Guest pairing with {} ended after its transfer", peer_name),
+                );
+                effects.push(Effect::Send(AppCommand::ListKnownPeers));
+            }
+
+            AppEvent::PairingInviteCreated {
+                uri,
+                expires_in_secs,
+            } => {
+                self.pair_state.show_invite(uri, expires_in_secs);
+            }
+
+            AppEvent::FileLinkCreated {
+                link_id,
+                file_name,
+                url,
+                wan_url,
+                expires_in_secs,
+                single_use,
+            } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Http,
+                    format!("Download link for {}: {}", file_name, url),
+                );
+                self.file_links.add_link(
+                    link_id,
+                    file_name,
+                    url,
+                    wan_url,
+                    expires_in_secs,
+                    single_use,
+                );
+                self.ui_state.show_qrcode = true;
+                self.share_tab = ShareTab::File;
+            }
+
+            AppEvent::FileShareCreated {
+                share_id,
+                file_names,
+                url,
+                wan_url,
+                expires_in_secs,
+            } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Http,
+                    format!("Shared {} files: {}", file_names.len(), url),
+                );
+                self.file_links
+                    .add_share(share_id, file_names, url, wan_url, expires_in_secs);
+                self.ui_state.show_qrcode = true;
+                self.share_tab = ShareTab::File;
+            }
+
+            AppEvent::FileShareDownloaded {
+                share_id,
+                file_name,
+                downloads,
+                from_ip,
+            } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Http,
+                    format!("{} downloaded {} from a share", from_ip, file_name),
+                );
+                self.file_links
+                    .record_download(&share_id, &file_name, downloads);
+            }
+
+            AppEvent::VerificationCancelled { session_id, reason } => {
+                self.status_log.push(
+                    LogLevel::Error,
+                    EventCategory::Pairing,
+                    format!("Verification ended: {}", reason),
+                );
+                self.verification_state.cancelled(&session_id);
+            }
+
+            AppEvent::TransferProgress {
+                file_name,
+                progress,
+                speed,
+                is_sending,
+                bytes,
+                ..
+            } => {
+                self.active_transfers
+                    .entry(file_name.clone())
+                    .and_modify(|t| {
+                        t.progress = progress;
+                        t.bytes = bytes;
+                        t.speed = speed.clone();
+                        t.interrupted = false;
+                    })
+                    .or_insert(TransferState {
+                        file_name: file_name.clone(),
+                        progress,
+                        bytes,
+                        speed: speed.clone(),
+                        is_sending,
+                        verification_status: None,
+                        security: self.pending_security.remove(&file_name),
+                        done: false,
+                        interrupted: false,
+                    });
+            }
+            AppEvent::SecurityInfo {
+                file_name,
+                security,
+                ..
+            } => {
+                if let Some(transfer) = self.active_transfers.get_mut(&file_name) {
+                    transfer.security = Some(security);
+                } else {
+                    self.pending_security.insert(file_name, security);
+                }
+            }
+            AppEvent::TransferCompleted {
+                file_name,
+                saved_path,
+                peer,
+            } => {
+                let mut message = format!("Transfer Complete: {}", file_name);
+                if let Some(peer) = peer {
+                    message.push_str(&format!(" from {}", peer));
+                    self.devices_state.record_received(peer, file_name.clone());
+                }
+                if let Some(path) = saved_path {
+                    message.push_str(&format!(" ({})", path.display()));
+                }
+                self.status_log
+                    .push(LogLevel::Success, EventCategory::Transfer, message);
+                match self.active_transfers.get_mut(&file_name) {
+                    Some(transfer)
+                        if matches!(
+                            transfer.verification_status,
+                            Some(VerificationStatus::Verifying(_))
+                        ) =>
+                    {
+                        transfer.done = true;
+                    }
+                    _ => {
+                        self.active_transfers.remove(&file_name);
+                    }
+                }
+                self.pending_security.remove(&file_name);
+                effects.push(Effect::RefreshLocalFiles);
+            }
+            AppEvent::TransferCancelled {
+                file_name,
+                by_peer,
+                reason,
+                ..
+            } => {
+                let who = if by_peer { "by peer" } else { "here" };
+                self.status_log.push(
+                    LogLevel::Warning,
+                    EventCategory::Transfer,
+                    format!("Transfer cancelled {}: {} ({})", who, file_name, reason),
+                );
+                self.active_transfers.remove(&file_name);
+                self.pending_security.remove(&file_name);
+                effects.push(Effect::RefreshLocalFiles);
+            }
+            AppEvent::Error(msg) => {
+                self.status_log.push(
+                    LogLevel::Error,
+                    EventCategory::Status,
+                    format!("[ERROR] {}", msg),
+                );
+            }
+            AppEvent::VerificationStarted {
+                file_name,
+                is_sending: _,
+            } => {
+                if let Some(transfer) = self.active_transfers.get_mut(&file_name) {
+                    transfer.verification_status = Some(VerificationStatus::Verifying(0.0));
+                }
+            }
+            AppEvent::VerificationProgress {
+                file_name,
+                is_sending: _,
+                progress,
+            } => {
+                if let Some(transfer) = self.active_transfers.get_mut(&file_name) {
+                    transfer.verification_status = Some(VerificationStatus::Verifying(progress));
+                }
+            }
+            AppEvent::VerificationCompleted {
+                file_name,
+                is_sending: _,
+                verified,
+            } => {
+                if let Some(transfer) = self.active_transfers.get_mut(&file_name) {
+                    transfer.verification_status = Some(if verified {
+                        VerificationStatus::Verified
+                    } else {
+                        VerificationStatus::Failed
+                    });
+                    if transfer.done {
+                        self.active_transfers.remove(&file_name);
+                    }
+                }
+                let status = if verified {
+                    format!("{} Verified", egui_phosphor::regular::CHECK_CIRCLE)
+                } else {
+                    format!("{} Corrupted", egui_phosphor::regular::X_CIRCLE)
+                };
+                self.status_log.push(
+                    if verified {
+                        LogLevel::Success
+                    } else {
+                        LogLevel::Error
+                    },
+                    EventCategory::Transfer,
+                    format!("Verification: {} - {}", file_name, status),
+                );
+            }
+            AppEvent::ShareUrlReady { url } => {
+                self.share_url = url;
+                // Reset QR cache to regenerate with new URL
+                self.qrcode_cache = QrCodeCache::default();
+            }
+            AppEvent::HttpServerStarted { url, owner_url } => {
+                self.share_url = url;
+                self.http_server_running = true;
+                self.http_server_pending = false;
+                self.qrcode_cache = QrCodeCache::default();
+                self.status_log.push(
+                    LogLevel::Success,
+                    EventCategory::Http,
+                    "HTTP server started".to_string(),
+                );
+                if let Some(owner_url) = owner_url {
+                    self.status_log.push(
+                        LogLevel::Info,
+                        EventCategory::Http,
+                        format!("Owner page (keep private): {}", owner_url),
+                    );
+                }
+            }
+            AppEvent::HttpServerStopped => {
+                self.http_server_running = false;
+                self.http_server_pending = false;
+                self.share_url = SERVER_NOT_STARTED.to_string();
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Http,
+                    "HTTP server stopped".to_string(),
+                );
+            }
+            AppEvent::UploadRequest {
+                request_id,
+                file_name,
+                file_size,
+                from_ip,
+                warning,
+            } => {
+                self.upload_confirm_state =
+                    UploadConfirmState::Pending(upload_confirm::PendingUpload {
+                        request_id,
+                        file_name,
+                        file_size,
+                        from_ip,
+                        warning,
+                    });
+            }
+            AppEvent::UploadAutoApproved {
+                request_id: _,
+                file_name,
+                file_size,
+                from_ip,
+                reason,
+            } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Http,
+                    format!(
+                        "Accepted upload of {} ({}) from {}: {}",
+                        file_name,
+                        p2p_core::units::format_size(file_size),
+                        from_ip,
+                        reason
+                    ),
+                );
+            }
+            AppEvent::TextReceived { text, from_ip } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Http,
+                    format!("Text received from {}", from_ip),
+                );
+                self.shared_text.receive(text, from_ip);
+                self.ui_state.show_qrcode = true;
+                self.share_tab = ShareTab::Lan;
+            }
+            AppEvent::UploadRequestCancelled { request_id } => {
+                if let UploadConfirmState::Pending(upload) = &self.upload_confirm_state
+                    && upload.request_id == request_id
+                {
+                    self.upload_confirm_state = UploadConfirmState::None;
+                    self.status_log.push(
+                        LogLevel::Info,
+                        EventCategory::Http,
+                        "Upload request cancelled".to_string(),
+                    );
+                }
+            }
+            AppEvent::UploadProgress {
+                request_id: _,
+                received_bytes,
+                total_bytes: _,
+            } => {
+                if received_bytes == 0 {
+                    self.status_log.push(
+                        LogLevel::Info,
+                        EventCategory::Http,
+                        "Incoming upload started...".to_string(),
+                    );
+                }
+            }
+            AppEvent::UploadCompleted {
+                file_name,
+                saved_path: _,
+            } => {
+                self.status_log.push(
+                    LogLevel::Success,
+                    EventCategory::Http,
+                    format!("Upload received: {}", file_name),
+                );
+                effects.push(Effect::RefreshLocalFiles);
+            }
+            AppEvent::WanConnected(conn) => {
+                self.status_log.push(
+                    LogLevel::Success,
+                    EventCategory::Wan,
+                    format!("Connected to WAN peer: {}", conn.remote_id()),
+                );
+
+                effects.push(Effect::MonitorWan(conn.clone()));
+                self.wan_connect_state.active_connection = Some(conn);
+                self.wan_connect_state.connection_status = "Connected".to_string();
+                self.wan_connect_state.connection_type = "Checking...".to_string();
+            }
+            AppEvent::WanConnectionInfo {
+                connection_type,
+                rtt_ms,
+                latency_ms,
+            } => {
+                let rtt_str = rtt_ms
+                    .map(|ms| format!(" (RTT: {}ms)", ms))
+                    .unwrap_or_default();
+                let ping_str = latency_ms
+                    .map(|ms| format!(" (ping: {}ms)", ms))
+                    .unwrap_or_default();
+                self.wan_connect_state.connection_type =
+                    format!("{}{}{}", connection_type, rtt_str, ping_str);
+            }
+            AppEvent::WanConnectionLost { reason } => {
+                self.wan_connect_state.active_connection = None;
+                self.wan_connect_state.connection_type.clear();
+                self.wan_connect_state.connection_status = format!("Connection lost: {}", reason);
+                self.status_log.push(
+                    LogLevel::Warning,
+                    EventCategory::Wan,
+                    format!("WAN connection lost: {}", reason),
+                );
+            }
+            AppEvent::WanShareReady { url } => {
+                self.wan_share_url = Some(url.clone());
+                self.wan_share_running = true;
+                self.wan_share_pending = false;
+                self.qrcode_cache = QrCodeCache::default();
+                self.status_log.push(
+                    LogLevel::Success,
+                    EventCategory::Wan,
+                    format!("WAN share ready: {}", url),
+                );
+            }
+            AppEvent::WanShareStopped => {
+                self.wan_share_url = None;
+                self.wan_share_running = false;
+                self.wan_share_pending = false;
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Wan,
+                    "WAN share stopped".to_string(),
+                );
+            }
+            AppEvent::WanOffer {
+                offer_id,
+                peer,
+                labels,
+                total_size,
+            } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Wan,
+                    format!("{} offers {} files with hidden names", peer, labels.len()),
+                );
+                self.wan_offers.pending.push_back(PendingWanOffer {
+                    offer_id,
+                    peer,
+                    labels,
+                    total_size,
+                });
+            }
+            AppEvent::MyDevices { devices } => {
+                self.wan_connect_state.set_my_devices(devices);
+            }
+            AppEvent::WanShareError(msg) => {
+                self.wan_share_pending = false;
+                self.status_log.push(
+                    LogLevel::Error,
+                    EventCategory::Wan,
+                    format!("[WAN Share Error] {}", msg),
+                );
+            }
+            AppEvent::ProfileSwitched { name } => {
+                // Peers were discovered, and WAN offers made, under the
+                // previous identity
+                self.peers.clear();
+                self.wan_offers.pending.clear();
+                effects.push(Effect::RestartWan);
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Status,
+                    format!("Switched to profile '{}'", name),
+                );
+            }
+            AppEvent::PolicyViolation { message, .. } => {
+                // Whatever was being turned on stays off
+                self.http_server_pending = false;
+                self.wan_share_pending = false;
+                self.status_log.push(
+                    LogLevel::Warning,
+                    EventCategory::Status,
+                    format!("[POLICY] {}", message),
+                );
+            }
+            AppEvent::BackendReady { receive_only, .. } => {
+                // Identity is already logged by the backend on startup
+                self.devices_state.receive_only = receive_only;
+                effects.push(Effect::Send(AppCommand::ListScheduledSends));
+                effects.push(Effect::Send(AppCommand::ListKnownPeers));
+                effects.push(Effect::Send(AppCommand::GetState));
+            }
+            AppEvent::BackendHealth {
+                discovery_ok,
+                quic_listening,
+                http_running,
+                wan_online,
+                relay_latency_ms,
+            } => self.health.record(BackendHealth {
+                discovery_ok,
+                quic_listening,
+                http_running,
+                wan_online,
+                relay_latency_ms,
+            }),
+            AppEvent::MetricsSample {
+                upload_bps,
+                download_bps,
+                system,
+                ..
+            } => self.metrics = (upload_bps, download_bps, system),
+            AppEvent::StateSnapshot(state) => self.apply_state(*state, &mut effects),
+            AppEvent::ScheduledSendsChanged { jobs } => {
+                self.scheduled_sends = jobs;
+            }
+            AppEvent::PendingSends { sends } => {
+                // Offered on startup; later updates only refresh the list
+                if self.pending_sends.is_empty() {
+                    self.show_pending_sends = !sends.is_empty();
+                }
+                self.pending_sends = sends;
+            }
+            AppEvent::LocalFilesChanged => effects.push(Effect::RefreshLocalFiles),
+            AppEvent::CleanupReport {
+                dry_run,
+                files,
+                freed_bytes,
+            } => {
+                let verb = if dry_run { "would delete" } else { "deleted" };
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Transfer,
+                    format!(
+                        "Cleanup {} {} file(s), {}",
+                        verb,
+                        files.len(),
+                        p2p_core::units::format_size(freed_bytes)
+                    ),
+                );
+                if !dry_run {
+                    effects.push(Effect::RefreshLocalFiles);
+                }
+            }
+            AppEvent::SendEstimated {
+                session_id,
+                target_peer_name,
+                estimate,
+            } => {
+                let size = p2p_core::units::format_size;
+                let mut line = format!(
+                    "Sending {} file(s) to {} would move {} of {}",
+                    estimate.files,
+                    target_peer_name,
+                    size(estimate.bytes_to_send),
+                    size(estimate.total_bytes)
+                );
+                if estimate.complete_files > 0 {
+                    line += &format!(", {} already there", estimate.complete_files);
+                }
+                if estimate.overwrites() > 0 {
+                    line += &format!(", {} would be overwritten", estimate.overwrites());
+                }
+                if estimate.streams > 0 {
+                    line += &format!(", plus {} stream(s) of unknown size", estimate.streams);
+                }
+                if let Some(secs) = estimate.expected_secs {
+                    line += &format!(", about {}m {}s", secs / 60, secs % 60);
+                }
+                let level = if !estimate.fits() || !estimate.refused.is_empty() {
+                    line += &format!(
+                        "; {} refused, {} free on the receiver",
+                        estimate.refused.len(),
+                        estimate.free_space.map_or("unknown".to_string(), size)
+                    );
+                    LogLevel::Warning
+                } else {
+                    LogLevel::Info
+                };
+                self.status_log.push(level, EventCategory::Transfer, line);
+                self.devices_state.set_estimate(&session_id, estimate);
+            }
+            AppEvent::PairingBlocked {
+                ip,
+                attempts,
+                retry_after_secs,
+                ..
+            } => {
+                self.status_log.push(
+                    LogLevel::Warning,
+                    EventCategory::Pairing,
+                    format!(
+                        "Blocked {} pairing attempt(s) from {} (locked out for {}s more)",
+                        attempts, ip, retry_after_secs
+                    ),
+                );
+            }
+            AppEvent::DuplicateReceived {
+                file_name,
+                path,
+                existing,
+                size,
+            } => {
+                self.duplicates.push(PendingDuplicate {
+                    file_name,
+                    path,
+                    existing,
+                    size,
+                });
+            }
+            AppEvent::ResendOffered {
+                file_name,
+                path,
+                target_ip,
+                target_peer_name,
+            } => {
+                self.resend_offers.push(ResendOffer {
+                    file_name,
+                    path,
+                    target_ip,
+                    target_peer_name,
+                });
+            }
+            AppEvent::OrphanedPartials { files } => {
+                self.orphaned_partials = files;
+            }
+            AppEvent::TelemetryPreview {
+                payload,
+                awaiting_consent,
+            } => {
+                self.telemetry_preview = Some(TelemetryPreview {
+                    payload,
+                    awaiting_consent,
+                });
+            }
+            AppEvent::SwarmAvailability { swarms } => {
+                self.swarm_availability = swarms;
+            }
+            AppEvent::MovePending {
+                move_id,
+                file_name,
+                path,
+                undo_secs,
+            } => {
+                self.pending_moves
+                    .push(PendingMove::new(move_id, file_name, path, undo_secs));
+            }
+            AppEvent::HistoryResults { records, .. } => {
+                self.history_window.results = Some(records);
+            }
+            AppEvent::BandwidthUsage(summary) => {
+                self.history_window.set_usage(summary);
+            }
+            AppEvent::BandwidthCapWarning {
+                used,
+                cap,
+                reached,
+                holding_scheduled,
+            } => {
+                let what = if reached { "reached" } else { "nearly reached" };
+                let mut message = format!(
+                    "Monthly data cap {}: {} of {}",
+                    what,
+                    p2p_core::units::format_size(used),
+                    p2p_core::units::format_size(cap)
+                );
+                if holding_scheduled {
+                    message.push_str("; scheduled sends are on hold");
+                }
+                self.status_log
+                    .push(LogLevel::Warning, EventCategory::Transfer, message);
+            }
+            AppEvent::NetworkCost { mode, metered, .. } => {
+                if metered != self.ui_state.metered {
+                    let message = if metered {
+                        "Metered connection: sends are slowed and large ones ask first"
+                    } else {
+                        "The connection is no longer metered"
+                    };
+                    self.status_log.push(
+                        LogLevel::Info,
+                        EventCategory::Status,
+                        message.to_string(),
+                    );
+                }
+                self.ui_state.metered = metered;
+                self.metered_mode = mode;
+                self.ui_state.metered_mode = mode;
+            }
+            AppEvent::NetworkChanged { lan_ip, .. } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Discovery,
+                    format!("Network changed, now at {}; looking for devices", lan_ip),
+                );
+            }
+            AppEvent::SystemResumed { slept_secs } => {
+                for transfer in self.active_transfers.values_mut() {
+                    transfer.interrupted |= !transfer.done;
+                }
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Status,
+                    format!(
+                        "Woke up after {} min; interrupted sends resume once their device answers",
+                        slept_secs / 60
+                    ),
+                );
+            }
+            AppEvent::SendInterrupted {
+                target_peer_name,
+                files,
+            } => {
+                for file_name in &files {
+                    if let Some(transfer) = self.active_transfers.get_mut(file_name) {
+                        transfer.interrupted = true;
+                    }
+                }
+                self.status_log.push(
+                    LogLevel::Warning,
+                    EventCategory::Transfer,
+                    format!(
+                        "Send of {} file(s) to {} interrupted by sleep",
+                        files.len(),
+                        target_peer_name
+                    ),
+                );
+            }
+            AppEvent::SendBroken {
+                session_id,
+                endpoint_id,
+                target_peer_name,
+                files,
+                reason,
+            } => {
+                let reachable = !self.ui_state.policy.disable_wan_share
+                    && self
+                        .registry
+                        .get(&endpoint_id)
+                        .is_some_and(|device| device.wan_candidate());
+                if reachable {
+                    self.status_log.push(
+                        LogLevel::Warning,
+                        EventCategory::Transfer,
+                        format!(
+                            "LAN send to {} broke ({}); trying over the internet",
+                            target_peer_name, reason
+                        ),
+                    );
+                    effects.push(Effect::ResumeOverWan(BrokenSend {
+                        session_id,
+                        endpoint_id,
+                        target_peer_name,
+                        files,
+                    }));
+                }
+            }
+            AppEvent::PathSwitched {
+                target_peer_name,
+                file_name,
+                offset,
+            } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Transfer,
+                    format!(
+                        "Send to {} continues over the WAN from {} of {}",
+                        target_peer_name,
+                        p2p_core::units::format_size(offset),
+                        file_name
+                    ),
+                );
+            }
+            AppEvent::GroupSendProgress {
+                group,
+                sent,
+                failed,
+                total,
+                ..
+            } => {
+                self.devices_state.set_group_progress(
+                    group,
+                    GroupProgress {
+                        sent,
+                        failed,
+                        total,
+                    },
+                );
+            }
+            AppEvent::GroupSendFinished {
+                group,
+                sent,
+                failed,
+                ..
+            } => {
+                let total = sent.len() + failed.len();
+                if failed.is_empty() {
+                    self.status_log.push(
+                        LogLevel::Success,
+                        EventCategory::Transfer,
+                        format!("Sent to all {} members of {}", total, group),
+                    );
+                } else {
+                    self.status_log.push(
+                        LogLevel::Warning,
+                        EventCategory::Transfer,
+                        format!("Sent to {} of {} members of {}", sent.len(), total, group),
+                    );
+                    for failure in &failed {
+                        self.status_log.push(
+                            LogLevel::Warning,
+                            EventCategory::Transfer,
+                            format!("{} in {}: {}", failure.name, group, failure.reason),
+                        );
+                    }
+                }
+                self.devices_state.set_group_progress(
+                    group,
+                    GroupProgress {
+                        sent: sent.len(),
+                        failed: failed.len(),
+                        total,
+                    },
+                );
+            }
+            AppEvent::MeteredSendHeld {
+                session_id,
+                target_peer_name,
+                files,
+                total_size,
+            } => {
+                self.held_sends.push(HeldSend {
+                    session_id,
+                    target_peer_name,
+                    files,
+                    total_size,
+                });
+            }
+            AppEvent::MoveFinished { move_id, .. } => {
+                self.pending_moves.retain(|entry| entry.move_id != move_id);
+            }
+            AppEvent::ScheduledSendStarted { job_id, .. } => {
+                self.status_log.push(
+                    LogLevel::Info,
+                    EventCategory::Transfer,
+                    format!("Starting scheduled send {}", job_id),
+                );
+            }
+            AppEvent::CommandResult { .. } => {
+                // The GUI sends untracked commands; failures already arrive as Error events
+            }
+        }
+        effects
+    }
+
+    /// Catch up with a backend that was running before this window attached
+    fn apply_state(&mut self, state: p2p_core::state::BackendState, effects: &mut Vec<Effect>) {
+        self.peers = state
+            .peers
+            .into_iter()
+            .map(|peer| {
+                (
+                    peer.ip.clone(),
+                    PeerEntry {
+                        endpoint_id: peer.endpoint_id,
+                        ip: peer.ip,
+                        port: peer.port,
+                        hostname: peer.hostname,
+                        display_name: peer.display_name,
+                        receive_only: peer.capabilities.receive_only,
+                        version: peer.version,
+                        protocol: peer.capabilities.protocol,
+                        rtt_ms: peer.rtt_ms,
+                        last_seen: Instant::now(),
+                    },
+                )
+            })
+            .collect();
+        for transfer in state.transfers {
+            let entry = self
+                .active_transfers
+                .entry(transfer.file_name.clone())
+                .or_insert(TransferState {
+                    file_name: transfer.file_name.clone(),
+                    progress: 0.0,
+                    bytes: None,
+                    speed: String::new(),
+                    is_sending: transfer.is_sending,
+                    verification_status: None,
+                    security: None,
+                    done: false,
+                    interrupted: false,
+                });
+            entry.progress = transfer.progress;
+            entry.bytes = transfer.bytes;
+            entry.speed = p2p_core::units::format_speed(transfer.speed_bps);
+        }
+
+        self.http_server_running = state.http_share_url.is_some();
+        self.share_url = state.http_share_url.unwrap_or_default();
+        self.wan_share_running = state.wan_share_url.is_some();
+        self.wan_share_url = state.wan_share_url;
+        self.qrcode_cache = QrCodeCache::default();
+
+        self.devices_state.receive_only = state.receive_only;
+        effects.push(Effect::UseUnits(state.units));
+        self.ui_state.units = state.units;
+        self.ui_state.hash_algorithm = state.hash_algorithm;
+        self.metered_mode = state.metered_mode;
+        self.ui_state.metered_mode = state.metered_mode;
+        self.ui_state.metered = state.metered;
+        self.download_path = state.download_dir;
+        effects.push(Effect::RefreshLocalFiles);
+    }
+
+    /// Forget peers not heard from for a while as of `now`
+    pub fn expire_peers(&mut self, now: Instant) {
+        let registry = &mut self.registry;
+        self.peers.retain(|ip, info| {
+            let fresh = now.duration_since(info.last_seen) < Duration::from_secs(PEER_TIMEOUT_SECS);
+            if !fresh {
+                registry.apply(&AppEvent::PeerLost {
+                    endpoint_id: info.endpoint_id.clone(),
+                    ip: ip.clone(),
+                });
+            }
+            fresh
+        });
+    }
+
+    /// Open a clicked `p2p://` link: a device fills in the WAN connect
+    /// window, a share page shows in the Pair tab to be opened from there
+    pub fn open_link(&mut self, link: AppLink) {
+        self.status_log.push(
+            LogLevel::Info,
+            EventCategory::Status,
+            format!("Opened link {}", link),
+        );
+        match link {
+            AppLink::Connect { endpoint_id } => {
+                self.wan_connect_state.fill_scanned(endpoint_id);
+                self.ui_state.show_wan_connect = true;
+            }
+            link @ AppLink::Share { .. } => {
+                if let Some(url) = link.share_page() {
+                    self.pair_state.show_share_page(url);
+                }
+                self.share_tab = ShareTab::Pair;
+                self.ui_state.show_qrcode = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p2p_core::PeerCapabilities;
+    use p2p_core::state::{BackendState, PeerSnapshot, TransferSnapshot};
+
+    const BINARY: UnitPreference = UnitPreference {
+        binary: true,
+        bits: false,
+    };
+
+    fn state() -> AppState {
+        let wan = WanConnectState::for_endpoint(
+            "my-endpoint".to_string(),
+            p2p_core::config::AppConfig::default(),
+        );
+        AppState::new(AppUIState::default(), wan)
+    }
+
+    fn peer_found(ip: &str) -> AppEvent {
+        AppEvent::PeerFound {
+            endpoint_id: format!("id-{}", ip),
+            ip: ip.to_string(),
+            port: 9000,
+            hostname: "laptop".to_string(),
+            display_name: "laptop".to_string(),
+            capabilities: PeerCapabilities {
+                receive_only: true,
+                ..Default::default()
+            },
+            version: Some("1.0.0".to_string()),
+            rtt_ms: Some(3),
+        }
+    }
+
+    fn progress(file_name: &str, percent: f32) -> AppEvent {
+        AppEvent::TransferProgress {
+            file_name: file_name.to_string(),
+            progress: percent,
+            speed: "1 MB/s".to_string(),
+            speed_bps: 1e6,
+            is_sending: false,
+            bytes: None,
+        }
+    }
+
+    fn verification(file_name: &str, verified: Option<bool>) -> AppEvent {
+        match verified {
+            None => AppEvent::VerificationStarted {
+                file_name: file_name.to_string(),
+                is_sending: false,
+            },
+            Some(verified) => AppEvent::VerificationCompleted {
+                file_name: file_name.to_string(),
+                is_sending: false,
+                verified,
+            },
+        }
+    }
+
+    fn completed(file_name: &str) -> AppEvent {
+        AppEvent::TransferCompleted {
+            file_name: file_name.to_string(),
+            saved_path: None,
+            peer: Some("laptop".to_string()),
+        }
+    }
+
+    fn last_log(state: &AppState) -> (LogLevel, String) {
+        let entry = state.status_log.visible().last().expect("a log entry");
+        (entry.level, entry.message.clone())
+    }
+
+    fn sent(effects: &[Effect]) -> Vec<String> {
+        effects
+            .iter()
+            .filter_map(|effect| match effect {
+                Effect::Send(command) => Some(format!("{:?}", command)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn refreshes(effects: &[Effect]) -> bool {
+        effects
+            .iter()
+            .any(|effect| matches!(effect, Effect::RefreshLocalFiles))
+    }
+
+    #[test]
+    fn test_status_level_is_guessed_from_wording() {
+        let mut state = state();
+        for (msg, level) in [
+            ("Connection error", LogLevel::Error),
+            ("Transfer Complete", LogLevel::Success),
+            ("Connecting to peer", LogLevel::Warning),
+            ("Listening", LogLevel::Info),
+        ] {
+            assert!(state.apply(AppEvent::Status(msg.to_string())).is_empty());
+            assert_eq!(last_log(&state), (level, msg.to_string()));
+        }
+
+        state.apply(AppEvent::Error("disk full".to_string()));
+        assert_eq!(
+            last_log(&state),
+            (LogLevel::Error, "[ERROR] disk full".to_string())
+        );
+        state.apply(AppEvent::Log {
+            level: LogLevel::Warning,
+            category: EventCategory::Discovery,
+            message: "port in use".to_string(),
+        });
+        assert_eq!(
+            last_log(&state),
+            (LogLevel::Warning, "port in use".to_string())
+        );
+    }
+
+    #[test]
+    fn test_peers_come_go_and_expire() {
+        let mut state = state();
+        state.apply(peer_found("10.0.0.2"));
+        state.apply(peer_found("10.0.0.3"));
+        let peer = &state.peers["10.0.0.2"];
+        assert!(peer.receive_only);
+        assert_eq!(peer.rtt_ms, Some(3));
+
+        state.apply(AppEvent::PeerLost {
+            endpoint_id: "id-10.0.0.2".to_string(),
+            ip: "10.0.0.2".to_string(),
+        });
+        assert_eq!(state.peers.len(), 1);
+
+        state.expire_peers(Instant::now());
+        assert_eq!(state.peers.len(), 1);
+        state.expire_peers(Instant::now() + Duration::from_secs(PEER_TIMEOUT_SECS));
+        assert!(state.peers.is_empty());
+
+        state.apply(peer_found("10.0.0.4"));
+        let effects = state.apply(AppEvent::ProfileSwitched {
+            name: "work".to_string(),
+        });
+        assert!(state.peers.is_empty());
+        assert!(matches!(effects[..], [Effect::RestartWan]));
+    }
+
+    #[test]
+    fn test_peer_deltas_update_the_list() {
+        use p2p_core::discovery::batch::PeerBatch;
+
+        let mut state = state();
+        state.apply(peer_found("10.0.0.2"));
+        let mut batch = PeerBatch::default();
+        batch.push(peer_found("10.0.0.3"));
+        batch.push(peer_found("10.0.0.4"));
+        state.apply(AppEvent::PeerListDelta(batch.take().unwrap()));
+        assert_eq!(state.peers.len(), 3);
+
+        batch.push(AppEvent::PeerLost {
+            endpoint_id: "id-10.0.0.3".to_string(),
+            ip: "10.0.0.3".to_string(),
+        });
+        state.apply(AppEvent::PeerListDelta(batch.take().unwrap()));
+        assert!(!state.peers.contains_key("10.0.0.3"));
+        assert_eq!(state.peers.len(), 2);
+    }
+
+    #[test]
+    fn test_transfer_without_verification_ends_on_completion() {
+        let mut state = state();
+        state.apply(progress("a.txt", 10.0));
+        state.apply(progress("a.txt", 60.0));
+        let transfer = &state.active_transfers["a.txt"];
+        assert_eq!(transfer.progress, 60.0);
+        assert_eq!(transfer.speed, "1 MB/s");
+        assert!(!transfer.is_sending);
+
+        let effects = state.apply(completed("a.txt"));
+        assert!(refreshes(&effects));
+        assert!(state.active_transfers.is_empty());
+        assert_eq!(
+            state.status_log.visible().last().unwrap().level,
+            LogLevel::Success
+        );
+    }
+
+    #[test]
+    fn test_transfer_stays_until_its_verification_ends() {
+        let mut state = state();
+        state.apply(progress("a.txt", 100.0));
+        state.apply(verification("a.txt", None));
+        state.apply(AppEvent::VerificationProgress {
+            file_name: "a.txt".to_string(),
+            is_sending: false,
+            progress: 40.0,
+        });
+        assert_eq!(
+            state.active_transfers["a.txt"].verification_status,
+            Some(VerificationStatus::Verifying(40.0))
+        );
+
+        state.apply(completed("a.txt"));
+        assert!(state.active_transfers["a.txt"].done);
+
+        state.apply(verification("a.txt", Some(false)));
+        assert!(state.active_transfers.is_empty());
+        assert_eq!(
+            state.status_log.visible().last().unwrap().level,
+            LogLevel::Error
+        );
+    }
+
+    #[test]
+    fn test_verification_before_completion_keeps_the_result() {
+        let mut state = state();
+        state.apply(progress("a.txt", 90.0));
+        state.apply(verification("a.txt", None));
+        state.apply(verification("a.txt", Some(true)));
+        assert_eq!(
+            state.active_transfers["a.txt"].verification_status,
+            Some(VerificationStatus::Verified)
+        );
+        // Nothing to do for files this window never saw
+        state.apply(verification("b.txt", None));
+        assert!(!state.active_transfers.contains_key("b.txt"));
+    }
+
+    #[test]
+    fn test_early_security_info_waits_for_the_transfer() {
+        let mut state = state();
+        state.apply(AppEvent::SecurityInfo {
+            file_name: "a.txt".to_string(),
+            is_sending: false,
+            security: SecurityInfo {
+                cipher_suite: "TLS13_AES_128_GCM_SHA256".to_string(),
+                cert_pin: p2p_core::transfer::security::CertPin::Unpinned,
+                path: p2p_core::transfer::security::ConnectionPath::Lan,
+                fingerprint: None,
+            },
+        });
+        assert!(state.pending_security.contains_key("a.txt"));
+
+        state.apply(progress("a.txt", 1.0));
+        assert!(state.pending_security.is_empty());
+        assert!(state.active_transfers["a.txt"].security.is_some());
+    }
+
+    #[test]
+    fn test_cancelled_transfer_is_dropped() {
+        let mut state = state();
+        state.apply(progress("a.txt", 5.0));
+        let effects = state.apply(AppEvent::TransferCancelled {
+            file_name: "a.txt".to_string(),
+            is_sending: false,
+            by_peer: true,
+            reason: "user".to_string(),
+        });
+        assert!(refreshes(&effects));
+        assert!(state.active_transfers.is_empty());
+        assert_eq!(
+            last_log(&state),
+            (
+                LogLevel::Warning,
+                "Transfer cancelled by peer: a.txt (user)".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_sleep_interrupts_running_transfers() {
+        let mut state = state();
+        state.apply(progress("a.txt", 5.0));
+        state.apply(progress("b.txt", 5.0));
+        state.apply(AppEvent::SendInterrupted {
+            target_peer_name: "laptop".to_string(),
+            files: vec!["a.txt".to_string()],
+        });
+        assert!(state.active_transfers["a.txt"].interrupted);
+        assert!(!state.active_transfers["b.txt"].interrupted);
+
+        state.apply(AppEvent::SystemResumed { slept_secs: 600 });
+        assert!(state.active_transfers["b.txt"].interrupted);
+
+        // Data flowing again clears the mark
+        state.apply(progress("a.txt", 6.0));
+        assert!(!state.active_transfers["a.txt"].interrupted);
+    }
+
+    #[test]
+    fn test_broken_send_to_a_paired_device_moves_to_the_wan() {
+        let mut state = state();
+        let broken = || AppEvent::SendBroken {
+            session_id: "s1".to_string(),
+            endpoint_id: "id-192.168.1.20".to_string(),
+            target_peer_name: "laptop".to_string(),
+            files: vec![JournaledFile {
+                path: PathBuf::from("/data/a.bin"),
+                sent: 4096,
+            }],
+            reason: "The peer stopped answering".to_string(),
+        };
+        state.apply(peer_found("192.168.1.20"));
+        // Not paired: nothing to dial over the WAN
+        assert!(state.apply(broken()).is_empty());
+
+        state.apply(AppEvent::KnownPeersChanged {
+            paired: vec!["id-192.168.1.20".to_string()],
+            guests: Vec::new(),
+            guest_mode: false,
+            pinned: Vec::new(),
+            groups: Vec::new(),
+        });
+        let effects = state.apply(broken());
+        let [Effect::ResumeOverWan(send)] = effects.as_slice() else {
+            panic!("Expected a WAN resume, got {} effects", effects.len());
+        };
+        assert_eq!(send.session_id, "s1");
+        assert_eq!(send.files[0].sent, 4096);
+
+        state.ui_state.policy.disable_wan_share = true;
+        assert!(state.apply(broken()).is_empty());
+    }
+
+    #[test]
+    fn test_pairing_outcomes_refresh_known_peers() {
+        let mut state = state();
+        let effects = state.apply(AppEvent::PairingResult {
+            session_id: "s1".to_string(),
+            success: false,
+            peer_name: "laptop".to_string(),
+            message: "wrong code".to_string(),
+        });
+        assert_eq!(sent(&effects), ["ListKnownPeers"]);
+        assert_eq!(
+            last_log(&state),
+            (
+                LogLevel::Error,
+                "Pairing with laptop: wrong code".to_string()
+            )
+        );
+
+        let effects = state.apply(AppEvent::DeviceLinked {
+            endpoint_id: "id".to_string(),
+            peer_name: "laptop".to_string(),
+        });
+        assert_eq!(sent(&effects), ["ListKnownPeers"]);
+
+        let effects = state.apply(AppEvent::GuestPairingEnded {
+            endpoint_id: "id".to_string(),
+            peer_name: "visitor".to_string(),
+        });
+        assert_eq!(sent(&effects), ["ListKnownPeers"]);
+
+        let effects = state.apply(AppEvent::LinkFailed {
+            message: "expired".to_string(),
+        });
+        assert!(effects.is_empty());
+        assert_eq!(
+            last_log(&state),
+            (LogLevel::Error, "Link failed: expired".to_string())
+        );
+    }
+
+    #[test]
+    fn test_backend_ready_asks_for_state() {
+        let mut state = state();
+        let effects = state.apply(AppEvent::BackendReady {
+            endpoint_id: "id".to_string(),
+            device_name: "desk".to_string(),
+            transfer_port: 9000,
+            receive_only: true,
+        });
+        assert!(state.devices_state.receive_only);
+        assert_eq!(
+            sent(&effects),
+            ["ListScheduledSends", "ListKnownPeers", "GetState"]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_restores_peers_transfers_and_settings() {
+        let mut state = state();
+        let effects = state.apply(AppEvent::StateSnapshot(Box::new(BackendState {
+            receive_only: true,
+            units: BINARY,
+            metered: true,
+            download_dir: PathBuf::from("/downloads"),
+            peers: vec![PeerSnapshot {
+                endpoint_id: "id".to_string(),
+                ip: "10.0.0.2".to_string(),
+                port: 9000,
+                hostname: "laptop".to_string(),
+                display_name: "laptop".to_string(),
+                capabilities: PeerCapabilities::default(),
+                version: None,
+                rtt_ms: None,
+            }],
+            transfers: vec![TransferSnapshot {
+                file_name: "a.txt".to_string(),
+                is_sending: true,
+                progress: 30.0,
+                speed_bps: 0.0,
+                bytes: None,
+            }],
+            http_share_url: Some("http://10.0.0.1:8080/token".to_string()),
+            ..Default::default()
+        })));
+
+        assert!(matches!(
+            effects.as_slice(),
+            [Effect::UseUnits(BINARY), Effect::RefreshLocalFiles]
+        ));
+        assert!(state.peers.contains_key("10.0.0.2"));
+        assert!(state.active_transfers["a.txt"].is_sending);
+        assert_eq!(state.active_transfers["a.txt"].progress, 30.0);
+        assert!(state.http_server_running);
+        assert_eq!(state.share_url, "http://10.0.0.1:8080/token");
+        assert!(!state.wan_share_running);
+        assert!(state.devices_state.receive_only);
+        assert_eq!(state.ui_state.units, BINARY);
+        assert!(state.ui_state.metered);
+        assert_eq!(state.download_path, PathBuf::from("/downloads"));
+    }
+
+    #[test]
+    fn test_http_server_start_and_stop() {
+        let mut state = state();
+        state.http_server_pending = true;
+        state.apply(AppEvent::HttpServerStarted {
+            url: "http://10.0.0.1:8080/token".to_string(),
+            owner_url: Some("http://10.0.0.1:8080/owner".to_string()),
+        });
+        assert!(state.http_server_running);
+        assert!(!state.http_server_pending);
+        assert_eq!(state.share_url, "http://10.0.0.1:8080/token");
+        assert_eq!(state.status_log.len(), 2);
+
+        state.apply(AppEvent::ShareUrlReady {
+            url: "http://10.0.0.9:8080/token".to_string(),
+        });
+        assert_eq!(state.share_url, "http://10.0.0.9:8080/token");
+
+        state.apply(AppEvent::HttpServerStopped);
+        assert!(!state.http_server_running);
+        assert_eq!(state.share_url, SERVER_NOT_STARTED);
+    }
+
+    #[test]
+    fn test_wan_share_lifecycle() {
+        let mut state = state();
+        state.wan_share_pending = true;
+        state.apply(AppEvent::WanShareError("no tunnel".to_string()));
+        assert!(!state.wan_share_pending);
+
+        state.apply(AppEvent::WanShareReady {
+            url: "http://bore.pub:1234".to_string(),
+        });
+        assert!(state.wan_share_running);
+        assert_eq!(state.wan_share_url.as_deref(), Some("http://bore.pub:1234"));
+
+        state.apply(AppEvent::WanShareStopped);
+        assert!(!state.wan_share_running);
+        assert!(state.wan_share_url.is_none());
+    }
+
+    #[test]
+    fn test_policy_violation_stops_pending_toggles() {
+        let mut state = state();
+        state.http_server_pending = true;
+        state.wan_share_pending = true;
+        state.apply(AppEvent::PolicyViolation {
+            rule: p2p_core::policy::PolicyRule::HttpShareDisabled,
+            message: "HTTP share is disabled".to_string(),
+        });
+        assert!(!state.http_server_pending);
+        assert!(!state.wan_share_pending);
+        assert_eq!(
+            last_log(&state),
+            (
+                LogLevel::Warning,
+                "[POLICY] HTTP share is disabled".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_upload_request_and_its_cancellation() {
+        let mut state = state();
+        state.apply(AppEvent::UploadRequest {
+            request_id: "r1".to_string(),
+            file_name: "a.txt".to_string(),
+            file_size: 10,
+            from_ip: "10.0.0.2".to_string(),
+            warning: None,
+        });
+        assert!(matches!(
+            state.upload_confirm_state,
+            UploadConfirmState::Pending(_)
+        ));
+
+        // Another request's cancellation leaves this one open
+        state.apply(AppEvent::UploadRequestCancelled {
+            request_id: "r2".to_string(),
+        });
+        assert!(matches!(
+            state.upload_confirm_state,
+            UploadConfirmState::Pending(_)
+        ));
+        state.apply(AppEvent::UploadRequestCancelled {
+            request_id: "r1".to_string(),
+        });
+        assert!(matches!(
+            state.upload_confirm_state,
+            UploadConfirmState::None
+        ));
+
+        let effects = state.apply(AppEvent::UploadCompleted {
+            file_name: "a.txt".to_string(),
+            saved_path: "/downloads/a.txt".to_string(),
+        });
+        assert!(refreshes(&effects));
+    }
+
+    #[test]
+    fn test_shared_text_and_file_links_open_the_share_window() {
+        let mut state = state();
+        state.apply(AppEvent::TextReceived {
+            text: "hello".to_string(),
+            from_ip: "10.0.0.2".to_string(),
+        });
+        assert!(state.ui_state.show_qrcode);
+        assert!(state.share_tab == ShareTab::Lan);
+
+        state.ui_state.show_qrcode = false;
+        state.apply(AppEvent::FileLinkCreated {
+            link_id: "l1".to_string(),
+            file_name: "a.txt".to_string(),
+            url: "http://10.0.0.1:8080/l1".to_string(),
+            wan_url: None,
+            expires_in_secs: Some(60),
+            single_use: true,
+        });
+        assert!(state.ui_state.show_qrcode);
+        assert!(state.share_tab == ShareTab::File);
+    }
+
+    #[test]
+    fn test_links_open_their_window() {
+        let mut state = state();
+        let endpoint_id = iroh::SecretKey::generate(&mut rand::rng())
+            .public()
+            .to_string();
+        state.open_link(AppLink::Connect {
+            endpoint_id: endpoint_id.clone(),
+        });
+        assert!(state.ui_state.show_wan_connect);
+        assert_eq!(state.wan_connect_state.target_endpoint_id, endpoint_id);
+        assert!(!state.ui_state.show_qrcode);
+
+        let share = AppLink::parse("p2p://share/token?at=http://10.0.0.1:8080").unwrap();
+        state.open_link(share);
+        assert!(state.ui_state.show_qrcode);
+        assert!(state.share_tab == ShareTab::Pair);
+        assert!(last_log(&state).1.contains("p2p://share/token"));
+    }
+
+    #[test]
+    fn test_file_share_counts_downloads() {
+        let mut state = state();
+        state.apply(AppEvent::FileShareCreated {
+            share_id: "s1".to_string(),
+            file_names: vec!["a.txt".to_string(), "b.txt".to_string()],
+            url: "http://10.0.0.1:8080/s/s1".to_string(),
+            wan_url: None,
+            expires_in_secs: 3600,
+        });
+        assert!(state.ui_state.show_qrcode);
+        assert!(state.share_tab == ShareTab::File);
+        assert_eq!(state.file_links.downloads("s1"), Some(0));
+
+        for downloads in [1, 2] {
+            state.apply(AppEvent::FileShareDownloaded {
+                share_id: "s1".to_string(),
+                file_name: "b.txt".to_string(),
+                downloads,
+                from_ip: "10.0.0.2".to_string(),
+            });
+        }
+        state.apply(AppEvent::FileShareDownloaded {
+            share_id: "s1".to_string(),
+            file_name: "a.txt".to_string(),
+            downloads: 1,
+            from_ip: "10.0.0.2".to_string(),
+        });
+        assert_eq!(state.file_links.downloads("s1"), Some(3));
+        assert_eq!(state.file_links.downloads("other"), None);
+    }
+
+    #[test]
+    fn test_wan_connection_info_and_loss() {
+        let mut state = state();
+        state.apply(AppEvent::WanConnectionInfo {
+            connection_type: "Direct".to_string(),
+            rtt_ms: Some(12),
+            latency_ms: None,
+        });
+        assert_eq!(
+            state.wan_connect_state.connection_type,
+            "Direct (RTT: 12ms)"
+        );
+
+        state.apply(AppEvent::WanConnectionLost {
+            reason: "timeout".to_string(),
+        });
+        assert!(state.wan_connect_state.connection_type.is_empty());
+        assert_eq!(
+            state.wan_connect_state.connection_status,
+            "Connection lost: timeout"
+        );
+
+        state.apply(AppEvent::WanOffer {
+            offer_id: "o1".to_string(),
+            peer: "friend".to_string(),
+            labels: vec!["File 1".to_string()],
+            total_size: 10,
+        });
+        assert_eq!(state.wan_offers.pending.len(), 1);
+    }
+
+    #[test]
+    fn test_pending_sends_are_offered_once() {
+        let mut state = state();
+        let send = PendingSend {
+            id: "s1".to_string(),
+            target: "10.0.0.2".to_string(),
+            target_peer_name: "laptop".to_string(),
+            files: Vec::new(),
+            started: 0,
+        };
+        state.apply(AppEvent::PendingSends {
+            sends: vec![send.clone()],
+        });
+        assert!(state.show_pending_sends);
+
+        state.show_pending_sends = false;
+        state.apply(AppEvent::PendingSends {
+            sends: vec![send.clone(), send],
+        });
+        assert!(!state.show_pending_sends);
+        assert_eq!(state.pending_sends.len(), 2);
+    }
+
+    #[test]
+    fn test_moves_duplicates_and_held_sends_queue_up() {
+        let mut state = state();
+        state.apply(AppEvent::MovePending {
+            move_id: "m1".to_string(),
+            file_name: "a.txt".to_string(),
+            path: PathBuf::from("/src/a.txt"),
+            undo_secs: 10,
+        });
+        assert_eq!(state.pending_moves.len(), 1);
+        state.apply(AppEvent::MoveFinished {
+            move_id: "m1".to_string(),
+            deleted: true,
+        });
+        assert!(state.pending_moves.is_empty());
+
+        state.apply(AppEvent::DuplicateReceived {
+            file_name: "a.txt".to_string(),
+            path: PathBuf::from("/downloads/a (1).txt"),
+            existing: PathBuf::from("/downloads/a.txt"),
+            size: 10,
+        });
+        state.apply(AppEvent::ResendOffered {
+            file_name: "a.txt".to_string(),
+            path: PathBuf::from("/src/a.txt"),
+            target_ip: "10.0.0.2".to_string(),
+            target_peer_name: "laptop".to_string(),
+        });
+        state.apply(AppEvent::MeteredSendHeld {
+            session_id: "s1".to_string(),
+            target_peer_name: "laptop".to_string(),
+            files: 2,
+            total_size: 1 << 30,
+        });
+        assert_eq!(state.duplicates.len(), 1);
+        assert_eq!(state.resend_offers.len(), 1);
+        assert_eq!(state.held_sends.len(), 1);
+    }
+
+    #[test]
+    fn test_estimate_reaches_its_preview() {
+        use crate::ui::windows::send_preview::SendPreview;
+        use p2p_core::transfer::SendEstimate;
+
+        let mut state = state();
+        state.devices_state.previews.push(SendPreview {
+            session_id: "s1".to_string(),
+            ip: "10.0.0.2".to_string(),
+            name: "laptop".to_string(),
+            files: vec![PathBuf::from("/src/a.txt")],
+            note: None,
+            estimate: None,
+        });
+        let estimated = |session_id: &str| AppEvent::SendEstimated {
+            session_id: session_id.to_string(),
+            target_peer_name: "laptop".to_string(),
+            estimate: SendEstimate {
+                files: 1,
+                ..Default::default()
+            },
+        };
+        state.apply(estimated("other"));
+        assert!(state.devices_state.previews[0].estimate.is_none());
+        state.apply(estimated("s1"));
+        assert_eq!(
+            state.devices_state.previews[0]
+                .estimate
+                .as_ref()
+                .map(|estimate| estimate.files),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_queued_pairing_shows_the_place_in_line() {
+        let mut state = state();
+        let queued = |ahead| AppEvent::PairingQueued {
+            session_id: "s1".to_string(),
+            peer_name: "laptop".to_string(),
+            ahead,
+        };
+        state.apply(queued(1));
+        assert_eq!(
+            last_log(&state).1,
+            "Waiting for laptop to show a code, 1 ahead of you"
+        );
+        state.apply(queued(0));
+        assert_eq!(
+            last_log(&state).1,
+            "Waiting for laptop to show a code, you are next"
+        );
+    }
+
+    #[test]
+    fn test_network_cost_logs_only_changes() {
+        let mut state = state();
+        let cost = |metered| AppEvent::NetworkCost {
+            detected: Default::default(),
+            mode: MeteredMode::default(),
+            metered,
+        };
+        state.apply(cost(false));
+        assert_eq!(state.status_log.len(), 0);
+        state.apply(cost(true));
+        assert!(state.ui_state.metered);
+        assert_eq!(state.status_log.len(), 1);
+        state.apply(cost(true));
+        assert_eq!(state.status_log.len(), 1);
+    }
+
+    #[test]
+    fn test_cleanup_refreshes_only_when_files_went() {
+        let mut state = state();
+        let cleanup = |dry_run| AppEvent::CleanupReport {
+            dry_run,
+            files: vec!["old.bin".to_string()],
+            freed_bytes: 1024,
+        };
+        assert!(!refreshes(&state.apply(cleanup(true))));
+        assert!(refreshes(&state.apply(cleanup(false))));
+        assert!(refreshes(&state.apply(AppEvent::LocalFilesChanged)));
+    }
+
+    #[test]
+    fn test_metrics_and_health_are_kept() {
+        let mut state = state();
+        state.apply(AppEvent::MetricsSample {
+            upload_bps: 10.0,
+            download_bps: 20.0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            active_transfers: 0,
+            system: None,
+        });
+        assert_eq!(state.metrics.0, 10.0);
+        assert_eq!(state.metrics.1, 20.0);
+
+        let effects = state.apply(AppEvent::CommandResult {
+            request_id: "r1".to_string(),
+            result: Ok(()),
+        });
+        assert!(effects.is_empty());
+    }
+}
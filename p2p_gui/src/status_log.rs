@@ -0,0 +1,202 @@
+//! Bounded, deduplicated status log shown in the main window.
+
+use p2p_core::{EventCategory, LogLevel};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Maximum number of entries kept before the oldest are dropped
+pub const MAX_LOG_ENTRIES: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub level: LogLevel,
+    pub category: EventCategory,
+    pub message: String,
+    /// Seconds since the Unix epoch when the message was last seen
+    pub timestamp: u64,
+    /// How many times this message arrived back to back
+    pub repeat: u32,
+}
+
+/// Which entries the log view shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFilter {
+    #[default]
+    All,
+    ErrorsOnly,
+    TransfersOnly,
+}
+
+impl LogFilter {
+    pub const ALL: [LogFilter; 3] = [
+        LogFilter::All,
+        LogFilter::ErrorsOnly,
+        LogFilter::TransfersOnly,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LogFilter::All => "All",
+            LogFilter::ErrorsOnly => "Errors only",
+            LogFilter::TransfersOnly => "Transfers only",
+        }
+    }
+}
+
+pub struct StatusLog {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+    pub filter: LogFilter,
+    pub search: String,
+    pub show_debug: bool,
+}
+
+impl Default for StatusLog {
+    fn default() -> Self {
+        Self::new(MAX_LOG_ENTRIES)
+    }
+}
+
+impl StatusLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+            filter: LogFilter::default(),
+            search: String::new(),
+            show_debug: false,
+        }
+    }
+
+    /// Append a message, folding it into the previous entry if identical
+    pub fn push(&mut self, level: LogLevel, category: EventCategory, message: impl Into<String>) {
+        let message = message.into();
+        let timestamp = unix_now();
+
+        if let Some(last) = self.entries.back_mut()
+            && last.level == level
+            && last.message == message
+        {
+            last.repeat += 1;
+            last.timestamp = timestamp;
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LogEntry {
+            level,
+            category,
+            message,
+            timestamp,
+            repeat: 1,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Entries matching the current filter, debug toggle and search text
+    pub fn visible(&self) -> impl Iterator<Item = &LogEntry> {
+        let needle = self.search.trim().to_lowercase();
+        self.entries.iter().filter(move |entry| {
+            if entry.level == LogLevel::Debug && !self.show_debug {
+                return false;
+            }
+            let filter_ok = match self.filter {
+                LogFilter::All => true,
+                LogFilter::ErrorsOnly => entry.level >= LogLevel::Warning,
+                LogFilter::TransfersOnly => entry.category == EventCategory::Transfer,
+            };
+            filter_ok && (needle.is_empty() || entry.message.to_lowercase().contains(&needle))
+        })
+    }
+
+    /// Write every entry (ignoring filters) as plain text for bug reports
+    pub fn export(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+
+    /// Every entry as plain text, one per line
+    pub fn write_to(&self, out: &mut impl Write) -> std::io::Result<()> {
+        for entry in &self.entries {
+            write!(
+                out,
+                "{} [{:?}] [{:?}] {}",
+                entry.timestamp, entry.level, entry.category, entry.message
+            )?;
+            if entry.repeat > 1 {
+                write!(out, " (x{})", entry.repeat)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer_drops_oldest() {
+        let mut log = StatusLog::new(3);
+        for i in 0..5 {
+            log.push(LogLevel::Info, EventCategory::Status, format!("msg {}", i));
+        }
+        assert_eq!(log.len(), 3);
+        let messages: Vec<_> = log.visible().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["msg 2", "msg 3", "msg 4"]);
+    }
+
+    #[test]
+    fn test_consecutive_duplicates_are_folded() {
+        let mut log = StatusLog::default();
+        log.push(LogLevel::Info, EventCategory::Discovery, "scan");
+        log.push(LogLevel::Info, EventCategory::Discovery, "scan");
+        log.push(LogLevel::Error, EventCategory::Status, "boom");
+        log.push(LogLevel::Info, EventCategory::Discovery, "scan");
+
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.visible().next().unwrap().repeat, 2);
+    }
+
+    #[test]
+    fn test_filters_and_search() {
+        let mut log = StatusLog::default();
+        log.push(LogLevel::Debug, EventCategory::Status, "debug noise");
+        log.push(LogLevel::Info, EventCategory::Transfer, "Sending: a.txt");
+        log.push(LogLevel::Error, EventCategory::Status, "Connection failed");
+
+        assert_eq!(log.visible().count(), 2);
+        log.show_debug = true;
+        assert_eq!(log.visible().count(), 3);
+
+        log.filter = LogFilter::ErrorsOnly;
+        assert_eq!(log.visible().count(), 1);
+
+        log.filter = LogFilter::TransfersOnly;
+        assert_eq!(log.visible().next().unwrap().message, "Sending: a.txt");
+
+        log.filter = LogFilter::All;
+        log.search = "FAILED".to_string();
+        assert_eq!(log.visible().count(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+//! Headless streams for scripts: `send --stdin` and `receive --stdout`.
+//!
+//! Both run a node of the active profile without the window, next to the
+//! app if it is running: the sender on ports of its own, the receiver on
+//! the transfer port given with `--port`. Scheduled sends, the journal and
+//! the history stay with the app. Standard input is the data, so no
+//! verification code can be typed: `send --stdin` only sends to a device
+//! that is already paired.
+
+use crate::cli::StreamSend;
+use anyhow::{Result, anyhow};
+use p2p_core::discovery::DISCOVERY_PORT;
+use p2p_core::json_events::EventOutput;
+use p2p_core::storage::pipe::PipeStorage;
+use p2p_core::transfer::StreamSource;
+use p2p_core::{AppCommand, AppEvent, NodeConfig, P2pNode, P2pNodeBuilder, profile_config};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long `send --stdin` looks for a device given by name
+const DISCOVERY_WAIT: Duration = Duration::from_secs(5);
+
+/// How long the receiver stays up after its answer, so it reaches the
+/// sender before the endpoint closes
+const ANSWER_GRACE: Duration = Duration::from_millis(500);
+
+/// Send standard input to `request.to` as one file
+pub fn send_stdin(request: &StreamSend) -> Result<()> {
+    runtime()?.block_on(async {
+        let mut node = P2pNodeBuilder::from_config(NodeConfig {
+            discovery_port: 0,
+            transfer_port: 0,
+            ..headless_config()
+        })
+        .spawn();
+        let result = stream_stdin(&mut node, request).await;
+        node.shutdown().await;
+        result
+    })
+}
+
+/// Write the next file received to standard output, listening on `port`
+pub fn receive_stdout(port: Option<u16>) -> Result<()> {
+    runtime()?.block_on(async {
+        let config = headless_config();
+        if let Some(dir) = &config.policy.download_dir {
+            return Err(anyhow!(
+                "This device's policy keeps received files in {}",
+                dir.display()
+            ));
+        }
+        // Senders find us by name only on the usual discovery port
+        let discovery_port = match UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)) {
+            Ok(_) => DISCOVERY_PORT,
+            Err(_) => {
+                eprintln!("The app has the discovery port; send to this device's address");
+                0
+            }
+        };
+        let mut node = P2pNodeBuilder::from_config(NodeConfig {
+            discovery_port,
+            transfer_port: port.unwrap_or(config.transfer_port),
+            storage: Arc::new(PipeStorage::new(tokio::io::stdout())),
+            per_peer_folders: false,
+            webhook_url: None,
+            post_receive_command: None,
+            ..config
+        })
+        .spawn();
+        let result = await_received(&mut node).await;
+        tokio::time::sleep(ANSWER_GRACE).await;
+        node.shutdown().await;
+        result
+    })
+}
+
+fn runtime() -> Result<tokio::runtime::Runtime> {
+    Ok(tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?)
+}
+
+/// The profile's settings, without what the app owns or what would corrupt
+/// standard output
+fn headless_config() -> NodeConfig {
+    let config = profile_config(NodeConfig::default());
+    NodeConfig {
+        schedule_file: None,
+        journal_file: None,
+        history_file: None,
+        orphan_age_days: 0,
+        retention: Default::default(),
+        rendezvous: Default::default(),
+        event_output: config
+            .event_output
+            .clone()
+            .filter(|output| *output != EventOutput::Stdout),
+        ..config
+    }
+}
+
+async fn stream_stdin(node: &mut P2pNode, request: &StreamSend) -> Result<()> {
+    let (target_ip, target_peer_name) = if is_address(&request.to) {
+        (request.to.clone(), request.to.clone())
+    } else {
+        find_peer(node, &request.to).await?
+    };
+    let session_id = p2p_core::new_session_id();
+    node.command(AppCommand::SendStream {
+        session_id: session_id.clone(),
+        target_ip,
+        target_peer_name: target_peer_name.clone(),
+        file_name: request.name.clone(),
+        source: StreamSource::new(tokio::io::stdin()),
+        note: request.note.clone(),
+    })
+    .await?;
+
+    let mut verified = false;
+    while let Some(event) = node.next_event().await {
+        match event {
+            AppEvent::RequestVerificationCode { session_id: id, .. } if id == session_id => {
+                node.command(AppCommand::CancelVerification { session_id })
+                    .await?;
+                return Err(anyhow!(
+                    "{} is not paired with this device; pair in the app first",
+                    target_peer_name
+                ));
+            }
+            AppEvent::VerificationCompleted {
+                is_sending: true,
+                verified: result,
+                ..
+            } => verified = result,
+            AppEvent::TransferCompleted {
+                saved_path: None, ..
+            } if verified => return Ok(()),
+            AppEvent::TransferCompleted {
+                saved_path: None, ..
+            } => return Err(anyhow!("The receiver's copy does not match")),
+            AppEvent::TransferCancelled {
+                is_sending: true,
+                reason,
+                ..
+            } => return Err(anyhow!(reason)),
+            AppEvent::Error(message) => return Err(anyhow!(message)),
+            _ => {}
+        }
+    }
+    Err(anyhow!("The transfer engine stopped"))
+}
+
+async fn await_received(node: &mut P2pNode) -> Result<()> {
+    while let Some(event) = node.next_event().await {
+        match event {
+            AppEvent::ShowVerificationCode {
+                code, from_name, ..
+            } => eprintln!("Code for {}: {}", from_name, code),
+            AppEvent::VerificationCompleted {
+                is_sending: false,
+                verified,
+                ..
+            } => {
+                return if verified {
+                    Ok(())
+                } else {
+                    Err(anyhow!("What was written does not match the sender's hash"))
+                };
+            }
+            AppEvent::TransferCancelled {
+                is_sending: false,
+                reason,
+                ..
+            } => return Err(anyhow!(reason)),
+            AppEvent::Error(message) => return Err(anyhow!(message)),
+            _ => {}
+        }
+    }
+    Err(anyhow!("The transfer engine stopped"))
+}
+
+/// Address and name of the device `to` names, by endpoint ID or
+/// (case-insensitive) name, as discovery finds it
+async fn find_peer(node: &mut P2pNode, to: &str) -> Result<(String, String)> {
+    let to = to.trim();
+    node.command(AppCommand::StartDiscovery).await?;
+    let found = tokio::time::timeout(DISCOVERY_WAIT, async {
+        while let Some(event) = node.next_event().await {
+            let found = match event {
+                AppEvent::PeerListDelta(delta) => delta.found,
+                _ => continue,
+            };
+            if let Some(peer) = found.into_iter().find(|peer| {
+                peer.endpoint_id == to
+                    || peer.display_name.eq_ignore_ascii_case(to)
+                    || peer.hostname.eq_ignore_ascii_case(to)
+            }) {
+                return Some((format!("{}:{}", peer.ip, peer.port), peer.hostname));
+            }
+        }
+        None
+    })
+    .await;
+    found
+        .ok()
+        .flatten()
+        .ok_or_else(|| anyhow!("No device named {} found on the network", to))
+}
+
+fn is_address(to: &str) -> bool {
+    to.parse::<IpAddr>().is_ok() || to.parse::<SocketAddr>().is_ok()
+}
@@ -0,0 +1,168 @@
+//! Overall transfer progress on the taskbar or dock icon.
+//!
+//! Windows fills the taskbar button, macOS puts a percentage badge on the
+//! dock icon. Other desktops have no common API for it, so the percentage
+//! goes in front of the window title, which their task lists show.
+
+/// Mean progress in percent of the transfers still running, `None` when
+/// nothing is running
+pub fn overall_progress(running: impl IntoIterator<Item = f32>) -> Option<u8> {
+    let (sum, count) = running.into_iter().fold((0.0, 0u32), |(sum, count), p| {
+        (sum + p.clamp(0.0, 100.0), count + 1)
+    });
+    (count > 0).then(|| (sum / count as f32).floor() as u8)
+}
+
+/// Keeps the icon in step with [`overall_progress`], touching the OS only
+/// when the shown percentage changes
+#[derive(Default)]
+pub struct TaskbarProgress {
+    shown: Option<u8>,
+    indicator: platform::Indicator,
+}
+
+impl TaskbarProgress {
+    pub fn update(
+        &mut self,
+        ctx: &eframe::egui::Context,
+        frame: &eframe::Frame,
+        percent: Option<u8>,
+    ) {
+        if percent != self.shown {
+            self.shown = percent;
+            self.indicator.show(ctx, frame, percent);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use raw_window_handle::{HasWindowHandle, RawWindowHandle};
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::System::Com::{
+        CLSCTX_INPROC_SERVER, COINIT_APARTMENTTHREADED, CoCreateInstance, CoInitializeEx,
+    };
+    use windows::Win32::UI::Shell::{ITaskbarList3, TBPF_NOPROGRESS, TaskbarList};
+
+    #[derive(Default)]
+    pub struct Indicator {
+        taskbar: Option<ITaskbarList3>,
+        unavailable: bool,
+    }
+
+    impl Indicator {
+        pub fn show(
+            &mut self,
+            _ctx: &eframe::egui::Context,
+            frame: &eframe::Frame,
+            percent: Option<u8>,
+        ) {
+            let Some(hwnd) = window(frame) else {
+                return;
+            };
+            let Some(taskbar) = self.taskbar() else {
+                return;
+            };
+            // SAFETY: `hwnd` is our own live window and this runs on its UI thread
+            let result = unsafe {
+                match percent {
+                    // Also leaves the no-progress state
+                    Some(percent) => taskbar.SetProgressValue(hwnd, percent as u64, 100),
+                    None => taskbar.SetProgressState(hwnd, TBPF_NOPROGRESS),
+                }
+            };
+            if let Err(e) = result {
+                tracing::debug!("Could not set taskbar progress: {}", e);
+            }
+        }
+
+        fn taskbar(&mut self) -> Option<&ITaskbarList3> {
+            if self.taskbar.is_none() && !self.unavailable {
+                // SAFETY: plain COM calls on the UI thread; COM stays initialized
+                // for the life of the thread, as winit expects anyway
+                let taskbar = unsafe {
+                    let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+                    CoCreateInstance::<_, ITaskbarList3>(&TaskbarList, None, CLSCTX_INPROC_SERVER)
+                        .and_then(|taskbar| taskbar.HrInit().map(|()| taskbar))
+                };
+                match taskbar {
+                    Ok(taskbar) => self.taskbar = Some(taskbar),
+                    Err(e) => {
+                        tracing::debug!("Taskbar progress unavailable: {}", e);
+                        self.unavailable = true;
+                    }
+                }
+            }
+            self.taskbar.as_ref()
+        }
+    }
+
+    fn window(frame: &eframe::Frame) -> Option<HWND> {
+        match frame.window_handle().ok()?.as_raw() {
+            RawWindowHandle::Win32(handle) => Some(HWND(handle.hwnd.get() as *mut _)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use objc2::MainThreadMarker;
+    use objc2_app_kit::NSApplication;
+    use objc2_foundation::NSString;
+
+    #[derive(Default)]
+    pub struct Indicator;
+
+    impl Indicator {
+        pub fn show(
+            &mut self,
+            _ctx: &eframe::egui::Context,
+            _frame: &eframe::Frame,
+            percent: Option<u8>,
+        ) {
+            let Some(mtm) = MainThreadMarker::new() else {
+                return;
+            };
+            let label = percent.map(|percent| NSString::from_str(&format!("{}%", percent)));
+            NSApplication::sharedApplication(mtm)
+                .dockTile()
+                .setBadgeLabel(label.as_deref());
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    #[derive(Default)]
+    pub struct Indicator;
+
+    impl Indicator {
+        pub fn show(
+            &mut self,
+            ctx: &eframe::egui::Context,
+            _frame: &eframe::Frame,
+            percent: Option<u8>,
+        ) {
+            let title = match percent {
+                Some(percent) => format!("{}% - {}", percent, crate::APP_TITLE),
+                None => crate::APP_TITLE.to_string(),
+            };
+            ctx.send_viewport_cmd(eframe::egui::ViewportCommand::Title(title));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overall_progress() {
+        assert_eq!(overall_progress([]), None);
+        assert_eq!(overall_progress([0.0]), Some(0));
+        assert_eq!(overall_progress([50.0, 100.0]), Some(75));
+        assert_eq!(overall_progress([33.3, 33.4, 33.4]), Some(33));
+        assert_eq!(overall_progress([-5.0, 250.0]), Some(50));
+    }
+}
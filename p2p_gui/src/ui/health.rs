@@ -0,0 +1,96 @@
+//! Status-bar lights fed by [`AppEvent::BackendHealth`](p2p_core::AppEvent::BackendHealth).
+
+use eframe::egui;
+use p2p_core::health::HEALTH_STALE_AFTER;
+use std::time::Instant;
+
+const UP: egui::Color32 = egui::Color32::from_rgb(60, 180, 75);
+const DOWN: egui::Color32 = egui::Color32::from_rgb(220, 50, 47);
+/// Services that are off by choice, like the HTTP share
+const OFF: egui::Color32 = egui::Color32::GRAY;
+
+/// Last heartbeat of the backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendHealth {
+    pub discovery_ok: bool,
+    pub quic_listening: bool,
+    pub http_running: bool,
+    pub wan_online: bool,
+    pub relay_latency_ms: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct HealthState {
+    last: Option<(BackendHealth, Instant)>,
+}
+
+impl HealthState {
+    pub fn record(&mut self, health: BackendHealth) {
+        self.last = Some((health, Instant::now()));
+    }
+}
+
+fn light(ui: &mut egui::Ui, color: egui::Color32, label: &str, hover: &str) {
+    ui.colored_label(color, format!("● {}", label))
+        .on_hover_text(hover);
+}
+
+pub fn show(ui: &mut egui::Ui, state: &HealthState) {
+    let Some((health, at)) = state.last else {
+        ui.weak("Backend starting...");
+        return;
+    };
+    // Notice a missing heartbeat even when nothing else repaints
+    ui.ctx().request_repaint_after(HEALTH_STALE_AFTER);
+    if at.elapsed() > HEALTH_STALE_AFTER {
+        ui.colored_label(DOWN, "● Backend not responding")
+            .on_hover_text(format!("No heartbeat for {}s", at.elapsed().as_secs()));
+        return;
+    }
+
+    let up_or_down = |up| if up { UP } else { DOWN };
+    light(
+        ui,
+        up_or_down(health.discovery_ok),
+        "LAN",
+        if health.discovery_ok {
+            "Discovery is broadcasting"
+        } else {
+            "Discovery is not running; peers will not appear"
+        },
+    );
+    light(
+        ui,
+        up_or_down(health.quic_listening),
+        "QUIC",
+        if health.quic_listening {
+            "Accepting transfers"
+        } else {
+            "The transfer server stopped; nothing can be received"
+        },
+    );
+    light(
+        ui,
+        if health.http_running { UP } else { OFF },
+        "HTTP",
+        if health.http_running {
+            "Share server running"
+        } else {
+            "Share server stopped"
+        },
+    );
+    let wan = match health.relay_latency_ms {
+        Some(ms) if health.wan_online => format!("WAN {} ms", ms),
+        _ => "WAN".to_string(),
+    };
+    light(
+        ui,
+        up_or_down(health.wan_online),
+        &wan,
+        if health.wan_online {
+            "Connected to the relay"
+        } else {
+            "No relay connection; WAN peers cannot reach this device"
+        },
+    );
+}
@@ -1,2 +1,4 @@
+pub mod health;
 pub mod toolbar;
+pub mod update_banner;
 pub mod windows;
@@ -1,6 +1,12 @@
 use crate::app::AppUIState;
 use eframe::egui;
-use egui_phosphor::regular::{DESKTOP_TOWER, FOLDER_SIMPLE, GLOBE, QR_CODE};
+use egui_phosphor::regular::{
+    CELL_SIGNAL_HIGH, CLOCK, CLOCK_COUNTER_CLOCKWISE, DESKTOP_TOWER, FOLDER_SIMPLE, GLOBE, QR_CODE,
+    SHIELD, X,
+};
+use p2p_core::network_info::MeteredMode;
+use p2p_core::telemetry::TelemetryMode;
+use p2p_core::transfer::HashAlgorithm;
 
 pub fn show(ctx: &egui::Context, state: &mut AppUIState) {
     egui::SidePanel::right("right_toolbar")
@@ -20,7 +26,14 @@ pub fn show(ctx: &egui::Context, state: &mut AppUIState) {
 
                 // WAN Connect button
                 if ui
-                    .selectable_label(state.show_wan_connect, format!("{} WAN", GLOBE))
+                    .add_enabled(
+                        !state.policy.disable_wan_share,
+                        egui::Button::selectable(
+                            state.show_wan_connect,
+                            format!("{} WAN", GLOBE),
+                        ),
+                    )
+                    .on_disabled_hover_text("Turned off by your administrator")
                     .clicked()
                 {
                     state.show_wan_connect = !state.show_wan_connect;
@@ -33,6 +46,15 @@ pub fn show(ctx: &egui::Context, state: &mut AppUIState) {
                 {
                     state.show_files = !state.show_files;
                 }
+                if ui
+                    .selectable_label(
+                        state.show_history,
+                        format!("{} History", CLOCK_COUNTER_CLOCKWISE),
+                    )
+                    .clicked()
+                {
+                    state.show_history = !state.show_history;
+                }
                 //QR code button
                 if ui
                     .selectable_label(state.show_qrcode, format!("{} QR Code", QR_CODE))
@@ -40,6 +62,121 @@ pub fn show(ctx: &egui::Context, state: &mut AppUIState) {
                 {
                     state.show_qrcode = !state.show_qrcode;
                 }
+                // Scheduled sends button
+                if ui
+                    .selectable_label(state.show_scheduled, format!("{} Scheduled", CLOCK))
+                    .clicked()
+                {
+                    state.show_scheduled = !state.show_scheduled;
+                }
+                if ui
+                    .selectable_label(state.show_proxy, format!("{} Proxy", SHIELD))
+                    .clicked()
+                {
+                    state.show_proxy = !state.show_proxy;
+                }
+                #[cfg(feature = "profiling")]
+                if ui
+                    .selectable_label(
+                        state.show_profiler,
+                        format!("{} Profiler", egui_phosphor::regular::GAUGE),
+                    )
+                    .clicked()
+                {
+                    state.show_profiler = !state.show_profiler;
+                }
+
+                ui.separator();
+                ui.label("Units");
+                ui.checkbox(&mut state.units.binary, "Binary (MiB)");
+                ui.checkbox(&mut state.units.bits, "Bits (Mbit/s)");
+
+                ui.separator();
+                ui.label("Integrity check");
+                egui::ComboBox::from_id_salt("hash_algorithm")
+                    .selected_text(state.hash_algorithm.label())
+                    .show_ui(ui, |ui| {
+                        for algorithm in HashAlgorithm::ALL {
+                            ui.selectable_value(
+                                &mut state.hash_algorithm,
+                                algorithm,
+                                algorithm.label(),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text("Checksum for files sent from here; xxHash is fastest but only catches accidental damage");
+
+                ui.separator();
+                ui.label(if state.metered {
+                    format!("{} Metered", CELL_SIGNAL_HIGH)
+                } else {
+                    "Metered connection".to_string()
+                });
+                egui::ComboBox::from_id_salt("metered_mode")
+                    .selected_text(state.metered_mode.label())
+                    .show_ui(ui, |ui| {
+                        for mode in MeteredMode::ALL {
+                            ui.selectable_value(&mut state.metered_mode, mode, mode.label());
+                        }
+                    })
+                    .response
+                    .on_hover_text("While metered, sends are slowed, large ones ask first and the WAN share is stopped");
+
+                ui.separator();
+                ui.add_enabled(
+                    crate::os_auth::SUPPORTED,
+                    egui::Checkbox::new(&mut state.confirm_sensitive, "Confirm sensitive actions"),
+                )
+                .on_hover_text("Ask for Windows Hello or the system password before forgetting a device or exporting the history")
+                .on_disabled_hover_text("Not supported on this system");
+                ui.checkbox(&mut state.update_check.enabled, "Check for updates")
+                    .on_hover_text("Once a day, ask the releases page for the latest version; nothing about this device is sent");
+
+                ui.separator();
+                ui.label("Extra fonts");
+                let mut removed = None;
+                for (i, path) in state.user_fonts.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.small_button(X).on_hover_text("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                        let name = path.file_name().unwrap_or(path.as_os_str());
+                        ui.label(name.to_string_lossy())
+                            .on_hover_text(path.display().to_string());
+                    });
+                }
+                if let Some(i) = removed {
+                    state.user_fonts.remove(i);
+                }
+                if ui
+                    .button("Add font...")
+                    .on_hover_text("For names in scripts the fonts found on this system lack")
+                    .clicked()
+                {
+                    state.pick_font = true;
+                }
+
+                ui.separator();
+                ui.label("Usage statistics");
+                egui::ComboBox::from_id_salt("telemetry_mode")
+                    .selected_text(state.telemetry_mode.label())
+                    .show_ui(ui, |ui| {
+                        for mode in TelemetryMode::ALL {
+                            ui.selectable_value(&mut state.telemetry_mode, mode, mode.label());
+                        }
+                    })
+                    .response
+                    .on_hover_text("Anonymous counts of the features used and errors seen, to help decide what to work on; every report is shown before it is sent");
+                if ui
+                    .add_enabled(
+                        state.telemetry_mode != TelemetryMode::Off,
+                        egui::Button::new("Show what is counted"),
+                    )
+                    .clicked()
+                {
+                    state.preview_telemetry = true;
+                }
             });
         });
 }
@@ -0,0 +1,43 @@
+//! "New version available" strip above the transfers.
+
+use crate::update::{UpdateChecker, UpdateSettings};
+use eframe::egui;
+use egui_phosphor::regular::{ARROW_CIRCLE_UP, X};
+
+pub fn show(ctx: &egui::Context, checker: &mut UpdateChecker, settings: &mut UpdateSettings) {
+    let Some(release) = checker.banner(settings).cloned() else {
+        return;
+    };
+    egui::TopBottomPanel::top("update_banner").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} Version {} is available (you have {})",
+                ARROW_CIRCLE_UP,
+                release.version,
+                env!("CARGO_PKG_VERSION")
+            ));
+            if !release.notes.trim().is_empty() {
+                ui.toggle_value(&mut checker.show_notes, "Release notes");
+            }
+            if !release.url.is_empty() {
+                ui.hyperlink_to("Download", &release.url);
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui
+                    .small_button(X)
+                    .on_hover_text("Hide until the next version")
+                    .clicked()
+                {
+                    settings.dismissed = Some(release.version.to_string());
+                }
+            });
+        });
+        if checker.show_notes {
+            ui.separator();
+            ui.strong(&release.name);
+            egui::ScrollArea::vertical()
+                .max_height(160.0)
+                .show(ui, |ui| ui.label(&release.notes));
+        }
+    });
+}
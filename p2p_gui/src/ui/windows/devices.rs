@@ -1,60 +1,1152 @@
+use super::send_preview::SendPreview;
+use crate::bridge::{CommandBridge, DialogResult, FileDialogTask};
+use crate::os_auth::SensitiveGate;
 use eframe::egui;
-use egui_phosphor::regular::{DESKTOP, PAPER_PLANE_RIGHT};
+use egui_phosphor::regular::{
+    CHAT_TEXT, CLOCK, DESKTOP, EYE, GLOBE, HANDSHAKE, INFO, MAGNIFYING_GLASS, PAPER_PLANE_RIGHT,
+    PUSH_PIN, TAG, TRASH, TRUCK, USERS_THREE, WARNING,
+};
 use p2p_core::AppCommand;
-use tokio::sync::mpsc;
+use p2p_core::groups::{GroupTarget, PeerGroup};
+use p2p_core::peers::PeerRegistry;
+use p2p_core::transfer::SendEstimate;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Instant;
 
+/// Marks receive-only peers in the device list
+pub const RECEIVE_ONLY_TAG: &str = "[receive only]";
+
+/// Marks peers paired as guests, for one transfer
+pub const GUEST_TAG: &str = "[guest]";
+
+/// Default delay offered by "Send Later"
+const DEFAULT_DELAY_MINUTES: u8 = 30;
+
+/// Transfers remembered for the detail pane, across all peers
+const MAX_HISTORY: usize = 200;
+
+/// A peer found on the LAN
+pub struct PeerEntry {
+    pub endpoint_id: String,
+    pub ip: String,
+    pub port: u16,
+    /// Name the peer announced; received files are reported under it
+    pub hostname: String,
+    /// Hostname, disambiguated by the backend if it is not unique
+    pub display_name: String,
+    /// Drop-box peer: it accepts files but never sends
+    pub receive_only: bool,
+    /// Version the peer announced, `None` for versions before announcing
+    pub version: Option<String>,
+    /// Protocol version the peer announced, 0 for versions before announcing
+    pub protocol: u32,
+    /// Discovery round-trip time, once measured
+    pub rtt_ms: Option<u32>,
+    pub last_seen: Instant,
+}
+
+/// One file sent to or received from a peer in this session
+struct PeerTransfer {
+    peer: String,
+    file_name: String,
+    sent: bool,
+    at: Instant,
+}
+
+/// Files for a known device, sent over whichever of the LAN and the WAN
+/// answers (see [`p2p_core::path`])
+pub struct AutoSend {
+    pub endpoint_id: String,
+    pub name: String,
+    /// Transfer address while the device is on the LAN
+    pub lan: Option<SocketAddr>,
+    pub files: Vec<PathBuf>,
+    pub note: Option<String>,
+}
+
+/// Members of a group send that have finished so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupProgress {
+    pub sent: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+/// File dialog opened for a whole group
+struct GroupPick {
+    group: String,
+    dialog: FileDialogTask,
+    note: Option<String>,
+}
+
+/// File dialog opened for a specific peer
+struct PendingPick {
+    endpoint_id: String,
+    name: String,
+    /// Empty for a device that is not on the LAN
+    ip: String,
+    dialog: FileDialogTask,
+    purpose: PickPurpose,
+    /// Note typed before the dialog opened
+    note: Option<String>,
+}
+
+/// What happens to the files picked for a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PickPurpose {
+    Send,
+    /// Send, then delete here once the peer verified them
+    Move,
+    /// Schedule instead of sending right away
+    Later,
+    /// Ask the peer what it already has, then send once confirmed
+    Preview,
+}
+
+/// Files picked for "Send Later", waiting for a start time
+struct ScheduleForm {
+    name: String,
+    ip: String,
+    files: Vec<std::path::PathBuf>,
+    hours: u8,
+    minutes: u8,
+}
+
+#[derive(Default)]
+pub struct DevicesState {
+    pending_pick: Option<PendingPick>,
+    group_pick: Option<GroupPick>,
+    schedule_form: Option<ScheduleForm>,
+    /// This device runs in receive-only mode and cannot send
+    pub receive_only: bool,
+    /// Endpoint ID of the peer shown in the detail pane
+    details: Option<String>,
+    text_draft: String,
+    /// Note or tag for the next files sent, e.g. "invoices Q3"
+    note_draft: String,
+    /// Peers that may send here without a code
+    paired: HashSet<String>,
+    /// The paired peers that are guests, for one transfer
+    guests: HashSet<String>,
+    /// New pairings are guest pairings
+    pub guest_mode: bool,
+    pinned: HashSet<String>,
+    groups: Vec<PeerGroup>,
+    /// Latest progress of the last send to each group, by group name
+    group_progress: HashMap<String, GroupProgress>,
+    /// Name typed for a new group in the detail pane
+    group_draft: String,
+    history: Vec<PeerTransfer>,
+    /// List the most responsive peers first instead of by name
+    pub sort_by_latency: bool,
+    /// Only list peers whose name, IP or endpoint ID contains this
+    pub search: String,
+    /// Sends to known devices waiting for a path to be picked
+    auto_sends: Vec<AutoSend>,
+    /// Sends waiting for the receiver's estimate and the user's go-ahead
+    pub previews: Vec<SendPreview>,
+}
+
+impl DevicesState {
+    /// Update from [`p2p_core::AppEvent::KnownPeersChanged`]
+    pub fn set_known_peers(
+        &mut self,
+        paired: Vec<String>,
+        guests: Vec<String>,
+        pinned: Vec<String>,
+        groups: Vec<PeerGroup>,
+    ) {
+        self.paired = paired.into_iter().collect();
+        self.guests = guests.into_iter().collect();
+        self.pinned = pinned.into_iter().collect();
+        self.groups = groups;
+    }
+
+    /// Update from [`p2p_core::AppEvent::GroupSendProgress`] and
+    /// [`p2p_core::AppEvent::GroupSendFinished`]
+    pub fn set_group_progress(&mut self, group: String, progress: GroupProgress) {
+        self.group_progress.insert(group, progress);
+    }
+
+    /// Attach [`p2p_core::AppEvent::SendEstimated`] to its preview; false
+    /// if no preview asked for it
+    pub fn set_estimate(&mut self, session_id: &str, estimate: SendEstimate) -> bool {
+        let Some(preview) = self
+            .previews
+            .iter_mut()
+            .find(|preview| preview.session_id == session_id)
+        else {
+            return false;
+        };
+        preview.estimate = Some(estimate);
+        true
+    }
+
+    /// Sends to known devices picked since the last call
+    pub fn take_auto_sends(&mut self) -> Vec<AutoSend> {
+        std::mem::take(&mut self.auto_sends)
+    }
+
+    /// Remember a file received from `peer` (hostname or endpoint ID)
+    pub fn record_received(&mut self, peer: String, file_name: String) {
+        self.record(peer, file_name, false);
+    }
+
+    fn record(&mut self, peer: String, file_name: String, sent: bool) {
+        if self.history.len() >= MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.history.push(PeerTransfer {
+            peer,
+            file_name,
+            sent,
+            at: Instant::now(),
+        });
+    }
+
+    /// Peers matching the search in display order: pinned first, then by
+    /// name or, when sorting by latency, fastest first
+    fn sorted<'a>(&self, peers: &'a HashMap<String, PeerEntry>) -> Vec<&'a PeerEntry> {
+        let search = self.search.trim().to_lowercase();
+        let mut sorted: Vec<_> = peers
+            .values()
+            .filter(|peer| {
+                [
+                    &peer.display_name,
+                    &peer.hostname,
+                    &peer.ip,
+                    &peer.endpoint_id,
+                ]
+                .iter()
+                .any(|field| field.to_lowercase().contains(&search))
+            })
+            .collect();
+        sorted.sort_by(|a, b| {
+            let unpinned = |peer: &PeerEntry| !self.pinned.contains(&peer.endpoint_id);
+            // Unmeasured peers go last
+            let latency = |peer: &PeerEntry| {
+                self.sort_by_latency
+                    .then(|| peer.rtt_ms.unwrap_or(u32::MAX))
+            };
+            (unpinned(a), latency(a), &a.display_name, &a.ip).cmp(&(
+                unpinned(b),
+                latency(b),
+                &b.display_name,
+                &b.ip,
+            ))
+        });
+        sorted
+    }
+}
+
+/// Why a send to `peer` may fail, when it runs another version than this
+/// device
+fn version_warning(peer: &PeerEntry) -> Option<String> {
+    let protocol = p2p_core::alpn::PROTOCOL_VERSION;
+    if peer.protocol != 0 && peer.protocol != protocol {
+        return Some(format!(
+            "Speaks protocol {}, this device {}; transfers will fail",
+            peer.protocol, protocol
+        ));
+    }
+    match &peer.version {
+        Some(version) if version == p2p_core::APP_VERSION => None,
+        Some(version) => Some(format!(
+            "Runs version {}, this device {}; transfers may fail",
+            version,
+            p2p_core::APP_VERSION
+        )),
+        None => Some(format!(
+            "Runs a version older than {}; transfers may fail",
+            p2p_core::APP_VERSION
+        )),
+    }
+}
+
+/// "Name [receive only] (IP)"
+fn peer_label(peer: &PeerEntry) -> String {
+    if peer.receive_only {
+        format!("{} {} ({})", peer.display_name, RECEIVE_ONLY_TAG, peer.ip)
+    } else {
+        format!("{} ({})", peer.display_name, peer.ip)
+    }
+}
+
+/// Draw the device list. `registry` holds the known devices, `None` when
+/// the WAN is turned off and every send goes over the LAN.
 pub fn show(
     ctx: &egui::Context,
     open: &mut bool,
-    peers: &[String],
-    cmd_tx: &mpsc::Sender<AppCommand>,
+    state: &mut DevicesState,
+    peers: &HashMap<String, PeerEntry>,
+    registry: Option<&PeerRegistry>,
+    gate: &mut SensitiveGate,
+    cmd_tx: &CommandBridge,
 ) {
+    poll_pending_pick(state, registry, cmd_tx);
+    poll_group_pick(state, peers, cmd_tx);
+
     egui::Window::new("Devices")
         .open(open)
         .resizable(true)
         .default_size([300.0, 200.0])
         .min_size([200.0, 150.0])
         .show(ctx, |ui| {
-            ui.label("Devices found on LAN:");
+            ui.horizontal(|ui| {
+                ui.label("Devices found on LAN:");
+                ui.checkbox(&mut state.sort_by_latency, "Fastest first")
+                    .on_hover_text("Sort by discovery round-trip time");
+                if ui
+                    .checkbox(&mut state.guest_mode, "Pair as guests")
+                    .on_hover_text(
+                        "Devices that pair now may send here once, within an hour, then need a code again",
+                    )
+                    .changed()
+                {
+                    cmd_tx.send(AppCommand::SetGuestPairing {
+                        enabled: state.guest_mode,
+                    });
+                }
+            });
             ui.separator();
 
+            if state.receive_only {
+                ui.label("Receive-only mode: this device does not send files.");
+                ui.separator();
+            } else {
+                ui.horizontal(|ui| {
+                    ui.label(TAG);
+                    ui.add(
+                        egui::TextEdit::singleline(&mut state.note_draft)
+                            .hint_text("Note for the next files, e.g. invoices Q3")
+                            .char_limit(p2p_core::transfer::metadata::MAX_NOTE_CHARS),
+                    )
+                    .on_hover_text("Shown to the receiver and kept in its history");
+                });
+                ui.separator();
+            }
+
             if peers.is_empty() {
                 ui.label("Searching...");
             } else {
-                for peer in peers {
+                ui.horizontal(|ui| {
+                    ui.label(MAGNIFYING_GLASS);
+                    ui.add(
+                        egui::TextEdit::singleline(&mut state.search)
+                            .hint_text("Filter by name, IP or ID"),
+                    );
+                });
+                let can_send = can_send(state);
+                let sorted = state.sorted(peers);
+                if sorted.len() < peers.len() {
+                    ui.weak(format!("{} of {} devices", sorted.len(), peers.len()));
+                }
+                // Only the visible rows are laid out, however many peers
+                // the LAN has
+                let row_height = ui.spacing().interact_size.y;
+                egui::ScrollArea::vertical()
+                    .id_salt("device_list")
+                    .max_height(320.0)
+                    .auto_shrink([false, true])
+                    .show_rows(ui, row_height, sorted.len(), |ui, rows| {
+                        for peer in &sorted[rows] {
+                            ui.horizontal(|ui| peer_row(ctx, ui, state, peer, can_send));
+                        }
+                    });
+            }
+
+            if !state.receive_only {
+                show_groups(ctx, ui, state);
+            }
+
+            if let Some(registry) = registry {
+                show_away(ctx, ui, state, registry);
+            }
+
+            show_schedule_form(ui, state, cmd_tx);
+        });
+
+    show_details(ctx, state, peers, gate, cmd_tx);
+}
+
+/// One line of the device list
+fn peer_row(
+    ctx: &egui::Context,
+    ui: &mut egui::Ui,
+    state: &mut DevicesState,
+    peer: &PeerEntry,
+    can_send: bool,
+) {
+    ui.label(if state.pinned.contains(&peer.endpoint_id) {
+        PUSH_PIN
+    } else {
+        DESKTOP
+    });
+    ui.label(peer_label(peer));
+    if state.guests.contains(&peer.endpoint_id) {
+        ui.weak(GUEST_TAG)
+            .on_hover_text("Paired as a guest, for one transfer");
+    }
+    if let Some(rtt_ms) = peer.rtt_ms {
+        ui.weak(format!("{} ms", rtt_ms));
+    }
+    let warning = version_warning(peer);
+    if let Some(warning) = &warning {
+        ui.colored_label(ui.visuals().warn_fg_color, WARNING)
+            .on_hover_text(warning);
+    }
+    let send = ui.add_enabled(
+        can_send,
+        egui::Button::new(format!("{} Send Files", PAPER_PLANE_RIGHT)),
+    );
+    let send = match &warning {
+        Some(warning) => send.on_hover_text(warning),
+        None => send,
+    };
+    if send.clicked() {
+        pick_files(ctx, state, peer, PickPurpose::Send);
+    }
+    if ui
+        .add_enabled(can_send, egui::Button::new(CLOCK))
+        .on_hover_text("Send Later")
+        .clicked()
+    {
+        pick_files(ctx, state, peer, PickPurpose::Later);
+    }
+    if ui.button(INFO).on_hover_text("Details").clicked() {
+        state.details = Some(peer.endpoint_id.clone());
+    }
+}
+
+fn can_send(state: &DevicesState) -> bool {
+    let picking =
+        state.pending_pick.is_some() || state.group_pick.is_some() || state.schedule_form.is_some();
+    !picking && !state.receive_only
+}
+
+/// One target per member of `group`; members not on the LAN get no
+/// address and are reported as failed by the backend
+fn group_targets(group: &PeerGroup, peers: &HashMap<String, PeerEntry>) -> Vec<GroupTarget> {
+    group
+        .members
+        .iter()
+        .map(|endpoint_id| {
+            let peer = peers.values().find(|peer| &peer.endpoint_id == endpoint_id);
+            GroupTarget {
+                session_id: p2p_core::new_session_id(),
+                endpoint_id: endpoint_id.clone(),
+                target_peer_name: peer
+                    .map(|peer| peer.hostname.clone())
+                    .unwrap_or_else(|| endpoint_id.clone()),
+                target_ip: peer.map(|peer| peer.ip.clone()),
+            }
+        })
+        .collect()
+}
+
+/// Peer groups, each with its send button and the progress of its last send
+fn show_groups(ctx: &egui::Context, ui: &mut egui::Ui, state: &mut DevicesState) {
+    if state.groups.is_empty() {
+        return;
+    }
+    ui.separator();
+    ui.label("Groups:");
+    let can_send = can_send(state);
+    let mut picked = None;
+    for group in &state.groups {
+        ui.horizontal(|ui| {
+            ui.label(USERS_THREE);
+            ui.label(format!("{} ({})", group.name, group.members.len()));
+            if let Some(progress) = state.group_progress.get(&group.name) {
+                let done = progress.sent + progress.failed;
+                let text = format!("{}/{} sent", progress.sent, progress.total);
+                if progress.failed > 0 {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!("{}, {} failed", text, progress.failed),
+                    );
+                } else {
+                    ui.weak(text);
+                }
+                if done < progress.total {
+                    ui.spinner();
+                }
+            }
+            if ui
+                .add_enabled(
+                    can_send,
+                    egui::Button::new(format!("{} Send to Group", PAPER_PLANE_RIGHT)),
+                )
+                .clicked()
+            {
+                picked = Some(group.name.clone());
+            }
+        });
+    }
+    if let Some(group) = picked {
+        state.group_pick = Some(GroupPick {
+            group,
+            dialog: FileDialogTask::pick_files(ctx),
+            note: note(state),
+        });
+    }
+}
+
+/// Send to every member once the dialog for a group has closed
+fn poll_group_pick(
+    state: &mut DevicesState,
+    peers: &HashMap<String, PeerEntry>,
+    cmd_tx: &CommandBridge,
+) {
+    let Some(pending) = &state.group_pick else {
+        return;
+    };
+    let files = match pending.dialog.poll() {
+        DialogResult::Pending => return,
+        DialogResult::Cancelled => {
+            state.group_pick = None;
+            return;
+        }
+        DialogResult::Picked(files) => files,
+    };
+    let Some(GroupPick { group, note, .. }) = state.group_pick.take() else {
+        return;
+    };
+    let Some(members) = state
+        .groups
+        .iter()
+        .find(|candidate| candidate.name == group)
+        .map(|group| group_targets(group, peers))
+    else {
+        return;
+    };
+    state.group_progress.remove(&group);
+    cmd_tx.send(AppCommand::SendToGroup {
+        group,
+        members,
+        files,
+        note,
+    });
+}
+
+/// Known devices that are not on the LAN, reached over the WAN
+fn show_away(
+    ctx: &egui::Context,
+    ui: &mut egui::Ui,
+    state: &mut DevicesState,
+    registry: &PeerRegistry,
+) {
+    let away = registry.away();
+    if away.is_empty() {
+        return;
+    }
+    ui.separator();
+    ui.label("Your devices elsewhere:");
+    let can_send = can_send(state);
+    for device in away {
+        ui.horizontal(|ui| {
+            ui.label(GLOBE);
+            ui.label(&device.name);
+            ui.weak("over the internet");
+            if ui
+                .add_enabled(
+                    can_send,
+                    egui::Button::new(format!("{} Send Files", PAPER_PLANE_RIGHT)),
+                )
+                .clicked()
+            {
+                state.pending_pick = Some(PendingPick {
+                    endpoint_id: device.endpoint_id.clone(),
+                    name: device.name.clone(),
+                    ip: String::new(),
+                    dialog: FileDialogTask::pick_files(ctx),
+                    purpose: PickPurpose::Send,
+                    note: note(state),
+                });
+            }
+        });
+    }
+}
+
+/// Note typed for the next files, if any
+fn note(state: &DevicesState) -> Option<String> {
+    Some(state.note_draft.trim())
+        .filter(|note| !note.is_empty())
+        .map(str::to_string)
+}
+
+fn pick_files(
+    ctx: &egui::Context,
+    state: &mut DevicesState,
+    peer: &PeerEntry,
+    purpose: PickPurpose,
+) {
+    state.pending_pick = Some(PendingPick {
+        endpoint_id: peer.endpoint_id.clone(),
+        name: peer.hostname.clone(),
+        ip: peer.ip.clone(),
+        dialog: FileDialogTask::pick_files(ctx),
+        purpose,
+        note: note(state),
+    });
+}
+
+/// Detail pane of one peer, with its transfers and actions
+fn show_details(
+    ctx: &egui::Context,
+    state: &mut DevicesState,
+    peers: &HashMap<String, PeerEntry>,
+    gate: &mut SensitiveGate,
+    cmd_tx: &CommandBridge,
+) {
+    let Some(endpoint_id) = state.details.clone() else {
+        return;
+    };
+    // The pane closes when the peer goes away
+    let Some(peer) = peers.values().find(|peer| peer.endpoint_id == endpoint_id) else {
+        state.details = None;
+        return;
+    };
+
+    let mut open = true;
+    egui::Window::new(format!("{} {}", DESKTOP, peer.display_name))
+        .id(egui::Id::new("peer_details"))
+        .open(&mut open)
+        .resizable(true)
+        .default_size([340.0, 320.0])
+        .show(ctx, |ui| {
+            let paired = state.paired.contains(&peer.endpoint_id);
+            let pinned = state.pinned.contains(&peer.endpoint_id);
+            egui::Grid::new("peer_details_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Endpoint ID:");
                     ui.horizontal(|ui| {
-                        ui.label(DESKTOP);
-                        ui.label(peer);
-                        if ui
-                            .button(format!("{} Send Files", PAPER_PLANE_RIGHT))
-                            .clicked()
-                        {
-                            let cmd_tx = cmd_tx.clone();
-                            let peer_str = peer.clone();
-
-                            // Spawn a thread for file dialog to avoid blocking the UI
-                            std::thread::spawn(move || {
-                                if let Some(files) = rfd::FileDialog::new().pick_files() {
-                                    // Extract IP from "Hostname (IP)"
-                                    if let Some(start) = peer_str.rfind('(')
-                                        && let Some(end) = peer_str.rfind(')')
-                                        && start < end
-                                    {
-                                        let ip = peer_str[start + 1..end].to_string();
-                                        let name = peer_str[..start].trim().to_string();
-
-                                        let _ = cmd_tx.blocking_send(AppCommand::SendFile {
-                                            target_ip: ip,
-                                            target_endpoint_id: String::new(),
-                                            target_peer_name: name,
-                                            files,
-                                        });
-                                    }
-                                }
-                            });
+                        ui.monospace(&peer.endpoint_id);
+                        if ui.small_button("Copy").clicked() {
+                            ctx.copy_text(peer.endpoint_id.clone());
                         }
                     });
+                    ui.end_row();
+                    ui.label("Address:");
+                    ui.monospace(format!("{}:{}", peer.ip, peer.port));
+                    ui.end_row();
+                    ui.label("Hostname:");
+                    ui.label(&peer.hostname);
+                    ui.end_row();
+                    ui.label("Pairing:");
+                    ui.label(if state.guests.contains(&peer.endpoint_id) {
+                        "Guest, can send here once without a code"
+                    } else if paired {
+                        "Paired, can send here without a code"
+                    } else {
+                        "Not paired"
+                    });
+                    ui.end_row();
+                    ui.label("Last seen:");
+                    ui.label(format!("{}s ago", peer.last_seen.elapsed().as_secs()));
+                    ui.end_row();
+                    ui.label("Capabilities:");
+                    ui.label(if peer.receive_only {
+                        "Receives only"
+                    } else {
+                        "Sends and receives"
+                    });
+                    ui.end_row();
+                    ui.label("Version:");
+                    match version_warning(peer) {
+                        Some(warning) => ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("{} {}", WARNING, warning),
+                        ),
+                        None => ui.label(p2p_core::APP_VERSION),
+                    };
+                    ui.end_row();
+                    ui.label("Latency:");
+                    ui.label(match peer.rtt_ms {
+                        Some(rtt_ms) => format!("{} ms", rtt_ms),
+                        None => "Not measured yet".to_string(),
+                    });
+                    ui.end_row();
+                });
+
+            ui.separator();
+            let can_send = can_send(state);
+            ui.horizontal_wrapped(|ui| {
+                if ui
+                    .add_enabled(
+                        can_send,
+                        egui::Button::new(format!("{} Send Files", PAPER_PLANE_RIGHT)),
+                    )
+                    .clicked()
+                {
+                    pick_files(ctx, state, peer, PickPurpose::Send);
+                }
+                if ui
+                    .add_enabled(can_send, egui::Button::new(format!("{} Move Files", TRUCK)))
+                    .on_hover_text("Delete the files here once the device has verified its copy")
+                    .clicked()
+                {
+                    pick_files(ctx, state, peer, PickPurpose::Move);
+                }
+                if ui
+                    .add_enabled(can_send, egui::Button::new(format!("{} Preview", EYE)))
+                    .on_hover_text("See what the device already has before sending")
+                    .clicked()
+                {
+                    pick_files(ctx, state, peer, PickPurpose::Preview);
+                }
+                if ui
+                    .add_enabled(
+                        !state.receive_only,
+                        egui::Button::new(format!("{} Pair Now", HANDSHAKE)),
+                    )
+                    .on_hover_text("Verify with a code now, so later sends go straight through")
+                    .clicked()
+                {
+                    cmd_tx.send(AppCommand::PairWithPeer {
+                        session_id: p2p_core::new_session_id(),
+                        target_ip: peer.ip.clone(),
+                        target_peer_name: peer.hostname.clone(),
+                    });
+                }
+                if ui
+                    .add_enabled(
+                        paired && !gate.busy(),
+                        egui::Button::new(format!("{} Forget", TRASH)),
+                    )
+                    .on_hover_text("Ask this device for a code again before accepting its files")
+                    .clicked()
+                {
+                    gate.send(
+                        ctx,
+                        cmd_tx,
+                        &format!("Forget the pairing with {}", peer.display_name),
+                        AppCommand::ForgetPeer {
+                            endpoint_id: peer.endpoint_id.clone(),
+                        },
+                    );
                 }
+                if ui
+                    .selectable_label(pinned, format!("{} Pin", PUSH_PIN))
+                    .on_hover_text("Keep at the top of the list")
+                    .clicked()
+                {
+                    cmd_tx.send(AppCommand::SetPeerPinned {
+                        endpoint_id: peer.endpoint_id.clone(),
+                        pinned: !pinned,
+                    });
+                }
+            });
+
+            show_membership(ui, state, &peer.endpoint_id, cmd_tx);
+
+            ui.add_space(4.0);
+            ui.add(
+                egui::TextEdit::multiline(&mut state.text_draft)
+                    .hint_text("Text to send")
+                    .desired_rows(2)
+                    .desired_width(f32::INFINITY),
+            );
+            if ui
+                .add_enabled(
+                    can_send && !state.text_draft.trim().is_empty(),
+                    egui::Button::new(format!("{} Send Text", CHAT_TEXT)),
+                )
+                .clicked()
+            {
+                cmd_tx.send(AppCommand::SendText {
+                    session_id: p2p_core::new_session_id(),
+                    target_ip: peer.ip.clone(),
+                    target_peer_name: peer.hostname.clone(),
+                    text: std::mem::take(&mut state.text_draft),
+                });
+                state.record(peer.hostname.clone(), "(text)".to_string(), true);
+            }
+
+            ui.separator();
+            ui.label("Transfers this session:");
+            let mut any = false;
+            egui::ScrollArea::vertical()
+                .max_height(120.0)
+                .show(ui, |ui| {
+                    for transfer in state.history.iter().rev().filter(|transfer| {
+                        transfer.peer == peer.hostname || transfer.peer == peer.endpoint_id
+                    }) {
+                        any = true;
+                        ui.label(format!(
+                            "{} {} ({}s ago)",
+                            if transfer.sent { "Sent" } else { "Received" },
+                            transfer.file_name,
+                            transfer.at.elapsed().as_secs()
+                        ));
+                    }
+                });
+            if !any {
+                ui.weak("None yet");
             }
         });
+    if !open {
+        state.details = None;
+    }
+}
+
+/// Groups the peer `endpoint_id` is in, toggled one by one, and a field to
+/// start a new group with it
+fn show_membership(
+    ui: &mut egui::Ui,
+    state: &mut DevicesState,
+    endpoint_id: &str,
+    cmd_tx: &CommandBridge,
+) {
+    ui.horizontal_wrapped(|ui| {
+        ui.label(format!("{} Groups:", USERS_THREE));
+        for group in &state.groups {
+            let member = group.members.iter().any(|id| id == endpoint_id);
+            if ui.selectable_label(member, &group.name).clicked() {
+                cmd_tx.send(AppCommand::SetPeerGroup {
+                    name: group.name.clone(),
+                    members: toggled(&group.members, endpoint_id),
+                });
+            }
+        }
+        ui.add(
+            egui::TextEdit::singleline(&mut state.group_draft)
+                .hint_text("New group")
+                .desired_width(100.0)
+                .char_limit(p2p_core::groups::MAX_GROUP_NAME_CHARS),
+        );
+        let name = state.group_draft.trim().to_string();
+        if ui
+            .add_enabled(!name.is_empty(), egui::Button::new("Add"))
+            .clicked()
+        {
+            let members = state
+                .groups
+                .iter()
+                .find(|group| group.name == name)
+                .map(|group| group.members.clone())
+                .unwrap_or_default();
+            let members = if members.iter().any(|id| id == endpoint_id) {
+                members
+            } else {
+                toggled(&members, endpoint_id)
+            };
+            cmd_tx.send(AppCommand::SetPeerGroup { name, members });
+            state.group_draft.clear();
+        }
+    });
+}
+
+/// `members` with `endpoint_id` removed if it is there, added if not
+fn toggled(members: &[String], endpoint_id: &str) -> Vec<String> {
+    if members.iter().any(|id| id == endpoint_id) {
+        members
+            .iter()
+            .filter(|id| *id != endpoint_id)
+            .cloned()
+            .collect()
+    } else {
+        let mut members = members.to_vec();
+        members.push(endpoint_id.to_string());
+        members
+    }
+}
+
+/// Send (or schedule) the files once the dialog for a peer has closed.
+/// Sends to a device in `registry` go over whichever path answers.
+fn poll_pending_pick(
+    state: &mut DevicesState,
+    registry: Option<&PeerRegistry>,
+    cmd_tx: &CommandBridge,
+) {
+    let Some(pending) = &state.pending_pick else {
+        return;
+    };
+
+    let files = match pending.dialog.poll() {
+        DialogResult::Pending => return,
+        DialogResult::Cancelled => {
+            state.pending_pick = None;
+            return;
+        }
+        DialogResult::Picked(files) => files,
+    };
+
+    let Some(PendingPick {
+        endpoint_id,
+        name,
+        ip,
+        purpose,
+        note,
+        ..
+    }) = state.pending_pick.take()
+    else {
+        return;
+    };
+    let known = registry
+        .and_then(|registry| registry.get(&endpoint_id))
+        .filter(|device| device.wan_candidate());
+    if let Some(device) = known.filter(|_| purpose == PickPurpose::Send) {
+        for file in &files {
+            if let Some(file_name) = file.file_name() {
+                state.record(name.clone(), file_name.to_string_lossy().into_owned(), true);
+            }
+        }
+        state.auto_sends.push(AutoSend {
+            endpoint_id,
+            name,
+            lan: device.lan.map(|route| route.addr),
+            files,
+            note,
+        });
+    } else if purpose == PickPurpose::Preview {
+        let session_id = p2p_core::new_session_id();
+        cmd_tx.send(AppCommand::EstimateSend {
+            session_id: session_id.clone(),
+            target_ip: ip.clone(),
+            target_peer_name: name.clone(),
+            files: files.clone(),
+            note: note.clone(),
+        });
+        state.previews.push(SendPreview {
+            session_id,
+            ip,
+            name,
+            files,
+            note,
+            estimate: None,
+        });
+    } else if purpose == PickPurpose::Later {
+        state.schedule_form = Some(ScheduleForm {
+            name,
+            ip,
+            files,
+            hours: 0,
+            minutes: DEFAULT_DELAY_MINUTES,
+        });
+    } else {
+        for file in &files {
+            if let Some(file_name) = file.file_name() {
+                state.record(name.clone(), file_name.to_string_lossy().into_owned(), true);
+            }
+        }
+        let session_id = p2p_core::new_session_id();
+        cmd_tx.send(if purpose == PickPurpose::Move {
+            AppCommand::MoveFiles {
+                session_id,
+                target_ip: ip,
+                target_endpoint_id: String::new(),
+                target_peer_name: name,
+                files,
+                note,
+            }
+        } else {
+            AppCommand::SendFile {
+                session_id,
+                target_ip: ip,
+                target_endpoint_id: String::new(),
+                target_peer_name: name,
+                files,
+                note,
+            }
+        });
+    }
+}
+
+/// Delay picker for "Send Later"
+fn show_schedule_form(ui: &mut egui::Ui, state: &mut DevicesState, cmd_tx: &CommandBridge) {
+    let Some(form) = &mut state.schedule_form else {
+        return;
+    };
+
+    let mut done = false;
+    ui.separator();
+    ui.label(format!(
+        "Send {} file(s) to {} later",
+        form.files.len(),
+        form.name
+    ));
+    ui.horizontal(|ui| {
+        ui.label("Start in");
+        ui.add(
+            egui::DragValue::new(&mut form.hours)
+                .range(0..=72)
+                .suffix(" h"),
+        );
+        ui.add(
+            egui::DragValue::new(&mut form.minutes)
+                .range(0..=59)
+                .suffix(" min"),
+        );
+    });
+    ui.horizontal(|ui| {
+        if ui.button(format!("{} Schedule", CLOCK)).clicked() {
+            let delay = u64::from(form.hours) * 3600 + u64::from(form.minutes) * 60;
+            cmd_tx.send(AppCommand::ScheduleSend {
+                target: form.ip.clone(),
+                target_peer_name: form.name.clone(),
+                files: std::mem::take(&mut form.files),
+                at: unix_now() + delay,
+            });
+            done = true;
+        }
+        if ui.button("Cancel").clicked() {
+            done = true;
+        }
+    });
+
+    if done {
+        state.schedule_form = None;
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(endpoint_id: &str, ip: &str, display_name: &str) -> PeerEntry {
+        PeerEntry {
+            endpoint_id: endpoint_id.to_string(),
+            ip: ip.to_string(),
+            port: 9000,
+            hostname: display_name.to_string(),
+            display_name: display_name.to_string(),
+            receive_only: false,
+            version: Some(p2p_core::APP_VERSION.to_string()),
+            protocol: p2p_core::alpn::PROTOCOL_VERSION,
+            rtt_ms: None,
+            last_seen: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_latency_sort_puts_fast_peers_first() {
+        let mut slow = entry("a", "10.0.0.2", "Alpha");
+        slow.rtt_ms = Some(40);
+        let unmeasured = entry("b", "10.0.0.3", "Beta");
+        let mut fast = entry("c", "10.0.0.4", "Gamma");
+        fast.rtt_ms = Some(2);
+        let peers: HashMap<String, PeerEntry> = [slow, unmeasured, fast]
+            .into_iter()
+            .map(|peer| (peer.ip.clone(), peer))
+            .collect();
+        let mut state = DevicesState {
+            sort_by_latency: true,
+            ..Default::default()
+        };
+        let order = |state: &DevicesState| {
+            state
+                .sorted(&peers)
+                .iter()
+                .map(|peer| peer.endpoint_id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(order(&state), ["c", "a", "b"]);
+        state.sort_by_latency = false;
+        assert_eq!(order(&state), ["a", "b", "c"]);
+
+        let mut older = entry("d", "10.0.0.5", "Old");
+        assert!(version_warning(&older).is_none());
+        older.version = None;
+        assert!(version_warning(&older).is_some());
+        let mut newer = entry("e", "10.0.0.6", "New");
+        newer.protocol += 1;
+        assert!(version_warning(&newer).unwrap().contains("will fail"));
+    }
+
+    #[test]
+    fn test_search_filters_by_name_ip_or_id() {
+        let peers: HashMap<String, PeerEntry> = [
+            entry("a1b2", "10.0.0.2", "Alpha"),
+            entry("c3d4", "10.0.0.3", "Beta"),
+            entry("e5f6", "192.168.1.9", "Gamma"),
+        ]
+        .into_iter()
+        .map(|peer| (peer.ip.clone(), peer))
+        .collect();
+        let mut state = DevicesState::default();
+        let found = |state: &DevicesState| {
+            state
+                .sorted(&peers)
+                .iter()
+                .map(|peer| peer.endpoint_id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(found(&state).len(), 3);
+        state.search = " beta ".to_string();
+        assert_eq!(found(&state), ["c3d4"]);
+        state.search = "10.0.0".to_string();
+        assert_eq!(found(&state), ["a1b2", "c3d4"]);
+        state.search = "E5F".to_string();
+        assert_eq!(found(&state), ["e5f6"]);
+        state.search = "nobody".to_string();
+        assert!(found(&state).is_empty());
+    }
+
+    #[test]
+    fn test_pinned_peers_are_listed_first() {
+        let peers: HashMap<String, PeerEntry> = [
+            entry("a", "10.0.0.2", "Alpha"),
+            entry("b", "10.0.0.3", "Beta"),
+            entry("c", "10.0.0.4", "Gamma"),
+        ]
+        .into_iter()
+        .map(|peer| (peer.ip.clone(), peer))
+        .collect();
+        let mut state = DevicesState::default();
+        let order = |state: &DevicesState| {
+            state
+                .sorted(&peers)
+                .iter()
+                .map(|peer| peer.endpoint_id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(order(&state), ["a", "b", "c"]);
+
+        state.set_known_peers(
+            vec!["a".to_string()],
+            Vec::new(),
+            vec!["c".to_string()],
+            Vec::new(),
+        );
+        assert_eq!(order(&state), ["c", "a", "b"]);
+
+        let mut receive_only = entry("d", "10.0.0.5", "Drop (box)");
+        receive_only.receive_only = true;
+        assert_eq!(
+            peer_label(&receive_only),
+            format!("Drop (box) {} (10.0.0.5)", RECEIVE_ONLY_TAG)
+        );
+    }
+
+    #[test]
+    fn test_group_targets_address_members_on_the_lan() {
+        let peers: HashMap<String, PeerEntry> = [entry("a", "10.0.0.2", "Alpha")]
+            .into_iter()
+            .map(|peer| (peer.ip.clone(), peer))
+            .collect();
+        let group = PeerGroup {
+            name: "Team A".to_string(),
+            members: vec!["a".to_string(), "b".to_string()],
+        };
+        let targets = group_targets(&group, &peers);
+        assert_eq!(targets[0].target_peer_name, "Alpha");
+        assert_eq!(targets[0].target_ip.as_deref(), Some("10.0.0.2"));
+        assert_eq!(targets[1].target_peer_name, "b");
+        assert!(targets[1].target_ip.is_none());
+        assert_ne!(targets[0].session_id, targets[1].session_id);
+
+        assert_eq!(toggled(&group.members, "a"), ["b"]);
+        assert_eq!(toggled(&group.members, "c"), ["a", "b", "c"]);
+    }
 }
@@ -0,0 +1,75 @@
+use crate::bridge::CommandBridge;
+use eframe::egui;
+use p2p_core::AppCommand;
+use p2p_core::history::DuplicateAction;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub struct PendingDuplicate {
+    pub file_name: String,
+    pub path: PathBuf,
+    pub existing: PathBuf,
+    pub size: u64,
+}
+
+/// Ask what to do with the oldest unanswered duplicate. Closing the window
+/// keeps both files.
+pub fn show(ctx: &egui::Context, queue: &mut Vec<PendingDuplicate>, cmd_tx: &CommandBridge) {
+    let Some(duplicate) = queue.first() else {
+        return;
+    };
+
+    let mut open = true;
+    let mut choice = None;
+    egui::Window::new("Duplicate File Received")
+        .collapsible(false)
+        .resizable(false)
+        .open(&mut open)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "{} ({}) is identical to a file you already have:",
+                duplicate.file_name,
+                p2p_core::units::format_size(duplicate.size)
+            ));
+            ui.add_space(10.0);
+            ui.group(|ui| {
+                ui.label(duplicate.existing.display().to_string());
+            });
+            if queue.len() > 1 {
+                ui.label(format!("{} more waiting", queue.len() - 1));
+            }
+            ui.add_space(15.0);
+
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Hard-link")
+                    .on_hover_text("Keep one copy on disk under both names")
+                    .clicked()
+                {
+                    choice = Some(DuplicateAction::HardLink);
+                }
+                if ui
+                    .button("Skip")
+                    .on_hover_text("Delete the new copy")
+                    .clicked()
+                {
+                    choice = Some(DuplicateAction::Skip);
+                }
+                if ui.button("Keep both").clicked() {
+                    choice = Some(DuplicateAction::KeepBoth);
+                }
+            });
+        });
+
+    if !open {
+        choice = Some(DuplicateAction::KeepBoth);
+    }
+    if let Some(action) = choice {
+        let duplicate = queue.remove(0);
+        cmd_tx.send(AppCommand::ResolveDuplicate {
+            path: duplicate.path,
+            action,
+        });
+    }
+}
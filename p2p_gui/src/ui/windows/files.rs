@@ -1,11 +1,114 @@
 use eframe::egui;
-use egui_phosphor::regular::{ARROWS_CLOCKWISE, FILE_TEXT, TRASH};
+use egui_phosphor::regular::{ARROW_U_UP_LEFT, ARROWS_CLOCKWISE, FILE_TEXT, TRASH, WARNING};
+use p2p_core::units::format_size;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a trashed file is offered back
+const UNDO_SECS: u64 = 10;
+
+/// Whether files can be taken back out of the trash here; macOS has no API
+/// for it
+const CAN_RESTORE: bool = cfg!(any(
+    target_os = "windows",
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+));
+
+/// A received file, relative to the download folder
+pub struct LocalFile {
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl LocalFile {
+    pub fn new(name: String, meta: &std::fs::Metadata) -> Self {
+        Self {
+            name,
+            size: meta.len(),
+            modified: meta.modified().ok(),
+        }
+    }
+}
+
+/// A file just moved to the trash, with an undo until `deadline`
+pub struct Trashed {
+    pub name: String,
+    /// Where it was, as the trash records it
+    pub path: PathBuf,
+    pub deadline: Instant,
+}
+
+/// Delete `path`, into the trash unless `hard`; returns the trashed file
+fn delete(path: &Path, name: &str, hard: bool) -> Result<Option<Trashed>, String> {
+    if hard {
+        return std::fs::remove_file(path)
+            .map(|()| None)
+            .map_err(|e| e.to_string());
+    }
+    // The trash records the resolved path
+    let path = std::fs::canonicalize(path).map_err(|e| e.to_string())?;
+    trash::delete(&path).map_err(|e| e.to_string())?;
+    Ok(Some(Trashed {
+        name: name.to_string(),
+        path,
+        deadline: Instant::now() + Duration::from_secs(UNDO_SECS),
+    }))
+}
+
+/// Put the most recently trashed file that was at `path` back
+#[cfg(any(
+    target_os = "windows",
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+))]
+fn restore(path: &Path) -> Result<(), String> {
+    use trash::os_limited;
+    let item = os_limited::list()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|item| item.original_path() == path)
+        .max_by_key(|item| item.time_deleted)
+        .ok_or_else(|| "It is no longer in the trash".to_string())?;
+    os_limited::restore_all([item]).map_err(|e| e.to_string())
+}
+
+#[cfg(not(any(
+    target_os = "windows",
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+)))]
+fn restore(_path: &Path) -> Result<(), String> {
+    Err("Restore it from the Trash".to_string())
+}
+
+/// Local date and time, e.g. "2026-10-18 14:05"
+fn format_modified(modified: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(modified)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
 
 pub fn show(
     ctx: &egui::Context,
     open: &mut bool,
-    download_path: &std::path::Path,
-    local_files: &[String],
+    download_path: &Path,
+    local_files: &[LocalFile],
+    hard_delete: &mut bool,
+    trashed: &mut Vec<Trashed>,
     refresh_files: impl FnOnce(),
 ) {
     let mut should_refresh = false;
@@ -33,6 +136,8 @@ pub fn show(
                 if ui.button(format!("{} Refresh", ARROWS_CLOCKWISE)).clicked() {
                     should_refresh = true;
                 }
+                ui.checkbox(hard_delete, "Delete permanently")
+                    .on_hover_text("Skip the trash; deleted files cannot be restored");
             });
 
             ui.add_space(5.0);
@@ -41,16 +146,36 @@ pub fn show(
                 if local_files.is_empty() {
                     ui.label(egui::RichText::new("No files found.").italics().weak());
                 } else {
-                    for file_name in local_files {
+                    for file in local_files {
                         ui.horizontal(|ui| {
                             ui.label(FILE_TEXT);
-                            ui.label(file_name);
+                            ui.label(&file.name);
+                            if p2p_core::transfer::is_executable(&file.name) {
+                                ui.colored_label(ui.visuals().warn_fg_color, WARNING)
+                                    .on_hover_text(
+                                        "A program from another device; only run it if you trust the sender",
+                                    );
+                            }
+                            ui.weak(format_size(file.size));
+                            if let Some(modified) = file.modified {
+                                ui.weak(format_modified(modified));
+                            }
 
-                            // Delete button
-                            if ui.button(TRASH).on_hover_text("Delete file").clicked() {
-                                let file_path = download_path.join(file_name);
-                                if let Err(e) = std::fs::remove_file(&file_path) {
-                                    eprintln!("Failed to delete file: {}", e);
+                            let hover = if *hard_delete {
+                                "Delete file"
+                            } else {
+                                "Move to the trash"
+                            };
+                            if ui.button(TRASH).on_hover_text(hover).clicked() {
+                                let file_path = download_path.join(&file.name);
+                                match delete(&file_path, &file.name, *hard_delete) {
+                                    Ok(Some(entry)) => trashed.push(entry),
+                                    Ok(None) => {}
+                                    Err(e) => tracing::warn!(
+                                        "Failed to delete {}: {}",
+                                        file_path.display(),
+                                        e
+                                    ),
                                 }
                                 should_refresh = true;
                             }
@@ -64,3 +189,46 @@ pub fn show(
             }
         });
 }
+
+/// Offer recently trashed files back; returns true when one was restored
+pub fn show_undo(ctx: &egui::Context, trashed: &mut Vec<Trashed>) -> bool {
+    let now = Instant::now();
+    trashed.retain(|entry| entry.deadline > now);
+    if trashed.is_empty() {
+        return false;
+    }
+    let mut restored = None;
+    egui::Window::new(format!("{} Moved to the Trash", TRASH))
+        .id(egui::Id::new("trashed_files"))
+        .collapsible(true)
+        .resizable(false)
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(10.0, -10.0))
+        .show(ctx, |ui| {
+            for (i, entry) in trashed.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(&entry.name)
+                        .on_hover_text(entry.path.display().to_string());
+                    if CAN_RESTORE {
+                        if ui.button(format!("{} Undo", ARROW_U_UP_LEFT)).clicked() {
+                            restored = Some(i);
+                        }
+                    } else {
+                        ui.weak("Restore it from the Trash");
+                    }
+                });
+            }
+        });
+    ctx.request_repaint_after(Duration::from_secs(1));
+
+    let Some(i) = restored else {
+        return false;
+    };
+    let entry = trashed.remove(i);
+    match restore(&entry.path) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!("Could not restore {}: {}", entry.name, e);
+            false
+        }
+    }
+}
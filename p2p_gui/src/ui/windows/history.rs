@@ -0,0 +1,329 @@
+use crate::bridge::{CommandBridge, DialogResult, FileDialogTask};
+use crate::os_auth::SensitiveGate;
+use eframe::egui;
+use egui_phosphor::regular::{
+    ARROW_DOWN_LEFT, ARROW_UP_RIGHT, EXPORT, MAGNIFYING_GLASS, SEAL_CHECK, TAG,
+};
+use p2p_core::AppCommand;
+use p2p_core::history::export::ExportFormat;
+use p2p_core::history::transfers::{Direction, HistoryQuery, TransferRecord, TransferStatus};
+use p2p_core::history::usage::{BandwidthCap, DayUsage, UsageSummary};
+use p2p_core::units::format_size;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const BYTES_PER_GB: f64 = 1e9;
+
+/// Date ranges offered by the window, in days; `None` is any time
+const RANGES: [Option<u64>; 4] = [None, Some(1), Some(7), Some(30)];
+
+const STATUSES: [TransferStatus; 5] = [
+    TransferStatus::Completed,
+    TransferStatus::Verified,
+    TransferStatus::Unverified,
+    TransferStatus::Failed,
+    TransferStatus::Cancelled,
+];
+
+/// Filters being edited and the last answer
+#[derive(Default)]
+pub struct HistoryWindow {
+    pub query: HistoryQuery,
+    /// Only transfers of the last this many days
+    pub days: Option<u64>,
+    pub results: Option<Vec<TransferRecord>>,
+    /// Save dialog of an export in progress
+    export: Option<(ExportFormat, FileDialogTask)>,
+    usage: Option<UsageSummary>,
+    /// Cap being edited, in GB; 0 is no cap
+    cap_gb: f64,
+    hold_scheduled: bool,
+}
+
+impl HistoryWindow {
+    /// Show `usage`; the cap fields are filled from the first answer
+    pub fn set_usage(&mut self, usage: UsageSummary) {
+        if self.usage.is_none() {
+            self.cap_gb = usage.cap.monthly_bytes.unwrap_or(0) as f64 / BYTES_PER_GB;
+            self.hold_scheduled = usage.cap.hold_scheduled;
+        }
+        self.usage = Some(usage);
+    }
+
+    fn search(&self, cmd_tx: &CommandBridge) {
+        let since = self
+            .days
+            .map(|days| unix_now().saturating_sub(days * 24 * 60 * 60));
+        cmd_tx.send(AppCommand::QueryHistory {
+            query: HistoryQuery {
+                since,
+                ..self.query.clone()
+            },
+        });
+    }
+}
+
+pub fn show(
+    ctx: &egui::Context,
+    open: &mut bool,
+    state: &mut HistoryWindow,
+    gate: &mut SensitiveGate,
+    cmd_tx: &CommandBridge,
+) {
+    // Fill the list when the window opens
+    if state.results.is_none() {
+        state.results = Some(Vec::new());
+        state.search(cmd_tx);
+        cmd_tx.send(AppCommand::GetBandwidthUsage);
+    }
+    if let Some((format, poll)) = state.export.as_ref().map(|(f, d)| (*f, d.poll())) {
+        match poll {
+            DialogResult::Pending => {}
+            DialogResult::Picked(mut paths) => {
+                gate.send(
+                    ctx,
+                    cmd_tx,
+                    "Export the transfer history",
+                    AppCommand::ExportHistory {
+                        format,
+                        path: paths.remove(0),
+                    },
+                );
+                state.export = None;
+            }
+            DialogResult::Cancelled => state.export = None,
+        }
+    }
+
+    egui::Window::new("History")
+        .open(open)
+        .resizable(true)
+        .default_size([480.0, 320.0])
+        .show(ctx, |ui| {
+            let mut submitted = false;
+            ui.horizontal(|ui| {
+                let field = ui.add(
+                    egui::TextEdit::singleline(&mut state.query.text)
+                        .hint_text("File name or note")
+                        .desired_width(160.0),
+                );
+                submitted |= field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                let field = ui.add(
+                    egui::TextEdit::singleline(&mut state.query.peer)
+                        .hint_text("Device")
+                        .desired_width(100.0),
+                );
+                submitted |= field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                submitted |= ui.button(format!("{} Search", MAGNIFYING_GLASS)).clicked();
+            });
+            ui.horizontal(|ui| {
+                submitted |= filter_combo(
+                    ui,
+                    "history_direction",
+                    &mut state.query.direction,
+                    &[Direction::Sent, Direction::Received],
+                    direction_label,
+                );
+                submitted |= filter_combo(
+                    ui,
+                    "history_status",
+                    &mut state.query.status,
+                    &STATUSES,
+                    status_label,
+                );
+                let before = state.days;
+                egui::ComboBox::from_id_salt("history_range")
+                    .selected_text(range_label(state.days))
+                    .show_ui(ui, |ui| {
+                        for days in RANGES {
+                            ui.selectable_value(&mut state.days, days, range_label(days));
+                        }
+                    });
+                submitted |= state.days != before;
+
+                ui.add_enabled_ui(state.export.is_none(), |ui| {
+                    ui.menu_button(EXPORT, |ui| {
+                        for format in [ExportFormat::Csv, ExportFormat::Json] {
+                            let name = format.extension();
+                            if ui.button(name.to_uppercase()).clicked() {
+                                let file_name = format!("p2p_transfer_history.{}", name);
+                                let dialog = FileDialogTask::save_file(ctx, &file_name);
+                                state.export = Some((format, dialog));
+                                ui.close();
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text("Export the whole history with hashes and peer IDs");
+                });
+            });
+            if submitted {
+                state.search(cmd_tx);
+            }
+            ui.separator();
+            show_usage(ui, state, cmd_tx);
+            ui.separator();
+
+            let records = state.results.as_deref().unwrap_or_default();
+            if records.is_empty() {
+                ui.weak("No transfers match.");
+                return;
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for record in records {
+                    show_record(ui, record);
+                }
+            });
+        });
+}
+
+/// Data used per period, and the monthly cap
+fn show_usage(ui: &mut egui::Ui, state: &mut HistoryWindow, cmd_tx: &CommandBridge) {
+    let Some(usage) = &state.usage else {
+        return;
+    };
+    egui::Grid::new("bandwidth_usage").show(ui, |ui| {
+        for (period, day) in [
+            ("Today", usage.today),
+            ("Last 7 days", usage.week),
+            ("This month", usage.month),
+        ] {
+            ui.label(period);
+            ui.weak(usage_line(day));
+            ui.end_row();
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Monthly cap");
+        ui.add(
+            egui::DragValue::new(&mut state.cap_gb)
+                .range(0.0..=1_000_000.0)
+                .speed(1.0)
+                .suffix(" GB"),
+        )
+        .on_hover_text("0 for no cap; you are warned at 80% and at the cap");
+        ui.checkbox(&mut state.hold_scheduled, "Hold scheduled sends at the cap");
+        if ui.button("Save").clicked() {
+            let monthly_bytes =
+                (state.cap_gb > 0.0).then_some((state.cap_gb * BYTES_PER_GB) as u64);
+            cmd_tx.send(AppCommand::SetBandwidthCap(BandwidthCap {
+                monthly_bytes,
+                hold_scheduled: state.hold_scheduled,
+            }));
+        }
+    });
+}
+
+fn usage_line(day: DayUsage) -> String {
+    format!(
+        "{} sent, {} received",
+        format_size(day.sent),
+        format_size(day.received)
+    )
+}
+
+/// A combo box picking one of `options` or "All"; true when changed
+fn filter_combo<T: Copy + PartialEq>(
+    ui: &mut egui::Ui,
+    id: &str,
+    value: &mut Option<T>,
+    options: &[T],
+    label: fn(T) -> &'static str,
+) -> bool {
+    let before = *value;
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(value.map_or("All", label))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(value, None, "All");
+            for &option in options {
+                ui.selectable_value(value, Some(option), label(option));
+            }
+        });
+    *value != before
+}
+
+fn show_record(ui: &mut egui::Ui, record: &TransferRecord) {
+    ui.horizontal(|ui| {
+        let (icon, towards) = match record.direction {
+            Direction::Sent => (ARROW_UP_RIGHT, "to"),
+            Direction::Received => (ARROW_DOWN_LEFT, "from"),
+        };
+        ui.label(icon);
+        let name = ui.label(&record.file_name);
+        if let Some(path) = &record.path {
+            name.on_hover_text(path.display().to_string());
+        }
+        ui.weak(format!("{} {}", towards, record.peer));
+        ui.weak(format_size(record.size));
+        ui.weak(format_time(record.at));
+        ui.label(status_label(record.status));
+        if let Some(note) = &record.note {
+            ui.label(format!("{} {}", TAG, note));
+        }
+        if let Some(receipt) = &record.receipt {
+            let hover = format!(
+                "Signed by {} at {}\nClick to copy the receipt",
+                receipt.receipt.receiver,
+                format_time(receipt.receipt.received_at)
+            );
+            let badge = ui.add(
+                egui::Label::new(format!("{} Receipt", SEAL_CHECK)).sense(egui::Sense::click()),
+            );
+            if badge.on_hover_text(hover).clicked()
+                && let Ok(json) = serde_json::to_string_pretty(receipt)
+            {
+                ui.ctx().copy_text(json);
+            }
+        }
+    });
+}
+
+fn direction_label(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Sent => "Sent",
+        Direction::Received => "Received",
+    }
+}
+
+fn status_label(status: TransferStatus) -> &'static str {
+    match status {
+        TransferStatus::Completed => "Completed",
+        TransferStatus::Verified => "Verified",
+        TransferStatus::Unverified => "Unverified",
+        TransferStatus::Failed => "Failed",
+        TransferStatus::Cancelled => "Cancelled",
+    }
+}
+
+fn range_label(days: Option<u64>) -> String {
+    match days {
+        None => "Any time".to_string(),
+        Some(1) => "Last 24 hours".to_string(),
+        Some(days) => format!("Last {} days", days),
+    }
+}
+
+/// Local date and time, e.g. "2026-10-18 14:05"
+fn format_time(at: u64) -> String {
+    chrono::DateTime::<chrono::Local>::from(UNIX_EPOCH + Duration::from_secs(at))
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_label() {
+        assert_eq!(range_label(None), "Any time");
+        assert_eq!(range_label(Some(1)), "Last 24 hours");
+        assert_eq!(range_label(Some(7)), "Last 7 days");
+    }
+}
@@ -0,0 +1,56 @@
+//! Large sends held back because the connection is metered.
+
+use crate::bridge::CommandBridge;
+use eframe::egui;
+use egui_phosphor::regular::{CELL_SIGNAL_HIGH, PAPER_PLANE_RIGHT, X};
+use p2p_core::AppCommand;
+use p2p_core::units::format_size;
+
+/// A send waiting for the user, see `AppEvent::MeteredSendHeld`
+#[derive(Debug, Clone)]
+pub struct HeldSend {
+    pub session_id: String,
+    pub target_peer_name: String,
+    pub files: usize,
+    pub total_size: u64,
+}
+
+/// Ask about each held send until it is started or dropped
+pub fn show(ctx: &egui::Context, held: &mut Vec<HeldSend>, cmd_tx: &CommandBridge) {
+    if held.is_empty() {
+        return;
+    }
+    let mut answered = None;
+    egui::Window::new(format!("{} Metered Connection", CELL_SIGNAL_HIGH))
+        .id(egui::Id::new("metered_sends"))
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            ui.label("This connection is metered. Send anyway?");
+            ui.separator();
+            for (index, send) in held.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{} file(s), {} to {}",
+                        send.files,
+                        format_size(send.total_size),
+                        send.target_peer_name
+                    ));
+                    if ui.button(format!("{} Send", PAPER_PLANE_RIGHT)).clicked() {
+                        answered = Some((index, true));
+                    }
+                    if ui.button(format!("{} Cancel", X)).clicked() {
+                        answered = Some((index, false));
+                    }
+                });
+            }
+        });
+    if let Some((index, accepted)) = answered {
+        let send = held.remove(index);
+        cmd_tx.send(AppCommand::RespondMeteredSend {
+            session_id: send.session_id,
+            accepted,
+        });
+    }
+}
@@ -1,6 +1,22 @@
 pub mod devices;
+pub mod duplicates;
 pub mod files;
+pub mod history;
+pub mod metered;
+pub mod moves;
+pub mod orphans;
+pub mod pending;
+#[cfg(feature = "profiling")]
+pub mod profiler;
+pub mod proxy;
 pub mod qr_code;
+pub mod resend;
+pub mod scheduled;
+pub mod send_picker;
+pub mod send_preview;
+pub mod swarms;
+pub mod telemetry;
 pub mod upload_confirm;
 pub mod verify;
 pub mod wan_connect;
+pub mod wan_offer;
@@ -0,0 +1,62 @@
+//! Moved files whose source is about to be deleted, each with an undo.
+
+use crate::bridge::CommandBridge;
+use eframe::egui;
+use egui_phosphor::regular::{ARROW_U_UP_LEFT, TRUCK};
+use p2p_core::AppCommand;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// A source the backend deletes at `deadline` unless undone
+#[derive(Debug, Clone)]
+pub struct PendingMove {
+    pub move_id: String,
+    pub file_name: String,
+    pub path: PathBuf,
+    pub deadline: Instant,
+}
+
+impl PendingMove {
+    pub fn new(move_id: String, file_name: String, path: PathBuf, undo_secs: u64) -> Self {
+        Self {
+            move_id,
+            file_name,
+            path,
+            deadline: Instant::now() + Duration::from_secs(undo_secs),
+        }
+    }
+}
+
+/// List pending moves until the backend reports them finished
+pub fn show(ctx: &egui::Context, pending: &mut [PendingMove], cmd_tx: &CommandBridge) {
+    if pending.is_empty() {
+        return;
+    }
+    egui::Window::new(format!("{} Moving Files", TRUCK))
+        .id(egui::Id::new("pending_moves"))
+        .collapsible(true)
+        .resizable(false)
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+        .show(ctx, |ui| {
+            ui.label("Verified by the receiver; the originals are deleted soon.");
+            ui.separator();
+            for entry in pending.iter() {
+                let left = entry.deadline.saturating_duration_since(Instant::now());
+                ui.horizontal(|ui| {
+                    ui.label(&entry.file_name)
+                        .on_hover_text(entry.path.display().to_string());
+                    ui.weak(format!("{}s", left.as_secs()));
+                    if ui
+                        .button(format!("{} Undo", ARROW_U_UP_LEFT))
+                        .on_hover_text("Keep the original")
+                        .clicked()
+                    {
+                        cmd_tx.send(AppCommand::UndoMove {
+                            move_id: entry.move_id.clone(),
+                        });
+                    }
+                });
+            }
+        });
+    ctx.request_repaint_after(Duration::from_secs(1));
+}
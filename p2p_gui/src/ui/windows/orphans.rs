@@ -0,0 +1,98 @@
+//! Partial files no transfer came back for, see `AppEvent::OrphanedPartials`.
+
+use crate::bridge::CommandBridge;
+use crate::ui::windows::devices::PeerEntry;
+use eframe::egui;
+use egui_phosphor::regular::{ARROW_CLOCKWISE, HOURGLASS, TRASH};
+use p2p_core::AppCommand;
+use p2p_core::transfer::orphans::{OrphanAction, OrphanedPart};
+use p2p_core::units::format_size;
+use std::collections::HashMap;
+
+/// List the orphaned partial files until each is resolved. Closing the
+/// window keeps them; the next scan reports them again.
+pub fn show(
+    ctx: &egui::Context,
+    orphans: &mut Vec<OrphanedPart>,
+    peers: &HashMap<String, PeerEntry>,
+    cmd_tx: &CommandBridge,
+) {
+    if orphans.is_empty() {
+        return;
+    }
+    let mut open = true;
+    let mut answered = None;
+    egui::Window::new(format!("{} Unfinished Downloads", HOURGLASS))
+        .id(egui::Id::new("orphaned_partials"))
+        .collapsible(false)
+        .resizable(false)
+        .open(&mut open)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            ui.label("These files stopped part way and were not continued:");
+            ui.separator();
+            for (index, orphan) in orphans.iter().enumerate() {
+                let received = match orphan.expected_size {
+                    Some(total) => {
+                        format!("{} of {}", format_size(orphan.size), format_size(total))
+                    }
+                    None => format_size(orphan.size),
+                };
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{} ({}, {} days old)",
+                        orphan.file_name, received, orphan.age_days
+                    ));
+                    let online = orphan.sender.as_ref().and_then(|sender| {
+                        peers
+                            .values()
+                            .find(|peer| peer.endpoint_id == sender.endpoint_id)
+                    });
+                    if let Some(sender) = &orphan.sender {
+                        let button = ui
+                            .add_enabled(
+                                online.is_some(),
+                                egui::Button::new(format!("{} Resume", ARROW_CLOCKWISE)),
+                            )
+                            .on_hover_text(format!(
+                                "Ask {} to send it again; it continues where it stopped",
+                                sender.name
+                            ))
+                            .on_disabled_hover_text(format!("{} is not online", sender.name));
+                        if button.clicked() {
+                            let peer = online.map(|peer| (peer.ip.clone(), peer.hostname.clone()));
+                            answered = Some((index, OrphanAction::Keep, peer));
+                        }
+                    }
+                    if ui.button("Keep").clicked() {
+                        answered = Some((index, OrphanAction::Keep, None));
+                    }
+                    if ui.button(format!("{} Delete", TRASH)).clicked() {
+                        answered = Some((index, OrphanAction::Delete, None));
+                    }
+                });
+            }
+        });
+    if !open {
+        orphans.clear();
+        return;
+    }
+    if let Some((index, action, ask)) = answered {
+        let orphan = orphans.remove(index);
+        if let Some((target_ip, target_peer_name)) = ask {
+            cmd_tx.send(AppCommand::SendText {
+                session_id: p2p_core::new_session_id(),
+                target_ip,
+                target_peer_name,
+                text: format!(
+                    "Please send {} again, the download stopped part way.",
+                    orphan.file_name
+                ),
+            });
+        }
+        cmd_tx.send(AppCommand::ResolveOrphan {
+            path: orphan.path,
+            action,
+        });
+    }
+}
@@ -0,0 +1,81 @@
+use crate::bridge::CommandBridge;
+use eframe::egui;
+use egui_phosphor::regular::{ARROW_CLOCKWISE, X};
+use p2p_core::AppCommand;
+use p2p_core::journal::PendingSend;
+
+/// Offer to resume sends that were interrupted last time. Closing the window
+/// keeps them for the next start.
+pub fn show(ctx: &egui::Context, open: &mut bool, sends: &[PendingSend], cmd_tx: &CommandBridge) {
+    if sends.is_empty() {
+        *open = false;
+        return;
+    }
+    egui::Window::new("Resume Pending Sends")
+        .open(open)
+        .resizable(true)
+        .default_size([360.0, 180.0])
+        .show(ctx, |ui| {
+            ui.label("These sends did not finish. Resumed sends start once the device answers.");
+            ui.add_space(6.0);
+            for send in sends {
+                ui.horizontal(|ui| {
+                    ui.label(summary(send));
+                    if ui
+                        .button(ARROW_CLOCKWISE)
+                        .on_hover_text("Resume this send")
+                        .clicked()
+                    {
+                        cmd_tx.send(AppCommand::ResumePendingSend {
+                            id: send.id.clone(),
+                        });
+                    }
+                    if ui.button(X).on_hover_text("Discard this send").clicked() {
+                        cmd_tx.send(AppCommand::DiscardPendingSend {
+                            id: send.id.clone(),
+                        });
+                    }
+                });
+            }
+            ui.add_space(6.0);
+            if sends.len() > 1 && ui.button("Resume all").clicked() {
+                for send in sends {
+                    cmd_tx.send(AppCommand::ResumePendingSend {
+                        id: send.id.clone(),
+                    });
+                }
+            }
+        });
+}
+
+/// "2 file(s) to Laptop, 1 partly sent"
+fn summary(send: &PendingSend) -> String {
+    let partial = send.files.iter().filter(|file| file.sent > 0).count();
+    let mut text = format!("{} file(s) to {}", send.files.len(), send.target_peer_name);
+    if partial > 0 {
+        text.push_str(&format!(", {} partly sent", partial));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p2p_core::journal::JournaledFile;
+
+    #[test]
+    fn test_summary_counts_partial_files() {
+        let file = |sent| JournaledFile {
+            path: "a.bin".into(),
+            sent,
+        };
+        let send = PendingSend {
+            id: "1".to_string(),
+            target: "192.168.1.20".to_string(),
+            target_peer_name: "Laptop".to_string(),
+            files: vec![file(0), file(4096)],
+            started: 0,
+        };
+        assert_eq!(summary(&send), "2 file(s) to Laptop, 1 partly sent");
+    }
+}
@@ -0,0 +1,113 @@
+//! Time spent in the profiled hot paths, built with the `profiling` feature.
+//!
+//! Scopes are recorded only while the window is open; they are merged over
+//! the last frames and summed per scope name, since the work moves between
+//! tokio's threads.
+
+use eframe::egui;
+use egui_phosphor::regular::GAUGE;
+use p2p_core::profiling::puffin::{
+    self, GlobalFrameView, MergeScope, ScopeCollection, merge_scopes_for_thread,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Frames merged into the table
+const FRAMES_SHOWN: usize = 120;
+
+/// Calls, total and slowest time of one scope name
+#[derive(Default, Clone, Copy)]
+struct ScopeTotal {
+    calls: usize,
+    total_ns: i64,
+    max_ns: i64,
+}
+
+/// Frames collected from the profiler
+#[derive(Default)]
+pub struct ProfilerWindow {
+    view: GlobalFrameView,
+}
+
+impl ProfilerWindow {
+    fn totals(&self) -> (BTreeMap<String, ScopeTotal>, i64) {
+        let view = self.view.lock();
+        let frames: Vec<_> = view
+            .latest_frames(FRAMES_SHOWN)
+            .filter_map(|frame| frame.unpacked().ok())
+            .collect();
+        let span_ns = match (frames.first(), frames.last()) {
+            (Some(first), Some(last)) => last.meta.range_ns.1 - first.meta.range_ns.0,
+            _ => 0,
+        };
+        let threads: BTreeSet<_> = frames
+            .iter()
+            .flat_map(|frame| frame.thread_streams.keys().cloned())
+            .collect();
+        let mut totals = BTreeMap::new();
+        for thread in &threads {
+            if let Ok(scopes) = merge_scopes_for_thread(view.scope_collection(), &frames, thread) {
+                add_scopes(&mut totals, view.scope_collection(), &scopes);
+            }
+        }
+        (totals, span_ns)
+    }
+}
+
+fn add_scopes(
+    totals: &mut BTreeMap<String, ScopeTotal>,
+    collection: &ScopeCollection,
+    scopes: &[MergeScope<'_>],
+) {
+    for scope in scopes {
+        let name = collection
+            .fetch_by_id(&scope.id)
+            .map_or_else(|| "?".to_string(), |details| details.name().to_string());
+        let total = totals.entry(name).or_default();
+        total.calls += scope.num_pieces;
+        total.total_ns += scope.total_duration_ns;
+        total.max_ns = total.max_ns.max(scope.max_duration_ns);
+        add_scopes(totals, collection, &scope.children);
+    }
+}
+
+pub fn show(ctx: &egui::Context, open: &mut bool, state: &ProfilerWindow) {
+    puffin::set_scopes_on(*open);
+    if !*open {
+        return;
+    }
+    // Frames end with repaints, so keep them coming while transfers run
+    ctx.request_repaint_after(std::time::Duration::from_millis(250));
+
+    egui::Window::new(format!("{} Profiler", GAUGE))
+        .open(open)
+        .resizable(true)
+        .default_size([360.0, 200.0])
+        .show(ctx, |ui| {
+            let (totals, span_ns) = state.totals();
+            if totals.is_empty() {
+                ui.weak("Nothing profiled yet; send or receive a file.");
+                return;
+            }
+            ui.weak(format!("Last {:.1} s", span_ns as f64 / 1e9));
+            egui::Grid::new("profiler_scopes")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("Scope");
+                    ui.strong("Calls");
+                    ui.strong("Total");
+                    ui.strong("Slowest");
+                    ui.end_row();
+                    for (name, total) in &totals {
+                        ui.label(name);
+                        ui.label(total.calls.to_string());
+                        ui.label(format_ms(total.total_ns));
+                        ui.label(format_ms(total.max_ns));
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+fn format_ms(ns: i64) -> String {
+    format!("{:.2} ms", ns as f64 / 1e6)
+}
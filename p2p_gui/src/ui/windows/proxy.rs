@@ -0,0 +1,109 @@
+use crate::bridge::CommandBridge;
+use eframe::egui;
+use p2p_core::AppCommand;
+use p2p_core::proxy::ProxySettings;
+
+/// Proxy fields as edited, saved with the button
+pub struct ProxyWindowState {
+    url: String,
+    /// One bypass rule per line
+    bypass: String,
+    error: Option<String>,
+}
+
+impl Default for ProxyWindowState {
+    fn default() -> Self {
+        let settings = p2p_core::config::AppConfig::load().proxy;
+        Self {
+            url: settings.url.unwrap_or_default(),
+            bypass: settings.bypass.join("\n"),
+            error: None,
+        }
+    }
+}
+
+impl ProxyWindowState {
+    fn settings(&self) -> ProxySettings {
+        let url = self.url.trim();
+        ProxySettings {
+            url: (!url.is_empty()).then(|| url.to_string()),
+            bypass: self
+                .bypass
+                .split([',', '\n'])
+                .map(str::trim)
+                .filter(|rule| !rule.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+pub fn show(
+    ctx: &egui::Context,
+    open: &mut bool,
+    state: &mut ProxyWindowState,
+    cmd_tx: &CommandBridge,
+) {
+    egui::Window::new("Proxy")
+        .open(open)
+        .resizable(true)
+        .default_size([360.0, 220.0])
+        .show(ctx, |ui| {
+            ui.label("Used for WAN relays and the WAN share tunnel, not for LAN transfers.");
+            ui.add_space(6.0);
+            ui.horizontal(|ui| {
+                ui.label("Proxy URL:");
+                ui.add(
+                    egui::TextEdit::singleline(&mut state.url)
+                        .hint_text("http://proxy:3128 or socks5://proxy:1080"),
+                );
+            });
+            ui.add_space(4.0);
+            ui.label("Connect directly to (one per line):");
+            ui.add(
+                egui::TextEdit::multiline(&mut state.bypass)
+                    .hint_text("*.example.com\n10.0.0.0/8")
+                    .desired_rows(3),
+            );
+            ui.small("WAN relays only work with HTTP proxies and pick up changes after a restart.");
+            ui.add_space(6.0);
+            if ui.button("Save").clicked() {
+                let settings = state.settings();
+                match settings.proxy_url() {
+                    Ok(_) => {
+                        state.error = None;
+                        cmd_tx.send(AppCommand::SetProxy(settings));
+                    }
+                    Err(e) => state.error = Some(e.to_string()),
+                }
+            }
+            if let Some(error) = &state.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields_become_settings() {
+        let state = ProxyWindowState {
+            url: " http://proxy:3128 ".to_string(),
+            bypass: "*.corp.example, 10.0.0.0/8\n\nrelay.local".to_string(),
+            error: None,
+        };
+        assert_eq!(
+            state.settings(),
+            ProxySettings {
+                url: Some("http://proxy:3128".to_string()),
+                bypass: vec![
+                    "*.corp.example".to_string(),
+                    "10.0.0.0/8".to_string(),
+                    "relay.local".to_string(),
+                ],
+            }
+        );
+    }
+}
@@ -1,12 +1,25 @@
+use crate::bridge::{CommandBridge, DialogResult, FileDialogTask};
+use crate::qr_scan::{self, ScannedCode};
 use eframe::egui::{self, ColorImage, TextureHandle, TextureOptions};
 use p2p_core::AppCommand;
+use p2p_core::link::AppLink;
+use p2p_core::pairing::invite::PairingInvite;
+use p2p_core::pairing::phrase::LinkPhrase;
 use qrcode::QrCode;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
 
-/// Cached QR code texture and the URL it was generated for
+/// Side of the QR code on screen, in points
+const QR_SIZE_POINTS: f32 = 220.0;
+
+/// Empty border required around a QR code, in modules
+const QR_QUIET_ZONE: u32 = 4;
+
+/// Cached QR code texture and what it was generated for
 #[derive(Default)]
 pub struct QrCodeCache {
     url: String,
+    /// Physical pixels the texture was rendered for
+    pixels: u32,
     texture: Option<TextureHandle>,
 }
 
@@ -16,16 +29,236 @@ pub enum ShareTab {
     #[default]
     Lan,
     Wan,
+    Pair,
+    File,
+}
+
+/// Link phrase action picked in the Pair tab, run by the app over WAN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkRequest {
+    /// Show a new phrase
+    Offer,
+    /// Link with the device showing this phrase
+    Join(LinkPhrase),
+}
+
+/// Pair tab: our own invite and the invite being scanned
+#[derive(Default)]
+pub struct PairTabState {
+    invite: Option<(String, Instant)>,
+    link_input: String,
+    scan_dialog: Option<FileDialogTask>,
+    message: Option<String>,
+    /// Link phrase we are showing, until it expires
+    phrase: Option<(String, Instant)>,
+    phrase_input: String,
+    link_request: Option<LinkRequest>,
+    /// A link is in progress
+    linking: bool,
+    /// Share page link read from a QR image or opened from a `p2p://` link
+    scanned_url: Option<String>,
+    /// Endpoint ID read from a QR image, for the WAN window
+    scanned_endpoint: Option<String>,
+}
+
+impl PairTabState {
+    /// Show a freshly issued invite until it expires
+    pub fn show_invite(&mut self, uri: String, expires_in_secs: u64) {
+        self.invite = Some((uri, Instant::now() + Duration::from_secs(expires_in_secs)));
+    }
+
+    /// Show a link phrase until it expires
+    pub fn show_phrase(&mut self, phrase: String, expires_in_secs: u64) {
+        self.linking = false;
+        self.phrase = Some((
+            phrase,
+            Instant::now() + Duration::from_secs(expires_in_secs),
+        ));
+    }
+
+    /// A link finished, either side; the phrase is used up
+    pub fn linked(&mut self, peer_name: &str) {
+        self.linking = false;
+        self.phrase = None;
+        self.message = Some(format!("Linked with {}", peer_name));
+    }
+
+    /// A link could not be made
+    pub fn link_failed(&mut self, message: String) {
+        self.linking = false;
+        self.message = Some(message);
+    }
+
+    /// The link action picked since the last call
+    pub fn take_link_request(&mut self) -> Option<LinkRequest> {
+        self.link_request.take()
+    }
+
+    /// Offer a share page to open, as if its QR code was scanned
+    pub fn show_share_page(&mut self, url: String) {
+        self.message = None;
+        self.scanned_url = Some(url);
+    }
+
+    /// The endpoint ID scanned since the last call
+    pub fn take_scanned_endpoint(&mut self) -> Option<String> {
+        self.scanned_endpoint.take()
+    }
+
+    fn scanned(&mut self, code: ScannedCode) {
+        match code {
+            ScannedCode::Invite(link) => self.link_input = link,
+            ScannedCode::ShareUrl(url) => self.scanned_url = Some(url),
+            ScannedCode::EndpointId(id) => self.scanned_endpoint = Some(id),
+        }
+    }
+
+    fn request_link(&mut self, request: LinkRequest) {
+        self.linking = true;
+        self.message = None;
+        self.link_request = Some(request);
+    }
+}
+
+/// LAN tab: text exchanged with the share page
+#[derive(Default)]
+pub struct SharedTextState {
+    draft: String,
+    /// Latest text pasted into the page, with the sender's IP
+    received: Option<(String, String)>,
+}
+
+impl SharedTextState {
+    pub fn receive(&mut self, text: String, from_ip: String) {
+        self.received = Some((text, from_ip));
+    }
+}
+
+/// What a link hands out
+enum LinkKind {
+    /// One file
+    File { single_use: bool },
+    /// Several files under one page, with downloads per file
+    Share { files: Vec<(String, u32)> },
+}
+
+/// A download link issued for one file or a share of several
+struct FileLink {
+    /// Link or share ID, for revoking
+    id: String,
+    label: String,
+    url: String,
+    wan_url: Option<String>,
+    expires_at: Option<Instant>,
+    kind: LinkKind,
+}
+
+/// File tab: download links for single files and temporary shares
+pub struct FileLinkTabState {
+    dialog: Option<FileDialogTask>,
+    single_use: bool,
+    /// Share the files picked together under one link
+    together: bool,
+    /// Expiry of new links; 0 keeps them until the app exits
+    expiry_minutes: u64,
+    links: Vec<FileLink>,
+    selected: usize,
+}
+
+impl Default for FileLinkTabState {
+    fn default() -> Self {
+        Self {
+            dialog: None,
+            single_use: true,
+            together: false,
+            expiry_minutes: 30,
+            links: Vec::new(),
+            selected: 0,
+        }
+    }
 }
 
-/// Generate a QR code image from URL string
-fn generate_qr_image(url: &str) -> Option<ColorImage> {
+impl FileLinkTabState {
+    /// Show a freshly issued link, selecting it
+    pub fn add_link(
+        &mut self,
+        link_id: String,
+        file_name: String,
+        url: String,
+        wan_url: Option<String>,
+        expires_in_secs: Option<u64>,
+        single_use: bool,
+    ) {
+        self.push(FileLink {
+            id: link_id,
+            label: file_name,
+            url,
+            wan_url,
+            expires_at: expires_in_secs.map(|secs| Instant::now() + Duration::from_secs(secs)),
+            kind: LinkKind::File { single_use },
+        });
+    }
+
+    /// Show a freshly created share, selecting it
+    pub fn add_share(
+        &mut self,
+        share_id: String,
+        file_names: Vec<String>,
+        url: String,
+        wan_url: Option<String>,
+        expires_in_secs: u64,
+    ) {
+        self.push(FileLink {
+            id: share_id,
+            label: format!("{} files", file_names.len()),
+            url,
+            wan_url,
+            expires_at: Some(Instant::now() + Duration::from_secs(expires_in_secs)),
+            kind: LinkKind::Share {
+                files: file_names.into_iter().map(|name| (name, 0)).collect(),
+            },
+        });
+    }
+
+    /// Note a download of `file_name` from the share `share_id`
+    pub fn record_download(&mut self, share_id: &str, file_name: &str, downloads: u32) {
+        for link in self.links.iter_mut().filter(|link| link.id == share_id) {
+            if let LinkKind::Share { files } = &mut link.kind {
+                for (_, count) in files.iter_mut().filter(|(name, _)| name == file_name) {
+                    *count = downloads.max(*count);
+                }
+            }
+        }
+    }
+
+    /// Downloads of all files of the share `share_id`
+    pub fn downloads(&self, share_id: &str) -> Option<u32> {
+        self.links
+            .iter()
+            .find(|link| link.id == share_id)
+            .and_then(|link| match &link.kind {
+                LinkKind::Share { files } => Some(files.iter().map(|(_, count)| count).sum()),
+                LinkKind::File { .. } => None,
+            })
+    }
+
+    fn push(&mut self, link: FileLink) {
+        self.links.push(link);
+        self.selected = self.links.len() - 1;
+    }
+}
+
+/// Generate a QR code image from URL string, at most `max_pixels` wide.
+/// Every module is a whole number of pixels, so edges stay sharp when the
+/// image is shown at its native size.
+fn generate_qr_image(url: &str, max_pixels: u32) -> Option<ColorImage> {
     let code = QrCode::new(url.as_bytes()).ok()?;
 
+    let modules = code.width() as u32 + 2 * QR_QUIET_ZONE;
+    let module_pixels = (max_pixels / modules).max(1);
     let qr_image = code
         .render::<image::Luma<u8>>()
-        .min_dimensions(200, 200)
-        .max_dimensions(400, 400)
+        .module_dimensions(module_pixels, module_pixels)
         .build();
 
     let width = qr_image.width() as usize;
@@ -124,8 +357,14 @@ pub fn show(
     wan_url: Option<&str>,
     wan_share_running: bool,
     wan_share_pending: &mut bool,
+    // Pairing invites
+    pair_state: &mut PairTabState,
+    // Text box shared with the LAN page
+    shared_text: &mut SharedTextState,
+    // Single-file download links
+    file_links: &mut FileLinkTabState,
     // Command sender
-    cmd_sender: &mpsc::Sender<AppCommand>,
+    cmd_sender: &CommandBridge,
 ) {
     egui::Window::new("QR Code Share")
         .open(open)
@@ -157,6 +396,28 @@ pub fn show(
                         *selected_tab = ShareTab::Wan;
                         *cache = QrCodeCache::default();
                     }
+                    ui.separator();
+                    if ui
+                        .selectable_label(
+                            *selected_tab == ShareTab::Pair,
+                            format!("{} Pair", egui_phosphor::regular::HANDSHAKE),
+                        )
+                        .clicked()
+                    {
+                        *selected_tab = ShareTab::Pair;
+                        *cache = QrCodeCache::default();
+                    }
+                    ui.separator();
+                    if ui
+                        .selectable_label(
+                            *selected_tab == ShareTab::File,
+                            format!("{} File", egui_phosphor::regular::FILE),
+                        )
+                        .clicked()
+                    {
+                        *selected_tab = ShareTab::File;
+                        *cache = QrCodeCache::default();
+                    }
                 });
 
                 ui.add_space(8.0);
@@ -171,6 +432,7 @@ pub fn show(
                             lan_url,
                             lan_server_running,
                             lan_server_pending,
+                            shared_text,
                             cmd_sender,
                         );
                     }
@@ -185,12 +447,19 @@ pub fn show(
                             cmd_sender,
                         );
                     }
+                    ShareTab::Pair => {
+                        show_pair_tab(ui, ctx, cache, pair_state, cmd_sender);
+                    }
+                    ShareTab::File => {
+                        show_file_tab(ui, ctx, cache, file_links, cmd_sender);
+                    }
                 }
             });
         });
 }
 
 /// Show LAN share tab content
+#[allow(clippy::too_many_arguments)]
 fn show_lan_tab(
     ui: &mut egui::Ui,
     ctx: &egui::Context,
@@ -198,7 +467,8 @@ fn show_lan_tab(
     url: &str,
     server_running: bool,
     server_pending: &mut bool,
-    cmd_sender: &mpsc::Sender<AppCommand>,
+    shared_text: &mut SharedTextState,
+    cmd_sender: &CommandBridge,
 ) {
     let mut toggle_state = server_running;
 
@@ -227,9 +497,9 @@ fn show_lan_tab(
         if response.changed() {
             *server_pending = true;
             if toggle_state {
-                let _ = cmd_sender.try_send(AppCommand::StartHttpServer);
+                cmd_sender.send(AppCommand::StartHttpServer);
             } else {
-                let _ = cmd_sender.try_send(AppCommand::StopHttpServer);
+                cmd_sender.send(AppCommand::StopHttpServer);
             }
         }
     });
@@ -239,6 +509,10 @@ fn show_lan_tab(
 
     if server_running {
         show_qr_and_url(ui, ctx, cache, url);
+        copy_app_link(ui, ctx, url);
+        ui.add_space(8.0);
+        ui.separator();
+        show_shared_text(ui, ctx, shared_text, cmd_sender);
     } else {
         ui.add_space(40.0);
         ui.label("LAN server is not running.");
@@ -255,7 +529,7 @@ fn show_wan_tab(
     wan_url: Option<&str>,
     wan_running: bool,
     wan_pending: &mut bool,
-    cmd_sender: &mpsc::Sender<AppCommand>,
+    cmd_sender: &CommandBridge,
 ) {
     let mut toggle_state = wan_running;
 
@@ -284,9 +558,9 @@ fn show_wan_tab(
         if response.changed() {
             *wan_pending = true;
             if toggle_state {
-                let _ = cmd_sender.try_send(AppCommand::StartWanShare);
+                cmd_sender.send(AppCommand::StartWanShare);
             } else {
-                let _ = cmd_sender.try_send(AppCommand::StopWanShare);
+                cmd_sender.send(AppCommand::StopWanShare);
             }
         }
     });
@@ -297,6 +571,7 @@ fn show_wan_tab(
     if wan_running {
         if let Some(url) = wan_url {
             show_qr_and_url(ui, ctx, cache, url);
+            copy_app_link(ui, ctx, url);
         } else {
             ui.add_space(40.0);
             ui.label("Waiting for tunnel...");
@@ -309,19 +584,398 @@ fn show_wan_tab(
     }
 }
 
+/// Show pairing tab content: our invite QR code, and scanning someone else's
+fn show_pair_tab(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    cache: &mut QrCodeCache,
+    state: &mut PairTabState,
+    cmd_sender: &CommandBridge,
+) {
+    if let Some(dialog) = &state.scan_dialog {
+        match dialog.poll() {
+            DialogResult::Pending => {}
+            DialogResult::Picked(paths) => {
+                state.scan_dialog = None;
+                if let Some(path) = paths.first() {
+                    match qr_scan::scan_file(path) {
+                        Ok(code) => state.scanned(code),
+                        Err(e) => state.message = Some(format!("Could not read QR code: {}", e)),
+                    }
+                }
+            }
+            DialogResult::Cancelled => state.scan_dialog = None,
+        }
+    }
+
+    ui.add_space(8.0);
+    ui.label("Let another device pair with this one:");
+    ui.add_space(4.0);
+
+    if let Some((_, expires_at)) = &state.invite
+        && Instant::now() >= *expires_at
+    {
+        state.invite = None;
+    }
+    match &state.invite {
+        Some((uri, expires_at)) => {
+            show_qr_and_url(ui, ctx, cache, uri);
+            let remaining = expires_at.saturating_duration_since(Instant::now());
+            ui.label(format!(
+                "Valid for one pairing, expires in {}:{:02}",
+                remaining.as_secs() / 60,
+                remaining.as_secs() % 60
+            ));
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+        None => {
+            if ui
+                .button(format!(
+                    "{} Show pairing QR code",
+                    egui_phosphor::regular::QR_CODE
+                ))
+                .clicked()
+            {
+                cmd_sender.send(AppCommand::CreatePairingInvite);
+            }
+        }
+    }
+
+    ui.add_space(8.0);
+    ui.separator();
+    ui.label("Pair with another device by scanning its code:");
+    ui.add_space(4.0);
+
+    if ui
+        .add_enabled(
+            state.scan_dialog.is_none(),
+            egui::Button::new(format!(
+                "{} Import QR from image...",
+                egui_phosphor::regular::IMAGE
+            )),
+        )
+        .on_hover_text("Pairing links, share page links and WAN endpoint IDs")
+        .clicked()
+    {
+        state.message = None;
+        state.scanned_url = None;
+        state.scan_dialog = Some(FileDialogTask::pick_image(ctx));
+    }
+
+    if let Some(url) = state.scanned_url.clone() {
+        ui.horizontal(|ui| {
+            ui.label("Share page:");
+            ui.hyperlink(&url);
+            if ui
+                .button(egui_phosphor::regular::COPY)
+                .on_hover_text("Copy to clipboard")
+                .clicked()
+            {
+                ctx.copy_text(url);
+            }
+        });
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Link:");
+        ui.text_edit_singleline(&mut state.link_input)
+            .on_hover_text("p2p-pair:// link, filled in by a scanned image or pasted");
+    });
+
+    if ui.button("Pair").clicked() {
+        match PairingInvite::parse(&state.link_input) {
+            Ok(invite) => {
+                cmd_sender.send(AppCommand::RedeemPairingInvite {
+                    session_id: p2p_core::new_session_id(),
+                    invite: state.link_input.trim().to_string(),
+                });
+                state.message = Some(format!("Pairing with {}...", invite.peer_name));
+                state.link_input.clear();
+            }
+            Err(e) => state.message = Some(e.to_string()),
+        }
+    }
+
+    ui.add_space(8.0);
+    ui.separator();
+    show_link_phrase(ui, ctx, state);
+
+    if let Some(message) = &state.message {
+        ui.label(message.as_str());
+    }
+}
+
+/// Link devices on different networks with a one-time phrase
+fn show_link_phrase(ui: &mut egui::Ui, ctx: &egui::Context, state: &mut PairTabState) {
+    ui.label("Or link a device on another network with a phrase:");
+    ui.add_space(4.0);
+
+    if let Some((_, expires_at)) = &state.phrase
+        && Instant::now() >= *expires_at
+    {
+        state.phrase = None;
+    }
+    match &state.phrase {
+        Some((phrase, expires_at)) => {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new(phrase).monospace().strong());
+                if ui
+                    .button(egui_phosphor::regular::COPY)
+                    .on_hover_text("Copy to clipboard")
+                    .clicked()
+                {
+                    ctx.copy_text(phrase.clone());
+                }
+            });
+            let remaining = expires_at.saturating_duration_since(Instant::now());
+            ui.label(format!(
+                "Type it on the other device within {}:{:02}",
+                remaining.as_secs() / 60,
+                remaining.as_secs() % 60
+            ));
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+        None => {
+            if ui
+                .add_enabled(!state.linking, egui::Button::new("Show link phrase"))
+                .clicked()
+            {
+                state.request_link(LinkRequest::Offer);
+            }
+        }
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Phrase:");
+        ui.text_edit_singleline(&mut state.phrase_input)
+            .on_hover_text("The six words shown on the other device");
+        if ui
+            .add_enabled(!state.linking, egui::Button::new("Link"))
+            .clicked()
+        {
+            match LinkPhrase::parse(&state.phrase_input) {
+                Ok(phrase) => {
+                    state.phrase_input.clear();
+                    state.request_link(LinkRequest::Join(phrase));
+                    state.message = Some("Linking...".to_string());
+                }
+                Err(e) => state.message = Some(e.to_string()),
+            }
+        }
+    });
+}
+
 /// Show QR code and URL with copy button
+/// Show the File tab: issue links for single files and show one as a QR code
+fn show_file_tab(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    cache: &mut QrCodeCache,
+    state: &mut FileLinkTabState,
+    cmd_sender: &CommandBridge,
+) {
+    if let Some(dialog) = &state.dialog {
+        match dialog.poll() {
+            DialogResult::Pending => {}
+            DialogResult::Picked(paths) => {
+                state.dialog = None;
+                let expires_in_secs = (state.expiry_minutes > 0).then(|| state.expiry_minutes * 60);
+                if state.together && paths.len() > 1 {
+                    cmd_sender.send(AppCommand::ShareFiles {
+                        paths,
+                        expires_in_secs,
+                    });
+                } else {
+                    for path in paths {
+                        cmd_sender.send(AppCommand::ShareFileLink {
+                            path,
+                            expires_in_secs,
+                            single_use: state.single_use,
+                        });
+                    }
+                }
+            }
+            DialogResult::Cancelled => state.dialog = None,
+        }
+    }
+
+    let now = Instant::now();
+    state
+        .links
+        .retain(|link| link.expires_at.is_none_or(|at| now < at));
+    state.selected = state.selected.min(state.links.len().saturating_sub(1));
+
+    ui.add_space(8.0);
+    ui.label("Give someone a link to exactly the files you choose:");
+    ui.add_space(4.0);
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut state.together, "One link for all")
+            .on_hover_text("Share the chosen files on one page, counting their downloads");
+        ui.add_enabled(
+            !state.together,
+            egui::Checkbox::new(&mut state.single_use, "Single use"),
+        );
+        ui.label("Expires after");
+        let expiry_hint = if state.together {
+            "0 uses the default of one hour"
+        } else {
+            "0 keeps the link until the app exits"
+        };
+        ui.add(
+            egui::DragValue::new(&mut state.expiry_minutes)
+                .range(0..=24 * 60)
+                .suffix(" min"),
+        )
+        .on_hover_text(expiry_hint);
+    });
+    if ui
+        .add_enabled(
+            state.dialog.is_none(),
+            egui::Button::new(format!(
+                "{} Choose files...",
+                egui_phosphor::regular::FOLDER_OPEN
+            )),
+        )
+        .clicked()
+    {
+        state.dialog = Some(FileDialogTask::pick_files(ctx));
+    }
+
+    let Some(link) = state.links.get(state.selected) else {
+        return;
+    };
+    ui.add_space(8.0);
+    ui.separator();
+    ui.label(&link.label);
+    show_qr_and_url(ui, ctx, cache, &link.url);
+    if let LinkKind::Share { files } = &link.kind {
+        for (name, downloads) in files {
+            ui.horizontal(|ui| {
+                ui.label(name);
+                ui.weak(format!("{} download(s)", downloads));
+            });
+        }
+        if let Some(total) = state.downloads(&link.id) {
+            ui.weak(format!("{} download(s) in total", total));
+        }
+    }
+    if let Some(wan_url) = &link.wan_url
+        && ui
+            .button(format!("{} Copy WAN URL", egui_phosphor::regular::GLOBE))
+            .clicked()
+    {
+        ctx.copy_text(wan_url.clone());
+    }
+
+    ui.add_space(8.0);
+    ui.separator();
+    let mut revoked = None;
+    for (i, link) in state.links.iter().enumerate() {
+        ui.horizontal(|ui| {
+            if ui
+                .selectable_label(i == state.selected, &link.label)
+                .clicked()
+            {
+                state.selected = i;
+            }
+            let mut notes = Vec::new();
+            match &link.kind {
+                LinkKind::File { single_use: true } => notes.push("single use".to_string()),
+                LinkKind::File { single_use: false } => {}
+                LinkKind::Share { files } => {
+                    let downloads: u32 = files.iter().map(|(_, count)| count).sum();
+                    notes.push(format!("{} download(s)", downloads));
+                }
+            }
+            if let Some(at) = link.expires_at {
+                let remaining = at.saturating_duration_since(now).as_secs();
+                notes.push(format!("{}:{:02} left", remaining / 60, remaining % 60));
+            }
+            ui.weak(notes.join(", "));
+            if ui
+                .small_button(egui_phosphor::regular::TRASH)
+                .on_hover_text("Revoke link")
+                .clicked()
+            {
+                revoked = Some(i);
+            }
+        });
+    }
+    if let Some(i) = revoked {
+        let link = state.links.remove(i);
+        cmd_sender.send(match link.kind {
+            LinkKind::File { .. } => AppCommand::RevokeFileLink { link_id: link.id },
+            LinkKind::Share { .. } => AppCommand::RevokeFileShare { share_id: link.id },
+        });
+    }
+    if state.links.iter().any(|link| link.expires_at.is_some()) {
+        ctx.request_repaint_after(Duration::from_secs(1));
+    }
+}
+
+/// Text box shared with the page: what the page sent, and what to send it
+fn show_shared_text(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    state: &mut SharedTextState,
+    cmd_sender: &CommandBridge,
+) {
+    if let Some((text, from_ip)) = &state.received {
+        ui.label(format!("Text from {}:", from_ip));
+        let mut shown = text.as_str();
+        ui.add(
+            egui::TextEdit::multiline(&mut shown)
+                .desired_rows(2)
+                .desired_width(f32::INFINITY),
+        );
+        if ui
+            .button(format!("{} Copy text", egui_phosphor::regular::CLIPBOARD))
+            .clicked()
+        {
+            ctx.copy_text(text.clone());
+        }
+        ui.add_space(4.0);
+    }
+
+    ui.add(
+        egui::TextEdit::multiline(&mut state.draft)
+            .hint_text("Text to show on the page")
+            .desired_rows(2)
+            .desired_width(f32::INFINITY),
+    );
+    let can_send = !state.draft.trim().is_empty();
+    if ui
+        .add_enabled(
+            can_send,
+            egui::Button::new(format!(
+                "{} Send to page",
+                egui_phosphor::regular::PAPER_PLANE_RIGHT
+            )),
+        )
+        .clicked()
+    {
+        cmd_sender.send(AppCommand::PushTextToWeb {
+            text: state.draft.clone(),
+        });
+    }
+}
+
 fn show_qr_and_url(ui: &mut egui::Ui, ctx: &egui::Context, cache: &mut QrCodeCache, url: &str) {
-    // Generate or reuse cached texture
-    if (cache.url != url || cache.texture.is_none())
-        && let Some(image) = generate_qr_image(url)
+    // Render for the display's pixel density; regenerate when the window
+    // moves to a screen with another scale
+    let pixels_per_point = ctx.pixels_per_point();
+    let pixels = (QR_SIZE_POINTS * pixels_per_point).round() as u32;
+    if (cache.url != url || cache.pixels != pixels || cache.texture.is_none())
+        && let Some(image) = generate_qr_image(url, pixels)
     {
         cache.texture = Some(ctx.load_texture("qr_code", image, TextureOptions::NEAREST));
         cache.url = url.to_string();
+        cache.pixels = pixels;
     }
 
-    // Display QR code
+    // Display QR code one texel per physical pixel
     if let Some(texture) = &cache.texture {
-        let size = egui::vec2(220.0, 220.0);
+        let size = texture.size_vec2() / pixels_per_point;
         ui.image((texture.id(), size));
     } else {
         ui.label("Failed to generate QR code");
@@ -349,3 +1003,53 @@ fn show_qr_and_url(ui: &mut egui::Ui, ctx: &egui::Context, cache: &mut QrCodeCac
         ctx.copy_text(url.to_string());
     }
 }
+
+/// Copy a `p2p://` link that opens the share page at `url` in the app
+fn copy_app_link(ui: &mut egui::Ui, ctx: &egui::Context, url: &str) {
+    let Ok(link) = AppLink::for_share_page(url) else {
+        return;
+    };
+    if ui
+        .button(format!("{} Copy app link", egui_phosphor::regular::LINK))
+        .on_hover_text(link.to_uri())
+        .clicked()
+    {
+        ctx.copy_text(link.to_uri());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_requests_are_taken_once() {
+        let mut state = PairTabState::default();
+        state.request_link(LinkRequest::Offer);
+        assert!(state.linking);
+        assert_eq!(state.take_link_request(), Some(LinkRequest::Offer));
+        assert_eq!(state.take_link_request(), None);
+
+        state.show_phrase("acid acorn actor adult agent alarm".to_string(), 600);
+        state.linked("Laptop");
+        assert!(state.phrase.is_none() && !state.linking);
+        assert_eq!(state.message.as_deref(), Some("Linked with Laptop"));
+    }
+
+    #[test]
+    fn test_qr_image_uses_whole_pixels_per_module() {
+        let url = "http://192.168.1.20:8080/0123456789abcdef0123456789abcdef";
+        let modules = QrCode::new(url.as_bytes()).unwrap().width() + 2 * QR_QUIET_ZONE as usize;
+        for max_pixels in [220, 330, 440, 880] {
+            let image = generate_qr_image(url, max_pixels).unwrap();
+            assert_eq!(image.size[0], image.size[1]);
+            assert!(image.size[0] <= max_pixels as usize);
+            assert_eq!(image.size[0] % modules, 0);
+        }
+        // A 4K screen gets a proportionally larger texture
+        assert!(
+            generate_qr_image(url, 880).unwrap().size[0]
+                > 3 * generate_qr_image(url, 220).unwrap().size[0]
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! Sent files the receiver could not verify, offered for sending again.
+
+use crate::bridge::CommandBridge;
+use eframe::egui;
+use egui_phosphor::regular::{ARROW_CLOCKWISE, WARNING, X};
+use p2p_core::AppCommand;
+use std::path::PathBuf;
+
+/// A corrupted send, see `AppEvent::ResendOffered`
+#[derive(Debug, Clone)]
+pub struct ResendOffer {
+    pub file_name: String,
+    pub path: PathBuf,
+    pub target_ip: String,
+    pub target_peer_name: String,
+}
+
+/// Ask about each corrupted send until it is sent again or dismissed
+pub fn show(ctx: &egui::Context, offers: &mut Vec<ResendOffer>, cmd_tx: &CommandBridge) {
+    if offers.is_empty() {
+        return;
+    }
+    let mut answered = None;
+    egui::Window::new(format!("{} Verification Failed", WARNING))
+        .id(egui::Id::new("resend_offers"))
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            ui.label("The receiver's copy does not match. Send it again?");
+            ui.separator();
+            for (index, offer) in offers.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} to {}", offer.file_name, offer.target_peer_name));
+                    if ui
+                        .button(format!("{} Send again", ARROW_CLOCKWISE))
+                        .clicked()
+                    {
+                        answered = Some((index, true));
+                    }
+                    if ui.button(format!("{} Dismiss", X)).clicked() {
+                        answered = Some((index, false));
+                    }
+                });
+            }
+        });
+    if let Some((index, resend)) = answered {
+        let offer = offers.remove(index);
+        if resend {
+            cmd_tx.send(AppCommand::SendFile {
+                session_id: p2p_core::new_session_id(),
+                target_ip: offer.target_ip,
+                target_endpoint_id: String::new(),
+                target_peer_name: offer.target_peer_name,
+                files: vec![offer.path],
+                note: None,
+            });
+        }
+    }
+}
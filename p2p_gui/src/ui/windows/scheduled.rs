@@ -0,0 +1,70 @@
+use crate::bridge::CommandBridge;
+use eframe::egui;
+use egui_phosphor::regular::{CLOCK, X};
+use p2p_core::AppCommand;
+use p2p_core::schedule::ScheduledSend;
+
+/// List pending scheduled sends with a cancel button each
+pub fn show(ctx: &egui::Context, open: &mut bool, jobs: &[ScheduledSend], cmd_tx: &CommandBridge) {
+    egui::Window::new("Scheduled Sends")
+        .open(open)
+        .resizable(true)
+        .default_size([320.0, 180.0])
+        .show(ctx, |ui| {
+            if jobs.is_empty() {
+                ui.label("Nothing scheduled. Use the clock button in Devices to send later.");
+                return;
+            }
+
+            let now = unix_now();
+            for job in jobs {
+                ui.horizontal(|ui| {
+                    ui.label(CLOCK);
+                    ui.label(format!(
+                        "{} file(s) to {} {}",
+                        job.files.len(),
+                        job.target_peer_name,
+                        format_countdown(job.at.saturating_sub(now))
+                    ));
+                    if ui.button(X).on_hover_text("Cancel this send").clicked() {
+                        cmd_tx.send(AppCommand::CancelScheduledSend {
+                            job_id: job.id.clone(),
+                        });
+                    }
+                });
+            }
+        });
+}
+
+/// "in 1h 05m", or a waiting note once the start time has passed
+fn format_countdown(remaining_secs: u64) -> String {
+    if remaining_secs == 0 {
+        return "(waiting for the device)".to_string();
+    }
+    let minutes = remaining_secs.div_ceil(60);
+    if minutes < 60 {
+        format!("in {}m", minutes)
+    } else {
+        format!("in {}h {:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_countdown() {
+        assert_eq!(format_countdown(0), "(waiting for the device)");
+        assert_eq!(format_countdown(30), "in 1m");
+        assert_eq!(format_countdown(59 * 60), "in 59m");
+        assert_eq!(format_countdown(65 * 60), "in 1h 05m");
+    }
+}
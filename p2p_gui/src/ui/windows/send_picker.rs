@@ -0,0 +1,250 @@
+//! Files from the command line or the file manager, waiting for a device.
+//!
+//! With `--to` the files go out as soon as a matching device is found (or
+//! right away to an IP address); otherwise, or when the device does not
+//! show up in time, the user picks one here.
+
+use crate::bridge::CommandBridge;
+use crate::cli::SendRequest;
+use crate::ui::windows::devices::PeerEntry;
+use eframe::egui;
+use egui_phosphor::regular::{DESKTOP, PAPER_PLANE_RIGHT};
+use p2p_core::AppCommand;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// How long a `--to` device may take to be discovered before asking
+const FIND_PEER_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// File names listed before "and N more"
+const LISTED_FILES: usize = 5;
+
+struct PendingSend {
+    files: Vec<PathBuf>,
+    to: Option<String>,
+    since: Instant,
+    /// Why the user is asked after all
+    note: Option<String>,
+}
+
+#[derive(Default)]
+pub struct SendPickerState {
+    queue: Vec<PendingSend>,
+    /// Requests handed over by later instances
+    hand_offs: Option<mpsc::Receiver<SendRequest>>,
+}
+
+impl SendPickerState {
+    pub fn new(
+        request: Option<SendRequest>,
+        hand_offs: Option<mpsc::Receiver<SendRequest>>,
+    ) -> Self {
+        let mut state = Self {
+            queue: Vec::new(),
+            hand_offs,
+        };
+        if let Some(request) = request {
+            state.add(request);
+        }
+        state
+    }
+
+    /// Queue `request`, joining the previous one for the same device, as
+    /// the file manager starts one process per selected file
+    pub fn add(&mut self, request: SendRequest) {
+        if let Some(last) = self.queue.last_mut()
+            && last.to == request.to
+        {
+            for file in request.files {
+                if !last.files.contains(&file) {
+                    last.files.push(file);
+                }
+            }
+            return;
+        }
+        self.queue.push(PendingSend {
+            files: request.files,
+            to: request.to,
+            since: Instant::now(),
+            note: None,
+        });
+    }
+}
+
+/// Peer named by `--to`: endpoint ID, IP address or (case-insensitive) name
+fn find_peer<'a>(peers: &'a HashMap<String, PeerEntry>, to: &str) -> Option<&'a PeerEntry> {
+    let to = to.trim();
+    peers.values().find(|peer| {
+        peer.endpoint_id == to
+            || peer.ip == to
+            || peer.display_name.eq_ignore_ascii_case(to)
+            || peer.hostname.eq_ignore_ascii_case(to)
+    })
+}
+
+fn is_address(to: &str) -> bool {
+    to.parse::<IpAddr>().is_ok() || to.parse::<SocketAddr>().is_ok()
+}
+
+fn send(cmd_tx: &CommandBridge, pending: PendingSend, target_ip: String, peer: Option<&PeerEntry>) {
+    cmd_tx.send(AppCommand::SendFile {
+        session_id: p2p_core::new_session_id(),
+        target_endpoint_id: peer
+            .map(|peer| peer.endpoint_id.clone())
+            .unwrap_or_default(),
+        target_peer_name: peer.map_or_else(|| target_ip.clone(), |peer| peer.hostname.clone()),
+        target_ip,
+        files: pending.files,
+        note: None,
+    });
+}
+
+pub fn show(
+    ctx: &egui::Context,
+    state: &mut SendPickerState,
+    peers: &HashMap<String, PeerEntry>,
+    receive_only: bool,
+    cmd_tx: &CommandBridge,
+) {
+    while let Some(request) = state.hand_offs.as_ref().and_then(|rx| rx.try_recv().ok()) {
+        state.add(request);
+    }
+    let Some(pending) = state.queue.first_mut() else {
+        return;
+    };
+
+    if let Some(to) = pending.to.clone()
+        && !receive_only
+    {
+        if let Some(peer) = find_peer(peers, &to) {
+            let pending = state.queue.remove(0);
+            send(cmd_tx, pending, peer.ip.clone(), Some(peer));
+            return;
+        }
+        if is_address(&to) {
+            let pending = state.queue.remove(0);
+            send(cmd_tx, pending, to, None);
+            return;
+        }
+        if pending.since.elapsed() >= FIND_PEER_TIMEOUT {
+            pending.note = Some(format!("No device called \"{}\" was found.", to));
+            pending.to = None;
+        } else {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+    }
+
+    let mut open = true;
+    let mut chosen = None;
+    let mut cancel = false;
+    egui::Window::new(format!("{} Send Files", PAPER_PLANE_RIGHT))
+        .id(egui::Id::new("send_picker"))
+        .collapsible(false)
+        .open(&mut open)
+        .default_size([320.0, 260.0])
+        .show(ctx, |ui| {
+            let count = pending.files.len();
+            ui.label(format!("{} file(s):", count));
+            for file in pending.files.iter().take(LISTED_FILES) {
+                let name = file.file_name().unwrap_or(file.as_os_str());
+                ui.monospace(name.to_string_lossy());
+            }
+            if count > LISTED_FILES {
+                ui.weak(format!("and {} more", count - LISTED_FILES));
+            }
+            ui.separator();
+
+            if receive_only {
+                ui.label("Receive-only mode: this device does not send files.");
+            } else if let Some(to) = &pending.to {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(format!("Looking for \"{}\"...", to));
+                });
+            } else {
+                if let Some(note) = &pending.note {
+                    ui.label(note);
+                }
+                ui.label("Send to:");
+                if peers.is_empty() {
+                    ui.label("Searching...");
+                }
+                let mut sorted: Vec<_> = peers.values().collect();
+                sorted.sort_by(|a, b| (&a.display_name, &a.ip).cmp(&(&b.display_name, &b.ip)));
+                for peer in sorted {
+                    if ui
+                        .button(format!("{} {} ({})", DESKTOP, peer.display_name, peer.ip))
+                        .clicked()
+                    {
+                        chosen = Some(peer);
+                    }
+                }
+            }
+            ui.separator();
+            cancel = ui.button("Cancel").clicked();
+        });
+
+    if let Some(peer) = chosen {
+        let pending = state.queue.remove(0);
+        send(cmd_tx, pending, peer.ip.clone(), Some(peer));
+    } else if cancel || !open {
+        state.queue.remove(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(endpoint_id: &str, ip: &str, name: &str) -> PeerEntry {
+        PeerEntry {
+            endpoint_id: endpoint_id.to_string(),
+            ip: ip.to_string(),
+            port: 8888,
+            hostname: name.to_string(),
+            display_name: format!("{} (2)", name),
+            receive_only: false,
+            version: None,
+            protocol: 0,
+            rtt_ms: None,
+            last_seen: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_find_peer() {
+        let peers: HashMap<_, _> = [
+            ("a".to_string(), peer("id-a", "192.168.1.2", "Laptop")),
+            ("b".to_string(), peer("id-b", "192.168.1.3", "Desktop")),
+        ]
+        .into();
+        let found = |to| find_peer(&peers, to).map(|peer| peer.endpoint_id.as_str());
+        assert_eq!(found("laptop"), Some("id-a"));
+        assert_eq!(found("Laptop (2)"), Some("id-a"));
+        assert_eq!(found("192.168.1.3"), Some("id-b"));
+        assert_eq!(found("id-b"), Some("id-b"));
+        assert_eq!(found("Phone"), None);
+        assert!(is_address("10.0.0.5") && is_address("10.0.0.5:9000"));
+        assert!(!is_address("Phone"));
+    }
+
+    #[test]
+    fn test_requests_for_same_device_merge() {
+        let request = |file: &str, to: Option<&str>| SendRequest {
+            files: vec![PathBuf::from(file)],
+            to: to.map(str::to_string),
+        };
+        let mut state = SendPickerState::new(Some(request("/a", None)), None);
+        state.add(request("/b", None));
+        state.add(request("/a", None));
+        state.add(request("/c", Some("Laptop")));
+        assert_eq!(state.queue.len(), 2);
+        assert_eq!(
+            state.queue[0].files,
+            [PathBuf::from("/a"), PathBuf::from("/b")]
+        );
+    }
+}
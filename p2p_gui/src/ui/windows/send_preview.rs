@@ -0,0 +1,126 @@
+//! What a send would do on the receiver, confirmed before any bytes flow.
+
+use crate::bridge::CommandBridge;
+use eframe::egui;
+use egui_phosphor::regular::{EYE, PAPER_PLANE_RIGHT, WARNING, X};
+use p2p_core::AppCommand;
+use p2p_core::transfer::{FileAction, SendEstimate};
+use p2p_core::units::format_size;
+use std::path::PathBuf;
+
+/// Files picked with "Preview", waiting for the receiver's answer and
+/// then for the user
+#[derive(Debug, Clone)]
+pub struct SendPreview {
+    /// Session of the `EstimateSend`, to match its answer
+    pub session_id: String,
+    pub ip: String,
+    pub name: String,
+    pub files: Vec<PathBuf>,
+    pub note: Option<String>,
+    /// `None` until the receiver answered
+    pub estimate: Option<SendEstimate>,
+}
+
+/// Show the oldest preview until it is sent or dropped
+pub fn show(ctx: &egui::Context, previews: &mut Vec<SendPreview>, cmd_tx: &CommandBridge) {
+    let Some(preview) = previews.first() else {
+        return;
+    };
+    let mut answered = None;
+    egui::Window::new(format!("{} Send to {}", EYE, preview.name))
+        .id(egui::Id::new("send_preview"))
+        .collapsible(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            let Some(estimate) = &preview.estimate else {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(format!("Asking {} what it already has…", preview.name));
+                });
+                if ui.button(format!("{} Cancel", X)).clicked() {
+                    answered = Some(false);
+                }
+                return;
+            };
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("send_preview_files")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for file in &estimate.preview {
+                                ui.label(&file.file_name);
+                                ui.weak(format_size(file.size));
+                                let text = action_label(&file.action);
+                                match file.action {
+                                    FileAction::Overwrite { .. } | FileAction::Refused { .. } => {
+                                        ui.colored_label(ui.visuals().warn_fg_color, text)
+                                    }
+                                    _ => ui.label(text),
+                                };
+                                ui.end_row();
+                            }
+                        });
+                });
+            if estimate.streams > 0 {
+                ui.weak(format!(
+                    "Plus {} stream(s) of unknown size",
+                    estimate.streams
+                ));
+            }
+            ui.separator();
+            ui.label(format!(
+                "{} of {} to send",
+                format_size(estimate.bytes_to_send),
+                format_size(estimate.total_bytes)
+            ));
+            if !estimate.fits() {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!(
+                        "{} Only {} free on {}",
+                        WARNING,
+                        estimate.free_space.map_or_else(String::new, format_size),
+                        preview.name
+                    ),
+                );
+            }
+            ui.horizontal(|ui| {
+                if ui.button(format!("{} Send", PAPER_PLANE_RIGHT)).clicked() {
+                    answered = Some(true);
+                }
+                if ui.button(format!("{} Cancel", X)).clicked() {
+                    answered = Some(false);
+                }
+            });
+        });
+    if let Some(send) = answered {
+        let preview = previews.remove(0);
+        if send {
+            cmd_tx.send(AppCommand::SendFile {
+                session_id: p2p_core::new_session_id(),
+                target_ip: preview.ip,
+                target_endpoint_id: String::new(),
+                target_peer_name: preview.name,
+                files: preview.files,
+                note: preview.note,
+            });
+        }
+    }
+}
+
+fn action_label(action: &FileAction) -> String {
+    match action {
+        FileAction::New => "New".to_string(),
+        FileAction::Resume { offset } => format!("Resume from {}", format_size(*offset)),
+        FileAction::Skip => "Already there, skipped".to_string(),
+        FileAction::Overwrite { existing_size } => {
+            format!(
+                "Overwrites a different file ({})",
+                format_size(*existing_size)
+            )
+        }
+        FileAction::Refused { reason } => format!("Refused: {}", reason),
+    }
+}
@@ -0,0 +1,89 @@
+//! Where the pieces of each swarm file are, see `AppEvent::SwarmAvailability`.
+
+use crate::ui::windows::devices::PeerEntry;
+use eframe::egui;
+use egui_phosphor::regular::SHARE_NETWORK;
+use p2p_core::swarm::availability::SwarmAvailability;
+use p2p_core::swarm::pieces::PieceBitmap;
+use std::collections::HashMap;
+
+const STRIP_HEIGHT: f32 = 10.0;
+
+/// Per file, a strip coloured by how many devices have each piece, then who
+/// has how much. Hidden when no swarm runs.
+pub fn show(ctx: &egui::Context, swarms: &[SwarmAvailability], peers: &HashMap<String, PeerEntry>) {
+    if swarms.is_empty() {
+        return;
+    }
+    egui::Window::new(format!("{} Swarm Availability", SHARE_NETWORK))
+        .id(egui::Id::new("swarm_availability"))
+        .collapsible(true)
+        .resizable(false)
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(10.0, -10.0))
+        .show(ctx, |ui| {
+            for (index, swarm) in swarms.iter().enumerate() {
+                if index > 0 {
+                    ui.separator();
+                }
+                ui.horizontal(|ui| {
+                    ui.strong(&swarm.file_name);
+                    ui.weak(p2p_core::units::format_size(swarm.file_size));
+                    if !swarm.is_available() {
+                        ui.colored_label(ui.visuals().warn_fg_color, "Some pieces are nowhere");
+                    }
+                });
+                piece_strip(ui, swarm);
+                member_row(ui, "This device", &swarm.local);
+                for member in &swarm.members {
+                    let ip = member.addr.ip().to_string();
+                    let name = peers
+                        .get(&ip)
+                        .map_or(member.addr.to_string(), |peer| peer.display_name.clone());
+                    member_row(ui, &name, &member.pieces);
+                }
+            }
+        });
+}
+
+/// One cell per piece: red where no device has it, greener the more do
+fn piece_strip(ui: &mut egui::Ui, swarm: &SwarmAvailability) {
+    let pieces = swarm.piece_count().max(1);
+    let devices = swarm.members.len() + 1;
+    let (rect, response) = ui.allocate_exact_size(
+        egui::vec2(ui.available_width().max(200.0), STRIP_HEIGHT),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    let width = rect.width() / pieces as f32;
+    for index in 0..pieces {
+        let copies = swarm.copies(index);
+        let color = if copies == 0 {
+            egui::Color32::from_rgb(200, 60, 60)
+        } else {
+            let share = copies as f32 / devices as f32;
+            egui::Color32::from_rgb(60, (110.0 + 120.0 * share) as u8, 80)
+        };
+        let left = rect.left() + width * index as f32;
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(left, rect.top()),
+                egui::pos2(left + width.max(1.0), rect.bottom()),
+            ),
+            0.0,
+            color,
+        );
+    }
+    response.on_hover_text("Brighter green: more devices have that piece; red: none");
+}
+
+fn member_row(ui: &mut egui::Ui, name: &str, pieces: &PieceBitmap) {
+    let total = pieces.len().max(1);
+    ui.horizontal(|ui| {
+        ui.label(name);
+        ui.add(
+            egui::ProgressBar::new(pieces.count() as f32 / total as f32)
+                .desired_width(160.0)
+                .text(format!("{}/{} pieces", pieces.count(), pieces.len())),
+        );
+    });
+}
@@ -0,0 +1,63 @@
+//! The exact usage report, see `AppEvent::TelemetryPreview`.
+
+use crate::bridge::CommandBridge;
+use eframe::egui;
+use egui_phosphor::regular::{CHART_BAR, PAPER_PLANE_TILT, X};
+use p2p_core::AppCommand;
+
+/// A report shown to the user
+#[derive(Debug, Clone)]
+pub struct TelemetryPreview {
+    pub payload: String,
+    /// Nothing is sent until the user answers
+    pub awaiting_consent: bool,
+}
+
+/// Show the report as it would be sent. A report waiting for consent is
+/// sent or discarded by the user's answer; closing it discards it.
+pub fn show(ctx: &egui::Context, preview: &mut Option<TelemetryPreview>, cmd_tx: &CommandBridge) {
+    let Some(shown) = preview else {
+        return;
+    };
+    let mut open = true;
+    let mut answer = None;
+    egui::Window::new(format!("{} Usage Statistics", CHART_BAR))
+        .id(egui::Id::new("telemetry_preview"))
+        .collapsible(false)
+        .resizable(true)
+        .open(&mut open)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            ui.label(if shown.awaiting_consent {
+                "This is exactly what would be sent. Nothing leaves this device unless you agree."
+            } else {
+                "This is everything counted so far, exactly as it would be sent."
+            });
+            ui.separator();
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    ui.monospace(&shown.payload);
+                });
+            if shown.awaiting_consent {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button(format!("{} Send", PAPER_PLANE_TILT)).clicked() {
+                        answer = Some(true);
+                    }
+                    if ui.button(format!("{} Don't send", X)).clicked() {
+                        answer = Some(false);
+                    }
+                });
+            }
+        });
+    if !open && shown.awaiting_consent {
+        answer = Some(false);
+    }
+    if let Some(send) = answer {
+        cmd_tx.send(AppCommand::RespondTelemetry { send });
+    }
+    if !open || answer.is_some() {
+        *preview = None;
+    }
+}
@@ -1,6 +1,7 @@
+use crate::bridge::CommandBridge;
 use eframe::egui;
+use egui_phosphor::regular::WARNING;
 use p2p_core::AppCommand;
-use tokio::sync::mpsc;
 
 #[derive(Debug, Clone)]
 pub struct PendingUpload {
@@ -8,6 +9,8 @@ pub struct PendingUpload {
     pub file_name: String,
     pub file_size: u64,
     pub from_ip: String,
+    /// Why the name may not be what it looks like
+    pub warning: Option<String>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -22,7 +25,7 @@ pub enum UploadConfirmState {
 pub fn show_upload_confirm_window(
     ctx: &egui::Context,
     state: &mut UploadConfirmState,
-    cmd_tx: &mpsc::Sender<AppCommand>,
+    cmd_tx: &CommandBridge,
 ) {
     let mut open = true;
     let mut should_close = false;
@@ -42,14 +45,25 @@ pub fn show_upload_confirm_window(
 
                 ui.group(|ui| {
                     ui.label(format!("File: {}", upload.file_name));
-                    ui.label(format!("Size: {}", format_size(upload.file_size)));
+                    ui.label(format!(
+                        "Size: {}",
+                        p2p_core::units::format_size(upload.file_size)
+                    ));
                 });
 
+                if let Some(warning) = &upload.warning {
+                    ui.add_space(5.0);
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!("{} {}", WARNING, warning),
+                    );
+                }
+
                 ui.add_space(15.0);
 
                 ui.horizontal(|ui| {
                     if ui.button("Accept").clicked() {
-                        let _ = cmd_tx.blocking_send(AppCommand::RespondUploadRequest {
+                        cmd_tx.send(AppCommand::RespondUploadRequest {
                             request_id: upload.request_id.clone(),
                             accepted: true,
                         });
@@ -57,7 +71,7 @@ pub fn show_upload_confirm_window(
                     }
 
                     if ui.button("Reject").clicked() {
-                        let _ = cmd_tx.blocking_send(AppCommand::RespondUploadRequest {
+                        cmd_tx.send(AppCommand::RespondUploadRequest {
                             request_id: upload.request_id.clone(),
                             accepted: false,
                         });
@@ -71,19 +85,3 @@ pub fn show_upload_confirm_window(
         }
     }
 }
-
-fn format_size(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-
-    if bytes >= GB {
-        format!("{:.2} GB", bytes as f64 / GB as f64)
-    } else if bytes >= MB {
-        format!("{:.2} MB", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.2} KB", bytes as f64 / KB as f64)
-    } else {
-        format!("{} B", bytes)
-    }
-}
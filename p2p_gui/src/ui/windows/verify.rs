@@ -1,125 +1,232 @@
+use crate::bridge::CommandBridge;
 use eframe::egui;
 use p2p_core::AppCommand;
-use tokio::sync::mpsc;
 
+/// Vertical offset between stacked prompts so each stays visible
+const STACK_OFFSET: f32 = 28.0;
+
+/// Receiver side: a code to read out to the sender
+#[derive(Debug, Clone)]
+pub struct ShownCode {
+    pub session_id: String,
+    pub code: String,
+    pub from_ip: String,
+    pub from_name: String,
+    /// The sender's note for the files that follow
+    pub note: Option<String>,
+}
+
+/// Sender side: waiting for the user to type the receiver's code
+#[derive(Debug, Clone)]
+pub struct CodeInput {
+    pub session_id: String,
+    pub target_ip: String,
+    pub target_name: String,
+    pub code_input: String,
+    pub error_msg: Option<String>,
+    /// A code was submitted and the receiver has not answered yet
+    pub checking: bool,
+}
+
+/// Every open verification prompt, one window per session
 #[derive(Debug, Clone, Default)]
-pub enum VerificationState {
-    #[default]
-    None,
-    /// Shows the code to the receiver
-    ShowingCode {
+pub struct VerificationState {
+    shown: Vec<ShownCode>,
+    inputs: Vec<CodeInput>,
+}
+
+impl VerificationState {
+    pub fn show_code(
+        &mut self,
+        session_id: String,
         code: String,
         from_ip: String,
         from_name: String,
-    },
-    /// Asks the sender to input the code
-    InputtingCode {
-        target_ip: String,
-        code_input: String,
-        error_msg: Option<String>,
-    },
+        note: Option<String>,
+    ) {
+        self.shown.retain(|s| s.session_id != session_id);
+        self.shown.push(ShownCode {
+            session_id,
+            code,
+            from_ip,
+            from_name,
+            note,
+        });
+    }
+
+    pub fn request_code(&mut self, session_id: String, target_ip: String, target_name: String) {
+        self.inputs.retain(|i| i.session_id != session_id);
+        self.inputs.push(CodeInput {
+            session_id,
+            target_ip,
+            target_name,
+            code_input: String::new(),
+            error_msg: None,
+            checking: false,
+        });
+    }
+
+    /// Close the prompts of a finished session; a rejected code leaves the
+    /// entry open with the error so the user can try again
+    pub fn pairing_result(&mut self, session_id: &str, success: bool, message: String) {
+        self.shown.retain(|s| s.session_id != session_id);
+
+        if !success && let Some(input) = self.inputs.iter_mut().find(|i| i.session_id == session_id)
+        {
+            input.error_msg = Some(message);
+            input.code_input.clear();
+            input.checking = false;
+            return;
+        }
+        self.inputs.retain(|i| i.session_id != session_id);
+    }
+
+    /// The session timed out or ran out of attempts; no more codes accepted
+    pub fn cancelled(&mut self, session_id: &str) {
+        self.shown.retain(|s| s.session_id != session_id);
+        self.inputs.retain(|i| i.session_id != session_id);
+    }
 }
 
-/// Render verification windows based on state
+/// Render one window per pending verification prompt
 pub fn show_verification_windows(
     ctx: &egui::Context,
     state: &mut VerificationState,
-    cmd_tx: &mpsc::Sender<AppCommand>,
+    cmd_tx: &CommandBridge,
 ) {
-    let mut open = true;
-    let mut should_close = false;
+    let mut index = 0;
 
-    match state {
-        VerificationState::ShowingCode {
-            code,
-            from_ip,
-            from_name,
-        } => {
-            egui::Window::new("Connection Request")
-                .collapsible(false)
-                .resizable(false)
-                .open(&mut open)
-                .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
-                .show(ctx, |ui| {
-                    ui.label(format!(
-                        "Device '{}' ({}) wants to send you a file.",
-                        from_name, from_ip
-                    ));
-                    ui.add_space(10.0);
-                    ui.label("Your verification code is:");
-                    ui.add_space(5.0);
-                    ui.heading(code.as_str());
-                    ui.add_space(15.0);
-                    if ui.button("Close").clicked() {
-                        should_close = true;
-                    }
-                });
-        }
-        VerificationState::InputtingCode {
-            target_ip,
-            code_input,
-            error_msg,
-        } => {
-            let mut submit_clicked = false;
-            let mut submitted_code = String::new();
-
-            egui::Window::new("Enter Verification Code")
-                .collapsible(false)
-                .resizable(false)
-                .open(&mut open)
-                .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
-                .show(ctx, |ui| {
-                    ui.label(format!(
-                        "Enter the code displayed on the target device ({})",
-                        target_ip
-                    ));
-                    ui.add_space(10.0);
-
-                    let response = ui.text_edit_singleline(code_input);
-
-                    if let Some(err) = error_msg {
-                        ui.colored_label(egui::Color32::RED, err.as_str());
-                    }
-
-                    ui.add_space(10.0);
-                    if ui.button("Submit Code").clicked()
-                        || (response.lost_focus() && ctx.input(|i| i.key_pressed(egui::Key::Enter)))
-                    {
-                        submit_clicked = true;
-                        submitted_code = code_input.clone();
-                    }
-                });
+    state.shown.retain(|shown| {
+        let mut open = true;
+        let mut should_close = false;
+
+        egui::Window::new("Connection Request")
+            .id(egui::Id::new(("verification_code", &shown.session_id)))
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .anchor(egui::Align2::CENTER_CENTER, stack_offset(index))
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Device '{}' ({}) wants to send you a file.",
+                    shown.from_name, shown.from_ip
+                ));
+                if let Some(note) = &shown.note {
+                    ui.label(format!("Note: {}", note));
+                }
+                ui.add_space(10.0);
+                ui.label("Your verification code is:");
+                ui.add_space(5.0);
+                ui.heading(shown.code.as_str());
+                ui.add_space(15.0);
+                if ui.button("Close").clicked() {
+                    should_close = true;
+                }
+            });
 
-            if submit_clicked {
-                if submitted_code.len() == 4 {
-                    let cmd_tx = cmd_tx.clone();
-                    let target_ip_clone = target_ip.clone();
-                    let code_clone = submitted_code;
+        index += 1;
+        open && !should_close
+    });
 
-                    let _ = cmd_tx.blocking_send(AppCommand::SubmitVerificationCode {
-                        target_ip: target_ip_clone,
-                        code: code_clone,
+    state.inputs.retain_mut(|input| {
+        let mut open = true;
+        let mut submit_clicked = false;
+
+        egui::Window::new(format!("Enter Verification Code: {}", input.target_name))
+            .id(egui::Id::new(("verification_input", &input.session_id)))
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .anchor(egui::Align2::CENTER_CENTER, stack_offset(index))
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Enter the code displayed on the target device ({})",
+                    input.target_ip
+                ));
+                ui.add_space(10.0);
+
+                let response = ui.add_enabled(
+                    !input.checking,
+                    egui::TextEdit::singleline(&mut input.code_input),
+                );
+
+                if let Some(err) = &input.error_msg {
+                    ui.colored_label(egui::Color32::RED, err.as_str());
+                }
+
+                ui.add_space(10.0);
+                if input.checking {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Checking code...");
                     });
-                    should_close = true;
-                } else {
-                    // Update error message in state?
-                    // Need to mutate state which is borrowed.
-                    // Actually 'state' is borrowed by 'match'.
-                    // We can't mutate 'state' here directly if it's borrowed.
-                    // But 'match state' borrows it.
-                    // Close on valid submit; otherwise update state.
-                    // But we can't easily update state while matching on it.
-                    // Refactor: clone necessary data first?
+                } else if ui.button("Submit Code").clicked()
+                    || (response.lost_focus() && ctx.input(|i| i.key_pressed(egui::Key::Enter)))
+                {
+                    submit_clicked = true;
                 }
+            });
+
+        index += 1;
+
+        if submit_clicked {
+            let code = input.code_input.trim();
+            if code.len() == 4 && code.chars().all(|c| c.is_ascii_digit()) {
+                cmd_tx.send(AppCommand::SubmitVerificationCode {
+                    session_id: input.session_id.clone(),
+                    code: code.to_string(),
+                });
+                input.checking = true;
+                input.error_msg = None;
+            } else {
+                input.error_msg = Some("The code has 4 digits".to_string());
             }
         }
-        VerificationState::None => {
-            return;
+
+        if !open {
+            // Stop the sender waiting for a code that will never come
+            cmd_tx.send(AppCommand::CancelVerification {
+                session_id: input.session_id.clone(),
+            });
         }
-    }
+        open
+    });
+}
+
+fn stack_offset(index: usize) -> egui::Vec2 {
+    egui::vec2(0.0, index as f32 * STACK_OFFSET)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompts_stack_per_session() {
+        let mut state = VerificationState::default();
+        state.request_code("a".into(), "10.0.0.2".into(), "Laptop".into());
+        state.request_code("b".into(), "10.0.0.2".into(), "Laptop".into());
+        state.show_code(
+            "r".into(),
+            "1234".into(),
+            "10.0.0.3".into(),
+            "Phone".into(),
+            None,
+        );
+        assert_eq!(state.inputs.len(), 2);
+
+        // A failure keeps only that prompt open, with the error
+        state.pairing_result("a", false, "Invalid code".into());
+        assert_eq!(state.inputs.len(), 2);
+        assert_eq!(state.inputs[0].error_msg.as_deref(), Some("Invalid code"));
+        assert!(state.inputs[1].error_msg.is_none());
 
-    // Logic to update state after match
-    if !open || should_close {
-        *state = VerificationState::None;
+        state.pairing_result("b", true, "ok".into());
+        state.cancelled("a");
+        assert!(state.inputs.is_empty());
+        state.request_code("c".into(), "10.0.0.2".into(), "Laptop".into());
+        state.pairing_result("r", true, "ok".into());
+        assert_eq!(state.inputs.len(), 1);
+        assert!(state.shown.is_empty());
     }
 }
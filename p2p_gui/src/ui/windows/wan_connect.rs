@@ -1,43 +1,224 @@
+use crate::bridge::{CommandBridge, DialogResult, FileDialogTask};
+use crate::qr_scan::{self, ScannedCode};
 use eframe::egui;
-use egui_phosphor::regular::{COPY, FILE, FOLDER_OPEN, GLOBE, PAPER_PLANE_RIGHT, PLUGS_CONNECTED};
-use p2p_core::{AppCommand, AppEvent};
+use egui_phosphor::regular::{
+    ARROWS_CLOCKWISE, CLIPBOARD_TEXT, COPY, DEVICES, FILE, FOLDER_OPEN, GLOBE, IMAGE, LINK,
+    PAPER_PLANE_RIGHT, PLUGS_CONNECTED,
+};
+use p2p_core::config::WanStrategy;
+use p2p_core::link::AppLink;
+use p2p_core::rendezvous::{RegisteredDevice, RendezvousSettings};
+use p2p_core::{AppCommand, AppEvent, EventCategory, LogLevel};
+use p2p_wan::PasteSource;
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
+/// How often the clipboard is checked while the window is open
+const CLIPBOARD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Fills the connect field from endpoint IDs found in the clipboard
+#[derive(Default)]
+pub struct PasteDetector {
+    /// "Paste detected" hint under the connect field
+    hint: Option<String>,
+    /// Value we put into the field, so user input is never overwritten
+    auto_filled: Option<String>,
+    last_clipboard: Option<String>,
+    checked_at: Option<Instant>,
+}
+
+impl PasteDetector {
+    /// Read the clipboard at most once per [`CLIPBOARD_POLL_INTERVAL`]
+    fn poll_clipboard(&mut self, field: &mut String, my_endpoint_id: &str) {
+        if self
+            .checked_at
+            .is_some_and(|at| at.elapsed() < CLIPBOARD_POLL_INTERVAL)
+        {
+            return;
+        }
+        self.checked_at = Some(Instant::now());
+
+        let Ok(text) = arboard::Clipboard::new().and_then(|mut c| c.get_text()) else {
+            return;
+        };
+        if self.last_clipboard.as_deref() == Some(text.as_str()) {
+            return;
+        }
+        self.offer(&text, field, my_endpoint_id);
+        self.last_clipboard = Some(text);
+    }
+
+    /// Put the endpoint ID found in `text` into `field`, unless the field
+    /// holds something the user typed. Returns whether the field changed.
+    fn offer(&mut self, text: &str, field: &mut String, my_endpoint_id: &str) -> bool {
+        let Some(detected) = p2p_wan::detect_endpoint_id(text) else {
+            return false;
+        };
+        let id = detected.endpoint_id.to_string();
+        let current = field.trim();
+        let untouched = current.is_empty() || self.auto_filled.as_deref() == Some(current);
+        if id == my_endpoint_id || id == current || !untouched {
+            return false;
+        }
+
+        *field = id.clone();
+        self.auto_filled = Some(id);
+        self.hint = Some(match detected.source {
+            PasteSource::EndpointId => "Paste detected: Endpoint ID from clipboard".to_string(),
+            PasteSource::Embedded => "Paste detected: Endpoint ID taken from a link".to_string(),
+        });
+        true
+    }
+
+    /// Put an endpoint ID read from a QR image into `field`
+    fn scanned(&mut self, id: String, field: &mut String) {
+        *field = id.clone();
+        self.auto_filled = Some(id);
+        self.hint = Some("Endpoint ID read from a QR image".to_string());
+    }
+
+    /// The user edited the field: keep an ID pasted inside a link, and stop
+    /// treating the field as auto-filled
+    fn field_edited(&mut self, field: &mut String) {
+        self.auto_filled = None;
+        self.hint = None;
+        if let Some(detected) = p2p_wan::detect_endpoint_id(field)
+            && detected.source == PasteSource::Embedded
+        {
+            *field = detected.endpoint_id.to_string();
+            self.hint = Some("Paste detected: Endpoint ID taken from a link".to_string());
+        }
+    }
+}
+
 pub struct WanConnectState {
     pub target_endpoint_id: String,
     pub my_endpoint_id: String,
     pub connection_status: String,
     pub active_connection: Option<iroh::endpoint::Connection>,
     pub selected_files: Vec<PathBuf>,
+    /// File dialog currently open for this window
+    pub file_dialog: Option<FileDialogTask>,
+    /// Image dialog for scanning a peer's QR code
+    pub scan_dialog: Option<FileDialogTask>,
     pub connection_type: String, // "Checking...", "Direct", "Relay", etc.
+    pub paste: PasteDetector,
+    /// How the next files are sent
+    pub strategy: WanStrategy,
+    /// Offer the next files without their names until the peer accepts
+    pub hide_names: bool,
+    /// Other devices in our rendezvous room, from the last refresh
+    pub my_devices: Vec<RegisteredDevice>,
+    /// Rendezvous fields as edited, saved with their button
+    pub rendezvous_url: String,
+    pub room_secret: String,
 }
 
-impl Default for WanConnectState {
-    fn default() -> Self {
-        // Load endpoint ID from Iroh identity
-        let my_endpoint_id = p2p_core::identity::get_iroh_endpoint_id();
-
+impl WanConnectState {
+    /// State of the device with `my_endpoint_id`, set up from `app_config`
+    pub fn for_endpoint(my_endpoint_id: String, app_config: p2p_core::config::AppConfig) -> Self {
         Self {
             target_endpoint_id: String::new(),
             my_endpoint_id,
             connection_status: String::new(),
             active_connection: None,
             selected_files: Vec::new(),
+            file_dialog: None,
+            scan_dialog: None,
             connection_type: String::new(),
+            paste: PasteDetector::default(),
+            strategy: app_config.wan_strategy,
+            hide_names: app_config.wan_hide_names,
+            my_devices: Vec::new(),
+            rendezvous_url: app_config.rendezvous.url.unwrap_or_default(),
+            room_secret: app_config.rendezvous.room_secret,
+        }
+    }
+
+    /// Keep the listed devices other than this one
+    pub fn set_my_devices(&mut self, devices: Vec<RegisteredDevice>) {
+        self.my_devices = devices
+            .into_iter()
+            .filter(|device| device.endpoint_id != self.my_endpoint_id)
+            .collect();
+    }
+
+    /// Fill the connect field with an endpoint ID scanned elsewhere
+    pub fn fill_scanned(&mut self, endpoint_id: String) {
+        self.paste
+            .scanned(endpoint_id, &mut self.target_endpoint_id);
+    }
+
+    /// Handle the image picked for scanning a QR code
+    fn scan(&mut self, path: &std::path::Path) {
+        match qr_scan::scan_file(path) {
+            Ok(ScannedCode::EndpointId(id)) => self.fill_scanned(id),
+            Ok(_) => {
+                self.connection_status = "The QR code holds no endpoint ID".to_string();
+            }
+            Err(e) => self.connection_status = format!("Could not read QR code: {}", e),
+        }
+    }
+
+    fn rendezvous_settings(&self) -> RendezvousSettings {
+        let url = self.rendezvous_url.trim();
+        RendezvousSettings {
+            url: (!url.is_empty()).then(|| url.to_string()),
+            room_secret: self.room_secret.clone(),
         }
     }
 }
 
+impl Default for WanConnectState {
+    fn default() -> Self {
+        // Load endpoint ID from Iroh identity
+        Self::for_endpoint(
+            p2p_core::identity::get_iroh_endpoint_id(),
+            p2p_core::config::AppConfig::load(),
+        )
+    }
+}
+
 pub fn show(
     ctx: &egui::Context,
     open: &mut bool,
     state: &mut WanConnectState,
-    cmd_tx: &mpsc::Sender<AppCommand>,
+    cmd_tx: &CommandBridge,
     event_tx: &mpsc::Sender<AppEvent>,
     wan_service: &std::sync::Arc<p2p_wan::ConnectionListener>,
     wan_rt: &tokio::runtime::Handle,
 ) {
+    if let Some(dialog) = &state.file_dialog {
+        match dialog.poll() {
+            DialogResult::Pending => {}
+            DialogResult::Picked(files) => {
+                state.selected_files = files;
+                state.file_dialog = None;
+            }
+            DialogResult::Cancelled => state.file_dialog = None,
+        }
+    }
+    if let Some(dialog) = &state.scan_dialog {
+        match dialog.poll() {
+            DialogResult::Pending => {}
+            DialogResult::Picked(paths) => {
+                state.scan_dialog = None;
+                if let Some(path) = paths.first() {
+                    state.scan(path);
+                }
+            }
+            DialogResult::Cancelled => state.scan_dialog = None,
+        }
+    }
+
+    if *open && state.active_connection.is_none() {
+        state
+            .paste
+            .poll_clipboard(&mut state.target_endpoint_id, &state.my_endpoint_id);
+        ctx.request_repaint_after(CLIPBOARD_POLL_INTERVAL);
+    }
+
     egui::Window::new(format!("{} WAN", GLOBE))
         .open(open)
         .resizable(true)
@@ -67,6 +248,17 @@ pub fn show(
                     {
                         ctx.copy_text(state.my_endpoint_id.clone());
                     }
+                    if !state.my_endpoint_id.is_empty()
+                        && ui
+                            .button(LINK.to_string())
+                            .on_hover_text("Copy a p2p:// link that opens this window elsewhere")
+                            .clicked()
+                    {
+                        let link = AppLink::Connect {
+                            endpoint_id: state.my_endpoint_id.clone(),
+                        };
+                        ctx.copy_text(link.to_uri());
+                    }
                 });
 
                 ui.add_space(12.0);
@@ -78,11 +270,25 @@ pub fn show(
                 ui.label("Enter the remote Endpoint ID:");
 
                 ui.horizontal(|ui| {
-                    ui.add(
+                    let response = ui.add(
                         egui::TextEdit::singleline(&mut state.target_endpoint_id)
                             .desired_width(250.0)
                             .hint_text("Paste Endpoint ID here..."),
                     );
+                    if response.changed() {
+                        state.paste.field_edited(&mut state.target_endpoint_id);
+                    }
+
+                    if ui
+                        .add_enabled(
+                            state.scan_dialog.is_none(),
+                            egui::Button::new(IMAGE.to_string()),
+                        )
+                        .on_hover_text("Import QR from image")
+                        .clicked()
+                    {
+                        state.scan_dialog = Some(FileDialogTask::pick_image(ctx));
+                    }
 
                     let can_connect = !state.target_endpoint_id.trim().is_empty();
                     if ui
@@ -94,12 +300,8 @@ pub fn show(
                         state.connection_status = format!("Connecting to {}...", target_id_str);
 
                         // Send Log command to backend (for consistent logging)
-                        let cmd_tx_clone = cmd_tx.clone();
-                        let target_id_for_cmd = target_id_str.clone();
-                        std::thread::spawn(move || {
-                            let _ = cmd_tx_clone.blocking_send(AppCommand::WanConnect {
-                                target_endpoint_id: target_id_for_cmd,
-                            });
+                        cmd_tx.send(AppCommand::WanConnect {
+                            target_endpoint_id: target_id_str.clone(),
                         });
 
                         // Perform connection using shared WanService
@@ -123,10 +325,11 @@ pub fn show(
                             };
 
                             let _ = event_tx
-                                .send(AppEvent::Status(format!(
-                                    "Connecting to {}...",
-                                    endpoint_id
-                                )))
+                                .send(AppEvent::log(
+                                    LogLevel::Info,
+                                    EventCategory::Wan,
+                                    format!("Connecting to {}...", endpoint_id),
+                                ))
                                 .await;
 
                             // 2. Connect
@@ -137,7 +340,10 @@ pub fn show(
                                 }
                                 Err(e) => {
                                     let _ = event_tx
-                                        .send(AppEvent::Error(format!("Connection failed: {}", e)))
+                                        .send(AppEvent::Error(format!(
+                                            "Connection failed: {}",
+                                            p2p_wan::close::explain(&e)
+                                        )))
                                         .await;
                                 }
                             }
@@ -145,6 +351,18 @@ pub fn show(
                     }
                 });
 
+                if let Some(hint) = &state.paste.hint {
+                    ui.label(
+                        egui::RichText::new(format!("{} {}", CLIPBOARD_TEXT, hint))
+                            .small()
+                            .weak(),
+                    );
+                }
+
+                if state.active_connection.is_none() {
+                    show_my_devices(ui, state, cmd_tx);
+                }
+
                 // Connection status
                 if !state.connection_status.is_empty() {
                     ui.add_space(8.0);
@@ -180,10 +398,14 @@ pub fn show(
                     ui.add_space(8.0);
 
                     ui.horizontal(|ui| {
-                        if ui.button(format!("{} Select Files", FOLDER_OPEN)).clicked()
-                            && let Some(files) = rfd::FileDialog::new().pick_files()
+                        if ui
+                            .add_enabled(
+                                state.file_dialog.is_none(),
+                                egui::Button::new(format!("{} Select Files", FOLDER_OPEN)),
+                            )
+                            .clicked()
                         {
-                            state.selected_files = files;
+                            state.file_dialog = Some(FileDialogTask::pick_files(ctx));
                         }
 
                         if !state.selected_files.is_empty() {
@@ -204,26 +426,80 @@ pub fn show(
                         }
 
                         ui.add_space(5.0);
+                        ui.horizontal(|ui| {
+                            ui.label("Mode:");
+                            egui::ComboBox::from_id_salt("wan_strategy")
+                                .selected_text(state.strategy.label())
+                                .show_ui(ui, |ui| {
+                                    for strategy in WanStrategy::ALL {
+                                        ui.selectable_value(
+                                            &mut state.strategy,
+                                            strategy,
+                                            strategy.label(),
+                                        );
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "Content-addressed mode only sends the chunks the peer lacks",
+                                );
+                        });
+                        ui.checkbox(&mut state.hide_names, "Hide names until accepted")
+                            .on_hover_text(
+                                "The peer sees only sizes and meaningless labels until it accepts",
+                            );
                         if ui
                             .button(format!("{} Send Files", PAPER_PLANE_RIGHT))
                             .clicked()
                         {
+                            let strategy = state.strategy;
+                            let hide_names = state.hide_names;
                             let conn_clone = conn.clone();
                             let files = state.selected_files.clone();
                             let event_tx = event_tx.clone();
+                            let security = p2p_wan::listener::security_info(
+                                wan_service.endpoint(),
+                                conn.remote_id(),
+                            );
 
                             state.selected_files.clear();
 
                             wan_rt.spawn(async move {
-                                if let Err(e) = p2p_wan::sender::send_files(
+                                if hide_names {
+                                    let approval =
+                                        p2p_wan::sealed::request_approval(&conn_clone, &files)
+                                            .await;
+                                    let declined = match approval {
+                                        Ok(true) => None,
+                                        Ok(false) => Some(AppEvent::log(
+                                            LogLevel::Warning,
+                                            EventCategory::Wan,
+                                            "The peer declined the files",
+                                        )),
+                                        Err(e) => Some(AppEvent::Error(format!(
+                                            "WAN send error: {}",
+                                            p2p_wan::close::explain(&e)
+                                        ))),
+                                    };
+                                    if let Some(event) = declined {
+                                        let _ = event_tx.send(event).await;
+                                        return;
+                                    }
+                                }
+                                if let Err(e) = p2p_wan::sender::send_with_strategy(
                                     &conn_clone,
                                     files,
+                                    strategy,
+                                    security,
                                     event_tx.clone(),
                                 )
                                 .await
                                 {
                                     let _ = event_tx
-                                        .send(AppEvent::Error(format!("WAN send error: {}", e)))
+                                        .send(AppEvent::Error(format!(
+                                            "WAN send error: {}",
+                                            p2p_wan::close::explain(&e)
+                                        )))
                                         .await;
                                 }
                             });
@@ -233,3 +509,103 @@ pub fn show(
             });
         });
 }
+
+/// Devices found through the rendezvous server; a click fills the
+/// connect field
+fn show_my_devices(ui: &mut egui::Ui, state: &mut WanConnectState, cmd_tx: &CommandBridge) {
+    ui.add_space(8.0);
+    egui::CollapsingHeader::new(format!("{} My Devices", DEVICES))
+        .id_salt("wan_my_devices")
+        .show(ui, |ui| {
+            let enabled = state.rendezvous_settings().is_enabled();
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(
+                        enabled,
+                        egui::Button::new(format!("{} Refresh", ARROWS_CLOCKWISE)),
+                    )
+                    .clicked()
+                {
+                    cmd_tx.send(AppCommand::ListMyDevices);
+                }
+                if state.my_devices.is_empty() {
+                    ui.weak("No other devices listed");
+                }
+            });
+            for device in &state.my_devices {
+                let short_id: String = device.endpoint_id.chars().take(12).collect();
+                if ui
+                    .selectable_label(
+                        state.target_endpoint_id.trim() == device.endpoint_id,
+                        format!("{} ({}...)", device.name, short_id),
+                    )
+                    .clicked()
+                {
+                    state.target_endpoint_id = device.endpoint_id.clone();
+                    state.paste.hint = None;
+                }
+            }
+
+            ui.add_space(6.0);
+            egui::Grid::new("rendezvous_settings").show(ui, |ui| {
+                ui.label("Server:");
+                ui.add(
+                    egui::TextEdit::singleline(&mut state.rendezvous_url)
+                        .hint_text("https://rendezvous.example.com"),
+                );
+                ui.end_row();
+                ui.label("Room secret:");
+                ui.add(egui::TextEdit::singleline(&mut state.room_secret).password(true));
+                ui.end_row();
+            });
+            ui.small("Use the same server and secret on each of your devices.");
+            if ui.button("Save").clicked() {
+                state.my_devices.clear();
+                cmd_tx.send(AppCommand::SetRendezvous(state.rendezvous_settings()));
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(seed: u8) -> String {
+        iroh::SecretKey::from_bytes(&[seed; 32])
+            .public()
+            .to_string()
+    }
+
+    #[test]
+    fn test_paste_fills_only_untouched_field() {
+        let mut paste = PasteDetector::default();
+        let (mine, first, second) = (id(1), id(2), id(3));
+        let mut field = String::new();
+
+        assert!(!paste.offer(&mine, &mut field, &mine));
+        assert!(!paste.offer("hello", &mut field, &mine));
+
+        assert!(paste.offer(&format!("https://x.test/c/{}", first), &mut field, &mine));
+        assert_eq!(field, first);
+        assert!(paste.hint.as_deref().unwrap().contains("link"));
+
+        // Still our own value, so a newer copy replaces it
+        assert!(paste.offer(&second, &mut field, &mine));
+        assert_eq!(field, second);
+
+        // Typed by the user: left alone
+        field = "abc".to_string();
+        paste.field_edited(&mut field);
+        assert!(!paste.offer(&first, &mut field, &mine));
+        assert_eq!(field, "abc");
+    }
+
+    #[test]
+    fn test_link_typed_into_field_is_reduced_to_id() {
+        let mut paste = PasteDetector::default();
+        let mut field = format!("iroh://{}", id(4));
+        paste.field_edited(&mut field);
+        assert_eq!(field, id(4));
+        assert!(paste.hint.is_some());
+    }
+}
@@ -0,0 +1,69 @@
+use eframe::egui;
+use std::collections::VecDeque;
+
+/// Files offered over WAN with hidden names
+#[derive(Debug, Clone)]
+pub struct PendingWanOffer {
+    pub offer_id: String,
+    pub peer: String,
+    pub labels: Vec<String>,
+    pub total_size: u64,
+}
+
+/// Offers waiting for an answer, oldest first
+#[derive(Debug, Default)]
+pub struct WanOfferState {
+    pub pending: VecDeque<PendingWanOffer>,
+}
+
+/// Ask about the oldest pending offer; the answer goes straight to the WAN
+/// listener, which reveals the names only once the files arrive
+pub fn show(
+    ctx: &egui::Context,
+    state: &mut WanOfferState,
+    wan_service: &p2p_wan::ConnectionListener,
+) {
+    let Some(offer) = state.pending.front() else {
+        return;
+    };
+    let mut answer = None;
+    egui::Window::new("Incoming WAN Files")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(ctx, |ui| {
+            let short_peer: String = offer.peer.chars().take(12).collect();
+            ui.label(format!(
+                "{}... wants to send you {} files ({}).",
+                short_peer,
+                offer.labels.len(),
+                p2p_core::units::format_size(offer.total_size)
+            ));
+            ui.label("The sender keeps their names hidden until you accept.");
+            ui.add_space(6.0);
+            ui.group(|ui| {
+                for label in offer.labels.iter().take(5) {
+                    ui.monospace(label);
+                }
+                if offer.labels.len() > 5 {
+                    ui.label(format!("...and {} more", offer.labels.len() - 5));
+                }
+            });
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                if ui.button("Accept").clicked() {
+                    answer = Some(true);
+                }
+                if ui.button("Decline").clicked() {
+                    answer = Some(false);
+                }
+            });
+        });
+
+    if let Some(accepted) = answer
+        && let Some(offer) = state.pending.pop_front()
+        && !wan_service.respond_offer(&offer.offer_id, accepted)
+    {
+        tracing::info!("WAN offer {} had already expired", offer.offer_id);
+    }
+}
@@ -0,0 +1,258 @@
+//! Optional check for a newer release.
+//!
+//! Off until turned on in the toolbar. When on, the releases URL (GitHub's
+//! "latest release" API by default) is fetched once per start and at most
+//! once a day, through the profile's proxy. The request carries nothing but
+//! a generic user agent: no version, device name or identifier.
+
+use anyhow::{Result, anyhow};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+
+pub const DEFAULT_RELEASES_URL: &str =
+    "https://api.github.com/repos/long113112113/p2p_transferRust/releases/latest";
+
+/// Minimum time between two checks, across restarts
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// GitHub refuses requests without one
+const USER_AGENT: &str = "p2p_transfer";
+
+/// Saved with the GUI state
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateSettings {
+    /// Opt-in; nothing is fetched while false
+    pub enabled: bool,
+    /// GitHub "latest release" API URL or anything answering in its format
+    pub releases_url: String,
+    /// Unix seconds of the last successful check
+    pub last_check: Option<u64>,
+    /// Version whose banner was closed; not shown again
+    pub dismissed: Option<String>,
+}
+
+impl Default for UpdateSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            releases_url: DEFAULT_RELEASES_URL.to_string(),
+            last_check: None,
+            dismissed: None,
+        }
+    }
+}
+
+impl UpdateSettings {
+    fn due(&self, now: u64) -> bool {
+        self.enabled
+            && self
+                .last_check
+                .is_none_or(|last| now.saturating_sub(last) >= CHECK_INTERVAL.as_secs())
+    }
+}
+
+/// A published release
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    pub version: Version,
+    pub name: String,
+    /// Markdown release notes
+    pub notes: String,
+    /// Release page to open in the browser
+    pub url: String,
+}
+
+/// The subset of GitHub's release object that is used
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    html_url: String,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+}
+
+/// Parse a GitHub release; `None` for drafts and pre-releases
+pub fn parse_release(json: &str) -> Result<Option<Release>> {
+    let release: GithubRelease = serde_json::from_str(json)?;
+    if release.draft || release.prerelease {
+        return Ok(None);
+    }
+    let tag = release.tag_name.trim();
+    let version = Version::parse(tag.strip_prefix(['v', 'V']).unwrap_or(tag))
+        .map_err(|e| anyhow!("Release tag {:?} is not a version: {}", tag, e))?;
+    Ok(Some(Release {
+        name: release
+            .name
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| tag.to_string()),
+        notes: release.body.unwrap_or_default(),
+        url: release.html_url,
+        version,
+    }))
+}
+
+/// `release` if it is newer than `current`
+pub fn newer_than(current: &Version, release: Option<Release>) -> Option<Release> {
+    release.filter(|release| release.version > *current)
+}
+
+pub fn current_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("package version is semver")
+}
+
+async fn fetch_latest(url: &str) -> Result<Option<Release>> {
+    let url = reqwest::Url::parse(url).map_err(|e| anyhow!("Invalid releases URL: {}", e))?;
+    if url.scheme() != "https" {
+        return Err(anyhow!("Releases URL must use https"));
+    }
+    let proxy = p2p_core::config::AppConfig::load().proxy;
+    let mut builder = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(USER_AGENT);
+    if let Some(proxy_url) = url.host_str().and_then(|host| proxy.url_for(host)) {
+        match reqwest::Proxy::all(proxy_url) {
+            Ok(proxy) => builder = builder.proxy(proxy),
+            Err(e) => tracing::warn!("Releases URL reached directly: {}", e),
+        }
+    }
+    let body = builder
+        .build()?
+        .get(url)
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    parse_release(&body)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Runs the check in the background and holds what it found
+#[derive(Default)]
+pub struct UpdateChecker {
+    pending: Option<oneshot::Receiver<Result<Option<Release>>>>,
+    checked_this_run: bool,
+    pub available: Option<Release>,
+    pub show_notes: bool,
+}
+
+impl UpdateChecker {
+    /// Start a check when one is due, collect a finished one. Call every frame.
+    pub fn poll(
+        &mut self,
+        settings: &mut UpdateSettings,
+        runtime: &tokio::runtime::Handle,
+        ctx: &eframe::egui::Context,
+    ) {
+        if let Some(rx) = self.pending.as_mut() {
+            match rx.try_recv() {
+                Ok(Ok(release)) => {
+                    settings.last_check = Some(now_secs());
+                    self.available = newer_than(&current_version(), release);
+                    self.pending = None;
+                }
+                Ok(Err(e)) => {
+                    tracing::debug!("Update check failed: {:#}", e);
+                    self.pending = None;
+                }
+                Err(oneshot::error::TryRecvError::Empty) => {}
+                Err(oneshot::error::TryRecvError::Closed) => self.pending = None,
+            }
+            return;
+        }
+        if self.checked_this_run || !settings.due(now_secs()) {
+            return;
+        }
+        self.checked_this_run = true;
+        let (tx, rx) = oneshot::channel();
+        let url = settings.releases_url.clone();
+        let ctx = ctx.clone();
+        runtime.spawn(async move {
+            let _ = tx.send(fetch_latest(&url).await);
+            ctx.request_repaint();
+        });
+        self.pending = Some(rx);
+    }
+
+    /// Release to announce, unless its banner was closed
+    pub fn banner(&self, settings: &UpdateSettings) -> Option<&Release> {
+        self.available.as_ref().filter(|release| {
+            settings.enabled && settings.dismissed.as_deref() != Some(&release.version.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn github_json(tag: &str, prerelease: bool) -> String {
+        serde_json::json!({
+            "tag_name": tag,
+            "name": "",
+            "body": "- Faster resumes",
+            "html_url": "https://github.com/long113112113/p2p_transferRust/releases/tag/v0.2.0",
+            "draft": false,
+            "prerelease": prerelease,
+            "assets": [],
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_parse_release() {
+        let release = parse_release(&github_json("v0.2.0", false))
+            .unwrap()
+            .unwrap();
+        assert_eq!(release.version, Version::new(0, 2, 0));
+        assert_eq!(release.name, "v0.2.0");
+        assert_eq!(release.notes, "- Faster resumes");
+        assert!(release.url.ends_with("/v0.2.0"));
+
+        assert_eq!(
+            parse_release(&github_json("v0.3.0-rc.1", true)).unwrap(),
+            None
+        );
+        assert!(parse_release(&github_json("nightly", false)).is_err());
+        assert!(parse_release("{}").is_err());
+    }
+
+    #[test]
+    fn test_only_newer_versions_are_offered() {
+        let current = Version::new(0, 1, 0);
+        let release = |tag| parse_release(&github_json(tag, false)).unwrap();
+        assert!(newer_than(&current, release("0.2.0")).is_some());
+        assert!(newer_than(&current, release("v0.1.1")).is_some());
+        assert!(newer_than(&current, release("v0.1.0")).is_none());
+        assert!(newer_than(&current, release("v0.0.9")).is_none());
+        assert!(newer_than(&current, None).is_none());
+    }
+
+    #[test]
+    fn test_check_is_opt_in_and_daily() {
+        let mut settings = UpdateSettings::default();
+        assert!(!settings.due(1_000_000));
+        settings.enabled = true;
+        assert!(settings.due(1_000_000));
+        settings.last_check = Some(1_000_000);
+        assert!(!settings.due(1_000_000 + 60));
+        assert!(settings.due(1_000_000 + CHECK_INTERVAL.as_secs()));
+    }
+}
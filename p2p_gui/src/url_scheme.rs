@@ -0,0 +1,191 @@
+//! `p2p://` links clicked in other apps (see [`p2p_core::link`]).
+//!
+//! `--register-url-scheme` makes this executable the scheme's handler: on
+//! Windows under `HKCU\Software\Classes\p2p`, on Linux with a `.desktop`
+//! file that `xdg-mime` makes the default for `x-scheme-handler/p2p`. The
+//! OS then runs `p2p_gui <link>`, which hands the link to the app that is
+//! already running like files (see [`crate::instance`]). macOS takes URL
+//! schemes from the app bundle's Info.plist, not from a binary.
+
+use anyhow::{Context, Result};
+use p2p_core::link::{AppLink, LINK_SCHEME};
+use std::path::Path;
+use std::sync::mpsc;
+
+/// Make the running executable open `p2p://` links; returns where it went
+pub fn register() -> Result<String> {
+    let exe = std::env::current_exe().context("Could not find the executable")?;
+    platform::register(&exe)
+}
+
+/// Stop opening `p2p://` links
+pub fn unregister() -> Result<()> {
+    platform::unregister()
+}
+
+/// Links to open: the one the app started with, then those handed over by
+/// later starts
+#[derive(Default)]
+pub struct LinkInbox {
+    first: Option<AppLink>,
+    later: Option<mpsc::Receiver<AppLink>>,
+}
+
+impl LinkInbox {
+    pub fn new(first: Option<AppLink>, later: Option<mpsc::Receiver<AppLink>>) -> Self {
+        Self { first, later }
+    }
+
+    /// The next link to open, if any arrived
+    pub fn next(&mut self) -> Option<AppLink> {
+        self.first
+            .take()
+            .or_else(|| self.later.as_ref().and_then(|rx| rx.try_recv().ok()))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::*;
+    use crate::share_target::reg;
+
+    fn key() -> String {
+        format!(r"HKCU\Software\Classes\{}", LINK_SCHEME)
+    }
+
+    pub fn register(exe: &Path) -> Result<String> {
+        let exe = exe.display().to_string();
+        let key = key();
+        reg(&["add", &key, "/ve", "/d", "URL:P2P Transfer link", "/f"])?;
+        reg(&["add", &key, "/v", "URL Protocol", "/d", "", "/f"])?;
+        reg(&[
+            "add",
+            &format!(r"{}\DefaultIcon", key),
+            "/ve",
+            "/d",
+            &exe,
+            "/f",
+        ])?;
+        let command = format!("\"{}\" \"%1\"", exe);
+        reg(&[
+            "add",
+            &format!(r"{}\shell\open\command", key),
+            "/ve",
+            "/d",
+            &command,
+            "/f",
+        ])?;
+        Ok(format!("{}:// links now open P2P Transfer", LINK_SCHEME))
+    }
+
+    pub fn unregister() -> Result<()> {
+        reg(&["delete", &key(), "/f"])
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use super::*;
+    use crate::share_target::{applications_dir, exec_quote};
+    use std::process::{Command, Stdio};
+
+    const DESKTOP_FILE: &str = "p2p-transfer-link.desktop";
+
+    pub fn register(exe: &Path) -> Result<String> {
+        let dir = applications_dir()?;
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(DESKTOP_FILE);
+        std::fs::write(&path, desktop_entry(exe))
+            .with_context(|| format!("Could not write {}", path.display()))?;
+        // Without xdg-utils the desktop may still pick the entry up
+        let mime = format!("x-scheme-handler/{}", LINK_SCHEME);
+        let set_default = Command::new("xdg-mime")
+            .args(["default", DESKTOP_FILE, &mime])
+            .stdout(Stdio::null())
+            .status();
+        match set_default {
+            Ok(status) if status.success() => Ok(format!(
+                "Wrote {} and made it the handler of {}",
+                path.display(),
+                mime
+            )),
+            _ => Ok(format!(
+                "Wrote {}; run `xdg-mime default {} {}` to make it the handler",
+                path.display(),
+                DESKTOP_FILE,
+                mime
+            )),
+        }
+    }
+
+    pub fn unregister() -> Result<()> {
+        match std::fs::remove_file(applications_dir()?.join(DESKTOP_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hidden from the launcher, offered for `p2p://` links only
+    pub(super) fn desktop_entry(exe: &Path) -> String {
+        format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=P2P Transfer\n\
+             Exec={} %u\n\
+             MimeType=x-scheme-handler/{};\n\
+             NoDisplay=true\n\
+             Terminal=false\n",
+            exec_quote(&exe.to_string_lossy()),
+            LINK_SCHEME
+        )
+    }
+}
+
+#[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+mod platform {
+    use super::*;
+    use anyhow::anyhow;
+
+    pub fn register(_exe: &Path) -> Result<String> {
+        Err(anyhow!(
+            "Not supported on this platform; {}:// is declared in the app bundle's Info.plist",
+            LINK_SCHEME
+        ))
+    }
+
+    pub fn unregister() -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect() -> AppLink {
+        AppLink::Connect {
+            endpoint_id: iroh::SecretKey::generate(&mut rand::rng())
+                .public()
+                .to_string(),
+        }
+    }
+
+    #[test]
+    fn test_inbox_opens_the_first_link_then_handed_over_ones() {
+        let (tx, rx) = mpsc::channel();
+        let (first, later) = (connect(), connect());
+        let mut inbox = LinkInbox::new(Some(first.clone()), Some(rx));
+        tx.send(later.clone()).unwrap();
+        assert_eq!(inbox.next(), Some(first));
+        assert_eq!(inbox.next(), Some(later));
+        assert_eq!(inbox.next(), None);
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_desktop_entry_handles_the_scheme() {
+        let entry = platform::desktop_entry(Path::new("/opt/P2P Transfer/p2p_gui"));
+        assert!(entry.contains("Exec=\"/opt/P2P Transfer/p2p_gui\" %u\n"));
+        assert!(entry.contains("MimeType=x-scheme-handler/p2p;\n"));
+    }
+}
@@ -0,0 +1,123 @@
+//! The WAN listener of the active profile.
+//!
+//! It is built from the profile's identity, settings and pairings, so a
+//! profile switch closes it and starts one for the new profile. The backend
+//! reports the health of whichever endpoint was started last.
+
+use anyhow::{Context, Result};
+use p2p_core::AppEvent;
+use p2p_core::config::AppConfig;
+use p2p_core::identity::IdentityManager;
+use p2p_core::pairing::{FilePairingStore, PairingStore};
+use p2p_core::policy::Policy;
+use p2p_wan::ConnectionListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// What the WAN listener of a profile is built from
+pub struct WanProfile {
+    /// Where the profile keeps its identity
+    pub config_dir: PathBuf,
+    pub app_config: AppConfig,
+    pub policy: Policy,
+    pub pairings: Arc<dyn PairingStore>,
+}
+
+impl WanProfile {
+    /// The active profile's
+    pub fn active() -> Self {
+        Self {
+            config_dir: p2p_core::config::get_config_dir().unwrap_or(PathBuf::from(".")),
+            app_config: AppConfig::load(),
+            policy: Policy::load(),
+            pairings: Arc::new(FilePairingStore::default()),
+        }
+    }
+}
+
+/// Build the WAN listener of `profile` and start listening on the current
+/// runtime
+pub async fn start(
+    profile: WanProfile,
+    event_tx: mpsc::Sender<AppEvent>,
+) -> Result<Arc<ConnectionListener>> {
+    let secret_key = IdentityManager::new(profile.config_dir)
+        .load_or_generate()
+        .await
+        .context("Failed to load identity")?;
+    let download_dir = profile
+        .policy
+        .download_dir
+        .clone()
+        .unwrap_or(profile.app_config.download_path);
+    let proxy = p2p_wan::proxy::relay_proxy(&profile.app_config.proxy);
+    let listener = ConnectionListener::new(secret_key, download_dir, event_tx, proxy)
+        .await?
+        .with_app_id(p2p_core::alpn::AppId::from_config(
+            profile.app_config.app_id.as_deref(),
+        ))
+        .with_preserve_metadata(profile.app_config.preserve_metadata)
+        .with_per_peer_folders(profile.app_config.per_peer_folders)
+        .with_linking(
+            profile.policy.pairings(profile.pairings),
+            p2p_core::identity::default_device_name(),
+        );
+    let listener = Arc::new(listener);
+
+    // The simulation stays off the network, and so does a device whose
+    // policy turns the WAN off
+    #[cfg(not(feature = "simulation"))]
+    if !profile.policy.disable_wan_share {
+        let listening = listener.clone();
+        tokio::spawn(async move {
+            if let Err(e) = listening.listen().await {
+                tracing::error!("WAN Listener error: {}", e);
+            }
+        });
+    }
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p2p_core::pairing::MemoryPairingStore;
+    use std::path::Path;
+
+    fn profile(dir: &Path) -> WanProfile {
+        WanProfile {
+            config_dir: dir.to_path_buf(),
+            app_config: AppConfig {
+                download_path: dir.join("downloads"),
+                ..AppConfig::default()
+            },
+            policy: Policy {
+                disable_wan_share: true,
+                ..Policy::default()
+            },
+            pairings: Arc::new(MemoryPairingStore::default()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_each_profile_gets_its_own_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let (event_tx, _event_rx) = mpsc::channel(16);
+
+        let home = start(profile(&dir.path().join("home")), event_tx.clone())
+            .await
+            .unwrap();
+        let work = start(profile(&dir.path().join("work")), event_tx.clone())
+            .await
+            .unwrap();
+        assert_ne!(home.node_id(), work.node_id());
+
+        // Switching back starts the first profile's listener again
+        home.endpoint().close().await;
+        let again = start(profile(&dir.path().join("home")), event_tx)
+            .await
+            .unwrap();
+        assert_eq!(again.node_id(), home.node_id());
+    }
+}
@@ -0,0 +1,224 @@
+//! Self-hosted rendezvous server for p2p-transfer.
+//!
+//! Devices sharing a room secret register their endpoint ID here and list
+//! each other to connect over WAN. Registrations are checked with
+//! [`Registration::verify`] and kept in memory only; a restarted server is
+//! repopulated within [`p2p_core::rendezvous::REFRESH_INTERVAL`].
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::put;
+use axum::{Json, Router};
+use p2p_core::pairing::now_timestamp;
+use p2p_core::rendezvous::{REGISTRATION_TTL_SECS, RegisteredDevice, Registration, is_room_id};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Rooms kept at once; registrations opening more are refused
+pub const MAX_ROOMS: usize = 10_000;
+
+/// Devices listed in one room
+pub const MAX_DEVICES_PER_ROOM: usize = 32;
+
+/// Why a registration was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    RoomFull,
+    TooManyRooms,
+}
+
+/// Live registrations by room, then endpoint ID
+#[derive(Default)]
+pub struct Registry {
+    rooms: Mutex<HashMap<String, HashMap<String, RegisteredDevice>>>,
+}
+
+impl Registry {
+    /// Add or refresh a verified registration at `now`
+    pub fn register(
+        &self,
+        room: &str,
+        registration: Registration,
+        now: u64,
+    ) -> Result<(), Refusal> {
+        let mut rooms = self.rooms.lock().unwrap();
+        prune(&mut rooms, now);
+        if !rooms.contains_key(room) && rooms.len() >= MAX_ROOMS {
+            return Err(Refusal::TooManyRooms);
+        }
+        let devices = rooms.entry(room.to_string()).or_default();
+        if !devices.contains_key(&registration.endpoint_id) && devices.len() >= MAX_DEVICES_PER_ROOM
+        {
+            return Err(Refusal::RoomFull);
+        }
+        devices.insert(
+            registration.endpoint_id.clone(),
+            RegisteredDevice {
+                endpoint_id: registration.endpoint_id,
+                name: registration.name,
+                last_seen: now,
+            },
+        );
+        Ok(())
+    }
+
+    /// Devices registered in `room` within the TTL, by name
+    pub fn devices(&self, room: &str, now: u64) -> Vec<RegisteredDevice> {
+        let mut rooms = self.rooms.lock().unwrap();
+        prune(&mut rooms, now);
+        let mut devices: Vec<_> = rooms
+            .get(room)
+            .map(|devices| devices.values().cloned().collect())
+            .unwrap_or_default();
+        devices.sort_by(|a, b| a.name.cmp(&b.name).then(a.endpoint_id.cmp(&b.endpoint_id)));
+        devices
+    }
+}
+
+fn prune(rooms: &mut HashMap<String, HashMap<String, RegisteredDevice>>, now: u64) {
+    rooms.retain(|_, devices| {
+        devices.retain(|_, device| now.saturating_sub(device.last_seen) <= REGISTRATION_TTL_SECS);
+        !devices.is_empty()
+    });
+}
+
+/// HTTP API: `PUT` registers, `GET` lists `/v1/rooms/{room}/devices`
+pub fn router(registry: Arc<Registry>) -> Router {
+    Router::new()
+        .route(
+            "/v1/rooms/{room}/devices",
+            put(register_device).get(list_devices),
+        )
+        .with_state(registry)
+}
+
+/// Serve a fresh registry on `listener` until the process ends
+pub async fn serve(listener: tokio::net::TcpListener) -> std::io::Result<()> {
+    axum::serve(listener, router(Arc::new(Registry::default()))).await
+}
+
+async fn register_device(
+    State(registry): State<Arc<Registry>>,
+    Path(room): Path<String>,
+    Json(registration): Json<Registration>,
+) -> (StatusCode, String) {
+    if !is_room_id(&room) {
+        return (StatusCode::BAD_REQUEST, "Invalid room".to_string());
+    }
+    let now = now_timestamp();
+    if let Err(e) = registration.verify(&room, now) {
+        return (StatusCode::FORBIDDEN, e.to_string());
+    }
+    let endpoint_id = registration.endpoint_id.clone();
+    match registry.register(&room, registration, now) {
+        Ok(()) => {
+            tracing::debug!("Registered {} in room {}", endpoint_id, &room[..8]);
+            (StatusCode::NO_CONTENT, String::new())
+        }
+        Err(Refusal::RoomFull) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("Room already has {} devices", MAX_DEVICES_PER_ROOM),
+        ),
+        Err(Refusal::TooManyRooms) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Server has no room left".to_string(),
+        ),
+    }
+}
+
+async fn list_devices(
+    State(registry): State<Arc<Registry>>,
+    Path(room): Path<String>,
+) -> Result<Json<Vec<RegisteredDevice>>, StatusCode> {
+    if !is_room_id(&room) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Json(registry.devices(&room, now_timestamp())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use iroh::SecretKey;
+    use p2p_core::rendezvous::room_id;
+    use tower::ServiceExt;
+
+    fn registration(name: &str) -> Registration {
+        let key = SecretKey::generate(&mut rand::rng());
+        Registration::signed(&room_id("room"), &key, name, 1_000)
+    }
+
+    #[test]
+    fn test_registrations_expire_and_are_capped() {
+        let registry = Registry::default();
+        let room = room_id("room");
+        let laptop = registration("Laptop");
+        registry.register(&room, laptop.clone(), 1_000).unwrap();
+        registry
+            .register(&room, registration("Desktop"), 1_200)
+            .unwrap();
+        let names: Vec<_> = registry
+            .devices(&room, 1_300)
+            .into_iter()
+            .map(|device| device.name)
+            .collect();
+        assert_eq!(names, ["Desktop", "Laptop"]);
+        assert!(registry.devices(&room_id("other"), 1_300).is_empty());
+
+        // The laptop stopped refreshing
+        let devices = registry.devices(&room, 1_000 + REGISTRATION_TTL_SECS + 1);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "Desktop");
+
+        for i in 0..MAX_DEVICES_PER_ROOM - 1 {
+            registry
+                .register(&room, registration(&format!("Device {}", i)), 1_300)
+                .unwrap();
+        }
+        assert_eq!(
+            registry.register(&room, registration("One more"), 1_300),
+            Err(Refusal::RoomFull)
+        );
+        // Refreshing a listed device still works
+        let desktop = registry.devices(&room, 1_300)[0].clone();
+        let refresh = Registration {
+            endpoint_id: desktop.endpoint_id,
+            ..laptop
+        };
+        assert!(registry.register(&room, refresh, 1_300).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_rooms_and_signatures() {
+        let app = router(Arc::new(Registry::default()));
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/rooms/not-a-room/devices")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Signed for 1970: too old to accept
+        let stale = registration("Laptop");
+        let body = format!(
+            r#"{{"endpoint_id":"{}","name":"{}","timestamp":{},"signature":"{}"}}"#,
+            stale.endpoint_id, stale.name, stale.timestamp, stale.signature
+        );
+        let response = app
+            .oneshot(
+                Request::put(format!("/v1/rooms/{}/devices", room_id("room")))
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::{Context, Result, anyhow};
+use p2p_rendezvous::serve;
+use std::net::SocketAddr;
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:7780";
+
+/// `--listen ADDR`, or the default address
+fn listen_addr() -> Result<SocketAddr> {
+    let mut args = std::env::args().skip(1);
+    let mut addr = DEFAULT_LISTEN.to_string();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => addr = args.next().context("--listen needs an address")?,
+            "-h" | "--help" => {
+                println!("Usage: p2p_rendezvous [--listen ADDR]  (default {DEFAULT_LISTEN})");
+                std::process::exit(0);
+            }
+            other => return Err(anyhow!("Unknown argument {}", other)),
+        }
+    }
+    addr.parse()
+        .with_context(|| format!("Invalid listen address {}", addr))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    use tracing_subscriber::{EnvFilter, fmt};
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    fmt::Subscriber::builder().with_env_filter(filter).init();
+
+    let addr = listen_addr()?;
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Could not listen on {}", addr))?;
+    tracing::info!("Rendezvous server listening on {}", listener.local_addr()?);
+    serve(listener).await?;
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use iroh::SecretKey;
+use p2p_core::proxy::ProxySettings;
+use p2p_core::rendezvous::{RendezvousClient, RendezvousSettings};
+use p2p_rendezvous::serve;
+
+#[tokio::test]
+async fn test_devices_in_a_room_list_each_other() -> Result<()> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(serve(listener));
+
+    let settings = |secret: &str| RendezvousSettings {
+        url: Some(url.clone()),
+        room_secret: secret.to_string(),
+    };
+    let proxy = ProxySettings::default();
+    let home = RendezvousClient::new(&settings("home"), &proxy)?;
+    let work = RendezvousClient::new(&settings("work"), &proxy)?;
+
+    let laptop = SecretKey::generate(&mut rand::rng());
+    let desktop = SecretKey::generate(&mut rand::rng());
+    home.register(&laptop, "Laptop").await?;
+    home.register(&desktop, "Desktop").await?;
+    work.register(&SecretKey::generate(&mut rand::rng()), "Office")
+        .await?;
+    // Refreshing does not list a device twice
+    home.register(&laptop, "Laptop").await?;
+
+    let devices = home.devices().await?;
+    let listed: Vec<_> = devices
+        .iter()
+        .map(|device| (device.name.as_str(), device.endpoint_id.clone()))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            ("Desktop", desktop.public().to_string()),
+            ("Laptop", laptop.public().to_string()),
+        ]
+    );
+    assert_eq!(work.devices().await?.len(), 1);
+    Ok(())
+}
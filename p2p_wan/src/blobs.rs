@@ -0,0 +1,558 @@
+//! Content-addressed collections, an alternative to the per-file streams.
+//!
+//! The sender splits every file into [`CHUNK_SIZE`] chunks and names each by
+//! its BLAKE3 hash. It announces the whole set in one
+//! [`WanTransferMsg::Collection`], followed by each file's table of chunk
+//! hashes. The receiver works out which chunks it already holds, anywhere:
+//! in the same place of an earlier partial copy, in another file already in
+//! the download folder, or earlier in the same collection. It asks only for
+//! the rest with [`WanTransferMsg::WantChunks`] and a bitmap, and checks every
+//! chunk against its hash as it arrives, so a bad chunk is caught at once
+//! rather than after the whole file. Files are assembled in `<name>.part`
+//! and renamed when complete; an interrupted transfer keeps the chunks it
+//! got and the next attempt does not ask for them again.
+
+use anyhow::{Result, anyhow};
+use iroh::endpoint::{Connection, RecvStream, SendStream};
+use p2p_core::transfer::{
+    ProgressReporter, SecurityInfo, normalize_file_name, validate_transfer_info,
+};
+use p2p_core::{AppEvent, EventCategory, LogLevel};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::protocol::{WanTransferMsg, recv_msg, send_msg};
+
+/// Size of every chunk but a file's last
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// BLAKE3 hash of one chunk
+pub type ChunkHash = [u8; 32];
+
+/// One file of a collection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct BlobEntry {
+    pub name: String,
+    pub size: u64,
+    /// BLAKE3 of the file's chunk hash table; names the content
+    pub hash: String,
+}
+
+/// Number of chunks in a file of `size` bytes
+pub fn chunk_count(size: u64) -> u64 {
+    size.div_ceil(CHUNK_SIZE)
+}
+
+fn chunk_len(size: u64, index: u64) -> u64 {
+    CHUNK_SIZE.min(size - index * CHUNK_SIZE)
+}
+
+/// Content hash of a file with these chunks
+pub fn content_hash(chunks: &[ChunkHash]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for chunk in chunks {
+        hasher.update(chunk);
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Hash every chunk of the first `size` bytes of `path`
+async fn hash_chunks(path: &Path, size: u64) -> Result<Vec<ChunkHash>> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let mut buf = vec![0u8; CHUNK_SIZE as usize];
+        (0..chunk_count(size))
+            .map(|index| {
+                let chunk = &mut buf[..chunk_len(size, index) as usize];
+                file.read_exact(chunk)?;
+                Ok(*blake3::hash(chunk).as_bytes())
+            })
+            .collect()
+    })
+    .await?
+}
+
+/// Hashes of the whole chunks found in a local file, with their offsets;
+/// stops where the file ends
+fn scan_local(path: &Path, size: u64) -> Vec<(ChunkHash, u64)> {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return Vec::new();
+    };
+    let local_len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut buf = vec![0u8; CHUNK_SIZE as usize];
+    let mut found = Vec::new();
+    for index in 0..chunk_count(size) {
+        let offset = index * CHUNK_SIZE;
+        let len = chunk_len(size, index);
+        if offset + len > local_len {
+            break;
+        }
+        let chunk = &mut buf[..len as usize];
+        if file.read_exact(chunk).is_err() {
+            break;
+        }
+        found.push((*blake3::hash(chunk).as_bytes(), offset));
+    }
+    found
+}
+
+/// Where the receiver gets one chunk from
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ChunkSource {
+    /// The partial file already has it in place
+    Kept,
+    /// Copied from a file in the download folder before the transfer
+    Local { from: usize, offset: u64 },
+    /// Sent by the peer
+    Peer,
+    /// Copied from the partial file `file` once the peer's chunks are in
+    Duplicate { file: usize, offset: u64 },
+}
+
+/// Decide where each chunk comes from, asking the peer for each missing
+/// hash only once. `kept[i]` lists the chunks already in place in file `i`'s
+/// partial copy; `local` maps hashes to a complete file and offset holding
+/// them.
+fn plan_chunks(
+    tables: &[Vec<ChunkHash>],
+    kept: &[HashSet<u64>],
+    local: &HashMap<ChunkHash, (usize, u64)>,
+) -> Vec<Vec<ChunkSource>> {
+    let mut first: HashMap<ChunkHash, (usize, u64)> = HashMap::new();
+    tables
+        .iter()
+        .enumerate()
+        .map(|(file, table)| {
+            table
+                .iter()
+                .enumerate()
+                .map(|(index, hash)| {
+                    let offset = index as u64 * CHUNK_SIZE;
+                    if kept[file].contains(&(index as u64)) {
+                        first.entry(*hash).or_insert((file, offset));
+                        return ChunkSource::Kept;
+                    }
+                    if let Some(&(from, offset)) = local.get(hash) {
+                        return ChunkSource::Local { from, offset };
+                    }
+                    match first.get(hash) {
+                        Some(&(file, offset)) => ChunkSource::Duplicate { file, offset },
+                        None => {
+                            first.insert(*hash, (file, offset));
+                            ChunkSource::Peer
+                        }
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Bitmap of the chunks to send, in collection order
+fn want_bitmap(plan: &[Vec<ChunkSource>]) -> Vec<u8> {
+    let total: usize = plan.iter().map(Vec::len).sum();
+    let mut bitmap = vec![0u8; total.div_ceil(8)];
+    for (bit, source) in plan.iter().flatten().enumerate() {
+        if *source == ChunkSource::Peer {
+            bitmap[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    bitmap
+}
+
+fn is_wanted(bitmap: &[u8], bit: usize) -> bool {
+    bitmap[bit / 8] & (1 << (bit % 8)) != 0
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Send `files` to the peer as one collection
+pub async fn send_collection(
+    connection: &Connection,
+    files: Vec<PathBuf>,
+    security: SecurityInfo,
+    event_tx: mpsc::Sender<AppEvent>,
+) -> Result<()> {
+    let mut entries = Vec::new();
+    let mut tables = Vec::new();
+    for path in &files {
+        let size = tokio::fs::metadata(path).await?.len();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| anyhow!("Invalid file name"))?
+            .to_string();
+        let _ = event_tx
+            .send(AppEvent::SecurityInfo {
+                file_name: name.clone(),
+                is_sending: true,
+                security: security.clone(),
+            })
+            .await;
+        let table = hash_chunks(path, size).await?;
+        entries.push(BlobEntry {
+            name,
+            size,
+            hash: content_hash(&table),
+        });
+        tables.push(table);
+    }
+
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!("Offering a collection of {} files", entries.len()),
+        ))
+        .await;
+
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_msg(
+        &mut send,
+        &WanTransferMsg::Collection {
+            entries: entries.clone(),
+        },
+    )
+    .await?;
+    for table in &tables {
+        send.write_all(table.as_flattened()).await?;
+    }
+
+    let total: u64 = entries.iter().map(|e| chunk_count(e.size)).sum();
+    match recv_msg(&mut recv).await? {
+        WanTransferMsg::WantChunks { total: announced } if announced == total => {}
+        WanTransferMsg::Error { message } => return Err(anyhow!("Receiver error: {}", message)),
+        other => return Err(anyhow!("Expected WantChunks, got {:?}", other)),
+    }
+    let mut bitmap = vec![0u8; (total as usize).div_ceil(8)];
+    recv.read_exact(&mut bitmap).await?;
+
+    let mut bit = 0;
+    let mut buf = vec![0u8; CHUNK_SIZE as usize];
+    for ((path, entry), table) in files.iter().zip(&entries).zip(&tables) {
+        let wanted: Vec<u64> = (0..table.len() as u64)
+            .filter(|index| is_wanted(&bitmap, bit + *index as usize))
+            .collect();
+        bit += table.len();
+        let wanted_bytes: u64 = wanted.iter().map(|i| chunk_len(entry.size, *i)).sum();
+        let mut sent = entry.size - wanted_bytes;
+        let mut progress = ProgressReporter::new(&event_tx, &entry.name, entry.size, sent, true);
+        progress.update(sent).await;
+        if wanted.is_empty() {
+            continue;
+        }
+
+        let mut file = tokio::fs::File::open(path).await?;
+        for index in wanted {
+            let chunk = &mut buf[..chunk_len(entry.size, index) as usize];
+            file.seek(SeekFrom::Start(index * CHUNK_SIZE)).await?;
+            file.read_exact(chunk).await?;
+            if blake3::hash(chunk).as_bytes() != &table[index as usize] {
+                return Err(anyhow!("{} changed during transfer", entry.name));
+            }
+            send.write_all(chunk).await?;
+            sent += chunk.len() as u64;
+            progress.update(sent).await;
+        }
+    }
+    send.finish()?;
+
+    match recv_msg(&mut recv).await? {
+        WanTransferMsg::TransferComplete => {}
+        WanTransferMsg::Error { message } => return Err(anyhow!("Transfer failed: {}", message)),
+        other => return Err(anyhow!("Expected TransferComplete, got {:?}", other)),
+    }
+    for entry in entries {
+        let _ = event_tx
+            .send(AppEvent::TransferCompleted {
+                file_name: entry.name,
+                saved_path: None,
+                peer: None,
+            })
+            .await;
+    }
+    Ok(())
+}
+
+/// Receive a collection announced with `entries` into `download_dir`,
+/// reporting failures to the sender
+pub async fn receive_collection(
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    download_dir: &Path,
+    event_tx: &mpsc::Sender<AppEvent>,
+    entries: Vec<BlobEntry>,
+    peer: &str,
+) -> Result<()> {
+    let result = receive_chunks(send, recv, download_dir, event_tx, entries, peer).await;
+    if let Err(e) = &result {
+        let _ = send_msg(
+            send,
+            &WanTransferMsg::Error {
+                message: e.to_string(),
+            },
+        )
+        .await;
+    }
+    result
+}
+
+async fn receive_chunks(
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    download_dir: &Path,
+    event_tx: &mpsc::Sender<AppEvent>,
+    mut entries: Vec<BlobEntry>,
+    peer: &str,
+) -> Result<()> {
+    let mut names = HashSet::new();
+    for entry in &mut entries {
+        validate_transfer_info(&entry.name, entry.size)?;
+        entry.name = normalize_file_name(&entry.name, download_dir).name;
+        if !names.insert(entry.name.clone()) {
+            return Err(anyhow!("{} appears twice in the collection", entry.name));
+        }
+    }
+
+    let mut tables = Vec::new();
+    for entry in &entries {
+        let mut raw = vec![0u8; chunk_count(entry.size) as usize * 32];
+        recv.read_exact(&mut raw).await?;
+        let table: Vec<ChunkHash> = raw
+            .chunks_exact(32)
+            .map(|hash| hash.try_into().unwrap())
+            .collect();
+        if content_hash(&table) != entry.hash {
+            return Err(anyhow!(
+                "Chunk table of {} does not match its hash",
+                entry.name
+            ));
+        }
+        tables.push(table);
+    }
+
+    tokio::fs::create_dir_all(download_dir).await?;
+    let paths: Vec<PathBuf> = entries.iter().map(|e| download_dir.join(&e.name)).collect();
+    let (kept, local) = {
+        let paths = paths.clone();
+        let sizes: Vec<u64> = entries.iter().map(|e| e.size).collect();
+        let tables = tables.clone();
+        tokio::task::spawn_blocking(move || find_local_chunks(&paths, &sizes, &tables)).await?
+    };
+    let plan = plan_chunks(&tables, &kept, &local);
+    let bitmap = want_bitmap(&plan);
+    let wanted = plan.iter().flatten().filter(|s| **s == ChunkSource::Peer);
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!(
+                "Receiving a collection of {} files: {} of {} chunks needed",
+                entries.len(),
+                wanted.count(),
+                plan.iter().map(Vec::len).sum::<usize>()
+            ),
+        ))
+        .await;
+
+    let mut parts = Vec::new();
+    for ((path, entry), sources) in paths.iter().zip(&entries).zip(&plan) {
+        let mut part = open_part(&part_path(path)).await?;
+        let mut buf = vec![0u8; CHUNK_SIZE as usize];
+        for (index, source) in sources.iter().enumerate() {
+            if let ChunkSource::Local { from, offset } = source {
+                let chunk = &mut buf[..chunk_len(entry.size, index as u64) as usize];
+                let mut source = tokio::fs::File::open(&paths[*from]).await?;
+                source.seek(SeekFrom::Start(*offset)).await?;
+                source.read_exact(chunk).await?;
+                part.seek(SeekFrom::Start(index as u64 * CHUNK_SIZE))
+                    .await?;
+                part.write_all(chunk).await?;
+            }
+        }
+        parts.push(part);
+    }
+
+    send_msg(
+        send,
+        &WanTransferMsg::WantChunks {
+            total: plan.iter().map(|t| t.len() as u64).sum(),
+        },
+    )
+    .await?;
+    send.write_all(&bitmap).await?;
+
+    let mut buf = vec![0u8; CHUNK_SIZE as usize];
+    for (((part, entry), sources), table) in parts.iter_mut().zip(&entries).zip(&plan).zip(&tables)
+    {
+        let wanted_bytes: u64 = sources
+            .iter()
+            .enumerate()
+            .filter(|(_, source)| **source == ChunkSource::Peer)
+            .map(|(index, _)| chunk_len(entry.size, index as u64))
+            .sum();
+        let mut received = entry.size - wanted_bytes;
+        let mut progress =
+            ProgressReporter::new(event_tx, &entry.name, entry.size, received, false);
+        progress.update(received).await;
+        for (index, source) in sources.iter().enumerate() {
+            if *source != ChunkSource::Peer {
+                continue;
+            }
+            let chunk = &mut buf[..chunk_len(entry.size, index as u64) as usize];
+            recv.read_exact(chunk).await?;
+            if blake3::hash(chunk).as_bytes() != &table[index] {
+                return Err(anyhow!(
+                    "Chunk {} of {} failed verification",
+                    index,
+                    entry.name
+                ));
+            }
+            part.seek(SeekFrom::Start(index as u64 * CHUNK_SIZE))
+                .await?;
+            part.write_all(chunk).await?;
+            received += chunk.len() as u64;
+            progress.update(received).await;
+        }
+    }
+    for part in &mut parts {
+        part.flush().await?;
+    }
+
+    for ((part, entry), sources) in parts.iter_mut().zip(&entries).zip(&plan) {
+        for (index, source) in sources.iter().enumerate() {
+            if let ChunkSource::Duplicate { file, offset } = source {
+                let chunk = &mut buf[..chunk_len(entry.size, index as u64) as usize];
+                let mut from = tokio::fs::File::open(part_path(&paths[*file])).await?;
+                from.seek(SeekFrom::Start(*offset)).await?;
+                from.read_exact(chunk).await?;
+                part.seek(SeekFrom::Start(index as u64 * CHUNK_SIZE))
+                    .await?;
+                part.write_all(chunk).await?;
+            }
+        }
+    }
+
+    for ((part, path), entry) in parts.into_iter().zip(&paths).zip(&entries) {
+        part.set_len(entry.size).await?;
+        part.sync_all().await?;
+        drop(part);
+        let _ = tokio::fs::remove_file(path).await;
+        tokio::fs::rename(part_path(path), path).await?;
+    }
+
+    send_msg(send, &WanTransferMsg::TransferComplete).await?;
+    for (path, entry) in paths.into_iter().zip(entries) {
+        let _ = event_tx
+            .send(AppEvent::TransferCompleted {
+                file_name: entry.name,
+                saved_path: Some(path),
+                peer: Some(peer.to_string()),
+            })
+            .await;
+    }
+    Ok(())
+}
+
+/// Chunks already in place in each partial file, and chunks of the
+/// collection found in complete files under the same names
+#[allow(clippy::type_complexity)]
+fn find_local_chunks(
+    paths: &[PathBuf],
+    sizes: &[u64],
+    tables: &[Vec<ChunkHash>],
+) -> (Vec<HashSet<u64>>, HashMap<ChunkHash, (usize, u64)>) {
+    let needed: HashSet<&ChunkHash> = tables.iter().flatten().collect();
+    let mut local = HashMap::new();
+    let kept = paths
+        .iter()
+        .zip(sizes)
+        .zip(tables)
+        .enumerate()
+        .map(|(file, ((path, size), table))| {
+            for (hash, offset) in scan_local(path, *size) {
+                if needed.contains(&hash) {
+                    local.entry(hash).or_insert((file, offset));
+                }
+            }
+            scan_local(&part_path(path), *size)
+                .into_iter()
+                .filter(|(hash, offset)| table[(offset / CHUNK_SIZE) as usize] == *hash)
+                .map(|(_, offset)| offset / CHUNK_SIZE)
+                .collect()
+        })
+        .collect();
+    (kept, local)
+}
+
+/// Open a partial file for writing anywhere, keeping what it holds
+async fn open_part(path: &Path) -> std::io::Result<tokio::fs::File> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.read(true).write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(byte: u8) -> ChunkHash {
+        [byte; 32]
+    }
+
+    #[test]
+    fn test_each_missing_chunk_is_asked_for_once() {
+        let tables = vec![
+            vec![hash(1), hash(2), hash(1)],
+            vec![hash(2), hash(3), hash(4)],
+        ];
+        let kept = vec![HashSet::from([1]), HashSet::new()];
+        let local = HashMap::from([(hash(4), (0, 7 * CHUNK_SIZE))]);
+
+        let plan = plan_chunks(&tables, &kept, &local);
+        assert_eq!(
+            plan,
+            vec![
+                vec![
+                    ChunkSource::Peer,
+                    ChunkSource::Kept,
+                    ChunkSource::Duplicate { file: 0, offset: 0 },
+                ],
+                vec![
+                    ChunkSource::Duplicate {
+                        file: 0,
+                        offset: CHUNK_SIZE,
+                    },
+                    ChunkSource::Peer,
+                    ChunkSource::Local {
+                        from: 0,
+                        offset: 7 * CHUNK_SIZE,
+                    },
+                ],
+            ]
+        );
+        let bitmap = want_bitmap(&plan);
+        let wanted: Vec<usize> = (0..6).filter(|bit| is_wanted(&bitmap, *bit)).collect();
+        assert_eq!(wanted, vec![0, 4]);
+    }
+
+    #[test]
+    fn test_chunk_lengths() {
+        assert_eq!(chunk_count(0), 0);
+        assert_eq!(chunk_count(CHUNK_SIZE), 1);
+        assert_eq!(chunk_count(CHUNK_SIZE + 1), 2);
+        assert_eq!(chunk_len(CHUNK_SIZE + 1, 1), 1);
+    }
+}
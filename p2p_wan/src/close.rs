@@ -0,0 +1,67 @@
+//! iroh's QUIC errors as [`CloseReason`]s, so WAN transfers fail with the
+//! same explanations as LAN ones.
+
+use iroh::endpoint::{ConnectionError, ReadError, ReadExactError, WriteError};
+use p2p_core::transfer::CloseReason;
+use p2p_core::transfer::close::stream_code_reason;
+
+pub fn close_reason(error: &ConnectionError) -> CloseReason {
+    match error {
+        ConnectionError::VersionMismatch => CloseReason::VersionMismatch,
+        ConnectionError::TransportError(e) => CloseReason::Transport {
+            code: e.code.into(),
+            reason: e.reason.clone(),
+        },
+        ConnectionError::ConnectionClosed(close) => CloseReason::Transport {
+            code: close.error_code.into(),
+            reason: String::from_utf8_lossy(&close.reason).into_owned(),
+        },
+        ConnectionError::ApplicationClosed(close) => CloseReason::Application {
+            code: close.error_code.into_inner(),
+            reason: String::from_utf8_lossy(&close.reason).into_owned(),
+        },
+        ConnectionError::Reset => CloseReason::Reset,
+        ConnectionError::TimedOut => CloseReason::TimedOut,
+        ConnectionError::LocallyClosed => CloseReason::LocallyClosed,
+        ConnectionError::CidsExhausted => CloseReason::CidsExhausted,
+    }
+}
+
+/// [`p2p_core::transfer::explain`] for errors of the WAN endpoint
+pub fn explain(error: &anyhow::Error) -> String {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<ConnectionError>() {
+            return close_reason(e).to_string();
+        }
+    }
+    for cause in error.chain() {
+        if let Some(ReadError::Reset(code)) = cause.downcast_ref() {
+            return stream_code_reason(code.into_inner());
+        }
+        if let Some(ReadExactError::ReadError(ReadError::Reset(code))) = cause.downcast_ref() {
+            return stream_code_reason(code.into_inner());
+        }
+        if let Some(WriteError::Stopped(code)) = cause.downcast_ref() {
+            return stream_code_reason(code.into_inner());
+        }
+        if let Some(ReadExactError::FinishedEarly(_)) = cause.downcast_ref() {
+            return "The peer ended the transfer early".to_string();
+        }
+    }
+    p2p_core::transfer::explain(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iroh_errors_are_explained() {
+        let error = anyhow::Error::from(ReadError::ConnectionLost(ConnectionError::TimedOut))
+            .context("Error reading message");
+        assert_eq!(explain(&error), CloseReason::TimedOut.to_string());
+
+        let plain = anyhow::anyhow!("Peer not found");
+        assert_eq!(explain(&plain), "Peer not found");
+    }
+}
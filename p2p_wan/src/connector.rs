@@ -1,14 +1,15 @@
 use anyhow::{Context, Result};
 use iroh::endpoint::Connection;
 use iroh::{Endpoint, EndpointAddr, EndpointId, SecretKey};
+use p2p_core::alpn::AppId;
 use std::time::Duration;
 use tracing::info;
-
-use crate::protocol::ALPN;
+use url::Url;
 
 /// Manages outbound P2P connections using Iroh
 pub struct Connector {
     endpoint: Endpoint,
+    app_id: AppId,
 }
 
 impl Connector {
@@ -16,7 +17,9 @@ impl Connector {
     ///
     /// # Arguments
     /// * `secret_key` - The secret key that determines the node's identity
-    pub async fn new(secret_key: SecretKey) -> Result<Self> {
+    /// * `proxy` - HTTP proxy for relay connections, see
+    ///   [`relay_proxy`](crate::proxy::relay_proxy)
+    pub async fn new(secret_key: SecretKey, proxy: Option<Url>) -> Result<Self> {
         info!("Initializing Iroh connector endpoint...");
 
         let mut transport_config = iroh::endpoint::TransportConfig::default();
@@ -29,10 +32,15 @@ impl Connector {
 
         info!("Transport config: receive_window=16MB, send_window=16MB, mtu=1200");
 
-        let endpoint = Endpoint::builder()
+        let app_id = AppId::default();
+        let mut builder = Endpoint::builder()
             .secret_key(secret_key)
-            .alpns(vec![ALPN.to_vec()])
-            .transport_config(transport_config)
+            .alpns(vec![app_id.wan_alpn()])
+            .transport_config(transport_config);
+        if let Some(proxy) = proxy {
+            builder = builder.proxy_url(proxy);
+        }
+        let endpoint = builder
             .bind()
             .await
             .context("Failed to bind connector endpoint")?;
@@ -40,7 +48,15 @@ impl Connector {
         let node_id = endpoint.id();
         info!("Connector endpoint initialized with Node ID: {}", node_id);
 
-        Ok(Self { endpoint })
+        Ok(Self { endpoint, app_id })
+    }
+
+    /// Speak the protocol of a private deployment (see [`p2p_core::alpn`])
+    /// instead of the public app's
+    pub fn with_app_id(mut self, app_id: AppId) -> Self {
+        self.endpoint.set_alpns(vec![app_id.wan_alpn()]);
+        self.app_id = app_id;
+        self
     }
 
     /// Returns the underlying endpoint
@@ -63,14 +79,15 @@ impl Connector {
         info!("=== WAN Connection Start ===");
         info!("Target Node ID: {}", target_id);
         info!("My Node ID: {}", self.endpoint.id());
-        info!("Using ALPN: {:?}", String::from_utf8_lossy(ALPN));
+        let alpn = self.app_id.wan_alpn();
+        info!("Using ALPN: {:?}", String::from_utf8_lossy(&alpn));
         info!("Attempting connection (UDP hole punch / DERP relay)...");
 
         let start = std::time::Instant::now();
 
         let connection = self
             .endpoint
-            .connect(target_id, ALPN)
+            .connect(target_id, &alpn)
             .await
             .context("Failed to connect to peer")?;
 
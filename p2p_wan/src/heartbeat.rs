@@ -0,0 +1,103 @@
+//! Application-level heartbeat for idle WAN connections.
+//!
+//! NATs drop UDP mappings that stay quiet for a while, often well before
+//! QUIC's idle timeout, and the connection then dies without notice. The
+//! side that connected sends [`WanTransferMsg::Ping`] on a stream of its own
+//! every interval and the listener answers each with a
+//! [`WanTransferMsg::Pong`]. The traffic keeps the mappings alive, the
+//! answers give the latency shown in
+//! [`AppEvent::WanConnectionInfo`](p2p_core::AppEvent::WanConnectionInfo),
+//! and [`MISSED_PINGS_BEFORE_DEAD`] unanswered pings in a row close the
+//! connection long before QUIC would notice.
+
+use anyhow::{Result, anyhow};
+use iroh::endpoint::{Connection, RecvStream, SendStream};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::protocol::{WanTransferMsg, recv_msg, send_msg};
+
+/// Ping interval unless configured otherwise
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Unanswered pings in a row after which the peer counts as gone
+pub const MISSED_PINGS_BEFORE_DEAD: u32 = 3;
+
+/// Close code of a connection whose peer stopped answering pings
+pub const HEARTBEAT_TIMEOUT_CODE: u32 = 0x4842;
+
+/// Ping the peer every `interval` until the connection closes, reporting
+/// each round trip to `on_pong`. Returns an error, after closing the
+/// connection, once the peer misses [`MISSED_PINGS_BEFORE_DEAD`] pings.
+pub async fn run_heartbeat(
+    connection: &Connection,
+    interval: Duration,
+    mut on_pong: impl FnMut(Duration),
+) -> Result<()> {
+    let (mut send, mut recv) = match connection.open_bi().await {
+        Ok(streams) => streams,
+        // Closed before the first ping
+        Err(_) => return Ok(()),
+    };
+    let mut ticker = tokio::time::interval(interval);
+    let mut missed = 0;
+    for seq in 0u64.. {
+        ticker.tick().await;
+        let sent = Instant::now();
+        if send_msg(&mut send, &WanTransferMsg::Ping { seq })
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+        match tokio::time::timeout(interval, wait_for_pong(&mut recv, seq)).await {
+            Ok(Ok(())) => {
+                missed = 0;
+                on_pong(sent.elapsed());
+            }
+            Ok(Err(e)) => {
+                debug!("Heartbeat stream ended: {}", e);
+                return Ok(());
+            }
+            Err(_) => {
+                missed += 1;
+                if missed >= MISSED_PINGS_BEFORE_DEAD {
+                    connection.close(HEARTBEAT_TIMEOUT_CODE.into(), b"heartbeat timeout");
+                    return Err(anyhow!(
+                        "Peer did not answer {} heartbeats in a row",
+                        missed
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Skip answers to earlier, timed out pings
+async fn wait_for_pong(recv: &mut RecvStream, seq: u64) -> Result<()> {
+    loop {
+        match recv_msg(recv).await? {
+            WanTransferMsg::Pong { seq: answered } if answered == seq => return Ok(()),
+            WanTransferMsg::Pong { .. } => continue,
+            other => return Err(anyhow!("Expected Pong, got {:?}", other)),
+        }
+    }
+}
+
+/// Answer the pings on a heartbeat stream, starting with `first_seq`
+/// already read, until the stream ends
+pub async fn answer_pings(
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    first_seq: u64,
+) -> Result<()> {
+    let mut seq = first_seq;
+    loop {
+        send_msg(send, &WanTransferMsg::Pong { seq }).await?;
+        seq = match recv_msg(recv).await? {
+            WanTransferMsg::Ping { seq } => seq,
+            other => return Err(anyhow!("Expected Ping, got {:?}", other)),
+        };
+    }
+}
@@ -1,11 +1,19 @@
+pub mod blobs;
+pub mod close;
 pub mod connector;
+pub mod heartbeat;
 pub mod identity;
+pub mod link;
 pub mod listener;
+pub mod paste;
 pub mod protocol;
+pub mod proxy;
 pub mod receiver;
+pub mod sealed;
 pub mod sender;
+pub mod vectors;
 
 pub use connector::Connector;
 pub use identity::IdentityManager;
 pub use listener::ConnectionListener;
-pub use protocol::ALPN;
+pub use paste::{DetectedTarget, PasteSource, detect_endpoint_id};
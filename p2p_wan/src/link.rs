@@ -0,0 +1,222 @@
+//! Linking two devices with a one-time phrase over Iroh.
+//!
+//! See [`p2p_core::pairing::phrase`] for the scheme. The host registers in
+//! the phrase's rendezvous room and waits for a connection on the link ALPN
+//! ([`AppId::link_alpn`]);
+//! the joiner looks it up there and runs [`join_link`]. Both sides end up
+//! with the same pairing key, stored as a pairing and as a receiver key, so
+//! either device can send to the other without a verification code.
+
+use anyhow::{Context, Result, anyhow};
+use iroh::endpoint::Connection;
+use iroh::{Endpoint, EndpointAddr, EndpointId, SecretKey};
+use p2p_core::alpn::AppId;
+use p2p_core::pairing::PairingStore;
+use p2p_core::pairing::phrase::{LinkOffer, LinkPhrase, LinkRole};
+use p2p_core::proxy::ProxySettings;
+use p2p_core::rendezvous::{MAX_DEVICE_NAME_LEN, RendezvousClient, RendezvousSettings};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+use zeroize::Zeroizing;
+
+use crate::protocol::{WanTransferMsg, recv_msg, send_msg};
+
+/// Longest a link handshake may take once connected
+pub const LINK_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SESSION_LABEL: &[u8] = b"p2p-transfer link session";
+const PAIR_KEY_LABEL: &[u8] = b"p2p-transfer pairing key";
+
+/// The other device of a completed link
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedDevice {
+    pub endpoint_id: EndpointId,
+    pub name: String,
+}
+
+/// What the host side needs to answer link connections
+#[derive(Debug)]
+pub struct LinkHost {
+    pub offer: LinkOffer,
+    pub pairings: Arc<dyn PairingStore>,
+    pub device_name: String,
+}
+
+fn session_secret(connection: &Connection) -> Result<Zeroizing<[u8; 32]>> {
+    let mut secret = Zeroizing::new([0u8; 32]);
+    connection
+        .export_keying_material(secret.as_mut(), SESSION_LABEL, &[])
+        .map_err(|_| anyhow!("Could not derive the session secret"))?;
+    Ok(secret)
+}
+
+/// Same on both ends: exported from the session, bound to the joiner's ID
+fn pair_key(connection: &Connection, joiner: EndpointId) -> Result<Zeroizing<String>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    connection
+        .export_keying_material(key.as_mut(), PAIR_KEY_LABEL, joiner.to_string().as_bytes())
+        .map_err(|_| anyhow!("Could not derive the pairing key"))?;
+    Ok(Zeroizing::new(
+        blake3::Hash::from_bytes(*key).to_hex().to_string(),
+    ))
+}
+
+fn device_name(name: &str) -> String {
+    name.trim().chars().take(MAX_DEVICE_NAME_LEN).collect()
+}
+
+/// Trust `peer` both ways: as a sender to us and as a receiver of ours
+fn store_link(pairings: &dyn PairingStore, peer: &LinkedDevice, key: &str) {
+    pairings.add_pairing(&peer.endpoint_id.to_string(), &peer.name, key);
+    pairings.add_receiver_key(&peer.name, key);
+}
+
+fn rendezvous(url: &str, phrase: &LinkPhrase, proxy: &ProxySettings) -> Result<RendezvousClient> {
+    RendezvousClient::new(
+        &RendezvousSettings {
+            url: Some(url.to_string()),
+            room_secret: phrase.room_secret(),
+        },
+        proxy,
+    )
+}
+
+/// Host side: show a fresh phrase and register in its room
+pub async fn offer_link(
+    host: &LinkHost,
+    secret_key: &SecretKey,
+    rendezvous_url: &str,
+    proxy: &ProxySettings,
+) -> Result<LinkPhrase> {
+    let phrase = LinkPhrase::generate();
+    rendezvous(rendezvous_url, &phrase, proxy)?
+        .register(secret_key, &host.device_name)
+        .await
+        .context("Could not reach the rendezvous server")?;
+    host.offer.offer(phrase.clone());
+    Ok(phrase)
+}
+
+/// Host side: answer a joiner that connected with the link ALPN
+pub async fn answer_link(connection: &Connection, host: &LinkHost) -> Result<LinkedDevice> {
+    let (mut send, mut recv) = connection.accept_bi().await?;
+    let secret = session_secret(connection)?;
+    let WanTransferMsg::LinkHello { name, proof } = recv_msg(&mut recv).await? else {
+        return Err(anyhow!("Expected a link request"));
+    };
+    let Some(phrase) = host.offer.redeem(&secret, &proof) else {
+        send_msg(
+            &mut send,
+            &WanTransferMsg::Error {
+                message: "Wrong or expired link phrase".to_string(),
+            },
+        )
+        .await?;
+        send.finish()?;
+        let _ = send.stopped().await;
+        return Err(anyhow!(
+            "{} tried to link with a wrong phrase",
+            connection.remote_id()
+        ));
+    };
+
+    let joiner = LinkedDevice {
+        endpoint_id: connection.remote_id(),
+        name: device_name(&name),
+    };
+    let key = pair_key(connection, joiner.endpoint_id)?;
+    send_msg(
+        &mut send,
+        &WanTransferMsg::LinkWelcome {
+            name: host.device_name.clone(),
+            proof: phrase.proof(LinkRole::Host, &secret),
+        },
+    )
+    .await?;
+    send.finish()?;
+    let _ = send.stopped().await;
+
+    store_link(host.pairings.as_ref(), &joiner, &key);
+    info!("Linked with {} ({})", joiner.name, joiner.endpoint_id);
+    Ok(joiner)
+}
+
+/// Joiner side: the host registered in the phrase's room, other than us
+pub async fn find_host(
+    phrase: &LinkPhrase,
+    rendezvous_url: &str,
+    proxy: &ProxySettings,
+    own_id: EndpointId,
+) -> Result<EndpointId> {
+    let own_id = own_id.to_string();
+    let host = rendezvous(rendezvous_url, phrase, proxy)?
+        .devices()
+        .await
+        .context("Could not reach the rendezvous server")?
+        .into_iter()
+        .filter(|device| device.endpoint_id != own_id)
+        .max_by_key(|device| device.last_seen)
+        .ok_or_else(|| anyhow!("No device is showing this phrase"))?;
+    host.endpoint_id
+        .parse()
+        .map_err(|_| anyhow!("The rendezvous server listed an invalid endpoint ID"))
+}
+
+/// Joiner side: connect to the host of `app_id` and link with `phrase`
+pub async fn join_link(
+    endpoint: &Endpoint,
+    app_id: &AppId,
+    host: impl Into<EndpointAddr>,
+    phrase: &LinkPhrase,
+    my_name: &str,
+    pairings: &dyn PairingStore,
+) -> Result<LinkedDevice> {
+    let connection = endpoint.connect(host, &app_id.link_alpn()).await?;
+    let result = tokio::time::timeout(
+        LINK_TIMEOUT,
+        join_handshake(&connection, endpoint.id(), phrase, my_name, pairings),
+    )
+    .await
+    .unwrap_or_else(|_| Err(anyhow!("The other device did not answer in time")));
+    connection.close(0u8.into(), b"linked");
+    result
+}
+
+async fn join_handshake(
+    connection: &Connection,
+    own_id: EndpointId,
+    phrase: &LinkPhrase,
+    my_name: &str,
+    pairings: &dyn PairingStore,
+) -> Result<LinkedDevice> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    let secret = session_secret(connection)?;
+    send_msg(
+        &mut send,
+        &WanTransferMsg::LinkHello {
+            name: device_name(my_name),
+            proof: phrase.proof(LinkRole::Joiner, &secret),
+        },
+    )
+    .await?;
+    send.finish()?;
+
+    match recv_msg(&mut recv).await? {
+        WanTransferMsg::LinkWelcome { name, proof } => {
+            if !phrase.verify(LinkRole::Host, &secret, &proof) {
+                return Err(anyhow!("The other device does not know this phrase"));
+            }
+            let host = LinkedDevice {
+                endpoint_id: connection.remote_id(),
+                name: device_name(&name),
+            };
+            let key = pair_key(connection, own_id)?;
+            store_link(pairings, &host, &key);
+            info!("Linked with {} ({})", host.name, host.endpoint_id);
+            Ok(host)
+        }
+        WanTransferMsg::Error { message } => Err(anyhow!(message)),
+        _ => Err(anyhow!("Unexpected answer to a link request")),
+    }
+}
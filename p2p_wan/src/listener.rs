@@ -2,19 +2,43 @@ use anyhow::{Context, Result};
 use iroh::endpoint::Incoming;
 use iroh::{Endpoint, EndpointAddr, EndpointId, SecretKey, Watcher};
 use p2p_core::AppEvent;
+use p2p_core::alpn::AppId;
+use p2p_core::pairing::PairingStore;
+use p2p_core::pairing::phrase::{LinkOffer, LinkPhrase};
+use p2p_core::proxy::ProxySettings;
+use p2p_core::storage::{LocalStorage, Storage};
+use p2p_core::transfer::{ConnectionPath, SecurityInfo, normalize_file_name, peer_folder};
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing::{error, info, warn};
-
-use crate::protocol::{ALPN, WanTransferMsg, recv_msg, send_msg};
+use url::Url;
+
+use crate::blobs::receive_collection;
+use crate::close::explain;
+use crate::heartbeat::{answer_pings, run_heartbeat};
+use crate::link::{
+    LINK_TIMEOUT, LinkHost, LinkedDevice, answer_link, find_host, join_link, offer_link,
+};
+use crate::protocol::{WanTransferMsg, recv_msg, send_msg};
 use crate::receiver::receive_file;
+use crate::sealed::{PendingOffers, answer_offer};
 
 /// Manages incoming P2P connections using Iroh
 pub struct ConnectionListener {
     endpoint: Endpoint,
     download_dir: PathBuf,
     event_tx: mpsc::Sender<AppEvent>,
+    preserve_metadata: bool,
+    per_peer_folders: bool,
+    /// Set by [`with_linking`](Self::with_linking); link connections are
+    /// refused without it
+    link_host: Option<Arc<LinkHost>>,
+    /// Offers with hidden names waiting for [`respond_offer`](Self::respond_offer)
+    offers: Arc<PendingOffers>,
+    storage: Arc<dyn Storage>,
+    app_id: AppId,
 }
 
 impl ConnectionListener {
@@ -24,10 +48,13 @@ impl ConnectionListener {
     /// * `secret_key` - The secret key that determines the node's identity
     /// * `download_dir` - Directory to save received files
     /// * `event_tx` - Channel to send events to GUI
+    /// * `proxy` - HTTP proxy for relay connections, see
+    ///   [`relay_proxy`](crate::proxy::relay_proxy)
     pub async fn new(
         secret_key: SecretKey,
         download_dir: PathBuf,
         event_tx: mpsc::Sender<AppEvent>,
+        proxy: Option<Url>,
     ) -> Result<Self> {
         info!("Initializing Iroh listener endpoint...");
 
@@ -41,13 +68,19 @@ impl ConnectionListener {
 
         info!("Transport config: receive_window=16MB, send_window=16MB, mtu=1200");
 
-        let endpoint = Endpoint::builder()
+        let app_id = AppId::default();
+        let mut builder = Endpoint::builder()
             .secret_key(secret_key)
-            .alpns(vec![ALPN.to_vec()])
-            .transport_config(transport_config)
-            .bind()
-            .await
-            .context("Failed to bind endpoint")?;
+            .alpns(vec![app_id.wan_alpn(), app_id.link_alpn()])
+            .transport_config(transport_config);
+        if let Some(proxy) = proxy {
+            info!(
+                "Reaching relays through proxy {}",
+                proxy.host_str().unwrap_or_default()
+            );
+            builder = builder.proxy_url(proxy);
+        }
+        let endpoint = builder.bind().await.context("Failed to bind endpoint")?;
 
         let node_id = endpoint.id();
         info!("Iroh endpoint initialized with Node ID: {}", node_id);
@@ -59,14 +92,104 @@ impl ConnectionListener {
             endpoint,
             download_dir,
             event_tx,
+            preserve_metadata: true,
+            per_peer_folders: false,
+            link_host: None,
+            offers: Arc::default(),
+            storage: Arc::new(LocalStorage),
+            app_id,
         })
     }
 
+    /// Restore (or with `false`, drop) the sender's modification time and
+    /// permissions on received files
+    pub fn with_preserve_metadata(mut self, enabled: bool) -> Self {
+        self.preserve_metadata = enabled;
+        self
+    }
+
+    /// Save each sender's files in its own subfolder of the download dir,
+    /// named after its node ID
+    pub fn with_per_peer_folders(mut self, enabled: bool) -> Self {
+        self.per_peer_folders = enabled;
+        self
+    }
+
+    /// Write received files through `storage` instead of the local file
+    /// system
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Speak the protocol of a private deployment (see [`p2p_core::alpn`])
+    /// instead of the public app's; only its devices can connect
+    pub fn with_app_id(mut self, app_id: AppId) -> Self {
+        self.endpoint
+            .set_alpns(vec![app_id.wan_alpn(), app_id.link_alpn()]);
+        self.app_id = app_id;
+        self
+    }
+
+    /// Accept devices linking with a phrase (see [`crate::link`]), storing
+    /// the pairings in `pairings` and introducing ourselves as `device_name`
+    pub fn with_linking(mut self, pairings: Arc<dyn PairingStore>, device_name: String) -> Self {
+        self.link_host = Some(Arc::new(LinkHost {
+            offer: LinkOffer::default(),
+            pairings,
+            device_name,
+        }));
+        self
+    }
+
+    /// Show a new link phrase, registered with the rendezvous server at
+    /// `rendezvous_url`; it replaces any earlier one
+    pub async fn offer_link(
+        &self,
+        rendezvous_url: &str,
+        proxy: &ProxySettings,
+    ) -> Result<LinkPhrase> {
+        let host = self.link_host.as_ref().context("Linking is not enabled")?;
+        offer_link(host, self.endpoint.secret_key(), rendezvous_url, proxy).await
+    }
+
+    /// Link with the device showing `phrase`
+    pub async fn link(
+        &self,
+        phrase: &LinkPhrase,
+        rendezvous_url: &str,
+        proxy: &ProxySettings,
+    ) -> Result<LinkedDevice> {
+        let host = self.link_host.as_ref().context("Linking is not enabled")?;
+        let host_id = find_host(phrase, rendezvous_url, proxy, self.node_id()).await?;
+        info!("Linking with {}...", host_id);
+        join_link(
+            &self.endpoint,
+            &self.app_id,
+            host_id,
+            phrase,
+            &host.device_name,
+            host.pairings.as_ref(),
+        )
+        .await
+    }
+
+    /// Accept or decline a [`AppEvent::WanOffer`]; false when the offer is
+    /// no longer waiting
+    pub fn respond_offer(&self, offer_id: &str, accepted: bool) -> bool {
+        self.offers.respond(offer_id, accepted)
+    }
+
     /// Returns the underlying endpoint
     pub fn endpoint(&self) -> &Endpoint {
         &self.endpoint
     }
 
+    /// Application ID the protocols are derived from
+    pub fn app_id(&self) -> &AppId {
+        &self.app_id
+    }
+
     /// Returns the Node ID of this endpoint
     pub fn node_id(&self) -> EndpointId {
         self.endpoint.id()
@@ -83,7 +206,10 @@ impl ConnectionListener {
             "Connecting to peer {} from existing listener endpoint...",
             node_id
         );
-        let conn = self.endpoint.connect(node_id, ALPN).await?;
+        let conn = self
+            .endpoint
+            .connect(node_id, &self.app_id.wan_alpn())
+            .await?;
         Ok(conn)
     }
 
@@ -100,13 +226,31 @@ impl ConnectionListener {
                 Some(incoming) => {
                     info!("Incoming connection detected, spawning handler...");
 
+                    let endpoint = self.endpoint.clone();
                     let download_dir = self.download_dir.clone();
                     let event_tx = self.event_tx.clone();
+                    let preserve_metadata = self.preserve_metadata;
+                    let per_peer_folders = self.per_peer_folders;
+                    let link_host = self.link_host.clone();
+                    let offers = self.offers.clone();
+                    let storage = self.storage.clone();
+                    let link_alpn = self.app_id.link_alpn();
                     tokio::spawn(async move {
-                        if let Err(e) =
-                            Self::handle_connection(incoming, download_dir, event_tx).await
+                        if let Err(e) = Self::handle_connection(
+                            &endpoint,
+                            incoming,
+                            download_dir,
+                            event_tx,
+                            preserve_metadata,
+                            per_peer_folders,
+                            link_host,
+                            offers,
+                            storage,
+                            &link_alpn,
+                        )
+                        .await
                         {
-                            error!("Error handling connection: {}", e);
+                            error!("Error handling connection: {}", explain(&e));
                         }
                     });
                 }
@@ -121,13 +265,42 @@ impl ConnectionListener {
     }
 
     /// Handles an individual incoming connection
+    #[allow(clippy::too_many_arguments)]
     async fn handle_connection(
+        endpoint: &Endpoint,
         incoming: Incoming,
         download_dir: PathBuf,
         event_tx: mpsc::Sender<AppEvent>,
+        preserve_metadata: bool,
+        per_peer_folders: bool,
+        link_host: Option<Arc<LinkHost>>,
+        offers: Arc<PendingOffers>,
+        storage: Arc<dyn Storage>,
+        link_alpn: &[u8],
     ) -> Result<()> {
         let connection = incoming.await.context("Failed to accept connection")?;
         let remote_node_id = connection.remote_id();
+        if connection.alpn() == link_alpn {
+            let Some(host) = link_host else {
+                connection.close(1u8.into(), b"linking disabled");
+                return Ok(());
+            };
+            let linked = tokio::time::timeout(LINK_TIMEOUT, answer_link(&connection, &host))
+                .await
+                .context("Link request timed out")??;
+            let _ = event_tx
+                .send(AppEvent::DeviceLinked {
+                    endpoint_id: linked.endpoint_id.to_string(),
+                    peer_name: linked.name,
+                })
+                .await;
+            return Ok(());
+        }
+        let download_dir = if per_peer_folders {
+            peer_folder(&download_dir, "", &remote_node_id.to_string())
+        } else {
+            download_dir
+        };
 
         info!(
             "Connection accepted and established with: {}",
@@ -143,14 +316,31 @@ impl ConnectionListener {
                         Ok(WanTransferMsg::FileMetadata { info }) => {
                             info!(
                                 "Receiving file: {} ({} bytes)",
-                                info.file_name, info.file_size
+                                info.file_name,
+                                info.file_size.unwrap_or(0)
                             );
+                            let _ = event_tx
+                                .send(AppEvent::SecurityInfo {
+                                    file_name: normalize_file_name(&info.file_name, &download_dir)
+                                        .name,
+                                    is_sending: false,
+                                    security: security_info(endpoint, remote_node_id),
+                                })
+                                .await;
 
-                            if let Err(e) =
-                                receive_file(&mut send, &mut recv, &download_dir, &event_tx, info)
-                                    .await
+                            if let Err(e) = receive_file(
+                                &mut send,
+                                &mut recv,
+                                &download_dir,
+                                &event_tx,
+                                info,
+                                preserve_metadata,
+                                &remote_node_id.to_string(),
+                                storage.as_ref(),
+                            )
+                            .await
                             {
-                                error!("Error receiving file: {}", e);
+                                error!("Error receiving file: {}", explain(&e));
                                 let _ = send_msg(
                                     &mut send,
                                     &WanTransferMsg::Error {
@@ -160,6 +350,31 @@ impl ConnectionListener {
                                 .await;
                             }
                         }
+                        Ok(WanTransferMsg::Collection { entries }) => {
+                            info!("Receiving a collection of {} files", entries.len());
+                            for entry in &entries {
+                                let _ = event_tx
+                                    .send(AppEvent::SecurityInfo {
+                                        file_name: normalize_file_name(&entry.name, &download_dir)
+                                            .name,
+                                        is_sending: false,
+                                        security: security_info(endpoint, remote_node_id),
+                                    })
+                                    .await;
+                            }
+                            if let Err(e) = receive_collection(
+                                &mut send,
+                                &mut recv,
+                                &download_dir,
+                                &event_tx,
+                                entries,
+                                &remote_node_id.to_string(),
+                            )
+                            .await
+                            {
+                                error!("Error receiving collection: {}", explain(&e));
+                            }
+                        }
                         Ok(WanTransferMsg::BenchmarkStart { data_size }) => {
                             info!("Benchmark started: expecting {} bytes", data_size);
                             let start = std::time::Instant::now();
@@ -192,6 +407,27 @@ impl ConnectionListener {
                             )
                             .await;
                         }
+                        Ok(WanTransferMsg::SealedOffer { files }) => {
+                            // The user may take a while; keep accepting streams
+                            let offers = offers.clone();
+                            let event_tx = event_tx.clone();
+                            let peer = remote_node_id.to_string();
+                            tokio::spawn(async move {
+                                if let Err(e) = answer_offer(
+                                    &mut send, &mut recv, files, peer, &offers, &event_tx,
+                                )
+                                .await
+                                {
+                                    warn!("Error answering an offer: {}", e);
+                                }
+                            });
+                        }
+                        Ok(WanTransferMsg::Ping { seq }) => {
+                            // Lives as long as the connection; keep accepting
+                            tokio::spawn(async move {
+                                let _ = answer_pings(&mut send, &mut recv, seq).await;
+                            });
+                        }
                         Ok(msg) => {
                             warn!("Unexpected message: {:?}", msg);
                         }
@@ -227,20 +463,58 @@ impl ConnectionListener {
     }
 }
 
-/// Monitor connection type and send updates to GUI
+/// Security of the connection to `peer`, with the path it currently uses
+pub fn security_info(endpoint: &Endpoint, peer: EndpointId) -> SecurityInfo {
+    let path = endpoint
+        .conn_type(peer)
+        .map(|mut watcher| match watcher.get() {
+            iroh::endpoint::ConnectionType::Direct(_) => ConnectionPath::Direct,
+            iroh::endpoint::ConnectionType::Relay(_) => ConnectionPath::Relay,
+            iroh::endpoint::ConnectionType::Mixed(_, _) => ConnectionPath::Mixed,
+            iroh::endpoint::ConnectionType::None => ConnectionPath::Unknown,
+        })
+        .unwrap_or(ConnectionPath::Unknown);
+    SecurityInfo::wan(path)
+}
+
+/// Monitor connection type and send updates to GUI. With a `heartbeat`
+/// interval the peer is also pinged (see [`crate::heartbeat`]) and
+/// [`AppEvent::WanConnectionLost`] reports a peer that stopped answering.
 pub async fn spawn_connection_monitor(
     endpoint: Endpoint,
     peer_id: EndpointId,
     connection: iroh::endpoint::Connection,
     event_tx: mpsc::Sender<AppEvent>,
+    heartbeat: Option<Duration>,
 ) {
     info!("Starting connection monitor for peer: {}", peer_id);
 
+    let (latency_tx, latency_rx) = tokio::sync::watch::channel(None);
+    if let Some(heartbeat) = heartbeat {
+        let connection = connection.clone();
+        let event_tx = event_tx.clone();
+        tokio::spawn(async move {
+            let result = run_heartbeat(&connection, heartbeat, |latency| {
+                let _ = latency_tx.send(Some(latency.as_millis() as u64));
+            })
+            .await;
+            if let Err(e) = result {
+                let reason = explain(&e);
+                warn!("WAN peer {} is gone: {}", peer_id, reason);
+                let _ = event_tx.send(AppEvent::WanConnectionLost { reason }).await;
+            }
+        });
+    }
+
     let mut last_type_str = String::new();
     let mut interval = tokio::time::interval(Duration::from_secs(3));
 
     loop {
         interval.tick().await;
+        if connection.close_reason().is_some() {
+            info!("Connection to {} closed, stopping monitor", peer_id);
+            break;
+        }
 
         if let Some(mut watcher) = endpoint.conn_type(peer_id) {
             let conn_type = watcher.get();
@@ -261,10 +535,12 @@ pub async fn spawn_connection_monitor(
                 iroh::endpoint::ConnectionType::None => "None".to_string(),
             };
 
+            let latency_ms = *latency_rx.borrow();
             let _ = event_tx
                 .send(AppEvent::WanConnectionInfo {
                     connection_type: display_type,
                     rtt_ms,
+                    latency_ms,
                 })
                 .await;
         } else {
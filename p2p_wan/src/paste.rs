@@ -0,0 +1,52 @@
+//! Recognise a WAN connect target in pasted or copied text.
+//!
+//! Accepts a bare iroh `EndpointId` (hex or base32) as well as links and
+//! messages that contain one, e.g. `https://example.com/connect/<id>` or
+//! "my id is <id>".
+
+use iroh::EndpointId;
+
+/// Longer clipboard contents are not scanned
+const MAX_SCAN_LEN: usize = 4096;
+
+/// Lengths of an encoded 32-byte key: base32 and hex
+const ID_LENGTHS: [usize; 2] = [52, 64];
+
+/// Where a detected endpoint ID was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasteSource {
+    /// The text is just the ID (surrounding whitespace aside)
+    EndpointId,
+    /// The ID is part of a URL or longer text
+    Embedded,
+}
+
+/// An endpoint ID found in pasted text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedTarget {
+    pub endpoint_id: EndpointId,
+    pub source: PasteSource,
+}
+
+/// Find the first valid endpoint ID in `text`
+pub fn detect_endpoint_id(text: &str) -> Option<DetectedTarget> {
+    let text = text.trim();
+    if text.is_empty() || text.len() > MAX_SCAN_LEN {
+        return None;
+    }
+
+    if let Ok(endpoint_id) = text.parse::<EndpointId>() {
+        return Some(DetectedTarget {
+            endpoint_id,
+            source: PasteSource::EndpointId,
+        });
+    }
+
+    text.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|token| ID_LENGTHS.contains(&token.len()))
+        .find_map(|token| token.parse::<EndpointId>().ok())
+        .map(|endpoint_id| DetectedTarget {
+            endpoint_id,
+            source: PasteSource::Embedded,
+        })
+}
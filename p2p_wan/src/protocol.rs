@@ -1,17 +1,25 @@
+use crate::blobs::BlobEntry;
+use crate::sealed::SealedFile;
 use anyhow::Result;
 use p2p_core::FileInfo;
+use p2p_core::protocol_doc::{self, ProtocolDoc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-/// ALPN protocol identifier for doanltm-p2p
-pub const ALPN: &[u8] = b"doanltm-p2p";
-
 /// Protocol messages for WAN file transfer
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum WanTransferMsg {
     /// File metadata sent before transfer
     FileMetadata { info: FileInfo },
-    /// Resume info with offset (0 = start from beginning)
-    ResumeInfo { offset: u64 },
+    /// Resume info with offset (0 = start from beginning), the hash of the
+    /// receiver's first `offset` bytes and the transfer token
+    ResumeInfo {
+        offset: u64,
+        prefix_hash: Option<String>,
+        token: String,
+    },
+    /// Offset the sender starts from (the offered one or 0), echoing the token
+    ResumeStart { offset: u64, token: String },
     /// Transfer completed successfully
     TransferComplete,
     /// Error occurred during transfer
@@ -20,11 +28,41 @@ pub enum WanTransferMsg {
     BenchmarkStart { data_size: u64 },
     /// Benchmark completed with timing info
     BenchmarkComplete { elapsed_ms: u64 },
+    /// Heartbeat on an otherwise idle connection; see
+    /// [`heartbeat`](crate::heartbeat)
+    Ping { seq: u64 },
+    /// Answer to the [`Ping`](Self::Ping) with the same `seq`
+    Pong { seq: u64 },
+    /// Content-addressed collection; each file's chunk hash table follows,
+    /// see [`blobs`](crate::blobs)
+    Collection { entries: Vec<BlobEntry> },
+    /// Chunks the receiver lacks; a bitmap of `total` bits follows
+    WantChunks { total: u64 },
+    /// Device typing a link phrase, with its proof; see [`link`](crate::link)
+    LinkHello { name: String, proof: String },
+    /// The host's answer to a correct [`LinkHello`](Self::LinkHello)
+    LinkWelcome { name: String, proof: String },
+    /// Files offered without their names; see [`sealed`](crate::sealed)
+    SealedOffer { files: Vec<SealedFile> },
+    /// Whether the receiver's user accepted a [`SealedOffer`](Self::SealedOffer)
+    OfferAnswer { accepted: bool },
+}
+
+/// The whole protocol: [`p2p_core`]'s channels and the WAN transfer
+pub fn describe() -> ProtocolDoc {
+    protocol_doc::describe().channel::<WanTransferMsg>(
+        "WAN transfer",
+        &protocol_doc::length_prefixed("a bidirectional iroh stream"),
+        crate::vectors::VECTORS,
+    )
 }
 
 /// Send a protocol message over an iroh bidirectional stream
 pub async fn send_msg(send: &mut iroh::endpoint::SendStream, msg: &WanTransferMsg) -> Result<()> {
-    let json = serde_json::to_vec(msg)?;
+    let json = {
+        p2p_core::profile_scope!("serialize");
+        serde_json::to_vec(msg)?
+    };
     let len = (json.len() as u32).to_be_bytes();
     send.write_all(&len).await?;
     send.write_all(&json).await?;
@@ -37,17 +75,28 @@ pub async fn recv_msg(recv: &mut iroh::endpoint::RecvStream) -> Result<WanTransf
     recv.read_exact(&mut len_buf).await?;
     let len = u32::from_be_bytes(len_buf) as usize;
 
-    if len > p2p_core::transfer::constants::MAX_MSG_SIZE {
+    if len > p2p_core::transfer::MAX_MSG_SIZE {
         return Err(anyhow::anyhow!(
             "Message too large: {} bytes (max {})",
             len,
-            p2p_core::transfer::constants::MAX_MSG_SIZE
+            p2p_core::transfer::MAX_MSG_SIZE
         ));
     }
 
     let mut buf = vec![0u8; len];
     recv.read_exact(&mut buf).await?;
 
-    let msg: WanTransferMsg = serde_json::from_slice(&buf)?;
-    Ok(msg)
+    decode_msg(&buf)
+}
+
+/// Decode a message body (without the length prefix)
+pub fn decode_msg(buf: &[u8]) -> Result<WanTransferMsg> {
+    if buf.len() > p2p_core::transfer::MAX_MSG_SIZE {
+        return Err(anyhow::anyhow!(
+            "Message too large: {} bytes (max {})",
+            buf.len(),
+            p2p_core::transfer::MAX_MSG_SIZE
+        ));
+    }
+    Ok(serde_json::from_slice(buf)?)
 }
@@ -0,0 +1,62 @@
+//! Proxy for the endpoint's relay connections.
+//!
+//! iroh tunnels relay traffic through HTTP(S) proxies with `CONNECT`; it has
+//! no SOCKS5 support, so a SOCKS5 proxy only applies to the ngrok tunnel and
+//! relays are reached directly. Direct peer-to-peer paths are UDP and never
+//! use the proxy.
+
+use iroh::endpoint::default_relay_mode;
+use p2p_core::proxy::ProxySettings;
+use tracing::warn;
+use url::Url;
+
+/// Proxy for the endpoint's relay connections, if the settings name one
+/// that iroh can use and not every relay is bypassed
+pub fn relay_proxy(settings: &ProxySettings) -> Option<Url> {
+    let relays: Vec<String> = default_relay_mode()
+        .relay_map()
+        .urls::<Vec<_>>()
+        .iter()
+        .filter_map(|url| url.host_str().map(str::to_string))
+        .collect();
+    proxy_for_hosts(settings, &relays)
+}
+
+fn proxy_for_hosts(settings: &ProxySettings, relays: &[String]) -> Option<Url> {
+    let url = match settings.proxy_url() {
+        Ok(url) => url?,
+        Err(e) => {
+            warn!("Ignoring proxy settings: {}", e);
+            return None;
+        }
+    };
+    if url.scheme() == "socks5" {
+        warn!("WAN relays cannot use a SOCKS5 proxy; connecting to them directly");
+        return None;
+    }
+    relays
+        .iter()
+        .any(|host| !settings.bypasses(host))
+        .then_some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relays_only_use_http_proxies() {
+        let relays = ["euc1-1.relay.n0.iroh.link".to_string()];
+        let settings = |url: &str, bypass: &[&str]| ProxySettings {
+            url: Some(url.to_string()),
+            bypass: bypass.iter().map(|rule| rule.to_string()).collect(),
+        };
+
+        let http = proxy_for_hosts(&settings("http://proxy.corp:3128", &[]), &relays);
+        assert_eq!(http.unwrap().as_str(), "http://proxy.corp:3128/");
+        assert!(proxy_for_hosts(&settings("socks5://proxy.corp:1080", &[]), &relays).is_none());
+        let bypassed = settings("http://proxy.corp:3128", &["*.iroh.link"]);
+        assert!(proxy_for_hosts(&bypassed, &relays).is_none());
+        assert!(proxy_for_hosts(&ProxySettings::default(), &relays).is_none());
+    }
+}
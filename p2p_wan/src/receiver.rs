@@ -1,15 +1,18 @@
-use anyhow::Result;
-use p2p_core::transfer::utils::{open_secure_file, validate_transfer_info, sanitize_file_name};
-use p2p_core::{AppEvent, FileInfo};
+use anyhow::{Result, anyhow};
+use p2p_core::storage::{self, Storage};
+use p2p_core::transfer::receiver::restore_metadata;
+use p2p_core::transfer::resume;
+use p2p_core::transfer::{
+    HashAlgorithm, ProgressReporter, SparseWriter, WritePipeline, compute_file_hash_with_progress,
+    normalize_file_name, transfer_buffers, validate_transfer_info, verification_progress,
+};
+use p2p_core::units::format_size;
+use p2p_core::{AppEvent, EventCategory, FileInfo, LogLevel};
 use std::path::PathBuf;
-use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
 use tracing::info;
 
-use crate::protocol::{WanTransferMsg, send_msg};
-
-/// Buffer size for file transfer (16MB)
-const BUFFER_SIZE: usize = 16 * 1024 * 1024;
+use crate::protocol::{WanTransferMsg, recv_msg, send_msg};
 
 /// Receive a single file from the stream
 ///
@@ -19,82 +22,115 @@ const BUFFER_SIZE: usize = 16 * 1024 * 1024;
 /// * `download_dir` - Directory to save received files
 /// * `event_tx` - Channel to send progress events to GUI
 /// * `file_info` - File metadata received from sender
+/// * `preserve_metadata` - Restore the sender's timestamps and permissions
+/// * `peer` - Endpoint ID of the sender, reported on completion
+/// * `storage` - Where the file is written; a file that does not fit is
+///   refused before any data is sent
+#[allow(clippy::too_many_arguments)]
 pub async fn receive_file(
     send: &mut iroh::endpoint::SendStream,
     recv: &mut iroh::endpoint::RecvStream,
     download_dir: &PathBuf,
     event_tx: &mpsc::Sender<AppEvent>,
     mut file_info: FileInfo,
+    preserve_metadata: bool,
+    peer: &str,
+    storage: &dyn Storage,
 ) -> Result<()> {
-    // Security check: Validate file size and name length
-    if let Err(e) = validate_transfer_info(&file_info.file_name, file_info.file_size) {
-        let err_msg = e.to_string();
-        tracing::error!("File validation failed: {}", err_msg);
-        let _ = send_msg(
-            send,
-            &WanTransferMsg::Error {
-                message: err_msg.clone(),
-            },
-        )
-        .await;
-        let _ = event_tx.send(AppEvent::Error(err_msg.clone())).await;
-        return Err(e);
-    }
+    // Security check: Validate file size and name length. Streams of
+    // unknown size only go over the LAN
+    let checked = file_info
+        .file_size
+        .ok_or_else(|| anyhow!("Streams of unknown size are not received over WAN"))
+        .and_then(|size| validate_transfer_info(&file_info.file_name, size).map(|()| size));
+    let file_size = match checked {
+        Ok(size) => size,
+        Err(e) => {
+            let err_msg = e.to_string();
+            tracing::error!("File validation failed: {}", err_msg);
+            let _ = send_msg(
+                send,
+                &WanTransferMsg::Error {
+                    message: err_msg.clone(),
+                },
+            )
+            .await;
+            let _ = event_tx.send(AppEvent::Error(err_msg.clone())).await;
+            return Err(e);
+        }
+    };
 
-    let file_name = sanitize_file_name(&file_info.file_name);
+    let normalized = normalize_file_name(&file_info.file_name, download_dir);
+    for notice in normalized.notices() {
+        let _ = event_tx
+            .send(AppEvent::log(
+                LogLevel::Warning,
+                EventCategory::Transfer,
+                notice,
+            ))
+            .await;
+    }
+    let file_name = normalized.name;
     // Update file_info name with sanitized version
     file_info.file_name = file_name.clone();
-    let file_size = file_info.file_size;
 
     info!("Receiving file: {} ({} bytes)", file_name, file_size);
     let _ = event_tx
-        .send(AppEvent::Status(format!(
-            "Receiving: {} ({} bytes)",
-            file_name, file_size
-        )))
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!("Receiving: {} ({})", file_name, format_size(file_size)),
+        ))
         .await;
 
     tokio::fs::create_dir_all(download_dir).await?;
-    let file_path = download_dir.join(&file_name);
-    let mut offset = 0u64;
-    if file_path.exists() {
-        let metadata = tokio::fs::metadata(&file_path).await?;
-        let current_size = metadata.len();
-        if current_size < file_size {
-            offset = current_size;
-            info!("Resuming from offset: {}", offset);
-        } else if current_size == file_size {
-            info!("File already complete, skipping transfer");
-            send_msg(send, &WanTransferMsg::ResumeInfo { offset: file_size }).await?;
-            send_msg(send, &WanTransferMsg::TransferComplete).await?;
-            let _ = event_tx
-                .send(AppEvent::TransferCompleted(file_name.clone()))
-                .await;
-            return Ok(());
-        } else {
-            info!("File size mismatch, restarting transfer");
-            tokio::fs::remove_file(&file_path).await?;
-            offset = 0;
+    let offer = resume::offer(storage, &download_dir.join(&file_name), &file_info).await?;
+    let part = offer.staged.part().to_path_buf();
+    storage::ensure_space(
+        storage,
+        download_dir,
+        file_size.saturating_sub(offer.offset),
+    )
+    .await?;
+    send_msg(
+        send,
+        &WanTransferMsg::ResumeInfo {
+            offset: offer.offset,
+            prefix_hash: offer.prefix_hash.clone(),
+            token: offer.token.clone(),
+        },
+    )
+    .await?;
+    let offset = match recv_msg(recv).await? {
+        WanTransferMsg::ResumeStart { offset, token } => {
+            resume::accept_start(&offer, offset, &token)?
+        }
+        other => return Err(anyhow::anyhow!("Expected ResumeStart, got {:?}", other)),
+    };
+    if offset == 0 {
+        if offer.offset > 0 {
+            info!("Partial copy of {} differs, restarting transfer", file_name);
         }
+        // WAN senders cannot be asked over the LAN to send again
+        resume::begin(&part, &file_info, &offer.token, None)?;
+    } else if offset == file_size {
+        info!("File already complete, skipping transfer");
+    } else {
+        info!("Resuming from offset: {}", offset);
     }
 
-    send_msg(send, &WanTransferMsg::ResumeInfo { offset }).await?;
-
-    // Use open_secure_file to ensure secure permissions (0o600 on Unix)
-    let mut file = open_secure_file(&file_path, offset).await?;
+    let file = storage::open_at(storage, &part, offset).await?;
+    let mut file = SparseWriter::new(file, offset);
+    file.preallocate(file_size).await?;
+    let mut file = WritePipeline::spawn(file);
 
     let mut received: u64 = offset;
-    let mut buffer = vec![0u8; BUFFER_SIZE];
-    let start_time = std::time::Instant::now();
-    let mut last_progress_update = 0u64;
-
-    report_progress(
-        event_tx, &file_name, received, file_size, start_time, offset, false,
-    )
-    .await;
+    let mut buffer = transfer_buffers().borrow(file_size.saturating_sub(offset));
+    let mut progress = ProgressReporter::new(event_tx, &file_name, file_size, offset, false);
+    progress.update(received).await;
 
     while received < file_size {
-        let to_read = std::cmp::min(BUFFER_SIZE as u64, file_size - received) as usize;
+        let to_read = std::cmp::min(buffer.len() as u64, file_size - received) as usize;
         match recv.read(&mut buffer[..to_read]).await {
             Ok(Some(n)) => {
                 if n == 0 {
@@ -116,16 +152,10 @@ pub async fn receive_file(
                     break;
                 }
 
-                file.write_all(&buffer[..n]).await?;
+                file.write(&buffer[..n]).await?;
                 received += n as u64;
 
-                if received == file_size || received - last_progress_update >= BUFFER_SIZE as u64 {
-                    last_progress_update = received;
-                    report_progress(
-                        event_tx, &file_name, received, file_size, start_time, offset, false,
-                    )
-                    .await;
-                }
+                progress.update(received).await;
             }
             Ok(None) => {
                 if received < file_size {
@@ -160,7 +190,7 @@ pub async fn receive_file(
         }
     }
 
-    file.flush().await?;
+    file.finish().await?;
 
     if received != file_size {
         let err_msg = format!(
@@ -179,8 +209,16 @@ pub async fn receive_file(
     }
 
     info!("File received successfully: {}", file_name);
+    resume::finish(&part).await;
+    let file_path = offer.staged.commit(storage).await?;
 
-    if let Some(expected_hash) = file_info.file_hash {
+    let mut intact = true;
+    if file_info.file_hash.is_some() && file_info.hash_algorithm == HashAlgorithm::Unknown {
+        tracing::warn!(
+            "{} was hashed with an unknown algorithm; not verified",
+            file_name
+        );
+    } else if let Some(expected_hash) = &file_info.file_hash {
         let _ = event_tx
             .send(AppEvent::VerificationStarted {
                 file_name: file_name.clone(),
@@ -188,8 +226,14 @@ pub async fn receive_file(
             })
             .await;
 
-        let computed_hash = p2p_core::transfer::hash::compute_file_hash(&file_path).await?;
-        let verified = computed_hash == expected_hash;
+        let computed_hash = compute_file_hash_with_progress(
+            &file_path,
+            file_info.hash_algorithm,
+            verification_progress(event_tx.clone(), file_name.clone(), false),
+        )
+        .await?;
+        let verified = computed_hash == *expected_hash;
+        intact = verified;
 
         if !verified {
             tracing::error!(
@@ -217,57 +261,19 @@ pub async fn receive_file(
             .await;
     }
 
-    send_msg(send, &WanTransferMsg::TransferComplete).await?;
-
-    let _ = event_tx
-        .send(AppEvent::TransferCompleted(file_name.clone()))
-        .await;
-
-    Ok(())
-}
+    if preserve_metadata && intact {
+        restore_metadata(&file_path, &file_info, event_tx).await;
+    }
 
-/// Report transfer progress to the event channel
-async fn report_progress(
-    event_tx: &mpsc::Sender<AppEvent>,
-    file_name: &str,
-    bytes_done: u64,
-    total_bytes: u64,
-    start_time: std::time::Instant,
-    offset: u64,
-    is_sending: bool,
-) {
-    let progress = (bytes_done as f32 / total_bytes as f32) * 100.0;
-    let elapsed = start_time.elapsed().as_secs_f64();
-    let speed_bps = if elapsed > 0.0 {
-        bytes_done.saturating_sub(offset) as f64 / elapsed
-    } else {
-        0.0
-    };
-    let speed = format_transfer_speed(bytes_done.saturating_sub(offset), elapsed);
+    send_msg(send, &WanTransferMsg::TransferComplete).await?;
 
     let _ = event_tx
-        .send(AppEvent::TransferProgress {
-            file_name: file_name.to_string(),
-            progress,
-            speed,
-            speed_bps,
-            is_sending,
+        .send(AppEvent::TransferCompleted {
+            file_name: file_name.clone(),
+            saved_path: Some(file_path.clone()),
+            peer: Some(peer.to_string()),
         })
         .await;
-}
-
-/// Format transfer speed from bytes and elapsed time
-fn format_transfer_speed(bytes_transferred: u64, elapsed_secs: f64) -> String {
-    if elapsed_secs <= 0.0 {
-        return "Starting...".to_string();
-    }
 
-    let speed_bps = bytes_transferred as f64 / elapsed_secs;
-    if speed_bps > 1_000_000.0 {
-        format!("{:.2} MB/s", speed_bps / 1_000_000.0)
-    } else if speed_bps > 1_000.0 {
-        format!("{:.1} KB/s", speed_bps / 1_000.0)
-    } else {
-        format!("{:.0} B/s", speed_bps)
-    }
+    Ok(())
 }
@@ -0,0 +1,167 @@
+//! File names withheld until the receiver accepts.
+//!
+//! With [`request_approval`] the sender first offers only sizes and opaque
+//! labels: hashes of the names under a salt that never leaves the sender,
+//! so they reveal nothing to the receiver, its logs or anything in between.
+//! Only after the receiver's user accepts are the files sent the usual
+//! way, real names included.
+
+use anyhow::{Result, anyhow};
+use iroh::endpoint::{Connection, RecvStream, SendStream};
+use p2p_core::{AppEvent, EventCategory, LogLevel};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::info;
+
+use crate::protocol::{WanTransferMsg, recv_msg, send_msg};
+
+/// An unanswered offer counts as declined after this long
+pub const OFFER_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A file as offered before approval
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SealedFile {
+    /// `file-` and 8 hex digits of the salted name hash
+    pub label: String,
+    pub size: u64,
+}
+
+/// Label of `name` under `salt`
+pub fn seal_name(salt: &[u8; 32], name: &str) -> String {
+    format!(
+        "file-{}",
+        &blake3::keyed_hash(salt, name.as_bytes()).to_hex()[..8]
+    )
+}
+
+/// Sender side: offer `files` without their names and wait for the answer.
+/// `Ok(false)` means the receiver declined or did not answer in time.
+pub async fn request_approval(connection: &Connection, files: &[PathBuf]) -> Result<bool> {
+    let salt: [u8; 32] = rand::random();
+    let mut sealed = Vec::with_capacity(files.len());
+    for path in files {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| anyhow!("Invalid file path: {}", path.display()))?;
+        sealed.push(SealedFile {
+            label: seal_name(&salt, &name),
+            size: tokio::fs::metadata(path).await?.len(),
+        });
+    }
+
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_msg(&mut send, &WanTransferMsg::SealedOffer { files: sealed }).await?;
+    // A little longer than the receiver waits, so its answer arrives
+    let answer = tokio::time::timeout(OFFER_TIMEOUT + Duration::from_secs(10), recv_msg(&mut recv))
+        .await
+        .map_err(|_| anyhow!("The receiver did not answer"))??;
+    let _ = send.finish();
+    match answer {
+        WanTransferMsg::OfferAnswer { accepted } => Ok(accepted),
+        WanTransferMsg::Error { message } => Err(anyhow!(message)),
+        other => Err(anyhow!("Unexpected answer to an offer: {:?}", other)),
+    }
+}
+
+/// Receiver side: offers waiting for the user, by offer ID
+#[derive(Debug, Default)]
+pub struct PendingOffers {
+    offers: Mutex<HashMap<String, oneshot::Sender<bool>>>,
+}
+
+impl PendingOffers {
+    /// Answer offer `offer_id`; false when it is no longer waiting
+    pub fn respond(&self, offer_id: &str, accepted: bool) -> bool {
+        self.offers()
+            .remove(offer_id)
+            .is_some_and(|tx| tx.send(accepted).is_ok())
+    }
+
+    /// The user's answer to `offer_id`, declined after [`OFFER_TIMEOUT`]
+    async fn decision(&self, offer_id: &str) -> bool {
+        let (tx, rx) = oneshot::channel();
+        self.offers().insert(offer_id.to_string(), tx);
+        let accepted = tokio::time::timeout(OFFER_TIMEOUT, rx).await;
+        self.offers().remove(offer_id);
+        matches!(accepted, Ok(Ok(true)))
+    }
+
+    fn offers(&self) -> std::sync::MutexGuard<'_, HashMap<String, oneshot::Sender<bool>>> {
+        self.offers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Receiver side: ask the user about a [`SealedOffer`](WanTransferMsg::SealedOffer)
+/// from `peer` and send back the decision
+pub(crate) async fn answer_offer(
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    files: Vec<SealedFile>,
+    peer: String,
+    pending: &PendingOffers,
+    event_tx: &mpsc::Sender<AppEvent>,
+) -> Result<()> {
+    let offer_id = p2p_core::new_session_id();
+    let total_size = files.iter().map(|file| file.size).sum();
+    info!("{} offers {} files with hidden names", peer, files.len());
+    let _ = event_tx
+        .send(AppEvent::WanOffer {
+            offer_id: offer_id.clone(),
+            peer: peer.clone(),
+            labels: files.into_iter().map(|file| file.label).collect(),
+            total_size,
+        })
+        .await;
+
+    let accepted = pending.decision(&offer_id).await;
+    if !accepted {
+        let _ = event_tx
+            .send(AppEvent::log(
+                LogLevel::Info,
+                EventCategory::Wan,
+                format!("Declined files from {}", peer),
+            ))
+            .await;
+    }
+    send_msg(send, &WanTransferMsg::OfferAnswer { accepted }).await?;
+    send.finish()?;
+    // Until the sender is done reading the answer
+    let _ = recv.read_to_end(0).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels_hide_names() {
+        let (salt, other_salt) = ([1u8; 32], [2u8; 32]);
+        let label = seal_name(&salt, "tax-return-2024.pdf");
+        assert!(label.starts_with("file-") && label.len() == 13);
+        assert!(!label.contains("tax"));
+        assert_eq!(label, seal_name(&salt, "tax-return-2024.pdf"));
+        assert_ne!(label, seal_name(&other_salt, "tax-return-2024.pdf"));
+        assert_ne!(label, seal_name(&salt, "tax-return-2023.pdf"));
+    }
+
+    #[tokio::test]
+    async fn test_offer_waits_for_one_answer() {
+        let pending = std::sync::Arc::new(PendingOffers::default());
+        let waiting = pending.clone();
+        let decision = tokio::spawn(async move { waiting.decision("offer").await });
+        while !pending.offers().contains_key("offer") {
+            tokio::task::yield_now().await;
+        }
+        assert!(!pending.respond("other", true));
+        assert!(pending.respond("offer", true));
+        assert!(!pending.respond("offer", false));
+        assert!(decision.await.unwrap());
+    }
+}
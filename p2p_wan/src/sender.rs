@@ -1,26 +1,49 @@
 use anyhow::{Result, anyhow};
 use iroh::endpoint::Connection;
-use p2p_core::{AppEvent, FileInfo};
+use p2p_core::config::WanStrategy;
+use p2p_core::transfer::{
+    ProgressReporter, SecurityInfo, compute_file_hash_with_progress, hash_algorithm, resume,
+    transfer_buffers, verification_progress,
+};
+use p2p_core::units::format_size;
+use p2p_core::{AppEvent, EventCategory, FileInfo, LogLevel};
 use std::path::PathBuf;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::mpsc;
 use tracing::{error, info};
 
+use crate::close::explain;
 use crate::protocol::{WanTransferMsg, recv_msg, send_msg};
 
-/// Buffer size for file transfer (16MB)
-const BUFFER_SIZE: usize = 16 * 1024 * 1024;
+/// Send files to a connected peer the way `strategy` says
+pub async fn send_with_strategy(
+    connection: &Connection,
+    files: Vec<PathBuf>,
+    strategy: WanStrategy,
+    security: SecurityInfo,
+    event_tx: mpsc::Sender<AppEvent>,
+) -> Result<()> {
+    match strategy {
+        WanStrategy::Stream => send_files(connection, files, security, event_tx).await,
+        WanStrategy::Blobs => {
+            crate::blobs::send_collection(connection, files, security, event_tx).await
+        }
+    }
+}
 
 /// Send files to a connected peer over WAN
 ///
 /// # Arguments
 /// * `connection` - Established iroh connection to the peer
 /// * `files` - List of file paths to send
+/// * `security` - Connection details reported for each file, see
+///   [`security_info`](crate::listener::security_info)
 /// * `event_tx` - Channel to send progress events to GUI
 pub async fn send_files(
     connection: &Connection,
     files: Vec<PathBuf>,
+    security: SecurityInfo,
     event_tx: mpsc::Sender<AppEvent>,
 ) -> Result<()> {
     let peer_id = connection.remote_id();
@@ -32,15 +55,16 @@ pub async fn send_files(
         let connection = connection.clone();
         let file_path = file_path.clone();
         let event_tx = event_tx.clone();
+        let security = security.clone();
 
         let handle = tokio::spawn(async move {
-            if let Err(e) = send_single_file(&connection, &file_path, &event_tx).await {
+            if let Err(e) = send_single_file(&connection, &file_path, security, &event_tx).await {
                 error!("Error sending {}: {}", file_path.display(), e);
                 let _ = event_tx
                     .send(AppEvent::Error(format!(
                         "Error sending {}: {}",
                         file_path.display(),
-                        e
+                        explain(&e)
                     )))
                     .await;
             }
@@ -63,10 +87,17 @@ pub async fn send_files(
 async fn send_single_file(
     connection: &Connection,
     file_path: &PathBuf,
+    security: SecurityInfo,
     event_tx: &mpsc::Sender<AppEvent>,
 ) -> Result<()> {
     let mut file = File::open(file_path).await?;
     let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Err(anyhow!(
+            "{} is not a regular file; streams are sent over the LAN only",
+            file_path.display()
+        ));
+    }
     let file_size = metadata.len();
     let file_name = file_path
         .file_name()
@@ -76,10 +107,18 @@ async fn send_single_file(
 
     info!("Sending file: {} ({} bytes)", file_name, file_size);
     let _ = event_tx
-        .send(AppEvent::Status(format!(
-            "Sending: {} ({} bytes)",
-            file_name, file_size
-        )))
+        .send(AppEvent::SecurityInfo {
+            file_name: file_name.clone(),
+            is_sending: true,
+            security,
+        })
+        .await;
+    let _ = event_tx
+        .send(AppEvent::log(
+            LogLevel::Info,
+            EventCategory::Transfer,
+            format!("Sending: {} ({})", file_name, format_size(file_size)),
+        ))
         .await;
 
     let _ = event_tx
@@ -89,7 +128,13 @@ async fn send_single_file(
         })
         .await;
 
-    let file_hash = p2p_core::transfer::hash::compute_file_hash(file_path).await?;
+    let algorithm = hash_algorithm();
+    let file_hash = compute_file_hash_with_progress(
+        file_path,
+        algorithm,
+        verification_progress(event_tx.clone(), file_name.clone(), true),
+    )
+    .await?;
     info!("Computed hash for {}: {}", file_name, &file_hash[..16]);
 
     let _ = event_tx
@@ -104,10 +149,16 @@ async fn send_single_file(
 
     let file_info = FileInfo {
         file_name: file_name.clone(),
-        file_size,
+        file_size: Some(file_size),
         file_path: PathBuf::new(),
         file_hash: Some(file_hash),
-    };
+        hash_algorithm: algorithm,
+        modified: None,
+        mode: None,
+        note: None,
+        xattrs: Vec::new(),
+    }
+    .with_source_metadata(&metadata);
 
     send_msg(
         &mut send_stream,
@@ -116,13 +167,30 @@ async fn send_single_file(
     .await?;
 
     let msg = recv_msg(&mut recv_stream).await?;
-    let offset = match msg {
-        WanTransferMsg::ResumeInfo { offset } => offset,
+    let (offered, prefix_hash, token) = match msg {
+        WanTransferMsg::ResumeInfo {
+            offset,
+            prefix_hash,
+            token,
+        } => (offset, prefix_hash, token),
         WanTransferMsg::Error { message } => {
             return Err(anyhow!("Receiver error: {}", message));
         }
         _ => return Err(anyhow!("Expected ResumeInfo, got {:?}", msg)),
     };
+    let offset =
+        resume::start_offset(file_path, file_size, offered, prefix_hash.as_deref()).await?;
+    if offset < offered {
+        info!(
+            "Receiver's partial copy of {} differs, sending it again",
+            file_name
+        );
+    }
+    send_msg(
+        &mut send_stream,
+        &WanTransferMsg::ResumeStart { offset, token },
+    )
+    .await?;
 
     if offset > 0 {
         info!("Resuming transfer from offset: {}", offset);
@@ -130,32 +198,27 @@ async fn send_single_file(
     }
 
     let mut sent: u64 = offset;
-    let mut buffer = vec![0u8; BUFFER_SIZE];
-    let start_time = std::time::Instant::now();
-    let mut last_progress_update = 0u64;
-
-    // Report initial progress
-    report_progress(
-        event_tx, &file_name, sent, file_size, start_time, offset, true,
-    )
-    .await;
-
-    loop {
-        let n = file.read(&mut buffer).await?;
+    let mut buffer = transfer_buffers().borrow(file_size.saturating_sub(offset));
+    let mut progress = ProgressReporter::new(event_tx, &file_name, file_size, offset, true);
+    progress.update(sent).await;
+
+    // Send exactly the announced size, even if the file changes meanwhile
+    while sent < file_size {
+        let to_read = std::cmp::min(buffer.len() as u64, file_size - sent) as usize;
+        let n = p2p_core::profile_future!("read", file.read(&mut buffer[..to_read])).await?;
         if n == 0 {
-            break;
+            return Err(anyhow!(
+                "{} shrank during transfer ({}/{} bytes)",
+                file_name,
+                sent,
+                file_size
+            ));
         }
 
         send_stream.write_all(&buffer[..n]).await?;
         sent += n as u64;
 
-        if sent == file_size || sent - last_progress_update >= BUFFER_SIZE as u64 {
-            last_progress_update = sent;
-            report_progress(
-                event_tx, &file_name, sent, file_size, start_time, offset, true,
-            )
-            .await;
-        }
+        progress.update(sent).await;
     }
 
     send_stream.finish()?;
@@ -175,52 +238,12 @@ async fn send_single_file(
         }
     }
 
-    let _ = event_tx.send(AppEvent::TransferCompleted(file_name)).await;
-    Ok(())
-}
-
-/// Report transfer progress to the event channel
-async fn report_progress(
-    event_tx: &mpsc::Sender<AppEvent>,
-    file_name: &str,
-    bytes_done: u64,
-    total_bytes: u64,
-    start_time: std::time::Instant,
-    offset: u64,
-    is_sending: bool,
-) {
-    let progress = (bytes_done as f32 / total_bytes as f32) * 100.0;
-    let elapsed = start_time.elapsed().as_secs_f64();
-    let speed_bps = if elapsed > 0.0 {
-        bytes_done.saturating_sub(offset) as f64 / elapsed
-    } else {
-        0.0
-    };
-    let speed = format_transfer_speed(bytes_done.saturating_sub(offset), elapsed);
-
     let _ = event_tx
-        .send(AppEvent::TransferProgress {
-            file_name: file_name.to_string(),
-            progress,
-            speed,
-            speed_bps,
-            is_sending,
+        .send(AppEvent::TransferCompleted {
+            file_name,
+            saved_path: None,
+            peer: None,
         })
         .await;
-}
-
-/// Format transfer speed from bytes and elapsed time
-fn format_transfer_speed(bytes_transferred: u64, elapsed_secs: f64) -> String {
-    if elapsed_secs <= 0.0 {
-        return "Starting...".to_string();
-    }
-
-    let speed_bps = bytes_transferred as f64 / elapsed_secs;
-    if speed_bps > 1_000_000.0 {
-        format!("{:.2} MB/s", speed_bps / 1_000_000.0)
-    } else if speed_bps > 1_000.0 {
-        format!("{:.1} KB/s", speed_bps / 1_000.0)
-    } else {
-        format!("{:.0} B/s", speed_bps)
-    }
+    Ok(())
 }
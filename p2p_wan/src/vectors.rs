@@ -0,0 +1,120 @@
+//! Golden encodings of [`WanTransferMsg`](crate::protocol::WanTransferMsg).
+//!
+//! Framed like the LAN messages, see [`p2p_core::transfer::vectors`]: a
+//! 4-byte big-endian length, then the JSON body held here.
+
+pub use p2p_core::transfer::vectors::TestVector;
+
+/// Golden [`WanTransferMsg`](crate::protocol::WanTransferMsg) bodies
+pub const VECTORS: &[TestVector] = &[
+    TestVector {
+        name: "FileMetadata",
+        body: br#"{"FileMetadata":{"info":{"file_name":"report.pdf","file_size":2048,"file_hash":"af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262","note":"for review"}}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "ResumeInfo",
+        body: br#"{"ResumeInfo":{"offset":0,"prefix_hash":null,"token":"9d2e4b7a"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "ResumeStart",
+        body: br#"{"ResumeStart":{"offset":1024,"token":"9d2e4b7a"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "TransferComplete",
+        body: br#""TransferComplete""#,
+        canonical: true,
+    },
+    TestVector {
+        name: "Error",
+        body: br#"{"Error":{"message":"Disk full"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "BenchmarkStart",
+        body: br#"{"BenchmarkStart":{"data_size":104857600}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "BenchmarkComplete",
+        body: br#"{"BenchmarkComplete":{"elapsed_ms":1500}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "Ping",
+        body: br#"{"Ping":{"seq":7}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "Pong",
+        body: br#"{"Pong":{"seq":7}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "Collection",
+        body: br#"{"Collection":{"entries":[{"name":"a.txt","size":3,"hash":"af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"}]}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "WantChunks",
+        body: br#"{"WantChunks":{"total":16}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "LinkHello",
+        body: br#"{"LinkHello":{"name":"Phone","proof":"c0ffee"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "LinkWelcome",
+        body: br#"{"LinkWelcome":{"name":"Desktop","proof":"beef"}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "SealedOffer",
+        body: br#"{"SealedOffer":{"files":[{"label":"file-1a2b3c4d","size":512}]}}"#,
+        canonical: true,
+    },
+    TestVector {
+        name: "OfferAnswer",
+        body: br#"{"OfferAnswer":{"accepted":false}}"#,
+        canonical: true,
+    },
+    // Without the optional prefix hash of older senders
+    TestVector {
+        name: "ResumeInfo",
+        body: br#"{"ResumeInfo":{"offset":0,"token":"9d2e4b7a"}}"#,
+        canonical: false,
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::decode_msg;
+
+    #[test]
+    fn test_vectors_decode_and_canonical_ones_encode_identically() {
+        for vector in VECTORS {
+            let body = String::from_utf8_lossy(vector.body);
+            let msg = decode_msg(vector.body)
+                .unwrap_or_else(|e| panic!("{} does not decode: {}", body, e));
+            assert!(
+                format!("{:?}", msg).starts_with(vector.name),
+                "{} decoded to {:?}",
+                body,
+                msg
+            );
+            if vector.canonical {
+                assert_eq!(
+                    String::from_utf8(serde_json::to_vec(&msg).unwrap()).unwrap(),
+                    body,
+                    "{} no longer encodes the same",
+                    vector.name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use iroh::{Endpoint, SecretKey};
+use p2p_core::AppEvent;
+use p2p_core::alpn::AppId;
+use p2p_wan::ConnectionListener;
+use p2p_wan::blobs::{CHUNK_SIZE, send_collection};
+use p2p_wan::listener::security_info;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// The "n of m chunks needed" part of the receiver's collection log
+fn chunks_needed(events: &mut mpsc::Receiver<AppEvent>) -> Option<String> {
+    let mut needed = None;
+    while let Ok(event) = events.try_recv() {
+        if let AppEvent::Log { message, .. } = event
+            && let Some((_, counts)) = message.split_once("files: ")
+        {
+            needed = Some(counts.to_string());
+        }
+    }
+    needed
+}
+
+#[tokio::test]
+async fn test_collection_only_sends_missing_chunks() -> Result<()> {
+    let source_dir = tempfile::tempdir()?;
+    let download_dir = tempfile::tempdir()?;
+    let (event_tx, mut events) = mpsc::channel(10_000);
+    let listener = Arc::new(
+        ConnectionListener::new(
+            SecretKey::generate(&mut rand::rng()),
+            download_dir.path().to_path_buf(),
+            event_tx,
+            None,
+        )
+        .await?,
+    );
+    let listening = listener.clone();
+    tokio::spawn(async move { listening.listen().await });
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let connector = Endpoint::builder()
+        .secret_key(SecretKey::generate(&mut rand::rng()))
+        .alpns(vec![AppId::default().wan_alpn()])
+        .bind()
+        .await?;
+    let connection = connector
+        .connect(listener.node_addr(), &AppId::default().wan_alpn())
+        .await?;
+
+    // Three distinct chunks and a short tail; the copy repeats them all
+    let mut data: Vec<u8> = (0..3 * CHUNK_SIZE + 100)
+        .map(|i| (i / CHUNK_SIZE) as u8)
+        .collect();
+    let original = source_dir.path().join("data.bin");
+    let copy = source_dir.path().join("copy.bin");
+    std::fs::write(&original, &data)?;
+    std::fs::write(&copy, &data)?;
+
+    let (sender_tx, _sender_events) = mpsc::channel(10_000);
+    let security = security_info(&connector, connection.remote_id());
+    send_collection(
+        &connection,
+        vec![original.clone(), copy.clone()],
+        security.clone(),
+        sender_tx.clone(),
+    )
+    .await?;
+    assert_eq!(std::fs::read(download_dir.path().join("data.bin"))?, data);
+    assert_eq!(std::fs::read(download_dir.path().join("copy.bin"))?, data);
+    assert!(!download_dir.path().join("data.bin.part").exists());
+    // The copy's chunks were all taken from the first file
+    assert_eq!(
+        chunks_needed(&mut events).as_deref(),
+        Some("4 of 8 chunks needed")
+    );
+
+    // Change one chunk: only it crosses the connection again
+    data[CHUNK_SIZE as usize + 7] = 0xff;
+    std::fs::write(&original, &data)?;
+    send_collection(&connection, vec![original], security, sender_tx).await?;
+    assert_eq!(std::fs::read(download_dir.path().join("data.bin"))?, data);
+    assert_eq!(
+        chunks_needed(&mut events).as_deref(),
+        Some("1 of 4 chunks needed")
+    );
+
+    connection.close(0u8.into(), b"done");
+    connector.close().await;
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use anyhow::Result;
+use iroh::{Endpoint, SecretKey};
+use p2p_core::alpn::AppId;
+use p2p_wan::ConnectionListener;
+use p2p_wan::heartbeat::run_heartbeat;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+async fn connector() -> Result<Endpoint> {
+    Ok(Endpoint::builder()
+        .secret_key(SecretKey::generate(&mut rand::rng()))
+        .alpns(vec![AppId::default().wan_alpn()])
+        .bind()
+        .await?)
+}
+
+#[tokio::test]
+async fn test_listener_answers_heartbeats() -> Result<()> {
+    let download_dir = tempfile::tempdir()?;
+    let (event_tx, _event_rx) = mpsc::channel(100);
+    let listener = std::sync::Arc::new(
+        ConnectionListener::new(
+            SecretKey::generate(&mut rand::rng()),
+            download_dir.path().to_path_buf(),
+            event_tx,
+            None,
+        )
+        .await?,
+    );
+    let listening = listener.clone();
+    tokio::spawn(async move { listening.listen().await });
+    // Let the listener reach its relay before dialing
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let connector = connector().await?;
+    let connection = connector
+        .connect(listener.node_addr(), &AppId::default().wan_alpn())
+        .await?;
+
+    let (pong_tx, mut pong_rx) = mpsc::unbounded_channel();
+    let heartbeat_connection = connection.clone();
+    let heartbeat = tokio::spawn(async move {
+        run_heartbeat(
+            &heartbeat_connection,
+            Duration::from_millis(100),
+            |latency| {
+                let _ = pong_tx.send(latency);
+            },
+        )
+        .await
+    });
+
+    for _ in 0..3 {
+        let latency = tokio::time::timeout(Duration::from_secs(5), pong_rx.recv())
+            .await?
+            .expect("heartbeat stopped");
+        assert!(latency < Duration::from_secs(1));
+    }
+
+    // Closing the connection ends the heartbeat quietly
+    connection.close(0u8.into(), b"done");
+    tokio::time::timeout(Duration::from_secs(5), heartbeat).await???;
+    connector.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_silent_peer_is_declared_dead() -> Result<()> {
+    let silent = connector().await?;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let silent_addr = silent.addr();
+    // Accepts the heartbeat stream but never answers
+    let accept = tokio::spawn(async move {
+        let connection = silent.accept().await.unwrap().await.unwrap();
+        let streams = connection.accept_bi().await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        drop(streams);
+    });
+
+    let connector = connector().await?;
+    let connection = connector
+        .connect(silent_addr, &AppId::default().wan_alpn())
+        .await?;
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        run_heartbeat(&connection, Duration::from_millis(100), |_| {
+            panic!("a silent peer cannot answer")
+        }),
+    )
+    .await?;
+
+    assert!(result.is_err());
+    assert!(connection.close_reason().is_some());
+    accept.abort();
+    connector.close().await;
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use anyhow::Result;
+use iroh::SecretKey;
+use p2p_core::alpn::AppId;
+use p2p_core::pairing::phrase::LinkPhrase;
+use p2p_core::pairing::{MemoryPairingStore, PairingStore};
+use p2p_core::proxy::ProxySettings;
+use p2p_wan::ConnectionListener;
+use p2p_wan::link::{find_host, join_link};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+async fn linking_listener(
+    name: &str,
+    pairings: Arc<MemoryPairingStore>,
+) -> Result<Arc<ConnectionListener>> {
+    let (event_tx, _events) = mpsc::channel(100);
+    let listener = ConnectionListener::new(
+        SecretKey::generate(&mut rand::rng()),
+        std::env::temp_dir(),
+        event_tx,
+        None,
+    )
+    .await?
+    .with_linking(pairings, name.to_string());
+    Ok(Arc::new(listener))
+}
+
+#[tokio::test]
+async fn test_phrase_links_both_ways() -> Result<()> {
+    let server = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}", server.local_addr()?);
+    tokio::spawn(p2p_rendezvous::serve(server));
+    let proxy = ProxySettings::default();
+
+    let host_pairings = Arc::new(MemoryPairingStore::default());
+    let host = linking_listener("Desktop", host_pairings.clone()).await?;
+    let listening = host.clone();
+    tokio::spawn(async move { listening.listen().await });
+    let joiner_pairings = Arc::new(MemoryPairingStore::default());
+    let joiner = linking_listener("Laptop", joiner_pairings.clone()).await?;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let phrase = host.offer_link(&url, &proxy).await?;
+    let typed = LinkPhrase::parse(&phrase.to_string().to_uppercase())?;
+    assert_eq!(
+        find_host(&typed, &url, &proxy, joiner.node_id()).await?,
+        host.node_id()
+    );
+
+    // A wrong phrase is refused and does not use up the real one
+    let wrong = LinkPhrase::generate();
+    assert!(
+        join_link(
+            joiner.endpoint(),
+            &AppId::default(),
+            host.node_addr(),
+            &wrong,
+            "Laptop",
+            joiner_pairings.as_ref(),
+        )
+        .await
+        .is_err()
+    );
+    assert!(host_pairings.get_all_pairings().is_empty());
+
+    let linked = join_link(
+        joiner.endpoint(),
+        &AppId::default(),
+        host.node_addr(),
+        &typed,
+        "Laptop",
+        joiner_pairings.as_ref(),
+    )
+    .await?;
+    assert_eq!(linked.endpoint_id, host.node_id());
+    assert_eq!(linked.name, "Desktop");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let host_id = host.node_id().to_string();
+    let joiner_id = joiner.node_id().to_string();
+    assert_eq!(
+        host_pairings.get_all_pairings(),
+        [(joiner_id.clone(), "Laptop".to_string())]
+    );
+    let key = host_pairings.pair_key(&joiner_id).unwrap();
+    assert_eq!(joiner_pairings.pair_key(&host_id), Some(key.clone()));
+    let key_id = p2p_core::pairing::key::key_id(&key);
+    assert_eq!(joiner_pairings.receiver_key(&key_id), Some(key.clone()));
+    assert_eq!(host_pairings.receiver_key(&key_id), Some(key));
+
+    // The phrase worked once
+    assert!(
+        join_link(
+            joiner.endpoint(),
+            &AppId::default(),
+            host.node_addr(),
+            &typed,
+            "Laptop",
+            joiner_pairings.as_ref(),
+        )
+        .await
+        .is_err()
+    );
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use iroh::SecretKey;
+use p2p_wan::{PasteSource, detect_endpoint_id};
+
+fn random_id() -> iroh::EndpointId {
+    SecretKey::generate(&mut rand::rng()).public()
+}
+
+#[test]
+fn test_bare_endpoint_id() {
+    let id = random_id();
+    let detected = detect_endpoint_id(&format!("  {}\n", id)).unwrap();
+    assert_eq!(detected.endpoint_id, id);
+    assert_eq!(detected.source, PasteSource::EndpointId);
+}
+
+#[test]
+fn test_id_inside_url_or_message() {
+    let id = random_id();
+    for text in [
+        format!("https://example.com/connect/{}?via=chat", id),
+        format!("iroh://{}", id),
+        format!("Connect to me: {} (laptop)", id),
+    ] {
+        let detected = detect_endpoint_id(&text).unwrap();
+        assert_eq!(detected.endpoint_id, id, "{}", text);
+        assert_eq!(detected.source, PasteSource::Embedded);
+    }
+}
+
+#[test]
+fn test_ignores_other_text() {
+    assert!(detect_endpoint_id("").is_none());
+    assert!(detect_endpoint_id("https://example.com/share?token=abc").is_none());
+    // Right length, but not a valid key
+    assert!(detect_endpoint_id(&"z".repeat(64)).is_none());
+    // Huge clipboard contents are skipped
+    let id = random_id();
+    assert!(detect_endpoint_id(&format!("{}{}", "x ".repeat(4096), id)).is_none());
+}
@@ -0,0 +1,50 @@
+//! The protocol documentation under `docs/` must match the message types.
+//! After changing a message, regenerate it with
+//! `UPDATE_PROTOCOL_DOCS=1 cargo test -p p2p_wan --test protocol_doc`, and
+//! bump `PROTOCOL_VERSION` if older builds can no longer understand it.
+
+use std::path::PathBuf;
+
+fn docs_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../docs")
+}
+
+fn check(file: &str, generated: &str) {
+    let path = docs_dir().join(file);
+    if std::env::var_os("UPDATE_PROTOCOL_DOCS").is_some() {
+        std::fs::create_dir_all(docs_dir()).unwrap();
+        std::fs::write(&path, generated).unwrap();
+        return;
+    }
+    let committed = std::fs::read_to_string(&path)
+        .unwrap_or_default()
+        .replace("\r\n", "\n");
+    assert!(
+        committed == generated,
+        "{} is out of date; regenerate it with \
+         UPDATE_PROTOCOL_DOCS=1 cargo test -p p2p_wan --test protocol_doc",
+        path.display()
+    );
+}
+
+#[test]
+fn test_protocol_docs_are_up_to_date() {
+    let doc = p2p_wan::protocol::describe();
+    check("protocol.md", &doc.to_markdown());
+    check("protocol.json", &doc.to_json());
+}
+
+#[test]
+fn test_every_channel_is_described() {
+    let doc = p2p_wan::protocol::describe();
+    let names: Vec<_> = doc.channels.iter().map(|channel| &channel.name).collect();
+    assert_eq!(names.len(), 4);
+    let md = doc.to_markdown();
+    for vector in p2p_wan::vectors::VECTORS {
+        assert!(
+            md.contains(&format!("### `{}`", vector.name)),
+            "{}",
+            vector.name
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use p2p_wan::blobs::BlobEntry;
+use p2p_wan::protocol::{WanTransferMsg, decode_msg};
+use proptest::prelude::*;
+
+fn wan_msg() -> impl Strategy<Value = WanTransferMsg> {
+    prop_oneof![
+        (
+            any::<u64>(),
+            proptest::option::of("[0-9a-f]{64}"),
+            "[0-9a-f]{32}"
+        )
+            .prop_map(|(offset, prefix_hash, token)| WanTransferMsg::ResumeInfo {
+                offset,
+                prefix_hash,
+                token,
+            }),
+        (any::<u64>(), "[0-9a-f]{32}")
+            .prop_map(|(offset, token)| WanTransferMsg::ResumeStart { offset, token }),
+        Just(WanTransferMsg::TransferComplete),
+        any::<String>().prop_map(|message| WanTransferMsg::Error { message }),
+        any::<u64>().prop_map(|data_size| WanTransferMsg::BenchmarkStart { data_size }),
+        any::<u64>().prop_map(|elapsed_ms| WanTransferMsg::BenchmarkComplete { elapsed_ms }),
+        any::<u64>().prop_map(|seq| WanTransferMsg::Ping { seq }),
+        any::<u64>().prop_map(|seq| WanTransferMsg::Pong { seq }),
+        proptest::collection::vec((any::<String>(), any::<u64>(), "[0-9a-f]{64}"), 0..4).prop_map(
+            |entries| WanTransferMsg::Collection {
+                entries: entries
+                    .into_iter()
+                    .map(|(name, size, hash)| BlobEntry { name, size, hash })
+                    .collect(),
+            }
+        ),
+        any::<u64>().prop_map(|total| WanTransferMsg::WantChunks { total }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn decode_arbitrary_bytes_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let _ = decode_msg(&bytes);
+    }
+
+    #[test]
+    fn wan_msg_round_trips(msg in wan_msg()) {
+        let json = serde_json::to_vec(&msg).unwrap();
+        let decoded = decode_msg(&json).unwrap();
+        prop_assert_eq!(format!("{:?}", decoded), format!("{:?}", msg));
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use iroh::{Endpoint, SecretKey};
+use p2p_core::AppEvent;
+use p2p_core::alpn::AppId;
+use p2p_wan::ConnectionListener;
+use p2p_wan::sealed::request_approval;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+async fn next_offer(events: &mut mpsc::Receiver<AppEvent>) -> (String, Vec<String>, u64) {
+    loop {
+        if let Some(AppEvent::WanOffer {
+            offer_id,
+            labels,
+            total_size,
+            ..
+        }) = events.recv().await
+        {
+            return (offer_id, labels, total_size);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_names_stay_hidden_until_accepted() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let (event_tx, mut events) = mpsc::channel(1000);
+    let listener = Arc::new(
+        ConnectionListener::new(
+            SecretKey::generate(&mut rand::rng()),
+            dir.path().join("downloads"),
+            event_tx,
+            None,
+        )
+        .await?,
+    );
+    let listening = listener.clone();
+    tokio::spawn(async move { listening.listen().await });
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let connector = Endpoint::builder()
+        .secret_key(SecretKey::generate(&mut rand::rng()))
+        .alpns(vec![AppId::default().wan_alpn()])
+        .bind()
+        .await?;
+    let connection = connector
+        .connect(listener.node_addr(), &AppId::default().wan_alpn())
+        .await?;
+
+    let file = dir.path().join("medical-report.pdf");
+    std::fs::write(&file, vec![0u8; 1234])?;
+    let files = vec![file];
+
+    let offer = tokio::spawn({
+        let (connection, files) = (connection.clone(), files.clone());
+        async move { request_approval(&connection, &files).await }
+    });
+    let (offer_id, labels, total_size) = next_offer(&mut events).await;
+    assert_eq!(total_size, 1234);
+    assert_eq!(labels.len(), 1);
+    assert!(!labels[0].contains("medical"));
+    assert!(listener.respond_offer(&offer_id, true));
+    assert!(offer.await??);
+
+    let offer = tokio::spawn({
+        let (connection, files) = (connection.clone(), files.clone());
+        async move { request_approval(&connection, &files).await }
+    });
+    let (offer_id, second_labels, _) = next_offer(&mut events).await;
+    // A fresh salt per offer: repeated offers cannot be matched up
+    assert_ne!(second_labels, labels);
+    assert!(listener.respond_offer(&offer_id, false));
+    assert!(!offer.await??);
+    assert!(!listener.respond_offer(&offer_id, true));
+
+    connection.close(0u8.into(), b"done");
+    connector.close().await;
+    Ok(())
+}
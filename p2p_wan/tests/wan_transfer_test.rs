@@ -1,6 +1,7 @@
 use anyhow::Result;
 use iroh::{Endpoint, EndpointId, SecretKey};
 use p2p_core::AppEvent;
+use p2p_core::alpn::AppId;
 use std::path::PathBuf;
 use std::str::FromStr;
 use tokio::sync::mpsc;
@@ -8,8 +9,10 @@ use tokio::sync::mpsc;
 /// Target EndpointID of the running listener (provided by user)
 const TARGET_ENDPOINT_ID: &str = "ddfadcc8a77b75372141e7dcaa3bdace906a65b7f7036fd472bb5d5b611febf6";
 
-/// ALPN protocol identifier - must match p2p_wan::protocol::ALPN
-const ALPN: &[u8] = b"doanltm-p2p";
+/// ALPN of WAN transfers, as the listener uses it
+fn alpn() -> Vec<u8> {
+    AppId::default().wan_alpn()
+}
 
 /// Test file size: 100MB
 const TEST_FILE_SIZE: usize = 100 * 1024 * 1024;
@@ -36,7 +39,7 @@ async fn test_wan_connection_to_remote_endpoint() -> Result<()> {
     // Build endpoint
     let endpoint = Endpoint::builder()
         .secret_key(secret_key)
-        .alpns(vec![ALPN.to_vec()])
+        .alpns(vec![alpn()])
         .bind()
         .await?;
 
@@ -46,7 +49,7 @@ async fn test_wan_connection_to_remote_endpoint() -> Result<()> {
     println!("Connecting to target...");
     let start = std::time::Instant::now();
 
-    let connection = endpoint.connect(target_id, ALPN).await?;
+    let connection = endpoint.connect(target_id, &alpn()).await?;
 
     let elapsed = start.elapsed();
     println!("✓ Connected in {:?}", elapsed);
@@ -97,7 +100,7 @@ async fn test_wan_file_transfer_to_remote_endpoint() -> Result<()> {
     let secret_key = SecretKey::generate(&mut rand::rng());
     let endpoint = Endpoint::builder()
         .secret_key(secret_key)
-        .alpns(vec![ALPN.to_vec()])
+        .alpns(vec![alpn()])
         .bind()
         .await?;
 
@@ -106,7 +109,7 @@ async fn test_wan_file_transfer_to_remote_endpoint() -> Result<()> {
     // Connect
     println!("Connecting to target...");
     let start = std::time::Instant::now();
-    let connection = endpoint.connect(target_id, ALPN).await?;
+    let connection = endpoint.connect(target_id, &alpn()).await?;
     println!("✓ Connected in {:?}", start.elapsed());
 
     // Setup event channel
@@ -124,7 +127,7 @@ async fn test_wan_file_transfer_to_remote_endpoint() -> Result<()> {
                 } => {
                     println!("  Progress: {} - {:.1}% @ {}", file_name, progress, speed);
                 }
-                AppEvent::TransferCompleted(file_name) => {
+                AppEvent::TransferCompleted { file_name, .. } => {
                     println!("✓ Transfer completed: {}", file_name);
                     break;
                 }
@@ -140,7 +143,8 @@ async fn test_wan_file_transfer_to_remote_endpoint() -> Result<()> {
     // Send file
     println!("Sending 100MB file...");
     let transfer_start = std::time::Instant::now();
-    send_files(&connection, vec![file_path], event_tx).await?;
+    let security = p2p_wan::listener::security_info(&endpoint, target_id);
+    send_files(&connection, vec![file_path], security, event_tx).await?;
 
     let transfer_elapsed = transfer_start.elapsed();
     let speed_mbps = (TEST_FILE_SIZE as f64 / transfer_elapsed.as_secs_f64()) / 1_000_000.0;
@@ -172,7 +176,7 @@ async fn test_local_endpoint_pair() -> Result<()> {
     let listener_key = SecretKey::generate(&mut rand::rng());
     let listener = Endpoint::builder()
         .secret_key(listener_key)
-        .alpns(vec![ALPN.to_vec()])
+        .alpns(vec![alpn()])
         .bind()
         .await?;
     let listener_id = listener.id();
@@ -190,7 +194,7 @@ async fn test_local_endpoint_pair() -> Result<()> {
     let connector_key = SecretKey::generate(&mut rand::rng());
     let connector = Endpoint::builder()
         .secret_key(connector_key)
-        .alpns(vec![ALPN.to_vec()])
+        .alpns(vec![alpn()])
         .bind()
         .await?;
     println!("Connector EndpointId: {}", connector.id());
@@ -212,7 +216,15 @@ async fn test_local_endpoint_pair() -> Result<()> {
             println!("Listener: Received message: {:?}", msg);
 
             // Send response
-            send_msg(&mut send, &WanTransferMsg::ResumeInfo { offset: 0 }).await?;
+            send_msg(
+                &mut send,
+                &WanTransferMsg::ResumeInfo {
+                    offset: 0,
+                    prefix_hash: None,
+                    token: "t".to_string(),
+                },
+            )
+            .await?;
             println!("Listener: Sent ResumeInfo");
 
             // Wait for stream to close
@@ -227,7 +239,7 @@ async fn test_local_endpoint_pair() -> Result<()> {
 
     // Connect using full EndpointAddr (includes relay URL)
     println!("Connector: Connecting to listener...");
-    let conn = connector.connect(listener_addr, ALPN).await?;
+    let conn = connector.connect(listener_addr, &alpn()).await?;
     println!("Connector: Connected!");
 
     // Open bi-stream and send test message
@@ -236,9 +248,14 @@ async fn test_local_endpoint_pair() -> Result<()> {
 
     let test_info = FileInfo {
         file_name: "test.txt".to_string(),
-        file_size: 1024,
+        file_size: Some(1024),
         file_path: PathBuf::new(),
         file_hash: None,
+        hash_algorithm: Default::default(),
+        modified: None,
+        mode: None,
+        note: None,
+        xattrs: Vec::new(),
     };
     send_msg(&mut send, &WanTransferMsg::FileMetadata { info: test_info }).await?;
     println!("Connector: Sent FileMetadata");
@@ -246,7 +263,10 @@ async fn test_local_endpoint_pair() -> Result<()> {
     // Receive response
     let response = recv_msg(&mut recv).await?;
     println!("Connector: Received response: {:?}", response);
-    assert!(matches!(response, WanTransferMsg::ResumeInfo { offset: 0 }));
+    assert!(matches!(
+        response,
+        WanTransferMsg::ResumeInfo { offset: 0, .. }
+    ));
 
     // Finish stream
     send.finish()?;
@@ -312,18 +332,18 @@ async fn test_wan_throughput_benchmark() -> Result<()> {
     let secret_key = SecretKey::generate(&mut rand::rng());
     let endpoint = Endpoint::builder()
         .secret_key(secret_key)
-        .alpns(vec![ALPN.to_vec()])
+        .alpns(vec![alpn()])
         .transport_config(transport_config)
         .bind()
         .await?;
 
     println!("Connecting...");
-    let connection = endpoint.connect(target_id, ALPN).await?;
+    let connection = endpoint.connect(target_id, &alpn()).await?;
     println!("✓ Connected");
 
     // Benchmark data sizes
     let data_sizes: Vec<usize> = vec![
-        1 * 1024 * 1024,   // 1MB
+        1024 * 1024,       // 1MB
         10 * 1024 * 1024,  // 10MB
         50 * 1024 * 1024,  // 50MB
         100 * 1024 * 1024, // 100MB
@@ -366,12 +386,8 @@ async fn test_wan_throughput_benchmark() -> Result<()> {
 
         // Wait for receiver's timing
         let receiver_speed = match recv_msg(&mut recv).await {
-            Ok(WanTransferMsg::BenchmarkComplete { elapsed_ms }) => {
-                if elapsed_ms > 0 {
-                    (data_size as f64 / (elapsed_ms as f64 / 1000.0)) / 1_000_000.0
-                } else {
-                    0.0
-                }
+            Ok(WanTransferMsg::BenchmarkComplete { elapsed_ms }) if elapsed_ms > 0 => {
+                (data_size as f64 / (elapsed_ms as f64 / 1000.0)) / 1_000_000.0
             }
             _ => 0.0,
         };
@@ -413,7 +429,7 @@ async fn test_local_throughput_benchmark() -> Result<()> {
     let listener_key = SecretKey::generate(&mut rand::rng());
     let listener = Endpoint::builder()
         .secret_key(listener_key)
-        .alpns(vec![ALPN.to_vec()])
+        .alpns(vec![alpn()])
         .transport_config(create_transport_config())
         .bind()
         .await?;
@@ -427,7 +443,7 @@ async fn test_local_throughput_benchmark() -> Result<()> {
     let connector_key = SecretKey::generate(&mut rand::rng());
     let connector = Endpoint::builder()
         .secret_key(connector_key)
-        .alpns(vec![ALPN.to_vec()])
+        .alpns(vec![alpn()])
         .transport_config(create_transport_config())
         .bind()
         .await?;
@@ -439,20 +455,15 @@ async fn test_local_throughput_benchmark() -> Result<()> {
             let conn = incoming.await?;
 
             // Accept multiple streams and drain data
-            loop {
-                match conn.accept_bi().await {
-                    Ok((mut _send, mut recv)) => {
-                        // Read all data
-                        let mut buf = vec![0u8; 1024 * 1024]; // 1MB buffer
-                        loop {
-                            match recv.read(&mut buf).await {
-                                Ok(Some(0)) | Ok(None) => break,
-                                Ok(Some(_)) => continue,
-                                Err(_) => break,
-                            }
-                        }
+            while let Ok((mut _send, mut recv)) = conn.accept_bi().await {
+                // Read all data
+                let mut buf = vec![0u8; 1024 * 1024]; // 1MB buffer
+                loop {
+                    match recv.read(&mut buf).await {
+                        Ok(Some(0)) | Ok(None) => break,
+                        Ok(Some(_)) => continue,
+                        Err(_) => break,
                     }
-                    Err(_) => break,
                 }
             }
         }
@@ -461,12 +472,12 @@ async fn test_local_throughput_benchmark() -> Result<()> {
 
     // Connect
     println!("Connecting...");
-    let connection = connector.connect(listener_addr, ALPN).await?;
+    let connection = connector.connect(listener_addr, &alpn()).await?;
     println!("✓ Connected");
 
     // Benchmark
     let data_sizes: Vec<usize> = vec![
-        1 * 1024 * 1024,  // 1MB
+        1024 * 1024,      // 1MB
         10 * 1024 * 1024, // 10MB
         50 * 1024 * 1024, // 50MB
     ];